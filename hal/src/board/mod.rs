@@ -10,9 +10,100 @@ mod loongarch64;
 #[cfg(target_arch = "loongarch64")]
 pub use loongarch64::*;
 
+use core::{ops::Range, sync::atomic::{AtomicUsize, Ordering}};
+
+use crate::constant::{Constant, ConstantsHal};
+
+/// physical address of the device tree passed by the bootloader, 0 if there is none
+static BOOT_DTB: AtomicUsize = AtomicUsize::new(0);
+
+/// the end of the usable physical memory, 0 if not computed yet
+static MEMORY_END: AtomicUsize = AtomicUsize::new(0);
+
+/// record the device tree passed by the bootloader,
+/// only riscv64 gets one in a register
+#[allow(unused)]
+pub(crate) fn set_boot_dtb(paddr: usize) {
+    BOOT_DTB.store(paddr, Ordering::Relaxed);
+}
+
+/// the physical range of the device tree passed by the bootloader,
+/// None if there is none or it is not a valid device tree
+pub fn boot_dtb() -> Option<Range<usize>> {
+    let paddr = BOOT_DTB.load(Ordering::Relaxed);
+    // only the low 4 GiB are mapped at boot
+    if paddr == 0 || paddr >= 1 << 32 {
+        return None;
+    }
+    let vaddr = paddr | Constant::KERNEL_ADDR_SPACE.start;
+    let fdt = unsafe { fdt::Fdt::from_ptr(vaddr as *const u8) }.ok()?;
+    Some(paddr..paddr + fdt.total_size())
+}
+
+/// the value of `key=value` on the command line in the device tree passed by the bootloader,
+/// for the choices made before the kernel parses the device tree
+#[allow(unused)]
+pub(crate) fn boot_arg(key: &str) -> Option<&'static str> {
+    let paddr = boot_dtb()?.start;
+    let fdt = unsafe { fdt::Fdt::from_ptr((paddr | Constant::KERNEL_ADDR_SPACE.start) as *const u8) }.ok()?;
+    fdt.find_node("/chosen")?
+        .property("bootargs")?
+        .as_str()?
+        .split_whitespace()
+        .filter_map(|arg| arg.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v)
+}
+
+/// the device tree to discover the devices from:
+/// the one passed by the bootloader, or the one built into the kernel
 pub fn get_device_tree_addr() -> usize {
     unsafe extern "C" {
         fn _dtb_start();
     }
-    _dtb_start as *const usize as usize
+    match boot_dtb() {
+        Some(dtb) => dtb.start | Constant::KERNEL_ADDR_SPACE.start,
+        None => _dtb_start as *const usize as usize,
+    }
+}
+
+/// the end of the physical memory the kernel may use,
+/// the end of the ram in the memory node of the device tree passed by the bootloader,
+/// lowered to the device tree itself when it sits inside the ram, which it does on qemu.
+/// Falls back to [`Constant::MEMORY_END`] without such a device tree
+pub fn memory_end() -> usize {
+    let end = MEMORY_END.load(Ordering::Relaxed);
+    if end != 0 {
+        return end;
+    }
+    let end = dt_memory_end().unwrap_or(Constant::MEMORY_END);
+    MEMORY_END.store(end, Ordering::Relaxed);
+    end
+}
+
+#[cfg(target_arch = "riscv64")]
+fn dt_memory_end() -> Option<usize> {
+    let paddr = boot_dtb()?.start;
+    let fdt = unsafe { fdt::Fdt::from_ptr((paddr | Constant::KERNEL_ADDR_SPACE.start) as *const u8) }.ok()?;
+    let ram = fdt.all_nodes()
+        .filter(|node| node.name.starts_with("memory"))
+        .filter_map(|node| node.reg())
+        .flatten()
+        .map(|region| {
+            let start = region.starting_address as usize;
+            start..start + region.size.unwrap_or(0)
+        })
+        .find(|ram| ram.contains(&Constant::KERNEL_ENTRY_PA))?;
+    let end = if ram.contains(&paddr) {
+        paddr & !(Constant::PAGE_SIZE - 1)
+    } else {
+        ram.end
+    };
+    Some(end)
+}
+
+/// the physical memory of loongarch64 is laid out by the constants
+#[cfg(target_arch = "loongarch64")]
+fn dt_memory_end() -> Option<usize> {
+    None
 }
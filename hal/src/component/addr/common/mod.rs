@@ -93,10 +93,11 @@ impl alloc::fmt::Debug for VirtAddr {
 
 impl From<usize> for VirtAddr {
     fn from(value: usize) -> Self {
-        if value & (1usize << (Constant::VA_WIDTH-1)) == 0 {
-            Self(value & ((1usize << Constant::VA_WIDTH) - 1))
+        let width = Constant::va_width();
+        if value & (1usize << (width-1)) == 0 {
+            Self(value & ((1usize << width) - 1))
         } else {
-            Self(value | !((1usize << Constant::VA_WIDTH) - 1))
+            Self(value | !((1usize << width) - 1))
         }
     }
 }
@@ -121,7 +122,7 @@ impl alloc::fmt::Debug for VirtPageNum {
 
 impl From<usize> for VirtPageNum {
     fn from(value: usize) -> Self {
-        Self(value & ((1 << Constant::VPN_WIDTH) - 1))
+        Self(value & ((1 << Constant::vpn_width()) - 1))
     }
 }
 ImplFor!(VirtPageNum);
@@ -132,7 +133,8 @@ pub trait VirtAddrHal {
 }
 
 pub trait VirtPageNumHal {
-    fn indexes(&self) -> [usize; Constant::PG_LEVEL];
+    /// the indexes into the page tables from the root, only the first [`ConstantsHal::pg_level`] are used
+    fn indexes(&self) -> [usize; Constant::MAX_PG_LEVEL];
     fn start_addr(&self) -> VirtAddr;
     fn end_addr(&self) -> VirtAddr;
 }
@@ -19,7 +19,7 @@ impl VirtAddrHal for VirtAddr {
 }
 
 impl VirtPageNumHal for VirtPageNum {
-    fn indexes(&self) -> [usize; Constant::PG_LEVEL] {
+    fn indexes(&self) -> [usize; Constant::MAX_PG_LEVEL] {
         let mut vpn = self.0;
         let mut idx = [0usize; Constant::PG_LEVEL];
         for i in (0..Constant::PG_LEVEL).rev() {
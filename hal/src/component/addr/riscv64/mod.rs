@@ -6,23 +6,23 @@ use super::{PhysAddr, PhysAddrHal, PhysPageNum, PhysPageNumHal, RangePPNHal, Vir
 
 impl VirtAddrHal for VirtAddr {
     fn floor(&self) -> VirtPageNum {
-        VirtPageNum((self.0 >> Constant::PAGE_SIZE_BITS) & ((1usize << Constant::VPN_WIDTH) - 1) )
+        VirtPageNum((self.0 >> Constant::PAGE_SIZE_BITS) & ((1usize << Constant::vpn_width()) - 1) )
     }
 
     fn ceil(&self) -> VirtPageNum {
         if self.0 == 0{
             VirtPageNum(0)
         } else {
-            VirtPageNum(((self.0 - 1 + Constant::PAGE_SIZE) >> Constant::PAGE_SIZE_BITS) & ((1usize << Constant::VPN_WIDTH) - 1))
+            VirtPageNum(((self.0 - 1 + Constant::PAGE_SIZE) >> Constant::PAGE_SIZE_BITS) & ((1usize << Constant::vpn_width()) - 1))
         }
     }
 }
 
 impl VirtPageNumHal for VirtPageNum {
-    fn indexes(&self) -> [usize; Constant::PG_LEVEL] {
+    fn indexes(&self) -> [usize; Constant::MAX_PG_LEVEL] {
         let mut vpn = self.0;
-        let mut idx = [0usize; 3];
-        for i in (0..3).rev() {
+        let mut idx = [0usize; Constant::MAX_PG_LEVEL];
+        for i in (0..Constant::pg_level()).rev() {
             idx[i] = vpn & 511;
             vpn >>= 9;
         }
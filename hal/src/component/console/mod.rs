@@ -1,6 +1,8 @@
 mod uart;
 
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use crate::component::instruction::{Instruction, InstructionHal};
+use crate::component::timer::{Timer, TimerHal};
 use crate::util::sie_guard::SieGuard;
 
 #[macro_export]
@@ -15,30 +17,197 @@ macro_rules! print {
 /// println string macro
 macro_rules! println {
     () => {
-        $crate::console::_print("\n");
+        $crate::console::_print(format_args!("\n"));
     };
     ($fmt: literal $(, $($arg: tt)+)?) => {
         $crate::console::_print(format_args!(concat!($fmt, "\n") $(, $($arg)+)?));
     }
 }
 
-/// every arch needs to impl core::fmt::Write for Stdout
-struct Stdout;
+/// the most bytes emitted as one record, longer output is split into records of this size
+pub const RECORD_SIZE: usize = 1024;
 
-/// Stdout mutex
-static STDOUT_MUTEX: AtomicBool = AtomicBool::new(false); 
+/// the console lock, each record is written while holding it so records never interleave
+static CONSOLE_LOCK: AtomicBool = AtomicBool::new(false);
+/// set once the kernel panics, the console lock is no longer waited for
+static PANICKING: AtomicBool = AtomicBool::new(false);
+/// tries for the console lock while panicking before writing without it
+const PANIC_LOCK_TRIES: usize = 1 << 20;
 
-pub fn _print(args: core::fmt::Arguments) {
-    loop {
-        let _sie_guard = SieGuard::new();
-        if STDOUT_MUTEX.compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed).is_ok() {
-            core::fmt::Write::write_fmt(&mut Stdout, args).unwrap();
-            STDOUT_MUTEX.store(false, Ordering::Release);
-            return;
+/// prefix every log record with the time since boot
+pub const LOG_FMT_TIME: usize = 1 << 0;
+/// prefix every log record with the hart id
+pub const LOG_FMT_HART: usize = 1 << 1;
+/// prefix every log record with the host name, from set_log_host
+pub const LOG_FMT_HOST: usize = 1 << 2;
+/// LOG_FMT_* flags of the log records
+static LOG_FORMAT: AtomicUsize = AtomicUsize::new(0);
+/// what writes the host name of the log records, a fn(&mut dyn Write)
+static LOG_HOST: AtomicUsize = AtomicUsize::new(0);
+/// where the log records go besides the console, the kernel log ring
+static LOG_SINK: AtomicUsize = AtomicUsize::new(0);
+
+/// the console is shared with a panicking hart from now on:
+/// never wait for the lock, which the panicking code may hold itself
+pub fn set_panicking() {
+    PANICKING.store(true, Ordering::Release);
+}
+
+/// set the LOG_FMT_* flags of the log records
+pub fn set_log_format(flags: usize) {
+    LOG_FORMAT.store(flags, Ordering::Relaxed);
+}
+
+/// also hand every log record to `sink`
+pub fn set_log_sink(sink: fn(&[u8])) {
+    LOG_SINK.store(sink as usize, Ordering::Release);
+}
+
+/// prefix every log record with the host name `host` writes, it may write nothing
+/// while the host has no name
+pub fn set_log_host(host: fn(&mut dyn core::fmt::Write)) {
+    LOG_HOST.store(host as usize, Ordering::Release);
+    LOG_FORMAT.fetch_or(LOG_FMT_HOST, Ordering::Relaxed);
+}
+
+/// run `f` holding the console lock, so what it writes to the console is not interleaved with
+/// the records of other harts, for other writers of the console such as the serial driver
+pub fn with_console_lock<R>(f: impl FnOnce() -> R) -> R {
+    let _sie_guard = SieGuard::new();
+    let try_lock = || CONSOLE_LOCK.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok();
+    let mut locked = try_lock();
+    if PANICKING.load(Ordering::Acquire) {
+        // a bounded wait: if the lock holder is the panicking code itself, write anyway
+        let mut tries = 0;
+        while !locked && tries < PANIC_LOCK_TRIES {
+            core::hint::spin_loop();
+            locked = try_lock();
+            tries += 1;
+        }
+    } else {
+        while !locked {
+            core::hint::spin_loop();
+            locked = try_lock();
+        }
+    }
+    let ret = f();
+    if locked {
+        CONSOLE_LOCK.store(false, Ordering::Release);
+    }
+    ret
+}
+
+/// write one record to the console, atomically with respect to other records
+fn emit(bytes: &[u8]) {
+    with_console_lock(|| {
+        for &c in bytes {
+            console_putchar(c as usize);
+        }
+    })
+}
+
+/// hand bytes of a log record to the log sink, if one is set
+fn to_sink(bytes: &[u8]) {
+    let sink = LOG_SINK.load(Ordering::Acquire);
+    if sink != 0 {
+        // SAFETY: only set_log_sink stores into LOG_SINK, always a fn(&[u8])
+        let sink: fn(&[u8]) = unsafe { core::mem::transmute(sink) };
+        sink(bytes);
+    }
+}
+
+/// a record assembled on the stack before it is emitted,
+/// it only spills early when it grows beyond RECORD_SIZE
+struct Record {
+    buf: [u8; RECORD_SIZE],
+    len: usize,
+    /// a log record, which also goes to the log sink
+    log: bool,
+}
+
+impl Record {
+    fn new(log: bool) -> Self {
+        Self { buf: [0; RECORD_SIZE], len: 0, log }
+    }
+    fn flush(&mut self) {
+        let bytes = &self.buf[..self.len];
+        if self.log {
+            to_sink(bytes);
+        }
+        emit(bytes);
+        self.len = 0;
+    }
+    fn push(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            if self.len == RECORD_SIZE {
+                self.flush();
+            }
+            let n = bytes.len().min(RECORD_SIZE - self.len);
+            self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+            self.len += n;
+            bytes = &bytes[n..];
+        }
+    }
+    fn finish(mut self) {
+        if self.len != 0 {
+            self.flush();
         }
     }
 }
 
+impl core::fmt::Write for Record {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.push(s.as_bytes());
+        Ok(())
+    }
+}
+
+pub fn _print(args: core::fmt::Arguments) {
+    let mut record = Record::new(false);
+    let _ = core::fmt::Write::write_fmt(&mut record, args);
+    record.finish();
+}
+
+/// write raw bytes to the console, each RECORD_SIZE chunk as one record
+pub fn print_bytes(bytes: &[u8]) {
+    for chunk in bytes.chunks(RECORD_SIZE) {
+        emit(chunk);
+    }
+}
+
+/// write a kernel log record: the LOG_FMT_* prefixes, `args` and a newline,
+/// to the console and to the log sink
+pub fn _log(args: core::fmt::Arguments) {
+    use core::fmt::Write;
+    let mut record = Record::new(true);
+    let format = LOG_FORMAT.load(Ordering::Relaxed);
+    if format & LOG_FMT_TIME != 0 {
+        let ticks = Timer::read();
+        let freq = Timer::get_timer_freq();
+        let _ = write!(record, "[{:>5}.{:06}] ", ticks / freq, ticks % freq * 1_000_000 / freq);
+    }
+    let host = LOG_HOST.load(Ordering::Acquire);
+    if format & LOG_FMT_HOST != 0 && host != 0 {
+        // SAFETY: only set_log_host stores into LOG_HOST, always a fn(&mut dyn Write)
+        let host: fn(&mut dyn Write) = unsafe { core::mem::transmute(host) };
+        host(&mut record);
+    }
+    if format & LOG_FMT_HART != 0 {
+        let _ = write!(record, "[h{}] ", Instruction::get_tp());
+    }
+    let _ = record.write_fmt(args);
+    let _ = record.write_str("\n");
+    record.finish();
+}
+
+#[macro_export]
+/// kernel log record macro, atomic on the console and copied to the log sink
+macro_rules! klog {
+    ($fmt: literal $(, $($arg: tt)+)?) => {
+        $crate::console::_log(format_args!($fmt $(, $($arg)+)?));
+    }
+}
+
 struct Logger;
 
 impl log::Log for Logger {
@@ -56,12 +225,12 @@ impl log::Log for Logger {
             log::Level::Debug => 32, // Green
             log::Level::Trace => 90, // BrightBlack
         };
-        println!(
+        _log(format_args!(
             "\u{1B}[{}m[{:>5}] {}\u{1B}[0m",
             color,
             record.level(),
             record.args(),
-        );
+        ));
     }
     fn flush(&self) {}
 }
@@ -78,6 +247,12 @@ pub fn init() {
         Some("TRACE") => log::LevelFilter::Trace,
         _ => log::LevelFilter::Info,
     });
+    set_log_format(match option_env!("LOG_FORMAT") {
+        Some("time") => LOG_FMT_TIME,
+        Some("hart") => LOG_FMT_HART,
+        Some("time,hart") | Some("hart,time") => LOG_FMT_TIME | LOG_FMT_HART,
+        _ => 0,
+    });
 }
 
 
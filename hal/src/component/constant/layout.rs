@@ -0,0 +1,120 @@
+//! The sizes of the user layout chosen on the boot command line:
+//! `stack-size=` for the most a user stack grows to, `mmap-window=` and `share-window=`
+//! for the file and share mmap areas, in bytes with an optional K, M or G suffix.
+//! A size left out keeps the constant of the architecture.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{Constant, ConstantsHal};
+
+/// the sizes set at boot, 0 keeps the default
+static STACK_SIZE: AtomicUsize = AtomicUsize::new(0);
+static FILE_SIZE: AtomicUsize = AtomicUsize::new(0);
+static SHARE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// the size of `key` set at boot, if any
+pub(super) fn configured(key: LayoutKey) -> Option<usize> {
+    match key.cell().load(Ordering::Relaxed) {
+        0 => None,
+        size => Some(size),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) enum LayoutKey {
+    Stack,
+    File,
+    Share,
+}
+
+impl LayoutKey {
+    const ALL: [Self; 3] = [Self::Stack, Self::File, Self::Share];
+
+    fn cell(self) -> &'static AtomicUsize {
+        match self {
+            Self::Stack => &STACK_SIZE,
+            Self::File => &FILE_SIZE,
+            Self::Share => &SHARE_SIZE,
+        }
+    }
+
+    fn arg(self) -> &'static str {
+        match self {
+            Self::Stack => "stack-size",
+            Self::File => "mmap-window",
+            Self::Share => "share-window",
+        }
+    }
+}
+
+/// the user layout in effect
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayoutConfig {
+    /// the most a user stack grows to, the guard lies below it
+    pub stack_size: usize,
+    /// the size of the file mmap area
+    pub file_size: usize,
+    /// the size of the share mmap area
+    pub share_size: usize,
+}
+
+impl LayoutConfig {
+    /// the layout in effect, the defaults until [`init_layout`] ran
+    pub fn get() -> Self {
+        Self {
+            stack_size: Constant::user_stack_size(),
+            file_size: Constant::user_file_range().len(),
+            share_size: Constant::user_share_range().len(),
+        }
+    }
+}
+
+/// `size` with an optional K, M or G suffix
+fn parse_size(size: &str) -> Option<usize> {
+    let (digits, shift) = match size.as_bytes().last()? {
+        b'k' | b'K' => (&size[..size.len() - 1], 10),
+        b'm' | b'M' => (&size[..size.len() - 1], 20),
+        b'g' | b'G' => (&size[..size.len() - 1], 30),
+        _ => (size, 0),
+    };
+    let n = match digits.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok()?,
+        None => digits.parse().ok()?,
+    };
+    n.checked_mul(1 << shift)
+}
+
+/// whether the stack, its guard and the two mmap areas fit in the user address space
+/// one under the other, above where the dynamic loader goes
+fn fits() -> bool {
+    let stack_top = Constant::user_stack_top();
+    let bottom = stack_top
+        .checked_sub(Constant::user_stack_size())
+        .and_then(|b| b.checked_sub(Constant::USER_STACK_GUARD_SIZE))
+        .and_then(|b| b.checked_sub(Constant::USER_STACK_GUARD_BOTTOM - Constant::USER_FILE_END))
+        .and_then(|b| b.checked_sub(Constant::user_file_size()))
+        .and_then(|b| b.checked_sub(Constant::USER_FILE_BEG - Constant::USER_SHARE_END))
+        .and_then(|b| b.checked_sub(Constant::user_share_size()));
+    matches!(bottom, Some(b) if b >= Constant::DL_INTERP_OFFSET)
+}
+
+/// read the layout from the boot command line and check it, on the first hart before
+/// any user space exists and after the paging mode is chosen; a bad layout stops the boot
+pub(crate) fn init_layout() {
+    for key in LayoutKey::ALL {
+        let Some(arg) = crate::board::boot_arg(key.arg()) else {
+            continue;
+        };
+        let size = parse_size(arg)
+            .unwrap_or_else(|| panic!("[CINPHAL] {}={}: not a size", key.arg(), arg));
+        if size == 0 || size % Constant::PAGE_SIZE != 0 {
+            panic!("[CINPHAL] {}={}: must be a non-zero multiple of the page size", key.arg(), arg);
+        }
+        key.cell().store(size, Ordering::Relaxed);
+    }
+    if !fits() {
+        panic!("[CINPHAL] the user layout {:x?} overlaps itself or the dynamic loader at {:#x}",
+            (Constant::user_stack_size(), Constant::user_file_size(), Constant::user_share_size()),
+            Constant::DL_INTERP_OFFSET);
+    }
+}
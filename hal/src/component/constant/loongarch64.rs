@@ -35,8 +35,8 @@ impl ConstantsHal for Constant {
     
     const USER_STACK_TOP: usize = Self::USER_ADDR_SPACE.end - Self::PAGE_SIZE;
     
-    // put the file mmap area under user stack
-    const USER_FILE_END: usize = Self::USER_STACK_BOTTOM;
+    // put the file mmap area under the user stack guard
+    const USER_FILE_END: usize = Self::USER_STACK_GUARD_BOTTOM;
     const USER_FILE_SIZE: usize = 0x2_0000_0000;
 
     // put the share mmap area under file mmap area
@@ -44,4 +44,6 @@ impl ConstantsHal for Constant {
     const USER_SHARE_SIZE: usize = 0x2_0000_0000;
     
     const DL_INTERP_OFFSET: usize = 0x20_0000_0000;
+
+    const ELF_MACHINE: u16 = 258; // EM_LOONGARCH
 }
\ No newline at end of file
@@ -1,5 +1,11 @@
 use core::ops::Range;
 
+mod layout;
+
+pub use layout::LayoutConfig;
+pub(crate) use layout::init_layout;
+use layout::{configured, LayoutKey};
+
 pub trait ConstantsHal {
     const MAX_PROCESSORS: usize = 4;
     const KERNEL_ENTRY_PA: usize;
@@ -19,6 +25,8 @@ pub trait ConstantsHal {
     const VPN_WIDTH: usize = Self::VA_WIDTH - Self::PAGE_SIZE_BITS;
 
     const PG_LEVEL: usize;
+    // the most page table levels of any paging mode of the architecture
+    const MAX_PG_LEVEL: usize = Self::PG_LEVEL;
 
     const MEMORY_END: usize;
 
@@ -27,6 +35,8 @@ pub trait ConstantsHal {
     const SIGRET_TRAMPOLINE_TOP: usize; 
 
     const KERNEL_STACK_SIZE: usize;
+    // the lowest pages of every per-hart kernel stack are left unmapped
+    const KERNEL_STACK_GUARD_SIZE: usize = Self::PAGE_SIZE;
     const KERNEL_STACK_BOTTOM: usize = Self::KERNEL_STACK_TOP - Self::KERNEL_STACK_SIZE * Self::MAX_PROCESSORS;
     const KERNEL_STACK_TOP: usize;
 
@@ -37,6 +47,9 @@ pub trait ConstantsHal {
     const USER_STACK_SIZE: usize;
     const USER_STACK_BOTTOM: usize = Self::USER_STACK_TOP - Self::USER_STACK_SIZE;
     const USER_STACK_TOP: usize;
+    // unmapped gap below the user stack, a fault in it is a stack overflow
+    const USER_STACK_GUARD_SIZE: usize = 16 * Self::PAGE_SIZE;
+    const USER_STACK_GUARD_BOTTOM: usize = Self::USER_STACK_BOTTOM - Self::USER_STACK_GUARD_SIZE;
 
     const USER_FILE_BEG: usize = Self::USER_FILE_END - Self::USER_FILE_SIZE;
     const USER_FILE_SIZE: usize;
@@ -49,6 +62,76 @@ pub trait ConstantsHal {
 
     const DL_INTERP_OFFSET: usize;
 
+    // e_machine of the ELF files of this architecture
+    const ELF_MACHINE: u16;
+
+    // The constants above lay out the paging mode every hart supports,
+    // the functions below follow the paging mode and the layout chosen at boot.
+
+    /// the width of the virtual addresses of the active paging mode
+    fn va_width() -> usize {
+        Self::VA_WIDTH
+    }
+
+    /// the width of the virtual page numbers of the active paging mode
+    fn vpn_width() -> usize {
+        Self::va_width() - Self::PAGE_SIZE_BITS
+    }
+
+    /// the page table levels of the active paging mode, at most [`Self::MAX_PG_LEVEL`]
+    fn pg_level() -> usize {
+        Self::PG_LEVEL
+    }
+
+    /// the user address space of the active paging mode
+    fn user_addr_space() -> Range<usize> {
+        Self::USER_ADDR_SPACE
+    }
+
+    /// how many times [`Self::USER_ADDR_SPACE`] fits in the active user address space
+    fn user_space_scale() -> usize {
+        Self::user_addr_space().end / Self::USER_ADDR_SPACE.end
+    }
+
+    /// the user stack stays as far from the end of the user address space as in the constants
+    fn user_stack_top() -> usize {
+        Self::user_addr_space().end - (Self::USER_ADDR_SPACE.end - Self::USER_STACK_TOP)
+    }
+
+    /// the most a user stack grows to, [`Self::USER_STACK_SIZE`] unless set at boot
+    fn user_stack_size() -> usize {
+        configured(LayoutKey::Stack).unwrap_or(Self::USER_STACK_SIZE)
+    }
+
+    fn user_stack_bottom() -> usize {
+        Self::user_stack_top() - Self::user_stack_size()
+    }
+
+    fn user_stack_guard_bottom() -> usize {
+        Self::user_stack_bottom() - Self::USER_STACK_GUARD_SIZE
+    }
+
+    /// the size of the file mmap area, grown with the user address space unless set at boot
+    fn user_file_size() -> usize {
+        configured(LayoutKey::File).unwrap_or(Self::USER_FILE_SIZE * Self::user_space_scale())
+    }
+
+    /// the size of the share mmap area, grown with the user address space unless set at boot
+    fn user_share_size() -> usize {
+        configured(LayoutKey::Share).unwrap_or(Self::USER_SHARE_SIZE * Self::user_space_scale())
+    }
+
+    /// the file mmap area, under the stack guard
+    fn user_file_range() -> Range<usize> {
+        let end = Self::user_stack_guard_bottom() - (Self::USER_STACK_GUARD_BOTTOM - Self::USER_FILE_END);
+        end - Self::user_file_size()..end
+    }
+
+    /// the share mmap area, under the file mmap area
+    fn user_share_range() -> Range<usize> {
+        let end = Self::user_file_range().start - (Self::USER_FILE_BEG - Self::USER_SHARE_END);
+        end - Self::user_share_size()..end
+    }
 }
 
 pub struct Constant;
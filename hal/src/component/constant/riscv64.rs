@@ -1,3 +1,5 @@
+use crate::pagetable::paging_mode;
+
 use super::{Constant, ConstantsHal};
 
 impl ConstantsHal for Constant {
@@ -5,6 +7,7 @@ impl ConstantsHal for Constant {
 
     const KERNEL_ADDR_SPACE: core::ops::Range<usize> = 0xffff_ffc0_0000_0000..0xffff_ffff_ffff_ffff;
 
+    // the layout of sv39, the kernel addresses are the same under sv48
     const USER_ADDR_SPACE: core::ops::Range<usize> = 0x0000_0000_0000_0000..0x0000_0040_0000_0000;
 
     const VA_WIDTH: usize = 39;
@@ -16,6 +19,7 @@ impl ConstantsHal for Constant {
     const PAGE_SIZE_BITS: usize = 12;
 
     const PG_LEVEL: usize = 3;
+    const MAX_PG_LEVEL: usize = 4;
     
     const PTE_WIDTH: usize = 64;
     
@@ -35,8 +39,8 @@ impl ConstantsHal for Constant {
     
     const USER_STACK_TOP: usize = Self::USER_ADDR_SPACE.end - Self::PAGE_SIZE;
 
-    // put the file mmap area under user stack
-    const USER_FILE_END: usize = Self::USER_STACK_BOTTOM - Self::PAGE_SIZE;
+    // put the file mmap area under the user stack guard
+    const USER_FILE_END: usize = Self::USER_STACK_GUARD_BOTTOM;
     const USER_FILE_SIZE: usize = 0x2_0000_0000;
 
     // put the share mmap area under file mmap area
@@ -44,4 +48,19 @@ impl ConstantsHal for Constant {
     const USER_SHARE_SIZE: usize = 0x2_0000_0000;
 
     const DL_INTERP_OFFSET: usize = 0x20_0000_0000;
+
+    const ELF_MACHINE: u16 = 243; // EM_RISCV
+
+    fn va_width() -> usize {
+        paging_mode().va_width()
+    }
+
+    fn pg_level() -> usize {
+        paging_mode().levels()
+    }
+
+    // the lower half of the virtual addresses
+    fn user_addr_space() -> core::ops::Range<usize> {
+        0..1 << (Self::va_width() - 1)
+    }
 }
\ No newline at end of file
@@ -1,6 +1,6 @@
 use core::sync::atomic::{AtomicBool, Ordering};
 
-use crate::{constant::{Constant, ConstantsHal}, entry::BOOT_STACK, println, timer::{Timer, TimerHal}, instruction::{Instruction, InstructionHal}};
+use crate::{constant::{Constant, ConstantsHal, LayoutConfig}, entry::BOOT_STACK, println, timer::{Timer, TimerHal}, instruction::{Instruction, InstructionHal}};
 
 use super::RUNNING_PROCESSOR;
 
@@ -55,6 +55,7 @@ pub(crate) fn rust_main(id: usize) {
     if is_first {
         super::clear_bss();
         crate::console::init();
+        crate::constant::init_layout();
         print_info();
         let _ = unsafe { super::_main_for_arch(id, true) };
     } else {
@@ -72,6 +73,7 @@ fn print_info() {
     println!("[CINPHAL] IOCSR Support: {}", loongArch64::cpu::get_support_iocsr());
     println!("[CINPHAL] PA_LEN: {}", loongArch64::cpu::get_palen());
     println!("[CINPHAL] VA_LEN: {}", loongArch64::cpu::get_valen());
+    println!("[CINPHAL] user layout: {:x?}", LayoutConfig::get());
     println!("[CINPHAL] Frequency: {} Hz", Timer::get_timer_freq());
     println!("[CINPHAL] start address: {:#x}", _start as usize);
     println!("");
@@ -1,5 +1,6 @@
-use core::sync::atomic::Ordering;
-use crate::{constant::{Constant, ConstantsHal}, entry::BOOT_STACK, instruction::{Instruction, InstructionHal}, println, timer::{Timer, TimerHal}};
+use core::{arch::asm, sync::atomic::Ordering};
+use riscv::register;
+use crate::{constant::{Constant, ConstantsHal, LayoutConfig}, entry::BOOT_STACK, instruction::{Instruction, InstructionHal}, pagetable::{paging_mode, set_paging_mode, PagingMode}, println, timer::{Timer, TimerHal}};
 
 use super::RUNNING_PROCESSOR;
 
@@ -11,15 +12,21 @@ pub static mut BOOT_PAGE_TABLE: BootPageTable = {
     arr[2] = (0x80000 << 10) | 0xcf;
     arr[256] = (0x00000 << 10) | 0xcf;
     arr[258] = (0x80000 << 10) | 0xcf;
+    // the top of a 2 GiB ram, where the device tree may be
+    arr[259] = (0xc0000 << 10) | 0xcf;
     BootPageTable(arr)
 };
 
+/// the root of the boot page table under sv48, its last entry leads to [`BOOT_PAGE_TABLE`]
+/// for the same top 256 GiB the kernel runs in under sv39
+pub static mut BOOT_PAGE_TABLE_SV48: BootPageTable = BootPageTable([0; Constant::PTES_PER_PAGE]);
+
 const VIRT_RAM_OFFSET: usize = Constant::KERNEL_ADDR_SPACE.start;
 
 #[naked]
 #[unsafe(no_mangle)]
 #[unsafe(link_section = ".text.entry")]
-unsafe extern "C" fn _start(id: usize) -> ! {
+unsafe extern "C" fn _start(id: usize, dtb: usize) -> ! {
     core::arch::naked_asm!(
         // 1. set boot stack
         // a0 = processor_id
@@ -49,7 +56,7 @@ unsafe extern "C" fn _start(id: usize) -> ! {
             csrs sstatus, t0 
         ",
         // 4. jump to rust_main
-        // add virtual address offset to sp and pc
+        // add virtual address offset to sp and pc, a1 still holds the device tree
         "
             li      t2, {virt_ram_offset}
             or      sp, sp, t2
@@ -65,11 +72,14 @@ unsafe extern "C" fn _start(id: usize) -> ! {
     )
 }
 
-pub(crate) fn rust_main(id: usize) {
+pub(crate) fn rust_main(id: usize, dtb: usize) {
     Instruction::set_tp(id);
     if RUNNING_PROCESSOR.fetch_add(1, Ordering::AcqRel) == 0 {
         super::clear_bss();
+        crate::board::set_boot_dtb(dtb);
         crate::console::init();
+        choose_paging_mode();
+        crate::constant::init_layout();
         print_info();
         let _ = unsafe { super::_main_for_arch(id, true) };
     } else {
@@ -83,10 +93,33 @@ pub(crate) fn rust_main(id: usize) {
     loop {}
 }
 
+/// switch to sv48 unless `paging=sv39` is on the command line or the harts lack it,
+/// the other harts boot in sv39 and switch with the kernel page table.
+/// A write of an unsupported mode to satp has no effect, which tells the two apart
+fn choose_paging_mode() {
+    let wanted = crate::board::boot_arg("paging");
+    if wanted == Some("sv39") {
+        return;
+    }
+    unsafe {
+        let root = core::ptr::addr_of_mut!(BOOT_PAGE_TABLE_SV48);
+        let next = core::ptr::addr_of!(BOOT_PAGE_TABLE) as usize & !VIRT_RAM_OFFSET;
+        (*root).0[Constant::PTES_PER_PAGE - 1] = (((next >> 12) << 10) | 0x1) as u64;
+        let satp = (PagingMode::Sv48.satp_mode() << 60) | ((root as usize & !VIRT_RAM_OFFSET) >> 12);
+        asm!("csrw satp, {}", "sfence.vma", in(reg) satp);
+        if register::satp::read().bits() >> 60 == PagingMode::Sv48.satp_mode() {
+            set_paging_mode(PagingMode::Sv48);
+        } else if wanted == Some("sv48") {
+            println!("[CINPHAL] sv48 is not supported, fall back to sv39");
+        }
+    }
+}
+
 fn print_info() {
     println!("\u{1B}[36m\n{}\u{1B}[0m", super::BANNER);
     println!("[CINPHAL] PA_LEN: {}", 56);
-    println!("[CINPHAL] VA_LEN: {}", 39);
+    println!("[CINPHAL] VA_LEN: {} ({:?})", Constant::va_width(), paging_mode());
+    println!("[CINPHAL] user layout: {:x?}", LayoutConfig::get());
     println!("[CINPHAL] Frequency: {} Hz", Timer::get_timer_freq());
     println!("[CINPHAL] start address: {:#x}", _start as usize);
     println!("");
@@ -1,10 +1,30 @@
 use loongArch64::register::{self, ecfg::LineBasedInterrupt};
 
-use crate::{constant::{Constant, ConstantsHal}, println, trap::FP_REG_DIRTY};
+use crate::{constant::{Constant, ConstantsHal}, println};
 
 const POWEROFF_REG_MMIO: usize = 0x8000_0000_100e_001c;
 const POWEROFF_VALUE: u8 = 0x34;
 
+const IOCSR_IPI_STATUS: usize = 0x1000;
+const IOCSR_IPI_ENABLE: usize = 0x1004;
+const IOCSR_IPI_CLEAR: usize = 0x100c;
+/// the ipi vector used by the kernel, also wakes up the harts in hart_start
+const IPI_VECTOR_KERNEL: usize = 1;
+
+fn iocsr_read_w(reg: usize) -> u32 {
+    let val: u32;
+    unsafe {
+        core::arch::asm!("iocsrrd.w {}, {}", out(reg) val, in(reg) reg);
+    }
+    val
+}
+
+fn iocsr_write_w(reg: usize, val: u32) {
+    unsafe {
+        core::arch::asm!("iocsrwr.w {}, {}", in(reg) val, in(reg) reg);
+    }
+}
+
 
 use super::{Instruction, InstructionHal};
 
@@ -63,7 +83,7 @@ impl InstructionHal for Instruction {
     
     fn hart_start(hartid: usize, _opaque: usize) {
         loongArch64::ipi::csr_mail_send(Constant::KERNEL_ENTRY_PA as u64 | 0x9000_0000_0000_0000, hartid, 0);
-        loongArch64::ipi::send_ipi_single(hartid, 1);
+        loongArch64::ipi::send_ipi_single(hartid, IPI_VECTOR_KERNEL as _);
     }
     
     fn set_tp(hartid: usize) {
@@ -85,18 +105,18 @@ impl InstructionHal for Instruction {
         }
         tp
     }
-    
-    fn set_float_status_clean() {
-        let cpuid = register::cpuid::read().core_id();
-        #[allow(static_mut_refs)]
-        unsafe {
-            if FP_REG_DIRTY[cpuid] {
-                FP_REG_DIRTY[cpuid] = false;
-                register::euen::set_fpe(true);
-            } else {
-                register::euen::set_fpe(false);
-            }
-        }
+
+    unsafe fn enable_software_interrupt() {
+        iocsr_write_w(IOCSR_IPI_ENABLE, u32::MAX);
+        register::ecfg::set_lie(LineBasedInterrupt::IPI);
+    }
+
+    fn send_ipi(hartid: usize) {
+        loongArch64::ipi::send_ipi_single(hartid, IPI_VECTOR_KERNEL as _);
+    }
+
+    fn clear_ipi() {
+        iocsr_write_w(IOCSR_IPI_CLEAR, iocsr_read_w(IOCSR_IPI_STATUS));
     }
 
     unsafe fn enable_external_interrupt() {
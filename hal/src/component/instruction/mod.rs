@@ -6,6 +6,12 @@ pub trait InstructionHal {
     unsafe fn is_interrupt_enabled() -> bool;
     unsafe fn enable_timer_interrupt();
     unsafe fn enable_external_interrupt();
+    /// let other harts interrupt this hart
+    unsafe fn enable_software_interrupt();
+    /// raise a software interrupt on `hartid`
+    fn send_ipi(hartid: usize);
+    /// acknowledge the software interrupt of this hart
+    fn clear_ipi();
     unsafe fn clear_sum();
     unsafe fn set_sum();
     /// shutdown is unsafe, because it will not trigger drop
@@ -13,7 +19,6 @@ pub trait InstructionHal {
     fn hart_start(hartid: usize, opaque: usize);
     fn set_tp(hartid: usize);
     fn get_tp() -> usize;
-    fn set_float_status_clean();
 }
 
 pub struct Instruction;
@@ -33,6 +33,18 @@ impl InstructionHal for Instruction {
     unsafe fn enable_external_interrupt() {
         register::sie::set_sext();
     } 
+
+    unsafe fn enable_software_interrupt() {
+        register::sie::set_ssoft();
+    }
+
+    fn send_ipi(hartid: usize) {
+        sbi_rt::send_ipi(1, hartid);
+    }
+
+    fn clear_ipi() {
+        unsafe { register::sip::clear_ssoft(); }
+    }
     unsafe fn clear_sum() {
         register::sstatus::clear_sum();
     }
@@ -77,10 +89,4 @@ impl InstructionHal for Instruction {
         }
         tp
     }
-    
-    fn set_float_status_clean() {
-        unsafe {
-            register::sstatus::set_fs(register::sstatus::FS::Clean);
-        }
-    }
-}
\ No newline at end of file
+}
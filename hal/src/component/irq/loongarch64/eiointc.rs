@@ -90,13 +90,21 @@ impl Eiointc {
         None
     }
 
-    /// 中断处理完毕
+    /// 中断处理完毕, 当前核的中断状态寄存器写 1 清除
     pub fn complete_irq(no: usize)  {
         let group = no >> 6;
         let idx = no & 63;
         let gp_offset = group << 3;
-        let mut en = iocsr_read_d(Self::EXT_IOI_SR_BASE + gp_offset);
-        en &= !(1u64 << idx);
-        iocsr_write_d(Self::EXT_IOI_SR_BASE + gp_offset, en);
+        iocsr_write_d(Self::PERCORE_EXT_IOI_SR_BASE + gp_offset, 1u64 << idx);
+    }
+
+    /// 把中断路由到指定的处理器核, 并关闭它的自动轮转
+    pub fn route_irq(no: usize, core: usize) {
+        let gp64 = no >> 6;
+        let off64 = no & 63;
+        let mut bounce = iocsr_read_d(Self::EXT_IOI_BOUNCE_BASE + gp64*8);
+        bounce &= !(1u64 << off64);
+        iocsr_write_d(Self::EXT_IOI_BOUNCE_BASE + gp64*8, bounce);
+        iocsr_write_b(Self::EXT_IOI_MAP_CORE_BASE + no, 1 << core);
     }
 }
\ No newline at end of file
@@ -65,12 +65,20 @@ impl IrqCtrlHal for IrqCtrl {
         }
     }
     
+    fn route_irq(&self, no: usize, hart: usize) {
+        Eiointc::route_irq(no, hart);
+    }
+
+    fn init_hart(&self, _hart: usize) {
+        // every core takes the extended io interrupts once ECFG enables them
+    }
+
     fn claim_irq(&self) -> Option<usize> {
         Eiointc::claim_irq()
     }
     
     fn complete_irq(&self, no: usize) {
-        Eiointc::disable_irq(no);
+        Eiointc::complete_irq(no);
     }
 }
 
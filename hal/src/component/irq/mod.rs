@@ -12,8 +12,16 @@ pub use loongarch64::*;
 
 pub trait IrqCtrlHal {
     fn from_dt(device_tree: &fdt::Fdt, mmio: impl crate::mapper::MmioMapperHal) -> Option<Self> where Self: Sized;
+    /// unmask the line `no`, it is delivered to the hart it is routed to
     fn enable_irq(&self, no: usize);
+    /// mask the line `no`
     fn disable_irq(&self, no: usize);
+    /// deliver the line `no` to `hart` only, hart 0 until routed
+    fn route_irq(&self, no: usize, hart: usize);
+    /// let `hart` take the external interrupts routed to it
+    fn init_hart(&self, hart: usize);
+    /// claim the highest priority interrupt pending on the current hart
     fn claim_irq(&self) -> Option<usize>;
+    /// tell the controller the current hart finished handling `no`
     fn complete_irq(&self, no: usize);
 }
\ No newline at end of file
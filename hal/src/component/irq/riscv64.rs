@@ -1,13 +1,22 @@
-use crate::mapper::MmioMapperHal;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use crate::{instruction::{Instruction, InstructionHal}, mapper::MmioMapperHal};
 
 use super::IrqCtrlHal;
 
+/// most interrupt sources a PLIC has
+const PLIC_MAX_IRQ: usize = 1024;
+
 pub struct PLIC {
     /// MMIO base address.
     pub mmio_base: usize,
     /// MMIO region size.
     pub mmio_size: usize,
     pub mmio_vbase: usize,
+    /// the hart each source is routed to
+    routes: [AtomicU8; PLIC_MAX_IRQ],
+    /// whether each source is unmasked
+    enabled: [AtomicBool; PLIC_MAX_IRQ],
 }
 
 // const PLIC_ADDR: usize = 0xc00_0000 + VIRT_RAM_OFFSET;
@@ -17,44 +26,50 @@ impl PLIC {
         PLIC {
             mmio_base,
             mmio_size,
-            mmio_vbase
+            mmio_vbase,
+            routes: [const { AtomicU8::new(0) }; PLIC_MAX_IRQ],
+            enabled: [const { AtomicBool::new(false) }; PLIC_MAX_IRQ],
         }
     }
 
-    pub fn enable_irq(&self, irq: usize, ctx_id: usize) {
-        let plic = self.mmio_vbase as *mut plic::Plic;
+    /// the S-mode context of `hart`,
+    /// qemu virt and most boards have an M-mode and an S-mode context per hart
+    pub fn s_context(hart: usize) -> usize {
+        2 * hart + 1
+    }
+
+    fn plic(&self) -> *mut plic::Plic {
+        self.mmio_vbase as *mut plic::Plic
+    }
+
+    pub fn set_threshold(&self, ctx_id: usize, threshold: u32) {
+        unsafe { (*self.plic()).set_threshold(PLICCtxWrapper::new(ctx_id), threshold) };
+    }
 
-        // Setup PLIC
+    pub fn enable_irq(&self, irq: usize, ctx_id: usize) {
         let src = PLICSrcWrapper::new(irq);
         let ctx = PLICCtxWrapper::new(ctx_id);
-
-        unsafe { (*plic).set_threshold(ctx, 0) };
-        unsafe { (*plic).enable(src, ctx) };
-        unsafe { (*plic).set_priority(src, 6) };
+        unsafe { (*self.plic()).enable(src, ctx) };
+        unsafe { (*self.plic()).set_priority(src, 6) };
     }
 
     pub fn disable_irq(&self, irq: usize, ctx_id: usize) {
-        let plic = self.mmio_vbase as *mut plic::Plic;
-        // Setup PLIC
         let src = PLICSrcWrapper::new(irq);
         let ctx = PLICCtxWrapper::new(ctx_id);
-        unsafe { (*plic).disable(src, ctx) };
+        unsafe { (*self.plic()).disable(src, ctx) };
     }
 
     /// Return the IRQ number of the highest priority pending interrupt
     pub fn claim_irq(&self, ctx_id: usize) -> Option<usize> {
-        let plic = self.mmio_vbase as *mut plic::Plic;
         let ctx = PLICCtxWrapper::new(ctx_id);
-
-        let irq = unsafe { (*plic).claim(ctx) };
+        let irq = unsafe { (*self.plic()).claim(ctx) };
         irq.map(|irq| irq.get() as usize)
     }
 
     pub fn complete_irq(&self, irq: usize, ctx_id: usize) {
-        let plic = self.mmio_vbase as *mut plic::Plic;
         let src = PLICSrcWrapper::new(irq);
         let ctx = PLICCtxWrapper::new(ctx_id);
-        unsafe { (*plic).complete(ctx, src) };
+        unsafe { (*self.plic()).complete(ctx, src) };
     }
 }
 
@@ -113,18 +128,36 @@ impl IrqCtrlHal for IrqCtrl {
     }
 
     fn enable_irq(&self, no: usize) {
-        self.plic.enable_irq(no, 0);
+        let hart = self.plic.routes[no].load(Ordering::Relaxed) as usize;
+        self.plic.enabled[no].store(true, Ordering::Relaxed);
+        self.plic.enable_irq(no, PLIC::s_context(hart));
     }
 
     fn disable_irq(&self, no: usize) {
-        self.plic.disable_irq(no, 0);
+        let hart = self.plic.routes[no].load(Ordering::Relaxed) as usize;
+        self.plic.enabled[no].store(false, Ordering::Relaxed);
+        self.plic.disable_irq(no, PLIC::s_context(hart));
+    }
+
+    fn route_irq(&self, no: usize, hart: usize) {
+        let old = self.plic.routes[no].swap(hart as u8, Ordering::Relaxed) as usize;
+        if old == hart || !self.plic.enabled[no].load(Ordering::Relaxed) {
+            return;
+        }
+        // enable on the new hart first, so no interrupt is lost in between
+        self.plic.enable_irq(no, PLIC::s_context(hart));
+        self.plic.disable_irq(no, PLIC::s_context(old));
+    }
+
+    fn init_hart(&self, hart: usize) {
+        self.plic.set_threshold(PLIC::s_context(hart), 0);
     }
 
     fn claim_irq(&self) -> Option<usize> {
-        self.plic.claim_irq(0)
+        self.plic.claim_irq(PLIC::s_context(Instruction::get_tp()))
     }
 
     fn complete_irq(&self, no: usize) {
-        self.plic.complete_irq(no, 0);
+        self.plic.complete_irq(no, PLIC::s_context(Instruction::get_tp()));
     }
 }
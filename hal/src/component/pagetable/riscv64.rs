@@ -1,4 +1,4 @@
-use core::{arch::asm, ops::Range};
+use core::{arch::asm, ops::Range, sync::atomic::{AtomicUsize, Ordering}};
 
 use alloc::vec::Vec;
 use bitflags::bitflags;
@@ -8,16 +8,60 @@ use crate::{addr::{PhysAddr, PhysAddrHal, PhysPageNum, PhysPageNumHal, RangePPNH
 
 use super::{MapPerm, PageTableEntryHal, PageTableHal};
 
+/// paging modes of the harts, sv39 is the fallback every hart supports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PagingMode {
+    Sv39 = 8,
+    Sv48 = 9,
+}
+
+impl PagingMode {
+    /// the MODE field of satp
+    pub const fn satp_mode(self) -> usize {
+        self as usize
+    }
+
+    pub const fn levels(self) -> usize {
+        match self {
+            PagingMode::Sv39 => 3,
+            PagingMode::Sv48 => 4,
+        }
+    }
+
+    pub const fn va_width(self) -> usize {
+        Constant::PAGE_SIZE_BITS + 9 * self.levels()
+    }
+}
+
+/// the satp mode of the paging mode chosen at boot
+static PAGING_MODE: AtomicUsize = AtomicUsize::new(PagingMode::Sv39 as usize);
+
+/// the paging mode all harts run in
+pub fn paging_mode() -> PagingMode {
+    match PAGING_MODE.load(Ordering::Relaxed) {
+        9 => PagingMode::Sv48,
+        _ => PagingMode::Sv39,
+    }
+}
+
+/// choose the paging mode, only at boot before any page table is built
+pub(crate) fn set_paging_mode(mode: PagingMode) {
+    PAGING_MODE.store(mode.satp_mode(), Ordering::Relaxed);
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum PageLevel {
-    Huge = 0,
-    Big = 1,
-    Small = 2
+    /// only the root of sv48
+    Giant = 0,
+    Huge = 1,
+    Big = 2,
+    Small = 3
 }
 
 impl PageLevel {
     pub const fn page_count(self) -> usize {
         match self {
+            PageLevel::Giant => 512 * 512 * 512,
             PageLevel::Huge => 512 * 512,
             PageLevel::Big => 512,
             PageLevel::Small => 1,
@@ -26,15 +70,19 @@ impl PageLevel {
 
     pub const fn lower(self) -> Self {
         match self {
+            PageLevel::Giant => PageLevel::Huge,
             PageLevel::Huge => PageLevel::Big,
             PageLevel::Big => PageLevel::Small,
             PageLevel::Small => PageLevel::Small,
         }
     }
 
-    pub const fn higher(self) -> Self {
+    pub fn higher(self) -> Self {
+        if self.highest() {
+            return self;
+        }
         match self {
-            PageLevel::Huge => PageLevel::Huge,
+            PageLevel::Giant | PageLevel::Huge => PageLevel::Giant,
             PageLevel::Big => PageLevel::Huge,
             PageLevel::Small => PageLevel::Big,
         }
@@ -47,11 +95,9 @@ impl PageLevel {
         }
     }
 
-    pub const fn highest(self) -> bool {
-        match self {
-            PageLevel::Huge => true,
-            _ => false
-        }
+    /// whether the entries of this level are in the root page table
+    pub fn highest(self) -> bool {
+        self == PageLevel::from(0)
     }
 
     pub const fn from_count(count: usize) -> Option<Self> {
@@ -59,17 +105,20 @@ impl PageLevel {
             0x1 => Some(Self::Small),
             0x200 => Some(Self::Big),
             0x40000 => Some(Self::Huge),
+            0x8000000 => Some(Self::Giant),
             _ => None
         }
     }
 }
 
 impl From<usize> for PageLevel {
+    /// the level of the page table `value` steps below the root
     fn from(value: usize) -> Self {
-        match value {
-            0 => Self::Huge,
-            1 => Self::Big,
-            2 => Self::Small,
+        match Constant::pg_level().checked_sub(value + 1) {
+            Some(0) => Self::Small,
+            Some(1) => Self::Big,
+            Some(2) => Self::Huge,
+            Some(3) => Self::Giant,
             _ => panic!("unsupport Page Level")
         }
     }
@@ -271,7 +320,7 @@ impl<A: FrameAllocatorHal + Clone> PageTable<A> {
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
         let mut result: Option<&mut PageTableEntry> = None;
-        for (i, &idx) in idxs.iter().enumerate() {
+        for (i, &idx) in idxs[..Constant::pg_level()].iter().enumerate() {
             let pte = &mut ppn.start_addr().get_mut::<[PageTableEntry; 512]>()[idx];
             if PageLevel::from(i) == level {
                 result = Some(pte);
@@ -302,7 +351,7 @@ impl<A: FrameAllocatorHal + Clone> PageTableHal<PageTableEntry, A> for PageTable
     }
 
     fn get_token(&self) -> usize {
-        (8usize << 60) | self.root_ppn.0
+        (paging_mode().satp_mode() << 60) | self.root_ppn.0
     }
 
     fn new_in(_: usize, alloc: A) -> Self {
@@ -317,13 +366,14 @@ impl<A: FrameAllocatorHal + Clone> PageTableHal<PageTableEntry, A> for PageTable
 
     fn find_pte(&self, vpn: crate::addr::VirtPageNum) -> Option<(&mut PageTableEntry, usize)> {
         let idxs = vpn.indexes();
+        let levels = Constant::pg_level();
         let mut ppn = self.root_ppn;
-        for (i, idx) in idxs.iter().enumerate() {
+        for (i, idx) in idxs[..levels].iter().enumerate() {
             let pte = &mut ppn.start_addr().get_mut::<[PageTableEntry; 512]>()[*idx];
             if !pte.is_valid() {
                 return None;
             }
-            if pte.is_leaf() || i == 2 {
+            if pte.is_leaf() || i == levels - 1 {
                 return Some((pte, i));
             }
             ppn = pte.ppn();
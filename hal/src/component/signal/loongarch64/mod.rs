@@ -3,7 +3,7 @@
 ///// signal context
 
 use super::UContextHal;
-use crate::{addr::VirtAddr, constant::{Constant, ConstantsHal}, trap::TrapContext};
+use crate::{addr::VirtAddr, constant::{Constant, ConstantsHal}, trap::{TrapContext, TrapContextHal}};
 
 core::arch::global_asm!(include_str!("trampoline.S"));
 
@@ -12,6 +12,8 @@ core::arch::global_asm!(include_str!("trampoline.S"));
 /// machine state
 pub struct MContext {
     pub user_r: [usize; 32],
+    /// f0 ~ f31, fcsr0, then fcc0 ~ fcc7 one byte each,
+    /// the float registers are synced before the frame is built
     pub fpstate: [usize; 66],
 }
 
@@ -74,11 +76,23 @@ impl UContextHal for UContext {
             uc_mcontext: MContext { user_r: cx.r, fpstate: [0; 66]},
         };
         ucx.uc_mcontext.user_r[0] = cx.era;
+        for (i, f) in cx.user_fx.f.iter().enumerate() {
+            ucx.uc_mcontext.fpstate[i] = f.to_bits() as usize;
+        }
+        ucx.uc_mcontext.fpstate[32] = cx.user_fx.fcsr as usize;
+        ucx.uc_mcontext.fpstate[33] = u64::from_le_bytes(cx.user_fx.fcc) as usize;
         ucx
     }
     fn restore_old_context(&self, cx: &mut TrapContext) {
         cx.era = self.uc_mcontext.user_r[0];
         cx.r = self.uc_mcontext.user_r;
+        for (i, f) in cx.user_fx.f.iter_mut().enumerate() {
+            *f = f64::from_bits(self.uc_mcontext.fpstate[i] as u64);
+        }
+        cx.user_fx.fcsr = self.uc_mcontext.fpstate[32] as u32;
+        cx.user_fx.fcc = (self.uc_mcontext.fpstate[33] as u64).to_le_bytes();
+        // the registers still hold what the handler left there
+        cx.fx_invalidate();
     }
 }
 
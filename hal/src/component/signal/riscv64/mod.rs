@@ -3,7 +3,7 @@
 ///// signal context
 
 use super::UContextHal;
-use crate::{constant::{Constant, ConstantsHal}, trap::TrapContext};
+use crate::{constant::{Constant, ConstantsHal}, trap::{TrapContext, TrapContextHal}};
 
 core::arch::global_asm!(include_str!("trampoline.S"));
 
@@ -12,6 +12,7 @@ core::arch::global_asm!(include_str!("trampoline.S"));
 /// machine state
 pub struct MContext {
     pub user_x: [usize; 32],
+    /// f0 ~ f31 then fcsr, the float registers are synced before the frame is built
     pub fpstate: [usize; 66],
 }
 
@@ -74,11 +75,21 @@ impl UContextHal for UContext {
             uc_mcontext: MContext { user_x: cx.x, fpstate: [0; 66]},
         };
         ucx.uc_mcontext.user_x[0] = cx.sepc;
+        for (i, f) in cx.user_fx.fx.iter().enumerate() {
+            ucx.uc_mcontext.fpstate[i] = f.to_bits() as usize;
+        }
+        ucx.uc_mcontext.fpstate[32] = cx.user_fx.fcsr as usize;
         ucx
     }
     fn restore_old_context(&self, cx: &mut TrapContext) {
         cx.sepc = self.uc_mcontext.user_x[0];
         cx.x = self.uc_mcontext.user_x;
+        for (i, f) in cx.user_fx.fx.iter_mut().enumerate() {
+            *f = f64::from_bits(self.uc_mcontext.fpstate[i] as u64);
+        }
+        cx.user_fx.fcsr = self.uc_mcontext.fpstate[32] as u32;
+        // the registers still hold what the handler left there
+        cx.fx_invalidate();
     }
 }
  
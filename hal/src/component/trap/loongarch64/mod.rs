@@ -1,15 +1,19 @@
-use core::{arch::asm, fmt::Debug};
+use core::{arch::asm, fmt::Debug, sync::atomic::{AtomicUsize, Ordering}};
 
 use log::{info, warn};
 use loongArch64::register::{self, estat::{Exception, Interrupt, Trap}};
 
 use crate::{addr::{VirtAddr, VirtAddrHal, VirtPageNum}, allocator::FakeFrameAllocator, board::MAX_PROCESSORS, constant::{Constant, ConstantsHal}, instruction::{Instruction, InstructionHal}, pagetable::{MapPerm, PTEFlags, PageTable, PageTableEntryHal, PageTableHal}, println};
 
-use super::{FloatContextHal, TrapContextHal, TrapType, TrapTypeHal};
+use super::{FloatContextHal, MisalignedAccess, TrapContextHal, TrapType, TrapTypeHal};
 
 core::arch::global_asm!(include_str!("trap.S"));
 
-pub(crate) static mut FP_REG_DIRTY: [bool; MAX_PROCESSORS] = [false; MAX_PROCESSORS];
+/// `last_hart` of a FloatContext no registers were loaded from
+const NO_HART: u8 = u8::MAX;
+
+/// the FloatContext each hart's FP registers were last loaded from
+static FP_OWNER: [AtomicUsize; MAX_PROCESSORS] = [const { AtomicUsize::new(0) }; MAX_PROCESSORS];
 
 impl TrapTypeHal for TrapType {
     fn get() -> Self {
@@ -70,9 +74,12 @@ pub struct KernelContext {
 pub struct FloatContext {
     pub(crate) f: [f64; 32], // 0 ~ 31
     pub(crate) fcsr: u32, // 32
-    pub(crate) need_save: u8, // 32
-    pub(crate) need_restore: u8, // 32
-    pub(crate) signal_dirty: u8, // 32
+    /// condition flags fcc0 ~ fcc7
+    pub(crate) fcc: [u8; 8], // 32
+    /// the registers may hold changes not saved here yet, there is no dirty bit
+    pub(crate) need_save: u8, // 33
+    /// the hart whose registers were last loaded from here
+    pub(crate) last_hart: u8, // 33
 }
 
 impl TrapContextHal for TrapContext {
@@ -133,6 +140,16 @@ impl TrapContextHal for TrapContext {
         &mut self.era
     }
 
+    const ELF_NGREG: usize = 45;
+
+    fn elf_gregs(&self, regs: &mut [usize]) {
+        // user_pt_regs: r0 ~ r31, orig_a0, csr_era, csr_badv, reserved[10]
+        regs[..45].fill(0);
+        regs[..32].copy_from_slice(&self.r);
+        regs[32] = self.r[4];
+        regs[33] = self.era;
+    }
+
     fn app_init_context(entry: usize, sp: usize, argc: usize, argv: usize, envp: usize) -> Self {
         // set CPU privilege to User after trapping back
         unsafe {
@@ -165,20 +182,58 @@ impl TrapContextHal for TrapContext {
     }
 
     fn mark_fx_save(&mut self) {
-        self.user_fx.need_save |= register::euen::read().fpe() as u8;
-        self.user_fx.signal_dirty |= register::euen::read().fpe() as u8;
+        // FP is only on for the task that owns the registers
+        if register::euen::read().fpe() {
+            self.user_fx.need_save = 1;
+        }
     }
 
-    fn fx_yield_task(&mut self) {
-        self.user_fx.yield_task();
+    fn fx_sync(&mut self) {
+        if self.user_fx.need_save != 0 {
+            self.user_fx.save();
+            self.user_fx.need_save = 0;
+        }
     }
 
-    fn fx_encounter_signal(&mut self) {
-        self.user_fx.encounter_signal();
+    fn fx_lazy_restore(&mut self) {
+        let hart = Instruction::get_tp();
+        let owner = FP_OWNER[hart].load(Ordering::Relaxed);
+        let owned = owner == &self.user_fx as *const _ as usize && self.user_fx.last_hart as usize == hart;
+        register::euen::set_fpe(owned);
     }
 
-    fn fx_restore(&mut self) {
+    fn fx_first_use(&mut self) {
+        let hart = Instruction::get_tp();
         self.user_fx.restore();
+        self.user_fx.last_hart = hart as u8;
+        FP_OWNER[hart].store(&self.user_fx as *const _ as usize, Ordering::Relaxed);
+    }
+
+    fn fx_invalidate(&mut self) {
+        self.user_fx.need_save = 0;
+        self.user_fx.last_hart = NO_HART;
+    }
+
+    fn gpr(&self, n: usize) -> usize {
+        self.r[n]
+    }
+
+    fn set_gpr(&mut self, n: usize, v: usize) {
+        if n != 0 {
+            self.r[n] = v;
+        }
+    }
+
+    fn fpr(&mut self, n: usize) -> u64 {
+        // the registers may still be live in the hardware
+        self.fx_sync();
+        self.user_fx.f[n].to_bits()
+    }
+
+    fn set_fpr(&mut self, n: usize, v: u64) {
+        self.fx_sync();
+        self.user_fx.f[n] = f64::from_bits(v);
+        self.fx_invalidate();
     }
 
     // fn save_last_user_arg0(&mut self) {
@@ -192,14 +247,12 @@ impl TrapContextHal for TrapContext {
 
 impl FloatContextHal for FloatContext {
     fn new() -> Self {
-        unsafe { core::mem::zeroed() }
+        let mut fx: Self = unsafe { core::mem::zeroed() };
+        fx.last_hart = NO_HART;
+        fx
     }
 
     fn save(&mut self) {
-        if self.need_save == 0 {
-            return;
-        }
-        self.need_save = 0;
         //warn!("FP save");
         let last_fpe = register::euen::read().fpe();
         register::euen::set_fpe(true);
@@ -240,6 +293,22 @@ impl FloatContextHal for FloatContext {
                 fst.d $f31, {0}, 31*8
                 movfcsr2gr {1}, $fcsr0
                 st.w  {1}, {0}, 32*8
+                movcf2gr {1}, $fcc0
+                st.b  {1}, {0}, 32*8+4
+                movcf2gr {1}, $fcc1
+                st.b  {1}, {0}, 32*8+5
+                movcf2gr {1}, $fcc2
+                st.b  {1}, {0}, 32*8+6
+                movcf2gr {1}, $fcc3
+                st.b  {1}, {0}, 32*8+7
+                movcf2gr {1}, $fcc4
+                st.b  {1}, {0}, 32*8+8
+                movcf2gr {1}, $fcc5
+                st.b  {1}, {0}, 32*8+9
+                movcf2gr {1}, $fcc6
+                st.b  {1}, {0}, 32*8+10
+                movcf2gr {1}, $fcc7
+                st.b  {1}, {0}, 32*8+11
             ", 
             in(reg) self,
             inout(reg) _t
@@ -249,10 +318,6 @@ impl FloatContextHal for FloatContext {
     }
 
     fn restore(&mut self) {
-        if self.need_restore == 0 {
-            return;
-        }
-        self.need_restore = 0;
         //warn!("FP restore");
         let last_fpe = register::euen::read().fpe();
         register::euen::set_fpe(true);
@@ -293,6 +358,22 @@ impl FloatContextHal for FloatContext {
                 fld.d $f31, {0}, 31*8
                 ld.w  {1}, {0}, 32*8
                 movgr2fcsr $fcsr0, {1}
+                ld.bu {1}, {0}, 32*8+4
+                movgr2cf $fcc0, {1}
+                ld.bu {1}, {0}, 32*8+5
+                movgr2cf $fcc1, {1}
+                ld.bu {1}, {0}, 32*8+6
+                movgr2cf $fcc2, {1}
+                ld.bu {1}, {0}, 32*8+7
+                movgr2cf $fcc3, {1}
+                ld.bu {1}, {0}, 32*8+8
+                movgr2cf $fcc4, {1}
+                ld.bu {1}, {0}, 32*8+9
+                movgr2cf $fcc5, {1}
+                ld.bu {1}, {0}, 32*8+10
+                movgr2cf $fcc6, {1}
+                ld.bu {1}, {0}, 32*8+11
+                movgr2cf $fcc7, {1}
             ", 
             in(reg) self,
             inout(reg) _t
@@ -300,15 +381,6 @@ impl FloatContextHal for FloatContext {
         }
         register::euen::set_fpe(last_fpe);
     }
-
-    fn yield_task(&mut self) {
-        self.save();
-        self.need_restore = 1;
-    }
-
-    fn encounter_signal(&mut self) {
-        self.save();
-    }
 }
 
 impl KernelContext {
@@ -494,7 +566,10 @@ fn get_trap_type() -> TrapType {
         Trap::Exception(Exception::LoadPageFault) => TrapType::LoadPageFault(badv),
         Trap::Exception(Exception::StorePageFault) => TrapType::StorePageFault(badv),
         Trap::Exception(Exception::FetchPageFault) => TrapType::InstructionPageFault(badv),
+        // ALE does not tell loads from stores
+        Trap::Exception(Exception::AddressNotAligned) => TrapType::LoadMisaligned(badv),
         Trap::Interrupt(Interrupt::Timer) => TrapType::Timer,
+        Trap::Interrupt(Interrupt::IPI) => TrapType::SoftwareInterrupt,
         Trap::Interrupt(Interrupt::HWI0) |
         Trap::Interrupt(Interrupt::HWI1) |
         Trap::Interrupt(Interrupt::HWI2) |
@@ -506,11 +581,8 @@ fn get_trap_type() -> TrapType {
         Trap::Exception(Exception::PageModifyFault) => {
             handle_page_modify_fault(badv)
         },
-        Trap::Exception(Exception::FloatingPointUnavailable) => {
-            let cpuid = register::cpuid::read().core_id();
-            unsafe { FP_REG_DIRTY[cpuid] = true; }
-            TrapType::Processed
-        },
+        // FP is off for the task until its first FP instruction
+        Trap::Exception(Exception::FloatingPointUnavailable) => TrapType::FpUnavailable,
         _ => {
             log::error!( 
                 "TrapType::Other cause: {:?} badv: {:#x} badi: {:#x} era: {:#x}", 
@@ -524,6 +596,23 @@ fn get_trap_type() -> TrapType {
     }
 }
 
+/// whether misaligned user accesses trap to the kernel,
+/// the cpus running Chronix do them in hardware
+pub fn misaligned_traps() -> bool {
+    false
+}
+
+/// length in bytes of the instruction whose lowest halfword is `low`
+pub fn insn_len(_low: u16) -> usize {
+    4
+}
+
+/// misaligned accesses are not emulated on loongarch,
+/// the cpus running Chronix handle them in hardware unless strict alignment is on
+pub fn decode_misaligned(_insn: u32) -> Option<MisalignedAccess> {
+    None
+}
+
 pub fn restore(cx: &mut TrapContext) {
     unsafe extern "C" {
         fn __restore(cx: usize);
@@ -532,3 +621,39 @@ pub fn restore(cx: &mut TrapContext) {
         __restore(cx as *mut _ as _);
     }
 }
+
+/// the registers `__trap_from_kernel` saves on the kernel stack
+#[repr(C)]
+pub struct KernelTrapFrame {
+    _pad: usize,
+    pub prmd: usize,
+    pub era: usize,
+    pub r21: usize,
+    pub tp: usize,
+    /// a0-a7
+    pub a: [usize; 8],
+    /// t0-t8
+    pub t: [usize; 9],
+    pub fp: usize,
+    pub ra: usize,
+}
+
+impl KernelTrapFrame {
+    /// the pc which trapped
+    pub fn pc(&self) -> usize {
+        self.era
+    }
+
+    /// print the saved registers, the stack pointer is the one before the trap
+    pub fn dump(&self) {
+        let sp = self as *const Self as usize + core::mem::size_of::<Self>();
+        println!("era  {:#018x} prmd    {:#018x} cause {:?} badv {:#x}", self.era, self.prmd, register::estat::read().cause(), register::badv::read().raw());
+        println!("ra   {:#018x} sp      {:#018x} fp    {:#018x}", self.ra, sp, self.fp);
+        println!("tp   {:#018x} r21     {:#018x}", self.tp, self.r21);
+        for (i, a) in self.a.chunks(4).enumerate() {
+            println!("a{}-a{} {:#018x} {:#018x} {:#018x} {:#018x}", i * 4, i * 4 + 3, a[0], a[1], a[2], a[3]);
+        }
+        println!("t0-t4 {:#018x} {:#018x} {:#018x} {:#018x} {:#018x}", self.t[0], self.t[1], self.t[2], self.t[3], self.t[4]);
+        println!("t5-t8 {:#018x} {:#018x} {:#018x} {:#018x}", self.t[5], self.t[6], self.t[7], self.t[8]);
+    }
+}
@@ -1,3 +1,7 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{board::MAX_PROCESSORS, instruction::{Instruction, InstructionHal}};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TrapType {
     Other,
@@ -6,10 +10,40 @@ pub enum TrapType {
     Syscall,
     Timer,
     ExternalInterrupt,
+    /// an inter-processor interrupt from another hart
+    SoftwareInterrupt,
     StorePageFault(usize),
     LoadPageFault(usize),
     InstructionPageFault(usize),
     IllegalInstruction(usize),
+    /// the first FP instruction since FP was turned off for the task
+    FpUnavailable,
+    /// a load from a misaligned address, the address
+    LoadMisaligned(usize),
+    /// a store to a misaligned address, the address
+    StoreMisaligned(usize),
+}
+
+/// the register a misaligned access loads into or stores from
+#[derive(Debug, Clone, Copy)]
+pub enum MisalignedReg {
+    /// a general purpose register
+    Gpr(usize),
+    /// a float register
+    Fpr(usize),
+}
+
+/// a decoded load or store instruction that faulted on a misaligned address
+#[derive(Debug, Clone, Copy)]
+pub struct MisalignedAccess {
+    pub store: bool,
+    /// bytes accessed
+    pub size: usize,
+    /// whether a load sign extends
+    pub signed: bool,
+    pub reg: MisalignedReg,
+    /// length of the instruction in bytes, to step over it
+    pub insn_len: usize,
 }
 
 pub trait TrapTypeHal: Sized {
@@ -45,13 +79,41 @@ pub trait TrapContextHal {
 
     fn load_from(&mut self, idx: usize) -> usize;
 
+    /// on a trap from user, note whether the task changed its FP registers
     fn mark_fx_save(&mut self);
 
-    fn fx_yield_task(&mut self);
+    /// bring the saved FP state up to date with the registers,
+    /// on switching the task out, before a signal frame or a fork copies it
+    fn fx_sync(&mut self);
+
+    /// before returning to user: unless the registers of this hart still hold the task's
+    /// FP state, turn FP off so that the first FP instruction traps to `fx_first_use`
+    fn fx_lazy_restore(&mut self);
+
+    /// the task used FP while it was off: load its saved state into the registers
+    fn fx_first_use(&mut self);
+
+    /// the saved FP state was changed behind the registers' back,
+    /// drop what the registers hold and load it again on the next use
+    fn fx_invalidate(&mut self);
 
-    fn fx_encounter_signal(&mut self);
+    /// number of words in the elf_gregset_t of a NT_PRSTATUS core note
+    const ELF_NGREG: usize;
 
-    fn fx_restore(&mut self);
+    /// write the user registers in the layout of elf_gregset_t
+    fn elf_gregs(&self, regs: &mut [usize]);
+
+    /// read general purpose register `n`
+    fn gpr(&self, n: usize) -> usize;
+
+    /// write general purpose register `n`, writes to the zero register are dropped
+    fn set_gpr(&mut self, n: usize, v: usize);
+
+    /// read the raw bits of float register `n`
+    fn fpr(&mut self, n: usize) -> u64;
+
+    /// write the raw bits of float register `n`, loaded back on return to user
+    fn set_fpr(&mut self, n: usize, v: u64);
 
     // fn save_last_user_arg0(&mut self);
 
@@ -60,13 +122,30 @@ pub trait TrapContextHal {
 pub trait FloatContextHal {
     fn new() -> Self;
 
+    /// store the FP registers here
     fn save(&mut self);
 
+    /// load the FP registers from here
     fn restore(&mut self);
+}
 
-    fn yield_task(&mut self);
+/// the kernel trap frame being handled on each hart, 0 when none
+static KERNEL_TRAP_FRAME: [AtomicUsize; MAX_PROCESSORS] = [const { AtomicUsize::new(0) }; MAX_PROCESSORS];
+
+/// note `frame` as the kernel trap frame being handled on this hart, return the outer one
+pub fn enter_kernel_trap(frame: *const KernelTrapFrame) -> usize {
+    KERNEL_TRAP_FRAME[Instruction::get_tp()].swap(frame as usize, Ordering::Relaxed)
+}
+
+/// the handler of a kernel trap returns, `outer` is handled again
+pub fn leave_kernel_trap(outer: usize) {
+    KERNEL_TRAP_FRAME[Instruction::get_tp()].store(outer, Ordering::Relaxed);
+}
 
-    fn encounter_signal(&mut self);
+/// the registers saved by the innermost kernel trap being handled on this hart
+pub fn kernel_trap_frame() -> Option<&'static KernelTrapFrame> {
+    let frame = KERNEL_TRAP_FRAME[Instruction::get_tp()].load(Ordering::Relaxed);
+    unsafe { (frame as *const KernelTrapFrame).as_ref() }
 }
 
 #[macro_export]
@@ -74,8 +153,10 @@ macro_rules! define_kernel_trap_handler {
     ($fn: ident) => {
         /// hal_kernel_trap_handler_for_arch
         #[unsafe(export_name = "kernel_trap_handler")]
-        pub fn __hal_kernel_trap_handler() {
-            $fn()
+        pub fn __hal_kernel_trap_handler(frame: *const $crate::trap::KernelTrapFrame) {
+            let outer = $crate::trap::enter_kernel_trap(frame);
+            $fn();
+            $crate::trap::leave_kernel_trap(outer);
         }
     };
 }
@@ -1,13 +1,33 @@
-use core::arch::asm;
+use core::{arch::asm, sync::atomic::{AtomicBool, AtomicUsize, Ordering}};
 
 use log::info;
-use riscv::register::{scause::{self, Exception, Interrupt, Trap}, sepc, sstatus::{self, Sstatus, FS, SPP}, stval, stvec::{self, TrapMode}};
+use riscv::register::{scause::{self, Exception, Interrupt, Trap}, sepc, sstatus::{self, FS, SPP}, stval, stvec::{self, TrapMode}};
 
-use crate::instruction::{Instruction, InstructionHal};
+use crate::{board::MAX_PROCESSORS, constant::{Constant, ConstantsHal}, instruction::{Instruction, InstructionHal}};
 
-use super::{FloatContextHal, TrapContextHal, TrapType, TrapTypeHal};
+use super::{FloatContextHal, MisalignedAccess, MisalignedReg, TrapContextHal, TrapType, TrapTypeHal};
 
-core::arch::global_asm!(include_str!("trap.S"));
+core::arch::global_asm!(
+    include_str!("trap.S"),
+    kstack_bottom = const Constant::KERNEL_STACK_BOTTOM as isize,
+    kstack_size_bits = const Constant::KERNEL_STACK_SIZE.trailing_zeros(),
+    kstack_guard = const Constant::KERNEL_STACK_GUARD_SIZE,
+    emergency_stack = sym EMERGENCY_STACK,
+    emergency_stack_size_bits = const EMERGENCY_STACK_SIZE.trailing_zeros(),
+);
+
+/// stack used to report a kernel stack overflow, when the kernel stack itself is unusable
+const EMERGENCY_STACK_SIZE: usize = 4 * Constant::PAGE_SIZE;
+
+static mut EMERGENCY_STACK: [u8; EMERGENCY_STACK_SIZE * MAX_PROCESSORS] = [0; EMERGENCY_STACK_SIZE * MAX_PROCESSORS];
+
+#[unsafe(no_mangle)]
+extern "C" fn __kernel_stack_overflow(sp: usize) -> ! {
+    panic!(
+        "kernel stack overflow on hart {}, sp: {:#x}, sepc: {:#x}, stval: {:#x}",
+        Instruction::get_tp(), sp, sepc::read(), stval::read()
+    );
+}
 
 
 impl TrapTypeHal for TrapType {
@@ -27,8 +47,8 @@ pub struct TrapContext {
     /// general regs[0..31]
     pub(crate) x: [usize; 32],
     /// CSR sstatus      
-    pub(crate) sstatus: Sstatus, // 32
-    // pub sstatus: usize, // 32
+    /// its FS field turns FP off for the task until the first use
+    pub(crate) sstatus: usize, // 32
     /// CSR sepc
     pub(crate) sepc: usize, // 33
 
@@ -90,6 +110,14 @@ impl TrapContextHal for TrapContext {
         &mut self.sepc
     }
 
+    const ELF_NGREG: usize = 32;
+
+    fn elf_gregs(&self, regs: &mut [usize]) {
+        // user_regs_struct: pc takes the slot of the hardwired zero
+        regs[..32].copy_from_slice(&self.x);
+        regs[0] = self.sepc;
+    }
+
     fn app_init_context(
         entry: usize,
         sp: usize,
@@ -104,7 +132,8 @@ impl TrapContextHal for TrapContext {
         }
         let mut cx = Self {
             x: [0; 32],
-            sstatus: sstatus::read(),
+            // FP is off until the first use
+            sstatus: sstatus::read().bits() & !SSTATUS_FS,
             sepc: entry,
             // saved in ___restore
             kernel_sp: 0,
@@ -158,20 +187,62 @@ impl TrapContextHal for TrapContext {
     }
     
     fn mark_fx_save(&mut self) {
-        self.user_fx.need_save |= (self.sstatus.fs() == FS::Dirty) as u8;
-        self.user_fx.signal_dirty |= (self.sstatus.fs() == FS::Dirty) as u8;
+        if self.sstatus & SSTATUS_FS == FS_DIRTY {
+            self.user_fx.need_save = 1;
+        }
     }
-    
-    fn fx_restore(&mut self) {
+
+    fn fx_sync(&mut self) {
+        if self.user_fx.need_save != 0 {
+            self.user_fx.save();
+            self.user_fx.need_save = 0;
+            // the registers and the saved state agree again
+            self.set_fs(FS_CLEAN);
+        }
+    }
+
+    fn fx_lazy_restore(&mut self) {
+        let hart = Instruction::get_tp();
+        let owner = FP_OWNER[hart].load(Ordering::Relaxed);
+        if owner != &self.user_fx as *const _ as usize || self.user_fx.last_hart as usize != hart {
+            self.set_fs(FS_OFF);
+        }
+    }
+
+    fn fx_first_use(&mut self) {
+        let hart = Instruction::get_tp();
         self.user_fx.restore();
+        self.user_fx.last_hart = hart as u8;
+        FP_OWNER[hart].store(&self.user_fx as *const _ as usize, Ordering::Relaxed);
+        self.set_fs(FS_CLEAN);
+    }
+
+    fn fx_invalidate(&mut self) {
+        self.user_fx.need_save = 0;
+        self.user_fx.last_hart = NO_HART;
+        self.set_fs(FS_OFF);
     }
 
-    fn fx_yield_task(&mut self) {
-        self.user_fx.yield_task();
+    fn gpr(&self, n: usize) -> usize {
+        self.x[n]
     }
 
-    fn fx_encounter_signal(&mut self){
-        self.user_fx.encounter_signal();
+    fn set_gpr(&mut self, n: usize, v: usize) {
+        if n != 0 {
+            self.x[n] = v;
+        }
+    }
+
+    fn fpr(&mut self, n: usize) -> u64 {
+        // the registers may still be live in the hardware
+        self.fx_sync();
+        self.user_fx.fx[n].to_bits()
+    }
+
+    fn set_fpr(&mut self, n: usize, v: u64) {
+        self.fx_sync();
+        self.user_fx.fx[n] = f64::from_bits(v);
+        self.fx_invalidate();
     }
 
     // fn save_last_user_arg0(&mut self) {
@@ -183,27 +254,46 @@ impl TrapContextHal for TrapContext {
     // }
 }
 
+/// the FS field of sstatus
+const SSTATUS_FS: usize = 0b11 << 13;
+const FS_OFF: usize = 0;
+const FS_CLEAN: usize = 0b10 << 13;
+const FS_DIRTY: usize = 0b11 << 13;
+
+/// `last_hart` of a FloatContext no registers were loaded from
+const NO_HART: u8 = u8::MAX;
+
+/// the FloatContext each hart's FP registers were last loaded from
+static FP_OWNER: [AtomicUsize; MAX_PROCESSORS] = [const { AtomicUsize::new(0) }; MAX_PROCESSORS];
+
+impl TrapContext {
+    fn set_fs(&mut self, fs: usize) {
+        self.sstatus = self.sstatus & !SSTATUS_FS | fs;
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 #[repr(C)]
 pub struct FloatContext {
     pub fx: [f64; 32],  // 50-81
     pub fcsr: u32,       
+    /// the registers hold changes not saved here yet
     pub need_save: u8,
-    pub need_restore: u8,
-    pub signal_dirty: u8,
+    /// the hart whose registers were last loaded from here
+    pub last_hart: u8,
 }
 
 impl FloatContextHal for FloatContext {
     fn new() -> Self{
-        unsafe {core::mem::zeroed()}
+        let mut fx: Self = unsafe {core::mem::zeroed()};
+        fx.last_hart = NO_HART;
+        fx
     }
     fn save(&mut self) {
-        if self.need_save == 0 {
-            return;
-        }
-        self.need_save = 0;
         //log::warn!("FP save");
         unsafe {
+            // FP may be off for the task the kernel trapped from
+            sstatus::set_fs(FS::Clean);
             let mut _t: usize = 1; // as long as not x0
             asm!("
             fsd  f0,  0*8({0})
@@ -245,21 +335,10 @@ impl FloatContextHal for FloatContext {
             );
         };
     }
-    fn yield_task(&mut self) {
-        self.save();
-        self.need_restore = 1;
-    }
-
-    fn encounter_signal(&mut self){
-        self.save();
-    }
 
     fn restore(&mut self) {
-        if self.need_restore == 0 {
-            return;
-        }
-        self.need_restore = 0;
         //log::warn!("FP restore");
+        unsafe { sstatus::set_fs(FS::Clean) };
         //println!("{:#x}", self as *mut Self as usize);
         unsafe {
             let mut _t: usize = 1; // as long as not x0
@@ -307,6 +386,41 @@ impl FloatContextHal for FloatContext {
 
 pub fn init() {
     set_kernel_trap_entry();
+    if !delegate_misaligned() {
+        MISALIGNED_TRAPS.store(false, Ordering::Relaxed);
+    }
+}
+
+/// the SBI firmware features extension, "FWFT"
+const SBI_EXT_FWFT: usize = 0x4657_4654;
+const SBI_FWFT_SET: usize = 0;
+/// the feature delegating misaligned load and store exceptions to S-mode
+const SBI_FWFT_MISALIGNED_EXC_DELEG: usize = 0;
+
+/// cleared once a hart fails to get the misaligned exceptions delegated
+static MISALIGNED_TRAPS: AtomicBool = AtomicBool::new(true);
+
+/// ask the firmware to delegate misaligned exceptions of this hart to S-mode,
+/// instead of emulating them in M-mode behind the kernel's back
+fn delegate_misaligned() -> bool {
+    let error: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a0") SBI_FWFT_MISALIGNED_EXC_DELEG => error,
+            inlateout("a1") 1usize => _,
+            in("a2") 0usize,
+            in("a6") SBI_FWFT_SET,
+            in("a7") SBI_EXT_FWFT,
+        );
+    }
+    error == 0
+}
+
+/// whether misaligned user accesses trap to the kernel on every hart,
+/// if not the firmware emulates them and SIGBUS can not be delivered
+pub fn misaligned_traps() -> bool {
+    MISALIGNED_TRAPS.load(Ordering::Relaxed)
 }
 
 
@@ -431,9 +545,15 @@ fn get_trap_type() -> TrapType {
         Trap::Exception(Exception::LoadPageFault) => TrapType::LoadPageFault(stval),
         Trap::Exception(Exception::StorePageFault) => TrapType::StorePageFault(stval),
         Trap::Exception(Exception::InstructionPageFault) => TrapType::InstructionPageFault(stval),
+        // FP is off for the task until its first FP instruction
+        Trap::Exception(Exception::IllegalInstruction)
+            if sstatus::read().spp() == SPP::User && sstatus::read().fs() == FS::Off => TrapType::FpUnavailable,
         Trap::Exception(Exception::IllegalInstruction) => TrapType::IllegalInstruction(stval),
+        Trap::Exception(Exception::LoadMisaligned) => TrapType::LoadMisaligned(stval),
+        Trap::Exception(Exception::StoreMisaligned) => TrapType::StoreMisaligned(stval),
         Trap::Interrupt(Interrupt::SupervisorTimer) => TrapType::Timer,
         Trap::Interrupt(Interrupt::SupervisorExternal) => TrapType::ExternalInterrupt,
+        Trap::Interrupt(Interrupt::SupervisorSoft) => TrapType::SoftwareInterrupt,
         _ => {
             info!("scause: {:?}, stval: {:x} sepc: {:x}", scause.cause(), stval, sepc::read());
             TrapType::Other
@@ -441,6 +561,61 @@ fn get_trap_type() -> TrapType {
     }
 }
 
+/// length in bytes of the instruction whose lowest halfword is `low`
+pub fn insn_len(low: u16) -> usize {
+    if low & 0b11 == 0b11 { 4 } else { 2 }
+}
+
+/// decode the load or store `insn` that faulted on a misaligned address,
+/// standard and compressed encodings of the integer and float accesses,
+/// None if it is not one
+pub fn decode_misaligned(insn: u32) -> Option<MisalignedAccess> {
+    let access = |store: bool, size: usize, signed: bool, reg: MisalignedReg, len: usize| {
+        Some(MisalignedAccess { store, size, signed, reg, insn_len: len })
+    };
+    if insn_len(insn as u16) == 4 {
+        let rd = (insn >> 7 & 0x1f) as usize;
+        let rs2 = (insn >> 20 & 0x1f) as usize;
+        let funct3 = insn >> 12 & 0x7;
+        match (insn & 0x7f, funct3) {
+            // LB LH LW LD LBU LHU LWU
+            (0x03, 0..=6) => access(false, 1 << (funct3 & 0x3), funct3 < 4, MisalignedReg::Gpr(rd), 4),
+            // SB SH SW SD
+            (0x23, 0..=3) => access(true, 1 << funct3, false, MisalignedReg::Gpr(rs2), 4),
+            // FLW FLD
+            (0x07, 2..=3) => access(false, 1 << funct3, false, MisalignedReg::Fpr(rd), 4),
+            // FSW FSD
+            (0x27, 2..=3) => access(true, 1 << funct3, false, MisalignedReg::Fpr(rs2), 4),
+            _ => None,
+        }
+    } else {
+        let insn = insn as u16;
+        // rd' and rs2' of quadrant 0 name x8 ~ x15 and f8 ~ f15
+        let rd_short = (insn >> 2 & 0x7) as usize + 8;
+        let rd = (insn >> 7 & 0x1f) as usize;
+        let rs2 = (insn >> 2 & 0x1f) as usize;
+        match (insn & 0x3, insn >> 13) {
+            // C.FLD C.LW C.LD
+            (0, 1) => access(false, 8, false, MisalignedReg::Fpr(rd_short), 2),
+            (0, 2) => access(false, 4, true, MisalignedReg::Gpr(rd_short), 2),
+            (0, 3) => access(false, 8, true, MisalignedReg::Gpr(rd_short), 2),
+            // C.FSD C.SW C.SD
+            (0, 5) => access(true, 8, false, MisalignedReg::Fpr(rd_short), 2),
+            (0, 6) => access(true, 4, false, MisalignedReg::Gpr(rd_short), 2),
+            (0, 7) => access(true, 8, false, MisalignedReg::Gpr(rd_short), 2),
+            // C.FLDSP C.LWSP C.LDSP
+            (2, 1) => access(false, 8, false, MisalignedReg::Fpr(rd), 2),
+            (2, 2) if rd != 0 => access(false, 4, true, MisalignedReg::Gpr(rd), 2),
+            (2, 3) if rd != 0 => access(false, 8, true, MisalignedReg::Gpr(rd), 2),
+            // C.FSDSP C.SWSP C.SDSP
+            (2, 5) => access(true, 8, false, MisalignedReg::Fpr(rs2), 2),
+            (2, 6) => access(true, 4, false, MisalignedReg::Gpr(rs2), 2),
+            (2, 7) => access(true, 8, false, MisalignedReg::Gpr(rs2), 2),
+            _ => None,
+        }
+    }
+}
+
 pub fn restore(cx: &mut TrapContext) {
     unsafe extern "C" {
         fn __restore(cx: usize);
@@ -449,3 +624,38 @@ pub fn restore(cx: &mut TrapContext) {
         __restore(cx as *mut _ as _);
     }
 }
+
+/// the registers `__trap_from_kernel` saves on the kernel stack
+#[repr(C)]
+pub struct KernelTrapFrame {
+    _pad: usize,
+    pub sepc: usize,
+    pub sstatus: usize,
+    /// t0-t2
+    pub t: [usize; 3],
+    /// t3-t6
+    pub t_hi: [usize; 4],
+    /// a0-a7
+    pub a: [usize; 8],
+    pub fp: usize,
+    pub ra: usize,
+}
+
+impl KernelTrapFrame {
+    /// the pc which trapped
+    pub fn pc(&self) -> usize {
+        self.sepc
+    }
+
+    /// print the saved registers, the stack pointer is the one before the trap
+    pub fn dump(&self) {
+        let sp = self as *const Self as usize + core::mem::size_of::<Self>();
+        crate::println!("sepc {:#018x} sstatus {:#018x} scause {:#x} stval {:#x}", self.sepc, self.sstatus, scause::read().bits(), stval::read());
+        crate::println!("ra   {:#018x} sp      {:#018x} fp     {:#018x}", self.ra, sp, self.fp);
+        for (i, a) in self.a.chunks(4).enumerate() {
+            crate::println!("a{}-a{} {:#018x} {:#018x} {:#018x} {:#018x}", i * 4, i * 4 + 3, a[0], a[1], a[2], a[3]);
+        }
+        crate::println!("t0-t2 {:#018x} {:#018x} {:#018x}", self.t[0], self.t[1], self.t[2]);
+        crate::println!("t3-t6 {:#018x} {:#018x} {:#018x} {:#018x}", self.t_hi[0], self.t_hi[1], self.t_hi[2], self.t_hi[3]);
+    }
+}
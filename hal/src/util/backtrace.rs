@@ -37,4 +37,52 @@ pub fn backtrace() {
             current_pc = *(current_fp as *const usize).offset(-1);
         }
     }
-}
\ No newline at end of file
+}
+
+/// fill `buf` with the return addresses of the callers of the caller, innermost first,
+/// return how many were found
+#[inline(never)]
+pub fn return_addrs(buf: &mut [usize]) -> usize {
+    unsafe extern "C" {
+        fn stext();
+        fn etext();
+    }
+    let mut current_pc: usize;
+    let mut current_fp: usize;
+    unsafe {
+        #[cfg(target_arch="riscv64")]
+        core::arch::asm!(
+            r"
+            mv {}, ra
+            mv {}, fp
+            ",
+            out(reg) current_pc,
+            out(reg) current_fp
+        );
+
+        #[cfg(target_arch="loongarch64")]
+        core::arch::asm!(
+            r"
+            move {}, $ra
+            move {}, $fp
+            ",
+            out(reg) current_pc,
+            out(reg) current_fp
+        );
+    }
+    let mut n = 0;
+    while n < buf.len() && current_pc >= stext as usize && current_pc <= etext as usize && current_fp != 0 {
+        buf[n] = current_pc - size_of::<usize>();
+        n += 1;
+        unsafe {
+            let caller_fp = *(current_fp as *const usize).offset(-2);
+            // the callers sit higher on the stack, anything else is a broken chain
+            if caller_fp <= current_fp || caller_fp % size_of::<usize>() != 0 {
+                break;
+            }
+            current_fp = caller_fp;
+            current_pc = *(current_fp as *const usize).offset(-1);
+        }
+    }
+    n
+}
@@ -2,6 +2,6 @@ pub mod smart_point;
 pub mod mutex;
 pub mod sie_guard;
 mod backtrace;
-pub use backtrace::backtrace;
+pub use backtrace::{backtrace, return_addrs};
 pub mod bitfield;
 pub(crate) mod timer;
\ No newline at end of file
@@ -2,9 +2,64 @@ use core::{alloc::Layout, ops::{Deref, DerefMut}, ptr::{self, NonNull}, sync::at
 
 use alloc::{alloc::{handle_alloc_error, Allocator, Global}, rc};
 
+use crate::println;
+
+/// the most owners a payload counts, past it the count is stuck and the payload leaked,
+/// so no wrap around can free it under a live owner
+const MAX_OWNERS: usize = isize::MAX as usize;
+
+/// who made a StrongArc, the live ones are counted per tag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ArcTag {
+    /// made by `new`, no subsystem given
+    Untagged = 0,
+    /// a page of a user address space
+    Anon,
+    /// a page of the page cache, shared memory included
+    PageCache,
+    /// the zero page mapped for reads of untouched anonymous memory
+    ZeroPage,
+}
+
+/// the number of kinds of [`ArcTag`]
+pub const ARC_TAGS: usize = 4;
+
+const ARC_TAG_NAMES: [&str; ARC_TAGS] = ["untagged", "anon", "page cache", "zero page"];
+
+/// the live payloads of one tag
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ArcCount {
+    /// payloads with at least one owner
+    pub live: usize,
+    /// payloads with more than one owner
+    pub shared: usize,
+}
+
+/// the live and the shared payloads of each tag
+static LIVE: [AtomicUsize; ARC_TAGS] = [const { AtomicUsize::new(0) }; ARC_TAGS];
+static SHARED: [AtomicUsize; ARC_TAGS] = [const { AtomicUsize::new(0) }; ARC_TAGS];
+
+/// the live payloads of every tag, indexed by [`ArcTag`]
+pub fn tally() -> [ArcCount; ARC_TAGS] {
+    core::array::from_fn(|i| ArcCount {
+        live: LIVE[i].load(Ordering::Relaxed),
+        shared: SHARED[i].load(Ordering::Relaxed),
+    })
+}
+
+/// print the tally, neither allocates nor locks so the oom and panic paths can call it
+pub fn debug_dump() {
+    println!("[StrongArc] live (shared) payloads:");
+    for (name, count) in ARC_TAG_NAMES.iter().zip(tally()) {
+        println!("  {:<10} {} ({})", name, count.live, count.shared);
+    }
+}
+
 struct StrongArcPayload<T> {
     data: T,
     rc: AtomicUsize,
+    tag: ArcTag,
 }
 
 impl<T> StrongArcPayload<T> {
@@ -28,8 +83,14 @@ unsafe impl<T: Send + Sync, A: Allocator + Clone + Send + Sync> Sync for StrongA
 
 impl<T, A: Allocator + Clone> Clone for StrongArc<T, A> {
     fn clone(&self) -> Self {
-        unsafe {
-            self.payload.as_ref().rc.fetch_add(1, Ordering::Release);
+        let payload = unsafe { self.payload.as_ref() };
+        let old = payload.rc.fetch_add(1, Ordering::Release);
+        if old == 1 {
+            SHARED[payload.tag as usize].fetch_add(1, Ordering::Relaxed);
+        } else if old >= MAX_OWNERS {
+            // leak the payload rather than let the count wrap and free it early
+            payload.rc.store(MAX_OWNERS, Ordering::Relaxed);
+            debug_assert!(false, "StrongArc owner count overflow, tag {:?}", payload.tag);
         }
         Self { payload: self.payload.clone(), alloc: self.alloc.clone() }
     }
@@ -40,19 +101,32 @@ impl<T> StrongArc<T, Global> {
     pub fn new(data: T) -> Self {
         Self::new_in(data, Global)
     }
+
+    /// a StrongArc counted under `tag` in the tally
+    pub fn new_tagged(data: T, tag: ArcTag) -> Self {
+        Self::new_tagged_in(data, tag, Global)
+    }
 }
 
 #[allow(unused, missing_docs)]
 impl<T, A: Allocator + Clone> StrongArc<T, A> {
     pub fn new_in(data: T, alloc: A) -> Self {
+        Self::new_tagged_in(data, ArcTag::Untagged, alloc)
+    }
+
+    pub fn new_tagged_in(data: T, tag: ArcTag, alloc: A) -> Self {
         let mut ret = Self {
             payload: NonNull::dangling(),
             alloc,
         };
-        ret.alloc_payload(data);
+        ret.alloc_payload(data, tag);
         ret
     }
 
+    pub fn tag(&self) -> ArcTag {
+        unsafe { self.payload.as_ref().tag }
+    }
+
     pub fn get_owners(&self) -> usize {
         unsafe {
             self.payload.as_ref().get_rc()
@@ -60,12 +134,24 @@ impl<T, A: Allocator + Clone> StrongArc<T, A> {
     }
 
     pub fn emplace(&mut self, val: T) {
+        self.emplace_tagged(val, self.tag());
+    }
+
+    /// let this owner hold `val` alone, counted under `tag`
+    pub fn emplace_tagged(&mut self, val: T, tag: ArcTag) {
         let rc_ref = unsafe { &self.payload.as_ref().rc };
         let mut oval = rc_ref.load(Ordering::Acquire);
         loop {
             if oval == 1 {
+                let old_tag = self.tag();
+                if old_tag != tag {
+                    LIVE[old_tag as usize].fetch_sub(1, Ordering::Relaxed);
+                    LIVE[tag as usize].fetch_add(1, Ordering::Relaxed);
+                }
                 unsafe { 
-                    self.payload.as_mut().data = val 
+                    let payload = self.payload.as_mut();
+                    payload.data = val;
+                    payload.tag = tag;
                 };
                 break;
             } else {
@@ -75,7 +161,10 @@ impl<T, A: Allocator + Clone> StrongArc<T, A> {
                 ) {
                     Ok(_) => {
                         core::sync::atomic::fence(Ordering::Release);
-                        self.alloc_payload(val);
+                        if oval == 2 {
+                            SHARED[self.tag() as usize].fetch_sub(1, Ordering::Relaxed);
+                        }
+                        self.alloc_payload(val, tag);
                         break;
                     }
                     Err(v) => oval = v
@@ -84,7 +173,27 @@ impl<T, A: Allocator + Clone> StrongArc<T, A> {
         }
     }
 
-    fn alloc_payload(&mut self, data: T) {
+    /// take the inner data out if this is the only owner
+    pub fn try_unwrap(self) -> Result<T, Self> {
+        let rc_ref = unsafe { &self.payload.as_ref().rc };
+        if rc_ref.compare_exchange(1, 0, Ordering::AcqRel, Ordering::Relaxed).is_err() {
+            return Err(self);
+        }
+        core::sync::atomic::fence(Ordering::Acquire);
+        LIVE[self.tag() as usize].fetch_sub(1, Ordering::Relaxed);
+        let this = core::mem::ManuallyDrop::new(self);
+        unsafe {
+            let data = ptr::read(&this.payload.as_ref().data);
+            let alloc = ptr::read(&this.alloc);
+            alloc.deallocate(
+                this.payload.cast(),
+                Layout::new::<StrongArcPayload<T>>()
+            );
+            Ok(data)
+        }
+    }
+
+    fn alloc_payload(&mut self, data: T, tag: ArcTag) {
         let layout = Layout::new::<StrongArcPayload<T>>();
         match self.alloc.allocate(layout) {
             Ok(p) => {
@@ -92,10 +201,12 @@ impl<T, A: Allocator + Clone> StrongArc<T, A> {
                 unsafe {
                     ptr::write(payload.as_ptr(), StrongArcPayload {
                         data,
-                        rc: AtomicUsize::new(1)
+                        rc: AtomicUsize::new(1),
+                        tag,
                     });
                 }
                 self.payload = payload;
+                LIVE[tag as usize].fetch_add(1, Ordering::Relaxed);
             },
             Err(_) => handle_alloc_error(layout)
         }
@@ -116,10 +227,25 @@ impl<T, A: Allocator + Clone> DerefMut for StrongArc<T, A> {
     }
 }
 
+/// The last owner frees the payload right away, with the destructor of `T` and the
+/// deallocation of `A`. For the frames these take only the frame allocator and the heap
+/// locks, which are held with the interrupts off, so the last owner may go away in an
+/// interrupt-disabled context or a handler, just not while this hart holds those locks
+/// itself; their lock panics on such a dead lock instead of hanging.
 impl<T, A: Allocator + Clone> Drop for StrongArc<T, A> {
     fn drop(&mut self) {
-        let rc_ref = unsafe { &self.payload.as_ref().rc };
-        if rc_ref.fetch_sub(1, Ordering::Release) == 1 {
+        let payload = unsafe { self.payload.as_ref() };
+        let rc_ref = &payload.rc;
+        if rc_ref.load(Ordering::Relaxed) >= MAX_OWNERS {
+            // overflowed once, leaked for good
+            return;
+        }
+        let old = rc_ref.fetch_sub(1, Ordering::Release);
+        if old == 2 {
+            SHARED[payload.tag as usize].fetch_sub(1, Ordering::Relaxed);
+        }
+        if old == 1 {
+            LIVE[payload.tag as usize].fetch_sub(1, Ordering::Relaxed);
             unsafe {
                 core::sync::atomic::fence(Ordering::Acquire);
                 ptr::drop_in_place(&mut self.payload.as_mut().data);
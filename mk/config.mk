@@ -13,6 +13,28 @@ GW ?= 10.0.2.2
 export GATEWAY=$(GW)
 export IP=$(IP_C)
 export NT :=
+# kernel command line, e.g. BOOTARGS="gateway=10.0.2.2" overrides the built-in gateway,
+# BOOTARGS="paging=sv39" keeps riscv64 on sv39 even where the harts support sv48
+# BOOTARGS="stack-size=8M mmap-window=4G share-window=4G" sizes the user stack and mmap areas
+BOOTARGS ?=
+
+# power off cleanly when init exits, instead of panicking
+INIT_EXIT_POWEROFF ?=n
+
+# a cpio (newc) archive to build into the kernel, e.g. PAYLOAD=tests.cpio:
+# the kernel boots from it in memory and runs its /init, no disk attached,
+# unless BOOTARGS="root=disk". `make payload` builds one from the user apps
+PAYLOAD ?=
+ifneq ($(PAYLOAD),)
+# the payload runs once, its exit ends the run
+INIT_EXIT_POWEROFF := y
+endif
+
+# record the recent large kernel heap allocations, printed on allocation failure
+HEAP_TRACE ?=n
+
+# test the core kernel subsystems during boot, with BOOTARGS="selftest=fatal" a failure stops it
+SELFTEST ?=n
 
 # Disk file system
 FS := ext4
@@ -23,4 +45,5 @@ SBI ?= rustsbi
 
 # Binutils
 OBJDUMP := rust-objdump --arch-name=${ARCH}
-OBJCOPY := rust-objcopy --binary-architecture=${ARCH}
\ No newline at end of file
+OBJCOPY := rust-objcopy --binary-architecture=${ARCH}
+NM := rust-nm
\ No newline at end of file
@@ -48,4 +48,36 @@ disk-img: user
 	cp $(DISK_IMG) $(DISK_IMG_COPY)
 	$(call success, "cp $(DISK_IMG) to $(DISK_IMG_COPY) finished")
 
-.PHONY: disk-img
+# the image a run used must be clean after the kernel powered off
+disk-fsck:
+	$(call building, "checking $(DISK_IMG_COPY)")
+	e2fsck -fn $(DISK_IMG_COPY)
+	$(call success, "$(DISK_IMG_COPY) is clean")
+
+.PHONY: disk-img disk-fsck
+
+########################################################
+# INITRAMFS PAYLOAD
+########################################################
+
+# the user apps put in bin/ of the archive, the first one is also /init
+PAYLOAD_APPS ?= test_dirfd
+PAYLOAD_DIR := ./payload
+ifeq ($(ARCH), riscv64)
+PAYLOAD_ELF_DIR := $(USER_TARGET_RV_DIR)
+else ifeq ($(ARCH), loongarch64)
+PAYLOAD_ELF_DIR := $(USER_TARGET_LA_DIR)
+endif
+
+# build the archive to boot with PAYLOAD=, tests.cpio unless PAYLOAD names another
+payload: user
+	$(call building, "building $(or $(PAYLOAD),tests.cpio) from $(PAYLOAD_APPS)")
+	rm -rf $(PAYLOAD_DIR)
+	mkdir -p $(PAYLOAD_DIR)/bin $(PAYLOAD_DIR)/tmp
+	cp $(addprefix $(PAYLOAD_ELF_DIR)/, $(PAYLOAD_APPS)) $(PAYLOAD_DIR)/bin
+	cp $(PAYLOAD_ELF_DIR)/$(firstword $(PAYLOAD_APPS)) $(PAYLOAD_DIR)/init
+	cd $(PAYLOAD_DIR) && find . | cpio -o -H newc --owner 0:0 > ../$(or $(PAYLOAD),tests.cpio)
+	rm -rf $(PAYLOAD_DIR)
+	$(call success, "$(or $(PAYLOAD),tests.cpio) finished")
+
+.PHONY: payload
@@ -17,6 +17,22 @@ ifeq ($(NET_C),y)
 KERNEL_FEATURES += net
 endif
 
+ifeq ($(INIT_EXIT_POWEROFF),y)
+KERNEL_FEATURES += init_exit_poweroff
+endif
+
+ifeq ($(HEAP_TRACE),y)
+KERNEL_FEATURES += heap_trace
+endif
+
+ifneq ($(PAYLOAD),)
+KERNEL_FEATURES += initramfs
+endif
+
+ifeq ($(SELFTEST),y)
+KERNEL_FEATURES += selftest
+endif
+
 # kernel target
 ifeq ($(ARCH), riscv64)
 KERNEL_TARGET := riscv64gc-unknown-none-elf
@@ -45,6 +61,16 @@ endif
 KERNEL_ELF := ./target/$(KERNEL_TARGET)/$(KERNEL_MODE)/os
 KERNEL_BIN := $(KERNEL_ELF).bin
 DISASM_TMP := $(KERNEL_ELF).asm
+# symbol table of the kernel for the panic backtrace, see scripts/ksyms.py
+KSYMS := os/ksyms.bin
+# where the PAYLOAD archive is built into the kernel from, see os/src/fs/initramfs.rs
+KERNEL_PAYLOAD := os/initramfs.cpio
+
+ifeq ($(KERNEL_FEATURES), )
+KERNEL_BUILD := cd os && cargo build $(KERNEL_TARGET_ARG) $(KERNEL_MODE_ARG)
+else
+KERNEL_BUILD := cd os && cargo build $(KERNEL_TARGET_ARG) $(KERNEL_MODE_ARG) --features "$(KERNEL_FEATURES)"
+endif
 
 # kernel in binary
 kernel-bin: kernel
@@ -59,11 +85,16 @@ kernel: dumpdtb
 	@rm -rf hal/.cargo
 	@cp -r os/cargo os/.cargo
 	@cp -r hal/cargo hal/.cargo
-ifeq ($(KERNEL_FEATURES), ) 
-	@cd os && cargo build $(KERNEL_TARGET_ARG) $(KERNEL_MODE_ARG)
-else
-	@cd os && cargo build $(KERNEL_TARGET_ARG) $(KERNEL_MODE_ARG) --features "$(KERNEL_FEATURES)"
+	@python3 scripts/ksyms.py --empty $(KSYMS)
+ifneq ($(PAYLOAD),)
+	@cp $(PAYLOAD) $(KERNEL_PAYLOAD)
 endif
+	@($(KERNEL_BUILD))
+	@# the table is linked after all code, building again with it moves nothing
+	@for pass in 1 2; do \
+		python3 scripts/ksyms.py $(NM) $(KERNEL_ELF) $(KSYMS) && break; \
+		($(KERNEL_BUILD)) || exit 1; \
+	done
 	@rm os/src/linker.ld
 	$(call success, "kernel $(KERNEL_ELF) finish building")
 
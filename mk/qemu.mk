@@ -32,6 +32,12 @@ ifneq ($(SMP),)
 QEMU_ARGS += -smp $(CPU)
 endif
 
+ifneq ($(BOOTARGS),)
+QEMU_RUN_ARGS += -append "$(BOOTARGS)"
+endif
+
+# a kernel with a PAYLOAD runs from memory without the disks
+ifeq ($(PAYLOAD),)
 ifeq ($(ARCH), riscv64)
 QEMU_ARGS += -drive file=sdcard-rv.img,if=none,format=raw,id=x0
 QEMU_ARGS += -device virtio-blk-device,drive=x0,bus=virtio-mmio-bus.0
@@ -43,6 +49,7 @@ QEMU_ARGS += -device virtio-blk-pci,drive=x0
 QEMU_ARGS += -drive file=$(DISK_IMG_COPY),if=none,format=raw,id=x1
 QEMU_ARGS += -device virtio-blk-pci,drive=x1
 endif
+endif
 
 ifeq ($(NET_C),y)
 $(info "enable qemu net device")
@@ -75,8 +82,10 @@ endif
 
 # debug: using tmux
 debug: build
+ifeq ($(PAYLOAD),)
 	$(call building, "cp $(DISK_IMG) to $(DISK_IMG_COPY)")
 	@cp $(DISK_IMG) $(DISK_IMG_COPY)
+endif
 	@tmux new-session -d \
 		"$(QEMU) $(QEMU_ARGS) $(QEMU_RUN_ARGS) -s -S" && \
 		tmux split-window -h "$(GDB) -ex 'file $(KERNEL_ELF)' -ex 'set arch $(GDB_ARCH)' -ex 'target remote localhost:1234'" && \
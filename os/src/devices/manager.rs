@@ -3,11 +3,10 @@
 use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
 use fdt::Fdt;
 use hal::{board::MAX_PROCESSORS, constant::{Constant, ConstantsHal}, instruction::{Instruction, InstructionHal}, irq::{IrqCtrl, IrqCtrlHal}, pagetable::MapPerm, println};
-use virtio_drivers::transport::Transport;
 
-use crate::{drivers::{block::{VirtIOMMIOBlock, VirtIOPCIBlock}, serial::UART0}, mm::{vm::{KernVmArea, KernVmAreaType, KernVmSpaceHal}, MmioMapper, KVMSPACE}, processor::processor::PROCESSORS};
+use crate::{mm::{vm::{KernVmArea, KernVmAreaType, KernVmSpaceHal}, MmioMapper, KVMSPACE}, processor::processor::{online_harts, PROCESSORS}};
 
-use super::{mmio::MmioManager, pci::{PciDeviceClass, PciManager}, plic::{scan_plic_device, PLIC}, serial::scan_char_device, DevId, Device, DeviceMajor};
+use super::{mmio::MmioManager, pci::PciManager, plic::{scan_plic_device, PLIC}, registry, DevId, Device, DeviceMajor};
 
 type IrqNo = usize;
 
@@ -26,7 +25,11 @@ pub struct DeviceManager {
     /// mapping from device id to device instance
     pub devices: BTreeMap<DevId, Arc<dyn Device>>,
     /// mapping from irq no to device instance
-    pub irq_map: BTreeMap<IrqNo, Arc<dyn Device>>
+    pub irq_map: BTreeMap<IrqNo, Arc<dyn Device>>,
+    /// the hart an irq is pinned to by its driver, the others are spread round robin
+    pub irq_affinity: BTreeMap<IrqNo, usize>,
+    /// how many times each irq fired
+    pub irq_count: BTreeMap<IrqNo, usize>,
 }
 
 impl DeviceManager {
@@ -38,13 +41,11 @@ impl DeviceManager {
             mmio: None,
             devices: BTreeMap::new(),
             irq_map: BTreeMap::new(),
+            irq_affinity: BTreeMap::new(),
+            irq_count: BTreeMap::new(),
         }
     }
 
-    
-    fn irq_ctrl(&self) -> &IrqCtrl {
-        self.irq_ctrl.as_ref().unwrap()
-    }
 
     fn pci(&self) -> &PciManager {
         self.pci.as_ref().unwrap()
@@ -54,63 +55,22 @@ impl DeviceManager {
         self.mmio.as_ref().unwrap()
     }
 
-    /// Device Init Stage1: scan the whole device tree and create instances
+    /// Device Init Stage1: scan the whole device tree and the buses on it,
+    /// and create the instances the drivers of the registry probe
     /// map DevId to device, map IrqNo to device
     pub fn map_devices(&mut self, device_tree: &Fdt) {
-        // map char device
-        let serial = scan_char_device(device_tree);
-        self.devices.insert(serial.dev_id(), serial.clone());
-        self.irq_map.insert(serial.irq_no().unwrap(), serial.clone());
-
         if let Some(irq_ctrl) = IrqCtrl::from_dt(device_tree, MmioMapper) {
             self.irq_ctrl = Some(irq_ctrl);
         }
-        
-        if let Some(mut pci) = PciManager::scan_pcie_root(device_tree) {
-            for mut device in pci.enumerate_devices() {
-                // pci bus has an advantage: no need to map or allocate 
-                // memory to recognize the device type.
-                let dev_class: PciDeviceClass = device.func_info.class.into();
-                let dev = match dev_class {
-                    PciDeviceClass::MassStorageContorller => {
-                        pci.init_device(&mut device).unwrap();
-                        Arc::new(VirtIOPCIBlock::new(device))
-                    }
-                    _ => continue
-                };
-
-                if let Some(irq_no) = dev.irq_no() {
-                    self.irq_map.insert(irq_no, dev.clone());
-                }
-                self.devices.insert(dev.dev_id(), dev);
-            }
-            self.pci = Some(pci);
-        }
-        
-        let mmio = MmioManager::scan_mmio_root(device_tree);
-        for deivce in mmio.enumerate_devices() {
-            if let Ok(mmio_transport) = deivce.transport() {
-
-                let dev = match mmio_transport.device_type() {
-                    virtio_drivers::transport::DeviceType::Block => {
-                        Arc::new(VirtIOMMIOBlock::new(deivce.clone(), mmio_transport))
-                    }
-                    _ => continue
-                };
-
-                if let Some(irq_no) = dev.irq_no() {
-                    self.irq_map.insert(irq_no, dev.clone());
-                }
-                self.devices.insert(dev.dev_id(), dev);
-            }
-        }
-        self.mmio = Some(mmio);
+        self.pci = PciManager::scan_pcie_root(device_tree);
+        self.mmio = Some(MmioManager::scan_mmio_root(device_tree));
 
-        // let plic = scan_plic_device(device_tree);
-        // if let Some(plic) = plic {
-        //     self.plic = Some(plic);
-        // }
-        // TODO
+        let found = registry::discover(self, device_tree);
+        registry::probe_all(self, found);
+
+        for dev in self.devices.values() {
+            log::info!("[Device Manager]: found {} {:?}, mmio {:x?}, irq {:?}", dev.name(), dev.dtype(), dev.mmio_ranges(), dev.irq_no());
+        }
     }
 
     /// Device Init Stage2: map the mmio region
@@ -176,62 +136,108 @@ impl DeviceManager {
     }
 
     /// using given device name and major to find devices
-    pub fn find_dev_by_name(&self, name: &str, major: DeviceMajor) -> Arc<dyn Device> {
+    pub fn find_dev_by_name(&self, name: &str, major: DeviceMajor) -> Option<Arc<dyn Device>> {
         self.devices
             .iter()
             .find(|(dev_id, dev)| 
             dev_id.major == major && dev.meta().name == name)
             .map(|(_, dev)| dev.clone())
-            .expect("device not found")
+    }
+
+    /// the device of the given (major, minor)
+    pub fn find_dev(&self, dev_id: DevId) -> Option<Arc<dyn Device>> {
+        self.devices.get(&dev_id).cloned()
+    }
+
+    /// add a device, and route its irq to it
+    pub fn register_device(&mut self, dev: Arc<dyn Device>) {
+        if let Some(irq_no) = dev.irq_no() {
+            self.irq_map.insert(irq_no, dev.clone());
+        }
+        self.devices.insert(dev.dev_id(), dev);
     }
 
     /// enable interrupt for device
     pub fn enable_irq(&mut self) {
-        #[cfg(feature="smp")]
-        use hal::board::MAX_PROCESSORS;
-        #[cfg(feature="smp")]
-        // todo!
-        for i in 0..MAX_PROCESSORS * 2 {
-            for dev in self.devices.values() {
-                if let Some(irq) = dev.irq_no() {
-                    self.irq_ctrl().enable_irq(irq);
-                    log::info!("Enable external interrupt:{irq}, context:{i}");
-                }
-            }
-        }
-        #[cfg(not(feature="smp"))]
-        for i in 0..2 {
-            for dev in self.devices.values() {
-                if let Some(irq) = dev.irq_no() {
-                    self.irq_ctrl().enable_irq(irq);
-                    log::info!("Enable external interrupt:{irq}, context:{i}");
-                }
-            }
+        let Some(irq_ctrl) = self.irq_ctrl.as_ref() else {
+            log::warn!("[Device Manager]: no interrupt controller, devices are polled");
+            return;
+        };
+        for &irq in self.irq_map.keys() {
+            irq_ctrl.enable_irq(irq);
+            log::info!("Enable external interrupt:{irq}");
         }
+        self.balance_irqs();
+    }
+
+    /// let `hart` take external interrupts, and spread the irqs over the online harts again
+    pub fn init_hart_irq(&self, hart: usize) {
+        let Some(irq_ctrl) = self.irq_ctrl.as_ref() else {
+            return;
+        };
+        irq_ctrl.init_hart(hart);
+        self.balance_irqs();
         unsafe {
             Instruction::enable_external_interrupt();
         }
     }
-    /// handle interrupt
-    pub fn handle_irq(&self) {
-        fn irq_ctx() -> usize {
-            #[cfg(not(feature="smp"))]
-            {
-                1
-            }
-            #[cfg(feature="smp")]
-            {
-                todo!()
-            }
+
+    /// route every irq not pinned by its driver to the online harts round robin
+    fn balance_irqs(&self) {
+        let Some(irq_ctrl) = self.irq_ctrl.as_ref() else {
+            return;
+        };
+        let online = online_harts();
+        let harts: Vec<usize> = (0..MAX_PROCESSORS).filter(|hart| online & (1 << hart) != 0).collect();
+        if harts.is_empty() {
+            return;
         }
-        unsafe { Instruction::disable_interrupt() };
-        log::trace!("[Device Manager]: handle interrupt");
-        if let Some(irq_num) = self.irq_ctrl().claim_irq() {
-            if let Some(dev) = self.irq_map.get(&irq_num) {
-                dev.handle_irq();
-                self.irq_ctrl().complete_irq(irq_num);
-                return;
-            }
-        } 
+        let unpinned = self.irq_map.keys().filter(|irq| !self.irq_affinity.contains_key(irq));
+        for (i, &irq) in unpinned.enumerate() {
+            irq_ctrl.route_irq(irq, harts[i % harts.len()]);
+        }
+    }
+
+    /// mask the irq line `irq`
+    pub fn mask_irq(&self, irq: IrqNo) {
+        if let Some(irq_ctrl) = self.irq_ctrl.as_ref() {
+            irq_ctrl.disable_irq(irq);
+        }
+    }
+
+    /// unmask the irq line `irq`
+    pub fn unmask_irq(&self, irq: IrqNo) {
+        if let Some(irq_ctrl) = self.irq_ctrl.as_ref() {
+            irq_ctrl.enable_irq(irq);
+        }
+    }
+
+    /// deliver `irq` to `hart` only, instead of spreading it round robin.
+    /// Returns false if the hart is not online
+    pub fn set_irq_affinity(&mut self, irq: IrqNo, hart: usize) -> bool {
+        if hart >= MAX_PROCESSORS || online_harts() & (1 << hart) == 0 {
+            return false;
+        }
+        self.irq_affinity.insert(irq, hart);
+        if let Some(irq_ctrl) = self.irq_ctrl.as_ref() {
+            irq_ctrl.route_irq(irq, hart);
+        }
+        true
     }
-}
\ No newline at end of file
+
+    /// claim the pending irq of this hart and count it,
+    /// return it with its device, None as the device of a spurious irq
+    pub fn claim_irq(&mut self) -> Option<(IrqNo, Option<Arc<dyn Device>>, usize)> {
+        let irq = self.irq_ctrl.as_ref()?.claim_irq()?;
+        let count = self.irq_count.entry(irq).or_insert(0);
+        *count += 1;
+        Some((irq, self.irq_map.get(&irq).cloned(), *count))
+    }
+
+    /// tell the interrupt controller this hart has handled `irq`
+    pub fn complete_irq(&self, irq: IrqNo) {
+        if let Some(irq_ctrl) = self.irq_ctrl.as_ref() {
+            irq_ctrl.complete_irq(irq);
+        }
+    }
+}
@@ -9,6 +9,8 @@ use crate::mm::{vm::{KernVmArea, KernVmAreaType, KernVmSpaceHal}, KVMSPACE};
 #[derive(Clone)]
 pub struct MmioDeviceDescripter {
     pub mmio_region: Range<usize>,
+    /// interrupt number from the device tree
+    pub irq_no: Option<usize>,
 }
 
 impl MmioDeviceDescripter {
@@ -32,6 +34,7 @@ impl MmioManager {
         let mut devices = Vec::new();
         for node in root.find_all_nodes("/soc/virtio_mmio") {
             if node.reg().is_none() { continue; }
+            let irq_no = node.property("interrupts").and_then(|irq| irq.as_usize());
             for region in node.reg().unwrap() {
                 if let Some(size) = region.size {
                     let paddr = region.starting_address as usize;
@@ -46,7 +49,8 @@ impl MmioManager {
                     );
                     
                     devices.push(MmioDeviceDescripter { 
-                        mmio_region: paddr..paddr+size
+                        mmio_region: paddr..paddr+size,
+                        irq_no,
                     });
                 }
             }
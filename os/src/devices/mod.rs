@@ -6,18 +6,19 @@ pub mod plic;
 pub mod manager;
 pub mod pci;
 pub mod mmio;
-use core::{any::Any, arch::global_asm, ops::Range};
-use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+pub mod rtc;
+pub mod registry;
+use core::{any::Any, arch::global_asm, ops::Range, time::Duration};
+use alloc::{boxed::Box, string::String, sync::Arc, vec, vec::Vec};
 use async_trait::async_trait;
 use downcast_rs::DowncastSync;
-use hal::println;
+use hal::{instruction::{Instruction, InstructionHal}, klog, println};
 use manager::DeviceManager;
 use net::{EthernetAddress, NetBuf};
-use serial::scan_char_device;
 use smoltcp::phy::{DeviceCapabilities,RxToken, TxToken};
 use spin::Once;
 
-use crate::sync::mutex::SpinNoIrqLock;
+use crate::{drivers::dma::DmaPages, sync::mutex::SpinNoIrqLock, utils::{dyn_future, Async}};
 use lazy_static::lazy_static;
 
 
@@ -28,6 +29,7 @@ pub enum DeviceType {
     Char,
     Net,
     Display,
+    Rtc,
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
@@ -36,6 +38,7 @@ pub enum DeviceMajor {
     Serial = 4,
     Block = 8,
     Net = 9,
+    Rtc = 254,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -137,6 +140,10 @@ pub trait Device: Sync + Send + DowncastSync {
     fn as_net(self: Arc<Self>) -> Option<Arc<dyn NetDevice>> {
         None
     }
+
+    fn as_rtc(self: Arc<Self>) -> Option<Arc<dyn RtcDevice>> {
+        None
+    }
 }
 
 /// Trait for block devices
@@ -151,6 +158,48 @@ pub trait BlockDevice: Send + Sync + Any {
 
     /// Write data from buffer to block
     fn write_block(&self, block_id: usize, buf: &[u8]);
+
+    /// Issue the writes held back by the device, if any
+    fn flush(&self) {}
+
+    /// Read `len` bytes from `block_id` without holding the caller, the device owns the
+    /// buffer until it hands the request back; dropping the future abandons the request
+    fn read_async(&self, block_id: usize, len: usize) -> Async<'_, DevResult<Vec<u8>>> {
+        dyn_future(async move {
+            let mut buf = vec![0; len];
+            self.read_block(block_id, &mut buf);
+            Ok(buf)
+        })
+    }
+
+    /// Write `buf` to `block_id` without holding the caller, as [`BlockDevice::read_async`]
+    fn write_async(&self, block_id: usize, buf: Vec<u8>) -> Async<'_, DevResult> {
+        dyn_future(async move {
+            self.write_block(block_id, &buf);
+            Ok(())
+        })
+    }
+
+    /// Stop the device, wait a while for the requests it holds, free its queues
+    /// and set it up again
+    fn reset(&self) -> DevResult {
+        Err(DevError::Unsupported)
+    }
+
+    /// The DMA memory the device holds
+    fn dma_pages(&self) -> DmaPages {
+        DmaPages::default()
+    }
+
+    /// The device number of the disk, the st_dev of the files on it
+    fn devno(&self) -> usize {
+        0
+    }
+
+    /// Whether the device refuses writes, a file system on it stays read-only
+    fn read_only(&self) -> bool {
+        false
+    }
 }
 
 pub trait NetDevice: Send + Sync + Any {
@@ -193,6 +242,13 @@ pub trait CharDevice: Send + Sync + Any {
     async fn poll_out(&self) -> bool;
 }
 
+/// Trait for real time clocks
+pub trait RtcDevice: Send + Sync + Any {
+    /// the wall clock time since the epoch
+    fn read_time(&self) -> Duration;
+    /// set the wall clock time, which survives a reboot
+    fn set_time(&self, now: Duration);
+}
 
 pub(crate) const fn as_dev_err(e: virtio_drivers::Error) -> DevError {
     use virtio_drivers::Error::*;
@@ -216,11 +272,50 @@ pub fn get_device_tree_addr() -> usize {
     hal::board::get_device_tree_addr()
 }
 
+/// devices the kernel maps at boot, whatever drives them later
+const BOOT_MMIO_COMPATIBLE: &[&str] = &[
+    "virtio,mmio",
+    "ns16550a",
+    "snps,dw-apb-uart",
+    "google,goldfish-rtc",
+    "riscv,plic0",
+    "sifive,plic-1.0.0",
+    "sifive,test0",
+];
+
+/// the mmio ranges, as (start, size), of the devices under /soc of the device tree
+/// the kernel address space maps at boot,
+/// the static table of the board if the device tree can not be parsed
+pub fn boot_mmio_ranges() -> Vec<(usize, usize)> {
+    let Ok(device_tree) = (unsafe { fdt::Fdt::from_ptr(get_device_tree_addr() as _) }) else {
+        return hal::board::MMIO.to_vec();
+    };
+    device_tree.all_nodes()
+        .filter(|node| node.compatible()
+            .is_some_and(|compatible| compatible.all().any(|c| BOOT_MMIO_COMPATIBLE.contains(&c))))
+        .filter_map(|node| node.reg())
+        .flatten()
+        .filter_map(|region| Some((region.starting_address as usize, region.size?)))
+        .collect()
+}
+
 lazy_static! {
     pub static ref DEVICE_MANAGER: SpinNoIrqLock<DeviceManager> = SpinNoIrqLock::new(DeviceManager::new());
 }
 
 
+/// the kernel command line from the device tree
+static BOOTARGS: Once<String> = Once::new();
+
+/// the value of `key=value` on the kernel command line, `None` if the key is absent
+pub fn bootarg(key: &str) -> Option<&'static str> {
+    BOOTARGS.get()?
+        .split_whitespace()
+        .filter_map(|arg| arg.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v)
+}
+
 pub fn init() {
     let device_tree_addr = get_device_tree_addr();
     log::info!("get device tree addr: {:#x}", device_tree_addr);
@@ -231,6 +326,7 @@ pub fn init() {
 
     if let Some(bootargs) = device_tree.chosen().bootargs() {
         println!("Bootargs: {:?}", bootargs);
+        BOOTARGS.call_once(|| String::from(bootargs));
     }
 
     // find all devices
@@ -242,7 +338,47 @@ pub fn init() {
     // init devices
     DEVICE_MANAGER.lock().init_devices();
 
-    // #[cfg(not(feature="smp"))]
-    // DEVICE_MANAGER.lock().enable_irq();
-    // log::info!("External interrupts enabled");
+    // enable the irqs of the devices found, they are routed as harts come online
+    DEVICE_MANAGER.lock().enable_irq();
+}
+
+/// let this hart take external interrupts, called once per hart after it is online
+pub fn init_hart_irq(hart: usize) {
+    DEVICE_MANAGER.lock().init_hart_irq(hart);
+}
+
+/// handle the external interrupts pending on this hart:
+/// claim each one, run the handler of its device and complete it
+pub fn handle_irq() {
+    loop {
+        // the device handler runs without the manager lock,
+        // so it may mask its line or look up other devices
+        let Some((irq, dev, count)) = DEVICE_MANAGER.lock().claim_irq() else {
+            break;
+        };
+        match &dev {
+            Some(dev) => dev.handle_irq(),
+            None => log::warn!("[irq] spurious irq {irq}"),
+        }
+        DEVICE_MANAGER.lock().complete_irq(irq);
+        if count.is_power_of_two() {
+            let name = dev.as_ref().map_or("none", |dev| dev.name());
+            klog!("[irq] {} ({}) fired {} times on hart {}", irq, name, count, Instruction::get_tp());
+        }
+    }
+}
+
+/// mask the irq line of a device
+pub fn mask_irq(irq: usize) {
+    DEVICE_MANAGER.lock().mask_irq(irq);
+}
+
+/// unmask the irq line of a device
+pub fn unmask_irq(irq: usize) {
+    DEVICE_MANAGER.lock().unmask_irq(irq);
+}
+
+/// deliver an irq to `hart` only, false if the hart is not online
+pub fn set_irq_affinity(irq: usize, hart: usize) -> bool {
+    DEVICE_MANAGER.lock().set_irq_affinity(irq, hart)
 }
\ No newline at end of file
@@ -1,10 +1,10 @@
-use core::{panic, ptr::NonNull};
+use core::{panic, ptr::NonNull, sync::atomic::{AtomicU64, Ordering}};
 
 use alloc::{boxed::Box, sync::Arc, vec::{self, Vec}};
 use log::info;
 use smoltcp::{phy::{Device, DeviceCapabilities, Medium, RxToken, TxToken}, time::Instant};
 
-use crate::{net::modify_packet, sync::{mutex::SpinLock, UPSafeCell}};
+use crate::{net::modify_packet, sync::mutex::{SpinLock, SpinNoIrqLock}};
 
 use super::{DevError, NetBufPtrTrait, NetDevice};
 /// NET_BUF_LEN
@@ -185,46 +185,103 @@ impl Drop for NetBuf {
 
 pub type NetBufBox = Box<NetBuf>;
 
+/// the packet counters of a device, in the order of the first fields of
+/// struct rtnl_link_stats64
+#[derive(Default)]
+pub struct NetStats {
+    /// frames handed to the stack
+    pub rx_packets: AtomicU64,
+    /// frames handed to the NIC
+    pub tx_packets: AtomicU64,
+    /// bytes of the frames received
+    pub rx_bytes: AtomicU64,
+    /// bytes of the frames sent
+    pub tx_bytes: AtomicU64,
+    /// receive failures of the device, and rx buffers it did not take back
+    pub rx_errors: AtomicU64,
+    /// frames the device failed to send
+    pub tx_errors: AtomicU64,
+    /// frames received and dropped before the stack looked at them
+    pub rx_dropped: AtomicU64,
+    /// frames dropped for want of a tx buffer
+    pub tx_dropped: AtomicU64,
+}
+
+impl NetStats {
+    /// the counters, in the order of the fields
+    pub fn snapshot(&self) -> [u64; 8] {
+        [
+            &self.rx_packets, &self.tx_packets, &self.rx_bytes, &self.tx_bytes,
+            &self.rx_errors, &self.tx_errors, &self.rx_dropped, &self.tx_dropped,
+        ]
+        .map(|counter| counter.load(Ordering::Relaxed))
+    }
+}
+
+fn count(counter: &AtomicU64, n: usize) {
+    counter.fetch_add(n as u64, Ordering::Relaxed);
+}
+
 /// device wrapper for network device
 pub struct NetDeviceWrapper {
-    /// the inner device wrapped by UPSafeCell
-    inner: UPSafeCell<Box<dyn NetDevice>>,
+    /// the inner device, the tokens handed to smoltcp lock it again when consumed
+    inner: SpinNoIrqLock<Box<dyn NetDevice>>,
+    /// the counters of the device, read without the lock of the interface
+    stats: Arc<NetStats>,
 }
 
 impl NetDeviceWrapper {
     /// new a NetDeviceWrapper
     pub fn new(dev: Box<dyn NetDevice>) -> Self {
         Self {
-            inner: UPSafeCell::new(dev),
+            inner: SpinNoIrqLock::new(dev),
+            stats: Arc::new(NetStats::default()),
+        }
+    }
+    /// the counters of the device
+    pub fn stats(&self) -> Arc<NetStats> {
+        self.stats.clone()
+    }
+    /// give a received buffer back to the device
+    fn recycle(&self, rx_buf: Box<dyn NetBufPtrTrait>) {
+        if let Err(e) = self.inner.lock().recycle_rx_buffer(rx_buf) {
+            log::warn!("[NetDeviceWrapper] recycle rx buffer failed: {:?}", e);
+            count(&self.stats.rx_errors, 1);
         }
     }
 }
-/// rx token and tx token needed for smoltcp
-pub struct NetRxToken<'a>(&'a UPSafeCell<Box<dyn NetDevice>>, Box<dyn NetBufPtrTrait>);
-pub struct NetTxToken<'a>(&'a UPSafeCell<Box<dyn NetDevice>>);
+/// rx token and tx token needed for smoltcp, the rx buffer is only taken by consume
+pub struct NetRxToken<'a>(&'a NetDeviceWrapper, Option<Box<dyn NetBufPtrTrait>>);
+pub struct NetTxToken<'a>(&'a NetDeviceWrapper);
 
 impl <'a> RxToken for NetRxToken<'a> {
     /// receive a packet than call the closure with the packet bytes
-    fn consume<R, F>(self, f: F) -> R
+    fn consume<R, F>(mut self, f: F) -> R
         where
             F: FnOnce(&mut [u8]) -> R 
     {
-        // need preprocess
-        let mut rx_buf = self.1;
-        // log::warn!(
-        //     "[RxToken::consume] RECV {} bytes",
-        //     rx_buf.packet_len(),
-            // rx_buf.packet()
-        // );
+        let mut rx_buf = self.1.take().unwrap();
+        count(&self.0.stats.rx_packets, 1);
+        count(&self.0.stats.rx_bytes, rx_buf.packet_len());
         let result = f(rx_buf.packet_mut());
-        self.0.exclusive_access().recycle_rx_buffer(rx_buf).unwrap();
+        self.0.recycle(rx_buf);
         result
     }
 
     fn preprocess(&self, sockets: &mut smoltcp::iface::SocketSet<'_>) {
-        let medium = self.0.exclusive_access().capabilities().medium;
+        let medium = self.0.inner.lock().capabilities().medium;
         let is_ethernet = medium == Medium::Ethernet;
-        modify_packet(self.1.packet(),sockets,is_ethernet).ok();
+        modify_packet(self.1.as_ref().unwrap().packet(),sockets,is_ethernet).ok();
+    }
+}
+
+impl Drop for NetRxToken<'_> {
+    /// a token smoltcp never consumed still has to give its buffer back to the ring
+    fn drop(&mut self) {
+        if let Some(rx_buf) = self.1.take() {
+            count(&self.0.stats.rx_dropped, 1);
+            self.0.recycle(rx_buf);
+        }
     }
 }
 
@@ -234,14 +291,28 @@ impl <'a> TxToken for NetTxToken<'a> {
         where
             F: FnOnce(&mut [u8]) -> R 
     {
-        let mut tx_buf = self.0.exclusive_access().alloc_tx_buffer(len).unwrap();
+        let stats = &self.0.stats;
+        let tx_buf = self.0.inner.lock().alloc_tx_buffer(len);
+        let mut tx_buf = match tx_buf {
+            Ok(tx_buf) => tx_buf,
+            Err(e) => {
+                // the ring is full: smoltcp still builds the frame, into a buffer thrown away
+                log::warn!("[TxToken::consume] no tx buffer for {} bytes: {:?}", len, e);
+                count(&stats.tx_dropped, 1);
+                return f(&mut alloc::vec![0; len]);
+            }
+        };
         let result = f(tx_buf.packet_mut());
-        // log::warn!(
-        //     "[TxToken::consume] SEND {} bytes",
-        //     len,
-             // tx_buf.packet()
-        // );
-        self.0.exclusive_access().transmit(tx_buf).unwrap();
+        match self.0.inner.lock().transmit(tx_buf) {
+            Ok(()) => {
+                count(&stats.tx_packets, 1);
+                count(&stats.tx_bytes, len);
+            }
+            Err(e) => {
+                log::warn!("[TxToken::consume] transmit failed: {:?}", e);
+                count(&stats.tx_errors, 1);
+            }
+        }
         result
     }
 }
@@ -251,12 +322,13 @@ impl Device for NetDeviceWrapper {
     type TxToken<'a> = NetTxToken<'a> where Self: 'a;
 
     fn capabilities(&self) -> DeviceCapabilities {
-        self.inner.get_ref().capabilities()
+        self.inner.lock().capabilities()
     }
     fn receive(&mut self, _: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
-        let inner = self.inner.exclusive_access();
+        let mut inner = self.inner.lock();
         if let Err(e) = inner.recycle_tx_buffer(){
             log::warn!("recycle_tx_buffers failed: {:?}", e);
+            count(&self.stats.tx_errors, 1);
             return None;
         };
         let rx_buf = match inner.receive(){
@@ -264,22 +336,26 @@ impl Device for NetDeviceWrapper {
             Err(e) => {
                 if !matches!(e, DevError::Again){
                     log::warn!("received failed!, Error: {:?}",e);
+                    count(&self.stats.rx_errors, 1);
                 }
                 return None;
             }
         };
-        Some((NetRxToken(&self.inner, rx_buf), NetTxToken(&self.inner)))
+        drop(inner);
+        let this = &*self;
+        Some((NetRxToken(this, Some(rx_buf)), NetTxToken(this)))
     }
     fn transmit(&mut self, _: Instant) -> Option<Self::TxToken<'_>> {
-        let inner = self.inner.exclusive_access();
-        match inner.recycle_tx_buffer(){
+        let recycled = self.inner.lock().recycle_tx_buffer();
+        match recycled {
             Err(e) => {
                 log::warn!("[transmit] recycle buffer failed: {:?}",e );
+                count(&self.stats.tx_errors, 1);
                 return None;    
             }
             Ok(_) => {
-                Some(NetTxToken(&self.inner))
+                Some(NetTxToken(&*self))
             },
         }
     }
-}
\ No newline at end of file
+}
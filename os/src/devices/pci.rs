@@ -249,7 +249,7 @@ impl PciManager {
     /// allocate device's bar memory, create transport
     pub fn init_device(&mut self, device: &mut PciDeviceDescriptor) -> Result<(), ()> {
         let PciDeviceDescriptor { func, ..} = *device;
-        for (i, info) in self.root.bars(func).unwrap().into_iter().enumerate() {
+        for (i, info) in self.root.bars(func).map_err(|_| ())?.into_iter().enumerate() {
             let Some(info) = info else { continue };
             log::info!("BAR {}: {}", i, info);
             if let BarInfo::Memory {
@@ -0,0 +1,166 @@
+//! the drivers built into the kernel, and the binding of the devices found at boot to them
+//!
+//! discovery lists what the device tree and the buses hold, each device goes to the
+//! matching driver of the lowest priority, and the drivers probe in priority order.
+//! A probe which fails is logged and its device left out, the boot goes on without it
+
+use alloc::{sync::Arc, vec::Vec};
+use fdt::{node::FdtNode, Fdt};
+use virtio_drivers::transport::{mmio::MmioTransport, DeviceType, Transport};
+
+use crate::drivers::block::{VirtIOMMIOBlock, VirtIOPCIBlock};
+
+use super::{
+    manager::DeviceManager, mmio::MmioDeviceDescripter, pci::{PciDeviceClass, PciDeviceDescriptor},
+    rtc::get_rtc, serial::{find_console, get_serial}, DevError, DevResult, Device,
+};
+
+/// a device found at boot, not bound to a driver yet
+pub enum Discovered<'b, 'a> {
+    /// the console named by /chosen of the device tree
+    Console(FdtNode<'b, 'a>),
+    /// any other node of the device tree with compatible strings
+    Node(FdtNode<'b, 'a>),
+    /// a virtio device behind an mmio slot
+    VirtioMmio(MmioDeviceDescripter, MmioTransport),
+    /// a function on the pci bus
+    Pci(PciDeviceDescriptor),
+}
+
+/// the devices a driver drives
+pub enum DriverMatch {
+    /// the console, if one of its compatible strings is listed
+    Console(&'static [&'static str]),
+    /// a device tree node with one of the compatible strings listed
+    Compatible(&'static [&'static str]),
+    /// a virtio mmio device of the type
+    VirtioMmio(DeviceType),
+    /// a pci function of the class
+    PciClass(PciDeviceClass),
+}
+
+impl DriverMatch {
+    fn matches(&self, dev: &Discovered) -> bool {
+        match (self, dev) {
+            (Self::Console(list), Discovered::Console(node)) | (Self::Compatible(list), Discovered::Node(node)) => {
+                node.compatible().is_some_and(|compatible| compatible.all().any(|c| list.contains(&c)))
+            }
+            (Self::VirtioMmio(dtype), Discovered::VirtioMmio(_, transport)) => transport.device_type() == *dtype,
+            (Self::PciClass(class), Discovered::Pci(pci_dev)) => PciDeviceClass::from(pci_dev.func_info.class) == *class,
+            _ => false,
+        }
+    }
+}
+
+/// a driver built into the kernel
+pub struct DriverDesc {
+    pub name: &'static str,
+    pub matches: DriverMatch,
+    /// drivers of a lower priority probe first, the console before the rest
+    pub priority: u8,
+    /// create the device, the manager holds the buses the device is on
+    pub probe: fn(&mut DeviceManager, Discovered) -> DevResult<Arc<dyn Device>>,
+}
+
+/// every driver of the kernel
+pub static DRIVERS: &[DriverDesc] = &[
+    DriverDesc {
+        name: "uart",
+        matches: DriverMatch::Console(&["ns16550a", "snps,dw-apb-uart"]),
+        priority: 0,
+        probe: probe_serial,
+    },
+    DriverDesc {
+        name: "goldfish-rtc",
+        matches: DriverMatch::Compatible(&["google,goldfish-rtc"]),
+        priority: 10,
+        probe: probe_rtc,
+    },
+    DriverDesc {
+        name: "virtio-blk-pci",
+        matches: DriverMatch::PciClass(PciDeviceClass::MassStorageContorller),
+        priority: 20,
+        probe: probe_pci_block,
+    },
+    DriverDesc {
+        name: "virtio-blk-mmio",
+        matches: DriverMatch::VirtioMmio(DeviceType::Block),
+        priority: 20,
+        probe: probe_mmio_block,
+    },
+];
+
+/// the devices of the device tree and of the buses the manager scanned,
+/// pci functions before mmio slots so the disks keep their names
+pub fn discover<'b, 'a>(manager: &DeviceManager, device_tree: &'b Fdt<'a>) -> Vec<Discovered<'b, 'a>> {
+    let mut found = Vec::new();
+    let console = find_console(device_tree);
+    let console_name = console.as_ref().map(|node| node.name);
+    found.extend(console.map(Discovered::Console));
+    found.extend(device_tree.all_nodes()
+        .filter(|node| node.compatible().is_some() && Some(node.name) != console_name)
+        .map(Discovered::Node));
+    if let Some(pci) = manager.pci.as_ref() {
+        found.extend(pci.enumerate_devices().map(Discovered::Pci));
+    }
+    if let Some(mmio) = manager.mmio.as_ref() {
+        found.extend(mmio.enumerate_devices()
+            .filter_map(|dev| Some(Discovered::VirtioMmio(dev.clone(), dev.transport().ok()?))));
+    }
+    found
+}
+
+/// bind each device to its driver and probe them in priority order,
+/// a device no driver matches is left alone, one whose probe fails is skipped
+pub fn probe_all(manager: &mut DeviceManager, found: Vec<Discovered>) {
+    let mut bound: Vec<(&DriverDesc, Discovered)> = found.into_iter()
+        .filter_map(|dev| {
+            let driver = DRIVERS.iter()
+                .filter(|driver| driver.matches.matches(&dev))
+                .min_by_key(|driver| driver.priority)?;
+            Some((driver, dev))
+        })
+        .collect();
+    // stable, the devices of a driver probe in the order they were found
+    bound.sort_by_key(|(driver, _)| driver.priority);
+    for (driver, dev) in bound {
+        match (driver.probe)(manager, dev) {
+            Ok(dev) => {
+                log::info!("[Device Manager]: {} probed {}", driver.name, dev.name());
+                manager.register_device(dev);
+            }
+            Err(e) => log::warn!("[Device Manager]: {} failed to probe a device: {:?}, skipped", driver.name, e),
+        }
+    }
+}
+
+fn probe_serial(_manager: &mut DeviceManager, dev: Discovered) -> DevResult<Arc<dyn Device>> {
+    let Discovered::Console(node) = dev else {
+        return Err(DevError::InvalidParam);
+    };
+    Ok(Arc::new(get_serial(&node)?))
+}
+
+fn probe_rtc(_manager: &mut DeviceManager, dev: Discovered) -> DevResult<Arc<dyn Device>> {
+    let Discovered::Node(node) = dev else {
+        return Err(DevError::InvalidParam);
+    };
+    Ok(Arc::new(get_rtc(&node)?))
+}
+
+fn probe_pci_block(manager: &mut DeviceManager, dev: Discovered) -> DevResult<Arc<dyn Device>> {
+    let (Discovered::Pci(mut pci_dev), Some(pci)) = (dev, manager.pci.as_mut()) else {
+        return Err(DevError::InvalidParam);
+    };
+    // pci bus has an advantage: no need to map or allocate
+    // memory to recognize the device type.
+    pci.init_device(&mut pci_dev).map_err(|_| DevError::NoMemory)?;
+    Ok(Arc::new(VirtIOPCIBlock::new(pci_dev)?))
+}
+
+fn probe_mmio_block(_manager: &mut DeviceManager, dev: Discovered) -> DevResult<Arc<dyn Device>> {
+    let Discovered::VirtioMmio(mmio_dev, transport) = dev else {
+        return Err(DevError::InvalidParam);
+    };
+    Ok(Arc::new(VirtIOMMIOBlock::new(mmio_dev, transport)?))
+}
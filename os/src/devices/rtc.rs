@@ -0,0 +1,126 @@
+//! goldfish real time clock, seeds the wall clock time at boot
+//! and keeps the time set by the user across reboots
+
+use core::time::Duration;
+
+use alloc::{string::ToString, sync::Arc, vec};
+use fdt::node::FdtNode;
+use hal::constant::{Constant, ConstantsHal};
+use lazy_static::lazy_static;
+
+use super::{DevError, DevId, DevResult, Device, DeviceMajor, DeviceMeta, DeviceType, RtcDevice, DEVICE_MANAGER};
+
+/// low 32 bits of the time in nanoseconds since the epoch,
+/// reading it latches the high bits, writing it sets the time
+const GOLDFISH_RTC_TIME_LOW: usize = 0x00;
+/// high 32 bits of the latched time, written before the low bits when setting the time
+const GOLDFISH_RTC_TIME_HIGH: usize = 0x04;
+
+lazy_static! {
+    /// the rtc of the board, None if it has none
+    /// WARNING: should only be called after devices manager finish init
+    pub static ref RTC: Option<Arc<dyn RtcDevice>> = {
+        DEVICE_MANAGER.lock()
+            .find_dev_by_major(DeviceMajor::Rtc)
+            .into_iter()
+            .filter_map(|device| device.as_rtc())
+            .next()
+    };
+}
+
+/// the goldfish rtc device of qemu virt
+pub struct GoldfishRtc {
+    meta: DeviceMeta,
+    /// virtual address of the registers
+    base: usize,
+}
+
+impl GoldfishRtc {
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (self.base + offset) as *mut u32
+    }
+}
+
+impl Device for GoldfishRtc {
+    fn meta(&self) -> &DeviceMeta {
+        &self.meta
+    }
+
+    fn init(&self) {
+        // the wall clock time only needs to be read once,
+        // CLOCK_REALTIME is kept as the boot wall time plus the monotonic time
+        let wall = self.read_time();
+        log::info!("[RTC] goldfish rtc wall time {}s", wall.as_secs());
+        crate::timer::set_realtime(wall);
+    }
+
+    fn handle_irq(&self) {
+        // alarms are not used
+    }
+
+    fn as_rtc(self: Arc<Self>) -> Option<Arc<dyn RtcDevice>> {
+        Some(self)
+    }
+}
+
+impl RtcDevice for GoldfishRtc {
+    fn read_time(&self) -> Duration {
+        let nanos = unsafe {
+            let low = self.reg(GOLDFISH_RTC_TIME_LOW).read_volatile() as u64;
+            let high = self.reg(GOLDFISH_RTC_TIME_HIGH).read_volatile() as u64;
+            (high << 32) | low
+        };
+        Duration::from_nanos(nanos)
+    }
+
+    fn set_time(&self, now: Duration) {
+        let nanos = now.as_nanos() as u64;
+        unsafe {
+            self.reg(GOLDFISH_RTC_TIME_HIGH).write_volatile((nanos >> 32) as u32);
+            self.reg(GOLDFISH_RTC_TIME_LOW).write_volatile(nanos as u32);
+        }
+    }
+}
+
+/// the goldfish rtc of a device tree node
+pub fn get_rtc(node: &FdtNode) -> DevResult<GoldfishRtc> {
+    let region = node.reg().and_then(|mut reg| reg.next()).ok_or(DevError::InvalidParam)?;
+    let paddr = region.starting_address as usize;
+    let size = region.size.unwrap_or(Constant::PAGE_SIZE);
+    log::info!("[RTC] goldfish rtc at {paddr:#x}");
+    let meta = DeviceMeta {
+        dev_id: DevId { major: DeviceMajor::Rtc, minor: 0 },
+        name: "rtc0".to_string(),
+        need_mapping: true,
+        mmio_ranges: vec![paddr..paddr + size],
+        irq_no: None,
+        dtype: DeviceType::Rtc,
+    };
+    Ok(GoldfishRtc { meta, base: paddr | Constant::KERNEL_ADDR_SPACE.start })
+}
+
+/// days since 1970-01-01 of a date of the proleptic gregorian calendar,
+/// `mon` counts from 1
+pub fn days_from_civil(year: i64, mon: u32, mday: u32) -> i64 {
+    let year = if mon <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (mon as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + mday as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// the date of a day since 1970-01-01 as (year, month from 1, day of month)
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let mday = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let mon = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if mon <= 2 { 1 } else { 0 };
+    (year, mon, mday)
+}
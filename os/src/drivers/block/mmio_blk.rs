@@ -4,33 +4,37 @@ use core::sync::atomic::Ordering;
 
 use alloc::string::ToString;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use alloc::{format, vec};
 use hal::constant::{Constant, ConstantsHal};
 use hal::pagetable::MapPerm;
-use virtio_drivers::device::blk::VirtIOBlk;
 use virtio_drivers::transport::{self, Transport};
 use virtio_drivers::transport::mmio::{MmioTransport, VirtIOHeader};
 use crate::config::BLOCK_SIZE;
 use crate::devices::mmio::MmioDeviceDescripter;
-use crate::devices::{BlockDevice, DevId, Device, DeviceMajor};
-use crate::drivers::dma::VirtioHal;
+use crate::devices::{BlockDevice, DevError, DevId, DevResult, Device, DeviceMajor};
+use crate::drivers::dma::DmaPages;
 
 use crate::mm::vm::{KernVmArea, KernVmAreaType, KernVmSpaceHal};
 use crate::mm::KVMSPACE;
-use crate::{devices::DeviceMeta, sync::UPSafeCell};
+use crate::utils::{dyn_future, Async};
+use crate::{devices::DeviceMeta, sync::mutex::SpinNoIrqLock};
 
+use super::virtio_req::{read_sync, write_sync, BlkRequest, VirtioBlk};
 use super::BLK_ID;
 
 pub struct VirtIOMMIOBlock {
-    blk: UPSafeCell<VirtIOBlk<VirtioHal, MmioTransport>>,
+    blk: SpinNoIrqLock<VirtioBlk<MmioTransport>>,
     meta: DeviceMeta,
+    /// where the device sits, to set it up again at reset
+    mmio_dev: MmioDeviceDescripter,
 }
 
 impl BlockDevice for VirtIOMMIOBlock {
 
     fn size(&self) -> u64 {
         self.blk
-            .exclusive_access()
+            .lock()
             .capacity() * (BLOCK_SIZE as u64)
     }
 
@@ -39,16 +43,33 @@ impl BlockDevice for VirtIOMMIOBlock {
     }
     
     fn read_block(&self, block_id: usize, buf: &mut [u8]) {
-        self.blk
-            .exclusive_access()
-            .read_blocks(block_id, buf)
-            .expect("Error when reading VirtIOBlk");
+        read_sync(&self.blk, block_id, buf).expect("Error when reading VirtIOBlk");
     }
     fn write_block(&self, block_id: usize, buf: &[u8]) {
-        self.blk
-            .exclusive_access()
-            .write_blocks(block_id, buf)
-            .expect("Error when writing VirtIOBlk");
+        write_sync(&self.blk, block_id, buf).expect("Error when writing VirtIOBlk");
+    }
+    fn read_async(&self, block_id: usize, len: usize) -> Async<'_, DevResult<Vec<u8>>> {
+        dyn_future(async move {
+            BlkRequest::submit(&self.blk, block_id, false, vec![0; len])?.await
+        })
+    }
+    fn write_async(&self, block_id: usize, buf: Vec<u8>) -> Async<'_, DevResult> {
+        dyn_future(async move {
+            BlkRequest::submit(&self.blk, block_id, true, buf)?.await.map(|_| ())
+        })
+    }
+    fn reset(&self) -> DevResult {
+        let transport = self.mmio_dev.transport().map_err(|_| DevError::BadState)?;
+        self.blk.lock().reset(transport)
+    }
+    fn dma_pages(&self) -> DmaPages {
+        self.blk.lock().dma_pages()
+    }
+    fn devno(&self) -> usize {
+        self.meta.dev_id.makedev()
+    }
+    fn read_only(&self) -> bool {
+        self.blk.lock().read_only()
     }
 }
 
@@ -62,7 +83,9 @@ impl Device for VirtIOMMIOBlock {
     }
 
     fn handle_irq(&self) {
-        // todo!
+        // requests are polled to completion, only clear the interrupt status
+        // so the level triggered line drops
+        self.blk.lock().ack_interrupt();
     }
 
     fn as_blk(self: Arc<Self>) -> Option<Arc<dyn BlockDevice>> {
@@ -72,10 +95,8 @@ impl Device for VirtIOMMIOBlock {
 
 impl VirtIOMMIOBlock {
     // use a VirtIO MMIO paddr
-    pub fn new(mmio_dev: MmioDeviceDescripter, mmio_transport: MmioTransport) -> Self {
-        let blk = UPSafeCell::new(
-            VirtIOBlk::<VirtioHal, MmioTransport>::new(mmio_transport).expect("failed to create blk driver"),
-        );
+    pub fn new(mmio_dev: MmioDeviceDescripter, mmio_transport: MmioTransport) -> DevResult<Self> {
+        let blk = SpinNoIrqLock::new(VirtioBlk::new(mmio_transport)?);
         let id = BLK_ID.fetch_add(1, Ordering::AcqRel);
         let meta = DeviceMeta {
             dev_id: DevId {
@@ -84,10 +105,10 @@ impl VirtIOMMIOBlock {
             },
             name: format!("sda{}", id),
             need_mapping: false,
-            mmio_ranges: vec![mmio_dev.mmio_region],
-            irq_no: None,
+            irq_no: mmio_dev.irq_no,
+            mmio_ranges: vec![mmio_dev.mmio_region.clone()],
             dtype: crate::devices::DeviceType::Block,
         };
-        Self { blk, meta }
+        Ok(Self { blk, meta, mmio_dev })
     }
 }
\ No newline at end of file
@@ -3,6 +3,8 @@
 mod virtio_blk;
 mod pci_blk;
 mod mmio_blk;
+mod virtio_req;
+pub mod queue;
 
 use core::sync::atomic::AtomicUsize;
 
@@ -13,7 +15,7 @@ pub use mmio_blk::VirtIOMMIOBlock;
 
 use alloc::sync::Arc;
 use crate::devices::{BlockDevice, DeviceMajor, DEVICE_MANAGER};
-use lazy_static::*;
+use spin::Once;
 
 // pub type BlockDeviceImpl = crate::drivers::block::VirtIOBlock;
 
@@ -23,38 +25,39 @@ use lazy_static::*;
 
 pub static BLK_ID: AtomicUsize = AtomicUsize::new(0);
 
-lazy_static! {
-    /// WARNING: should only be called after devices manager finish init
-    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = {
-        /*
-        let blk = DEVICE_MANAGER.lock()
-        .find_dev_by_major(DeviceMajor::Block)
-        .into_iter()
-        .map(|device| device.as_blk().unwrap())
-        .next()
-        .unwrap();
-         */
+/// the disk holding the root file system, and the one mounted at /sdcard
+#[cfg(target_arch="riscv64")]
+pub const DISK_DEV_NAME: &str = "sda0";
+#[cfg(target_arch="riscv64")]
+pub const SDCARD_DEV_NAME: &str = "sda1";
+#[cfg(target_arch="loongarch64")]
+pub const DISK_DEV_NAME: &str = "sda1";
+#[cfg(target_arch="loongarch64")]
+pub const SDCARD_DEV_NAME: &str = "sda0";
 
-        #[cfg(target_arch="riscv64")]
-        let blk = DEVICE_MANAGER.lock()
-            .find_dev_by_name("sda0", DeviceMajor::Block)
-            .as_blk()
-            .unwrap();
+/// the disk of the root file system, looked up once
+static BLOCK_DEVICE: Once<Option<Arc<dyn BlockDevice>>> = Once::new();
 
-        #[cfg(target_arch="loongarch64")]
-        let blk = DEVICE_MANAGER.lock()
-            .find_dev_by_name("sda1", DeviceMajor::Block)
-            .as_blk()
-            .unwrap();
+/// the queue in front of the block device `name`, None if no driver probed it
+pub fn find_block_device(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    let blk = DEVICE_MANAGER.lock()
+        .find_dev_by_name(name, DeviceMajor::Block)?
+        .as_blk()?;
+    // share the queue of the file system on it
+    let blk: Arc<dyn BlockDevice> = queue::block_queue(name, blk);
+    Some(blk)
+}
 
-        blk.clone()
-    };
+/// the disk of the root file system, None if the board has none
+/// WARNING: should only be called after devices manager finish init
+pub fn block_device() -> Option<&'static Arc<dyn BlockDevice>> {
+    BLOCK_DEVICE.call_once(|| find_block_device(DISK_DEV_NAME)).as_ref()
 }
 
 
 #[allow(unused)]
 pub fn block_device_test() {
-    let block_device = BLOCK_DEVICE.clone();
+    let block_device = block_device().expect("no block device").clone();
     let mut write_buffer = [0u8; 512];
     let mut read_buffer = [0u8; 512];
     for i in 0..512 {
@@ -66,4 +69,4 @@ pub fn block_device_test() {
         assert_eq!(write_buffer, read_buffer);
     }
     println!("block device test passed!");
-}
\ No newline at end of file
+}
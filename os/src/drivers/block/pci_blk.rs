@@ -5,35 +5,39 @@ use core::sync::atomic::Ordering;
 use alloc::format;
 use alloc::string::ToString;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use hal::addr::PhysPageNumHal;
 use hal::allocator::FrameAllocatorHal;
 use hal::constant::{Constant, ConstantsHal};
 use lazy_static::lazy_static;
-use virtio_drivers::transport::pci::bus::{BarInfo, Cam, Command, MemoryBarType, MmioCam, PciRoot};
+use virtio_drivers::transport::pci::bus::{BarInfo, Cam, Command, DeviceFunction, MemoryBarType, MmioCam, PciRoot};
 
 use crate::config::BLOCK_SIZE;
 use crate::devices::pci::{PciDeviceClass, PciDeviceDescriptor};
-use crate::devices::{BlockDevice, DevId, Device, DeviceMajor, DeviceMeta};
-use crate::drivers::dma::VirtioHal;
-use crate::sync::UPSafeCell;
-use virtio_drivers::device::blk::VirtIOBlk;
+use crate::devices::{BlockDevice, DevError, DevId, DevResult, Device, DeviceMajor, DeviceMeta, DEVICE_MANAGER};
+use crate::drivers::dma::{DmaPages, VirtioHal};
+use crate::sync::mutex::SpinNoIrqLock;
+use crate::utils::{dyn_future, Async};
 use virtio_drivers::transport::pci::PciTransport;
 use virtio_drivers::transport::{DeviceType, Transport};
 use virtio_drivers::BufferDirection;
 
+use super::virtio_req::{read_sync, write_sync, BlkRequest, VirtioBlk};
 use super::BLK_ID;
 
 pub struct VirtIOPCIBlock {
     meta: DeviceMeta,
-    blk: UPSafeCell<VirtIOBlk<VirtioHal, PciTransport>>,
+    blk: SpinNoIrqLock<VirtioBlk<PciTransport>>,
+    /// the function of the device, to set it up again at reset
+    func: DeviceFunction,
 }
 
 impl BlockDevice for VirtIOPCIBlock {
 
     fn size(&self) -> u64 {
         self.blk
-            .exclusive_access()
+            .lock()
             .capacity() * (BLOCK_SIZE as u64)
     }
 
@@ -42,16 +46,38 @@ impl BlockDevice for VirtIOPCIBlock {
     }
     
     fn read_block(&self, block_id: usize, buf: &mut [u8]) {
-        self.blk
-            .exclusive_access()
-            .read_blocks(block_id, buf)
-            .expect("Error when reading VirtIOBlk");
+        read_sync(&self.blk, block_id, buf).expect("Error when reading VirtIOBlk");
     }
     fn write_block(&self, block_id: usize, buf: &[u8]) {
-        self.blk
-            .exclusive_access()
-            .write_blocks(block_id, buf)
-            .expect("Error when writing VirtIOBlk");
+        write_sync(&self.blk, block_id, buf).expect("Error when writing VirtIOBlk");
+    }
+    fn read_async(&self, block_id: usize, len: usize) -> Async<'_, DevResult<Vec<u8>>> {
+        dyn_future(async move {
+            BlkRequest::submit(&self.blk, block_id, false, vec![0; len])?.await
+        })
+    }
+    fn write_async(&self, block_id: usize, buf: Vec<u8>) -> Async<'_, DevResult> {
+        dyn_future(async move {
+            BlkRequest::submit(&self.blk, block_id, true, buf)?.await.map(|_| ())
+        })
+    }
+    fn reset(&self) -> DevResult {
+        // the bars keep what was allocated at probe, only the transport is made again
+        let transport = {
+            let mut manager = DEVICE_MANAGER.lock();
+            let pci = manager.pci.as_mut().ok_or(DevError::BadState)?;
+            PciTransport::new::<VirtioHal, _>(&mut pci.root, self.func).map_err(|_| DevError::BadState)?
+        };
+        self.blk.lock().reset(transport)
+    }
+    fn dma_pages(&self) -> DmaPages {
+        self.blk.lock().dma_pages()
+    }
+    fn devno(&self) -> usize {
+        self.meta.dev_id.makedev()
+    }
+    fn read_only(&self) -> bool {
+        self.blk.lock().read_only()
     }
 }
 
@@ -77,10 +103,9 @@ impl VirtIOPCIBlock {
     /// create a new Virt IO PCI drive Block device
     /// start: PCI memory space start addr
     /// size: PCI memory space size
-    pub fn new(pci_dev: PciDeviceDescriptor) -> Self {
-        let blk = UPSafeCell::new(
-            VirtIOBlk::<VirtioHal, PciTransport>::new(pci_dev.transport.unwrap()).expect("failed to create blk driver"),
-        );
+    pub fn new(pci_dev: PciDeviceDescriptor) -> DevResult<Self> {
+        let transport = pci_dev.transport.ok_or(DevError::BadState)?;
+        let blk = SpinNoIrqLock::new(VirtioBlk::new(transport)?);
         let id = BLK_ID.fetch_add(1, Ordering::AcqRel);
         let meta = DeviceMeta {
             dev_id: DevId {
@@ -97,7 +122,7 @@ impl VirtIOPCIBlock {
             irq_no: None,
             dtype: crate::devices::DeviceType::Block,
         };
-        Self { blk, meta }
+        Ok(Self { blk, meta, func: pci_dev.func })
     }
 }
 
@@ -0,0 +1,331 @@
+//! A thin request queue in front of a block device
+//!
+//! ext4 reads its metadata one 512 byte block at a time, mostly in ascending
+//! order, and writes it back the same way. Issued one by one every block is a
+//! virtio request of its own, so the queue merges them:
+//! - a read following the previous one fetches up to [`READ_AHEAD_BLOCKS`]
+//!   blocks in one transfer, the reads after it are served from that buffer
+//! - writes are plugged while they extend or overwrite a contiguous run, the
+//!   run is issued as one transfer when it breaks, fills [`MAX_SEGMENT_BLOCKS`],
+//!   gets older than [`PLUG_WINDOW_US`] or is flushed
+//! - reads go ahead of the plugged run, the part of a read that falls in the
+//!   run is copied from it, so a read always sees the data written before it
+
+use alloc::{format, string::String, sync::Arc, vec, vec::Vec};
+
+use crate::{devices::{BlockDevice, DevResult}, drivers::dma::DmaPages, sync::mutex::SpinNoIrqLock, timer::get_current_time_us, utils::Async};
+
+/// the most blocks merged into one transfer
+const MAX_SEGMENT_BLOCKS: usize = 64;
+/// the blocks fetched by a sequential read
+const READ_AHEAD_BLOCKS: usize = 32;
+/// how long writes may stay plugged waiting for a neighbour
+const PLUG_WINDOW_US: usize = 2_000;
+/// the unit of the sector counters
+const SECTOR_SIZE: usize = 512;
+
+/// the counters of one direction, as in /proc/diskstats
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IoStats {
+    /// requests submitted to the queue
+    pub submitted: usize,
+    /// requests served by a transfer issued for another one
+    pub merged: usize,
+    /// transfers issued to the device
+    pub ios: usize,
+    /// sectors moved by the transfers
+    pub sectors: usize,
+    /// time spent in the transfers
+    pub time_us: usize,
+}
+
+impl IoStats {
+    /// the average latency of a transfer
+    pub fn avg_latency_us(&self) -> usize {
+        self.time_us.checked_div(self.ios).unwrap_or(0)
+    }
+}
+
+/// the counters of a queue
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BlockStats {
+    pub read: IoStats,
+    pub write: IoStats,
+}
+
+struct QueueInner {
+    /// the first block of the plugged writes
+    plug_start: usize,
+    /// the data of the plugged writes, empty when nothing is plugged
+    plug: Vec<u8>,
+    /// when the first write of the run was plugged
+    plug_since: usize,
+    /// the first block of the read ahead buffer
+    ra_start: usize,
+    /// the blocks read ahead, kept up to date by the writes
+    ra: Vec<u8>,
+    /// the block after the last read, a read starting there is sequential
+    next_read: usize,
+    stats: BlockStats,
+}
+
+/// a block device whose requests go through a merging queue
+pub struct BlockQueue {
+    name: String,
+    dev: Arc<dyn BlockDevice>,
+    block_size: usize,
+    inner: SpinNoIrqLock<QueueInner>,
+}
+
+/// copy the overlap of the blocks `src` starting at `src_start` into the blocks `dst` starting at `dst_start`
+fn copy_overlap(block_size: usize, dst_start: usize, dst: &mut [u8], src_start: usize, src: &[u8]) {
+    let start = (dst_start * block_size).max(src_start * block_size);
+    let end = (dst_start * block_size + dst.len()).min(src_start * block_size + src.len());
+    if start >= end {
+        return;
+    }
+    let dst_off = start - dst_start * block_size;
+    let src_off = start - src_start * block_size;
+    dst[dst_off..dst_off + end - start].copy_from_slice(&src[src_off..src_off + end - start]);
+}
+
+impl BlockQueue {
+    fn new(name: &str, dev: Arc<dyn BlockDevice>) -> Self {
+        Self {
+            name: name.into(),
+            block_size: dev.block_size(),
+            dev,
+            inner: SpinNoIrqLock::new(QueueInner {
+                plug_start: 0,
+                plug: Vec::new(),
+                plug_since: 0,
+                ra_start: 0,
+                ra: Vec::new(),
+                next_read: usize::MAX,
+                stats: BlockStats::default(),
+            }),
+        }
+    }
+
+    /// the name of the device behind the queue
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// a snapshot of the counters
+    pub fn stats(&self) -> BlockStats {
+        self.inner.lock().stats
+    }
+
+    fn blocks(&self) -> usize {
+        self.dev.size() as usize / self.block_size
+    }
+
+    /// issue the plugged writes as one transfer
+    fn unplug(&self, inner: &mut QueueInner) {
+        if inner.plug.is_empty() {
+            return;
+        }
+        let start = get_current_time_us();
+        self.dev.write_block(inner.plug_start, &inner.plug);
+        let stats = &mut inner.stats.write;
+        stats.ios += 1;
+        stats.sectors += inner.plug.len() / SECTOR_SIZE;
+        stats.time_us += get_current_time_us().saturating_sub(start);
+        inner.plug.clear();
+    }
+
+    /// unplug the writes that waited long enough
+    fn unplug_expired(&self, inner: &mut QueueInner) {
+        if !inner.plug.is_empty() && get_current_time_us() - inner.plug_since >= PLUG_WINDOW_US {
+            self.unplug(inner);
+        }
+    }
+}
+
+impl BlockDevice for BlockQueue {
+    fn size(&self) -> u64 {
+        self.dev.size()
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        let bs = self.block_size;
+        let mut inner = self.inner.lock();
+        inner.stats.read.submitted += 1;
+        self.unplug_expired(&mut inner);
+        let count = buf.len().div_ceil(bs);
+
+        let ra_blocks = inner.ra.len() / bs;
+        if block_id >= inner.ra_start && block_id + count <= inner.ra_start + ra_blocks {
+            // the read ahead buffer has it, and the writes after it are in there too
+            let off = (block_id - inner.ra_start) * bs;
+            buf.copy_from_slice(&inner.ra[off..off + buf.len()]);
+            inner.stats.read.merged += 1;
+            inner.next_read = block_id + count;
+            return;
+        }
+
+        let sequential = block_id == inner.next_read && buf.len() % bs == 0;
+        let fetch = if sequential {
+            READ_AHEAD_BLOCKS.max(count).min(self.blocks().saturating_sub(block_id))
+        } else {
+            count
+        };
+        let start = get_current_time_us();
+        if fetch > count {
+            let mut ra = vec![0u8; fetch * bs];
+            self.dev.read_block(block_id, &mut ra);
+            // the plugged writes are newer than the disk
+            copy_overlap(bs, block_id, &mut ra, inner.plug_start, &inner.plug);
+            buf.copy_from_slice(&ra[..buf.len()]);
+            inner.ra_start = block_id;
+            inner.ra = ra;
+        } else {
+            self.dev.read_block(block_id, buf);
+            copy_overlap(bs, block_id, buf, inner.plug_start, &inner.plug);
+        }
+        let stats = &mut inner.stats.read;
+        stats.ios += 1;
+        stats.sectors += fetch * bs / SECTOR_SIZE;
+        stats.time_us += get_current_time_us().saturating_sub(start);
+        inner.next_read = block_id + count;
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let bs = self.block_size;
+        let mut inner = self.inner.lock();
+        inner.stats.write.submitted += 1;
+        let QueueInner { ra_start, ra, .. } = &mut *inner;
+        copy_overlap(bs, *ra_start, ra, block_id, buf);
+        if buf.len() % bs != 0 {
+            // a partial block cannot be merged, keep the order with the plugged run
+            self.unplug(&mut inner);
+            let start = get_current_time_us();
+            self.dev.write_block(block_id, buf);
+            let stats = &mut inner.stats.write;
+            stats.ios += 1;
+            stats.sectors += buf.len().div_ceil(SECTOR_SIZE);
+            stats.time_us += get_current_time_us().saturating_sub(start);
+            return;
+        }
+
+        let count = buf.len() / bs;
+        let plug_end = inner.plug_start + inner.plug.len() / bs;
+        let mergeable = !inner.plug.is_empty()
+            && block_id >= inner.plug_start
+            && block_id <= plug_end
+            && (block_id + count).max(plug_end) - inner.plug_start <= MAX_SEGMENT_BLOCKS;
+        if mergeable {
+            let off = (block_id - inner.plug_start) * bs;
+            let end = off + buf.len();
+            if end > inner.plug.len() {
+                inner.plug.resize(end, 0);
+            }
+            inner.plug[off..end].copy_from_slice(buf);
+            inner.stats.write.merged += 1;
+        } else {
+            self.unplug(&mut inner);
+            inner.plug_start = block_id;
+            inner.plug.extend_from_slice(buf);
+            inner.plug_since = get_current_time_us();
+        }
+        if inner.plug.len() >= MAX_SEGMENT_BLOCKS * bs {
+            self.unplug(&mut inner);
+        } else {
+            self.unplug_expired(&mut inner);
+        }
+    }
+
+    fn flush(&self) {
+        let mut inner = self.inner.lock();
+        self.unplug(&mut inner);
+    }
+
+    /// goes around the merging, behind the plugged writes
+    fn read_async(&self, block_id: usize, len: usize) -> Async<'_, DevResult<Vec<u8>>> {
+        self.flush();
+        self.dev.read_async(block_id, len)
+    }
+
+    fn write_async(&self, block_id: usize, buf: Vec<u8>) -> Async<'_, DevResult> {
+        let mut inner = self.inner.lock();
+        self.unplug(&mut inner);
+        let QueueInner { ra_start, ra, .. } = &mut *inner;
+        copy_overlap(self.block_size, *ra_start, ra, block_id, &buf);
+        drop(inner);
+        self.dev.write_async(block_id, buf)
+    }
+
+    fn reset(&self) -> DevResult {
+        let mut inner = self.inner.lock();
+        self.unplug(&mut inner);
+        self.dev.reset()
+    }
+
+    fn dma_pages(&self) -> DmaPages {
+        self.dev.dma_pages()
+    }
+
+    fn devno(&self) -> usize {
+        self.dev.devno()
+    }
+
+    fn read_only(&self) -> bool {
+        self.dev.read_only()
+    }
+}
+
+/// the queues of the block devices, one per device
+static QUEUES: SpinNoIrqLock<Vec<Arc<BlockQueue>>> = SpinNoIrqLock::new(Vec::new());
+
+/// the queue in front of the device `name`, created on first use
+pub fn block_queue(name: &str, dev: Arc<dyn BlockDevice>) -> Arc<BlockQueue> {
+    let mut queues = QUEUES.lock();
+    if let Some(queue) = queues.iter().find(|queue| queue.name == name) {
+        return queue.clone();
+    }
+    let queue = Arc::new(BlockQueue::new(name, dev));
+    queues.push(queue.clone());
+    queue
+}
+
+/// issue the plugged writes of every queue
+pub fn flush_all() {
+    let queues = QUEUES.lock().clone();
+    for queue in queues {
+        queue.flush();
+    }
+}
+
+/// the counters of every queue in the /proc/diskstats layout, times in ms
+pub fn diskstats() -> String {
+    let mut res = String::new();
+    for (minor, queue) in QUEUES.lock().iter().enumerate() {
+        let BlockStats { read, write } = queue.stats();
+        res += &format!(
+            "{:4} {:7} {} {} {} {} {} {} {} {} {} 0 {} {}\n",
+            8, minor, queue.name,
+            read.ios, read.merged, read.sectors, read.time_us / 1000,
+            write.ios, write.merged, write.sectors, write.time_us / 1000,
+            (read.time_us + write.time_us) / 1000, (read.time_us + write.time_us) / 1000,
+        );
+    }
+    res
+}
+
+/// log the counters of every queue
+pub fn dump_stats() {
+    for queue in QUEUES.lock().iter() {
+        let BlockStats { read, write } = queue.stats();
+        log::info!(
+            "[BLK] {}: read {} submitted {} merged {} ios {} sectors avg {} us, write {} submitted {} merged {} ios {} sectors avg {} us",
+            queue.name,
+            read.submitted, read.merged, read.ios, read.sectors, read.avg_latency_us(),
+            write.submitted, write.merged, write.ios, write.sectors, write.avg_latency_us(),
+        );
+    }
+}
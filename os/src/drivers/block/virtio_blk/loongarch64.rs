@@ -3,15 +3,13 @@
 use crate::devices::BlockDevice;
 use crate::config::BLOCK_SIZE;
 use crate::mm::allocator::{frames_alloc, frames_alloc_clean, frames_dealloc, FrameAllocator};
-use crate::mm::{FrameTracker, PageTable, KVMSPACE};
-use crate::sync::UPSafeCell;
-use alloc::vec::Vec;
+use crate::mm::{PageTable, KVMSPACE};
+use crate::sync::mutex::SpinNoIrqLock;
 use hal::addr::{PhysAddr, PhysAddrHal, PhysPageNum, PhysPageNumHal, VirtAddr};
 use hal::constant::{Constant, ConstantsHal};
 use hal::pagetable::PageTableHal;
 use hal::println;
 use crate::mm::vm::{KernVmSpaceHal, UserVmSpaceHal};
-use lazy_static::*;
 use crate::drivers::dma::VirtioHal;
 use alloc::{string::ToString, sync::Arc};
 use virtio_drivers::transport::pci::bus::{BarInfo, Cam, Command, DeviceFunction, MemoryBarType, MmioCam, PciRoot};
@@ -26,17 +24,13 @@ use log::*;
 
 const VIRTIO0: usize = 0x8000_0000_2000_0000;
 
-pub struct VirtIOBlock(UPSafeCell<VirtIOBlk<VirtioHal, PciTransport>>);
-
-lazy_static! {
-    static ref QUEUE_FRAMES: UPSafeCell<Vec<FrameTracker>> = UPSafeCell::new(Vec::new());
-}
+pub struct VirtIOBlock(SpinNoIrqLock<VirtIOBlk<VirtioHal, PciTransport>>);
 
 impl BlockDevice for VirtIOBlock {
 
     fn size(&self) -> u64 {
         self.0
-            .exclusive_access()
+            .lock()
             .capacity() * (BLOCK_SIZE as u64)
     }
 
@@ -46,13 +40,13 @@ impl BlockDevice for VirtIOBlock {
     
     fn read_block(&self, block_id: usize, buf: &mut [u8]) {
         self.0
-            .exclusive_access()
+            .lock()
             .read_blocks(block_id, buf)
             .expect("Error when reading VirtIOBlk");
     }
     fn write_block(&self, block_id: usize, buf: &[u8]) {
         self.0
-            .exclusive_access()
+            .lock()
             .write_blocks(block_id, buf)
             .expect("Error when writing VirtIOBlk");
     }
@@ -115,7 +109,7 @@ impl VirtIOBlock {
             transport.device_type(),
             transport.read_device_features(),
         );
-        Self(UPSafeCell::new(
+        Self(SpinNoIrqLock::new(
             VirtIOBlk::<VirtioHal, PciTransport>::new(transport).expect("failed to create blk driver"),
         ))
     }
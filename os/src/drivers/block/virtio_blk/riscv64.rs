@@ -3,7 +3,7 @@
 use crate::devices::BlockDevice;
 use crate::config::BLOCK_SIZE;
 use crate::drivers::dma::VirtioHal;
-use crate::sync::UPSafeCell;
+use crate::sync::mutex::SpinNoIrqLock;
 use hal::constant::{Constant, ConstantsHal};
 use core::ptr::NonNull;
 
@@ -16,13 +16,13 @@ use log::*;
 
 const VIRTIO0: usize = 0x10001000 | Constant::KERNEL_ADDR_SPACE.start;
 
-pub struct VirtIOBlock(UPSafeCell<VirtIOBlk<VirtioHal, MmioTransport>>);
+pub struct VirtIOBlock(SpinNoIrqLock<VirtIOBlk<VirtioHal, MmioTransport>>);
 
 impl BlockDevice for VirtIOBlock {
 
     fn size(&self) -> u64 {
         self.0
-            .exclusive_access()
+            .lock()
             .capacity() * (BLOCK_SIZE as u64)
     }
 
@@ -32,13 +32,13 @@ impl BlockDevice for VirtIOBlock {
     
     fn read_block(&self, block_id: usize, buf: &mut [u8]) {
         self.0
-            .exclusive_access()
+            .lock()
             .read_blocks(block_id, buf)
             .expect("Error when reading VirtIOBlk");
     }
     fn write_block(&self, block_id: usize, buf: &[u8]) {
         self.0
-            .exclusive_access()
+            .lock()
             .write_blocks(block_id, buf)
             .expect("Error when writing VirtIOBlk");
     }
@@ -50,7 +50,7 @@ impl VirtIOBlock {
         unsafe {
             let header = core::ptr::NonNull::new(VIRTIO0 as *mut VirtIOHeader).unwrap();
             let transport = MmioTransport::new(header, 4096).unwrap();
-            Self(UPSafeCell::new(
+            Self(SpinNoIrqLock::new(
                 VirtIOBlk::<VirtioHal, MmioTransport>::new(transport).expect("failed to create blk driver"),
             ))
         }
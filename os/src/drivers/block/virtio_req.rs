@@ -0,0 +1,244 @@
+//! The requests of a virtio block device
+//!
+//! A request owns its buffers from the moment it is handed to the device: the header, the data
+//! and the status sit in the completion table under the token of the request, not in whoever
+//! issued it. Dropping the future of a request, e.g. as its task is killed, only marks it
+//! abandoned; the buffers stay until the device hands the request back or the queue is reset.
+
+use core::{future::Future, pin::Pin, task::{Context, Poll}};
+
+use alloc::{boxed::Box, collections::btree_map::BTreeMap, vec::Vec};
+use virtio_drivers::{device::blk::{BlkReq, BlkResp, VirtIOBlk}, transport::Transport};
+
+use crate::{
+    devices::{as_dev_err, DevError, DevResult},
+    drivers::dma::{dma_pages, dma_release, new_dma_owner, with_dma_owner, DmaPages, VirtioHal},
+    sync::mutex::SpinNoIrqLock,
+    timer::get_current_time_ms,
+    utils::block_on,
+};
+
+/// how long a reset waits for the device to hand back the requests it holds
+const RESET_DRAIN_MS: usize = 1000;
+
+/// a request the device holds
+struct Inflight {
+    /// tells the request apart from an earlier one under the same token
+    id: u64,
+    write: bool,
+    req: Box<BlkReq>,
+    resp: Box<BlkResp>,
+    buf: Vec<u8>,
+    /// nobody waits for it any more, it is dropped once the device hands it back
+    abandoned: bool,
+}
+
+/// a virtio block device with the table of the requests it holds
+pub(super) struct VirtioBlk<T: Transport> {
+    /// None only while the device is torn down
+    dev: Option<VirtIOBlk<VirtioHal, T>>,
+    /// the DMA memory of the device is charged to it
+    owner: usize,
+    /// the requests the device holds, by token
+    inflight: BTreeMap<u16, Inflight>,
+    /// the results of the requests handed back, by id, until their issuer takes them
+    done: BTreeMap<u64, DevResult<Vec<u8>>>,
+    next_id: u64,
+}
+
+impl<T: Transport> VirtioBlk<T> {
+    pub fn new(transport: T) -> DevResult<Self> {
+        let owner = new_dma_owner();
+        let dev = with_dma_owner(owner, || VirtIOBlk::new(transport)).map_err(as_dev_err)?;
+        Ok(Self { dev: Some(dev), owner, inflight: BTreeMap::new(), done: BTreeMap::new(), next_id: 0 })
+    }
+
+    fn dev(&mut self) -> DevResult<&mut VirtIOBlk<VirtioHal, T>> {
+        self.dev.as_mut().ok_or(DevError::BadState)
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.dev.as_ref().map_or(0, |dev| dev.capacity())
+    }
+
+    /// whether the device offers VIRTIO_BLK_F_RO
+    pub fn read_only(&self) -> bool {
+        self.dev.as_ref().is_some_and(|dev| dev.readonly())
+    }
+
+    pub fn ack_interrupt(&mut self) {
+        if let Ok(dev) = self.dev() {
+            dev.ack_interrupt();
+        }
+    }
+
+    /// the DMA memory of the device
+    pub fn dma_pages(&self) -> DmaPages {
+        dma_pages(Some(self.owner))
+    }
+
+    /// hand the device a request for `buf` at `block_id`, the buffer goes to the table.
+    /// Again if the queue is full, `buf` is left alone then
+    fn submit(&mut self, block_id: usize, write: bool, buf: &mut Vec<u8>) -> DevResult<(u16, u64)> {
+        let mut req = Box::new(BlkReq::default());
+        let mut resp = Box::new(BlkResp::default());
+        let owner = self.owner;
+        let dev = self.dev()?;
+        let token = with_dma_owner(owner, || unsafe {
+            if write {
+                dev.write_blocks_nb(block_id, &mut req, buf, &mut resp)
+            } else {
+                dev.read_blocks_nb(block_id, &mut req, buf, &mut resp)
+            }
+        });
+        let token = match token {
+            Ok(token) => token,
+            Err(virtio_drivers::Error::QueueFull) => return Err(DevError::Again),
+            Err(e) => return Err(as_dev_err(e)),
+        };
+        let id = self.next_id;
+        self.next_id += 1;
+        let buf = core::mem::take(buf);
+        self.inflight.insert(token, Inflight { id, write, req, resp, buf, abandoned: false });
+        Ok((token, id))
+    }
+
+    /// take the requests the device handed back out of the table
+    fn reap(&mut self) {
+        let Some(dev) = self.dev.as_mut() else {
+            return;
+        };
+        while let Some(token) = dev.peek_used() {
+            let mut r = self.inflight.remove(&token)
+                .unwrap_or_else(|| panic!("[virtio-blk] the device handed back token {} it does not hold", token));
+            let ret = unsafe {
+                if r.write {
+                    dev.complete_write_blocks(token, &r.req, &r.buf, &mut r.resp)
+                } else {
+                    dev.complete_read_blocks(token, &r.req, &mut r.buf, &mut r.resp)
+                }
+            };
+            if !r.abandoned {
+                self.done.insert(r.id, ret.map(|_| r.buf).map_err(as_dev_err));
+            }
+        }
+    }
+
+    /// the result of the request `id`, once the device handed it back
+    fn take(&mut self, id: u64) -> Option<DevResult<Vec<u8>>> {
+        self.reap();
+        self.done.remove(&id)
+    }
+
+    /// nobody waits for the request `id` any more
+    fn abandon(&mut self, token: u16, id: u64) {
+        match self.inflight.get_mut(&token) {
+            Some(r) if r.id == id => r.abandoned = true,
+            _ => {
+                self.done.remove(&id);
+            }
+        }
+    }
+
+    /// stop the device: the requests it hands back within [`RESET_DRAIN_MS`] complete as usual,
+    /// the rest fail with Io. Then the queue is freed, which must give back every ring page
+    fn teardown(&mut self) {
+        let deadline = get_current_time_ms() + RESET_DRAIN_MS;
+        while !self.inflight.is_empty() && get_current_time_ms() < deadline {
+            self.reap();
+            core::hint::spin_loop();
+        }
+        let lost = core::mem::take(&mut self.inflight);
+        for r in lost.values().filter(|r| !r.abandoned) {
+            self.done.insert(r.id, Err(DevError::Io));
+        }
+        // the queue is unset and the transport resets the device as they drop,
+        // before the rings are freed
+        drop(self.dev.take());
+        // the bounce buffers of the requests never handed back are the only thing left
+        let left = dma_release(self.owner);
+        assert_eq!(left.rings, 0, "[virtio-blk] the rings of a queue outlived the device");
+        if !lost.is_empty() {
+            log::warn!("[virtio-blk] {} requests lost at reset, {} pages of bounce buffers freed", lost.len(), left.bounce);
+        }
+    }
+
+    /// reset the device and set it up again on `transport`
+    pub fn reset(&mut self, transport: T) -> DevResult {
+        self.teardown();
+        let dev = with_dma_owner(self.owner, || VirtIOBlk::new(transport)).map_err(as_dev_err)?;
+        self.dev = Some(dev);
+        Ok(())
+    }
+}
+
+impl<T: Transport> Drop for VirtioBlk<T> {
+    fn drop(&mut self) {
+        if self.dev.is_some() {
+            self.teardown();
+        }
+    }
+}
+
+/// a request handed to the device, ready once the device handed it back.
+/// Dropping it abandons the request, the table keeps the buffers meanwhile
+pub(super) struct BlkRequest<'a, T: Transport> {
+    blk: &'a SpinNoIrqLock<VirtioBlk<T>>,
+    token: u16,
+    id: u64,
+    finished: bool,
+}
+
+impl<'a, T: Transport> BlkRequest<'a, T> {
+    /// hand the device a request for `buf` at `block_id`, waiting while the queue is full
+    pub fn submit(blk: &'a SpinNoIrqLock<VirtioBlk<T>>, block_id: usize, write: bool, mut buf: Vec<u8>) -> DevResult<Self> {
+        loop {
+            let mut inner = blk.lock();
+            match inner.submit(block_id, write, &mut buf) {
+                Ok((token, id)) => return Ok(Self { blk, token, id, finished: false }),
+                Err(DevError::Again) => inner.reap(),
+                Err(e) => return Err(e),
+            }
+            drop(inner);
+            core::hint::spin_loop();
+        }
+    }
+}
+
+impl<T: Transport> Future for BlkRequest<'_, T> {
+    type Output = DevResult<Vec<u8>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.blk.lock().take(self.id) {
+            Some(ret) => {
+                self.finished = true;
+                Poll::Ready(ret)
+            }
+            None => {
+                // completions are polled, the interrupt only acknowledged
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T: Transport> Drop for BlkRequest<'_, T> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.blk.lock().abandon(self.token, self.id);
+        }
+    }
+}
+
+/// read the blocks at `block_id` into `buf`, spinning until the device hands them back
+pub(super) fn read_sync<T: Transport>(blk: &SpinNoIrqLock<VirtioBlk<T>>, block_id: usize, buf: &mut [u8]) -> DevResult {
+    let data = block_on(BlkRequest::submit(blk, block_id, false, alloc::vec![0; buf.len()])?)?;
+    buf.copy_from_slice(&data);
+    Ok(())
+}
+
+/// write `buf` to the blocks at `block_id`, spinning until the device hands them back
+pub(super) fn write_sync<T: Transport>(blk: &SpinNoIrqLock<VirtioBlk<T>>, block_id: usize, buf: &[u8]) -> DevResult {
+    block_on(BlkRequest::submit(blk, block_id, true, buf.to_vec())?).map(|_| ())
+}
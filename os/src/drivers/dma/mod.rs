@@ -1,13 +1,186 @@
+//! The DMA memory of the virtio devices
+//!
+//! Every allocation of the virtio drivers, the rings of a queue from `dma_alloc` and the
+//! bounce buffers of a request from `share`, is kept in [`DMA_TABLE`] by its physical address,
+//! charged to the device it was made for. A free takes its frames out of the table, so it gives
+//! back exactly what was handed out there, and a device torn down can check nothing of it is left.
+
 #[cfg(target_arch="riscv64")]
 mod riscv64;
 
 #[cfg(target_arch="riscv64")]
-pub use riscv64::*;
+use riscv64::*;
 
 #[cfg(target_arch="loongarch64")]
 mod loongarch64;
 
 #[cfg(target_arch="loongarch64")]
-pub use loongarch64::*;
+use loongarch64::*;
+
+use core::{ptr::NonNull, sync::atomic::{AtomicUsize, Ordering}};
+
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+use hal::{addr::{PhysAddr, PhysAddrHal, PhysPageNumHal, RangePPNHal}, board::MAX_PROCESSORS, constant::{Constant, ConstantsHal}, instruction::{Instruction, InstructionHal}};
+use virtio_drivers::BufferDirection;
+
+use crate::{mm::{allocator::{frames_alloc, frames_alloc_clean}, FrameTracker}, sync::mutex::SpinNoIrqLock};
+
+pub struct VirtioHal;
+
+/// the owner of the allocations made outside of any device
+pub const NO_OWNER: usize = 0;
+
+static NEXT_OWNER: AtomicUsize = AtomicUsize::new(NO_OWNER + 1);
+
+/// the device whose driver runs on each hart, what it allocates is charged to it
+static CURRENT_OWNER: [AtomicUsize; MAX_PROCESSORS] = [const { AtomicUsize::new(NO_OWNER) }; MAX_PROCESSORS];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DmaKind {
+    /// the rings of a queue, from `dma_alloc`
+    Ring,
+    /// the copy of a buffer of a request, from `share`
+    Bounce,
+}
+
+struct DmaEntry {
+    frames: FrameTracker,
+    owner: usize,
+    kind: DmaKind,
+}
+
+/// the DMA memory handed out, by physical address
+static DMA_TABLE: SpinNoIrqLock<BTreeMap<usize, DmaEntry>> = SpinNoIrqLock::new(BTreeMap::new());
+
+/// the pages of DMA memory handed out
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DmaPages {
+    /// the rings of the queues
+    pub rings: usize,
+    /// the bounce buffers of the requests the devices hold
+    pub bounce: usize,
+}
+
+impl DmaPages {
+    pub fn total(&self) -> usize {
+        self.rings + self.bounce
+    }
+}
+
+/// a new owner for the DMA memory of a device
+pub fn new_dma_owner() -> usize {
+    NEXT_OWNER.fetch_add(1, Ordering::Relaxed)
+}
+
+/// run `f` with the DMA memory it allocates charged to `owner`,
+/// `f` must stay on this hart, e.g. it runs under the lock of the device
+pub fn with_dma_owner<T>(owner: usize, f: impl FnOnce() -> T) -> T {
+    let current = &CURRENT_OWNER[Instruction::get_tp()];
+    let prev = current.swap(owner, Ordering::Relaxed);
+    let ret = f();
+    current.store(prev, Ordering::Relaxed);
+    ret
+}
+
+/// the DMA memory charged to `owner`, or to anyone if None
+pub fn dma_pages(owner: Option<usize>) -> DmaPages {
+    let mut pages = DmaPages::default();
+    for entry in DMA_TABLE.lock().values().filter(|entry| owner.is_none_or(|owner| entry.owner == owner)) {
+        let count = entry.frames.range_ppn.clone().count();
+        match entry.kind {
+            DmaKind::Ring => pages.rings += count,
+            DmaKind::Bounce => pages.bounce += count,
+        }
+    }
+    pages
+}
+
+/// free what is left of the DMA memory of `owner`, only once its device is stopped
+/// and can no longer reach it; returns what was freed
+pub fn dma_release(owner: usize) -> DmaPages {
+    let left = dma_pages(Some(owner));
+    let frames: Vec<FrameTracker> = {
+        let mut table = DMA_TABLE.lock();
+        let addrs: Vec<usize> = table.iter()
+            .filter(|(_, entry)| entry.owner == owner)
+            .map(|(pa, _)| *pa)
+            .collect();
+        addrs.iter().filter_map(|pa| table.remove(pa)).map(|entry| entry.frames).collect()
+    };
+    drop(frames);
+    left
+}
+
+/// put `frames` in the table, charged to the device running on this hart; returns their address
+fn track(frames: FrameTracker, kind: DmaKind) -> usize {
+    let pa = frames.range_ppn.start.start_addr().0;
+    let owner = CURRENT_OWNER[Instruction::get_tp()].load(Ordering::Relaxed);
+    DMA_TABLE.lock().insert(pa, DmaEntry { frames, owner, kind });
+    pa
+}
+
+/// take the `pages` frames at `pa` out of the table, None if nothing is handed out there
+fn untrack(pa: usize, pages: usize) -> Option<FrameTracker> {
+    let entry = DMA_TABLE.lock().remove(&pa)?;
+    assert_eq!(entry.frames.range_ppn.clone().count(), pages,
+        "[DMA] {:#x} freed with a size other than it was allocated with", pa);
+    Some(entry.frames)
+}
+
+unsafe impl virtio_drivers::Hal for VirtioHal {
+    fn dma_alloc(pages: usize, _direction: BufferDirection) -> (virtio_drivers::PhysAddr, NonNull<u8>) {
+        let frames = frames_alloc_clean(pages).expect("[DMA] out of memory for the rings of a queue");
+        let pa = track(frames, DmaKind::Ring);
+        (pa, NonNull::new(PhysAddr::from(pa).get_mut::<u8>()).unwrap())
+    }
+
+    unsafe fn dma_dealloc(paddr: virtio_drivers::PhysAddr, _vaddr: NonNull<u8>, pages: usize) -> i32 {
+        match untrack(paddr, pages) {
+            Some(_frames) => 0,
+            None => {
+                log::error!("[DMA] dma_dealloc of {:#x} ({} pages) which is not handed out", paddr, pages);
+                -1
+            }
+        }
+    }
+
+    unsafe fn mmio_phys_to_virt(paddr: virtio_drivers::PhysAddr, _size: usize) -> NonNull<u8> {
+        mmio_phys_to_virt(paddr)
+    }
+
+    unsafe fn share(
+        buffer: NonNull<[u8]>,
+        direction: BufferDirection,
+    ) -> virtio_drivers::PhysAddr {
+        let buffer = buffer.as_ref();
+        let pages = buffer.len().div_ceil(Constant::PAGE_SIZE);
+        let frames = frames_alloc(pages).expect("[DMA] out of memory for a bounce buffer");
+        match direction {
+            BufferDirection::DriverToDevice |
+            BufferDirection::Both => {
+                frames.range_ppn.get_slice_mut()[..buffer.len()].copy_from_slice(buffer);
+            }
+            BufferDirection::DeviceToDriver => {}
+        }
+        track(frames, DmaKind::Bounce)
+    }
 
-pub struct VirtioHal;
\ No newline at end of file
+    unsafe fn unshare(
+        paddr: virtio_drivers::PhysAddr,
+        mut buffer: NonNull<[u8]>,
+        direction: BufferDirection,
+    ) {
+        let buffer = buffer.as_mut();
+        let Some(frames) = untrack(paddr, buffer.len().div_ceil(Constant::PAGE_SIZE)) else {
+            log::error!("[DMA] unshare of {:#x} which is not handed out", paddr);
+            return;
+        };
+        match direction {
+            BufferDirection::DeviceToDriver |
+            BufferDirection::Both => {
+                buffer.copy_from_slice(&frames.range_ppn.get_slice()[..buffer.len()]);
+            }
+            BufferDirection::DriverToDevice => {}
+        }
+    }
+}
@@ -2,4 +2,4 @@ pub mod block;
 pub mod dma;
 pub mod net;
 pub mod serial;
-pub use block::BLOCK_DEVICE;
\ No newline at end of file
+pub use block::block_device;
\ No newline at end of file
@@ -1,6 +1,8 @@
 use alloc::{boxed::Box, collections::vec_deque::VecDeque, sync::Arc, vec,vec::Vec};
+use core::sync::atomic::{AtomicU16, Ordering};
 use fatfs::{info, warn};
 use smoltcp::phy::{DeviceCapabilities, Medium};
+use smoltcp::wire::{IpProtocol, Ipv4Packet, TcpPacket};
 use spin::Lazy;
 
 use crate::devices::{net::{EthernetAddress, NetBufPool, NetBuf}, DevError, DevResult, NetBufPtrTrait, NetDevice};
@@ -20,6 +22,27 @@ impl LoopbackDevice {
     }
 }
 
+/// the TCP port whose segments are dropped, 0 for none
+static BLACKHOLE_PORT: AtomicU16 = AtomicU16::new(0);
+
+/// drop the TCP segments from or to `port` from now on, 0 stops dropping
+pub fn set_blackhole(port: u16) {
+    BLACKHOLE_PORT.store(port, Ordering::Relaxed);
+}
+
+/// whether `packet` is a TCP segment of the blackholed port
+fn blackholed(packet: &[u8]) -> bool {
+    let port = BLACKHOLE_PORT.load(Ordering::Relaxed);
+    if port == 0 {
+        return false;
+    }
+    let Ok(ip) = Ipv4Packet::new_checked(packet) else {
+        return false;
+    };
+    ip.next_header() == IpProtocol::Tcp
+        && TcpPacket::new_checked(ip.payload()).is_ok_and(|tcp| tcp.src_port() == port || tcp.dst_port() == port)
+}
+
 unsafe impl Send for LoopbackDevice {}
 unsafe impl Sync for LoopbackDevice {}
 
@@ -58,6 +81,9 @@ impl NetDevice for LoopbackDevice {
     }
 
     fn transmit(&mut self, tx_buf: Box<dyn NetBufPtrTrait>) -> DevResult {
+        if blackholed(tx_buf.packet()) {
+            return Ok(());
+        }
         let data = tx_buf.packet().to_vec();
         // log::warn!("[Loopback::transmit] now transmit {} bytes", data.len());
         self.queue.push_back(data);
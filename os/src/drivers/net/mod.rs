@@ -3,25 +3,54 @@ pub mod virtio_net;
 pub mod loopback;
 use core::{mem, ptr::NonNull};
 
-use alloc::{boxed::Box, string::ToString};
+use alloc::{boxed::Box, string::ToString, sync::Arc};
 use fatfs::info;
 use spin::relax::Loop;
-use virtio_drivers::transport::{self, mmio::{MmioTransport, VirtIOHeader}, Transport};
-use hal::constant::{Constant, ConstantsHal};
-use crate::{devices::NetDevice, drivers::net::virtio_net::VirtIoNetDev};
+use virtio_drivers::transport::{self, mmio::{MmioTransport, VirtIOHeader}, DeviceType, Transport};
+use crate::{devices::{mmio::MmioDeviceDescripter, DevError, Device, NetDevice, DEVICE_MANAGER}, drivers::net::virtio_net::{VirtIoNetDev, VirtIoNetIrq}, net::poller};
 use loopback::LoopbackDevice;
-const VIRTIO1: usize = 0x10002000 | Constant::KERNEL_ADDR_SPACE.start;
-pub fn init_network_device() -> (Box<dyn NetDevice>,bool) {
-    let _devflag = false;
-    #[cfg(feature = "net")]
-    let _devflag = true;
-    log::info!("net device flag: {}",_devflag);
-    let dev:Box<dyn NetDevice> = if _devflag {
-        let header = NonNull::new(VIRTIO1 as *mut VirtIOHeader).unwrap();
-        let transport = unsafe{MmioTransport::new(header, 4096).unwrap()};
-        VirtIoNetDev::new(transport).unwrap()
-    }else {
-        LoopbackDevice::new()
+/// the device of the network stack, and whether it is a real NIC.
+/// A kernel built with `net` drives the first virtio-net among the virtio mmio slots,
+/// None if there is none or it fails to probe; the one built without gets the loopback device
+pub fn init_network_device() -> Option<(Box<dyn NetDevice>, bool)> {
+    if !cfg!(feature = "net") {
+        let dev: Box<dyn NetDevice> = LoopbackDevice::new();
+        return Some((dev, false));
+    }
+    let mmio_dev = DEVICE_MANAGER.lock().mmio.as_ref().and_then(|mmio| {
+        mmio.enumerate_devices()
+            .find(|dev| dev.transport().is_ok_and(|transport| transport.device_type() == DeviceType::Network))
+            .cloned()
+    });
+    let Some(mmio_dev) = mmio_dev else {
+        log::warn!("no virtio-net device found");
+        return None;
     };
-    (dev,_devflag)
-}
\ No newline at end of file
+    let dev = mmio_dev.transport().map_err(|_| DevError::BadState).and_then(VirtIoNetDev::new);
+    match dev {
+        Ok(dev) => {
+            register_irq(&mmio_dev);
+            let dev: Box<dyn NetDevice> = dev;
+            Some((dev, true))
+        }
+        Err(e) => {
+            log::warn!("virtio-net failed to probe: {:?}", e);
+            None
+        }
+    }
+}
+
+/// route the interrupt of the NIC to the poller, a NIC which can not interrupt is polled
+fn register_irq(mmio_dev: &MmioDeviceDescripter) {
+    let mut manager = DEVICE_MANAGER.lock();
+    let irq = manager.irq_ctrl.as_ref().and_then(|_| VirtIoNetIrq::new(mmio_dev));
+    let Some(irq) = irq else {
+        log::warn!("virtio-net has no interrupt, it is polled");
+        poller::poll_when_idle();
+        return;
+    };
+    let irq_no = irq.irq_no().unwrap();
+    manager.register_device(Arc::new(irq));
+    manager.unmask_irq(irq_no);
+    log::info!("virtio-net interrupts on irq {}", irq_no);
+}
@@ -5,13 +5,15 @@ use fatfs::warn;
 use log::info;
 use smoltcp::phy::{DeviceCapabilities, Medium};
 use crate::drivers::dma::VirtioHal;
-use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use alloc::{boxed::Box, string::ToString, sync::Arc, vec, vec::Vec};
 use smoltcp::phy::Device;
 use virtio_drivers::{
     device::net::VirtIONetRaw,
     transport::{mmio::MmioTransport, Transport},
 };
-use crate::devices::net::EthernetAddress;
+use crate::devices::{mmio::MmioDeviceDescripter, net::EthernetAddress, DevId, Device, DeviceMajor, DeviceMeta, DeviceType};
+use crate::net::poller;
+use crate::sync::mutex::SpinNoIrqLock;
 
 pub const NET_QUEUE_SIZE: usize = 32;
 pub struct VirtIoNetDev<T: Transport> {
@@ -154,3 +156,42 @@ impl<T: Transport> VirtIoNetDev<T> {
         EthernetAddress(self.raw_device.mac_address())
     }
  }
+
+/// the interrupt line of the virtio-net: the handler only drops the line and kicks the
+/// poller, the frames are taken by the poll of the interface
+pub struct VirtIoNetIrq {
+    /// a second transport on the registers of the device, for the interrupt status
+    transport: SpinNoIrqLock<MmioTransport>,
+    meta: DeviceMeta,
+}
+
+unsafe impl Send for VirtIoNetIrq {}
+unsafe impl Sync for VirtIoNetIrq {}
+
+impl VirtIoNetIrq {
+    /// the irq of the virtio-net at `mmio_dev`, None if the device tree gives it none
+    pub fn new(mmio_dev: &MmioDeviceDescripter) -> Option<Self> {
+        let irq_no = mmio_dev.irq_no?;
+        let transport = mmio_dev.transport().ok()?;
+        let meta = DeviceMeta {
+            dev_id: DevId { major: DeviceMajor::Net, minor: 0 },
+            name: "eth0".to_string(),
+            need_mapping: false,
+            mmio_ranges: vec![mmio_dev.mmio_region.clone()],
+            irq_no: Some(irq_no),
+            dtype: DeviceType::Net,
+        };
+        Some(Self { transport: SpinNoIrqLock::new(transport), meta })
+    }
+}
+
+impl Device for VirtIoNetIrq {
+    fn meta(&self) -> &DeviceMeta {
+        &self.meta
+    }
+
+    fn handle_irq(&self) {
+        self.transport.lock().ack_interrupt();
+        poller::kick();
+    }
+}
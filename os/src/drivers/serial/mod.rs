@@ -3,16 +3,16 @@
 #![allow(dead_code)]
 pub mod uart;
 
-use core::task::Waker;
+use core::{future::Future, pin::Pin, task::{Context, Poll, Waker}};
 
-use alloc::{boxed::Box, collections::vec_deque::VecDeque, string::ToString, sync::Arc, vec::Vec};
+use alloc::{boxed::Box, string::ToString, sync::Arc, vec::Vec};
 use async_trait::async_trait;
 use hal::constant::{Constant, ConstantsHal};
 use lazy_static::lazy_static;
 use uart::{Uart, UART_BAUD_RATE, UART_BUF_LEN};
 use alloc::vec;
 
-use crate::{devices::{CharDevice, DevId, Device, DeviceMajor, DeviceMeta, DeviceType, DEVICE_MANAGER}, sync::{mutex::SpinNoIrqLock, UPSafeCell}, utils::{get_waker, suspend_now, RingBuffer}, with_methods};
+use crate::{devices::{CharDevice, DevId, Device, DeviceMajor, DeviceMeta, DeviceType, DEVICE_MANAGER}, fs::devfs::tty, sync::{mutex::SpinNoIrqLock, WaitEntry, WaitQueue}, utils::{get_waker, RingBuffer}, with_methods};
 
 lazy_static! {
     /// WARNING: should only be called after devices manager finish init
@@ -37,14 +37,14 @@ pub trait UartDriver: Send + Sync {
 
 pub struct Serial {
     meta: DeviceMeta,
-    uart: UPSafeCell<Box<dyn UartDriver>>,
+    uart: SpinNoIrqLock<Box<dyn UartDriver>>,
     inner: SpinNoIrqLock<SerialInner>,
+    /// the readers and pollers waiting for input, registered under the inner lock
+    input_queue: WaitQueue,
 }
 
 pub struct SerialInner {
     read_buf: RingBuffer,
-    /// Hold wakers of pollin tasks.
-    pollin_queue: VecDeque<Waker>,
 }
 
 unsafe impl Send for Serial {}
@@ -66,56 +66,99 @@ impl Serial {
 
         Self {
             meta,
-            uart: UPSafeCell::new(driver),
+            uart: SpinNoIrqLock::new(driver),
             inner: SpinNoIrqLock::new(SerialInner {
                 read_buf: RingBuffer::new(UART_BUF_LEN),
-                pollin_queue: VecDeque::new(),
             }),
+            input_queue: WaitQueue::new(),
         }
     }
 
-    fn uart(&self) -> &mut Box<dyn UartDriver> {
-        &mut *self.uart.exclusive_access()
+    with_methods!(inner: SerialInner);
+
+    /// whether there is input, else queue the waiter of `entry` for it
+    fn has_input_or_wait(&self, entry: &mut WaitEntry, waker: &Waker) -> bool {
+        let uart = self.uart.lock();
+        self.with_mut_inner(|inner| {
+            if uart.poll_in() || !inner.read_buf.is_empty() {
+                self.input_queue.finish(entry);
+                return true;
+            }
+            self.input_queue.wait(entry, waker);
+            false
+        })
     }
+}
 
-    with_methods!(inner: SerialInner);
+/// waits for input as one reader, readers are woken one at a time in turn
+struct InputFuture<'a> {
+    serial: &'a Serial,
+    entry: WaitEntry,
+}
+
+impl Future for InputFuture<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if this.serial.has_input_or_wait(&mut this.entry, cx.waker()) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for InputFuture<'_> {
+    fn drop(&mut self) {
+        self.serial.input_queue.cancel(&mut self.entry);
+    }
 }
 
 #[async_trait]
 impl CharDevice for Serial {
     async fn read(&self, buf: &mut [u8]) -> usize {
-        while !self.poll_in().await {
-            suspend_now().await
-        }
+        InputFuture { serial: self, entry: WaitEntry::new() }.await;
         let mut len = 0;
         self.with_mut_inner(|inner| {
             len = inner.read_buf.read(buf);
         });
-        let uart = self.uart();
+        let mut uart = self.uart.lock();
         while uart.poll_in() && len < buf.len() {
             let c = uart.getc();
             buf[len] = c;
             len += 1;
         }
+        // what this reader left is for the next one
+        let left = uart.poll_in() || self.with_inner(|inner| !inner.read_buf.is_empty());
+        drop(uart);
+        if left {
+            self.input_queue.wake_one();
+        }
         len
     }
 
     async fn write(&self, buf: &[u8]) -> usize {
-        let uart = self.uart();
-        for &c in buf {
-            uart.putc(c)
+        let mut uart = self.uart.lock();
+        // each chunk is one record on the console, like the kernel's own output
+        for chunk in buf.chunks(hal::console::RECORD_SIZE) {
+            hal::console::with_console_lock(|| {
+                for &c in chunk {
+                    uart.putc(c)
+                }
+            });
         }
         buf.len()
     }
 
     async fn poll_in(&self) -> bool {
-        let uart = self.uart();
         let waker = get_waker().await;
+        let uart = self.uart.lock();
         self.with_mut_inner(|inner| {
             if uart.poll_in() || !inner.read_buf.is_empty() {
                 return true;
             }
-            inner.pollin_queue.push_back(waker);
+            self.input_queue.register(&waker);
             false
         })
     }
@@ -132,12 +175,13 @@ impl Device for Serial {
     }
 
     fn init(&self) {
-        unsafe { &mut *self.uart.get() }.as_mut().init();
+        self.uart.lock().init();
     }
 
     fn handle_irq(&self) {
-        let uart = self.uart();
-        self.with_mut_inner(|inner| {
+        let mut uart = self.uart.lock();
+        let arrived = self.with_mut_inner(|inner| {
+            let was_empty = inner.read_buf.is_empty();
             while uart.poll_in() {
                 let byte = uart.getc();
                 log::trace!(
@@ -148,11 +192,14 @@ impl Device for Serial {
                     break;
                 }
             }
-            // Round Robin
-            if let Some(waiting) = inner.pollin_queue.pop_front() {
-                waiting.wake();
-            }
+            was_empty && !inner.read_buf.is_empty()
         });
+        drop(uart);
+        // the readers take turns, every poller is told
+        self.input_queue.wake_one();
+        if arrived {
+            tty::input_arrived();
+        }
     }
 
     fn as_char(self: Arc<Self>) -> Option<Arc<dyn CharDevice>> {
@@ -1,4 +1,3 @@
-use alloc::collections::VecDeque;
 use alloc::task;
 use hal::println;
 use lazy_static::*;
@@ -8,48 +7,53 @@ use alloc::sync::Arc;
 use core::future::Future;
 use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use crate::processor;
-use crate::signal::{SigInfo, SIGKILL, SIGTERM};
 use crate::sync::mutex::SpinNoIrqLock;
 use crate::processor::processor::{current_processor, PROCESSORS};
+#[cfg(feature = "smp")]
+use crate::processor::processor::CPU_MASK_ALL;
 use crate::syscall::process;
-use crate::task::manager::TASK_MANAGER;
-use crate::task::INITPROC_PID;
+use crate::syscall::sche::SCHED_BATCH;
 use crate::task::{schedule::UserTaskFuture,task::TaskControlBlock};
 use crate::timer::timed_task::suspend_timeout;
+mod run_queue;
+pub mod shutdown;
+
+pub use run_queue::{nice_to_level, RunQueue, DEFAULT_LEVEL, IDLE_LEVEL, NICE_MAX, NICE_MIN};
+
 #[cfg(not(feature = "smp"))]
 pub struct TaskQueue {
-    queue: SpinNoIrqLock<VecDeque<Runnable>>,
+    queue: SpinNoIrqLock<RunQueue>,
 }
 #[allow(dead_code)]
 #[cfg(not(feature = "smp"))]
 impl TaskQueue {
     pub const fn new() -> Self {
         Self {
-            queue: SpinNoIrqLock::new(VecDeque::new()),
+            queue: SpinNoIrqLock::new(RunQueue::new()),
         }
     }
     
     pub fn init(&self)  {
-        *self.queue.lock() = VecDeque::new();
+        *self.queue.lock() = RunQueue::new();
     }
-    pub fn push(&self, runnable: Runnable) {
-        self.queue.lock().push_back(runnable);
+    pub fn push(&self, level: usize, runnable: Runnable) {
+        self.queue.lock().push_back(level, runnable);
     }
-    pub fn push_preempt(&self, runnable: Runnable) {
-        self.queue.lock().push_front(runnable);
+    pub fn push_preempt(&self, level: usize, runnable: Runnable) {
+        self.queue.lock().push_front(level, runnable);
     }
     pub fn fetch(&self) -> Option<Runnable> {
         self.queue.lock().pop_front()
     }   
     pub fn pop_back(&self) -> Option<Runnable> {
-        self.queue.lock().pop_back()
+        self.queue.lock().pop_back().map(|(_, runnable)| runnable)
     }
     pub fn is_empty(&self) -> bool {
         self.queue.lock().is_empty()
     }
 
     pub fn len(&self) -> usize {
-        self.queue.lock().len() as usize
+        self.queue.lock().len()
     }
 }
 #[cfg(not(feature = "smp"))]
@@ -63,39 +67,34 @@ pub fn spawn<F>(future: UserTaskFuture<F>) -> (Runnable, Task<F::Output>)
         F: Future + Send + 'static,
         F::Output: Send + 'static,
 {
-    #[cfg(feature = "smp")]
-    let cpu_mask_id = <Arc<TaskControlBlock> as Clone>::clone(&(&future.task.clone())).turn_cpu_mask_id();
+    // weak: the task holds its own waker, which holds this closure
+    let task = Arc::downgrade(&future.task);
     let schedule= move |runnable:Runnable, info: ScheduleInfo | {
+            // read the policy and nice value on every wake up,
+            // they may be changed by sched_setscheduler and setpriority
+            let (level, batch) = task.upgrade()
+                .map_or((DEFAULT_LEVEL, false), |t| (t.sched_level(), t.sched_policy() == SCHED_BATCH));
+            // a SCHED_BATCH task never preempts the others of its level on wake up
+            let woken_while_running = info.woken_while_running || batch;
             #[cfg(not(feature = "smp"))]
-            if info.woken_while_running{
-                TASK_QUEUE.push(runnable);
+            if woken_while_running{
+                TASK_QUEUE.push(level, runnable);
             }else {
-                TASK_QUEUE.push_preempt(runnable);
+                TASK_QUEUE.push_preempt(level, runnable);
             }
             #[cfg(feature = "smp")]
-            if info.woken_while_running{
-                unsafe{
-                    if cpu_mask_id == 4 {
-                        PROCESSORS[crate::processor::schedule::select_run_queue_index()]
-                        .unwrap_with_mut_task_queue(|task_queue|task_queue.push_back(runnable))
+            {
+                // read the affinity on every wake up, it may be changed by sched_setaffinity
+                let cpu_allowed = task.upgrade().map_or(CPU_MASK_ALL, |t| t.cpu_allowed());
+                let index = crate::processor::schedule::select_run_queue_index(cpu_allowed);
+                unsafe {
+                    if woken_while_running {
+                        PROCESSORS[index].unwrap_with_mut_task_queue(|task_queue|task_queue.push_back(level, runnable))
                     } else {
-                        PROCESSORS[cpu_mask_id]
-                        .unwrap_with_mut_task_queue(|task_queue|task_queue.push_back(runnable))
-                    }
-                    
-                };
-            }else {
-                unsafe{
-                    if cpu_mask_id == 4 {
-                        PROCESSORS[crate::processor::schedule::select_run_queue_index()]
-                        .unwrap_with_mut_task_queue(|task_queue|task_queue.push_front(runnable))
-                    } else {
-                        PROCESSORS[cpu_mask_id]
-                        .unwrap_with_mut_task_queue(|task_queue|task_queue.push_front(runnable))
+                        PROCESSORS[index].unwrap_with_mut_task_queue(|task_queue|task_queue.push_front(level, runnable))
                     }
                 }
             }
-            
     };
     async_task::spawn(future, WithInfo(schedule))
 }
@@ -108,9 +107,9 @@ pub fn kernel_spawn<F>(future: F) -> (Runnable, Task<F::Output>)
     let schedule= move |runnable:Runnable, _info: ScheduleInfo | {
         // todo: judge push method by ScheduleInfo
         #[cfg(not(feature = "smp"))]
-        TASK_QUEUE.push(runnable);
+        TASK_QUEUE.push(DEFAULT_LEVEL, runnable);
         #[cfg(feature = "smp")]
-        current_processor().unwrap_with_mut_task_queue(|task_queue|task_queue.push_back(runnable));
+        current_processor().unwrap_with_mut_task_queue(|task_queue|task_queue.push_back(DEFAULT_LEVEL, runnable));
     };
     async_task::spawn(future, WithInfo(schedule))
 }
@@ -145,46 +144,26 @@ pub fn os_is_shutting_down() -> bool {
     SystemStatus::ShutingDown == SYSTEM_STATUS.load(Ordering::Acquire).into()
 }
 
-#[allow(unused)]
-pub fn do_shutdown() -> Result<(), ()> {
-    if TASK_MANAGER.has_task_except_initproc() {
-        TASK_MANAGER.for_each_task(|task| {
-            if task.tid() == INITPROC_PID || !task.is_leader() {
-                return;
-            }
-            task.recv_sigs(SigInfo { si_signo: SIGKILL, si_code: SigInfo::KERNEL, si_pid: None });
-        });
-        Err(())
-    } else {
-        Ok(())
-    }
-}
-
 pub fn run_until_idle() -> usize {
     let mut len = 0;
+    // an idle hart has no task to park after
+    processor::ipi::park_if_requested();
     #[cfg(not(feature = "smp"))]
     while let Some(runnable) = TASK_QUEUE.fetch() {
         //info!("already fetch a runnable");
         runnable.run();
         len += 1;
+        processor::ipi::park_if_requested();
         if os_is_shutting_down() {
             break;
         }
     }
     #[cfg(feature = "smp")]
-    if current_processor().need_migrate_check() {
-        let processor = current_processor();
-        let migrate_id = processor.migrate_id();
-        processor.set_need_migrate(processor.id());
-        if let Some(migrate_runnable) = processor.unwrap_with_mut_task_queue(|task_queue| task_queue.pop_back()){
-            unsafe{PROCESSORS[migrate_id].unwrap_with_mut_task_queue(|task_queue| task_queue.push_back(migrate_runnable))};
-        }
-    }
-    #[cfg(feature = "smp")]
     while let Some(runnable) = current_processor().unwrap_with_mut_task_queue(|task_queue| task_queue.pop_front()) {
         //info!("already fetch a runnable, runnable_num: {:?},current_processor_id: {}",current_processor().task_nums(),current_processor().id());
         runnable.run();
         len += 1;
+        processor::ipi::park_if_requested();
         if os_is_shutting_down() {
             break;
         }
@@ -0,0 +1,90 @@
+//! multi-level run queue
+//! nice values are mapped to a few levels, round robin inside a level,
+//! the highest non-empty level runs first and a level passed over for too long is aged in.
+//! SCHED_IDLE tasks have a level of their own below every nice value, aged in far more slowly
+
+use alloc::collections::VecDeque;
+use async_task::Runnable;
+
+/// number of priority levels, level 0 is the highest
+pub const PRIO_LEVELS: usize = 6;
+/// level of SCHED_IDLE tasks, whatever their nice value
+pub const IDLE_LEVEL: usize = PRIO_LEVELS - 1;
+/// nice values sharing one level
+const NICE_PER_LEVEL: i32 = 8;
+/// level of kernel tasks and tasks with nice 0
+pub const DEFAULT_LEVEL: usize = nice_to_level(0);
+/// a non-empty level passed over this many times in a row runs next,
+/// so a nice 19 task still gets about one slice in AGING_LIMIT + 1 against a busy higher level
+const AGING_LIMIT: usize = 8;
+/// the aging limit of [`IDLE_LEVEL`], about the share linux gives a SCHED_IDLE task
+/// against a nice 0 one; it is not 0 so that a lock held by an idle task is let go at last
+const IDLE_AGING_LIMIT: usize = 256;
+
+/// the lowest nice value, highest priority
+pub const NICE_MIN: i32 = -20;
+/// the highest nice value, lowest priority
+pub const NICE_MAX: i32 = 19;
+
+/// the run queue level of a task with `nice`
+pub const fn nice_to_level(nice: i32) -> usize {
+    ((nice - NICE_MIN) / NICE_PER_LEVEL) as usize
+}
+
+/// runnables queued by priority level
+pub struct RunQueue {
+    levels: [VecDeque<Runnable>; PRIO_LEVELS],
+    /// times each level was passed over while non-empty since it last ran
+    waited: [usize; PRIO_LEVELS],
+}
+
+impl RunQueue {
+    /// an empty run queue
+    pub const fn new() -> Self {
+        Self {
+            levels: [const { VecDeque::new() }; PRIO_LEVELS],
+            waited: [0; PRIO_LEVELS],
+        }
+    }
+    /// queue at the tail of `level`
+    pub fn push_back(&mut self, level: usize, runnable: Runnable) {
+        self.levels[level].push_back(runnable);
+    }
+    /// queue at the head of `level`, to run before the others of the same level
+    pub fn push_front(&mut self, level: usize, runnable: Runnable) {
+        self.levels[level].push_front(runnable);
+    }
+    /// take the next runnable: the head of the highest non-empty level,
+    /// unless a lower level has waited long enough
+    pub fn pop_front(&mut self) -> Option<Runnable> {
+        let highest = self.levels.iter().position(|q| !q.is_empty())?;
+        let mut pick = highest;
+        for level in highest + 1..PRIO_LEVELS {
+            if self.levels[level].is_empty() {
+                self.waited[level] = 0;
+                continue;
+            }
+            self.waited[level] += 1;
+            let limit = if level == IDLE_LEVEL { IDLE_AGING_LIMIT } else { AGING_LIMIT };
+            if pick == highest && self.waited[level] > limit {
+                pick = level;
+            }
+        }
+        self.waited[pick] = 0;
+        self.levels[pick].pop_front()
+    }
+    /// take a runnable from the tail of the lowest non-empty level, with its level,
+    /// for moving to another queue
+    pub fn pop_back(&mut self) -> Option<(usize, Runnable)> {
+        let level = self.levels.iter().rposition(|q| !q.is_empty())?;
+        self.levels[level].pop_back().map(|r| (level, r))
+    }
+    /// number of queued runnables
+    pub fn len(&self) -> usize {
+        self.levels.iter().map(|q| q.len()).sum()
+    }
+    /// whether nothing is queued
+    pub fn is_empty(&self) -> bool {
+        self.levels.iter().all(|q| q.is_empty())
+    }
+}
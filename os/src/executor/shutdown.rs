@@ -0,0 +1,103 @@
+//! orderly power off
+//!
+//! The remaining user tasks get SIGTERM, then SIGKILL after a grace period,
+//! the other harts are parked, every dirty cached page is written back and the
+//! file systems are unmounted in reverse mount order, so the disk is left clean.
+//! Only then the platform power off is invoked. A watchdog checked on every
+//! timer interrupt forces the power off if any of this hangs.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+
+use hal::instruction::{Instruction, InstructionHal};
+use hal::klog;
+
+use crate::processor::ipi::park_other_harts;
+use crate::signal::{SigInfo, SIGKILL, SIGTERM};
+use crate::task::manager::TASK_MANAGER;
+use crate::task::INITPROC_PID;
+use crate::timer::get_current_time_duration;
+use crate::timer::timed_task::ksleep;
+
+/// how long the tasks may take to handle SIGTERM
+const TERM_GRACE: Duration = Duration::from_secs(1);
+/// how long the tasks may take to die of SIGKILL
+const KILL_GRACE: Duration = Duration::from_secs(2);
+/// the power goes off this long after the shutdown started, whatever is left
+const WATCHDOG: Duration = Duration::from_secs(5);
+/// how often to look whether the tasks are gone
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+/// the watchdog deadline in microseconds, 0 while no shutdown is going on
+static WATCHDOG_DEADLINE: AtomicU64 = AtomicU64::new(0);
+
+/// shut the system down and power it off, `spare` is the process asking for it,
+/// which is left alone. A second caller just waits for the power to go
+pub async fn power_off(spare: Option<usize>) -> ! {
+    if SHUTTING_DOWN.swap(true, Ordering::SeqCst) {
+        loop {
+            ksleep(WATCHDOG).await;
+        }
+    }
+    let deadline = get_current_time_duration() + WATCHDOG;
+    WATCHDOG_DEADLINE.store(deadline.as_micros() as u64, Ordering::SeqCst);
+    klog!("[shutdown] terminating the remaining tasks");
+
+    signal_all(spare, SIGTERM);
+    if !wait_for_tasks(spare, TERM_GRACE).await {
+        signal_all(spare, SIGKILL);
+        if !wait_for_tasks(spare, KILL_GRACE).await {
+            klog!("[shutdown] some tasks refuse to die, going on without them");
+        }
+    }
+
+    // no await from here on, this hart is the only one left running
+    park_other_harts();
+    klog!("[shutdown] unmounting the file systems");
+    crate::fs::unmount_all();
+    klog!("[shutdown] power off");
+    unsafe { Instruction::shutdown(false) }
+}
+
+/// force the power off once the shutdown took too long, called on timer interrupts
+pub fn check_watchdog() {
+    let deadline = WATCHDOG_DEADLINE.load(Ordering::Relaxed);
+    if deadline == 0 || (get_current_time_duration().as_micros() as u64) < deadline {
+        return;
+    }
+    klog!("[shutdown] the shutdown hangs, forcing the power off");
+    unsafe { Instruction::shutdown(false) }
+}
+
+/// whether `pid` is a live user process to be taken down
+fn is_victim(pid: usize, spare: Option<usize>) -> bool {
+    pid != INITPROC_PID && Some(pid) != spare
+}
+
+fn signal_all(spare: Option<usize>, signo: usize) {
+    TASK_MANAGER.for_each_task(|task| {
+        if !task.is_leader() || task.is_zombie() || !is_victim(task.pid(), spare) {
+            return;
+        }
+        task.recv_sigs(SigInfo::new(signo, SigInfo::KERNEL));
+    });
+}
+
+/// wait up to `timeout` for the victims to exit, return whether they all did
+async fn wait_for_tasks(spare: Option<usize>, timeout: Duration) -> bool {
+    let deadline = get_current_time_duration() + timeout;
+    loop {
+        let mut alive = false;
+        TASK_MANAGER.for_each_task(|task| {
+            alive |= !task.is_zombie() && is_victim(task.pid(), spare);
+        });
+        if !alive {
+            return true;
+        }
+        if get_current_time_duration() >= deadline {
+            return false;
+        }
+        ksleep(POLL_INTERVAL).await;
+    }
+}
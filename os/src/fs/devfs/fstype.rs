@@ -32,7 +32,7 @@ impl FSType for DevFsType {
         let root_dentry = SpDentry::new(name, parent.clone());
         root_dentry.set_inode(root_inode);
         sb.set_root_dentry(root_dentry.clone());
-        DCACHE.lock().insert(root_dentry.path(), root_dentry.clone());
+        DCACHE.pin(root_dentry.clone());
         self.add_sb(&root_dentry.path(), sb);
         Some(root_dentry)
     }
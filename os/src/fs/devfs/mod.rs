@@ -11,7 +11,7 @@ use tty::{TtyDentry, TtyFile, TtyInode, TTY};
 use urandom::{UrandomDentry, UrandomInode};
 use zero::{ZeroDentry, ZeroInode};
 
-use crate::{fs::{devfs::cpu_dma_latency::{CpuDmaLatencyDentry, CpuDmaLatencyInode}, tmpfs::{dentry::TmpDentry, inode::TmpInode}}, sync::mutex::SpinNoIrqLock};
+use crate::{fs::devfs::cpu_dma_latency::{CpuDmaLatencyDentry, CpuDmaLatencyInode}, sync::mutex::SpinNoIrqLock};
 
 use super::{vfs::{inode::InodeMode, Dentry, DentryInner, DentryState, Inode, InodeInner, DCACHE}, OpenFlags, SuperBlock};
 
@@ -34,7 +34,7 @@ pub fn init_devfs(root_dentry: Arc<dyn Dentry>) {
     tty_dentry.set_inode(tty_inode);
     root_dentry.add_child(tty_dentry.clone());
     log::debug!("dcache insert: {}", tty_dentry.path());
-    DCACHE.lock().insert(tty_dentry.path(), tty_dentry.clone());
+    DCACHE.pin(tty_dentry.clone());
     let tty_file = TtyFile::new(tty_dentry);
     TTY.call_once(|| tty_file);
 
@@ -44,15 +44,17 @@ pub fn init_devfs(root_dentry: Arc<dyn Dentry>) {
     null_dentry.set_inode(null_inode);
     root_dentry.add_child(null_dentry.clone());
     log::debug!("dcache insert: {}", null_dentry.path());
-    DCACHE.lock().insert(null_dentry.path(), null_dentry.clone());
-
-    // add /dev/rtc
-    let rtc_dentry = RtcDentry::new("rtc", Some(root_dentry.clone()));
-    let rtc_inode = RtcInode::new(sb.clone().unwrap());
-    rtc_dentry.set_inode(rtc_inode);
-    root_dentry.add_child(rtc_dentry.clone());
-    log::debug!("dcache insert: {}", rtc_dentry.path());
-    DCACHE.lock().insert(rtc_dentry.path(), rtc_dentry.clone());
+    DCACHE.pin(null_dentry.clone());
+
+    // add /dev/rtc0, and /dev/rtc for the programs that look for it
+    for name in ["rtc0", "rtc"] {
+        let rtc_dentry = RtcDentry::new(name, Some(root_dentry.clone()));
+        let rtc_inode = RtcInode::new(sb.clone().unwrap());
+        rtc_dentry.set_inode(rtc_inode);
+        root_dentry.add_child(rtc_dentry.clone());
+        log::debug!("dcache insert: {}", rtc_dentry.path());
+        DCACHE.pin(rtc_dentry.clone());
+    }
 
     // add /dev/urandom
     let urandom_dentry = UrandomDentry::new("urandom", Some(root_dentry.clone()));
@@ -60,7 +62,7 @@ pub fn init_devfs(root_dentry: Arc<dyn Dentry>) {
     urandom_dentry.set_inode(urandom_inode);
     root_dentry.add_child(urandom_dentry.clone());
     log::debug!("dcache insert: {}", urandom_dentry.path());
-    DCACHE.lock().insert(urandom_dentry.path(), urandom_dentry.clone());
+    DCACHE.pin(urandom_dentry.clone());
 
     // add /dev/zero
     let zero_dentry = ZeroDentry::new("zero", Some(root_dentry.clone()));
@@ -68,7 +70,7 @@ pub fn init_devfs(root_dentry: Arc<dyn Dentry>) {
     zero_dentry.set_inode(zero_inode);
     root_dentry.add_child(zero_dentry.clone());
     log::debug!("dcache insert: {}", zero_dentry.path());
-    DCACHE.lock().insert(zero_dentry.path(), zero_dentry.clone());
+    DCACHE.pin(zero_dentry.clone());
     
     // add /dev/cpu_dma_latency
     let cpu_dma_latency_dentry = CpuDmaLatencyDentry::new("cpu_dma_latency", Some(root_dentry.clone()));
@@ -76,16 +78,7 @@ pub fn init_devfs(root_dentry: Arc<dyn Dentry>) {
     cpu_dma_latency_dentry.set_inode(cpu_dma_latency_inode);
     root_dentry.add_child(cpu_dma_latency_dentry.clone());
     log::debug!("dcache insert: {}", cpu_dma_latency_dentry.path());
-    DCACHE.lock().insert(cpu_dma_latency_dentry.path(), cpu_dma_latency_dentry.clone());
-
-    // add /dev/shm
-    // TODO: now only implement by tmp file
-    let shm_dentry = TmpDentry::new("shm", Some(root_dentry.clone()));
-    let shm_inode = TmpInode::new(sb.clone().unwrap(), InodeMode::DIR);
-    shm_dentry.set_inode(shm_inode);
-    root_dentry.add_child(shm_dentry.clone());
-    log::debug!("dcache insert: {}", shm_dentry.path());
-    DCACHE.lock().insert(shm_dentry.path(), shm_dentry.clone());
+    DCACHE.pin(cpu_dma_latency_dentry.clone());
 }
 
 
@@ -1,11 +1,11 @@
-//! the real time clock device
+//! the real time clock device, /dev/rtc0 and /dev/rtc
 
 use alloc::sync::{Arc, Weak};
 use async_trait::async_trait;
 use alloc::boxed::Box;
-use hal::instruction::{Instruction, InstructionHal};
+use core::time::Duration;
 
-use crate::{config::BLOCK_SIZE, fs::{vfs::{inode::InodeMode, Dentry, DentryInner, File, FileInner, Inode, InodeInner}, Kstat, OpenFlags, StatxTimestamp, SuperBlock, Xstat, XstatMask}, sync::mutex::SpinNoIrqLock, syscall::{SysError, SysResult}};
+use crate::{config::BLOCK_SIZE, devices::rtc::{civil_from_days, days_from_civil, RTC}, fs::{vfs::{inode::InodeMode, Dentry, DentryInner, File, FileCount, FileInner, FileOwner, Inode, InodeInner, OpenRef}, Kstat, OpenFlags, StatxTimestamp, SuperBlock, Xstat, XstatMask}, mm::UserPtrRaw, sync::mutex::SpinNoIrqLock, syscall::{SysError, SysResult}, task::current_task, timer::{get_realtime_duration, set_realtime}};
 
 
 pub struct RtcFile {
@@ -16,8 +16,11 @@ impl RtcFile {
     pub fn new(dentry: Arc<dyn Dentry>) -> Arc<Self> {
         let inner = FileInner {
             offset: 0.into(),
+            open: OpenRef::new(&dentry),
             dentry,
             flags: SpinNoIrqLock::new(OpenFlags::empty()),
+            count: FileCount::new(),
+            owner: FileOwner::new(),
         };
         Arc::new(Self { inner })
     }
@@ -47,24 +50,100 @@ impl File for RtcFile {
         Ok(buf.len())
     }
 
-    fn ioctl(&self, _cmd: usize, arg: usize) -> SysResult {
-        unsafe {
-            Instruction::set_sum();
-            (arg as *mut RtcTime).write(RtcTime::default());
+    fn ioctl(&self, cmd: usize, arg: usize) -> SysResult {
+        let task = current_task().unwrap().clone();
+        match cmd {
+            RTC_RD_TIME => {
+                let now = match RTC.as_ref() {
+                    Some(rtc) => rtc.read_time(),
+                    None => get_realtime_duration(),
+                };
+                let user_tm = UserPtrRaw::new(arg as *mut RtcTime)
+                    .ensure_write(&mut task.get_vm_space().lock())
+                    .ok_or(SysError::EFAULT)?;
+                user_tm.write(RtcTime::from_secs(now.as_secs() as i64));
+                Ok(0)
+            }
+            RTC_SET_TIME => {
+                if !task.with_cred(|cred| cred.is_privileged()) {
+                    return Err(SysError::EACCES);
+                }
+                let tm = *UserPtrRaw::new(arg as *const RtcTime)
+                    .ensure_read(&mut task.get_vm_space().lock())
+                    .ok_or(SysError::EFAULT)?
+                    .to_ref();
+                let secs = tm.to_secs().ok_or(SysError::EINVAL)?;
+                let now = Duration::from_secs(secs);
+                // the rtc only, like linux the system clock is left alone
+                match RTC.as_ref() {
+                    Some(rtc) => rtc.set_time(now),
+                    None => set_realtime(now),
+                }
+                Ok(0)
+            }
+            _ => Err(SysError::ENOTTY),
         }
-        Ok(0)
     }
 }
 
-#[derive(Default)]
+/// ioctl: read the time of the rtc into a struct rtc_time
+const RTC_RD_TIME: usize = 0x8024_7009;
+/// ioctl: set the time of the rtc from a struct rtc_time, needs privilege
+const RTC_SET_TIME: usize = 0x4024_700a;
+
+/// broken down time of the rtc ioctls, see <linux/rtc.h>
+#[derive(Default, Clone, Copy)]
 #[repr(C)]
 pub struct RtcTime {
     tm_sec: i32,
     tm_min: i32,
     tm_hour: i32,
     tm_mday: i32,
+    /// month since january, 0..11
     tm_mon: i32,
+    /// years since 1900
     tm_year: i32,
+    /// days since sunday, 0..6
+    tm_wday: i32,
+    /// days since january 1st, 0..365
+    tm_yday: i32,
+    tm_isdst: i32,
+}
+
+impl RtcTime {
+    /// the utc time of `secs` since the epoch
+    fn from_secs(secs: i64) -> Self {
+        let days = secs.div_euclid(86400);
+        let rem = secs.rem_euclid(86400);
+        let (year, mon, mday) = civil_from_days(days);
+        Self {
+            tm_sec: (rem % 60) as i32,
+            tm_min: (rem / 60 % 60) as i32,
+            tm_hour: (rem / 3600) as i32,
+            tm_mday: mday as i32,
+            tm_mon: mon as i32 - 1,
+            tm_year: (year - 1900) as i32,
+            // 1970-01-01 is a thursday
+            tm_wday: (days + 4).rem_euclid(7) as i32,
+            tm_yday: (days - days_from_civil(year, 1, 1)) as i32,
+            tm_isdst: 0,
+        }
+    }
+
+    /// seconds since the epoch, None for a bad date or one before the epoch
+    fn to_secs(&self) -> Option<u64> {
+        if !(0..60).contains(&self.tm_sec) || !(0..60).contains(&self.tm_min)
+            || !(0..24).contains(&self.tm_hour) || !(0..12).contains(&self.tm_mon)
+            || !(1..=31).contains(&self.tm_mday) {
+            return None;
+        }
+        let days = days_from_civil(self.tm_year as i64 + 1900, self.tm_mon as u32 + 1, self.tm_mday as u32);
+        let secs = days * 86400
+            + self.tm_hour as i64 * 3600
+            + self.tm_min as i64 * 60
+            + self.tm_sec as i64;
+        u64::try_from(secs).ok()
+    }
 }
 
 pub struct RtcDentry {
@@ -113,7 +192,7 @@ impl RtcInode {
     pub fn new(super_block: Weak<dyn SuperBlock>) -> Arc<Self> {
         let size = BLOCK_SIZE;
         Arc::new(Self {
-            inner: InodeInner::new(Some(super_block), InodeMode::FILE, size),
+            inner: InodeInner::new(Some(super_block), InodeMode::CHAR, size),
         })
     }
 }
@@ -129,7 +208,7 @@ impl Inode for RtcInode {
         Kstat {
             st_dev: 0,
             st_ino: inner.ino as u64,
-            st_mode: inner.mode.bits() as _,
+            st_mode: inner.mode().bits() as _,
             st_nlink: inner.nlink() as u32,
             st_uid: 0,
             st_gid: 0,
@@ -148,27 +227,27 @@ impl Inode for RtcInode {
         }
     }
 
-    fn getxattr(&self, mask: crate::fs::XstatMask) -> crate::fs::Xstat {
+    fn getxattr(&self, _mask: crate::fs::XstatMask) -> crate::fs::Xstat {
         const SUPPORTED_MASK: XstatMask = XstatMask::from_bits_truncate({
             XstatMask::STATX_BLOCKS.bits |
             XstatMask::STATX_ATIME.bits |
             XstatMask::STATX_CTIME.bits |
             XstatMask::STATX_MTIME.bits |
             XstatMask::STATX_NLINK.bits |
+            XstatMask::STATX_TYPE.bits |
             XstatMask::STATX_MODE.bits |
             XstatMask::STATX_SIZE.bits |
             XstatMask::STATX_INO.bits
         });
-        let mask = mask & SUPPORTED_MASK;
         let inner = self.inode_inner();
         Xstat {
-            stx_mask: mask.bits,
+            stx_mask: SUPPORTED_MASK.bits,
             stx_blksize: 0,
             stx_attributes: 0,
             stx_nlink: inner.nlink() as u32,
             stx_uid: 0,
             stx_gid: 0,
-            stx_mode: inner.mode.bits() as _,
+            stx_mode: inner.mode().bits() as _,
             stx_ino: inner.ino as u64,
             stx_size: inner.size() as _,
             stx_blocks: 0,
@@ -8,8 +8,9 @@ use hal::console::console_getchar;
 use spin::Once;
 use strum::FromRepr;
 use lazy_static::lazy_static;
+use spin::Lazy;
 
-use crate::{devices::CharDevice, drivers::serial::UART0, fs::{vfs::{inode::InodeMode, Dentry, DentryInner, File, FileInner, Inode, InodeInner}, Kstat, OpenFlags, StatxTimestamp, SuperBlock, Xstat, XstatMask}, sync::mutex::SpinNoIrqLock, syscall::{SysError, SysResult}, task::{current_task, suspend_current_and_run_next}};
+use crate::{devices::{CharDevice, DevId, DeviceMajor}, drivers::serial::UART0, fs::{stdio::CONSOLE_BLKSIZE, vfs::{file::PollEvents, inode::InodeMode, ioctl, Dentry, DentryInner, FasyncList, File, FileCount, FileInner, FileOwner, Inode, InodeInner, OpenRef}, Kstat, OpenFlags, StatxTimestamp, SuperBlock, Xstat, XstatMask}, signal::{SigInfo, SIGCONT, SIGHUP}, sync::mutex::SpinNoIrqLock, syscall::{SysError, SysResult}, task::{current_task, manager::TASK_MANAGER, suspend_current_and_run_next, INITPROC_PID}};
 
 /// Defined in <asm-generic/ioctls.h>
 #[derive(FromRepr, Debug)]
@@ -49,6 +50,13 @@ enum TtyIoctlCmd {
     TIOCGPGRP = 0x540F,
     /// Set the foreground process group ID of this terminal.
     TIOCSPGRP = 0x5410,
+    /// Make the terminal the controlling terminal of the session of the caller,
+    /// arg 1 steals it from another session.
+    TIOCSCTTY = 0x540E,
+    /// Give up the controlling terminal.
+    TIOCNOTTY = 0x5422,
+    /// Get the session ID of the terminal.
+    TIOCGSID = 0x5429,
     /// Get window size.
     TIOCGWINSZ = 0x5413,
     /// Set window size.
@@ -141,22 +149,123 @@ impl Termios {
 
 pub static TTY: Once<Arc<TtyFile>> = Once::new();
 
+/// the opens of the tty with O_ASYNC on
+pub static TTY_FASYNC: FasyncList = FasyncList::new();
+
+/// the serial port got input into its empty buffer, signal the owners of the O_ASYNC opens
+pub fn input_arrived() {
+    TTY_FASYNC.notify();
+}
+
+/// the state of the device, shared by every open of it
+static TTY_META: Lazy<SpinNoIrqLock<TtyMeta>> = Lazy::new(|| SpinNoIrqLock::new(TtyMeta {
+    fg_pgid: 1 as u32, // warning: shell will use this process group id
+    session: Some(INITPROC_PID),
+    win_size: WinSize::new(),
+    termios: Termios::new(),
+}));
+
+/// the session of a process and its link to the tty
+#[derive(Debug, Clone, Copy)]
+pub struct Session {
+    /// session id, the pid of its leader
+    pub sid: usize,
+    /// the tty is the controlling terminal of the process
+    pub ctty: bool,
+    /// the controlling terminal was hung up under the process, its reads fail with EIO
+    pub hung_up: bool,
+}
+
+impl Session {
+    /// the session led by `sid`, the one of initproc owns the tty from the boot
+    pub fn new(sid: usize) -> Self {
+        Self { sid, ctty: sid == INITPROC_PID, hung_up: false }
+    }
+}
+
+/// send `signo` from the kernel to every process of the group `pgid`
+fn signal_group(pgid: usize, signo: usize) {
+    TASK_MANAGER.for_each_task(|task| {
+        if task.is_leader() && task.pgid() == pgid {
+            task.recv_sigs_process_level(SigInfo::new(signo, SigInfo::KERNEL));
+        }
+    });
+}
+
+/// take the tty from the processes of the session `sid`, and hang it up under them if `hang_up`
+fn detach_session(sid: usize, hang_up: bool) {
+    TASK_MANAGER.for_each_task(|task| {
+        task.with_mut_session(|session| {
+            if session.sid == sid && session.ctty {
+                session.ctty = false;
+                session.hung_up |= hang_up;
+            }
+        });
+    });
+}
+
+/// the console has no getty to take it again: once another session lets it go, it goes back
+/// to the session of initproc as at the boot
+fn give_back(meta: &mut TtyMeta) {
+    meta.session = Some(INITPROC_PID);
+    meta.fg_pgid = INITPROC_PID as u32;
+    TASK_MANAGER.for_each_task(|task| {
+        task.with_mut_session(|session| {
+            if session.sid == INITPROC_PID && !session.hung_up {
+                session.ctty = true;
+            }
+        });
+    });
+}
+
+/// the tty is the controlling terminal of the caller
+pub fn is_ctty_of_current() -> bool {
+    let session = current_task().unwrap().session();
+    session.ctty && TTY_META.lock().session == Some(session.sid)
+}
+
+/// vhangup: hang up the controlling terminal of the caller. Its session loses the tty and
+/// can no longer read it, the foreground group gets SIGHUP and the leader SIGHUP and SIGCONT.
+/// initproc keeps a hung up tty from its session, no one takes it back then
+pub fn hangup() -> SysResult {
+    if !is_ctty_of_current() {
+        return Ok(0);
+    }
+    let (sid, fg_pgid) = {
+        let mut meta = TTY_META.lock();
+        let Some(sid) = meta.session.take() else {
+            return Ok(0);
+        };
+        (sid, meta.fg_pgid as usize)
+    };
+    detach_session(sid, true);
+    signal_group(fg_pgid, SIGHUP);
+    if let Some(leader) = TASK_MANAGER.get_task(sid) {
+        for signo in [SIGHUP, SIGCONT] {
+            leader.recv_sigs_process_level(SigInfo::new(signo, SigInfo::KERNEL));
+        }
+    }
+    if sid != INITPROC_PID {
+        give_back(&mut TTY_META.lock());
+    }
+    Ok(0)
+}
+
 pub struct TtyFile {
-    pub(crate) meta: SpinNoIrqLock<TtyMeta>,
+    pub(crate) meta: &'static SpinNoIrqLock<TtyMeta>,
     inner: FileInner,
 }
 
 impl TtyFile {
     pub fn new(dentry: Arc<dyn Dentry>) -> Arc<Self> {
-        let meta = SpinNoIrqLock::new(TtyMeta {
-            fg_pgid: 1 as u32, // warning: shell will use this process group id
-            win_size: WinSize::new(),
-            termios: Termios::new(),
-        });
+        let meta = &*TTY_META;
         let inner = FileInner {
             offset: 0.into(),
+            open: OpenRef::new(&dentry),
             dentry,
             flags: SpinNoIrqLock::new(OpenFlags::empty()),
+            count: FileCount::new(),
+            owner: FileOwner::new(),
         };
         Arc::new(Self { meta, inner })
     }
@@ -164,6 +273,8 @@ impl TtyFile {
 
 pub struct TtyMeta {
     fg_pgid: u32,
+    /// the session the tty is the controlling terminal of
+    session: Option<usize>,
     win_size: WinSize,
     termios: Termios,
 }
@@ -182,7 +293,14 @@ impl File for TtyFile {
         true
     }
 
+    fn fasync_list(&self) -> Option<&FasyncList> {
+        Some(&TTY_FASYNC)
+    }
+
     async fn read(&self, buf: &mut [u8]) -> Result<usize, SysError> {
+        if current_task().unwrap().session().hung_up {
+            return Err(SysError::EIO);
+        }
         let char_dev = UART0.clone();
         //let len = char_dev.read(buf).await;
         let mut c: usize;
@@ -222,63 +340,121 @@ impl File for TtyFile {
         Ok(len)
     }
 
+    /// readable once the serial line received something, always writable
+    async fn poll(&self, events: PollEvents) -> PollEvents {
+        let mut res = events & PollEvents::OUT;
+        if events.contains(PollEvents::IN) && UART0.poll_in().await {
+            res |= PollEvents::IN;
+        }
+        res
+    }
+
+    /// the termios and window size set, the struct arguments go through the user pointer checks
     fn ioctl(&self, cmd: usize, arg: usize) -> SysResult {
         use TtyIoctlCmd::*;
         let Some(cmd) = TtyIoctlCmd::from_repr(cmd) else {
-            log::error!("[TtyFile::ioctl] cmd {cmd} not included");
-            unimplemented!()
+            log::warn!("[TtyFile::ioctl] cmd {cmd:#x} not supported");
+            return Err(SysError::ENOTTY);
         };
         log::debug!("[TtyFile::ioctl] cmd {:?}, value {:#x}", cmd, arg);
         match cmd {
             TCGETS | TCGETA => {
-                unsafe {
-                    *(arg as *mut Termios) = self.meta.lock().termios;
-                }
-                Ok(0)
+                let termios = self.meta.lock().termios;
+                ioctl::write_arg(arg, termios)
             }
             TCSETS | TCSETSW | TCSETSF => {
-                unsafe {
-                    self.meta.lock().termios = *(arg as *const Termios);
-                    log::debug!("termios {:#x?}", self.meta.lock().termios);
-                }
+                let termios = ioctl::read_arg::<Termios>(arg)?;
+                self.meta.lock().termios = termios;
+                log::debug!("termios {:#x?}", termios);
                 Ok(0)
             }
             TIOCGPGRP => {
                 let fg_pgid = self.meta.lock().fg_pgid;
                 log::debug!("[TtyFile::ioctl] get fg pgid {fg_pgid}");
-                unsafe {
-                    *(arg as *mut u32) = fg_pgid;
-                }
-                Ok(0)
+                ioctl::write_arg(arg, fg_pgid)
             }
             TIOCSPGRP => {
-                unsafe {
-                    self.meta.lock().fg_pgid = *(arg as *const u32);
-                }
-                let fg_pgid = self.meta.lock().fg_pgid;
+                let fg_pgid = ioctl::read_arg::<u32>(arg)?;
+                self.meta.lock().fg_pgid = fg_pgid;
                 log::debug!("[TtyFile::ioctl] set fg pgid {fg_pgid}");
                 Ok(0)
             }
             TIOCGWINSZ => {
                 let win_size = self.meta.lock().win_size;
                 log::debug!("[TtyFile::ioctl] get window size {win_size:?}",);
-                unsafe {
-                    *(arg as *mut WinSize) = win_size;
+                ioctl::write_arg(arg, win_size)
+            }
+            TIOCSWINSZ => {
+                let win_size = ioctl::read_arg::<WinSize>(arg)?;
+                self.meta.lock().win_size = win_size;
+                Ok(0)
+            }
+            TIOCSCTTY => {
+                let task = current_task().unwrap();
+                let session = task.session();
+                let mut meta = self.meta.lock();
+                if session.ctty && meta.session == Some(session.sid) {
+                    return Ok(0);
+                }
+                // only a session leader without a controlling terminal takes one
+                if task.pid() != session.sid || session.ctty {
+                    return Err(SysError::EPERM);
+                }
+                if let Some(other) = meta.session.filter(|&other| other != session.sid) {
+                    if arg != 1 || !task.with_cred(|c| c.is_privileged()) {
+                        return Err(SysError::EPERM);
+                    }
+                    detach_session(other, false);
                 }
+                meta.session = Some(session.sid);
+                meta.fg_pgid = task.pgid() as u32;
+                drop(meta);
+                task.with_mut_session(|s| {
+                    s.ctty = true;
+                    s.hung_up = false;
+                });
                 Ok(0)
             }
-            TIOCSWINSZ => {
-                unsafe {
-                    self.meta.lock().win_size = *(arg as *const WinSize);
+            TIOCNOTTY => {
+                if !is_ctty_of_current() {
+                    return Err(SysError::ENOTTY);
+                }
+                let task = current_task().unwrap();
+                let sid = task.session().sid;
+                if task.pid() != sid {
+                    task.with_mut_session(|s| s.ctty = false);
+                    return Ok(0);
+                }
+                // the leader gives it up for the whole session
+                let fg_pgid = {
+                    let mut meta = self.meta.lock();
+                    meta.session = None;
+                    meta.fg_pgid as usize
+                };
+                detach_session(sid, false);
+                signal_group(fg_pgid, SIGHUP);
+                signal_group(fg_pgid, SIGCONT);
+                if sid != INITPROC_PID {
+                    give_back(&mut self.meta.lock());
                 }
                 Ok(0)
             }
+            TIOCGSID => {
+                if !is_ctty_of_current() {
+                    return Err(SysError::ENOTTY);
+                }
+                ioctl::write_arg(arg, current_task().unwrap().session().sid as u32)
+            }
             TCSBRK => Ok(0),
-            _ => todo!(),
+            // the termio set is not kept apart from termios
+            TCSETA | TCSETAW | TCSETAF => Err(SysError::ENOTTY),
         }
     }
 }
 
+/// the device number of the tty, the serial port behind it
+const TTY_DEV_ID: DevId = DevId { major: DeviceMajor::Serial, minor: 0 };
+
 pub struct TtyInode {
     inner: InodeInner,
     char_dev: Arc<dyn CharDevice>,
@@ -303,15 +479,15 @@ impl Inode for TtyInode {
         Kstat {
             st_dev: 0,
             st_ino: inner.ino as u64,
-            st_mode: inner.mode.bits() as _,
+            st_mode: inner.mode().bits() as _,
             st_nlink: inner.nlink() as u32,
             st_uid: 0,
             st_gid: 0,
-            st_rdev: 0,
+            st_rdev: TTY_DEV_ID.makedev() as u64,
             _pad0: 0,
             st_size: inner.size() as _,
             _pad1: 0,
-            st_blksize: 0,
+            st_blksize: CONSOLE_BLKSIZE,
             st_blocks: 0,
             st_atime_sec: inner.atime().tv_sec as _,
             st_atime_nsec: inner.atime().tv_nsec as _,
@@ -322,27 +498,27 @@ impl Inode for TtyInode {
         }
     }
 
-    fn getxattr(&self, mask: crate::fs::XstatMask) -> crate::fs::Xstat {
+    fn getxattr(&self, _mask: crate::fs::XstatMask) -> crate::fs::Xstat {
         const SUPPORTED_MASK: XstatMask = XstatMask::from_bits_truncate({
             XstatMask::STATX_BLOCKS.bits |
             XstatMask::STATX_ATIME.bits |
             XstatMask::STATX_CTIME.bits |
             XstatMask::STATX_MTIME.bits |
             XstatMask::STATX_NLINK.bits |
+            XstatMask::STATX_TYPE.bits |
             XstatMask::STATX_MODE.bits |
             XstatMask::STATX_SIZE.bits |
             XstatMask::STATX_INO.bits
         });
-        let mask = mask & SUPPORTED_MASK;
         let inner = self.inode_inner();
         Xstat {
-            stx_mask: mask.bits,
-            stx_blksize: 0,
+            stx_mask: SUPPORTED_MASK.bits,
+            stx_blksize: CONSOLE_BLKSIZE as u32,
             stx_attributes: 0,
             stx_nlink: inner.nlink() as u32,
             stx_uid: 0,
             stx_gid: 0,
-            stx_mode: inner.mode.bits() as _,
+            stx_mode: inner.mode().bits() as _,
             stx_ino: inner.ino as u64,
             stx_size: inner.size() as _,
             stx_blocks: 0,
@@ -363,8 +539,8 @@ impl Inode for TtyInode {
                 tv_sec: inner.mtime().tv_sec as _,
                 tv_nsec: inner.mtime().tv_nsec as _,
             },
-            stx_rdev_major: 0,
-            stx_rdev_minor: 0,
+            stx_rdev_major: TTY_DEV_ID.major as u32,
+            stx_rdev_minor: TTY_DEV_ID.minor as u32,
             stx_dev_major: 0,
             stx_dev_minor: 0,
             stx_mnt_id: 0,
@@ -412,7 +588,11 @@ impl Dentry for TtyDentry {
         dentry
     }
     
+    /// the controlling terminal of the caller, none without one
     fn open(self: Arc<Self>, flags: OpenFlags) -> Option<Arc<dyn File>> {
+        if !is_ctty_of_current() {
+            return None;
+        }
         Some(TtyFile::new(self.clone()))
     }
 }
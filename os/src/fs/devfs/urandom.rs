@@ -7,7 +7,7 @@ use async_trait::async_trait;
 use alloc::boxed::Box;
 use hal::instruction::{Instruction, InstructionHal};
 
-use crate::{config::BLOCK_SIZE, fs::{vfs::{inode::InodeMode, Dentry, DentryInner, File, FileInner, Inode, InodeInner}, Kstat, OpenFlags, StatxTimestamp, SuperBlock, Xstat, XstatMask}, sync::mutex::SpinNoIrqLock, syscall::SysError};
+use crate::{config::BLOCK_SIZE, fs::{vfs::{inode::InodeMode, Dentry, DentryInner, File, FileCount, FileInner, FileOwner, Inode, InodeInner, OpenRef}, Kstat, OpenFlags, StatxTimestamp, SuperBlock, Xstat, XstatMask}, sync::mutex::SpinNoIrqLock, syscall::SysError};
 
 /// Linear congruence generator (LCG)
 pub struct SimpleRng {
@@ -79,8 +79,11 @@ impl UrandomFile {
     pub fn new(dentry: Arc<dyn Dentry>) -> Arc<Self> {
         let inner = FileInner {
             offset: 0.into(),
+            open: OpenRef::new(&dentry),
             dentry,
             flags: SpinNoIrqLock::new(OpenFlags::empty()),
+            count: FileCount::new(),
+            owner: FileOwner::new(),
         };
         Arc::new(Self { inner })
     }
@@ -172,7 +175,7 @@ impl Inode for UrandomInode {
         Kstat {
             st_dev: 0,
             st_ino: inner.ino as u64,
-            st_mode: inner.mode.bits() as _,
+            st_mode: inner.mode().bits() as _,
             st_nlink: inner.nlink() as u32,
             st_uid: 0,
             st_gid: 0,
@@ -191,27 +194,27 @@ impl Inode for UrandomInode {
         }
     }
 
-    fn getxattr(&self, mask: crate::fs::XstatMask) -> crate::fs::Xstat {
+    fn getxattr(&self, _mask: crate::fs::XstatMask) -> crate::fs::Xstat {
         const SUPPORTED_MASK: XstatMask = XstatMask::from_bits_truncate({
             XstatMask::STATX_BLOCKS.bits |
             XstatMask::STATX_ATIME.bits |
             XstatMask::STATX_CTIME.bits |
             XstatMask::STATX_MTIME.bits |
             XstatMask::STATX_NLINK.bits |
+            XstatMask::STATX_TYPE.bits |
             XstatMask::STATX_MODE.bits |
             XstatMask::STATX_SIZE.bits |
             XstatMask::STATX_INO.bits
         });
-        let mask = mask & SUPPORTED_MASK;
         let inner = self.inode_inner();
         Xstat {
-            stx_mask: mask.bits,
+            stx_mask: SUPPORTED_MASK.bits,
             stx_blksize: 0,
             stx_attributes: 0,
             stx_nlink: inner.nlink() as u32,
             stx_uid: 0,
             stx_gid: 0,
-            stx_mode: inner.mode.bits() as _,
+            stx_mode: inner.mode().bits() as _,
             stx_ino: inner.ino as u64,
             stx_size: inner.size() as _,
             stx_blocks: 0,
@@ -44,45 +44,10 @@ impl Dentry for Ext4Dentry {
         Some(Arc::new(Ext4File::new(readable, writable, self.clone())))
     }
     fn load_child_dentry(self: Arc<Self>) -> Result<Vec<Arc<dyn Dentry>>, SysError> {
-        //info!("in child dentry, under: {}", self.path());
-        let inode = self.inode().unwrap().clone();
-        let mut child_dentrys: Vec<Arc<dyn Dentry>> = Vec::new();
-        // look into the children first
-        // to avoid unneccsary IO
-        for (_, child) in self.children().iter() {
-            if child.state() == DentryState::NEGATIVE {
-                // skip the invalid child dentry
-                // todo: should invalid dentry stay or clean?
-                continue;
-            }
-            child_dentrys.push(child.clone());
-        }
-        // try to update
-        for name in inode.ls() {
-            // skip the . and ..
-            if name == "." || name == ".." {
-                continue;
-            }
-
-            if let Some(_child_dentry) = self.get_child(&name) {
-                // do nothing 
-            } else {
-                // not find in the mem
-                // try to find by IO
-                log::debug!("look up name: {}", name);
-                let child_inode = inode.lookup(&name).unwrap();
-                let child_dentry = self.new(
-                    &name,
-                    Some(self.clone()),
-                );
-                child_dentry.set_inode(child_inode);
-                child_dentry.set_state(DentryState::USED);
-                self.add_child(child_dentry.clone());
-                DCACHE.lock().insert(child_dentry.path(), child_dentry.clone());
-                child_dentrys.push(child_dentry);
-            }
-        }
-        Ok(child_dentrys)
+        // the directory on disk is the truth, the children are only a cache of it
+        let names = self.inode().unwrap().ls();
+        let this: Arc<dyn Dentry> = self;
+        this.sync_children(names)
     }
 
     fn new_neg_dentry(self: Arc<Self>, name: &str) -> Arc<dyn Dentry> {
@@ -92,4 +57,8 @@ impl Dentry for Ext4Dentry {
         neg_dentry.set_state(DentryState::NEGATIVE);
         neg_dentry
     }
+    fn can_evict(&self) -> bool {
+        // everything can be looked up again on the disk
+        true
+    }
 }
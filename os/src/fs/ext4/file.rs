@@ -10,7 +10,7 @@ use hal::println;
 
 use crate::fs::page::page::PAGE_SIZE;
 use crate::fs::vfs::dentry::global_find_dentry;
-use crate::fs::vfs::file::SeekFrom;
+use crate::fs::vfs::file::{io_in_chunks, SeekFrom};
 use crate::fs::vfs::inode::InodeMode;
 use crate::fs::vfs::{Dentry, DentryState, Inode, DCACHE};
 use crate::fs::FS_MANAGER;
@@ -28,23 +28,21 @@ use super::inode::Ext4Inode;
 use super::disk::Disk;
 
 use crate::fs::{
-    vfs::{File, FileInner},
+    vfs::{File, FileCount, FileInner, FileOwner, OpenRef},
     OpenFlags,
 };
-use crate::sync::UPSafeCell;
 use alloc::sync::Arc;
 use bitflags::*;
 use lazy_static::*;
 
 use log::*;
 
-
 /// A wrapper around a filesystem inode
 /// to implement File trait atop
 pub struct Ext4File {
     readable: bool,
     writable: bool,
-    inner: UPSafeCell<FileInner>,
+    inner: FileInner,
 }
 
 unsafe impl Send for Ext4File {}
@@ -56,21 +54,33 @@ impl Ext4File {
         Self {
             readable,
             writable,
-            inner: UPSafeCell::new(FileInner { 
+            inner: FileInner { 
                 offset: AtomicUsize::new(0), 
+                open: OpenRef::new(&dentry),
                 dentry, 
-                flags: SpinNoIrqLock::new(OpenFlags::empty()), 
-            }),
+                flags: SpinNoIrqLock::new(OpenFlags::empty()),
+                count: FileCount::new(), 
+                owner: FileOwner::new(),
+            },
         }
     }
 
-    /// Read all data inside a inode into vector
+    /// Read all data inside a inode into vector,
+    /// stopping at the size seen on entry even if the file grows meanwhile
     pub fn read_all(&self) -> Vec<u8> {
-        let inode = self.dentry().unwrap().inode().unwrap();
+        let inode = self.inode().unwrap();
+        let end = self.size();
         let mut buffer = [0u8; PAGE_SIZE];
         let mut v: Vec<u8> = Vec::new();
-        loop {
-            let len = inode.clone().cache_read_at(self.pos(), &mut buffer).unwrap();
+        while self.pos() < end {
+            let want = cmp::min(PAGE_SIZE, end - self.pos());
+            let len = match inode.clone().cache_read_at(self.pos(), &mut buffer[..want]) {
+                Ok(len) => len,
+                Err(e) => {
+                    warn!("[Ext4File::read_all] read failed: {:?}", SysError::from(e));
+                    break;
+                }
+            };
             if len == 0 {
                 break;
             }
@@ -79,12 +89,23 @@ impl Ext4File {
         }
         v
     }
+
+    /// read at `offset` no further than the current end of file,
+    /// the size is checked again on every call as the file may be truncated concurrently
+    fn read_in_size(&self, inode: Arc<dyn Inode>, offset: usize, buf: &mut [u8]) -> Result<usize, SysError> {
+        let size = self.size();
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = cmp::min(buf.len(), size - offset);
+        inode.cache_read_at(offset, &mut buf[..len]).map_err(SysError::from)
+    }
 }
 
 #[async_trait]
 impl File for Ext4File {
     fn file_inner(&self) -> &FileInner {
-        self.inner.exclusive_access()
+        &self.inner
     }
     fn readable(&self) -> bool {
         self.readable
@@ -98,33 +119,39 @@ impl File for Ext4File {
     }
 
     async fn read(&self, buf: &mut [u8]) -> Result<usize, SysError> {
-        let inode = self.dentry().unwrap().inode().unwrap();
-
-        let size = inode.cache_read_at(self.pos(), buf).unwrap();
-        self.seek(SeekFrom::Current(size as i64)).expect("seek failed");
-        Ok(size)
+        // a big read goes in chunks and lets the others run between them
+        io_in_chunks(buf.len(), |chunk| {
+            let inode = self.inode().unwrap();
+            self.file_inner().read_with(|pos| self.read_in_size(inode.clone(), pos, &mut buf[chunk.clone()]))
+        }).await
     }
     async fn write(&self, buf: &[u8]) -> Result<usize, SysError> {
-        if self.flags().contains(OpenFlags::O_APPEND) {
-            self.set_pos(self.size());
-        }
-        let pos = self.pos();
-        let inode = self.dentry().unwrap().inode().unwrap();
-        let size = inode.cache_write_at(pos, buf).unwrap();
-        self.set_pos(pos + size);
-        Ok(size)
+        let size = || self.size();
+        let append_end: Option<&(dyn Fn() -> usize + Sync)> = if self.flags().contains(OpenFlags::O_APPEND) {
+            Some(&size)
+        } else {
+            None
+        };
+        // every chunk reserves its own range, a short write gives back the unwritten part of it
+        io_in_chunks(buf.len(), |chunk| {
+            let inode = self.inode().unwrap();
+            let buf = &buf[chunk];
+            self.file_inner().write_with(buf.len(), append_end, |pos| inode.cache_write_at(pos, buf).map_err(SysError::from))
+        }).await
     }
 
     async fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, SysError> {
-        let inode = self.dentry().unwrap().inode().unwrap();
-        let size = inode.cache_read_at(offset, buf).unwrap();
-        Ok(size)
+        io_in_chunks(buf.len(), |chunk| {
+            let inode = self.inode().unwrap();
+            self.read_in_size(inode, offset + chunk.start, &mut buf[chunk])
+        }).await
     }
     
     async fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, SysError> {
-        let inode = self.dentry().unwrap().inode().unwrap();
-        let size = inode.cache_write_at(offset, buf).unwrap();
-        Ok(size)
+        io_in_chunks(buf.len(), |chunk| {
+            let inode = self.inode().unwrap();
+            inode.cache_write_at(offset + chunk.start, &buf[chunk]).map_err(SysError::from)
+        }).await
     }
 }
 
@@ -3,7 +3,8 @@
 use crate::devices::BlockDevice;
 use crate::fs::vfs::{
     fstype::{FSType, FSTypeInner},
-    dentry::{Dentry, DentryState, DCACHE},
+    dentry::{Dentry, DentryState},
+    DCACHE,
     fstype::MountFlags,
     SuperBlockInner,
     inode::{Inode, InodeInner},
@@ -15,7 +16,7 @@ use lwext4_rust::InodeTypes;
 use alloc::sync::Arc;
 
 use super::{
-    Ext4Dentry, Ext4Inode, Ext4SuperBlock
+    inode::remove_orphans, Ext4Dentry, Ext4Inode, Ext4SuperBlock
 };
 
 pub struct Ext4FSType {
@@ -67,12 +68,14 @@ impl FSType for Ext4FSType {
         };
         
         let sb = Ext4SuperBlock::new(SuperBlockInner::new(dev, fs_type.clone()), mount_point_path, dev_name);
-        let root_inode = Arc::new(Ext4Inode::new(Arc::downgrade(&sb), &mount_point_path, InodeTypes::EXT4_DE_DIR)); 
+        let root_inode = Ext4Inode::get(Arc::downgrade(&sb), &mount_point_path, InodeTypes::EXT4_DE_DIR);
+        // the files unlinked while open when the system went down
+        remove_orphans(mount_point_path);
         let root_dentry = Ext4Dentry::new(name, parent.clone());
         root_dentry.set_inode(root_inode);
         root_dentry.set_state(DentryState::USED);
         sb.set_root_dentry(root_dentry.clone());
-        DCACHE.lock().insert(mount_point_path.to_string(), root_dentry.clone());
+        DCACHE.pin(root_dentry.clone());
         self.add_sb(&root_dentry.path(), sb);
         Some(root_dentry)
     }
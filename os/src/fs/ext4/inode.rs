@@ -3,27 +3,32 @@
 
 use core::cell::RefCell;
 use core::cmp;
+use core::sync::atomic::{AtomicBool, Ordering};
 use core::ptr::NonNull;
 
 use alloc::string::{String, ToString};
 use alloc::ffi::CString;
+use core::ffi::CStr;
 use hal::addr::RangePPNHal;
 use super::disk::Disk;
 use alloc::sync::{Arc, Weak};
-use alloc::{vec, vec::Vec};
+use alloc::{format, vec, vec::Vec};
 
 use log::*;
 use crate::fs::page::cache::PageCache;
 use crate::fs::page::page::{Page, PAGE_SIZE};
-use crate::fs::vfs::inode::InodeMode;
+use crate::fs::vfs::inode::{InodeMode, XattrFlags, XATTR_LIST_MAX, XATTR_SIZE_MAX};
 use crate::fs::vfs::{InodeInner, Inode};
-use crate::fs::{Kstat, StatxTimestamp, SuperBlock, Xstat, XstatMask};
+use crate::fs::{writeback, Kstat, StatxTimestamp, SuperBlock, Xstat, XstatMask};
 use crate::sync::mutex::SpinNoIrqLock;
-use crate::sync::UPSafeCell;
 use crate::utils::rel_path_to_abs;
 use crate::syscall::SysError;
+use crate::timer::ffi::TimeSpec;
 
 use lwext4_rust::bindings::{
+    ext4_atime_get, ext4_atime_set, ext4_ctime_get, ext4_ctime_set, ext4_getxattr, ext4_listxattr,
+    ext4_mode_get, ext4_mode_set, ext4_mtime_get, ext4_mtime_set, ext4_owner_get, ext4_owner_set,
+    ext4_raw_inode_fill, ext4_removexattr, ext4_setxattr, ext4_inode,
     O_APPEND, O_CREAT, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY, SEEK_CUR, SEEK_END, SEEK_SET,
 };
 use lwext4_rust::{Ext4BlockWrapper, Ext4File, InodeTypes, KernelDevOp};
@@ -39,27 +44,235 @@ pub struct Ext4Inode {
     inner: InodeInner,
     file: SpinNoIrqLock<Ext4File>,
     cache: Arc<PageCache>,
+    /// lost its last name while open, it lives on at an orphan name until the last close
+    orphan: AtomicBool,
 }
 
 unsafe impl Send for Ext4Inode {}
 unsafe impl Sync for Ext4Inode {}
 
+/// the name a file lost its last name while open is kept by, at the root of the file system
+fn orphan_path(root: &str, ino: usize) -> String {
+    format!("{}/{}{}", root.trim_end_matches('/'), ORPHAN_PREFIX, ino)
+}
+
+/// the names of the orphans start with it
+const ORPHAN_PREFIX: &str = ".orphan-";
+
+/// remove the orphans left at `root` by the last boot, their files were closed by the reset
+pub(super) fn remove_orphans(root: &str) {
+    let Ok((names, types)) = Ext4File::new(root, InodeTypes::EXT4_DE_DIR).lwext4_dir_entries() else {
+        return;
+    };
+    for (name, ty) in names.iter().zip(types.iter()) {
+        let name = core::str::from_utf8(name).unwrap_or("").trim_end_matches('\0');
+        if *ty != InodeTypes::EXT4_DE_REG_FILE || !name.starts_with(ORPHAN_PREFIX) {
+            continue;
+        }
+        let path = format!("{}/{}", root.trim_end_matches('/'), name);
+        match Ext4File::new(&path, InodeTypes::EXT4_DE_REG_FILE).file_remove(&path) {
+            Ok(_) => info!("[Ext4] removed the orphan {}", path),
+            Err(e) => warn!("[Ext4] remove the orphan {} failed: {}", path, e),
+        }
+    }
+}
+
+/// read the on-disk inode at `path` and its inode number
+pub(super) fn raw_inode(path: &CStr) -> Option<(u32, ext4_inode)> {
+    let mut ino = 0u32;
+    let mut raw: ext4_inode = unsafe { core::mem::zeroed() };
+    if unsafe { ext4_raw_inode_fill(path.as_ptr(), &mut ino, &mut raw) } != 0 {
+        return None;
+    }
+    Some((ino, raw))
+}
+
+/// read the extended attribute `name`, with its namespace, of the file at `path`
+fn read_xattr(path: &CStr, name: &str) -> Result<Vec<u8>, SysError> {
+    let mut buf = vec![0u8; XATTR_SIZE_MAX];
+    let mut len = 0;
+    let ret = unsafe {
+        ext4_getxattr(path.as_ptr(), name.as_ptr() as _, name.len() as _, buf.as_mut_ptr() as _, buf.len() as _, &mut len)
+    };
+    if ret != 0 {
+        return Err(SysError::from(ret));
+    }
+    buf.truncate(len as usize);
+    Ok(buf)
+}
+
 impl Ext4Inode {
-    /// Create a new inode
-    pub fn new(super_block: Weak<dyn SuperBlock>, path: &str, types: InodeTypes) -> Self {
+    /// Get the inode at `path`. Every path of one on-disk inode gets
+    /// the same instance while it is in use, which keeps a single page cache
+    pub fn get(super_block: Weak<dyn SuperBlock>, path: &str, types: InodeTypes) -> Arc<dyn Inode> {
+        let raw = raw_inode(&CString::new(path).unwrap());
+        let (Some(sb), Some((ino, _))) = (super_block.upgrade(), raw.as_ref()) else {
+            return Arc::new(Self::new(super_block, path, types, raw.as_ref()));
+        };
+        let inode = sb.inner().inodes.get_or_insert_with(*ino as usize, || {
+            Arc::new(Self::new(super_block.clone(), path, types.clone(), raw.as_ref()))
+        });
+        // only ext4 inodes are cached in an ext4 super block
+        let ext4_inode = unsafe { &*(Arc::as_ptr(&inode) as *const Ext4Inode) };
+        ext4_inode.set_path(path, types);
+        inode
+    }
+
+    /// Create a new inode, numbered by the on-disk inode `raw` if there is one
+    fn new(super_block: Weak<dyn SuperBlock>, path: &str, types: InodeTypes, raw: Option<&(u32, ext4_inode)>) -> Self {
         //info!("Inode new {:?} {}", types, path);
         let mode = InodeMode::from_inode_type(types.clone());
-        let mut file  = Ext4File::new(path, types);
-        // (todo) notice that lwext4 mention in file_size(): should open file as RDONLY first 
-        // may be a bug in the future
-        let size = file.file_size();
-        Self {
-            inner: InodeInner::new(Some(super_block.clone()), mode, size as usize),
+        let mut file  = Ext4File::new(path, types.clone());
+        // lwext4 only knows the size of an opened file, read it once here and
+        // keep it in the inner so stat and cached reads never reopen the file
+        let size = if types == InodeTypes::EXT4_DE_REG_FILE && file.file_open(path, O_RDONLY).is_ok() {
+            let size = file.file_size();
+            let _ = file.file_close();
+            size
+        } else {
+            0
+        };
+        let inner = match raw {
+            Some((ino, _)) => InodeInner::with_ino(Some(super_block.clone()), *ino as usize, mode, size as usize),
+            None => InodeInner::new(Some(super_block.clone()), mode, size as usize),
+        };
+        let inode = Self {
+            inner,
             file: SpinNoIrqLock::new(file),
             cache: Arc::new(PageCache::new()),
+            orphan: AtomicBool::new(false),
+        };
+        inode.load_times();
+        if let Some((_, raw)) = raw {
+            inode.load_btime(raw);
+        }
+        inode.load_owner();
+        inode
+    }
+
+    /// follow the inode to `path`, another link to it or its name after a rename,
+    /// as lwext4 reaches the inode by path
+    fn set_path(&self, path: &str, types: InodeTypes) {
+        let mut file = self.file.lock();
+        if file.get_path().as_bytes() != path.as_bytes() {
+            *file = Ext4File::new(path, types);
         }
     }
 
+    /// the device number of the super block
+    fn dev(&self) -> usize {
+        self.inner.super_block.as_ref()
+            .and_then(|sb| sb.upgrade())
+            .map_or(0, |sb| sb.inner().dev)
+    }
+
+    /// forget the inode at `path` before it is removed, its number may be reused.
+    /// Removing its last name frees the inode, which makes the file handles to it stale
+    fn evict(&self, path: &str) {
+        let Some(sb) = self.inner.super_block.as_ref().and_then(|sb| sb.upgrade()) else {
+            return;
+        };
+        if let Some((ino, raw)) = raw_inode(&CString::new(path).unwrap()) {
+            let is_dir = InodeMode::from_bits_truncate(raw.mode as u32).get_type() == InodeMode::DIR;
+            if is_dir || raw.links_count <= 1 {
+                sb.inner().inodes.retire(ino as usize);
+            } else {
+                sb.inner().inodes.remove(ino as usize);
+            }
+        }
+    }
+
+    /// the path lwext4 knows the root of the file system by
+    fn root_path(&self) -> Option<String> {
+        let sb = self.inner.super_block.as_ref()?.upgrade()?;
+        let root = sb.inner().root.get()?.inode()?;
+        // only ext4 inodes are cached in an ext4 super block
+        let root = unsafe { &*(Arc::as_ptr(&root) as *const Ext4Inode) };
+        let path = root.file.lock().get_path();
+        path.to_str().ok().map(String::from)
+    }
+
+    /// keep the regular file at `path` past its last name while it is open: it moves to an
+    /// orphan name at `root`, the root of the file system, and is removed on the last close.
+    /// false if it is to be removed now
+    fn defer_remove(&self, file: &mut Ext4File, path: &str, root: &str) -> Result<bool, i32> {
+        let Some(sb) = self.inner.super_block.as_ref().and_then(|sb| sb.upgrade()) else {
+            return Ok(false);
+        };
+        let Some((ino, raw)) = raw_inode(&CString::new(path).unwrap()) else {
+            return Ok(false);
+        };
+        let Some(inode) = sb.inner().inodes.get(ino as usize) else {
+            return Ok(false);
+        };
+        if raw.links_count > 1 || inode.inode_inner().opens() == 0 {
+            return Ok(false);
+        }
+        let orphan = orphan_path(root, ino as usize);
+        file.file_rename(path, &orphan)?;
+        let inode = unsafe { &*(Arc::as_ptr(&inode) as *const Ext4Inode) };
+        inode.set_path(&orphan, InodeTypes::EXT4_DE_REG_FILE);
+        inode.orphan.store(true, Ordering::Release);
+        info!("[Ext4Inode] {} is open, kept as {}", path, orphan);
+        Ok(true)
+    }
+
+    /// the size seen by readers: the on-disk size or the end of the cached data, whichever is larger.
+    /// Directories report 0
+    fn size(&self) -> usize {
+        if self.inner.mode().get_type() != InodeMode::FILE {
+            return 0;
+        }
+        cmp::max(self.inner.size.load(Ordering::Acquire), self.cache.end())
+    }
+
+    /// read the on-disk owner and permission bits into the inner
+    fn load_owner(&self) {
+        let cpath = self.file.lock().get_path();
+        let (mut uid, mut gid, mut mode) = (0u32, 0u32, 0u32);
+        unsafe {
+            if ext4_owner_get(cpath.as_ptr(), &mut uid, &mut gid) != 0
+                || ext4_mode_get(cpath.as_ptr(), &mut mode) != 0 {
+                return;
+            }
+        }
+        self.inner.set_uid(uid);
+        self.inner.set_gid(gid);
+        let mut cur = self.inner.mode.lock();
+        *cur = cur.get_type() | (InodeMode::from_bits_truncate(mode) - InodeMode::TYPE_MASK);
+    }
+
+    /// read the on-disk timestamps into the inner, which only keeps seconds
+    fn load_times(&self) {
+        let cpath = self.file.lock().get_path();
+        let (mut atime, mut mtime, mut ctime) = (0u32, 0u32, 0u32);
+        unsafe {
+            if ext4_atime_get(cpath.as_ptr(), &mut atime) != 0
+                || ext4_mtime_get(cpath.as_ptr(), &mut mtime) != 0
+                || ext4_ctime_get(cpath.as_ptr(), &mut ctime) != 0 {
+                return;
+            }
+        }
+        let to_spec = |sec: u32| TimeSpec { tv_sec: sec as usize, tv_nsec: 0 };
+        self.inner.set_atime(to_spec(atime));
+        self.inner.set_mtime(to_spec(mtime));
+        self.inner.set_ctime(to_spec(ctime));
+    }
+
+    /// read the creation time, which lives in the extra inode space
+    /// and is missing from inodes too small to hold it
+    fn load_btime(&self, raw: &ext4_inode) {
+        /// i_crtime_extra ends 24 bytes into the extra space after the 128 byte base inode
+        const CRTIME_EXTRA_END: u16 = 24;
+        if raw.extra_isize < CRTIME_EXTRA_END {
+            return;
+        }
+        // the low two bits of the extra field extend the seconds, the rest are nanoseconds
+        let extra = raw.crtime_extra;
+        let sec = raw.crtime as usize | ((extra as usize & 0b11) << 32);
+        self.inner.set_btime(Some(TimeSpec { tv_sec: sec, tv_nsec: (extra >> 2) as usize }));
+    }
+
     #[allow(unused)]
     fn path_deal_with(&self, path: &str) -> String {
         if path.starts_with('/') {
@@ -100,7 +313,7 @@ impl Inode for Ext4Inode {
     }
 
     fn read_page_at(self: Arc<Self>, offset: usize) -> Option<Arc<Page>> {
-        let size = self.getattr().st_size as usize;
+        let size = self.size();
         if offset >= size {
             info!("[Ext4 INode]: read_page_at: reach EOF, offset: {} size: {}", offset, size);
             return None;
@@ -109,6 +322,7 @@ impl Inode for Ext4Inode {
         let page = if let Some(page) = page_cache.get_page(offset) {
             page.clone()
         } else {
+            page_cache.shrink();
             let mut page = Page::new(offset);
             let read_size = Arc::get_mut(&mut page).unwrap().read_from(self.clone(), offset);
             page_cache.insert_page(offset, page.clone());
@@ -124,24 +338,17 @@ impl Inode for Ext4Inode {
         let mut file = self.file.lock();
         let full_path = String::from(file.get_path().to_str().unwrap().trim_end_matches('/')) + "/" + name;
         log::debug!("try to look up {}", full_path);
-        if file.check_inode_exist(full_path.as_str(), InodeTypes::EXT4_DE_REG_FILE) {
-            log::debug!("lookup {} success", name);
-            return Some(Arc::new(Ext4Inode::new(
-                self.inode_inner().super_block.clone().unwrap(), 
-                full_path.as_str(), 
-                InodeTypes::EXT4_DE_REG_FILE)));
-        } else if file.check_inode_exist(full_path.as_str(), InodeTypes::EXT4_DE_DIR) {
-            log::debug!("lookup dir {} success", name);
-            return Some(Arc::new(Ext4Inode::new(
-                self.inode_inner().super_block.clone().unwrap(), 
-                full_path.as_str(), 
-                InodeTypes::EXT4_DE_DIR)));
-        } else if file.check_inode_exist(full_path.as_str(), InodeTypes::EXT4_DE_SYMLINK) {
-            log::debug!("look up symlink {} success", name);
-            return Some(Arc::new(Ext4Inode::new(
+        let types = [InodeTypes::EXT4_DE_REG_FILE, InodeTypes::EXT4_DE_DIR, InodeTypes::EXT4_DE_SYMLINK]
+            .into_iter()
+            .find(|types| file.check_inode_exist(full_path.as_str(), types.clone()));
+        // the child may be cached, whose lock is taken to follow it to this path
+        drop(file);
+        if let Some(types) = types {
+            log::debug!("lookup {} success, {:?}", name, types);
+            return Some(Ext4Inode::get(
                 self.inode_inner().super_block.clone().unwrap(),
                 full_path.as_str(),
-                InodeTypes::EXT4_DE_SYMLINK)));
+                types));
         }
         //info!("lookup {} failed", name);
         None
@@ -202,6 +409,9 @@ impl Inode for Ext4Inode {
         let r = file.file_write(buf);
 
         let _ = file.file_close();
+        if let Ok(written) = r {
+            self.inner.size.fetch_max(offset + written, Ordering::AcqRel);
+        }
         r
     }
 
@@ -211,14 +421,8 @@ impl Inode for Ext4Inode {
         let mut current_offset = offset;
         let mut buf_offset = 0usize;
 
-        // get the file size on disk (may not sync)
-        let file_size = {
-            let mut file = self.file.lock();
-            let cpath = file.get_path();
-            let path = cpath.to_str().unwrap();
-            file.file_open(path, O_RDWR)?;
-            file.file_size() as usize
-        };
+        // the file size on disk (may not sync), cache hits never take the file lock
+        let file_size = self.inner.size.load(Ordering::Acquire);
 
         while buf_offset < buf.len() {
             let cache = self.cache.clone();
@@ -238,6 +442,7 @@ impl Inode for Ext4Inode {
             } else {
                 // info!("[PAGE CACHE]: read miss at offset: {:#x}", page_offset);
                 // direct read at the offset of page size
+                cache.shrink();
                 let mut page = Page::new(page_offset);
                 let read_size = Arc::get_mut(&mut page).unwrap()
                     .read_from(self.clone(), page_offset);
@@ -261,20 +466,15 @@ impl Inode for Ext4Inode {
     }
 
     fn cache_write_at(self: Arc<Self>, offset: usize, buf: &[u8]) -> Result<usize, i32> {
-        // get file size
-        let file_size = {
-            let mut file = self.file.lock();
-            let cpath = file.get_path();
-            let path = cpath.to_str().unwrap();
-            file.file_open(path, O_RDWR)?;
-            file.file_size() as usize
-        };
+        // get file size on disk
+        let file_size = self.inner.size.load(Ordering::Acquire);
         // get the page-aligned offset
         let mut total_write_size = 0usize;
         let mut current_offset = offset;
         let mut buf_offset = 0usize;
 
         let cache = self.cache.clone();
+        let mut dirtied = 0;
 
         while buf_offset < buf.len() {
             let page_offset = current_offset / PAGE_SIZE * PAGE_SIZE;
@@ -297,13 +497,17 @@ impl Inode for Ext4Inode {
 
             // now use the buf to fill in the page
             let page_write_size = page.write_at(in_page_offset, &buf[buf_offset..]);
-            page.set_dirty();
+            if page.set_dirty() {
+                dirtied += 1;
+            }
             cache.update_end(page_offset + page_write_size + in_page_offset);
 
             total_write_size += page_write_size;
             buf_offset += page_write_size;
             current_offset += page_write_size;
         }
+        let inode: Arc<dyn Inode> = self;
+        writeback::mark_dirty(&inode, dirtied);
 
         // log::info!("[cache_write_at] buf len {}, offset {:#x}, write size {:#x}", buf.len(), offset, total_write_size);
         Ok(total_write_size)
@@ -315,9 +519,13 @@ impl Inode for Ext4Inode {
         let mut file = self.file.lock();
         let path = file.get_path();
         let path = path.to_str().unwrap();
-        file.file_open(path, O_RDWR).expect("file open failed");
-        let t = file.file_truncate(size as _).map_err(|e| SysError::from_i32(e))?;
+        file.file_open(path, O_RDWR).map_err(SysError::from)?;
+        let t = file.file_truncate(size as _).map_err(SysError::from)?;
         let _ = file.file_close();
+        self.inner.size.store(size, Ordering::Release);
+        drop(file);
+        // the cache may hold data past the new end, which would otherwise be read back or flushed
+        self.cache.truncate(size);
         Ok(t)
     }
 
@@ -345,8 +553,7 @@ impl Inode for Ext4Inode {
                 file.dir_mk(fpath)
             } else {
                 file.file_open(fpath, O_WRONLY | O_CREAT | O_TRUNC)
-                    .expect("create file failed");
-                file.file_close()
+                    .and_then(|_| file.file_close())
             }
         };
 
@@ -357,37 +564,24 @@ impl Inode for Ext4Inode {
             }
             Ok(_) => {
                 info!("create inode success");
-                Some(Arc::new(Ext4Inode::new(
+                Some(Ext4Inode::get(
                     self.inode_inner().super_block.clone().unwrap(),
-                    fpath, types)))
+                    fpath, types))
             }
         }
     }
 
     fn getattr(&self) -> Kstat {
         let inner = self.inode_inner();
-        let mut file = self.file.lock();
-        let ty = file.get_type();
-
-        let size = if ty == InodeTypes::EXT4_DE_REG_FILE {
-            let path = file.get_path();
-            file.file_open(&path.to_str().unwrap(), O_RDONLY).expect("failed to open");
-            let fsize = file.file_size() as usize;
-            file.file_close().expect("failed to close");
-            let page_cache_end = self.cache().end();
-            cmp::max(page_cache_end, fsize)
-        } else {
-            // DIR size should be 0
-            0
-        };
+        let size = self.size();
         log::debug!("file size: {}", size);
         Kstat {
-            st_dev: 0,
+            st_dev: self.dev() as u64,
             st_ino: inner.ino as u64,
-            st_mode: inner.mode.bits() as _,
+            st_mode: inner.mode().bits() as _,
             st_nlink: inner.nlink() as u32,
-            st_uid: 0,
-            st_gid: 0,
+            st_uid: inner.uid(),
+            st_gid: inner.gid(),
             st_rdev: 0,
             _pad0: 0,
             st_size: size as _,
@@ -403,40 +597,36 @@ impl Inode for Ext4Inode {
         }
     }
 
-    fn getxattr(&self, mask: crate::fs::XstatMask) -> crate::fs::Xstat {
+    fn getxattr(&self, _mask: crate::fs::XstatMask) -> crate::fs::Xstat {
         const SUPPORTED_MASK: XstatMask = XstatMask::from_bits_truncate({
             XstatMask::STATX_BLOCKS.bits |
             XstatMask::STATX_ATIME.bits |
             XstatMask::STATX_CTIME.bits |
             XstatMask::STATX_MTIME.bits |
             XstatMask::STATX_NLINK.bits |
+            XstatMask::STATX_TYPE.bits |
             XstatMask::STATX_MODE.bits |
+            XstatMask::STATX_UID.bits |
+            XstatMask::STATX_GID.bits |
             XstatMask::STATX_SIZE.bits |
             XstatMask::STATX_INO.bits
         });
-        let mask = mask & SUPPORTED_MASK;
         let inner = self.inode_inner();
-        let mut file = self.file.lock();
-        let ty = file.get_type();
-        let size = if ty == InodeTypes::EXT4_DE_REG_FILE {
-            let path = file.get_path();
-            file.file_open(&path.to_str().unwrap(), O_RDONLY).expect("failed to open");
-            let fsize = file.file_size() as usize;
-            file.file_close().expect("failed to close");
-            let page_cache_end = self.cache().end();
-            cmp::max(page_cache_end, fsize)
-        } else {
-            // DIR size should be 0
-            0
+        let btime = inner.btime();
+        let mask = match btime {
+            Some(_) => SUPPORTED_MASK | XstatMask::STATX_BTIME,
+            None => SUPPORTED_MASK,
         };
+        let size = self.size();
+        let dev = self.dev();
         Xstat {
             stx_mask: mask.bits,
             stx_blksize: BLOCK_SIZE as _,
             stx_attributes: 0,
             stx_nlink: inner.nlink() as u32,
-            stx_uid: 0,
-            stx_gid: 0,
-            stx_mode: inner.mode.bits() as _,
+            stx_uid: inner.uid(),
+            stx_gid: inner.gid(),
+            stx_mode: inner.mode().bits() as _,
             stx_ino: inner.ino as u64,
             stx_size: size as _,
             stx_blocks: (size / BLOCK_SIZE) as _,
@@ -446,8 +636,8 @@ impl Inode for Ext4Inode {
                 tv_nsec: inner.atime().tv_nsec as _,
             },
             stx_btime: StatxTimestamp {
-                tv_sec: 0,
-                tv_nsec: 0,
+                tv_sec: btime.map_or(0, |t| t.tv_sec) as _,
+                tv_nsec: btime.map_or(0, |t| t.tv_nsec) as _,
             },
             stx_ctime: StatxTimestamp {
                 tv_sec: inner.ctime().tv_sec as _,
@@ -459,8 +649,8 @@ impl Inode for Ext4Inode {
             },
             stx_rdev_major: 0,
             stx_rdev_minor: 0,
-            stx_dev_major: 0,
-            stx_dev_minor: 0,
+            stx_dev_major: (dev >> 8) as _,
+            stx_dev_minor: (dev & 0xff) as _,
             stx_mnt_id: 0,
             stx_dio_mem_align: 0,
             std_dio_offset_align: 0,
@@ -475,32 +665,28 @@ impl Inode for Ext4Inode {
     fn symlink(&self, target_path: &str) -> Result<Arc<dyn Inode>, SysError> {
         let file = self.file.lock();
         // create symlink
-        file.symlink_create(target_path).expect("symlink create failed");
+        file.symlink_create(target_path).map_err(SysError::from)?;
         // get the symlink Inode
-        Ok(Arc::new(Ext4Inode::new(
+        Ok(Ext4Inode::get(
             self.inode_inner().super_block.clone().unwrap(),
             target_path,
             InodeTypes::EXT4_DE_SYMLINK
-        )))
+        ))
     }
 
     fn link(&self, target_path: &str) -> Result<usize, SysError> {
         let file = self.file.lock();
         // create hard link
-        file.link_create(target_path).expect("link create failed");
+        file.link_create(target_path).map_err(SysError::from)?;
         Ok(0)
     }
 
     fn readlink(&self) -> Result<String, SysError> {
         let file = self.file.lock();
         let mut path_buf: Vec<u8> = vec![0u8; 512];
-        let len = file.symlink_read(&mut path_buf).expect("symlink read failed");
-        path_buf.truncate(len + 1);
-        let path = CString::from_vec_with_nul(path_buf)
-            .unwrap()
-            .into_string()
-            .unwrap();
-        Ok(path)
+        let len = file.symlink_read(&mut path_buf).map_err(SysError::from)?;
+        path_buf.truncate(len);
+        String::from_utf8(path_buf).map_err(|_| SysError::EINVAL)
     }
 
     /// remove the file that Ext4Inode holds
@@ -509,8 +695,9 @@ impl Inode for Ext4Inode {
         let itype = file.get_type();
         let cpath = file.get_path();
         let path = cpath.to_str().unwrap();
+        self.evict(path);
         match itype {
-            InodeTypes::EXT4_DE_REG_FILE => {
+            InodeTypes::EXT4_DE_REG_FILE | InodeTypes::EXT4_DE_SYMLINK => {
                 file.file_remove(path)
             }
             InodeTypes::EXT4_DE_DIR => {
@@ -524,6 +711,7 @@ impl Inode for Ext4Inode {
 
     fn remove(&self, name: &str, mode: InodeMode) -> Result<usize, i32> {
         let ty = InodeTypes::from(mode);
+        let root = self.root_path();
         let mut file = self.file.lock();
         let parent_path = String::from(file.get_path().to_str().unwrap());
         let fpath = rel_path_to_abs(&parent_path, name).unwrap();
@@ -533,9 +721,15 @@ impl Inode for Ext4Inode {
         let fpath = fpath.as_str();
 
         assert!(!fpath.is_empty()); // already check at `root.rs`
+        if let Some(root) = root.as_deref().filter(|_| ty == InodeTypes::EXT4_DE_REG_FILE) {
+            if self.defer_remove(&mut file, fpath, root)? {
+                return Ok(0);
+            }
+        }
+        self.evict(fpath);
 
         match ty {
-            InodeTypes::EXT4_DE_REG_FILE => {
+            InodeTypes::EXT4_DE_REG_FILE | InodeTypes::EXT4_DE_SYMLINK => {
                 file.file_remove(fpath)
             }
             InodeTypes::EXT4_DE_DIR => {
@@ -548,14 +742,15 @@ impl Inode for Ext4Inode {
     }
 
     fn rename(&self, target: &str, new_inode: Option<Arc<dyn Inode>>) -> Result<(), SysError> {
+        let root = self.root_path();
         let mut file = self.file.lock();
         let path = file.get_path();
-        let old_path = path.to_str().expect("failed");
+        let old_path = path.to_str().map_err(|_| SysError::EINVAL)?;
         let ty = file.get_type();
-        let old_mode = InodeMode::from_inode_type(ty).get_type();
+        let old_mode = InodeMode::from_inode_type(ty.clone()).get_type();
         log::debug!("old mode: {:x}", old_mode.bits());
         if let Some(new) = new_inode {
-            let new_mode = new.inode_inner().mode;
+            let new_mode = new.inode_inner().mode().get_type();
             if new_mode != old_mode {
                 return match (old_mode, new_mode) {
                     (InodeMode::FILE, InodeMode::DIR) => Err(SysError::EISDIR),
@@ -563,17 +758,96 @@ impl Inode for Ext4Inode {
                     _ => unimplemented!(),
                 };
             }
-            let _ = match new_mode {
-                InodeMode::FILE => file.file_remove(target),
-                InodeMode::DIR => file.dir_rm(target),
-                _ => unimplemented!(),
+            let deferred = match (new_mode, root.as_deref()) {
+                (InodeMode::FILE, Some(root)) => self.defer_remove(&mut file, target, root).map_err(SysError::from)?,
+                _ => false,
             };
+            if !deferred {
+                self.evict(target);
+                let removed = match new_mode {
+                    InodeMode::FILE => file.file_remove(target),
+                    InodeMode::DIR => file.dir_rm(target),
+                    _ => unimplemented!(),
+                };
+                removed.map_err(SysError::from)?;
+            }
         }
-        let _ = match old_mode {
+        let renamed = match old_mode {
             InodeMode::FILE => file.file_rename(old_path, target),
             InodeMode::DIR => file.dir_mv(old_path, target),
             _ => unimplemented!(),
         };
+        renamed.map_err(SysError::from)?;
+        *file = Ext4File::new(target, ty);
+        Ok(())
+    }
+
+    fn sync_meta(&self) -> Result<(), SysError> {
+        let cpath = self.file.lock().get_path();
+        let inner = self.inode_inner();
+        let ret = unsafe {
+            ext4_mode_set(cpath.as_ptr(), inner.mode().bits())
+                | ext4_owner_set(cpath.as_ptr(), inner.uid(), inner.gid())
+                | ext4_atime_set(cpath.as_ptr(), inner.atime().tv_sec as u32)
+                | ext4_mtime_set(cpath.as_ptr(), inner.mtime().tv_sec as u32)
+                | ext4_ctime_set(cpath.as_ptr(), inner.ctime().tv_sec as u32)
+        };
+        if ret != 0 {
+            warn!("[Ext4Inode] sync meta of {:?} failed: {}", cpath, ret);
+            return Err(SysError::EIO);
+        }
+        Ok(())
+    }
+
+    fn get_xattr(&self, name: &str) -> Result<Vec<u8>, SysError> {
+        let cpath = self.file.lock().get_path();
+        read_xattr(&cpath, name)
+    }
+
+    fn set_xattr(&self, name: &str, value: &[u8], flags: XattrFlags) -> Result<(), SysError> {
+        // the lock keeps the check and the set together
+        let file = self.file.lock();
+        let cpath = file.get_path();
+        let exists = match read_xattr(&cpath, name) {
+            Ok(_) => true,
+            Err(SysError::ENODATA) => false,
+            Err(e) => return Err(e),
+        };
+        flags.check(exists)?;
+        let ret = unsafe {
+            ext4_setxattr(cpath.as_ptr(), name.as_ptr() as _, name.len() as _, value.as_ptr() as _, value.len() as _)
+        };
+        drop(file);
+        if ret != 0 {
+            return Err(SysError::from(ret));
+        }
+        self.inode_inner().touch_ctime();
+        Ok(())
+    }
+
+    fn list_xattr(&self) -> Result<Vec<String>, SysError> {
+        let cpath = self.file.lock().get_path();
+        let mut buf = vec![0u8; XATTR_LIST_MAX];
+        let mut len = 0;
+        let ret = unsafe { ext4_listxattr(cpath.as_ptr(), buf.as_mut_ptr() as _, buf.len() as _, &mut len) };
+        if ret != 0 {
+            return Err(SysError::from(ret));
+        }
+        // the names come with their namespace, each ends with a nul
+        Ok(buf[..len as usize]
+            .split(|&c| c == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect())
+    }
+
+    fn remove_xattr(&self, name: &str) -> Result<(), SysError> {
+        let cpath = self.file.lock().get_path();
+        let ret = unsafe { ext4_removexattr(cpath.as_ptr(), name.as_ptr() as _, name.len() as _) };
+        if ret != 0 {
+            return Err(SysError::from(ret));
+        }
+        self.inode_inner().touch_ctime();
         Ok(())
     }
 
@@ -584,14 +858,25 @@ impl Inode for Ext4Inode {
             page.set_clean();
         }
     }
-}
 
-impl Drop for Ext4Inode {
-    fn drop(&mut self) {
-        // let mut file = self.file.lock();
-        info!("Drop struct Inode");
+    /// an orphan is removed with its last close, and its number retired
+    fn release(&self) {
+        if !self.orphan.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        self.clean_cached();
+        let mut file = self.file.lock();
+        let cpath = file.get_path();
+        let path = cpath.to_str().unwrap();
+        if let Err(e) = file.file_remove(path) {
+            warn!("[Ext4Inode] remove the orphan {} failed: {}", path, e);
+        }
+        if let Some(sb) = self.inner.super_block.as_ref().and_then(|sb| sb.upgrade()) {
+            sb.inner().inodes.retire(self.inner.ino);
+        }
+    }
 
-        // flush the dirty page in page cache
+    fn sync_cached(&self) {
         let cache = self.cache.clone();
         let mut pages = cache.get_pages().lock();
         for (&offset, page) in pages.iter_mut() {
@@ -600,7 +885,27 @@ impl Drop for Ext4Inode {
             }
             // info!("flush dirty page at offset {:#x}", offset);
             let buf_flush_size = cmp::min(cache.end() - offset, PAGE_SIZE);
-            self.write_at(offset, &page.get_slice::<u8>()[..buf_flush_size]).expect("[PageCache]: failed at flush");
+            // run on drop too, where the error can only be reported
+            if let Err(e) = self.write_at(offset, &page.get_slice::<u8>()[..buf_flush_size]) {
+                error!("[Ext4Inode] flush at {:#x} failed: {:?}", offset, SysError::from(e));
+                continue;
+            }
+            page.set_clean();
+        }
+    }
+}
+
+impl Drop for Ext4Inode {
+    fn drop(&mut self) {
+        // let mut file = self.file.lock();
+        info!("Drop struct Inode");
+
+        // flush the dirty page in page cache
+        self.sync_cached();
+
+        if let Some(sb) = self.inner.super_block.as_ref().and_then(|sb| sb.upgrade()) {
+            sb.inner().inodes.remove_dead(self.inner.ino);
+            sb.inner().dirty.remove_dead(self.inner.ino);
         }
 
         // file.file_close().expect("failed to close fd");
@@ -26,7 +26,7 @@ use super::vfs::DCACHE;
 #[allow(unused)]
 pub fn page_cache_test() {
     // create a new inode at root
-    let root_dentry = DCACHE.lock().get("/").unwrap().clone();
+    let root_dentry = DCACHE.root();
     let root = root_dentry.inode().unwrap();
     let inode = root.create("/page_cache_test.txt", InodeMode::FILE).unwrap();
 
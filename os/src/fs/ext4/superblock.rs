@@ -1,10 +1,14 @@
 //! ext4 file system implement for the VFS super block
 use crate::fs::vfs::{Dentry, DentryInner, DentryState, Inode, SuperBlock, SuperBlockInner, DCACHE};
+use crate::syscall::SysError;
+use alloc::ffi::CString;
 use alloc::string::ToString;
+use lwext4_rust::bindings::{ext4_cache_flush, ext4_journal_stop, ext4_umount};
 use lwext4_rust::{Ext4BlockWrapper, Ext4File, InodeTypes, KernelDevOp};
 use super::{disk::Disk, Ext4Dentry};
-use super::inode::Ext4Inode;
+use super::inode::{raw_inode, Ext4Inode};
 use alloc::sync::{Arc, Weak};
+use alloc::{format, string::String, vec};
 
 #[allow(dead_code)]
 /// EXT4 FS super block
@@ -13,6 +17,8 @@ pub struct Ext4SuperBlock {
     inner: SuperBlockInner,
     /// lwext4 object to control file system
     block: Ext4BlockWrapper<Disk>,
+    /// the mount point lwext4 knows the file system by
+    mount_point: &'static str,
 }
 
 unsafe impl Send for Ext4SuperBlock {}
@@ -26,7 +32,7 @@ impl Ext4SuperBlock {
         let block_device = inner.device.as_ref().unwrap().clone();
         let disk = Disk::new(block_device);
         let block = Ext4BlockWrapper::<Disk>::new(disk, mount_point, device_name).expect("failed to create ext4fs");
-        Arc::new(Self {inner, block})
+        Arc::new(Self {inner, block, mount_point})
     }
 }
 
@@ -37,4 +43,64 @@ impl SuperBlock for Ext4SuperBlock {
     fn get_root_inode(&'static self, _name: &str) -> Arc<dyn Inode> {
         self.inner().root.get().unwrap().clone().inode().unwrap()
     }
+    fn unmount(&self) -> Result<(), SysError> {
+        let mp = CString::new(self.mount_point).unwrap();
+        unsafe {
+            // the block cache first, then the journal, umount marks the superblock clean
+            let ret = ext4_cache_flush(mp.as_ptr());
+            if ret != 0 {
+                return Err(SysError::from(ret));
+            }
+            let ret = ext4_journal_stop(mp.as_ptr());
+            if ret != 0 {
+                log::warn!("[Ext4SuperBlock] stop the journal of {} failed: {}", self.mount_point, ret);
+            }
+            let ret = ext4_umount(mp.as_ptr());
+            if ret != 0 {
+                return Err(SysError::from(ret));
+            }
+        }
+        log::info!("[Ext4SuperBlock] unmounted {}", self.mount_point);
+        Ok(())
+    }
+    fn sync(&self) -> Result<(), SysError> {
+        let mp = CString::new(self.mount_point).unwrap();
+        let ret = unsafe { ext4_cache_flush(mp.as_ptr()) };
+        if ret != 0 {
+            return Err(SysError::from(ret));
+        }
+        Ok(())
+    }
+    fn file_handles(&self) -> bool {
+        true
+    }
+    /// lwext4 only reaches inodes by path, so search the tree for a name of `ino`
+    fn lookup_ino(&self, ino: usize) -> Result<Arc<dyn Dentry>, SysError> {
+        let root = self.inner.root.get().ok_or(SysError::ESTALE)?.clone();
+        let base = self.mount_point.trim_end_matches('/');
+        let mut dirs = vec![String::from(self.mount_point)];
+        while let Some(dir) = dirs.pop() {
+            let cpath = CString::new(dir.as_str()).unwrap();
+            if raw_inode(&cpath).is_some_and(|(i, _)| i as usize == ino) {
+                let rel = dir[base.len()..].trim_start_matches('/');
+                return if rel.is_empty() { Ok(root) } else { root.walk(rel) };
+            }
+            let Ok((names, types)) = Ext4File::new(&dir, InodeTypes::EXT4_DE_DIR).lwext4_dir_entries() else {
+                continue;
+            };
+            for (name, ty) in names.iter().zip(types.iter()) {
+                let name = core::str::from_utf8(name).unwrap_or("").trim_end_matches('\0');
+                if name.is_empty() || name == "." || name == ".." {
+                    continue;
+                }
+                let path = format!("{}/{}", dir.trim_end_matches('/'), name);
+                if *ty == InodeTypes::EXT4_DE_DIR {
+                    dirs.push(path);
+                } else if raw_inode(&CString::new(path.as_str()).unwrap()).is_some_and(|(i, _)| i as usize == ino) {
+                    return root.walk(path[base.len()..].trim_start_matches('/'));
+                }
+            }
+        }
+        Err(SysError::ESTALE)
+    }
 }
@@ -1,4 +1,4 @@
-use crate::fs::{fat32::file::FatFile, vfs::{Dentry, DentryInner, DentryState, File, DCACHE}, OpenFlags, SuperBlock};
+use crate::{fs::{fat32::file::FatFile, vfs::{Dentry, DentryInner, DentryState, File, DCACHE}, OpenFlags}, syscall::SysError};
 use alloc::{sync::Arc, vec::Vec};
 
 
@@ -41,6 +41,13 @@ impl Dentry for FatDentry {
         let (readable, writable) = flags.read_write();
         Some(Arc::new(FatFile::new(readable, writable, self.clone())))
     }
+    fn load_child_dentry(self: Arc<Self>) -> Result<Vec<Arc<dyn Dentry>>, SysError> {
+        // the names ls gives are the ones lookup finds,
+        // long names where an entry has one and short names otherwise
+        let names = self.inode().unwrap().ls();
+        let this: Arc<dyn Dentry> = self;
+        this.sync_children(names)
+    }
     fn new_neg_dentry(self: Arc<Self>, name: &str) -> Arc<dyn Dentry> {
         let neg_dentry = Arc::new(Self {
             inner: DentryInner::new(name, Some(self.clone()))
@@ -48,4 +55,8 @@ impl Dentry for FatDentry {
         neg_dentry.set_state(DentryState::NEGATIVE);
         neg_dentry
     }
-}
\ No newline at end of file
+    fn can_evict(&self) -> bool {
+        // everything can be looked up again on the disk
+        true
+    }
+}
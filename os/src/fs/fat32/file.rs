@@ -1,9 +1,10 @@
+use core::cmp;
 use core::sync::atomic::AtomicUsize;
 
-use alloc::{sync::Arc, vec::Vec, boxed::Box};
+use alloc::{sync::Arc, boxed::Box};
 use async_trait::async_trait;
 
-use crate::{fs::{page::page::PAGE_SIZE, vfs::{file::SeekFrom, Dentry, File, FileInner}, OpenFlags}, sync::{mutex::SpinNoIrqLock, UPSafeCell}};
+use crate::{fs::{vfs::{file::io_in_chunks, Dentry, File, FileCount, FileInner, FileOwner, Inode, OpenRef}, OpenFlags}, sync::mutex::SpinNoIrqLock};
 
 use super::SysError;
 
@@ -11,31 +12,44 @@ use super::SysError;
 pub struct FatFile {
     readable: bool,
     writable: bool,
-    inner: UPSafeCell<FileInner>,
+    inner: FileInner,
 }
 
 unsafe impl Send for FatFile {}
 unsafe impl Sync for FatFile {}
 
 impl FatFile {
-    /// Construct an Ext4File from a dentry
+    /// Construct an FatFile from a dentry
     pub fn new(readable: bool, writable: bool, dentry: Arc<dyn Dentry>) -> Self {
         Self {
             readable,
             writable,
-            inner: UPSafeCell::new(FileInner { 
-                offset: AtomicUsize::new(0), 
+            inner: FileInner {
+                offset: AtomicUsize::new(0),
+                open: OpenRef::new(&dentry),
                 dentry,
-                flags: SpinNoIrqLock::new(OpenFlags::empty())
-            }) ,
+                flags: SpinNoIrqLock::new(OpenFlags::empty()),
+                count: FileCount::new(),
+                owner: FileOwner::new(),
+            },
         }
     }
+
+    /// read at `offset` no further than the current end of file
+    fn read_in_size(&self, inode: Arc<dyn Inode>, offset: usize, buf: &mut [u8]) -> Result<usize, SysError> {
+        let size = self.size();
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = cmp::min(buf.len(), size - offset);
+        inode.cache_read_at(offset, &mut buf[..len]).map_err(SysError::from)
+    }
 }
 
 #[async_trait]
 impl File for FatFile {
     fn file_inner(&self) -> &FileInner {
-        self.inner.exclusive_access()
+        &self.inner
     }
     fn readable(&self) -> bool {
         self.readable
@@ -43,16 +57,38 @@ impl File for FatFile {
     fn writable(&self) -> bool {
         self.writable
     }
+    fn size(&self) -> usize {
+        self.inode().unwrap().getattr().st_size as usize
+    }
     async fn read(&self, buf: &mut [u8]) -> Result<usize, SysError> {
-        let inode = self.dentry().unwrap().inode().unwrap();
-        let size = inode.read_at(self.pos(), buf).unwrap();
-        self.seek(SeekFrom::Current(size as i64)).expect("seek failed");
-        Ok(size)
+        io_in_chunks(buf.len(), |chunk| {
+            let inode = self.inode().unwrap();
+            self.file_inner().read_with(|pos| self.read_in_size(inode.clone(), pos, &mut buf[chunk.clone()]))
+        }).await
     }
     async fn write(&self, buf: &[u8]) -> Result<usize, SysError> {
-        let inode = self.dentry().unwrap().inode().unwrap();
-        let size = inode.write_at(self.pos(), buf).unwrap();
-        self.seek(SeekFrom::Current(size as i64)).expect("seek failed");
-        Ok(size)
+        let size = || self.size();
+        let append_end: Option<&(dyn Fn() -> usize + Sync)> = if self.flags().contains(OpenFlags::O_APPEND) {
+            Some(&size)
+        } else {
+            None
+        };
+        io_in_chunks(buf.len(), |chunk| {
+            let inode = self.inode().unwrap();
+            let buf = &buf[chunk];
+            self.file_inner().write_with(buf.len(), append_end, |pos| inode.cache_write_at(pos, buf).map_err(SysError::from))
+        }).await
     }
-}
\ No newline at end of file
+    async fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, SysError> {
+        io_in_chunks(buf.len(), |chunk| {
+            let inode = self.inode().unwrap();
+            self.read_in_size(inode, offset + chunk.start, &mut buf[chunk])
+        }).await
+    }
+    async fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, SysError> {
+        io_in_chunks(buf.len(), |chunk| {
+            let inode = self.inode().unwrap();
+            inode.cache_write_at(offset + chunk.start, &buf[chunk]).map_err(SysError::from)
+        }).await
+    }
+}
@@ -1,10 +1,10 @@
 //! fat32 file system implementation for VFS file system type
 
-use alloc::{string::{String, ToString}, sync::Arc};
+use alloc::{boxed::Box, sync::Arc};
 
-use crate::{devices::BlockDevice, fs::{vfs::{fstype::{FSType, FSTypeInner, MountFlags}, inode::{InodeInner, InodeMode}, Dentry, DentryState, DCACHE}, SuperBlock, SuperBlockInner}, sync::UPSafeCell};
+use crate::{devices::BlockDevice, fs::{vfs::{fstype::{FSType, FSTypeInner, MountFlags}, Dentry, DentryState, DCACHE}, SuperBlock, SuperBlockInner}};
 
-use super::{dentry::FatDentry, inode::{FatDirInode, FatDirMeta}, superblock::FatSuperBlock};
+use super::{dentry::FatDentry, superblock::FatSuperBlock};
 
 
 pub struct Fat32FSType {
@@ -12,10 +12,9 @@ pub struct Fat32FSType {
 }
 
 impl Fat32FSType {
-    #[allow(unused)]
-    pub fn new() -> Arc<Self> {
+    pub fn new(name: &str) -> Arc<Self> {
         Arc::new(Self {
-            inner: FSTypeInner::new("fat32"),
+            inner: FSTypeInner::new(name),
         })
     }
 }
@@ -33,15 +32,15 @@ impl FSType for Fat32FSType {
             let ptr: *const dyn FSType = self;
             Arc::from_raw(ptr)
         };
-        let sb = FatSuperBlock::new(SuperBlockInner::new(dev, fs_type.clone()));
-        self.add_sb(name, sb);
-        let sb = self.get_static_sb(name);
-        let dir = sb.get_root_inode(name);
+        // the inodes borrow the file system, which is never freed
+        let sb: &'static Arc<FatSuperBlock> = Box::leak(Box::new(FatSuperBlock::new(SuperBlockInner::new(dev, fs_type.clone()))));
+        let root_inode = FatSuperBlock::root_inode(sb);
         let root_dentry = FatDentry::new(name, parent.clone());
-        root_dentry.set_inode(dir);
+        root_dentry.set_inode(root_inode);
         root_dentry.set_state(DentryState::USED);
         sb.set_root_dentry(root_dentry.clone());
-        DCACHE.lock().insert("/".to_string(), root_dentry.clone());
+        DCACHE.pin(root_dentry.clone());
+        self.add_sb(&root_dentry.path(), sb.clone());
         Some(root_dentry)
     }
-}
\ No newline at end of file
+}
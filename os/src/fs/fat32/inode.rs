@@ -1,352 +1,428 @@
 //! fat32 inode implement for vfs
-//! fat only have file and dir, they will be view same as Inode in VFS
+//! fat only have file and dir, both are kept by one FatInode.
+//! fat keeps no owner, permission bits or change time: every inode is owned by root with
+//! full permission, and the change time is the modification time. It keeps no extended attributes either.
+//! The timestamps come from the directory entry, see `time`
 
 use core::cmp;
+use core::sync::atomic::Ordering;
 
 use alloc::string::String;
+use alloc::sync::{Arc, Weak};
 use alloc::{vec, vec::Vec};
-use alloc::sync::Arc;
-use fatfs::{Error, Dir, File, LossyOemCpConverter, NullTimeProvider};
 use fatfs::{Read, Seek, SeekFrom, Write};
-use log::{debug, info};
+use log::{debug, info, warn};
 
-use crate::fs::vfs::inode::InodeMode;
+use crate::config::BLOCK_SIZE;
+use crate::fs::vfs::inode::{InodeMode, XattrFlags};
 use crate::fs::page::cache::PageCache;
 use crate::fs::page::page::Page;
-use crate::fs::{Kstat, StatxTimestamp, SuperBlock, Xstat, XstatMask};
+use crate::fs::{writeback, Kstat, StatxTimestamp, SuperBlock, Xstat, XstatMask};
 use crate::sync::mutex::SpinNoIrqLock;
-use crate::{fs::vfs::{Inode, InodeInner}, sync::UPSafeCell};
+use crate::fs::vfs::{Inode, InodeInner};
 
-use super::disk::DiskCursor;
-use super::superblock::FatSuperBlock;
-use super::SysError;
+use super::time::{from_fat_date, from_fat_datetime, to_fat_datetime};
+use super::{as_vfs_err, FatDir, FatDirEntry, FatFileHandle, SysError};
 
-/// fit fat file into inode
-pub struct FatFileInode {
-    inner: InodeInner,
-    file: UPSafeCell<FatFileMeta>,
+/// what a fat inode is on the disk
+pub enum FatNode {
+    File(FatFileHandle),
+    Dir(FatDir),
 }
 
-pub struct FatFileMeta {
-    #[allow(unused)]
+/// where the inode is in the file system
+pub struct FatMeta {
+    /// the directory holding its entry, None for the root
+    pub(crate) parent: Option<FatDir>,
+    /// the name of its entry in the parent
     pub(crate) name: String,
-    pub(crate) inner: File<'static, DiskCursor, NullTimeProvider, LossyOemCpConverter>,
-    pub(crate) size: usize,
+    pub(crate) node: FatNode,
 }
 
-/// fit fat dir into inode
-pub struct FatDirInode {
-    pub(crate) inner: InodeInner,
-    pub(crate) dir: UPSafeCell<FatDirMeta>,
+/// fit fat file or dir into inode
+pub struct FatInode {
+    inner: InodeInner,
+    meta: SpinNoIrqLock<FatMeta>,
+    /// the root of the file system, rename targets are found from it
+    root: FatDir,
+    /// directories take whole clusters
+    cluster_size: usize,
+    cache: Arc<PageCache>,
 }
 
-pub struct FatDirMeta {
-    #[allow(unused)]
-    pub(crate) name: String,
-    pub(crate) inner: Dir<'static, DiskCursor, NullTimeProvider, LossyOemCpConverter>,
-    pub(crate) size: usize,
+unsafe impl Send for FatInode {}
+unsafe impl Sync for FatInode {}
+
+/// the error code of a fatfs error, for the i32 results of the Inode trait
+fn fat_err(err: fatfs::Error<()>) -> i32 {
+    as_vfs_err(err) as i32
 }
 
-impl Inode for FatFileInode {
+impl FatInode {
+    /// the inode of the root directory
+    pub fn root(super_block: Weak<dyn SuperBlock>, root: FatDir, cluster_size: usize) -> Arc<Self> {
+        Arc::new(Self {
+            inner: InodeInner::new(Some(super_block), InodeMode::DIR, 0),
+            meta: SpinNoIrqLock::new(FatMeta {
+                parent: None,
+                name: String::new(),
+                node: FatNode::Dir(root.clone()),
+            }),
+            root,
+            cluster_size,
+            cache: Arc::new(PageCache::new()),
+        })
+    }
+
+    /// the inode of `entry` found in the directory `parent`
+    fn from_entry(&self, parent: FatDir, entry: FatDirEntry) -> Arc<Self> {
+        let (mode, size, node) = if entry.is_dir() {
+            (InodeMode::DIR, 0, FatNode::Dir(entry.to_dir()))
+        } else {
+            (InodeMode::FILE, entry.len() as usize, FatNode::File(entry.to_file()))
+        };
+        let inner = InodeInner::new(self.inner.super_block.clone(), mode, size);
+        let mtime = from_fat_datetime(entry.modified());
+        inner.set_mtime(mtime);
+        inner.set_ctime(mtime);
+        inner.set_atime(from_fat_date(entry.accessed()));
+        inner.set_btime(Some(from_fat_datetime(entry.created())));
+        Arc::new(Self {
+            inner,
+            meta: SpinNoIrqLock::new(FatMeta {
+                parent: Some(parent),
+                name: entry.file_name(),
+                node,
+            }),
+            root: self.root.clone(),
+            cluster_size: self.cluster_size,
+            cache: Arc::new(PageCache::new()),
+        })
+    }
+
+    /// the directory this inode is, ENOTDIR for a file
+    fn dir(&self) -> Result<FatDir, SysError> {
+        match &self.meta.lock().node {
+            FatNode::Dir(dir) => Ok(dir.clone()),
+            FatNode::File(_) => Err(SysError::ENOTDIR),
+        }
+    }
+
+    /// the entry called `name` in this directory. Names are matched like fat does,
+    /// ignoring case, by the long name or by the 8.3 short name
+    fn find_entry(dir: &FatDir, name: &str) -> Option<FatDirEntry> {
+        let mut entries = dir.iter().filter_map(|entry| entry.ok());
+        entries.find(|entry| {
+            let long_name = entry.file_name();
+            long_name == name
+                || long_name.to_lowercase() == name.to_lowercase()
+                || entry.short_file_name().eq_ignore_ascii_case(name)
+        })
+    }
+
+    /// the size seen by readers: the on-disk size or the end of the cached data, whichever is larger.
+    /// A directory takes the clusters holding its entries
+    fn size(&self) -> usize {
+        let dir = match &self.meta.lock().node {
+            FatNode::File(_) => return cmp::max(self.inner.size.load(Ordering::Acquire), self.cache.end()),
+            FatNode::Dir(dir) => dir.clone(),
+        };
+        // one slot for the short entry and one for every 13 characters of a long name
+        let slots: usize = dir.iter()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let long_name = entry.file_name();
+                if long_name == entry.short_file_name() {
+                    1
+                } else {
+                    1 + (long_name.encode_utf16().count() + 12) / 13
+                }
+            })
+            .sum();
+        cmp::max(slots * 32, 1).div_ceil(self.cluster_size) * self.cluster_size
+    }
+
+    /// the device number of the super block
+    fn dev(&self) -> usize {
+        self.inner.super_block.as_ref()
+            .and_then(|sb| sb.upgrade())
+            .map_or(0, |sb| sb.inner().dev)
+    }
+
+    /// split the absolute `path` into the directory holding it, found from the root
+    /// of this file system, and the name in it
+    fn resolve_parent(&self, path: &str) -> Result<(FatDir, String), SysError> {
+        let mount_point = self.inner.super_block.as_ref()
+            .and_then(|sb| sb.upgrade())
+            .map_or(String::from("/"), |sb| sb.root().path());
+        let path = path.strip_prefix(mount_point.as_str()).ok_or(SysError::EXDEV)?;
+        let path = path.trim_matches('/');
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let dir = if parent.is_empty() {
+            self.root.clone()
+        } else {
+            self.root.open_dir(parent).map_err(as_vfs_err)?
+        };
+        Ok((dir, String::from(name)))
+    }
+}
+
+impl Inode for FatInode {
     fn inode_inner(&self) -> &InodeInner {
         &self.inner
     }
 
     fn cache(&self) -> Arc<PageCache> {
-        panic!("not support");
+        self.cache.clone()
     }
 
-    fn read_page_at(self: Arc<Self>, _offset: usize) -> Option<Arc<Page>> {
-        panic!("not support");
+    fn read_page_at(self: Arc<Self>, offset: usize) -> Option<Arc<Page>> {
+        let size = self.inner.size.load(Ordering::Acquire);
+        self.cache.clone().read_page(self, size, offset)
     }
 
     fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, i32> {
-        //info!("try to read at: offset: {}, buf len: {}", offset, buf.len());
-        let inner = self.file.exclusive_access();
-
-        if offset >= inner.size {
+        let mut meta = self.meta.lock();
+        let FatNode::File(file) = &mut meta.node else {
+            return Err(SysError::EISDIR as i32);
+        };
+        let len = file.seek(SeekFrom::End(0)).map_err(fat_err)? as usize;
+        if offset >= len {
             return Ok(0);
         }
-        let seek_curr = SeekFrom::Start(offset as _);
-        inner.inner.seek(seek_curr).expect("seek failed");
-        let len = inner.size;
-        debug!("off: {:#x} rlen: {:#x}", offset, len);
-        // read cached file.
-        inner
-            .inner
-            .seek(SeekFrom::Start(offset as u64))
-            .expect("seek failed");
-        let rlen = cmp::min(buf.len(), len as usize - offset);
-        inner
-            .inner
-            .read_exact(&mut buf[..rlen])
-            .expect("read failed");
-        Ok(rlen)
+        debug!("[FatInode] read at {:#x}, len {:#x}", offset, buf.len());
+        file.seek(SeekFrom::Start(offset as u64)).map_err(fat_err)?;
+        let want = cmp::min(buf.len(), len - offset);
+        let mut read = 0;
+        while read < want {
+            let n = file.read(&mut buf[read..want]).map_err(fat_err)?;
+            if n == 0 {
+                break;
+            }
+            read += n;
+        }
+        Ok(read)
     }
 
     fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, i32> {
-        let inner = self.file.exclusive_access();
-
-        // if offset > len
-        let seek_curr = SeekFrom::Start(offset as _);
-        let curr_off = inner.inner.seek(seek_curr).expect("seek failed") as usize;
-        if offset != curr_off {
-            let buffer = vec![0u8; 512];
-            loop {
-                let wlen = cmp::min(offset - inner.size, 512);
-
-                if wlen == 0 {
-                    break;
-                }
-                let real_wlen = inner.inner.write(&buffer).expect("write failed");
-                inner.size += real_wlen;
+        let mut meta = self.meta.lock();
+        let FatNode::File(file) = &mut meta.node else {
+            return Err(SysError::EISDIR as i32);
+        };
+        // fatfs can not seek past the end, fill the hole with zeros
+        let mut len = file.seek(SeekFrom::End(0)).map_err(fat_err)? as usize;
+        if offset > len {
+            let zeros = vec![0u8; BLOCK_SIZE];
+            while len < offset {
+                let n = cmp::min(offset - len, BLOCK_SIZE);
+                file.write_all(&zeros[..n]).map_err(fat_err)?;
+                len += n;
             }
+        } else {
+            file.seek(SeekFrom::Start(offset as u64)).map_err(fat_err)?;
         }
-
-        inner.inner.write_all(buf).expect("write failed");
-
-        if offset + buf.len() > inner.size {
-            inner.size = offset + buf.len();
-        }
+        file.write_all(buf).map_err(fat_err)?;
+        self.inner.size.fetch_max(offset + buf.len(), Ordering::AcqRel);
         Ok(buf.len())
     }
 
-    fn truncate(&self, size: usize) -> Result<usize, SysError> {
-        self.file
-            .exclusive_access()
-            .inner
-            .seek(SeekFrom::Start(size as u64))
-            .expect("seek failed");
-        self.file.exclusive_access().inner.truncate().expect("truncate failed");
-        Ok(0)
+    fn cache_read_at(self: Arc<Self>, offset: usize, buf: &mut [u8]) -> Result<usize, i32> {
+        let size = self.inner.size.load(Ordering::Acquire);
+        Ok(self.cache.clone().read(self, size, offset, buf))
     }
 
-    fn getattr(&self) -> crate::fs::Kstat {
-        Kstat {
-            st_ino: 1,
-            st_mode: InodeMode::FILE.bits(),
-            st_atime_sec: 0,
-            st_atime_nsec: 0,
-            st_blksize: 512,
-            st_ctime_sec: 0,
-            st_ctime_nsec: 0,
-            st_blocks: self.file.exclusive_access().size as i64 / 512,
-            st_dev: 0,
-            st_gid: 0,
-            st_mtime_sec: 0,
-            st_mtime_nsec: 0,
-            st_nlink: 1,
-            st_size: self.file.exclusive_access().size as i64,
-            st_rdev: 0,
-            st_uid: 0,
-            _pad0: 0,
-            _pad1: 0,
+    fn cache_write_at(self: Arc<Self>, offset: usize, buf: &[u8]) -> Result<usize, i32> {
+        let size = self.inner.size.load(Ordering::Acquire);
+        let inode: Arc<dyn Inode> = self.clone();
+        let (written, dirtied) = self.cache.write(inode.clone(), size, offset, buf);
+        writeback::mark_dirty(&inode, dirtied);
+        Ok(written)
+    }
+
+    fn truncate(&self, size: usize) -> Result<usize, SysError> {
+        info!("[FatInode] truncate to {}", size);
+        // the cache may hold data past the new end, which would otherwise be read back or flushed
+        self.cache.truncate(size);
+        let len = {
+            let mut meta = self.meta.lock();
+            let FatNode::File(file) = &mut meta.node else {
+                return Err(SysError::EISDIR);
+            };
+            let len = file.seek(SeekFrom::End(0)).map_err(as_vfs_err)? as usize;
+            if size < len {
+                file.seek(SeekFrom::Start(size as u64)).map_err(as_vfs_err)?;
+                file.truncate().map_err(as_vfs_err)?;
+            }
+            len
+        };
+        self.inner.size.store(size, Ordering::Release);
+        if size > len {
+            self.write_at(size, &[]).map_err(SysError::from)?;
         }
+        Ok(0)
     }
 
-    fn getxattr(&self, mask: crate::fs::XstatMask) -> crate::fs::Xstat {
-        const SUPPORTED_MASK: XstatMask = XstatMask::from_bits_truncate({
-            XstatMask::STATX_BLOCKS.bits |
-            XstatMask::STATX_NLINK.bits |
-            XstatMask::STATX_MODE.bits |
-            XstatMask::STATX_SIZE.bits |
-            XstatMask::STATX_INO.bits
-        });
-        let mask = mask & SUPPORTED_MASK;
-        Xstat {
-            stx_mask: mask.bits,
-            stx_blksize: 512,
-            stx_attributes: 0,
-            stx_nlink: 1,
-            stx_uid: 0,
-            stx_gid: 0,
-            stx_mode: InodeMode::FILE.bits() as _,
-            stx_ino: 1,
-            stx_size: self.file.exclusive_access().size as u64,
-            stx_blocks: self.file.exclusive_access().size as u64 / 512,
-            stx_attributes_mask: 0,
-            stx_atime: StatxTimestamp {
-                tv_sec: 0,
-                tv_nsec: 0,
-            },
-            stx_btime: StatxTimestamp {
-                tv_sec: 0,
-                tv_nsec: 0,
-            },
-            stx_ctime: StatxTimestamp {
-                tv_sec: 0,
-                tv_nsec: 0,
-            },
-            stx_mtime: StatxTimestamp {
-                tv_sec: 0,
-                tv_nsec: 0,
-            },
-            stx_rdev_major: 0,
-            stx_rdev_minor: 0,
-            stx_dev_major: 0,
-            stx_dev_minor: 0,
-            stx_mnt_id: 0,
-            stx_dio_mem_align: 0,
-            std_dio_offset_align: 0,
-            stx_subvol: 0,
-            stx_atomic_write_unit_min: 0,
-            stx_atomic_write_unit_max: 0,
-            stx_atomic_write_segments_max: 0,
-            stx_dio_read_offset_align: 0,
+    fn create(&self, name: &str, mode: InodeMode) -> Option<Arc<dyn Inode>> {
+        let dir = self.dir().ok()?;
+        let ret = match mode.get_type() {
+            InodeMode::FILE => dir.create_file(name).map(|_| ()),
+            InodeMode::DIR => dir.create_dir(name).map(|_| ()),
+            // fat has no symlinks, fifos or device files
+            _ => return None,
+        };
+        if let Err(e) = ret {
+            warn!("[FatInode] create {} failed: {:?}", name, as_vfs_err(e));
+            return None;
         }
+        let entry = Self::find_entry(&dir, name)?;
+        Some(self.from_entry(dir, entry))
     }
 
-    fn lookup(&self, _name: &str) -> Option<Arc<dyn Inode>> {
-        panic!("fat32 file inode dont support lookup!")
+    fn lookup(&self, name: &str) -> Option<Arc<dyn Inode>> {
+        let dir = self.dir().ok()?;
+        let entry = Self::find_entry(&dir, name)?;
+        Some(self.from_entry(dir, entry))
     }
 
+    /// the long names, or the short ones of entries without, as getdents shows them
     fn ls(&self) -> Vec<String> {
-        panic!("fat32 file inode dont support ls!")
+        let Ok(dir) = self.dir() else {
+            return Vec::new();
+        };
+        dir.iter()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name())
+            .filter(|name| name != "." && name != "..")
+            .collect()
     }
 
     fn unlink(&self) -> Result<usize, i32> {
-        panic!("fat32 file can only be unlink by parent dir")
-    }
-
-    fn create(&self, _path: &str, _mode: InodeMode) -> Option<Arc<dyn Inode>> {
-        panic!("fat32 file can not create file!")
+        let meta = self.meta.lock();
+        let parent = meta.parent.as_ref().ok_or(SysError::EBUSY as i32)?;
+        parent.remove(&meta.name).map_err(fat_err)?;
+        Ok(0)
     }
 
-    fn cache_read_at(self: Arc<Self>, _offset: usize, _buf: &mut [u8]) -> Result<usize, i32> {
-        panic!("not support cached read")
+    fn remove(&self, name: &str, _mode: InodeMode) -> Result<usize, i32> {
+        let dir = self.dir().map_err(|e| e as i32)?;
+        dir.remove(name).map_err(fat_err)?;
+        Ok(0)
     }
 
-    fn cache_write_at(self: Arc<Self>, _offset: usize, _buf: &[u8]) -> Result<usize, i32> {
-        panic!("not support cached write")
+    fn rename(&self, target: &str, new_inode: Option<Arc<dyn Inode>>) -> Result<(), SysError> {
+        let old_mode = self.inner.mode().get_type();
+        if let Some(new) = new_inode.as_ref() {
+            let new_mode = new.inode_inner().mode().get_type();
+            if new_mode != old_mode {
+                return Err(match old_mode {
+                    InodeMode::DIR => SysError::ENOTDIR,
+                    _ => SysError::EISDIR,
+                });
+            }
+        }
+        let (dst_dir, dst_name) = self.resolve_parent(target)?;
+        let mut meta = self.meta.lock();
+        let parent = meta.parent.clone().ok_or(SysError::EBUSY)?;
+        if new_inode.is_some() {
+            dst_dir.remove(&dst_name).map_err(as_vfs_err)?;
+        }
+        parent.rename(&meta.name, &dst_dir, &dst_name).map_err(as_vfs_err)?;
+        // the entry moved, the handle has to point at the new one
+        meta.node = match old_mode {
+            InodeMode::DIR => FatNode::Dir(dst_dir.open_dir(&dst_name).map_err(as_vfs_err)?),
+            _ => FatNode::File(dst_dir.open_file(&dst_name).map_err(as_vfs_err)?),
+        };
+        meta.parent = Some(dst_dir);
+        meta.name = dst_name;
+        Ok(())
     }
 
-    fn remove(&self, _name: &str, _mode: InodeMode) -> Result<usize, i32> {
-        panic!()
+    fn symlink(&self, _target: &str) -> Result<Arc<dyn Inode>, SysError> {
+        Err(SysError::EPERM)
     }
 
-    fn symlink(&self, _target: &str) -> Result<Arc<dyn Inode>, super::SysError> {
-        panic!()
+    fn link(&self, _target: &str) -> Result<usize, SysError> {
+        Err(SysError::EPERM)
     }
 
-    fn readlink(&self) -> Result<String, super::SysError> {
-        panic!()
+    fn readlink(&self) -> Result<String, SysError> {
+        Err(SysError::EINVAL)
     }
-}
 
-impl Inode for FatDirInode {
-    fn inode_inner(&self) -> &InodeInner {
-        &self.inner
-    }
-    fn cache(&self) -> Arc<PageCache> {
-        panic!("fat32 not support for caching")
-    }
-    fn read_page_at(self: Arc<Self>, _offset: usize) -> Option<Arc<Page>> {
-        panic!("not support");
-    }
-    fn create(&self, name: &str, mode: InodeMode) -> Option<Arc<dyn Inode>> {
-        let dir = self.dir.exclusive_access();
-        let super_block = self.inode_inner().super_block.clone();
-        match mode {
-            InodeMode::FILE => {
-                dir.inner
-                .create_file(name)
-                .map(|file| -> Option<Arc<dyn Inode>> {
-                    Some(
-                        Arc::new(FatFileInode {
-                            inner: InodeInner::new(super_block, mode, 0),
-                            file: UPSafeCell::new(FatFileMeta {
-                                name: String::from(name),
-                                inner: file,
-                                size: 0,
-                            })
-                        }))
-                })
-                .expect("create file failed")
-            }
-            InodeMode::DIR => {
-                dir.inner
-                .create_dir(name)
-                .map(|dir| -> Option<Arc<dyn Inode>> {
-                    Some(
-                        Arc::new(FatDirInode {
-                            inner: InodeInner::new(super_block, mode, 0),
-                            dir: UPSafeCell::new(FatDirMeta {
-                                name: String::from(name),
-                                inner: dir,
-                                size: 0,
-                            })
-                        }))
-                })
-                .expect("create dir failed")
-            }
-            _ => {
-                panic!("fat32 not support!")
-            }
-        }
-    }
-    fn getattr(&self) -> crate::fs::Kstat {
+    fn getattr(&self) -> Kstat {
+        let inner = self.inode_inner();
+        let size = self.size();
         Kstat {
-            st_ino: 1,
-            st_mode: InodeMode::DIR.bits(),
-            st_atime_sec: 0,
-            st_atime_nsec: 0,
-            st_blksize: 512,
-            st_ctime_sec: 0,
-            st_ctime_nsec: 0,
-            st_blocks: self.dir.exclusive_access().size as i64 / 512,
-            st_dev: 0,
-            st_gid: 0,
-            st_mtime_sec: 0,
-            st_mtime_nsec: 0,
-            st_nlink: 1,
-            st_size: self.dir.exclusive_access().size as i64,
+            st_dev: self.dev() as u64,
+            st_ino: inner.ino as u64,
+            st_mode: inner.mode().bits() as _,
+            st_nlink: inner.nlink() as u32,
+            st_uid: inner.uid(),
+            st_gid: inner.gid(),
             st_rdev: 0,
-            st_uid: 0,
             _pad0: 0,
+            st_size: size as _,
             _pad1: 0,
+            st_blksize: BLOCK_SIZE as _,
+            st_blocks: (size / BLOCK_SIZE) as _,
+            st_atime_sec: inner.atime().tv_sec as _,
+            st_atime_nsec: inner.atime().tv_nsec as _,
+            st_mtime_sec: inner.mtime().tv_sec as _,
+            st_mtime_nsec: inner.mtime().tv_nsec as _,
+            st_ctime_sec: inner.ctime().tv_sec as _,
+            st_ctime_nsec: inner.ctime().tv_nsec as _,
         }
     }
-    fn getxattr(&self, mask: crate::fs::XstatMask) -> crate::fs::Xstat {
+
+    fn getxattr(&self, _mask: XstatMask) -> Xstat {
         const SUPPORTED_MASK: XstatMask = XstatMask::from_bits_truncate({
             XstatMask::STATX_BLOCKS.bits |
+            XstatMask::STATX_ATIME.bits |
+            XstatMask::STATX_CTIME.bits |
+            XstatMask::STATX_MTIME.bits |
+            XstatMask::STATX_BTIME.bits |
             XstatMask::STATX_NLINK.bits |
+            XstatMask::STATX_TYPE.bits |
             XstatMask::STATX_MODE.bits |
             XstatMask::STATX_SIZE.bits |
             XstatMask::STATX_INO.bits
         });
-        let mask = mask & SUPPORTED_MASK;
+        let inner = self.inode_inner();
+        let btime = inner.btime().unwrap_or(inner.mtime());
+        let size = self.size();
+        let dev = self.dev();
         Xstat {
-            stx_mask: mask.bits,
-            stx_blksize: 512,
+            stx_mask: SUPPORTED_MASK.bits,
+            stx_blksize: BLOCK_SIZE as _,
             stx_attributes: 0,
-            stx_nlink: 1,
-            stx_uid: 0,
-            stx_gid: 0,
-            stx_mode: InodeMode::DIR.bits() as _,
-            stx_ino: 1,
-            stx_size: self.dir.exclusive_access().size as u64,
-            stx_blocks: self.dir.exclusive_access().size as u64 / 512,
+            stx_nlink: inner.nlink() as u32,
+            stx_uid: inner.uid(),
+            stx_gid: inner.gid(),
+            stx_mode: inner.mode().bits() as _,
+            stx_ino: inner.ino as u64,
+            stx_size: size as _,
+            stx_blocks: (size / BLOCK_SIZE) as _,
             stx_attributes_mask: 0,
             stx_atime: StatxTimestamp {
-                tv_sec: 0,
-                tv_nsec: 0,
+                tv_sec: inner.atime().tv_sec as _,
+                tv_nsec: inner.atime().tv_nsec as _,
             },
             stx_btime: StatxTimestamp {
-                tv_sec: 0,
-                tv_nsec: 0,
+                tv_sec: btime.tv_sec as _,
+                tv_nsec: btime.tv_nsec as _,
             },
             stx_ctime: StatxTimestamp {
-                tv_sec: 0,
-                tv_nsec: 0,
+                tv_sec: inner.ctime().tv_sec as _,
+                tv_nsec: inner.ctime().tv_nsec as _,
             },
             stx_mtime: StatxTimestamp {
-                tv_sec: 0,
-                tv_nsec: 0,
+                tv_sec: inner.mtime().tv_sec as _,
+                tv_nsec: inner.mtime().tv_nsec as _,
             },
             stx_rdev_major: 0,
             stx_rdev_minor: 0,
-            stx_dev_major: 0,
-            stx_dev_minor: 0,
+            stx_dev_major: (dev >> 8) as _,
+            stx_dev_minor: (dev & 0xff) as _,
             stx_mnt_id: 0,
             stx_dio_mem_align: 0,
             std_dio_offset_align: 0,
@@ -357,69 +433,42 @@ impl Inode for FatDirInode {
             stx_dio_read_offset_align: 0,
         }
     }
-    fn lookup(&self, name: &str) -> Option<Arc<dyn Inode>> {
-        let dir = self.dir.exclusive_access();
-        let target = dir.inner
-        .iter()
-        .find(|x| {
-            x.as_ref().unwrap().file_name() == name
-        });
-        if target.is_none() {
-            return None;
-        }
-        let target = target.unwrap().unwrap();
-        if target.is_dir() {
-            Some(Arc::new(FatDirInode {
-                inner: InodeInner::new(
-                self.inode_inner().super_block.clone(),
-                InodeMode::DIR,
-                0,
-                ),
-                dir: UPSafeCell::new(FatDirMeta {
-                    name: String::from(name),
-                    inner: target.to_dir(),
-                    size: 0,
-                }),
-            }))
-        } else if target.is_file() {
-            Some(Arc::new(FatFileInode {
-                inner: InodeInner::new(
-                    self.inode_inner().super_block.clone(),
-                InodeMode::FILE,
-                0,
-                ),
-                file: UPSafeCell::new(FatFileMeta {
-                    name: String::from(name),
-                    inner: target.to_file(),
-                    size: target.len() as usize,
-                }),
-            }))
-        } else {
-            panic!("should not reach here!")
-        }
+
+    fn set_xattr(&self, _name: &str, _value: &[u8], _flags: XattrFlags) -> Result<(), SysError> {
+        // fat has nowhere to keep them
+        Err(SysError::EOPNOTSUPP)
     }
 
-    fn ls(&self) -> Vec<String> {
-        let dir = self.dir.exclusive_access();
-        dir.inner
-        .iter()
-        .filter_map(|x| {
-            let x = x.unwrap();
-            if x.file_name() == "." || x.file_name() == ".." {
-                return None;
-            }
-            Some(x.file_name())
-        })
-        .collect()
+    /// only the timestamps of files can be written, fatfs has no setters for directories.
+    /// The modification time is rounded down to 2 seconds and the access time to the day
+    fn sync_meta(&self) -> Result<(), SysError> {
+        let mut meta = self.meta.lock();
+        if let FatNode::File(file) = &mut meta.node {
+            let mtime = to_fat_datetime(self.inner.mtime());
+            file.set_modified(mtime);
+            file.set_accessed(to_fat_datetime(self.inner.atime()).date);
+            file.flush().map_err(as_vfs_err)?;
+        }
+        Ok(())
     }
 
-    fn unlink(&self) -> Result<usize, i32> {
-        panic!("fat32 not support for unlink")
+    fn clean_cached(&self) {
+        self.cache.clean();
     }
 
-    fn remove(&self, name: &str, _mode: InodeMode) -> Result<usize, i32> {
-        let _ = self.dir.exclusive_access().inner.remove(name);
-        Ok(0)
+    fn sync_cached(&self) {
+        if let Err(e) = self.cache.sync(self) {
+            warn!("[FatInode] flush {} failed: {:?}", self.meta.lock().name, e);
+        }
     }
 }
 
+impl Drop for FatInode {
+    fn drop(&mut self) {
+        // flush the dirty page in page cache
+        self.sync_cached();
+        if let Some(sb) = self.inner.super_block.as_ref().and_then(|sb| sb.upgrade()) {
+            sb.inner().dirty.remove_dead(self.inner.ino);
+        }
+    }
+}
@@ -6,24 +6,36 @@ pub mod inode;
 pub mod dentry;
 pub mod file;
 pub mod superblock;
+pub mod time;
 
-use fatfs::Error;
+use fatfs::{Error, LossyOemCpConverter};
 
 pub use sys_error::SysError;
 
 use crate::syscall::sys_error;
 
+use disk::DiskCursor;
+use time::FatTimeProvider;
+
+/// the fatfs file system on a block device
+pub type FatFs = fatfs::FileSystem<DiskCursor, FatTimeProvider, LossyOemCpConverter>;
+/// a directory of a mounted fat file system, which lives as long as the kernel
+pub type FatDir = fatfs::Dir<'static, DiskCursor, FatTimeProvider, LossyOemCpConverter>;
+/// a file of a mounted fat file system
+pub type FatFileHandle = fatfs::File<'static, DiskCursor, FatTimeProvider, LossyOemCpConverter>;
+/// an entry of a fat directory
+pub type FatDirEntry = fatfs::DirEntry<'static, DiskCursor, FatTimeProvider, LossyOemCpConverter>;
+
 /// match fat32 error to sys error
-/// (todo): match more error
 pub fn as_vfs_err(err: Error<()>) -> SysError {
     match err {
         Error::AlreadyExists => SysError::EEXIST,
         Error::CorruptedFileSystem => SysError::EIO,
         Error::DirectoryIsNotEmpty => SysError::ENOTEMPTY,
         Error::InvalidInput
-        | Error::InvalidFileNameLength
-        | Error::UnsupportedFileNameCharacter => SysError::EIO,
-        Error::NotEnoughSpace => SysError::ENOMEM,
+        | Error::UnsupportedFileNameCharacter => SysError::EINVAL,
+        Error::InvalidFileNameLength => SysError::ENAMETOOLONG,
+        Error::NotEnoughSpace => SysError::ENOSPC,
         Error::NotFound => SysError::ENOENT,
         Error::UnexpectedEof => SysError::EIO,
         Error::WriteZero => SysError::EIO,
@@ -31,4 +43,4 @@ pub fn as_vfs_err(err: Error<()>) -> SysError {
         _ => SysError::EIO,
 
     }
-}
\ No newline at end of file
+}
@@ -1,17 +1,17 @@
 //! fat32 file system implement for the VFS super block
 
-use crate::{fs::{vfs::{inode::InodeMode, Inode, InodeInner}, SuperBlock, SuperBlockInner}, sync::UPSafeCell};
-use alloc::{string::String, sync::Arc};
-use fatfs::{Dir, Error, File, LossyOemCpConverter, NullTimeProvider};
+use crate::{config::BLOCK_SIZE, fs::{vfs::Inode, SuperBlock, SuperBlockInner}};
+use alloc::sync::{Arc, Weak};
+use fatfs::FsOptions;
 
-use super::{disk::DiskCursor, inode::{FatDirInode, FatDirMeta}};
+use super::{disk::DiskCursor, inode::FatInode, time::FatTimeProvider, FatFs};
 
 
 pub struct FatSuperBlock {
     /// basic data
     pub(crate) inner: SuperBlockInner,
     /// fat32 object to control filesystem
-    pub(crate) block: Arc<fatfs::FileSystem<DiskCursor, NullTimeProvider, LossyOemCpConverter>>,
+    pub(crate) block: FatFs,
 }
 
 unsafe impl Send for FatSuperBlock {}
@@ -23,32 +23,26 @@ impl FatSuperBlock {
     pub fn new(inner: SuperBlockInner) -> Arc<Self> {
         let block_device = inner.device.as_ref().unwrap().clone();
         let cursor = DiskCursor::new(block_device);
-        let block = Arc::new(fatfs::FileSystem::new(cursor, fatfs::FsOptions::new()).expect("open fs wrong"));
+        let options = FsOptions::new().time_provider(FatTimeProvider);
+        let block = fatfs::FileSystem::new(cursor, options).expect("open fs wrong");
         Arc::new(Self {inner, block })
     }
+
+    /// create the inode of the root directory,
+    /// the directories and files of the inodes borrow the super block for as long as the kernel runs
+    pub fn root_inode(sb: &'static Arc<Self>) -> Arc<dyn Inode> {
+        let cluster_size = sb.block.stats()
+            .map_or(BLOCK_SIZE, |stats| stats.cluster_size() as usize);
+        let weak: Weak<dyn SuperBlock> = Arc::downgrade(sb);
+        FatInode::root(weak, sb.block.root_dir(), cluster_size)
+    }
 }
 
 impl SuperBlock for FatSuperBlock {
     fn inner(&self) -> &SuperBlockInner {
         &self.inner
     }
-    fn get_root_inode(&'static self, name: &str) -> Arc<dyn Inode> {
-        let sb = unsafe {
-            let ptr: *const dyn SuperBlock = self;
-            Arc::from_raw(ptr)
-        };
-        let dir = Arc::new(FatDirInode {
-            inner: InodeInner::new(
-            Some(Arc::downgrade(&sb)),
-            InodeMode::DIR,
-            0,
-            ),
-            dir: UPSafeCell::new(FatDirMeta {
-                name: String::from(name),
-                inner: self.block.root_dir(),
-                size: 0,
-            }),
-        });
-        dir
+    fn get_root_inode(&'static self, _name: &str) -> Arc<dyn Inode> {
+        self.inner().root.get().unwrap().clone().inode().unwrap()
     }
 }
@@ -0,0 +1,81 @@
+//! timestamps of fat32 directory entries
+//! fat keeps local calendar dates without a time zone, they are taken as UTC here.
+//! The modification time only has a 2 second granularity and the access time is a bare date,
+//! so a timestamp read back is rounded down to what the entry can hold
+
+use fatfs::{Date, DateTime, Time, TimeProvider};
+
+use crate::timer::{ffi::TimeSpec, get_realtime_duration};
+
+const SECS_PER_DAY: usize = 24 * 60 * 60;
+/// 1980-01-01 00:00:00, the earliest time fat can hold
+const FAT_MIN_SEC: usize = 315_532_800;
+/// 2107-12-31 23:59:59, the latest time fat can hold
+const FAT_MAX_SEC: usize = 4_354_819_199;
+
+/// stamps the entries fatfs creates or modifies with the realtime clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FatTimeProvider;
+
+impl TimeProvider for FatTimeProvider {
+    fn get_current_date(&self) -> Date {
+        self.get_current_date_time().date
+    }
+    fn get_current_date_time(&self) -> DateTime {
+        to_fat_datetime(TimeSpec::from(get_realtime_duration()))
+    }
+}
+
+/// days since 1970-01-01 of a civil date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// the civil date of `days` since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+/// the time of a fat date and time
+pub fn from_fat_datetime(date_time: DateTime) -> TimeSpec {
+    let date = date_time.date;
+    let time = date_time.time;
+    let days = days_from_civil(date.year as i64, date.month as i64, date.day as i64);
+    let sec = days * SECS_PER_DAY as i64 + time.hour as i64 * 3600 + time.min as i64 * 60 + time.sec as i64;
+    TimeSpec { tv_sec: sec.max(0) as usize, tv_nsec: time.millis as usize * 1_000_000 }
+}
+
+/// the time of the start of a fat date
+pub fn from_fat_date(date: Date) -> TimeSpec {
+    from_fat_datetime(DateTime::new(date, Time::new(0, 0, 0, 0)))
+}
+
+/// the fat date and time of `time`, clamped to the years fat can hold
+pub fn to_fat_datetime(time: TimeSpec) -> DateTime {
+    let sec = time.tv_sec.clamp(FAT_MIN_SEC, FAT_MAX_SEC);
+    let (year, month, day) = civil_from_days((sec / SECS_PER_DAY) as i64);
+    let sec_of_day = sec % SECS_PER_DAY;
+    DateTime::new(
+        Date::new(year as u16, month as u16, day as u16),
+        Time::new(
+            (sec_of_day / 3600) as u16,
+            (sec_of_day % 3600 / 60) as u16,
+            (sec_of_day % 60) as u16,
+            (time.tv_nsec / 1_000_000).min(999) as u16,
+        ),
+    )
+}
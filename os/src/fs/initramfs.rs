@@ -0,0 +1,170 @@
+//! initramfs: a cpio archive built into the kernel and unpacked into a tmpfs root
+//!
+//! `make PAYLOAD=tests.cpio` copies the archive to `os/initramfs.cpio` and turns on the
+//! `initramfs` feature. At boot the kernel then mounts a tmpfs as `/`, unpacks the archive
+//! there and runs `/init` or `/bin/init` from it, no block device needed. `root=disk` on
+//! the kernel command line boots from the disk as a kernel without an archive does.
+//!
+//! Only the "new" ASCII format (`cpio -H newc`) is read: directories, regular files and
+//! symlinks, with the permission bits, owners and mtime of the archive. Other entries,
+//! such as device nodes, are skipped. Hard links are not recognized, every entry is its
+//! own file.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::sync::Arc;
+use log::{info, warn};
+
+use crate::{syscall::SysError, timer::ffi::TimeSpec};
+
+use super::vfs::{inode::InodeMode, Dentry, DentryState};
+
+#[cfg(feature = "initramfs")]
+static PAYLOAD: &[u8] = include_bytes!("../../initramfs.cpio");
+
+/// whether the root is the unpacked archive
+static BOOTED: AtomicBool = AtomicBool::new(false);
+
+/// the paths tried in order for the first user task
+pub const INIT_PATHS: [&str; 2] = ["/init", "/bin/init"];
+
+const MAGIC: &[u8] = b"070701";
+/// the same layout, with a checksum of the data we do not verify
+const MAGIC_CRC: &[u8] = b"070702";
+const HEADER_LEN: usize = 110;
+const TRAILER: &[u8] = b"TRAILER!!!";
+
+/// the archive to boot from: the one built in, unless the command line asks for the disk
+pub fn payload() -> Option<&'static [u8]> {
+    builtin().filter(|_| crate::devices::bootarg("root") != Some("disk"))
+}
+
+/// the archive built into the kernel
+#[cfg(feature = "initramfs")]
+pub fn builtin() -> Option<&'static [u8]> {
+    Some(PAYLOAD)
+}
+
+/// the archive built into the kernel, there is none
+#[cfg(not(feature = "initramfs"))]
+pub fn builtin() -> Option<&'static [u8]> {
+    None
+}
+
+/// whether the kernel booted from the archive instead of the disk
+pub fn booted() -> bool {
+    BOOTED.load(Ordering::Relaxed)
+}
+
+/// the fields of a newc header, all but the magic in 8 hex digits
+struct Header {
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    mtime: usize,
+    filesize: usize,
+    namesize: usize,
+}
+
+fn hex_field(header: &[u8], index: usize) -> Result<usize, SysError> {
+    let start = MAGIC.len() + index * 8;
+    let digits = core::str::from_utf8(&header[start..start + 8]).map_err(|_| SysError::EINVAL)?;
+    usize::from_str_radix(digits, 16).map_err(|_| SysError::EINVAL)
+}
+
+impl Header {
+    fn parse(header: &[u8]) -> Result<Self, SysError> {
+        if header.len() < HEADER_LEN || (&header[..6] != MAGIC && &header[..6] != MAGIC_CRC) {
+            return Err(SysError::EINVAL);
+        }
+        // ino, mode, uid, gid, nlink, mtime, filesize, devmajor, devminor,
+        // rdevmajor, rdevminor, namesize, check
+        Ok(Self {
+            mode: hex_field(header, 1)? as u32,
+            uid: hex_field(header, 2)? as u32,
+            gid: hex_field(header, 3)? as u32,
+            mtime: hex_field(header, 5)?,
+            filesize: hex_field(header, 6)?,
+            namesize: hex_field(header, 11)?,
+        })
+    }
+}
+
+/// headers, names and data all start 4 bytes aligned
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// unpack `archive` under `root`, return the number of entries created
+pub fn unpack(root: &Arc<dyn Dentry>, archive: &[u8]) -> Result<usize, SysError> {
+    let mut offset = 0;
+    let mut count = 0;
+    loop {
+        let header = Header::parse(archive.get(offset..).ok_or(SysError::EINVAL)?)?;
+        let name_start = offset + HEADER_LEN;
+        // the name size counts the terminating nul
+        let name = archive
+            .get(name_start..name_start + header.namesize.saturating_sub(1))
+            .ok_or(SysError::EINVAL)?;
+        let data_start = align4(name_start + header.namesize);
+        let data = archive.get(data_start..data_start + header.filesize).ok_or(SysError::EINVAL)?;
+        offset = align4(data_start + header.filesize);
+        if name == TRAILER {
+            break;
+        }
+        let path = core::str::from_utf8(name).map_err(|_| SysError::EINVAL)?;
+        let path = path.trim_start_matches("./").trim_start_matches('/');
+        if path.is_empty() || path == "." {
+            continue;
+        }
+        if create(root, path, &header, data)? {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// create the entry of `header` at `path`, return false for a skipped one
+fn create(root: &Arc<dyn Dentry>, path: &str, header: &Header, data: &[u8]) -> Result<bool, SysError> {
+    let mode = InodeMode::from_bits_truncate(header.mode);
+    let ty = mode.get_type();
+    if ty != InodeMode::DIR && ty != InodeMode::FILE && ty != InodeMode::LINK {
+        warn!("[initramfs] skip {} of mode {:#o}", path, header.mode);
+        return Ok(false);
+    }
+    let dentry = root.clone().walk(path)?;
+    let inode = if dentry.state() != DentryState::NEGATIVE {
+        // a directory may come again, to set what the first mention left out
+        let inode = dentry.inode().unwrap();
+        if ty != InodeMode::DIR || inode.inode_inner().mode().get_type() != InodeMode::DIR {
+            warn!("[initramfs] skip {}, it exists", path);
+            return Ok(false);
+        }
+        inode
+    } else {
+        let parent = dentry.parent().ok_or(SysError::EINVAL)?;
+        let inode = parent.inode().unwrap().create(dentry.name(), ty).ok_or(SysError::EIO)?;
+        // a symlink keeps its target as its data
+        if ty != InodeMode::DIR {
+            inode.clone().cache_write_at(0, data).map_err(|_| SysError::EIO)?;
+        }
+        dentry.set_inode(inode.clone());
+        dentry.set_state(DentryState::USED);
+        parent.add_child(dentry.clone());
+        inode
+    };
+    let inner = inode.inode_inner();
+    inner.init_owner(header.uid, header.gid, InodeMode::from_bits_truncate(header.mode & 0o7777));
+    inner.init_times();
+    inner.set_mtime(TimeSpec { tv_sec: header.mtime, tv_nsec: 0 });
+    Ok(true)
+}
+
+/// unpack the built in archive into the tmpfs mounted at `root`
+pub fn init(root: &Arc<dyn Dentry>, archive: &[u8]) {
+    match unpack(root, archive) {
+        Ok(count) => info!("[initramfs] unpacked {} entries from {} bytes", count, archive.len()),
+        Err(e) => panic!("[initramfs] bad archive: {:?}", e),
+    }
+    BOOTED.store(true, Ordering::Relaxed);
+}
@@ -15,6 +15,8 @@ pub mod simplefs;
 pub mod procfs;
 pub mod shmfs;
 pub mod tmpfs;
+pub mod initramfs;
+pub mod writeback;
 
 use devfs::{fstype::DevFsType, init_devfs};
 use ext4::Ext4FSType;
@@ -23,11 +25,11 @@ use log::*;
 use procfs::{fstype::ProcFSType, init_procfs};
 pub use stdio::{Stdin, Stdout};
 
-use alloc::{boxed::Box, collections::btree_map::BTreeMap, string::{String, ToString}, sync::Arc};
+use alloc::{boxed::Box, collections::btree_map::BTreeMap, format, string::{String, ToString}, sync::Arc, vec::Vec};
 use tmpfs::{fstype::TmpFSType, init_tmpfs};
-use vfs::{fstype::{FSType, MountFlags}, DCACHE};
+use vfs::{fstype::{FSType, MountFlags}, inode::{sync_inode_meta, InodeMode}, Dentry, DCACHE};
 
-use crate::{devices::{DeviceMajor, DEVICE_MANAGER}, drivers::BLOCK_DEVICE, sync::mutex::{SpinNoIrq, SpinNoIrqLock}};
+use crate::{drivers::block::{block_device, find_block_device, queue, SDCARD_DEV_NAME}, sync::mutex::{SpinNoIrq, SpinNoIrqLock}, syscall::SysError, sysctl::StrParam, task::manager::TASK_MANAGER};
 pub use ext4::Ext4SuperBlock;
 pub use vfs::{SuperBlock, SuperBlockInner};
 
@@ -37,6 +39,15 @@ pub use vfs::{SuperBlock, SuperBlockInner};
 pub static FS_MANAGER: SpinNoIrqLock<BTreeMap<String, Arc<dyn FSType>>> =
     SpinNoIrqLock::new(BTreeMap::new());
 
+/// the mounted file systems in mount order, with the path of each mount point
+static MOUNTS: SpinNoIrqLock<Vec<(&'static Arc<dyn FSType>, String)>> =
+    SpinNoIrqLock::new(Vec::new());
+
+/// the directories covered by the mounts made after boot by mount point,
+/// back in the tree once the mount is gone
+static COVERED: SpinNoIrqLock<BTreeMap<String, Arc<dyn Dentry>>> =
+    SpinNoIrqLock::new(BTreeMap::new());
+
 /// the default filesystem on disk
 #[cfg(not(feature = "fat32"))]
 pub const DISK_FS_NAME: &str = "ext4";
@@ -80,65 +91,270 @@ pub fn get_filesystem(name: &str) -> &'static Arc<dyn FSType> {
 }
 
 
-/// init the file system
-pub fn init() {
-    register_all_fs();
-    let sdcard_dev_name;
-    let disk_dev_name;
-    #[cfg(target_arch="riscv64")]
-    {
-        sdcard_dev_name = "sda1";
-        disk_dev_name = "sda0";
+/// remember a mount for `unmount_all`, with the flags it keeps
+fn record_mount(fs: &'static Arc<dyn FSType>, root: &Arc<dyn Dentry>, flags: MountFlags) {
+    let path = root.path();
+    if let Some(sb) = fs.get_sb(&path) {
+        sb.inner().set_mount_flags(flags);
+    }
+    MOUNTS.lock().push((fs, path));
+}
+
+/// the super block of the file system mounted at `path`
+pub fn find_mount(path: &str) -> Option<Arc<dyn SuperBlock>> {
+    MOUNTS.lock().iter().find(|(_, p)| p == path).and_then(|(fs, p)| fs.get_sb(p))
+}
+
+/// the super blocks of the mounted file systems in mount order
+pub fn mounted_sbs() -> Vec<Arc<dyn SuperBlock>> {
+    MOUNTS.lock().iter().filter_map(|(fs, path)| fs.get_sb(path)).collect()
+}
+
+/// whether some task holds a regular file of `sb` open for writing
+fn open_for_write(sb: &Arc<dyn SuperBlock>) -> bool {
+    let sb = Arc::as_ptr(sb) as *const ();
+    let mut busy = false;
+    TASK_MANAGER.for_each_task(|task| {
+        busy = busy || task.with_fd_table(|table| {
+            table.fd_table.iter().flatten().any(|info| {
+                let file = &info.file;
+                file.flags().writable() && file.inode().is_some_and(|inode| {
+                    let inner = inode.inode_inner();
+                    inner.mode().get_type() == InodeMode::FILE
+                        && inner.super_block.as_ref().is_some_and(|s| s.as_ptr() as *const () == sb)
+                })
+            })
+        });
+    });
+    busy
+}
+
+/// change the flags of the mount at `path`. going read-only writes the dirty pages back first
+/// and fails with EBUSY while a file is open for writing, going back read-write fails with
+/// EROFS only if the device itself is read-only
+pub fn remount(path: &str, flags: MountFlags) -> Result<(), SysError> {
+    let sb = find_mount(path).ok_or(SysError::EINVAL)?;
+    let was_ro = sb.inner().read_only();
+    let to_ro = flags.contains(MountFlags::MS_RDONLY);
+    if to_ro && !was_ro {
+        if open_for_write(&sb) {
+            return Err(SysError::EBUSY);
+        }
+        sync_all();
+        sb.sync()?;
+        queue::flush_all();
+    }
+    if !to_ro && sb.inner().device.as_ref().is_some_and(|dev| dev.read_only()) {
+        return Err(SysError::EROFS);
+    }
+    sb.inner().set_mount_flags(flags);
+    info!("[FS] remounted {} {}", path, flags.options());
+    Ok(())
+}
+
+/// mount a new tmpfs on the directory `target`, which it covers until it is unmounted.
+/// The file systems on a disk are mounted at boot only
+pub fn mount(fs_name: &str, target: &Arc<dyn Dentry>, flags: MountFlags) -> Result<(), SysError> {
+    if fs_name != "tmpfs" {
+        return Err(SysError::ENODEV);
+    }
+    let parent = target.parent().ok_or(SysError::EBUSY)?;
+    let path = target.path();
+    if MOUNTS.lock().iter().any(|(_, p)| *p == path) {
+        return Err(SysError::EBUSY);
+    }
+    let tmpfs = get_filesystem(fs_name);
+    let root = tmpfs.mount(target.name(), Some(parent.clone()), flags, None).ok_or(SysError::ENOMEM)?;
+    init_tmpfs(root.clone());
+    parent.add_child(root.clone());
+    record_mount(tmpfs, &root, flags);
+    COVERED.lock().insert(path.clone(), target.clone());
+    info!("[FS] mounted {} at {}", fs_name, path);
+    Ok(())
+}
+
+/// detach the file system mounted at `path` after writing it back. EBUSY while one of
+/// its files is open or another file system is mounted under it. The directory it
+/// covered is put back, or found again by the next lookup for a mount made at boot
+pub fn unmount(path: &str) -> Result<(), SysError> {
+    if path == "/" {
+        return Err(SysError::EBUSY);
+    }
+    let (fs, sb) = {
+        let mounts = MOUNTS.lock();
+        let (fs, _) = mounts.iter().find(|(_, p)| p == path).ok_or(SysError::EINVAL)?;
+        let sb = fs.get_sb(path).ok_or(SysError::EINVAL)?;
+        let under = format!("{}/", path);
+        if sb.inner().opens.total() > 0 || mounts.iter().any(|(_, p)| p.starts_with(&under)) {
+            return Err(SysError::EBUSY);
+        }
+        (*fs, sb)
+    };
+    writeback::drain_sb(&sb);
+    sb.unmount()?;
+    queue::flush_all();
+    MOUNTS.lock().retain(|(_, p)| p != path);
+    fs.inner().supers.lock().remove(path);
+    let root = sb.root();
+    DCACHE.remove_tree(&root);
+    DCACHE.remove(&root);
+    if let Some(parent) = root.parent() {
+        parent.remove_child(root.name());
+        if let Some(covered) = COVERED.lock().remove(path) {
+            parent.add_child(covered.clone());
+            DCACHE.insert(covered);
+        }
+    }
+    info!("[FS] unmounted {} at {}", fs.name(), path);
+    Ok(())
+}
+
+/// sysctl fs/open-inodes: for every mount its path, file system and open files,
+/// then the inodes with open files by number and how many each has
+pub fn open_inodes_read() -> String {
+    let mut res = String::new();
+    for (fs, path) in MOUNTS.lock().iter() {
+        let Some(sb) = fs.get_sb(path) else {
+            continue;
+        };
+        let opens = &sb.inner().opens;
+        res += &format!("{} {} {}\n", path, fs.name(), opens.total());
+        for (ino, count) in opens.list() {
+            res += &format!("\t{} {}\n", ino, count);
+        }
     }
-    #[cfg(target_arch="loongarch64")]
-    {
-        sdcard_dev_name = "sda0";
-        disk_dev_name = "sda1";
+    res
+}
+
+/// the mount id of the file system of `sb`, its place in the mount table counted from 1
+pub fn mount_id(sb: &Arc<dyn SuperBlock>) -> Option<usize> {
+    MOUNTS.lock()
+        .iter()
+        .position(|(fs, path)| fs.get_sb(path).is_some_and(|s| Arc::as_ptr(&s) as *const () == Arc::as_ptr(sb) as *const ()))
+        .map(|i| i + 1)
+}
+
+/// write back the dirty cached pages and metadata of every cached inode,
+/// and the writes still plugged in the block queues.
+/// The dirty lists are drained first, the walk of the dentries finds what is left
+pub fn sync_all() {
+    for sb in mounted_sbs() {
+        writeback::drain_sb(&sb);
     }
+    for dentry in DCACHE.dentries() {
+        let Some(inode) = dentry.inode() else {
+            continue;
+        };
+        inode.sync_cached();
+        if let Err(e) = sync_inode_meta(&inode) {
+            warn!("[FS] sync meta of {} failed: {:?}", dentry.path(), e);
+        }
+    }
+    queue::flush_all();
+    queue::dump_stats();
+}
 
-    let disk_device = DEVICE_MANAGER.lock()
-            .find_dev_by_name(disk_dev_name, DeviceMajor::Block)
-            .as_blk()
-            .unwrap();
+/// write everything back and detach the file systems in the reverse mount order,
+/// called once on shutdown
+pub fn unmount_all() {
+    sync_all();
+    let mounts = core::mem::take(&mut *MOUNTS.lock());
+    for (fs, path) in mounts.iter().rev() {
+        let Some(sb) = fs.get_sb(path) else {
+            continue;
+        };
+        match sb.unmount() {
+            Ok(()) => info!("[FS] unmounted {} at {}", fs.name(), path),
+            Err(e) => warn!("[FS] unmount {} at {} failed: {:?}", fs.name(), path, e),
+        }
+    }
+    // the file systems wrote their caches back on unmount
+    queue::flush_all();
+}
 
-    let sdcard_device = DEVICE_MANAGER.lock()
-            .find_dev_by_name(sdcard_dev_name, DeviceMajor::Block)
-            .as_blk()
-            .unwrap();
+/// mount the disk file system as the root, with the sdcard under it,
+/// None if there is no disk
+fn mount_disks() -> Option<Arc<dyn Dentry>> {
+    let disk_device = block_device()?.clone();
 
     // create the ext4 file system using the block device
     let diskfs = get_filesystem(DISK_FS_NAME);
     let diskfs_root = diskfs.mount("/", None, MountFlags::empty(), Some(disk_device)).unwrap();
+    record_mount(diskfs, &diskfs_root, MountFlags::empty());
 
+    // the sdcard is optional
+    let Some(sdcard_device) = find_block_device(SDCARD_DEV_NAME) else {
+        warn!("[FS] no block device {}, /sdcard is not mounted", SDCARD_DEV_NAME);
+        return Some(diskfs_root);
+    };
     let sdcard = get_filesystem(SDCARD_NAME);
     let sdcard_root = sdcard.mount("sdcard", Some(diskfs_root.clone()), MountFlags::empty(), Some(sdcard_device)).unwrap();
     diskfs_root.add_child(sdcard_root.clone());
+    record_mount(sdcard, &sdcard_root, MountFlags::empty());
     log::info!("[FS] insert path: {}", sdcard_root.path());
-    DCACHE.lock().insert(sdcard_root.path(), sdcard_root);
+    DCACHE.pin(sdcard_root);
+    Some(diskfs_root)
+}
+
+/// mount a tmpfs as the root and unpack the built in archive into it, no disk is touched
+fn mount_initramfs(archive: &[u8]) -> Arc<dyn Dentry> {
+    let tmpfs = get_filesystem("tmpfs");
+    let root = tmpfs.mount("/", None, MountFlags::empty(), None).unwrap();
+    init_tmpfs(root.clone());
+    record_mount(tmpfs, &root, MountFlags::empty());
+    initramfs::init(&root, archive);
+    root
+}
 
-    // mount the dev file system under diskfs
+/// init the file system
+pub fn init() {
+    register_all_fs();
+    // without a disk the archive built in is the root, whatever the command line asks for
+    let root = match initramfs::payload() {
+        Some(archive) => mount_initramfs(archive),
+        None => mount_disks().unwrap_or_else(|| {
+            warn!("[FS] no block device, booting from the initramfs");
+            mount_initramfs(initramfs::builtin().expect("no block device and no initramfs to boot from"))
+        }),
+    };
+
+    // mount the dev file system under the root
     let devfs = get_filesystem("devfs");
-    let devfs_root = devfs.mount("dev", Some(diskfs_root.clone()), MountFlags::empty(), None).unwrap();
+    let devfs_flags = MountFlags::MS_NOSUID;
+    let devfs_root = devfs.mount("dev", Some(root.clone()), devfs_flags, None).unwrap();
     init_devfs(devfs_root.clone());
-    diskfs_root.add_child(devfs_root.clone());
+    root.add_child(devfs_root.clone());
+    record_mount(devfs, &devfs_root, devfs_flags);
     log::info!("[FS] insert path: {}", devfs_root.path());
-    DCACHE.lock().insert(devfs_root.path(), devfs_root);
+    DCACHE.pin(devfs_root.clone());
 
-    // mount the proc file system under diskfs
+    // mount the proc file system under the root
     let procfs = get_filesystem("procfs");
-    let procfs_root = procfs.mount("proc", Some(diskfs_root.clone()), MountFlags::empty(), None).unwrap();
+    let procfs_flags = MountFlags::MS_NOSUID | MountFlags::MS_NODEV | MountFlags::MS_NOEXEC;
+    let procfs_root = procfs.mount("proc", Some(root.clone()), procfs_flags, None).unwrap();
     init_procfs(procfs_root.clone());
-    diskfs_root.add_child(procfs_root.clone());
+    root.add_child(procfs_root.clone());
+    record_mount(procfs, &procfs_root, procfs_flags);
     log::info!("[FS] insert path: {}", procfs_root.path());
-    DCACHE.lock().insert(procfs_root.path(), procfs_root);
+    DCACHE.pin(procfs_root);
 
-    // mount the tmp file system under diskfs
+    // mount the tmp file system under the root
     let tmpfs = get_filesystem("tmpfs");
-    let tmpfs_root = tmpfs.mount("tmp", Some(diskfs_root.clone()), MountFlags::empty(), None).unwrap();
+    let tmpfs_flags = MountFlags::MS_NOSUID | MountFlags::MS_NODEV;
+    let tmpfs_root = tmpfs.mount("tmp", Some(root.clone()), tmpfs_flags, None).unwrap();
     init_tmpfs(tmpfs_root.clone());
-    diskfs_root.add_child(tmpfs_root.clone());
+    root.add_child(tmpfs_root.clone());
+    record_mount(tmpfs, &tmpfs_root, tmpfs_flags);
     log::info!("[FS] insert path: {}", tmpfs_root.path());
-    DCACHE.lock().insert(tmpfs_root.path(), tmpfs_root);
+    DCACHE.pin(tmpfs_root);
+
+    // mount another tmp file system at /dev/shm for the POSIX shared memory
+    let shm_root = tmpfs.mount("shm", Some(devfs_root.clone()), tmpfs_flags, None).unwrap();
+    init_tmpfs(shm_root.clone());
+    devfs_root.add_child(shm_root.clone());
+    record_mount(tmpfs, &shm_root, tmpfs_flags);
+    log::info!("[FS] insert path: {}", shm_root.path());
+    DCACHE.pin(shm_root);
 
     info!("[FS] fs finish init");
 }
@@ -239,6 +455,13 @@ impl OpenFlags {
         .union(Self::O_TMPFILE)
         .union(Self::O_TRUNC);
 
+    /// the flags fcntl F_SETFL may change, the others stay as the file was opened
+    pub const SETFL_FLAGS: Self = Self::O_APPEND
+        .union(Self::O_ASYNC)
+        .union(Self::O_DIRECT)
+        .union(Self::O_NOATIME)
+        .union(Self::O_NONBLOCK);
+
     pub fn readable(&self) -> bool {
         !self.contains(Self::O_WRONLY) || self.contains(Self::O_RDWR)
     }
@@ -309,6 +532,87 @@ pub struct Kstat {
     pub st_ctime_nsec: isize,
 }
 
+impl Kstat {
+    /// the attributes of an open file with no inode behind it, like a socket:
+    /// one link, owned by root, timestamps at 0
+    pub fn anonymous(mode: InodeMode, rdev: u64, blksize: i32) -> Self {
+        Self {
+            st_dev: 0,
+            st_ino: 0,
+            st_mode: mode.bits() as _,
+            st_nlink: 1,
+            st_uid: 0,
+            st_gid: 0,
+            st_rdev: rdev,
+            _pad0: 0,
+            st_size: 0,
+            st_blksize: blksize,
+            _pad1: 0,
+            st_blocks: 0,
+            st_atime_sec: 0,
+            st_atime_nsec: 0,
+            st_mtime_sec: 0,
+            st_mtime_nsec: 0,
+            st_ctime_sec: 0,
+            st_ctime_nsec: 0,
+        }
+    }
+}
+
+impl From<&Kstat> for Xstat {
+    /// the basic stats of `stat`, as statx reports them
+    fn from(stat: &Kstat) -> Self {
+        let time = |sec: isize, nsec: isize| StatxTimestamp { tv_sec: sec as _, tv_nsec: nsec as _ };
+        Xstat {
+            stx_mask: XstatMask::STATX_BASIC_STATS.bits(),
+            stx_blksize: stat.st_blksize as _,
+            stx_attributes: 0,
+            stx_nlink: stat.st_nlink,
+            stx_uid: stat.st_uid,
+            stx_gid: stat.st_gid,
+            stx_mode: stat.st_mode as _,
+            stx_ino: stat.st_ino,
+            stx_size: stat.st_size as _,
+            stx_blocks: stat.st_blocks as _,
+            stx_attributes_mask: 0,
+            stx_atime: time(stat.st_atime_sec, stat.st_atime_nsec),
+            stx_btime: time(0, 0),
+            stx_ctime: time(stat.st_ctime_sec, stat.st_ctime_nsec),
+            stx_mtime: time(stat.st_mtime_sec, stat.st_mtime_nsec),
+            stx_rdev_major: (stat.st_rdev >> 8) as u32 & 0xfff,
+            stx_rdev_minor: stat.st_rdev as u32 & 0xff,
+            stx_dev_major: (stat.st_dev >> 8) as u32 & 0xfff,
+            stx_dev_minor: stat.st_dev as u32 & 0xff,
+            stx_mnt_id: 0,
+            stx_dio_mem_align: 0,
+            std_dio_offset_align: 0,
+            stx_subvol: 0,
+            stx_atomic_write_unit_min: 0,
+            stx_atomic_write_unit_max: 0,
+            stx_atomic_write_segments_max: 0,
+            stx_dio_read_offset_align: 0,
+        }
+    }
+}
+
+/// the longest host or domain name, the fields of UtsName keep a nul after it
+pub const HOST_NAME_MAX: usize = 64;
+/// the node name before the host is named
+const DEFAULT_HOSTNAME: &str = "Linux";
+/// the node name of uname, set by sethostname, sysctl kernel/hostname and hostname= on the command line
+pub static HOSTNAME: StrParam = StrParam::new(DEFAULT_HOSTNAME, HOST_NAME_MAX);
+/// the domain name of uname, set by setdomainname and sysctl kernel/domainname
+pub static DOMAINNAME: StrParam = StrParam::new("localhost", HOST_NAME_MAX);
+
+/// the prefix of the kernel log records naming the host, nothing until it has a name of its own
+pub fn write_log_host(w: &mut dyn core::fmt::Write) {
+    HOSTNAME.with(|name| {
+        if name != DEFAULT_HOSTNAME {
+            let _ = write!(w, "[{}] ", name);
+        }
+    });
+}
+
 // Defined in <sys/utsname.h>.
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
@@ -331,13 +635,13 @@ impl UtsName {
     pub fn default() -> Self {
         Self {
             sysname: Self::from_str("Linux"),
-            nodename: Self::from_str("Linux"),
+            nodename: Self::from_str(&HOSTNAME.get()),
             release: Self::from_str("5.19.0-42-generic"),
             version: Self::from_str(
                 "#43~22.04.1-Ubuntu SMP PREEMPT_DYNAMIC Fri Apr 21 16:51:08 UTC 2",
             ),
             machine: Self::from_str("RISC-V SiFive Freedom U740 SoC"),
-            domainname: Self::from_str("localhost"),
+            domainname: Self::from_str(&DOMAINNAME.get()),
         }
     }
 
@@ -454,18 +758,20 @@ bitflags! {
         /// Want stx_blocks
         const STATX_BLOCKS = 1 << 10;
         /// [All of the above]
-        const STATX_BASIC_STATS = 1 << 11;
+        const STATX_BASIC_STATS = 0x7ff;
         /// Want stx_btime
-        const STATX_BTIME = 1 << 12;
+        const STATX_BTIME = 1 << 11;
         /// The same as STATX_BASIC_STATS | STATX_BTIME.
         /// It is deprecated and should not be used.
         #[deprecated]
         const STATX_ALL = Self::STATX_BASIC_STATS.bits | Self::STATX_BTIME.bits;
         /// Want stx_mnt_id (since Linux 5.8)
-        const STATX_MNT_ID = 1 << 13;
+        const STATX_MNT_ID = 1 << 12;
         /// Want stx_dio_mem_align and stx_dio_offset_align.
         /// (since Linux 6.1; support varies by filesystem)
-        const STATX_DIOALIGN = 1 << 14;
+        const STATX_DIOALIGN = 1 << 13;
+        /// Want the unique stx_mnt_id (since Linux 6.8)
+        const STATX_MNT_ID_UNIQUE = 1 << 14;
         ///  Want stx_subvol
         /// (since Linux 6.10; support varies by filesystem)
         const STATX_SUBVOL = 1 << 15;
@@ -3,15 +3,34 @@
 //! (todos): 1. radix tree to manage the offset to page
 //! 2. ahead read 
 
-use core::{cmp, sync::atomic::{AtomicUsize, Ordering}};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::{fs::vfs::Inode, sync::mutex::SpinNoIrqLock};
+use crate::{fs::vfs::Inode, sync::mutex::SpinNoIrqLock, sysctl::IntParam};
 use alloc::{collections::btree_map::BTreeMap, sync::Arc};
 // use hashbrown::HashMap;
 use log::info;
 
 use super::page::{Page, PAGE_SIZE};
 
+/// sysctl vm/page-cache-limit-mb: the cached pages beyond which a read miss first
+/// gives back clean pages nobody maps, 0 for no limit
+pub static PAGE_CACHE_LIMIT_MB: IntParam = IntParam::new(0, 0, 1 << 20);
+/// the pages held by every page cache
+static CACHED_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+/// the pages held by every page cache
+pub fn cached_pages() -> usize {
+    CACHED_PAGES.load(Ordering::Relaxed)
+}
+
+/// the pages above the limit, 0 when under it or unlimited
+fn pages_over_limit() -> usize {
+    match PAGE_CACHE_LIMIT_MB.get() {
+        0 => 0,
+        limit => cached_pages().saturating_sub(limit * (1 << 20) / PAGE_SIZE),
+    }
+}
+
 pub struct PageCache {
     /// from file offset(should be page aligned)
     /// to the cached page
@@ -46,16 +65,163 @@ impl PageCache {
     /// insert the page at file offset
     pub fn insert_page(&self, offset: usize, page: Arc<Page>) {
         assert!(offset % PAGE_SIZE == 0);
-        self.pages.lock().insert(offset, page);
+        if self.pages.lock().insert(offset, page).is_none() {
+            CACHED_PAGES.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    /// while the caches are over the limit, drop the clean pages of this one which
+    /// nobody else holds. Dirty and mapped pages stay, so a cache never loses data
+    pub fn shrink(&self) {
+        let mut over = pages_over_limit();
+        if over == 0 {
+            return;
+        }
+        self.pages.lock().retain(|_, page| {
+            if over == 0 || page.is_dirty() || Arc::strong_count(page) > 1 {
+                return true;
+            }
+            over -= 1;
+            CACHED_PAGES.fetch_sub(1, Ordering::Relaxed);
+            false
+        });
     }
     pub fn update_end(&self, offset: usize) {
-        let end = self.end.load(Ordering::Acquire);
-        // log::info!("updated end ({:#x} {:#x}) => {:#x}", end, offset, cmp::max(end, offset));
-        self.end.store(cmp::max(end, offset), Ordering::Release);
+        self.end.fetch_max(offset, Ordering::AcqRel);
     }
     pub fn end(&self) -> usize {
         self.end.load(Ordering::Acquire)
     }
+    /// drop the cached pages beyond `size` and zero the tail of the page holding it,
+    /// so a later extension reads zeros instead of the stale data.
+    /// The dropped pages are zeroed too, as a mapping may still hold them
+    pub fn truncate(&self, size: usize) {
+        const ZEROS: [u8; PAGE_SIZE] = [0; PAGE_SIZE];
+        let mut pages = self.pages.lock();
+        let boundary = size / PAGE_SIZE * PAGE_SIZE;
+        let mut dropped = pages.split_off(&(boundary + PAGE_SIZE));
+        if size % PAGE_SIZE != 0 {
+            if let Some(page) = pages.get(&boundary) {
+                page.write_at(size % PAGE_SIZE, &ZEROS);
+                page.set_dirty();
+            }
+        } else if let Some(page) = pages.remove(&boundary) {
+            dropped.insert(boundary, page);
+        }
+        for page in dropped.values() {
+            page.write_at(0, &ZEROS);
+        }
+        CACHED_PAGES.fetch_sub(dropped.len(), Ordering::Relaxed);
+        self.end.store(size, Ordering::Release);
+    }
+    /// the page at `offset` of `inode`, which is read in on a miss.
+    /// `size` is the size of the file on disk, None past the end of the file
+    pub fn read_page(&self, inode: Arc<dyn Inode>, size: usize, offset: usize) -> Option<Arc<Page>> {
+        let offset = offset / PAGE_SIZE * PAGE_SIZE;
+        if offset >= size.max(self.end()) {
+            return None;
+        }
+        if let Some(page) = self.get_page(offset) {
+            return Some(page);
+        }
+        self.shrink();
+        let mut page = Page::new(offset);
+        if offset < size {
+            let read_size = Arc::get_mut(&mut page).unwrap().read_from(inode, offset);
+            self.update_end(offset + read_size);
+        }
+        self.insert_page(offset, page.clone());
+        Some(page)
+    }
+    /// read at `offset` of `inode` through the cache, no further than the end of the file
+    pub fn read(&self, inode: Arc<dyn Inode>, size: usize, offset: usize, buf: &mut [u8]) -> usize {
+        let mut read = 0;
+        while read < buf.len() {
+            let current = offset + read;
+            let end = size.max(self.end());
+            if current >= end {
+                break;
+            }
+            let Some(page) = self.read_page(inode.clone(), size, current) else {
+                break;
+            };
+            let len = (end - current).min(buf.len() - read);
+            let page_read = page.read_at(current % PAGE_SIZE, &mut buf[read..read + len]);
+            if page_read == 0 {
+                break;
+            }
+            read += page_read;
+        }
+        read
+    }
+    /// write at `offset` of `inode` into the cache, the dirty pages are written back on sync.
+    /// Returns the bytes written and the pages which were clean before
+    pub fn write(&self, inode: Arc<dyn Inode>, size: usize, offset: usize, buf: &[u8]) -> (usize, usize) {
+        let mut written = 0;
+        let mut dirtied = 0;
+        while written < buf.len() {
+            let current = offset + written;
+            let page_offset = current / PAGE_SIZE * PAGE_SIZE;
+            let page = self.get_page(page_offset).unwrap_or_else(|| {
+                let mut page = Page::new(page_offset);
+                if page_offset < size {
+                    // a write inside the file keeps the rest of the page
+                    let _ = Arc::get_mut(&mut page).unwrap().read_from(inode.clone(), page_offset);
+                }
+                self.insert_page(page_offset, page.clone());
+                page
+            });
+            let page_written = page.write_at(current % PAGE_SIZE, &buf[written..]);
+            if page.set_dirty() {
+                dirtied += 1;
+            }
+            written += page_written;
+            self.update_end(offset + written);
+        }
+        (written, dirtied)
+    }
+    /// write the dirty pages back to `inode`, no further than the end of the cached data
+    pub fn sync(&self, inode: &dyn Inode) -> Result<(), i32> {
+        let pages = self.pages.lock();
+        for (&offset, page) in pages.iter() {
+            if !page.is_dirty() {
+                continue;
+            }
+            let len = self.end().saturating_sub(offset).min(PAGE_SIZE);
+            inode.write_at(offset, &page.get_slice::<u8>()[..len])?;
+            page.set_clean();
+        }
+        Ok(())
+    }
+    /// write back to `inode` the pages dirtied before `before` ms, at most `budget` of them,
+    /// which is lowered by the pages written. Ok(true) if dirty pages are left.
+    /// A page is clean before it is written, so a write racing with it dirties it again
+    pub fn write_back_expired(&self, inode: &dyn Inode, before: usize, budget: &mut usize) -> Result<bool, i32> {
+        let pages = self.pages.lock();
+        let mut left = false;
+        for (&offset, page) in pages.iter() {
+            if !page.is_dirty() {
+                continue;
+            }
+            if *budget == 0 || page.dirtied_at() >= before {
+                left = true;
+                continue;
+            }
+            let len = self.end().saturating_sub(offset).min(PAGE_SIZE);
+            page.set_clean();
+            if let Err(e) = inode.write_at(offset, &page.get_slice::<u8>()[..len]) {
+                page.set_dirty();
+                return Err(e);
+            }
+            *budget -= 1;
+        }
+        Ok(left)
+    }
+    /// forget the dirty state of every page, the file is gone
+    pub fn clean(&self) {
+        for page in self.pages.lock().values() {
+            page.set_clean();
+        }
+    }
     /// flush all dirty pages
     pub fn flush(&self, inode: Arc<dyn Inode>) {
         info!("start to flush all pages");
@@ -67,4 +233,10 @@ impl PageCache {
             inode.write_at(offset, page.get_slice::<u8>()).expect("[PageCache]: failed at flush");
         }
     }
-}
\ No newline at end of file
+}
+
+impl Drop for PageCache {
+    fn drop(&mut self) {
+        CACHED_PAGES.fetch_sub(self.pages.lock().len(), Ordering::Relaxed);
+    }
+}
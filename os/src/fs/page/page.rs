@@ -3,13 +3,15 @@
 use core::{cmp, sync::atomic::{AtomicBool, AtomicUsize, Ordering}};
 
 use alloc::{alloc::Global, sync::{Arc, Weak}};
-use hal::{addr::{PhysPageNum, RangePPNHal}, allocator::{FrameAllocatorHal, FrameAllocatorTrackerExt}, constant::{Constant, ConstantsHal}, util::smart_point::StrongArc};
+use hal::{addr::{PhysPageNum, RangePPNHal}, allocator::{FrameAllocatorHal, FrameAllocatorTrackerExt}, constant::{Constant, ConstantsHal}, util::smart_point::{ArcTag, StrongArc}};
 
-use crate::{fs::vfs::Inode, mm::{allocator::{frames_alloc, FrameAllocator}, FrameTracker}, sync::mutex::SpinNoIrqLock};
+use crate::{fs::vfs::Inode, mm::{allocator::{frames_alloc, FrameAllocator}, FrameTracker}, sync::mutex::SpinNoIrqLock, timer::get_current_time_ms};
 
 pub struct Page {
     /// page frame state or attribute
     pub is_dirty: AtomicBool,
+    /// when the page last went from clean to dirty, in ms
+    dirtied_at: AtomicUsize,
     /// offset in a file (if is owned by file)
     pub index: usize, 
     /// the physical frame it owns
@@ -28,8 +30,9 @@ impl Page {
         frame.range_ppn.get_slice_mut::<u8>().fill(0);
         Arc::new(Self {
             is_dirty: AtomicBool::new(false), // need more flags
+            dirtied_at: AtomicUsize::new(0),
             index,
-            frame: StrongArc::new(frame),
+            frame: StrongArc::new_tagged(frame, ArcTag::PageCache),
         })
     }
     /// return the mutable slice of the raw data the page points to
@@ -87,9 +90,13 @@ impl Page {
         // no need to care about the EOF, write_at will handle this
         write_size
     }
-    /// set the page dirty
-    pub fn set_dirty(&self) {
-        self.is_dirty.store(true, Ordering::Release);
+    /// set the page dirty, true if it was clean
+    pub fn set_dirty(&self) -> bool {
+        let newly = !self.is_dirty.swap(true, Ordering::AcqRel);
+        if newly {
+            self.dirtied_at.store(get_current_time_ms(), Ordering::Release);
+        }
+        newly
     }
     /// set the page clean
     pub fn set_clean(&self) {
@@ -99,4 +106,8 @@ impl Page {
     pub fn is_dirty(&self) -> bool {
         self.is_dirty.load(Ordering::Acquire)
     }
+    /// when the page went dirty, in ms, only meaningful while it is
+    pub fn dirtied_at(&self) -> usize {
+        self.dirtied_at.load(Ordering::Acquire)
+    }
 }
\ No newline at end of file
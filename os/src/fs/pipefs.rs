@@ -1,29 +1,37 @@
 //! pipe file system
 //! adapt from phoenix
 
-use core::{future::Future, pin::Pin, task::{Context, Poll, Waker}};
+use core::{future::Future, pin::Pin, task::{Context, Poll}};
 
-use alloc::{collections::vec_deque::VecDeque, string::ToString, sync::Arc};
+use alloc::{string::ToString, sync::Arc};
 use alloc::boxed::Box;
 use async_trait::async_trait;
 
-use crate::{fs::StatxTimestamp, sync::mutex::SpinNoIrqLock, syscall::SysError, utils::{get_waker, RingBuffer}};
+use hal::constant::{Constant, ConstantsHal};
 
-use super::{vfs::{file::PollEvents, inode::InodeMode, Dentry, DentryInner, File, FileInner, Inode, InodeInner}, Kstat, OpenFlags, Xstat, XstatMask};
+use crate::{fs::StatxTimestamp, sync::{mutex::SpinNoIrqLock, WaitEntry, WaitQueue}, syscall::{SysError, SysResult}, sysctl::IntParam, task::{current_task, signal::interruptible}, utils::{get_waker, RingBuffer}};
 
+use super::{vfs::{file::PollEvents, ioctl, inode::InodeMode, Dentry, DentryInner, FasyncList, File, FileCount, FileInner, FileOwner, Inode, InodeInner, OpenRef}, Kstat, OpenFlags, Xstat, XstatMask};
 
 
+/// sysctl fs/pipe-max-size: the largest F_SETPIPE_SZ an unprivileged task may ask for
+pub static PIPE_MAX_SIZE: IntParam = IntParam::new(1 << 20, Constant::PAGE_SIZE, 1 << 30);
+
 pub struct PipeInode {
     inner: InodeInner,
-    pipe_meta: SpinNoIrqLock<PipeMeta>
+    pipe_meta: SpinNoIrqLock<PipeMeta>,
+    /// the readers waiting for data, registered under the meta lock
+    read_queue: WaitQueue,
+    /// the writers waiting for room, registered under the meta lock
+    write_queue: WaitQueue,
+    /// the read ends with O_ASYNC on, signalled when the pipe turns readable
+    fasync: FasyncList,
 }
 
 pub struct PipeMeta {
     is_write_closed: bool,
     is_read_closed: bool,
     ring_buffer: RingBuffer,
-    read_waker: VecDeque<Waker>,
-    write_waker: VecDeque<Waker>,
 }
 
 impl PipeInode {
@@ -33,10 +41,15 @@ impl PipeInode {
             is_write_closed: false,
             is_read_closed: false,
             ring_buffer: RingBuffer::new(len),
-            read_waker: VecDeque::new(),
-            write_waker: VecDeque::new(),
         });
-        Arc::new(Self { inner, pipe_meta })
+        Arc::new(Self { inner, pipe_meta, read_queue: WaitQueue::new(), write_queue: WaitQueue::new(), fasync: FasyncList::new() })
+    }
+}
+
+impl PipeInode {
+    /// the bytes written and not read yet, the size fstat reports
+    fn buffered(&self) -> usize {
+        self.pipe_meta.lock().ring_buffer.len()
     }
 }
 
@@ -50,15 +63,15 @@ impl Inode for PipeInode {
         Kstat {
             st_dev: 0,
             st_ino: inner.ino as u64,
-            st_mode: inner.mode.bits() as _,
+            st_mode: inner.mode().bits() as _,
             st_nlink: inner.nlink() as u32,
             st_uid: 0,
             st_gid: 0,
             st_rdev: 0,
             _pad0: 0,
-            st_size: inner.size() as _,
+            st_size: self.buffered() as _,
             _pad1: 0,
-            st_blksize: 0,
+            st_blksize: Constant::PAGE_SIZE as _,
             st_blocks: 0,
             st_atime_sec: inner.atime().tv_sec as _,
             st_atime_nsec: inner.atime().tv_nsec as _,
@@ -69,29 +82,29 @@ impl Inode for PipeInode {
         }
     }
 
-    fn getxattr(&self, mask: XstatMask) -> Xstat {
+    fn getxattr(&self, _mask: XstatMask) -> Xstat {
         const SUPPORTED_MASK: XstatMask = XstatMask::from_bits_truncate({
             XstatMask::STATX_BLOCKS.bits |
             XstatMask::STATX_ATIME.bits |
             XstatMask::STATX_CTIME.bits |
             XstatMask::STATX_MTIME.bits |
             XstatMask::STATX_NLINK.bits |
+            XstatMask::STATX_TYPE.bits |
             XstatMask::STATX_MODE.bits |
             XstatMask::STATX_SIZE.bits |
             XstatMask::STATX_INO.bits
         });
-        let mask = mask & SUPPORTED_MASK;
         let inner = self.inode_inner();
         Xstat {
-            stx_mask: mask.bits,
-            stx_blksize: 0,
+            stx_mask: SUPPORTED_MASK.bits,
+            stx_blksize: Constant::PAGE_SIZE as _,
             stx_attributes: 0,
             stx_nlink: inner.nlink() as u32,
             stx_uid: 0,
             stx_gid: 0,
-            stx_mode: inner.mode.bits() as _,
+            stx_mode: inner.mode().bits() as _,
             stx_ino: inner.ino as u64,
-            stx_size: inner.size() as _,
+            stx_size: self.buffered() as _,
             stx_blocks: 0,
             stx_attributes_mask: 0,
             stx_atime: StatxTimestamp {
@@ -128,12 +141,13 @@ impl Inode for PipeInode {
 
 pub struct PipeWriteFuture {
     events: PollEvents,
-    pipe: Arc<PipeInode>
+    pipe: Arc<PipeInode>,
+    entry: WaitEntry,
 }
 
 impl PipeWriteFuture {
     pub fn new(pipe: Arc<PipeInode>, events: PollEvents) -> Self {
-        Self { pipe, events }
+        Self { pipe, events, entry: WaitEntry::new() }
     }
 }
 
@@ -141,30 +155,37 @@ impl Future for PipeWriteFuture {
     type Output = PollEvents;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut meta = self.pipe.pipe_meta.lock();
+        let this = self.get_mut();
+        let meta = this.pipe.pipe_meta.lock();
         let mut res = PollEvents::empty();
         if meta.is_read_closed {
             res |= PollEvents::ERR;
-            return Poll::Ready(res);
-        }
-        if self.events.contains(PollEvents::OUT) && !meta.ring_buffer.is_full() {
+        } else if this.events.contains(PollEvents::OUT) && !meta.ring_buffer.is_full() {
             res |= PollEvents::OUT;
-            Poll::Ready(res)
         } else {
-            meta.write_waker.push_back(cx.waker().clone());
-            Poll::Pending
+            this.pipe.write_queue.wait(&mut this.entry, cx.waker());
+            return Poll::Pending;
         }
+        this.pipe.write_queue.finish(&mut this.entry);
+        Poll::Ready(res)
+    }
+}
+
+impl Drop for PipeWriteFuture {
+    fn drop(&mut self) {
+        self.pipe.write_queue.cancel(&mut self.entry);
     }
 }
 
 pub struct PipeReadFuture {
     events: PollEvents,
     pipe: Arc<PipeInode>,
+    entry: WaitEntry,
 }
 
 impl PipeReadFuture {
     fn new(pipe: Arc<PipeInode>, events: PollEvents) -> Self {
-        Self { pipe, events }
+        Self { pipe, events, entry: WaitEntry::new() }
     }
 }
 
@@ -172,19 +193,25 @@ impl Future for PipeReadFuture {
     type Output = PollEvents;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut meta = self.pipe.pipe_meta.lock();
+        let this = self.get_mut();
+        let meta = this.pipe.pipe_meta.lock();
         let mut res = PollEvents::empty();
-        if self.events.contains(PollEvents::IN) && !meta.ring_buffer.is_empty() {
+        if this.events.contains(PollEvents::IN) && !meta.ring_buffer.is_empty() {
             res |= PollEvents::IN;
-            Poll::Ready(res)
+        } else if meta.is_write_closed {
+            res |= PollEvents::HUP;
         } else {
-            if meta.is_write_closed {
-                res |= PollEvents::HUP;
-                return Poll::Ready(res);
-            }
-            meta.read_waker.push_back(cx.waker().clone());
-            Poll::Pending
+            this.pipe.read_queue.wait(&mut this.entry, cx.waker());
+            return Poll::Pending;
         }
+        this.pipe.read_queue.finish(&mut this.entry);
+        Poll::Ready(res)
+    }
+}
+
+impl Drop for PipeReadFuture {
+    fn drop(&mut self) {
+        self.pipe.read_queue.cancel(&mut self.entry);
     }
 }
 
@@ -198,8 +225,11 @@ impl PipeFile {
     fn new(dentry: Arc<dyn Dentry>, is_reader: bool, pipe: Arc<PipeInode>) -> Arc<Self> {
         let inner = FileInner {
             offset: 0.into(),
+            open: OpenRef::new(&dentry),
             dentry: dentry,
             flags: SpinNoIrqLock::new(OpenFlags::empty()),
+            count: FileCount::new(),
+            owner: FileOwner::new(),
         };
         Arc::new(Self {
             pipe,
@@ -209,6 +239,29 @@ impl PipeFile {
     }
 }
 
+impl PipeFile {
+    /// F_GETPIPE_SZ: the capacity of the pipe
+    pub fn pipe_size(&self) -> usize {
+        self.pipe.pipe_meta.lock().ring_buffer.capacity()
+    }
+
+    /// F_SETPIPE_SZ: resize the pipe to at least `size` bytes, a power of two pages,
+    /// EPERM beyond PIPE_MAX_SIZE unless privileged and EBUSY when the buffered data does not fit
+    pub fn set_pipe_size(&self, size: usize) -> Result<usize, SysError> {
+        let size = size.max(Constant::PAGE_SIZE).checked_next_power_of_two().ok_or(SysError::EINVAL)?;
+        if size > PIPE_MAX_SIZE.get() && !current_task().unwrap().with_cred(|c| c.is_privileged()) {
+            return Err(SysError::EPERM);
+        }
+        let mut meta = self.pipe.pipe_meta.lock();
+        if !meta.ring_buffer.resize(size) {
+            return Err(SysError::EBUSY);
+        }
+        // a larger pipe has room for blocked writers
+        self.pipe.write_queue.wake_all();
+        Ok(size)
+    }
+}
+
 #[async_trait]
 impl File for PipeFile {
     fn file_inner(&self) ->  &FileInner {
@@ -223,6 +276,10 @@ impl File for PipeFile {
         !self.operate
     }
 
+    fn fasync_list(&self) -> Option<&FasyncList> {
+        self.operate.then_some(&self.pipe.fasync)
+    }
+
     /// override the inode, some test will need pipe inode
     fn inode(&self) -> Option<Arc<dyn Inode>> {
         Some(self.pipe.clone())
@@ -232,7 +289,8 @@ impl File for PipeFile {
         assert!(self.operate == true);
         let pipe = self.pipe.clone();
         let events = PollEvents::IN;
-        let revents = PipeReadFuture::new(pipe.clone(), events).await;
+        let task = current_task().unwrap().clone();
+        let revents = interruptible(&task, PipeReadFuture::new(pipe.clone(), events)).await?;
         if revents.contains(PollEvents::HUP) {
             return Ok(0);
         }
@@ -241,34 +299,57 @@ impl File for PipeFile {
 
         // log::info!("reading into buf ptr: {:p}", buf.as_ptr());
         let len = meta.ring_buffer.read(buf);
-        if let Some(waker) = meta.write_waker.pop_front() {
-            waker.wake();
+        // what this reader left is for the next one
+        if !meta.ring_buffer.is_empty() {
+            pipe.read_queue.wake_one();
         }
+        drop(meta);
+        pipe.write_queue.wake_one();
         return Ok(len);
     }
 
     async fn write(&self, buf: &[u8]) -> Result<usize, SysError> {
         assert!(self.operate == false);
         let pipe = self.pipe.clone();
-        let revents = PipeWriteFuture::new(pipe.clone(), PollEvents::OUT).await;
+        let task = current_task().unwrap().clone();
+        let revents = interruptible(&task, PipeWriteFuture::new(pipe.clone(), PollEvents::OUT)).await?;
         if revents.contains(PollEvents::ERR) {
             return Err(SysError::EPIPE);
         }
         assert!(revents.contains(PollEvents::OUT));
         let mut meta = pipe.pipe_meta.lock();
+        let was_empty = meta.ring_buffer.is_empty();
         let len = meta.ring_buffer.write(buf);
-        if let Some(waker) = meta.read_waker.pop_front() {
-            waker.wake();
+        // the room this writer left is for the next one
+        if !meta.ring_buffer.is_full() {
+            pipe.write_queue.wake_one();
+        }
+        drop(meta);
+        pipe.read_queue.wake_one();
+        // one SIGIO when the pipe turns readable, not one per write
+        if was_empty && len > 0 {
+            pipe.fasync.notify();
         }
         return Ok(len);
     }
 
-    async fn base_poll(&self, events: PollEvents) -> PollEvents {
+    /// FIONREAD, on either end
+    fn ioctl(&self, cmd: usize, arg: usize) -> SysResult {
+        match cmd {
+            ioctl::FIONREAD => {
+                let len = self.pipe.pipe_meta.lock().ring_buffer.len();
+                ioctl::write_arg(arg, len as i32)
+            }
+            _ => Err(SysError::ENOTTY),
+        }
+    }
+
+    async fn poll(&self, events: PollEvents) -> PollEvents {
         if self.operate == false {
             // writer
             let waker = get_waker().await;
             let pipe = self.pipe.clone();
-            let mut meta = pipe.pipe_meta.lock();
+            let meta = pipe.pipe_meta.lock();
             let mut res = PollEvents::empty();
             if meta.is_read_closed {
                 res |= PollEvents::ERR;
@@ -276,14 +357,14 @@ impl File for PipeFile {
             if events.contains(PollEvents::OUT) && !meta.ring_buffer.is_full() {
                 res |= PollEvents::OUT;
             } else {
-                meta.write_waker.push_back(waker);
+                pipe.write_queue.register(&waker);
             }
             res
         } else {
             // reader
             let pipe = self.pipe.clone();
             let waker = get_waker().await;
-            let mut meta = pipe.pipe_meta.lock();
+            let meta = pipe.pipe_meta.lock();
             let mut res = PollEvents::empty();
             if meta.is_write_closed {
                 res |= PollEvents::HUP;
@@ -291,7 +372,7 @@ impl File for PipeFile {
             if events.contains(PollEvents::IN) && !meta.ring_buffer.is_empty() {
                 res |= PollEvents::IN;
             } else {
-                meta.read_waker.push_back(waker);
+                pipe.read_queue.register(&waker);
             }
             res
         }
@@ -302,18 +383,12 @@ impl Drop for PipeFile {
     fn drop(&mut self) {
         if self.operate == true {
             let pipe = self.pipe.clone();
-            let mut meta = pipe.pipe_meta.lock();
-            meta.is_read_closed = true;
-            while let Some(waker) = meta.write_waker.pop_front() {
-                waker.wake();
-            }
+            pipe.pipe_meta.lock().is_read_closed = true;
+            pipe.write_queue.wake_all();
         } else {
             let pipe = self.pipe.clone();
-            let mut meta = pipe.pipe_meta.lock();
-            meta.is_write_closed = true;
-            while let Some(waker) = meta.read_waker.pop_front() {
-                waker.wake();
-            }
+            pipe.pipe_meta.lock().is_write_closed = true;
+            pipe.read_queue.wake_all();
         }
     }
 }
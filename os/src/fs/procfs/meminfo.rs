@@ -5,7 +5,7 @@ use alloc::sync::{Arc, Weak};
 use async_trait::async_trait;
 use alloc::boxed::Box;
 
-use crate::{config::BLOCK_SIZE, fs::{vfs::{inode::InodeMode, Dentry, DentryInner, File, FileInner, Inode, InodeInner}, Kstat, OpenFlags, StatxTimestamp, SuperBlock, Xstat, XstatMask}, syscall::SysError};
+use crate::{config::BLOCK_SIZE, fs::{vfs::{inode::InodeMode, Dentry, DentryInner, File, FileCount, FileInner, FileOwner, Inode, InodeInner, OpenRef}, Kstat, OpenFlags, StatxTimestamp, SuperBlock, Xstat, XstatMask}, syscall::SysError};
 
 use alloc::string::{String, ToString};
 
@@ -63,6 +63,9 @@ impl MemInfo {
         let free_swap = "SwapFree:\t".to_string() + self.free_swap.to_string().as_str() + end;
         let shmem = "Shmem:\t".to_string() + self.shmem.to_string().as_str() + end;
         let slab = "Slab:\t".to_string() + self.slab.to_string().as_str() + end;
+        let heap = crate::mm::allocator::heap_stats();
+        let kernel_heap = "KernelHeap:\t".to_string() + (heap.allocated / 1024).to_string().as_str() + end;
+        let kernel_heap_peak = "KernelHeapPeak:\t".to_string() + (heap.peak / 1024).to_string().as_str() + end;
         res += total_mem.as_str();
         res += free_mem.as_str();
         res += avail_mem.as_str();
@@ -73,6 +76,8 @@ impl MemInfo {
         res += free_swap.as_str();
         res += shmem.as_str();
         res += slab.as_str();
+        res += kernel_heap.as_str();
+        res += kernel_heap_peak.as_str();
         res
     }
 }
@@ -85,8 +90,11 @@ impl MemInfoFile {
     pub fn new(dentry: Arc<dyn Dentry>) -> Arc<Self> {
         let inner = FileInner {
             offset: 0.into(),
+            open: OpenRef::new(&dentry),
             dentry,
             flags: SpinNoIrqLock::new(OpenFlags::empty()),
+            count: FileCount::new(),
+            owner: FileOwner::new(),
         };
         Arc::new(Self { inner })
     }
@@ -182,7 +190,7 @@ impl Inode for MemInfoInode {
         Kstat {
             st_dev: 0,
             st_ino: inner.ino as u64,
-            st_mode: inner.mode.bits() as _,
+            st_mode: inner.mode().bits() as _,
             st_nlink: inner.nlink() as u32,
             st_uid: 0,
             st_gid: 0,
@@ -201,27 +209,27 @@ impl Inode for MemInfoInode {
         }
     }
 
-    fn getxattr(&self, mask: crate::fs::XstatMask) -> crate::fs::Xstat {
+    fn getxattr(&self, _mask: crate::fs::XstatMask) -> crate::fs::Xstat {
         const SUPPORTED_MASK: XstatMask = XstatMask::from_bits_truncate({
             XstatMask::STATX_BLOCKS.bits |
             XstatMask::STATX_ATIME.bits |
             XstatMask::STATX_CTIME.bits |
             XstatMask::STATX_MTIME.bits |
             XstatMask::STATX_NLINK.bits |
+            XstatMask::STATX_TYPE.bits |
             XstatMask::STATX_MODE.bits |
             XstatMask::STATX_SIZE.bits |
             XstatMask::STATX_INO.bits
         });
-        let mask = mask & SUPPORTED_MASK;
         let inner = self.inode_inner();
         Xstat {
-            stx_mask: mask.bits,
+            stx_mask: SUPPORTED_MASK.bits,
             stx_blksize: 0,
             stx_attributes: 0,
             stx_nlink: inner.nlink() as u32,
             stx_uid: 0,
             stx_gid: 0,
-            stx_mode: inner.mode.bits() as _,
+            stx_mode: inner.mode().bits() as _,
             stx_ino: inner.ino as u64,
             stx_size: inner.size() as _,
             stx_blocks: 0,
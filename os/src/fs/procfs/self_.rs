@@ -1,8 +1,9 @@
 //! /proc/self
 
-use alloc::{string::String, sync::{Arc, Weak}};
+use alloc::{boxed::Box, string::String, sync::{Arc, Weak}};
+use async_trait::async_trait;
 
-use crate::{fs::{simplefs::file::SpFile, vfs::{inode::InodeMode, Dentry, DentryInner, File, Inode, InodeInner}, Kstat, OpenFlags, StatxTimestamp, SuperBlock, Xstat, XstatMask}, syscall::SysError, task::current_task};
+use crate::{fs::{simplefs::file::SpFile, vfs::{inode::InodeMode, Dentry, DentryInner, File, FileCount, FileInner, FileOwner, Inode, InodeInner, OpenRef}, Kstat, OpenFlags, StatxTimestamp, SuperBlock, Xstat, XstatMask}, sync::mutex::SpinNoIrqLock, syscall::{prctl::{comm_from_bytes, TASK_COMM_LEN}, SysError}, task::current_task};
 
 /// exe dentry
 pub struct ExeDentry {
@@ -64,7 +65,7 @@ impl Inode for ExeInode {
         Kstat {
             st_dev: 0,
             st_ino: inner.ino as u64,
-            st_mode: inner.mode.bits() as _,
+            st_mode: inner.mode().bits() as _,
             st_nlink: inner.nlink() as u32,
             st_uid: 0,
             st_gid: 0,
@@ -83,27 +84,27 @@ impl Inode for ExeInode {
         }
     }
 
-    fn getxattr(&self, mask: crate::fs::XstatMask) -> crate::fs::Xstat {
+    fn getxattr(&self, _mask: crate::fs::XstatMask) -> crate::fs::Xstat {
         const SUPPORTED_MASK: XstatMask = XstatMask::from_bits_truncate({
             XstatMask::STATX_BLOCKS.bits |
             XstatMask::STATX_ATIME.bits |
             XstatMask::STATX_CTIME.bits |
             XstatMask::STATX_MTIME.bits |
             XstatMask::STATX_NLINK.bits |
+            XstatMask::STATX_TYPE.bits |
             XstatMask::STATX_MODE.bits |
             XstatMask::STATX_SIZE.bits |
             XstatMask::STATX_INO.bits
         });
-        let mask = mask & SUPPORTED_MASK;
         let inner = self.inode_inner();
         Xstat {
-            stx_mask: mask.bits,
+            stx_mask: SUPPORTED_MASK.bits,
             stx_blksize: 0,
             stx_attributes: 0,
             stx_nlink: inner.nlink() as u32,
             stx_uid: 0,
             stx_gid: 0,
-            stx_mode: inner.mode.bits() as _,
+            stx_mode: inner.mode().bits() as _,
             stx_ino: inner.ino as u64,
             stx_size: inner.size() as _,
             stx_blocks: 0,
@@ -142,4 +143,194 @@ impl Inode for ExeInode {
     fn readlink(&self) -> Result<String, SysError> {
         return Ok(current_task().unwrap().elf.lock().clone().ok_or(SysError::ENFILE)?.dentry().ok_or(SysError::ENOENT)?.path());
     }
-}
\ No newline at end of file
+}
+
+/// /proc/self/comm: the name of the reading thread, which it may also write
+pub struct CommFile {
+    inner: FileInner,
+}
+
+impl CommFile {
+    pub fn new(dentry: Arc<dyn Dentry>) -> Arc<Self> {
+        let inner = FileInner {
+            offset: 0.into(),
+            open: OpenRef::new(&dentry),
+            dentry,
+            flags: SpinNoIrqLock::new(OpenFlags::empty()),
+            count: FileCount::new(),
+            owner: FileOwner::new(),
+        };
+        Arc::new(Self { inner })
+    }
+}
+
+#[async_trait]
+impl File for CommFile {
+    fn file_inner(&self) ->  &FileInner {
+        &self.inner
+    }
+
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    async fn read(&self, buf: &mut [u8]) -> Result<usize, SysError> {
+        let mut comm = current_task().unwrap().comm.lock().clone();
+        comm.push('\n');
+        let pos = self.pos();
+        if pos >= comm.len() {
+            return Ok(0);
+        }
+        let len = buf.len().min(comm.len() - pos);
+        buf[..len].copy_from_slice(&comm.as_bytes()[pos..pos + len]);
+        self.set_pos(pos + len);
+        Ok(len)
+    }
+
+    async fn write(&self, buf: &[u8]) -> Result<usize, SysError> {
+        // like PR_SET_NAME, without the trailing newline echo leaves
+        let name = &buf[..buf.len().min(TASK_COMM_LEN)];
+        let name = name.strip_suffix(b"\n").unwrap_or(name);
+        *current_task().unwrap().comm.lock() = comm_from_bytes(name);
+        Ok(buf.len())
+    }
+}
+
+/// comm dentry
+pub struct CommDentry {
+    inner: DentryInner
+}
+
+impl CommDentry {
+    pub fn new(parent: Option<Arc<dyn Dentry>>) -> Arc<Self> {
+        Arc::new(Self {
+            inner: DentryInner::new("comm", parent),
+        })
+    }
+}
+
+unsafe impl Send for CommDentry {}
+unsafe impl Sync for CommDentry {}
+
+impl Dentry for CommDentry {
+    fn dentry_inner(&self) -> &DentryInner {
+        &self.inner
+    }
+
+    fn new(
+            &self,
+            name: &str,
+            parent: Option<Arc<dyn Dentry>>,
+        ) -> Arc<dyn Dentry> {
+        Arc::new(Self {
+            inner: DentryInner::new(name, parent)
+        })
+    }
+
+    fn open(self: Arc<Self>, _flags: OpenFlags) -> Option<Arc<dyn File>> {
+        Some(CommFile::new(self.clone()))
+    }
+}
+
+/// comm inode
+pub struct CommInode {
+    inner: InodeInner,
+}
+
+impl CommInode {
+    pub fn new(super_block: Weak<dyn SuperBlock>) -> Arc<Self> {
+        // the size is that of the longest name with its newline, the actual one depends on the reader
+        let inner = InodeInner::new(Some(super_block), InodeMode::FILE, TASK_COMM_LEN);
+        Arc::new(Self { inner })
+    }
+}
+
+impl Inode for CommInode {
+    fn inode_inner(&self) -> &InodeInner {
+        &self.inner
+    }
+
+    fn getattr(&self) -> crate::fs::Kstat {
+        let inner = self.inode_inner();
+        Kstat {
+            st_dev: 0,
+            st_ino: inner.ino as u64,
+            st_mode: inner.mode().bits() as _,
+            st_nlink: inner.nlink() as u32,
+            st_uid: 0,
+            st_gid: 0,
+            st_rdev: 0,
+            _pad0: 0,
+            st_size: inner.size() as _,
+            _pad1: 0,
+            st_blksize: 0,
+            st_blocks: 0,
+            st_atime_sec: inner.atime().tv_sec as _,
+            st_atime_nsec: inner.atime().tv_nsec as _,
+            st_mtime_sec: inner.mtime().tv_sec as _,
+            st_mtime_nsec: inner.mtime().tv_nsec as _,
+            st_ctime_sec: inner.ctime().tv_sec as _,
+            st_ctime_nsec: inner.ctime().tv_nsec as _,
+        }
+    }
+
+    fn getxattr(&self, _mask: crate::fs::XstatMask) -> crate::fs::Xstat {
+        const SUPPORTED_MASK: XstatMask = XstatMask::from_bits_truncate({
+            XstatMask::STATX_BLOCKS.bits |
+            XstatMask::STATX_ATIME.bits |
+            XstatMask::STATX_CTIME.bits |
+            XstatMask::STATX_MTIME.bits |
+            XstatMask::STATX_NLINK.bits |
+            XstatMask::STATX_TYPE.bits |
+            XstatMask::STATX_MODE.bits |
+            XstatMask::STATX_SIZE.bits |
+            XstatMask::STATX_INO.bits
+        });
+        let inner = self.inode_inner();
+        Xstat {
+            stx_mask: SUPPORTED_MASK.bits,
+            stx_blksize: 0,
+            stx_attributes: 0,
+            stx_nlink: inner.nlink() as u32,
+            stx_uid: 0,
+            stx_gid: 0,
+            stx_mode: inner.mode().bits() as _,
+            stx_ino: inner.ino as u64,
+            stx_size: inner.size() as _,
+            stx_blocks: 0,
+            stx_attributes_mask: 0,
+            stx_atime: StatxTimestamp {
+                tv_sec: inner.atime().tv_sec as _,
+                tv_nsec: inner.atime().tv_nsec as _,
+            },
+            stx_btime: StatxTimestamp {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+            stx_ctime: StatxTimestamp {
+                tv_sec: inner.ctime().tv_sec as _,
+                tv_nsec: inner.ctime().tv_nsec as _,
+            },
+            stx_mtime: StatxTimestamp {
+                tv_sec: inner.mtime().tv_sec as _,
+                tv_nsec: inner.mtime().tv_nsec as _,
+            },
+            stx_rdev_major: 0,
+            stx_rdev_minor: 0,
+            stx_dev_major: 0,
+            stx_dev_minor: 0,
+            stx_mnt_id: 0,
+            stx_dio_mem_align: 0,
+            std_dio_offset_align: 0,
+            stx_subvol: 0,
+            stx_atomic_write_unit_min: 0,
+            stx_atomic_write_unit_max: 0,
+            stx_atomic_write_segments_max: 0,
+            stx_dio_read_offset_align: 0,
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//! /proc/sys: a file for every sysctl, readable by all and writable by root
+
+use alloc::{boxed::Box, sync::{Arc, Weak}};
+use async_trait::async_trait;
+
+use crate::{fs::{simplefs::{dentry::SpDentry, inode::SpInode}, vfs::{inode::InodeMode, Dentry, DentryInner, File, FileCount, FileInner, FileOwner, Inode, InodeInner, OpenRef, DCACHE}, Kstat, OpenFlags, StatxTimestamp, SuperBlock, Xstat, XstatMask}, sync::mutex::SpinNoIrqLock, syscall::SysError, sysctl::{Sysctl, SYSCTLS}, task::current_task};
+
+/// mkdir /proc/sys and the directories of the sysctl paths, then touch a file for each
+pub fn init(root_dentry: Arc<dyn Dentry>, super_block: Weak<dyn SuperBlock>) {
+    for sysctl in SYSCTLS.iter() {
+        let (dirs, name) = sysctl.path.rsplit_once('/').unwrap_or(("", sysctl.path));
+        let mut parent = root_dentry.clone();
+        for dir in core::iter::once("sys").chain(dirs.split('/').filter(|dir| !dir.is_empty())) {
+            parent = match parent.get_child(dir) {
+                Some(child) => child,
+                None => {
+                    let dentry = SpDentry::new(dir, Some(parent.clone()));
+                    dentry.set_inode(SpInode::new(super_block.clone()));
+                    parent.add_child(dentry.clone());
+                    DCACHE.pin(dentry.clone());
+                    dentry
+                }
+            };
+        }
+        let dentry = SysctlDentry::new(name, Some(parent.clone()), sysctl);
+        dentry.set_inode(SysctlInode::new(super_block.clone(), sysctl));
+        parent.add_child(dentry.clone());
+        DCACHE.pin(dentry);
+    }
+}
+
+/// a sysctl file: reads show the value, a write sets it
+pub struct SysctlFile {
+    inner: FileInner,
+    sysctl: &'static Sysctl,
+}
+
+impl SysctlFile {
+    pub fn new(dentry: Arc<dyn Dentry>, sysctl: &'static Sysctl) -> Arc<Self> {
+        let inner = FileInner {
+            offset: 0.into(),
+            open: OpenRef::new(&dentry),
+            dentry,
+            flags: SpinNoIrqLock::new(OpenFlags::empty()),
+            count: FileCount::new(),
+            owner: FileOwner::new(),
+        };
+        Arc::new(Self { inner, sysctl })
+    }
+}
+
+#[async_trait]
+impl File for SysctlFile {
+    fn file_inner(&self) ->  &FileInner {
+        &self.inner
+    }
+
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    async fn read(&self, buf: &mut [u8]) -> Result<usize, SysError> {
+        let value = self.sysctl.read();
+        let pos = self.pos();
+        if pos >= value.len() {
+            return Ok(0);
+        }
+        let len = buf.len().min(value.len() - pos);
+        buf[..len].copy_from_slice(&value.as_bytes()[pos..pos + len]);
+        self.set_pos(pos + len);
+        Ok(len)
+    }
+
+    /// the whole value in one write, as echo does
+    async fn write(&self, buf: &[u8]) -> Result<usize, SysError> {
+        if !current_task().unwrap().with_cred(|c| c.is_privileged()) {
+            return Err(SysError::EPERM);
+        }
+        self.sysctl.write(buf)?;
+        Ok(buf.len())
+    }
+}
+
+/// sysctl dentry
+pub struct SysctlDentry {
+    inner: DentryInner,
+    sysctl: &'static Sysctl,
+}
+
+impl SysctlDentry {
+    pub fn new(name: &str, parent: Option<Arc<dyn Dentry>>, sysctl: &'static Sysctl) -> Arc<Self> {
+        Arc::new(Self {
+            inner: DentryInner::new(name, parent),
+            sysctl,
+        })
+    }
+}
+
+unsafe impl Send for SysctlDentry {}
+unsafe impl Sync for SysctlDentry {}
+
+impl Dentry for SysctlDentry {
+    fn dentry_inner(&self) -> &DentryInner {
+        &self.inner
+    }
+
+    fn new(
+            &self,
+            name: &str,
+            parent: Option<Arc<dyn Dentry>>,
+        ) -> Arc<dyn Dentry> {
+        Arc::new(Self {
+            inner: DentryInner::new(name, parent),
+            sysctl: self.sysctl,
+        })
+    }
+
+    fn open(self: Arc<Self>, _flags: OpenFlags) -> Option<Arc<dyn File>> {
+        Some(SysctlFile::new(self.clone(), self.sysctl))
+    }
+}
+
+/// sysctl inode
+pub struct SysctlInode {
+    inner: InodeInner,
+}
+
+impl SysctlInode {
+    pub fn new(super_block: Weak<dyn SuperBlock>, sysctl: &'static Sysctl) -> Arc<Self> {
+        // the size is unknown until read, like linux it shows 0
+        let mode = InodeMode::FILE | InodeMode::from_bits_truncate(sysctl.mode);
+        let inner = InodeInner::new(Some(super_block), mode, 0);
+        Arc::new(Self { inner })
+    }
+}
+
+impl Inode for SysctlInode {
+    fn inode_inner(&self) -> &InodeInner {
+        &self.inner
+    }
+
+    fn getattr(&self) -> crate::fs::Kstat {
+        let inner = self.inode_inner();
+        Kstat {
+            st_dev: 0,
+            st_ino: inner.ino as u64,
+            st_mode: inner.mode().bits() as _,
+            st_nlink: inner.nlink() as u32,
+            st_uid: 0,
+            st_gid: 0,
+            st_rdev: 0,
+            _pad0: 0,
+            st_size: inner.size() as _,
+            _pad1: 0,
+            st_blksize: 0,
+            st_blocks: 0,
+            st_atime_sec: inner.atime().tv_sec as _,
+            st_atime_nsec: inner.atime().tv_nsec as _,
+            st_mtime_sec: inner.mtime().tv_sec as _,
+            st_mtime_nsec: inner.mtime().tv_nsec as _,
+            st_ctime_sec: inner.ctime().tv_sec as _,
+            st_ctime_nsec: inner.ctime().tv_nsec as _,
+        }
+    }
+
+    fn getxattr(&self, _mask: crate::fs::XstatMask) -> crate::fs::Xstat {
+        const SUPPORTED_MASK: XstatMask = XstatMask::from_bits_truncate({
+            XstatMask::STATX_BLOCKS.bits |
+            XstatMask::STATX_ATIME.bits |
+            XstatMask::STATX_CTIME.bits |
+            XstatMask::STATX_MTIME.bits |
+            XstatMask::STATX_NLINK.bits |
+            XstatMask::STATX_TYPE.bits |
+            XstatMask::STATX_MODE.bits |
+            XstatMask::STATX_SIZE.bits |
+            XstatMask::STATX_INO.bits
+        });
+        let inner = self.inode_inner();
+        Xstat {
+            stx_mask: SUPPORTED_MASK.bits,
+            stx_blksize: 0,
+            stx_attributes: 0,
+            stx_nlink: inner.nlink() as u32,
+            stx_uid: 0,
+            stx_gid: 0,
+            stx_mode: inner.mode().bits() as _,
+            stx_ino: inner.ino as u64,
+            stx_size: inner.size() as _,
+            stx_blocks: 0,
+            stx_attributes_mask: 0,
+            stx_atime: StatxTimestamp {
+                tv_sec: inner.atime().tv_sec as _,
+                tv_nsec: inner.atime().tv_nsec as _,
+            },
+            stx_btime: StatxTimestamp {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+            stx_ctime: StatxTimestamp {
+                tv_sec: inner.ctime().tv_sec as _,
+                tv_nsec: inner.ctime().tv_nsec as _,
+            },
+            stx_mtime: StatxTimestamp {
+                tv_sec: inner.mtime().tv_sec as _,
+                tv_nsec: inner.mtime().tv_nsec as _,
+            },
+            stx_rdev_major: 0,
+            stx_rdev_minor: 0,
+            stx_dev_major: 0,
+            stx_dev_minor: 0,
+            stx_mnt_id: 0,
+            stx_dio_mem_align: 0,
+            std_dio_offset_align: 0,
+            stx_subvol: 0,
+            stx_atomic_write_unit_min: 0,
+            stx_atomic_write_unit_max: 0,
+            stx_atomic_write_segments_max: 0,
+            stx_dio_read_offset_align: 0,
+        }
+    }
+}
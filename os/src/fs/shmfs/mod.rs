@@ -0,0 +1,26 @@
+//! anonymous shared memory files of memfd_create
+
+use alloc::{format, sync::Arc};
+
+use crate::{fs::{tmpfs::{dentry::TmpDentry, file::TmpFile, inode::TmpInode}, vfs::{inode::InodeMode, DentryState, File}, FS_MANAGER}, syscall::SysError};
+
+/// the tmpfs instance the memfds belong to
+const MEMFD_MOUNT: &str = "/dev/shm";
+
+/// create the file of a memfd, linked in no directory like an O_TMPFILE,
+/// it lives as long as an fd or a mapping holds it
+pub fn memfd_create(name: &str, allow_sealing: bool) -> Result<Arc<dyn File>, SysError> {
+    let sb = FS_MANAGER.lock()
+        .get("tmpfs")
+        .and_then(|fs| fs.get_sb(MEMFD_MOUNT))
+        .ok_or(SysError::ENODEV)?;
+    let inode = TmpInode::new(Arc::downgrade(&sb), InodeMode::FILE);
+    if allow_sealing {
+        inode.allow_sealing();
+    }
+    let dentry = TmpDentry::new(&format!("memfd:{}", name), None);
+    dentry.set_inode(inode);
+    // cannot be found by path
+    dentry.set_state(DentryState::NEGATIVE);
+    Ok(Arc::new(TmpFile::new(dentry)))
+}
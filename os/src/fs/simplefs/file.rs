@@ -4,7 +4,7 @@ use alloc::sync::Arc;
 use async_trait::async_trait;
 use alloc::boxed::Box;
 
-use crate::{fs::{vfs::{file::SeekFrom, Dentry, File, FileInner}, OpenFlags}, sync::mutex::SpinNoIrqLock, syscall::SysError};
+use crate::{fs::{vfs::{file::SeekFrom, Dentry, File, FileCount, FileInner, FileOwner, OpenRef}, OpenFlags}, sync::mutex::SpinNoIrqLock, syscall::SysError};
 
 
 /// simple file system file
@@ -19,9 +19,12 @@ impl SpFile {
     pub fn new(dentry: Arc<dyn Dentry>) -> Arc<Self> {
         Arc::new(Self {
             inner: FileInner { 
+                open: OpenRef::new(&dentry),
                 dentry: dentry, 
                 offset: AtomicUsize::new(0), 
                 flags:  SpinNoIrqLock::new(OpenFlags::empty()),
+                count: FileCount::new(),
+                owner: FileOwner::new(),
             }
         })
     }
@@ -1,10 +1,10 @@
 //!Stdin & Stdout
 use async_trait::async_trait;
 use hal::print;
-use alloc::boxed::Box;
-use crate::syscall::SysError;
+use alloc::{boxed::Box, sync::Arc};
+use crate::{devices::{CharDevice, DevId, DeviceMajor}, drivers::serial::UART0, syscall::SysError};
 
-use crate::fs::vfs::File;
+use crate::fs::{vfs::{file::PollEvents, inode::InodeMode, Dentry, File, Inode}, Kstat};
 use hal::console::console_getchar;
 use crate::task::suspend_current_and_run_next;
 ///Standard input
@@ -12,11 +12,29 @@ pub struct Stdin;
 ///Standard output
 pub struct Stdout;
 
+/// the block size of the console, which sizes the stdio buffers of libc
+pub const CONSOLE_BLKSIZE: i32 = 1024;
+
+/// what fstat reports on the console: a character device, the serial port
+fn console_stat() -> Kstat {
+    let rdev = DevId { major: DeviceMajor::Serial, minor: 0 }.makedev();
+    Kstat::anonymous(InodeMode::CHAR | InodeMode::from_bits_truncate(0o620), rdev as u64, CONSOLE_BLKSIZE)
+}
+
 #[async_trait]
 impl File for Stdin {
     fn file_inner(&self) -> &super::vfs::FileInner {
         panic!("[Stdin]: dont support get inner")
     }
+    fn dentry(&self) -> Option<Arc<dyn Dentry>> {
+        None
+    }
+    fn inode(&self) -> Option<Arc<dyn Inode>> {
+        None
+    }
+    fn stat(&self) -> Result<Kstat, SysError> {
+        Ok(console_stat())
+    }
     fn readable(&self) -> bool {
         true
     }
@@ -44,6 +62,14 @@ impl File for Stdin {
     async fn write(&self, _user_buf: &[u8]) -> Result<usize, SysError> {
         panic!("Cannot write to stdin!");
     }
+    /// readable once the console received something
+    async fn poll(&self, events: PollEvents) -> PollEvents {
+        if events.contains(PollEvents::IN) && UART0.poll_in().await {
+            PollEvents::IN
+        } else {
+            PollEvents::empty()
+        }
+    }
 }
 
 #[async_trait]
@@ -51,6 +77,15 @@ impl File for Stdout {
     fn file_inner(&self) -> &super::vfs::FileInner {
         panic!("[Stdout]: dont support get inner")
     }
+    fn dentry(&self) -> Option<Arc<dyn Dentry>> {
+        None
+    }
+    fn inode(&self) -> Option<Arc<dyn Inode>> {
+        None
+    }
+    fn stat(&self) -> Result<Kstat, SysError> {
+        Ok(console_stat())
+    }
     fn readable(&self) -> bool {
         false
     }
@@ -61,7 +96,11 @@ impl File for Stdout {
         panic!("Cannot read from stdout!");
     }
     async fn write(&self, buf: &[u8]) -> Result<usize, SysError> {
-        print!("{}", core::str::from_utf8(buf).unwrap());
+        hal::console::print_bytes(buf);
         Ok(buf.len())
     }
+    /// the console never blocks a write
+    async fn poll(&self, events: PollEvents) -> PollEvents {
+        events & PollEvents::OUT
+    }
 }
@@ -4,11 +4,11 @@ use alloc::sync::Arc;
 use async_trait::async_trait;
 use alloc::boxed::Box;
 
-use crate::{fs::{vfs::{file::SeekFrom, Dentry, File, FileInner}, OpenFlags}, sync::{mutex::SpinNoIrqLock, UPSafeCell}, syscall::SysError};
+use crate::{fs::{vfs::{file::io_in_chunks, inode::SealFlags, Dentry, File, FileCount, FileInner, FileOwner, Inode, OpenRef}, OpenFlags}, sync::mutex::SpinNoIrqLock, syscall::SysError};
 
 
 pub struct TmpFile {
-    inner: UPSafeCell<FileInner>,
+    inner: FileInner,
 }
 
 unsafe impl Send for TmpFile {}
@@ -18,11 +18,14 @@ impl TmpFile {
     /// Construct an TmpFile from a dentry
     pub fn new(dentry: Arc<dyn Dentry>) -> Self {
         Self {
-            inner: UPSafeCell::new(FileInner { 
+            inner: FileInner { 
                 offset: AtomicUsize::new(0), 
+                open: OpenRef::new(&dentry),
                 dentry, 
-                flags: SpinNoIrqLock::new(OpenFlags::empty()), 
-            }),
+                flags: SpinNoIrqLock::new(OpenFlags::empty()),
+                count: FileCount::new(), 
+                owner: FileOwner::new(),
+            },
         }
     }
 }
@@ -30,7 +33,7 @@ impl TmpFile {
 #[async_trait]
 impl File for TmpFile {
     fn file_inner(&self) -> &FileInner {
-        self.inner.exclusive_access()
+        &self.inner
     }
     fn readable(&self) -> bool {
         true
@@ -39,22 +42,51 @@ impl File for TmpFile {
         true
     }
     async fn read(&self, buf: &mut [u8]) -> Result<usize, SysError> {
-        let inode = self.dentry().unwrap().inode().unwrap();
         log::debug!("[Tmp file] read start from pos {}", self.pos());
-        let size = inode.cache_read_at(self.pos(), buf).unwrap();
-        self.seek(SeekFrom::Current(size as i64)).expect("seek failed");
-        Ok(size)
+        io_in_chunks(buf.len(), |chunk| {
+            let inode = self.inode().unwrap();
+            self.file_inner().read_with(|pos| inode.clone().cache_read_at(pos, &mut buf[chunk.clone()]).map_err(SysError::from))
+        }).await
     }
     async fn write(&self, buf: &[u8]) -> Result<usize, SysError> {
-        if self.flags().contains(OpenFlags::O_APPEND) {
-            self.set_pos(self.size());
-        }
-        let pos = self.pos();
         log::debug!("[Tmp file] writing {}, state: {:?}", self.dentry().unwrap().path(), self.dentry().unwrap().state());
-        let inode = self.dentry().unwrap().inode().unwrap();
-        let size = inode.cache_write_at(pos, buf).unwrap();
-        log::debug!("[Tmp file] set pos at {}", pos + size);
-        self.set_pos(pos + size);
-        Ok(size)
+        let size = || self.size();
+        let append_end: Option<&(dyn Fn() -> usize + Sync)> = if self.flags().contains(OpenFlags::O_APPEND) {
+            Some(&size)
+        } else {
+            None
+        };
+        io_in_chunks(buf.len(), |chunk| {
+            let inode = self.inode().unwrap();
+            let buf = &buf[chunk];
+            self.file_inner().write_with(buf.len(), append_end, |pos| {
+                check_seals(&inode, pos, buf.len())?;
+                inode.cache_write_at(pos, buf).map_err(SysError::from)
+            })
+        }).await
+    }
+    async fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, SysError> {
+        io_in_chunks(buf.len(), |chunk| {
+            let inode = self.inode().unwrap();
+            inode.cache_read_at(offset + chunk.start, &mut buf[chunk]).map_err(SysError::from)
+        }).await
+    }
+    async fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, SysError> {
+        io_in_chunks(buf.len(), |chunk| {
+            let inode = self.inode().unwrap();
+            check_seals(&inode, offset + chunk.start, chunk.len())?;
+            inode.cache_write_at(offset + chunk.start, &buf[chunk]).map_err(SysError::from)
+        }).await
+    }
+}
+
+/// a write of `len` bytes at `offset` must not break F_SEAL_WRITE or F_SEAL_GROW
+fn check_seals(inode: &Arc<dyn Inode>, offset: usize, len: usize) -> Result<(), SysError> {
+    let seals = inode.seals()?;
+    if seals.intersects(SealFlags::F_SEAL_WRITE | SealFlags::F_SEAL_FUTURE_WRITE)
+        || (seals.contains(SealFlags::F_SEAL_GROW) && offset + len > inode.inode_inner().size())
+    {
+        return Err(SysError::EPERM);
     }
+    Ok(())
 }
\ No newline at end of file
@@ -33,7 +33,7 @@ impl FSType for TmpFSType {
         root_dentry.set_inode(root_inode);
         root_dentry.set_state(DentryState::USED);
         sb.set_root_dentry(root_dentry.clone());
-        DCACHE.lock().insert(root_dentry.path(), root_dentry.clone());
+        DCACHE.pin(root_dentry.clone());
         self.add_sb(&root_dentry.path(), sb);
         Some(root_dentry)
     }
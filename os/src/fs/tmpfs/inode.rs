@@ -1,12 +1,18 @@
 //! inode in memory
 
-use alloc::sync::{Arc, Weak};
+use core::sync::atomic::{AtomicU32, Ordering};
 
-use crate::{config::{BLOCK_SIZE, PAGE_SIZE}, fs::{page::{cache::PageCache, page::Page}, vfs::{inode::InodeMode, Inode, InodeInner}, Kstat, StatxTimestamp, SuperBlock, Xstat, XstatMask}, syscall::SysError};
+use alloc::{string::String, sync::{Arc, Weak}, vec};
+
+use crate::{config::{BLOCK_SIZE, PAGE_SIZE}, fs::{page::{cache::PageCache, page::Page}, vfs::{inode::{InodeMode, SealFlags}, Inode, InodeInner}, Kstat, StatxTimestamp, SuperBlock, Xstat, XstatMask}, syscall::SysError};
+
+use super::{charge_page, force_charge, uncharge};
 
 pub struct TmpInode {
     inner: InodeInner,
     cache: Arc<PageCache>,
+    /// the F_SEAL_* seals, only a memfd created with MFD_ALLOW_SEALING starts unsealed
+    seals: AtomicU32,
 }
 
 unsafe impl Send for TmpInode {}
@@ -17,7 +23,18 @@ impl TmpInode {
     pub fn new(super_block: Weak<dyn SuperBlock>, mode: InodeMode) -> Arc<Self> {
         let inner = InodeInner::new(Some(super_block), mode, 0);
         let cache = Arc::new(PageCache::new());
-        Arc::new(Self { inner, cache })
+        let seals = AtomicU32::new(SealFlags::F_SEAL_SEAL.bits());
+        Arc::new(Self { inner, cache, seals })
+    }
+    /// let seals be added, for memfd_create with MFD_ALLOW_SEALING
+    pub fn allow_sealing(&self) {
+        self.seals.store(0, Ordering::Release);
+    }
+}
+
+impl Drop for TmpInode {
+    fn drop(&mut self) {
+        uncharge(self.cache.get_pages().lock().len());
     }
 }
 
@@ -45,6 +62,7 @@ impl Inode for TmpInode {
             page.clone()
         } else {
             let page = Page::new(offset);
+            force_charge(1);
             page_cache.insert_page(offset, page.clone());
             page_cache.update_end(offset + PAGE_SIZE);
             page
@@ -73,6 +91,7 @@ impl Inode for TmpInode {
                 page.clone()
             } else {
                 let page = Page::new(page_offset);
+                force_charge(1);
                 cache.insert_page(page_offset, page.clone());
                 // cache.update_end(page_offset + PAGE_SIZE);
                 page
@@ -103,10 +122,14 @@ impl Inode for TmpInode {
 
             let page = if let Some(page) = cache.get_page(page_offset) {
                 page.clone()
-            } else {
+            } else if charge_page() {
                 let page = Page::new(page_offset);
                 cache.insert_page(page_offset, page.clone());
                 page
+            } else if total_write_size > 0 {
+                break;
+            } else {
+                return Err(SysError::ENOSPC as i32);
             };
             let page_write_size = page.write_at(in_page_offset, &buf[buf_offset..]);
             page.set_dirty();
@@ -132,14 +155,31 @@ impl Inode for TmpInode {
         Ok(0)
     }
 
+    fn link(&self, _target: &str) -> Result<usize, SysError> {
+        // the dentry tree is all the names a tmp file has,
+        // the new dentry holding this inode is the link
+        Ok(0)
+    }
+
     fn truncate(&self, size: usize) -> Result<usize, SysError> {
         let old_size = self.inner.size();
+        let seals = SealFlags::from_bits_truncate(self.seals.load(Ordering::Acquire));
+        if (size > old_size && seals.contains(SealFlags::F_SEAL_GROW))
+            || (size < old_size && seals.contains(SealFlags::F_SEAL_SHRINK))
+        {
+            return Err(SysError::EPERM);
+        }
         if size > old_size {
             // expand the page cache
             let page_cache = self.cache.clone();
             let offset_aligned_start = old_size / PAGE_SIZE * PAGE_SIZE;
             for offset_aligned in (offset_aligned_start..size).step_by(PAGE_SIZE) {
+                // the page holding the old end keeps its data
+                if page_cache.get_page(offset_aligned).is_some() {
+                    continue;
+                }
                 let page = Page::new(offset_aligned);
+                force_charge(1);
                 page_cache.insert_page(offset_aligned, page.clone());
             }
             self.inner.set_size(size);
@@ -147,21 +187,59 @@ impl Inode for TmpInode {
         } else if old_size == size {
             return Ok(size)
         } else {
-            log::warn!("not support reduce size for tmp file");
+            // the page cache is all the tmp file has
+            let before = self.cache.get_pages().lock().len();
+            self.cache.truncate(size);
+            uncharge(before.saturating_sub(self.cache.get_pages().lock().len()));
+            self.inner.set_size(size);
             return Ok(size)
         }
     }
 
+    fn readlink(&self) -> Result<String, SysError> {
+        if self.inner.mode().get_type() != InodeMode::LINK {
+            return Err(SysError::EINVAL);
+        }
+        // the target is kept as the data of the link
+        let size = self.inner.size();
+        let mut target = vec![0u8; size];
+        let mut len = 0;
+        while len < size {
+            let page = self.cache.get_page(len / PAGE_SIZE * PAGE_SIZE).ok_or(SysError::EIO)?;
+            len += page.read_at(len % PAGE_SIZE, &mut target[len..]);
+        }
+        String::from_utf8(target).map_err(|_| SysError::EINVAL)
+    }
+
+    fn seals(&self) -> Result<SealFlags, SysError> {
+        if self.inner.mode().get_type() != InodeMode::FILE {
+            return Err(SysError::EINVAL);
+        }
+        Ok(SealFlags::from_bits_truncate(self.seals.load(Ordering::Acquire)))
+    }
+
+    fn add_seals(&self, seals: SealFlags) -> Result<(), SysError> {
+        if self.inner.mode().get_type() != InodeMode::FILE {
+            return Err(SysError::EINVAL);
+        }
+        self.seals
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |old| {
+                (old & SealFlags::F_SEAL_SEAL.bits() == 0).then_some(old | seals.bits())
+            })
+            .map(|_| ())
+            .map_err(|_| SysError::EPERM)
+    }
+
     fn getattr(&self) -> Kstat {
         let inner = self.inode_inner();
         let size = inner.size();
         Kstat {
             st_dev: 0,
             st_ino: inner.ino as u64,
-            st_mode: inner.mode.bits() as _,
+            st_mode: inner.mode().bits() as _,
             st_nlink: inner.nlink() as u32,
-            st_uid: 0,
-            st_gid: 0,
+            st_uid: inner.uid(),
+            st_gid: inner.gid(),
             st_rdev: 0,
             _pad0: 0,
             st_size: size as _,
@@ -177,28 +255,30 @@ impl Inode for TmpInode {
         }
     }
 
-    fn getxattr(&self, mask: crate::fs::XstatMask) -> crate::fs::Xstat {
+    fn getxattr(&self, _mask: crate::fs::XstatMask) -> crate::fs::Xstat {
         const SUPPORTED_MASK: XstatMask = XstatMask::from_bits_truncate({
             XstatMask::STATX_BLOCKS.bits |
             XstatMask::STATX_ATIME.bits |
             XstatMask::STATX_CTIME.bits |
             XstatMask::STATX_MTIME.bits |
             XstatMask::STATX_NLINK.bits |
+            XstatMask::STATX_TYPE.bits |
             XstatMask::STATX_MODE.bits |
+            XstatMask::STATX_UID.bits |
+            XstatMask::STATX_GID.bits |
             XstatMask::STATX_SIZE.bits |
             XstatMask::STATX_INO.bits
         });
-        let mask = mask & SUPPORTED_MASK;
         let inner = self.inode_inner();
         let size = inner.size();
         Xstat {
-            stx_mask: mask.bits,
+            stx_mask: SUPPORTED_MASK.bits,
             stx_blksize: BLOCK_SIZE as _,
             stx_attributes: 0,
             stx_nlink: inner.nlink() as u32,
-            stx_uid: 0,
-            stx_gid: 0,
-            stx_mode: inner.mode.bits() as _,
+            stx_uid: inner.uid(),
+            stx_gid: inner.gid(),
+            stx_mode: inner.mode().bits() as _,
             stx_ino: inner.ino as u64,
             stx_size: size as _,
             stx_blocks: (size / BLOCK_SIZE) as _,
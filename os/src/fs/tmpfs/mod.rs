@@ -1,7 +1,11 @@
 //! Tmp file system
 
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use alloc::sync::Arc;
 
+use crate::{config::PAGE_SIZE, sysctl::IntParam};
+
 use super::vfs::Dentry;
 
 
@@ -11,6 +15,30 @@ pub mod inode;
 pub mod dentry;
 pub mod file;
 
+/// sysctl fs/tmpfs-size-mb: the most the files of every tmpfs hold together,
+/// a write needing a page beyond it fails with ENOSPC; 0 for no limit
+pub static TMPFS_SIZE_MB: IntParam = IntParam::new(0, 0, 1 << 20);
+/// the pages held by the files of every tmpfs
+static TMPFS_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+/// take a page for a tmpfs file, false when the limit leaves none
+fn charge_page() -> bool {
+    let limit = TMPFS_SIZE_MB.get() * (1 << 20) / PAGE_SIZE;
+    TMPFS_PAGES
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| (limit == 0 || n < limit).then_some(n + 1))
+        .is_ok()
+}
+
+/// take `n` pages whatever the limit, for pages of zeros a read or an extension adds
+fn force_charge(n: usize) {
+    TMPFS_PAGES.fetch_add(n, Ordering::Relaxed);
+}
+
+/// give back `n` pages of a tmpfs file
+fn uncharge(n: usize) {
+    TMPFS_PAGES.fetch_sub(n, Ordering::Relaxed);
+}
+
 /// init the /tmp
 pub fn init_tmpfs(_root_dentry: Arc<dyn Dentry>) {
     // do nothing
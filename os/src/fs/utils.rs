@@ -6,7 +6,7 @@ use range_map::RangeMap;
 use segment_tree::RangeSet;
 use xmas_elf::reader::Reader;
 
-use crate::{mm::{vm::{KernVmSpaceHal, PageFaultAccessType}, KVMSPACE}, sync::{mutex::SpinNoIrqLock, UPSafeCell}};
+use crate::{mm::{vm::{KernVmSpaceHal, PageFaultAccessType}, KVMSPACE}, sync::mutex::SpinNoIrqLock};
 
 use super::vfs::{File, Inode};
 
@@ -16,7 +16,7 @@ pub struct FileReader {
     inode: Arc<dyn Inode>,
     va: VirtAddr,
     len: usize,
-    mapped: UPSafeCell<RangeSet<usize>>,
+    mapped: SpinNoIrqLock<RangeSet<usize>>,
 } 
 
 impl FileReader {
@@ -29,7 +29,7 @@ impl FileReader {
             inode,
             va,
             len,
-            mapped: UPSafeCell::new(
+            mapped: SpinNoIrqLock::new(
                 RangeSet::new(vpn_range)
             )
         })
@@ -45,7 +45,7 @@ impl Reader for FileReader {
         if offset + len <= self.len {
             let start = (self.va + offset).floor();
             let end = (self.va + offset + len).ceil();
-            let mapped = self.mapped.exclusive_access();
+            let mut mapped = self.mapped.lock();
             if !mapped.contains(start.0..end.0) { 
                 for vpn in start..end {
                     KVMSPACE.lock().handle_page_fault(vpn.start_addr(), PageFaultAccessType::READ).unwrap();
@@ -0,0 +1,271 @@
+//! dentry cache
+//!
+//! Entries are keyed by (parent dentry, name) and spread over hash buckets
+//! which are locked independently, so lookups of different names do not
+//! contend on a single lock.
+//! Both positive and negative dentries are cached: a failed lookup leaves a
+//! negative dentry here, and creating the file later turns that same dentry
+//! positive, so the entry never goes stale.
+//! The number of entries is bounded; beyond it the least recently used entries
+//! that nobody else refers to are dropped (approximately, by sampling buckets).
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use alloc::{boxed::Box, string::{String, ToString}, sync::Arc, vec, vec::Vec};
+use spin::Lazy;
+
+use crate::sync::mutex::SpinNoIrqLock;
+
+use super::Dentry;
+
+/// number of hash buckets, must be a power of 2
+const DCACHE_BUCKETS: usize = 1024;
+/// max number of entries before eviction kicks in
+const DCACHE_CAPACITY: usize = 8192;
+/// number of buckets sampled when looking for an entry to evict
+const DCACHE_EVICT_SCAN: usize = 16;
+
+/// dcache: dentry cache to speed up path lookup
+pub static DCACHE: Lazy<DentryCache> = Lazy::new(DentryCache::new);
+
+struct DcacheEntry {
+    /// the parent holds a strong reference,
+    /// so its address can not be reused by another dentry while the entry is alive
+    parent: Option<Arc<dyn Dentry>>,
+    name: String,
+    dentry: Arc<dyn Dentry>,
+    /// tick of the last lookup
+    last_used: AtomicU64,
+    /// pinned entries are never evicted (fs roots, mountpoints, device nodes)
+    pinned: bool,
+}
+
+impl DcacheEntry {
+    fn key(&self) -> usize {
+        key_of(self.parent.as_ref())
+    }
+
+    /// an entry can be dropped when only the cache (and the parent's children map) refer to it,
+    /// this also keeps the parent of every cached entry in the cache
+    fn evictable(&self) -> bool {
+        if self.pinned || !self.dentry.dentry_inner().children.lock().is_empty() {
+            return false;
+        }
+        let refs = Arc::strong_count(&self.dentry);
+        if self.dentry.is_negative() {
+            refs == 1
+        } else {
+            self.dentry.can_evict() && refs <= 2
+        }
+    }
+}
+
+fn key_of(parent: Option<&Arc<dyn Dentry>>) -> usize {
+    parent.map_or(0, |p| Arc::as_ptr(p) as *const () as usize)
+}
+
+fn bucket_of(parent: usize, name: &str) -> usize {
+    // FNV-1a
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325 ^ parent as u64;
+    for &b in name.as_bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    (hash ^ (hash >> 32)) as usize & (DCACHE_BUCKETS - 1)
+}
+
+/// the dentry cache
+pub struct DentryCache {
+    buckets: Box<[SpinNoIrqLock<Vec<DcacheEntry>>]>,
+    /// root of the whole tree
+    root: SpinNoIrqLock<Option<Arc<dyn Dentry>>>,
+    /// number of entries
+    count: AtomicUsize,
+    /// logical clock for the lru
+    tick: AtomicU64,
+    /// the bucket where the next eviction scan starts
+    hand: AtomicUsize,
+}
+
+impl DentryCache {
+    /// create an empty cache
+    pub fn new() -> Self {
+        let buckets = (0..DCACHE_BUCKETS)
+            .map(|_| SpinNoIrqLock::new(Vec::new()))
+            .collect::<Vec<_>>()
+            .into_boxed_slice();
+        Self {
+            buckets,
+            root: SpinNoIrqLock::new(None),
+            count: AtomicUsize::new(0),
+            tick: AtomicU64::new(0),
+            hand: AtomicUsize::new(0),
+        }
+    }
+
+    /// the root dentry "/"
+    pub fn root(&self) -> Arc<dyn Dentry> {
+        self.root.lock().clone().expect("[DCACHE] root is not mounted")
+    }
+
+    /// look up the child `name` of `parent`, may return a negative dentry
+    pub fn lookup(&self, parent: &Arc<dyn Dentry>, name: &str) -> Option<Arc<dyn Dentry>> {
+        let key = key_of(Some(parent));
+        let bucket = self.buckets[bucket_of(key, name)].lock();
+        let entry = bucket.iter().find(|e| e.key() == key && e.name == name)?;
+        entry.last_used.store(self.tick.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
+        Some(entry.dentry.clone())
+    }
+
+    /// insert a dentry, replacing the old one of the same name under the same parent
+    pub fn insert(&self, dentry: Arc<dyn Dentry>) {
+        self.insert_inner(dentry, false);
+        if self.count.load(Ordering::Relaxed) > DCACHE_CAPACITY {
+            self.shrink();
+        }
+    }
+
+    /// insert a dentry that is never evicted,
+    /// a dentry without parent becomes the root
+    pub fn pin(&self, dentry: Arc<dyn Dentry>) {
+        if dentry.parent().is_none() {
+            *self.root.lock() = Some(dentry.clone());
+        }
+        self.insert_inner(dentry, true);
+    }
+
+    fn insert_inner(&self, dentry: Arc<dyn Dentry>, pinned: bool) {
+        let parent = dentry.parent();
+        let key = key_of(parent.as_ref());
+        let entry = DcacheEntry {
+            parent,
+            name: dentry.name().to_string(),
+            dentry,
+            last_used: AtomicU64::new(self.tick.fetch_add(1, Ordering::Relaxed)),
+            pinned,
+        };
+        let mut bucket = self.buckets[bucket_of(key, &entry.name)].lock();
+        if let Some(old) = bucket.iter_mut().find(|e| e.key() == key && e.name == entry.name) {
+            let pinned = old.pinned || pinned;
+            *old = entry;
+            old.pinned = pinned;
+        } else {
+            bucket.push(entry);
+            self.count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// drop the entry of `dentry`
+    pub fn remove(&self, dentry: &Arc<dyn Dentry>) {
+        self.remove_key(key_of(dentry.parent().as_ref()), dentry.name());
+    }
+
+    fn remove_key(&self, key: usize, name: &str) -> Option<DcacheEntry> {
+        let mut bucket = self.buckets[bucket_of(key, name)].lock();
+        let idx = bucket.iter().position(|e| e.key() == key && e.name == name)?;
+        self.count.fetch_sub(1, Ordering::Relaxed);
+        Some(bucket.swap_remove(idx))
+    }
+
+    /// take out every entry under `dir`
+    fn take_children(&self, dir: &Arc<dyn Dentry>) -> Vec<DcacheEntry> {
+        let key = key_of(Some(dir));
+        let mut taken = Vec::new();
+        for bucket in self.buckets.iter() {
+            let mut bucket = bucket.lock();
+            let mut i = 0;
+            while i < bucket.len() {
+                if bucket[i].key() == key {
+                    taken.push(bucket.swap_remove(i));
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        self.count.fetch_sub(taken.len(), Ordering::Relaxed);
+        taken
+    }
+
+    /// drop every entry under `dir`, used when the directory is removed
+    pub fn remove_children(&self, dir: &Arc<dyn Dentry>) {
+        // the entries are dropped after every bucket lock is released
+        let _ = self.take_children(dir);
+    }
+
+    /// drop every entry below `dir` at any depth, used when its file system is unmounted
+    pub fn remove_tree(&self, dir: &Arc<dyn Dentry>) {
+        let mut dirs = vec![dir.clone()];
+        while let Some(dir) = dirs.pop() {
+            dirs.extend(self.take_children(&dir).into_iter().map(|entry| entry.dentry));
+        }
+    }
+
+    /// move every entry under `old` to `new`, used when a directory is renamed
+    pub fn move_children(&self, old: &Arc<dyn Dentry>, new: &Arc<dyn Dentry>) {
+        for entry in self.take_children(old) {
+            entry.dentry.set_parent(new);
+            self.insert_inner(entry.dentry, entry.pinned);
+        }
+    }
+
+    /// evict least recently used entries until the cache is within its capacity
+    fn shrink(&self) {
+        while self.count.load(Ordering::Relaxed) > DCACHE_CAPACITY {
+            if !self.evict_one() {
+                log::warn!("[DCACHE] over capacity but every entry is in use");
+                return;
+            }
+        }
+    }
+
+    /// sample some buckets and drop the oldest evictable entry among them
+    fn evict_one(&self) -> bool {
+        for _ in 0..DCACHE_BUCKETS / DCACHE_EVICT_SCAN {
+            let start = self.hand.fetch_add(DCACHE_EVICT_SCAN, Ordering::Relaxed);
+            // (last used, key, name)
+            let mut victim: Option<(u64, usize, String)> = None;
+            for i in start..start + DCACHE_EVICT_SCAN {
+                for e in self.buckets[i & (DCACHE_BUCKETS - 1)].lock().iter() {
+                    let used = e.last_used.load(Ordering::Relaxed);
+                    if victim.as_ref().map_or(true, |v| used < v.0) && e.evictable() {
+                        victim = Some((used, e.key(), e.name.clone()));
+                    }
+                }
+            }
+            let Some((_, key, name)) = victim else {
+                continue;
+            };
+            if let Some(entry) = self.remove_key(key, &name) {
+                // a positive dentry is loaded again from the disk on the next lookup
+                if let Some(parent) = entry.parent.as_ref() {
+                    parent.remove_child(&name);
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    /// drop every entry nobody else refers to, returns how many were dropped
+    pub fn drop_unused(&self) -> usize {
+        let mut dropped = 0;
+        // a parent becomes evictable once its children are gone
+        while self.evict_one() {
+            dropped += 1;
+        }
+        dropped
+    }
+
+    /// all cached dentries, negative ones included
+    pub fn dentries(&self) -> Vec<Arc<dyn Dentry>> {
+        self.buckets
+            .iter()
+            .flat_map(|bucket| bucket.lock().iter().map(|e| e.dentry.clone()).collect::<Vec<_>>())
+            .collect()
+    }
+
+    /// number of cached entries
+    pub fn len(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
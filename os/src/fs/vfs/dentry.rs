@@ -4,10 +4,10 @@ use core::{default, mem::MaybeUninit};
 
 use crate::{fs::{vfs::{dentry, inode::InodeMode}, OpenFlags}, sync::mutex::SpinNoIrqLock, syscall::SysError};
 
-use super::{superblock, File, Inode, SuperBlock};
+use super::{superblock, File, Inode, SuperBlock, DCACHE};
 
 use alloc::{
-    collections::btree_map::BTreeMap, string::{String, ToString}, sync::{Arc, Weak}, vec::Vec
+    collections::{btree_map::BTreeMap, btree_set::BTreeSet}, string::{String, ToString}, sync::{Arc, Weak}, vec::Vec
 };
 use log::{info, warn};
 
@@ -18,8 +18,8 @@ pub struct DentryInner {
     pub name: String,
     /// inode it points to
     pub inode: SpinNoIrqLock<Option<Arc<dyn Inode>>>,
-    /// parent
-    pub parent: Option<Weak<dyn Dentry>>,
+    /// parent, may change when a directory is renamed
+    pub parent: SpinNoIrqLock<Option<Weak<dyn Dentry>>>,
     /// children
     /// in the case of mount a fs under another fs
     /// we cannot get the child using inode
@@ -27,6 +27,8 @@ pub struct DentryInner {
     pub children: SpinNoIrqLock<BTreeMap<String, Arc<dyn Dentry>>>,
     /// state
     pub state: SpinNoIrqLock<DentryState>,
+    /// the dentry it was renamed to, followed by the files still open on it
+    pub moved_to: SpinNoIrqLock<Option<Arc<dyn Dentry>>>,
 }
 
 impl DentryInner {
@@ -39,9 +41,10 @@ impl DentryInner {
         Self {
             name: name.to_string(),
             inode,
-            parent: parent.map(|p| Arc::downgrade(&p)),
+            parent: SpinNoIrqLock::new(parent.map(|p| Arc::downgrade(&p))),
             children: SpinNoIrqLock::new(BTreeMap::new()),
             state: SpinNoIrqLock::new(DentryState::UNUSED),
+            moved_to: SpinNoIrqLock::new(None),
         }
     }
 }
@@ -81,7 +84,11 @@ pub trait Dentry: Send + Sync {
     }
     /// tidier way to get parent
     fn parent(&self) -> Option<Arc<dyn Dentry>> {
-        self.dentry_inner().parent.as_ref().map(|p| p.upgrade().unwrap())
+        self.dentry_inner().parent.lock().as_ref().and_then(|p| p.upgrade())
+    }
+    /// change the parent, used when the parent directory is renamed
+    fn set_parent(&self, parent: &Arc<dyn Dentry>) {
+        *self.dentry_inner().parent.lock() = Some(Arc::downgrade(parent));
     }
     /// get all children
     fn children(&self) -> BTreeMap<String, Arc<dyn Dentry>> {
@@ -116,18 +123,27 @@ pub trait Dentry: Send + Sync {
         *self.dentry_inner().state.lock() == DentryState::NEGATIVE
     }
     /// get the absolute path of the dentry
+    /// walk up the parents iteratively, so deep trees cannot overflow the kernel stack
     fn path(&self) -> String {
-        if let Some(p) = self.parent() {
-            let p_path = p.path();
-            if p_path == "/" {
-                p_path + self.name()
-            } else {
-                p_path + "/" + self.name()
-            }
-        } else {
+        let mut names = Vec::new();
+        let mut parent = self.parent();
+        if parent.is_none() {
             // no parent: at the root
-            String::from("/")
+            return String::from("/");
         }
+        names.push(self.name().to_string());
+        while let Some(p) = parent {
+            parent = p.parent();
+            if parent.is_some() {
+                names.push(p.name().to_string());
+            }
+        }
+        let mut path = String::new();
+        for name in names.iter().rev() {
+            path.push('/');
+            path.push_str(name);
+        }
+        path
     }
     /// load all child dentry 
     /// can also be use to update
@@ -141,83 +157,88 @@ pub trait Dentry: Send + Sync {
     fn new_neg_dentry(self: Arc<Self>, _name: &str) -> Arc<dyn Dentry> {
         todo!()
     }
+    /// whether the dcache may drop this dentry when it is unused,
+    /// only dentries that can be loaded again from the backing store should return true
+    fn can_evict(&self) -> bool {
+        false
+    }
 }
 
 impl dyn Dentry {
     
     /// find the dentry by given path
-    /// first look up the dcache
-    /// if missed, try to search, start from this dentry
-    /// only return USED dentry, panic on invalid path
+    /// search start from this dentry, see walk
+    /// only return USED dentry, None if the path does not exist
     pub fn find(self: &Arc<Self>, path: &str) -> Result<Option<Arc<dyn Dentry>>, SysError> {
         // the path should be relative!
         let path = path.trim_start_matches("/");
-        let mut current = self.clone();
-        if path.is_empty() {
-            return Ok(Some(current))
-        }
-        log::info!("path {}", path);
-        let normalize_path = {
-            let mut compoents = Vec::new();
-            for compoent in path.split("/") {
-                match compoent {
-                    "" | "." => continue,
-                    ".." => {
-                        current = current.parent().ok_or(SysError::ENOENT)?;
-                    }
-                    name => {
-                        compoents.push(name);
-                    }
-                }
-            }
-
-            compoents.join("/")
-        };
-        log::info!("normalize path: {}", normalize_path);
-
-        // dcache lock must be release before calling other dentry trait
-        {
-            let cache = DCACHE.lock();
-            let abs_path = current.path() + &normalize_path;
-            //info!("[DCACHE] try to get {}", abs_path);
-            if let Some(dentry) = cache.get(&abs_path) {
-                //info!("[DCACHE] hit one: {:?}", dentry.name());
-                if dentry.state() == DentryState::NEGATIVE {
-                    return Ok(None);
-                } else {
-                    return Ok(Some(dentry.clone()));
-                }  
-            }
-        }
-        //info!("[DCACHE] miss one: {:?}, start to search from {}", path, self.path());
-        let dentry = current.clone().walk(path)?;
+        let dentry = self.clone().walk(path)?;
         if dentry.state() == DentryState::NEGATIVE {
-            //info!("[DENTRY] invalid path!");
             Ok(None)
         } else {
-            Ok(Some(dentry.clone()))
+            Ok(Some(dentry))
         }
     }
 
     /// walk and search the dentry using the given related path(ex. a/b/c)
     /// construct the dentry tree along the way
-    /// walk start from the current entry, recrusivly
-    /// once find the target dentry or reach unexisted path, return
+    /// walk start from the current entry, component by component
     /// if find, should return a USED dentry
-    /// if not find, should return a NEGATIVE dentry
+    /// if only the last component is missing, should return a NEGATIVE dentry
+    /// a missing directory on the way is ENOENT, and a non directory ENOTDIR.
+    /// ".." is walked through the dentry tree instead of being dropped lexically,
+    /// so it leaves a mounted file system by its mount point
     pub fn walk(self: Arc<Self>, path: &str) -> Result<Arc<dyn Dentry>, SysError> {
+        let mut links = 0;
+        self.walk_links(path, &mut links)
+    }
+
+    /// walk, `links` counts the symlinks followed on the way for ELOOP
+    fn walk_links(self: Arc<Self>, path: &str, links: &mut usize) -> Result<Arc<dyn Dentry>, SysError> {
+        if path.len() >= PATH_MAX {
+            return Err(SysError::ENAMETOOLONG);
+        }
         let mut current_dentry = self.clone();
         // break down the path: string a/b/c -> vec [a, b, c]
         let name_vec: Vec<&str> = path
             .split('/')
-            .filter(|s| !s.is_empty() && *s != ".")
+            .filter(|s| !s.is_empty())
             .collect();
+        if name_vec.iter().any(|name| name.len() > NAME_MAX) {
+            return Err(SysError::ENAMETOOLONG);
+        }
         // use the vec to walk, loop
         // if the element exist, keeping walking
         // if not exist, stop.
-        for name in name_vec.iter() {
-            if let Some(child_dentry) = current_dentry.get_child(name) {
-                // first look into self children field
+        for (i, name) in name_vec.iter().enumerate() {
+            // the directories on the way may be symlinks, but nothing else
+            current_dentry = current_dentry.follow_links(links)?;
+            let Some(inode) = current_dentry.inode().filter(|_| !current_dentry.is_negative()) else {
+                return Err(SysError::ENOENT);
+            };
+            if inode.inode_inner().mode().get_type() != InodeMode::DIR {
+                return Err(SysError::ENOTDIR);
+            }
+            match *name {
+                "." => continue,
+                ".." => {
+                    // the root is its own parent
+                    if let Some(parent) = current_dentry.parent() {
+                        current_dentry = parent;
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            let is_last = i == name_vec.len() - 1;
+            if let Some(child_dentry) = DCACHE.lookup(&current_dentry, name) {
+                // hit in the dcache, a negative one means the path does not exist
+                if child_dentry.is_negative() {
+                    return if is_last { Ok(child_dentry) } else { Err(SysError::ENOENT) };
+                }
+                current_dentry = child_dentry;
+            } else if let Some(child_dentry) = current_dentry.get_child(name) {
+                // then look into self children field
                 // if find, just keep walking
                 current_dentry = child_dentry;
             } else {
@@ -230,26 +251,133 @@ impl dyn Dentry {
                     // child not exist
                     // create a negative dentry
                     // WARNING: the neg dentry and its parent should have same types
-                    // let neg_dentry = self.new(
-                    //     name,
-                    //     self.superblock(),
-                    //     Some(current_dentry)
-                    // );
-                    // neg_dentry.set_state(DentryState::NEGATIVE);
                     let neg_dentry = current_dentry.new_neg_dentry(name);
-                    // info!("[DCACHE]: insert key: {}", neg_dentry.path());
-                    DCACHE.lock().insert(neg_dentry.path(), neg_dentry.clone());
+                    DCACHE.insert(neg_dentry.clone());
+                    if !is_last {
+                        return Err(SysError::ENOENT);
+                    }
                     return Ok(neg_dentry);
                 }
             }
         }
 
+        // a trailing slash names a directory, a missing one may still be created by mkdir
+        if path.ends_with('/') && !current_dentry.is_negative() {
+            let dir = current_dentry.clone().follow_links(links)?;
+            let is_dir = dir.inode().map_or(true, |inode| inode.inode_inner().mode().get_type() == InodeMode::DIR);
+            if !dir.is_negative() && !is_dir {
+                return Err(SysError::ENOTDIR);
+            }
+        }
         return Ok(current_dentry.clone());
     }
 
+    /// follow the symlinks met while walking, a relative target is walked from the link's directory
+    fn follow_links(self: Arc<Self>, links: &mut usize) -> Result<Arc<dyn Dentry>, SysError> {
+        let mut current = self;
+        loop {
+            let Some(inode) = current.inode().filter(|_| !current.is_negative()) else {
+                return Ok(current);
+            };
+            if inode.inode_inner().mode().get_type() != InodeMode::LINK {
+                return Ok(current);
+            }
+            *links += 1;
+            if *links > MAX_LINK_DEPTH {
+                return Err(SysError::ELOOP);
+            }
+            let target = inode.readlink()?;
+            let base = if target.starts_with('/') {
+                DCACHE.root()
+            } else {
+                current.parent().unwrap_or_else(|| DCACHE.root())
+            };
+            current = base.walk_links(&target, links)?;
+        }
+    }
+
+    /// make the cached children agree with `names`, the entries the inode of this directory lists:
+    /// a child the file system no longer has is dropped from the cache, a name the cache has
+    /// not seen or thought missing is looked up, and the file systems mounted here stay.
+    /// Return the children in name order, the same order on every call
+    pub fn sync_children(self: &Arc<Self>, names: Vec<String>) -> Result<Vec<Arc<dyn Dentry>>, SysError> {
+        let inode = self.inode().ok_or(SysError::ENOENT)?;
+        let names: BTreeSet<String> = names
+            .into_iter()
+            .filter(|name| name != "." && name != "..")
+            .collect();
+        for (name, child) in self.children() {
+            if self.is_mounted_here(&child) {
+                continue;
+            }
+            if child.is_negative() || !names.contains(&name) {
+                // removed behind the cache, or created since it was cached as missing
+                self.remove_child(&name);
+                DCACHE.remove(&child);
+                DCACHE.remove_children(&child);
+            } else if DCACHE.lookup(self, &name).is_some_and(|cached| cached.is_negative()) {
+                // the walk looks into the dcache first
+                DCACHE.insert(child);
+            }
+        }
+        for name in names {
+            if self.get_child(&name).is_some() {
+                continue;
+            }
+            let Some(child_inode) = inode.lookup(&name) else {
+                continue;
+            };
+            let child = self.new(&name, Some(self.clone()));
+            child.set_inode(child_inode);
+            child.set_state(DentryState::USED);
+            self.add_child(child.clone());
+            // replaces a negative one of the same name
+            DCACHE.insert(child);
+        }
+        Ok(self.children().into_values().filter(|child| !child.is_negative()).collect())
+    }
+
+    /// whether `child` is the root of another file system mounted on this directory
+    fn is_mounted_here(&self, child: &Arc<dyn Dentry>) -> bool {
+        let sb = |dentry: &dyn Dentry| dentry.inode().and_then(|inode| inode.inode_inner().super_block.clone());
+        match (sb(self), sb(child.as_ref())) {
+            (Some(this), Some(other)) => !Weak::ptr_eq(&this, &other),
+            _ => false,
+        }
+    }
+
+    /// move all the children to `new` after the directory is renamed,
+    /// so paths under it are rebuilt from the new name instead of the stale one
+    pub fn move_children_to(self: &Arc<Self>, new: &Arc<dyn Dentry>) {
+        let children = core::mem::take(&mut *self.dentry_inner().children.lock());
+        for (_, child) in children {
+            child.set_parent(new);
+            new.add_child(child);
+        }
+        // cached entries are keyed by the parent, including the negative ones
+        DCACHE.move_children(self, new);
+    }
+
+    /// record that the file of this dentry was renamed to `new`
+    pub fn set_moved_to(&self, new: &Arc<dyn Dentry>) {
+        *self.dentry_inner().moved_to.lock() = Some(new.clone());
+    }
+
+    /// the dentry the file now has, following the renames since this one was looked up,
+    /// so an open fd keeps naming its file and not the stale path
+    pub fn current(self: Arc<Self>) -> Arc<dyn Dentry> {
+        let mut current = self;
+        loop {
+            let next = current.dentry_inner().moved_to.lock().clone();
+            match next {
+                Some(next) => current = next,
+                None => return current,
+            }
+        }
+    }
+
     /// follow the link and jump until reach the first NOT link Inode or reach the max depth
     pub fn follow(self: Arc<Self>) -> Result<Arc<dyn Dentry>, SysError> {
-        const MAX_LINK_DEPTH: usize = 40;
         let mut current = self.clone();
 
         for _ in 0..MAX_LINK_DEPTH {
@@ -257,7 +385,7 @@ impl dyn Dentry {
                 return Ok(current)
             }
 
-            let mode = current.inode().unwrap().inode_inner().mode;
+            let mode = current.inode().unwrap().inode_inner().mode();
             // log::info!("[walk] mode {:?}", mode);
             if mode.contains(InodeMode::LINK) {
                 // follow to the next
@@ -286,44 +414,28 @@ pub enum DentryState {
     NEGATIVE,
 }
 
-#[allow(unused)]
-/// dcache: dentry cache to speed up dentry looking
-/// when open a file, need to add the related dentry to cache
-/// when close a file, remove it in the cache
-/// every used or negative dentry should be in cache
-/// the key is the absolute path of the dentry
-/// the value is the dentry
-pub static DCACHE: SpinNoIrqLock<BTreeMap<String, Arc<dyn Dentry>>> = 
-    SpinNoIrqLock::new(BTreeMap::new());
-
+/// the longest path, including the terminating NUL
+pub const PATH_MAX: usize = 4096;
+/// the longest file name
+pub const NAME_MAX: usize = 255;
+/// the most symlinks followed in one lookup
+const MAX_LINK_DEPTH: usize = 40;
 
 /// helper function: Search from root using absolute path,
 /// return the target dentry: maybe negative
-/// first lookup in the dcache
-/// if not found, search from root
 pub fn global_find_dentry(path: &str) -> Result<Arc<dyn Dentry>, SysError> {
     log::debug!("global find dentry: {}", path);
-    {
-        let cache = DCACHE.lock();
-        if let Some(dentry) = cache.get(path) {
-            return Ok(dentry.clone());
-        }
-    }
-    // get the root dentry
-    let root_dentry = {
-        let dcache = DCACHE.lock();
-        Arc::clone(dcache.get("/").unwrap())
-    };
-    root_dentry.walk(path)
+    DCACHE.root().walk(path)
 }
 
-/// helper function: try to update DCACHE when create new inode
+/// helper function: attach the newly created inode to the dentry of path
 pub fn global_update_dentry(path: &str, inode: Arc<dyn Inode>) -> Result<(), SysError> {
-    let cache = DCACHE.lock();
-    if let Some(dentry) = cache.get(path) {
-        dentry.set_inode(inode);
+    let dentry = global_find_dentry(path)?;
+    dentry.set_inode(inode);
+    if let Some(parent) = dentry.parent() {
+        parent.add_child(dentry);
     }
-    return Ok(())
+    Ok(())
 }
 
 impl<T: Send + Sync + 'static> Dentry for MaybeUninit<T> {
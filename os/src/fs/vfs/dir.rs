@@ -0,0 +1,59 @@
+//! directory file object
+//!
+//! an open directory is only listed by getdents64 or used as a dirfd,
+//! read and write on it fail with EISDIR whatever file system it is on
+
+use core::sync::atomic::AtomicUsize;
+
+use alloc::{boxed::Box, sync::Arc};
+use async_trait::async_trait;
+
+use crate::{fs::OpenFlags, sync::mutex::SpinNoIrqLock, syscall::SysError};
+
+use super::{Dentry, File, FileCount, FileInner, FileOwner, OpenRef};
+
+/// an open directory, the position counts the entries listed so far
+pub struct DirFile {
+    inner: FileInner,
+}
+
+impl DirFile {
+    /// open the directory of `dentry`
+    pub fn new(dentry: Arc<dyn Dentry>) -> Self {
+        Self {
+            inner: FileInner {
+                offset: AtomicUsize::new(0),
+                open: OpenRef::new(&dentry),
+                dentry,
+                flags: SpinNoIrqLock::new(OpenFlags::empty()),
+                count: FileCount::new(),
+                owner: FileOwner::new(),
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl File for DirFile {
+    fn file_inner(&self) -> &FileInner {
+        &self.inner
+    }
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    async fn read(&self, _buf: &mut [u8]) -> Result<usize, SysError> {
+        Err(SysError::EISDIR)
+    }
+    async fn write(&self, _buf: &[u8]) -> Result<usize, SysError> {
+        Err(SysError::EISDIR)
+    }
+    async fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize, SysError> {
+        Err(SysError::EISDIR)
+    }
+    async fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize, SysError> {
+        Err(SysError::EISDIR)
+    }
+}
@@ -0,0 +1,178 @@
+//! signal driven io of an open file
+//!
+//! fcntl F_SETOWN names the process or the process group an open file signals,
+//! F_SETSIG the signal, and O_ASYNC puts the file on the list of its source,
+//! which signals the owners of the files on it when it turns readable.
+//! The credentials of the caller of F_SETOWN are kept with the owner, a signal
+//! goes only to the processes they may signal, as sigio_perm on linux
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+
+use crate::{
+    signal::{SigInfo, SigPoll, SIGIO, SIGRTMAX},
+    sync::mutex::SpinNoIrqLock,
+    syscall::SysError,
+    task::{
+        cred::Credentials,
+        current_task,
+        manager::{PROCESS_GROUP_MANAGER, TASK_MANAGER},
+        task::TaskControlBlock,
+    },
+};
+
+use super::File;
+
+/// POLLIN | POLLRDNORM, the band of a SIGIO for input
+const BAND_IN: i64 = 0x41;
+
+/// who an open file signals, with the credentials of who set it
+#[derive(Clone)]
+enum Owner {
+    None,
+    Process(Weak<TaskControlBlock>, Credentials),
+    Group(usize, Credentials),
+}
+
+/// the owner of an open file and the signal it gets, kept in the file description
+pub struct FileOwner {
+    owner: SpinNoIrqLock<Owner>,
+    /// the signal of F_SETSIG, 0 for a plain SIGIO
+    sig: AtomicUsize,
+    /// the fd O_ASYNC was last turned on through, for si_fd
+    fd: AtomicUsize,
+}
+
+impl FileOwner {
+    pub const fn new() -> Self {
+        Self {
+            owner: SpinNoIrqLock::new(Owner::None),
+            sig: AtomicUsize::new(0),
+            fd: AtomicUsize::new(0),
+        }
+    }
+
+    /// F_SETOWN: a process for a positive `pid`, the group -`pid` for a negative one, nobody for 0
+    pub fn set(&self, pid: isize) -> Result<(), SysError> {
+        let cred = current_task().unwrap().with_cred(|cred| cred.clone());
+        let owner = if pid == 0 {
+            Owner::None
+        } else if pid > 0 {
+            let task = TASK_MANAGER.get_task(pid as usize).ok_or(SysError::ESRCH)?;
+            Owner::Process(Arc::downgrade(&task), cred)
+        } else {
+            let pgid = pid.unsigned_abs();
+            match PROCESS_GROUP_MANAGER.get_group(pgid) {
+                Some(group) if !group.is_empty() => Owner::Group(pgid, cred),
+                _ => return Err(SysError::ESRCH),
+            }
+        };
+        *self.owner.lock() = owner;
+        Ok(())
+    }
+
+    /// F_GETOWN: the pid of the owner process, minus the pgid of the owner group, or 0
+    pub fn get(&self) -> isize {
+        match self.current() {
+            Owner::None => 0,
+            Owner::Process(task, _) => task.upgrade().map_or(0, |task| task.pid() as isize),
+            Owner::Group(pgid, _) => -(pgid as isize),
+        }
+    }
+
+    /// the owner, a process which has exited is forgotten
+    fn current(&self) -> Owner {
+        let mut owner = self.owner.lock();
+        if let Owner::Process(task, _) = &*owner {
+            if task.upgrade().is_none_or(|task| task.is_zombie()) {
+                *owner = Owner::None;
+            }
+        }
+        owner.clone()
+    }
+
+    /// F_SETSIG
+    pub fn set_sig(&self, sig: usize) -> Result<(), SysError> {
+        if sig > SIGRTMAX {
+            return Err(SysError::EINVAL);
+        }
+        self.sig.store(sig, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// F_GETSIG
+    pub fn sig(&self) -> usize {
+        self.sig.load(Ordering::Relaxed)
+    }
+
+    pub fn set_fd(&self, fd: usize) {
+        self.fd.store(fd, Ordering::Relaxed);
+    }
+
+    /// signal the owner that the file turned readable:
+    /// a bare SIGIO, or the signal of F_SETSIG with the band and the fd,
+    /// the processes the caller of F_SETOWN may not signal are skipped
+    pub fn notify_readable(&self) {
+        let sig_info = match self.sig() {
+            0 => SigInfo::new(SIGIO, SigInfo::KERNEL),
+            sig => SigInfo {
+                si_poll: Some(SigPoll { band: BAND_IN, fd: self.fd.load(Ordering::Relaxed) as i32 }),
+                ..SigInfo::new(sig, SigInfo::POLL_IN)
+            },
+        };
+        match self.current() {
+            Owner::None => {}
+            Owner::Process(task, cred) => {
+                if let Some(task) = task.upgrade().filter(|task| task.with_cred(|target| cred.can_signal(target))) {
+                    task.recv_sigs_process_level(sig_info);
+                }
+            }
+            Owner::Group(pgid, cred) => {
+                for process in PROCESS_GROUP_MANAGER
+                    .get_group(pgid)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|task| task.upgrade())
+                    .filter(|task| task.is_leader() && !task.is_zombie())
+                    .filter(|task| task.with_cred(|target| cred.can_signal(target)))
+                {
+                    process.recv_sigs_process_level(sig_info);
+                }
+            }
+        }
+    }
+}
+
+/// the open files with O_ASYNC on of one source of input
+pub struct FasyncList(SpinNoIrqLock<Vec<Weak<dyn File>>>);
+
+impl FasyncList {
+    pub const fn new() -> Self {
+        Self(SpinNoIrqLock::new(Vec::new()))
+    }
+
+    /// put `file` on the list when O_ASYNC is turned `on`, take it off when off
+    pub fn update(&self, file: &Arc<dyn File>, on: bool) {
+        let mut files = self.0.lock();
+        files.retain(|f| f.upgrade().is_some_and(|f| !Arc::ptr_eq(&f, file)));
+        if on {
+            files.push(Arc::downgrade(file));
+        }
+    }
+
+    /// the files on the list which are still open
+    pub fn files(&self) -> Vec<Arc<dyn File>> {
+        self.0.lock().iter().filter_map(|f| f.upgrade()).collect()
+    }
+
+    /// the source turned readable, signal the owner of every file on the list
+    pub fn notify(&self) {
+        for file in self.files() {
+            file.file_inner().owner.notify_readable();
+        }
+    }
+}
@@ -1,28 +1,226 @@
 //! virtual file system file object
 
-use core::{any::Any, sync::atomic::{AtomicUsize, Ordering}, task::Poll};
+use core::{any::Any, ops::Range, sync::atomic::{AtomicUsize, Ordering}, task::Poll};
 
 
-use crate::{fs::{page::page::PAGE_SIZE, vfs::{dentry::global_find_dentry, inode::InodeMode, DentryState}, OpenFlags}, sync::mutex::{spin_mutex::SpinMutex, SpinNoIrqLock}, syscall::{SysError, SysResult}, utils::{abs_path_to_name, abs_path_to_parent}};
+use crate::{fs::{page::page::PAGE_SIZE, vfs::{dentry::global_find_dentry, inode::InodeMode, DentryState}, Kstat, OpenFlags, Xstat, XstatMask}, sync::mutex::{spin_mutex::SpinMutex, SpinNoIrqLock}, syscall::{SysError, SysResult}, sysctl::IntParam, task::schedule::cond_resched, utils::{abs_path_to_name, abs_path_to_parent}};
 use async_trait::async_trait;
 
 use alloc::{
-    boxed::Box, sync::Arc, vec::Vec
+    boxed::Box, format, string::String, sync::Arc, vec::Vec
 };
 use downcast_rs::{impl_downcast, Downcast, DowncastSync};
 use log::info;
 use hal::println;
 use xmas_elf::reader::Reader;
-use super::{Dentry, Inode, DCACHE};
+use super::{Dentry, FasyncList, FileOwner, Inode, DCACHE};
 
 /// basic File object
+/// one File is one open file description:
+/// every open creates a new one, dup and fork share the same Arc
 pub struct FileInner {
     /// the dentry it points to
     pub dentry: Arc<dyn Dentry>,
-    /// the current pos 
+    /// the current pos, shared by every fd referring to this description
     pub offset: AtomicUsize,
     /// file flags
     pub flags: SpinNoIrqLock<OpenFlags>,
+    /// its place in the open files of the system
+    pub count: FileCount,
+    /// the open of the inode, which keeps it for the file once its name is gone
+    pub open: OpenRef,
+    /// who the file signals with O_ASYNC, see fcntl F_SETOWN
+    pub owner: FileOwner,
+}
+
+/// the open file descriptions of the whole system
+static NR_FILES: AtomicUsize = AtomicUsize::new(0);
+/// sysctl fs/file-max: the open files beyond which only a privileged task may open more
+pub static FILE_MAX: IntParam = IntParam::new(65536, 64, 1 << 20);
+
+/// the open file descriptions of the whole system
+pub fn nr_files() -> usize {
+    NR_FILES.load(Ordering::Relaxed)
+}
+
+/// ENFILE when `n` more open files would go beyond fs/file-max and the caller is unprivileged
+pub fn ensure_nr_files(n: usize, privileged: bool) -> Result<(), SysError> {
+    if nr_files() + n > FILE_MAX.get() && !privileged {
+        return Err(SysError::ENFILE);
+    }
+    Ok(())
+}
+
+/// sysctl fs/file-nr: the open files, the free ones, always 0, and fs/file-max
+pub fn file_nr_read() -> String {
+    format!("{}\t0\t{}\n", nr_files(), FILE_MAX.get())
+}
+
+/// bytes a read or write of a file moves between two yield points.
+/// a write is atomic up to this size only, the chunks of a bigger one may
+/// interleave with those of other writers, as they may past PIPE_BUF on linux
+pub const IO_CHUNK: usize = 64 * PAGE_SIZE;
+
+/// move `len` bytes with `io`, which is given the range of the bytes of each chunk
+/// of at most IO_CHUNK and holds no lock across it, and let the others run between them.
+/// a short chunk is all there is for now, an error after some bytes ends with what was moved
+pub async fn io_in_chunks<F>(len: usize, mut io: F) -> Result<usize, SysError>
+where
+    F: FnMut(Range<usize>) -> Result<usize, SysError>,
+{
+    let mut done = 0;
+    while done < len {
+        let chunk = done..len.min(done + IO_CHUNK);
+        let want = chunk.len();
+        let n = match io(chunk) {
+            Ok(n) => n,
+            Err(e) if done == 0 => return Err(e),
+            Err(_) => break,
+        };
+        done += n;
+        if n < want {
+            break;
+        }
+        if done < len {
+            cond_resched().await;
+        }
+    }
+    Ok(done)
+}
+
+/// one open file in NR_FILES, taken with the file and given back when it drops
+pub struct FileCount(());
+
+impl FileCount {
+    pub fn new() -> Self {
+        NR_FILES.fetch_add(1, Ordering::Relaxed);
+        Self(())
+    }
+}
+
+impl Drop for FileCount {
+    fn drop(&mut self) {
+        NR_FILES.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// one open file of an inode, counted in the inode and its super block while the file lives.
+/// dup, fork and mmap share the file and so the count, the last close releases the inode
+pub struct OpenRef(Option<Arc<dyn Inode>>);
+
+impl OpenRef {
+    /// open the inode of `dentry`, nothing for a negative one
+    pub fn new(dentry: &Arc<dyn Dentry>) -> Self {
+        let inode = dentry.inode();
+        if let Some(inode) = inode.as_ref() {
+            inode.inode_inner().open();
+        }
+        Self(inode)
+    }
+    /// for a file with no inode
+    pub fn none() -> Self {
+        Self(None)
+    }
+    /// the inode opened
+    pub fn inode(&self) -> Option<Arc<dyn Inode>> {
+        self.0.clone()
+    }
+}
+
+impl Drop for OpenRef {
+    fn drop(&mut self) {
+        if let Some(inode) = self.0.take() {
+            if inode.inode_inner().close() {
+                inode.release();
+            }
+        }
+    }
+}
+
+impl FileInner {
+    /// read at the current offset with `read_at` and move the offset past the data read.
+    /// if another task moved the offset meanwhile, read again at the new offset,
+    /// so tasks sharing the description never read the same bytes twice
+    pub fn read_with<F>(&self, mut read_at: F) -> Result<usize, SysError>
+    where
+        F: FnMut(usize) -> Result<usize, SysError>,
+    {
+        let mut pos = self.offset.load(Ordering::Acquire);
+        loop {
+            let size = read_at(pos)?;
+            match self.offset.compare_exchange(pos, pos + size, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => {
+                    self.accessed();
+                    return Ok(size);
+                }
+                Err(now) => pos = now,
+            }
+        }
+    }
+
+    /// write `len` bytes with `write_at` at the current offset,
+    /// or at the end of file given by `append_end` for O_APPEND.
+    /// the range is reserved before writing,
+    /// so tasks sharing the description never overwrite each other
+    pub fn write_with<F>(&self, len: usize, append_end: Option<&(dyn Fn() -> usize + Sync)>, write_at: F) -> Result<usize, SysError>
+    where
+        F: FnOnce(usize) -> Result<usize, SysError>,
+    {
+        self.check_writable()?;
+        let pos = match append_end {
+            Some(end) => {
+                let mut cur = self.offset.load(Ordering::Acquire);
+                let mut pos = end();
+                loop {
+                    match self.offset.compare_exchange(cur, pos + len, Ordering::AcqRel, Ordering::Acquire) {
+                        Ok(_) => break pos,
+                        Err(now) => {
+                            // another appender reserved the range up to `now`
+                            cur = now;
+                            pos = end().max(now);
+                        }
+                    }
+                }
+            }
+            None => self.offset.fetch_add(len, Ordering::AcqRel),
+        };
+        let ret = write_at(pos);
+        let written = *ret.as_ref().unwrap_or(&0);
+        if written < len {
+            // give back the part of the range that was not written
+            let _ = self.offset.compare_exchange(pos + len, pos + written, Ordering::AcqRel, Ordering::Relaxed);
+        }
+        if written > 0 {
+            self.modified();
+        }
+        ret
+    }
+
+    /// EROFS if the file is a regular file of a read-only mount,
+    /// devices, fifos and sockets stay writable there
+    pub fn check_writable(&self) -> Result<(), SysError> {
+        match self.dentry.inode() {
+            Some(inode) if inode.inode_inner().mode().get_type() == InodeMode::FILE => inode.inode_inner().check_writable(),
+            _ => Ok(()),
+        }
+    }
+
+    /// update the access time of the inode after a read, unless opened with O_NOATIME
+    pub fn accessed(&self) {
+        if self.flags.lock().contains(OpenFlags::O_NOATIME) {
+            return;
+        }
+        if let Some(inode) = self.dentry.inode() {
+            inode.inode_inner().touch_atime();
+        }
+    }
+
+    /// update the modification time of the inode after a write
+    pub fn modified(&self) {
+        if let Some(inode) = self.dentry.inode() {
+            inode.inode_inner().touch_mtime();
+        }
+    }
 }
 
 bitflags! {
@@ -45,8 +243,8 @@ bitflags! {
         const ERR = 0x008;
         /// Hang up.
         const HUP = 0x010;
-        /// Invalid poll request.
-        const INVAL = 0x020;
+        /// Invalid poll request, the fd is not open.
+        const NVAL = 0x020;
     }
 }
 
@@ -75,35 +273,41 @@ pub trait File: Send + Sync + DowncastSync {
     async fn write(&self, buf: &[u8]) -> Result<usize, SysError>;
     /// Read file, file offset will not change
     async fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize, SysError> {
-        todo!()
+        Err(SysError::ESPIPE)
     }
     /// Write file, file offset will not change
     async fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize, SysError> {
-        todo!()
+        Err(SysError::ESPIPE)
     }
-    /// get the dentry it points to
+    /// get the dentry it points to, the one it was renamed to if it was
     fn dentry(&self) -> Option<Arc<dyn Dentry>> {
-        Some(self.file_inner().dentry.clone())
+        Some(self.file_inner().dentry.clone().current())
     }
-    /// quicker way to get the inode it points to
-    /// notice that maybe unsafe!
+    /// the inode it opened, which stays after its name is unlinked
     fn inode(&self) -> Option<Arc<dyn Inode>> {
-        self.dentry().unwrap().inode().clone()
+        self.file_inner().open.inode().or_else(|| self.dentry().unwrap().inode())
+    }
+    /// the attributes fstat reports, those of the inode for a file with one
+    fn stat(&self) -> Result<Kstat, SysError> {
+        self.inode().map(|inode| inode.getattr()).ok_or(SysError::EBADF)
+    }
+    /// the attributes statx reports, the same as [`File::stat`] for a file with no inode
+    fn statx(&self, mask: XstatMask) -> Result<Xstat, SysError> {
+        match self.inode() {
+            Some(inode) => Ok(inode.getxattr(mask)),
+            None => self.stat().map(|stat| Xstat::from(&stat)),
+        }
     }
     /// call by ioctl syscall
     fn ioctl(&self, _cmd: usize, _arg: usize) -> SysResult {
         Err(SysError::ENOTTY)
     }
-    /// base poll 
-    async fn base_poll(&self, events: PollEvents) -> PollEvents{
-        let mut res = PollEvents::empty();
-        if events.contains(PollEvents::IN) {
-            res |= PollEvents::IN
-        }
-        if events.contains(PollEvents::OUT) {
-            res |= PollEvents::OUT;
-        }
-        res
+    /// the events of `events` the file is ready for, plus ERR and HUP which are always reported.
+    /// It checks once and does not wait: a file that is not ready registers the waker
+    /// of the polling task to be woken when it may be, ppoll and pselect await it again then.
+    /// Regular files and directories are always readable and writable
+    async fn poll(&self, events: PollEvents) -> PollEvents {
+        events & (PollEvents::IN | PollEvents::OUT)
     }
     /// get the file flags
     fn flags(&self) -> OpenFlags {
@@ -113,6 +317,10 @@ pub trait File: Send + Sync + DowncastSync {
     fn set_flags(&self, flags: OpenFlags) {
         *self.file_inner().flags.lock() = flags
     }
+    /// the list O_ASYNC puts the file on, None for a file which never signals
+    fn fasync_list(&self) -> Option<&FasyncList> {
+        None
+    }
     /// the file size 
     /// (this method should only be called when inode is a file)
     fn size(&self) -> usize {
@@ -167,7 +375,7 @@ impl dyn File {
     /// Read all data inside a inode into vector
     pub fn read_all(&self) -> Vec<u8> {
         let mut offset = 0usize;
-        let inode = self.dentry().unwrap().inode().unwrap();
+        let inode = self.inode().unwrap();
         let mut buffer = [0u8; PAGE_SIZE];
         let mut v: Vec<u8> = Vec::new();
         loop {
@@ -181,10 +389,6 @@ impl dyn File {
         //info!("read total size: {}", v.len());
         v
     }
-    // given the event and track the event async, returns the event if is ready
-    pub async fn poll(&self, events: PollEvents) -> PollEvents {
-        self.base_poll(events).await
-    }
 }
 
 /// helper function: Open file in disk fs with flags
@@ -194,13 +398,10 @@ impl dyn File {
 pub fn open_file(path: &str, flags: OpenFlags) -> Option<Arc<dyn File>> {
     //info!("try to open file: {}", path);
     // get the root dentry and look up for the inode first
-    let root_dentry = {
-        let dcache = DCACHE.lock();
-        Arc::clone(dcache.get("/").unwrap())
-    };
+    let root_dentry = DCACHE.root();
     
     if flags.contains(OpenFlags::O_CREAT) {
-        if let Some(dentry) = root_dentry.find(path).expect("failed") {
+        if let Some(dentry) = root_dentry.find(path).ok()? {
             // clear size
             let inode = dentry.inode().unwrap();
             inode.truncate(0).expect("Error when truncating inode");
@@ -215,10 +416,13 @@ pub fn open_file(path: &str, flags: OpenFlags) -> Option<Arc<dyn File>> {
             let dentry = parent_dentry.new(&name, Some(parent_dentry.clone()));
             dentry.set_state(DentryState::USED);
             dentry.set_inode(inode);
+            // replace the negative dentry left by the lookup above
+            parent_dentry.add_child(dentry.clone());
+            DCACHE.insert(dentry.clone());
             dentry.open(flags)
         }
     } else {
-        if let Some(dentry) = root_dentry.find(path).expect("failed") {
+        if let Some(dentry) = root_dentry.find(path).ok()? {
             // get the dentry and it is valid (see dentry::find)
             let inode = dentry.inode().unwrap();
             if flags.contains(OpenFlags::O_TRUNC) {
@@ -236,10 +440,7 @@ impl_downcast!(sync File);
 
 /// helper function: List all files in the ext4 filesystem
 pub fn list_apps() {
-    let root_dentry = {
-        let dcache = DCACHE.lock();
-        Arc::clone(dcache.get("/").unwrap())
-    };
+    let root_dentry = DCACHE.root();
     let root_inode = root_dentry.inode().unwrap();
     println!("/**** APPS ****");
     for app in root_inode.ls() {
@@ -135,4 +135,21 @@ bitflags::bitflags! {
         const MS_ACTIVE = 1 << 30;
         const MS_NOUSER = 1 << 31;
     }
-}
\ No newline at end of file
+}
+impl MountFlags {
+    /// the options of a mount as /proc/mounts shows them
+    pub fn options(&self) -> String {
+        let mut res = String::from(if self.contains(Self::MS_RDONLY) { "ro" } else { "rw" });
+        for (flag, name) in [
+            (Self::MS_NOSUID, ",nosuid"),
+            (Self::MS_NODEV, ",nodev"),
+            (Self::MS_NOEXEC, ",noexec"),
+        ] {
+            if self.contains(flag) {
+                res += name;
+            }
+        }
+        res += if self.contains(Self::MS_NOATIME) { ",noatime" } else { ",relatime" };
+        res
+    }
+}
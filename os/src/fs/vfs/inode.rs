@@ -1,11 +1,11 @@
 //! VFS Inode
 
-use core::{ops::Range, sync::atomic::{AtomicUsize, Ordering}};
+use core::{ops::Range, sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering}};
 
-use alloc::{string::String, sync::{Arc, Weak}, vec::Vec};
+use alloc::{collections::btree_map::BTreeMap, string::String, sync::{Arc, Weak}, vec::Vec};
 
-use super::SuperBlock;
-use crate::{fs::{page::{cache::PageCache, page::Page}, Xstat, XstatMask}, generate_atomic_accessors, generate_lock_accessors, generate_with_methods, sync::mutex::SpinNoIrqLock, syscall::SysError, timer::ffi::TimeSpec};
+use super::{fstype::MountFlags, SuperBlock};
+use crate::{fs::{page::{cache::PageCache, page::Page}, Xstat, XstatMask}, generate_atomic_accessors, generate_lock_accessors, generate_with_methods, sync::mutex::SpinNoIrqLock, syscall::SysError, timer::{ffi::TimeSpec, get_realtime_duration}};
 use crate::fs::Kstat;
 
 /// the base Inode of all file system
@@ -19,39 +19,205 @@ pub struct InodeInner {
     /// link count
     pub nlink: AtomicUsize,
     /// mode of inode
-    pub mode: InodeMode,
+    pub mode: SpinNoIrqLock<InodeMode>,
+    /// owner user id
+    pub uid: AtomicU32,
+    /// owner group id
+    pub gid: AtomicU32,
     /// last access time
     pub atime: SpinNoIrqLock<TimeSpec>,
     /// last modification time
     pub mtime: SpinNoIrqLock<TimeSpec>,
-    #[allow(unused)]
-    /// last state change time(todo: support state change)
+    /// last state change time
     pub ctime: SpinNoIrqLock<TimeSpec>,
+    /// creation time, None if the file system does not keep it
+    pub btime: SpinNoIrqLock<Option<TimeSpec>>,
+    /// the mode or the timestamps changed since they were last written back
+    pub meta_dirty: AtomicBool,
+    /// extended attributes of the file systems that keep none on disk
+    pub xattrs: SpinNoIrqLock<BTreeMap<String, Vec<u8>>>,
+    /// the open files of the inode, a mapping of one holds its file open
+    opens: AtomicUsize,
 }
 
+/// atime is updated at least once a day under relatime
+const RELATIME_INTERVAL_SEC: usize = 24 * 60 * 60;
+
 impl InodeInner {
-    /// create a inner using super block
+    /// create a inner using super block, numbered from a counter shared by the
+    /// file systems that keep no inode numbers of their own
     pub fn new(super_block: Option<Weak<dyn SuperBlock>>, mode: InodeMode, size: usize) -> Self {
+        Self::with_ino(super_block, inode_alloc(), mode, size)
+    }
+
+    /// create a inner with the inode number the file system keeps for it
+    pub fn with_ino(super_block: Option<Weak<dyn SuperBlock>>, ino: usize, mode: InodeMode, size: usize) -> Self {
+        // file systems that keep no permission bits give everyone full access
+        let mode = if (mode - InodeMode::TYPE_MASK).is_empty() {
+            mode | InodeMode::OWNER_MASK | InodeMode::GROUP_MASK | InodeMode::OTHER_MASK
+        } else {
+            mode
+        };
         Self {
-            ino: inode_alloc(),
+            ino,
             super_block: super_block,
             size: AtomicUsize::new(size),
             nlink: AtomicUsize::new(1),
-            mode: mode,
+            mode: SpinNoIrqLock::new(mode),
+            uid: AtomicU32::new(0),
+            gid: AtomicU32::new(0),
             atime: SpinNoIrqLock::new(TimeSpec::default()),
             mtime: SpinNoIrqLock::new(TimeSpec::default()),
             ctime: SpinNoIrqLock::new(TimeSpec::default()),
+            btime: SpinNoIrqLock::new(None),
+            meta_dirty: AtomicBool::new(false),
+            xattrs: SpinNoIrqLock::new(BTreeMap::new()),
+            opens: AtomicUsize::new(0),
         }
     }
     generate_atomic_accessors!(
         size: usize,
-        nlink: usize
+        nlink: usize,
+        uid: u32,
+        gid: u32
     );
     generate_lock_accessors!(
+        mode: InodeMode,
         atime: TimeSpec,
         mtime: TimeSpec,
-        ctime: TimeSpec
+        ctime: TimeSpec,
+        btime: Option<TimeSpec>
     );
+
+    /// the flags of the mount the inode lives in, none without a super block
+    pub fn mount_flags(&self) -> MountFlags {
+        self.super_block
+            .as_ref()
+            .and_then(|sb| sb.upgrade())
+            .map_or(MountFlags::empty(), |sb| sb.inner().mount_flags())
+    }
+
+    /// EROFS if the inode lives in a read-only mount
+    pub fn check_writable(&self) -> Result<(), SysError> {
+        if self.mount_flags().contains(MountFlags::MS_RDONLY) {
+            return Err(SysError::EROFS);
+        }
+        Ok(())
+    }
+
+    /// update atime after a read, following relatime:
+    /// only when it is older than mtime or ctime, or older than a day.
+    /// never on a noatime or read-only mount
+    pub fn touch_atime(&self) {
+        if self.mount_flags().intersects(MountFlags::MS_NOATIME | MountFlags::MS_RDONLY) {
+            return;
+        }
+        let now = TimeSpec::from(get_realtime_duration());
+        let key = |t: TimeSpec| (t.tv_sec, t.tv_nsec);
+        let mut atime = self.atime.lock();
+        if key(*atime) <= key(self.mtime()) || key(*atime) <= key(self.ctime())
+            || now.tv_sec >= atime.tv_sec + RELATIME_INTERVAL_SEC {
+            *atime = now;
+            self.meta_dirty.store(true, Ordering::Release);
+        }
+    }
+
+    /// update mtime and ctime after the content changed
+    pub fn touch_mtime(&self) {
+        let now = TimeSpec::from(get_realtime_duration());
+        self.set_mtime(now);
+        self.set_ctime(now);
+        self.meta_dirty.store(true, Ordering::Release);
+    }
+
+    /// update ctime after the metadata changed
+    pub fn touch_ctime(&self) {
+        self.set_ctime(TimeSpec::from(get_realtime_duration()));
+        self.meta_dirty.store(true, Ordering::Release);
+    }
+
+    /// whether `other` lives in the same file system
+    pub fn same_fs(&self, other: &InodeInner) -> bool {
+        let sb = |inner: &InodeInner| inner.super_block.as_ref().map(|sb| sb.as_ptr() as *const ());
+        sb(self) == sb(other)
+    }
+
+    /// stamp a newly created inode with the current time
+    pub fn init_times(&self) {
+        let now = TimeSpec::from(get_realtime_duration());
+        self.set_atime(now);
+        self.set_mtime(now);
+        self.set_ctime(now);
+        self.meta_dirty.store(true, Ordering::Release);
+    }
+
+    /// change the permission bits, keeping the file type
+    pub fn chmod(&self, perm: InodeMode) {
+        let mut mode = self.mode.lock();
+        *mode = mode.get_type() | (perm - InodeMode::TYPE_MASK);
+        drop(mode);
+        self.touch_ctime();
+    }
+
+    /// give a newly created inode to its creator, with the permission bits it asked for
+    pub fn init_owner(&self, uid: u32, gid: u32, perm: InodeMode) {
+        self.set_uid(uid);
+        self.set_gid(gid);
+        self.chmod(perm);
+    }
+
+    /// the open files of the inode
+    pub fn opens(&self) -> usize {
+        self.opens.load(Ordering::Acquire)
+    }
+
+    /// one more open file of the inode, counted in its super block too
+    pub fn open(&self) {
+        self.opens.fetch_add(1, Ordering::AcqRel);
+        if let Some(sb) = self.super_block.as_ref().and_then(|sb| sb.upgrade()) {
+            sb.inner().opens.inc(self.ino);
+        }
+    }
+
+    /// one open file of the inode less, true for the last one
+    pub fn close(&self) -> bool {
+        if let Some(sb) = self.super_block.as_ref().and_then(|sb| sb.upgrade()) {
+            sb.inner().opens.dec(self.ino);
+        }
+        self.opens.fetch_sub(1, Ordering::AcqRel) == 1
+    }
+
+    /// clear the dirty mark, return whether it was set
+    pub fn take_meta_dirty(&self) -> bool {
+        self.meta_dirty.swap(false, Ordering::AcqRel)
+    }
+
+    /// the value of the in-memory extended attribute `name`
+    pub fn get_xattr(&self, name: &str) -> Result<Vec<u8>, SysError> {
+        self.xattrs.lock().get(name).cloned().ok_or(SysError::ENODATA)
+    }
+
+    /// set the in-memory extended attribute `name`
+    pub fn set_xattr(&self, name: &str, value: &[u8], flags: XattrFlags) -> Result<(), SysError> {
+        let mut xattrs = self.xattrs.lock();
+        flags.check(xattrs.contains_key(name))?;
+        xattrs.insert(name.into(), value.into());
+        drop(xattrs);
+        self.touch_ctime();
+        Ok(())
+    }
+
+    /// the names of the in-memory extended attributes
+    pub fn list_xattr(&self) -> Vec<String> {
+        self.xattrs.lock().keys().cloned().collect()
+    }
+
+    /// remove the in-memory extended attribute `name`
+    pub fn remove_xattr(&self, name: &str) -> Result<(), SysError> {
+        self.xattrs.lock().remove(name).ok_or(SysError::ENODATA)?;
+        self.touch_ctime();
+        Ok(())
+    }
 }
 
 /// Inode trait for all file system to implement
@@ -113,6 +279,23 @@ pub trait Inode {
     fn getxattr(&self, _mask: XstatMask) -> Xstat {
         todo!()
     }
+    /// the value of the extended attribute `name`, ENODATA if there is none;
+    /// by default they are kept in memory only
+    fn get_xattr(&self, name: &str) -> Result<Vec<u8>, SysError> {
+        self.inode_inner().get_xattr(name)
+    }
+    /// set the extended attribute `name`, as `flags` allow
+    fn set_xattr(&self, name: &str, value: &[u8], flags: XattrFlags) -> Result<(), SysError> {
+        self.inode_inner().set_xattr(name, value, flags)
+    }
+    /// the names of all the extended attributes, with their namespace
+    fn list_xattr(&self) -> Result<Vec<String>, SysError> {
+        Ok(self.inode_inner().list_xattr())
+    }
+    /// remove the extended attribute `name`, ENODATA if there is none
+    fn remove_xattr(&self, name: &str) -> Result<(), SysError> {
+        self.inode_inner().remove_xattr(name)
+    }
     /// create a symlink of this inode and return the symlink inode
     fn symlink(&self, _target: &str) -> Result<Arc<dyn Inode>, SysError> {
         todo!()
@@ -142,6 +325,35 @@ pub trait Inode {
     fn clean_cached(&self) {
         // do nothing
     }
+    /// write the dirty cached pages back to the disk
+    fn sync_cached(&self) {
+        // do nothing
+    }
+    /// the last open file of the inode is closed,
+    /// a file system which kept it past its last name frees it now
+    fn release(&self) {
+        // do nothing
+    }
+    /// write the mode and the timestamps back to the disk
+    fn sync_meta(&self) -> Result<(), SysError> {
+        Ok(())
+    }
+    /// the seals of the file, only files in memory can be sealed
+    fn seals(&self) -> Result<SealFlags, SysError> {
+        Err(SysError::EINVAL)
+    }
+    /// add seals to the file, EPERM once F_SEAL_SEAL is set
+    fn add_seals(&self, _seals: SealFlags) -> Result<(), SysError> {
+        Err(SysError::EINVAL)
+    }
+}
+
+/// write back the metadata of `inode` if it changed, called on fsync and close
+pub fn sync_inode_meta(inode: &Arc<dyn Inode>) -> Result<(), SysError> {
+    if inode.inode_inner().take_meta_dirty() {
+        inode.sync_meta()?;
+    }
+    Ok(())
 }
 
 static INODE_NUMBER: AtomicUsize = AtomicUsize::new(0);
@@ -150,6 +362,52 @@ fn inode_alloc() -> usize {
     INODE_NUMBER.fetch_add(1, Ordering::Relaxed)
 }
 
+bitflags::bitflags! {
+    /// File seals of F_ADD_SEALS and F_GET_SEALS
+    pub struct SealFlags: u32 {
+        /// no more seals can be added
+        const F_SEAL_SEAL = 0x1;
+        /// the file cannot shrink
+        const F_SEAL_SHRINK = 0x2;
+        /// the file cannot grow
+        const F_SEAL_GROW = 0x4;
+        /// the content cannot be modified
+        const F_SEAL_WRITE = 0x8;
+        /// no new writable mapping, the existing ones still write
+        const F_SEAL_FUTURE_WRITE = 0x10;
+    }
+}
+
+/// the longest name of an extended attribute
+pub const XATTR_NAME_MAX: usize = 255;
+/// the largest value of an extended attribute
+pub const XATTR_SIZE_MAX: usize = 65536;
+/// the longest list of extended attribute names
+pub const XATTR_LIST_MAX: usize = 65536;
+
+bitflags::bitflags! {
+    /// flags of setxattr
+    pub struct XattrFlags: i32 {
+        /// fail with EEXIST if the attribute exists
+        const CREATE = 0x1;
+        /// fail with ENODATA if the attribute does not exist
+        const REPLACE = 0x2;
+    }
+}
+
+impl XattrFlags {
+    /// whether an attribute may be set, given whether it `exists`
+    pub fn check(self, exists: bool) -> Result<(), SysError> {
+        if exists && self.contains(Self::CREATE) {
+            Err(SysError::EEXIST)
+        } else if !exists && self.contains(Self::REPLACE) {
+            Err(SysError::ENODATA)
+        } else {
+            Ok(())
+        }
+    }
+}
+
 bitflags::bitflags! {
     /// Inode mode(use in kstat)
     pub struct InodeMode: u32 {
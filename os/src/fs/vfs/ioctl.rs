@@ -0,0 +1,39 @@
+//! ioctl commands and arguments
+//!
+//! sys_ioctl handles the commands on the fd itself, FIONBIO, FIOCLEX and FIONCLEX,
+//! whatever the file is. Every other command goes to [`File::ioctl`] of the file,
+//! which fails with ENOTTY for a command its type does not know: regular files
+//! take none, pipes take FIONREAD, sockets FIONREAD and the interface queries,
+//! the tty the termios and window size set.
+//!
+//! [`File::ioctl`]: super::File::ioctl
+
+use crate::{mm::UserPtrRaw, syscall::{SysError, SysResult}, task::current_task};
+
+/// int: the bytes which can be read without blocking
+pub const FIONREAD: usize = 0x541B;
+/// int: nonzero sets O_NONBLOCK, zero clears it, as fcntl F_SETFL does
+pub const FIONBIO: usize = 0x5421;
+/// no argument: clear close-on-exec of the fd
+pub const FIONCLEX: usize = 0x5450;
+/// no argument: set close-on-exec of the fd
+pub const FIOCLEX: usize = 0x5451;
+
+/// read the struct the argument of an ioctl points to
+pub fn read_arg<T: Copy>(arg: usize) -> Result<T, SysError> {
+    let task = current_task().unwrap();
+    let ptr = UserPtrRaw::new(arg as *const T)
+        .ensure_read(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?;
+    Ok(*ptr.to_ref())
+}
+
+/// write `val` to where the argument of an ioctl points
+pub fn write_arg<T: Copy>(arg: usize, val: T) -> SysResult {
+    let task = current_task().unwrap();
+    let ptr = UserPtrRaw::new(arg as *mut T)
+        .ensure_write(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?;
+    ptr.write(val);
+    Ok(0)
+}
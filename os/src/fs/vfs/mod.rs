@@ -6,9 +6,18 @@ pub mod superblock;
 pub mod inode;
 pub mod file;
 pub mod dentry;
+pub mod dcache;
+pub mod dir;
+pub mod path;
+pub mod ioctl;
 pub mod fstype;
+pub mod fasync;
 
 pub use superblock::{SuperBlockInner, SuperBlock};
 pub use inode::{InodeInner, Inode};
-pub use file::{FileInner, File};
-pub use dentry::{DentryInner, Dentry, DCACHE, DentryState};
+pub use file::{FileCount, FileInner, File, OpenRef};
+pub use fasync::{FileOwner, FasyncList};
+pub use dentry::{DentryInner, Dentry, DentryState};
+pub use dcache::DCACHE;
+pub use dir::DirFile;
+pub use path::PathFile;
@@ -0,0 +1,62 @@
+//! O_PATH file object
+//!
+//! an fd opened with O_PATH only names a file: nothing of the file system is
+//! opened behind it, and the fd table keeps it away from read, write and the like
+
+use core::sync::atomic::AtomicUsize;
+
+use alloc::{boxed::Box, sync::Arc};
+use async_trait::async_trait;
+
+use crate::{fs::OpenFlags, sync::mutex::SpinNoIrqLock, syscall::{SysError, SysResult}};
+
+use super::{Dentry, File, FileCount, FileInner, FileOwner, OpenRef};
+
+/// a file, directory or symlink opened with O_PATH
+pub struct PathFile {
+    inner: FileInner,
+}
+
+impl PathFile {
+    /// name the file of `dentry`, which is not followed if it is a symlink
+    pub fn new(dentry: Arc<dyn Dentry>) -> Self {
+        Self {
+            inner: FileInner {
+                offset: AtomicUsize::new(0),
+                open: OpenRef::new(&dentry),
+                dentry,
+                flags: SpinNoIrqLock::new(OpenFlags::O_PATH),
+                count: FileCount::new(),
+                owner: FileOwner::new(),
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl File for PathFile {
+    fn file_inner(&self) -> &FileInner {
+        &self.inner
+    }
+    fn readable(&self) -> bool {
+        false
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    async fn read(&self, _buf: &mut [u8]) -> Result<usize, SysError> {
+        Err(SysError::EBADF)
+    }
+    async fn write(&self, _buf: &[u8]) -> Result<usize, SysError> {
+        Err(SysError::EBADF)
+    }
+    async fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize, SysError> {
+        Err(SysError::EBADF)
+    }
+    async fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize, SysError> {
+        Err(SysError::EBADF)
+    }
+    fn ioctl(&self, _cmd: usize, _arg: usize) -> SysResult {
+        Err(SysError::EBADF)
+    }
+}
@@ -1,14 +1,19 @@
 //! vfs super block
 //! 
 use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 
+use alloc::collections::btree_map::BTreeMap;
 use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
 use spin::Once;
 
 use crate::devices::BlockDevice;
 use crate::fs::vfs::Inode;
+use crate::sync::mutex::SpinNoIrqLock;
+use crate::syscall::SysError;
 
-use super::fstype::FSType;
+use super::fstype::{FSType, MountFlags};
 use super::Dentry;
 
 /// the base of super block of all file system
@@ -19,17 +24,187 @@ pub struct SuperBlockInner {
     pub fs_type: Weak<dyn FSType>,
     /// the root dentry to the mount point
     pub root: Once<Arc<dyn Dentry>>,
+    /// the device number reported as st_dev, 0 without a device
+    pub dev: usize,
+    /// the inodes in memory, for the file systems whose inode numbers are stable
+    pub inodes: InodeCache,
+    /// unique among the super blocks since boot, names the file system in a file handle
+    pub id: usize,
+    /// the inodes with dirty cached pages, for the flusher
+    pub dirty: DirtyInodes,
+    /// the open files of the inodes, a file system with any is busy
+    pub opens: OpenInodes,
+    /// the MountFlags of the mount, changed by a remount
+    flags: AtomicU32,
 }
 
+/// the id of the next super block
+static NEXT_SB_ID: AtomicUsize = AtomicUsize::new(1);
+
 impl SuperBlockInner {
     /// create a super block inner with device
     pub fn new(device: Option<Arc<dyn BlockDevice>>, fs_type: Arc<dyn FSType>) -> Self {
         Self {
+            dev: device.as_ref().map_or(0, |d| d.devno()),
             device,
             fs_type: Arc::downgrade(&fs_type),
             root: Once::new(),
+            inodes: InodeCache::new(),
+            dirty: DirtyInodes::new(),
+            opens: OpenInodes::new(),
+            id: NEXT_SB_ID.fetch_add(1, Ordering::Relaxed),
+            flags: AtomicU32::new(0),
         }
     }
+
+    /// the flags of the mount
+    pub fn mount_flags(&self) -> MountFlags {
+        MountFlags::from_bits_truncate(self.flags.load(Ordering::Acquire))
+    }
+
+    /// keep the flags of the mount which outlive it, the others only act on the mount call
+    pub fn set_mount_flags(&self, flags: MountFlags) {
+        let kept = MountFlags::MS_RDONLY | MountFlags::MS_NOSUID | MountFlags::MS_NODEV
+            | MountFlags::MS_NOEXEC | MountFlags::MS_NOATIME;
+        self.flags.store((flags & kept).bits(), Ordering::Release);
+    }
+
+    /// whether the mount is read-only
+    pub fn read_only(&self) -> bool {
+        self.mount_flags().contains(MountFlags::MS_RDONLY)
+    }
+}
+
+/// the inodes of a super block by inode number,
+/// so that every lookup of one inode shares the same instance and page cache
+pub struct InodeCache {
+    inodes: SpinNoIrqLock<BTreeMap<usize, Weak<dyn Inode>>>,
+    /// the times each inode number was freed, the numbers never freed are at 0
+    generations: SpinNoIrqLock<BTreeMap<usize, u32>>,
+}
+
+unsafe impl Send for InodeCache {}
+unsafe impl Sync for InodeCache {}
+
+impl InodeCache {
+    /// create an empty cache
+    pub fn new() -> Self {
+        Self {
+            inodes: SpinNoIrqLock::new(BTreeMap::new()),
+            generations: SpinNoIrqLock::new(BTreeMap::new()),
+        }
+    }
+    /// the inode numbered `ino` if it is still in use,
+    /// otherwise the one `create` makes, which is cached
+    pub fn get_or_insert_with(&self, ino: usize, create: impl FnOnce() -> Arc<dyn Inode>) -> Arc<dyn Inode> {
+        let mut inodes = self.inodes.lock();
+        if let Some(inode) = inodes.get(&ino).and_then(|w| w.upgrade()) {
+            return inode;
+        }
+        let inode = create();
+        inodes.insert(ino, Arc::downgrade(&inode));
+        inode
+    }
+    /// the inode numbered `ino` if it is in use
+    pub fn get(&self, ino: usize) -> Option<Arc<dyn Inode>> {
+        self.inodes.lock().get(&ino).and_then(|w| w.upgrade())
+    }
+    /// forget the inode numbered `ino`, the number may be given to another file
+    pub fn remove(&self, ino: usize) {
+        self.inodes.lock().remove(&ino);
+    }
+    /// forget the inode numbered `ino` which is freed, the file handles to it go stale
+    pub fn retire(&self, ino: usize) {
+        self.remove(ino);
+        let mut generations = self.generations.lock();
+        let generation = generations.entry(ino).or_insert(0);
+        *generation = generation.wrapping_add(1);
+    }
+    /// the generation of the inode number `ino`, the times it was freed
+    pub fn generation(&self, ino: usize) -> u32 {
+        self.generations.lock().get(&ino).copied().unwrap_or(0)
+    }
+    /// drop the entry of `ino` if its inode is no longer in use
+    pub fn remove_dead(&self, ino: usize) {
+        let mut inodes = self.inodes.lock();
+        if inodes.get(&ino).is_some_and(|w| w.strong_count() == 0) {
+            inodes.remove(&ino);
+        }
+    }
+}
+
+/// the inodes of a super block with dirty cached pages by inode number, each once.
+/// An inode is added when a write dirties one of its pages and removed when
+/// its pages are written back
+pub struct DirtyInodes {
+    inodes: SpinNoIrqLock<BTreeMap<usize, Weak<dyn Inode>>>,
+}
+
+unsafe impl Send for DirtyInodes {}
+unsafe impl Sync for DirtyInodes {}
+
+impl DirtyInodes {
+    /// create an empty list
+    pub fn new() -> Self {
+        Self { inodes: SpinNoIrqLock::new(BTreeMap::new()) }
+    }
+    /// add the inode numbered `ino`, if it is not in already
+    pub fn add(&self, ino: usize, inode: &Arc<dyn Inode>) {
+        let mut inodes = self.inodes.lock();
+        if inodes.get(&ino).is_none_or(|w| w.strong_count() == 0) {
+            inodes.insert(ino, Arc::downgrade(inode));
+        }
+    }
+    /// take the inode numbered `ino` out of the list, None if it was not in or is gone
+    pub fn take(&self, ino: usize) -> Option<Arc<dyn Inode>> {
+        self.inodes.lock().remove(&ino).and_then(|w| w.upgrade())
+    }
+    /// the numbers of the inodes in the list
+    pub fn inos(&self) -> Vec<usize> {
+        self.inodes.lock().keys().copied().collect()
+    }
+    /// drop the entry of `ino` if its inode is no longer in use
+    pub fn remove_dead(&self, ino: usize) {
+        let mut inodes = self.inodes.lock();
+        if inodes.get(&ino).is_some_and(|w| w.strong_count() == 0) {
+            inodes.remove(&ino);
+        }
+    }
+}
+
+/// the open files of a super block by inode number, kept by the inodes as they are
+/// opened and closed. An unmount fails while there is any
+pub struct OpenInodes {
+    inodes: SpinNoIrqLock<BTreeMap<usize, usize>>,
+}
+
+impl OpenInodes {
+    /// create an empty table
+    pub fn new() -> Self {
+        Self { inodes: SpinNoIrqLock::new(BTreeMap::new()) }
+    }
+    /// one more open file of the inode numbered `ino`
+    pub fn inc(&self, ino: usize) {
+        *self.inodes.lock().entry(ino).or_insert(0) += 1;
+    }
+    /// one open file of the inode numbered `ino` less, it leaves the table with the last
+    pub fn dec(&self, ino: usize) {
+        let mut inodes = self.inodes.lock();
+        if let Some(count) = inodes.get_mut(&ino) {
+            *count -= 1;
+            if *count == 0 {
+                inodes.remove(&ino);
+            }
+        }
+    }
+    /// the open files of the whole file system
+    pub fn total(&self) -> usize {
+        self.inodes.lock().values().sum()
+    }
+    /// the inode numbers with open files and how many each has
+    pub fn list(&self) -> Vec<(usize, usize)> {
+        self.inodes.lock().iter().map(|(&ino, &count)| (ino, count)).collect()
+    }
 }
 
 /// super block trait left for file system implement
@@ -42,6 +217,23 @@ pub trait SuperBlock: Send + Sync {
     }
     /// get root dir inode (will only use construct)
     fn get_root_inode(&'static self, name: &str) -> Arc<dyn Inode>;
+    /// write back what the file system caches and leave the device clean,
+    /// nothing may use the file system afterwards
+    fn unmount(&self) -> Result<(), SysError> {
+        Ok(())
+    }
+    /// write back the blocks the file system caches itself, the file system stays usable
+    fn sync(&self) -> Result<(), SysError> {
+        Ok(())
+    }
+    /// whether the inode numbers are stable, so a file handle can name a file by them
+    fn file_handles(&self) -> bool {
+        false
+    }
+    /// find a name of the inode numbered `ino` which is not in memory, for open_by_handle_at
+    fn lookup_ino(&self, _ino: usize) -> Result<Arc<dyn Dentry>, SysError> {
+        Err(SysError::EOPNOTSUPP)
+    }
 }
 
 impl dyn SuperBlock {
@@ -0,0 +1,202 @@
+//! writeback of the dirty cached pages
+//!
+//! a write to a file on disk only dirties its cached pages and puts the inode on the
+//! dirty list of its super block. The flusher, a kernel task, wakes every
+//! [`DIRTY_WRITEBACK_MS`] and writes back the pages dirty for longer than
+//! [`DIRTY_EXPIRE_MS`], or is kicked once [`DIRTY_BACKGROUND_PAGES`] pages were dirtied
+//! since its last pass and writes back everything. It writes at most [`BATCH_PAGES`]
+//! pages at a time and yields between the batches. sync and fsync drain the lists
+//! themselves, so little is left to write on shutdown
+
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use alloc::sync::Arc;
+use log::warn;
+
+use crate::{
+    drivers::block::queue,
+    sync::mutex::SpinNoIrqLock,
+    syscall::SysError,
+    sysctl::IntParam,
+    task::schedule::spawn_kernel_task,
+    timer::{get_current_time_ms, timed_task::{TimedTaskFuture, TimedTaskOutput}},
+    utils::yield_now,
+};
+
+use super::{mounted_sbs, vfs::{inode::sync_inode_meta, Inode, SuperBlock}};
+
+/// sysctl vm/dirty-writeback-ms: how often the flusher wakes
+pub static DIRTY_WRITEBACK_MS: IntParam = IntParam::new(5000, 100, 600_000);
+/// sysctl vm/dirty-expire-ms: how long a page stays dirty before the flusher writes it back
+pub static DIRTY_EXPIRE_MS: IntParam = IntParam::new(30_000, 0, 3_600_000);
+/// sysctl vm/dirty-background-pages: the pages dirtied since the last pass which kick
+/// the flusher, 0 for none
+pub static DIRTY_BACKGROUND_PAGES: IntParam = IntParam::new(4096, 0, 1 << 24);
+
+/// the most pages written back between two yields of the flusher
+const BATCH_PAGES: usize = 256;
+/// the longest the flusher sleeps before it looks at its interval again,
+/// so that a shorter one set meanwhile takes effect
+const MAX_NAP_MS: usize = 1000;
+
+/// the pages dirtied since the flusher last started a pass
+static DIRTIED: AtomicUsize = AtomicUsize::new(0);
+/// set by a kick the flusher has not seen yet
+static KICKED: AtomicBool = AtomicBool::new(false);
+/// the waker of the flusher while it sleeps
+static FLUSHER: SpinNoIrqLock<Option<Waker>> = SpinNoIrqLock::new(None);
+
+/// wake the flusher for a pass over every dirty page
+fn kick() {
+    if !KICKED.swap(true, Ordering::AcqRel) {
+        if let Some(waker) = FLUSHER.lock().take() {
+            waker.wake();
+        }
+    }
+}
+
+/// ready once the flusher is kicked
+struct Kicked;
+
+impl Future for Kicked {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if KICKED.swap(false, Ordering::AcqRel) {
+            return Poll::Ready(());
+        }
+        *FLUSHER.lock() = Some(cx.waker().clone());
+        // a kick between the check and the waker being stored found no waker
+        if KICKED.swap(false, Ordering::AcqRel) {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+/// `pages` pages of `inode` went dirty: put it on the dirty list of its super block.
+/// The file systems in memory have nothing to write back and are left out
+pub fn mark_dirty(inode: &Arc<dyn Inode>, pages: usize) {
+    let inner = inode.inode_inner();
+    let Some(sb) = inner.super_block.as_ref().and_then(|sb| sb.upgrade()) else {
+        return;
+    };
+    if sb.inner().device.is_none() {
+        return;
+    }
+    sb.inner().dirty.add(inner.ino, inode);
+    let threshold = DIRTY_BACKGROUND_PAGES.get();
+    if pages > 0 && threshold != 0 && DIRTIED.fetch_add(pages, Ordering::Relaxed) + pages >= threshold {
+        kick();
+    }
+}
+
+/// write back the pages of the inodes of `sb` dirtied before `before` ms, at most `budget` of
+/// them. The inodes left with dirty pages stay on the list. Returns the pages written
+fn write_back_sb(sb: &Arc<dyn SuperBlock>, before: usize, budget: usize) -> usize {
+    let dirty = &sb.inner().dirty;
+    let mut left = budget;
+    for ino in dirty.inos() {
+        if left == 0 {
+            break;
+        }
+        // taken off before the pages are looked at, a write after it puts the inode back
+        let Some(inode) = dirty.take(ino) else {
+            continue;
+        };
+        match inode.cache().write_back_expired(&*inode, before, &mut left) {
+            Ok(false) => {}
+            Ok(true) => dirty.add(ino, &inode),
+            Err(e) => {
+                warn!("[writeback] inode {} failed: {:?}", ino, SysError::from(e));
+                dirty.add(ino, &inode);
+                continue;
+            }
+        }
+        if let Err(e) = sync_inode_meta(&inode) {
+            warn!("[writeback] meta of inode {} failed: {:?}", ino, e);
+        }
+    }
+    let written = budget - left;
+    if written > 0 {
+        // the data is only safe once the blocks the file system caches are out too
+        if let Err(e) = sb.sync() {
+            warn!("[writeback] sync of the file system failed: {:?}", e);
+        }
+    }
+    written
+}
+
+/// one batch of a pass: write back at most `BATCH_PAGES` pages dirtied before `before` ms,
+/// returns the pages written
+fn write_back_batch(before: usize) -> usize {
+    let mut written = 0;
+    for sb in mounted_sbs() {
+        if written == BATCH_PAGES {
+            break;
+        }
+        if sb.inner().read_only() {
+            continue;
+        }
+        written += write_back_sb(&sb, before, BATCH_PAGES - written);
+    }
+    queue::flush_all();
+    written
+}
+
+/// write back every dirty page of `sb`, for sync and a read-only remount
+pub fn drain_sb(sb: &Arc<dyn SuperBlock>) {
+    write_back_sb(sb, usize::MAX, usize::MAX);
+}
+
+/// write back the dirty pages and the metadata of `inode` and take it off the dirty list,
+/// for fsync
+pub fn drain_inode(inode: &Arc<dyn Inode>) -> Result<(), SysError> {
+    let inner = inode.inode_inner();
+    let sb = inner.super_block.as_ref().and_then(|sb| sb.upgrade());
+    if let Some(sb) = sb.as_ref() {
+        sb.inner().dirty.take(inner.ino);
+    }
+    inode.sync_cached();
+    sync_inode_meta(inode)?;
+    match sb {
+        Some(sb) => sb.sync(),
+        None => Ok(()),
+    }
+}
+
+/// the flusher: sleep until the interval is over or a kick, then write back what expired,
+/// or everything after a kick, a batch at a time
+async fn flusher() {
+    let mut last = get_current_time_ms();
+    loop {
+        let due = last + DIRTY_WRITEBACK_MS.get();
+        let nap = due.saturating_sub(get_current_time_ms()).min(MAX_NAP_MS);
+        let kicked = matches!(
+            TimedTaskFuture::new(Duration::from_millis(nap as u64), Kicked).await,
+            TimedTaskOutput::OK(())
+        );
+        let now = get_current_time_ms();
+        if !kicked && now < last + DIRTY_WRITEBACK_MS.get() {
+            continue;
+        }
+        last = now;
+        DIRTIED.store(0, Ordering::Relaxed);
+        let before = if kicked { usize::MAX } else { now.saturating_sub(DIRTY_EXPIRE_MS.get()) };
+        // a batch short of the limit found nothing more to write
+        while write_back_batch(before) == BATCH_PAGES {
+            yield_now().await;
+        }
+    }
+}
+
+/// start the flusher, once the executor takes tasks
+pub fn spawn_flusher() {
+    spawn_kernel_task(flusher());
+}
@@ -1,7 +1,7 @@
 use async_trait::async_trait;
 use alloc::{borrow::ToOwned, boxed::Box, collections::{btree_map::BTreeMap, btree_set::BTreeSet}, sync::{Arc, Weak}, vec::Vec};
 use hal::{addr::RangePPNHal, constant::{Constant, ConstantsHal}, println};
-use crate::{fs::{page::{cache::PageCache, page::Page}, vfs::{File, FileInner, Inode}}, mm::allocator::{FrameAllocator, SlabAllocator}, sync::mutex::SpinNoIrqLock, syscall::SysError, task::{TidAllocator, TidHandle}, timer::get_current_time_sec};
+use crate::{fs::{page::{cache::PageCache, page::Page}, vfs::{File, FileInner, Inode}}, mm::allocator::{FrameAllocator, SlabAllocator}, sync::mutex::SpinNoIrqLock, syscall::SysError, task::{TidAllocator, TidHandle}, timer::get_realtime_duration};
 
 use super::IpcPerm;
 
@@ -36,7 +36,7 @@ impl ShmIdDs {
             segsz: sz,
             atime: 0,
             dtime: 0,
-            ctime: get_current_time_sec(),
+            ctime: get_realtime_duration().as_secs() as usize,
             cpid: cpid,
             lpid: 0,
             nattch: 0,
@@ -45,7 +45,7 @@ impl ShmIdDs {
 
     pub fn attach(&mut self, lpid: usize) {
         // shm_atime is set to the current time.
-        self.atime = get_current_time_sec();
+        self.atime = get_realtime_duration().as_secs() as usize;
         // shm_lpid is set to the process-ID of the calling process.
         self.lpid = lpid;
         // shm_nattch is incremented by one.
@@ -56,7 +56,7 @@ impl ShmIdDs {
     /// which self ShmIdDs belongs to;
     pub fn detach(&mut self, lpid: usize) -> bool {
         // shm_dtime is set to the current time.
-        self.dtime = get_current_time_sec();
+        self.dtime = get_realtime_duration().as_secs() as usize;
         // shm_lpid is set to the process-ID of the calling process.
         self.lpid = lpid;
         // shm_nattch is decremented by one.
@@ -1,24 +1,100 @@
 //! The panic handler
+//!
+//! The report neither allocates nor waits for the console lock, so a panic inside the
+//! allocator or the logger still gets out: the message, the registers if a kernel trap
+//! was being handled, the current task with the syscall it is in, and a backtrace
+//! resolved against the symbol table in [`crate::utils::ksyms`]. The other harts stop
+//! scheduling and print what they were running before the power goes.
 
 use hal::instruction::{Instruction, InstructionHal};
-use core::{arch::asm, panic::PanicInfo};
-use hal::{addr::VirtAddrHal, constant::{Constant, ConstantsHal}, println};
+use core::{arch::asm, fmt, panic::PanicInfo, sync::atomic::{AtomicUsize, Ordering}};
+use hal::{addr::VirtAddrHal, board::MAX_PROCESSORS, constant::{Constant, ConstantsHal}, println};
 use log::*;
 use hal::addr::VirtAddr;
 
+use crate::{processor::{ipi::park_other_harts_for_panic, processor::current_task}, syscall::trace::syscall_name, task::task::NO_SYSCALL, utils::ksyms};
+
+/// the hart reporting a panic, MAX_PROCESSORS while none is
+static PANIC_HART: AtomicUsize = AtomicUsize::new(MAX_PROCESSORS);
+/// return addresses printed at most
+const BACKTRACE_DEPTH: usize = 32;
+
+/// whether some hart is reporting a panic
+pub fn panic_in_progress() -> bool {
+    PANIC_HART.load(Ordering::Acquire) != MAX_PROCESSORS
+}
+
+/// print `addr` after `label` as the symbol it lies in
+pub(crate) fn print_symbol(label: fmt::Arguments, addr: usize) {
+    match ksyms::lookup(addr) {
+        Some((name, offset)) => println!("  {} {:#018x} {}+{:#x}", label, addr, name, offset),
+        None => println!("  {} {:#018x}", label, addr),
+    }
+}
+
+/// print the task running on this hart and the syscall it is in, taking no lock that may be held
+pub fn print_current_task(hart: usize) {
+    let Some(task) = current_task() else {
+        println!("[kernel] hart {} runs no task", hart);
+        return;
+    };
+    let comm = task.comm.try_lock();
+    let comm = comm.as_deref().map_or("<locked>", |comm| comm.as_str());
+    let syscall = task.current_syscall();
+    if syscall == NO_SYSCALL {
+        println!("[kernel] hart {} runs tid {} ({}), not in a syscall", hart, task.tid(), comm);
+    } else {
+        println!("[kernel] hart {} runs tid {} ({}), in syscall {} ({})", hart, task.tid(), comm, syscall_name(syscall), syscall);
+    }
+}
+
+/// walk the frame pointers of the kernel stack from the caller up
+fn print_backtrace() {
+    let mut addrs = [0usize; BACKTRACE_DEPTH];
+    let n = hal::util::return_addrs(&mut addrs);
+    println!("backtrace:");
+    for (i, &addr) in addrs[..n].iter().enumerate() {
+        print_symbol(format_args!("#{:<2}", i), addr);
+    }
+}
+
 #[panic_handler]
 /// panic handler
 fn panic(info: &PanicInfo) -> ! {
-    hal::util::backtrace();
+    // the panic may come from inside the console, never wait for its lock from now on
+    hal::console::set_panicking();
+    unsafe { Instruction::disable_interrupt() };
+    let hart = Instruction::get_tp();
+    if let Err(reporting) = PANIC_HART.compare_exchange(MAX_PROCESSORS, hart, Ordering::AcqRel, Ordering::Acquire) {
+        if reporting == hart {
+            println!("[kernel] Panicked again while reporting: {}", info.message());
+            unsafe { Instruction::shutdown(true) }
+        }
+        // the reporting hart powers off soon
+        println!("[kernel] hart {} panicked too: {}", hart, info.message());
+        loop {
+            core::hint::spin_loop();
+        }
+    }
     if let Some(location) = info.location() {
-        error!(
-            "[kernel] Panicked at {}:{} {}",
+        println!(
+            "[kernel] Panicked on hart {} at {}:{} {}",
+            hart,
             location.file(),
             location.line(),
             info.message()
         );
     } else {
-        error!("[kernel] Panicked: {}", info.message());
+        println!("[kernel] Panicked on hart {}: {}", hart, info.message());
+    }
+    if let Some(frame) = hal::trap::kernel_trap_frame() {
+        println!("in a kernel trap from:");
+        print_symbol(format_args!("pc "), frame.pc());
+        frame.dump();
     }
+    print_current_task(hart);
+    print_backtrace();
+    hal::util::smart_point::debug_dump();
+    park_other_harts_for_panic();
     unsafe { Instruction::shutdown(true) }
 }
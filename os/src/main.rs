@@ -58,6 +58,7 @@ pub mod mm;
 //pub mod sbi;
 pub mod sync;
 pub mod syscall;
+pub mod sysctl;
 pub mod signal;
 pub mod task;
 mod processor;
@@ -65,6 +66,8 @@ pub mod timer;
 pub mod trap;
 mod executor;
 pub mod utils;
+#[cfg(feature = "selftest")]
+pub mod selftest;
 
 use core::{arch::{global_asm, naked_asm}, sync::atomic::{AtomicBool,Ordering}};
 
@@ -88,17 +91,39 @@ fn processor_start(id: usize) {
 /// return true if need reboot (but not supported yet)
 fn main(id: usize, first: bool) -> bool {
     if first {
+        // kernel log records also go to the ring read by syslog
+        hal::console::set_log_sink(utils::klog::klog_write);
         info!("id: {id}");
         banner::print_banner();
+        #[cfg(debug_assertions)]
+        mm::kstack::init_watermark();
         devices::init();
+        // a host name on the command line tells the kernels of a test network apart in their logs
+        if let Some(name) = devices::bootarg("hostname") {
+            let _ = fs::HOSTNAME.set(name.as_bytes());
+        }
+        hal::console::set_log_host(fs::write_log_host);
+        // randomize_va_space=0 gives every run the same layout, to reproduce a bug at an address
+        if let Some(level) = devices::bootarg("randomize_va_space") {
+            let _ = sysctl::parse_int(level.as_bytes()).and_then(|level| mm::vm::aslr::RANDOMIZE_VA_SPACE.set(level));
+        }
         processor::processor::init(id);
         hal::trap::init();
+        #[cfg(feature = "selftest")]
+        selftest::run_stage(selftest::Stage::Mm);
         fs::init();
+        #[cfg(feature = "selftest")]
+        {
+            selftest::run_stage(selftest::Stage::Fs);
+            selftest::run_stage(selftest::Stage::Sync);
+        }
         // fs::vfs::file::list_apps(); 
         net::init_network();
         // fs::ext4::page_cache_test();       
         #[cfg(not(feature = "smp"))]
         executor::init();
+        fs::writeback::spawn_flusher();
+        net::poller::spawn_poller();
         task::schedule::spawn_kernel_task(
             async move{
                 task::add_initproc();
@@ -106,17 +131,25 @@ fn main(id: usize, first: bool) -> bool {
         );
 
         #[cfg(feature = "smp")]
-        processor_start(id);
+        {
+            processor_start(id);
+            timer::skew::serve(id);
+        }
     } else {
         processor::processor::init(id);
         hal::trap::init();
+        timer::skew::calibrate(id);
     }
     info!("[kernel] -------hart {} start-------",id);
+    devices::init_hart_irq(id);
     unsafe { 
         Instruction::enable_timer_interrupt();
+        Instruction::enable_software_interrupt();
     }
     timer::set_next_trigger();
     executor::run_until_shutdown();
+    #[cfg(debug_assertions)]
+    mm::kstack::report_watermark();
     // return false: HAL will shutdown
     false
 }
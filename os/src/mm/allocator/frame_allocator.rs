@@ -2,7 +2,6 @@
 //! controls all the frames in the operating system.
 use crate::sync::mutex::spin_mutex::SpinMutex;
 use crate::sync::mutex::{Spin, SpinNoIrqLock};
-use crate::sync::UPSafeCell;
 use alloc::alloc::Allocator;
 use alloc::vec::Vec;
 use bitmap_allocator::{BitAlloc, BitAlloc16M, BitAlloc4K};
@@ -31,14 +30,18 @@ struct BitMapFrameAllocator {
     align_log2: usize,
     inner: bitmap_allocator::BitAlloc16M,
     last: usize,
+    /// frames handed to the allocator
+    total: usize,
 }
 
 impl FrameAllocatorTrait for BitMapFrameAllocator {
     const DEFAULT: Self = BitMapFrameAllocator {
         range: PhysPageNum(0)..PhysPageNum(0),
-        align_log2: 8,
+        // 2 MiB aligned, so that huge frames can be handed out
+        align_log2: 9,
         inner: bitmap_allocator::BitAlloc16M::DEFAULT,
-        last: 0
+        last: 0,
+        total: 0,
     };
 
     fn init(&mut self, range_pa: Range<PhysAddr>) {
@@ -51,6 +54,7 @@ impl FrameAllocatorTrait for BitMapFrameAllocator {
         let beg = start.0 - aligned_range_ppn.start.0;
         let end = aligned_range_ppn.end.0 - aligned_range_ppn.start.0;
         self.last = end - beg;
+        self.total = end - beg;
         info!("[FrameAllocator] pages: {}", self.last);
         self.inner.insert(beg..end);
     }
@@ -104,7 +108,10 @@ impl FrameAllocatorHal for FrameAllocator {
         alloc_guard.alloc_contiguous(cnt, align_log2)
     }
 
+    /// safe with the interrupts off, the lock keeps them off while held.
+    /// The last owner of a frame must not go away under this lock, e.g. from the allocator itself
     fn dealloc(&self, range_ppn: Range<PhysPageNum>) {
+        debug_assert!(!FRAME_ALLOCATOR.held_by_current(), "frames {:?} freed under the frame allocator lock", range_ppn);
         let mut alloc_guard = FRAME_ALLOCATOR.lock();
         alloc_guard.dealloc_contiguous(range_ppn)
     }
@@ -127,14 +134,14 @@ unsafe impl Allocator for FrameAllocator {
     }
 }
 
-/// initiate the frame allocator using `ekernel` and `MEMORY_END`
+/// initiate the frame allocator using `ekernel` and the end of memory from the device tree
 pub fn init_frame_allocator() {
     extern "C" {
         fn ekernel();
     }
 
     FRAME_ALLOCATOR.lock().init(
-        PhysAddr::from(ekernel as usize & !Constant::KERNEL_ADDR_SPACE.start)..PhysAddr::from(Constant::MEMORY_END),
+        PhysAddr::from(ekernel as usize & !Constant::KERNEL_ADDR_SPACE.start)..PhysAddr::from(hal::board::memory_end()),
     );
 }
 
@@ -155,6 +162,21 @@ pub fn frames_alloc_clean(size: usize) -> Option<FrameTracker> {
     })
 }
 
+/// allocate contiguous frames whose first ppn is aligned to `1 << align_log2` pages
+pub fn frames_alloc_aligned(size: usize, align_log2: usize) -> Option<FrameTracker> {
+    FrameAllocator
+        .alloc_with_align(size, align_log2)
+        .map(|ppn| {
+            FrameTracker::new_in(ppn, FrameAllocator)
+        })
+}
+
+/// the number of free frames and of all the frames
+pub fn frames_stat() -> (usize, usize) {
+    let allocator = FRAME_ALLOCATOR.lock();
+    (allocator.last, allocator.total)
+}
+
 /// deallocate frames
 pub fn frames_dealloc(range_ppn: Range<PhysPageNum>) {
     if range_ppn.clone().count() > 0 {
@@ -1,6 +1,6 @@
 //! The global allocator
 const KERNEL_HEAP_SIZE: usize = 256*1024*1024; // 64 MiB reserved for operating system
-use core::{alloc::{GlobalAlloc, Layout}, ptr::NonNull};
+use core::{alloc::{GlobalAlloc, Layout}, fmt, ptr::NonNull, sync::atomic::{AtomicUsize, Ordering}};
 
 use alloc::alloc::Allocator;
 use buddy_system_allocator::{Heap, LockedHeap};
@@ -8,6 +8,8 @@ use hal::println;
 
 use crate::sync::mutex::SpinNoIrqLock;
 
+use super::slab_allocator::SLAB_ALLOCATOR_INNER;
+
 /// heap allocator instance
 static HEAP_INSTANCE: SpinNoIrqLock<Heap> = SpinNoIrqLock::new(Heap::empty());
 
@@ -16,8 +18,12 @@ static HEAP_INSTANCE: SpinNoIrqLock<Heap> = SpinNoIrqLock::new(Heap::empty());
 static HEAP_ALLOCATOR: HeapAllocator = HeapAllocator;
 
 #[alloc_error_handler]
-/// panic when heap allocation error occurs
+/// print the heap statistics and panic when heap allocation error occurs
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
+    println!("{}", heap_stats());
+    hal::util::smart_point::debug_dump();
+    #[cfg(feature = "heap_trace")]
+    trace::dump();
     panic!("Heap allocation error, layout = {:?}", layout);
 }
 
@@ -27,28 +33,234 @@ pub struct HeapAllocator;
 
 unsafe impl Allocator for HeapAllocator {
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, alloc::alloc::AllocError> {
-        HEAP_INSTANCE
-            .lock()
-            .alloc(layout)
+        heap_alloc(layout)
             .map(|ptr| NonNull::slice_from_raw_parts(ptr, layout.size()))
-            .map_err(|_| alloc::alloc::AllocError)
+            .ok_or(alloc::alloc::AllocError)
     }
 
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
-        HEAP_INSTANCE.lock().dealloc(ptr, layout)
+        heap_dealloc(ptr, layout)
     }
 }
 
 unsafe impl GlobalAlloc for HeapAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        HEAP_INSTANCE
-            .lock()
-            .alloc(layout).ok()
-            .map_or(0 as *mut u8, |allocation| allocation.as_ptr())
+        heap_alloc(layout).map_or(0 as *mut u8, |allocation| allocation.as_ptr())
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        HEAP_INSTANCE.lock().dealloc(NonNull::new_unchecked(ptr), layout)
+        heap_dealloc(NonNull::new_unchecked(ptr), layout)
+    }
+}
+
+/// allocate from the heap, shrink the slab caches and retry once before giving up.
+/// Only the slab caches are shrunk: the page cache keeps its pages in frames rather than
+/// in the heap, and is trimmed against its own limit on a read miss
+fn heap_alloc(layout: Layout) -> Option<NonNull<u8>> {
+    let mut ptr = HEAP_INSTANCE.lock().alloc(layout).ok();
+    if ptr.is_none() {
+        SLAB_ALLOCATOR_INNER.try_shrink();
+        ptr = HEAP_INSTANCE.lock().alloc(layout).ok();
+    }
+    match ptr {
+        Some(ptr) => {
+            STATS.on_alloc(layout.size());
+            #[cfg(feature = "heap_trace")]
+            trace::on_alloc(ptr.as_ptr() as usize, layout.size());
+        }
+        None => {
+            STATS.failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    ptr
+}
+
+fn heap_dealloc(ptr: NonNull<u8>, layout: Layout) {
+    #[cfg(feature = "heap_trace")]
+    trace::on_dealloc(ptr.as_ptr() as usize, layout.size());
+    HEAP_INSTANCE.lock().dealloc(ptr, layout);
+    STATS.on_dealloc(layout.size());
+}
+
+/// upper bounds of the size classes, the last class takes everything larger
+const SIZE_CLASSES: [usize; 8] = [16, 64, 256, 1024, 4096, 16384, 65536, 262144];
+const NR_CLASSES: usize = SIZE_CLASSES.len() + 1;
+
+fn size_class(size: usize) -> usize {
+    SIZE_CLASSES.iter().position(|&bound| size <= bound).unwrap_or(SIZE_CLASSES.len())
+}
+
+struct ClassCounter {
+    allocs: AtomicUsize,
+    live: AtomicUsize,
+    live_bytes: AtomicUsize,
+}
+
+impl ClassCounter {
+    const fn new() -> Self {
+        Self {
+            allocs: AtomicUsize::new(0),
+            live: AtomicUsize::new(0),
+            live_bytes: AtomicUsize::new(0),
+        }
+    }
+}
+
+struct Counters {
+    allocated: AtomicUsize,
+    peak: AtomicUsize,
+    allocs: AtomicUsize,
+    frees: AtomicUsize,
+    failures: AtomicUsize,
+    classes: [ClassCounter; NR_CLASSES],
+}
+
+impl Counters {
+    fn on_alloc(&self, size: usize) {
+        let allocated = self.allocated.fetch_add(size, Ordering::Relaxed) + size;
+        self.peak.fetch_max(allocated, Ordering::Relaxed);
+        self.allocs.fetch_add(1, Ordering::Relaxed);
+        let class = &self.classes[size_class(size)];
+        class.allocs.fetch_add(1, Ordering::Relaxed);
+        class.live.fetch_add(1, Ordering::Relaxed);
+        class.live_bytes.fetch_add(size, Ordering::Relaxed);
+    }
+
+    fn on_dealloc(&self, size: usize) {
+        self.allocated.fetch_sub(size, Ordering::Relaxed);
+        self.frees.fetch_add(1, Ordering::Relaxed);
+        let class = &self.classes[size_class(size)];
+        class.live.fetch_sub(1, Ordering::Relaxed);
+        class.live_bytes.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+static STATS: Counters = Counters {
+    allocated: AtomicUsize::new(0),
+    peak: AtomicUsize::new(0),
+    allocs: AtomicUsize::new(0),
+    frees: AtomicUsize::new(0),
+    failures: AtomicUsize::new(0),
+    classes: [const { ClassCounter::new() }; NR_CLASSES],
+};
+
+/// usage of one size class
+#[derive(Clone, Copy, Default)]
+pub struct ClassStats {
+    /// allocations ever made in the class
+    pub allocs: usize,
+    /// allocations still alive
+    pub live: usize,
+    /// bytes still alive
+    pub live_bytes: usize,
+}
+
+/// a snapshot of the kernel heap usage, in bytes
+#[derive(Clone, Copy)]
+pub struct HeapStats {
+    pub total: usize,
+    pub allocated: usize,
+    pub peak: usize,
+    pub allocs: usize,
+    pub frees: usize,
+    pub failures: usize,
+    pub classes: [ClassStats; NR_CLASSES],
+}
+
+/// take a snapshot of the heap statistics, allocates nothing so it works on allocation failure
+pub fn heap_stats() -> HeapStats {
+    let mut classes = [ClassStats::default(); NR_CLASSES];
+    for (stats, counter) in classes.iter_mut().zip(STATS.classes.iter()) {
+        stats.allocs = counter.allocs.load(Ordering::Relaxed);
+        stats.live = counter.live.load(Ordering::Relaxed);
+        stats.live_bytes = counter.live_bytes.load(Ordering::Relaxed);
+    }
+    HeapStats {
+        total: KERNEL_HEAP_SIZE,
+        allocated: STATS.allocated.load(Ordering::Relaxed),
+        peak: STATS.peak.load(Ordering::Relaxed),
+        allocs: STATS.allocs.load(Ordering::Relaxed),
+        frees: STATS.frees.load(Ordering::Relaxed),
+        failures: STATS.failures.load(Ordering::Relaxed),
+        classes,
+    }
+}
+
+impl fmt::Display for HeapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "[heap] total {:#x}, allocated {:#x}, peak {:#x}, allocs {}, frees {}, failures {}",
+            self.total, self.allocated, self.peak, self.allocs, self.frees, self.failures
+        )?;
+        for (i, class) in self.classes.iter().enumerate() {
+            match SIZE_CLASSES.get(i) {
+                Some(bound) => write!(f, "[heap]   <= {:>6}:", bound)?,
+                None => write!(f, "[heap]    > {:>6}:", SIZE_CLASSES[SIZE_CLASSES.len() - 1])?,
+            }
+            writeln!(f, " allocs {}, live {}, live bytes {:#x}", class.allocs, class.live, class.live_bytes)?;
+        }
+        Ok(())
+    }
+}
+
+/// the recent large allocations and who made them, to attribute leaks
+#[cfg(feature = "heap_trace")]
+mod trace {
+    use hal::println;
+
+    use crate::sync::mutex::SpinNoIrqLock;
+
+    /// allocations from this size on are recorded
+    const LARGE: usize = 4096;
+    const RING_SIZE: usize = 32;
+    const DEPTH: usize = 6;
+
+    #[derive(Clone, Copy)]
+    struct Record {
+        addr: usize,
+        size: usize,
+        callers: [usize; DEPTH],
+    }
+
+    struct Ring {
+        records: [Option<Record>; RING_SIZE],
+        next: usize,
+    }
+
+    static RING: SpinNoIrqLock<Ring> = SpinNoIrqLock::new(Ring { records: [None; RING_SIZE], next: 0 });
+
+    pub fn on_alloc(addr: usize, size: usize) {
+        if size < LARGE {
+            return;
+        }
+        let mut callers = [0; DEPTH];
+        hal::util::return_addrs(&mut callers);
+        let mut ring = RING.lock();
+        let next = ring.next;
+        ring.records[next] = Some(Record { addr, size, callers });
+        ring.next = (next + 1) % RING_SIZE;
+    }
+
+    pub fn on_dealloc(addr: usize, size: usize) {
+        if size < LARGE {
+            return;
+        }
+        let mut ring = RING.lock();
+        if let Some(slot) = ring.records.iter_mut().find(|r| r.is_some_and(|r| r.addr == addr)) {
+            *slot = None;
+        }
+    }
+
+    /// print the recorded allocations still alive
+    pub fn dump() {
+        let Some(ring) = RING.try_lock() else {
+            return;
+        };
+        println!("[heap] recent large allocations still alive:");
+        for record in ring.records.iter().flatten() {
+            println!("[heap]   {:#x} size {:#x} callers {:x?}", record.addr, record.size, record.callers);
+        }
     }
 }
 
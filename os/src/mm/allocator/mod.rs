@@ -3,11 +3,11 @@ mod heap_allocator;
 mod slab_allocator;
 
 #[allow(unused)]
-pub use frame_allocator::{FrameAllocator, init_frame_allocator, frames_alloc, frames_alloc_clean, frames_dealloc};
+pub use frame_allocator::{FrameAllocator, init_frame_allocator, frames_alloc, frames_alloc_aligned, frames_alloc_clean, frames_dealloc, frames_stat};
 #[allow(unused)]
-pub use heap_allocator::{handle_alloc_error, init_heap, HeapAllocator};
+pub use heap_allocator::{handle_alloc_error, heap_stats, init_heap, HeapAllocator, HeapStats};
 #[allow(unused)]
-pub use slab_allocator::{SlabAllocator, SlabCache};
+pub use slab_allocator::{SlabAllocator, SlabCache, SLAB_ALLOCATOR_INNER};
 
 /// next power of two
 #[cfg(target_pointer_width="32")]
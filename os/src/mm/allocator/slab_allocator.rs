@@ -115,6 +115,29 @@ impl SlabAllocatorInner {
         layout.size() <= 8192 && layout.align() <= layout.size() && layout.align() <= 4096
     }
 
+    /// release the useless frames of the caches nobody is using right now,
+    /// safe to call from inside an allocation
+    pub fn try_shrink(&self) {
+        // the freed blocks go back to SLAB_BLOCK_SLAB_CACHE, the allocation may come from inside it
+        if SLAB_BLOCK_SLAB_CACHE.held_by_current() {
+            return;
+        }
+        macro_rules! try_shrink {
+            ($($cache:ident),*) => {
+                $(if let Some(mut cache) = self.$cache.try_lock() {
+                    cache.shrink();
+                })*
+            };
+        }
+        try_shrink!(
+            cache8, cache16, cache32, cache64, cache96, cache128, cache192,
+            cache256, cache512, cache1024, cache2048, cache4096, cache8192
+        );
+        if let Some(mut cache) = SLAB_BLOCK_SLAB_CACHE.try_lock() {
+            cache.shrink();
+        }
+    }
+
     /// release useless frames
     pub fn shrink(&self) {
         self.cache8.lock().shrink();
@@ -677,12 +700,19 @@ impl<const S: usize> SmallSlabCache<S> {
         NonNull::new(ret as *mut u8)
     }
 
+    /// whether `ptr` lies in a block of this cache, a dealloc of anything else is a bug
+    pub fn owns(&self, ptr: NonNull<u8>) -> bool {
+        let ppn = SmallSlabBlock::<S>::floor(ptr.addr().get());
+        core::ptr::eq(ppn.start_addr().get_ref::<SmallSlabBlock<S>>().owner, self)
+    }
+
     pub fn dealloc(&mut self, ptr: NonNull<u8>) -> Option<()> {
+        let owned = self.owns(ptr);
         let mut ptr: NonNull<FreeNode<S>> = ptr.cast();
         let addr = ptr.addr().get();
         let ppn = SlabBlock::<S>::floor(addr);
         let blk = ppn.start_addr().get_mut::<SmallSlabBlock<S>>();
-        if blk.owner != self {
+        if !owned {
             panic!("block {:?} is not belong to this cache {:#x}", blk, self as *const _ as usize);
         }
         let free_node = unsafe { ptr.as_mut() };
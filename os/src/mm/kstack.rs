@@ -0,0 +1,89 @@
+//! Per-hart kernel stack layout, overflow detection and usage watermark
+//!
+//! Every hart owns `KERNEL_STACK_SIZE` bytes of kernel stack. On riscv64 the
+//! stacks are used through the `KernelStack` area, where the lowest
+//! `KERNEL_STACK_GUARD_SIZE` bytes of each stack are left unmapped, so an
+//! overflow faults instead of silently running into the neighbouring hart.
+
+use hal::{constant::{Constant, ConstantsHal}, println};
+
+/// a pattern which is unlikely to be a real value on the stack
+const STACK_PATTERN: usize = 0xdead_beef_cafe_babe;
+
+/// bottom of the kernel stack of `hart`, in the address space the hart runs on
+fn stack_bottom(hart: usize) -> usize {
+    #[cfg(target_arch = "riscv64")]
+    let base = Constant::KERNEL_STACK_BOTTOM;
+    #[cfg(target_arch = "loongarch64")]
+    let base = {
+        unsafe extern "C" {
+            fn kernel_stack_bottom();
+        }
+        kernel_stack_bottom as usize
+    };
+    base + hart * Constant::KERNEL_STACK_SIZE
+}
+
+/// the usable (mapped) range of the kernel stack of `hart`
+fn usable_range(hart: usize) -> core::ops::Range<usize> {
+    let bottom = stack_bottom(hart);
+    #[cfg(target_arch = "riscv64")]
+    let start = bottom + Constant::KERNEL_STACK_GUARD_SIZE;
+    #[cfg(target_arch = "loongarch64")]
+    let start = bottom;
+    start..bottom + Constant::KERNEL_STACK_SIZE
+}
+
+/// return the hart whose stack guard contains `va`
+pub fn guard_hart(va: usize) -> Option<usize> {
+    if !cfg!(target_arch = "riscv64") {
+        return None;
+    }
+    (0..Constant::MAX_PROCESSORS).find(|&hart| {
+        let bottom = stack_bottom(hart);
+        (bottom..bottom + Constant::KERNEL_STACK_GUARD_SIZE).contains(&va)
+    })
+}
+
+/// fill the unused part of every kernel stack with a pattern,
+/// must be called before the other harts are started
+#[cfg(debug_assertions)]
+pub fn init_watermark() {
+    let cur_hart = hal::instruction::Instruction::get_tp();
+    let marker = 0usize;
+    // keep some space for the frames of this function
+    let cur_sp = (&marker as *const usize as usize) - 256;
+    for hart in 0..Constant::MAX_PROCESSORS {
+        let mut range = usable_range(hart);
+        if hart == cur_hart {
+            if !range.contains(&cur_sp) {
+                continue;
+            }
+            range.end = cur_sp & !(size_of::<usize>() - 1);
+        }
+        let words = unsafe {
+            core::slice::from_raw_parts_mut(range.start as *mut usize, (range.end - range.start) / size_of::<usize>())
+        };
+        words.fill(STACK_PATTERN);
+    }
+}
+
+/// the deepest usage of the kernel stack of `hart` in bytes
+#[cfg(debug_assertions)]
+pub fn max_depth(hart: usize) -> usize {
+    let range = usable_range(hart);
+    let words = unsafe {
+        core::slice::from_raw_parts(range.start as *const usize, (range.end - range.start) / size_of::<usize>())
+    };
+    let untouched = words.iter().take_while(|&&w| w == STACK_PATTERN).count();
+    range.end - range.start - untouched * size_of::<usize>()
+}
+
+/// print how deep every kernel stack has been used
+#[cfg(debug_assertions)]
+pub fn report_watermark() {
+    for hart in 0..Constant::MAX_PROCESSORS {
+        let size = usable_range(hart).len();
+        println!("[kstack] hart {}: max depth {:#x} / {:#x} bytes", hart, max_depth(hart), size);
+    }
+}
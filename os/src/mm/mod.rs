@@ -12,6 +12,10 @@ mod page_table;
 use core::ops::Deref;
 /// virtual memory
 pub mod vm;
+/// kernel stacks
+pub mod kstack;
+/// counters of memory events
+pub mod stats;
 
 mod user;
 
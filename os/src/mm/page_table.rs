@@ -1,7 +1,7 @@
 use core::{cmp::min, ptr::slice_from_raw_parts_mut};
 
 use alloc::{string::String, vec::Vec};
-use hal::{addr::{PhysAddr, PhysAddrHal, PhysPageNumHal, VirtAddr, VirtAddrHal, VirtPageNumHal}, constant::{Constant, ConstantsHal}, pagetable::{PageTableEntryHal, PageTableHal}};
+use hal::{addr::{PhysAddr, PhysAddrHal, PhysPageNumHal, VirtAddr, VirtAddrHal, VirtPageNumHal}, constant::{Constant, ConstantsHal}, pagetable::PageTableHal};
 
 use crate::mm::vm::{PageFaultAccessType, UserVmSpaceHal};
 
@@ -74,17 +74,10 @@ pub unsafe fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T
         .get_mut()
 }
 
-/// translate user va by user_vm_space
+/// translate user va by user_vm_space, faulting the page in first like a user access would
 pub fn translate_uva_checked(user_vm_space: &mut UserVmSpace, va: VirtAddr, access_type: PageFaultAccessType) -> Option<PhysAddr> {
-    match user_vm_space.get_page_table().find_pte(va.floor()) {
-        Some((pte, _)) if access_type.can_access(pte.flags()) => {
-            Some(pte.ppn().start_addr() + va.page_offset())
-        }
-        _ => {
-            user_vm_space.handle_page_fault(va, access_type).ok()?;
-            Some(user_vm_space.translate_va(va).unwrap())
-        }
-    }
+    user_vm_space.ensure_access(va, 1, access_type).ok()?;
+    user_vm_space.translate_va(va)
 }
 
 
@@ -92,7 +85,7 @@ pub fn translate_uva_checked(user_vm_space: &mut UserVmSpace, va: VirtAddr, acce
 #[deprecated = "UserSlice is better"]
 /// copy out 
 pub fn copy_out<T: Copy>(user_vm_space: &mut UserVmSpace, mut dst: VirtAddr, mut src: &[T]) {
-    assert!(dst.0 < Constant::USER_ADDR_SPACE.end);
+    assert!(dst.0 < Constant::user_addr_space().end);
     let size = size_of::<T>();
     // size is power of 2 and less than PAGE_SIZE, dst is aligned to size
     assert!((size & (size - 1) == 0) && (size <= Constant::PAGE_SIZE) && (dst.0 & (size - 1) == 0));
@@ -114,7 +107,7 @@ pub fn copy_out<T: Copy>(user_vm_space: &mut UserVmSpace, mut dst: VirtAddr, mut
 #[allow(unused)]
 /// copy out a str
 pub fn copy_out_str(user_vm_space: &mut UserVmSpace, mut dst: VirtAddr, str: &str) {
-    assert!(dst.0 < Constant::USER_ADDR_SPACE.end);
+    assert!(dst.0 < Constant::user_addr_space().end);
     let mut src = str.as_bytes();
     let mut bytes = src.len() + 1;
 
@@ -0,0 +1,75 @@
+//! counters of the memory events of a task, like the page faults it took
+//!
+//! every task counts its own events with relaxed atomics, so counting costs the
+//! fault path next to nothing, a process sums the counters of its threads.
+//! The counters go on across execve, a child made by fork starts from zero
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::processor::processor::current_task;
+
+/// a memory event of a task
+#[derive(Debug, Clone, Copy)]
+pub enum VmEvent {
+    /// a fault served without device io: a zero page, a page in the cache, a copy or a permission change
+    MinorFault = 0,
+    /// a fault which read its page from the device
+    MajorFault,
+    /// a write fault which copied a page shared by fork
+    Cow,
+    /// a successful mmap
+    Mmap,
+    /// a successful munmap
+    Munmap,
+    /// a flush of the tlb of every hart running the address space
+    TlbShootdown,
+}
+
+/// the number of kinds of [`VmEvent`]
+pub const VM_EVENTS: usize = 6;
+
+/// the events counted so far, indexed by [`VmEvent`]
+pub type VmEventCounts = [usize; VM_EVENTS];
+
+/// the memory event counters of a task
+pub struct VmStats {
+    counters: [AtomicUsize; VM_EVENTS],
+}
+
+impl VmStats {
+    /// all counters at zero
+    pub const fn new() -> Self {
+        Self { counters: [const { AtomicUsize::new(0) }; VM_EVENTS] }
+    }
+
+    /// count one `event`
+    #[inline]
+    pub fn count(&self, event: VmEvent) {
+        self.counters[event as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// the times `event` happened
+    pub fn get(&self, event: VmEvent) -> usize {
+        self.counters[event as usize].load(Ordering::Relaxed)
+    }
+
+    /// every counter
+    pub fn counts(&self) -> VmEventCounts {
+        core::array::from_fn(|i| self.counters[i].load(Ordering::Relaxed))
+    }
+}
+
+/// add the counts of `other` to `counts`
+pub fn add_counts(counts: &mut VmEventCounts, other: &VmEventCounts) {
+    for (count, other) in counts.iter_mut().zip(other) {
+        *count += other;
+    }
+}
+
+/// charge `event` to the current task, if a task runs
+#[inline]
+pub fn count_vm_event(event: VmEvent) {
+    if let Some(task) = current_task() {
+        task.vm_stats.count(event);
+    }
+}
@@ -111,27 +111,38 @@ impl<T> UserPtrRaw<T> {
 
 impl UserPtrRaw<u8> {
     pub fn cstr_slice(self, vm: &mut UserVmSpace) -> Option<UserSlice<u8, ReadMark>> {
+        self.cstr_slice_max(vm, usize::MAX)
+    }
+
+    /// the c string, or its first `max` bytes when it has no nul before,
+    /// like strncpy_from_user
+    pub fn cstr_slice_max(self, vm: &mut UserVmSpace, max: usize) -> Option<UserSlice<u8, ReadMark>> {
         let sum_guard = SumGuard::new();
         let mut cur = self.ptr;
         let mut len = 0;
         loop {
+            if len == max {
+                break;
+            }
             vm.ensure_access((cur as usize).into(), 1, PageFaultAccessType::READ).ok()?;
             let pg_end = ((cur as usize + Constant::PAGE_SIZE) & !(Constant::PAGE_SIZE - 1)) as *mut u8;
-            while cur != pg_end {
-                if unsafe { *cur != 0u8 } {
-                    len += 1;
-                } else {
-                    return Some(UserSlice {
-                        raw: UserSliceRaw { len, ptr: self.ptr },
-                        _mark: PhantomData,
-                        _sum_guard: sum_guard,
-                        locker: UserVmPagesLocker { },
-                    });
+            while cur != pg_end && len != max {
+                if unsafe { *cur == 0u8 } {
+                    break;
                 }
+                len += 1;
                 cur = unsafe { cur.add(1) };
             }
-            cur = pg_end;
+            if cur != pg_end {
+                break;
+            }
         }
+        Some(UserSlice {
+            raw: UserSliceRaw { len, ptr: self.ptr },
+            _mark: PhantomData,
+            _sum_guard: sum_guard,
+            locker: UserVmPagesLocker { },
+        })
     }
 }
 
@@ -0,0 +1,71 @@
+//! address space layout randomization
+//!
+//! every exec shifts the search base of mmap, the stack top, the break and the load
+//! bias of the dynamic loader by random whole pages, and the argument block by a random
+//! part of a page, all drawn from the pool of getrandom. kernel/randomize_va_space
+//! chooses as on linux: 0 for none, 1 for all but the break, 2 for the break too
+
+use hal::constant::{Constant, ConstantsHal};
+
+use crate::{fs::devfs::urandom::RNG, sysctl::IntParam};
+
+/// sysctl kernel/randomize_va_space, also `randomize_va_space=` on the boot command line
+pub static RANDOMIZE_VA_SPACE: IntParam = IntParam::new(2, 0, 2);
+
+/// the mmap search base moves within this many bytes of the start of its area
+const MMAP_RND_SIZE: usize = 256 << 20;
+/// the stack top moves down within this many bytes
+const STACK_RND_SIZE: usize = 1 << 20;
+/// the break moves up within this many bytes above the image
+const BRK_RND_SIZE: usize = 32 << 20;
+/// the dynamic loader moves up within this many bytes above DL_INTERP_OFFSET
+const INTERP_RND_SIZE: usize = 256 << 20;
+/// the alignment of the stack pointer, the argument block moves by its multiples
+const STACK_ALIGN: usize = 16;
+
+/// a random multiple of `align` below `size`, 0 when the layout is randomized below `level`
+fn random_shift(level: usize, size: usize, align: usize) -> usize {
+    if RANDOMIZE_VA_SPACE.get() < level || size < align {
+        return 0;
+    }
+    let mut rng = RNG.lock();
+    let random = (rng.next_u32() as usize) << 32 | rng.next_u32() as usize;
+    random % (size / align) * align
+}
+
+/// how far above the start of an mmap area the search for a free range begins
+pub fn mmap_rnd() -> usize {
+    random_shift(1, MMAP_RND_SIZE, Constant::PAGE_SIZE)
+}
+
+/// how far below its place the stack top goes, at most an eighth of the stack area
+pub fn stack_rnd() -> usize {
+    random_shift(1, STACK_RND_SIZE.min(Constant::user_stack_size() / 8), Constant::PAGE_SIZE)
+}
+
+/// the bytes left free above the strings of the argument block
+pub fn stack_pad() -> usize {
+    random_shift(1, Constant::PAGE_SIZE, STACK_ALIGN)
+}
+
+/// how far above the end of the image the break begins
+pub fn brk_rnd() -> usize {
+    random_shift(2, BRK_RND_SIZE, Constant::PAGE_SIZE)
+}
+
+/// the bound of the load bias of the dynamic loader, which has `room` under the mmap areas
+pub fn interp_rnd_max(room: usize) -> usize {
+    INTERP_RND_SIZE.min(room / 2)
+}
+
+/// the load bias of the dynamic loader above DL_INTERP_OFFSET, a multiple of `align`
+pub fn interp_rnd(room: usize, align: usize) -> usize {
+    random_shift(1, interp_rnd_max(room), align.max(Constant::PAGE_SIZE))
+}
+
+/// 16 bytes from the pool for AT_RANDOM, whether the layout is randomized or not
+pub fn at_random() -> [u8; 16] {
+    let mut bytes = [0u8; 16];
+    RNG.lock().fill_buf(&mut bytes);
+    bytes
+}
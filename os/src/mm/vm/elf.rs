@@ -0,0 +1,145 @@
+//! validation of an ELF before it is loaded
+//!
+//! the header and the program headers are read from the raw bytes, before xmas_elf
+//! parses the file, so a truncated file or one built for another machine is turned
+//! away with ENOEXEC instead of panicking in the parser, and a layout which would map
+//! garbage or overlap itself with EINVAL
+
+use core::ops::Range;
+
+use alloc::vec::Vec;
+use hal::constant::{Constant, ConstantsHal};
+use xmas_elf::reader::Reader;
+
+use crate::syscall::SysError;
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ELFDATA2MSB: u8 = 2;
+const EV_CURRENT: u8 = 1;
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+#[cfg(target_arch = "riscv64")]
+const EM_NATIVE: u16 = 243; // EM_RISCV
+#[cfg(target_arch = "loongarch64")]
+const EM_NATIVE: u16 = 258; // EM_LOONGARCH
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+
+/// the size of the ELF64 header
+const EHDR_SIZE: usize = 64;
+/// the size of an ELF64 program header
+const PHDR_SIZE: usize = 56;
+/// the largest program header table, as linux takes it
+const MAX_PHDRS_SIZE: usize = 64 * 1024;
+
+// the file is in the byte order of this machine once the header says so
+fn u16_at(buf: &[u8], at: usize) -> u16 {
+    u16::from_ne_bytes(buf[at..at + 2].try_into().unwrap())
+}
+
+fn u32_at(buf: &[u8], at: usize) -> u32 {
+    u32::from_ne_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], at: usize) -> usize {
+    u64::from_ne_bytes(buf[at..at + 8].try_into().unwrap()) as usize
+}
+
+/// the loadable segment of a program header
+struct Load {
+    flags: u32,
+    offset: usize,
+    vaddr: usize,
+    filesz: usize,
+    memsz: usize,
+}
+
+impl Load {
+    fn parse(ph: &[u8]) -> Option<Self> {
+        (u32_at(ph, 0) == PT_LOAD).then(|| Self {
+            flags: u32_at(ph, 4),
+            offset: u64_at(ph, 8),
+            vaddr: u64_at(ph, 16),
+            filesz: u64_at(ph, 32),
+            memsz: u64_at(ph, 40),
+        })
+    }
+
+    /// the pages the segment covers, None if its end overflows
+    fn pages(&self) -> Option<Range<usize>> {
+        let end = self.vaddr.checked_add(self.memsz)?.checked_next_multiple_of(Constant::PAGE_SIZE)?;
+        Some(self.vaddr / Constant::PAGE_SIZE..end / Constant::PAGE_SIZE)
+    }
+}
+
+/// check that the ELF in `input` is a 64-bit executable or shared object of this machine
+/// with a sane program header table, whose loadable segments lie in the file, fit
+/// below `limit` without overlapping each other, and hold its entry point in an
+/// executable one. ENOEXEC for what is not such a program, EINVAL for a broken layout
+pub fn check_elf<T: Reader + ?Sized>(input: &T, limit: usize) -> Result<(), SysError> {
+    if input.len() < EHDR_SIZE {
+        log::warn!("[check_elf] {} bytes, shorter than the ELF header", input.len());
+        return Err(SysError::ENOEXEC);
+    }
+    let ehdr = input.read(0, EHDR_SIZE);
+    if ehdr[..4] != ELF_MAGIC {
+        return Err(SysError::ENOEXEC);
+    }
+    let native_data = if cfg!(target_endian = "little") { ELFDATA2LSB } else { ELFDATA2MSB };
+    if ehdr[4] != ELFCLASS64 || ehdr[5] != native_data || ehdr[6] != EV_CURRENT {
+        log::warn!("[check_elf] class {} data {} version {}, not a native 64-bit ELF", ehdr[4], ehdr[5], ehdr[6]);
+        return Err(SysError::ENOEXEC);
+    }
+    let (e_type, machine) = (u16_at(ehdr, 16), u16_at(ehdr, 18));
+    if machine != EM_NATIVE || !matches!(e_type, ET_EXEC | ET_DYN) {
+        log::warn!("[check_elf] type {} machine {}, not a program of this machine", e_type, machine);
+        return Err(SysError::ENOEXEC);
+    }
+    let entry = u64_at(ehdr, 24);
+    let (phoff, phentsize, phnum) = (u64_at(ehdr, 32), u16_at(ehdr, 54) as usize, u16_at(ehdr, 56) as usize);
+    let phdrs_size = phentsize * phnum;
+    if phentsize != PHDR_SIZE || phnum == 0 || phdrs_size > MAX_PHDRS_SIZE {
+        log::warn!("[check_elf] {} program headers of {} bytes", phnum, phentsize);
+        return Err(SysError::ENOEXEC);
+    }
+    if phoff.checked_add(phdrs_size).is_none_or(|end| end > input.len()) {
+        log::warn!("[check_elf] the program headers at {:#x} run past the end of the file", phoff);
+        return Err(SysError::ENOEXEC);
+    }
+
+    let phdrs = input.read(phoff, phdrs_size);
+    let segments: Vec<Load> = phdrs.chunks(PHDR_SIZE).filter_map(Load::parse).collect();
+    let mut loads: Vec<(Range<usize>, &Load)> = Vec::new();
+    for load in segments.iter() {
+        let in_file = load.offset.checked_add(load.filesz).is_some_and(|end| end <= input.len());
+        let aligned = load.offset % Constant::PAGE_SIZE == load.vaddr % Constant::PAGE_SIZE;
+        let pages = load.pages().filter(|pages| pages.end * Constant::PAGE_SIZE <= limit);
+        let Some(pages) = pages.filter(|_| load.filesz <= load.memsz && in_file && aligned) else {
+            log::warn!(
+                "[check_elf] bad segment at {:#x}: {:#x} bytes of the file at {:#x}, {:#x} in memory",
+                load.vaddr, load.filesz, load.offset, load.memsz,
+            );
+            return Err(SysError::EINVAL);
+        };
+        loads.push((pages, load));
+    }
+    if loads.is_empty() {
+        log::warn!("[check_elf] nothing to load");
+        return Err(SysError::ENOEXEC);
+    }
+    loads.sort_by_key(|(pages, _)| pages.start);
+    if loads.windows(2).any(|pair| pair[0].0.end > pair[1].0.start) {
+        log::warn!("[check_elf] the loadable segments overlap");
+        return Err(SysError::EINVAL);
+    }
+    let entry_ok = loads.iter().any(|(_, load)| {
+        load.flags & PF_X != 0 && (load.vaddr..load.vaddr + load.memsz).contains(&entry)
+    });
+    if !entry_ok {
+        log::warn!("[check_elf] the entry {:#x} is in no executable segment", entry);
+        return Err(SysError::EINVAL);
+    }
+    Ok(())
+}
@@ -15,17 +15,20 @@ pub struct KernVmSpace {
 }
 
 impl KernVmSpace {
-    /// The second-level page table in the kernel virtual mapping area is pre-allocated to avoid synchronization
+    /// The page tables under the root entries of the kernel virtual mapping area are pre-allocated to avoid synchronization,
+    /// the user page tables copy these root entries. Sv39 takes one per GiB of the area, sv48 one for all of it
     fn map_vm_area_huge_pages(&mut self) {
         let ptes = self.page_table.root_ppn
             .start_addr().get_mut::<[PageTableEntry; Constant::PTES_PER_PAGE]>();
 
-        const HUGE_PAGES: usize = Constant::KERNEL_VM_SIZE / (Constant::PAGE_SIZE * 512 * 512);
-        const VM_START: usize = (Constant::KERNEL_VM_BOTTOM & ((1 << Constant::VA_WIDTH)-1)) / (Constant::PAGE_SIZE * 512 * 512);
-        let range_ppn = FrameAllocator.alloc(HUGE_PAGES).unwrap();
+        let root_span = PageLevel::from(0).page_count() * Constant::PAGE_SIZE;
+        let va_mask = (1 << Constant::va_width()) - 1;
+        let vm_start = (Constant::KERNEL_VM_BOTTOM & va_mask) / root_span;
+        let vm_end = ((Constant::KERNEL_VM_TOP - 1) & va_mask) / root_span + 1;
+        let range_ppn = FrameAllocator.alloc(vm_end - vm_start).unwrap();
         range_ppn.get_slice_mut::<u8>().fill(0);
         let ppn = range_ppn.start;
-        for (i, pte_i) in (VM_START..VM_START+HUGE_PAGES).enumerate() {
+        for (i, pte_i) in (vm_start..vm_end).enumerate() {
             ptes[pte_i] = PageTableEntry::new(ppn+i, MapPerm::empty());
             ptes[pte_i].set_valid(true);
         }
@@ -55,6 +58,7 @@ impl KernVmSpaceHal for KernVmSpace {
             fn edata();
             fn sbss_with_stack();
             fn ebss();
+            fn sksyms();
             fn ekernel();
         }
 
@@ -105,6 +109,15 @@ impl KernVmSpaceHal for KernVmSpace {
             None
         );
 
+        // the symbol table, read by the panic handler
+        ret.push_area(KernVmArea::new(
+                (sksyms as usize).into()..(ekernel as usize).into(), 
+                KernVmAreaType::Data, 
+                MapPerm::R,
+            ),
+            None
+        );
+
         ret.push_area(KernVmArea::new(
                 Constant::KERNEL_STACK_BOTTOM.into()..Constant::KERNEL_STACK_TOP.into(), 
                 KernVmAreaType::KernelStack, 
@@ -122,17 +135,29 @@ impl KernVmSpaceHal for KernVmSpace {
         );
 
         ret.push_area(KernVmArea::new(
-                (ekernel as usize).into()..(Constant::MEMORY_END + Constant::KERNEL_ADDR_SPACE.start).into(), 
+                (ekernel as usize).into()..(hal::board::memory_end() + Constant::KERNEL_ADDR_SPACE.start).into(), 
                 KernVmAreaType::PhysMem, 
                 MapPerm::R | MapPerm::W,
             ),
             None
         );
         
-        for pair in hal::board::MMIO {
+        // the device tree passed by the bootloader lies past the end of memory
+        if let Some(dtb) = hal::board::boot_dtb() {
+            let start = dtb.start & !(Constant::PAGE_SIZE - 1);
+            ret.push_area(KernVmArea::new(
+                    (start + Constant::KERNEL_ADDR_SPACE.start).into()..(dtb.end + Constant::KERNEL_ADDR_SPACE.start).into(),
+                    KernVmAreaType::PhysMem,
+                    MapPerm::R,
+                ),
+                None
+            );
+        }
+
+        for pair in crate::devices::boot_mmio_ranges() {
             ret.push_area(
                 KernVmArea::new(
-                    ((*pair).0 + Constant::KERNEL_ADDR_SPACE.start).into()..((*pair).0 + Constant::KERNEL_ADDR_SPACE.start + (*pair).1).into(),
+                    (pair.0 + Constant::KERNEL_ADDR_SPACE.start).into()..(pair.0 + Constant::KERNEL_ADDR_SPACE.start + pair.1).into(),
                     KernVmAreaType::MemMappedReg, 
                     MapPerm::R | MapPerm::W,
                 ),
@@ -291,11 +316,18 @@ impl KernVmArea {
                 );
             }
             KernVmAreaType::KernelStack => {
-                self.map_range_to(
-                    page_table, 
-                    range_vpn.clone(),
-                    PhysPageNum((kernel_stack_bottom as usize & !(Constant::KERNEL_ADDR_SPACE.start)) >> 12)
-                );
+                // leave a hole at the bottom of every hart's stack to catch overflows
+                let stack_pages = Constant::KERNEL_STACK_SIZE / Constant::PAGE_SIZE;
+                let guard_pages = Constant::KERNEL_STACK_GUARD_SIZE / Constant::PAGE_SIZE;
+                let start_ppn = PhysPageNum((kernel_stack_bottom as usize & !(Constant::KERNEL_ADDR_SPACE.start)) >> 12);
+                for hart in 0..Constant::MAX_PROCESSORS {
+                    let offset = hart * stack_pages + guard_pages;
+                    self.map_range_to(
+                        page_table, 
+                        range_vpn.start + offset..range_vpn.start + (hart + 1) * stack_pages,
+                        start_ppn + offset
+                    );
+                }
             },
             KernVmAreaType::VirtMemory => {
                 for (&vpn, frame) in self.frames.iter() {
@@ -5,7 +5,7 @@ use bitflags::bitflags;
 use hal::{addr::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum}, instruction::{Instruction, InstructionHal}, pagetable::{MapPerm, PageTableHal}, util::smart_point::StrongArc};
 use xmas_elf::{reader::Reader, ElfFile};
 
-use crate::{ipc::sysv, fs::vfs::File, sync::mutex::{spin_mutex::SpinMutex, MutexSupport}, syscall::{mm::MmapFlags, SysError, SysResult}, task::utils::AuxHeader};
+use crate::{ipc::sysv, fs::{page::page::Page, vfs::File}, sync::mutex::{spin_mutex::SpinMutex, MutexSupport}, syscall::{mm::MmapFlags, SysError, SysResult}, task::utils::AuxHeader};
 
 use super::{allocator::{FrameAllocator, SlabAllocator}, FrameTracker, PageTable};
 
@@ -44,6 +44,10 @@ pub enum UserVmAreaType {
 bitflags! {
     pub struct MapFlags: u8 {
         const SHARED = 1 << 0;
+        /// backed by huge frames only, never falls back to small pages
+        const HUGE = 1 << 1;
+        /// locked by mlock, its pages are faulted in and stay in memory
+        const LOCKED = 1 << 2;
     }
 }
 
@@ -53,6 +57,9 @@ impl From<MmapFlags> for MapFlags {
         if value.contains(MmapFlags::MAP_SHARED) || value.contains(MmapFlags::MAP_SHARED_VALIDATE) {
             ret.insert(MapFlags::SHARED);
         }
+        if value.contains(MmapFlags::MAP_HUGETLB) {
+            ret.insert(MapFlags::HUGE);
+        }
         ret
     }
 }
@@ -155,6 +162,8 @@ pub struct UserVmArea {
     pub vma_type: UserVmAreaType,
     pub map_perm: MapPerm,
     frames: BTreeMap<VirtPageNum, StrongArc<FrameTracker>>,
+    /// the cached file pages of a locked area, held so the page cache never gives them back
+    pinned: BTreeMap<VirtPageNum, Arc<Page>>,
     /// for mmap usage
     pub file: UserVmFile,
     pub map_flags: MapFlags,
@@ -208,6 +217,9 @@ impl UserVmAreaView {
         } else {
             ret.insert(MmapFlags::MAP_PRIVATE);
         }
+        if self.map_flags.contains(MapFlags::HUGE) {
+            ret.insert(MmapFlags::MAP_HUGETLB);
+        }
         if !self.file.is_file() {
             ret.insert(MmapFlags::MAP_ANONYMOUS);
         }
@@ -215,6 +227,19 @@ impl UserVmAreaView {
     }
 }
 
+/// A run of pages of a user area, dumped as one PT_LOAD segment of a core file
+pub struct CoreSegment {
+    /// the pages of the run
+    pub range_va: Range<VirtAddr>,
+    /// permission of the area
+    pub map_perm: MapPerm,
+    /// the page holding each part of the contents and the frame it is in, which the segment
+    /// keeps alive, empty if the run is recorded as zero-filled
+    pub pages: Vec<(PhysPageNum, StrongArc<FrameTracker>)>,
+    /// offset of the run in the mapped file, 0 for anonymous memory
+    pub file_offset: usize,
+}
+
 #[allow(missing_docs, unused)]
 impl UserVmArea {
     fn new(
@@ -227,6 +252,7 @@ impl UserVmArea {
             vma_type,
             map_perm,
             frames: BTreeMap::new(),
+            pinned: BTreeMap::new(),
             file: UserVmFile::None,
             map_flags: MapFlags::empty(),
             offset: 0,
@@ -247,6 +273,7 @@ impl UserVmArea {
             vma_type: UserVmAreaType::Mmap,
             map_perm,
             frames: BTreeMap::new(),
+            pinned: BTreeMap::new(),
             file,
             map_flags: flags.into(),
             offset,
@@ -311,6 +338,10 @@ bitflags! {
 #[allow(missing_docs, unused)]
 impl PageFaultAccessType {
     pub fn can_access(self, flag: MapPerm) -> bool {
+        // PROT_NONE: used by userspace as guard pages, never faulted in
+        if !flag.intersects(MapPerm::R | MapPerm::W | MapPerm::X) {
+            return false;
+        }
         if self.contains(Self::WRITE) && !flag.contains(MapPerm::W) {
             return false;
         }
@@ -371,15 +402,17 @@ pub trait UserVmSpaceHal: Sized {
     fn map_elf<T: Reader + ?Sized>(&mut self, elf: &ElfFile<'_, T>, elf_file: Option<Arc<dyn File>>, offset: VirtAddr) -> 
         (MaxEndVpn, StartPoint);
 
-    fn from_elf<T: Reader + ?Sized>(elf: &ElfFile<'_, T>, elf_file: Option<Arc<dyn File>>) -> 
+    /// a new address space running `elf`, with a main thread stack of `stack_size` bytes
+    /// at most the configured user stack size
+    fn from_elf<T: Reader + ?Sized>(elf: &ElfFile<'_, T>, elf_file: Option<Arc<dyn File>>, stack_size: usize) -> 
         Result<(Self, StackTop, EntryPoint, Vec<AuxHeader>), SysError>;
 
-    fn from_existed(uvm_space: &mut Self) -> Self;
+    fn from_existed(uvm_space: &mut Self, mm: usize) -> Self;
 
     /// warning: data must must be page-aligned
     fn push_area(&mut self, area: UserVmArea, data: Option<&[u8]>) -> &mut UserVmArea;
 
-    fn reset_heap_break(&mut self, new_brk: VirtAddr) -> VirtAddr;
+    fn reset_heap_break(&mut self, new_brk: VirtAddr, data_limit: usize) -> VirtAddr;
 
     fn handle_page_fault(&mut self, va: VirtAddr, access_type: PageFaultAccessType) -> Result<(), ()>;
 
@@ -415,3 +448,8 @@ pub use uvm::*;
 
 mod kvm;
 pub use kvm::*;
+
+mod elf;
+pub use elf::*;
+
+pub mod aslr;
@@ -1,20 +1,39 @@
 use core::ops::{Deref, DerefMut, Range};
 
 use alloc::{collections::btree_map::BTreeMap, format, string::{String, ToString}, sync::Arc, vec::Vec};
-use hal::{addr::{PhysAddr, PhysAddrHal, PhysPageNum, PhysPageNumHal, RangePPNHal, VirtAddr, VirtAddrHal, VirtPageNum, VirtPageNumHal}, allocator::{FrameAllocatorHal, FrameAllocatorTrackerExt}, constant::{Constant, ConstantsHal}, instruction::{Instruction, InstructionHal}, pagetable::{MapPerm, PageLevel, PageTableEntry, PageTableEntryHal, PageTableHal, VpnPageRangeIter}, println, util::smart_point::StrongArc};
+use hal::{addr::{PhysAddr, PhysAddrHal, PhysPageNum, PhysPageNumHal, RangePPNHal, VirtAddr, VirtAddrHal, VirtPageNum, VirtPageNumHal}, allocator::{FrameAllocatorHal, FrameAllocatorTrackerExt}, constant::{Constant, ConstantsHal}, instruction::{Instruction, InstructionHal}, pagetable::{MapPerm, PageLevel, PageTableEntry, PageTableEntryHal, PageTableHal, VpnPageRangeIter}, println, util::smart_point::{ArcTag, StrongArc}};
 use log::info;
 use range_map::RangeMap;
 use xmas_elf::reader::Reader;
 
-use crate::{config::PAGE_SIZE, fs::{page, utils::FileReader, vfs::{dentry::global_find_dentry, file::open_file, DentryState, File}, OpenFlags}, ipc::sysv::{self, ShmObj}, mm::{allocator::{frames_alloc, FrameAllocator, SlabAllocator}, FrameTracker, PageTable, KVMSPACE}, sync::mutex::{spin_rw_mutex::SpinRwMutex, MutexSupport, SpinNoIrqLock}, syscall::{mm::MmapFlags, SysError, SysResult}, task::utils::{generate_early_auxv, AuxHeader, AT_BASE, AT_CLKTCK, AT_EGID, AT_ENTRY, AT_EUID, AT_FLAGS, AT_GID, AT_HWCAP, AT_NOTELF, AT_PAGESZ, AT_PHDR, AT_PHENT, AT_PHNUM, AT_PLATFORM, AT_RANDOM, AT_SECURE, AT_UID}, utils::{round_down_to_page, timer::TimerGuard}};
+use crate::{config::PAGE_SIZE, fs::{page::{self, page::Page}, utils::FileReader, vfs::{dentry::{global_find_dentry, PATH_MAX}, file::open_file, inode::InodeMode, DentryState, File, Inode}, OpenFlags}, ipc::sysv::{self, ShmObj}, mm::{allocator::{frames_alloc, frames_alloc_aligned, log2, FrameAllocator, SlabAllocator}, stats::{count_vm_event, VmEvent}, FrameTracker, PageTable, KVMSPACE}, processor::ipi::shootdown_tlb, sync::mutex::{spin_rw_mutex::SpinRwMutex, MutexSupport, SpinNoIrqLock}, syscall::{mm::MmapFlags, SysError, SysResult}, task::utils::{generate_early_auxv, AuxHeader, AT_BASE, AT_CLKTCK, AT_EGID, AT_ENTRY, AT_EUID, AT_FLAGS, AT_GID, AT_HWCAP, AT_NOTELF, AT_PAGESZ, AT_PHDR, AT_PHENT, AT_PHNUM, AT_PLATFORM, AT_SECURE, AT_UID}, utils::{round_down_to_page, timer::TimerGuard}};
 
-use super::{KernVmArea, KernVmAreaType, KernVmSpaceHal, MapFlags, MaxEndVpn, PageFaultAccessType, StartPoint, UserVmArea, UserVmAreaType, UserVmAreaView, UserVmFile, UserVmSpaceHal};
+use super::{aslr, check_elf, CoreSegment, KernVmArea, KernVmAreaType, KernVmSpaceHal, MapFlags, MaxEndVpn, PageFaultAccessType, StartPoint, UserVmArea, UserVmAreaType, UserVmAreaView, UserVmFile, UserVmSpaceHal};
+
+/// pages covered by one huge user mapping (2 MiB)
+pub(crate) const HUGE_PAGE_COUNT: usize = 512;
+
+/// page table level used for huge user mappings, None if the arch can't map them
+#[cfg(target_arch = "riscv64")]
+pub(crate) const USER_HUGE_PAGE_LEVEL: Option<PageLevel> = Some(PageLevel::Big);
+#[cfg(target_arch = "loongarch64")]
+pub(crate) const USER_HUGE_PAGE_LEVEL: Option<PageLevel> = None;
+
+/// the smallest main thread stack, however low RLIMIT_STACK is
+const USER_STACK_MIN: usize = 128 * 1024;
 
 /// User's VmSpace
 pub struct UserVmSpace {
     page_table: PageTable,
     areas: RangeMap<VirtPageNum, UserVmArea>,
-    heap_bottom_va: VirtAddr
+    heap_bottom_va: VirtAddr,
+    /// the search for a free range of an mmap area begins this far into it, see aslr
+    mmap_rnd: usize,
+    /// membarrier commands registered by the process, cleared on fork and exec like linux
+    membarrier_state: usize,
+    /// set by mlockall(MCL_FUTURE): the areas mapped from now on are locked,
+    /// cleared on fork and exec like linux
+    lock_future: bool,
 }
 
 impl UserVmSpace {
@@ -24,7 +43,152 @@ impl UserVmSpace {
             page_table: PageTable::new_in(0, FrameAllocator),
             areas: RangeMap::new(),
             heap_bottom_va: VirtAddr(0),
+            mmap_rnd: 0,
+            membarrier_state: 0,
+            lock_future: false,
+        }
+    }
+
+    /// get the registered membarrier commands
+    pub fn membarrier_state(&self) -> usize {
+        self.membarrier_state
+    }
+
+    /// register membarrier commands
+    pub fn register_membarrier(&mut self, cmds: usize) {
+        self.membarrier_state |= cmds;
+    }
+
+    /// whether the areas mapped from now on are locked
+    pub fn lock_future(&self) -> bool {
+        self.lock_future
+    }
+
+    /// lock the areas mapped from now on or stop doing so, for mlockall and munlockall
+    pub fn set_lock_future(&mut self, lock: bool) {
+        self.lock_future = lock;
+    }
+
+    /// the pages of the locked areas
+    pub fn locked_pages(&self) -> usize {
+        self.areas.iter()
+            .filter(|(_, area)| area.map_flags.contains(MapFlags::LOCKED))
+            .map(|(range, _)| range.end.0 - range.start.0)
+            .sum()
+    }
+
+    /// the pages of [va, va + len) not locked yet, ENOMEM when part of the range is not mapped
+    pub fn unlocked_pages(&self, va: VirtAddr, len: usize) -> Result<usize, SysError> {
+        let range = Self::user_range_vpn(va, len).ok_or(SysError::ENOMEM)?;
+        let mut vpn = range.start;
+        let mut unlocked = 0;
+        while vpn < range.end {
+            let (area_range, area) = self.areas.get_key_value(vpn).ok_or(SysError::ENOMEM)?;
+            let end = area_range.end.min(range.end);
+            if !area.map_flags.contains(MapFlags::LOCKED) {
+                unlocked += end.0 - vpn.0;
+            }
+            vpn = end;
+        }
+        Ok(unlocked)
+    }
+
+    /// lock or unlock the areas in [va, va + len), the areas it cuts are split.
+    /// Locking faults every page in the way an access would and pins the cached pages of
+    /// a file mapping, unlocking unpins them. The caller checks the range is mapped
+    pub fn mlock(&mut self, va: VirtAddr, len: usize, lock: bool) -> Result<(), SysError> {
+        let range = Self::user_range_vpn(va, len).ok_or(SysError::ENOMEM)?;
+        let mut vpn = range.start;
+        while vpn < range.end {
+            let mut area = self.unmap(vpn.start_addr(), (range.end.0 - vpn.0) * Constant::PAGE_SIZE)?;
+            vpn = area.range_vpn().end;
+            area.map_flags.set(MapFlags::LOCKED, lock);
+            if !lock {
+                area.pinned.clear();
+            }
+            self.push_area(area, None);
+        }
+        if !lock {
+            return Ok(());
+        }
+        for vpn in range.clone() {
+            let Some(area) = self.areas.get(vpn) else {
+                continue;
+            };
+            // a private writable page is copied now, so the first write does not fault either
+            let access = if area.map_perm.contains(MapPerm::W) && !area.map_flags.contains(MapFlags::SHARED) {
+                PageFaultAccessType::WRITE
+            } else {
+                PageFaultAccessType::READ
+            };
+            // the pages no access is allowed to, and those past the end of the file, stay out
+            if access.can_access(area.map_perm) && !self.page_allows(vpn, access) {
+                let _ = self.handle_page_fault(vpn.start_addr(), access);
+            }
         }
+        let mut vpn = range.start;
+        while vpn < range.end {
+            let Some(area) = self.areas.get_mut(vpn) else {
+                break;
+            };
+            area.pin_file_pages();
+            vpn = area.range_vpn().end;
+        }
+        Ok(())
+    }
+
+    /// the pages of every area
+    pub fn mapped_pages(&self) -> usize {
+        self.areas.iter().map(|(range, _)| range.end.0 - range.start.0).sum()
+    }
+
+    /// lock or unlock every area, for mlockall and munlockall
+    pub fn mlock_all(&mut self, lock: bool) -> Result<(), SysError> {
+        let ranges: Vec<Range<VirtPageNum>> = self.areas.iter().map(|(range, _)| range).collect();
+        for range in ranges {
+            self.mlock(range.start.start_addr(), (range.end.0 - range.start.0) * Constant::PAGE_SIZE, lock)?;
+        }
+        Ok(())
+    }
+
+    /// split the readable areas into runs of pages for a core dump: present pages are dumped,
+    /// except the clean pages of a file mapping, which are recorded as zero-filled
+    pub fn core_segments(&self) -> Vec<CoreSegment> {
+        let mut segments: Vec<CoreSegment> = Vec::new();
+        for (_, area) in self.areas.iter() {
+            if !area.map_perm.contains(MapPerm::R) {
+                continue;
+            }
+            let is_file = area.file.is_file();
+            let mut run: Option<CoreSegment> = None;
+            for vpn in area.range_vpn() {
+                let page = area.present_page(vpn).filter(|_| {
+                    !is_file || self.page_table.find_pte(vpn).map_or(false, |(pte, _)| pte.is_valid() && pte.is_dirty())
+                });
+                match run.as_mut() {
+                    Some(seg) if seg.pages.is_empty() == page.is_none() => {
+                        seg.range_va.end = (vpn + 1).start_addr();
+                        seg.pages.extend(page);
+                    }
+                    _ => {
+                        segments.extend(run.take());
+                        let file_offset = if is_file {
+                            area.offset + (vpn.0 - area.range_vpn().start.0) * Constant::PAGE_SIZE
+                        } else {
+                            0
+                        };
+                        run = Some(CoreSegment {
+                            range_va: vpn.start_addr()..(vpn + 1).start_addr(),
+                            map_perm: area.map_perm,
+                            pages: page.into_iter().collect(),
+                            file_offset,
+                        });
+                    }
+                }
+            }
+            segments.extend(run);
+        }
+        segments
     }
 
     pub fn enable(&self) {
@@ -49,7 +213,7 @@ impl UserVmSpace {
         // map the elf data to user space
         for i in 0..ph_count {
             let ph = elf.program_header(i).unwrap();
-            if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
+            if matches!(ph.get_type(), Ok(xmas_elf::program::Type::Load)) {
                 let start_va: VirtAddr = (ph.virtual_addr() as usize + offset.0).into();
                 let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize + offset.0).into();
                 log::debug!("i: {}, start_va: {:#x}, end_va: {:#x}", i, start_va.0, end_va.0);
@@ -104,7 +268,7 @@ impl UserVmSpace {
         )
     }
     
-    pub fn from_elf<T: Reader + ?Sized>(elf: &xmas_elf::ElfFile<'_, T>, elf_file: Option<Arc<dyn File>>) -> 
+    pub fn from_elf<T: Reader + ?Sized>(elf: &xmas_elf::ElfFile<'_, T>, elf_file: Option<Arc<dyn File>>, stack_size: usize) -> 
         Result<(Self, super::StackTop, super::EntryPoint, Vec<AuxHeader>), SysError> {
         let mut ret = KVMSPACE.lock().to_user();
 
@@ -143,14 +307,19 @@ impl UserVmSpace {
         let (max_end_vpn, header_va) = ret.map_elf(&elf, elf_file, 0.into());
 
         let ph_head_addr = header_va.0 + elf.header.pt2.ph_offset() as usize;
-        auxv.push(AuxHeader::new(AT_RANDOM, ph_head_addr));
         auxv.push(AuxHeader::new(AT_PHDR, ph_head_addr));
 
-        ret.heap_bottom_va = max_end_vpn.start_addr();
-
-        // map user stack with U flags
-        let user_stack_bottom = Constant::USER_STACK_BOTTOM;
-        let user_stack_top = Constant::USER_STACK_TOP;
+        ret.heap_bottom_va = max_end_vpn.start_addr() + aslr::brk_rnd();
+        ret.mmap_rnd = aslr::mmap_rnd();
+
+        // map user stack with U flags, sized by RLIMIT_STACK within the configured area,
+        // which a randomized stack top leaves less of
+        let user_stack_top = Constant::user_stack_top() - aslr::stack_rnd();
+        let stack_size = stack_size
+            .max(USER_STACK_MIN)
+            .min(user_stack_top - Constant::user_stack_bottom())
+            .next_multiple_of(Constant::PAGE_SIZE);
+        let user_stack_bottom = user_stack_top - stack_size;
         log::debug!("user_stack_bottom: {:#x}, user_stack_top: {:#x}", user_stack_bottom, user_stack_top);
         ret.push_area(
             UserVmArea::new(
@@ -183,77 +352,112 @@ impl UserVmSpace {
         }
     }
 
-    pub fn reset_heap_break(&mut self, new_brk: VirtAddr) -> VirtAddr {
-        let heap = match self.find_heap() {
-            Some(heap) => heap,
-            None => {
-                if new_brk > self.heap_bottom_va {
-                    self.push_area(
-                        UserVmArea::new(
-                            self.heap_bottom_va..new_brk,
-                            UserVmAreaType::Heap,
-                            MapPerm::R | MapPerm::W | MapPerm::U,
-                        ), 
-                        None
-                    );
-                    return new_brk;
-                } else {
-                    return self.heap_bottom_va;
-                }
+    /// move the program break to `new_brk`, return the new break,
+    /// or the current one when the request is refused like linux
+    pub fn reset_heap_break(&mut self, new_brk: VirtAddr, data_limit: usize) -> VirtAddr {
+        let bottom = self.heap_bottom();
+        let old_brk = self.heap_break();
+        // brk(0) and other queries end here
+        if new_brk < bottom || new_brk == old_brk {
+            return old_brk;
+        }
+        if new_brk.0 - bottom.0 > data_limit {
+            return old_brk;
+        }
+        let Some(top) = self.heap_top() else {
+            // no heap yet, create it lazily
+            if self.check_free(bottom, new_brk.0 - bottom.0).is_err() {
+                return old_brk;
             }
+            self.heap_bottom_va = bottom;
+            self.push_area(
+                UserVmArea::new(bottom..new_brk, UserVmAreaType::Heap, MapPerm::R | MapPerm::W | MapPerm::U),
+                None
+            );
+            return new_brk;
         };
-        let range = heap.range_va.clone();
-        if new_brk.ceil() > range.end.ceil() {
-            match self.areas.extend_back(range.start.floor()..new_brk.ceil()) {
-                Ok(_) => {}
-                Err(_) => return range.end
+        if new_brk > old_brk {
+            if new_brk.ceil() > top.end {
+                // the growth must not run into any area, one inherited by fork included
+                let (end, new_end) = (top.end.start_addr(), new_brk.ceil().start_addr());
+                if self.check_free(end, new_end.0 - end.0).is_err() {
+                    return old_brk;
+                }
+                self.areas.extend_back(top.start..new_brk.ceil()).unwrap();
             }
-        } else if new_brk.ceil() > range.start.floor() && new_brk.ceil() < range.end.ceil() {
-            match self.areas.reduce_back(range.start.floor()..new_brk.ceil()) {
-                Ok(_) => {}
-                Err(_) => return range.end
+            self.areas.get_mut(top.start).unwrap().range_va.end = new_brk;
+            return new_brk;
+        }
+        // a huge page across the new break is split first, the only step which can fail
+        if self.demote_huge_at(new_brk.ceil()).is_err() {
+            return old_brk;
+        }
+        // the areas wholly above the new break go, the one holding it is cut
+        let mut top = top;
+        while top.start.start_addr() >= new_brk {
+            let heap = self.areas.force_remove_one(top);
+            heap.unmap(&mut self.page_table);
+            match self.heap_top() {
+                Some(below) => top = below,
+                // shrunk to nothing, the heap is destroyed
+                None => return new_brk,
             }
         }
-
-        let heap = self.find_heap().unwrap();
-        if new_brk >= range.end {
-            heap.range_va = range.start..new_brk;
-            new_brk
-        } else if new_brk > range.start {
-            let right = heap.split_off(new_brk.ceil());
+        if new_brk.ceil() < top.end {
+            // drop the pages above the page holding the new break
+            self.areas.reduce_back(top.start..new_brk.ceil()).unwrap();
+            let heap = self.areas.get_mut(top.start).unwrap();
+            let right = heap.split_off(new_brk.ceil()).expect("the huge page at the break is split");
             right.unmap(&mut self.page_table);
-            new_brk
-        } else {
-            range.end
         }
+        self.areas.get_mut(top.start).unwrap().range_va.end = new_brk;
+        new_brk
     }
-    
-    pub fn from_existed(uvm_space: &mut Self) -> Self {
-        let mut ret = KVMSPACE.lock().to_user();
-        ret.heap_bottom_va = uvm_space.heap_bottom_va;
+
+    /// the address space of a child made by fork, `mm` is the key of this one
+    /// in the ipi bookkeeping, its harts are told to drop the writable tlb entries
+    pub fn from_existed(uvm_space: &mut Self, mm: usize) -> Self {
+        // only the page table is copied, every frame is shared with the child.
+        // First share every frame and take the write permission away from the parent,
+        let mut new_areas = Vec::new();
         for (_, area) in uvm_space.areas.iter_mut() {
-            if let Ok(new_area) =  area.clone_cow(&mut uvm_space.page_table) {
-                ret.push_area(new_area, None);
-            } else {
-                ret.push_area(area.clone(), None);
-            }
+            new_areas.push(area.clone_cow(&mut uvm_space.page_table));
         }
+        // then make sure no parent thread still writes through an old tlb entry,
+        // its next write faults and waits for the lock we hold,
+        shootdown_tlb(mm);
+        // only then map the frames in the child
+        let mut ret = KVMSPACE.lock().to_user();
+        for new_area in new_areas {
+            ret.push_area(new_area, None);
+        }
+        // the heap of the child is found by the type of its copied areas,
+        // the cached bottom only stands while there is no heap
+        ret.heap_bottom_va = ret.areas.iter()
+            .find(|(_, area)| area.vma_type == UserVmAreaType::Heap)
+            .map_or(uvm_space.heap_bottom_va, |(range, _)| range.start.start_addr());
+        debug_assert_eq!(ret.heap_break(), uvm_space.heap_break());
+        ret.mmap_rnd = uvm_space.mmap_rnd;
         ret
     }
     
     pub fn alloc_mmap_area(&mut self, va: VirtAddr, len: usize, perm: MapPerm, flags: MmapFlags, file: Arc<dyn File>, offset: usize) -> Result<VirtAddr, SysError> {
-        if len == 0 {
+        if len == 0 || flags.contains(MmapFlags::MAP_HUGETLB) {
+            return Err(SysError::EINVAL);
+        }
+        if flags.contains(MmapFlags::MAP_FIXED) && va.page_offset() != 0 {
             return Err(SysError::EINVAL);
         }
-        let len = (va.page_offset() + len - 1 + Constant::PAGE_SIZE) & !(Constant::PAGE_SIZE - 1);
+        let len = (len - 1 + Constant::PAGE_SIZE) & !(Constant::PAGE_SIZE - 1);
         let range = if flags.contains(MmapFlags::MAP_FIXED) {
             let range = va.floor()..(va+len).ceil();
+            Self::check_not_stack_guard(range.clone()).map_err(|_| SysError::ENOMEM)?;
             self.areas.is_range_free(range.clone()).map_err(|_| SysError::ENOMEM)?;
             range
         } else {
-            self.areas
-            .find_free_range(
-                VirtAddr::from(Constant::USER_FILE_BEG).floor()..VirtAddr::from(Constant::USER_FILE_END).floor(), 
+            self.find_free_range_near(
+                va,
+                Self::region(Constant::user_file_range()), 
                 len / Constant::PAGE_SIZE
             )
             .ok_or(SysError::ENOMEM)?
@@ -273,19 +477,34 @@ impl UserVmSpace {
         if flags.contains(MmapFlags::MAP_SHARED) && shm.is_none() {
             return Err(SysError::EINVAL);
         }
-        let len = (va.page_offset() + len - 1 + Constant::PAGE_SIZE) & !(Constant::PAGE_SIZE - 1);
+        if flags.contains(MmapFlags::MAP_HUGETLB) {
+            return self.alloc_huge_anon_area(va, len, perm, flags, shm);
+        }
+        if flags.contains(MmapFlags::MAP_FIXED) && va.page_offset() != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let len = (len - 1 + Constant::PAGE_SIZE) & !(Constant::PAGE_SIZE - 1);
         let va= va.floor().start_addr();
         let range = if flags.contains(MmapFlags::MAP_FIXED) {
             let range = va.floor()..(va+len).ceil();
+            Self::check_not_stack_guard(range.clone()).map_err(|_| SysError::ENOMEM)?;
             self.areas.is_range_free(range.clone()).map_err(|_| SysError::ENOMEM)?;
             range
-        } else {
-            self.areas
-                .find_free_range(
-                    VirtAddr::from(Constant::USER_SHARE_BEG).floor()..VirtAddr::from(Constant::USER_SHARE_END).floor(), 
+        } else if shm.is_none() && va.0 == 0 && len >= HUGE_PAGE_COUNT * Constant::PAGE_SIZE {
+            // large private mapping: align it so that the fault path can use huge pages
+            self.find_huge_aligned_range(len / Constant::PAGE_SIZE)
+                .or_else(|| self.find_free_range(
+                    Self::region(Constant::user_share_range()), 
                     len / Constant::PAGE_SIZE
-                )
+                ))
                 .ok_or(SysError::ENOMEM)?
+        } else {
+            self.find_free_range_near(
+                va,
+                Self::region(Constant::user_share_range()), 
+                len / Constant::PAGE_SIZE
+            )
+            .ok_or(SysError::ENOMEM)?
         };
         let range_va = range.start.start_addr()..range.end.start_addr();
         let start = range_va.start;
@@ -299,6 +518,84 @@ impl UserVmSpace {
         Ok(start)
     }
 
+    /// MAP_HUGETLB: the whole area is populated with huge frames right now,
+    /// fail with ENOMEM instead of falling back to small pages
+    fn alloc_huge_anon_area(&mut self, va: VirtAddr, len: usize, perm: MapPerm, flags: MmapFlags, shm: Option<Arc<ShmObj>>) -> Result<VirtAddr, SysError> {
+        if USER_HUGE_PAGE_LEVEL.is_none() || shm.is_some() {
+            return Err(SysError::ENOMEM);
+        }
+        let huge_size = HUGE_PAGE_COUNT * Constant::PAGE_SIZE;
+        let len = (len - 1 + huge_size) & !(huge_size - 1);
+        let range = if flags.contains(MmapFlags::MAP_FIXED) {
+            if va.0 % huge_size != 0 {
+                return Err(SysError::EINVAL);
+            }
+            let range = va.floor()..(va+len).floor();
+            Self::check_not_stack_guard(range.clone()).map_err(|_| SysError::ENOMEM)?;
+            self.areas.is_range_free(range.clone()).map_err(|_| SysError::ENOMEM)?;
+            range
+        } else {
+            self.find_huge_aligned_range(len / Constant::PAGE_SIZE).ok_or(SysError::ENOMEM)?
+        };
+        let range_va = range.start.start_addr()..range.end.start_addr();
+        let start = range_va.start;
+        let vma = UserVmArea::new_mmap(range_va.clone(), perm, flags, UserVmFile::None, range_va.start.0, len);
+        self.push_area(vma, None);
+        let vma = self.areas.get_mut(range.start).unwrap();
+        if vma.populate_huge(&mut self.page_table).is_err() {
+            log::warn!("[alloc_huge_anon_area] out of huge frames, len {:#x}", len);
+            let _ = self.unmap(start, len);
+            return Err(SysError::ENOMEM);
+        }
+        Ok(start)
+    }
+
+    /// the pages of a region of the user address space
+    fn region(range: Range<usize>) -> Range<VirtPageNum> {
+        VirtAddr::from(range.start).floor()..VirtAddr::from(range.end).floor()
+    }
+
+    /// find `pg_cnt` free pages in `region`, from the randomized base of the search on
+    /// and then from the start of the region, so the search wraps around
+    fn find_free_range(&self, region: Range<VirtPageNum>, pg_cnt: usize) -> Option<Range<VirtPageNum>> {
+        let pages = region.end.0.saturating_sub(region.start.0);
+        if pages == 0 {
+            return None;
+        }
+        let base = region.start + self.mmap_rnd / Constant::PAGE_SIZE % pages;
+        self.areas.find_free_range(base..region.end, pg_cnt)
+            .or_else(|| self.areas.find_free_range(region.start..(base + pg_cnt).min(region.end), pg_cnt))
+    }
+
+    /// find `pg_cnt` free pages for a mapping which is not fixed: the hint itself if it is free,
+    /// else the first free range after it in `region`, else anywhere in `region`
+    fn find_free_range_near(&self, hint: VirtAddr, region: Range<VirtPageNum>, pg_cnt: usize) -> Option<Range<VirtPageNum>> {
+        let hint = hint.floor();
+        if hint.0 != 0 {
+            let range = hint..hint + pg_cnt;
+            let in_user = range.end.start_addr().0 <= Constant::user_addr_space().end;
+            if in_user && self.check_free(hint.start_addr(), pg_cnt * Constant::PAGE_SIZE).is_ok() {
+                return Some(range);
+            }
+            if region.contains(&hint) {
+                if let Some(range) = self.areas.find_free_range(hint..region.end, pg_cnt) {
+                    return Some(range);
+                }
+            }
+        }
+        self.find_free_range(region, pg_cnt)
+    }
+
+    /// find a free range in the share area whose start is huge page aligned
+    fn find_huge_aligned_range(&self, pg_cnt: usize) -> Option<Range<VirtPageNum>> {
+        let free = self.find_free_range(
+            Self::region(Constant::user_share_range()), 
+            pg_cnt + HUGE_PAGE_COUNT - 1
+        )?;
+        let start = VirtPageNum((free.start.0 + HUGE_PAGE_COUNT - 1) & !(HUGE_PAGE_COUNT - 1));
+        Some(start..start + pg_cnt)
+    }
+
     /// try union the VMAs in a given vpn range, if all sucess, return Ok 
     fn try_union(&mut self, vpn: VirtPageNum, pg_len: usize) -> Result<(), ()> {
         let mut start = vpn;
@@ -330,17 +627,20 @@ impl UserVmSpace {
         let vpn = va.floor();
         let pg_len = (va + len).ceil().0 - vpn.0;
         let _ = self.try_union(vpn, pg_len);
+        // a huge mapping crossing either boundary has to be split first
+        self.demote_huge_at(vpn)?;
+        self.demote_huge_at(vpn + pg_len)?;
         
         let mut mid: UserVmArea;
         let old_range;
         let new_range;
         if let Some((range_vpn, front)) = self.areas.get_key_value_mut(vpn) {
-            mid = front.split_off(va.floor());
+            mid = front.split_off(va.floor())?;
             new_range = front.range_vpn();
             old_range = range_vpn;
         } else {
             if let Some((range_vpn, front)) = self.areas.range_mut(vpn..vpn+pg_len).next() {
-                mid = front.split_off(va.floor());
+                mid = front.split_off(va.floor())?;
                 new_range = front.range_vpn();
                 old_range = range_vpn;
             } else {
@@ -358,7 +658,7 @@ impl UserVmSpace {
         }
 
         if vpn + pg_len < mid.range_vpn().end {
-            let back = mid.split_off(vpn + pg_len);
+            let back = mid.split_off(vpn + pg_len)?;
             if !back.range_va.is_empty() {
                 self.areas.try_insert(back.range_vpn(), back).map_err(|_| { 
                         log::warn!("[unmap] try insert error");
@@ -373,10 +673,45 @@ impl UserVmSpace {
         Ok(mid)
     }
     
+    /// change the permission of the pages in `range`, every one of them must be mapped.
+    /// The areas are split at the ends of the range and mapped again with `perm`, the
+    /// frames stay so the pages of a PROT_NONE area come back as they were
+    pub fn mprotect(&mut self, range: Range<VirtPageNum>, perm: MapPerm) -> Result<(), SysError> {
+        let mut vpn = range.start;
+        while vpn < range.end {
+            vpn = self.areas.get(vpn).ok_or(SysError::ENOMEM)?.range_vpn().end;
+        }
+        let mut vpn = range.start;
+        while vpn < range.end {
+            let end = self.areas.get(vpn).unwrap().range_vpn().end.min(range.end);
+            let mut area = self.unmap(vpn.start_addr(), (end.0 - vpn.0) * Constant::PAGE_SIZE)?;
+            area.map_perm = perm;
+            self.push_area(area, None);
+            vpn = end;
+        }
+        Ok(())
+    }
+
     pub fn check_free(&self, va: VirtAddr, len: usize) -> Result<(), ()> {
         let range = va.floor()..(va+len).ceil();
+        Self::check_not_stack_guard(range.clone())?;
         self.areas.is_range_free(range)
     }
+
+    /// whether `va` lies in the guard gap below the user stack
+    pub fn is_stack_guard(va: VirtAddr) -> bool {
+        (Constant::user_stack_guard_bottom()..Constant::user_stack_bottom()).contains(&va.0)
+    }
+
+    /// the stack guard must never be mapped by anyone
+    fn check_not_stack_guard(range: Range<VirtPageNum>) -> Result<(), ()> {
+        let guard = Self::region(Constant::user_stack_guard_bottom()..Constant::user_stack_bottom());
+        if range.start < guard.end && guard.start < range.end {
+            Err(())
+        } else {
+            Ok(())
+        }
+    }
     
     pub fn get_area_view(&self, va: VirtAddr) -> Option<UserVmAreaView> {
         let area = self.areas.get(va.floor())?;
@@ -393,6 +728,9 @@ impl UserVmSpace {
 
     pub fn handle_page_fault(&mut self, va: VirtAddr, access_type: super::PageFaultAccessType) -> Result<(), ()> {
         let vpn = va.floor();
+        if Self::is_stack_guard(va) {
+            return Err(());
+        }
         if let Some(area) = self.areas.get_mut(va.floor()) {
             area.handle_page_fault(&mut self.page_table, vpn, access_type)
         } else {
@@ -419,100 +757,50 @@ impl UserVmSpace {
         return true;
     }
     
-    pub fn ensure_access(&mut self, va: VirtAddr, len: usize, access_type: PageFaultAccessType) -> Result<(), ()> {
-        if va.0 >= Constant::USER_ADDR_SPACE.end {
-            return Err(());
+    /// the pages of [va, va + len), None if the range leaves the user address space
+    fn user_range_vpn(va: VirtAddr, len: usize) -> Option<Range<VirtPageNum>> {
+        if len == 0 {
+            return Some(va.floor()..va.floor());
         }
-        let mut vpn = va.floor();
-        let end = (va+len).ceil();
-        while vpn < end {
-            if access_type.contains(PageFaultAccessType::WRITE) {
-                let ret = unsafe { 
-                    hal::trap::try_write_user(vpn.start_addr().0 as *mut u8)
-                };
-                if ret.is_ok() {
-                    vpn += 1;
-                    continue;
-                }
-            } else if access_type.contains(PageFaultAccessType::READ) {
-                let ret = unsafe { 
-                    hal::trap::try_read_user(vpn.start_addr().0 as *mut u8)
-                };
-                if ret.is_ok() {
-                    vpn += 1;
-                    continue;
-                }
+        let end = va.0.checked_add(len).filter(|&end| end <= Constant::user_addr_space().end)?;
+        Some(va.floor()..VirtAddr(end).ceil())
+    }
+
+    /// whether the mapping of `vpn` already allows `access_type`, a copy-on-write page
+    /// does not allow a write until it is broken
+    fn page_allows(&self, vpn: VirtPageNum, access_type: PageFaultAccessType) -> bool {
+        match self.page_table.find_pte(vpn) {
+            Some((pte, _)) if pte.is_valid() && access_type.can_access(pte.flags()) => {
+                !(access_type.contains(PageFaultAccessType::WRITE) && pte.is_cow())
             }
-            if let Some(area) = self.areas.get_mut(vpn) {
-                for vpn in vpn..end.min(area.range_vpn().end) {
-                    if !area.access_no_fault(vpn, access_type) {
-                        area.handle_page_fault(&mut self.page_table, vpn, access_type)?;
-                    }
-                }
-                vpn = area.range_vpn().end;
-            } else {
-                return Err(())
+            _ => false,
+        }
+    }
+
+    /// make [va, va + len) accessible for `access_type` the way a user access would:
+    /// a page not present yet is faulted in and a copy-on-write page is broken for a write.
+    /// Fails only when a page lies in no area, or its area does not allow the access
+    pub fn ensure_access(&mut self, va: VirtAddr, len: usize, access_type: PageFaultAccessType) -> Result<(), ()> {
+        for vpn in Self::user_range_vpn(va, len).ok_or(())? {
+            if !self.page_allows(vpn, access_type) {
+                self.handle_page_fault(vpn.start_addr(), access_type)?;
             }
         }
-        return Ok(());
+        Ok(())
     }
 
+    /// [`Self::ensure_access`] taking the write lock only when some page has to be faulted in
     pub fn ensure_access_in_lock(mutex: &SpinRwMutex<Self, impl MutexSupport>, va: VirtAddr, len: usize, access_type: PageFaultAccessType) -> Result<(), ()> {
-        if va.0 >= Constant::USER_ADDR_SPACE.end {
-            return Err(());
+        let range = Self::user_range_vpn(va, len).ok_or(())?;
+        let rself = mutex.rlock();
+        if range.clone().all(|vpn| rself.page_allows(vpn, access_type)) {
+            return Ok(());
         }
-        let mut vpn = va.floor();
-        let end = (va+len).ceil();
-        while vpn < end {
-            if access_type.contains(PageFaultAccessType::WRITE) {
-                let ret = unsafe { 
-                    hal::trap::try_write_user(vpn.start_addr().0 as *mut u8)
-                };
-                if ret.is_ok() {
-                    vpn += 1;
-                    continue;
-                }
-            } else if access_type.contains(PageFaultAccessType::READ) {
-                let ret = unsafe { 
-                    hal::trap::try_read_user(vpn.start_addr().0 as *mut u8)
-                };
-                if ret.is_ok() {
-                    vpn += 1;
-                    continue;
-                }
-            }
-            let rself = mutex.rlock();
-            if let Some(area) = rself.areas.get(vpn) {
-                let mut fault = false;
-                for vpn in vpn..end.min(area.range_vpn().end) {
-                    if !area.access_no_fault(vpn, access_type) {
-                        fault = true;
-                        break;
-                    }
-                }
-                if !fault {
-                    vpn = area.range_vpn().end;
-                    continue;
-                }
-            } else {
-                return Err(())
-            }
-            let mut wself = match rself.upgrade() {
-                Some(v) => v,
-                None => mutex.wlock()
-            };
-            let vm = &mut wself.deref_mut();
-            if let Some(area) = vm.areas.get_mut(vpn) {
-                for vpn in vpn..end.min(area.range_vpn().end) {
-                    if !area.access_no_fault(vpn, access_type) {
-                        area.handle_page_fault(&mut vm.page_table, vpn, access_type)?;
-                    }
-                }
-            } else {
-                return Err(())
-            }
-        }
-        return Ok(());
+        let mut wself = match rself.upgrade() {
+            Some(v) => v,
+            None => mutex.wlock()
+        };
+        wself.ensure_access(va, len, access_type)
     }
 
     pub fn translate_vpn(&self, vpn: VirtPageNum) -> Option<PhysPageNum> {
@@ -526,63 +814,114 @@ impl UserVmSpace {
     pub fn clear(&mut self) {
         self.areas.iter_mut().for_each(|(_, vma)| {
             vma.frames.clear();
+            vma.pinned.clear();
         });
     }
 }
 
 impl UserVmSpace {
-    fn find_heap(&mut self) -> Option<&mut UserVmArea> {
-        while let Some(area) = self.areas.get_mut(self.heap_bottom_va.floor()) {
+    /// demote the huge mapping that strictly contains `vpn` into small pages
+    fn demote_huge_at(&mut self, vpn: VirtPageNum) -> Result<(), SysError> {
+        if let Some(area) = self.areas.get_mut(vpn) {
+            match area.huge_frame_base(vpn) {
+                Some(base) if base != vpn => area.demote_huge(&mut self.page_table, base)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// the start of the heap, skipping the areas mapped at the initial bottom before the heap exists
+    fn heap_bottom(&self) -> VirtAddr {
+        let mut bottom = self.heap_bottom_va;
+        while let Some(area) = self.areas.get(bottom.floor()) {
+            if area.vma_type == UserVmAreaType::Heap {
+                break;
+            }
+            bottom = area.range_vpn().end.start_addr();
+        }
+        bottom
+    }
+
+    /// the last area of the heap, the heap is the run of heap areas from its bottom up,
+    /// mprotect and munmap may have split it into several
+    fn heap_top(&self) -> Option<Range<VirtPageNum>> {
+        let mut top = None;
+        let mut vpn = self.heap_bottom().floor();
+        while let Some((range, area)) = self.areas.get_key_value(vpn) {
             if area.vma_type != UserVmAreaType::Heap {
-                self.heap_bottom_va = area.range_vpn().end.start_addr();
-            } else {
                 break;
             }
+            vpn = range.end;
+            top = Some(range);
+        }
+        top
+    }
+
+    /// the current program break
+    fn heap_break(&self) -> VirtAddr {
+        match self.heap_top() {
+            Some(top) => self.areas.get(top.start).unwrap().range_va.end,
+            None => self.heap_bottom(),
         }
-        self.areas.get_mut(self.heap_bottom_va.floor())
     }
 
     fn load_dl_interp_if_needed<T: Reader + ?Sized>(&mut self, elf: &xmas_elf::ElfFile<'_, T>) -> Result<Option<(usize, usize)>, SysError> {
         let elf_header = elf.header;
         let ph_count = elf_header.pt2.ph_count();
-        let mut is_dl = false;
+        let mut interp_ph = None;
         for i in 0..ph_count {
             let ph = elf.program_header(i).unwrap();
-            if ph.get_type().unwrap() == xmas_elf::program::Type::Interp {
-                is_dl = true;
+            if matches!(ph.get_type(), Ok(xmas_elf::program::Type::Interp)) {
+                interp_ph = Some(ph);
                 break;
             }
         };
-        is_dl |= elf_header.pt2.type_().as_type() == xmas_elf::header::Type::SharedObject;
+        let is_dl = interp_ph.is_some() || elf_header.pt2.type_().as_type() == xmas_elf::header::Type::SharedObject;
         if !is_dl {
             return Ok(None);
         }
 
-        let mut interp: String;
-        if let Some(section) = elf.find_section_by_name(".interp") {
-            interp = String::from_utf8(section.raw_data(&elf).to_vec()).unwrap();
-            interp = interp.strip_suffix("\0").unwrap_or(&interp).to_string();   
-        } else {
-            interp = "/lib/libc.so".to_string();
-        }
+        let interp = match interp_ph {
+            Some(ph) => {
+                // the path is read from the file, it has to lie in it
+                let (offset, len) = (ph.offset() as usize, ph.file_size() as usize);
+                if len == 0 || len > PATH_MAX || offset.checked_add(len).is_none_or(|end| end > elf.input.len()) {
+                    log::warn!("[load_dl] bad PT_INTERP of {:#x} bytes at {:#x}", len, offset);
+                    return Err(SysError::ENOEXEC);
+                }
+                let path = core::str::from_utf8(elf.input.read(offset, len)).map_err(|_| SysError::ENOEXEC)?;
+                path.strip_suffix("\0").unwrap_or(path).to_string()
+            }
+            None => "/lib/libc.so".to_string(),
+        };
         log::info!("[load_dl] interp {}", interp);
 
         let interp_file;
-        let dentry = global_find_dentry(&interp).expect("cannot find interp dentry");
+        let dentry = global_find_dentry(&interp)?;
         if dentry.state() == DentryState::NEGATIVE {
             log::warn!("[load_dl] missing dl {}", interp);
             return Err(SysError::ENOENT);
         }
-        // log::info!("find symlink: {}, mode: {:?}", dentry.path(), dentry.inode().unwrap().inode_inner().mode);
+        // log::info!("find symlink: {}, mode: {:?}", dentry.path(), dentry.inode().unwrap().inode_inner().mode());
         let dentry = dentry.follow()?;
         // log::info!("follow symlink to {}", dentry.path());
-        interp_file = dentry.open(OpenFlags::O_RDWR).unwrap();
+        interp_file = dentry.open(OpenFlags::O_RDWR).ok_or(SysError::ENOEXEC)?;
 
         let reader = FileReader::new(interp_file.clone()).map_err(|_| SysError::ENOEXEC)?;
+        // the loader goes at DL_INTERP_OFFSET, or randomly above it, under the mmap areas
+        let room = Constant::user_share_range().start - Constant::DL_INTERP_OFFSET;
+        check_elf(&reader, room - aslr::interp_rnd_max(room))?;
         let interp_elf = xmas_elf::ElfFile::new(&reader).map_err(|_| SysError::ENOEXEC)?;
-        self.map_elf(&interp_elf, Some(interp_file), Constant::DL_INTERP_OFFSET.into());
-
-        Ok(Some((Constant::DL_INTERP_OFFSET, interp_elf.header.pt2.entry_point() as usize + Constant::DL_INTERP_OFFSET)))
+        let align = interp_elf.program_iter()
+            .filter(|ph| matches!(ph.get_type(), Ok(xmas_elf::program::Type::Load)))
+            .map(|ph| ph.align() as usize)
+            .max()
+            .unwrap_or(0);
+        let base = Constant::DL_INTERP_OFFSET + aslr::interp_rnd(room, align);
+        self.map_elf(&interp_elf, Some(interp_file), base.into());
+
+        Ok(Some((base, interp_elf.header.pt2.entry_point() as usize + base)))
     }
 }
 
@@ -612,7 +951,7 @@ impl UserVmArea {
             } else {
                 let frame = FrameAllocator.alloc_tracker(1).unwrap();
                 ppn = frame.range_ppn.start;
-                self.frames.insert(vpn, StrongArc::new(frame));
+                self.frames.insert(vpn, StrongArc::new_tagged(frame, ArcTag::Anon));
             }
             let dst = &mut ppn
                     .start_addr()
@@ -622,7 +961,11 @@ impl UserVmArea {
         }
     }
 
-    fn split_off(&mut self, p: VirtPageNum) -> Self {
+    fn split_off(&mut self, p: VirtPageNum) -> Result<Self, SysError> {
+        match self.huge_frame_base(p) {
+            Some(base) if base != p => self.split_huge_frame(base)?,
+            _ => {}
+        }
         let new_offset = self.offset + (p.0 - self.range_vpn().start.0) * Constant::PAGE_SIZE;
         let new_len = if new_offset - self.offset > self.len {
             0
@@ -639,24 +982,34 @@ impl UserVmArea {
             file: self.file.clone(),
             offset: new_offset,
             map_flags: self.map_flags,
-            len: new_len
-
+            len: new_len,
+            pinned: self.pinned.split_off(&p),
         };
         self.range_va = self.range_va.start..p.start_addr();
-        ret
+        Ok(ret)
     }
 
     fn alloc_frames(&mut self) {
         for vpn in self.range_vpn() {
             let frame = FrameAllocator.alloc_tracker(1).unwrap();
-            self.frames.insert(vpn, StrongArc::new(frame));
+            self.frames.insert(vpn, StrongArc::new_tagged(frame, ArcTag::Anon));
         }
     }
 
     fn map(&mut self, page_table: &mut PageTable) {
+        // a valid pte needs one of R, W and X, the pages of a PROT_NONE area stay unmapped
+        // and every access faults
+        if !self.map_perm.intersects(MapPerm::R | MapPerm::W | MapPerm::X) {
+            return;
+        }
         for (&vpn, frame) in self.frames.iter() {
+            let level = if frame.range_ppn.clone().count() == HUGE_PAGE_COUNT {
+                USER_HUGE_PAGE_LEVEL.unwrap()
+            } else {
+                PageLevel::Small
+            };
             let pte = page_table
-                .map(vpn, frame.range_ppn.start, self.map_perm, PageLevel::Small)
+                .map(vpn, frame.range_ppn.start, self.map_perm, level)
                 .expect(format!("vpn: {:#x} is mapped", vpn.0).as_str());
             if frame.get_owners() > 1 && !self.map_flags.contains(MapFlags::SHARED) {
                 pte.set_writable(false);
@@ -672,26 +1025,44 @@ impl UserVmArea {
         }
     }
 
-    fn clone_cow(&mut self, page_table: &mut PageTable) -> Result<Self, ()> {
-        if !self.map_flags.contains(MapFlags::SHARED) && self.map_perm.contains(MapPerm::W) {
-            /// update flag bit
-            for &vpn in self.frames.keys() {
-                let (pte, _) = page_table.find_pte(vpn).unwrap();
-                pte.set_writable(false);
-                pte.set_dirty(false);
-                unsafe { Instruction::tlb_flush_addr(vpn.start_addr().0); }
-            }
+    /// share the frames with a new area for fork, private writable pages become read only
+    /// in both so the first write breaks the sharing. The frames count the new owner
+    /// before the parent loses the write permission, the caller flushes the tlb
+    /// before mapping the new area
+    fn clone_cow(&mut self, page_table: &mut PageTable) -> Self {
+        // huge frames are not shared if it can be helped, the cow break path only deals
+        // with small pages. One which can not be demoted for want of memory is shared
+        // read only all the same, the write fault demotes it then
+        let huge_bases: Vec<VirtPageNum> = self.frames.iter()
+            .filter(|(_, frame)| frame.range_ppn.clone().count() > 1)
+            .map(|(&vpn, _)| vpn)
+            .collect();
+        for base in huge_bases {
+            let _ = self.demote_huge(page_table, base);
         }
-        Ok(Self {
+        // cloning the frames counts the child as an owner, so a write fault
+        // of the parent copies the frame instead of taking it back in place
+        let new_area = Self {
             range_va: self.range_va.clone(), 
             frames: self.frames.clone(), 
             map_perm: self.map_perm.clone(), 
             vma_type: self.vma_type.clone(),
             file: self.file.clone(),
-            map_flags: self.map_flags.clone(),
+            // the locks are not inherited by the child
+            map_flags: self.map_flags.difference(MapFlags::LOCKED),
             offset: self.offset,
-            len: self.len
-        })
+            len: self.len,
+            pinned: BTreeMap::new(),
+        };
+        if !self.map_flags.contains(MapFlags::SHARED) && self.map_perm.contains(MapPerm::W) {
+            // update flag bit
+            for &vpn in self.frames.keys() {
+                let (pte, _) = page_table.find_pte(vpn).unwrap();
+                pte.set_writable(false);
+                pte.set_dirty(false);
+            }
+        }
+        new_area
     }
 
     pub fn extend(&mut self, size: usize) {
@@ -705,21 +1076,33 @@ impl UserVmArea {
         }
     }
 
-    pub fn shrink(&mut self, size: usize) {
+    pub fn shrink(&mut self, size: usize) -> Result<(), SysError> {
         if size == 0 {
-            return;
+            return Ok(());
         }
-        self.split_off((self.range_va.end - size).floor());
+        self.split_off((self.range_va.end - size).floor())?;
+        Ok(())
     }
 
-    pub fn move_frames_to(&mut self, other: &mut Self) {
+    pub fn move_frames_to(&mut self, other: &mut Self) -> Result<(), SysError> {
         let self_start =  self.range_va.start.floor();
         let other_start = other.range_va.start.floor();
+        if (other_start.0 - self_start.0) % HUGE_PAGE_COUNT != 0 {
+            // the new place is not huge page aligned, keep only small frames
+            let huge_bases: Vec<VirtPageNum> = self.frames.iter()
+                .filter(|(_, frame)| frame.range_ppn.clone().count() > 1)
+                .map(|(&vpn, _)| vpn)
+                .collect();
+            for base in huge_bases {
+                self.split_huge_frame(base)?;
+            }
+        }
         for (vpn, frame) in self.frames.iter() {
             let new_vpn = other_start + (vpn.0 - self_start.0);
             other.frames.insert(new_vpn, frame.clone());
         }
         self.frames.clear();
+        Ok(())
     }
 
     pub fn handle_page_fault(&mut self, 
@@ -735,13 +1118,21 @@ impl UserVmArea {
             return Err(());
         }
         match page_table.find_pte(vpn).map(|(pte, i)| (pte, PageLevel::from(i)) ) {
-            Some((pte, _)) if pte.is_valid() => {
+            Some((pte, level)) if pte.is_valid() => {
                 if !access_type.contains(PageFaultAccessType::WRITE) {
                     return Err(());
                 }
                 if pte.is_writable() {
+                    // a stale tlb entry, another thread made the page writable meanwhile
+                    count_vm_event(VmEvent::MinorFault);
                     return Ok(());
                 }
+                if !level.lowest() {
+                    let base = self.huge_frame_base(vpn).ok_or(())?;
+                    self.demote_huge(page_table, base).map_err(|_| ())?;
+                    return self.handle_page_fault(page_table, vpn, access_type);
+                }
+                count_vm_event(VmEvent::MinorFault);
                 if self.map_flags.contains(MapFlags::SHARED) {
                     pte.set_writable(true);
                     pte.set_dirty(true);
@@ -750,12 +1141,13 @@ impl UserVmArea {
                 }
                 let old_frame = self.frames.get_mut(&vpn).unwrap();
                 if old_frame.get_owners() > 1 {
+                    count_vm_event(VmEvent::Cow);
                     let new_frame = frames_alloc(1).unwrap();
                     new_frame.range_ppn.get_slice_mut::<usize>().copy_from_slice(
                         old_frame.range_ppn.get_slice()
                     );
                     pte.set_ppn(new_frame.range_ppn.start);
-                    old_frame.emplace(new_frame);
+                    old_frame.emplace_tagged(new_frame, ArcTag::Anon);
                 }
                 pte.set_writable(true);
                 pte.set_dirty(true);
@@ -803,6 +1195,7 @@ impl UserVmArea {
         self.range_va.end = back.range_va.end;
         self.len += back.len;
         self.frames.append(&mut back.frames);
+        self.pinned.append(&mut back.pinned);
     }
 
     pub fn push_back(&mut self, back: Self) -> Result<(), Self> {
@@ -813,8 +1206,33 @@ impl UserVmArea {
         Ok(())
     }
 
+    /// hold the cached pages behind a locked file mapping, so the page cache keeps them
+    fn pin_file_pages(&mut self) {
+        let UserVmFile::File(file) = &self.file else {
+            return;
+        };
+        let Some(inode) = file.inode() else {
+            return;
+        };
+        // only the files on a file system have a page cache
+        if inode.inode_inner().mode().get_type() != InodeMode::FILE {
+            return;
+        }
+        let cache = inode.cache();
+        let start = self.range_vpn().start;
+        for vpn in self.range_vpn() {
+            let offset = self.offset + (vpn.0 - start.0) * Constant::PAGE_SIZE;
+            if let Some(page) = cache.get_page(offset) {
+                self.pinned.insert(vpn, page);
+            }
+        }
+    }
+
     fn access_no_fault(&self, vpn: VirtPageNum, access_type: PageFaultAccessType) -> bool {
-        if let Some(frame) = self.frames.get(&vpn) {
+        if !access_type.can_access(self.map_perm) {
+            return false;
+        }
+        if self.frames.contains_key(&vpn) || self.huge_frame_base(vpn).is_some() {
             if access_type.contains(PageFaultAccessType::WRITE) && !self.map_flags.contains(MapFlags::SHARED){
                 false
             } else {
@@ -826,30 +1244,119 @@ impl UserVmArea {
     }
 }
 
-impl Clone for UserVmArea {
-    fn clone(&self) -> Self {
-        let frames;
-        if !self.map_flags.contains(MapFlags::SHARED) {
-            let mut new_frames = BTreeMap::new();
-            for (&vpn, frame) in self.frames.iter() {
-                let new_frame = FrameAllocator.alloc_tracker(frame.range_ppn.clone().count()).unwrap();
-                new_frame.range_ppn.get_slice_mut::<usize>().copy_from_slice(frame.range_ppn.get_slice());
-                new_frames.insert(vpn, StrongArc::new(new_frame));
-            }
-            frames = new_frames;
+#[allow(missing_docs, unused)]
+impl UserVmArea {
+    /// the page holding `vpn` and the frame it is in, if the page is present
+    fn present_page(&self, vpn: VirtPageNum) -> Option<(PhysPageNum, StrongArc<FrameTracker>)> {
+        if let Some(frame) = self.frames.get(&vpn) {
+            return Some((frame.range_ppn.start, frame.clone()));
+        }
+        let base = self.huge_frame_base(vpn)?;
+        let frame = &self.frames[&base];
+        Some((frame.range_ppn.start + (vpn.0 - base.0), frame.clone()))
+    }
+
+    /// find the huge frame covering `vpn`, return its first vpn
+    fn huge_frame_base(&self, vpn: VirtPageNum) -> Option<VirtPageNum> {
+        let (&base, frame) = self.frames.range(..=vpn).next_back()?;
+        let count = frame.range_ppn.clone().count();
+        if count > 1 && vpn.0 < base.0 + count {
+            Some(base)
         } else {
-            frames = self.frames.clone();
+            None
         }
-        Self { 
-            range_va: self.range_va.clone(), 
-            vma_type: self.vma_type.clone(), 
-            map_perm: self.map_perm.clone(), 
-            frames,
-            file: self.file.clone(),
-            map_flags: self.map_flags.clone(),
-            offset: self.offset,
-            len: self.len
+    }
+
+    /// replace the huge frame at `base` by small frames, only the bookkeeping is changed.
+    /// ENOMEM when the frame is shared and there is no memory for a copy, it stays as it is then
+    fn split_huge_frame(&mut self, base: VirtPageNum) -> Result<(), SysError> {
+        let frame = self.frames.remove(&base).unwrap();
+        match StrongArc::try_unwrap(frame) {
+            Ok(frame) => {
+                let range_ppn = frame.leak();
+                for (i, ppn) in range_ppn.enumerate() {
+                    self.frames.insert(base + i, StrongArc::new_tagged(FrameTracker::new_in(ppn..ppn+1, FrameAllocator), ArcTag::Anon));
+                }
+            }
+            Err(frame) => {
+                // still shared by someone else, give this area its own copy,
+                // every small frame is allocated before anything changes
+                let mut copies = Vec::with_capacity(HUGE_PAGE_COUNT);
+                for _ in frame.range_ppn.clone() {
+                    let Some(copy) = FrameAllocator.alloc_tracker(1) else {
+                        self.frames.insert(base, frame);
+                        return Err(SysError::ENOMEM);
+                    };
+                    copies.push(copy);
+                }
+                for (i, (ppn, copy)) in frame.range_ppn.clone().zip(copies).enumerate() {
+                    copy.range_ppn.get_slice_mut::<usize>().copy_from_slice((ppn..ppn+1).get_slice());
+                    self.frames.insert(base + i, StrongArc::new_tagged(copy, ArcTag::Anon));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// demote a mapped huge page at `base` into small pages, keeping the permission and dirty bit,
+    /// the mapping is left alone when the frame can not be split
+    fn demote_huge(&mut self, page_table: &mut PageTable, base: VirtPageNum) -> Result<(), SysError> {
+        let (perm, dirty) = match page_table.find_pte(base) {
+            Some((pte, _)) if pte.is_valid() => (pte.flags(), pte.is_dirty()),
+            _ => return self.split_huge_frame(base),
+        };
+        self.split_huge_frame(base)?;
+        let _ = page_table.unmap(base);
+        unsafe { Instruction::tlb_flush_addr(base.start_addr().0); }
+        for vpn in base..base + HUGE_PAGE_COUNT {
+            let frame = self.frames.get(&vpn).unwrap();
+            let pte = page_table
+                .map(vpn, frame.range_ppn.start, perm, PageLevel::Small)
+                .expect(format!("vpn: {:#x} is mapped", vpn.0).as_str());
+            pte.set_dirty(dirty);
         }
+        unsafe { Instruction::tlb_flush_all(); }
+        Ok(())
+    }
+
+    /// try to back the 2 MiB block around `vpn` with one huge frame,
+    /// return false when the block doesn't fit or memory is fragmented
+    fn try_map_huge(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        let Some(level) = USER_HUGE_PAGE_LEVEL else {
+            return false;
+        };
+        let base = VirtPageNum(vpn.0 & !(HUGE_PAGE_COUNT - 1));
+        let range = self.range_vpn();
+        if base < range.start || base + HUGE_PAGE_COUNT > range.end {
+            return false;
+        }
+        if self.frames.range(base..base + HUGE_PAGE_COUNT).next().is_some() {
+            return false;
+        }
+        let Some(frame) = frames_alloc_aligned(HUGE_PAGE_COUNT, log2(HUGE_PAGE_COUNT)) else {
+            return false;
+        };
+        frame.range_ppn.get_slice_mut::<usize>().fill(0);
+        let pte = page_table
+            .map(base, frame.range_ppn.start, self.map_perm, level)
+            .expect(format!("vpn: {:#x} is mapped", base.0).as_str());
+        pte.set_dirty(true);
+        self.frames.insert(base, StrongArc::new_tagged(frame, ArcTag::Anon));
+        unsafe { Instruction::tlb_flush_addr(base.start_addr().0); }
+        true
+    }
+
+    /// populate the whole area with huge frames
+    fn populate_huge(&mut self, page_table: &mut PageTable) -> Result<(), ()> {
+        let range = self.range_vpn();
+        let mut vpn = range.start;
+        while vpn < range.end {
+            if !self.try_map_huge(page_table, vpn) {
+                return Err(());
+            }
+            vpn += HUGE_PAGE_COUNT;
+        }
+        Ok(())
     }
 }
 
@@ -873,8 +1380,9 @@ const ZERO_PAGE: ZeroPage = ZeroPage([0u8; 4096]);
 lazy_static::lazy_static!{
     static ref ZERO_PAGE_ARC: StrongArc<FrameTracker> = {
         let ppn = PhysAddr(&ZERO_PAGE as *const _ as usize & !Constant::KERNEL_ADDR_SPACE.start).floor();
-        StrongArc::new(
-            FrameTracker::new_in(ppn..ppn+1, FrameAllocator)
+        StrongArc::new_tagged(
+            FrameTracker::new_in(ppn..ppn+1, FrameAllocator),
+            ArcTag::ZeroPage,
         )
     };
 }
@@ -884,6 +1392,18 @@ struct PageFaultProcessor;
 
 #[allow(unused)]
 impl PageFaultProcessor {
+    /// the page of a file at `offset` for a fault, which is major when
+    /// the page is not in the cache yet and is read from the device
+    fn fault_in_file_page(inode: &Arc<dyn Inode>, offset: usize) -> Result<Arc<Page>, ()> {
+        let event = match inode.cache().get_page(offset) {
+            Some(_) => VmEvent::MinorFault,
+            None => VmEvent::MajorFault,
+        };
+        let page = inode.clone().read_page_at(offset).ok_or(())?;
+        count_vm_event(event);
+        Ok(page)
+    }
+
     /// map zero page
     fn map_zero_page(
         page_table: &mut PageTable,
@@ -899,7 +1419,7 @@ impl PageFaultProcessor {
                     .map(vpn, frame.range_ppn.start, perm, PageLevel::Small)
                     .expect(format!("vpn: {:#x} is mapped", vpn.0).as_str());
             pte.set_dirty(true);
-            frames.insert(vpn, StrongArc::new(frame));
+            frames.insert(vpn, StrongArc::new_tagged(frame, ArcTag::Anon));
         } else { // zero page optimize
             let mut new_perm = perm;
             new_perm.remove(MapPerm::W);
@@ -909,6 +1429,7 @@ impl PageFaultProcessor {
             frames.insert(vpn, ZERO_PAGE_ARC.clone());
         }
         unsafe { Instruction::tlb_flush_addr(vpn.start_addr().0) };
+        count_vm_event(VmEvent::MinorFault);
         Ok(())
     }
 
@@ -927,7 +1448,7 @@ impl PageFaultProcessor {
         if len < Constant::PAGE_SIZE {
             let new_frame = FrameAllocator.alloc_tracker(1).ok_or(())?;
             let data = new_frame.range_ppn.get_slice_mut::<u8>();
-            let page = inode.read_page_at(offset).ok_or(())?;
+            let page = Self::fault_in_file_page(&inode, offset)?;
             data[len..].fill(0);
             data[..len].copy_from_slice(&page.get_slice()[..len]);
             let pte = page_table
@@ -936,20 +1457,20 @@ impl PageFaultProcessor {
             if access_type.contains(PageFaultAccessType::WRITE) {
                 pte.set_dirty(true);
             }
-            frames.insert(vpn, StrongArc::new(new_frame));
+            frames.insert(vpn, StrongArc::new_tagged(new_frame, ArcTag::Anon));
         } else {
             if access_type.contains(PageFaultAccessType::WRITE) {
                 let new_frame = FrameAllocator.alloc_tracker(1).ok_or(())?;
-                let page = inode.read_page_at(offset).ok_or(())?;
+                let page = Self::fault_in_file_page(&inode, offset)?;
                 let data = new_frame.range_ppn.get_slice_mut::<u8>();
                 data.copy_from_slice(page.get_slice());
                 let pte = page_table
                     .map(vpn, new_frame.range_ppn.start, perm, PageLevel::Small)
                     .expect(format!("vpn: {:#x} is mapped", vpn.0).as_str());
                 pte.set_dirty(true);
-                frames.insert(vpn, StrongArc::new(new_frame));
+                frames.insert(vpn, StrongArc::new_tagged(new_frame, ArcTag::Anon));
             } else {
-                let page = inode.read_page_at(offset).ok_or(())?;
+                let page = Self::fault_in_file_page(&inode, offset)?;
                 let mut new_perm = perm;
                 new_perm.remove(MapPerm::W);
                 let pte = page_table
@@ -974,14 +1495,16 @@ impl PageFaultProcessor {
     ) -> Result<(), ()> {
         let inode = file.inode().ok_or(())?.clone();
         // share file mapping
-        let page = inode.read_page_at(offset).ok_or(())?;
+        let page = Self::fault_in_file_page(&inode, offset)?;
         // map a single page
         let pte = page_table
             .map(vpn, page.ppn(), perm, PageLevel::Small)
             .expect(format!("vpn: {:#x} is mapped", vpn.0).as_str());
         if access_type.contains(PageFaultAccessType::WRITE) {
             pte.set_dirty(true);
-            page.set_dirty();
+            if page.set_dirty() {
+                crate::fs::writeback::mark_dirty(&inode, 1);
+            }
         }
         frames.insert(vpn, page.frame());
         unsafe { Instruction::tlb_flush_addr(vpn.start_addr().0); }
@@ -999,6 +1522,7 @@ impl PageFaultProcessor {
     ) -> Result<(), ()> {
         // share file mapping
         let page = shm.read_page_at(offset).ok_or(())?;
+        count_vm_event(VmEvent::MinorFault);
         // map a single page
         let pte = page_table
             .map(vpn, page.ppn(), perm, PageLevel::Small)
@@ -1086,6 +1610,10 @@ impl UserLazyFaultHandler for UserHeapHandler {
             vpn: VirtPageNum,
             access_type: PageFaultAccessType,
         ) -> Result<(), ()> {
+        if access_type.contains(PageFaultAccessType::WRITE) && vma.try_map_huge(page_table, vpn) {
+            count_vm_event(VmEvent::MinorFault);
+            return Ok(());
+        }
         PageFaultProcessor::map_zero_page(page_table, vpn, access_type, vma.map_perm, &mut vma.frames)
     }
 }
@@ -1139,6 +1667,10 @@ impl UserLazyFaultHandler for UserMmapHandler {
                 &mut vma.frames
             )
         } else {
+            if access_type.contains(PageFaultAccessType::WRITE) && vma.try_map_huge(page_table, vpn) {
+                count_vm_event(VmEvent::MinorFault);
+                return Ok(());
+            }
             PageFaultProcessor::map_zero_page(
                 page_table, 
                 vpn, 
@@ -0,0 +1,178 @@
+//! the interface ioctls of sockets, SIOCGIFCONF and the SIOCGIF* queries of one interface.
+//! eth0 is the only interface, a query of any other name is ENODEV. The packet counters
+//! of the device are read with SIOCGIFSTATS, into the buffer ifr_data points to
+
+use core::mem::size_of;
+
+use smoltcp::wire::{IpCidr, Ipv4Address, Ipv4Cidr};
+
+use crate::{mm::{UserPtrRaw, UserSliceRaw}, syscall::{SysError, SysResult}, task::current_task};
+
+use super::{addr::SockAddrIn4, SaFamily, ETH0};
+
+/// ioctl: list the addresses of the interfaces into a struct ifconf
+pub const SIOCGIFCONF: usize = 0x8912;
+/// ioctl: the flags of an interface
+pub const SIOCGIFFLAGS: usize = 0x8913;
+/// ioctl: the address of an interface
+pub const SIOCGIFADDR: usize = 0x8915;
+/// ioctl: the netmask of an interface
+pub const SIOCGIFNETMASK: usize = 0x891B;
+/// ioctl: the mtu of an interface
+pub const SIOCGIFMTU: usize = 0x8921;
+/// ioctl: the hardware address of an interface
+pub const SIOCGIFHWADDR: usize = 0x8927;
+/// ioctl: the index of an interface
+pub const SIOCGIFINDEX: usize = 0x8933;
+/// ioctl: the packet counters of an interface, the first private ioctl of a device
+/// (SIOCDEVPRIVATE). ifr_data points to the first 8 fields of a struct rtnl_link_stats64
+pub const SIOCGIFSTATS: usize = 0x89F0;
+
+const IFNAMSIZ: usize = 16;
+const IFF_UP: i16 = 0x1;
+const IFF_BROADCAST: i16 = 0x2;
+const IFF_RUNNING: i16 = 0x40;
+const IFF_MULTICAST: i16 = 0x1000;
+/// sa_family of an ethernet hardware address
+const ARPHRD_ETHER: u16 = 1;
+const ETH_MTU: i32 = 1500;
+/// eth0 is the first and only interface
+const ETH0_INDEX: i32 = 1;
+
+/// struct ifreq: the name of the interface and the union of what is asked
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct IfReq {
+    ifr_name: [u8; IFNAMSIZ],
+    ifr_data: [u8; 24],
+}
+
+impl IfReq {
+    /// the name of the interface, up to the nul
+    fn name(&self) -> &[u8] {
+        let len = self.ifr_name.iter().position(|&c| c == 0).unwrap_or(IFNAMSIZ);
+        &self.ifr_name[..len]
+    }
+
+    /// the pointer stored in the union, ifr_data
+    fn data_ptr(&self) -> usize {
+        unsafe { (self.ifr_data.as_ptr() as *const usize).read_unaligned() }
+    }
+
+    /// store `val` as the answer
+    fn set<T: Copy>(&mut self, val: T) {
+        assert!(size_of::<T>() <= 24);
+        self.ifr_data = [0; 24];
+        unsafe { (self.ifr_data.as_mut_ptr() as *mut T).write_unaligned(val) };
+    }
+}
+
+/// struct ifconf: the length of the buffer, then the buffer of ifreqs
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct IfConf {
+    ifc_len: i32,
+    ifc_buf: usize,
+}
+
+/// the hardware address as a struct sockaddr
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct HwAddr {
+    sa_family: u16,
+    sa_data: [u8; 14],
+}
+
+/// whether `cmd` is one of the interface ioctls
+pub fn is_iface_ioctl(cmd: usize) -> bool {
+    matches!(
+        cmd,
+        SIOCGIFCONF | SIOCGIFFLAGS | SIOCGIFADDR | SIOCGIFNETMASK | SIOCGIFMTU | SIOCGIFHWADDR | SIOCGIFINDEX
+            | SIOCGIFSTATS
+    )
+}
+
+fn sockaddr_in(addr: Ipv4Address) -> SockAddrIn4 {
+    SockAddrIn4 { sin_family: SaFamily::AfInet as u16, sin_port: 0, sin_addr: addr, sin_zero: [0; 8] }
+}
+
+/// the address of eth0 with its prefix, the most specific one it has
+fn eth0_cidr() -> Option<Ipv4Cidr> {
+    let eth0 = ETH0.get()?;
+    let iface = eth0.iface.lock();
+    iface.ip_addrs().iter().filter_map(|cidr| match cidr {
+        IpCidr::Ipv4(cidr) => Some(*cidr),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }).max_by_key(|cidr| cidr.prefix_len())
+}
+
+/// the ifreq of eth0 with its address, as SIOCGIFCONF lists it
+fn eth0_addr_req(cidr: Ipv4Cidr) -> IfReq {
+    let mut req = IfReq { ifr_name: [0; IFNAMSIZ], ifr_data: [0; 24] };
+    let name = ETH0.get().unwrap().name().as_bytes();
+    req.ifr_name[..name.len()].copy_from_slice(name);
+    req.set(sockaddr_in(cidr.address()));
+    req
+}
+
+/// the interface ioctl `cmd` with the struct at `arg`
+pub fn ioctl(cmd: usize, arg: usize) -> SysResult {
+    let task = current_task().unwrap().clone();
+    let eth0 = ETH0.get().ok_or(SysError::ENODEV)?;
+    if cmd == SIOCGIFCONF {
+        let conf_ptr = UserPtrRaw::new(arg as *mut IfConf)
+            .ensure_write(&mut task.get_vm_space().lock())
+            .ok_or(SysError::EFAULT)?;
+        let mut conf = *conf_ptr.to_ref();
+        let req = eth0_cidr().map(eth0_addr_req);
+        let reqs = req.as_slice();
+        let len = reqs.len() * size_of::<IfReq>();
+        // a null buffer asks for the length needed
+        if conf.ifc_buf != 0 {
+            let room = (conf.ifc_len.max(0) as usize / size_of::<IfReq>()).min(reqs.len());
+            if room > 0 {
+                let buf = UserSliceRaw::new(conf.ifc_buf as *mut IfReq, room)
+                    .ensure_write(&mut task.get_vm_space().lock())
+                    .ok_or(SysError::EFAULT)?;
+                buf.to_mut().copy_from_slice(&reqs[..room]);
+            }
+            conf.ifc_len = (room * size_of::<IfReq>()) as i32;
+        } else {
+            conf.ifc_len = len as i32;
+        }
+        conf_ptr.write(conf);
+        return Ok(0);
+    }
+
+    let req_ptr = UserPtrRaw::new(arg as *mut IfReq)
+        .ensure_write(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?;
+    let mut req = *req_ptr.to_ref();
+    if req.name() != eth0.name().as_bytes() {
+        return Err(SysError::ENODEV);
+    }
+    match cmd {
+        SIOCGIFFLAGS => req.set(IFF_UP | IFF_BROADCAST | IFF_RUNNING | IFF_MULTICAST),
+        SIOCGIFADDR => req.set(sockaddr_in(eth0_cidr().ok_or(SysError::EADDRNOTAVAIL)?.address())),
+        SIOCGIFNETMASK => req.set(sockaddr_in(eth0_cidr().ok_or(SysError::EADDRNOTAVAIL)?.netmask())),
+        SIOCGIFMTU => req.set(ETH_MTU),
+        SIOCGIFHWADDR => {
+            let mut sa_data = [0u8; 14];
+            sa_data[..6].copy_from_slice(eth0.ethernet_address().as_bytes());
+            req.set(HwAddr { sa_family: ARPHRD_ETHER, sa_data });
+        }
+        SIOCGIFINDEX => req.set(ETH0_INDEX),
+        SIOCGIFSTATS => {
+            // the request itself is left as it is
+            let stats_ptr = UserPtrRaw::new(req.data_ptr() as *mut [u64; 8])
+                .ensure_write(&mut task.get_vm_space().lock())
+                .ok_or(SysError::EFAULT)?;
+            stats_ptr.write(eth0.stats().snapshot());
+            return Ok(0);
+        }
+        _ => return Err(SysError::ENOTTY),
+    }
+    req_ptr.write(req);
+    Ok(0)
+}
@@ -0,0 +1,266 @@
+use alloc::{boxed::Box, collections::btree_map::BTreeMap, sync::{Arc, Weak}};
+use core::{sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering}, time::Duration};
+
+use smoltcp::{iface::SocketHandle, socket::tcp, wire::IpEndpoint};
+
+use crate::{sync::mutex::SpinNoIrqLock, timer::{get_current_time_duration, timer::{Timer, TimerEvent, TimerHandle, TIMER_MANAGER}}};
+
+use super::{poller, waker_list::WakerList, SOCKET_SET};
+
+/// the largest TCP_KEEPIDLE in seconds, as in linux
+pub const MAX_TCP_KEEPIDLE: i32 = 32767;
+/// the largest TCP_KEEPINTVL in seconds, as in linux
+pub const MAX_TCP_KEEPINTVL: i32 = 32767;
+/// the largest TCP_KEEPCNT, as in linux
+pub const MAX_TCP_KEEPCNT: i32 = 127;
+/// the shortest time between two checks of a watchdog
+const MIN_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// the keepalive options of a TCP socket
+#[derive(Debug, Clone, Copy)]
+pub struct KeepAlive {
+    /// SO_KEEPALIVE
+    pub enabled: bool,
+    /// TCP_KEEPIDLE, the silence of the peer before the first probe
+    pub idle: Duration,
+    /// TCP_KEEPINTVL, the time between two probes
+    pub intvl: Duration,
+    /// TCP_KEEPCNT, the probes left unanswered before the connection is reset
+    pub cnt: u32,
+    /// TCP_USER_TIMEOUT, the longest sent data may stay unacknowledged, None if off
+    pub user_timeout: Option<Duration>,
+}
+
+impl KeepAlive {
+    /// the defaults of linux: the first probe after two hours of silence, nine of them 75 seconds apart
+    pub const DEFAULT: Self = Self {
+        enabled: false,
+        idle: Duration::from_secs(7200),
+        intvl: Duration::from_secs(75),
+        cnt: 9,
+        user_timeout: None,
+    };
+
+    /// whether the connection needs a watchdog
+    pub fn armed(&self) -> bool {
+        self.enabled || self.user_timeout.is_some()
+    }
+
+    /// the probes sent after `silence` of the peer
+    fn probes(&self, silence: Duration) -> u32 {
+        if !self.enabled || silence < self.idle {
+            return 0;
+        }
+        ((silence - self.idle).as_micros() / self.intvl.as_micros().max(1)) as u32 + 1
+    }
+
+    /// how long after `silence` the next probe or user timeout is due
+    fn next_check(&self, silence: Duration) -> Duration {
+        let mut next = Duration::MAX;
+        if self.enabled {
+            next = if silence < self.idle {
+                self.idle - silence
+            } else {
+                let intvl = self.intvl.as_micros().max(1);
+                Duration::from_micros((intvl - (silence - self.idle).as_micros() % intvl) as u64)
+            };
+        }
+        if let Some(timeout) = self.user_timeout {
+            next = next.min(timeout.saturating_sub(silence).max(MIN_CHECK_INTERVAL).min(timeout));
+        }
+        next.max(MIN_CHECK_INTERVAL)
+    }
+}
+
+/// the watchdogs of the connections by (local port, peer), as the segments from the peer name them
+static WATCHDOGS: SpinNoIrqLock<BTreeMap<(u16, IpEndpoint), Weak<Watchdog>>> = SpinNoIrqLock::new(BTreeMap::new());
+
+/// Dead peer detection of a TCP connection, only made while SO_KEEPALIVE or
+/// TCP_USER_TIMEOUT is on, so an idle socket costs nothing.
+///
+/// smoltcp sends a probe after every TCP_KEEPINTVL of silence, sooner than linux
+/// which waits TCP_KEEPIDLE for the first one. The watchdog counts the probes
+/// due since the peer was last heard from on a timer of its own, and resets the
+/// connection once more than TCP_KEEPCNT of them went unanswered, or once data
+/// sent stayed unacknowledged past the user timeout. The waiters then fail with ETIMEDOUT
+pub struct Watchdog {
+    /// the socket watched, None once the socket is closed and may leave the socket set
+    handle: SpinNoIrqLock<Option<SocketHandle>>,
+    key: (u16, IpEndpoint),
+    config: SpinNoIrqLock<KeepAlive>,
+    /// when the peer was last heard from, in microseconds
+    last_rx: AtomicU64,
+    /// when the oldest data not acknowledged yet was sent, in microseconds, 0 if none
+    unacked_since: AtomicU64,
+    /// the probes the peer left unanswered so far
+    unanswered: AtomicU32,
+    /// the connection was reset for a dead peer
+    timed_out: AtomicBool,
+    /// the pending check
+    timer: SpinNoIrqLock<Option<TimerHandle>>,
+    rx_wakers: Arc<WakerList>,
+    tx_wakers: Arc<WakerList>,
+}
+
+impl Watchdog {
+    /// watch the connection `handle` from `local_port` to `peer`
+    pub fn start(
+        handle: SocketHandle,
+        local_port: u16,
+        peer: IpEndpoint,
+        config: KeepAlive,
+        rx_wakers: Arc<WakerList>,
+        tx_wakers: Arc<WakerList>,
+    ) -> Arc<Self> {
+        let now = get_current_time_duration();
+        let dog = Arc::new(Self {
+            handle: SpinNoIrqLock::new(Some(handle)),
+            key: (local_port, peer),
+            config: SpinNoIrqLock::new(config),
+            last_rx: AtomicU64::new(now.as_micros() as u64),
+            unacked_since: AtomicU64::new(0),
+            unanswered: AtomicU32::new(0),
+            timed_out: AtomicBool::new(false),
+            timer: SpinNoIrqLock::new(None),
+            rx_wakers,
+            tx_wakers,
+        });
+        WATCHDOGS.lock().insert(dog.key, Arc::downgrade(&dog));
+        dog.arm(now + config.next_check(Duration::ZERO));
+        dog
+    }
+
+    /// change the options, the next check is moved to match them
+    pub fn set_config(self: &Arc<Self>, config: KeepAlive) {
+        *self.config.lock() = config;
+        let now = get_current_time_duration();
+        self.arm(now + config.next_check(self.silence(now)));
+    }
+
+    /// stop watching, before the socket is closed
+    pub fn stop(&self) {
+        *self.handle.lock() = None;
+        if let Some(timer) = self.timer.lock().take() {
+            timer.cancel();
+        }
+    }
+
+    /// whether the connection was reset for a dead peer
+    pub fn timed_out(&self) -> bool {
+        self.timed_out.load(Ordering::Acquire)
+    }
+
+    /// the probes the peer left unanswered at the last check
+    pub fn unanswered(&self) -> u32 {
+        self.unanswered.load(Ordering::Relaxed)
+    }
+
+    /// data was sent, the user timeout counts from the oldest data not acknowledged
+    pub fn note_sent(&self) {
+        let now = get_current_time_duration().as_micros() as u64;
+        let _ = self.unacked_since.compare_exchange(0, now, Ordering::AcqRel, Ordering::Relaxed);
+    }
+
+    fn silence(&self, now: Duration) -> Duration {
+        now.saturating_sub(Duration::from_micros(self.last_rx.load(Ordering::Acquire)))
+    }
+
+    /// move the pending check to `deadline`, or add one if it has fired
+    fn arm(self: &Arc<Self>, deadline: Duration) {
+        let mut timer = self.timer.lock();
+        if timer.is_some_and(|handle| handle.reset(deadline)) {
+            return;
+        }
+        *timer = Some(TIMER_MANAGER.add_timer(Timer::new(deadline, Box::new(WatchdogTimer(Arc::downgrade(self))))));
+    }
+
+    /// count the probes due, reset the connection if the peer is dead, otherwise check again later
+    fn check(self: &Arc<Self>) {
+        if self.timed_out() {
+            return;
+        }
+        // held until the check is done, so the socket stays in the set
+        let watched = self.handle.lock();
+        let Some(handle) = *watched else {
+            return;
+        };
+        let config = *self.config.lock();
+        let now = get_current_time_duration();
+        let (active, send_queue) = SOCKET_SET.with_socket::<tcp::Socket, _, _>(handle, |socket| {
+            (socket.is_active(), socket.send_queue())
+        });
+        if !active {
+            return;
+        }
+        let silence = self.silence(now);
+        let probes = config.probes(silence);
+        self.unanswered.store(probes, Ordering::Relaxed);
+        if send_queue == 0 {
+            self.unacked_since.store(0, Ordering::Relaxed);
+        }
+        // an ack from the peer is progress too, so the wait counts from the later of the two
+        let unacked_for = match self.unacked_since.load(Ordering::Acquire) {
+            0 => None,
+            since => Some(now.saturating_sub(Duration::from_micros(since)).min(silence)),
+        };
+        let keepalive_dead = config.enabled && probes > config.cnt;
+        let user_timeout_dead = config.user_timeout.zip(unacked_for).is_some_and(|(timeout, waited)| waited >= timeout);
+        if keepalive_dead || user_timeout_dead {
+            log::warn!(
+                "[Watchdog] peer {} of port {} silent for {:?}, {} probes unanswered, reset",
+                self.key.1, self.key.0, silence, probes,
+            );
+            self.timed_out.store(true, Ordering::Release);
+            SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| socket.abort());
+            poller::kick();
+            self.rx_wakers.wake_all();
+            self.tx_wakers.wake_all();
+            return;
+        }
+        // the probes go out on an interface poll
+        poller::kick();
+        self.arm(now + config.next_check(silence));
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        if let Some(timer) = self.timer.lock().take() {
+            timer.cancel();
+        }
+        let mut dogs = WATCHDOGS.lock();
+        // the same endpoints may be watched again by a newer connection
+        if dogs.get(&self.key).is_some_and(|dog| dog.strong_count() == 0) {
+            dogs.remove(&self.key);
+        }
+    }
+}
+
+/// the timer of a watchdog, does nothing once the connection is gone
+struct WatchdogTimer(Weak<Watchdog>);
+
+impl TimerEvent for WatchdogTimer {
+    fn callback(self: Box<Self>) -> Option<Timer> {
+        if let Some(dog) = self.0.upgrade() {
+            dog.check();
+        }
+        None
+    }
+}
+
+/// a segment came from `peer` to `local_port`, the peer is alive.
+/// Called for every TCP segment received, with the socket set locked
+pub fn note_rx(local_port: u16, peer: IpEndpoint) {
+    let dog = {
+        let dogs = WATCHDOGS.lock();
+        if dogs.is_empty() {
+            return;
+        }
+        dogs.get(&(local_port, peer)).cloned()
+    };
+    // upgraded outside the table lock, the drop of the last reference takes it
+    if let Some(dog) = dog.and_then(|dog| dog.upgrade()) {
+        dog.last_rx.store(get_current_time_duration().as_micros() as u64, Ordering::Release);
+        dog.unanswered.store(0, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,97 @@
+use alloc::collections::btree_map::BTreeMap;
+use core::time::Duration;
+
+use smoltcp::{iface::SocketHandle, socket::tcp::{self, State}};
+
+use crate::{sync::mutex::SpinNoIrqLock, timer::get_current_time_duration};
+
+use super::{arm_poll_timer, SOCKET_SET};
+
+/// the longest a closed socket is kept for its FIN handshake, 2*MSL capped to a few seconds
+pub const LINGER_TIMEOUT: Duration = Duration::from_secs(4);
+/// how often the interfaces are polled while some socket is lingering
+const LINGER_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// a closed socket still in the socket set
+struct Lingering {
+    /// the local port, not handed out again until the socket is gone
+    local_port: u16,
+    /// when the socket is reset if it has not finished closing
+    deadline: Duration,
+    /// reset already, dropped once the next poll has sent the RST
+    aborted: bool,
+}
+
+/// TCP sockets closed by the user but still finishing their FIN handshake.
+///
+/// Removing a smoltcp socket from the set right after close() makes the peer
+/// see a RST and loses the data still in the send buffer. Instead the socket
+/// stays in the set until it reaches CLOSED, driven by the interface polls,
+/// TIME_WAIT is cut short and any other state reset after `LINGER_TIMEOUT`.
+/// The local port of a lingering socket is not handed out again meanwhile.
+pub struct LingerTable {
+    sockets: SpinNoIrqLock<BTreeMap<SocketHandle, Lingering>>,
+}
+
+impl LingerTable {
+    /// create an empty table
+    pub const fn new() -> Self {
+        Self {
+            sockets: SpinNoIrqLock::new(BTreeMap::new()),
+        }
+    }
+    /// keep the closed `handle` in the socket set until it is done closing
+    pub fn add(&self, handle: SocketHandle, local_port: u16) {
+        let now = get_current_time_duration();
+        self.sockets.lock().insert(handle, Lingering {
+            local_port,
+            deadline: now + LINGER_TIMEOUT,
+            aborted: false,
+        });
+        arm_poll_timer(now + LINGER_POLL_INTERVAL);
+    }
+    /// whether a lingering socket still uses `port` as its local port
+    pub fn is_lingering(&self, port: u16) -> bool {
+        self.sockets.lock().values().any(|l| l.local_port == port)
+    }
+    /// drop the sockets which are done closing and reset the ones past their deadline,
+    /// called after every interface poll
+    pub fn reap(&self) {
+        let now = get_current_time_duration();
+        let mut sockets = self.sockets.lock();
+        if sockets.is_empty() {
+            return;
+        }
+        sockets.retain(|&handle, lingering| {
+            if lingering.aborted {
+                SOCKET_SET.remove(handle);
+                return false;
+            }
+            let state = SOCKET_SET.with_socket::<tcp::Socket, _, _>(handle, |socket| socket.state());
+            match state {
+                State::Closed => {
+                    SOCKET_SET.remove(handle);
+                    false
+                }
+                // everything is acknowledged, TIME_WAIT only guards against late segments
+                // so it is cut short at the deadline without a reset
+                State::TimeWait if now >= lingering.deadline => {
+                    SOCKET_SET.remove(handle);
+                    false
+                }
+                _ if now >= lingering.deadline => {
+                    log::warn!("[LingerTable] socket {} stuck in {}, reset", handle, state);
+                    SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| socket.abort());
+                    lingering.aborted = true;
+                    true
+                }
+                _ => true,
+            }
+        });
+        let pending = !sockets.is_empty();
+        drop(sockets);
+        if pending {
+            arm_poll_timer(now + LINGER_POLL_INTERVAL);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use alloc::{boxed::Box, collections::VecDeque, vec::Vec};
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec::Vec};
 use fatfs::{info, warn};
 use core::{
     ops::{Deref, DerefMut},
@@ -12,7 +12,7 @@ use smoltcp::{
 
 use crate::{net::SocketSetWrapper, sync::mutex::SpinNoIrqLock, syscall::sys_error::SysError};
 
-use super::{socket::SockResult, LISTEN_QUEUE_SIZE,SOCKET_SET};
+use super::{socket::SockResult, waker_list::WakerList, BufLens, LINGER_TABLE, SOCKET_SET};
 /// u16 num 
 const PORT_NUM: usize = 65536;
 /// entry for listen table
@@ -23,16 +23,22 @@ struct ListenEntry{
     /// —that is, connection requests that have received a SYN from a client, 
     /// but have not yet completed the three-way handshake.
     syn_queue: VecDeque<SocketHandle>,
-    /// waker for waiting for incoming connection
-    waker: Waker,
+    /// tasks waiting for incoming connection
+    wakers: Arc<WakerList>,
+    /// the buffer sizes of the listening socket, which its connections get
+    buf_lens: BufLens,
+    /// the connections pending accept beyond which a new one is refused
+    backlog: usize,
 }
 
 impl ListenEntry {
-    pub fn new(listen_endpoint: IpListenEndpoint, waker: &Waker) -> Self {
+    pub fn new(listen_endpoint: IpListenEndpoint, buf_lens: BufLens, backlog: usize) -> Self {
         Self {
             listen_endpoint,
-            syn_queue: VecDeque::with_capacity(LISTEN_QUEUE_SIZE),
-            waker: waker.clone(),
+            syn_queue: VecDeque::new(),
+            wakers: WakerList::new(),
+            buf_lens,
+            backlog,
         }
     }
     /// check if the listen entry can accept incoming connection
@@ -52,9 +58,19 @@ impl ListenEntry {
             None => true,
         }
     }
-    /// get self waker wake
+    /// wake all the tasks waiting on this entry
     pub fn wake(self) {
-        self.waker.wake_by_ref()
+        self.wakers.wake_all()
+    }
+    /// register `waker` on the entry and on every half-open connection,
+    /// so that it is woken when one of them finishes the handshake
+    fn register(&self, waker: &Waker) {
+        let fan_out = self.wakers.register(waker);
+        for &handle in &self.syn_queue {
+            SOCKET_SET.with_socket_mut::<tcp::Socket,_,_>(handle, |socket| {
+                socket.register_recv_waker(&fan_out);
+            });
+        }
     }
 }
 
@@ -84,16 +100,17 @@ impl ListenTable {
         };
         Self { inner }
     }
-    /// check if a port can listen
+    /// check if a port can listen, it must be free and not held by closing connections
     pub fn can_listen(&self, port: u16) -> bool {
-        self.inner[port as usize].lock().is_none()
+        self.inner[port as usize].lock().is_none() && !LINGER_TABLE.is_lingering(port)
     }
-    /// set a port listen
-    pub fn listen(&self, listen_endpoint: IpListenEndpoint, waker: &Waker)-> SockResult<()> {
+    /// set a port listen, the connections made on it have buffers of `buf_lens`
+    /// and at most `backlog` of them, one more like linux, wait for accept
+    pub fn listen(&self, listen_endpoint: IpListenEndpoint, buf_lens: BufLens, backlog: usize)-> SockResult<()> {
         let port = listen_endpoint.port;
         let mut entry = self.inner[port as usize].lock();
         if entry.is_none() {
-            *entry = Some(Box::new(ListenEntry::new(listen_endpoint, waker)));
+            *entry = Some(Box::new(ListenEntry::new(listen_endpoint, buf_lens, backlog)));
             Ok(())
         }
         else {
@@ -108,18 +125,30 @@ impl ListenTable {
             entry.wake()
         }
     }
-    /// accept a connection, check the syn queue and find the available connection
-    pub fn accept(&self, port: u16) -> SockResult<(SocketHandle, (IpEndpoint, IpEndpoint))> {
+    /// accept a connection, check the syn queue and find the available connection,
+    /// `waker` is registered if there is none yet
+    pub fn accept(&self, port: u16, waker: &Waker) -> SockResult<(SocketHandle, (IpEndpoint, IpEndpoint))> {
         if let Some(entry) = self.inner[port as usize].lock().deref_mut() {
-            let syn_queue = &mut entry.syn_queue;
-            let (idx, addr_tuple) = syn_queue.iter()
+            // a connection reset between the handshake and accept has lost its endpoints,
+            // drop it here and hand out the next pending one instead
+            entry.syn_queue.retain(|&handle| {
+                if is_closed(handle) {
+                    log::info!("TCP socket {}: reset before accept, dropped", handle);
+                    SOCKET_SET.remove(handle);
+                    return false;
+                }
+                true
+            });
+            let Some((idx, addr_tuple)) = entry.syn_queue.iter()
             .enumerate()
             .find_map(|(idx, &handle)| {
-                is_connected(handle).then(||(idx, get_addr_tuple(handle)))
-            }).ok_or_else(||{
+                is_connected(handle).then(|| get_addr_tuple(handle)).flatten().map(|tuple| (idx, tuple))
+            }) else {
                 log::warn!("[Listen Table] no available socket_handle");
-                SysError::EAGAIN
-            })?; 
+                entry.register(waker);
+                return Err(SysError::EAGAIN);
+            };
+            let syn_queue = &mut entry.syn_queue;
             if idx > 0 {
                 log::warn!(
                     "slow SYN queue enumeration: index = {}, len = {}!",
@@ -135,8 +164,14 @@ impl ListenTable {
             Err(SysError::EINVAL)
         }
     }
-    pub fn can_accept(&self,port: u16) -> bool {
+    /// check if there is a connection ready to accept, otherwise register `waker`
+    pub fn can_accept_or_register(&self, port: u16, waker: &Waker) -> bool {
         if let Some(entry) = self.inner[port as usize].lock().deref(){
+            if entry.syn_queue.iter().any(|&handle| is_connected(handle)) {
+                return true;
+            }
+            entry.register(waker);
+            // check again, a handshake may finish right before registering
             entry.syn_queue.iter().any(|&handle| is_connected(handle))
         }else{
             log::error!("have been set as listening, wouldn't happen");
@@ -151,16 +186,17 @@ impl ListenTable {
                 log::warn!("[LISTEN_TABLE] not listening on addr {}", dst.addr);
                 return;
             }
-            if entry.syn_queue.len() >= LISTEN_QUEUE_SIZE {
-                log::warn!("[LISTEN_TABLE] syn_queue overflow!");
+            // no socket takes the syn, so the interface answers it with a reset
+            if entry.syn_queue.len() > entry.backlog {
+                log::warn!("[LISTEN_TABLE] syn_queue overflow on port {}, backlog {}", dst.port, entry.backlog);
                 return;
             }
-            entry.waker.wake_by_ref();
+            entry.wakers.wake_all();
             log::info!(
                 "[ListenTable::incoming_tcp_packet] wake the socket who listens port {}",
                 dst.port
             );
-            let mut socket = SocketSetWrapper::new_tcp_socket();
+            let mut socket = SocketSetWrapper::new_tcp_socket(entry.buf_lens);
             if socket.listen(entry.listen_endpoint).is_ok() {
                 let handle = sockets.add(socket);
                 log::info!("TCP socket {}: prepare for connection {} -> {}", handle, src, entry.listen_endpoint);
@@ -177,11 +213,14 @@ fn is_connected(handle: SocketHandle) -> bool {
     })
 }
 
-fn get_addr_tuple(handle: SocketHandle) -> (IpEndpoint, IpEndpoint) {
+fn is_closed(handle: SocketHandle) -> bool {
+    SOCKET_SET.with_socket::<tcp::Socket,_,_>(handle, |socket| {
+        socket.state() == State::Closed
+    })
+}
+
+fn get_addr_tuple(handle: SocketHandle) -> Option<(IpEndpoint, IpEndpoint)> {
     SOCKET_SET.with_socket::<tcp::Socket, _, _>(handle, |socket| {
-        (
-            socket.local_endpoint().unwrap(),
-            socket.remote_endpoint().unwrap(),
-        )
+        Some((socket.local_endpoint()?, socket.remote_endpoint()?))
     })
 }
\ No newline at end of file
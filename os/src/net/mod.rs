@@ -1,14 +1,15 @@
-use core::{ops::DerefMut, time::Duration};
+use core::{ops::DerefMut, sync::atomic::{AtomicBool, Ordering}, time::Duration};
 
-use alloc::{boxed::Box, collections::btree_map::BTreeMap, vec,vec::Vec};
+use alloc::{boxed::Box, collections::btree_map::BTreeMap, sync::Arc, vec,vec::Vec};
+use linger::LingerTable;
 use listen_table::ListenTable;
 use log::info;
 use rand::{rngs::SmallRng, Rng, SeedableRng};
-use smoltcp::{iface::{Config, Interface, SocketHandle, SocketSet}, phy::Medium, socket::{tcp::{Socket, SocketBuffer}, AnySocket}, time::Instant, wire::{EthernetAddress, HardwareAddress, IpAddress, IpCidr, IpListenEndpoint}};
+use smoltcp::{iface::{Config, Interface, SocketHandle, SocketSet}, phy::Medium, socket::{tcp::{Socket, SocketBuffer}, AnySocket}, time::Instant, wire::{EthernetAddress, HardwareAddress, IpAddress, IpCidr, IpListenEndpoint, Ipv4Address}};
 use socket::SockResult;
 use spin::{Lazy, Once};
 
-use crate::{devices::{net::NetDeviceWrapper, NetDevice}, drivers::net::{init_network_device, loopback::LoopbackDevice}, sync::{mutex::{SpinNoIrq, SpinNoIrqLock}, UPSafeCell}, syscall::SysError, timer::{get_current_time_duration, get_current_time_us, timer::{Timer, TimerEvent, TIMER_MANAGER}}};
+use crate::{devices::{net::{NetDeviceWrapper, NetStats}, NetDevice}, drivers::net::{init_network_device, loopback::{self, LoopbackDevice}}, sync::mutex::{SpinNoIrq, SpinNoIrqLock}, syscall::SysError, sysctl::IntParam, timer::{get_current_time_duration, get_current_time_us, timer::{Timer, TimerEvent, TimerHandle, TIMER_MANAGER}}};
 /// Network Address Module
 pub mod addr;
 /// Network Socket Module
@@ -19,6 +20,18 @@ pub mod tcp;
 pub mod udp;
 /// A Listen Table for Server to allocte port
 pub mod listen_table;
+/// Fan-out wakers for sockets shared by several waiters
+pub mod waker_list;
+/// Closed TCP sockets finishing their FIN handshake
+pub mod linger;
+/// Keepalive probes and the user timeout of TCP connections
+pub mod keepalive;
+/// Routing table and ICMP errors
+pub mod route;
+/// Interface queries of sockets
+pub mod iface;
+/// The task driving the interface
+pub mod poller;
 #[repr(u16)]
 #[derive(Debug, Clone, Copy)]
 /// socket address family, used for syscalls
@@ -66,19 +79,54 @@ const CONFIG_RANDOM_SEED: u64 = 0x3A0C_1495_BC68_9A2C; // for smoltcp random see
 const PORT_START: u16 = 0xc000; // 49152
 const PORT_END: u16 = 0xffff;   // 65535
 
-const LISTEN_QUEUE_SIZE: usize = 512;
+/// sysctl net/core/somaxconn: the cap on the backlog of listen
+pub static SOMAXCONN: IntParam = IntParam::new(4096, 1, 65535);
 static LISTEN_TABLE: Lazy<ListenTable> = Lazy::new(ListenTable::new);
+static LINGER_TABLE: LingerTable = LingerTable::new();
 
 /// A wrapper for SocketSet in smoltcp
 struct SocketSetWrapper<'a>(SpinNoIrqLock<SocketSet<'a>>) ; 
 static SOCKET_SET: Lazy<SocketSetWrapper> = Lazy::new(SocketSetWrapper::new);
 
-/// TCP RX and TX buffer size
+/// the default TCP receive buffer size, SO_RCVBUF may change it
 pub const TCP_RX_BUF_LEN: usize = 64 * 1024;
-/// TCP RX and TX buffer size
+/// the default TCP send buffer size, SO_SNDBUF may change it
 pub const TCP_TX_BUF_LEN: usize = 64 * 1024;
-const UDP_RX_BUF_LEN: usize = 64 * 1024;
-const UDP_TX_BUF_LEN: usize = 64 * 1024;
+/// the default UDP receive buffer size, SO_RCVBUF may change it
+pub const UDP_RX_BUF_LEN: usize = 64 * 1024;
+/// the default UDP send buffer size, SO_SNDBUF may change it
+pub const UDP_TX_BUF_LEN: usize = 64 * 1024;
+/// the smallest buffer SO_SNDBUF and SO_RCVBUF can ask for
+pub const SOCK_MIN_BUF_LEN: usize = 2048;
+/// the largest buffer SO_SNDBUF and SO_RCVBUF can ask for, net.core.wmem_max and rmem_max
+pub const SOCK_MAX_BUF_LEN: usize = 1024 * 1024;
+
+/// the buffer sizes of a socket, fixed once its smoltcp socket is made
+#[derive(Debug, Clone, Copy)]
+pub struct BufLens {
+    /// receive buffer size
+    pub rx: usize,
+    /// send buffer size
+    pub tx: usize,
+}
+
+impl BufLens {
+    /// the default sizes of a TCP socket
+    pub const TCP: Self = Self { rx: TCP_RX_BUF_LEN, tx: TCP_TX_BUF_LEN };
+    /// the default sizes of a UDP socket
+    pub const UDP: Self = Self { rx: UDP_RX_BUF_LEN, tx: UDP_TX_BUF_LEN };
+
+    /// the size a SO_SNDBUF or SO_RCVBUF of `val` asks for, clamped to the limits
+    pub fn from_sockopt(val: i32) -> usize {
+        (val.max(0) as usize).clamp(SOCK_MIN_BUF_LEN, SOCK_MAX_BUF_LEN)
+    }
+
+    /// what getsockopt reports for a buffer of `len`: linux doubles the value set
+    /// to leave room for its bookkeeping, so a program reads back twice what it set
+    pub fn to_sockopt(len: usize) -> i32 {
+        (len * 2) as i32
+    }
+}
 
 static ETH0: Once<InterfaceWrapper> = Once::new();
 /// A wrapper for interface in smoltcp
@@ -92,6 +140,8 @@ struct InterfaceWrapper {
     /// The network interface protected by a SpinNoIrqLock to ensure thread-safe
     /// access.
     iface: SpinNoIrqLock<Interface>,
+    /// The counters of the device, read without its lock.
+    stats: Arc<NetStats>,
 }
 
 impl InterfaceWrapper {
@@ -103,11 +153,13 @@ impl InterfaceWrapper {
         config.random_seed = CONFIG_RANDOM_SEED;
         let mut raw_dev = NetDeviceWrapper::new(dev);
         let iface = SpinNoIrqLock::new(Interface::new(config, &mut raw_dev, Self::current_time()));
+        let stats = raw_dev.stats();
         Self {
             name,
             ether_addr,
             dev:SpinNoIrqLock::new(raw_dev),
             iface,
+            stats,
         }
     }
     pub fn name(&self) -> &str {
@@ -130,35 +182,16 @@ impl InterfaceWrapper {
         // log::warn!("[net::InterfaceWrapper::poll] does something have been changed? {res:?}");
         timestamp
     }
-    /// check the interface and call poll socket_handle to detect device status then poll sockets
-    pub fn check_poll(&self, timestamp: Instant, sockets: &SpinNoIrqLock<SocketSet>) {
+    /// how long from now smoltcp can wait for the next poll, None if it waits for a packet
+    pub fn poll_delay(&self, sockets: &SpinNoIrqLock<SocketSet>) -> Option<Duration> {
         let mut iface = self.iface.lock();
-        let mut sockets = sockets.lock();
-        match iface.poll_delay(timestamp, &mut sockets)
-        .map(smol_dur_to_core_cur){
-            Some(Duration::ZERO) => {
-                iface.poll(Self::current_time(), self.dev.lock().deref_mut(), &mut sockets);
-            }
-            Some(delay) => {
-                // current time + delay is the deadline for the next poll
-                let next_poll_deadline = delay +  Duration::from_micros(timestamp.total_micros() as u64);
-                let current_time = get_current_time_duration();
-                if next_poll_deadline < current_time {
-                    iface.poll(Self::current_time(), self.dev.lock().deref_mut(), &mut sockets);
-                }else {
-                    let timer = Timer::new(next_poll_deadline, Box::new(NetPollTimer{}));
-                    TIMER_MANAGER.add_timer(timer);
-                }
-            }
-            // when return None means no active sockets or all the sockets are handled
-            None => {
-                // do nothing, just call poll interface
-                let empty_timer = Timer::new(get_current_time_duration()+Duration::from_millis(2), Box::new(NetPollTimer{}));
-                TIMER_MANAGER.add_timer(empty_timer);
-            }
-        }
+        let sockets = sockets.lock();
+        iface.poll_delay(Self::current_time(), &sockets).map(smol_dur_to_core_cur)
+    }
+    /// the counters of the device
+    pub fn stats(&self) -> &NetStats {
+        &self.stats
     }
-
 }
 /// random port alloc
 pub fn get_ephemeral_port() -> SockResult<u16> {
@@ -166,14 +199,20 @@ pub fn get_ephemeral_port() -> SockResult<u16> {
     const PORT_END: u16 = 0xffff;
     static CURR: SpinNoIrqLock<u16> = SpinNoIrqLock::new(PORT_START);
     let mut curr = CURR.lock();
-
-    let port = *curr;
-    if *curr == PORT_END {
-        *curr = PORT_START;
-    } else {
-        *curr += 1;
+    // skip the ports still held by closing connections, their late segments
+    // must not reach a new connection
+    for _ in PORT_START..=PORT_END {
+        let port = *curr;
+        if *curr == PORT_END {
+            *curr = PORT_START;
+        } else {
+            *curr += 1;
+        }
+        if !LINGER_TABLE.is_lingering(port) {
+            return Ok(port);
+        }
     }
-    Ok(port)
+    Err(SysError::EADDRINUSE)
 }
 
 impl <'a> SocketSetWrapper<'a> {
@@ -182,20 +221,20 @@ impl <'a> SocketSetWrapper<'a> {
         Self(SpinNoIrqLock::new(socket_set))
     }
     /// allocate tx buffer and rx buffer ,return a Socket struct in smoltcp
-    pub fn new_tcp_socket() -> smoltcp::socket::tcp::Socket<'a> {
-        let rx_buffer = SocketBuffer::new(vec![0; TCP_RX_BUF_LEN]);
-        let tx_buffer = SocketBuffer::new(vec![0; TCP_TX_BUF_LEN]);
+    pub fn new_tcp_socket(lens: BufLens) -> smoltcp::socket::tcp::Socket<'a> {
+        let rx_buffer = SocketBuffer::new(vec![0; lens.rx]);
+        let tx_buffer = SocketBuffer::new(vec![0; lens.tx]);
         Socket::new(rx_buffer, tx_buffer)
     }
     /// allocate a udp socket, return a Socket struct in smoltcp
-    pub fn new_udp_socket() -> smoltcp::socket::udp::Socket<'a> {
+    pub fn new_udp_socket(lens: BufLens) -> smoltcp::socket::udp::Socket<'a> {
         let rx_buffer = smoltcp::socket::udp::PacketBuffer::new(
             vec![smoltcp::socket::udp::PacketMetadata::EMPTY; 8],
-            vec![0; UDP_RX_BUF_LEN], 
+            vec![0; lens.rx], 
         );
         let tx_buffer = smoltcp::socket::udp::PacketBuffer::new(
             vec![smoltcp::socket::udp::PacketMetadata::EMPTY; 8],
-            vec![0; UDP_TX_BUF_LEN],
+            vec![0; lens.tx],
         );
         smoltcp::socket::udp::Socket::new(rx_buffer, tx_buffer)
     }
@@ -223,17 +262,22 @@ impl <'a> SocketSetWrapper<'a> {
         let socket = set.get_mut(handle);
         f(socket)
     }
-    /// wrapper for eth timed poll
+    /// wrapper for eth timed poll, also drops the lingering sockets done closing.
+    /// The tasks the poll makes ready are woken once the socket set is unlocked
     pub fn poll_interfaces(&self) -> Instant {
-        ETH0.get()
-        .unwrap()
-        .poll(&self.0)
+        let timestamp = waker_list::defer_wakes(|| {
+            ETH0.get()
+            .unwrap()
+            .poll(&self.0)
+        });
+        LINGER_TABLE.reap();
+        timestamp
     }
-    /// wrapper for eth timed check_polled
-    pub fn check_poll(&self, timestamp: Instant) {
+    /// wrapper for eth poll_delay
+    pub fn poll_delay(&self) -> Option<Duration> {
         ETH0.get()
         .unwrap()
-        .check_poll(timestamp, &self.0)
+        .poll_delay(&self.0)
     }
 
     pub fn remove(&self, handle: SocketHandle) {
@@ -242,13 +286,6 @@ impl <'a> SocketSetWrapper<'a> {
     }
 }
 
-/// Poll the network stack.
-///
-/// It may receive packets from the NIC and process them, and transmit queued
-/// packets to the NIC.
-pub fn poll_interfaces() -> smoltcp::time::Instant {
-    SOCKET_SET.poll_interfaces()
-}
 /// modify the socket first, a helper method for use smoltcp consume
 pub fn modify_packet(buf: &[u8], sockets: &mut SocketSet<'_>, is_ethernet: bool) ->Result<(), smoltcp::wire::Error>{
     use smoltcp::wire::{EthernetFrame, IpProtocol, Ipv4Packet, TcpPacket};
@@ -263,11 +300,15 @@ pub fn modify_packet(buf: &[u8], sockets: &mut SocketSet<'_>, is_ethernet: bool)
         let tcp_packet = TcpPacket::new_checked(ipv4_packet.payload())?;
         let src_addr = (ipv4_packet.src_addr(), tcp_packet.src_port()).into();
         let dst_addr = (ipv4_packet.dst_addr(),tcp_packet.dst_port()).into();
+        // the peer is alive, whatever it sent
+        keepalive::note_rx(tcp_packet.dst_port(), src_addr);
         let first_flag = tcp_packet.syn() && !tcp_packet.ack();
         if first_flag {
             // info!("[modify packet]receive packet");
             LISTEN_TABLE.handle_coming_packet(src_addr, dst_addr, sockets);
         }
+    } else if ipv4_packet.next_header() == IpProtocol::Icmp {
+        route::handle_icmp(&ipv4_packet);
     }
     Ok(())
 }
@@ -296,11 +337,27 @@ impl PortManager {
     }
 }
 // function or struct concerning time ,from microseconds to smoltcp::time::Instant, from core::time::Duration to smoltcp::time::Duration
-/// timer for network poll
+/// the pending network poll timer, there is at most one
+static NET_POLL_TIMER: SpinNoIrqLock<Option<TimerHandle>> = SpinNoIrqLock::new(None);
+
+/// poll the interfaces by `deadline`, moving the pending poll timer earlier instead of adding another
+fn arm_poll_timer(deadline: Duration) {
+    let mut poll_timer = NET_POLL_TIMER.lock();
+    if let Some(timer) = *poll_timer {
+        match timer.deadline() {
+            Some(expire) if expire <= deadline => return,
+            Some(_) if timer.reset(deadline) => return,
+            _ => {}
+        }
+    }
+    *poll_timer = Some(TIMER_MANAGER.add_timer(Timer::new(deadline, Box::new(NetPollTimer{}))));
+}
+
+/// timer for network poll, the poller does the poll
 struct NetPollTimer;
 impl TimerEvent for NetPollTimer {
     fn callback(self: Box<Self>) -> Option<Timer> {
-        SOCKET_SET.poll_interfaces();
+        poller::kick();
         None
     }
 }
@@ -309,19 +366,37 @@ pub fn smol_dur_to_core_cur(duration: smoltcp::time::Duration) -> core::time::Du
     core::time::Duration::from_micros(duration.micros())
 }
 
+/// whether the network stack has an interface, it stays down without a NIC
+pub fn is_up() -> bool {
+    ETH0.get().is_some()
+}
+
+/// whether eth0 is the loopback device
+static ON_LOOPBACK: AtomicBool = AtomicBool::new(false);
+
+/// drop the TCP segments from or to `port` on the loopback device, 0 stops dropping.
+/// A test hook to play a dead peer, ENODEV when the traffic goes to a NIC
+pub fn blackhole(port: u16) -> Result<(), SysError> {
+    if !is_up() || !ON_LOOPBACK.load(Ordering::Relaxed) {
+        return Err(SysError::ENODEV);
+    }
+    loopback::set_blackhole(port);
+    Ok(())
+}
+
 pub fn init_network() {
     info!("Initialize network");
-    let (dev, dev_flag) = init_network_device();
+    let Some((dev, dev_flag)) = init_network_device() else {
+        log::warn!("no network device, the network stack stays down");
+        return;
+    };
+    ON_LOOPBACK.store(!dev_flag, Ordering::Relaxed);
     let ehter_addr = EthernetAddress(dev.mac_address().0);
     let eth0 = InterfaceWrapper::new("eth0", dev, ehter_addr);
-    let gateway: IpAddress = match option_env!("GATEWAY") {
-        Some(gw) => {
-            gw.parse().unwrap()
-        },
-        None => {
-            "".parse().unwrap()
-        }
-    };
+    // the boot argument wins over the address built in
+    let gateway: Option<Ipv4Address> = crate::devices::bootarg("gateway")
+        .or(option_env!("GATEWAY"))
+        .and_then(|gw| gw.parse().ok());
     let ip = if dev_flag {
         IP.parse().unwrap()
     }else {
@@ -335,17 +410,14 @@ pub fn init_network() {
     eth0.iface.lock().update_ip_addrs(|inner_ip_addrs|{
         inner_ip_addrs.extend(ip_addrs);
     });
-    match gateway {
-        IpAddress::Ipv4(gateway_v4) => {
-            eth0.iface.lock().routes_mut().add_default_ipv4_route(gateway_v4).unwrap();
-        }
-        _ => {}
-    }
     ETH0.call_once(|| eth0);
+    if let Some(gateway) = gateway {
+        route::set_default_gateway(gateway).unwrap();
+    }
 
     info!("created net interface {:?}:", ETH0.get().unwrap().name());
     info!("  ether:    {}", ETH0.get().unwrap().ethernet_address());
     info!("  ip:       {}", ip);
-    info!("  gateway:  {}", gateway);
+    info!("  gateway:  {:?}", gateway);
     
 }
\ No newline at end of file
@@ -0,0 +1,111 @@
+//! the poller, the one task driving eth0
+//!
+//! the interface is polled when the NIC raises its interrupt, when the timer armed
+//! from the delay smoltcp asks for expires, and when a syscall kicks it after queueing
+//! data to send. A syscall never polls the interface itself, so the sockets idle in
+//! the set cost nothing on the path of the busy ones. A NIC without an interrupt line
+//! is polled every [`IDLE_POLL_MS`] on top of that
+
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use crate::{
+    sync::mutex::SpinNoIrqLock,
+    task::schedule::spawn_kernel_task,
+    timer::get_current_time_duration,
+    utils::yield_now,
+};
+
+use super::{arm_poll_timer, is_up, socket::check_async_sockets, SOCKET_SET};
+
+/// the period of the polls of a NIC which can not interrupt
+const IDLE_POLL_MS: u64 = 2;
+/// the polls in a row before the poller yields, while smoltcp keeps asking for one at once
+const MAX_ROUNDS: usize = 8;
+
+/// set by a kick the poller has not seen yet
+static KICKED: AtomicBool = AtomicBool::new(false);
+/// the waker of the poller while it sleeps
+static POLLER: SpinNoIrqLock<Option<Waker>> = SpinNoIrqLock::new(None);
+/// whether the NIC interrupts on a frame, the loopback device has nothing to tell
+static IDLE_POLL: AtomicBool = AtomicBool::new(false);
+
+/// wake the poller for a poll of the interface, cheap enough for an interrupt handler
+pub fn kick() {
+    if !KICKED.swap(true, Ordering::AcqRel) {
+        if let Some(waker) = POLLER.lock().take() {
+            waker.wake();
+        }
+    }
+}
+
+/// the NIC has no interrupt line, poll it even when smoltcp has nothing to wait for
+pub fn poll_when_idle() {
+    IDLE_POLL.store(true, Ordering::Relaxed);
+}
+
+/// ready once the poller is kicked
+struct Kicked;
+
+impl Future for Kicked {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if KICKED.swap(false, Ordering::AcqRel) {
+            return Poll::Ready(());
+        }
+        *POLLER.lock() = Some(cx.waker().clone());
+        // a kick between the check and the waker being stored found no waker
+        if KICKED.swap(false, Ordering::AcqRel) {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+/// poll until smoltcp has nothing to do at once, at most `MAX_ROUNDS` times,
+/// and arm the timer for the next poll it asks for. Returns whether it is done
+fn poll_rounds() -> bool {
+    for _ in 0..MAX_ROUNDS {
+        SOCKET_SET.poll_interfaces();
+        let idle = IDLE_POLL.load(Ordering::Relaxed).then(|| Duration::from_millis(IDLE_POLL_MS));
+        let delay = match SOCKET_SET.poll_delay() {
+            Some(Duration::ZERO) => continue,
+            Some(delay) => Some(idle.map_or(delay, |idle| delay.min(idle))),
+            None => idle,
+        };
+        if let Some(delay) = delay {
+            arm_poll_timer(get_current_time_duration() + delay);
+        }
+        return true;
+    }
+    false
+}
+
+/// the poller: sleep until a kick, then poll the interface and tell the O_ASYNC sockets
+async fn poller() {
+    loop {
+        Kicked.await;
+        let done = poll_rounds();
+        check_async_sockets().await;
+        if !done {
+            // the rest of the work waits for the tasks woken so far
+            kick();
+            yield_now().await;
+        }
+    }
+}
+
+/// start the poller if the network is up, once the executor takes tasks
+pub fn spawn_poller() {
+    if !is_up() {
+        return;
+    }
+    spawn_kernel_task(poller());
+    kick();
+}
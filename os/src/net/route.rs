@@ -0,0 +1,198 @@
+use alloc::{collections::btree_map::BTreeMap, sync::{Arc, Weak}};
+
+use smoltcp::{
+    iface::Route,
+    wire::{Icmpv4DstUnreachable, Icmpv4Message, Icmpv4Packet, IpCidr, IpProtocol, Ipv4Address, Ipv4Cidr, Ipv4Packet},
+};
+
+use crate::{mm::UserPtrRaw, sync::mutex::SpinNoIrqLock, syscall::{SysError, SysResult}, task::current_task};
+
+use super::{addr::SockAddrIn4, socket::SockResult, waker_list::WakerList, ETH0};
+
+/// ioctl: add a routing table entry from a struct rtentry, needs privilege
+pub const SIOCADDRT: usize = 0x890B;
+/// ioctl: delete a routing table entry from a struct rtentry, needs privilege
+pub const SIOCDELRT: usize = 0x890C;
+/// rt_flags: the destination is reached through the gateway in rt_gateway
+const RTF_GATEWAY: u16 = 0x2;
+
+/// struct rtentry of SIOCADDRT and SIOCDELRT, the addresses are IPv4 sockaddrs
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RtEntry {
+    pub rt_pad1: usize,
+    /// the destination network
+    pub rt_dst: SockAddrIn4,
+    /// the gateway, used with RTF_GATEWAY
+    pub rt_gateway: SockAddrIn4,
+    /// the netmask of the destination
+    pub rt_genmask: SockAddrIn4,
+    pub rt_flags: u16,
+    pub rt_pad2: i16,
+    pub rt_pad3: usize,
+    pub rt_pad4: usize,
+    pub rt_metric: i16,
+    /// the device name, ignored as there is only eth0
+    pub rt_dev: usize,
+    pub rt_mtu: usize,
+    pub rt_window: usize,
+    pub rt_irtt: u16,
+}
+
+/// route everything off-link through `gateway`, replacing the previous default route
+pub fn set_default_gateway(gateway: Ipv4Address) -> SockResult<()> {
+    let iface = &ETH0.get().ok_or(SysError::ENODEV)?.iface;
+    iface.lock()
+        .routes_mut()
+        .add_default_ipv4_route(gateway)
+        .map_err(|_| SysError::ENOSPC)?;
+    Ok(())
+}
+
+/// route `cidr` through `gateway`, replacing a previous route to the same network
+pub fn add_route(cidr: Ipv4Cidr, gateway: Ipv4Address) -> SockResult<()> {
+    if cidr.prefix_len() == 0 {
+        return set_default_gateway(gateway);
+    }
+    let iface = &ETH0.get().ok_or(SysError::ENODEV)?.iface;
+    let cidr = IpCidr::Ipv4(cidr);
+    iface.lock().routes_mut().update(|routes| {
+        routes.retain(|route| route.cidr != cidr);
+        routes.push(Route {
+            cidr,
+            via_router: gateway.into(),
+            preferred_until: None,
+            expires_at: None,
+        }).map_err(|_| SysError::ENOSPC)
+    })
+}
+
+/// remove the route to `cidr`
+pub fn del_route(cidr: Ipv4Cidr) -> SockResult<()> {
+    let iface = &ETH0.get().ok_or(SysError::ENODEV)?.iface;
+    let cidr = IpCidr::Ipv4(cidr);
+    iface.lock().routes_mut().update(|routes| {
+        let before = routes.len();
+        routes.retain(|route| route.cidr != cidr);
+        if routes.len() == before { Err(SysError::ESRCH) } else { Ok(()) }
+    })
+}
+
+/// the routing ioctls of a socket
+pub fn ioctl(cmd: usize, arg: usize) -> SysResult {
+    let task = current_task().unwrap().clone();
+    if !task.with_cred(|cred| cred.is_privileged()) {
+        return Err(SysError::EPERM);
+    }
+    let entry = *UserPtrRaw::new(arg as *const RtEntry)
+        .ensure_read(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?
+        .to_ref();
+    let netmask = entry.rt_genmask.sin_addr;
+    let cidr = Ipv4Cidr::from_netmask(entry.rt_dst.sin_addr, netmask).map_err(|_| SysError::EINVAL)?;
+    match cmd {
+        SIOCADDRT => {
+            // routes to directly attached networks come from the interface addresses
+            if entry.rt_flags & RTF_GATEWAY == 0 {
+                return Err(SysError::EINVAL);
+            }
+            add_route(cidr, entry.rt_gateway.sin_addr)?;
+        }
+        SIOCDELRT => del_route(cidr)?,
+        _ => return Err(SysError::ENOTTY),
+    }
+    Ok(0)
+}
+
+/// the transport protocol of a local port waiting for ICMP errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+/// a socket waiting for ICMP errors about what it sent
+struct Watcher {
+    /// woken when an error arrives
+    wakers: Weak<WakerList>,
+    /// the error not picked up by the socket yet
+    error: Option<SysError>,
+}
+
+/// ICMP destination unreachable errors for the local ports of connected sockets.
+///
+/// smoltcp drops ICMP errors, so a send to a closed UDP port or an unreachable
+/// host would go unnoticed. Incoming ICMP errors are matched against the
+/// ports watched here and kept until the socket picks them up.
+static UNREACHABLE: SpinNoIrqLock<BTreeMap<(Transport, u16), Watcher>> = SpinNoIrqLock::new(BTreeMap::new());
+
+/// report ICMP errors about packets sent from local `port`, waking `wakers` when one arrives
+pub fn watch(transport: Transport, port: u16, wakers: &Arc<WakerList>) {
+    UNREACHABLE.lock().insert((transport, port), Watcher {
+        wakers: Arc::downgrade(wakers),
+        error: None,
+    });
+}
+
+/// stop reporting ICMP errors for local `port`
+pub fn unwatch(transport: Transport, port: u16) {
+    UNREACHABLE.lock().remove(&(transport, port));
+}
+
+/// take the pending ICMP error of local `port`
+pub fn take_error(transport: Transport, port: u16) -> Option<SysError> {
+    UNREACHABLE.lock().get_mut(&(transport, port))?.error.take()
+}
+
+/// whether local `port` has an ICMP error not picked up yet
+pub fn has_error(transport: Transport, port: u16) -> bool {
+    UNREACHABLE.lock().get(&(transport, port)).is_some_and(|watcher| watcher.error.is_some())
+}
+
+/// record the error an incoming ICMP destination unreachable reports,
+/// called for every received ICMP packet
+pub fn handle_icmp(ipv4_packet: &Ipv4Packet<&[u8]>) {
+    let Ok(icmp_packet) = Icmpv4Packet::new_checked(ipv4_packet.payload()) else {
+        return;
+    };
+    if icmp_packet.msg_type() != Icmpv4Message::DstUnreachable {
+        return;
+    }
+    let error = match Icmpv4DstUnreachable::from(icmp_packet.msg_code()) {
+        Icmpv4DstUnreachable::PortUnreachable => SysError::ECONNREFUSED,
+        Icmpv4DstUnreachable::NetUnreachable => SysError::ENETUNREACH,
+        Icmpv4DstUnreachable::ProtoUnreachable => SysError::ENOPROTOOPT,
+        Icmpv4DstUnreachable::FragRequired => SysError::EMSGSIZE,
+        _ => SysError::EHOSTUNREACH,
+    };
+    // the data holds the IP header of the offending packet and the first 8 bytes of its payload,
+    // enough for the source port of both TCP and UDP, new_checked would reject it as truncated
+    let data = icmp_packet.data();
+    if data.len() < 20 {
+        return;
+    }
+    let sent = Ipv4Packet::new_unchecked(data);
+    let transport = match sent.next_header() {
+        IpProtocol::Tcp => Transport::Tcp,
+        IpProtocol::Udp => Transport::Udp,
+        _ => return,
+    };
+    let Some(header) = data.get(sent.header_len() as usize..) else {
+        return;
+    };
+    if header.len() < 2 {
+        return;
+    }
+    let port = u16::from_be_bytes([header[0], header[1]]);
+    let mut unreachable = UNREACHABLE.lock();
+    let Some(watcher) = unreachable.get_mut(&(transport, port)) else {
+        return;
+    };
+    log::info!("[handle_icmp] {:?} port {} unreachable: {:?}", transport, port, error);
+    watcher.error = Some(error);
+    let wakers = watcher.wakers.upgrade();
+    drop(unreachable);
+    if let Some(wakers) = wakers {
+        wakers.wake_all();
+    }
+}
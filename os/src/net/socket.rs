@@ -1,12 +1,13 @@
-use core::{sync::atomic::AtomicUsize, task::Poll};
+use core::{sync::atomic::{AtomicBool, AtomicUsize, Ordering}, task::Poll};
 
 use alloc::{boxed::Box, sync::Arc};
 use async_trait::async_trait;
+use hal::constant::{Constant, ConstantsHal};
 use fatfs::info;
 use smoltcp::{socket::udp, wire::{IpEndpoint, IpListenEndpoint}};
-use crate::{fs::{vfs::{file::PollEvents, Dentry, File, FileInner}, OpenFlags}, sync::mutex::SpinNoIrqLock, syscall::sys_error::SysError, task::current_task};
+use crate::{fs::{vfs::{file::PollEvents, inode::InodeMode, ioctl, Dentry, FasyncList, File, FileCount, FileInner, FileOwner, Inode, OpenRef}, Kstat, OpenFlags}, sync::mutex::SpinNoIrqLock, syscall::{sys_error::SysError, SysResult}, task::current_task};
 use crate::syscall::net::SocketType;
-use super::{addr::{SockAddr, SockAddrIn4, ZERO_IPV4_ADDR}, poll_interfaces, tcp::TcpSocket, udp::UdpSocket, SaFamily};
+use super::{addr::{SockAddr, SockAddrIn4, ZERO_IPV4_ADDR}, iface, route::{self, SIOCADDRT, SIOCDELRT}, tcp::TcpSocket, udp::UdpSocket, BufLens, SaFamily};
 pub type SockResult<T> = Result<T, SysError>;
 /// a trait for differnt socket types
 /// net poll results.
@@ -56,10 +57,11 @@ impl Sock {
             }
         }
     }
-    /// listen method for socket to listen for incoming connections, for server socket
-    pub fn listen(&self) -> SockResult<()>{
+    /// listen method for socket to listen for incoming connections, for server socket,
+    /// `backlog` connections may wait for accept
+    pub fn listen(&self, backlog: usize) -> SockResult<()>{
         match self {
-            Sock::TCP(tcp) => tcp.listen(),
+            Sock::TCP(tcp) => tcp.listen(backlog),
             Sock::UDP(udp) => Err(SysError::EOPNOTSUPP)
         }
     }
@@ -70,6 +72,36 @@ impl Sock {
             Sock::UDP(udp) => udp.set_nonblocking(),
         }
     }
+    /// set or clear non-blocking, as O_NONBLOCK of the file does
+    pub fn set_nonblock(&self, nonblock: bool) {
+        match self {
+            Sock::TCP(tcp) => tcp.set_nonblock(nonblock),
+            Sock::UDP(udp) => udp.set_nonblock(nonblock),
+        }
+    }
+    /// the bytes a read gets without blocking: the stream received for tcp,
+    /// the next datagram for udp
+    pub fn recv_queue(&self) -> usize {
+        match self {
+            Sock::TCP(tcp) => tcp.recv_queue(),
+            Sock::UDP(udp) => udp.recv_queue(),
+        }
+    }
+    /// get the buffer sizes, SO_RCVBUF and SO_SNDBUF
+    pub fn buf_lens(&self) -> BufLens {
+        match self {
+            Sock::TCP(tcp) => tcp.buf_lens(),
+            Sock::UDP(udp) => udp.buf_lens(),
+        }
+    }
+    /// change the buffer sizes, see `TcpSocket::set_buf_lens` and `UdpSocket::set_buf_lens`
+    /// for when they take effect
+    pub fn set_buf_lens(&self, f: impl FnOnce(&mut BufLens)) {
+        match self {
+            Sock::TCP(tcp) => tcp.set_buf_lens(f),
+            Sock::UDP(udp) => udp.set_buf_lens(f),
+        }
+    }
     /// get the peer_addr of the socket
     pub fn peer_addr(&self) -> SockResult<SockAddr>{
         match self {
@@ -148,6 +180,26 @@ pub struct Socket {
     pub sk_type: SocketType,
     /// fd flags
     pub file_inner: FileInner,
+    /// the socket was readable when the poller last looked, for the edge of SIGIO
+    async_readable: AtomicBool,
+}
+
+/// the sockets with O_ASYNC on, the poller looks at them after every poll of the interface
+pub static ASYNC_SOCKETS: FasyncList = FasyncList::new();
+
+/// signal the owners of the sockets with O_ASYNC on which have turned readable
+pub async fn check_async_sockets() {
+    for file in ASYNC_SOCKETS.files() {
+        let Ok(socket) = file.downcast_arc::<Socket>() else {
+            continue;
+        };
+        let readable = socket.sk.poll().await.readable;
+        if !readable {
+            socket.async_readable.store(false, Ordering::Relaxed);
+        } else if !socket.async_readable.swap(true, Ordering::Relaxed) {
+            socket.file_inner.owner.notify_readable();
+        }
+    }
 }
 
 impl Socket {
@@ -175,20 +227,43 @@ impl Socket {
                 dentry: Arc::<usize>::new_zeroed(),
                 offset: AtomicUsize::new(0),
                 flags: SpinNoIrqLock::new(fd_flags),
+                count: FileCount::new(),
+                owner: FileOwner::new(),
+                open: OpenRef::none(),
             },
+            async_readable: AtomicBool::new(false),
         }
     }
-    /// new a socket with a given socket 
-    pub fn from_another(another: &Self, sk: Sock) -> Self {
+    /// new a socket with a given socket of the same type as `another`,
+    /// nothing else is inherited so the new one blocks unless `non_block`
+    pub fn from_another(another: &Self, sk: Sock, non_block: bool) -> Self {
+        let fd_flags = if non_block {
+            sk.set_nonblocking();
+            OpenFlags::O_RDWR | OpenFlags::O_NONBLOCK
+        } else {
+            OpenFlags::O_RDWR
+        };
         Self {
             sk: sk,
             sk_type: another.sk_type,
             file_inner: FileInner{
                 dentry: Arc::<usize>::new_zeroed(),
                 offset: AtomicUsize::new(0),
-                flags: SpinNoIrqLock::new(OpenFlags::O_RDWR),
+                flags: SpinNoIrqLock::new(fd_flags),
+                count: FileCount::new(),
+                owner: FileOwner::new(),
+                open: OpenRef::none(),
             },
+            async_readable: AtomicBool::new(false),
+        }
+    }
+    /// receive from the socket, once it is drained the next data turns it readable again for SIGIO
+    pub async fn recv(&self, buf: &mut [u8]) -> SockResult<(usize, IpEndpoint)> {
+        let ret = self.sk.recv(buf).await;
+        if self.sk.recv_queue() == 0 {
+            self.async_readable.store(false, Ordering::Relaxed);
         }
+        ret
     }
 }
 
@@ -216,7 +291,7 @@ impl File for Socket {
         if buf.len() == 0 {
             return Ok(0);
         }
-        self.sk.recv(buf).await.map(|e|e.0)
+        self.recv(buf).await.map(|e|e.0)
     }
 
     #[doc = " Write `UserBuffer` to file"]
@@ -228,9 +303,42 @@ impl File for Socket {
         self.sk.send(buf, None).await.map(|e|e)
     }
 
-    async fn base_poll(&self, events:PollEvents) -> PollEvents {
+    #[doc = " the sockets with O_ASYNC on are looked at by the poller"]
+    fn fasync_list(&self) -> Option<&FasyncList> {
+        Some(&ASYNC_SOCKETS)
+    }
+
+    #[doc = " a socket has no dentry, the one in the inner is a placeholder"]
+    fn dentry(&self) -> Option<Arc<dyn Dentry>> {
+        None
+    }
+
+    fn inode(&self) -> Option<Arc<dyn Inode>> {
+        None
+    }
+
+    fn stat(&self) -> Result<Kstat, SysError> {
+        Ok(Kstat::anonymous(InodeMode::SOCKET | InodeMode::from_bits_truncate(0o777), 0, Constant::PAGE_SIZE as i32))
+    }
+
+    #[doc = " FIONREAD, and the routing table and interface ioctls shared by every socket"]
+    fn ioctl(&self, cmd: usize, arg: usize) -> SysResult {
+        match cmd {
+            ioctl::FIONREAD => ioctl::write_arg(arg, self.sk.recv_queue() as i32),
+            SIOCADDRT | SIOCDELRT => route::ioctl(cmd, arg),
+            cmd if iface::is_iface_ioctl(cmd) => iface::ioctl(cmd, arg),
+            _ => Err(SysError::ENOTTY),
+        }
+    }
+
+    #[doc = " O_NONBLOCK of the flags is the mode of the socket"]
+    fn set_flags(&self, flags: OpenFlags) {
+        self.sk.set_nonblock(flags.contains(OpenFlags::O_NONBLOCK));
+        *self.file_inner.flags.lock() = flags;
+    }
+
+    async fn poll(&self, events: PollEvents) -> PollEvents {
         let mut res = PollEvents::empty();
-        poll_interfaces();
         let netstate = self.sk.poll().await;
         if events.contains(PollEvents::IN) && netstate.readable {
             res |= PollEvents::IN;
@@ -239,10 +347,10 @@ impl File for Socket {
             res |= PollEvents::OUT;
         }
         if netstate.hangup {
-            log::warn!("[Socket::bask_poll] PollEvents is hangup");
+            log::warn!("[Socket::poll] PollEvents is hangup");
             res |= PollEvents::HUP;
         }
-        // log::info!("[Socket::base_poll] ret events:{res:?} {netstate:?}");
+        // log::info!("[Socket::poll] ret events:{res:?} {netstate:?}");
         res
     }
 }
\ No newline at end of file
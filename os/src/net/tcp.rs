@@ -1,14 +1,14 @@
-use core::{fmt::UpperExp, future::Future, net::SocketAddr, sync::atomic::{AtomicBool, AtomicU8, Ordering}, time::{self, Duration}};
+use core::{fmt::UpperExp, future::Future, net::SocketAddr, sync::atomic::{AtomicBool, AtomicU8, Ordering}, time};
 
-use crate::{ net::addr::LOCAL_IPV4, sync::{mutex::SpinNoIrqLock, UPSafeCell}, syscall::{sys_error::SysError, SysResult}, task::current_task, timer::timed_task::ksleep, utils::{get_waker, suspend_now, yield_now}};
+use crate::{ net::addr::LOCAL_IPV4, sync::mutex::SpinNoIrqLock, syscall::{sys_error::SysError, SysResult}, task::{current_task, signal::interruptible}, utils::{get_waker, suspend_now, yield_now}};
 
-use super::{addr::{ ZERO_IPV4_ADDR, ZERO_IPV4_ENDPOINT}, get_ephemeral_port, listen_table::ListenTable, socket::{PollState, Sock}, NetPollTimer, SocketSetWrapper, ETH0, LISTEN_TABLE, PORT_END, PORT_START, RCV_SHUTDOWN, SEND_SHUTDOWN, SHUTDOWN_MASK, SHUTRD, SHUTRDWR, SHUTWR, SOCKET_SET, SOCK_RAND_SEED, TCP_TX_BUF_LEN};
-use alloc::vec::Vec;
+use super::{addr::{ ZERO_IPV4_ADDR, ZERO_IPV4_ENDPOINT}, get_ephemeral_port, keepalive::{KeepAlive, Watchdog}, listen_table::ListenTable, poller, route::{self, Transport}, socket::{PollState, Sock}, waker_list::WakerList, NetPollTimer, SocketSetWrapper, ETH0, LINGER_TABLE, LISTEN_TABLE, PORT_END, PORT_START, RCV_SHUTDOWN, SEND_SHUTDOWN, SHUTDOWN_MASK, SHUTRD, SHUTRDWR, SHUTWR, SOCKET_SET, SOCK_RAND_SEED, BufLens};
+use alloc::{sync::Arc, vec::Vec};
 use fatfs::warn;
 use hal::println;
 use smoltcp::{
     iface::{SocketHandle, SocketSet},
-    socket::tcp::{self, ConnectError, State},
+    socket::tcp::{self, State},
     wire::{IpAddress, IpEndpoint, IpListenEndpoint},
 };
 use spin::Spin;
@@ -49,16 +49,30 @@ impl From<u8> for SocketState {
 pub struct TcpSocket {
     /// socket state
     state: AtomicU8,
-    /// socket handle
-    handle: UPSafeCell<Option<SocketHandle>>,
+    /// socket handle, set once when the socket joins the SocketSet
+    handle: SpinNoIrqLock<Option<SocketHandle>>,
     /// local endpoint
-    local_endpoint: UPSafeCell<Option<IpEndpoint>>,
+    local_endpoint: SpinNoIrqLock<Option<IpEndpoint>>,
     /// remote endpoint
-    remote_endpoint: UPSafeCell<Option<IpEndpoint>>,
+    remote_endpoint: SpinNoIrqLock<Option<IpEndpoint>>,
     /// whether in non=blokcing mode
     nonblock_flag: AtomicBool,
-    /// shutdown flag
-    shutdown_flag: UPSafeCell<u8>,
+    /// shutdown flag, only ever gains bits so updates are fetch_or
+    shutdown_flag: AtomicU8,
+    /// SO_LINGER timeout in seconds, None if off
+    linger: SpinNoIrqLock<Option<u32>>,
+    /// SO_REUSEADDR, listen even while closed connections still hold the port
+    reuse_addr: AtomicBool,
+    /// SO_RCVBUF and SO_SNDBUF, the buffers of the smoltcp socket made by connect or for an accepted connection
+    buf_lens: SpinNoIrqLock<BufLens>,
+    /// SO_KEEPALIVE, TCP_KEEPIDLE, TCP_KEEPINTVL, TCP_KEEPCNT and TCP_USER_TIMEOUT
+    keepalive: SpinNoIrqLock<KeepAlive>,
+    /// the dead peer detection of the connection, only while keepalive or the user timeout is on
+    watchdog: SpinNoIrqLock<Option<Arc<Watchdog>>>,
+    /// tasks waiting for the socket to become readable
+    rx_wakers: Arc<WakerList>,
+    /// tasks waiting for the socket to become writable
+    tx_wakers: Arc<WakerList>,
 }
 
 unsafe impl Send for TcpSocket {}
@@ -66,25 +80,39 @@ unsafe impl Sync for TcpSocket {}
 
 impl TcpSocket {
     /// new a TcpSocket without a socket handle (Still not get in the SocketSet)
-    pub const fn new_v4_without_handle() -> Self {
+    pub fn new_v4_without_handle() -> Self {
         Self {
             state: AtomicU8::new(SocketState::Closed as u8),
-            handle: UPSafeCell::const_new(None),
-            local_endpoint: UPSafeCell::const_new(Some(ZERO_IPV4_ENDPOINT)),
-            remote_endpoint: UPSafeCell::const_new(Some(ZERO_IPV4_ENDPOINT)),
+            handle: SpinNoIrqLock::new(None),
+            local_endpoint: SpinNoIrqLock::new(Some(ZERO_IPV4_ENDPOINT)),
+            remote_endpoint: SpinNoIrqLock::new(Some(ZERO_IPV4_ENDPOINT)),
             nonblock_flag: AtomicBool::new(false),
-            shutdown_flag: UPSafeCell::const_new(0),
+            shutdown_flag: AtomicU8::new(0),
+            linger: SpinNoIrqLock::new(None),
+            reuse_addr: AtomicBool::new(false),
+            buf_lens: SpinNoIrqLock::new(BufLens::TCP),
+            keepalive: SpinNoIrqLock::new(KeepAlive::DEFAULT),
+            watchdog: SpinNoIrqLock::new(None),
+            rx_wakers: WakerList::new(),
+            tx_wakers: WakerList::new(),
         }
     }
-    /// create a TcpSocket with a socket handle
-    pub const fn new_v4_connected(handle: SocketHandle, local_endpoint: IpEndpoint, remote_endpoint: IpEndpoint) -> Self {
+    /// create a TcpSocket with a socket handle whose buffers are `buf_lens`
+    pub fn new_v4_connected(handle: SocketHandle, local_endpoint: IpEndpoint, remote_endpoint: IpEndpoint, buf_lens: BufLens) -> Self {
         Self {
             state: AtomicU8::new(SocketState::Connected as u8),
-            handle: UPSafeCell::const_new(Some(handle)),
-            local_endpoint: UPSafeCell::const_new(Some(local_endpoint)),
-            remote_endpoint: UPSafeCell::const_new(Some(remote_endpoint)),
+            handle: SpinNoIrqLock::new(Some(handle)),
+            local_endpoint: SpinNoIrqLock::new(Some(local_endpoint)),
+            remote_endpoint: SpinNoIrqLock::new(Some(remote_endpoint)),
             nonblock_flag: AtomicBool::new(false),
-            shutdown_flag: UPSafeCell::const_new(0),
+            shutdown_flag: AtomicU8::new(0),
+            linger: SpinNoIrqLock::new(None),
+            reuse_addr: AtomicBool::new(false),
+            buf_lens: SpinNoIrqLock::new(buf_lens),
+            keepalive: SpinNoIrqLock::new(KeepAlive::DEFAULT),
+            watchdog: SpinNoIrqLock::new(None),
+            rx_wakers: WakerList::new(),
+            tx_wakers: WakerList::new(),
         }
     }
     /// get the socket state
@@ -114,52 +142,34 @@ impl TcpSocket {
             Err(actual_state) => {Err(actual_state as u8)}
         }
     }
-    /// get the socket handle mut ref
-    pub fn mut_handle(&self) -> Option<&mut SocketHandle> {
-        self.handle.exclusive_access().as_mut()
-    }
-    /// get the socket handle ref
+    /// get the socket handle
     pub fn handle(&self) -> Option<SocketHandle> {
-        unsafe{
-            self.handle.get().read()
-        }
+        *self.handle.lock()
     }
     /// set the socket handle
     pub fn set_handle(&self, handle: SocketHandle) {
-        unsafe {
-            self.handle.get().write(Some(handle));
-        }
+        *self.handle.lock() = Some(handle);
     }
-    /// get the local endpoint ref
+    /// get the local endpoint
     pub fn local_endpoint(&self) -> Option<IpEndpoint> {
-        unsafe{
-            self.local_endpoint.get().read()
-        }
+        *self.local_endpoint.lock()
     }
     /// set the local endpoint
     pub fn set_local_endpoint(&self, endpoint: IpEndpoint) {
-        unsafe{
-            self.local_endpoint.get().write(Some(endpoint));
-        }
+        *self.local_endpoint.lock() = Some(endpoint);
     }
     pub fn set_local_endpoint_with_port(&self, port: u16) {
-        let inner_endpoint = self.local_endpoint.exclusive_access().clone().unwrap();
-        let addr = inner_endpoint.addr;
-        unsafe {
-            self.local_endpoint.get().write(Some(IpEndpoint::new(addr, port)));
-        }
+        let mut local_endpoint = self.local_endpoint.lock();
+        let addr = local_endpoint.unwrap().addr;
+        *local_endpoint = Some(IpEndpoint::new(addr, port));
     }
-    /// get the remote endpoint ref
+    /// get the remote endpoint
     pub fn remote_endpoint(&self) -> Option<IpEndpoint> {
-        unsafe {
-            self.remote_endpoint.get().read()
-        }
+        *self.remote_endpoint.lock()
     }
     /// set the remote endpoint
     pub fn set_remote_endpoint(&self, endpoint: IpEndpoint) {
-        unsafe{
-            self.remote_endpoint.get().write(Some(endpoint));
-        }
+        *self.remote_endpoint.lock() = Some(endpoint);
     }
     /// set non-blocking mode
     pub fn set_nonblock(&self, nonblock: bool) {
@@ -171,12 +181,84 @@ impl TcpSocket {
     }
     /// get shutdown flag
     pub fn get_shutdown(&self) -> u8 {
-        self.shutdown_flag.exclusive_access().clone()
+        self.shutdown_flag.load(Ordering::Acquire)
     }
-    /// set shutdown flag
+    /// add bits to the shutdown flag, a direction once shut down stays shut down
     pub fn set_shutdown(&self, flag: u8) {
-        unsafe {
-            self.shutdown_flag.get().write(flag)
+        self.shutdown_flag.fetch_or(flag, Ordering::Release);
+    }
+    /// get the SO_LINGER timeout
+    pub fn linger(&self) -> Option<u32> {
+        *self.linger.lock()
+    }
+    /// set the SO_LINGER timeout, a zero timeout resets the connection on close.
+    /// Close never blocks, a nonzero timeout lingers in the background like no timeout
+    pub fn set_linger(&self, linger: Option<u32>) {
+        *self.linger.lock() = linger;
+    }
+    /// get SO_REUSEADDR
+    pub fn reuse_addr(&self) -> bool {
+        self.reuse_addr.load(Ordering::Relaxed)
+    }
+    /// set SO_REUSEADDR
+    pub fn set_reuse_addr(&self, reuse: bool) {
+        self.reuse_addr.store(reuse, Ordering::Relaxed)
+    }
+    /// get the buffer sizes
+    pub fn buf_lens(&self) -> BufLens {
+        *self.buf_lens.lock()
+    }
+    /// change the buffer sizes, they take effect at the next listen or connect,
+    /// a socket already connected keeps the buffers it has
+    pub fn set_buf_lens(&self, f: impl FnOnce(&mut BufLens)) {
+        f(&mut self.buf_lens.lock())
+    }
+    /// get the keepalive options
+    pub fn keepalive(&self) -> KeepAlive {
+        *self.keepalive.lock()
+    }
+    /// change the keepalive options, a connected socket starts using them at once
+    pub fn set_keepalive(&self, f: impl FnOnce(&mut KeepAlive)) {
+        f(&mut self.keepalive.lock());
+        self.apply_keepalive();
+    }
+    /// hand the keepalive interval to smoltcp and start, update or stop the watchdog,
+    /// nothing before the connection is established
+    fn apply_keepalive(&self) {
+        let (Some(handle), SocketState::Connected) = (self.handle(), self.state()) else {
+            return;
+        };
+        let config = self.keepalive();
+        let interval = config.enabled.then(|| smoltcp::time::Duration::from_micros(config.intvl.as_micros() as u64));
+        SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| socket.set_keep_alive(interval));
+        let mut watchdog = self.watchdog.lock();
+        if !config.armed() {
+            // stopped without the lock, a check running meanwhile holds the socket set
+            let dog = watchdog.take();
+            drop(watchdog);
+            if let Some(dog) = dog {
+                dog.stop();
+            }
+            return;
+        }
+        if let Some(dog) = watchdog.as_ref() {
+            dog.set_config(config);
+            return;
+        }
+        let (Some(local), Some(peer)) = (self.local_endpoint(), self.remote_endpoint()) else {
+            return;
+        };
+        *watchdog = Some(Watchdog::start(handle, local.port, peer, config, self.rx_wakers.clone(), self.tx_wakers.clone()));
+    }
+    /// the error of a connection smoltcp no longer has: ETIMEDOUT if the watchdog
+    /// gave up on the peer, otherwise the peer reset it
+    fn reset_error(&self) -> SysError {
+        match self.watchdog.lock().as_ref() {
+            Some(dog) if dog.timed_out() => {
+                log::warn!("[TcpSocket] connection timed out, {} probes unanswered", dog.unanswered());
+                SysError::ETIMEOUT
+            }
+            _ => SysError::ECONNRESET,
         }
     }
 }
@@ -187,37 +269,41 @@ impl TcpSocket {
         yield_now().await;
         // now change the state to connecting , wait for poll connect event
         self.update_state(SocketState::Closed, SocketState::Connecting, ||{
-            let handle = self.handle().unwrap_or_else(||SOCKET_SET.add_socket(SocketSetWrapper::new_tcp_socket()));
+            let handle = self.handle().unwrap_or_else(||SOCKET_SET.add_socket(SocketSetWrapper::new_tcp_socket(self.buf_lens())));
             let robust_endpoint = self.robost_port_endpoint()?;
             let (local_endpoint, remote_endpoint) = SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket|{
                 socket.connect(ETH0.get().unwrap().iface.lock().context(),addr,robust_endpoint)
-                .or_else(|e| match e {
-                    ConnectError::InvalidState => {
-                        log::warn!("[TcpSocket::connect] failed: InvalidState");
-                        Err(SysError::EBADF)
-                    }
-                    ConnectError::Unaddressable => {
-                        log::warn!("[TcpSocket::connect] failed: Unaddressable");
-                        Err(SysError::EADDRNOTAVAIL)
-                    }
-                })?;
+                    .inspect_err(|e| log::warn!("[TcpSocket::connect] failed: {:?}", e))?;
                 Ok((socket.local_endpoint(), socket.remote_endpoint()))
             })?;
             self.set_local_endpoint(local_endpoint.unwrap());
             self.set_remote_endpoint(remote_endpoint.unwrap());
             self.set_handle(handle);
+            // the SYN goes out on the next poll
+            poller::kick();
+            // an unreachable peer answers the SYN with an ICMP error instead of a RST
+            route::watch(Transport::Tcp, local_endpoint.unwrap().port, &self.rx_wakers);
             // log::info!("[TCP CONNCECT], local_endpoint_port: {}, remote_endpoint_port:{}", self.local_endpoint().port,self.remote_endpoint().port);
             Ok(())
-        }).unwrap_or_else(|_|{
-            log::warn!("[TcpSocket::connect] failed to connect for alreay connected socket");
-            Err(SysError::EEXIST)
+        }).unwrap_or_else(|state|{
+            let state = SocketState::from(state);
+            log::warn!("[TcpSocket::connect] failed to connect in state {:?}", state);
+            match state {
+                SocketState::Connecting => Err(SysError::EALREADY),
+                SocketState::Listening => Err(SysError::EINVAL),
+                _ => Err(SysError::EISCONN),
+            }
         })?;
         
         // up to now the state is connecting, wait for poll connect event
         if self.nonblock() {
             Err(SysError::EINPROGRESS)
         }else {
-            self.block_on_future(|| async {
+            let local_port = self.local_endpoint().unwrap().port;
+            let ret = self.block_on_future(|| async {
+                if let Some(e) = self.take_connect_error(local_port) {
+                    return Err(e);
+                }
                 let connection_info = self.poll_connect().await;
                 if !connection_info {
                     log::warn!("[TcpSocket::connect] try agian");
@@ -228,9 +314,28 @@ impl TcpSocket {
                     log::warn!("[TcpSocket::connect] connection refused");
                     Err(SysError::ECONNREFUSED)
                 }
-            }).await
+            }).await;
+            route::unwatch(Transport::Tcp, local_port);
+            if ret.is_ok() {
+                self.apply_keepalive();
+            }
+            ret
         }
     }
+
+    /// the ICMP error reported for the SYN sent from `local_port`,
+    /// the connection attempt is reset when there is one
+    fn take_connect_error(&self, local_port: u16) -> Option<SysError> {
+        let error = route::take_error(Transport::Tcp, local_port)?;
+        log::warn!("[TcpSocket::connect] peer unreachable: {:?}", error);
+        if let Some(handle) = self.handle() {
+            SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| socket.abort());
+        }
+        self.set_local_endpoint(ZERO_IPV4_ENDPOINT);
+        self.set_remote_endpoint(ZERO_IPV4_ENDPOINT);
+        self.set_state(SocketState::Closed as u8);
+        Some(error)
+    }
     
     pub fn bind(&self, mut new_endpoint: IpEndpoint) -> SockResult<()>  {
         // log::info!("[TcpSocket::bind] start to bind");
@@ -241,9 +346,7 @@ impl TcpSocket {
                 new_endpoint.port = port;
                 // info!("[TcpSocket::bind] local port is 0, use port {}",port);
             }
-            let old = unsafe {
-                self.local_endpoint.get().read().unwrap()
-            };
+            let old = self.local_endpoint().unwrap();
             if old != ZERO_IPV4_ENDPOINT {
                 // already bind
                 return Err(SysError::EINVAL); 
@@ -266,12 +369,15 @@ impl TcpSocket {
         })
     }
     
-    pub fn listen(&self) -> SockResult<()> {
-        let waker = current_task().unwrap().waker_ref().as_ref().unwrap();
+    pub fn listen(&self, backlog: usize) -> SockResult<()> {
         self.update_state(SocketState::Closed, SocketState::Listening, ||{
             let inner_endpoint = self.robost_port_endpoint()?;
+            if !self.reuse_addr() && !LISTEN_TABLE.can_listen(inner_endpoint.port) {
+                return Err(SysError::EADDRINUSE);
+            }
             self.set_local_endpoint_with_port(inner_endpoint.port);
-            LISTEN_TABLE.listen(inner_endpoint, waker)?;
+            // the connections accepted get the buffer sizes of the listener
+            LISTEN_TABLE.listen(inner_endpoint, self.buf_lens(), backlog)?;
             // info!("[TcpSocket::listen] listening on endpoint which addr is {}, port is {}", inner_endpoint.addr.unwrap(),inner_endpoint.port);
             Ok(())
         }).unwrap_or_else(|_| {
@@ -282,6 +388,13 @@ impl TcpSocket {
     pub fn set_nonblocking(&self) {
         self.set_nonblock(true);
     }
+
+    /// the bytes received and not read yet, none before the connection
+    pub fn recv_queue(&self) -> usize {
+        self.handle().map_or(0, |handle| {
+            SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| socket.recv_queue())
+        })
+    }
     
     pub fn peer_addr(&self) -> SockResult<IpEndpoint> {
         match self.state() {
@@ -303,7 +416,11 @@ impl TcpSocket {
         }
     }
     
-    pub async fn send(&self, data: &[u8], _remote_addr: Option<IpEndpoint>) -> SockResult<usize> {
+    /// send `data` on the connection, `remote_addr` may only name the peer it is connected to
+    pub async fn send(&self, data: &[u8], remote_addr: Option<IpEndpoint>) -> SockResult<usize> {
+        if remote_addr.is_some_and(|addr| self.state() == SocketState::Connected && Some(addr) != self.remote_endpoint()) {
+            return Err(SysError::EISCONN);
+        }
         let shutdown = self.get_shutdown();
         if shutdown & SEND_SHUTDOWN != 0 {
             log::warn!("[TcpSocket::send] shutdown&SEND_SHUTDOWN != 0, return 0");
@@ -318,30 +435,28 @@ impl TcpSocket {
             let waker = get_waker().await;
             let ret = self.block_on(|| {
                 SOCKET_SET.with_socket_mut::<tcp::Socket,_,_>( handle, |socket| {
-                    if !socket.is_active() || !socket.may_send() {
+                    if !socket.is_active() {
+                        return Err(self.reset_error());
+                    }else if !socket.may_send() {
                         return Err(SysError::ECONNRESET);
                     }else if socket.can_send() {
-                        let len = socket.send_slice(data).map_err(|_| {
-                            log::warn!("send error beacuse of EBADF");
-                            SysError::EBADF
-                        })?;
+                        let len = socket.send_slice(data)
+                            .inspect_err(|e| log::warn!("[TcpSocket::send] send_slice failed: {:?}", e))?;
                         Ok(len)
                     }else {
-                         // tx buffer is full
+                        // tx buffer is full, the waker runs once acked data frees some of it
                         log::info!("[TcpSocket::send] handle{handle} send buffer is full, register waker and suspend");
-                        socket.register_send_waker(&waker);
+                        socket.register_send_waker(&self.tx_wakers.register(&waker));
                         Err(SysError::EAGAIN)
                     }
                 })
             }).await;
-            if let Ok(bytes) = ret {
-                if bytes > TCP_TX_BUF_LEN / 2 {
-                    ksleep(Duration::from_millis(2)).await;
-                } else {
-                    yield_now().await;
+            if ret.as_ref().is_ok_and(|&len| len > 0) {
+                if let Some(dog) = self.watchdog.lock().as_ref() {
+                    dog.note_sent();
                 }
+                poller::kick();
             }
-            SOCKET_SET.poll_interfaces();
             ret
         }
     }
@@ -366,22 +481,25 @@ impl TcpSocket {
             self.block_on(|| {
                 SOCKET_SET.with_socket_mut::<tcp::Socket,_,_>(handle, |socket|{
                     if !socket.is_active() {
-                        // not open 
+                        // reset by the peer
                         log::warn!("[TcpSocket::recv] socket recv() failed because handle is not active");
-                        return Err(SysError::ECONNREFUSED);
+                        return Err(self.reset_error());
                     }else if !socket.may_recv() {
                         return Ok((0,peer_addr));
                     }else if socket.recv_queue() > 0 {
                         //data available
-                        let len = socket.recv_slice(data).map_err(|_|{
-                            log::warn!("socket recv failed becasue of bad state");
-                            SysError::EBADF
-                        })?;
+                        let window_short = socket.recv_queue() * 2 > socket.recv_capacity();
+                        let len = socket.recv_slice(data)
+                            .inspect_err(|e| log::warn!("[TcpSocket::recv] recv_slice failed: {:?}", e))?;
+                        if window_short {
+                            // the peer may be waiting for the window the read opened
+                            poller::kick();
+                        }
                         return Ok((len, peer_addr))
                     }else {
                         // no more data
                         // log::info!("[TcpSocket::recv] handle{handle} has no data to recv, register waker and suspend");
-                        socket.register_recv_waker(&waker);
+                        socket.register_recv_waker(&self.rx_wakers.register(&waker));
                         Err(SysError::EAGAIN)
                     }
                 })
@@ -407,8 +525,8 @@ impl TcpSocket {
                 socket.close();
                 // info!("tcp socket shutdown, after state is {}" , socket.state());
             });
-            let time_instance = SOCKET_SET.poll_interfaces();
-            SOCKET_SET.check_poll(time_instance);
+            // the FIN goes out on the next poll
+            poller::kick();
             Ok(())
         }).unwrap_or(Ok(()))?;
         // for listener socket
@@ -416,8 +534,6 @@ impl TcpSocket {
             let local_port = self.local_endpoint().unwrap().port;
             self.set_local_endpoint(ZERO_IPV4_ENDPOINT);
             LISTEN_TABLE.unlisten(local_port);
-            let time_instance = SOCKET_SET.poll_interfaces();
-            SOCKET_SET.check_poll(time_instance);
             Ok(())
         }).unwrap_or(Ok(()))?;
         Ok(()) 
@@ -426,6 +542,8 @@ impl TcpSocket {
         match self.state() {
             SocketState::Connecting => {
                 let writable = self.poll_connect().await;
+                // a nonblocking connect is done here
+                self.apply_keepalive();
                 PollState {
                     readable: false,
                     writable: writable,
@@ -443,7 +561,7 @@ impl TcpSocket {
             SocketState::Busy => PollState { readable: false, writable: false, hangup: false },
             SocketState::Connected => self.poll_stream().await,
             SocketState::Listening => {
-                let readable = self.poll_listener();
+                let readable = self.poll_listener().await;
                 PollState {
                     readable,
                     writable: false,
@@ -489,25 +607,15 @@ impl TcpSocket {
                 f().await
             }else {
                 loop {
-                    let time_instance = SOCKET_SET.poll_interfaces();
                     let ret = f().await;
-                    SOCKET_SET.check_poll(time_instance);
                     match ret {
                         Ok(res) => {
                             return Ok(res);
                         }
                         Err(SysError::EAGAIN) => {
                             log::warn!("[block_on_future] ret state:EAGAIN!");
-                            suspend_now().await;
-                            let task = current_task().unwrap();
-                            let has_signal_flag = task.with_sig_manager(|sig_manager| {
-                                let block_sig = sig_manager.blocked_sigs;
-                                sig_manager.check_pending_flag(!block_sig)
-                            });
-                            if has_signal_flag {
-                                log::warn!("[block_on] has signal flag, return EINTR");
-                                return Err(SysError::EINTR);
-                            }
+                            let task = current_task().unwrap().clone();
+                            interruptible(&task, suspend_now()).await?;
                         }
                         Err(e) => {
                             return Err(e);
@@ -524,24 +632,14 @@ impl TcpSocket {
             f()
         }else {
             loop {
-                let time_instance = SOCKET_SET.poll_interfaces();
                 let ret = f();
-                SOCKET_SET.check_poll(time_instance);
                 match ret {
                     Ok(res) => {
                         return Ok(res);
                     }
                     Err(SysError::EAGAIN) => {
-                        suspend_now().await;
-                        let task = current_task().unwrap();
-                        let has_signal_flag = task.with_sig_manager(|sig_manager| {
-                            let block_sig = sig_manager.blocked_sigs;
-                            sig_manager.check_pending_flag(!block_sig)
-                        });
-                        if has_signal_flag {
-                            log::warn!("[block_on] has signal flag, return EINTR");
-                            return Err(SysError::EINTR);
-                        }
+                        let task = current_task().unwrap().clone();
+                        interruptible(&task, suspend_now()).await?;
                     }
                     Err(e) => {
                         return Err(e);
@@ -552,14 +650,14 @@ impl TcpSocket {
     }
     /// poll the tcp connect event and return true if the socket is connected
     async fn poll_connect(&self) -> bool {
-        let handle = unsafe{self.handle.get().read()}.unwrap();
+        let handle = self.handle().unwrap();
         let waker = get_waker().await;
         SOCKET_SET.with_socket_mut::<tcp::Socket,_,_>(handle, |socket|{
             match socket.state() {
                 State::SynSent => {
                     // this means the request is sent, but not yet received by the remote endpoint
                     info!("[TcpSocket::poll_concect]:the request is sent, but not yet received by the remote endpoint ");
-                    socket.register_recv_waker(&waker);
+                    socket.register_recv_waker(&self.rx_wakers.register(&waker));
                     false
                 }
                 State::Established => {
@@ -570,8 +668,8 @@ impl TcpSocket {
                 }
                 _ => {
                     log::warn!("wrong state, back to zero state");
-                    self.local_endpoint.exclusive_access().replace(ZERO_IPV4_ENDPOINT);
-                    self.remote_endpoint.exclusive_access().replace(ZERO_IPV4_ENDPOINT);
+                    self.set_local_endpoint(ZERO_IPV4_ENDPOINT);
+                    self.set_remote_endpoint(ZERO_IPV4_ENDPOINT);
                     self.set_state(SocketState::Closed as u8);
                     true
                 }
@@ -579,37 +677,37 @@ impl TcpSocket {
         })
     }
     async fn poll_stream(&self) -> PollState {
-        let handle = unsafe {
-            self.handle.get().read().unwrap()
-        };
+        let handle = self.handle().unwrap();
         let waker = get_waker().await;
         SOCKET_SET.with_socket_mut::<tcp::Socket,_,_>(handle, |socket|{
-            let readable = !socket.may_recv()  || socket.can_recv();
-            let writable = !socket.may_send() || socket.can_send();
+            let mut readable = !socket.may_recv()  || socket.can_recv();
+            let mut writable = !socket.may_send() || socket.can_send();
             if !readable {
-                socket.register_recv_waker(&waker);
+                socket.register_recv_waker(&self.rx_wakers.register(&waker));
+                // check again, the state may change right before registering
+                readable = !socket.may_recv() || socket.can_recv();
             }  
             if !writable {
-                socket.register_send_waker(&waker);
+                socket.register_send_waker(&self.tx_wakers.register(&waker));
+                writable = !socket.may_send() || socket.can_send();
             }
             PollState {
                 readable,
                 writable,
-                hangup: false,
+                // reset by the peer or given up on by the watchdog
+                hangup: !socket.is_active(),
             }
         })
     }
 
-    fn poll_listener(&self) -> bool {
+    async fn poll_listener(&self) -> bool {
         let local_addr = self.local_addr().unwrap();
-        let readable = LISTEN_TABLE.can_accept(local_addr.port);
-        readable
+        let waker = get_waker().await;
+        LISTEN_TABLE.can_accept_or_register(local_addr.port, &waker)
     }
 
     fn poll_closed(&self) -> bool {
-        let handle = unsafe {
-            self.handle.get().read()
-        };
+        let handle = self.handle();
         if let Some(handle) = handle {
             SOCKET_SET.with_socket_mut::<tcp::Socket,_,_>(handle, |socket| {
                 log::warn!(
@@ -630,20 +728,49 @@ impl TcpSocket {
         }
         let local_port = self.local_endpoint().unwrap().port;
         // log::info!("[accept]: local_port is {}", local_port);
-        self.block_on(|| {
-            let (handle, (local_endpoint, remote_endpoint)) = LISTEN_TABLE.accept(local_port)?;
+        let waker = get_waker().await;
+        let socket = self.block_on(|| {
+            let (handle, (local_endpoint, remote_endpoint)) = LISTEN_TABLE.accept(local_port, &waker)?;
             // info!("TCP socket accepted a new connection {}", remote_endpoint);
-            Ok(TcpSocket::new_v4_connected(handle, local_endpoint, remote_endpoint))
-        }).await
+            Ok(TcpSocket::new_v4_connected(handle, local_endpoint, remote_endpoint, self.buf_lens()))
+        }).await?;
+        // the connection inherits the keepalive options of the listener, as in linux
+        let keepalive = self.keepalive();
+        socket.set_keepalive(|k| *k = keepalive);
+        Ok(socket)
     }
 }
 
 impl Drop for TcpSocket {
     fn drop (&mut self) {
         log::info!("[TcpSocket::drop]");
-        self.shutdown(SHUTRDWR).ok();
-        if let Some(handle) = unsafe{self.handle.get().read()} {
+        // the watchdog lets go of the handle before the socket may leave the set
+        let dog = self.watchdog.lock().take();
+        if let Some(dog) = dog {
+            dog.stop();
+        }
+        // a nonblocking connect may still be waiting for ICMP errors
+        if let Some(endpoint) = self.local_endpoint() {
+            route::unwatch(Transport::Tcp, endpoint.port);
+        }
+        let Some(handle) = self.handle() else {
+            self.shutdown(SHUTRDWR).ok();
+            return;
+        };
+        if self.linger() == Some(0) {
+            // SO_LINGER with a zero timeout: drop the pending data and reset the peer
+            SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| socket.abort());
+            // the RST has to go out before the socket leaves the set
+            SOCKET_SET.poll_interfaces();
             SOCKET_SET.remove(handle);
+            return;
         }
+        self.shutdown(SHUTRDWR).ok();
+        // a connect still in progress is not closed by shutdown
+        SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| socket.close());
+        // keep the socket until the FIN handshake is done, the linger table removes it
+        let local_port = self.local_endpoint().map_or(0, |endpoint| endpoint.port);
+        LINGER_TABLE.add(handle, local_port);
+        poller::kick();
     }
 }
\ No newline at end of file
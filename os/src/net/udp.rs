@@ -1,15 +1,15 @@
 use core::{sync::atomic::AtomicBool, time};
 
-use alloc::vec::Vec;
+use alloc::{sync::Arc, vec::Vec};
 use fatfs::{info, warn};
 use lwext4_rust::bindings::EEXIST;
 use rand::{rngs::SmallRng, Rng, SeedableRng};
-use smoltcp::{iface::SocketHandle, socket::{dns::GetQueryResultError, udp::{BindError, SendError}}, wire::{IpEndpoint, IpListenEndpoint}};
+use smoltcp::{iface::SocketHandle, socket::{dns::GetQueryResultError, udp::SendError}, wire::{IpEndpoint, IpListenEndpoint}};
 use spin::{RwLock, Spin};
 
-use crate::{net::{LISTEN_TABLE, PORT_END, PORT_START, SOCK_RAND_SEED}, sync::mutex::SpinNoIrqLock, syscall::{SysError, SysResult}, task::current_task, utils::{get_waker, suspend_now, yield_now}};
+use crate::{net::{LISTEN_TABLE, PORT_END, PORT_START, SOCK_RAND_SEED}, sync::mutex::SpinNoIrqLock, syscall::{SysError, SysResult}, task::{current_task, signal::interruptible}, utils::{get_waker, suspend_now, yield_now}};
 
-use super::{addr::{is_unspecified, to_endpoint, SockAddr, UNSPECIFIED_LISTEN_ENDPOINT}, socket::{PollState, SockResult}, SocketSetWrapper, PORT_MANAGER, SOCKET_SET};
+use super::{addr::{is_unspecified, to_endpoint, SockAddr, UNSPECIFIED_LISTEN_ENDPOINT}, route::{self, Transport}, poller, socket::{PollState, SockResult}, waker_list::WakerList, BufLens, SocketSetWrapper, PORT_MANAGER, SOCKET_SET};
 
 pub struct UdpSocket {
     /// socket handle
@@ -20,18 +20,27 @@ pub struct UdpSocket {
     peer_endpoint: RwLock<Option<IpEndpoint>>,
     /// nonblock flag
     nonblock_flag: AtomicBool,
+    /// SO_RCVBUF and SO_SNDBUF
+    buf_lens: SpinNoIrqLock<BufLens>,
+    /// tasks waiting for the socket to become readable
+    rx_wakers: Arc<WakerList>,
+    /// tasks waiting for the socket to become writable
+    tx_wakers: Arc<WakerList>,
 }
 
 impl UdpSocket {
     /// create a new UdpSocket
     pub fn new() -> Self {
-        let socket = SocketSetWrapper::new_udp_socket();
+        let socket = SocketSetWrapper::new_udp_socket(BufLens::UDP);
         let handle = SOCKET_SET.add_socket(socket);
         Self {
             handle,
             local_endpoint: RwLock::new(None),
             peer_endpoint: RwLock::new(None),
             nonblock_flag: AtomicBool::new(false),
+            buf_lens: SpinNoIrqLock::new(BufLens::UDP),
+            rx_wakers: WakerList::new(),
+            tx_wakers: WakerList::new(),
         }
     }
     /// check if the nonblock flag is nonblock
@@ -52,13 +61,7 @@ impl UdpSocket {
             return Err(SysError::EINVAL);
         }
         SOCKET_SET.with_socket_mut::<smoltcp::socket::udp::Socket,_,_>(self.handle, |socket|{
-            socket.bind(local_endpoint).map_err(|e| {
-                log::warn!("socket bind error: {}", e);
-                match e {
-                    BindError::InvalidState => SysError::EEXIST,
-                    BindError::Unaddressable => SysError::EINVAL,
-                }
-            })
+            socket.bind(local_endpoint).inspect_err(|e| log::warn!("socket bind error: {}", e))
         })?;
         *local_addr = Some(local_endpoint);
         log::info!(
@@ -83,7 +86,34 @@ impl UdpSocket {
     } 
     /// set nonblock flag ture
     pub fn set_nonblocking(&self) {
-        self.nonblock_flag.store(true, core::sync::atomic::Ordering::Release);
+        self.set_nonblock(true);
+    }
+    /// set or clear the nonblock flag
+    pub fn set_nonblock(&self, nonblock: bool) {
+        self.nonblock_flag.store(nonblock, core::sync::atomic::Ordering::Release);
+    }
+    /// get the buffer sizes
+    pub fn buf_lens(&self) -> BufLens {
+        *self.buf_lens.lock()
+    }
+    /// change the buffer sizes. The smoltcp socket is made anew with them while nothing
+    /// is bound, a bound socket keeps the buffers it has
+    pub fn set_buf_lens(&self, f: impl FnOnce(&mut BufLens)) {
+        let local_endpoint = self.local_endpoint.read();
+        let mut buf_lens = self.buf_lens.lock();
+        f(&mut buf_lens);
+        if local_endpoint.is_none() {
+            let lens = *buf_lens;
+            SOCKET_SET.with_socket_mut::<smoltcp::socket::udp::Socket,_,_>(self.handle, |socket| {
+                *socket = SocketSetWrapper::new_udp_socket(lens);
+            });
+        }
+    }
+    /// the size of the next datagram, 0 when none is queued
+    pub fn recv_queue(&self) -> usize {
+        SOCKET_SET.with_socket_mut::<smoltcp::socket::udp::Socket,_,_>(self.handle, |socket| {
+            socket.peek().map_or(0, |(payload, _)| payload.len())
+        })
     }
     /// connect remote endpoint
     pub fn connect(&self, addr: IpEndpoint) -> SockResult<()> {
@@ -92,8 +122,22 @@ impl UdpSocket {
         }
         let mut peer_addr = self.peer_endpoint.write();
         *peer_addr = Some(addr);
+        // like linux only a connected socket hears about ICMP errors
+        if let Some(local) = *self.local_endpoint.read() {
+            route::watch(Transport::Udp, local.port, &self.rx_wakers);
+        }
         Ok(())
     }
+    /// the pending ICMP error of a connected socket
+    fn take_error(&self) -> SockResult<()> {
+        if self.peer_endpoint.read().is_none() {
+            return Ok(());
+        }
+        match *self.local_endpoint.read() {
+            Some(local) => route::take_error(Transport::Udp, local.port).map_or(Ok(()), Err),
+            None => Ok(()),
+        }
+    }
     /// get the peer endpoint
     pub fn peer_addr(&self) -> SockResult<IpEndpoint> {
         match self.peer_endpoint.try_read() {
@@ -118,25 +162,22 @@ impl UdpSocket {
         }
         let waker =get_waker().await;
         let bytes = self.block_on(|| {
+            self.take_error()?;
             SOCKET_SET.with_socket_mut::<smoltcp::socket::udp::Socket,_,_>(self.handle, |socket|{
                 if socket.can_send() {
-                    socket.send_slice(data, remote_endpoint)
-                    .map_err(|e|match e {
-                        SendError::BufferFull => {
-                            socket.register_send_waker(&waker);
-                            SysError::EAGAIN
-                        }
-                        SendError::Unaddressable => {
-                            SysError::ECONNREFUSED
+                    socket.send_slice(data, remote_endpoint).inspect_err(|e| {
+                        if let SendError::BufferFull = e {
+                            socket.register_send_waker(&self.tx_wakers.register(&waker));
                         }
                     })?;
                     Ok(data.len())
                 }else {
-                    socket.register_send_waker(&waker);
+                    socket.register_send_waker(&self.tx_wakers.register(&waker));
                     Err(SysError::EAGAIN)
                 }
             })
         }).await?;
+        poller::kick();
         yield_now().await;
         return Ok(bytes);
     }
@@ -157,31 +198,25 @@ impl UdpSocket {
         let bytes = self.block_on(|| {
             SOCKET_SET.with_socket_mut::<smoltcp::socket::udp::Socket,_,_>(self.handle, |socket| {
                 if socket.can_send() {
-                    socket
-                    .send_slice(data, remote_endpoint)
-                    .map_err(|e|match e {
-                         SendError::BufferFull => {
-                            log::warn!("socket send() failed, {e:?}");
-                             socket.register_send_waker(&waker);
-                             SysError::EAGAIN
-                         }
-                         SendError::Unaddressable => {
-                             log::warn!("socket send() failed, {e:?}");
-                             SysError::ECONNREFUSED
-                         }
-                     })?;
+                    socket.send_slice(data, remote_endpoint).inspect_err(|e| {
+                        log::warn!("socket send() failed, {e:?}");
+                        if let SendError::BufferFull = e {
+                            socket.register_send_waker(&self.tx_wakers.register(&waker));
+                        }
+                    })?;
                     Ok(data.len())
                 }else {
                     log::info!(
                         "[UdpSocket::send_to] handle{} can't send now, tx buffer is full",
                         self.handle
                     );
-                    socket.register_send_waker(&waker);
+                    socket.register_send_waker(&self.tx_wakers.register(&waker));
                     Err(SysError::EAGAIN)
                 }
             })
         }).await?;
         // log::info!("[UdpSocket::send_impl] send {bytes}bytes to {remote_endpoint:?}");
+        poller::kick();
         yield_now().await;
         return Ok(bytes);
     }
@@ -193,6 +228,7 @@ impl UdpSocket {
         }
         let waker = get_waker().await;
         let ret = self.block_on(||{
+            self.take_error()?;
             SOCKET_SET.with_socket_mut::<smoltcp::socket::udp::Socket,_,_>(self.handle, |socket|{
                 if socket.can_recv() {
                     match socket.recv_slice(data) {
@@ -209,7 +245,7 @@ impl UdpSocket {
                     return Err(SysError::ENOTCONN);
                 }else {
                     log::info!("[recv_impl] {} no more data, register waker and suspend now", self.handle);
-                    socket.register_recv_waker(&waker);
+                    socket.register_recv_waker(&self.rx_wakers.register(&waker));
                     return Err(SysError::EAGAIN);
                 } 
             })
@@ -221,8 +257,6 @@ impl UdpSocket {
         SOCKET_SET.with_socket_mut::<smoltcp::socket::udp::Socket,_,_>(self.handle, |socket| {
             socket.close();
         });
-        let timestamp = SOCKET_SET.poll_interfaces();
-        SOCKET_SET.check_poll(timestamp);
         Ok(())
     }
     pub async fn poll(&self) -> PollState {
@@ -234,16 +268,22 @@ impl UdpSocket {
             };
         }
         let waker = get_waker().await;
+        // a pending ICMP error is reported by the next recv
+        let errored = self.local_endpoint.read()
+            .is_some_and(|local| route::has_error(Transport::Udp, local.port));
         SOCKET_SET.with_socket_mut::<smoltcp::socket::udp::Socket, _, _>(self.handle, |socket|{
-            let readable = socket.can_recv();
-            let writable = socket.can_send();
+            let mut readable = errored || socket.can_recv();
+            let mut writable = socket.can_send();
             if !readable {
                 log::info!("[UdpSocket::poll] handle{} can't recv now, rx buffer is empty", self.handle);
-                socket.register_recv_waker(&waker);
+                socket.register_recv_waker(&self.rx_wakers.register(&waker));
+                // check again, the state may change right before registering
+                readable = socket.can_recv();
             }
             if !writable {
                 log::info!("[UdpSocket::poll] handle{} can't send now, tx buffer is full", self.handle);
-                socket.register_send_waker(&waker);
+                socket.register_send_waker(&self.tx_wakers.register(&waker));
+                writable = socket.can_send();
             }
             PollState {
                 readable,
@@ -279,23 +319,13 @@ impl UdpSocket {
             f()
         }else {
             loop {
-                let timestamp = SOCKET_SET.poll_interfaces();
                 let ret = f();
-                SOCKET_SET.check_poll(timestamp);
                 match ret {
                     Ok(r) => return Ok(r),
                     Err(SysError::EAGAIN) => {
                         log::info!("[UdpSocket::block_on] handle, EAGAIN, suspend now");
-                        suspend_now().await;
-                        let task = current_task().unwrap();
-                        let has_signal_flag = task.with_sig_manager(|sig_manager| {
-                            let block_sig = sig_manager.blocked_sigs;
-                            sig_manager.check_pending_flag(!block_sig)
-                        });
-                        if has_signal_flag {
-                            log::warn!("[block_on] has signal flag, return EINTR");
-                            return Err(SysError::EINTR);
-                        }
+                        let task = current_task().unwrap().clone();
+                        interruptible(&task, suspend_now()).await?;
                     }
                     Err(e) => return Err(e),
                 }
@@ -311,6 +341,7 @@ impl Drop for UdpSocket {
         SOCKET_SET.remove(self.handle);
         if let Ok(addr) = self.local_addr() {
             PORT_MANAGER.remove(addr.port);
+            route::unwatch(Transport::Udp, addr.port);
         }
     }
 }
\ No newline at end of file
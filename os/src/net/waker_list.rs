@@ -0,0 +1,82 @@
+use alloc::{sync::Arc, task::Wake, vec::Vec};
+use core::task::Waker;
+
+use crate::sync::mutex::SpinNoIrqLock;
+
+/// A list of wakers waiting on one readiness event of a socket.
+///
+/// smoltcp only keeps one waker per direction, so registering a second
+/// task silently drops the first one. Instead every waiter is pushed here
+/// and smoltcp is given a single fan-out waker built from the list, which
+/// wakes all of them when the event fires.
+pub struct WakerList {
+    wakers: SpinNoIrqLock<Vec<Waker>>,
+}
+
+impl WakerList {
+    /// create an empty waker list
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            wakers: SpinNoIrqLock::new(Vec::new()),
+        })
+    }
+
+    /// add `waker` to the list (once) and return the fan-out waker
+    /// that should be registered on the smoltcp socket
+    pub fn register(self: &Arc<Self>, waker: &Waker) -> Waker {
+        let mut wakers = self.wakers.lock();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+        drop(wakers);
+        Waker::from(self.clone())
+    }
+
+    /// wake every registered waker and clear the list,
+    /// waiters have to register again if they are still not ready
+    pub fn wake_all(&self) {
+        let wakers = core::mem::take(&mut *self.wakers.lock());
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+/// the polls running and the lists smoltcp fired meanwhile: smoltcp fires the
+/// fan-out wakers with the socket set locked, they are only noted then
+/// and woken once the last poll is done
+static DEFERRED: SpinNoIrqLock<(usize, Vec<Arc<WakerList>>)> = SpinNoIrqLock::new((0, Vec::new()));
+
+/// run `poll` with the wakes smoltcp fires held back, then wake them,
+/// so the woken tasks do not run into the socket set the poll still holds
+pub fn defer_wakes<R>(poll: impl FnOnce() -> R) -> R {
+    DEFERRED.lock().0 += 1;
+    let ret = poll();
+    let lists = {
+        let mut deferred = DEFERRED.lock();
+        deferred.0 -= 1;
+        if deferred.0 == 0 { core::mem::take(&mut deferred.1) } else { Vec::new() }
+    };
+    for list in lists {
+        list.wake_all();
+    }
+    ret
+}
+
+impl Wake for WakerList {
+    fn wake(self: Arc<Self>) {
+        let mut deferred = DEFERRED.lock();
+        if deferred.0 > 0 {
+            if !deferred.1.iter().any(|list| Arc::ptr_eq(list, &self)) {
+                deferred.1.push(self);
+            }
+            return;
+        }
+        drop(deferred);
+        self.wake_all();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.clone().wake();
+    }
+}
@@ -0,0 +1,175 @@
+//! inter-processor interrupts
+//!
+//! Every ipi makes the target hart execute a full memory barrier before acknowledging it.
+//! A sender bumps the request counter of the target, raises the interrupt and waits
+//! until the acknowledged counter catches up, so the barrier on the target is
+//! ordered after everything the sender did before calling [`send_ipi_and_wait`].
+//! [`shootdown_tlb`] also has the targets flush their tlb before acknowledging.
+//! A hart spinning on a lock has its interrupts off, it acknowledges the ipis
+//! in the spin loop through [`poll_ipi`], as the sender may hold that lock.
+//! On shutdown [`park_other_harts`] uses an ipi to stop the other harts for good,
+//! on a panic [`park_other_harts_for_panic`] does so without waiting long.
+//! A hart parks only where it holds no lock: on a trap from user mode or between
+//! two tasks in the executor, an ipi taken in the kernel is only acknowledged.
+
+use core::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
+
+use alloc::sync::Arc;
+use hal::{board::MAX_PROCESSORS, instruction::{Instruction, InstructionHal}};
+
+use crate::{lang_items::{panic_in_progress, print_current_task}, mm::stats::{count_vm_event, VmEvent}, task::task::TaskControlBlock};
+
+use super::processor::{current_processor, online_harts, set_hart_offline};
+
+/// ipis requested of each hart
+static IPI_REQUESTED: [AtomicUsize; MAX_PROCESSORS] = [const { AtomicUsize::new(0) }; MAX_PROCESSORS];
+/// ipis handled by each hart
+static IPI_HANDLED: [AtomicUsize; MAX_PROCESSORS] = [const { AtomicUsize::new(0) }; MAX_PROCESSORS];
+/// the harts asked to flush their tlb before acknowledging the next ipi
+static TLB_FLUSH_REQUESTED: [AtomicBool; MAX_PROCESSORS] = [const { AtomicBool::new(false) }; MAX_PROCESSORS];
+/// the address space each hart is running, 0 when it runs no user task
+static RUNNING_MM: [AtomicUsize; MAX_PROCESSORS] = [const { AtomicUsize::new(0) }; MAX_PROCESSORS];
+/// the hart which asked all the others to park, MAX_PROCESSORS when none did
+static PARKER: AtomicUsize = AtomicUsize::new(MAX_PROCESSORS);
+/// mask of the parked harts
+static PARKED: AtomicUsize = AtomicUsize::new(0);
+/// spins a panicking hart waits for the others to park
+const PANIC_PARK_SPINS: usize = 1 << 24;
+
+/// the key identifying the address space of a task, shared by its threads
+pub fn mm_key(task: &Arc<TaskControlBlock>) -> usize {
+    Arc::as_ptr(task.get_vm_space()) as usize
+}
+
+/// record the address space running on this hart, 0 for none
+pub fn set_running_mm(key: usize) {
+    RUNNING_MM[current_processor().id()].store(key, Ordering::SeqCst);
+}
+
+/// mask of the other online harts currently running a user task,
+/// only those running the address space `mm` if it is given
+pub fn harts_running(mm: Option<usize>) -> usize {
+    let me = current_processor().id();
+    let mut mask = 0;
+    for hart in 0..MAX_PROCESSORS {
+        if hart == me || online_harts() & (1 << hart) == 0 {
+            continue;
+        }
+        let running = RUNNING_MM[hart].load(Ordering::SeqCst);
+        if running != 0 && mm.map_or(true, |mm| mm == running) {
+            mask |= 1 << hart;
+        }
+    }
+    mask
+}
+
+/// handle the ipis pending on this hart, taken on a trap from user mode
+pub fn handle_ipi() {
+    ack_ipi();
+    park_if_requested();
+}
+
+/// acknowledge the ipis pending on this hart, the only handling of an ipi taken
+/// in the kernel: the hart may be holding a lock there and parks later in the executor
+pub fn ack_ipi() {
+    Instruction::clear_ipi();
+    let id = current_processor().id();
+    let requested = IPI_REQUESTED[id].load(Ordering::SeqCst);
+    if TLB_FLUSH_REQUESTED[id].swap(false, Ordering::SeqCst) {
+        unsafe { Instruction::tlb_flush_all(); }
+    }
+    fence(Ordering::SeqCst);
+    IPI_HANDLED[id].fetch_max(requested, Ordering::SeqCst);
+}
+
+/// acknowledge the ipis requested of this hart if there are any, without waiting for the interrupt
+pub fn poll_ipi() {
+    let id = current_processor().id();
+    if IPI_REQUESTED[id].load(Ordering::Relaxed) > IPI_HANDLED[id].load(Ordering::Relaxed) {
+        ack_ipi();
+    }
+}
+
+/// interrupt every hart in `harts` and wait until all of them have handled it
+pub fn send_ipi_and_wait(harts: usize) {
+    fence(Ordering::SeqCst);
+    let mut tickets = [0usize; MAX_PROCESSORS];
+    for hart in (0..MAX_PROCESSORS).filter(|hart| harts & (1 << hart) != 0) {
+        tickets[hart] = IPI_REQUESTED[hart].fetch_add(1, Ordering::SeqCst) + 1;
+        Instruction::send_ipi(hart);
+    }
+    for hart in (0..MAX_PROCESSORS).filter(|hart| harts & (1 << hart) != 0) {
+        while IPI_HANDLED[hart].load(Ordering::SeqCst) < tickets[hart] {
+            // the target may be waiting for us with interrupts off
+            ack_ipi();
+            core::hint::spin_loop();
+        }
+    }
+    fence(Ordering::SeqCst);
+}
+
+/// flush the tlb of this hart and of every other hart running the address space `mm`,
+/// no hart uses an entry cached before the call once it returns
+pub fn shootdown_tlb(mm: usize) {
+    count_vm_event(VmEvent::TlbShootdown);
+    unsafe { Instruction::tlb_flush_all(); }
+    let harts = harts_running(Some(mm));
+    for hart in (0..MAX_PROCESSORS).filter(|hart| harts & (1 << hart) != 0) {
+        TLB_FLUSH_REQUESTED[hart].store(true, Ordering::SeqCst);
+    }
+    send_ipi_and_wait(harts);
+}
+
+/// stop every other online hart for good, they are all parked on return
+pub fn park_other_harts() {
+    let me = current_processor().id();
+    PARKER.store(me, Ordering::SeqCst);
+    let harts = online_harts() & !(1 << me);
+    for hart in (0..MAX_PROCESSORS).filter(|hart| harts & (1 << hart) != 0) {
+        Instruction::send_ipi(hart);
+    }
+    // a hart that took the ipi while waiting in send_ipi_and_wait parks
+    // once it is back in the executor
+    while PARKED.load(Ordering::SeqCst) & harts != harts {
+        ack_ipi();
+        core::hint::spin_loop();
+    }
+}
+
+/// stop every other online hart after a panic, each prints the task it ran as it parks,
+/// a hart spinning with its interrupts off never does and is not waited for long
+pub fn park_other_harts_for_panic() {
+    let me = Instruction::get_tp();
+    PARKER.store(me, Ordering::SeqCst);
+    let harts = online_harts() & !(1 << me);
+    for hart in (0..MAX_PROCESSORS).filter(|hart| harts & (1 << hart) != 0) {
+        Instruction::send_ipi(hart);
+    }
+    for _ in 0..PANIC_PARK_SPINS {
+        if PARKED.load(Ordering::SeqCst) & harts == harts {
+            break;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// park this hart if another one asked for it, called where no lock is held:
+/// from the executor between two tasks, or on a trap from user mode
+pub fn park_if_requested() {
+    let id = current_processor().id();
+    let parker = PARKER.load(Ordering::SeqCst);
+    if parker == MAX_PROCESSORS || parker == id {
+        return;
+    }
+    unsafe { Instruction::disable_interrupt() };
+    if panic_in_progress() {
+        print_current_task(id);
+    }
+    // leave the online mask first, so nobody waits for us any more
+    set_hart_offline(id);
+    PARKED.fetch_or(1 << id, Ordering::SeqCst);
+    // spin with interrupts off until the power goes
+    loop {
+        core::hint::spin_loop();
+    }
+}
@@ -1,11 +1,9 @@
 //!Implementation of [`Processor`] and Intersection of control flow
 use core::arch::asm;
-use core::sync::atomic::{AtomicU64, AtomicUsize};
+use core::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
 use crate::sync::mutex::SpinNoIrqLock;
-use crate::task::task::{get_cpu_mask, new_shared, turn_cpu_mask_to_id, Shared, TaskControlBlock, TaskStatus};
-use crate::sync::UPSafeCell;
+use crate::task::task::{new_shared, Shared, TaskControlBlock, TaskStatus};
 use crate::processor::context::EnvContext;
-use alloc::collections::vec_deque::VecDeque;
 use alloc::sync::Arc;
 use async_task::Runnable;
 use hal::instruction::{Instruction, InstructionHal};
@@ -21,12 +19,10 @@ pub static mut PROCESSORS: [Processor; MAX_PROCESSORS] = [const { Processor::new
 #[cfg(feature = "smp")]
 use super::schedule::TaskLoadTracker;
 #[cfg(feature = "smp")]
-pub type TaskQueue = VecDeque<Runnable>;
+pub type TaskQueue = crate::executor::RunQueue;
 ///Processor management structure
 pub struct Processor {
     id: usize,
-    ///The task currently executing on the current processor
-    current: Option<Arc<TaskControlBlock>>,
     env: EnvContext,
     #[cfg(feature = "smp")]
     /// each processor has its own task queue
@@ -37,9 +33,6 @@ pub struct Processor {
     #[cfg(feature = "smp")]
     /// sche_entity for rq
     pub sche_entity: Option<Shared<TaskLoadTracker>>,
-    #[cfg(feature = "smp")]
-    /// mark whether there is a task need to be migrate
-    pub need_migrate: AtomicUsize,
     /// the cpu timeline
     pub timeline: AtomicU64
 }
@@ -72,7 +65,6 @@ impl Processor {
     pub const fn new() -> Self {
         Self {
             id: 0,
-            current: None,
             env: EnvContext::new(),
             #[cfg(feature = "smp")]
             task_queue: None,
@@ -81,8 +73,6 @@ impl Processor {
             #[cfg(feature = "smp")]
             sche_entity: None,
             timeline: AtomicU64::new(0),
-            #[cfg(feature = "smp")]
-            need_migrate: AtomicUsize::new(0),
         }
     }
     /// Get the id of the current processor
@@ -93,17 +83,13 @@ impl Processor {
     pub fn set_id(&mut self, id: usize) {
         self.id = id;
     }
-    ///Get current task in cloning semanteme
-    pub fn current(&self) -> Option<&Arc<TaskControlBlock>> {
-        self.current.as_ref()
-    }
-    /// Set current task
-    pub fn set_current(&mut self, task:Arc<TaskControlBlock>) {
-        self.current = Some(task);
+    ///Get the task running on this processor
+    pub fn current(&self) -> Option<&'static Arc<TaskControlBlock>> {
+        hart_current(self.id)
     }
     /// judge whether cuurent is None
     pub fn has_current(&self) -> bool {
-        self.current.is_some()
+        !CURRENT[self.id].load(Ordering::Acquire).is_null()
     }
     /// Get the mutable reference to the environment of the current task
     pub fn env_mut(&mut self) -> &mut EnvContext {
@@ -119,7 +105,7 @@ impl Processor {
     #[cfg(feature = "smp")]
     /// set task_queue when first initiated
     pub fn set_task_queue(&mut self) {
-        self.task_queue = Some(new_shared(VecDeque::new()));
+        self.task_queue = Some(new_shared(TaskQueue::new()));
     }
     #[cfg(feature = "smp")]
     generate_unwrap_with_methods!(
@@ -137,21 +123,6 @@ impl Processor {
     pub fn initial_sche_entity(&mut self){
         self.sche_entity = Some(new_shared(TaskLoadTracker::new()));
     }
-    #[cfg(feature = "smp")]
-    /// get migrate id
-    pub fn migrate_id(&self) -> usize {
-        self.need_migrate.load(core::sync::atomic::Ordering::SeqCst)
-    }
-    #[cfg(feature = "smp")]
-    /// get need_migrate flag
-    pub fn need_migrate_check(&self) -> bool {
-        self.need_migrate.load(core::sync::atomic::Ordering::SeqCst) != self.id()
-    }
-    #[cfg(feature = "smp")]
-    /// set need_migrate flag to true
-    pub fn set_need_migrate(&mut self, need_migrate: usize) {
-        self.need_migrate.store(need_migrate, core::sync::atomic::Ordering::SeqCst);
-    } 
     /// get current cpu timeline 
     pub fn get_current_timeline(&self) -> u64 {
         self.timeline.load(core::sync::atomic::Ordering::SeqCst)
@@ -178,9 +149,27 @@ impl Processor {
         self.get_current_timeline()
     }
 }
+/// the task running on each hart, indexed by the hart id in tp.
+///
+/// A slot points at the `Arc` held by the pinned [`UserTaskFuture`] of the task, not at a
+/// copy of its own, so a reference taken from it stays good for as long as that future
+/// lives, even after the future moved on to another hart. Only the owning hart writes its
+/// slot, from the scheduler around a poll, so a read is a single load and takes no lock.
+///
+/// [`UserTaskFuture`]: crate::task::schedule::UserTaskFuture
+static CURRENT: [AtomicPtr<Arc<TaskControlBlock>>; MAX_PROCESSORS] =
+    [const { AtomicPtr::new(core::ptr::null_mut()) }; MAX_PROCESSORS];
+
+/// the task running on hart `id`
+fn hart_current(id: usize) -> Option<&'static Arc<TaskControlBlock>> {
+    // the future owning the Arc is dropped only after its poll cleared the slot
+    unsafe { CURRENT[id].load(Ordering::Acquire).as_ref() }
+}
+
 /// current running task of the current processsor
+#[inline]
 pub fn current_task() -> Option<&'static Arc<TaskControlBlock>> {
-    current_processor().current()
+    hart_current(Instruction::get_tp())
 }
 ///Get token of the address space of current task
 pub fn current_user_token(processor: &Processor) -> usize {
@@ -196,18 +185,18 @@ pub fn current_trap_cx(processor: &Processor) -> &mut TrapContext {
 }
 
 /// Switch to the given task ,change page_table temporarily
+///
+/// `task` is the Arc kept by the pinned future of the task, the slot of this hart points
+/// at it until [`switch_out_current_task`]
 pub fn switch_to_current_task(processor: &mut Processor, task: &mut Arc<TaskControlBlock>, env: &mut EnvContext) {
     unsafe{ Instruction::disable_interrupt();}
     unsafe {env.auto_sum();}
     //info!("already in switch");
-    #[cfg(feature = "smp")]
-    if task.cpu_allowed() != 15 && task.cpu_allowed() != get_cpu_mask(processor.id()) {
-        warn!("[switch_to_current_task], set need migrate");
-        processor.set_need_migrate(turn_cpu_mask_to_id(task.cpu_allowed()));
-    }
-    processor.set_current(Arc::clone(task));
-    #[cfg(feature = "smp")]
+    task.release_cells();
+    CURRENT[processor.id()].store(task as *mut _, Ordering::Release);
     task.set_processor_id(processor.id());
+    task.set_on_cpu(true);
+    super::ipi::set_running_mm(super::ipi::mm_key(task));
     //info!("[in switch to current task] processor id: {}, task id: {}", processor.id(),task.tid.0);
     task.time_recorder().record_switch_in();
     //info!("[in switch to current task] task id: {}kernel_time:{:?}",task.tid(),task.time_recorder().kernel_time());
@@ -233,9 +222,14 @@ pub fn switch_out_current_task(processor: &mut Processor, env: &mut EnvContext){
     current.time_recorder().record_switch_out();
     processor.add_current_timeline(current.time_recorder().processor_time().as_micros() as u64);
     //info!("task id: {}kernel_time:{:?}",current.tid(),current.time_recorder().kernel_time());
-    // float_pointer saved, marked restore is needed
-    current.get_trap_cx().fx_yield_task();
-    processor.current = None;
+    // save the float registers the task dirtied, another task may take them
+    current.get_trap_cx().fx_sync();
+    current.save_user_regs();
+    current.set_on_cpu(false);
+    current.release_cells();
+    super::ipi::set_running_mm(0);
+    // the Arc itself stays in the future, the executor drops it after this poll
+    CURRENT[processor.id()].store(core::ptr::null_mut(), Ordering::Release);
     unsafe { Instruction::enable_interrupt()};
     //info!("switch_out_current_task done");
 }
@@ -261,11 +255,26 @@ pub fn set_processor(id:usize) {
     processor.set_task_queue();
     #[cfg(feature = "smp")]
     processor.initial_sche_entity();
-    #[cfg(feature = "smp")]
-    processor.set_need_migrate(id);
+    ONLINE_HARTS.fetch_or(1 << id, Ordering::SeqCst);
     processor as *const _ as usize;
 }
 
+/// mask of the harts which have started running tasks, bit i stands for hart i
+static ONLINE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+/// affinity mask allowing every hart
+pub const CPU_MASK_ALL: usize = (1 << MAX_PROCESSORS) - 1;
+
+/// get the mask of the online harts
+pub fn online_harts() -> usize {
+    ONLINE_HARTS.load(Ordering::SeqCst)
+}
+
+/// take a hart out of the online mask, it runs no tasks any more
+pub fn set_hart_offline(id: usize) {
+    ONLINE_HARTS.fetch_and(!(1 << id), Ordering::SeqCst);
+}
+
 /// get current processor
 pub fn current_processor() -> &'static mut Processor {
     get_processor(Instruction::get_tp())
@@ -167,24 +167,29 @@ pub fn load_balance() -> bool {
     }
 }
 
+/// move a task from the tail of one run queue to another,
+/// a task moved to a hart outside its affinity puts itself back when polled there
 #[allow(unused)]
 fn migrate_tasks(from_core: usize, to_core: usize) {
-    let task = unsafe{PROCESSORS[from_core].unwrap_with_mut_task_queue(|queue|queue.pop_back().unwrap())};
-    unsafe{PROCESSORS[to_core].unwrap_with_mut_task_queue(|queue| queue.push_back(task))};
+    let (level, task) = unsafe{PROCESSORS[from_core].unwrap_with_mut_task_queue(|queue|queue.pop_back().unwrap())};
+    unsafe{PROCESSORS[to_core].unwrap_with_mut_task_queue(|queue| queue.push_back(level, task))};
 }
 
-pub fn select_run_queue_index() -> usize {
+/// pick a run queue among the online harts allowed by `cpu_allowed`, round robin
+pub fn select_run_queue_index(cpu_allowed: usize) -> usize {
     use core::sync::atomic::{AtomicUsize, Ordering};
 
-    use log::info;
-
-    use super::processor::current_processor;
+    use super::processor::{current_processor, online_harts};
     static TASK_QUEUE_INDEX: AtomicUsize = AtomicUsize::new(2);
-    //info!("lazy_static TASK_QUEUE_INDEX: {}", TASK_QUEUE_INDEX.load(Ordering::SeqCst));
+    let allowed = cpu_allowed & online_harts();
+    if allowed == 0 {
+        // the allowed harts have not started yet
+        return current_processor().id();
+    }
     loop {
-        let index = TASK_QUEUE_INDEX.fetch_add(1, Ordering::SeqCst) % (MAX_PROCESSORS) ;
-        return index 
+        let index = TASK_QUEUE_INDEX.fetch_add(1, Ordering::SeqCst) % (MAX_PROCESSORS);
+        if allowed & (1 << index) != 0 {
+            return index;
+        }
     }
 } 
-
-
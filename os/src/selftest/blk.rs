@@ -0,0 +1,134 @@
+//! the requests of the disk of the root file system, abandoned, across a reset
+//! and through the merging queue
+
+use core::{future::Future, task::{Context, Waker}};
+
+use alloc::vec::Vec;
+
+use crate::{drivers::block::{block_device, queue, DISK_DEV_NAME}, timer::get_current_time_ms, utils::{block_on, Async}};
+use crate::devices::DevResult;
+
+use super::{ensure, TestResult};
+
+/// the requests started, the bytes each reads
+const REQUESTS: usize = 8;
+const LEN: usize = 4096;
+/// how long the device may take to hand back the abandoned requests
+const DRAIN_MS: usize = 1000;
+/// the blocks the queue test reads, and writes back as they were
+const QUEUE_START: usize = 1024;
+const QUEUE_BLOCKS: usize = 64;
+
+/// the block read by the request `i`
+fn block_of(i: usize) -> usize {
+    i * LEN / 512
+}
+
+/// what the requests are to read, read one at a time
+fn expected() -> Result<Vec<Vec<u8>>, &'static str> {
+    let blk = block_device().ok_or("no block device")?;
+    (0..REQUESTS)
+        .map(|i| block_on(blk.read_async(block_of(i), LEN)).map_err(|_| "a read failed"))
+        .collect()
+}
+
+/// start `REQUESTS` reads, each handed to the device by a first poll
+fn start_reads() -> Vec<Async<'static, DevResult<Vec<u8>>>> {
+    let blk = block_device().unwrap();
+    let mut cx = Context::from_waker(Waker::noop());
+    (0..REQUESTS)
+        .map(|i| {
+            let mut req = blk.read_async(block_of(i), LEN);
+            // whatever the device already did, it comes back through the table
+            let _ = req.as_mut().poll(&mut cx);
+            req
+        })
+        .collect()
+}
+
+/// requests dropped before the device handed them back keep their buffers until it does,
+/// then the DMA memory of the disk is back where it was
+pub fn blk_abandoned_request() -> TestResult {
+    let blk = block_device().ok_or("no block device")?;
+    let before = blk.dma_pages();
+    let expect = expected()?;
+
+    let mut reqs = start_reads();
+    let kept = reqs.split_off(REQUESTS / 2);
+    drop(reqs);
+    for (req, expect) in kept.into_iter().zip(&expect[REQUESTS / 2..]) {
+        let data = block_on(req).map_err(|_| "a request kept failed")?;
+        ensure(data == *expect, "a request kept read other data")?;
+    }
+    // the next requests reap the abandoned ones as the device hands them back
+    let deadline = get_current_time_ms() + DRAIN_MS;
+    while blk.dma_pages() != before && get_current_time_ms() < deadline {
+        block_on(blk.read_async(0, LEN)).map_err(|_| "a read failed")?;
+    }
+    ensure(blk.dma_pages() == before, "the buffers of the abandoned requests are freed")
+}
+
+/// a reset with requests in flight, kept and abandoned, frees what the device held
+/// and leaves it working
+pub fn blk_reset() -> TestResult {
+    let blk = block_device().ok_or("no block device")?;
+    let before = blk.dma_pages();
+    let expect = expected()?;
+
+    let mut reqs = start_reads();
+    let kept = reqs.split_off(REQUESTS / 2);
+    drop(reqs);
+    blk.reset().map_err(|_| "the reset failed")?;
+    // those handed back before the reset have their data, the others failed
+    for (req, expect) in kept.into_iter().zip(&expect[REQUESTS / 2..]) {
+        if let Ok(data) = block_on(req) {
+            ensure(data == *expect, "a request kept read other data")?;
+        }
+    }
+    ensure(blk.dma_pages() == before, "the DMA memory is back after the reset")?;
+    ensure(expected()? == expect, "the reads after the reset read other data")
+}
+
+/// the read and write merges of the disk, as /proc/diskstats shows them
+fn merged() -> Result<(usize, usize), &'static str> {
+    let stats = queue::diskstats();
+    let fields: Vec<&str> = stats
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .find(|fields| fields.get(2) == Some(&DISK_DEV_NAME))
+        .ok_or("the disk is not in diskstats")?;
+    let field = |i: usize| fields.get(i).and_then(|f| f.parse().ok()).ok_or("a bad line in diskstats");
+    Ok((field(4)?, field(8)?))
+}
+
+/// sequential reads and contiguous writes are merged, and a read sees the write
+/// before it whether that is still plugged or already flushed
+pub fn blk_queue_merge() -> TestResult {
+    let blk = block_device().ok_or("no block device")?;
+    let (read_before, write_before) = merged()?;
+
+    let mut blocks = [[0u8; 512]; QUEUE_BLOCKS];
+    for (i, block) in blocks.iter_mut().enumerate() {
+        blk.read_block(QUEUE_START + i, block);
+    }
+    let (read_after, _) = merged()?;
+    ensure(read_after > read_before, "sequential reads are merged")?;
+
+    let mut written = blocks[3];
+    written.iter_mut().for_each(|byte| *byte = !*byte);
+    let mut read = [0u8; 512];
+    blk.write_block(QUEUE_START + 3, &written);
+    blk.write_block(QUEUE_START + 4, &blocks[4]);
+    blk.read_block(QUEUE_START + 3, &mut read);
+    ensure(read == written, "a read sees the plugged write")?;
+    blk.flush();
+    let (_, write_after) = merged()?;
+    ensure(write_after > write_before, "contiguous writes are merged")?;
+    blk.read_block(QUEUE_START + 3, &mut read);
+    ensure(read == written, "a read sees the flushed write")?;
+
+    blk.write_block(QUEUE_START + 3, &blocks[3]);
+    blk.flush();
+    blk.read_block(QUEUE_START + 3, &mut read);
+    ensure(read == blocks[3], "the block is written back as it was")
+}
@@ -0,0 +1,142 @@
+//! the page cache, over a file kept in a plain buffer
+
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::{config::PAGE_SIZE, fs::{page::cache::PageCache, vfs::{inode::InodeMode, Inode, InodeInner}}, sync::mutex::SpinNoIrqLock};
+
+use super::{ensure, TestResult};
+
+/// a file in memory which counts the writes reaching it, the "disk" under the cache
+struct RamInode {
+    inner: InodeInner,
+    data: SpinNoIrqLock<Vec<u8>>,
+    writes: SpinNoIrqLock<usize>,
+}
+
+unsafe impl Send for RamInode {}
+unsafe impl Sync for RamInode {}
+
+impl RamInode {
+    fn new(data: Vec<u8>) -> Arc<Self> {
+        let inner = InodeInner::new(None, InodeMode::FILE, data.len());
+        Arc::new(Self { inner, data: SpinNoIrqLock::new(data), writes: SpinNoIrqLock::new(0) })
+    }
+
+    fn len(&self) -> usize {
+        self.data.lock().len()
+    }
+
+    fn byte(&self, offset: usize) -> u8 {
+        self.data.lock()[offset]
+    }
+}
+
+impl Inode for RamInode {
+    fn inode_inner(&self) -> &InodeInner {
+        &self.inner
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, i32> {
+        let data = self.data.lock();
+        let len = data.len().saturating_sub(offset).min(buf.len());
+        buf[..len].copy_from_slice(&data[offset..offset + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, i32> {
+        let mut data = self.data.lock();
+        if data.len() < offset + buf.len() {
+            data.resize(offset + buf.len(), 0);
+        }
+        data[offset..offset + buf.len()].copy_from_slice(buf);
+        *self.writes.lock() += 1;
+        Ok(buf.len())
+    }
+}
+
+/// the contents of the test file, no two neighbouring pages alike
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i / PAGE_SIZE * 31 + i % 251) as u8).collect()
+}
+
+/// reads see the file, writes stay in the cache until a sync writes back
+/// exactly the dirty pages, an append moves the end
+pub fn page_cache_coherence() -> TestResult {
+    let size = 3 * PAGE_SIZE + 100;
+    let inode = RamInode::new(pattern(size));
+    let cache = PageCache::new();
+
+    let mut buf = alloc::vec![0u8; size + PAGE_SIZE];
+    ensure(cache.read(inode.clone(), size, 0, &mut buf) == size, "the length of a read to the end")?;
+    ensure(buf[..size] == pattern(size), "the contents read through the cache")?;
+
+    // a write across a page boundary
+    let at = PAGE_SIZE - 50;
+    ensure(cache.write(inode.clone(), size, at, &[0xee; 100]).0 == 100, "the length of a write")?;
+    let mut small = [0u8; 120];
+    cache.read(inode.clone(), size, at - 10, &mut small);
+    ensure(small[..10] == pattern(size)[at - 10..at] && small[10..110].iter().all(|&b| b == 0xee), "a read after the write")?;
+    ensure(inode.byte(at) != 0xee, "the write went through the cache")?;
+    ensure(cache.get_page(0).is_some_and(|page| page.is_dirty()), "the written page is clean")?;
+    ensure(cache.get_page(2 * PAGE_SIZE).is_some_and(|page| !page.is_dirty()), "a page only read is dirty")?;
+
+    cache.sync(&*inode).map_err(|_| "sync")?;
+    ensure(*inode.writes.lock() == 2, "sync wrote other than the two dirty pages")?;
+    ensure((at..at + 100).all(|i| inode.byte(i) == 0xee), "the written data on the disk")?;
+    ensure(inode.byte(at - 1) == pattern(size)[at - 1], "the data around the write on the disk")?;
+    ensure(cache.get_page(0).is_some_and(|page| !page.is_dirty()), "the page is dirty after sync")?;
+
+    // an append is part of the file at once and reaches the disk on sync
+    ensure(cache.write(inode.clone(), size, size, b"tail").0 == 4, "append")?;
+    ensure(cache.end() == size + 4, "the end after the append")?;
+    ensure(cache.read(inode.clone(), size, 0, &mut buf) == size + 4, "a read past the old end")?;
+    cache.sync(&*inode).map_err(|_| "sync")?;
+    ensure(inode.len() == size + 4, "the length on the disk after the append")
+}
+
+/// a truncate drops the pages past the end and zeroes the tail of the last one,
+/// a later extension reads zeros instead of the old data
+pub fn page_cache_truncate() -> TestResult {
+    let size = 3 * PAGE_SIZE;
+    let inode = RamInode::new(pattern(size));
+    let cache = PageCache::new();
+    let mut buf = alloc::vec![0u8; size];
+    cache.read(inode.clone(), size, 0, &mut buf);
+
+    let cut = PAGE_SIZE + 10;
+    cache.truncate(cut);
+    ensure(cache.end() == cut, "the end after truncate")?;
+    ensure(cache.get_page(2 * PAGE_SIZE).is_none(), "a page past the end stays cached")?;
+    ensure(cache.read(inode.clone(), cut, 0, &mut buf) == cut, "the length of a read after truncate")?;
+    ensure(buf[..cut] == pattern(size)[..cut], "the contents kept by truncate")?;
+
+    // grow the file again by a write past the hole
+    ensure(cache.write(inode.clone(), cut, 2 * PAGE_SIZE, b"x").0 == 1, "extend")?;
+    let mut tail = alloc::vec![0xffu8; 2 * PAGE_SIZE - cut];
+    ensure(cache.read(inode.clone(), cut, cut, &mut tail) == tail.len(), "read the hole")?;
+    ensure(tail.iter().all(|&b| b == 0), "the hole holds the old data")
+}
+
+/// the writeback of aged pages leaves the young ones dirty, stops at its budget
+/// and reports the pages it left
+pub fn page_cache_write_back_expired() -> TestResult {
+    let size = 4 * PAGE_SIZE;
+    let inode = RamInode::new(pattern(size));
+    let cache = PageCache::new();
+    let (_, dirtied) = cache.write(inode.clone(), size, 0, &alloc::vec![0xee; size]);
+    ensure(dirtied == 4, "the pages a write dirtied")?;
+    ensure(cache.write(inode.clone(), size, 0, b"again").1 == 0, "a dirty page dirtied again")?;
+    let dirtied_at = cache.get_page(0).map_or(0, |page| page.dirtied_at());
+
+    let mut budget = usize::MAX;
+    ensure(cache.write_back_expired(&*inode, dirtied_at, &mut budget) == Ok(true), "young pages are left")?;
+    ensure(*inode.writes.lock() == 0, "young pages were written back")?;
+
+    let mut budget = 3;
+    ensure(cache.write_back_expired(&*inode, usize::MAX, &mut budget) == Ok(true), "the pages past the budget are left")?;
+    ensure(budget == 0 && *inode.writes.lock() == 3, "the budget is the pages written")?;
+    let mut budget = 3;
+    ensure(cache.write_back_expired(&*inode, usize::MAX, &mut budget) == Ok(false), "a clean cache")?;
+    ensure(budget == 2 && *inode.writes.lock() == 4, "only the dirty page is written")?;
+    ensure(inode.byte(size - 1) == 0xee, "the data on the disk")
+}
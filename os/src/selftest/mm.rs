@@ -0,0 +1,189 @@
+//! the frame allocator, the slab caches, page tables and address spaces
+
+use core::{alloc::Layout, ptr::NonNull};
+
+use alloc::vec::Vec;
+use hal::{addr::{PhysPageNumHal, RangePPNHal, VirtAddr, VirtAddrHal, VirtPageNum}, constant::{Constant, ConstantsHal}, pagetable::{MapPerm, PageLevel, PageTableEntryHal, PageTableHal}};
+
+use crate::{mm::{allocator::{frames_alloc, frames_alloc_aligned, frames_stat, FrameAllocator, SLAB_ALLOCATOR_INNER}, vm::{KernVmSpaceHal, PageFaultAccessType, UserVmSpace, USER_HUGE_PAGE_LEVEL}, FrameTracker, PageTable, KVMSPACE}, syscall::mm::MmapFlags};
+
+use super::{at_boot, ensure, TestResult};
+
+/// the free frames, compared before and after a test to catch a leak
+fn free_frames() -> usize {
+    frames_stat().0
+}
+
+/// whether `expected` frames are free, always true on a running system
+/// where other harts allocate meanwhile
+fn free_is(expected: usize) -> bool {
+    !at_boot() || free_frames() == expected
+}
+
+/// single frames freed out of order are handed out again, nothing leaks
+pub fn frame_alloc_free() -> TestResult {
+    let before = free_frames();
+    let mut frames: Vec<Option<FrameTracker>> = (0..64).map(|_| frames_alloc(1)).collect();
+    ensure(frames.iter().all(|frame| frame.is_some()), "alloc 64 frames")?;
+    ensure(free_is(before - 64), "the free count after alloc")?;
+    // free every other one, then take as many again
+    for frame in frames.iter_mut().step_by(2) {
+        *frame = None;
+    }
+    ensure(free_is(before - 32), "the free count after freeing half")?;
+    frames.extend((0..32).map(|_| frames_alloc(1)));
+    let mut ppns: Vec<usize> = frames.iter().flatten().map(|frame| frame.range_ppn.start.0).collect();
+    ensure(ppns.len() == 64, "realloc 32 frames")?;
+    ppns.sort_unstable();
+    ensure(ppns.windows(2).all(|pair| pair[0] != pair[1]), "a frame handed out twice")?;
+    drop(frames);
+    ensure(free_is(before), "frames leaked")
+}
+
+/// contiguous runs are as long and as aligned as asked
+pub fn frame_contiguous() -> TestResult {
+    let before = free_frames();
+    let run = frames_alloc(17).ok_or("alloc 17 contiguous frames")?;
+    ensure(run.range_ppn.clone().count() == 17, "the length of the run")?;
+    let aligned = frames_alloc_aligned(512, 9).ok_or("alloc 512 frames aligned to 2 MiB")?;
+    ensure(aligned.range_ppn.clone().count() == 512, "the length of the aligned run")?;
+    ensure(aligned.range_ppn.start.0 % 512 == 0, "the alignment of the run")?;
+    ensure(run.range_ppn.end <= aligned.range_ppn.start || aligned.range_ppn.end <= run.range_ppn.start, "the runs overlap")?;
+    // the frames of a run are usable memory
+    aligned.range_ppn.get_slice_mut::<u8>().fill(0x5a);
+    ensure(aligned.range_ppn.get_slice::<u8>().iter().all(|&byte| byte == 0x5a), "write the run")?;
+    drop((run, aligned));
+    ensure(free_is(before), "frames leaked")
+}
+
+/// take every free frame, an allocation fails cleanly, all of them come back
+pub fn frame_exhaustion() -> TestResult {
+    let before = free_frames();
+    // big runs first, so the list of them stays short
+    let mut taken = Vec::with_capacity(before / 512 + 4096);
+    let mut size = 512;
+    while size > 0 {
+        match frames_alloc(size) {
+            Some(frames) if taken.len() < taken.capacity() => taken.push(frames),
+            Some(_) => return Err("too many runs to hold"),
+            None => size /= 2,
+        }
+    }
+    let exhausted = free_frames() == 0 && frames_alloc(1).is_none();
+    drop(taken);
+    ensure(exhausted, "memory left after taking every frame")?;
+    ensure(free_frames() == before, "frames lost after exhaustion")?;
+    ensure(frames_alloc(1).is_some(), "alloc after exhaustion")
+}
+
+/// objects of every cache size hold their contents while the others are in use
+pub fn slab_sizes() -> TestResult {
+    const SIZES: [usize; 13] = [8, 16, 32, 64, 96, 128, 192, 256, 512, 1024, 2048, 4096, 8192];
+    const PER_SIZE: usize = 24;
+    let mut objects = Vec::new();
+    for (i, &size) in SIZES.iter().enumerate() {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        for j in 0..PER_SIZE {
+            let ptr = SLAB_ALLOCATOR_INNER.alloc_by_layout(layout).ok_or("slab alloc")?;
+            let fill = (i * PER_SIZE + j) as u8;
+            unsafe { ptr.as_ptr().write_bytes(fill, size) };
+            objects.push((ptr, layout, fill));
+        }
+    }
+    let intact = objects.iter().all(|&(ptr, layout, fill)| {
+        unsafe { core::slice::from_raw_parts(ptr.as_ptr(), layout.size()) }.iter().all(|&byte| byte == fill)
+    });
+    // freed in another order than allocated, every block goes from full to free to empty
+    for i in (0..objects.len()).step_by(2).chain((1..objects.len()).step_by(2)) {
+        let (ptr, layout, _) = objects[i];
+        SLAB_ALLOCATOR_INNER.dealloc_by_layout(ptr, layout);
+    }
+    ensure(intact, "an object overwritten by another")
+}
+
+/// a pointer from one cache is not taken for one of another, the check which
+/// makes a dealloc into the wrong cache panic
+pub fn slab_foreign_dealloc() -> TestResult {
+    let small = Layout::from_size_align(8, 8).unwrap();
+    let ptr: NonNull<u8> = SLAB_ALLOCATOR_INNER.alloc_by_layout(small).ok_or("slab alloc")?;
+    let own = SLAB_ALLOCATOR_INNER.cache8.lock().owns(ptr);
+    let foreign = SLAB_ALLOCATOR_INNER.cache16.lock().owns(ptr) || SLAB_ALLOCATOR_INNER.cache64.lock().owns(ptr);
+    SLAB_ALLOCATOR_INNER.dealloc_by_layout(ptr, small);
+    ensure(own, "the cache does not own its object")?;
+    ensure(!foreign, "another cache would take the object")
+}
+
+/// a user address no test space uses otherwise, 1 GiB aligned
+const TEST_VPN: VirtPageNum = VirtPageNum(0x4000_0000 >> 12);
+
+/// map, translate and unmap single pages in a fresh page table
+pub fn page_table_small() -> TestResult {
+    let mut table = PageTable::new_in(0, FrameAllocator);
+    let frames = frames_alloc(4).ok_or("alloc frames")?;
+    let perm = MapPerm::R | MapPerm::W | MapPerm::U;
+    for i in 0..4 {
+        table.map(TEST_VPN + i, frames.range_ppn.start + i, perm, PageLevel::Small).map_err(|_| "map")?;
+    }
+    ensure(table.map(TEST_VPN, frames.range_ppn.start, perm, PageLevel::Small).is_err(), "map over a mapping")?;
+    for i in 0..4 {
+        ensure(table.translate_vpn(TEST_VPN + i) == Some(frames.range_ppn.start + i), "translate_vpn")?;
+    }
+    let va = VirtAddr((TEST_VPN + 2).0 * Constant::PAGE_SIZE + 0x123);
+    ensure(table.translate_va(va).map(|pa| pa.0) == Some((frames.range_ppn.start + 2).0 * Constant::PAGE_SIZE + 0x123), "translate_va")?;
+    let (pte, _) = table.find_pte(TEST_VPN + 1).ok_or("find_pte")?;
+    ensure(pte.flags().contains(perm), "the permission of the entry")?;
+    let old = table.unmap(TEST_VPN + 1).map_err(|_| "unmap")?;
+    ensure(old.ppn() == frames.range_ppn.start + 1, "the entry unmapped")?;
+    ensure(table.translate_vpn(TEST_VPN + 1).is_none(), "translate after unmap")?;
+    ensure(table.translate_vpn(TEST_VPN + 2).is_some(), "the neighbour after unmap")?;
+    ensure(table.unmap(TEST_VPN + 1).is_err(), "unmap twice")
+}
+
+/// one huge entry translates every small page below it
+pub fn page_table_huge() -> TestResult {
+    let Some(level) = USER_HUGE_PAGE_LEVEL else {
+        // nothing maps huge user pages on this arch
+        return Ok(());
+    };
+    let count = level.page_count();
+    let mut table = PageTable::new_in(0, FrameAllocator);
+    let frames = frames_alloc_aligned(count, count.trailing_zeros() as usize).ok_or("alloc a huge frame")?;
+    let base = frames.range_ppn.start;
+    table.map(TEST_VPN, base, MapPerm::R | MapPerm::W | MapPerm::U, level).map_err(|_| "map a huge page")?;
+    for i in [0, 1, count / 2, count - 1] {
+        ensure(table.translate_vpn(TEST_VPN + i) == Some(base + i), "translate inside the huge page")?;
+    }
+    ensure(table.translate_vpn(TEST_VPN + count).is_none(), "translate past the huge page")?;
+    let (_, index) = table.find_pte(TEST_VPN + 7).ok_or("find_pte")?;
+    ensure(PageLevel::from(index) == level, "the level of the entry")?;
+    ensure(table.unmap(TEST_VPN).is_ok(), "unmap the huge page")?;
+    ensure(table.translate_vpn(TEST_VPN + 7).is_none(), "translate after unmap")
+}
+
+/// a fork shares the written frame read-only, the first write of either side
+/// copies it, the other side keeps the old contents
+pub fn vm_cow_fork() -> TestResult {
+    let mut parent = KVMSPACE.lock().to_user();
+    let perm = MapPerm::R | MapPerm::W | MapPerm::U;
+    let flags = MmapFlags::MAP_PRIVATE | MmapFlags::MAP_ANONYMOUS;
+    let va = parent.alloc_anon_area(VirtAddr(0), 2 * Constant::PAGE_SIZE, perm, flags, None).map_err(|_| "alloc_anon_area")?;
+    parent.handle_page_fault(va, PageFaultAccessType::WRITE).map_err(|_| "the write fault of the parent")?;
+    let ppn = parent.translate_vpn(va.floor()).ok_or("the parent page not mapped")?;
+    ppn.get_slice_mut::<u8>().fill(0xa5);
+
+    // no hart runs either space, so the key for the tlb shootdown matches none
+    let mut child = UserVmSpace::from_existed(&mut parent, 0);
+    ensure(child.translate_vpn(va.floor()) == Some(ppn), "the child shares the frame")?;
+    ensure(child.translate_vpn(va.floor() + 1).is_none(), "the page never touched is mapped")?;
+
+    child.handle_page_fault(va, PageFaultAccessType::WRITE).map_err(|_| "the write fault of the child")?;
+    let copy = child.translate_vpn(va.floor()).ok_or("the child page not mapped")?;
+    ensure(copy != ppn, "the child wrote to the shared frame")?;
+    ensure(copy.get_slice::<u8>().iter().all(|&byte| byte == 0xa5), "the copy of the child")?;
+    copy.get_slice_mut::<u8>().fill(0x3c);
+    ensure(ppn.get_slice::<u8>().iter().all(|&byte| byte == 0xa5), "the parent sees the write of the child")?;
+
+    // the parent is the last owner, it writes in place
+    parent.handle_page_fault(va, PageFaultAccessType::WRITE).map_err(|_| "the second write fault of the parent")?;
+    ensure(parent.translate_vpn(va.floor()) == Some(ppn), "the last owner copied its frame")
+}
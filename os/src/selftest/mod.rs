@@ -0,0 +1,122 @@
+//! In-kernel self tests of the core subsystems, built with the `selftest` feature.
+//!
+//! Each stage runs right after the subsystems it covers came up during boot,
+//! and the whole suite can be run again from userspace through kdebug.
+//! A failure is reported loudly, it stops the boot only with `selftest=fatal`
+//! on the kernel command line
+
+mod blk;
+mod fs;
+mod mm;
+mod sync;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use hal::println;
+
+use crate::timer::get_current_time_ms;
+
+/// the outcome of a test, the reason on failure
+pub type TestResult = Result<(), &'static str>;
+
+/// fail with `what` unless `ok`
+fn ensure(ok: bool, what: &'static str) -> TestResult {
+    if ok { Ok(()) } else { Err(what) }
+}
+
+/// the suite runs during boot, nothing else allocates or takes locks meanwhile
+static AT_BOOT: AtomicBool = AtomicBool::new(false);
+
+/// whether the free memory may be compared exactly, as nothing else runs
+fn at_boot() -> bool {
+    AT_BOOT.load(Ordering::Relaxed)
+}
+
+/// the subsystems a group of tests needs, in boot order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// the allocators, page tables and address spaces, after the traps are set up
+    Mm,
+    /// the page cache and the disk, after the file systems are mounted
+    Fs,
+    /// futexes, wait queues and timers
+    Sync,
+}
+
+struct SelfTest {
+    name: &'static str,
+    stage: Stage,
+    /// only safe while nothing else runs, e.g. it takes all the free memory for a while
+    boot_only: bool,
+    run: fn() -> TestResult,
+}
+
+const TESTS: &[SelfTest] = &[
+    SelfTest { name: "frame alloc/free", stage: Stage::Mm, boot_only: false, run: mm::frame_alloc_free },
+    SelfTest { name: "frame contiguous", stage: Stage::Mm, boot_only: false, run: mm::frame_contiguous },
+    SelfTest { name: "frame exhaustion", stage: Stage::Mm, boot_only: true, run: mm::frame_exhaustion },
+    SelfTest { name: "slab sizes", stage: Stage::Mm, boot_only: false, run: mm::slab_sizes },
+    SelfTest { name: "slab foreign dealloc", stage: Stage::Mm, boot_only: false, run: mm::slab_foreign_dealloc },
+    SelfTest { name: "page table small", stage: Stage::Mm, boot_only: false, run: mm::page_table_small },
+    SelfTest { name: "page table huge", stage: Stage::Mm, boot_only: false, run: mm::page_table_huge },
+    SelfTest { name: "vm cow fork", stage: Stage::Mm, boot_only: false, run: mm::vm_cow_fork },
+    SelfTest { name: "page cache coherence", stage: Stage::Fs, boot_only: false, run: fs::page_cache_coherence },
+    SelfTest { name: "page cache truncate", stage: Stage::Fs, boot_only: false, run: fs::page_cache_truncate },
+    SelfTest { name: "page cache write back expired", stage: Stage::Fs, boot_only: false, run: fs::page_cache_write_back_expired },
+    SelfTest { name: "blk abandoned request", stage: Stage::Fs, boot_only: false, run: blk::blk_abandoned_request },
+    SelfTest { name: "blk reset", stage: Stage::Fs, boot_only: false, run: blk::blk_reset },
+    SelfTest { name: "blk queue merge", stage: Stage::Fs, boot_only: false, run: blk::blk_queue_merge },
+    SelfTest { name: "futex wake order", stage: Stage::Sync, boot_only: false, run: sync::futex_wake_order },
+    SelfTest { name: "futex requeue", stage: Stage::Sync, boot_only: false, run: sync::futex_requeue },
+    SelfTest { name: "wait queue wake one", stage: Stage::Sync, boot_only: false, run: sync::wait_queue_wake_one },
+    SelfTest { name: "timer cancel", stage: Stage::Sync, boot_only: false, run: sync::timer_cancel },
+    SelfTest { name: "timer reset", stage: Stage::Sync, boot_only: false, run: sync::timer_reset },
+    SelfTest { name: "timer rearm", stage: Stage::Sync, boot_only: false, run: sync::timer_rearm },
+    SelfTest { name: "timer stress", stage: Stage::Sync, boot_only: false, run: sync::timer_stress },
+    #[cfg(debug_assertions)]
+    SelfTest { name: "lockdep abba", stage: Stage::Sync, boot_only: false, run: sync::lockdep_abba },
+];
+
+/// run the tests of `stage`, return the number which failed
+fn run(stage: Stage, boot: bool) -> usize {
+    AT_BOOT.store(boot, Ordering::Relaxed);
+    let mut failed = 0;
+    for test in TESTS.iter().filter(|test| test.stage == stage) {
+        if test.boot_only && !boot {
+            println!("[selftest] {}: SKIP (boot only)", test.name);
+            continue;
+        }
+        let start = get_current_time_ms();
+        match (test.run)() {
+            Ok(()) => println!("[selftest] {}: PASS ({} ms)", test.name, get_current_time_ms() - start),
+            Err(reason) => {
+                println!("[selftest] {}: FAIL: {}", test.name, reason);
+                log::error!("[selftest] {} failed: {}", test.name, reason);
+                failed += 1;
+            }
+        }
+    }
+    failed
+}
+
+/// run the tests of `stage` during boot, stop the boot on a failure if asked to
+pub fn run_stage(stage: Stage) {
+    let failed = run(stage, true);
+    if failed == 0 {
+        return;
+    }
+    println!("[selftest] {:?}: {} FAILED", stage, failed);
+    if crate::devices::bootarg("selftest") == Some("fatal") {
+        panic!("[selftest] {} tests of stage {:?} failed", failed, stage);
+    }
+}
+
+/// run every test which is safe on a running system, return the number which failed
+pub fn run_all() -> usize {
+    let failed: usize = [Stage::Mm, Stage::Fs, Stage::Sync]
+        .into_iter()
+        .map(|stage| run(stage, false))
+        .sum();
+    println!("[selftest] done, {} failed", failed);
+    failed
+}
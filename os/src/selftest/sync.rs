@@ -0,0 +1,261 @@
+//! futex and wait queues and the timer manager
+
+use core::{sync::atomic::{AtomicBool, AtomicUsize, Ordering}, task::Waker, time::Duration};
+
+use alloc::{boxed::Box, sync::Arc, task::Wake, vec::Vec};
+use hal::addr::VirtAddr;
+
+use crate::{sync::{mutex::SpinNoIrqLock, WaitEntry, WaitQueue}, syscall::futex::{FutexHashKey, FutexManager, FutexWaiter}, timer::{get_current_time_duration, timer::{Timer, TimerEvent, TimerHandle, TIMER_MANAGER}}};
+
+use super::{ensure, TestResult};
+
+/// the tids of the waiters in the order they were woken
+type WakeLog = Arc<SpinNoIrqLock<Vec<usize>>>;
+
+struct LogWaker {
+    tid: usize,
+    log: WakeLog,
+}
+
+impl Wake for LogWaker {
+    fn wake(self: Arc<Self>) {
+        self.log.lock().push(self.tid);
+    }
+}
+
+fn wake_log() -> WakeLog {
+    Arc::new(SpinNoIrqLock::new(Vec::new()))
+}
+
+fn log_waker(tid: usize, log: &WakeLog) -> Waker {
+    Waker::from(Arc::new(LogWaker { tid, log: log.clone() }))
+}
+
+fn waiter(tid: usize, mask: u32, log: &WakeLog) -> FutexWaiter {
+    FutexWaiter { tid, waker: log_waker(tid, log), mask }
+}
+
+/// a key no task uses, the manager is a private one anyway
+fn key(vaddr: usize) -> FutexHashKey {
+    FutexHashKey::Private { mm: 0, vaddr: VirtAddr(vaddr) }
+}
+
+/// waiters wake in arrival order, a bitset wake skips the ones whose mask misses,
+/// a removed waiter is never woken
+pub fn futex_wake_order() -> TestResult {
+    let mut futexes = FutexManager::new();
+    let log = wake_log();
+    let (one, two) = (0b01, 0b10);
+    for (tid, mask) in [(1, one), (2, two), (3, one), (4, two), (5, one), (6, two)] {
+        futexes.add_waiter(&key(0x1000), waiter(tid, mask, &log));
+    }
+    ensure(futexes.wake(&key(0x2000), 1) == Ok(0), "wake a futex nobody waits on")?;
+    ensure(futexes.wake(&key(0x1000), 2) == Ok(2), "wake two")?;
+    ensure(*log.lock() == [1, 2], "the first two woken in order")?;
+    ensure(futexes.wake_bitset(&key(0x1000), 1, two) == Ok(1), "wake one by the bitset")?;
+    ensure(*log.lock() == [1, 2, 4], "the bitset wake skips a mask which misses")?;
+    ensure(futexes.remove_waiter(&key(0x1000), 5).is_some_and(|w| w.tid == 5), "remove a waiter")?;
+    ensure(futexes.remove_waiter(&key(0x1000), 5).is_none(), "remove a waiter twice")?;
+    ensure(futexes.wake(&key(0x1000), u32::MAX) == Ok(2), "wake the rest")?;
+    ensure(*log.lock() == [1, 2, 4, 3, 6], "the rest woken in order, the removed one not")
+}
+
+/// a requeue moves the oldest waiters behind the ones already on the target
+pub fn futex_requeue() -> TestResult {
+    let mut futexes = FutexManager::new();
+    let log = wake_log();
+    for tid in 1..=4 {
+        futexes.add_waiter(&key(0x1000), waiter(tid, u32::MAX, &log));
+    }
+    futexes.add_waiter(&key(0x2000), waiter(9, u32::MAX, &log));
+    ensure(futexes.requeue_waiters(key(0x1000), key(0x2000), 2) == Ok(2), "requeue two")?;
+    ensure(futexes.wake(&key(0x2000), u32::MAX) == Ok(3), "wake the target")?;
+    ensure(*log.lock() == [9, 1, 2], "the requeued ones behind the waiter already there")?;
+    ensure(futexes.wake(&key(0x1000), u32::MAX) == Ok(2), "wake the source")?;
+    ensure(*log.lock() == [9, 1, 2, 3, 4], "the ones left on the source")
+}
+
+/// wake_one goes to the oldest entry and every poller, a waiter queued twice
+/// keeps its place, a cancelled waiter hands on the wake it got
+pub fn wait_queue_wake_one() -> TestResult {
+    let queue = WaitQueue::new();
+    let log = wake_log();
+    let mut entries = [WaitEntry::new(), WaitEntry::new(), WaitEntry::new()];
+    for (tid, entry) in entries.iter_mut().enumerate() {
+        queue.wait(entry, &log_waker(tid + 1, &log));
+    }
+    queue.wait(&mut entries[0], &log_waker(1, &log));
+    queue.register(&log_waker(9, &log));
+    queue.register(&log_waker(9, &log));
+    ensure(queue.wake_one(), "wake one")?;
+    ensure(*log.lock() == [1, 9], "the oldest and the poller, once")?;
+    // the first waiter was woken but gives up, say on a timeout
+    queue.cancel(&mut entries[0]);
+    ensure(*log.lock() == [1, 9, 2], "the unused wake handed on")?;
+    queue.finish(&mut entries[1]);
+    queue.cancel(&mut entries[2]);
+    ensure(*log.lock() == [1, 9, 2], "a waiter still queued leaves without a wake")?;
+    ensure(queue.is_empty() && !queue.wake_one(), "nobody left")?;
+    for (tid, entry) in entries.iter_mut().enumerate() {
+        queue.wait(entry, &log_waker(tid + 1, &log));
+    }
+    ensure(queue.wake_all() == 3, "wake all")?;
+    ensure(*log.lock() == [1, 9, 2, 1, 2, 3], "all woken in order")
+}
+
+/// taking two locks in the order reversing an earlier one is caught before it can deadlock
+#[cfg(debug_assertions)]
+pub fn lockdep_abba() -> TestResult {
+    use crate::sync::mutex::lockdep;
+    // constructed at two places, so of two classes
+    let a = SpinNoIrqLock::new(());
+    let b = SpinNoIrqLock::new(());
+    {
+        let _a = a.lock();
+        let _b = b.lock();
+    }
+    ensure(!lockdep::catch_cycle(|| {
+        let _a = a.lock();
+        let _b = b.lock();
+    }), "the same order again taken as a cycle")?;
+    ensure(lockdep::catch_cycle(|| {
+        let _b = b.lock();
+        let _a = a.lock();
+    }), "the reversed order not caught")
+}
+
+/// counts its runs, runs again at once until it ran `rearm` times,
+/// cancels itself from inside the callback if given its handle
+struct CountEvent {
+    fired: Arc<AtomicUsize>,
+    rearm: usize,
+    own: Option<Arc<SpinNoIrqLock<Option<TimerHandle>>>>,
+}
+
+impl TimerEvent for CountEvent {
+    fn callback(self: Box<Self>) -> Option<Timer> {
+        let fired = self.fired.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Some(handle) = self.own.as_ref().and_then(|own| *own.lock()) {
+            // the timer is firing, not pending, so this only marks it cancelled
+            handle.cancel();
+        }
+        (fired < self.rearm).then(|| Timer::new(Duration::ZERO, self))
+    }
+}
+
+fn add_counted(deadline: Duration, rearm: usize) -> (TimerHandle, Arc<AtomicUsize>) {
+    let fired = Arc::new(AtomicUsize::new(0));
+    let event = CountEvent { fired: fired.clone(), rearm, own: None };
+    (TIMER_MANAGER.add_timer(Timer::new(deadline, Box::new(event))), fired)
+}
+
+/// fire the expired timers until `done`, another hart may fire them too
+fn check_until(limit: Duration, done: impl Fn() -> bool) -> bool {
+    let end = get_current_time_duration() + limit;
+    loop {
+        TIMER_MANAGER.check();
+        if done() {
+            return true;
+        }
+        if get_current_time_duration() > end {
+            return false;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// a cancelled timer never fires, only the first cancel finds it
+pub fn timer_cancel() -> TestResult {
+    let deadline = get_current_time_duration() + Duration::from_millis(5);
+    let (handle, fired) = add_counted(deadline, 1);
+    ensure(handle.deadline() == Some(deadline), "the deadline of a pending timer")?;
+    ensure(handle.cancel(), "cancel a pending timer")?;
+    ensure(!handle.cancel(), "cancel twice")?;
+    ensure(handle.deadline().is_none(), "the deadline after cancel")?;
+    check_until(Duration::from_millis(10), || false);
+    ensure(fired.load(Ordering::SeqCst) == 0, "a cancelled timer fired")
+}
+
+/// a reset moves a pending timer, a fired one can't be reset or cancelled
+pub fn timer_reset() -> TestResult {
+    let (handle, fired) = add_counted(get_current_time_duration() + Duration::from_secs(3600), 1);
+    ensure(handle.reset(Duration::ZERO), "reset a pending timer")?;
+    ensure(handle.deadline() == Some(Duration::ZERO), "the deadline after reset")?;
+    ensure(check_until(Duration::from_millis(100), || fired.load(Ordering::SeqCst) == 1), "the timer moved forward fired")?;
+    ensure(!handle.reset(Duration::from_secs(3600)), "reset a fired timer")?;
+    ensure(!handle.cancel(), "cancel a fired timer")?;
+    check_until(Duration::from_millis(2), || false);
+    ensure(fired.load(Ordering::SeqCst) == 1, "a timer fired twice")
+}
+
+/// what a callback returns runs again under the same handle, unless the
+/// timer was cancelled while its callback ran
+pub fn timer_rearm() -> TestResult {
+    let (handle, fired) = add_counted(Duration::ZERO, 3);
+    ensure(check_until(Duration::from_millis(100), || fired.load(Ordering::SeqCst) == 3), "fire three times")?;
+    check_until(Duration::from_millis(2), || false);
+    ensure(fired.load(Ordering::SeqCst) == 3, "fired more than asked")?;
+    ensure(handle.deadline().is_none(), "pending after the last run")?;
+
+    let fired = Arc::new(AtomicUsize::new(0));
+    let own = Arc::new(SpinNoIrqLock::new(None));
+    let event = CountEvent { fired: fired.clone(), rearm: 2, own: Some(own.clone()) };
+    // the handle is stored before the timer is due
+    let deadline = get_current_time_duration() + Duration::from_millis(2);
+    *own.lock() = Some(TIMER_MANAGER.add_timer(Timer::new(deadline, Box::new(event))));
+    ensure(check_until(Duration::from_millis(100), || fired.load(Ordering::SeqCst) >= 1), "fire the self cancelling timer")?;
+    check_until(Duration::from_millis(2), || false);
+    ensure(fired.load(Ordering::SeqCst) == 1, "rearmed after a cancel while firing")?;
+    let handle = own.lock().take().unwrap();
+    ensure(handle.deadline().is_none(), "pending after a cancel while firing")
+}
+
+/// counts its run, and the runs after its timer was cancelled
+struct StressEvent {
+    cancelled: Arc<AtomicBool>,
+    fired: Arc<AtomicUsize>,
+    late: Arc<AtomicUsize>,
+}
+
+impl TimerEvent for StressEvent {
+    fn callback(self: Box<Self>) -> Option<Timer> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            self.late.fetch_add(1, Ordering::SeqCst);
+        }
+        self.fired.fetch_add(1, Ordering::SeqCst);
+        None
+    }
+}
+
+/// arm 10k timers, cancel or move about half of them while the others fire:
+/// no timer runs after a successful cancel, every other one runs once
+pub fn timer_stress() -> TestResult {
+    const TIMERS: usize = 10_000;
+    let fired = Arc::new(AtomicUsize::new(0));
+    let late = Arc::new(AtomicUsize::new(0));
+    let mut cancelled = 0;
+    for i in 0..TIMERS {
+        let was_cancelled = Arc::new(AtomicBool::new(false));
+        let expire = get_current_time_duration() + Duration::from_micros((i * 7 % 50) as u64);
+        let event = StressEvent { cancelled: was_cancelled.clone(), fired: fired.clone(), late: late.clone() };
+        let handle = TIMER_MANAGER.add_timer(Timer::new(expire, Box::new(event)));
+        match i % 4 {
+            0 | 1 => if handle.cancel() {
+                was_cancelled.store(true, Ordering::SeqCst);
+                cancelled += 1;
+            },
+            2 => {
+                handle.reset(expire + Duration::from_micros(100));
+            }
+            _ => {}
+        }
+        // fire the expired ones now and then, while the rest are still being armed
+        if i % 64 == 0 {
+            TIMER_MANAGER.check();
+        }
+    }
+    ensure(check_until(Duration::from_millis(200), || fired.load(Ordering::SeqCst) + cancelled == TIMERS), "every timer not cancelled fired")?;
+    check_until(Duration::from_millis(2), || false);
+    ensure(late.load(Ordering::SeqCst) == 0, "a cancelled timer fired")?;
+    ensure(fired.load(Ordering::SeqCst) + cancelled == TIMERS, "a timer fired twice")
+}
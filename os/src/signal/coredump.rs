@@ -0,0 +1,302 @@
+//! core dump
+//! write an ELF core file of a process killed by a fatal signal,
+//! holding the registers of every thread and the readable memory
+
+use core::{mem::size_of, time::Duration};
+
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use hal::{addr::RangePPNHal, constant::{Constant, ConstantsHal}};
+use log::*;
+
+use crate::{fs::{vfs::{file::open_file, File}, OpenFlags}, mm::vm::CoreSegment, task::task::TaskControlBlock, timer::get_current_time_duration, utils::{block_on, rel_path_to_abs}};
+
+use super::{SIGABRT, SIGBUS, SIGILL, SIGSEGV};
+
+const ET_CORE: u16 = 4;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+const NT_PRPSINFO: u32 = 3;
+const NOTE_NAME: &[u8; 8] = b"CORE\0\0\0\0";
+/// how long the dump waits for the other threads to leave their harts
+const OFF_CPU_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// ELF64 file header
+#[repr(C)]
+#[derive(Default)]
+struct Elf64Ehdr {
+    e_ident: [u8; 16],
+    e_type: u16,
+    e_machine: u16,
+    e_version: u32,
+    e_entry: u64,
+    e_phoff: u64,
+    e_shoff: u64,
+    e_flags: u32,
+    e_ehsize: u16,
+    e_phentsize: u16,
+    e_phnum: u16,
+    e_shentsize: u16,
+    e_shnum: u16,
+    e_shstrndx: u16,
+}
+
+/// ELF64 program header
+#[repr(C)]
+#[derive(Default)]
+struct Elf64Phdr {
+    p_type: u32,
+    p_flags: u32,
+    p_offset: u64,
+    p_vaddr: u64,
+    p_paddr: u64,
+    p_filesz: u64,
+    p_memsz: u64,
+    p_align: u64,
+}
+
+/// struct elf_prstatus up to pr_reg, followed by elf_gregset_t and pr_fpvalid
+#[repr(C)]
+#[derive(Default)]
+struct ElfPrStatusHead {
+    si_signo: i32,
+    si_code: i32,
+    si_errno: i32,
+    pr_cursig: i16,
+    pr_sigpend: u64,
+    pr_sighold: u64,
+    pr_pid: i32,
+    pr_ppid: i32,
+    pr_pgrp: i32,
+    pr_sid: i32,
+    /// utime, stime, cutime, cstime
+    pr_times: [u64; 8],
+}
+
+/// struct elf_prpsinfo
+#[repr(C)]
+struct ElfPrPsInfo {
+    pr_state: u8,
+    pr_sname: u8,
+    pr_zomb: u8,
+    pr_nice: i8,
+    pr_flag: u64,
+    pr_uid: u32,
+    pr_gid: u32,
+    pr_pid: i32,
+    pr_ppid: i32,
+    pr_pgrp: i32,
+    pr_sid: i32,
+    pr_fname: [u8; 16],
+    pr_psargs: [u8; 80],
+}
+
+fn push<T>(buf: &mut Vec<u8>, val: &T) {
+    let bytes = unsafe { core::slice::from_raw_parts(val as *const T as *const u8, size_of::<T>()) };
+    buf.extend_from_slice(bytes);
+}
+
+fn push_note(buf: &mut Vec<u8>, note_type: u32, desc: &[u8]) {
+    push(buf, &5u32);
+    push(buf, &(desc.len() as u32));
+    push(buf, &note_type);
+    buf.extend_from_slice(NOTE_NAME);
+    buf.extend_from_slice(desc);
+    buf.resize(buf.len().next_multiple_of(4), 0);
+}
+
+fn prstatus(thread: &Arc<TaskControlBlock>, signo: usize) -> Vec<u8> {
+    let (sigpend, sighold) = thread.with_sig_manager(|m| (m.bitmap.bits(), m.blocked_sigs.bits()));
+    let head = ElfPrStatusHead {
+        si_signo: signo as i32,
+        pr_cursig: signo as i16,
+        pr_sigpend: sigpend as u64,
+        pr_sighold: sighold as u64,
+        pr_pid: thread.tid() as i32,
+        pr_ppid: thread.parent().and_then(|p| p.upgrade()).map_or(0, |p| p.pid()) as i32,
+        pr_pgrp: thread.pgid() as i32,
+        ..Default::default()
+    };
+    let regs = thread.user_regs();
+    let mut desc = Vec::new();
+    push(&mut desc, &head);
+    push(&mut desc, &regs);
+    // pr_fpvalid and the tail padding
+    push(&mut desc, &0u64);
+    desc
+}
+
+fn prpsinfo(task: &Arc<TaskControlBlock>) -> Vec<u8> {
+    let mut info = ElfPrPsInfo {
+        pr_state: 0,
+        pr_sname: b'R',
+        pr_zomb: 0,
+        pr_nice: 0,
+        pr_flag: 0,
+        pr_uid: 0,
+        pr_gid: 0,
+        pr_pid: task.pid() as i32,
+        pr_ppid: task.parent().and_then(|p| p.upgrade()).map_or(0, |p| p.pid()) as i32,
+        pr_pgrp: task.pgid() as i32,
+        pr_sid: 0,
+        pr_fname: [0; 16],
+        pr_psargs: [0; 80],
+    };
+    if let Some(dentry) = task.elf.lock().as_ref().and_then(|f| f.dentry()) {
+        let name = dentry.name().as_bytes();
+        let len = name.len().min(15);
+        info.pr_fname[..len].copy_from_slice(&name[..len]);
+        let len = name.len().min(79);
+        info.pr_psargs[..len].copy_from_slice(&name[..len]);
+    }
+    let mut desc = Vec::new();
+    push(&mut desc, &info);
+    desc
+}
+
+/// the headers and notes of the core file, the segment contents follow at page aligned offsets
+fn core_headers(task: &Arc<TaskControlBlock>, signo: usize, segments: &[CoreSegment]) -> Vec<u8> {
+    let mut notes = Vec::new();
+    // the thread that took the signal comes first, gdb selects it
+    push_note(&mut notes, NT_PRSTATUS, &prstatus(task, signo));
+    task.with_thread_group(|tg| {
+        for thread in tg.iter().filter(|t| t.tid() != task.tid()) {
+            push_note(&mut notes, NT_PRSTATUS, &prstatus(&thread, signo));
+        }
+    });
+    push_note(&mut notes, NT_PRPSINFO, &prpsinfo(task));
+
+    let phnum = segments.len() + 1;
+    let notes_offset = size_of::<Elf64Ehdr>() + phnum * size_of::<Elf64Phdr>();
+    let mut data_offset = (notes_offset + notes.len()).next_multiple_of(Constant::PAGE_SIZE);
+
+    let mut e_ident = [0u8; 16];
+    e_ident[..7].copy_from_slice(b"\x7fELF\x02\x01\x01");
+    let ehdr = Elf64Ehdr {
+        e_ident,
+        e_type: ET_CORE,
+        e_machine: Constant::ELF_MACHINE,
+        e_version: 1,
+        e_phoff: size_of::<Elf64Ehdr>() as u64,
+        e_ehsize: size_of::<Elf64Ehdr>() as u16,
+        e_phentsize: size_of::<Elf64Phdr>() as u16,
+        e_phnum: phnum as u16,
+        ..Default::default()
+    };
+    let mut buf = Vec::new();
+    push(&mut buf, &ehdr);
+    push(&mut buf, &Elf64Phdr {
+        p_type: PT_NOTE,
+        p_offset: notes_offset as u64,
+        p_filesz: notes.len() as u64,
+        p_align: 4,
+        ..Default::default()
+    });
+    for seg in segments {
+        let mut p_flags = PF_R;
+        if seg.map_perm.contains(hal::pagetable::MapPerm::W) {
+            p_flags |= PF_W;
+        }
+        if seg.map_perm.contains(hal::pagetable::MapPerm::X) {
+            p_flags |= PF_X;
+        }
+        let memsz = seg.range_va.end.0 - seg.range_va.start.0;
+        let filesz = seg.pages.len() * Constant::PAGE_SIZE;
+        push(&mut buf, &Elf64Phdr {
+            p_type: PT_LOAD,
+            p_flags,
+            // a zero-filled run keeps the offset in the mapped file for reference
+            p_offset: if filesz == 0 { seg.file_offset } else { data_offset } as u64,
+            p_vaddr: seg.range_va.start.0 as u64,
+            p_filesz: filesz as u64,
+            p_memsz: memsz as u64,
+            p_align: Constant::PAGE_SIZE as u64,
+            ..Default::default()
+        });
+        data_offset += filesz;
+    }
+    buf.extend_from_slice(&notes);
+    buf.resize(buf.len().next_multiple_of(Constant::PAGE_SIZE), 0);
+    buf
+}
+
+/// writes the core file, stopping quietly at RLIMIT_CORE or when the disk is full
+pub struct CoreWriter {
+    file: Arc<dyn File>,
+    path: String,
+    written: usize,
+    limit: usize,
+}
+
+impl CoreWriter {
+    fn write(&mut self, data: &[u8]) -> Result<(), ()> {
+        let len = data.len().min(self.limit - self.written);
+        let mut done = 0;
+        while done < len {
+            match block_on(self.file.write(&data[done..len])) {
+                Ok(n) if n > 0 => done += n,
+                ret => {
+                    warn!("[coredump] write failed at {} bytes: {:?}", self.written + done, ret);
+                    return Err(());
+                }
+            }
+        }
+        self.written += len;
+        if len < data.len() { Err(()) } else { Ok(()) }
+    }
+}
+
+/// whether the default action of `signo` dumps core
+pub fn dumps_core(signo: usize) -> bool {
+    matches!(signo, SIGSEGV | SIGABRT | SIGILL | SIGBUS)
+}
+
+/// create core.<pid> in the cwd of `task` for a dump of `signo`,
+/// None if the signal, RLIMIT_CORE or PR_SET_DUMPABLE rules a dump out or the file can not be made
+pub fn open_core_file(task: &Arc<TaskControlBlock>, signo: usize) -> Option<CoreWriter> {
+    let limit = task.with_rlimit_core(|l| l.rlim_cur);
+    if limit == 0 || !dumps_core(signo) || !task.dumpable() {
+        return None;
+    }
+    let path = rel_path_to_abs(&task.cwd().path(), &format!("core.{}", task.pid()))?;
+    let Some(file) = open_file(&path, OpenFlags::O_CREAT | OpenFlags::O_WRONLY | OpenFlags::O_TRUNC) else {
+        warn!("[coredump] can not create {}", path);
+        return None;
+    };
+    Some(CoreWriter { file, path, written: 0, limit })
+}
+
+/// wait for the other threads of `task`, killed by now, to leave their harts: from then on
+/// their registers are those saved at their switch out and they write no more memory
+fn wait_other_threads(task: &Arc<TaskControlBlock>) {
+    let others: Vec<_> = task.with_thread_group(|tg| tg.iter().filter(|t| t.tid() != task.tid()).collect());
+    let deadline = get_current_time_duration() + OFF_CPU_TIMEOUT;
+    while let Some(thread) = others.iter().find(|t| t.on_cpu()) {
+        if get_current_time_duration() > deadline {
+            warn!("[coredump] thread {} is still running, its registers may be stale", thread.tid());
+            return;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// dump `task`, which took `signo`, into `writer`; the caller has killed the other threads
+pub fn do_coredump(task: &Arc<TaskControlBlock>, signo: usize, mut writer: CoreWriter) {
+    // the registers of the thread which took the signal, the others come from their last switch out
+    task.save_user_regs();
+    wait_other_threads(task);
+    // the segments hold the frames, the address space is not locked while the file is written
+    let segments = task.get_vm_space().lock().core_segments();
+    let ret = writer.write(&core_headers(task, signo, &segments)).and_then(|_| {
+        for seg in segments.iter() {
+            for (ppn, _) in seg.pages.iter() {
+                writer.write((*ppn..*ppn + 1).get_slice::<u8>())?;
+            }
+        }
+        Ok(())
+    });
+    info!("[coredump] task {} dumped {} bytes to {}, complete: {}", task.tid(), writer.written, writer.path, ret.is_ok());
+}
@@ -6,7 +6,7 @@
 
 use log::*;
 
-use crate::{signal::{SigSet, SIGABRT, SIGALRM, SIGBUS, SIGCHLD, SIGCONT, SIGFPE, SIGHUP, SIGILL, SIGINT, SIGIO, SIGKILL, SIGPIPE, SIGPROF, SIGPWR, SIGQUIT, SIGRTMAX, SIGSEGV, SIGSTKFLT, SIGSTOP, SIGSYS, SIGTERM, SIGTRAP, SIGTSTP, SIGTTIN, SIGTTOU, SIGURG, SIGUSR1, SIGUSR2, SIGVTALRM, SIGWINCH, SIGXCPU, SIGXFSZ}, task::current_task, utils::{dyn_future, Async}};
+use crate::{signal::{do_coredump, open_core_file, SigInfo, SigSet, SIGABRT, SIGALRM, SIGBUS, SIGCHLD, SIGCONT, SIGFPE, SIGHUP, SIGILL, SIGINT, SIGIO, SIGKILL, SIGPIPE, SIGPROF, SIGPWR, SIGQUIT, SIGRTMAX, SIGSEGV, SIGSTKFLT, SIGSTOP, SIGSYS, SIGTERM, SIGTRAP, SIGTSTP, SIGTTIN, SIGTTOU, SIGURG, SIGUSR1, SIGUSR2, SIGVTALRM, SIGWINCH, SIGXCPU, SIGXFSZ}, task::current_task, utils::{dyn_future, Async}};
 
 pub const SIG_ERR: usize = usize::MAX;
 /// when sig_handler is set to SIG_DFL
@@ -43,12 +43,21 @@ pub fn ign_sig_handler(_signo: i32) {
 /// of the process's memory at the time of termination. 
 pub fn core_sig_handler(signo: i32) {
     let task = current_task().unwrap().clone();
-    info!("[core_sig_handler]: task {} recv sig {}, terminated and coredump", task.gettid(), signo);
+    // a crash is worth a line even when info is off, with the thread name to tell the threads apart
+    warn!("[core_sig_handler]: task {} ({}) recv sig {}, terminated and coredump", task.gettid(), task.comm.lock().as_str(), signo);
 
+    // only the first fatal signal of the group dumps, WCOREDUMP is reported in the wait status
+    let core_file = if task.with_thread_group(|tg| tg.group_exiting) { None } else { open_core_file(&task, signo as usize) };
+    let core_flag = if core_file.is_some() { 0x80 } else { 0 };
+    let code = (task.exit_code() & 0xff80) | core_flag | ((signo as usize) & 0x7f);
+    // the other threads are killed before the dump, they must not run on under it
+    if task.zap_threads(code) {
+        if let Some(core_file) = core_file {
+            do_coredump(&task, signo as usize, core_file);
+        }
+    }
     // exit all the members of a thread group (process)
-    task.do_group_exit((task.exit_code() & 0xff80) | ((signo as usize) & 0x7f));
-    // task.do_group_exit(0);
-    // todo: produce a core dump file?
+    task.do_group_exit(code);
 }
 
 /// handlers for Stop
@@ -64,7 +73,8 @@ pub fn stop_sig_handler(signo: i32) {
             // the task should be wake up by SIGCONT
             t.set_wake_up_sigs(SigSet::SIGCONT);
         }
-    })
+    });
+    task.get_leader().notify_parent_with(SigInfo::CLD_STOPPED, signo);
 }
 
 /// handlers for Cont
@@ -123,6 +123,17 @@ impl SigManager {
         ret
     }
 
+    /// return the signal sets that are ignored, by default or by SIG_IGN
+    pub fn ignored_sets(&self) -> SigSet {
+        let mut ret = SigSet::empty();
+        for i in 1..self.sig_handler.len() {
+            if self.sig_handler[i].sa.sa_handler == ign_sig_handler as *const () as usize {
+                ret.add_sig(i);
+            }
+        }
+        ret
+    }
+
     /// signal manager set signal action
     pub fn set_sigaction(&mut self, signo: usize, sigaction: KSigAction) {
         if signo == SIGSTOP || signo == SIGKILL {
@@ -2,10 +2,12 @@
 #![allow(missing_docs)]
 
 mod action;
+mod coredump;
 mod handler;
 mod manager;
 
 pub use action::*;
+pub use coredump::*;
 pub use handler::*;
 pub use manager::*;
 
@@ -184,6 +186,34 @@ pub struct SigInfo {
     pub si_code: i32,
     /// pid of sender
     pub si_pid: Option<usize>,
+    /// what happened to the child for SIGCHLD
+    pub si_chld: Option<SigChld>,
+    /// the faulting address for SIGSEGV
+    pub si_addr: Option<usize>,
+    /// the file which became ready for SIGIO
+    pub si_poll: Option<SigPoll>,
+}
+
+#[derive(Clone, Copy, Debug)]
+/// the SIGCHLD part of the signal info
+pub struct SigChld {
+    /// real uid of the child
+    pub uid: u32,
+    /// exit code for CLD_EXITED, else the signal number
+    pub status: i32,
+    /// user time of the child in clock ticks
+    pub utime: i64,
+    /// system time of the child in clock ticks
+    pub stime: i64,
+}
+
+#[derive(Clone, Copy, Debug)]
+/// the SIGIO part of the signal info
+pub struct SigPoll {
+    /// the poll events of the file
+    pub band: i64,
+    /// the fd the owner enabled O_ASYNC through
+    pub fd: i32,
 }
 
 impl SigInfo {
@@ -222,14 +252,70 @@ impl SigInfo {
     /// stopped child has continued
     pub const CLD_CONTINUED: i32 = 6;
     pub const NSIGCHLD: i32 = 6;
+
+    // SIGSEGV si_codes
+    /// address not mapped to object
+    pub const SEGV_MAPERR: i32 = 1;
+    /// invalid permissions for mapped object
+    pub const SEGV_ACCERR: i32 = 2;
+
+    // SIGBUS si_codes
+    /// invalid address alignment
+    pub const BUS_ADRALN: i32 = 1;
+
+    // SIGIO si_codes
+    /// data input available
+    pub const POLL_IN: i32 = 1;
+
+    /// the signal `signo` with the code `code` and nothing else,
+    /// the other fields are set with `..SigInfo::new(signo, code)`
+    pub const fn new(signo: usize, code: i32) -> Self {
+        Self { si_signo: signo, si_code: code, si_pid: None, si_chld: None, si_addr: None, si_poll: None }
+    }
 }
 
 #[derive(Default, Copy, Clone)]
 #[repr(C)]
+/// siginfo_t of user space, the fields after si_code are those of kill and SIGCHLD
 pub struct LinuxSigInfo {
     pub si_signo: i32,
     pub si_errno: i32,
     pub si_code: i32,
-    pub _pad: [i32; 29],
-    _align: [u64; 0],
+    _pad0: i32,
+    pub si_pid: i32,
+    pub si_uid: u32,
+    pub si_status: i32,
+    _pad1: i32,
+    pub si_utime: i64,
+    pub si_stime: i64,
+    _pad: [i32; 20],
+}
+
+impl From<SigInfo> for LinuxSigInfo {
+    fn from(sig: SigInfo) -> Self {
+        let mut info = Self {
+            si_signo: sig.si_signo as _,
+            si_code: sig.si_code,
+            si_pid: sig.si_pid.unwrap_or(0) as _,
+            ..Default::default()
+        };
+        if let Some(chld) = sig.si_chld {
+            info.si_uid = chld.uid;
+            info.si_status = chld.status;
+            info.si_utime = chld.utime;
+            info.si_stime = chld.stime;
+        }
+        if let Some(addr) = sig.si_addr {
+            // si_addr shares its place with si_pid and si_uid
+            info.si_pid = addr as i32;
+            info.si_uid = (addr >> 32) as u32;
+        }
+        if let Some(poll) = sig.si_poll {
+            // si_band takes the place of si_pid and si_uid, si_fd that of si_status
+            info.si_pid = poll.band as i32;
+            info.si_uid = (poll.band >> 32) as u32;
+            info.si_status = poll.fd;
+        }
+        info
+    }
 }
\ No newline at end of file
@@ -8,3 +8,7 @@ extern crate alloc;
 pub mod mutex;
 
 pub mod lazy;
+
+pub mod wait_queue;
+
+pub use wait_queue::{WaitEntry, WaitQueue};
@@ -0,0 +1,340 @@
+//! Lock dependency tracking of the spin mutexes, built into debug builds only.
+//!
+//! Every [`SpinMutex`](super::spin_mutex::SpinMutex) belongs to the class of the place it was
+//! constructed at. Each hart keeps the stack of the locks it holds, and the first time a class
+//! is taken while another is held, the edge between the two goes into a global graph.
+//! Taking a lock whose edge would close a cycle panics before the deadlock can happen, with
+//! where every edge of the cycle was first taken. A lock nested in another of its own class
+//! records nothing, as two tasks' locks of one kind are told apart by nothing but order.
+//! The tables have a fixed size, so nothing allocates while a lock is held.
+
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    panic::Location,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering},
+};
+
+use hal::{
+    constant::{Constant, ConstantsHal},
+    instruction::{Instruction, InstructionHal},
+    println,
+    timer::{Timer, TimerHal},
+    util::sie_guard::SieGuard,
+};
+
+/// the place a lock was constructed or taken at
+pub type Site = &'static Location<'static>;
+
+/// lock construction sites told apart, those beyond are not tracked
+const MAX_CLASSES: usize = 512;
+/// edges remembered with where they were first taken, those beyond only in the graph
+const MAX_EDGES: usize = 1024;
+/// locks one hart may hold at once and be tracked
+const MAX_HELD: usize = 32;
+/// return addresses kept of the first acquisition of an edge
+const BACKTRACE_DEPTH: usize = 6;
+const WORDS: usize = MAX_CLASSES / 64;
+/// a spin this long prints who holds the lock
+pub const SLOW_SPIN_MS: usize = 100;
+
+/// the construction site of every class, by class id
+static CLASSES: [AtomicPtr<Location<'static>>; MAX_CLASSES] =
+    [const { AtomicPtr::new(ptr::null_mut()) }; MAX_CLASSES];
+
+/// bit `to` of row `from`: the class `to` was taken while `from` was held
+static DEPENDS: [[AtomicU64; WORDS]; MAX_CLASSES] =
+    [const { [const { AtomicU64::new(0) }; WORDS] }; MAX_CLASSES];
+
+/// a table ran full, reported once
+static FULL: AtomicBool = AtomicBool::new(false);
+
+/// the hart expects a cycle, see [`catch_cycle`]
+static EXPECT_CYCLE: [AtomicBool; Constant::MAX_PROCESSORS] =
+    [const { AtomicBool::new(false) }; Constant::MAX_PROCESSORS];
+/// the cycle the hart expected was found
+static CAUGHT_CYCLE: [AtomicBool; Constant::MAX_PROCESSORS] =
+    [const { AtomicBool::new(false) }; Constant::MAX_PROCESSORS];
+
+#[derive(Clone, Copy)]
+struct Edge {
+    from: u16,
+    to: u16,
+    /// where `from` was taken
+    held_at: Option<Site>,
+    /// where `to` was taken with `from` held
+    taken_at: Option<Site>,
+    backtrace: [usize; BACKTRACE_DEPTH],
+}
+
+impl Edge {
+    const EMPTY: Self = Self { from: 0, to: 0, held_at: None, taken_at: None, backtrace: [0; BACKTRACE_DEPTH] };
+}
+
+struct Graph {
+    edges: [Edge; MAX_EDGES],
+    len: usize,
+    /// scratch of the search for a cycle: the class each one was reached from
+    prev: [u16; MAX_CLASSES],
+    /// scratch of the search for a cycle: the classes left to visit
+    queue: [u16; MAX_CLASSES],
+}
+
+#[derive(Clone, Copy)]
+struct Held {
+    lock: usize,
+    class: u16,
+    site: Option<Site>,
+}
+
+struct HeldStack {
+    held: [Held; MAX_HELD],
+    len: usize,
+}
+
+/// a lock of its own, the tracker must not take the locks it tracks
+struct RawLock<T> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for RawLock<T> {}
+
+impl<T> RawLock<T> {
+    fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let _irq = SieGuard::new();
+        while self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            core::hint::spin_loop();
+        }
+        let ret = f(unsafe { &mut *self.data.get() });
+        self.locked.store(false, Ordering::Release);
+        ret
+    }
+}
+
+static GRAPH: RawLock<Graph> = RawLock {
+    locked: AtomicBool::new(false),
+    data: UnsafeCell::new(Graph {
+        edges: [Edge::EMPTY; MAX_EDGES],
+        len: 0,
+        prev: [0; MAX_CLASSES],
+        queue: [0; MAX_CLASSES],
+    }),
+};
+
+/// the locks held by each hart, only ever touched by the hart itself with interrupts off
+struct PerHart([UnsafeCell<HeldStack>; Constant::MAX_PROCESSORS]);
+
+unsafe impl Sync for PerHart {}
+
+static HELD: PerHart = PerHart(
+    [const { UnsafeCell::new(HeldStack { held: [Held { lock: 0, class: 0, site: None }; MAX_HELD], len: 0 }) };
+        Constant::MAX_PROCESSORS],
+);
+
+fn with_held<R>(f: impl FnOnce(&mut HeldStack) -> R) -> R {
+    let _irq = SieGuard::new();
+    f(unsafe { &mut *HELD.0[Instruction::get_tp()].get() })
+}
+
+fn table_full(what: &str) {
+    if !FULL.swap(true, Ordering::Relaxed) {
+        println!("[lockdep] too many {}, the tracking is partial from now on", what);
+    }
+}
+
+/// the class of the locks constructed at `site`
+fn class_of(site: Site) -> Option<u16> {
+    let key = site as *const Location<'static> as *mut Location<'static>;
+    let start = (key as usize).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 55;
+    for i in 0..MAX_CLASSES {
+        let class = (start + i) % MAX_CLASSES;
+        match CLASSES[class].compare_exchange(ptr::null_mut(), key, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => return Some(class as u16),
+            Err(cur) if cur == key => return Some(class as u16),
+            Err(_) => {}
+        }
+    }
+    table_full("lock classes");
+    None
+}
+
+/// `site` to print, if known
+fn or_unknown(site: Option<Site>) -> &'static dyn fmt::Display {
+    match site {
+        Some(site) => site,
+        None => &"?",
+    }
+}
+
+fn class_site(class: u16) -> Site {
+    unsafe { &*CLASSES[class as usize].load(Ordering::Acquire) }
+}
+
+fn has_edge(from: u16, to: u16) -> bool {
+    DEPENDS[from as usize][to as usize / 64].load(Ordering::Relaxed) & (1 << (to % 64)) != 0
+}
+
+impl Graph {
+    /// the classes on a path `from` -> .. -> `to` in the graph, into `self.queue` backwards
+    /// from `to`, with their number
+    fn find_path(&mut self, from: u16, to: u16) -> Option<usize> {
+        let mut visited = [0u64; WORDS];
+        visited[from as usize / 64] |= 1 << (from % 64);
+        self.queue[0] = from;
+        let (mut head, mut tail) = (0, 1);
+        while head < tail {
+            let class = self.queue[head];
+            head += 1;
+            if class == to {
+                // walk back to `from`, the queue before `head` is done with
+                let mut len = 0;
+                let mut cur = to;
+                loop {
+                    self.queue[len] = cur;
+                    len += 1;
+                    if cur == from {
+                        return Some(len);
+                    }
+                    cur = self.prev[cur as usize];
+                }
+            }
+            for next in 0..MAX_CLASSES as u16 {
+                if has_edge(class, next) && visited[next as usize / 64] & (1 << (next % 64)) == 0 {
+                    visited[next as usize / 64] |= 1 << (next % 64);
+                    self.prev[next as usize] = class;
+                    self.queue[tail] = next;
+                    tail += 1;
+                }
+            }
+        }
+        None
+    }
+
+    fn edge(&self, from: u16, to: u16) -> Option<&Edge> {
+        self.edges[..self.len].iter().find(|e| e.from == from && e.to == to)
+    }
+
+    /// print the cycle closed by `held` -> `class`, whose path back is in `self.queue`
+    fn report(&self, held: &Held, class: u16, site: Site, len: usize) {
+        println!(
+            "[lockdep] hart {} takes {} at {} while holding {} taken at {}",
+            Instruction::get_tp(),
+            class_site(class),
+            site,
+            class_site(held.class),
+            or_unknown(held.site),
+        );
+        println!("[lockdep] an earlier order of them, which the current one reverses:");
+        // the path runs from `class` back to the held class, the queue holds it backwards
+        for i in (1..len).rev() {
+            let (from, to) = (self.queue[i], self.queue[i - 1]);
+            println!("  {} -> {}", class_site(from), class_site(to));
+            let Some(edge) = self.edge(from, to) else {
+                continue;
+            };
+            if let (Some(held_at), Some(taken_at)) = (edge.held_at, edge.taken_at) {
+                println!("    first taken at {}, holding the other since {}", taken_at, held_at);
+            }
+            for &addr in edge.backtrace.iter().take_while(|&&addr| addr != 0) {
+                crate::lang_items::print_symbol(format_args!("   "), addr);
+            }
+        }
+    }
+}
+
+/// check that taking a lock of the class constructed at `class_site` at `site` keeps the order
+/// every lock held by the hart was taken in so far, and record the new edges.
+/// Called before spinning, so an inversion is reported instead of hanging
+pub fn check_acquire(lock: usize, class_site: Site, site: Site) {
+    let Some(class) = class_of(class_site) else {
+        return;
+    };
+    let hart = Instruction::get_tp();
+    let (stack, len) = with_held(|stack| (stack.held, stack.len));
+    for held in stack[..len].iter() {
+        if held.lock == lock || held.class == class || has_edge(held.class, class) {
+            continue;
+        }
+        let fired = GRAPH.with(|graph| {
+            if has_edge(held.class, class) {
+                return false;
+            }
+            if let Some(path) = graph.find_path(class, held.class) {
+                if EXPECT_CYCLE[hart].load(Ordering::Relaxed) {
+                    CAUGHT_CYCLE[hart].store(true, Ordering::Relaxed);
+                } else {
+                    graph.report(held, class, site, path);
+                }
+                return true;
+            }
+            DEPENDS[held.class as usize][class as usize / 64].fetch_or(1 << (class % 64), Ordering::Relaxed);
+            if graph.len == MAX_EDGES {
+                table_full("lock dependencies");
+                return false;
+            }
+            let mut edge = Edge { from: held.class, to: class, held_at: held.site, taken_at: Some(site), ..Edge::EMPTY };
+            hal::util::return_addrs(&mut edge.backtrace);
+            graph.edges[graph.len] = edge;
+            graph.len += 1;
+            false
+        });
+        if fired && !EXPECT_CYCLE[hart].load(Ordering::Relaxed) {
+            panic!("[lockdep] lock order inversion between {} and {}", class_site(held.class), class_site);
+        }
+    }
+}
+
+/// the hart took `lock` of the class constructed at `class_site`, at `site`
+pub fn acquired(lock: usize, class_site: Site, site: Site) {
+    let Some(class) = class_of(class_site) else {
+        return;
+    };
+    with_held(|stack| {
+        if stack.len == MAX_HELD {
+            table_full("locks held at once");
+            return;
+        }
+        stack.held[stack.len] = Held { lock, class, site: Some(site) };
+        stack.len += 1;
+    });
+}
+
+/// the hart let `lock` go, in whatever order the locks are let go
+pub fn released(lock: usize) {
+    with_held(|stack| {
+        if let Some(i) = stack.held[..stack.len].iter().rposition(|h| h.lock == lock) {
+            stack.held.copy_within(i + 1..stack.len, i);
+            stack.len -= 1;
+        }
+    });
+}
+
+/// run `f`, which takes locks in an order reversing an earlier one on purpose,
+/// and tell whether the tracker caught it; the cycle is neither reported nor recorded
+pub fn catch_cycle(f: impl FnOnce()) -> bool {
+    let hart = Instruction::get_tp();
+    CAUGHT_CYCLE[hart].store(false, Ordering::Relaxed);
+    EXPECT_CYCLE[hart].store(true, Ordering::Relaxed);
+    f();
+    EXPECT_CYCLE[hart].store(false, Ordering::Relaxed);
+    CAUGHT_CYCLE[hart].swap(false, Ordering::Relaxed)
+}
+
+/// the time in ms counted by the hart, free of any lock
+pub fn now_ms() -> usize {
+    Timer::read() / (Timer::get_timer_freq() / 1000)
+}
+
+/// a spin on the lock of the class constructed at `class_site` has lasted [`SLOW_SPIN_MS`]
+pub fn report_slow_spin(class_site: Site, owner: usize, held_at: Option<Site>) {
+    println!(
+        "[lockdep] hart {} spins for {} ms on {}, held by hart {} since {}",
+        Instruction::get_tp(),
+        SLOW_SPIN_MS,
+        class_site,
+        owner,
+        or_unknown(held_at),
+    );
+}
@@ -5,6 +5,9 @@ use hal::instruction::{Instruction, InstructionHal};
 use hal::util::sie_guard::SieGuard;
 /// spin_mutex
 pub mod spin_mutex;
+/// lock dependency tracking of the spin mutexes
+#[cfg(debug_assertions)]
+pub mod lockdep;
 pub mod spin_rw_mutex;
 
 /// SpinLock
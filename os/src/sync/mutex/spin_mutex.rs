@@ -7,8 +7,12 @@ use core::{
 
 use hal::{constant::{Constant, ConstantsHal}, instruction::{Instruction, InstructionHal}, println};
 
-use crate::{processor::processor::current_processor, utils::async_utils::SendWrapper};
+use crate::{processor::{ipi::poll_ipi, processor::current_processor}, utils::async_utils::SendWrapper};
 use super::MutexSupport;
+#[cfg(debug_assertions)]
+use core::{panic::Location, sync::atomic::AtomicPtr};
+#[cfg(debug_assertions)]
+use super::lockdep::{self, Site};
 
 /// A spin-lock based mutex.
 pub struct MutexGuard<'a, T: ?Sized, S: MutexSupport> {
@@ -19,6 +23,12 @@ pub struct MutexGuard<'a, T: ?Sized, S: MutexSupport> {
 /// `SpinMutex` can include different `MutexSupport` type
 pub struct SpinMutex<T: ?Sized, S: MutexSupport> {
     owner: AtomicUsize,
+    /// where the lock was constructed, its class for the lock dependency tracking
+    #[cfg(debug_assertions)]
+    class: Site,
+    /// where the holder took the lock
+    #[cfg(debug_assertions)]
+    held_at: AtomicPtr<Location<'static>>,
     _marker: PhantomData<S>,
     data: UnsafeCell<T>,
 }
@@ -32,9 +42,14 @@ unsafe impl<T: ?Sized + Send, S: MutexSupport> Send for SpinMutex<T, S> {}
 
 impl<T, S: MutexSupport> SpinMutex<T, S> {
     /// Construct a SpinMutex
+    #[cfg_attr(debug_assertions, track_caller)]
     pub const fn new(user_data: T) -> Self {
         SpinMutex {
             owner: AtomicUsize::new(usize::MAX),
+            #[cfg(debug_assertions)]
+            class: Location::caller(),
+            #[cfg(debug_assertions)]
+            held_at: AtomicPtr::new(core::ptr::null_mut()),
             _marker: PhantomData,
             data: UnsafeCell::new(user_data),
         }
@@ -45,12 +60,20 @@ impl<T, S: MutexSupport> SpinMutex<T, S> {
     fn wait_unlock(&self) {
         let mut try_count = 0usize;
         let mut cur_owner = self.owner.load(Ordering::Acquire);
+        #[cfg(debug_assertions)]
+        let mut spin_start = None;
         while cur_owner != usize::MAX {
             if cur_owner >= Constant::MAX_PROCESSORS {
                 panic!("owner {:#x} {} > MAX_PROCESSORS", &self.owner as *const _ as usize, cur_owner);
             }
             core::hint::spin_loop();
+            // the holder may be waiting for this hart to acknowledge an ipi
+            poll_ipi();
             try_count += 1;
+            #[cfg(debug_assertions)]
+            if try_count % 0x1000 == 1 {
+                self.watch_spin(&mut spin_start, cur_owner);
+            }
             if try_count == 0x1000000 {
                 panic!("Mutex: deadlock detected! {} try_count > {:#x}, {} is holding lock\n", 
                     Instruction::get_tp(),
@@ -62,10 +85,37 @@ impl<T, S: MutexSupport> SpinMutex<T, S> {
         }
     }
 
+    /// print who holds the lock once a spin on it lasted [`lockdep::SLOW_SPIN_MS`],
+    /// `start` is when the spin began, None before the first call
+    #[cfg(debug_assertions)]
+    fn watch_spin(&self, start: &mut Option<usize>, owner: usize) {
+        let now = lockdep::now_ms();
+        match *start {
+            None => *start = Some(now),
+            Some(begin) if begin != usize::MAX && now - begin >= lockdep::SLOW_SPIN_MS => {
+                let held_at = unsafe { self.held_at.load(Ordering::Acquire).as_ref() };
+                lockdep::report_slow_spin(self.class, owner, held_at);
+                // once per spin
+                *start = Some(usize::MAX);
+            }
+            _ => {}
+        }
+    }
+
+    /// record that the current hart took the lock at `site`
+    #[cfg(debug_assertions)]
+    fn note_acquired(&self, site: Site) {
+        self.held_at.store(site as *const _ as *mut _, Ordering::Release);
+        lockdep::acquired(self as *const Self as *const u8 as usize, self.class, site);
+    }
+
     /// Note that the locked data cannot step over `await`,
     /// i.e. cannot be sent between thread.
     #[inline(always)]
+    #[cfg_attr(debug_assertions, track_caller)]
     pub fn lock(&self) -> MutexGuard<T, S> {
+        #[cfg(debug_assertions)]
+        lockdep::check_acquire(self as *const Self as *const u8 as usize, self.class, Location::caller());
         let support_guard = S::before_lock();
         loop {
             let old_owner = self.owner.load(Ordering::Acquire);
@@ -80,6 +130,8 @@ impl<T, S: MutexSupport> SpinMutex<T, S> {
                 .is_ok()
             {
                 assert!(new_owner < Constant::MAX_PROCESSORS);
+                #[cfg(debug_assertions)]
+                self.note_acquired(Location::caller());
                 return MutexGuard {
                     mutex: self,
                     support_guard,
@@ -88,12 +140,37 @@ impl<T, S: MutexSupport> SpinMutex<T, S> {
         }
     }
 
+    /// Take the lock only if nobody holds it, without spinning.
+    /// Also fails if the current hart holds it already
+    #[inline(always)]
+    #[cfg_attr(debug_assertions, track_caller)]
+    pub fn try_lock(&self) -> Option<MutexGuard<T, S>> {
+        let support_guard = S::before_lock();
+        self.owner
+            .compare_exchange(usize::MAX, Instruction::get_tp(), Ordering::Release, Ordering::Relaxed)
+            .ok()?;
+        // a try never waits, so it adds no dependency
+        #[cfg(debug_assertions)]
+        self.note_acquired(Location::caller());
+        Some(MutexGuard {
+            mutex: self,
+            support_guard,
+        })
+    }
+
+    /// whether the current hart holds the lock
+    #[inline(always)]
+    pub fn held_by_current(&self) -> bool {
+        self.owner.load(Ordering::Acquire) == Instruction::get_tp()
+    }
+
     /// # Safety
     ///
     /// This is highly unsafe.
     /// You should ensure that context switch won't happen during
     /// the locked data's lifetime.
     #[inline(always)]
+    #[cfg_attr(debug_assertions, track_caller)]
     pub unsafe fn sent_lock(&self) -> impl DerefMut<Target = T> + '_ {
         SendWrapper::new(self.lock())
     }
@@ -125,6 +202,8 @@ impl<'a, T: ?Sized, S: MutexSupport> Drop for MutexGuard<'a, T, S> {
     /// from.
     #[inline(always)]
     fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        lockdep::released(self.mutex as *const SpinMutex<T, S> as *const u8 as usize);
         self.mutex.owner.store(usize::MAX, Ordering::Release);
         S::after_unlock(&mut self.support_guard);
     }
@@ -2,7 +2,7 @@ use core::{cell::UnsafeCell, marker::PhantomData, ops::{Deref, DerefMut}, sync::
 
 use hal::println;
 
-use crate::sync::mutex::MutexSupport;
+use crate::{processor::ipi::poll_ipi, sync::mutex::MutexSupport};
 
 pub struct ReadMutexGuard<'a, T: ?Sized, S: MutexSupport> {
     mutex: &'a SpinRwMutex<T, S>,
@@ -41,6 +41,7 @@ impl<T: ?Sized, S: MutexSupport> SpinRwMutex<T, S> {
         let mut try_count = 0usize;
         while self.status.load(Ordering::Acquire) & WRITER_MASK != 0 {
             core::hint::spin_loop();
+            poll_ipi();
             try_count += 1;
             if try_count == 0x1000000 {
                 panic!("RwMutex: deadlock detected! try_count > {:#x}\n", try_count);
@@ -56,6 +57,7 @@ impl<T: ?Sized, S: MutexSupport> SpinRwMutex<T, S> {
             status & WRITER_MASK != 0 || status & READER_MASK != 0
         } {
             core::hint::spin_loop();
+            poll_ipi();
             try_count += 1;
             if try_count == 0x1000000 {
                 panic!("RwMutex: deadlock detected! try_count > {:#x}\n", try_count);
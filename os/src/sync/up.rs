@@ -2,7 +2,11 @@
 
 use core::cell::{UnsafeCell, RefMut,RefCell};
 use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(debug_assertions)]
+use core::sync::atomic::AtomicUsize;
 use core::ops::{Deref, DerefMut};
+#[cfg(debug_assertions)]
+use hal::instruction::{Instruction, InstructionHal};
 use log::info;
 
 /// Wrap a static data structure inside it so that we are
@@ -12,9 +16,16 @@ use log::info;
 ///
 /// In order to get mutable reference of inner data, call
 /// `exclusive_access`.
+///
+/// Data shared across harts belongs in a lock. In debug builds the first
+/// `exclusive_access` binds the cell to its hart and an access from any other
+/// hart panics, until the owner hands the cell over with `release`.
 pub struct UPSafeCell<T> {
     /// inner data
     inner: UnsafeCell<T>,
+    /// the hart the cell is bound to, usize::MAX when unbound
+    #[cfg(debug_assertions)]
+    owner: AtomicUsize,
 }
 
 unsafe impl<T> Sync for UPSafeCell<T> {}
@@ -23,22 +34,41 @@ impl<T> UPSafeCell<T> {
     /// User is responsible to guarantee that inner struct is only used in
     /// uniprocessor.
     pub fn new(value: T) -> Self {
-        Self {
-            inner: UnsafeCell::new(value),
-        }
+        Self::const_new(value)
     }
     /// new a const UPSafeCell
     pub const fn const_new(value: T) -> Self {
         Self {
             inner: UnsafeCell::new(value),
+            #[cfg(debug_assertions)]
+            owner: AtomicUsize::new(usize::MAX),
         }
     }
-    /// Panic if the data has been borrowed.
+    /// Panic in debug builds if the cell is bound to another hart.
     pub fn exclusive_access(&self) -> &mut T {
+        #[cfg(debug_assertions)]
+        self.check_owner();
         unsafe {
             &mut *self.inner.get()
         }
     }
+    /// unbind the cell from its hart, the next `exclusive_access` may come from any hart
+    pub fn release(&self) {
+        #[cfg(debug_assertions)]
+        self.owner.store(usize::MAX, Ordering::Release);
+    }
+    #[cfg(debug_assertions)]
+    fn check_owner(&self) {
+        let hart = Instruction::get_tp();
+        match self.owner.compare_exchange(usize::MAX, hart, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => {}
+            Err(owner) if owner == hart => {}
+            Err(owner) => panic!(
+                "UPSafeCell<{}> accessed on hart {:#x} while bound to hart {:#x}",
+                core::any::type_name::<T>(), hart, owner
+            ),
+        }
+    }
     /// get the inner data
     pub fn get(&self) -> *mut T{
         self.inner.get()
@@ -0,0 +1,153 @@
+//! Wait queues, the tasks waiting for an event in the order they came.
+//!
+//! A waiter which keeps a [`WaitEntry`] is exclusive: [`WaitQueue::wake_one`] wakes only the
+//! oldest of them, and the entry leaves the queue when its future is dropped, handing on a
+//! wake it took but never used. A waiter without one, like a poll, is woken by every wake.
+//! The lock is a [`SpinNoIrqLock`], so an interrupt handler may wake a queue.
+
+use alloc::{collections::vec_deque::VecDeque, vec::Vec};
+use core::task::Waker;
+
+use super::mutex::SpinNoIrqLock;
+
+/// the place of an exclusive waiter in a [`WaitQueue`], kept by its future;
+/// the future calls [`WaitQueue::finish`] once done and [`WaitQueue::cancel`] when dropped
+#[derive(Debug, Default)]
+pub struct WaitEntry {
+    /// 0 while not queued
+    id: usize,
+}
+
+impl WaitEntry {
+    /// an entry in no queue yet
+    pub const fn new() -> Self {
+        Self { id: 0 }
+    }
+}
+
+struct Waiter {
+    /// the id of the entry, 0 for a waiter without one
+    id: usize,
+    waker: Waker,
+}
+
+struct WaitQueueInner {
+    next_id: usize,
+    waiters: VecDeque<Waiter>,
+}
+
+impl WaitQueueInner {
+    fn position(&self, id: usize) -> Option<usize> {
+        self.waiters.iter().position(|w| w.id == id)
+    }
+
+    /// take the oldest exclusive waiter and every one without an entry
+    fn take_one(&mut self) -> Vec<Waker> {
+        let mut woken = Vec::new();
+        let mut exclusive = false;
+        self.waiters.retain(|w| {
+            if w.id == 0 || !exclusive {
+                exclusive |= w.id != 0;
+                woken.push(w.waker.clone());
+                false
+            } else {
+                true
+            }
+        });
+        woken
+    }
+}
+
+/// a FIFO of the tasks waiting for one event
+pub struct WaitQueue {
+    inner: SpinNoIrqLock<WaitQueueInner>,
+}
+
+impl WaitQueue {
+    /// an empty queue
+    pub const fn new() -> Self {
+        Self {
+            inner: SpinNoIrqLock::new(WaitQueueInner { next_id: 1, waiters: VecDeque::new() }),
+        }
+    }
+
+    /// queue the waiter of `entry` woken by `waker`; one still queued only has its waker
+    /// replaced and keeps its place
+    pub fn wait(&self, entry: &mut WaitEntry, waker: &Waker) {
+        let mut inner = self.inner.lock();
+        if entry.id != 0 {
+            if let Some(i) = inner.position(entry.id) {
+                if !inner.waiters[i].waker.will_wake(waker) {
+                    inner.waiters[i].waker = waker.clone();
+                }
+                return;
+            }
+        } else {
+            entry.id = inner.next_id;
+            inner.next_id += 1;
+        }
+        inner.waiters.push_back(Waiter { id: entry.id, waker: waker.clone() });
+    }
+
+    /// queue `waker` until the next wake, for a waiter which keeps no entry,
+    /// once however often the same task registers
+    pub fn register(&self, waker: &Waker) {
+        let mut inner = self.inner.lock();
+        if !inner.waiters.iter().any(|w| w.id == 0 && w.waker.will_wake(waker)) {
+            inner.waiters.push_back(Waiter { id: 0, waker: waker.clone() });
+        }
+    }
+
+    /// wake the oldest waiter with an entry and all those without one,
+    /// return whether anyone was woken
+    pub fn wake_one(&self) -> bool {
+        let woken = self.inner.lock().take_one();
+        let any = !woken.is_empty();
+        woken.into_iter().for_each(Waker::wake);
+        any
+    }
+
+    /// wake every waiter, return how many
+    pub fn wake_all(&self) -> usize {
+        let woken = core::mem::take(&mut self.inner.lock().waiters);
+        let n = woken.len();
+        woken.into_iter().for_each(|w| w.waker.wake());
+        n
+    }
+
+    /// the waiter of `entry` got what it waited for and leaves the queue
+    pub fn finish(&self, entry: &mut WaitEntry) {
+        if entry.id == 0 {
+            return;
+        }
+        let mut inner = self.inner.lock();
+        if let Some(i) = inner.position(entry.id) {
+            inner.waiters.remove(i);
+        }
+        entry.id = 0;
+    }
+
+    /// the waiter of `entry` gives up, e.g. on a timeout or a signal: it leaves the queue,
+    /// and a wake it was given but never used goes to the next waiter
+    pub fn cancel(&self, entry: &mut WaitEntry) {
+        if entry.id == 0 {
+            return;
+        }
+        let mut inner = self.inner.lock();
+        let woken = match inner.position(entry.id) {
+            Some(i) => {
+                inner.waiters.remove(i);
+                Vec::new()
+            }
+            None => inner.take_one(),
+        };
+        drop(inner);
+        entry.id = 0;
+        woken.into_iter().for_each(Waker::wake);
+    }
+
+    /// whether nobody waits
+    pub fn is_empty(&self) -> bool {
+        self.inner.lock().waiters.is_empty()
+    }
+}
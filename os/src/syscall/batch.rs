@@ -0,0 +1,157 @@
+//! batched submission of file operations
+//! a light io_uring: one syscall runs a whole array of operations one after another,
+//! saving the trap and the user pointer checks of every single operation
+
+use alloc::vec::Vec;
+
+use crate::{mm::{ReadMark, UserPtrRaw, UserSlice, UserSliceRaw, WriteMark}, task::current_task};
+
+use super::{sys_close, sys_fsync, sys_openat, SysError, SysResult};
+
+/// the most operations one call may submit
+pub const IO_BATCH_MAX: usize = 256;
+
+/// pread: read `len` bytes of `fd` at `offset` into `buf`
+pub const IO_BATCH_PREAD: u32 = 0;
+/// pwrite: write `len` bytes of `buf` into `fd` at `offset`
+pub const IO_BATCH_PWRITE: u32 = 1;
+/// fsync `fd`
+pub const IO_BATCH_FSYNC: u32 = 2;
+/// openat: open the path at `buf` relative to the dirfd `fd`, with `flags` and `mode`
+pub const IO_BATCH_OPENAT: u32 = 3;
+/// close `fd`
+pub const IO_BATCH_CLOSE: u32 = 4;
+
+/// an operation descriptor of io_submit_batch
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct IoBatchOp {
+    /// one of IO_BATCH_*
+    pub opcode: u32,
+    /// the fd to work on, the dirfd for openat
+    pub fd: i32,
+    /// open flags for openat
+    pub flags: u32,
+    /// file mode for openat
+    pub mode: u32,
+    /// the data buffer, the path for openat
+    pub buf: usize,
+    /// length of the data buffer
+    pub len: usize,
+    /// file offset for pread and pwrite
+    pub offset: usize,
+    /// copied into the result untouched
+    pub user_data: u64,
+}
+
+/// the result of an operation, in the same slot as its descriptor
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct IoBatchResult {
+    /// user_data of the descriptor
+    pub user_data: u64,
+    /// what the single syscall would have returned, a negative errno on failure
+    pub res: i64,
+}
+
+/// the user buffer of an operation, checked before anything runs
+enum OpBuf {
+    Read(UserSlice<u8, WriteMark>),
+    Write(UserSlice<u8, ReadMark>),
+    None,
+}
+
+/// syscall: io_submit_batch
+/// runs the `count` (at most 256) operations at `ops` in order,
+/// and writes the result of each into the same slot of `results`.
+/// A failed operation does not stop the batch, but the buffers of all operations are checked first
+/// and the batch stops at the first one that is bad, whose result is EFAULT.
+/// Returns the number of results written
+pub async fn sys_io_submit_batch(ops: usize, count: usize, results: usize) -> SysResult {
+    if count > IO_BATCH_MAX {
+        return Err(SysError::EINVAL);
+    }
+    if count == 0 {
+        return Ok(0);
+    }
+    let task = current_task().unwrap().clone();
+    let (ops, user_results) = {
+        let mut vm = task.get_vm_space().lock();
+        let ops: Vec<IoBatchOp> = UserSliceRaw::new(ops as *const IoBatchOp, count)
+            .ensure_read(&mut vm)
+            .ok_or(SysError::EFAULT)?
+            .to_ref()
+            .to_vec();
+        let user_results = UserSliceRaw::new(results as *mut IoBatchResult, count)
+            .ensure_write(&mut vm)
+            .ok_or(SysError::EFAULT)?;
+        (ops, user_results)
+    };
+
+    let mut bufs = Vec::with_capacity(count);
+    {
+        let mut vm = task.get_vm_space().lock();
+        for op in ops.iter() {
+            let buf = match op.opcode {
+                IO_BATCH_PREAD => UserSliceRaw::new(op.buf as *mut u8, op.len)
+                    .ensure_write(&mut vm)
+                    .map(OpBuf::Read),
+                IO_BATCH_PWRITE => UserSliceRaw::new(op.buf as *const u8, op.len)
+                    .ensure_read(&mut vm)
+                    .map(OpBuf::Write),
+                IO_BATCH_OPENAT => UserPtrRaw::new(op.buf as *const u8)
+                    .cstr_slice(&mut vm)
+                    .map(|_| OpBuf::None),
+                _ => Some(OpBuf::None),
+            };
+            match buf {
+                Some(buf) => bufs.push(buf),
+                None => break,
+            }
+        }
+    }
+
+    let results = user_results.to_mut();
+    for (i, (op, buf)) in ops.iter().zip(bufs.iter()).enumerate() {
+        let ret = run_op(op, buf).await;
+        results[i] = IoBatchResult {
+            user_data: op.user_data,
+            res: ret.map_or_else(|e| -(e.code() as i64), |r| r as i64),
+        };
+    }
+    let done = bufs.len();
+    if done < count {
+        log::info!("[sys_io_submit_batch] bad buffer in op {}, batch stopped", done);
+        results[done] = IoBatchResult {
+            user_data: ops[done].user_data,
+            res: -(SysError::EFAULT.code() as i64),
+        };
+        return Ok(done as isize + 1);
+    }
+    Ok(done as isize)
+}
+
+/// run one operation the way its own syscall would
+async fn run_op(op: &IoBatchOp, buf: &OpBuf) -> SysResult {
+    let task = current_task().unwrap().clone();
+    match (op.opcode, buf) {
+        (IO_BATCH_PREAD, OpBuf::Read(buf)) => {
+            let file = task.with_fd_table(|t| t.get_file(op.fd as usize))?;
+            let ret = file.read_at(op.offset, buf.to_mut()).await?;
+            file.file_inner().accessed();
+            Ok(ret as isize)
+        }
+        (IO_BATCH_PWRITE, OpBuf::Write(buf)) => {
+            let file = task.with_fd_table(|t| t.get_file(op.fd as usize))?;
+            let ret = file.write_at(op.offset, buf.to_ref()).await?;
+            if ret > 0 {
+                file.file_inner().modified();
+            }
+            Ok(ret as isize)
+        }
+        (IO_BATCH_FSYNC, _) => sys_fsync(op.fd as usize),
+        (IO_BATCH_OPENAT, _) => sys_openat(op.fd as isize, op.buf as *const u8, op.flags, op.mode),
+        (IO_BATCH_CLOSE, _) => sys_close(op.fd as usize),
+        _ => Err(SysError::EINVAL),
+    }
+}
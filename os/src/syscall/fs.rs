@@ -1,19 +1,21 @@
 //! File and filesystem-related syscalls
-use core::{any::Any, ops::DerefMut, ptr::copy_nonoverlapping};
+use core::{any::Any, ops::DerefMut, ptr::copy_nonoverlapping, sync::atomic::Ordering};
 
-use alloc::{string::ToString, sync::Arc, vec};
+use alloc::{string::ToString, sync::Arc, vec, vec::Vec};
 use hal::{addr::{PhysAddrHal, PhysPageNumHal, VirtAddr, VirtAddrHal}, constant::{Constant, ConstantsHal}, instruction::{Instruction, InstructionHal}, pagetable::PageTableHal, println};
 use log::{info, warn};
 use strum::FromRepr;
 use virtio_drivers::PAGE_SIZE;
-use crate::{config::BLOCK_SIZE, drivers::BLOCK_DEVICE, fs::{
-    get_filesystem, pipefs::make_pipe, vfs::{dentry::{self, global_find_dentry, global_update_dentry}, file::{open_file, SeekFrom}, fstype::MountFlags, inode::InodeMode, Dentry, DentryState, File}, AtFlags, Kstat, OpenFlags, RenameFlags, StatFs, UtsName, Xstat, XstatMask
-}, mm::{translate_uva_checked, vm::{PageFaultAccessType, UserVmSpaceHal}, UserPtrRaw, UserSliceRaw}, processor::context::SumGuard, task::{fs::{FdFlags, FdInfo}, task::TaskControlBlock}, timer::{ffi::TimeSpec, get_current_time_duration}, utils::block_on};
+use crate::{config::BLOCK_SIZE, fs::{
+    get_filesystem, pipefs::{make_pipe, PipeFile}, vfs::{dentry::{self, global_find_dentry, global_update_dentry}, file::{open_file, SeekFrom, IO_CHUNK}, ioctl, fstype::MountFlags, inode::{sync_inode_meta, InodeMode, SealFlags}, Dentry, DentryState, DirFile, File, InodeInner, PathFile, DCACHE}, AtFlags, Kstat, OpenFlags, RenameFlags, StatFs, UtsName, Xstat, XstatMask, DOMAINNAME, HOSTNAME, HOST_NAME_MAX
+}, mm::{translate_uva_checked, vm::{PageFaultAccessType, UserVmSpaceHal}, UserPtrRaw, UserSliceRaw}, processor::context::SumGuard, task::{cred::{MAY_EXEC, MAY_READ, MAY_WRITE}, fs::FdFlags, manager::TASK_MANAGER, task::TaskControlBlock}, timer::{ffi::TimeSpec, get_realtime_duration}, utils::{block_on, klog::{klog_clear, klog_len, klog_read_all, KLOG_SIZE}}};
 use crate::utils::{
     path::*,
     string::*,
 };
 use super::{SysResult,SysError};
+use crate::sysctl::StrParam;
+use crate::task::schedule::cond_resched;
 use crate::processor::processor::{current_processor,current_task,current_user_token};
 
 /// syscall: write
@@ -24,7 +26,7 @@ pub async fn sys_write(fd: usize, buf: usize, len: usize) -> SysResult {
     let user_buf = 
         UserSliceRaw::new(buf as *mut u8, len)
             .ensure_read(&mut task.get_vm_space().lock())
-            .ok_or(SysError::EINVAL)?;
+            .ok_or(SysError::EFAULT)?;
     let buf = user_buf.to_ref();
     let ret = file.write(buf).await?;
 
@@ -54,7 +56,7 @@ pub async fn sys_read(fd: usize, buf: usize, len: usize) -> SysResult {
     let user_buf = 
         UserSliceRaw::new(buf as *mut u8, len)
             .ensure_write(&mut task.get_vm_space().lock())
-            .ok_or(SysError::EINVAL)?;
+            .ok_or(SysError::EFAULT)?;
     let buf = user_buf.to_mut();
     let ret = file.read(buf).await?;
 
@@ -86,7 +88,12 @@ pub fn sys_close(fd: usize) -> SysResult {
     }
     log::info!("[sys_close]: close on fd: {}", fd);
     let task = current_task().unwrap();
+    let file = task.with_fd_table(|t| t.get_file(fd)).ok();
     task.with_mut_fd_table(|table| table.remove(fd))?;
+    // the fd is gone even when the times can not be written back
+    if let Some(inode) = file.and_then(|f| f.inode()) {
+        sync_inode_meta(&inode)?;
+    }
     Ok(0)
 }
 
@@ -118,32 +125,47 @@ pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> SysResult {
 /// The getcwd() function copies an absolute pathname of 
 /// the current working directory to the array pointed to by buf, 
 /// which is of length size.
-/// On success, these functions return a pointer to 
-/// a string containing the pathname of the current working directory. 
-/// In the case getcwd() and getwd() this is the same value as buf.
-/// On failure, these functions return NULL, 
-/// and errno is set to indicate the error. 
+/// The raw syscall returns the length of the path placed in buf including
+/// its terminating nul, the C wrappers turn that into buf themselves.
+/// A cwd removed by rmdir fails with ENOENT, one renamed has its new path.
 /// The contents of the array pointed to by buf are undefined on error.
 pub fn sys_getcwd(buf: usize, len: usize) -> SysResult {
+    if buf == 0 {
+        return Err(SysError::EFAULT);
+    }
+    if len == 0 {
+        return Err(SysError::EINVAL);
+    }
     let task = current_task().unwrap();
-    task.with_cwd(|cwd| {
-        let path = cwd.path();
-        if len < path.len() + 1 {
-            info!("[sys_getcwd]: buf len too small to recv path");
-            return Err(SysError::ERANGE);
-        } else {
-            //info!("copying path: {}, len: {}", path, path.len());
-            let new_buf = UserSliceRaw::new(buf as *mut u8, len)
-                .ensure_write(&mut task.get_vm_space().lock())
-                .ok_or(SysError::EINVAL)?;
-            new_buf.to_mut()[path.len()..].fill(0 as u8);
-            new_buf.to_mut()[..path.len()].copy_from_slice(path.as_bytes());
-            return Ok(buf as isize);
+    // a renamed cwd went on under the new name
+    let cwd = task.cwd().current();
+    let mut dentry = Some(cwd.clone());
+    while let Some(d) = dentry {
+        if d.is_negative() {
+            // the cwd or a directory above it has been removed
+            info!("[sys_getcwd]: cwd {} has been removed", cwd.name());
+            return Err(SysError::ENOENT);
         }
-    })
+        dentry = d.parent();
+    }
+    let path = cwd.path();
+    // the path and its nul
+    let size = path.len() + 1;
+    if len < size {
+        info!("[sys_getcwd]: buf len too small to recv path");
+        return Err(SysError::ERANGE);
+    }
+    let new_buf = UserSliceRaw::new(buf as *mut u8, size)
+        .ensure_write(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?;
+    let out = new_buf.to_mut();
+    out[..path.len()].copy_from_slice(path.as_bytes());
+    out[path.len()] = 0;
+    Ok(size as isize)
 }
 
 /// syscall: dup
+/// the new fd is the lowest free one, sharing the open file of old_fd but not its close-on-exec flag
 pub fn sys_dup(old_fd: usize) -> SysResult {
     log::debug!("dup old fd: {}", old_fd);
     let task = current_task().unwrap();
@@ -152,10 +174,15 @@ pub fn sys_dup(old_fd: usize) -> SysResult {
 }
 
 /// syscall: dup3
+/// like dup, but the new fd is `new_fd`, closing what it referred to
 pub fn sys_dup3(old_fd: usize, new_fd: usize, flags: u32) -> SysResult {
     log::debug!("dup3: old_fd = {}, new_fd = {}", old_fd, new_fd);
     let task = current_task().unwrap();
-    let flags = OpenFlags::from_bits(flags as i32).ok_or(SysError::EINVAL)?;
+    // O_CLOEXEC is the only flag dup3 takes
+    let flags = OpenFlags::from_bits(flags as i32)
+        .filter(|f| (*f - OpenFlags::O_CLOEXEC).is_empty())
+        .ok_or(SysError::EINVAL)?;
+    // unlike dup2, dup3 onto the same fd is an error
     if old_fd == new_fd {
         return Err(SysError::EINVAL);
     }
@@ -171,9 +198,18 @@ pub fn sys_dup3(old_fd: usize, new_fd: usize, flags: u32) -> SysResult {
 /// If pathname is relative and dirfd is the special value AT_FDCWD, 
 /// then pathname is interpreted relative to the current working directory of the calling process (like open(2)).
 /// If pathname is absolute, then dirfd is ignored.
-pub fn sys_openat(dirfd: isize, pathname: *const u8, flags: u32, _mode: u32) -> SysResult {
-    let open_flags = OpenFlags::from_bits(flags as i32).unwrap();
-    let at_flags = AtFlags::from_bits_truncate(flags as i32);
+pub fn sys_openat(dirfd: isize, pathname: *const u8, flags: u32, mode: u32) -> SysResult {
+    let open_flags = OpenFlags::from_bits_truncate(flags as i32);
+    // the open flags share no bits with the at flags, O_NOFOLLOW is the only one to pass on
+    let at_flags = if open_flags.contains(OpenFlags::O_NOFOLLOW) {
+        AtFlags::AT_SYMLINK_NOFOLLOW
+    } else {
+        AtFlags::empty()
+    };
+    // creating a directory is mkdir's job
+    if open_flags.contains(OpenFlags::O_DIRECTORY | OpenFlags::O_CREAT) {
+        return Err(SysError::EINVAL);
+    }
     let task = current_task().unwrap().clone();
     let opt_path = user_path_to_string(
             UserPtrRaw::new(pathname), 
@@ -182,43 +218,126 @@ pub fn sys_openat(dirfd: isize, pathname: *const u8, flags: u32, _mode: u32) ->
     if let Some(path) = opt_path {
         // log::info!("task {} trying to open {}, oflags: {:?}, atflags: {:?}", task.tid(), path, open_flags, at_flags);
         let dentry = at_helper(task.clone(), dirfd, pathname, at_flags)?;
+        // fail before creating or opening anything, so nothing is left behind
+        task.ensure_can_open(1)?;
+        if open_flags.contains(OpenFlags::O_PATH) {
+            return open_path(&task, dentry, open_flags);
+        }
+        let mut mask = 0;
+        if open_flags.readable() {
+            mask |= MAY_READ;
+        }
+        if open_flags.writable() || open_flags.contains(OpenFlags::O_TRUNC) {
+            mask |= MAY_WRITE;
+        }
+        // the creator may open the new file however it likes, whatever mode it asked for
+        let mut checked = false;
         if open_flags.contains(OpenFlags::O_CREAT) {
-            // the dir may not exist
-            if abs_path_to_name(&path).unwrap() != abs_path_to_name(&dentry.path()).unwrap() {
-                return Err(SysError::ENOENT);
-            }
             // inode not exist, create it as a regular file
-            if open_flags.contains(OpenFlags::O_EXCL) && dentry.state() != DentryState::NEGATIVE {
+            let existed = dentry.state() != DentryState::NEGATIVE;
+            if open_flags.contains(OpenFlags::O_EXCL) && existed {
                 return Err(SysError::EEXIST);
             }
-            let parent = dentry.parent().expect("[sys_openat]: can not open root as file!");
-            let name = abs_path_to_name(&path).unwrap();
-            let new_inode = parent.inode().unwrap().create(&name, InodeMode::FILE).unwrap();
-            dentry.set_inode(new_inode);
-            // we shall not add child to parent until child is valid!
-            parent.add_child(dentry.clone());
+            // a trailing slash names a directory, which O_CREAT cannot make
+            if path.ends_with('/') {
+                return Err(SysError::EISDIR);
+            }
+            // the walk only leaves the last component missing,
+            // which may be the target of a dangling symlink
+            let parent = dentry.parent().ok_or(SysError::EISDIR)?;
+            let name = dentry.name().to_string();
+            let parent_inode = parent.inode().unwrap();
+            if existed {
+                if dentry.inode().unwrap().inode_inner().mode().get_type() == InodeMode::DIR {
+                    return Err(SysError::EISDIR);
+                }
+                // keep the inode, and its page cache, of an existing file
+                task.check_access(dentry.inode().unwrap().inode_inner(), mask)?;
+            } else {
+                task.check_access(parent_inode.inode_inner(), MAY_WRITE | MAY_EXEC)?;
+                let new_inode = parent_inode.create(&name, InodeMode::FILE).unwrap();
+                task.init_new_owner(parent_inode.inode_inner(), new_inode.inode_inner(), mode);
+                new_inode.inode_inner().init_times();
+                parent_inode.inode_inner().touch_mtime();
+                dentry.set_inode(new_inode);
+                // we shall not add child to parent until child is valid!
+                parent.add_child(dentry.clone());
+            }
+            checked = true;
         }
         if dentry.state() == DentryState::NEGATIVE {
             log::warn!("cannot open {}, not exist", path);
             return Err(SysError::ENOENT);
         }
         let inode = dentry.inode().unwrap();
-        if open_flags.contains(OpenFlags::O_DIRECTORY) && inode.inode_inner().mode.get_type() != InodeMode::DIR {
+        // only O_PATH opens a symlink itself
+        if inode.inode_inner().mode().get_type() == InodeMode::LINK {
+            return Err(SysError::ELOOP);
+        }
+        let is_dir = inode.inode_inner().mode().get_type() == InodeMode::DIR;
+        if open_flags.contains(OpenFlags::O_DIRECTORY) && !is_dir {
             return Err(SysError::ENOTDIR);
         }
-        let file = dentry.open(open_flags).unwrap();
+        // a directory is only opened for reading, O_TMPFILE names the directory to create in
+        let tmpfile = open_flags.contains(OpenFlags::O_TMPFILE);
+        if is_dir && open_flags.writable() && !tmpfile {
+            return Err(SysError::EISDIR);
+        }
+        if !checked {
+            task.check_access(inode.inode_inner(), mask)?;
+        }
+        // the devices of a nodev mount cannot be opened
+        if matches!(inode.inode_inner().mode().get_type(), InodeMode::CHAR | InodeMode::BLOCK)
+            && inode.inode_inner().mount_flags().contains(MountFlags::MS_NODEV) {
+            return Err(SysError::EACCES);
+        }
+        // linux truncates with O_RDONLY as well
+        if open_flags.contains(OpenFlags::O_TRUNC) {
+            let ty = inode.inode_inner().mode().get_type();
+            if ty == InodeMode::DIR {
+                return Err(SysError::EISDIR);
+            }
+            // devices, fifos and sockets ignore it
+            if ty == InodeMode::FILE {
+                inode.inode_inner().check_writable()?;
+                inode.truncate(0)?;
+                inode.inode_inner().touch_mtime();
+            }
+        }
+        let file = if is_dir && !tmpfile {
+            Arc::new(DirFile::new(dentry)) as Arc<dyn File>
+        } else {
+            // a device with nothing behind it for the caller, like /dev/tty without a controlling terminal
+            dentry.open(open_flags).ok_or(SysError::ENXIO)?
+        };
         file.set_flags(open_flags);
-        let fd = task.with_mut_fd_table(|table| table.alloc_fd())?;
-        let fd_info = FdInfo { file, flags: open_flags.into() };
-        task.with_mut_fd_table(|t|t.put_file(fd, fd_info))?;
+        let fd = task.with_mut_fd_table(|table| table.install(file, open_flags.into()))?;
         log::info!("return fd {fd}");
         return Ok(fd as isize)
+    } else if pathname.is_null() {
+        return Err(SysError::EFAULT);
     } else {
         log::info!("[sys_openat]: pathname is empty!");
         return Err(SysError::ENOENT);
     }
 }
 
+/// open the file `dentry` names with O_PATH, a symlink itself with O_NOFOLLOW.
+/// No permission on the file is needed and nothing is opened behind the fd,
+/// the flags but O_CLOEXEC, O_DIRECTORY and O_NOFOLLOW are ignored
+fn open_path(task: &Arc<TaskControlBlock>, dentry: Arc<dyn Dentry>, open_flags: OpenFlags) -> SysResult {
+    let inode = match dentry.inode() {
+        Some(inode) if dentry.state() != DentryState::NEGATIVE => inode,
+        _ => return Err(SysError::ENOENT),
+    };
+    if open_flags.contains(OpenFlags::O_DIRECTORY) && inode.inode_inner().mode().get_type() != InodeMode::DIR {
+        return Err(SysError::ENOTDIR);
+    }
+    let file: Arc<dyn File> = Arc::new(PathFile::new(dentry));
+    let fd = task.with_mut_fd_table(|table| table.install_path(file, open_flags.into()))?;
+    Ok(fd as isize)
+}
+
 /// syscall: mkdirat
 /// If the pathname given in pathname is relative, 
 /// then it is interpreted relative to the directory referred to by the file descriptor dirfd 
@@ -227,7 +346,7 @@ pub fn sys_openat(dirfd: isize, pathname: *const u8, flags: u32, _mode: u32) ->
 /// If pathname is relative and dirfd is the special value AT_FDCWD, 
 /// then pathname is interpreted relative to the current working directory of the calling process (like mkdir(2)).
 /// If pathname is absolute, then dirfd is ignored.
-pub fn sys_mkdirat(dirfd: isize, pathname: *const u8, _mode: usize) -> SysResult {
+pub fn sys_mkdirat(dirfd: isize, pathname: *const u8, mode: usize) -> SysResult {
     let task = current_task().unwrap();
     let opt_path = user_path_to_string(
             UserPtrRaw::new(pathname), 
@@ -240,10 +359,19 @@ pub fn sys_mkdirat(dirfd: isize, pathname: *const u8, _mode: usize) -> SysResult
             return Err(SysError::EEXIST);
         }
         let parent = dentry.parent().unwrap();
-        let name = abs_path_to_name(&path).unwrap();
-        let new_inode = parent.inode().unwrap().create(&name, InodeMode::DIR).unwrap();
+        let name = dentry.name().to_string();
+        let parent_inode = parent.inode().unwrap();
+        task.check_access(parent_inode.inode_inner(), MAY_WRITE | MAY_EXEC)?;
+        let new_inode = parent_inode.create(&name, InodeMode::DIR).ok_or(SysError::EIO)?;
+        // mkdir takes no set-id bits, only the parent may give set-group-id
+        task.init_new_owner(parent_inode.inode_inner(), new_inode.inode_inner(), mode as u32 & 0o1777);
+        new_inode.inode_inner().init_times();
+        parent_inode.inode_inner().touch_mtime();
         dentry.set_inode(new_inode);
         dentry.set_state(DentryState::USED);
+        parent.add_child(dentry.clone());
+    } else if pathname.is_null() {
+        return Err(SysError::EFAULT);
     } else {
         warn!("[sys_mkdirat]: pathname is empty!");
         return Err(SysError::ENOENT);
@@ -258,14 +386,25 @@ pub fn sys_fstatat(dirfd: isize, pathname: *const u8, stat_buf: usize, flags: i3
     let o_flags = OpenFlags::from_bits_truncate(flags);
 
     let task = current_task().unwrap().clone();
-    let dentry = at_helper(task.clone(), dirfd, pathname, at_flags)?;
-    log::debug!("fstatat dirfd {}, path {}, at_flags {:?}, oflags {:?}", dirfd, dentry.path(), at_flags, o_flags);
-    let inode = dentry.inode();
-    if inode.is_none() {
-        log::warn!("no inode");
-        return Err(SysError::ENOENT)
-    }
-    let stat = inode.unwrap().getattr();
+    // a path which can not be read is EFAULT, only "" is empty
+    let empty_path = UserPtrRaw::new(pathname)
+        .cstr_slice(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?
+        .to_ref()
+        .is_empty();
+    let stat = if empty_path && at_flags.contains(AtFlags::AT_EMPTY_PATH) && dirfd as i32 != AtFlags::AT_FDCWD.bits() {
+        // the open file itself, as fstat
+        task.with_fd_table(|t| t.get_path_file(dirfd as usize))?.stat()?
+    } else {
+        let dentry = at_helper(task.clone(), dirfd, pathname, at_flags)?;
+        log::debug!("fstatat dirfd {}, path {}, at_flags {:?}, oflags {:?}", dirfd, dentry.path(), at_flags, o_flags);
+        let inode = dentry.inode();
+        if inode.is_none() {
+            log::warn!("no inode");
+            return Err(SysError::ENOENT)
+        }
+        inode.unwrap().getattr()
+    };
     let stat_ptr = UserPtrRaw::new(stat_buf as *const Kstat)
         .ensure_write(&mut task.get_vm_space().lock())
         .ok_or(SysError::EFAULT)?;
@@ -279,28 +418,38 @@ pub fn sys_fstatat(dirfd: isize, pathname: *const u8, stat_buf: usize, flags: i3
 /// is set to indicate the error.
 pub fn sys_chdir(path: *const u8) -> SysResult {
     let task = current_task().unwrap().clone();
-    let path = user_path_to_string(
-            UserPtrRaw::new(path), 
-            &mut task.get_vm_space().lock()
-        ).ok_or(SysError::EINVAL)?;
-    info!("try to switch to path {}", path);
-    let old_dentry = task.cwd();
-    let new_dentry = if path.starts_with("/") {
-        global_find_dentry(&path)?
-    } else {
-        old_dentry.find(&path)?.ok_or(SysError::ENOENT)?
-    };
+    if path.is_null() {
+        return Err(SysError::EFAULT);
+    }
+    // follow the symlinks, so that the cwd is the resolved physical directory
+    let new_dentry = at_helper(task.clone(), AtFlags::AT_FDCWD.bits() as isize, path, AtFlags::empty())?;
+    info!("try to switch to path {}", new_dentry.path());
+    change_cwd(&task, new_dentry)
+}
+
+/// syscall: fchdir
+/// change the current working directory to the directory referred to by fd
+pub fn sys_fchdir(fd: usize) -> SysResult {
+    let task = current_task().unwrap().clone();
+    let file = task.with_fd_table(|t| t.get_path_file(fd))?;
+    let dentry = file.dentry().ok_or(SysError::ENOTDIR)?;
+    change_cwd(&task, dentry)
+}
+
+/// common part of chdir and fchdir
+fn change_cwd(task: &Arc<TaskControlBlock>, new_dentry: Arc<dyn Dentry>) -> SysResult {
     if new_dentry.state() == DentryState::NEGATIVE {
-        log::warn!("[sys_chdir]: dentry not found");
+        log::warn!("[change_cwd]: dentry not found");
         return Err(SysError::ENOENT);
-    } else if !new_dentry.inode().unwrap().inode_inner().mode.contains(InodeMode::DIR) {
-        log::warn!("[sys_chdir]: path is not dir");
+    }
+    let mode = new_dentry.inode().unwrap().inode_inner().mode();
+    if !mode.contains(InodeMode::DIR) {
+        log::warn!("[change_cwd]: path is not dir");
         return Err(SysError::ENOTDIR);
-    } else {
-        let task = current_task().unwrap().clone();
-        task.set_cwd(new_dentry);
-        return Ok(0);
     }
+    task.check_access(new_dentry.inode().unwrap().inode_inner(), MAY_EXEC)?;
+    task.set_cwd(new_dentry);
+    Ok(0)
 }
 
 
@@ -317,11 +466,15 @@ const PIPE_BUF_LEN: usize = 16 * PAGE_SIZE;
 pub fn sys_pipe2(pipe: *mut i32, flags: u32) -> SysResult {
     let task = current_task().unwrap().clone();
     let flags = OpenFlags::from_bits(flags as i32).unwrap();
+    task.ensure_can_open(2)?;
     let (read_file, write_file) = make_pipe(PIPE_BUF_LEN);
-    let read_fd = task.with_mut_fd_table(|t|t.alloc_fd())?;
-    task.with_mut_fd_table(|t| t.put_file(read_fd, FdInfo { file: read_file, flags: flags.into() }))?;
-    let write_fd = task.with_mut_fd_table(|t|t.alloc_fd())?;
-    task.with_mut_fd_table(|t| t.put_file(write_fd, FdInfo { file: write_file, flags: flags.into() }))?;
+    // both fds or neither
+    let (read_fd, write_fd) = task.with_mut_fd_table(|t| {
+        t.ensure_free_fds(2)?;
+        let read_fd = t.install(read_file, flags.into())?;
+        let write_fd = t.install(write_file, flags.into())?;
+        Ok::<_, SysError>((read_fd, write_fd))
+    })?;
 
     let _sum = SumGuard::new();
     let pipefd = unsafe { core::slice::from_raw_parts_mut(pipe, 2 * core::mem::size_of::<i32>()) };
@@ -335,8 +488,9 @@ pub fn sys_pipe2(pipe: *mut i32, flags: u32) -> SysResult {
 pub fn sys_fstat(fd: usize, stat_buf: usize) -> SysResult {
     let _sum_guard = SumGuard::new();
     let task = current_task().unwrap().clone();
-    let file = task.with_fd_table(|t| t.get_file(fd))?;
-    let stat = file.inode().unwrap().getattr();
+    let file = task.with_fd_table(|t| t.get_path_file(fd))?;
+    // pipes, sockets and the console answer without a path
+    let stat = file.stat()?;
     log::debug!("[sys_fstat]: fstat file {}, size {}", fd, stat.st_size);
     let stat_ptr = stat_buf as *mut Kstat;
     unsafe {
@@ -347,8 +501,17 @@ pub fn sys_fstat(fd: usize, stat_buf: usize) -> SysResult {
 }
 
 /// syscall statfs
-/// TODO
-pub fn sys_statfs(_path: usize, buf: usize) -> SysResult {
+/// the sizes are made up, f_flags has the flags of the mount
+pub fn sys_statfs(path: *const u8, buf: usize) -> SysResult {
+    // ST_RDONLY, ST_NOSUID, ST_NODEV, ST_NOEXEC and ST_NOATIME share the bits of the MS_ flags
+    let st_flags = MountFlags::MS_RDONLY | MountFlags::MS_NOSUID | MountFlags::MS_NODEV
+        | MountFlags::MS_NOEXEC | MountFlags::MS_NOATIME;
+    let task = current_task().unwrap().clone();
+    let dentry = at_helper(task, AtFlags::AT_FDCWD.bits() as isize, path, AtFlags::empty())?;
+    if dentry.is_negative() {
+        return Err(SysError::ENOENT);
+    }
+    let flags = dentry.inode().unwrap().inode_inner().mount_flags() & st_flags;
     let info = StatFs {
         f_type: 0x2011BAB0 as i64,
         f_bsize: BLOCK_SIZE as i64,
@@ -360,7 +523,7 @@ pub fn sys_statfs(_path: usize, buf: usize) -> SysResult {
         f_fsid: [0; 2],
         f_namelen: 1 << 8,
         f_frsize: 1 << 9,
-        f_flags: 1 << 1 as i64,
+        f_flags: flags.bits() as isize,
         f_spare: [0; 4],
     };
     unsafe {
@@ -372,24 +535,43 @@ pub fn sys_statfs(_path: usize, buf: usize) -> SysResult {
 
 /// syscall statx
 pub fn sys_statx(dirfd: isize, pathname: *const u8, flags: i32, mask: u32, statx_buf: VirtAddr) -> SysResult {
-    let _sum_guard = SumGuard::new();
-    let open_flags = OpenFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+    /// reserved for a future struct statx expansion
+    const STATX_RESERVED: u32 = 0x8000_0000;
+    let valid_flags = AtFlags::AT_SYMLINK_NOFOLLOW | AtFlags::AT_NO_AUTOMOUNT
+        | AtFlags::AT_EMPTY_PATH | AtFlags::AT_STATX_SYNC_TYPE;
     let at_flags = AtFlags::from_bits_truncate(flags);
+    if flags & !valid_flags.bits() != 0
+        || at_flags.contains(AtFlags::AT_STATX_SYNC_TYPE)
+        || mask & STATX_RESERVED != 0 {
+        return Err(SysError::EINVAL);
+    }
     let mask = XstatMask::from_bits_truncate(mask);
     let task = current_task().unwrap().clone();
 
-    log::debug!("[sys_statx]: statx dirfd: {}, path: {:?}, at_flags {:?}, open_flags: {:?}", dirfd, pathname, at_flags, open_flags);
-
-    let dentry = at_helper(task.clone(), dirfd, pathname, at_flags)?;
-    if dentry.state() == DentryState::NEGATIVE && dentry.inode().is_none() {
-        return Err(SysError::ENOENT);
-    }
-    let inode = dentry.inode().unwrap();
-    let statx_ptr = statx_buf.0 as *mut Xstat;
-    let statx = inode.getxattr(mask);
-    unsafe {
-        statx_ptr.write(statx);
-    }
+    log::debug!("[sys_statx]: statx dirfd: {}, path: {:?}, at_flags {:?}, mask: {:?}", dirfd, pathname, at_flags, mask);
+
+    // AT_NO_AUTOMOUNT is meaningless without automounts, and every sync type is as good as stat()
+    // a path which can not be read is EFAULT, only "" is empty
+    let empty_path = UserPtrRaw::new(pathname)
+        .cstr_slice(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?
+        .to_ref()
+        .is_empty();
+    let statx = if empty_path && at_flags.contains(AtFlags::AT_EMPTY_PATH) && dirfd as i32 != AtFlags::AT_FDCWD.bits() {
+        // stat the open file itself, it may have no path at all, like a pipe or a socket
+        let file = task.with_fd_table(|t| t.get_path_file(dirfd as usize))?;
+        file.statx(mask)?
+    } else {
+        let dentry = at_helper(task.clone(), dirfd, pathname, at_flags)?;
+        if dentry.is_negative() {
+            return Err(SysError::ENOENT);
+        }
+        dentry.inode().ok_or(SysError::ENOENT)?.getxattr(mask)
+    };
+    let statx_ptr = UserPtrRaw::new(statx_buf.0 as *const Xstat)
+        .ensure_write(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?;
+    statx_ptr.write(statx);
     Ok(0)
 }
 
@@ -404,12 +586,79 @@ pub fn sys_uname(uname_buf: usize) -> SysResult {
     Ok(0)
 }
 
-/// syscall: syslog
-/// TODO: unimplement
-pub fn sys_syslog(_log_type: usize, _bufp: usize, _len: usize) -> SysResult {
+/// the name set by sethostname and setdomainname, root only
+fn set_uts_name(param: &StrParam, name: usize, len: usize) -> SysResult {
+    let task = current_task().unwrap().clone();
+    if !task.with_cred(|c| c.is_privileged()) {
+        return Err(SysError::EPERM);
+    }
+    if len > HOST_NAME_MAX {
+        return Err(SysError::EINVAL);
+    }
+    if len == 0 {
+        param.set(&[])?;
+        return Ok(0);
+    }
+    let buf = UserSliceRaw::new(name as *const u8, len)
+        .ensure_read(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?;
+    param.set(buf.to_ref())?;
     Ok(0)
 }
 
+/// syscall: sethostname
+pub fn sys_sethostname(name: usize, len: usize) -> SysResult {
+    set_uts_name(&HOSTNAME, name, len)
+}
+
+/// syscall: setdomainname
+pub fn sys_setdomainname(name: usize, len: usize) -> SysResult {
+    set_uts_name(&DOMAINNAME, name, len)
+}
+
+/// syscall: syslog
+/// read or clear the kernel log ring, the console actions are accepted and ignored;
+/// only root may clear the ring, also by reading it destructively
+pub fn sys_syslog(log_type: usize, bufp: usize, len: usize) -> SysResult {
+    const SYSLOG_ACTION_READ: usize = 2;
+    const SYSLOG_ACTION_READ_ALL: usize = 3;
+    const SYSLOG_ACTION_READ_CLEAR: usize = 4;
+    const SYSLOG_ACTION_CLEAR: usize = 5;
+    const SYSLOG_ACTION_SIZE_UNREAD: usize = 9;
+    const SYSLOG_ACTION_SIZE_BUFFER: usize = 10;
+    let clears = matches!(log_type, SYSLOG_ACTION_READ | SYSLOG_ACTION_READ_CLEAR | SYSLOG_ACTION_CLEAR);
+    if clears && !current_task().unwrap().with_cred(|c| c.is_privileged()) {
+        return Err(SysError::EPERM);
+    }
+    match log_type {
+        SYSLOG_ACTION_READ | SYSLOG_ACTION_READ_ALL | SYSLOG_ACTION_READ_CLEAR => {
+            if (len as isize) < 0 {
+                return Err(SysError::EINVAL);
+            }
+            if len == 0 {
+                return Ok(0);
+            }
+            let task = current_task().unwrap().clone();
+            let buf = UserSliceRaw::new(bufp as *mut u8, len)
+                .ensure_write(&mut task.get_vm_space().lock())
+                .ok_or(SysError::EFAULT)?;
+            let n = klog_read_all(buf.to_mut());
+            if log_type != SYSLOG_ACTION_READ_ALL {
+                klog_clear();
+            }
+            Ok(n as isize)
+        }
+        SYSLOG_ACTION_CLEAR => {
+            klog_clear();
+            Ok(0)
+        }
+        SYSLOG_ACTION_SIZE_UNREAD => Ok(klog_len() as isize),
+        SYSLOG_ACTION_SIZE_BUFFER => Ok(KLOG_SIZE as isize),
+        0..=10 => Ok(0),
+        _ => Err(SysError::EINVAL),
+    }
+}
+
 
 
 #[derive(Debug, Clone, Copy)]
@@ -433,12 +682,12 @@ pub fn sys_getdents64(fd: usize, buf: usize, len: usize) -> SysResult {
     let task = current_task().unwrap().clone();
     let user_buf = UserSliceRaw::new(buf as *mut u8, len)
         .ensure_write(&mut task.get_vm_space().lock())
-        .ok_or(SysError::EINVAL)?;
+        .ok_or(SysError::EFAULT)?;
     let buf_slice = user_buf.to_mut();
     assert!(buf_slice.len() == len);
 
     let file = task.with_fd_table(|t| t.get_file(fd))?;
-    let dentry = file.dentry().unwrap();
+    let dentry = file.dentry().ok_or(SysError::ENOTDIR)?;
     let mut buf_it = buf_slice;
     let mut writen_len = 0;
     for child in dentry.load_child_dentry()?.iter().skip(file.pos()) {
@@ -450,7 +699,8 @@ pub fn sys_getdents64(fd: usize, buf: usize, len: usize) -> SysResult {
         let linux_dirent = LinuxDirent64 {
             d_ino: inode.inode_inner().ino as u64,
             d_off: file.pos() as u64,
-            d_type: inode.inode_inner().mode.bits() as u8,
+            // DT_* is the file type of the mode shifted down
+            d_type: (inode.inode_inner().mode().get_type().bits() >> 12) as u8,
             d_reclen: rec_len as u16,
         };
 
@@ -483,40 +733,68 @@ pub fn sys_getdents64(fd: usize, buf: usize, len: usize) -> SysResult {
 /// If the name referred to a symbolic link, the link is removed.
 /// If the name referred to a socket, FIFO, or device, the name for it
 /// is removed but processes which have the object open may continue to use it.
-/// (todo): now only remove, but not check for remaining referred.
+/// An open file keeps its cached pages, the file system removes it on the last close
 pub fn sys_unlinkat(dirfd: isize, pathname: *const u8, flags: i32) -> SysResult {
     const AT_REMOVEDIR: i32 = 0x200;
+    if flags & !AT_REMOVEDIR != 0 {
+        return Err(SysError::EINVAL);
+    }
     let task = current_task().unwrap().clone();
+    // null is EFAULT and "" ENOENT, like every other path
+    let dentry = at_helper(task.clone(), dirfd, pathname, AtFlags::AT_SYMLINK_NOFOLLOW)?;
     let path = user_path_to_string(
             UserPtrRaw::new(pathname), 
             &mut task.get_vm_space().lock()
-        ).ok_or(SysError::EINVAL)?;
+        ).ok_or(SysError::ENOENT)?;
     log::info!("[sys_unlinkat]: task {} unlink {}", task.tid(), path);
-    let dentry = at_helper(task, dirfd, pathname, AtFlags::AT_SYMLINK_NOFOLLOW)?;
     if dentry.parent().is_none() {
         warn!("cannot unlink root!");
-        return Err(SysError::ENOENT);
+        return Err(SysError::EBUSY);
     }
     if dentry.is_negative() {
         return Err(SysError::ENOENT);
     }
+    // the walk resolved them to a directory of another name
+    if flags == AT_REMOVEDIR {
+        match abs_path_to_name(&path).as_deref() {
+            Some(".") => return Err(SysError::EINVAL),
+            Some("..") => return Err(SysError::ENOTEMPTY),
+            _ => {}
+        }
+    }
     let inode = dentry.inode().unwrap();
-    let inode_mode = inode.inode_inner().mode;
-    let is_dir = inode_mode == InodeMode::DIR;
+    let inode_mode = inode.inode_inner().mode();
+    let is_dir = inode_mode.get_type() == InodeMode::DIR;
     if flags == AT_REMOVEDIR && !is_dir {
         return Err(SysError::ENOTDIR);
     } else if flags != AT_REMOVEDIR && is_dir {
-        return Err(SysError::EPERM);
+        return Err(SysError::EISDIR);
+    }
+    if is_dir && !dentry.clone().load_child_dentry()?.is_empty() {
+        return Err(SysError::ENOTEMPTY);
+    }
+    let parent_inode = dentry.parent().unwrap().inode().unwrap();
+    task.check_delete(parent_inode.inode_inner(), inode.inode_inner())?;
+    // another name may keep the inode, which then has one link less and a new ctime
+    if !is_dir && inode.inode_inner().nlink() > 1 {
+        inode.inode_inner().set_nlink(inode.inode_inner().nlink() - 1);
+        inode.inode_inner().touch_ctime();
+    } else if inode.inode_inner().opens() == 0 {
+        inode.clean_cached();
     }
     // should clear inode first to drop inode (flush datas to disk)
     dentry.clear_inode();
-    inode.clean_cached();
     drop(inode);
     // use parent inode to remove the inode in the fs
-    let name = abs_path_to_name(&path).unwrap();
+    let name = dentry.name().to_string();
     let parent = dentry.parent().unwrap();
-    parent.inode().unwrap().remove(&name, inode_mode).expect("remove failed");
+    parent_inode.remove(&name, inode_mode).map_err(SysError::from)?;
+    parent_inode.inode_inner().touch_mtime();
     parent.remove_child(&name);
+    if is_dir {
+        // the dentry stays cached as a negative one, but nothing under it exists any more
+        DCACHE.remove_children(&dentry);
+    }
 
     //inode.unlink().expect("inode unlink failed");
     Ok(0)
@@ -527,14 +805,19 @@ pub fn sys_symlinkat(old_path_ptr: *const u8, new_dirfd: isize, new_path_ptr: *c
     let task = current_task().unwrap().clone();
     let old_path = user_path_to_string(
         UserPtrRaw::new(old_path_ptr), 
-        &mut task.get_vm_space().lock()).expect("failed to get old path");
+        &mut task.get_vm_space().lock()).ok_or(SysError::ENOENT)?;
     let new_path = user_path_to_string(
         UserPtrRaw::new(new_path_ptr), 
-        &mut task.get_vm_space().lock()).expect("failed to get new path");
+        &mut task.get_vm_space().lock()).ok_or(SysError::ENOENT)?;
     log::info!("[sys_symlinkat] task {}, sym-link old path {} to new path {}", task.tid(), old_path, new_path);
     let dentry = at_helper(task, new_dirfd, old_path_ptr, AtFlags::AT_SYMLINK_NOFOLLOW)?;
+    // the link is made in the file system of the inode it is made from
+    dentry.inode().unwrap().inode_inner().check_writable()?;
     let new_inode = dentry.inode().unwrap().symlink(&new_path)?;
     global_update_dentry(&new_path, new_inode)?;
+    if let Some(parent_inode) = global_find_dentry(&new_path).ok().and_then(|d| d.parent()).and_then(|p| p.inode()) {
+        parent_inode.inode_inner().touch_mtime();
+    }
     Ok(0)
 }
 
@@ -545,20 +828,24 @@ pub fn sys_symlinkat(old_path_ptr: *const u8, new_dirfd: isize, new_path_ptr: *c
 /// too small to hold all of the contents.
 pub fn sys_readlinkat(dirfd: isize, pathname: *const u8, buf: usize, len: usize) -> SysResult {
     let task = current_task().unwrap().clone();
-    let dentry = at_helper(task.clone(), dirfd, pathname, AtFlags::AT_SYMLINK_NOFOLLOW)?;
+    if pathname.is_null() {
+        return Err(SysError::EFAULT);
+    }
+    // an empty path reads the symlink dirfd refers to, opened with O_PATH | O_NOFOLLOW
+    let dentry = at_helper(task.clone(), dirfd, pathname, AtFlags::AT_SYMLINK_NOFOLLOW | AtFlags::AT_EMPTY_PATH)?;
     info!("[sys_readlinkat]: reading link {}", dentry.path());
     if dentry.state() == DentryState::NEGATIVE {
         return Err(SysError::EBADF);
     }
     let inode = dentry.inode().unwrap();
-    if inode.inode_inner().mode != InodeMode::LINK {
+    if inode.inode_inner().mode().get_type() != InodeMode::LINK {
         return Err(SysError::EINVAL);
     }
     
     let path = inode.readlink()?;
     let new_buf = UserSliceRaw::new(buf as *mut u8, len)
         .ensure_write(&mut task.get_vm_space().lock())
-        .ok_or(SysError::EINVAL)?;
+        .ok_or(SysError::EFAULT)?;
     new_buf.to_mut()[path.len()..].fill(0u8);
     new_buf.to_mut()[..path.len()].copy_from_slice(path.as_bytes());
 
@@ -597,8 +884,9 @@ pub fn sys_utimensat(dirfd: isize, pathname: *const u8, times: usize, flags: i32
     };
     
     let inner = inode.inode_inner();
+    inner.check_writable()?;
     
-    let current_time = TimeSpec::from(get_current_time_duration());
+    let current_time = TimeSpec::from(get_realtime_duration());
     if times == 0 {
         inner.set_atime(current_time);
         inner.set_ctime(current_time);
@@ -607,7 +895,7 @@ pub fn sys_utimensat(dirfd: isize, pathname: *const u8, times: usize, flags: i32
         let times_ptr =
             UserSliceRaw::new(times as *mut TimeSpec, 2)
             .ensure_write(&mut task.get_vm_space().lock())
-            .ok_or(SysError::EINVAL)?;
+            .ok_or(SysError::EFAULT)?;
         let times = times_ptr.to_mut();
         log::info!("[sys_utimensat] times {:?}", times);
         match times[0].tv_nsec {
@@ -622,43 +910,96 @@ pub fn sys_utimensat(dirfd: isize, pathname: *const u8, times: usize, flags: i32
         }
         inner.set_ctime(current_time);
     }
+    inner.meta_dirty.store(true, Ordering::Release);
     Ok(0)
 }
 
 /// syscall: mount
-/// (todo)
+/// MS_REMOUNT changes the flags of a mount, otherwise a tmpfs is mounted on the target.
+/// A mount of another file system is accepted and ignored (todo)
 pub fn sys_mount(
     _source: *const u8,
-    _target: *const u8,
-    _fstype: *const u8,
-    _flags: u32,
+    target: *const u8,
+    fstype: *const u8,
+    flags: u32,
     _data: usize,
 ) -> SysResult {
-    /*
-    let _source_path = user_path_to_string(source).unwrap();
-    let target_path = user_path_to_string(target).unwrap();
-    let flags = MountFlags::from_bits(flags).unwrap();
-    let fat32_type = get_filesystem("fat32");
-    let dev = Some(BLOCK_DEVICE.clone());
-    let parent_path = abs_path_to_parent(&target_path).unwrap();
-    let name = abs_path_to_name(&target_path).unwrap();
-    let parent = global_find_dentry(&parent_path);
-
-    fat32_type.mount(&name, Some(parent), flags, dev);
-    */
+    let flags = MountFlags::from_bits_truncate(flags);
+    let task = current_task().unwrap().clone();
+    if !task.with_cred(|c| c.is_privileged()) {
+        return Err(SysError::EPERM);
+    }
+    if !flags.contains(MountFlags::MS_REMOUNT) {
+        let fstype = user_path_to_string(UserPtrRaw::new(fstype), &mut task.get_vm_space().lock())
+            .ok_or(SysError::EFAULT)?;
+        if fstype != "tmpfs" {
+            warn!("[sys_mount] mount of {} ignored", fstype);
+            return Ok(0);
+        }
+    }
+    let dentry = at_helper(task, AtFlags::AT_FDCWD.bits() as isize, target, AtFlags::empty())?;
+    if dentry.is_negative() {
+        return Err(SysError::ENOENT);
+    }
+    if flags.contains(MountFlags::MS_REMOUNT) {
+        crate::fs::remount(&dentry.path(), flags)?;
+        return Ok(0);
+    }
+    if dentry.inode().unwrap().inode_inner().mode().get_type() != InodeMode::DIR {
+        return Err(SysError::ENOTDIR);
+    }
+    crate::fs::mount("tmpfs", &dentry, flags)?;
     Ok(0)
 }
 
-/// fake unmount
-pub fn sys_umount2(_target: *const u8, _flags: u32) -> SysResult {
+/// syscall: umount2
+/// EBUSY while a file of the mount is open, a target which is not a mount point
+/// was a mount sys_mount ignored and succeeds
+pub fn sys_umount2(target: *const u8, flags: u32) -> SysResult {
+    const MNT_FORCE: u32 = 1;
+    const UMOUNT_NOFOLLOW: u32 = 8;
+    if flags & !(MNT_FORCE | UMOUNT_NOFOLLOW) != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let task = current_task().unwrap().clone();
+    if !task.with_cred(|c| c.is_privileged()) {
+        return Err(SysError::EPERM);
+    }
+    let at_flags = if flags & UMOUNT_NOFOLLOW != 0 { AtFlags::AT_SYMLINK_NOFOLLOW } else { AtFlags::empty() };
+    let dentry = at_helper(task, AtFlags::AT_FDCWD.bits() as isize, target, at_flags)?;
+    if dentry.is_negative() {
+        return Err(SysError::ENOENT);
+    }
+    let path = dentry.path();
+    if crate::fs::find_mount(&path).is_none() {
+        return Ok(0);
+    }
+    crate::fs::unmount(&path)?;
     Ok(0)
 }
 
 /// syscall: ioctl
+/// the commands on the fd are handled here, the others by the file
 pub fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> SysResult {
     let task = current_task().unwrap().clone();
     let file = task.with_fd_table(|t| t.get_file(fd))?;
-    file.ioctl(cmd, arg)
+    match cmd {
+        ioctl::FIONBIO => {
+            let nonblock = ioctl::read_arg::<i32>(arg)? != 0;
+            let mut flags = file.flags();
+            flags.set(OpenFlags::O_NONBLOCK, nonblock);
+            file.set_flags(flags);
+            Ok(0)
+        }
+        ioctl::FIOCLEX | ioctl::FIONCLEX => task.with_mut_fd_table(|table| {
+            let fd_info = table.get_mut_fd_info(fd)?;
+            let mut flags = fd_info.flags();
+            flags.set(FdFlags::CLOEXEC, cmd == ioctl::FIOCLEX);
+            fd_info.set_flags(flags);
+            Ok(0)
+        }),
+        _ => file.ioctl(cmd, arg),
+    }
 }
 
 #[derive(FromRepr, Debug, Eq, PartialEq, Clone, Copy, Default)]
@@ -672,6 +1013,14 @@ pub enum FcntlOp {
     F_SETFD = 2,
     F_GETFL = 3,
     F_SETFL = 4,
+    F_SETOWN = 8,
+    F_GETOWN = 9,
+    F_SETSIG = 10,
+    F_GETSIG = 11,
+    F_SETPIPE_SZ = 1031,
+    F_GETPIPE_SZ = 1032,
+    F_ADD_SEALS = 1033,
+    F_GET_SEALS = 1034,
     #[default]
     F_UNIMPL,
 }
@@ -709,15 +1058,67 @@ pub fn sys_fnctl(fd: usize, op: isize, arg: usize) -> SysResult {
             })
         }
         FcntlOp::F_GETFL => {
-            let file = task.with_fd_table(|table| table.get_file(fd))?;
+            let file = task.with_fd_table(|table| table.get_path_file(fd))?;
             Ok(file.flags().bits() as _)
         }
         FcntlOp::F_SETFL => {
-            let flags = OpenFlags::from_bits_truncate(arg as _);
+            let flags = OpenFlags::from_bits_truncate(arg as _) & OpenFlags::SETFL_FLAGS;
+            let file = task.with_fd_table(|table| table.get_file(fd))?;
+            let old = file.flags();
+            file.set_flags(old.difference(OpenFlags::SETFL_FLAGS) | flags);
+            let on = flags.contains(OpenFlags::O_ASYNC);
+            if on != old.contains(OpenFlags::O_ASYNC) {
+                file.file_inner().owner.set_fd(fd);
+                if let Some(list) = file.fasync_list() {
+                    list.update(&file, on);
+                }
+            }
+            Ok(0)
+        }
+        FcntlOp::F_SETOWN => {
+            let file = task.with_fd_table(|table| table.get_file(fd))?;
+            // the pid is an int, a process group comes negative
+            file.file_inner().owner.set(arg as i32 as isize)?;
+            Ok(0)
+        }
+        FcntlOp::F_GETOWN => {
+            let file = task.with_fd_table(|table| table.get_file(fd))?;
+            Ok(file.file_inner().owner.get())
+        }
+        FcntlOp::F_SETSIG => {
             let file = task.with_fd_table(|table| table.get_file(fd))?;
-            file.set_flags(flags.status());
+            file.file_inner().owner.set_sig(arg)?;
             Ok(0)
         }
+        FcntlOp::F_GETSIG => {
+            let file = task.with_fd_table(|table| table.get_file(fd))?;
+            Ok(file.file_inner().owner.sig() as isize)
+        }
+        FcntlOp::F_ADD_SEALS => {
+            let seals = SealFlags::from_bits(arg as u32).ok_or(SysError::EINVAL)?;
+            let file = task.with_fd_table(|table| table.get_file(fd))?;
+            // sealing changes what the file may become, so it takes a writable fd
+            if !file.flags().writable() {
+                return Err(SysError::EPERM);
+            }
+            file.inode().ok_or(SysError::EINVAL)?.add_seals(seals)?;
+            Ok(0)
+        }
+        FcntlOp::F_GET_SEALS => {
+            let file = task.with_fd_table(|table| table.get_file(fd))?;
+            let seals = file.inode().ok_or(SysError::EINVAL)?.seals()?;
+            Ok(seals.bits() as isize)
+        }
+        FcntlOp::F_SETPIPE_SZ => {
+            let file = task.with_fd_table(|table| table.get_file(fd))?;
+            let pipe = file.downcast_arc::<PipeFile>().map_err(|_| SysError::EBADF)?;
+            Ok(pipe.set_pipe_size(arg as u32 as usize)? as isize)
+        }
+        FcntlOp::F_GETPIPE_SZ => {
+            let file = task.with_fd_table(|table| table.get_file(fd))?;
+            let pipe = file.downcast_arc::<PipeFile>().map_err(|_| SysError::EBADF)?;
+            Ok(pipe.pipe_size() as isize)
+        }
         _ => {
             log::warn!("fcntl cmd: {op:?} not implemented");
             Ok(0)
@@ -733,18 +1134,39 @@ pub struct IoVec {
     pub len: usize,
 }
 
+/// the most iovecs one call takes, like linux
+pub const IOV_MAX: usize = 1024;
+
+/// copy in the iovec array of the caller: no more than IOV_MAX of them,
+/// with a total length that fits in the returned isize, else EINVAL
+pub fn read_iovecs(task: &Arc<TaskControlBlock>, iov: usize, iovcnt: usize) -> Result<Vec<IoVec>, SysError> {
+    if iovcnt > IOV_MAX {
+        return Err(SysError::EINVAL);
+    }
+    if iovcnt == 0 {
+        return Ok(Vec::new());
+    }
+    let iovs = UserSliceRaw::new(iov as *const IoVec, iovcnt)
+        .ensure_read(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?
+        .to_ref()
+        .to_vec();
+    iovs.iter()
+        .try_fold(0usize, |total, iov| total.checked_add(iov.len).filter(|&total| total <= isize::MAX as usize))
+        .ok_or(SysError::EINVAL)?;
+    Ok(iovs)
+}
+
 /// The readv() system call reads iovcnt buffers from the file
 /// associated with the file descriptor fd into the buffers described
 /// by iov ("scatter input").
 pub async fn sys_readv(fd: usize, iov: usize, iovcnt: usize) -> SysResult {
     let task = current_task().unwrap().clone();
     let file = task.with_fd_table(|t| t.get_file(fd))?;
-    let iovs = UserSliceRaw::new(iov as *const IoVec, iovcnt)
-        .ensure_read(&mut task.get_vm_space().lock())
-        .ok_or(SysError::EINVAL)?;
+    let iovs = read_iovecs(&task, iov, iovcnt)?;
     let mut totol_len = 0usize;
     let mut offset = file.pos();
-    for (i, iov) in iovs.to_ref().iter().enumerate() {
+    for (i, iov) in iovs.iter().enumerate() {
         if iov.len == 0 {
             continue;
         }
@@ -753,8 +1175,9 @@ pub async fn sys_readv(fd: usize, iov: usize, iovcnt: usize) -> SysResult {
         let iov_buf =
             UserSliceRaw::new(iov.base as *mut u8, iov.len)
                 .ensure_write(&mut task.get_vm_space().lock())
-                .ok_or(SysError::EINVAL)?;
+                .ok_or(SysError::EFAULT)?;
         let ret = file.read(iov_buf.to_mut()).await?;
+        cond_resched().await;
 
         // ugly way
         // let start = iov.base & !(Constant::PAGE_SIZE - 1);
@@ -790,11 +1213,9 @@ pub async fn sys_readv(fd: usize, iov: usize, iovcnt: usize) -> SysResult {
 pub async fn sys_writev(fd: usize, iov: usize, iovcnt: usize) -> SysResult {
     let task = current_task().unwrap().clone();
     let file = task.with_fd_table(|t| t.get_file(fd))?;
-    let iovs = UserSliceRaw::new(iov as *const IoVec, iovcnt)
-        .ensure_read(&mut task.get_vm_space().lock())
-        .ok_or(SysError::EINVAL)?;
+    let iovs = read_iovecs(&task, iov, iovcnt)?;
     let mut totol_len = 0usize;
-    for (i, iov) in iovs.to_ref().iter().enumerate() {
+    for (i, iov) in iovs.iter().enumerate() {
         if iov.len == 0 {
             continue;
         }
@@ -803,8 +1224,9 @@ pub async fn sys_writev(fd: usize, iov: usize, iovcnt: usize) -> SysResult {
         let iov_buf =
             UserSliceRaw::new(iov.base as *mut u8, iov.len)
                 .ensure_read(&mut task.get_vm_space().lock())
-                .ok_or(SysError::EINVAL)?;
+                .ok_or(SysError::EFAULT)?;
         let ret = file.write(iov_buf.to_ref()).await?;
+        cond_resched().await;
 
         // let start = iov.base & !(Constant::PAGE_SIZE - 1);
         // let end = iov.base + iov.len;
@@ -827,20 +1249,17 @@ pub async fn sys_writev(fd: usize, iov: usize, iovcnt: usize) -> SysResult {
 /// pread() reads up to count bytes from file descriptor fd at offset
 /// offset (from the start of the file) into the buffer starting at buf.  
 /// The file offset is not changed.
-/// TODOS: now mostly copy from sys_read; need to deal with file offset under multi-thread
-/// UGLY: now reset file offset, try to add a read_at method for file?
 pub async fn sys_pread(fd: usize, buf: usize, count: usize, offset: usize) -> SysResult {
     let task = current_task().unwrap().clone();
     log::debug!("[sys_pread] task {} try to read fd {} to buf {:#x} at offset {}, len {}", task.tid(), fd, buf, offset, count);
     let file = task.with_fd_table(|t| t.get_file(fd))?;
-    let old_pos = file.pos();
-    // assume: during file read, no task switch
-    file.seek(SeekFrom::Start(offset as u64))?;
+    // the offset is shared with other fds of the description, never touch it here
     let user_buf =
         UserSliceRaw::new(buf as *mut u8, count)
                 .ensure_write(&mut task.get_vm_space().lock())
-                .ok_or(SysError::EINVAL)?;
-    let ret = file.read(user_buf.to_mut()).await?;
+                .ok_or(SysError::EFAULT)?;
+    let ret = file.read_at(offset, user_buf.to_mut()).await?;
+    file.file_inner().accessed();
     // let start = buf & !(Constant::PAGE_SIZE - 1);
     // let end = buf + count;
     // let mut ret = 0;
@@ -858,26 +1277,25 @@ pub async fn sys_pread(fd: usize, buf: usize, count: usize, offset: usize) -> Sy
     //         break;
     //     }
     // }
-    file.set_pos(old_pos);
     Ok(ret as isize)
 }
 
 /// pwrite() writes up to count bytes from the buffer starting at buf 
 /// to the file descriptor fd at offset offset. 
 /// The file offset is not changed.
-/// NOTICE: see pread
 pub async fn sys_pwrite(fd: usize, buf: usize, count: usize, offset: usize) -> SysResult {
     let task = current_task().unwrap().clone();
     log::debug!("[sys_pwrite] task {} try to read fd {} to buf {:#x} at offset {}, len {}", task.tid(), fd, buf, offset, count);
     let file = task.with_fd_table(|t| t.get_file(fd))?;
-    let old_pos = file.pos();
-    // assume: during file read, no task switch
-    file.seek(SeekFrom::Start(offset as u64))?;
     let user_buf = 
         UserSliceRaw::new(buf as *mut u8, count)
             .ensure_read(&mut task.get_vm_space().lock())
-            .ok_or(SysError::EINVAL)?;
-    let ret = file.write(user_buf.to_ref()).await?;
+            .ok_or(SysError::EFAULT)?;
+    file.file_inner().check_writable()?;
+    let ret = file.write_at(offset, user_buf.to_ref()).await?;
+    if ret > 0 {
+        file.file_inner().modified();
+    }
     // let start = buf & !(Constant::PAGE_SIZE - 1);
     // let end = buf + count;
     // let mut ret = 0;
@@ -891,7 +1309,6 @@ pub async fn sys_pwrite(fd: usize, buf: usize, count: usize, offset: usize) -> S
     //     let data = pa.get_slice(len);
     //     ret += file.write(data).await?;
     // }
-    file.set_pos(old_pos);
     log::debug!("finish pwrite return {}", ret);
     Ok(ret as isize)
 }
@@ -913,22 +1330,44 @@ pub async fn sys_sendfile(out_fd: usize, in_fd: usize, offset: usize, count: usi
     let task = current_task().unwrap().clone();
     let in_file = task.with_fd_table(|t| t.get_file(in_fd))?;
     let out_file = task.with_fd_table(|t| t.get_file(out_fd))?;
-    let mut buf = vec![0u8; count];
+    let mut buf = vec![0u8; count.min(IO_CHUNK)];
     let off_ptr = {
         UserPtrRaw::new(offset as *mut usize)
             .ensure_write(&mut task.get_vm_space().lock())
-            .ok_or(SysError::EINVAL)?
+            .ok_or(SysError::EFAULT)?
     };
-    let len;
-    if off_ptr.raw == core::ptr::null() {
-        len = in_file.read(&mut buf).await?;
-    } else {
-        let off = off_ptr.to_mut();
-        len = in_file.read_at(*off, &mut buf).await?;
-        *off += len;
+    // a big copy goes in chunks and lets the others run between them,
+    // an error after some bytes were sent ends the copy with what was sent
+    let mut sent = 0;
+    while sent < count {
+        let want = (count - sent).min(IO_CHUNK);
+        let len = if off_ptr.raw == core::ptr::null() {
+            in_file.read(&mut buf[..want]).await
+        } else {
+            let off = off_ptr.to_mut();
+            in_file.read_at(*off, &mut buf[..want]).await.inspect(|len| *off += len)
+        };
+        let len = match len {
+            Ok(len) => len,
+            Err(e) if sent == 0 => return Err(e),
+            Err(_) => break,
+        };
+        if len == 0 {
+            break;
+        }
+        let written = match out_file.write(&buf[..len]).await {
+            Ok(written) => written,
+            Err(e) if sent == 0 => return Err(e),
+            Err(_) => break,
+        };
+        sent += written;
+        // a short read or write is all there is for now
+        if written < len || len < want {
+            break;
+        }
+        cond_resched().await;
     }
-    let ret = out_file.write(&buf[..len]).await?;
-    Ok(ret as isize)
+    Ok(sent as isize)
 }
 
 /// syscall: linkat
@@ -936,29 +1375,220 @@ pub async fn sys_sendfile(out_fd: usize, in_fd: usize, offset: usize, count: usi
 /// The linkat() system call operates in exactly the same way as link(2), 
 pub fn sys_linkat(old_dirfd: isize, old_pathname: *const u8, new_dirfd: isize, new_pathname: *const u8, flags: i32) -> SysResult {
     let task = current_task().unwrap().clone();
-    let at_flags = AtFlags::from_bits_truncate(flags);
-    let old_dentry = at_helper(task.clone(), old_dirfd, old_pathname, at_flags)?;
-    let new_dentry = at_helper(task.clone(), new_dirfd, new_pathname, at_flags)?;
-    log::debug!("[sys_linkat]: try to create hard link between {} {}", old_dentry.path(), new_dentry.path());
+    let at_flags = AtFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+    if !(AtFlags::AT_SYMLINK_FOLLOW | AtFlags::AT_EMPTY_PATH).contains(at_flags) {
+        return Err(SysError::EINVAL);
+    }
+    // a symlink source is linked itself unless AT_SYMLINK_FOLLOW asks for its target,
+    // AT_EMPTY_PATH links the file olddirfd refers to
+    let old_flags = if at_flags.contains(AtFlags::AT_SYMLINK_FOLLOW) {
+        at_flags - AtFlags::AT_SYMLINK_FOLLOW
+    } else {
+        at_flags | AtFlags::AT_SYMLINK_NOFOLLOW
+    };
+    let old_dentry = at_helper(task.clone(), old_dirfd, old_pathname, old_flags)?;
+    if old_dentry.is_negative() {
+        return Err(SysError::ENOENT);
+    }
+    // the new name itself is never followed, not even when it is a dangling symlink
+    let new_dentry = at_helper(task.clone(), new_dirfd, new_pathname, AtFlags::AT_SYMLINK_NOFOLLOW)?;
+    if !new_dentry.is_negative() {
+        return Err(SysError::EEXIST);
+    }
+    let parent = new_dentry.parent().ok_or(SysError::EEXIST)?;
+    let parent_inode = parent.inode().ok_or(SysError::ENOENT)?;
+    task.check_access(parent_inode.inode_inner(), MAY_WRITE | MAY_EXEC)?;
     let old_inode = old_dentry.inode().unwrap();
+    if !old_inode.inode_inner().same_fs(parent_inode.inode_inner()) {
+        return Err(SysError::EXDEV);
+    }
+    if old_inode.inode_inner().mode().get_type() == InodeMode::DIR {
+        return Err(SysError::EPERM);
+    }
+    log::debug!("[sys_linkat]: try to create hard link between {} {}", old_dentry.path(), new_dentry.path());
     old_inode.link(&new_dentry.path())?;
+    // the link count is part of the status, the new name changes the directory
+    let inner = old_inode.inode_inner();
+    inner.set_nlink(inner.nlink() + 1);
+    inner.touch_ctime();
     new_dentry.set_inode(old_inode);
     new_dentry.set_state(DentryState::USED);
+    parent_inode.inode_inner().touch_mtime();
+    parent.add_child(new_dentry.clone());
     Ok(0)
 }
 
+/// the head of `struct file_handle`, the handle itself follows it
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FileHandleHead {
+    handle_bytes: u32,
+    handle_type: i32,
+}
+
+/// the handle of a file: its super block, its inode number and the generation of the number.
+/// Packed as the user only aligns the head
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct InoHandle {
+    sb_id: u32,
+    generation: u32,
+    ino: u64,
+}
+
+/// the largest handle a caller may ask for
+const MAX_HANDLE_SZ: usize = 128;
+/// a 64 bit inode number and a 32 bit generation
+const FILEID_INO64_GEN: i32 = 0x81;
+
+/// syscall: name_to_handle_at
+/// write a handle of the file at `pathname` to `handle` and the id of its mount to `mount_id`,
+/// open_by_handle_at reopens the file by it after every name and fd of it is gone.
+/// A `handle_bytes` too small for the handle is set to the size needed, with EOVERFLOW
+pub fn sys_name_to_handle_at(dirfd: isize, pathname: *const u8, handle: usize, mount_id: usize, flags: i32) -> SysResult {
+    let task = current_task().unwrap().clone();
+    let at_flags = AtFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+    if !(AtFlags::AT_SYMLINK_FOLLOW | AtFlags::AT_EMPTY_PATH).contains(at_flags) {
+        return Err(SysError::EINVAL);
+    }
+    // a symlink names itself unless AT_SYMLINK_FOLLOW asks for its target
+    let lookup_flags = if at_flags.contains(AtFlags::AT_SYMLINK_FOLLOW) {
+        at_flags - AtFlags::AT_SYMLINK_FOLLOW
+    } else {
+        at_flags | AtFlags::AT_SYMLINK_NOFOLLOW
+    };
+    let dentry = at_helper(task.clone(), dirfd, pathname, lookup_flags)?;
+    if dentry.is_negative() {
+        return Err(SysError::ENOENT);
+    }
+    let inode = dentry.inode().unwrap();
+    let inner = inode.inode_inner();
+    let sb = inner.super_block.as_ref().and_then(|sb| sb.upgrade()).ok_or(SysError::EOPNOTSUPP)?;
+    if !sb.file_handles() {
+        return Err(SysError::EOPNOTSUPP);
+    }
+    let mut vm = task.get_vm_space().lock();
+    let head_ptr = UserPtrRaw::new(handle as *mut FileHandleHead)
+        .ensure_write(&mut vm)
+        .ok_or(SysError::EFAULT)?;
+    let head = *head_ptr.to_ref();
+    if head.handle_bytes as usize > MAX_HANDLE_SZ {
+        return Err(SysError::EINVAL);
+    }
+    let size = core::mem::size_of::<InoHandle>();
+    if (head.handle_bytes as usize) < size {
+        head_ptr.write(FileHandleHead { handle_bytes: size as u32, ..head });
+        return Err(SysError::EOVERFLOW);
+    }
+    let body_ptr = UserPtrRaw::new((handle + core::mem::size_of::<FileHandleHead>()) as *mut InoHandle)
+        .ensure_write(&mut vm)
+        .ok_or(SysError::EFAULT)?;
+    let mount_ptr = UserPtrRaw::new(mount_id as *mut i32)
+        .ensure_write(&mut vm)
+        .ok_or(SysError::EFAULT)?;
+    body_ptr.write(InoHandle {
+        sb_id: sb.inner().id as u32,
+        generation: sb.inner().inodes.generation(inner.ino),
+        ino: inner.ino as u64,
+    });
+    head_ptr.write(FileHandleHead { handle_bytes: size as u32, handle_type: FILEID_INO64_GEN });
+    mount_ptr.write(crate::fs::mount_id(&sb).unwrap_or(0) as i32);
+    Ok(0)
+}
+
+/// syscall: open_by_handle_at
+/// open the file a handle of name_to_handle_at names, on the file system `mount_fd` is on.
+/// The handle is stale once the file was removed, even if its inode number is in use again.
+/// The handle skips the permissions of the directories, so only the privileged may use it
+pub fn sys_open_by_handle_at(mount_fd: isize, handle: usize, flags: u32) -> SysResult {
+    let task = current_task().unwrap().clone();
+    if !task.with_cred(|c| c.is_privileged()) {
+        return Err(SysError::EPERM);
+    }
+    let open_flags = OpenFlags::from_bits_truncate(flags as i32);
+    let mount = if mount_fd as i32 == AtFlags::AT_FDCWD.bits() {
+        task.with_cwd(|d| d.clone())
+    } else {
+        task.with_fd_table(|t| t.get_path_file(mount_fd as usize))?.dentry().ok_or(SysError::EBADF)?
+    };
+    let sb = mount.inode()
+        .and_then(|inode| inode.inode_inner().super_block.clone())
+        .and_then(|sb| sb.upgrade())
+        .ok_or(SysError::ESTALE)?;
+    let (head, body) = {
+        let mut vm = task.get_vm_space().lock();
+        let head = *UserPtrRaw::new(handle as *const FileHandleHead)
+            .ensure_read(&mut vm)
+            .ok_or(SysError::EFAULT)?
+            .to_ref();
+        if head.handle_bytes == 0 || head.handle_bytes as usize > MAX_HANDLE_SZ {
+            return Err(SysError::EINVAL);
+        }
+        if head.handle_type != FILEID_INO64_GEN || head.handle_bytes as usize != core::mem::size_of::<InoHandle>() {
+            return Err(SysError::ESTALE);
+        }
+        let body = *UserPtrRaw::new((handle + core::mem::size_of::<FileHandleHead>()) as *const InoHandle)
+            .ensure_read(&mut vm)
+            .ok_or(SysError::EFAULT)?
+            .to_ref();
+        (head, body)
+    };
+    log::debug!("[sys_open_by_handle_at]: handle of {} bytes, ino {}", head.handle_bytes, { body.ino });
+    let ino = body.ino as usize;
+    if body.sb_id as usize != sb.inner().id || !sb.file_handles() || sb.inner().inodes.generation(ino) != body.generation {
+        return Err(SysError::ESTALE);
+    }
+    // an inode in memory is reached by its cached dentry, the file system looks up the others
+    let cached = sb.inner().inodes.get(ino).and_then(|inode| {
+        DCACHE.dentries().into_iter().find(|d| {
+            d.inode().is_some_and(|i| Arc::as_ptr(&i) as *const () == Arc::as_ptr(&inode) as *const ())
+        })
+    });
+    let dentry = match cached {
+        Some(dentry) => dentry,
+        None => sb.lookup_ino(ino)?,
+    };
+    if dentry.is_negative() {
+        return Err(SysError::ESTALE);
+    }
+    task.ensure_can_open(1)?;
+    let is_dir = dentry.inode().unwrap().inode_inner().mode().get_type() == InodeMode::DIR;
+    if open_flags.contains(OpenFlags::O_DIRECTORY) && !is_dir {
+        return Err(SysError::ENOTDIR);
+    }
+    if is_dir && open_flags.writable() {
+        return Err(SysError::EISDIR);
+    }
+    let file = if is_dir {
+        Arc::new(DirFile::new(dentry)) as Arc<dyn File>
+    } else {
+        dentry.open(open_flags).ok_or(SysError::EINVAL)?
+    };
+    file.set_flags(open_flags);
+    let fd = task.with_mut_fd_table(|table| table.install(file, open_flags.into()))?;
+    Ok(fd as isize)
+}
+
 /// syscall: faccessat
 /// access() checks whether the calling process can access the file
 /// pathname.  If pathname is a symbolic link, it is dereferenced.
-/// TODO: now do nothing
-pub fn sys_faccessat(dirfd: isize, pathname: *const u8, _mode: usize, flags: i32) -> SysResult {
-    if flags == 0x200 || flags == 0x1000 {
-        log::warn!("not support flags");
-    }
+/// The check is done using the real uid and gid, unless AT_EACCESS is given.
+pub fn sys_faccessat(dirfd: isize, pathname: *const u8, mode: usize, flags: i32) -> SysResult {
+    /// check with the effective ids, shares its value with AT_REMOVEDIR
+    const AT_EACCESS: i32 = 0x200;
     let at_flags = AtFlags::from_bits_truncate(flags);
 
     let task = current_task().unwrap().clone();
-    let _dentry = at_helper(task, dirfd, pathname, at_flags)?;
+    let dentry = at_helper(task.clone(), dirfd, pathname, at_flags)?;
+    if dentry.is_negative() {
+        return Err(SysError::ENOENT);
+    }
+    // F_OK only asks whether the file exists
+    let mask = mode as u32 & (MAY_READ | MAY_WRITE | MAY_EXEC);
+    let use_real = flags & AT_EACCESS == 0;
+    if !task.with_cred(|c| c.permits(dentry.inode().unwrap().inode_inner(), mask, use_real)) {
+        return Err(SysError::EACCES);
+    }
     Ok(0)
 }
 
@@ -974,6 +1604,13 @@ pub fn sys_renameat2(old_dirfd: isize, old_path: *const u8, new_dirfd: isize, ne
     let flags = RenameFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
     let old_dentry = at_helper(task.clone(), old_dirfd, old_path, AtFlags::AT_SYMLINK_NOFOLLOW)?;
     let new_dentry = at_helper(task.clone(), new_dirfd, new_path, AtFlags::AT_SYMLINK_NOFOLLOW)?;
+    if old_dentry.is_negative() {
+        return Err(SysError::ENOENT);
+    }
+    // renaming a file to itself does nothing
+    if Arc::ptr_eq(&old_dentry, &new_dentry) {
+        return Ok(0);
+    }
 
     if flags.contains(RenameFlags::RENAME_EXCHANGE)
             && (flags.contains(RenameFlags::RENAME_NOREPLACE)
@@ -998,27 +1635,120 @@ pub fn sys_renameat2(old_dirfd: isize, old_path: *const u8, new_dirfd: isize, ne
 
     let old_inode = old_dentry.inode().unwrap();
     let new_inode = new_dentry.inode();
+    // the old name goes away, and the new one if it exists
+    let old_parent = old_dentry.parent().and_then(|p| p.inode()).ok_or(SysError::EBUSY)?;
+    let new_parent = new_dentry.parent().and_then(|p| p.inode()).ok_or(SysError::EBUSY)?;
+    task.check_delete(old_parent.inode_inner(), old_inode.inode_inner())?;
+    match new_inode.as_ref() {
+        Some(victim) if !new_dentry.is_negative() => task.check_delete(new_parent.inode_inner(), victim.inode_inner())?,
+        _ => task.check_access(new_parent.inode_inner(), MAY_WRITE | MAY_EXEC)?,
+    }
+    let is_dir = old_inode.inode_inner().mode().contains(InodeMode::DIR);
     old_inode.rename(&new_dentry.path(), new_inode)?;
+    old_inode.inode_inner().touch_ctime();
+    for dentry in [&old_dentry, &new_dentry] {
+        if let Some(parent_inode) = dentry.parent().and_then(|p| p.inode()) {
+            parent_inode.inode_inner().touch_mtime();
+        }
+    }
     new_dentry.set_inode(old_inode);
+    if let Some(parent) = new_dentry.parent() {
+        parent.add_child(new_dentry.clone());
+    }
     // warning: due to lwext4 unsupport for RENAME_EXCHANGE
     if flags.contains(RenameFlags::RENAME_EXCHANGE) {
         old_dentry.set_inode(new_dentry.inode().unwrap());
     } else {
         old_dentry.clear_inode();
+        if let Some(parent) = old_dentry.parent() {
+            parent.remove_child(old_dentry.name());
+        }
+        // the files open on the old dentry follow it to the new one,
+        // and a later file of the old name must not reuse it
+        DCACHE.remove(&old_dentry);
+        old_dentry.set_moved_to(&new_dentry);
+        if is_dir {
+            // keep the subtree and the tasks working inside it reachable by the new name
+            old_dentry.move_children_to(&new_dentry);
+            TASK_MANAGER.for_each_task(|t| {
+                if Arc::ptr_eq(&t.cwd(), &old_dentry) {
+                    t.set_cwd(new_dentry.clone());
+                }
+            });
+        }
     }
     Ok(0)
 }
 
 /// syscall: ftruncate
-pub fn sys_ftruncate(fildes: usize, length: usize) -> SysResult {
+pub fn sys_ftruncate(fildes: usize, length: isize) -> SysResult {
+    if length < 0 {
+        return Err(SysError::EINVAL);
+    }
     let task = current_task().unwrap().clone();
     let file = task.with_fd_table(|f| f.get_file(fildes))?;
     log::info!("[sys_ftruncate] fd {} truncate size to {}", fildes, length);
-    file.inode().unwrap().truncate(length)?;
+    // only a regular file opened for writing may be truncated
+    if !file.flags().writable() {
+        return Err(SysError::EINVAL);
+    }
+    let inode = file.inode().ok_or(SysError::EINVAL)?;
+    if inode.inode_inner().mode().get_type() != InodeMode::FILE {
+        return Err(SysError::EINVAL);
+    }
+    inode.inode_inner().check_writable()?;
+    inode.truncate(length as usize)?;
+    inode.inode_inner().touch_mtime();
     Ok(0)
 }
 
 
+bitflags::bitflags! {
+    /// flags of memfd_create
+    pub struct MemfdFlags: u32 {
+        /// set close-on-exec on the new fd
+        const MFD_CLOEXEC = 0x1;
+        /// seals can be added with fcntl
+        const MFD_ALLOW_SEALING = 0x2;
+    }
+}
+
+/// the longest name of a memfd, NAME_MAX less the "memfd:" prefix
+const MEMFD_NAME_MAX: usize = 249;
+
+/// syscall: memfd_create
+/// creates an anonymous file living in memory and returns an fd to it,
+/// the file behaves like a regular tmpfs file but has no path
+pub fn sys_memfd_create(name: *const u8, flags: u32) -> SysResult {
+    // MFD_HUGETLB and the huge page sizes are not supported
+    let flags = MemfdFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+    let task = current_task().unwrap().clone();
+    let name = {
+        let name = UserPtrRaw::new(name)
+            .cstr_slice(&mut task.get_vm_space().lock())
+            .ok_or(SysError::EFAULT)?;
+        if name.len() > MEMFD_NAME_MAX {
+            return Err(SysError::EINVAL);
+        }
+        core::str::from_utf8(&name).map_err(|_| SysError::EINVAL)?.to_string()
+    };
+    task.ensure_can_open(1)?;
+    let file = crate::fs::shmfs::memfd_create(&name, flags.contains(MemfdFlags::MFD_ALLOW_SEALING))?;
+    let inode = file.inode().unwrap();
+    let (euid, egid) = task.with_cred(|c| (c.euid, c.egid));
+    inode.inode_inner().init_owner(euid, egid, InodeMode::from_bits_truncate(0o777));
+    inode.inode_inner().init_times();
+    let open_flags = if flags.contains(MemfdFlags::MFD_CLOEXEC) {
+        OpenFlags::O_RDWR | OpenFlags::O_CLOEXEC
+    } else {
+        OpenFlags::O_RDWR
+    };
+    file.set_flags(open_flags);
+    let fd = task.with_mut_fd_table(|table| table.install(file, open_flags.into()))?;
+    log::info!("[sys_memfd_create] memfd:{} fd {}", name, fd);
+    Ok(fd as isize)
+}
+
 /// at helper:
 /// since many "xxxat" type file system syscalls will use the same logic of getting dentry,
 /// we need to write a helper function to reduce code duplication
@@ -1038,31 +1768,26 @@ pub fn at_helper(task: Arc<TaskControlBlock>, dirfd: isize, pathname: *const u8,
             if path.starts_with("/") {
                 global_find_dentry(&path)?
             } else {
-                // getting full path (absolute path)
-                let fpath = if dirfd as i32 == AtFlags::AT_FDCWD.bits() {
-                    // look up in the current dentry
-                    let cw_dentry = task.with_cwd(|d| d.clone());
-                    rel_path_to_abs(&cw_dentry.path(), &path).unwrap()
+                // walk from the directory itself rather than from its path,
+                // which is stale once the directory has been renamed
+                let dir = if dirfd as i32 == AtFlags::AT_FDCWD.bits() {
+                    task.with_cwd(|d| d.clone())
                 } else {
-                    // look up in the current task's fd table
-                    // which the inode fd points to should be a dir
-                    let dir = task.with_fd_table(|t| t.get_file(dirfd as usize))?;
-                    let dentry = dir.dentry().unwrap();
-                    rel_path_to_abs(&dentry.path(), &path).unwrap()
+                    dirfd_dentry(&task, dirfd)?
                 };
-                global_find_dentry(&fpath)?
+                dir.walk(&path)?
             }
         }
         None => {
             if !flags.contains(AtFlags::AT_EMPTY_PATH) {
                 return Err(SysError::ENOENT);
             }
+            // the file dirfd refers to itself, a symlink opened with O_PATH is not followed
             if dirfd as i32 == AtFlags::AT_FDCWD.bits() {
-                task.with_cwd(|d| d.clone())
-            } else {
-                let file = task.with_fd_table(|t| t.get_file(dirfd as usize))?;
-                file.dentry().unwrap()
+                return Ok(task.with_cwd(|d| d.clone()));
             }
+            let file = task.with_fd_table(|t| t.get_path_file(dirfd as usize))?;
+            return file.dentry().ok_or(SysError::EBADF);
         }
     };
 
@@ -1074,15 +1799,75 @@ pub fn at_helper(task: Arc<TaskControlBlock>, dirfd: isize, pathname: *const u8,
     }
 }
 
+/// the directory an fd given as dirfd refers to, ENOTDIR if it is anything else
+fn dirfd_dentry(task: &Arc<TaskControlBlock>, dirfd: isize) -> Result<Arc<dyn Dentry>, SysError> {
+    let file = task.with_fd_table(|t| t.get_path_file(dirfd as usize))?;
+    let dentry = file.dentry().ok_or(SysError::ENOTDIR)?;
+    match dentry.inode() {
+        Some(inode) if !dentry.is_negative() && inode.inode_inner().mode().get_type() == InodeMode::DIR => Ok(dentry),
+        _ => Err(SysError::ENOTDIR),
+    }
+}
+
 /// Modify the permissions of a file or directory relative to a certain
 /// directory or location
-pub fn sys_fchmodat() -> SysResult {
+pub fn sys_fchmodat(dirfd: isize, pathname: *const u8, mode: u32, flags: i32) -> SysResult {
+    let task = current_task().unwrap().clone();
+    let at_flags = AtFlags::from_bits_truncate(flags);
+    let dentry = at_helper(task.clone(), dirfd, pathname, at_flags)?;
+    if dentry.is_negative() {
+        return Err(SysError::ENOENT);
+    }
+    log::info!("[sys_fchmodat]: {} mode {:#o}", dentry.path(), mode);
+    do_chmod(&task, dentry.inode().unwrap().inode_inner(), mode)
+}
+
+/// fchmod() changes the permissions of the file referred to by the open fd
+pub fn sys_fchmod(fd: usize, mode: u32) -> SysResult {
+    let task = current_task().unwrap().clone();
+    let file = task.with_fd_table(|t| t.get_file(fd))?;
+    let inode = file.inode().ok_or(SysError::EBADF)?;
+    do_chmod(&task, inode.inode_inner(), mode)
+}
+
+/// only the owner or root may chmod, and set-group-id is dropped
+/// when the caller is not in the group of the file
+fn do_chmod(task: &Arc<TaskControlBlock>, inode: &InodeInner, mode: u32) -> SysResult {
+    inode.check_writable()?;
+    let mut perm = InodeMode::from_bits_truncate(mode);
+    let (owns, keeps_sgid) = task.with_cred(|c| (c.owns(inode), c.is_privileged() || c.in_group(inode.gid())));
+    if !owns {
+        return Err(SysError::EPERM);
+    }
+    if !keeps_sgid {
+        perm.remove(InodeMode::SET_GID);
+    }
+    inode.chmod(perm);
+    Ok(0)
+}
+
+/// fsync() transfers the dirty cached pages and the metadata of the file referred to by fd
+/// to the disk, ahead of the flusher
+pub fn sys_fsync(fd: usize) -> SysResult {
+    let task = current_task().unwrap().clone();
+    let file = task.with_fd_table(|t| t.get_file(fd))?;
+    if let Some(inode) = file.inode() {
+        crate::fs::writeback::drain_inode(&inode)?;
+    }
+    crate::drivers::block::queue::flush_all();
+    Ok(0)
+}
+
+/// sync() writes every cached file and the plugged block writes back,
+/// the block queue counters go to the log
+pub fn sys_sync() -> SysResult {
+    crate::fs::sync_all();
     Ok(0)
 }
 
 /// umask() sets the calling process's file mode creation mask (umask) to
 /// mask & 0777 
-pub fn sys_umask(_mask: i32) -> SysResult {
-    // TODO: implement this
-    Ok(0x777)
+pub fn sys_umask(mask: i32) -> SysResult {
+    let task = current_task().unwrap();
+    Ok(task.set_umask(mask as u32) as isize)
 }
\ No newline at end of file
@@ -1,12 +1,12 @@
 use core::{hash::{BuildHasher, Hasher}, ops::DerefMut, sync::atomic::{AtomicU32, Ordering}, task::Waker, time::Duration};
 
 use alloc::{collections::vec_deque::VecDeque, sync::Arc, vec::Vec};
-use hal::{addr::{PhysAddr, VirtAddr}, println};
+use hal::{addr::VirtAddr, println};
 use hashbrown::HashMap;
 use log::{info, warn};
 use smoltcp::time;
 
-use crate::{mm::{translate_uva_checked, vm::{PageFaultAccessType, UserVmSpaceHal}, UserPtrRaw}, processor::context::SumGuard, signal::{SigSet, SIGKILL, SIGSTOP}, sync::mutex::SpinNoIrqLock, task::{self, current_task, manager::TASK_MANAGER, task::TaskControlBlock}, timer::{self, ffi::TimeSpec, get_current_time_duration, timed_task::suspend_timeout}, utils::{suspend_now, SendWrapper}};
+use crate::{fs::vfs::Inode, ipc::sysv::ShmObj, mm::{vm::{MapFlags, UserVmFile, UserVmSpaceHal}, UserPtrRaw, UserVmSpace}, processor::context::SumGuard, signal::{SigSet, SIGKILL, SIGSTOP}, sync::mutex::SpinNoIrqLock, task::{self, current_task, manager::TASK_MANAGER, task::TaskControlBlock}, timer::{self, ffi::TimeSpec, get_realtime_duration, timed_task::suspend_timeout}, utils::{suspend_now, SendWrapper}};
 
 use super::{SysError, SysResult};
 
@@ -26,10 +26,7 @@ const FUTEX_OP_CMP_GE: u32 = 5;
 
 fn add_awaiter(fm: &mut FutexManager, task: &Arc<TaskControlBlock>, key: FutexHashKey, mask: u32) {
     task.set_interruptable();
-    let wake_up_sigs = task.with_sig_manager(|s| {
-        !s.blocked_sigs
-    });
-    task.set_wake_up_sigs(wake_up_sigs);
+    task.set_wake_up_sigs(task.intr_sigs());
     fm.add_waiter(
         &key,
         FutexWaiter { 
@@ -63,21 +60,11 @@ pub async fn sys_futex(
     let task = current_task().unwrap().clone();
     
     log::info!("[sys_futex] task {}, futexop {:?}", task.tid(), futex_op);
-    let key = if is_private {
-        FutexHashKey::Private {
-            mm: task.get_raw_vm_ptr(),
-            vaddr: VirtAddr::from(uaddr as *const _ as usize),
-        }
-    } else {
-        let paddr = task.with_mut_vm_space(|vm| {
-            translate_uva_checked(
-                vm, 
-                VirtAddr::from(uaddr as *const _ as usize), 
-                PageFaultAccessType::WRITE
-            ).ok_or(SysError::EINVAL)
-        })?;
-        FutexHashKey::Shared { paddr }
+    let key_of = |addr: usize| {
+        task.with_mut_vm_space(|vm| futex_key(vm, task.get_raw_vm_ptr(), addr, is_private))
     };
+    // held until the call returns, so the object under a shared key outlives the wait
+    let (key, _pin) = key_of(uaddr as *const _ as usize)?;
     match futex_op {
         FutexOp::Wait | FutexOp::WaitBitset => {
             log::info!("[sys_futex] task {} wait at {:?}", task.tid(), key);
@@ -115,7 +102,7 @@ pub async fn sys_futex(
                         return Err(SysError::EAGAIN);
                     }
                     add_awaiter(&mut fm, &task, key, mask);
-                    let cur = get_current_time_duration();
+                    let cur = get_realtime_duration();
                     let timeout = unsafe {
                         timeout.0.read()
                     };
@@ -149,9 +136,7 @@ pub async fn sys_futex(
                 }
             }
             let mut fm = futex_manager();
-            let wake_up_sigs = task.with_sig_manager(|s| {
-                    !s.blocked_sigs
-                });
+            let wake_up_sigs = task.intr_sigs();
             if task.with_sig_manager(|s| s.check_pending_flag(wake_up_sigs)) {
                 task.set_running();
                 if fm.remove_waiter(&key, task.tid()).is_none() {
@@ -172,25 +157,12 @@ pub async fn sys_futex(
             return Err(SysError::EINVAL);
         }
         FutexOp::Requeue => {
-            let n_woke = futex_manager().wake(&key, val)?;
-            let new_key = if is_private {
-                FutexHashKey::Private {
-                    mm: task.get_raw_vm_ptr(),
-                    vaddr: (uaddr2 as *const _ as usize).into(),
-                }
-            } else {
-                let paddr = task.with_mut_vm_space(|vm| {
-                    translate_uva_checked(
-                        vm, 
-                        (uaddr2 as *const _ as usize).into(), 
-                        PageFaultAccessType::WRITE
-                    ).ok_or(SysError::EINVAL)
-                })?;
-                FutexHashKey::Shared { paddr }
-            };
+            let (new_key, _new_pin) = key_of(uaddr2 as *const _ as usize)?;
             // info!("[sys_futex] requeue {:?} to {:?}", key, new_key);
             let timeout = timeout.0 as usize;
-            futex_manager().requeue_waiters(key, new_key, timeout)?;
+            let mut fm = futex_manager();
+            let n_woke = fm.wake(&key, val)?;
+            fm.requeue_waiters(key, new_key, timeout)?;
             Ok(n_woke)
         }
         FutexOp::CmpRequeue => {
@@ -200,24 +172,11 @@ pub async fn sys_futex(
             } != val3 {
                 return Err(SysError::EAGAIN);
             }
-            let n_woke = futex_manager().wake(&key, val)?;
-            let new_key = if is_private {
-                FutexHashKey::Private {
-                    mm: task.get_raw_vm_ptr(),
-                    vaddr: (uaddr2 as *const _ as usize).into(),
-                }
-            } else {
-                let paddr = task.with_mut_vm_space(|vm| {
-                    translate_uva_checked(
-                        vm, 
-                        (uaddr2 as *const _ as usize).into(), 
-                        PageFaultAccessType::WRITE
-                    ).ok_or(SysError::EINVAL)
-                })?;
-                FutexHashKey::Shared { paddr }
-            };
+            let (new_key, _new_pin) = key_of(uaddr2 as *const _ as usize)?;
             let timeout = timeout.0 as usize;
-            futex_manager().requeue_waiters(key, new_key, timeout)?;
+            let mut fm = futex_manager();
+            let n_woke = fm.wake(&key, val)?;
+            fm.requeue_waiters(key, new_key, timeout)?;
             Ok(n_woke)
         }
         FutexOp::WakeOp => {
@@ -273,22 +232,8 @@ pub async fn sys_futex(
             };
 
             let n_woke2 = if check {
-                let key2 = if is_private {
-                    FutexHashKey::Private {
-                        mm: task.get_raw_vm_ptr(),
-                        vaddr: VirtAddr::from(uaddr2 as *const _ as usize),
-                    }
-                } else {
-                    let paddr = task.with_mut_vm_space(|vm| {
-                        translate_uva_checked(
-                            vm, 
-                            VirtAddr::from(uaddr2 as *const _ as usize), 
-                            PageFaultAccessType::WRITE
-                        ).ok_or(SysError::EINVAL)
-                    })?;
-                    FutexHashKey::Shared { paddr }
-                };
-                fm.wake(&key, val2)?
+                let (key2, _pin2) = key_of(uaddr2 as *const _ as usize)?;
+                fm.wake(&key2, val2)?
             } else {
                 0
             };
@@ -385,10 +330,49 @@ impl From<i32> for FutexOp {
 #[allow(missing_docs, unused)]
 #[derive(Debug, Hash, PartialEq, PartialOrd, Eq, Copy, Clone)]
 pub enum FutexHashKey {
-    Shared { paddr: PhysAddr },
+    /// a page of a file or a sysv shm segment, the same wherever it is mapped
+    Object { id: usize, offset: usize },
     Private { mm: usize, vaddr: VirtAddr },
 }
 
+/// keeps the object under a shared key alive while a waiter is queued on it
+#[allow(missing_docs, unused)]
+pub enum FutexPin {
+    Inode(Arc<dyn Inode>),
+    Shm(Arc<ShmObj>),
+}
+
+/// the key of the futex at `uaddr` in the address space `vm`, whose raw pointer is `mm`;
+/// a shared futex on a mapping of a file or shm segment is keyed by the object and the
+/// offset in it, so other processes mapping it elsewhere meet at the same key,
+/// anything else is private to the address space
+pub fn futex_key(vm: &UserVmSpace, mm: usize, uaddr: usize, private: bool) -> Result<(FutexHashKey, Option<FutexPin>), SysError> {
+    if uaddr % size_of::<u32>() != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let private_key = FutexHashKey::Private { mm, vaddr: uaddr.into() };
+    if private {
+        return Ok((private_key, None));
+    }
+    let area = vm.get_area_ref(VirtAddr::from(uaddr)).ok_or(SysError::EFAULT)?;
+    if !area.map_flags.contains(MapFlags::SHARED) {
+        return Ok((private_key, None));
+    }
+    let offset = area.offset + (uaddr - area.range_va.start.0);
+    match &area.file {
+        UserVmFile::File(file) => {
+            let inode = file.inode().ok_or(SysError::EINVAL)?;
+            let id = Arc::as_ptr(&inode) as *const () as usize;
+            Ok((FutexHashKey::Object { id, offset }, Some(FutexPin::Inode(inode))))
+        }
+        UserVmFile::Shm(shm) => {
+            let id = Arc::as_ptr(shm) as usize;
+            Ok((FutexHashKey::Object { id, offset }, Some(FutexPin::Shm(shm.clone()))))
+        }
+        UserVmFile::None => Ok((private_key, None)),
+    }
+}
+
 ///
 pub static FUTEX_MANAGER: SpinNoIrqLock<FutexManager> =
     SpinNoIrqLock::new(FutexManager::new());
@@ -452,6 +436,16 @@ impl BuildHasher for FutexHashKeyBuilder {
     }
 }
 
+/// the futex buckets, a queue of waiters for each key
+///
+/// The buckets are not converted to a [`WaitQueue`] yet and keep their own queues. A
+/// [`WaitQueue`] can not match a wake against the bitset of a waiter, move sleeping waiters to
+/// another key for FUTEX_REQUEUE, or take out a waiter by its tid after a timeout or a signal,
+/// so the conversion waits on it growing those. The buckets do not lose wakes meanwhile: a
+/// waiter woken here has left its bucket, and the one lock of the manager orders the wait
+/// against the check of the futex word.
+///
+/// [`WaitQueue`]: crate::sync::wait_queue::WaitQueue
 #[allow(missing_docs, unused)]
 pub struct FutexManager {
     futexs: HashMap<FutexHashKey, VecDeque<FutexWaiter>, FutexHashKeyBuilder>,
@@ -478,54 +472,44 @@ impl FutexManager {
 
     /// 用于移除任务，任务可能是过期了，也可能是被信号中断了
     pub fn remove_waiter(&mut self, key: &FutexHashKey, tid: Tid) -> Option<FutexWaiter> {
-        if let Some(waiters) = self.futexs.get_mut(key) {
-            for i in 0..waiters.len() {
-                if waiters[i].tid == tid {
-                    return waiters.remove(i);
-                }
-            }
+        let waiters = self.futexs.get_mut(key)?;
+        let idx = waiters.iter().position(|w| w.tid == tid)?;
+        let waiter = waiters.remove(idx);
+        if waiters.is_empty() {
+            self.futexs.remove(key);
         }
-        None
+        waiter
     }
 
+    /// wake the first `n` waiters in arrival order
     pub fn wake(&mut self, key: &FutexHashKey, n: u32) -> SysResult {
-        if let Some(waiters) = self.futexs.get_mut(key) {
-            let n = core::cmp::min(n as usize, waiters.len());
-            for _ in 0..n {
-                let waiter = waiters.pop_front().unwrap();
-                log::debug!("[futex_wake] task {} has been woken at {:?}", waiter.tid, key);
-                waiter.wake();
-            }
-            Ok(n as isize)
-        } else {
-            log::debug!("can not find key {key:?}");
-            Err(SysError::EINVAL)
-        }
+        self.wake_bitset(key, n, FutexWaiter::FUTEX_BITSET_MATCH_ANY)
     }
 
+    /// wake the first `n` waiters whose mask intersects `mask`, in arrival order
     pub fn wake_bitset(&mut self, key: &FutexHashKey, n: u32, mask: u32) -> SysResult {
-        if let Some(waiters) = self.futexs.get_mut(key) {
-            let mut count = 0;
-            let max_count = n as usize;
-
-            let mut i = 0;
-            let len = waiters.len();
-            while i < len && count < max_count {
-                if (waiters[len - 1 - i].mask & mask) != 0 {
-                    let waiter = waiters.remove(i).unwrap();
-                    // log::info!("[futex_wake] {:?} has been woken", waiter);
-                    waiter.wake();
-                    count += 1;
-                } else {
-                    i += 1;
-                }
-            }
-            
-            Ok(count as isize)
-        } else {
+        let Some(waiters) = self.futexs.get_mut(key) else {
+            // nobody is waiting, which is not an error
             log::debug!("can not find key {key:?}");
-            Err(SysError::EINVAL)
+            return Ok(0);
+        };
+        let max_count = n as usize;
+        let mut count = 0;
+        let mut i = 0;
+        while i < waiters.len() && count < max_count {
+            if (waiters[i].mask & mask) != 0 {
+                let waiter = waiters.remove(i).unwrap();
+                log::debug!("[futex_wake] task {} has been woken at {:?}", waiter.tid, key);
+                waiter.wake();
+                count += 1;
+            } else {
+                i += 1;
+            }
+        }
+        if waiters.is_empty() {
+            self.futexs.remove(key);
         }
+        Ok(count as isize)
     }
 
     pub fn requeue_waiters(
@@ -534,10 +518,10 @@ impl FutexManager {
         new: FutexHashKey,
         n_req: usize,
     ) -> SysResult {
-        let mut old_waiters = self.futexs.remove(&old).ok_or_else(|| {
+        let Some(mut old_waiters) = self.futexs.remove(&old) else {
             log::info!("[futex] no waiters in key {:?}", old);
-            SysError::EINVAL
-        })?;
+            return Ok(0);
+        };
         let n = core::cmp::min(n_req as usize, old_waiters.len());
         if let Some(new_waiters) = self.futexs.get_mut(&new) {
             for _ in 0..n {
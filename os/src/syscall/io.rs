@@ -1,13 +1,10 @@
 //! io related syscall
 
-use core::{future::Future, mem, pin::Pin, ptr::read, task::{Context, Poll}, time::Duration, usize};
+use core::{future::Future, pin::Pin, task::{Context, Poll}, time::Duration};
 
 use alloc::{sync::Arc, vec::Vec};
-use hal::instruction::{Instruction, InstructionHal};
-use log::SetLoggerError;
-use virtio_drivers::device::socket::SocketError;
 
-use crate::{fs::vfs::{file::PollEvents, File}, signal::SigSet, task::{current_task, signal::IntrBySignalFuture}, timer::{ffi::TimeSpec, timed_task::{TimedTaskFuture, TimedTaskOutput}}, utils::{Select2Futures, SelectOutput}};
+use crate::{fs::vfs::{file::PollEvents, File}, mm::{UserPtrRaw, UserSliceRaw}, signal::SigSet, task::{current_task, signal::IntrBySignalFuture}, timer::{ffi::TimeSpec, timed_task::{TimedTaskFuture, TimedTaskOutput}}, utils::{Select2Futures, SelectOutput}};
 
 use super::{SysError, SysResult};
 
@@ -22,25 +19,30 @@ pub struct PollFd {
     /// returned events
     revents: PollEvents,
 }
-/// future use to poll the files
-pub struct PPollFuture {
-    polls: Vec<(PollEvents, Arc<dyn File>)>,
+/// the events reported whether asked for or not
+const ALWAYS_POLLED: PollEvents = PollEvents::ERR.union(PollEvents::HUP).union(PollEvents::NVAL);
+
+/// future polling a set of files through [`File::poll`],
+/// ready with the index and revents of every ready file once one of them is
+pub struct PollFuture {
+    polls: Vec<(usize, PollEvents, Arc<dyn File>)>,
 }
 
-impl Future for PPollFuture {
+impl Future for PollFuture {
     type Output = Vec<(usize, PollEvents)>;
 
-    fn poll(self: Pin<&mut Self>, cx: &mut core::task::Context<'_>) -> core::task::Poll<Self::Output> {
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = unsafe { self.get_unchecked_mut() };
-        let mut ret_vec = Vec::new();
-        for (i, (events, file)) in this.polls.iter().enumerate() {
-            // try to poll every file in the polls vec
-            let res = unsafe { Pin::new_unchecked(&mut file.poll(*events)).poll(cx) };
-            match res {
+        let mut ret_vec = Vec::with_capacity(this.polls.len());
+        for (i, events, file) in this.polls.iter() {
+            // File::poll checks once and registers the waker, it never stays pending
+            let result = unsafe { Pin::new_unchecked(&mut file.poll(*events)).poll(cx) };
+            match result {
                 Poll::Pending => unreachable!(),
-                Poll::Ready(res) => {
-                    if !res.is_empty() {
-                        ret_vec.push((i, res));
+                Poll::Ready(result) => {
+                    let result = result & (*events | ALWAYS_POLLED);
+                    if !result.is_empty() {
+                        ret_vec.push((*i, result));
                     }
                 }
             }
@@ -53,83 +55,124 @@ impl Future for PPollFuture {
     }
 }
 
-/// syscall: ppoll
-/// it waits for one of a set of file descriptors to become ready to perform I/O.
-pub async fn sys_ppoll(fds: usize, nfds: usize, timeout_ts: usize, sigmask: usize) -> SysResult {
+/// wait until one of `polls` is ready, `timeout` runs out or a signal not in `mask` arrives.
+/// Nothing is ready when it times out
+async fn wait_for_files(
+    polls: Vec<(usize, PollEvents, Arc<dyn File>)>,
+    timeout: Option<Duration>,
+    mask: SigSet,
+) -> Result<Vec<(usize, PollEvents)>, SysError> {
     let task = current_task().unwrap().clone();
-    let raw_fds: &mut [PollFd] = unsafe {
-        Instruction::set_sum();
-        core::slice::from_raw_parts_mut(fds as *mut PollFd, nfds)
-    };
-    let mut poll_fds: Vec<PollFd> = Vec::new();
-    poll_fds.extend_from_slice(raw_fds);
-
-    let timeout = if timeout_ts == 0 {
-        None
-    } else {
-        Some(unsafe {
-            *(timeout_ts as *const TimeSpec)
-        })
+    let poll_future = PollFuture { polls };
+    task.set_interruptable();
+    task.set_wake_up_sigs(!mask);
+    let intr_future = IntrBySignalFuture {
+        task: task.clone(),
+        mask,
     };
-
-    let new_mask = if sigmask == 0 {
-        None
+    let ret = if let Some(timeout) = timeout {
+        match Select2Futures::new(TimedTaskFuture::new(timeout, poll_future), intr_future).await {
+            SelectOutput::Output1(TimedTaskOutput::OK(ret)) => Ok(ret),
+            SelectOutput::Output1(TimedTaskOutput::TimedOut) => Ok(Vec::new()),
+            SelectOutput::Output2(_) => Err(SysError::EINTR),
+        }
     } else {
-        Some(unsafe {
-            *(sigmask as *const SigSet)
-        })
+        match Select2Futures::new(poll_future, intr_future).await {
+            SelectOutput::Output1(ret) => Ok(ret),
+            SelectOutput::Output2(_) => Err(SysError::EINTR),
+        }
     };
+    task.set_running();
+    ret
+}
 
-    // put the file in the vec of polling futures
-    let mut polls = Vec::<(PollEvents, Arc<dyn File>)>::with_capacity(nfds);
-    for poll_fd in poll_fds.iter() {
-        let fd = poll_fd.fd as usize;
-        let events = poll_fd.events;
-        let file = task.with_fd_table(|t| t.get_file(fd))?;
-        polls.push((events, file));
+/// read the timeout of ppoll and pselect, None to wait forever
+fn read_timeout(timeout_ptr: usize) -> Result<Option<Duration>, SysError> {
+    if timeout_ptr == 0 {
+        return Ok(None);
+    }
+    let task = current_task().unwrap().clone();
+    let timeout = *UserPtrRaw::new(timeout_ptr as *const TimeSpec)
+        .ensure_read(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?
+        .to_ref();
+    if !timeout.is_valid() {
+        return Err(SysError::EINVAL);
     }
+    Ok(Some(timeout.into()))
+}
 
-    // save the old sig mask
-    let old_mask = task.sig_manager.lock().blocked_sigs;
-    let mut current_mask = old_mask;
-    if let Some(mask) = new_mask {
-        task.sig_manager.lock().blocked_sigs |= mask;
-        current_mask |= mask;
+/// block the signals of the user mask at `sigmask_ptr` too while waiting,
+/// returns the mask to restore
+fn block_user_sigmask(sigmask_ptr: usize) -> Result<Option<SigSet>, SysError> {
+    if sigmask_ptr == 0 {
+        return Ok(None);
     }
+    let task = current_task().unwrap().clone();
+    let mask = *UserPtrRaw::new(sigmask_ptr as *const SigSet)
+        .ensure_read(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?
+        .to_ref();
+    Ok(Some(task.with_mut_sig_manager(|m| {
+        let old = m.blocked_sigs;
+        m.blocked_sigs |= mask;
+        old
+    })))
+}
 
-    let poll_future = PPollFuture { polls };
-    task.set_interruptable();
-    task.set_wake_up_sigs(!current_mask);
+/// syscall: ppoll
+/// it waits for one of a set of file descriptors to become ready to perform I/O.
+/// A negative fd is skipped, a closed one reports POLLNVAL in its revents
+pub async fn sys_ppoll(fds: usize, nfds: usize, timeout_ts: usize, sigmask: usize) -> SysResult {
+    let task = current_task().unwrap().clone();
+    if nfds > task.with_fd_table(|t| t.rlimit()).rlim_cur {
+        return Err(SysError::EINVAL);
+    }
+    let mut poll_fds: Vec<PollFd> = UserSliceRaw::new(fds as *const PollFd, nfds)
+        .ensure_read(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?
+        .to_ref()
+        .to_vec();
+    let mut timeout = read_timeout(timeout_ts)?;
 
-    let ret_vec = if let Some(timeout) = timeout {
-        // need to set a timer
-        match TimedTaskFuture::new(timeout.into(), poll_future).await {
-            TimedTaskOutput::OK(ret_vec) => ret_vec,
-            TimedTaskOutput::TimedOut => {
-                log::info!("timeout!");
-                return Ok(0);
-            }
+    // put the file in the vec of polling futures
+    let mut polls = Vec::<(usize, PollEvents, Arc<dyn File>)>::with_capacity(nfds);
+    let mut invalid = false;
+    for (i, poll_fd) in poll_fds.iter_mut().enumerate() {
+        poll_fd.revents = PollEvents::empty();
+        if poll_fd.fd < 0 {
+            continue;
         }
-    } else {
-        let intr_future = IntrBySignalFuture {
-            task: task.clone(),
-            mask: current_mask,
-        };
-        match Select2Futures::new(poll_future, intr_future).await {
-            SelectOutput::Output1(ret_vec) => ret_vec,
-            SelectOutput::Output2(_) => return Err(SysError::EINTR),
+        match task.with_fd_table(|t| t.get_file(poll_fd.fd as usize)) {
+            Ok(file) => polls.push((i, poll_fd.events, file)),
+            Err(_) => {
+                poll_fd.revents = PollEvents::NVAL;
+                invalid = true;
+            }
         }
-    };
-    task.set_running();
-    let ret = ret_vec.len();
-    for (i, result) in ret_vec {
-        poll_fds[i].revents |= result;
     }
-    raw_fds.copy_from_slice(&poll_fds);
+    // an invalid fd is an event already, only look at the others
+    if invalid {
+        timeout = Some(Duration::ZERO);
+    }
 
+    let old_mask = block_user_sigmask(sigmask)?;
+    let mask = task.with_sig_manager(|m| m.blocked_sigs);
+    let ret = wait_for_files(polls, timeout, mask).await;
     // restore the sig mask
-    task.sig_manager.lock().blocked_sigs = old_mask;
-    Ok(ret as isize)
+    if let Some(old_mask) = old_mask {
+        task.with_mut_sig_manager(|m| m.blocked_sigs = old_mask);
+    }
+    for (i, revents) in ret? {
+        poll_fds[i].revents = revents;
+    }
+    UserSliceRaw::new(fds as *mut PollFd, nfds)
+        .ensure_write(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?
+        .to_mut()
+        .copy_from_slice(&poll_fds);
+    let ready = poll_fds.iter().filter(|poll_fd| !poll_fd.revents.is_empty()).count();
+    Ok(ready as isize)
 }
 
 #[derive(Debug)]
@@ -178,173 +221,81 @@ pub async fn sys_pselect6(
     timeout_ptr: usize,
     sigmask_ptr: usize,
 ) -> SysResult {
-    if nfds < 0 {
+    if nfds < 0 || nfds as usize > FD_SET_SIZE {
         return Err(SysError::EINVAL);
     }
-    let task = current_task().unwrap();
-    let mut readfds = unsafe {
-        if readfds_ptr == 0 {
-            None
-        }else {
-            Instruction::set_sum();
-           Some(&mut *(readfds_ptr as *mut FdSet))
-        } 
-    };
-    let mut writefds = unsafe {
-        if writefds_ptr == 0 {
-            None
-        }else {
-            Instruction::set_sum();
-            Some(&mut *(writefds_ptr as *mut FdSet))
-        }
-    };
-    let mut exceptfds = unsafe {
-        if exceptfds_ptr == 0 {
-            None
-        }else {
-            Instruction::set_sum();
-            Some(&mut *(exceptfds_ptr as *mut FdSet))
-        }
-    };
-    let timeout: Option<Duration> = unsafe {
-        if timeout_ptr == 0 {
-            None
-        }else {
-            Instruction::set_sum();
-            Some((*(timeout_ptr as *const TimeSpec)).into())
-        }
-    };
-    // log::info!(
-    //     "[sys_pselect]: readfds {:?}, writefds {:?}, exceptfds {:?}, timeout {:?}",
-    //     readfds, writefds, exceptfds, timeout
-    // );
-    let new_mask = if sigmask_ptr == 0 {
-        None
-    } else {
-        unsafe {
-            Instruction::set_sum();
-            Some(*(sigmask_ptr as *const SigSet))
+    let task = current_task().unwrap().clone();
+    let read_set = |ptr: usize| -> Result<Option<FdSet>, SysError> {
+        if ptr == 0 {
+            return Ok(None);
         }
+        let set = UserPtrRaw::new(ptr as *const FdSet)
+            .ensure_read(&mut task.get_vm_space().lock())
+            .ok_or(SysError::EFAULT)?
+            .to_ref()
+            .fds_bits;
+        Ok(Some(FdSet { fds_bits: set }))
     };
+    let readfds = read_set(readfds_ptr)?;
+    let writefds = read_set(writefds_ptr)?;
+    let exceptfds = read_set(exceptfds_ptr)?;
+    let timeout = read_timeout(timeout_ptr)?;
 
-    let mut polls= Vec::<(usize,PollEvents, Arc<dyn File>)>::with_capacity(nfds as usize);
+    let mut polls = Vec::<(usize, PollEvents, Arc<dyn File>)>::with_capacity(nfds as usize);
     for fd in 0..nfds as usize {
         let mut events = PollEvents::empty();
-        readfds.as_ref().map(|fds|{
-            if fds.is_set(fd) {
-                events.insert(PollEvents::IN);
-            }
-        });
-        writefds.as_ref().map(|fds|{
-            if fds.is_set(fd) {
-                events.insert(PollEvents::OUT);
-            }
-        });
-        if !events.is_empty() {
-            let file = task.with_fd_table(|f|f.get_file(fd))?;
-            polls.push(
-                (fd , events, file)
-            );
+        if readfds.as_ref().is_some_and(|fds| fds.is_set(fd)) {
+            events.insert(PollEvents::IN);
         }
-    }
-
-    // save the old sig mask
-    let mut prev_mask = None; 
-    if let Some(mask) = new_mask {
-        task.with_mut_sig_manager(|sig_manager| {
-            prev_mask = Some(sig_manager.blocked_sigs);
-            sig_manager.blocked_sigs |= mask;
-        })
-    }
-    task.set_interruptable();
-    task.set_wake_up_sigs(task.with_sig_manager(|m| !m.blocked_sigs));
-    let intr_future = IntrBySignalFuture {
-        task: task.clone(),
-        mask: task.with_sig_manager(|m|m.blocked_sigs),
-    };
-    let pselect_future = PSelectFuture{polls};
-    let ret = if let Some(timeout) = timeout {
-        match Select2Futures::new(
-            TimedTaskFuture::new(timeout,pselect_future),
-            intr_future
-        ).await {
-            SelectOutput::Output1(output1) => match output1 {
-                TimedTaskOutput::OK(ret) => ret,
-                TimedTaskOutput::TimedOut => {
-                    // log::info!("[sys_pselect]: timeout!");
-                    readfds.as_mut().map(|fds|fds.clear());
-                    writefds.as_mut().map(|fds|fds.clear());
-                    exceptfds.as_mut().map(|fds|fds.clear());
-                    task.set_running();
-                    // restore old mask
-                    if let Some(mask) = prev_mask {
-                        task.with_mut_sig_manager(|m|m.blocked_sigs = mask);
-                    }
-                    return Ok(0);
-                }
-            }
-            SelectOutput::Output2(_) => return Err(SysError::EINTR),
+        if writefds.as_ref().is_some_and(|fds| fds.is_set(fd)) {
+            events.insert(PollEvents::OUT);
         }
-    }else {
-        match Select2Futures::new(pselect_future, intr_future).await {
-            SelectOutput::Output1(ret) => ret,  
-            SelectOutput::Output2(_) => return Err(SysError::EINTR),
+        if exceptfds.as_ref().is_some_and(|fds| fds.is_set(fd)) {
+            events.insert(PollEvents::PRI);
         }
-    };
-
-    readfds.as_mut().map(|fds| fds.clear());
-    writefds.as_mut().map(|fds| fds.clear());
-    exceptfds.as_mut().map(|fds| fds.clear());
+        if !events.is_empty() {
+            // unlike poll, select fails on a closed fd
+            let file = task.with_fd_table(|f| f.get_file(fd))?;
+            polls.push((fd, events, file));
+        }
+    }
 
-    task.set_running(); 
+    let old_mask = block_user_sigmask(sigmask_ptr)?;
+    let mask = task.with_sig_manager(|m| m.blocked_sigs);
+    let ret = wait_for_files(polls, timeout, mask).await;
     // restore old mask
-    if let Some(mask) = prev_mask {
-        task.with_mut_sig_manager(|m| m.blocked_sigs = mask);
+    if let Some(old_mask) = old_mask {
+        task.with_mut_sig_manager(|m| m.blocked_sigs = old_mask);
     }
+    let ret = ret?;
+
+    // every set is rewritten with only the ready fds, a timeout clears them all
+    let empty_like = |set: &Option<FdSet>| set.as_ref().map(|_| FdSet { fds_bits: [0; FD_SET_LEN] });
+    let (mut ready_read, mut ready_write, mut ready_except) =
+        (empty_like(&readfds), empty_like(&writefds), empty_like(&exceptfds));
     let mut res = 0;
     for (fd, events) in ret {
-        if events.contains(PollEvents::IN) || events.contains(PollEvents::HUP){
-            // log::info!("[sys_pselect]: fd {} is ready for read", fd);
-            readfds.as_mut().map(|fds| fds.mark_fd(fd));
-            res += 1;
-        }
-        if events.contains(PollEvents::OUT) {
-            // log::info!("[sys_pselect]: fd {} is ready for write", fd);
-            writefds.as_mut().map(|fds| fds.mark_fd(fd));
-            res += 1;
-        }
-    }
-    Ok(res)
-}
-
-/// select future for aysnc select system call
-pub struct PSelectFuture {
-    polls: Vec<(usize, PollEvents, Arc<dyn File>)>,
-}
-
-impl Future for PSelectFuture {
-    type Output = Vec<(usize, PollEvents)>;
-
-    /// Return vec of futures that are ready. Return `Poll::Pending` if
-    /// no futures are ready.
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let this = unsafe { self.get_unchecked_mut() };
-        let mut ret_vec = Vec::with_capacity(this.polls.len());
-        for (fd, events, file) in this.polls.iter() {
-            let result = unsafe { Pin::new_unchecked(&mut file.poll(*events)).poll(cx) };
-            match result {
-                Poll::Pending => unreachable!(),
-                Poll::Ready(result) => {
-                    if !result.is_empty() {
-                        ret_vec.push((*fd, result))
-                    }
+        let mut mark = |asked: &Option<FdSet>, ready: &mut Option<FdSet>, wanted: PollEvents| {
+            if events.intersects(wanted) && asked.as_ref().is_some_and(|fds| fds.is_set(fd)) {
+                if let Some(fds) = ready.as_mut() {
+                    fds.mark_fd(fd);
                 }
+                res += 1;
             }
-        }
-        if ret_vec.len() > 0 {
-            Poll::Ready(ret_vec)
-        } else {
-            Poll::Pending
+        };
+        // a hang up or an error makes a read or write return at once
+        mark(&readfds, &mut ready_read, PollEvents::IN | PollEvents::HUP | PollEvents::ERR);
+        mark(&writefds, &mut ready_write, PollEvents::OUT | PollEvents::ERR);
+        mark(&exceptfds, &mut ready_except, PollEvents::PRI);
+    }
+    for (ptr, set) in [(readfds_ptr, ready_read), (writefds_ptr, ready_write), (exceptfds_ptr, ready_except)] {
+        if let Some(set) = set {
+            UserPtrRaw::new(ptr as *mut FdSet)
+                .ensure_write(&mut task.get_vm_space().lock())
+                .ok_or(SysError::EFAULT)?
+                .to_mut()
+                .fds_bits = set.fds_bits;
         }
     }
-}
\ No newline at end of file
+    Ok(res)
+}
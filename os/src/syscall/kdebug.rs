@@ -0,0 +1,54 @@
+//! not in linux, aids for debugging the kernel itself
+
+use crate::{drivers::dma::dma_pages, fs::vfs::DCACHE, mm::{stats::VmEventCounts, UserPtrRaw}, task::current_task};
+
+use super::{SysError, SysResult};
+
+/// panic the kernel on purpose, to check the crash report
+pub const KDEBUG_PANIC: usize = 1;
+/// copy the memory event counters of the process to the array at `arg`,
+/// indexed by [`crate::mm::stats::VmEvent`]
+pub const KDEBUG_VM_STATS: usize = 2;
+/// drop the dentries nobody uses, and with them the inodes only they held,
+/// returns the number of dentries dropped
+pub const KDEBUG_DROP_CACHES: usize = 3;
+/// drop the TCP segments from or to the port `arg` on the loopback device, 0 stops dropping
+pub const KDEBUG_NET_BLACKHOLE: usize = 4;
+/// run the kernel self tests which are safe on a running system,
+/// returns the number which failed, ENOSYS without the `selftest` feature
+pub const KDEBUG_SELFTEST: usize = 5;
+/// the pages of DMA memory the devices hold, the rings of their queues
+/// and the bounce buffers of the requests in flight
+pub const KDEBUG_DMA_PAGES: usize = 6;
+/// the times the calling thread called sched_yield, for the scheduler statistics
+pub const KDEBUG_YIELD_COUNT: usize = 7;
+
+/// syscall: kdebug
+/// run the debugging aid `cmd`, only reading the own counters is open to everyone
+pub fn sys_kdebug(cmd: usize, arg: usize) -> SysResult {
+    let task = current_task().unwrap();
+    match cmd {
+        KDEBUG_VM_STATS => {
+            let ptr = UserPtrRaw::new(arg as *mut VmEventCounts)
+                .ensure_write(&mut task.get_vm_space().lock())
+                .ok_or(SysError::EFAULT)?;
+            // the counters are read after the buffer faulted in, so they include that fault
+            ptr.write(task.process_vm_events());
+            Ok(0)
+        }
+        KDEBUG_YIELD_COUNT => Ok(task.yield_count() as isize),
+        _ if !task.with_cred(|c| c.is_privileged()) => Err(SysError::EPERM),
+        KDEBUG_PANIC => panic!("[sys_kdebug] panic asked for by task {}", task.tid()),
+        KDEBUG_DROP_CACHES => Ok(DCACHE.drop_unused() as isize),
+        KDEBUG_NET_BLACKHOLE => {
+            crate::net::blackhole(u16::try_from(arg).map_err(|_| SysError::EINVAL)?)?;
+            Ok(0)
+        }
+        #[cfg(feature = "selftest")]
+        KDEBUG_SELFTEST => Ok(crate::selftest::run_all() as isize),
+        #[cfg(not(feature = "selftest"))]
+        KDEBUG_SELFTEST => Err(SysError::ENOSYS),
+        KDEBUG_DMA_PAGES => Ok(dma_pages(None).total() as isize),
+        _ => Err(SysError::EINVAL),
+    }
+}
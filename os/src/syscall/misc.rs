@@ -1,12 +1,14 @@
 //! misc syscall
 #![allow(missing_docs)]
 
-use hal::constant::ConstantsHal;
+use hal::constant::{Constant, ConstantsHal};
 use hal::instruction::{Instruction, InstructionHal};
 use strum::FromRepr;
 
+use crate::mm::allocator::frames_stat;
+use crate::mm::stats::VmEvent;
 use crate::syscall::SysError;
-use crate::{fs::devfs::urandom::RNG, task::{current_task, manager::TASK_MANAGER}, timer::{get_current_time,ffi::TimeVal}};
+use crate::{fs::devfs::urandom::RNG, task::{current_task, fs::NR_OPEN, manager::TASK_MANAGER}, timer::{get_current_time,ffi::TimeVal}};
 
 use super::SysResult;
 
@@ -49,14 +51,17 @@ pub struct Sysinfo {
 }
 
 /// syscall: sysinfo
-/// TODO: unimlement
+/// only the uptime and the memory in frames are filled,
+/// the shared memory is the frames with more than one owner
 pub fn sys_sysinfo(info: usize) -> SysResult {
+    let (free, total) = frames_stat();
+    let shared: usize = hal::util::smart_point::tally().iter().map(|count| count.shared).sum();
     let sysinfo = Sysinfo {
         uptime: get_current_time() as i64,
         loads: [0; 3],
-        totalram: 0,
-        freeram: 0,
-        sharedram: 0,
+        totalram: total as u64,
+        freeram: free as u64,
+        sharedram: shared as u64,
         bufferram: 0,
         totalswap: 0,
         freeswap: 0,
@@ -64,7 +69,7 @@ pub fn sys_sysinfo(info: usize) -> SysResult {
         pad: 0,
         totalhigh: 0,
         freehigh: 0,
-        mem_uint: 0,
+        mem_uint: Constant::PAGE_SIZE as u32,
         _f: [0; _F_SIZE],
     };
     unsafe {
@@ -168,11 +173,11 @@ pub fn sys_prlimit64(pid: usize, resource: i32, new_limit: usize, old_limit: usi
 
     if old_limit != 0 {
         let limit = match resource {
-            Resource::STACK => RLimit {
-                rlim_cur: hal::constant::Constant::USER_STACK_SIZE,
-                rlim_max: hal::constant::Constant::USER_STACK_SIZE,
-            },
+            Resource::STACK => task.with_rlimit_stack(|limit| *limit),
             Resource::NOFILE => task.with_fd_table(|table| table.rlimit()),
+            Resource::DATA => task.with_rlimit_data(|limit| *limit),
+            Resource::CORE => task.with_rlimit_core(|limit| *limit),
+            Resource::MEMLOCK => task.with_rlimit_memlock(|limit| *limit),
             r => {
                 log::warn!("[sys_prlimit64] get old_limit : unimplemented {r:?}");
                 RLimit {
@@ -194,8 +199,48 @@ pub fn sys_prlimit64(pid: usize, resource: i32, new_limit: usize, old_limit: usi
         match resource {
             Resource::NOFILE => {
                 log::debug!("[sys_prlimit64] new_limit: {limit:?}");
+                if limit.rlim_cur > limit.rlim_max {
+                    return Err(SysError::EINVAL);
+                }
+                // no more than NR_OPEN fds, and only root raises the hard limit
+                let old = task.with_fd_table(|table| table.rlimit());
+                if limit.rlim_max > NR_OPEN
+                    || (limit.rlim_max > old.rlim_max && !current_task().unwrap().with_cred(|c| c.is_privileged()))
+                {
+                    return Err(SysError::EPERM);
+                }
                 task.with_mut_fd_table(|table| table.set_rlimit(limit));
             }
+            Resource::DATA => {
+                if limit.rlim_cur > limit.rlim_max {
+                    return Err(SysError::EINVAL);
+                }
+                task.with_mut_rlimit_data(|data| *data = limit);
+            }
+            Resource::CORE => {
+                if limit.rlim_cur > limit.rlim_max {
+                    return Err(SysError::EINVAL);
+                }
+                task.with_mut_rlimit_core(|core| *core = limit);
+            }
+            Resource::MEMLOCK => {
+                if limit.rlim_cur > limit.rlim_max {
+                    return Err(SysError::EINVAL);
+                }
+                // only root raises the hard limit
+                let old = task.with_rlimit_memlock(|limit| *limit);
+                if limit.rlim_max > old.rlim_max && !current_task().unwrap().with_cred(|c| c.is_privileged()) {
+                    return Err(SysError::EPERM);
+                }
+                task.with_mut_rlimit_memlock(|memlock| *memlock = limit);
+            }
+            Resource::STACK => {
+                if limit.rlim_cur > limit.rlim_max {
+                    return Err(SysError::EINVAL);
+                }
+                // takes effect at the next exec, up to the configured stack size
+                task.with_mut_rlimit_stack(|stack| *stack = limit);
+            }
             r => {
                 log::warn!("[sys_prlimit64] set new_limit : unimplemented {r:?}");
             }
@@ -272,16 +317,22 @@ pub fn sys_getrusage(who: i32, usage: usize) -> SysResult {
     let mut res = Rusage::default();
     match who {
         RUSAGE_SELF => {
-            let (utime, stime) = task.time_recorder().time_pair();
+            let (utime, stime) = task.process_time_pair();
             res.ru_utime = utime.into();
             res.ru_stime = stime.into();
+            res.ru_nivcsw = task.with_thread_group(|thread_group| {
+                thread_group.iter().map(|thread| thread.time_recorder().nivcsw()).sum()
+            });
+            let events = task.process_vm_events();
+            res.ru_minflt = events[VmEvent::MinorFault as usize];
+            res.ru_majflt = events[VmEvent::MajorFault as usize];
             unsafe {
                 let usage_ptr = usage as *mut Rusage;
                 usage_ptr.write(res);
             }
         }
         RUSAGE_CHILDREN => {
-            let (c_utime, c_stime) = task.time_recorder().child_time_pair();
+            let (c_utime, c_stime) = task.get_leader().time_recorder().child_time_pair();
             res.ru_utime = c_utime.into();
             res.ru_stime = c_stime.into();
             unsafe {
@@ -293,6 +344,9 @@ pub fn sys_getrusage(who: i32, usage: usize) -> SysResult {
             let (utime, stime) = task.time_recorder().time_pair();
             res.ru_utime = utime.into();
             res.ru_stime = stime.into();
+            res.ru_nivcsw = task.time_recorder().nivcsw();
+            res.ru_minflt = task.vm_stats.get(VmEvent::MinorFault);
+            res.ru_majflt = task.vm_stats.get(VmEvent::MajorFault);
             unsafe {
                 let usage_ptr = usage as *mut Rusage;
                 usage_ptr.write(res);
@@ -3,12 +3,14 @@
 
 use core::time::Duration;
 
-use hal::{addr::{VirtAddr, VirtAddrHal, VirtPageNumHal}, constant::{Constant, ConstantsHal}, pagetable::MapPerm, println};
+use hal::{addr::{PhysAddrHal, VirtAddr, VirtAddrHal, VirtPageNumHal}, constant::{Constant, ConstantsHal}, pagetable::MapPerm, println};
 use log::info;
 
-use crate::{config::PAGE_SIZE, ipc::sysv::SHM_MANAGER, mm::vm::{self, MapFlags, UserVmArea, UserVmAreaType, UserVmFile, UserVmSpaceHal}, task::current_task, timer::get_current_time_duration, utils::timer::TimerGuard};
+use alloc::{sync::Arc, vec, vec::Vec};
 
-use super::{SysError, SysResult};
+use crate::{config::PAGE_SIZE, fs::vfs::{inode::{InodeMode, SealFlags}, File}, ipc::sysv::SHM_MANAGER, net::socket::Socket, processor::ipi::{mm_key, shootdown_tlb}, mm::{stats::VmEvent, translate_uva_checked, vm::{self, MapFlags, PageFaultAccessType, UserVmArea, UserVmAreaType, UserVmFile, UserVmSpaceHal}}, task::{current_task, manager::TASK_MANAGER, task::TaskControlBlock}, timer::get_current_time_duration, utils::timer::TimerGuard};
+
+use super::{read_iovecs, IoVec, SysError, SysResult};
 
 bitflags! {
     // Defined in <bits/mman-linux.h>
@@ -30,6 +32,8 @@ bitflags! {
         const MAP_ANONYMOUS = 0x20;
         /// Don't check for reservations.
         const MAP_NORESERVE = 0x04000;
+        /// Create a huge page mapping.
+        const MAP_HUGETLB = 0x40000;
     }
 }
 
@@ -94,6 +98,43 @@ impl From<MmapProt> for MapPerm {
     }
 }
 
+/// only regular files and devices can back a mapping, pipes and sockets can not
+fn mmappable(file: &Arc<dyn File>) -> bool {
+    if file.is::<Socket>() {
+        return false;
+    }
+    file.inode().is_some_and(|inode| matches!(
+        inode.inode_inner().mode().get_type(),
+        InodeMode::FILE | InodeMode::CHAR | InodeMode::BLOCK
+    ))
+}
+
+/// get the file backing a mapping, checking it can be mapped with `prot`
+fn mmap_file(fd: usize, prot: MmapProt, flags: MmapFlags) -> Result<Arc<dyn File>, SysError> {
+    let task = current_task().unwrap().clone();
+    let file = task.with_fd_table(|t| t.get_file(fd))?;
+    if !mmappable(&file) {
+        return Err(SysError::ENODEV);
+    }
+    if !file.readable() {
+        return Err(SysError::EACCES);
+    }
+    // a private mapping never writes back, so only a shared one needs the file writable
+    if flags.contains(MmapFlags::MAP_SHARED) && prot.contains(MmapProt::PROT_WRITE) {
+        if !file.writable() {
+            return Err(SysError::EACCES);
+        }
+        // a write sealed file takes no new writable shared mapping
+        let write_sealed = file.inode()
+            .and_then(|inode| inode.seals().ok())
+            .is_some_and(|seals| seals.intersects(SealFlags::F_SEAL_WRITE | SealFlags::F_SEAL_FUTURE_WRITE));
+        if write_sealed {
+            return Err(SysError::EPERM);
+        }
+    }
+    Ok(file)
+}
+
 /// syscall mmap
 pub fn sys_mmap(
     addr: VirtAddr, 
@@ -110,17 +151,34 @@ pub fn sys_mmap(
 
     if length == 0 {
         return Err(SysError::EINVAL);
-    } else if addr.0 == 0 && flags.contains(MmapFlags::MAP_FIXED) {
-        return Err(SysError::EINVAL);
     } else if offset % PAGE_SIZE != 0 {
         return Err(SysError::EINVAL);
     }
+    let fixed = flags.contains(MmapFlags::MAP_FIXED);
+    if fixed && (addr.0 == 0 || addr.page_offset() != 0) {
+        return Err(SysError::EINVAL);
+    }
+    let aligned_len = length.checked_add(PAGE_SIZE - 1).ok_or(SysError::ENOMEM)? & !(PAGE_SIZE - 1);
+    if !flags.contains(MmapFlags::MAP_ANONYMOUS) && offset.checked_add(aligned_len).is_none() {
+        return Err(SysError::EOVERFLOW);
+    }
+    let end = addr.0.checked_add(aligned_len).ok_or(SysError::ENOMEM)?;
+    if fixed && end > Constant::user_addr_space().end {
+        return Err(SysError::EFAULT);
+    }
 
-    if flags.contains(MmapFlags::MAP_FIXED) {
+    // check the file before the fixed range is thrown away
+    let file = if flags.contains(MmapFlags::MAP_ANONYMOUS) {
+        None
+    } else {
+        Some(mmap_file(fd, prot, flags)?)
+    };
+
+    if fixed {
         task.with_mut_vm_space(|m| m.unmap(addr, length))?;
     }
 
-    match flags.intersection(MmapFlags::MAP_TYPE_MASK) {
+    let ret = match flags.intersection(MmapFlags::MAP_TYPE_MASK) {
         MmapFlags::MAP_SHARED => {
             if flags.contains(MmapFlags::MAP_ANONYMOUS) {
                 let start_va = task.with_mut_vm_space(|m| {
@@ -128,7 +186,7 @@ pub fn sys_mmap(
                 })?;
                 Ok(start_va.0 as _)
             } else {
-                let file = task.with_fd_table(|t| t.get_file(fd))?;
+                let file = file.unwrap();
                 let start_va = task.with_mut_vm_space(|m| {
                     m.alloc_mmap_area(addr, length, perm, flags, file, offset)
                 })?;
@@ -143,7 +201,7 @@ pub fn sys_mmap(
                 // log::info!("[sys_mmap] private anonymous: {:?}", start_va);
                 Ok(start_va.0 as _)
             } else {
-                let file = task.with_fd_table(|t| t.get_file(fd))?;
+                let file = file.unwrap();
                 // TODO: private copy on write
                 let start_va = task.with_mut_vm_space(|m| {
                     m.alloc_mmap_area(addr, length, perm, flags, file, offset)
@@ -152,14 +210,119 @@ pub fn sys_mmap(
             }
         }
         _ => Err(SysError::EINVAL),
+    };
+    let start = ret?;
+    task.vm_stats.count(VmEvent::Mmap);
+    if task.with_mut_vm_space(|m| m.lock_future()) {
+        lock_new_mapping(&task, VirtAddr::from(start as usize), length)?;
+    }
+    Ok(start)
+}
+
+/// lock the whole address space now
+pub const MCL_CURRENT: i32 = 1;
+/// lock the areas mapped from now on
+pub const MCL_FUTURE: i32 = 2;
+
+/// check `task` may lock `pages` more pages beside the `locked` ones under RLIMIT_MEMLOCK,
+/// which does not bind a privileged caller. EPERM when the limit forbids locking at all
+fn check_memlock(task: &TaskControlBlock, locked: usize, pages: usize) -> Result<(), SysError> {
+    if task.with_cred(|c| c.is_privileged()) {
+        return Ok(());
+    }
+    let limit = task.with_rlimit_memlock(|limit| limit.rlim_cur);
+    if limit == 0 {
+        return Err(SysError::EPERM);
+    }
+    if (locked + pages).saturating_mul(PAGE_SIZE) > limit {
+        return Err(SysError::ENOMEM);
+    }
+    Ok(())
+}
+
+/// lock a new mapping after mlockall(MCL_FUTURE), without room under RLIMIT_MEMLOCK
+/// the mapping is dropped again and mmap fails with EAGAIN
+fn lock_new_mapping(task: &TaskControlBlock, start: VirtAddr, length: usize) -> Result<(), SysError> {
+    task.with_mut_vm_space(|m| {
+        let pages = m.unlocked_pages(start, length)?;
+        if check_memlock(task, m.locked_pages(), pages).is_err() {
+            m.unmap(start, length)?;
+            return Err(SysError::EAGAIN);
+        }
+        m.mlock(start, length, true)
+    })
+}
+
+/// the page aligned range of [addr, addr + len) for mlock and munlock
+fn lock_range(addr: VirtAddr, len: usize) -> (VirtAddr, usize) {
+    (VirtAddr::from(addr.0 & !(PAGE_SIZE - 1)), len + addr.page_offset())
+}
+
+/// syscall mlock: fault in the pages of the range and keep them in memory
+pub fn sys_mlock(addr: VirtAddr, len: usize) -> SysResult {
+    if len == 0 {
+        return Ok(0);
+    }
+    let task = current_task().unwrap().clone();
+    let (start, len) = lock_range(addr, len);
+    task.with_mut_vm_space(|m| {
+        let pages = m.unlocked_pages(start, len)?;
+        check_memlock(&task, m.locked_pages(), pages)?;
+        m.mlock(start, len, true)
+    })?;
+    Ok(0)
+}
+
+/// syscall munlock
+pub fn sys_munlock(addr: VirtAddr, len: usize) -> SysResult {
+    if len == 0 {
+        return Ok(0);
     }
+    let task = current_task().unwrap().clone();
+    let (start, len) = lock_range(addr, len);
+    task.with_mut_vm_space(|m| {
+        m.unlocked_pages(start, len)?;
+        m.mlock(start, len, false)
+    })?;
+    Ok(0)
+}
+
+/// syscall mlockall
+pub fn sys_mlockall(flags: i32) -> SysResult {
+    if flags == 0 || flags & !(MCL_CURRENT | MCL_FUTURE) != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let task = current_task().unwrap().clone();
+    task.with_mut_vm_space(|m| {
+        if flags & MCL_CURRENT != 0 {
+            check_memlock(&task, 0, m.mapped_pages())?;
+            m.mlock_all(true)?;
+        }
+        if flags & MCL_FUTURE != 0 {
+            // the limit is checked again at each new mapping
+            check_memlock(&task, 0, 0)?;
+            m.set_lock_future(true);
+        }
+        Ok(())
+    })?;
+    Ok(0)
+}
+
+/// syscall munlockall
+pub fn sys_munlockall() -> SysResult {
+    let task = current_task().unwrap().clone();
+    task.with_mut_vm_space(|m| {
+        m.set_lock_future(false);
+        m.mlock_all(false)
+    })?;
+    Ok(0)
 }
 
 /// syscall munmap
 pub fn sys_munmap(addr: VirtAddr, mut length: usize) -> SysResult {
     let task = current_task().unwrap().clone();
-    if length == 0 {
-        return Ok(0);
+    if length == 0 || addr.page_offset() != 0 {
+        return Err(SysError::EINVAL);
     }
     length = (length - 1 + Constant::PAGE_SIZE) & !(Constant::PAGE_SIZE - 1);
     task.with_mut_vm_space(|m| {
@@ -176,32 +339,27 @@ pub fn sys_munmap(addr: VirtAddr, mut length: usize) -> SysResult {
         }
         Ok(())
     })?;
+    task.vm_stats.count(VmEvent::Munmap);
     Ok(0)
 }
 
-/// syscall mprotect
-pub fn sys_mprotect(addr: VirtAddr, mut length: usize, prot: i32) -> SysResult {
-    if addr.page_offset() != 0 || length == 0 || length % Constant::PAGE_SIZE != 0 {
+/// syscall mprotect, the pages get the new permission one by one. Those of PROT_NONE lose
+/// their ptes and an access is a SIGSEGV with SEGV_ACCERR, private pages still shared
+/// with another process stay read only until a write breaks the sharing
+pub fn sys_mprotect(addr: VirtAddr, length: usize, prot: i32) -> SysResult {
+    if addr.page_offset() != 0 {
         return Err(SysError::EINVAL);
     }
+    if length == 0 {
+        return Ok(0);
+    }
     let prot = MmapProt::from_bits_truncate(prot);
     let perm = MapPerm::from(prot);
-    // println!("[mprotect] {:#x} {:#x} {:?}", addr.0, length, prot);
     let task = current_task().unwrap().clone();
     task.with_mut_vm_space(|vm| -> SysResult {
-        let end_vpn = (addr + length).ceil();
-        let mut cur_vpn = addr.floor();
-        while cur_vpn < end_vpn {
-            if let Ok(mut vma) = vm.unmap(cur_vpn.start_addr(), length) {
-                let new_vpn = vma.range_vpn().end;
-                length -= (new_vpn.0 - cur_vpn.0) << Constant::PAGE_SIZE_BITS;
-                cur_vpn = new_vpn;
-                vma.map_perm = perm;
-                vm.push_area(vma, None);
-            } else {
-                break;
-            }
-        }
+        vm.mprotect(addr.floor()..(addr + length).ceil(), perm)?;
+        // no thread keeps writing or reading through a tlb entry of the old permission
+        shootdown_tlb(mm_key(&task));
         Ok(0)
     })
 }
@@ -239,8 +397,10 @@ pub fn sys_mremap(
     if flags.is_empty() || flags == MremapFlags::MAYMOVE {
         if old_size >= new_size {
             let mut old_area = vm.unmap(old_addr, old_size)?;
-            old_area.shrink(old_size - new_size);
+            // the area goes back whole if a huge page in it can not be split
+            let shrunk = old_area.shrink(old_size - new_size);
             vm.push_area(old_area, None);
+            shrunk?;
             return Ok(old_size as isize);
         }
         if vm.check_free(old_addr + old_size, new_size-old_size).is_ok() {
@@ -279,11 +439,106 @@ pub fn sys_mremap(
     
     let mut new_area = vm.unmap(new_addr, new_size).unwrap();
     let mut old_area = vm.unmap(old_addr, old_size)?;
-    old_area.move_frames_to(&mut new_area);
+    if let Err(err) = old_area.move_frames_to(&mut new_area) {
+        // the new area is dropped, the old one goes back
+        vm.push_area(old_area, None);
+        return Err(err);
+    }
     vm.push_area(new_area, None);
     if flags.contains(MremapFlags::DONTUNMAP) {
         vm.push_area(old_area, None);
     }
 
     Ok(new_addr.0 as isize)
-}
\ No newline at end of file
+}
+/// copy `len` bytes, within one page on both sides, from `src_va` of `src` to `dst_va` of `dst`
+/// through `buf`, faulting the pages in where their areas allow it.
+/// Only one address space is locked at a time, so a task may name itself
+fn copy_between(src: &TaskControlBlock, src_va: usize, dst: &TaskControlBlock, dst_va: usize, buf: &mut [u8]) -> Result<(), SysError> {
+    let len = buf.len();
+    {
+        let mut vm = src.get_vm_space().lock();
+        let pa = translate_uva_checked(&mut vm, VirtAddr::from(src_va), PageFaultAccessType::READ)
+            .ok_or(SysError::EFAULT)?;
+        buf.copy_from_slice(pa.get_slice::<u8>(len));
+    }
+    let mut vm = dst.get_vm_space().lock();
+    let pa = translate_uva_checked(&mut vm, VirtAddr::from(dst_va), PageFaultAccessType::WRITE)
+        .ok_or(SysError::EFAULT)?;
+    pa.get_slice_mut::<u8>(len).copy_from_slice(buf);
+    Ok(())
+}
+
+/// the bytes of an iovec array still to be transferred
+struct IoVecCursor {
+    iovs: Vec<IoVec>,
+    idx: usize,
+    off: usize,
+}
+
+impl IoVecCursor {
+    fn new(iovs: Vec<IoVec>) -> Self {
+        Self { iovs, idx: 0, off: 0 }
+    }
+
+    /// the next address and how many bytes follow it in the same iovec
+    fn peek(&mut self) -> Option<(usize, usize)> {
+        while self.idx < self.iovs.len() && self.off == self.iovs[self.idx].len {
+            self.idx += 1;
+            self.off = 0;
+        }
+        let iov = self.iovs.get(self.idx)?;
+        Some((iov.base + self.off, iov.len - self.off))
+    }
+
+    fn advance(&mut self, len: usize) {
+        self.off += len;
+    }
+}
+
+/// process_vm_readv and process_vm_writev: `write` copies from the local iovecs to the remote ones
+fn process_vm_rw(pid: usize, local_iov: usize, liovcnt: usize, remote_iov: usize, riovcnt: usize, flags: usize, write: bool) -> SysResult {
+    if flags != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let task = current_task().unwrap().clone();
+    let mut local = IoVecCursor::new(read_iovecs(&task, local_iov, liovcnt)?);
+    let mut remote = IoVecCursor::new(read_iovecs(&task, remote_iov, riovcnt)?);
+    let target = TASK_MANAGER.get_task(pid)
+        .filter(|t| !t.is_zombie())
+        .ok_or(SysError::ESRCH)?;
+    if !task.can_access_mm(&target) {
+        return Err(SysError::EPERM);
+    }
+    let (src, dst) = if write { (&task, &target) } else { (&target, &task) };
+    let mut buf = vec![0u8; PAGE_SIZE];
+    let mut total = 0;
+    while let (Some((lva, llen)), Some((rva, rlen))) = (local.peek(), remote.peek()) {
+        // stay within one page of both sides, so that each of them is translated once
+        let len = llen.min(rlen)
+            .min(PAGE_SIZE - lva % PAGE_SIZE)
+            .min(PAGE_SIZE - rva % PAGE_SIZE);
+        let (src_va, dst_va) = if write { (lva, rva) } else { (rva, lva) };
+        if let Err(err) = copy_between(src, src_va, dst, dst_va, &mut buf[..len]) {
+            // a partial transfer stops at the first fault
+            if total == 0 {
+                return Err(err);
+            }
+            break;
+        }
+        local.advance(len);
+        remote.advance(len);
+        total += len;
+    }
+    Ok(total as isize)
+}
+
+/// syscall process_vm_readv
+pub fn sys_process_vm_readv(pid: usize, local_iov: usize, liovcnt: usize, remote_iov: usize, riovcnt: usize, flags: usize) -> SysResult {
+    process_vm_rw(pid, local_iov, liovcnt, remote_iov, riovcnt, flags, false)
+}
+
+/// syscall process_vm_writev
+pub fn sys_process_vm_writev(pid: usize, local_iov: usize, liovcnt: usize, remote_iov: usize, riovcnt: usize, flags: usize) -> SysResult {
+    process_vm_rw(pid, local_iov, liovcnt, remote_iov, riovcnt, flags, true)
+}
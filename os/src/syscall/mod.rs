@@ -10,6 +10,18 @@
 //! `sys_` then the name of the syscall. You can find functions like this in
 //! submodules, and you should also implement syscalls this way.
 
+const SYSCALL_SETXATTR: usize = 5;
+const SYSCALL_LSETXATTR: usize = 6;
+const SYSCALL_FSETXATTR: usize = 7;
+const SYSCALL_GETXATTR: usize = 8;
+const SYSCALL_LGETXATTR: usize = 9;
+const SYSCALL_FGETXATTR: usize = 10;
+const SYSCALL_LISTXATTR: usize = 11;
+const SYSCALL_LLISTXATTR: usize = 12;
+const SYSCALL_FLISTXATTR: usize = 13;
+const SYSCALL_REMOVEXATTR: usize = 14;
+const SYSCALL_LREMOVEXATTR: usize = 15;
+const SYSCALL_FREMOVEXATTR: usize = 16;
 const SYSCALL_GETCWD: usize = 17;
 const SYSCALL_DUP: usize = 23;
 const SYSCALL_DUP3: usize = 24;
@@ -25,9 +37,12 @@ const SYSCALL_STATFS: usize = 43;
 const SYSCALL_FTRUNCATE: usize = 46;
 const SYSCALL_FACCESSAT: usize = 48;
 const SYSCALL_CHDIR: usize = 49;
+const SYSCALL_FCHDIR: usize = 50;
+const SYSCALL_FCHMOD: usize = 52;
 const SYSCALL_FCHMODAT: usize = 53;
 const SYSCALL_OPENAT: usize = 56;
 const SYSCALL_CLOSE: usize = 57;
+const SYSCALL_VHANGUP: usize = 58;
 const SYSCALL_PIPE: usize = 59;
 const SYSCALL_GETDENTS: usize = 61;
 const SYSCALL_LSEEK: usize = 62;
@@ -55,10 +70,12 @@ const SYSCALL_GET_ROBUST_LIST: usize = 100;
 const SYSCALL_NANOSLEEP: usize = 101;
 const SYSCALL_GETITIMER: usize = 102;
 const SYSCALL_SETITIMER: usize = 103;
+const SYSCALL_CLOCK_SETTIME: usize = 112;
 const SYSCALL_CLOCK_GETTIME: usize = 113;
 const SYSCALL_CLOCK_GETRES: usize = 114;
 const SYSCALL_CLOCK_NANOSLEEP: usize = 115;
 const SYSCALL_SYSLOG: usize = 116;
+const SYSCALL_PTRACE: usize = 117;
 const SYSCALL_SCHED_SETSCHEDULER: usize = 119;
 const SYSCALL_SCHED_GETSCHEDULER: usize = 120;
 const SYSCALL_SCHED_GETPARAM: usize = 121;
@@ -73,19 +90,37 @@ const SYSCALL_RT_SIGACTION: usize = 134;
 const SYSCALL_RT_SIGPROCMASK: usize = 135;
 const SYSCALL_RT_SIGTIMEDWAIT: usize = 137;
 const SYSCALL_RT_SIGRETURN: usize = 139;
+const SYSCALL_SETPRIORITY: usize = 140;
+const SYSCALL_GETPRIORITY: usize = 141;
 const SYSCALL_REBOOT: usize = 142;
+const SYSCALL_SETGID: usize = 144;
+const SYSCALL_SETUID: usize = 146;
+const SYSCALL_SETRESUID: usize = 147;
+const SYSCALL_GETRESUID: usize = 148;
+const SYSCALL_SETRESGID: usize = 149;
+const SYSCALL_GETRESGID: usize = 150;
 const SYSCALL_TIMES: usize = 153;
 const SYSCALL_SETPGID: usize = 154;
 const SYSCALL_GETPGID: usize = 155;
+const SYSCALL_GETSID: usize = 156;
 const SYSCALL_SETSID: usize = 157;
+const SYSCALL_GETGROUPS: usize = 158;
+const SYSCALL_SETGROUPS: usize = 159;
 const SYSCALL_UNAME: usize = 160;
+const SYSCALL_SETHOSTNAME: usize = 161;
+const SYSCALL_SETDOMAINNAME: usize = 162;
 const SYSCALL_GETRUSAGE: usize = 165;
 const SYSCALL_UMASK: usize = 166;
+const SYSCALL_PRCTL: usize = 167;
+const SYSCALL_GETCPU: usize = 168;
 const SYSCALL_GETTIMEOFDAY: usize = 169;
+const SYSCALL_SETTIMEOFDAY: usize = 170;
+const SYSCALL_ADJTIMEX: usize = 171;
 const SYSCALL_GETPID: usize = 172;
 const SYSCALL_GETPPID: usize = 173;
 const SYSCALL_GETUID: usize = 174;
 const SYSCALL_GETEUID: usize = 175;
+const SYSCALL_GETGID: usize = 176;
 const SYSCALL_GETEGID: usize = 177;
 const SYSCALL_GETTID: usize = 178;
 const SYSCALL_SYSINFO: usize = 179;
@@ -117,15 +152,28 @@ const SYSCALL_MMAP: usize = 222;
 const SYSCALL_MPROTECE: usize = 226;
 const SYSCALL_MSYNC: usize = 227;
 const SYSCALL_MLOCK: usize = 228;
+const SYSCALL_MUNLOCK: usize = 229;
+const SYSCALL_MLOCKALL: usize = 230;
+const SYSCALL_MUNLOCKALL: usize = 231;
 const SYSCALL_MADSIVE: usize = 233;
 const SYSCALL_GET_MEMPOLICY: usize = 236;
+const SYSCALL_ACCEPT4: usize = 242;
 const SYSCALL_WAITPID: usize = 260;
 const SYSCALL_PRLIMIT64: usize = 261;
+const SYSCALL_NAME_TO_HANDLE_AT: usize = 264;
+const SYSCALL_OPEN_BY_HANDLE_AT: usize = 265;
+const SYSCALL_PROCESS_VM_READV: usize = 270;
+const SYSCALL_PROCESS_VM_WRITEV: usize = 271;
 const SYSCALL_RENAMEAT2: usize = 276;
 const SYSCALL_GETRANDOM: usize = 278;
+const SYSCALL_MEMFD_CREATE: usize = 279;
 const SYSCALL_MEMBARRIER: usize = 283;
 const SYSCALL_STATX: usize = 291;
 const SYSCALL_CLONE3: usize = 435;
+/// not in linux, batched file operations, see [`batch`]
+const SYSCALL_IO_SUBMIT_BATCH: usize = 1024;
+/// not in linux, debugging aids, see [`kdebug`]
+const SYSCALL_KDEBUG: usize = 1025;
 
 pub mod fs;
 /// futex
@@ -145,6 +193,18 @@ pub mod net;
 /// ipc
 pub mod ipc;
 pub mod reboot;
+/// process tracing
+pub mod ptrace;
+/// process control and syscall filtering
+pub mod prctl;
+/// batched submission of file operations
+pub mod batch;
+/// in-kernel syscall tracing
+pub mod trace;
+/// debugging aids for the kernel
+pub mod kdebug;
+/// extended attributes
+pub mod xattr;
 use alloc::format;
 use fatfs::info;
 pub use fs::*;
@@ -153,22 +213,59 @@ use hal::{addr::VirtAddr, println};
 use io::*;
 use ipc::sysv::{sys_shmat, sys_shmctl, sys_shmdt, sys_shmget};
 use misc::*;
-use mm::{sys_mmap, sys_mprotect, sys_mremap, sys_munmap};
+use mm::{sys_mlock, sys_mlockall, sys_mmap, sys_mprotect, sys_munlock, sys_munlockall, sys_mremap, sys_munmap, sys_process_vm_readv, sys_process_vm_writev};
 use net::*;
 pub use process::*;
 pub use time::*;
 pub use signal::*;
 pub use sche::*;
 pub use reboot::*;
+pub use ptrace::*;
+pub use prctl::*;
+use trace::{trace_syscall_enter, trace_syscall_exit};
+use batch::sys_io_submit_batch;
+use kdebug::sys_kdebug;
+use xattr::*;
 pub use self::sys_error::SysError;
-use crate::{fs::RenameFlags, mm::{UserPtr, UserPtrRaw}, signal::{SigAction, SigSet}, task::current_task, timer::ffi::{TimeVal, Tms}, utils::{timer::TimerGuard, SendWrapper}};
+use crate::{fs::RenameFlags, mm::{UserPtr, UserPtrRaw}, signal::{SigAction, SigSet}, task::{current_task, task::NO_SYSCALL}, timer::ffi::{TimeVal, Tms}, utils::{timer::TimerGuard, SendWrapper}};
 /// The result of a syscall, either Ok(return value) or Err(error code)
 pub type SysResult = Result<isize, SysError>;
 
 /// handle syscall exception with `syscall_id` and other arguments
 pub async fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
     // log::info!("task {}, syscall id: {}", current_task().unwrap().tid() ,syscall_id);
+    let traced = current_task().unwrap().syscall_trace();
+    if traced {
+        trace_syscall_enter(current_task().unwrap(), syscall_id, &args);
+    }
+    // named in the report if the kernel panics inside
+    current_task().unwrap().set_current_syscall(syscall_id);
+    let ret = match check_syscall_filter(syscall_id) {
+        Ok(()) => dispatch(syscall_id, args).await,
+        Err(err) => -err.code(),
+    };
+    current_task().unwrap().set_current_syscall(NO_SYSCALL);
+    if traced {
+        trace_syscall_exit(current_task().unwrap(), syscall_id, ret);
+    }
+    ret
+}
+
+/// call the handler of syscall `syscall_id`
+async fn dispatch(syscall_id: usize, args: [usize; 6]) -> isize {
     let result = match syscall_id { 
+        SYSCALL_SETXATTR => sys_setxattr(args[0] as *const u8, args[1] as *const u8, args[2], args[3], args[4] as i32),
+        SYSCALL_LSETXATTR => sys_lsetxattr(args[0] as *const u8, args[1] as *const u8, args[2], args[3], args[4] as i32),
+        SYSCALL_FSETXATTR => sys_fsetxattr(args[0], args[1] as *const u8, args[2], args[3], args[4] as i32),
+        SYSCALL_GETXATTR => sys_getxattr(args[0] as *const u8, args[1] as *const u8, args[2], args[3]),
+        SYSCALL_LGETXATTR => sys_lgetxattr(args[0] as *const u8, args[1] as *const u8, args[2], args[3]),
+        SYSCALL_FGETXATTR => sys_fgetxattr(args[0], args[1] as *const u8, args[2], args[3]),
+        SYSCALL_LISTXATTR => sys_listxattr(args[0] as *const u8, args[1], args[2]),
+        SYSCALL_LLISTXATTR => sys_llistxattr(args[0] as *const u8, args[1], args[2]),
+        SYSCALL_FLISTXATTR => sys_flistxattr(args[0], args[1], args[2]),
+        SYSCALL_REMOVEXATTR => sys_removexattr(args[0] as *const u8, args[1] as *const u8),
+        SYSCALL_LREMOVEXATTR => sys_lremovexattr(args[0] as *const u8, args[1] as *const u8),
+        SYSCALL_FREMOVEXATTR => sys_fremovexattr(args[0], args[1] as *const u8),
         SYSCALL_GETCWD => sys_getcwd(args[0] as usize, args[1] as usize),
         SYSCALL_DUP => sys_dup(args[0] as usize),
         SYSCALL_DUP3 => sys_dup3(args[0] as usize, args[1] as usize, args[2] as u32),
@@ -178,15 +275,20 @@ pub async fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         SYSCALL_MKDIR => sys_mkdirat(args[0] as isize, args[1] as *const u8, args[2] as usize),
         SYSCALL_UNLINKAT => sys_unlinkat(args[0] as isize, args[1] as *const u8, args[3] as i32),
         SYSCALL_SYMLINKAT => sys_symlinkat(args[0] as *const u8, args[1] as isize, args[2] as *const u8),
+        SYSCALL_NAME_TO_HANDLE_AT => sys_name_to_handle_at(args[0] as isize, args[1] as *const u8, args[2], args[3], args[4] as i32),
+        SYSCALL_OPEN_BY_HANDLE_AT => sys_open_by_handle_at(args[0] as isize, args[1], args[2] as u32),
         SYSCALL_LINKAT => sys_linkat(args[0] as isize, args[1] as *const u8, args[2] as isize, args[3] as *const u8, args[4] as i32),
         SYSCALL_MOUNT => sys_mount(args[0] as *const u8, args[1] as *const u8, args[2] as *const u8, args[3] as u32, args[4] as usize),
-        SYSCALL_STATFS => sys_statfs(args[0], args[1]),
-        SYSCALL_FTRUNCATE => sys_ftruncate(args[0], args[1]),
+        SYSCALL_STATFS => sys_statfs(args[0] as *const u8, args[1]),
+        SYSCALL_FTRUNCATE => sys_ftruncate(args[0], args[1] as isize),
         SYSCALL_FACCESSAT => sys_faccessat(args[0] as isize, args[1] as *const u8, args[2], args[3] as i32),
         SYSCALL_UMOUNT2 => sys_umount2(args[0] as *const u8, args[1] as u32),
         SYSCALL_CHDIR => sys_chdir(args[0] as *const u8),
-        SYSCALL_FCHMODAT => sys_fchmodat(),
+        SYSCALL_FCHDIR => sys_fchdir(args[0]),
+        SYSCALL_FCHMOD => sys_fchmod(args[0], args[1] as u32),
+        SYSCALL_FCHMODAT => sys_fchmodat(args[0] as isize, args[1] as *const u8, args[2] as u32, args[3] as i32),
         SYSCALL_CLOSE => sys_close(args[0]),
+        SYSCALL_VHANGUP => sys_vhangup(),
         SYSCALL_PIPE => sys_pipe2(args[0] as *mut i32, args[1] as u32),
         SYSCALL_GETDENTS => sys_getdents64(args[0], args[1], args[2]),
         SYSCALL_LSEEK => sys_lseek(args[0], args[1] as isize, args[2]),
@@ -212,15 +314,17 @@ pub async fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         SYSCALL_NANOSLEEP => sys_nanosleep(args[0].into(),args[1].into()).await,
         SYSCALL_GETITIMER => sys_getitimer(args[0], args[1]),
         SYSCALL_SETITIMER => sys_setitimer(args[0],args[1],args[2]),
+        SYSCALL_CLOCK_SETTIME => sys_clock_settime(args[0], args[1]),
         SYSCALL_CLOCK_GETTIME => sys_clock_gettime(args[0], args[1]),
         SYSCALL_CLOCK_GETRES => sys_clock_getres(args[0], args[1]),
         SYSCALL_CLOCK_NANOSLEEP => sys_clock_nanosleep(args[0], args[1], args[2], args[3]).await,
         SYSCALL_SYSLOG => sys_syslog(args[0], args[1], args[2]),
-        SYSCALL_SCHED_SETAFFINITY => sys_sched_setaffinity(args[0] , args[1] , args[2] ),
+        SYSCALL_PTRACE => sys_ptrace(args[0], args[1], args[2], args[3]),
+        SYSCALL_SCHED_SETAFFINITY => sys_sched_setaffinity(args[0] , args[1] , args[2] ).await,
         SYSCALL_SCHED_GETAFFINITY => sys_sched_getaffinity(args[0] , args[1] , args[2] ),
-        SYSCALL_SCHED_GETSCHEDULER => sys_sched_getscheduler(),
-        SYSCALL_SCHED_SETSCHEDULER => sys_sched_setscheduler(),
-        SYSCALL_SCHED_GETPARAM => sys_sched_getparam(),
+        SYSCALL_SCHED_GETSCHEDULER => sys_sched_getscheduler(args[0] as isize),
+        SYSCALL_SCHED_SETSCHEDULER => sys_sched_setscheduler(args[0] as isize, args[1] as i32, args[2]),
+        SYSCALL_SCHED_GETPARAM => sys_sched_getparam(args[0] as isize, args[1]),
         SYSCALL_YIELD => sys_yield().await,
         SYSCALL_KILL => sys_kill(args[0] as isize, args[1] as i32),
         SYSCALL_TKILL => sys_tkill(args[0] as isize, args[1] as i32),
@@ -230,17 +334,35 @@ pub async fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         SYSCALL_RT_SIGPROCMASK => sys_rt_sigprocmask(args[0] as i32, args[1] as *const u32, args[2] as *mut SigSet),
         SYSCALL_RT_SIGRETURN => sys_rt_sigreturn(),
         SYSCALL_RT_SIGTIMEDWAIT => sys_rt_sigtimedwait(args[0] , args[1] , args[2] ).await,
-        SYSCALL_REBOOT => sys_reboot(args[0] as _, args[0] as _, args[0] as _, args[0]).await,
+        SYSCALL_SETPRIORITY => sys_setpriority(args[0] as i32, args[1] as u32, args[2] as i32),
+        SYSCALL_GETPRIORITY => sys_getpriority(args[0] as i32, args[1] as u32),
+        SYSCALL_REBOOT => sys_reboot(args[0] as _, args[1] as _, args[2] as _, args[3]).await,
         SYSCALL_TIMES => sys_times(args[0]),
         SYSCALL_UNAME => sys_uname(args[0]),
+        SYSCALL_SETHOSTNAME => sys_sethostname(args[0], args[1]),
+        SYSCALL_SETDOMAINNAME => sys_setdomainname(args[0], args[1]),
         SYSCALL_UMASK => sys_umask(args[0] as i32),
+        SYSCALL_PRCTL => sys_prctl(args[0] as i32, args[1], args[2], args[3], args[4]),
+        SYSCALL_GETCPU => sys_getcpu(args[0], args[1], args[2]),
         SYSCALL_GETTIMEOFDAY => sys_gettimeofday(args[0]),
+        SYSCALL_SETTIMEOFDAY => sys_settimeofday(args[0], args[1]),
+        SYSCALL_ADJTIMEX => sys_adjtimex(args[0]),
         SYSCALL_GETPID => sys_getpid(),
         SYSCALL_GETPPID => sys_getppid(),
         SYSCALL_GETUID => sys_getuid(),
         SYSCALL_GETEUID => sys_geteuid(),
+        SYSCALL_GETGID => sys_getgid(),
         SYSCALL_GETEGID => sys_getegid(),
+        SYSCALL_SETUID => sys_setuid(args[0] as u32),
+        SYSCALL_SETGID => sys_setgid(args[0] as u32),
+        SYSCALL_SETRESUID => sys_setresuid(args[0] as u32, args[1] as u32, args[2] as u32),
+        SYSCALL_GETRESUID => sys_getresuid(args[0], args[1], args[2]),
+        SYSCALL_SETRESGID => sys_setresgid(args[0] as u32, args[1] as u32, args[2] as u32),
+        SYSCALL_GETRESGID => sys_getresgid(args[0], args[1], args[2]),
+        SYSCALL_GETGROUPS => sys_getgroups(args[0], args[1]),
+        SYSCALL_SETGROUPS => sys_setgroups(args[0], args[1]),
         SYSCALL_GETTID => sys_gettid(),
+        SYSCALL_GETSID => sys_getsid(args[0]),
         SYSCALL_SETSID => sys_setsid(),
         SYSCALL_SYSINFO => sys_sysinfo(args[0]),
         SYSCALL_SHMGET => sys_shmget(args[0] as _, args[1] as _, args[2] as _),
@@ -251,22 +373,28 @@ pub async fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         SYSCALL_GETPGID => sys_getpgid(args[0]),
         SYSCALL_CLONE => sys_clone(args[0] as u64, args[1].into(), args[2].into(), args[3].into(), args[4].into()),
         SYSCALL_CLONE3 => sys_clone3(args[0], args[1]),
+        SYSCALL_IO_SUBMIT_BATCH => sys_io_submit_batch(args[0], args[1], args[2]).await,
+        SYSCALL_KDEBUG => sys_kdebug(args[0], args[1]),
         SYSCALL_WAITPID => sys_waitpid(args[0] as isize, args[1], args[2] as i32).await,
         SYSCALL_PRLIMIT64 => sys_prlimit64(args[0], args[1] as i32, args[2], args[3]),
         SYSCALL_GETRUSAGE => sys_getrusage(args[0] as i32, args[1]),
         SYSCALL_EXEC => sys_execve(args[0] , args[1], args[2]).await,
         SYSCALL_BRK => sys_brk(VirtAddr::from(args[0])),
         SYSCALL_MUNMAP => sys_munmap(VirtAddr::from(args[0]), args[1]),
+        SYSCALL_PROCESS_VM_READV => sys_process_vm_readv(args[0], args[1], args[2], args[3], args[4], args[5]),
+        SYSCALL_PROCESS_VM_WRITEV => sys_process_vm_writev(args[0], args[1], args[2], args[3], args[4], args[5]),
         SYSCALL_MMAP => sys_mmap(VirtAddr::from(args[0]), args[1], args[2] as i32, args[3] as i32, args[4], args[5]),
         SYSCALL_MREMAP => sys_mremap(VirtAddr::from(args[0]), args[1], args[2], args[3] as i32, args[4]),
         SYSCALL_RENAMEAT2 => sys_renameat2(args[0] as isize, args[1] as *const u8, args[2] as isize, args[3] as *const u8, args[4] as i32),
         SYSCALL_GETRANDOM => sys_getrandom(args[0], args[1], args[2]),
+        SYSCALL_MEMFD_CREATE => sys_memfd_create(args[0] as *const u8, args[1] as u32),
         SYSCALL_STATX => sys_statx(args[0] as _, args[1] as _, args[2] as _, args[3] as _, args[4].into()),
         SYSCALL_SOCKET => sys_socket(args[0], args[1] as i32, args[2]),
         SYSCALL_SOCKETPAIR => sys_socketpair(args[0], args[1],  args[2], args[3]),
         SYSCALL_BIND => sys_bind(args[0], args[1], args[2]),
         SYSCALL_LISTEN => sys_listen(args[0], args[1]),
         SYSCALL_ACCEPT => sys_accept(args[0], args[1], args[2]).await,
+        SYSCALL_ACCEPT4 => sys_accept4(args[0], args[1], args[2], args[3] as i32).await,
         SYSCALL_CONNECT => sys_connect(args[0], args[1], args[2]).await,
         SYSCALL_GETSOCKNAME => sys_getsockname(args[0], args[1], args[2]),
         SYSCALL_GETPEERNAME => sys_getpeername(args[0], args[1], args[2]),
@@ -278,13 +406,16 @@ pub async fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         SYSCALL_SENDMSG => sys_sendmsg(args[0], args[1], args[2]).await,
         SYSCALL_RECVMSG => sys_recvmsg(args[0], args[1], args[2]).await,
         SYSCALL_MPROTECE => sys_mprotect(args[0].into(), args[1], args[2] as _),
+        SYSCALL_MEMBARRIER => sys_membarrier(args[0], args[1], args[2]),
         SYSCALL_MADSIVE =>  sys_temp(),
         SYSCALL_GET_MEMPOLICY => sys_temp(),
-        SYSCALL_SYNC => sys_temp(),
-        SYSCALL_FSYNC => sys_temp(),
+        SYSCALL_SYNC => sys_sync(),
+        SYSCALL_FSYNC => sys_fsync(args[0]),
         SYSCALL_MSYNC => sys_temp(),
-        SYSCALL_MLOCK => sys_temp(),
-        SYSCALL_MEMBARRIER => sys_temp(),
+        SYSCALL_MLOCK => sys_mlock(args[0].into(), args[1]),
+        SYSCALL_MUNLOCK => sys_munlock(args[0].into(), args[1]),
+        SYSCALL_MLOCKALL => sys_mlockall(args[0] as _),
+        SYSCALL_MUNLOCKALL => sys_munlockall(),
         _ => { 
             log::warn!("Unsupported syscall_id: {}", syscall_id);
             Err(SysError::ENOSYS)
@@ -1,13 +1,13 @@
-use core::{any::Any, clone, mem, option, panic, ptr};
+use core::{any::Any, clone, mem, option, panic, ptr, time::Duration};
 
 use alloc::{ffi::CString, sync::Arc, task, vec::Vec,vec};
 use fatfs::{info, warn};
-use hal::{addr, instruction::{Instruction, InstructionHal}, println};
+use hal::{addr, println};
 use lwext4_rust::bindings::EXT4_SUPERBLOCK_FLAGS_TEST_FILESYS;
 
-use crate::{config::PAGE_SIZE, fs::{pipefs, OpenFlags}, net::{addr::{SockAddr, SockAddrIn4, SockAddrIn6}, socket::{self, Sock}, tcp::TcpSocket, SaFamily}, signal::SigSet, task::{current_task, fs::{FdFlags, FdInfo}}, utils::yield_now};
+use crate::{config::PAGE_SIZE, fs::{pipefs, OpenFlags}, mm::{UserPtrRaw, UserSliceRaw}, net::{addr::{SockAddr, SockAddrIn4, SockAddrIn6, ZERO_IPV4_ENDPOINT}, keepalive::{KeepAlive, MAX_TCP_KEEPCNT, MAX_TCP_KEEPIDLE, MAX_TCP_KEEPINTVL}, socket::{self, Sock}, tcp::TcpSocket, BufLens, SaFamily, SOMAXCONN}, task::{current_task, fs::FdFlags, task::TaskControlBlock}, utils::yield_now};
 
-use super::{IoVec, SysError, SysResult};
+use super::{read_iovecs, SysError, SysResult};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 /// Socket types
@@ -68,6 +68,10 @@ pub const SOCK_CLOEXEC: i32 = 0x80000;
 pub fn sys_socket(domain: usize, types: i32, _protocol: usize) -> SysResult {
     log::info!("[sys_socket] domain: {:?}, types: {:?}, protocol: {:?}", domain, types, _protocol);
     let domain = SaFamily::try_from(domain as u16)?;
+    // both families go through eth0, which a kernel without a NIC does not bring up
+    if !crate::net::is_up() {
+        return Err(SysError::EAFNOSUPPORT);
+    }
     let mut types = types as i32;
     let mut nonblock = false;
     // file descriptor flags
@@ -83,16 +87,10 @@ pub fn sys_socket(domain: usize, types: i32, _protocol: usize) -> SysResult {
     }
 
     let types = SocketType::try_from(types)?;
-    let socket = socket::Socket::new(domain,types, nonblock);
-    let fd_info = FdInfo {
-        file: Arc::new(socket),
-        flags: flags.into(),
-    };
     let task = current_task().unwrap();
-    let fd = task.with_mut_fd_table(|t|t.alloc_fd())?;
-    task.with_mut_fd_table(|t| {
-        t.put_file(fd, fd_info).or_else(|e|Err(e))
-    })?;
+    task.ensure_can_open(1)?;
+    let socket = socket::Socket::new(domain,types, nonblock);
+    let fd = task.with_mut_fd_table(|t| t.install(Arc::new(socket), flags.into()))?;
     log::info!("[sys_socket]socket types:{:?}, fd: {}", types,fd);
     Ok(fd as isize)
 }
@@ -103,30 +101,7 @@ pub fn sys_bind(fd: usize, addr: usize, addr_len: usize) -> SysResult {
         return Err(SysError::EBADF);
     }
     let task = current_task().unwrap();
-    let family = SaFamily::try_from(unsafe {
-        Instruction::set_sum();
-        *(addr as *const u16)
-    })?;
-    let local_addr = match family {
-        SaFamily::AfInet => {
-            if addr_len < size_of::<SockAddrIn4>() {
-                return Err(SysError::EINVAL);
-            }
-            Ok(SockAddr{
-                ipv4: unsafe { *(addr as *const SockAddrIn4)},
-            })
-        }
-        SaFamily::AfInet6 => {
-            if addr_len < size_of::<SockAddrIn6>() {
-                return Err(SysError::EINVAL);
-            }
-            Ok(SockAddr{
-                ipv6: unsafe {
-                    *(addr as *const SockAddrIn6)
-                }
-            })
-        },
-    }?;
+    let local_addr = read_sockaddr(task, addr, addr_len)?;
     log::info!("[sys_bind] local_addr's port is: {}",unsafe {
         local_addr.ipv4
     });
@@ -142,8 +117,8 @@ pub fn sys_bind(fd: usize, addr: usize, addr_len: usize) -> SysResult {
 
 /// Mark the stream socket referenced by the file descriptor `sockfd` as
 /// passive. This socket will be used later to accept connections from other
-/// (active) sockets
-pub fn sys_listen(fd: usize, _backlog: usize) -> SysResult {
+/// (active) sockets, the backlog is capped by sysctl net/core/somaxconn
+pub fn sys_listen(fd: usize, backlog: usize) -> SysResult {
     if (fd as isize) < 0 {
         return Err(SysError::EBADF);
     }
@@ -154,7 +129,9 @@ pub fn sys_listen(fd: usize, _backlog: usize) -> SysResult {
         .unwrap_or_else(|_| {
             panic!("Failed to downcast to socket::Socket")
         });
-    socket_file.sk.listen()?;
+    // a negative backlog is taken as unsigned, so asks for the cap
+    let backlog = (backlog as i32 as u32 as usize).min(SOMAXCONN.get());
+    socket_file.sk.listen(backlog)?;
     Ok(0)
 }
 
@@ -167,27 +144,7 @@ pub async fn sys_connect(fd: usize, addr: usize, addr_len: usize) -> SysResult {
         return Err(SysError::EBADF);
     }
     let task = current_task().unwrap().clone();
-    let remote_addr = match SaFamily::try_from(unsafe {
-        Instruction::set_sum();
-        *(addr as *const u16)
-    })? {
-        SaFamily::AfInet => {
-            if addr_len < size_of::<SockAddrIn4>() {
-                return Err(SysError::EINVAL);
-            }
-            Ok(SockAddr{
-                ipv4: unsafe { *(addr as *const SockAddrIn4) },
-            })
-        }
-        SaFamily::AfInet6 => {
-            if addr_len < size_of::<SockAddrIn6>() {
-                return Err(SysError::EINVAL);
-            }
-            Ok(SockAddr{
-                ipv6: unsafe { *(addr as *const SockAddrIn6) },
-            })
-        }
-    }?;
+    let remote_addr = read_sockaddr(&task, addr, addr_len)?;
     // log::info!("[sys_connect] remote_addr is: {}",
     //     unsafe {
     //         remote_addr.ipv4
@@ -217,56 +174,44 @@ pub async fn sys_connect(fd: usize, addr: usize, addr_len: usize) -> SysResult {
 /// incoming connections. It extracts the first connection request on
 /// the queue of pending connections, creates a new socket for the
 /// connection, and returns a new file descriptor referring to that
-/// socket. The newly created socket is usually in the `ESTABLISHED`
-
+/// socket. The newly created socket is usually in the `ESTABLISHED` state.
+///
+/// As on Linux the new socket does not inherit `O_NONBLOCK` or `FD_CLOEXEC`
+/// from the listening one, use `accept4` to set them atomically.
 pub async fn sys_accept(fd: usize, addr: usize, addr_len: usize) -> SysResult {
+    sys_accept4(fd, addr, addr_len, 0).await
+}
+
+/// `accept` with `flags`: SOCK_NONBLOCK sets O_NONBLOCK on the new socket
+/// and SOCK_CLOEXEC sets FD_CLOEXEC on the new fd
+pub async fn sys_accept4(fd: usize, addr: usize, addr_len: usize, flags: i32) -> SysResult {
     if (fd as isize) < 0 {
         return Err(SysError::EBADF);
     }
+    if flags & !(SOCK_NONBLOCK | SOCK_CLOEXEC) != 0 {
+        return Err(SysError::EINVAL);
+    }
     let task = current_task().unwrap();
     let socket_file = task.with_fd_table(|table| {
         table.get_file(fd)})?
         .downcast_arc::<socket::Socket>()
-        .unwrap_or_else(|_| {
-            panic!("Failed to downcast to socket::Socket")
-        });
-    // moniter accept, allow sig_kill and sig_stop to interrupt
-    task.set_interruptable();
-    let old_mask = task.sig_manager.lock().blocked_sigs;
-    task.set_wake_up_sigs(!old_mask);
+        .map_err(|_| SysError::ENOTSOCK)?;
+    // the wait for a connection is interruptible, see TcpSocket::block_on
     let accept_sk = socket_file.sk.accept().await?;
-    task.set_running();
     log::info!("get accept correct");
-    let peer_addr_endpoint = accept_sk.peer_addr().unwrap();
+    // the connection may already be reset, report an unspecified peer rather than failing
+    let peer_addr_endpoint = accept_sk.peer_addr().unwrap_or(ZERO_IPV4_ENDPOINT);
     let peer_addr = SockAddr::from_endpoint(peer_addr_endpoint);
     // log::info!("Accept a connection from {:?}", peer_addr);
-    // write to pointer
-    unsafe {
-        match SaFamily::try_from(peer_addr.family).unwrap() {
-            SaFamily::AfInet => {
-                let addr_ptr = addr as *mut SockAddrIn4;
-                addr_ptr.write_volatile(peer_addr.ipv4);
-                let addr_len_ptr = addr_len as *mut u32;
-                addr_len_ptr.write_volatile(size_of::<SockAddrIn4>() as u32);
-            }
-            SaFamily::AfInet6 => {
-                let addr_ptr = addr as *mut SockAddrIn6;
-                addr_ptr.write_volatile(peer_addr.ipv6);
-                let addr_len_ptr = addr_len as *mut u32;
-                addr_len_ptr.write_volatile(size_of::<SockAddrIn6>() as u32);
-            },
-        }
-    }
+    write_sockaddr(task, addr, addr_len, &peer_addr)?;
 
-    let accept_socket = Arc::new(socket::Socket::from_another(&socket_file, Sock::TCP(accept_sk)));
-    let fd_info = FdInfo {
-        file: accept_socket,
-        flags: OpenFlags::empty().into(),
-    };
-    let new_fd = task.with_mut_fd_table(|t|t.alloc_fd())?;
-    task.with_mut_fd_table(|t| {
-        t.put_file(new_fd, fd_info)
-    })?;
+    let non_block = flags & SOCK_NONBLOCK != 0;
+    let mut fd_flags = OpenFlags::empty();
+    if flags & SOCK_CLOEXEC != 0 {
+        fd_flags |= OpenFlags::O_CLOEXEC;
+    }
+    let accept_socket = Arc::new(socket::Socket::from_another(&socket_file, Sock::TCP(accept_sk), non_block));
+    let new_fd = task.with_mut_fd_table(|t| t.install(accept_socket, fd_flags.into()))?;
     Ok(new_fd as isize)
 }
 
@@ -285,55 +230,20 @@ pub async fn sys_sendto(
     }
     // log::info!("addr is {}, addr_len is {}", addr, addr_len);
     let task = current_task().unwrap().clone();
-    let buf_slice = unsafe {
-        core::slice::from_raw_parts_mut(buf as *mut u8, len)
-    };
     let socket_file = task.with_fd_table(|table| {
         table.get_file(fd)})?
         .downcast_arc::<socket::Socket>()
-        .unwrap_or_else(|_| {
-            panic!("Failed to downcast to socket::Socket")
-        });
-    task.set_interruptable();
-    let bytes = match socket_file.sk_type {
-        SocketType::DGRAM => {
-            let remote_addr = if addr != 0 {  Some(
-                match SaFamily::try_from(unsafe {
-                    Instruction::set_sum();
-                    *(addr as *const u16)
-                })? {
-                    SaFamily::AfInet => {
-                        if addr_len < size_of::<SockAddrIn4>() {
-                            log::warn!("sys_sendto: addr_len < size_of::<SockAddrIn4>() which is {}",size_of::<SockAddrIn4>());
-                            return Err(SysError::EINVAL);
-                        }
-                        Ok(SockAddr{
-                            ipv4: unsafe { *(addr as *const SockAddrIn4) },
-                        })
-                    }
-                    SaFamily::AfInet6 => {
-                        if addr_len < size_of::<SockAddrIn6>() {
-                            return Err(SysError::EINVAL);
-                        }
-                        Ok(SockAddr{
-                            ipv6: unsafe { *(addr as *const SockAddrIn6) },
-                        })
-                    }
-                }?
-            .into_endpoint())}else {
-                None
-            };
-            socket_file.sk.send(&buf_slice, remote_addr).await?    
-        }
-        SocketType::STREAM => {
-            if addr != 0 {
-                return Err(SysError::EISCONN);
-            }
-            socket_file.sk.send(&buf_slice, None).await?
-        },
-        _ => todo!(),
+        .map_err(|_| SysError::ENOTSOCK)?;
+    // a connected stream takes only the address of its peer, TcpSocket::send checks it
+    let remote_addr = match socket_file.sk_type {
+        SocketType::DGRAM | SocketType::STREAM if addr != 0 => Some(read_sockaddr(&task, addr, addr_len)?.into_endpoint()),
+        SocketType::DGRAM | SocketType::STREAM => None,
+        _ => return Err(SysError::EOPNOTSUPP),
     };
-    task.set_running();
+    let user_buf = UserSliceRaw::new(buf as *const u8, len)
+        .ensure_read(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?;
+    let bytes = socket_file.sk.send(user_buf.to_ref(), remote_addr).await?;
     Ok(bytes as isize)
 }
 
@@ -357,45 +267,15 @@ pub async fn sys_recvfrom(
     let socket_file = task.with_fd_table(|table| {
         table.get_file(sockfd)})?
         .downcast_arc::<socket::Socket>()
-        .unwrap_or_else(|_| {
-            panic!("Failed to downcast to socket::Socket")
-        });
-    let mut inner_vec = Vec::with_capacity(len);
-    unsafe {
-        inner_vec.set_len(len);
-    }
-    task.set_interruptable();
-    let (bytes, remote_endpoint) = socket_file.sk.recv(&mut inner_vec).await?;
+        .map_err(|_| SysError::ENOTSOCK)?;
+    // no single receive returns more than the socket buffer holds
+    let len = len.min(socket_file.sk.buf_lens().rx);
+    let user_buf = UserSliceRaw::new(buf as *mut u8, len)
+        .ensure_write(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?;
+    let (bytes, remote_endpoint) = socket_file.recv(user_buf.to_mut()).await?;
     // log::info!("recvfrom: bytes: {}, remote_endpoint: {:?}", bytes, remote_endpoint);
-    let remote_addr = SockAddr::from_endpoint(remote_endpoint);
-    task.set_running();
-    // write to pointer
-    // log::info!("now set running");
-    let buf_slice = unsafe {
-        core::slice::from_raw_parts_mut(buf as *mut u8, bytes)
-    };
-    buf_slice[..bytes].copy_from_slice(&inner_vec[..bytes]);
-    // write to sockaddr_in
-    if addr == 0 {
-        return Ok(bytes as isize);  
-    }
-    unsafe {
-        match SaFamily::try_from(remote_addr.family).unwrap() {
-            SaFamily::AfInet => {
-                let addr_ptr = addr as *mut SockAddrIn4;
-                addr_ptr.write_volatile(remote_addr.ipv4);
-                let addr_len_ptr = addrlen as *mut u32;
-                addr_len_ptr.write_volatile(size_of::<SockAddrIn4>() as u32);
-            }
-            SaFamily::AfInet6 => {
-                let addr_ptr = addr as *mut SockAddrIn6;
-                addr_ptr.write_volatile(remote_addr.ipv6);
-                let addr_len_ptr = addrlen as *mut u32;
-                addr_len_ptr.write_volatile(size_of::<SockAddrIn6>() as u32);
-            },
-        }
-    }
-    // log::info!("now return bytes: {}",bytes);
+    write_sockaddr(&task, addr, addrlen, &SockAddr::from_endpoint(remote_endpoint))?;
     Ok(bytes as isize)
 }
 /// Returns the local address of the Socket corresponding to `sockfd`.
@@ -405,29 +285,13 @@ pub fn sys_getsockname(fd: usize, addr: usize, addr_len: usize) -> SysResult {
     }
     log::info!("sys_getsockname fd: {}, addr: {:#x}, addr_len: {}", fd, addr, addr_len);
     let task = current_task().unwrap();
-    let socket_file = task.with_fd_table(|table| -> Result<Arc<socket::Socket>, SysError> {
-        let arc = table.get_file(fd).clone()?;
-        arc.downcast_arc::<socket::Socket>().map_err(|_| panic!("Failed to downcast to socket::Socket"))
-    })?;
+    let socket_file = task.with_fd_table(|table| {
+        table.get_file(fd)})?
+        .downcast_arc::<socket::Socket>()
+        .map_err(|_| SysError::ENOTSOCK)?;
     let local_addr = socket_file.sk.local_addr()?;
     // log::info!("Get local address of socket: {:?}", local_addr);
-    // write to pointer
-    unsafe {
-        match SaFamily::try_from(local_addr.family).unwrap() {
-            SaFamily::AfInet => {
-                let addr_ptr = addr as *mut SockAddrIn4;
-                addr_ptr.write_volatile(local_addr.ipv4);
-                let addr_len_ptr = addr_len as *mut u32;
-                addr_len_ptr.write_volatile(size_of::<SockAddrIn4>() as u32);
-            }
-            SaFamily::AfInet6 => {
-                let addr_ptr = addr as *mut SockAddrIn6;
-                addr_ptr.write_volatile(local_addr.ipv6);
-                let addr_len_ptr = addr_len as *mut u32;
-                addr_len_ptr.write_volatile(size_of::<SockAddrIn6>() as u32);
-            },
-        }
-    }
+    write_sockaddr(task, addr, addr_len, &local_addr)?;
     Ok(0)
 }
 
@@ -440,30 +304,78 @@ pub fn sys_getpeername(fd: usize, addr: usize, addr_len: usize) -> SysResult {
     let socket_file = task.with_fd_table(|table| {
         table.get_file(fd)})?
         .downcast_arc::<socket::Socket>()
-        .unwrap_or_else(|_| {
-            panic!("Failed to downcast to socket::Socket")
-        });
-    let peer_addr = socket_file.sk.peer_addr().unwrap();
+        .map_err(|_| SysError::ENOTSOCK)?;
+    let peer_addr = socket_file.sk.peer_addr()?;
     log::info!("Get peer address of socket: {:?}", peer_addr);
-    // write to pointer
-    unsafe {
-        match SaFamily::try_from(peer_addr.family).unwrap() {
-            SaFamily::AfInet => {
-                let addr_ptr = addr as *mut SockAddrIn4;
-                addr_ptr.write_volatile(peer_addr.ipv4);
-                let addr_len_ptr = addr_len as *mut u32;
-                addr_len_ptr.write_volatile(size_of::<SockAddrIn4>() as u32);
+    write_sockaddr(task, addr, addr_len, &peer_addr)?;
+    Ok(0)
+}
+
+/// read a socket address from the user `addr`,
+/// `addr_len` must cover the whole address of its family
+fn read_sockaddr(task: &Arc<TaskControlBlock>, addr: usize, addr_len: usize) -> Result<SockAddr, SysError> {
+    let mut vm = task.get_vm_space().lock();
+    let family = *UserPtrRaw::new(addr as *const u16)
+        .ensure_read(&mut vm)
+        .ok_or(SysError::EFAULT)?
+        .to_ref();
+    let sock_addr = match SaFamily::try_from(family)? {
+        SaFamily::AfInet => {
+            if addr_len < size_of::<SockAddrIn4>() {
+                return Err(SysError::EINVAL);
+            }
+            SockAddr {
+                ipv4: *UserPtrRaw::new(addr as *const SockAddrIn4)
+                    .ensure_read(&mut vm)
+                    .ok_or(SysError::EFAULT)?
+                    .to_ref(),
+            }
+        }
+        SaFamily::AfInet6 => {
+            if addr_len < size_of::<SockAddrIn6>() {
+                return Err(SysError::EINVAL);
+            }
+            SockAddr {
+                ipv6: *UserPtrRaw::new(addr as *const SockAddrIn6)
+                    .ensure_read(&mut vm)
+                    .ok_or(SysError::EFAULT)?
+                    .to_ref(),
             }
-            SaFamily::AfInet6 => {
-                let addr_ptr = addr as *mut SockAddrIn6;
-                addr_ptr.write_volatile(peer_addr.ipv6);
-                let addr_len_ptr = addr_len as *mut u32;
-                addr_len_ptr.write_volatile(size_of::<SockAddrIn6>() as u32);
-            },
         }
+    };
+    Ok(sock_addr)
+}
+
+/// write `sock_addr` to the user `addr` as Linux does: nothing is written if `addr` is NULL,
+/// otherwise it is truncated to the buffer size in `*addr_len` and the real size stored back
+fn write_sockaddr(task: &Arc<TaskControlBlock>, addr: usize, addr_len: usize, sock_addr: &SockAddr) -> Result<(), SysError> {
+    if addr == 0 {
+        return Ok(());
     }
-    Ok(0)
+    let size = match SaFamily::try_from(unsafe { sock_addr.family })? {
+        SaFamily::AfInet => size_of::<SockAddrIn4>(),
+        SaFamily::AfInet6 => size_of::<SockAddrIn6>(),
+    };
+    let mut vm = task.get_vm_space().lock();
+    let len_ptr = UserPtrRaw::new(addr_len as *const u32)
+        .ensure_write(&mut vm)
+        .ok_or(SysError::EFAULT)?;
+    let buf_len = *len_ptr.to_ref() as i32;
+    if buf_len < 0 {
+        return Err(SysError::EINVAL);
+    }
+    let copy_len = size.min(buf_len as usize);
+    let user_addr = UserSliceRaw::new(addr as *mut u8, copy_len)
+        .ensure_write(&mut vm)
+        .ok_or(SysError::EFAULT)?;
+    let bytes = unsafe {
+        core::slice::from_raw_parts(sock_addr as *const SockAddr as *const u8, copy_len)
+    };
+    user_addr.to_mut().copy_from_slice(bytes);
+    len_ptr.write(size as u32);
+    Ok(())
 }
+
 #[allow(missing_docs)]
 pub enum SocketLevel {
     /// Dummy protocol for TCP
@@ -571,8 +483,12 @@ impl TryFrom<usize> for SocketOption {
 pub enum TcpSocketOption {
     NODELAY = 1, // disable nagle algorithm and flush
     MAXSEG = 2,
+    KEEPIDLE = 4,
+    KEEPINTVL = 5,
+    KEEPCNT = 6,
     INFO = 11,
     CONGESTION = 13,
+    USER_TIMEOUT = 18,
 }
 
 impl TryFrom<usize> for TcpSocketOption {
@@ -582,8 +498,12 @@ impl TryFrom<usize> for TcpSocketOption {
         match value {
             1 => Ok(TcpSocketOption::NODELAY),
             2 => Ok(TcpSocketOption::MAXSEG),
+            4 => Ok(TcpSocketOption::KEEPIDLE),
+            5 => Ok(TcpSocketOption::KEEPINTVL),
+            6 => Ok(TcpSocketOption::KEEPCNT),
             11 => Ok(TcpSocketOption::INFO),
             13 => Ok(TcpSocketOption::CONGESTION),
+            18 => Ok(TcpSocketOption::USER_TIMEOUT),
             opt => {
                 log::warn!("[TcpSocketOpt] unsupported option: {opt}");
                 Err(Self::Error::EINVAL)
@@ -596,17 +516,127 @@ impl TryFrom<usize> for TcpSocketOption {
 /// level: protocel level at which the option resides,
 /// option name
 pub fn sys_setsockopt  (
-    _fd: usize,
-    _level: usize,
-    _option_name: usize,
-    _option_value: usize,
-    _option_len: usize,
+    fd: usize,
+    level: usize,
+    option_name: usize,
+    option_value: usize,
+    option_len: usize,
 ) -> SysResult {
+    let task = current_task().unwrap();
+    let socket_file = task.with_fd_table(|table| {
+        table.get_file(fd)})?
+        .downcast_arc::<socket::Socket>()
+        .map_err(|_| SysError::ENOTSOCK)?;
+    let level = SocketLevel::try_from(level);
+    let option = SocketOption::try_from(option_name);
+    // the buffer sizes are kept for either kind of socket, the FORCE variants are not privileged here
+    if let (Ok(SocketLevel::SolSocket), Ok(opt @ (SocketOption::SNDBUF | SocketOption::RCVBUF | SocketOption::SNDBUFFORCE | SocketOption::RCVBUFFORCE))) = (&level, option) {
+        let len = BufLens::from_sockopt(read_int_opt(task, option_value, option_len)?);
+        socket_file.sk.set_buf_lens(|lens| match opt {
+            SocketOption::SNDBUF | SocketOption::SNDBUFFORCE => lens.tx = len,
+            _ => lens.rx = len,
+        });
+        return Ok(0);
+    }
+    // otherwise only the options changing how a tcp socket closes or times out are kept, the rest is accepted and ignored
+    let Sock::TCP(tcp) = &socket_file.sk else {
+        return Ok(0);
+    };
+    match (level, option) {
+        (Ok(SocketLevel::SolSocket), Ok(SocketOption::LINGER)) => {
+            if option_len < size_of::<Linger>() {
+                return Err(SysError::EINVAL);
+            }
+            let linger = *UserPtrRaw::new(option_value as *const Linger)
+                .ensure_read(&mut task.get_vm_space().lock())
+                .ok_or(SysError::EFAULT)?
+                .to_ref();
+            tcp.set_linger((linger.l_onoff != 0).then_some(linger.l_linger.max(0) as u32));
+        }
+        (Ok(SocketLevel::SolSocket), Ok(SocketOption::REUSEADDR)) => {
+            tcp.set_reuse_addr(read_int_opt(task, option_value, option_len)? != 0);
+        }
+        (Ok(SocketLevel::SolSocket), Ok(SocketOption::KEEPALIVE)) => {
+            let enabled = read_int_opt(task, option_value, option_len)? != 0;
+            tcp.set_keepalive(|k| k.enabled = enabled);
+        }
+        (Ok(SocketLevel::IpprotoTcp), _) => {
+            let Ok(option) = TcpSocketOption::try_from(option_name) else {
+                return Ok(0);
+            };
+            let max = match option {
+                TcpSocketOption::KEEPIDLE => MAX_TCP_KEEPIDLE,
+                TcpSocketOption::KEEPINTVL => MAX_TCP_KEEPINTVL,
+                TcpSocketOption::KEEPCNT => MAX_TCP_KEEPCNT,
+                TcpSocketOption::USER_TIMEOUT => i32::MAX,
+                _ => return Ok(0),
+            };
+            let val = read_int_opt(task, option_value, option_len)?;
+            // the user timeout may be 0 to turn it off, the others are at least 1
+            let min = (option != TcpSocketOption::USER_TIMEOUT) as i32;
+            if !(min..=max).contains(&val) {
+                return Err(SysError::EINVAL);
+            }
+            tcp.set_keepalive(|k| match option {
+                TcpSocketOption::KEEPIDLE => k.idle = Duration::from_secs(val as u64),
+                TcpSocketOption::KEEPINTVL => k.intvl = Duration::from_secs(val as u64),
+                TcpSocketOption::KEEPCNT => k.cnt = val as u32,
+                _ => k.user_timeout = (val > 0).then(|| Duration::from_millis(val as u64)),
+            });
+        }
+        _ => {}
+    }
     Ok(0)
 }
+
+/// write the int value of a socket option and its length
+fn write_int_opt(task: &Arc<TaskControlBlock>, option_value: usize, option_len: usize, val: i32) -> Result<(), SysError> {
+    let mut vm = task.get_vm_space().lock();
+    UserPtrRaw::new(option_value as *const i32)
+        .ensure_write(&mut vm)
+        .ok_or(SysError::EFAULT)?
+        .write(val);
+    UserPtrRaw::new(option_len as *const u32)
+        .ensure_write(&mut vm)
+        .ok_or(SysError::EFAULT)?
+        .write(size_of::<i32>() as u32);
+    Ok(())
+}
+
+/// the keepalive options of the socket `fd`, the defaults for a udp socket
+fn keepalive_of(task: &Arc<TaskControlBlock>, fd: usize) -> Result<(KeepAlive, bool), SysError> {
+    let socket_file = task.with_fd_table(|table| table.get_file(fd))?
+        .downcast_arc::<socket::Socket>()
+        .map_err(|_| SysError::ENOTSOCK)?;
+    Ok(match &socket_file.sk {
+        Sock::TCP(tcp) => (tcp.keepalive(), true),
+        Sock::UDP(_) => (KeepAlive::DEFAULT, false),
+    })
+}
+
+/// read the int value of a socket option
+fn read_int_opt(task: &Arc<TaskControlBlock>, option_value: usize, option_len: usize) -> Result<i32, SysError> {
+    if option_len < size_of::<i32>() {
+        return Err(SysError::EINVAL);
+    }
+    Ok(*UserPtrRaw::new(option_value as *const i32)
+        .ensure_read(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?
+        .to_ref())
+}
+
+/// struct linger of SO_LINGER
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Linger {
+    /// linger active
+    pub l_onoff: i32,
+    /// how many seconds to linger for
+    pub l_linger: i32,
+}
 /// get socket configure interface for user
 pub fn sys_getsockopt (
-    _fd: usize,
+    fd: usize,
     level: usize,
     option_name: usize,
     option_value: usize,
@@ -627,24 +657,24 @@ pub fn sys_getsockopt (
     }
     match SocketLevel::try_from(level)? {
         SocketLevel::SolSocket => {
-            const SEND_BUFFER_SIZE: usize = 64 * 1024; // 64KB
-            const RECV_BUFFER_SIZE: usize = 64 * 1024; // 64KB
             match SocketOption::try_from(option_name)?{
-                SocketOption::SNDBUF => {
-                    let optval_ptr = option_value as *mut u32;
-                    let optlen_ptr = option_len as *mut u32;
-                    unsafe {
-                        optval_ptr.write_volatile(SEND_BUFFER_SIZE as u32);
-                        optlen_ptr.write_volatile(size_of::<u32>() as u32);
-                    }
-                },
-                SocketOption::RCVBUF => {
-                    let optval_ptr = option_value as *mut u32;
-                    let optlen_ptr = option_len as *mut u32;
-                    unsafe {
-                        optval_ptr.write_volatile(RECV_BUFFER_SIZE as u32);
-                        optlen_ptr.write_volatile(size_of::<u32>() as u32);
-                    }
+                opt @ (SocketOption::SNDBUF | SocketOption::RCVBUF) => {
+                    let task = current_task().unwrap();
+                    let socket_file = task.with_fd_table(|table| {
+                        table.get_file(fd)})?
+                        .downcast_arc::<socket::Socket>()
+                        .map_err(|_| SysError::ENOTSOCK)?;
+                    let lens = socket_file.sk.buf_lens();
+                    let len = if opt == SocketOption::SNDBUF { lens.tx } else { lens.rx };
+                    let mut vm = task.get_vm_space().lock();
+                    UserPtrRaw::new(option_value as *const i32)
+                        .ensure_write(&mut vm)
+                        .ok_or(SysError::EFAULT)?
+                        .write(BufLens::to_sockopt(len));
+                    UserPtrRaw::new(option_len as *const u32)
+                        .ensure_write(&mut vm)
+                        .ok_or(SysError::EFAULT)?
+                        .write(size_of::<i32>() as u32);
                 },
                 SocketOption::ERROR => {
                     let optval_ptr = option_value as *mut u32;
@@ -654,6 +684,35 @@ pub fn sys_getsockopt (
                         optlen_ptr.write_volatile(size_of::<u32>() as u32);
                     }
                 }
+                SocketOption::KEEPALIVE => {
+                    let task = current_task().unwrap();
+                    let (keepalive, _) = keepalive_of(task, fd)?;
+                    write_int_opt(task, option_value, option_len, keepalive.enabled as i32)?;
+                }
+                SocketOption::LINGER => {
+                    let task = current_task().unwrap();
+                    let socket_file = task.with_fd_table(|table| {
+                        table.get_file(fd)})?
+                        .downcast_arc::<socket::Socket>()
+                        .map_err(|_| SysError::ENOTSOCK)?;
+                    let linger = match &socket_file.sk {
+                        Sock::TCP(tcp) => tcp.linger(),
+                        Sock::UDP(_) => None,
+                    };
+                    let linger = Linger {
+                        l_onoff: linger.is_some() as i32,
+                        l_linger: linger.unwrap_or(0) as i32,
+                    };
+                    let mut vm = task.get_vm_space().lock();
+                    UserPtrRaw::new(option_value as *const Linger)
+                        .ensure_write(&mut vm)
+                        .ok_or(SysError::EFAULT)?
+                        .write(linger);
+                    UserPtrRaw::new(option_len as *const u32)
+                        .ensure_write(&mut vm)
+                        .ok_or(SysError::EFAULT)?
+                        .write(size_of::<Linger>() as u32);
+                }
                 _ =>{
                     todo!()
                 } 
@@ -677,6 +736,20 @@ pub fn sys_getsockopt (
                         optlen_ptr.write_volatile(size_of::<u32>() as u32);
                     } 
                 },
+                opt @ (TcpSocketOption::KEEPIDLE | TcpSocketOption::KEEPINTVL | TcpSocketOption::KEEPCNT | TcpSocketOption::USER_TIMEOUT) => {
+                    let task = current_task().unwrap();
+                    let (keepalive, is_tcp) = keepalive_of(task, fd)?;
+                    if !is_tcp {
+                        return Err(SysError::EOPNOTSUPP);
+                    }
+                    let val = match opt {
+                        TcpSocketOption::KEEPIDLE => keepalive.idle.as_secs() as i32,
+                        TcpSocketOption::KEEPINTVL => keepalive.intvl.as_secs() as i32,
+                        TcpSocketOption::KEEPCNT => keepalive.cnt as i32,
+                        _ => keepalive.user_timeout.map_or(0, |t| t.as_millis() as i32),
+                    };
+                    write_int_opt(task, option_value, option_len, val)?;
+                }
                 TcpSocketOption::INFO => {},
                 TcpSocketOption::CONGESTION => {
                     log::warn!("[sys_getsockopt], TcpSocketOption::CONGESTION");
@@ -714,21 +787,13 @@ pub fn sys_shutdown(fd: usize, how: usize) -> SysResult {
 /// create a pair of connected sockets
 pub fn sys_socketpair(_domain: usize, _types: usize, _protocol: usize, sv: usize) -> SysResult {
     let task = current_task().unwrap();
+    task.ensure_can_open(2)?;
     let (pipe_read, pipe_write) = pipefs::make_pipe(PAGE_SIZE);
     let pipe = task.with_mut_fd_table(|table| {
-        let fd_read = table.alloc_fd()?;
-        let fd_info_read = FdInfo {
-            file: pipe_read,
-            flags: FdFlags::empty(),
-        };
-        table.put_file(fd_read, fd_info_read)?;
-        let fd_write = table.alloc_fd()?;
-        let fd_info_write = FdInfo {
-            file: pipe_write,
-            flags: FdFlags::empty(),    
-        };
-        table.put_file(fd_write, fd_info_write)?;
-        Ok([fd_read as u32, fd_write as u32])
+        table.ensure_free_fds(2)?;
+        let fd_read = table.install(pipe_read, FdFlags::empty())?;
+        let fd_write = table.install(pipe_write, FdFlags::empty())?;
+        Ok::<_, SysError>([fd_read as u32, fd_write as u32])
     })?;
     let sv_ptr = sv as *mut [u32; 2];
     unsafe {
@@ -787,50 +852,51 @@ pub async fn sys_sendmsg(
         .unwrap_or_else(|_| {
             panic!("Failed to downcast to socket::Socket")
         });
-    let msg_ptr = msg as *const MsgHdr;
-    let msg = unsafe { msg_ptr.read() };
+    let msg = *UserPtrRaw::new(msg as *const MsgHdr)
+        .ensure_read(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?
+        .to_ref();
     if msg.msg_controllen != 0 {
         log::warn!("unsupported control data");
     }
-    let addr = match SaFamily::try_from(unsafe {
-        Instruction::set_sum();
-        *(msg.msg_name as *const u16)
-    })? {
+    let family = *UserPtrRaw::new(msg.msg_name as *const u16)
+        .ensure_read(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?
+        .to_ref();
+    let addr = match SaFamily::try_from(family)? {
         SaFamily::AfInet => {
             if msg.msg_namelen < mem::size_of::<SockAddrIn4>() as u32 {
                 log::error!("[sendmsg] invalid address length: {}", msg.msg_namelen);
                 return Err(SysError::EINVAL);
             }
-            Ok(SockAddr{
-                ipv4: unsafe { *(msg.msg_name as *const SockAddrIn4) },
-            }.into_endpoint())
+            let ipv4 = *UserPtrRaw::new(msg.msg_name as *const SockAddrIn4)
+                .ensure_read(&mut task.get_vm_space().lock())
+                .ok_or(SysError::EFAULT)?
+                .to_ref();
+            SockAddr { ipv4 }.into_endpoint()
         },
         SaFamily::AfInet6 => {
             if msg.msg_namelen < mem::size_of::<SockAddrIn6>() as u32 {
                 log::error!("[sendmsg] invalid address length: {}", msg.msg_namelen);
                 return Err(SysError::EINVAL);
             }
-            Ok(SockAddr{
-                ipv6: unsafe {
-                    *(msg.msg_name as *const SockAddrIn6)
-                }
-            }.into_endpoint())
+            let ipv6 = *UserPtrRaw::new(msg.msg_name as *const SockAddrIn6)
+                .ensure_read(&mut task.get_vm_space().lock())
+                .ok_or(SysError::EFAULT)?
+                .to_ref();
+            SockAddr { ipv6 }.into_endpoint()
         },
-    }?;
-    let iovs = unsafe {
-        Instruction::set_sum();
-        core::slice::from_raw_parts(msg.msg_iov as *const IoVec, msg.msg_iovlen as usize)
     };
+    let iovs = read_iovecs(task, msg.msg_iov, msg.msg_iovlen as usize)?;
     let mut total_len = 0;
-    for (_i, iov) in iovs.iter().enumerate() {
+    for iov in iovs.iter() {
         if iov.len == 0 {
             continue;
         }
-        let ptr = iov.base as *const u8;
-        let buf_slice = unsafe {
-            core::slice::from_raw_parts(ptr, iov.len as usize)
-        };
-        let send_len = socket_file.sk.send(buf_slice, Some(addr)).await?;
+        let buf = UserSliceRaw::new(iov.base as *const u8, iov.len)
+            .ensure_read(&mut task.get_vm_space().lock())
+            .ok_or(SysError::EFAULT)?;
+        let send_len = socket_file.sk.send(buf.to_ref(), Some(addr)).await?;
         total_len += send_len;
     }
     Ok(total_len as isize)
@@ -856,48 +922,51 @@ pub async fn sys_recvmsg(
         .unwrap_or_else(|_| {
             panic!("Failed to downcast to socket::Socket")
         });
-    let msg_ptr = msg as *mut MsgHdr;
-    let inner_msg = unsafe { msg_ptr.read() };
+    let inner_msg = *UserPtrRaw::new(msg as *const MsgHdr)
+        .ensure_read(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?
+        .to_ref();
     if inner_msg.msg_controllen != 0 {
         log::warn!("unsupported control data");
     }
-    let iovs = unsafe {
-        Instruction::set_sum();
-        core::slice::from_raw_parts(inner_msg.msg_iov as *const IoVec, inner_msg.msg_iovlen as usize)
-    };
+    let iovs = read_iovecs(task, inner_msg.msg_iov, inner_msg.msg_iovlen as usize)?;
     let mut tmp_buf = vec![0u8; 64 * 1024];
-    let (recv_len,src_addr) = socket_file.sk.recv(&mut tmp_buf).await?;
+    let (recv_len,src_addr) = socket_file.recv(&mut tmp_buf).await?;
     let mut copied = 0;
-    let data = tmp_buf[..recv_len].to_vec();
     for iov in iovs {
         if copied >= recv_len {
             break;
         }
-        let to_copy = (iov.len as usize).min(recv_len - copied);
-        let dst = iov.base as *mut u8;
-        unsafe  {
-            core::ptr::copy_nonoverlapping(data[copied..].as_ptr(), dst, to_copy)
-        };
+        let to_copy = iov.len.min(recv_len - copied);
+        let dst = UserSliceRaw::new(iov.base as *mut u8, to_copy)
+            .ensure_write(&mut task.get_vm_space().lock())
+            .ok_or(SysError::EFAULT)?;
+        dst.to_mut().copy_from_slice(&tmp_buf[copied..copied + to_copy]);
         copied += to_copy;
     }
 
     if inner_msg.msg_name != 0 {
         let addr = SockAddr::from_endpoint(src_addr);
-        unsafe {
-            match SaFamily::try_from(addr.family)? {
-                SaFamily::AfInet => {
-                    let addr_ptr = inner_msg.msg_name as *mut SockAddrIn4;
-                    addr_ptr.write_volatile(addr.ipv4);
-                    let addr_len_ptr = inner_msg.msg_namelen as *mut u32;
-                    addr_len_ptr.write_volatile(size_of::<SockAddrIn4>() as u32);
-                },
-                SaFamily::AfInet6 => {
-                    let addr_ptr = inner_msg.msg_name as *mut SockAddrIn6;
-                    addr_ptr.write_volatile(addr.ipv6);
-                    let addr_len_ptr = inner_msg.msg_namelen as *mut u32;
-                    addr_len_ptr.write_volatile(size_of::<SockAddrIn6>() as u32);
-                },
-            }
+        let mut vm = task.get_vm_space().lock();
+        // msg_namelen is a field of the header, the length goes back there
+        let namelen = UserPtrRaw::new((msg + mem::offset_of!(MsgHdr, msg_namelen)) as *const u32)
+            .ensure_write(&mut vm)
+            .ok_or(SysError::EFAULT)?;
+        match SaFamily::try_from(addr.family)? {
+            SaFamily::AfInet => {
+                UserPtrRaw::new(inner_msg.msg_name as *const SockAddrIn4)
+                    .ensure_write(&mut vm)
+                    .ok_or(SysError::EFAULT)?
+                    .write(unsafe { addr.ipv4 });
+                namelen.write(size_of::<SockAddrIn4>() as u32);
+            },
+            SaFamily::AfInet6 => {
+                UserPtrRaw::new(inner_msg.msg_name as *const SockAddrIn6)
+                    .ensure_write(&mut vm)
+                    .ok_or(SysError::EFAULT)?
+                    .write(unsafe { addr.ipv6 });
+                namelen.write(size_of::<SockAddrIn6>() as u32);
+            },
         }
     }
                     
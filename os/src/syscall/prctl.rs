@@ -0,0 +1,204 @@
+//! prctl syscall
+//! the standard operations Chronix can do trivially, the misaligned access mode, plus a Chronix-specific
+//! syscall filter: a bitmap of allowed syscall numbers, a poor man's seccomp,
+//! and the switch of the in-kernel syscall trace
+
+use alloc::string::String;
+use log::*;
+
+use super::{SysError, SysResult};
+use crate::{mm::{UserPtrRaw, UserSliceRaw}, signal::SIGSYS, task::current_task, trap::misaligned::{set_unalign_ctl, unalign_ctl, PR_UNALIGN_NOPRINT, PR_UNALIGN_SIGBUS}};
+
+/// get the dumpable flag of the process
+pub const PR_GET_DUMPABLE: i32 = 3;
+/// set the dumpable flag of the process
+pub const PR_SET_DUMPABLE: i32 = 4;
+/// get the misaligned access control bits, Chronix keeps them system wide
+pub const PR_GET_UNALIGN: i32 = 5;
+/// set the misaligned access control bits, PR_UNALIGN_NOPRINT or PR_UNALIGN_SIGBUS
+pub const PR_SET_UNALIGN: i32 = 6;
+/// set the name of the calling thread
+pub const PR_SET_NAME: i32 = 15;
+/// get the name of the calling thread
+pub const PR_GET_NAME: i32 = 16;
+/// execve never grants privileges the caller did not have
+pub const PR_SET_NO_NEW_PRIVS: i32 = 38;
+/// get the no_new_privs flag
+pub const PR_GET_NO_NEW_PRIVS: i32 = 39;
+/// Chronix-specific: install a syscall filter,
+/// arg2 is a `FilterMode`, arg3 points to a bitmap of `SYSCALL_FILTER_WORDS` u64 of allowed syscall numbers
+pub const PR_SET_SYSCALL_FILTER: i32 = 0x4358_0001;
+/// Chronix-specific: arg2 1 logs every syscall of the task and its future children, 0 stops
+pub const PR_SET_SYSCALL_TRACE: i32 = 0x4358_0002;
+/// Chronix-specific: get the syscall trace flag
+pub const PR_GET_SYSCALL_TRACE: i32 = 0x4358_0003;
+
+/// the length of the thread name including the trailing nul, TASK_COMM_LEN in linux
+pub const TASK_COMM_LEN: usize = 16;
+/// the thread name of `name`, cut to TASK_COMM_LEN - 1 bytes at a char boundary
+pub fn comm_from_bytes(name: &[u8]) -> String {
+    let name = &name[..name.iter().position(|&c| c == 0).unwrap_or(name.len())];
+    let mut comm = String::from_utf8_lossy(&name[..name.len().min(TASK_COMM_LEN - 1)]).into_owned();
+    // a replacement char may have made it longer than the bytes it stands for
+    let mut len = comm.len().min(TASK_COMM_LEN - 1);
+    while !comm.is_char_boundary(len) {
+        len -= 1;
+    }
+    comm.truncate(len);
+    comm
+}
+
+/// u64 words in a syscall filter bitmap, enough for every syscall number below 512
+pub const SYSCALL_FILTER_WORDS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(usize)]
+/// what happens to a syscall the filter denies
+pub enum FilterMode {
+    /// the syscall fails with ENOSYS
+    Errno = 0,
+    /// the process is killed by SIGSYS
+    Kill = 1,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// the syscalls a task may make, inherited on clone and kept across execve
+pub struct SyscallFilter {
+    allowed: [u64; SYSCALL_FILTER_WORDS],
+    /// how a denied syscall is enforced
+    pub mode: FilterMode,
+}
+
+impl SyscallFilter {
+    /// whether syscall `id` passes the filter
+    pub fn allows(&self, id: usize) -> bool {
+        id < SYSCALL_FILTER_WORDS * 64 && self.allowed[id / 64] & (1 << (id % 64)) != 0
+    }
+    /// whether `other` allows nothing self denies and enforces at least as strictly
+    fn is_narrowed_by(&self, other: &SyscallFilter) -> bool {
+        self.allowed.iter().zip(other.allowed.iter()).all(|(old, new)| new & !old == 0)
+            && other.mode >= self.mode
+    }
+}
+
+/// check syscall `id` against the filter of the current task,
+/// return the error of a denied syscall
+pub fn check_syscall_filter(id: usize) -> Result<(), SysError> {
+    let task = current_task().unwrap().clone();
+    let Some(filter) = *task.syscall_filter.lock() else {
+        return Ok(());
+    };
+    if filter.allows(id) {
+        return Ok(());
+    }
+    warn!("[syscall_filter]: task {} made denied syscall {}", task.tid(), id);
+    if filter.mode == FilterMode::Kill {
+        // like SECCOMP_RET_KILL_PROCESS, the signal can not be caught
+        task.do_group_exit(SIGSYS);
+    }
+    Err(SysError::ENOSYS)
+}
+
+/// syscall: prctl
+pub fn sys_prctl(option: i32, arg2: usize, arg3: usize, _arg4: usize, _arg5: usize) -> SysResult {
+    let task = current_task().unwrap().clone();
+    match option {
+        PR_GET_DUMPABLE => Ok(task.dumpable() as isize),
+        PR_SET_DUMPABLE => {
+            // SUID_DUMP_ROOT is only settable through the sysctl
+            if arg2 > 1 {
+                return Err(SysError::EINVAL);
+            }
+            task.with_thread_group(|tg| tg.iter().for_each(|t| t.set_dumpable(arg2 == 1)));
+            Ok(0)
+        }
+        PR_GET_UNALIGN => {
+            UserPtrRaw::new(arg2 as *mut u32)
+                .ensure_write(&mut task.get_vm_space().lock())
+                .ok_or(SysError::EFAULT)?
+                .write(unalign_ctl() as u32);
+            Ok(0)
+        }
+        PR_SET_UNALIGN => {
+            if arg2 & !(PR_UNALIGN_NOPRINT | PR_UNALIGN_SIGBUS) != 0 {
+                return Err(SysError::EINVAL);
+            }
+            // the mode is system wide
+            if !task.with_cred(|c| c.is_privileged()) {
+                return Err(SysError::EPERM);
+            }
+            // the firmware fixes up what does not trap to the kernel
+            if arg2 & PR_UNALIGN_SIGBUS != 0 && !hal::trap::misaligned_traps() {
+                return Err(SysError::EINVAL);
+            }
+            set_unalign_ctl(arg2);
+            Ok(0)
+        }
+        PR_SET_NAME => {
+            // at most TASK_COMM_LEN bytes are read, the name need not end in a nul
+            let name = UserPtrRaw::new(arg2 as *const u8)
+                .cstr_slice_max(&mut task.get_vm_space().lock(), TASK_COMM_LEN)
+                .ok_or(SysError::EFAULT)?;
+            *task.comm.lock() = comm_from_bytes(name.to_ref());
+            Ok(0)
+        }
+        PR_GET_NAME => {
+            let mut buf = [0u8; TASK_COMM_LEN];
+            task.with_comm(|comm| {
+                let len = comm.len().min(TASK_COMM_LEN - 1);
+                buf[..len].copy_from_slice(&comm.as_bytes()[..len]);
+            });
+            UserSliceRaw::new(arg2 as *mut u8, TASK_COMM_LEN)
+                .ensure_write(&mut task.get_vm_space().lock())
+                .ok_or(SysError::EFAULT)?
+                .to_mut()
+                .copy_from_slice(&buf);
+            Ok(0)
+        }
+        PR_SET_NO_NEW_PRIVS => {
+            // the flag can never be cleared
+            if arg2 != 1 || arg3 != 0 {
+                return Err(SysError::EINVAL);
+            }
+            task.set_no_new_privs(true);
+            Ok(0)
+        }
+        PR_GET_NO_NEW_PRIVS => Ok(task.no_new_privs() as isize),
+        PR_SET_SYSCALL_FILTER => {
+            // same rule as seccomp: a filter can only be installed under no_new_privs
+            if !task.no_new_privs() {
+                return Err(SysError::EACCES);
+            }
+            let mode = match arg2 {
+                0 => FilterMode::Errno,
+                1 => FilterMode::Kill,
+                _ => return Err(SysError::EINVAL),
+            };
+            let allowed = *UserPtrRaw::new(arg3 as *const [u64; SYSCALL_FILTER_WORDS])
+                .ensure_read(&mut task.get_vm_space().lock())
+                .ok_or(SysError::EFAULT)?
+                .to_ref();
+            let filter = SyscallFilter { allowed, mode };
+            let mut cur = task.syscall_filter.lock();
+            // once set, a filter can only be narrowed
+            if cur.is_some_and(|old| !old.is_narrowed_by(&filter)) {
+                return Err(SysError::EPERM);
+            }
+            info!("[sys_prctl]: task {} installs syscall filter {:?}", task.tid(), filter);
+            *cur = Some(filter);
+            Ok(0)
+        }
+        PR_SET_SYSCALL_TRACE => {
+            if arg2 > 1 {
+                return Err(SysError::EINVAL);
+            }
+            task.set_syscall_trace(arg2 == 1);
+            Ok(0)
+        }
+        PR_GET_SYSCALL_TRACE => Ok(task.syscall_trace() as isize),
+        _ => {
+            warn!("[sys_prctl]: unsupported option {}", option);
+            Err(SysError::EINVAL)
+        }
+    }
+}
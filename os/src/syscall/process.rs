@@ -8,31 +8,36 @@ use crate::fs::fat32::dentry;
 use crate::fs::utils::FileReader;
 use crate::fs::vfs::dentry::global_find_dentry;
 use crate::fs::vfs::DentryState;
+use crate::fs::vfs::inode::InodeMode;
 use crate::fs::AtFlags;
+use crate::fs::devfs::tty::{self, Session};
 use crate::fs::{
     vfs::file::open_file,
     OpenFlags,
 };
-use crate::mm::UserPtrRaw;
+use crate::mm::{UserPtrRaw, UserSliceRaw};
 use crate::processor::context::SumGuard;
 use crate::syscall::at_helper;
 use crate::task::schedule::spawn_user_task;
 use crate::task::INITPROC;
+use crate::task::cred::{MAY_EXEC, NGROUPS_MAX};
+use crate::task::task::{ExecImage, TaskControlBlock};
 use crate::task::manager::{TaskManager, PROCESS_GROUP_MANAGER, TASK_MANAGER};
 use crate::processor::processor::{current_processor, current_task, current_trap_cx, current_user_token, PROCESSORS};
-use crate::signal::{SigInfo, SigSet, SIGKILL};
+use crate::signal::{SigInfo, SigSet, SIGKILL, SIGTRAP};
 use crate::timer::get_current_time_duration;
 use crate::utils::{suspend_now, user_path_to_string};
 use alloc::string::ToString;
 use alloc::{sync::Arc, vec::Vec, string::String};
 use fatfs::warn;
 use hal::addr::{PhysAddrHal, PhysPageNumHal, VirtAddr};
+use hal::constant::{Constant, ConstantsHal};
 use hal::instruction::{Instruction, InstructionHal};
 use hal::pagetable::PageTableHal;
 use hal::println;
 use hal::trap::{TrapContext, TrapContextHal};
 use lwext4_rust::bindings::EINVAL;
-use crate::mm::vm::{KernVmSpaceHal, UserVmSpaceHal};
+use crate::mm::vm::{check_elf, KernVmSpaceHal, UserVmSpaceHal};
 use log::info;
 
 use super::{SysResult,SysError};
@@ -242,10 +247,13 @@ pub fn sys_clone(flags: u64, stack: VirtAddr, parent_tid: VirtAddr, child_tid: V
 /// more details, see: https://man7.org/linux/man-pages/man2/execve.2.html
 pub async fn sys_execve(pathname: usize, argv: usize, envp: usize) -> SysResult {
     let task = current_task().unwrap();
+    if pathname == 0 {
+        return Err(SysError::EFAULT);
+    }
     let path = user_path_to_string(
             UserPtrRaw::new(pathname as *const u8), 
             &mut task.get_vm_space().lock()
-        ).unwrap();
+        ).ok_or(SysError::ENOENT)?;
     let mut argv = UserPtrRaw::new(argv as *const UserPtrRaw<u8>);
     let mut envp = UserPtrRaw::new(envp as *const UserPtrRaw<u8>);
 
@@ -316,15 +324,33 @@ pub async fn sys_execve(pathname: usize, argv: usize, envp: usize) -> SysResult
     log::info!("[sys_execve]: try to open file at path {}", dentry.path());
     if dentry.state() != DentryState::NEGATIVE {
         let task = current_task().unwrap();
-        let app = dentry.open(OpenFlags::empty()).unwrap();
-        let reader = FileReader::new(app.clone()).map_err(|_| SysError::EINVAL)?;
-        let elf = xmas_elf::ElfFile::new(&reader).map_err(
-            |err| {
-                log::warn!("[sys_execve] file: {} err: {}", app.dentry().unwrap().name(), err); 
-                SysError::EINVAL
+        {
+            let inode = dentry.inode().unwrap();
+            if inode.inode_inner().mode().get_type() != InodeMode::FILE {
+                return Err(SysError::EACCES);
             }
-        )?;
-        task.exec(&elf, Some(app), argv_vec, envp_vec)?;
+            task.check_access(inode.inode_inner(), MAY_EXEC)?;
+        }
+        let app = dentry.open(OpenFlags::empty()).unwrap();
+        // the new program is loaded before the point of no return
+        let image = {
+            let reader = FileReader::new(app.clone()).map_err(|_| SysError::ENOEXEC)?;
+            // the program goes below the dynamic loader
+            check_elf(&reader, Constant::DL_INTERP_OFFSET)?;
+            let elf = xmas_elf::ElfFile::new(&reader).map_err(
+                |err| {
+                    log::warn!("[sys_execve] file: {} err: {}", app.dentry().unwrap().name(), err); 
+                    SysError::ENOEXEC
+                }
+            )?;
+            ExecImage::load(&elf, Some(app), task.with_rlimit_stack(|limit| limit.rlim_cur))?
+        };
+        task.de_thread().await?;
+        task.exec(image, argv_vec, envp_vec);
+        // a traced task stops with SIGTRAP after a successful execve
+        if task.is_traced() {
+            task.recv_sigs(SigInfo::new(SIGTRAP, SigInfo::USER));
+        }
         Ok(0)
     } else {
         Err(SysError::ENOENT)
@@ -348,11 +374,16 @@ pub async fn sys_waitpid(pid: isize, exit_code_ptr: usize, option: i32) -> SysRe
     let task = current_task().unwrap().clone();
     // println!("[sys_waitpid]: TCB: {}, pid: {}, exitcode_ptr: {:x}, option: {}", task.tid(), pid, exit_code_ptr, option);
     let option = WaitOptions::from_bits_truncate(option);
+    // a ptrace stop of a tracee is reported before any exit
+    if let Some((tid, status)) = task.take_ptrace_report(pid) {
+        write_wait_status(&task, exit_code_ptr, status)?;
+        return Ok(tid as isize);
+    }
     // todo: now only support for pid == -1 and pid > 0
     // get the all target zombie process
     let res_task = {
         let children = task.children();
-        if children.is_empty() {
+        if children.is_empty() && !task.has_tracee(pid) {
             return Err(SysError::ECHILD);
         }
         match pid {
@@ -368,6 +399,8 @@ pub async fn sys_waitpid(pid: isize, exit_code_ptr: usize, option: i32) -> SysRe
                     } else {
                         None
                     }
+                } else if task.has_tracee(pid) {
+                    None
                 } else {
                     log::warn!("[sys_waitpid]: no child with pid {}", pid);
                     return Err(SysError::ECHILD);
@@ -381,38 +414,14 @@ pub async fn sys_waitpid(pid: isize, exit_code_ptr: usize, option: i32) -> SysRe
     };
 
     if let Some(res_task) = res_task {
-        res_task.time_recorder().update_child_time(res_task.time_recorder().time_pair());
-
-        if exit_code_ptr != 0 {
-            let mut vm = task.get_vm_space().lock();
-            let exit_code_ptr = UserPtrRaw::new(exit_code_ptr as *mut i32)
-                .ensure_write(vm.deref_mut())
-                .ok_or(SysError::EINVAL)?;
-            let exit_code_mut = exit_code_ptr.to_mut();
-            let exit_code = res_task.exit_code();
-            *exit_code_mut = exit_code as i32;
-        }
-
-        let mut res_task_tg = res_task.thread_group.lock();
-        for thread in res_task_tg.iter() {
-            TASK_MANAGER.remove_task(thread.tid());
-        }
-        res_task_tg.clear();
-        
-        let tid = res_task.tid();
-        task.remove_child(tid);
-        PROCESS_GROUP_MANAGER.remove(&task);
-        return Ok(tid as isize);
+        return reap_child(&task, res_task, exit_code_ptr);
     } else if option.contains(WaitOptions::WNOHANG) {
         return Ok(0);
     } else {
         log::debug!("[sys_waitpid]: TCB {} waiting for SIGCHLD", task.gettid());
         let res_task = loop {
             task.set_interruptable();
-            let block_sig = task.with_sig_manager(|sig_manager|{
-                sig_manager.blocked_sigs
-            });
-            task.set_wake_up_sigs(!block_sig | SigSet::SIGCHLD);
+            task.set_wake_up_sigs(task.intr_sigs() | SigSet::SIGCHLD);
             
             suspend_now().await;
             task.set_running();
@@ -426,6 +435,10 @@ pub async fn sys_waitpid(pid: isize, exit_code_ptr: usize, option: i32) -> SysRe
             });
             if let Some(si) = si {
                 log::debug!("[sys_waitpid] task {} get signal: {}", task.gettid(), si.si_signo);
+                if let Some((tid, status)) = task.take_ptrace_report(pid) {
+                    write_wait_status(&task, exit_code_ptr, status)?;
+                    return Ok(tid as isize);
+                }
                 let children = task.children();
                 let child = match pid {
                     -1 => {
@@ -440,6 +453,8 @@ pub async fn sys_waitpid(pid: isize, exit_code_ptr: usize, option: i32) -> SysRe
                             } else {
                                 None
                             }
+                        } else if task.has_tracee(pid) {
+                            None
                         } else {
                             log::warn!("[sys_waitpid]: no child with pid {}", pid);
                             return Err(SysError::ECHILD);
@@ -459,39 +474,64 @@ pub async fn sys_waitpid(pid: isize, exit_code_ptr: usize, option: i32) -> SysRe
             }
         };
 
-        res_task.time_recorder().update_child_time(res_task.time_recorder().time_pair());
-        
-        if exit_code_ptr != 0 {
-            let mut vm: crate::sync::mutex::spin_mutex::MutexGuard<'_, crate::mm::vm::UserVmSpace, crate::sync::mutex::SpinNoIrq> = task.get_vm_space().lock();
-            let exit_code_ptr = UserPtrRaw::new(exit_code_ptr as *mut i32)
-                .ensure_write(vm.deref_mut())
-                .ok_or(SysError::EINVAL)?;
-            let exit_code_mut = exit_code_ptr.to_mut();
-            let exit_code = res_task.exit_code();
-            *exit_code_mut = exit_code as i32;
-        }
+        return reap_child(&task, res_task, exit_code_ptr);
+    }
+}
+/// release a zombie child found by waitpid and return its pid
+fn reap_child(task: &Arc<TaskControlBlock>, res_task: Arc<TaskControlBlock>, exit_code_ptr: usize) -> SysResult {
+    if exit_code_ptr != 0 {
+        let mut vm = task.get_vm_space().lock();
+        let exit_code_ptr = UserPtrRaw::new(exit_code_ptr as *mut i32)
+            .ensure_write(vm.deref_mut())
+            .ok_or(SysError::EINVAL)?;
+        let exit_code_mut = exit_code_ptr.to_mut();
+        let exit_code = res_task.exit_code();
+        *exit_code_mut = exit_code as i32;
+    }
 
-        let mut res_task_tg = res_task.thread_group.lock();
-        for thread in res_task_tg.iter() {
-            TASK_MANAGER.remove_task(thread.tid());
-        }
-        res_task_tg.clear();
-        
-        let tid = res_task.tid();
-        task.remove_child(tid);
-        PROCESS_GROUP_MANAGER.remove(&task);
-        return Ok(tid as isize);
+    let tid = res_task.tid();
+    // another thread of ours may be reaping the same child, only the one removing it goes on
+    if task.remove_child(tid).is_none() {
+        return Err(SysError::ECHILD);
     }
+    // charged exactly once: the child's own threads and the children it reaped
+    let (user_time, kernel_time) = res_task.process_time_pair();
+    let (child_user_time, child_kernel_time) = res_task.time_recorder().child_time_pair();
+    task.get_leader().time_recorder().update_child_time((user_time + child_user_time, kernel_time + child_kernel_time));
+
+    let mut res_task_tg = res_task.thread_group.lock();
+    for thread in res_task_tg.iter() {
+        TASK_MANAGER.remove_task(thread.tid());
+    }
+    res_task_tg.clear();
+    drop(res_task_tg);
+
+    PROCESS_GROUP_MANAGER.remove(task);
+    Ok(tid as isize)
 }
+/// write the wait status of a ptrace stop to the user pointer of waitpid
+fn write_wait_status(task: &Arc<TaskControlBlock>, status_ptr: usize, status: usize) -> SysResult {
+    if status_ptr != 0 {
+        let mut vm = task.get_vm_space().lock();
+        let status_ptr = UserPtrRaw::new(status_ptr as *mut i32)
+            .ensure_write(vm.deref_mut())
+            .ok_or(SysError::EINVAL)?;
+        *status_ptr.to_mut() = status as i32;
+    }
+    Ok(0)
+}
+
 /// yield immediatly to another process
 pub async fn sys_yield() -> SysResult {
+    current_task().unwrap().yield_count.fetch_add(1, Ordering::Relaxed);
     crate::utils::async_utils::yield_now().await;
     Ok(0)
 }
 /// change the size of the heap
 pub fn sys_brk(addr: VirtAddr) -> SysResult {
     let task = current_task().unwrap();
-    let ret  = task.with_mut_vm_space(|vm_space| vm_space.reset_heap_break(addr).0) as isize;
+    let data_limit = task.with_rlimit_data(|limit| limit.rlim_cur);
+    let ret  = task.with_mut_vm_space(|vm_space| vm_space.reset_heap_break(addr, data_limit).0) as isize;
     Ok(ret)
 }
 
@@ -551,29 +591,160 @@ pub fn sys_exit_group(exit_code: i32) -> SysResult {
 /// syscall: getuid
 /// returns the real user ID of the calling process.
 /// These functions are always successful and never modify errno.
-/// todo
 pub fn sys_getuid() -> SysResult {
-    Ok(0)
+    let task = current_task().unwrap();
+    Ok(task.with_cred(|c| c.ruid) as isize)
 }
 
 /// syscall: geteuid
 /// returns the effective user ID of the calling process.
-/// todo
 pub fn sys_geteuid() -> SysResult {
-    Ok(0)
+    let task = current_task().unwrap();
+    Ok(task.with_cred(|c| c.euid) as isize)
+}
+
+/// syscall: getgid
+/// returns the real group ID of the calling process.
+pub fn sys_getgid() -> SysResult {
+    let task = current_task().unwrap();
+    Ok(task.with_cred(|c| c.rgid) as isize)
 }
 
 /// syscall: getegid
 /// getegid() returns the effective group ID of the calling process.
-/// todo
 pub fn sys_getegid() -> SysResult {
+    let task = current_task().unwrap();
+    Ok(task.with_cred(|c| c.egid) as isize)
+}
+
+/// syscall: setuid
+/// root sets the real, effective and saved user ID,
+/// others may only set the effective one to their real or saved user ID
+pub fn sys_setuid(uid: u32) -> SysResult {
+    let task = current_task().unwrap();
+    task.with_mut_cred(|c| c.setuid(uid))?;
     Ok(0)
 }
 
-///
+/// syscall: setgid
+pub fn sys_setgid(gid: u32) -> SysResult {
+    let task = current_task().unwrap();
+    task.with_mut_cred(|c| c.setgid(gid))?;
+    Ok(0)
+}
+
+/// syscall: setresuid
+/// sets the real, effective and saved user ID, -1 leaves an ID unchanged
+pub fn sys_setresuid(ruid: u32, euid: u32, suid: u32) -> SysResult {
+    let task = current_task().unwrap();
+    task.with_mut_cred(|c| c.setresuid(ruid, euid, suid))?;
+    Ok(0)
+}
+
+/// syscall: setresgid
+pub fn sys_setresgid(rgid: u32, egid: u32, sgid: u32) -> SysResult {
+    let task = current_task().unwrap();
+    task.with_mut_cred(|c| c.setresgid(rgid, egid, sgid))?;
+    Ok(0)
+}
+
+/// write three ids to user space, for getresuid and getresgid
+fn write_res_ids(task: &Arc<TaskControlBlock>, ptrs: [usize; 3], ids: [u32; 3]) -> SysResult {
+    for (ptr, id) in ptrs.into_iter().zip(ids) {
+        UserPtrRaw::new(ptr as *mut u32)
+            .ensure_write(&mut task.get_vm_space().lock())
+            .ok_or(SysError::EFAULT)?
+            .write(id);
+    }
+    Ok(0)
+}
+
+/// syscall: getresuid
+pub fn sys_getresuid(ruid: usize, euid: usize, suid: usize) -> SysResult {
+    let task = current_task().unwrap().clone();
+    let ids = task.with_cred(|c| [c.ruid, c.euid, c.suid]);
+    write_res_ids(&task, [ruid, euid, suid], ids)
+}
+
+/// syscall: getresgid
+pub fn sys_getresgid(rgid: usize, egid: usize, sgid: usize) -> SysResult {
+    let task = current_task().unwrap().clone();
+    let ids = task.with_cred(|c| [c.rgid, c.egid, c.sgid]);
+    write_res_ids(&task, [rgid, egid, sgid], ids)
+}
+
+/// syscall: getgroups
+/// size 0 returns the number of supplementary groups without writing the list
+pub fn sys_getgroups(size: usize, list: usize) -> SysResult {
+    let task = current_task().unwrap().clone();
+    let groups = task.with_cred(|c| c.groups.clone());
+    if size == 0 {
+        return Ok(groups.len() as isize);
+    }
+    if size < groups.len() {
+        return Err(SysError::EINVAL);
+    }
+    if !groups.is_empty() {
+        UserSliceRaw::new(list as *mut u32, groups.len())
+            .ensure_write(&mut task.get_vm_space().lock())
+            .ok_or(SysError::EFAULT)?
+            .to_mut()
+            .copy_from_slice(&groups);
+    }
+    Ok(groups.len() as isize)
+}
+
+/// syscall: setgroups
+/// only root may set the supplementary groups
+pub fn sys_setgroups(size: usize, list: usize) -> SysResult {
+    let task = current_task().unwrap().clone();
+    if !task.with_cred(|c| c.is_privileged()) {
+        return Err(SysError::EPERM);
+    }
+    if size > NGROUPS_MAX {
+        return Err(SysError::EINVAL);
+    }
+    let groups = if size == 0 {
+        Vec::new()
+    } else {
+        UserSliceRaw::new(list as *const u32, size)
+            .ensure_read(&mut task.get_vm_space().lock())
+            .ok_or(SysError::EFAULT)?
+            .to_ref()
+            .to_vec()
+    };
+    task.with_mut_cred(|c| c.groups = groups);
+    Ok(0)
+}
+
+/// setsid: start a new session and process group led by the caller, without a
+/// controlling terminal. A group leader stays where it is, EPERM
 pub fn sys_setsid() -> SysResult {
+    let task = current_task().unwrap().get_leader();
+    let pid = task.pid();
+    if task.pgid() == pid {
+        return Err(SysError::EPERM);
+    }
+    PROCESS_GROUP_MANAGER.add_group(&task);
+    *task.session.lock() = Session { sid: pid, ctty: false, hung_up: false };
+    Ok(pid as isize)
+}
+/// getsid: the session id of the process `pid`, of the caller for 0
+pub fn sys_getsid(pid: usize) -> SysResult {
+    let task = if pid == 0 {
+        current_task().unwrap().clone()
+    } else {
+        TASK_MANAGER.get_task(pid).ok_or(SysError::ESRCH)?
+    };
+    Ok(task.session().sid as isize)
+}
+/// vhangup: hang up the controlling terminal of the caller, see [`tty::hangup`]
+pub fn sys_vhangup() -> SysResult {
     let task = current_task().unwrap();
-    Ok(task.pid() as isize)
+    if !task.with_cred(|c| c.is_privileged()) {
+        return Err(SysError::EPERM);
+    }
+    tty::hangup()
 }
 ///  long syscall(SYS_clone3, struct clone_args *cl_args, size_t size);
 ///  glibc provides no wrapper for clone3(), necessitating the
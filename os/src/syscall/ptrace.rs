@@ -0,0 +1,151 @@
+//! ptrace related syscall
+//! enough of the interface for a strace-like tracer: attach, syscall stops,
+//! reading the registers and memory of the tracee
+
+use alloc::sync::Arc;
+use hal::addr::{PhysAddrHal, VirtAddr};
+
+use super::{IoVec, SysError, SysResult};
+use crate::{mm::{translate_uva_checked, vm::PageFaultAccessType, UserPtrRaw, UserSliceRaw}, signal::{SigInfo, SIGKILL, SIGRTMAX, SIGSTOP}, task::{current_task, manager::TASK_MANAGER, task::TaskControlBlock, INITPROC_PID}};
+
+/// the calling task is traced by its parent
+pub const PTRACE_TRACEME: usize = 0;
+/// read a word at addr of the tracee
+pub const PTRACE_PEEKTEXT: usize = 1;
+/// read a word at addr of the tracee
+pub const PTRACE_PEEKDATA: usize = 2;
+/// resume the tracee
+pub const PTRACE_CONT: usize = 7;
+/// kill the tracee
+pub const PTRACE_KILL: usize = 8;
+/// attach to a running task and stop it
+pub const PTRACE_ATTACH: usize = 16;
+/// detach from the tracee and resume it
+pub const PTRACE_DETACH: usize = 17;
+/// resume the tracee, stopping at the next syscall entry or exit
+pub const PTRACE_SYSCALL: usize = 24;
+/// set the ptrace options
+pub const PTRACE_SETOPTIONS: usize = 0x4200;
+/// read a register set of the tracee
+pub const PTRACE_GETREGSET: usize = 0x4204;
+/// the general purpose registers, for PTRACE_GETREGSET
+const NT_PRSTATUS: usize = 1;
+
+/// the tracee `pid` of the caller, which must be in a ptrace stop
+fn stopped_tracee(tracer: &Arc<TaskControlBlock>, pid: usize) -> Result<Arc<TaskControlBlock>, SysError> {
+    tracer.tracee(pid)
+        .filter(|t| t.in_ptrace_stop())
+        .ok_or(SysError::ESRCH)
+}
+
+/// whether `tracer` may trace `tracee`: it must be allowed to access the memory of the
+/// tracee, which must be dumpable
+fn may_trace(tracer: &TaskControlBlock, tracee: &TaskControlBlock) -> bool {
+    tracer.can_access_mm(tracee) && tracee.dumpable()
+}
+
+/// the signal to deliver on resume
+fn resume_sig(data: usize) -> Result<usize, SysError> {
+    if data > SIGRTMAX {
+        return Err(SysError::EIO);
+    }
+    Ok(data)
+}
+
+/// read a word of the tracee's memory
+fn peek_word(tracee: &Arc<TaskControlBlock>, addr: usize) -> Result<usize, SysError> {
+    let mut vm = tracee.get_vm_space().lock();
+    let mut bytes = [0u8; core::mem::size_of::<usize>()];
+    // byte by byte, the word may cross a page boundary
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        let pa = translate_uva_checked(&mut vm, VirtAddr::from(addr + i), PageFaultAccessType::READ)
+            .ok_or(SysError::EIO)?;
+        *byte = *pa.get_ref::<u8>();
+    }
+    Ok(usize::from_ne_bytes(bytes))
+}
+
+/// syscall: ptrace
+pub fn sys_ptrace(request: usize, pid: usize, addr: usize, data: usize) -> SysResult {
+    let task = current_task().unwrap().clone();
+    log::info!("[sys_ptrace]: task {} request {:#x} pid {} addr {:#x} data {:#x}", task.tid(), request, pid, addr, data);
+    match request {
+        PTRACE_TRACEME => {
+            if task.is_traced() {
+                return Err(SysError::EPERM);
+            }
+            let parent = task.get_leader().parent()
+                .and_then(|p| p.upgrade())
+                .ok_or(SysError::EPERM)?;
+            if !may_trace(&parent, &task) {
+                return Err(SysError::EPERM);
+            }
+            task.ptrace_attach(&parent);
+            Ok(0)
+        }
+        PTRACE_ATTACH => {
+            let tracee = TASK_MANAGER.get_task(pid).ok_or(SysError::ESRCH)?;
+            if tracee.pid() == task.pid() || tracee.tid() == INITPROC_PID || tracee.is_zombie() || tracee.is_traced()
+                || !may_trace(&task, &tracee)
+            {
+                return Err(SysError::EPERM);
+            }
+            tracee.ptrace_attach(&task);
+            tracee.recv_sigs(SigInfo { si_pid: Some(task.pid()), ..SigInfo::new(SIGSTOP, SigInfo::USER) });
+            Ok(0)
+        }
+        PTRACE_DETACH => {
+            let tracee = stopped_tracee(&task, pid)?;
+            tracee.ptrace_detach(resume_sig(data)?);
+            Ok(0)
+        }
+        PTRACE_CONT | PTRACE_SYSCALL => {
+            let tracee = stopped_tracee(&task, pid)?;
+            tracee.ptrace_resume(resume_sig(data)?, request == PTRACE_SYSCALL);
+            Ok(0)
+        }
+        PTRACE_KILL => {
+            let tracee = task.tracee(pid).ok_or(SysError::ESRCH)?;
+            tracee.recv_sigs(SigInfo { si_pid: Some(task.pid()), ..SigInfo::new(SIGKILL, SigInfo::USER) });
+            Ok(0)
+        }
+        PTRACE_SETOPTIONS => {
+            let tracee = stopped_tracee(&task, pid)?;
+            tracee.with_mut_ptrace(|p| p.options = data);
+            Ok(0)
+        }
+        PTRACE_PEEKTEXT | PTRACE_PEEKDATA => {
+            let tracee = stopped_tracee(&task, pid)?;
+            let word = peek_word(&tracee, addr)?;
+            // the raw syscall stores the word at data, the libc wrapper returns it
+            let data_ptr = UserPtrRaw::new(data as *mut usize)
+                .ensure_write(&mut task.get_vm_space().lock())
+                .ok_or(SysError::EFAULT)?;
+            data_ptr.write(word);
+            Ok(0)
+        }
+        PTRACE_GETREGSET => {
+            let tracee = stopped_tracee(&task, pid)?;
+            if addr != NT_PRSTATUS {
+                return Err(SysError::EINVAL);
+            }
+            let regs = tracee.user_regs();
+            let iov_ptr = UserPtrRaw::new(data as *mut IoVec)
+                .ensure_write(&mut task.get_vm_space().lock())
+                .ok_or(SysError::EFAULT)?;
+            let iov = iov_ptr.to_mut();
+            let len = iov.len.min(core::mem::size_of_val(&regs));
+            let bytes = unsafe { core::slice::from_raw_parts(regs.as_ptr() as *const u8, len) };
+            let buf = UserSliceRaw::new(iov.base as *mut u8, len)
+                .ensure_write(&mut task.get_vm_space().lock())
+                .ok_or(SysError::EFAULT)?;
+            buf.to_mut().copy_from_slice(bytes);
+            iov.len = len;
+            Ok(0)
+        }
+        _ => {
+            log::warn!("[sys_ptrace]: unsupported request {:#x}", request);
+            Err(SysError::EIO)
+        }
+    }
+}
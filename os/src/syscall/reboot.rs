@@ -1,14 +1,41 @@
-use core::time::Duration;
+use crate::{executor::shutdown::power_off, task::current_task};
 
-use hal::println;
+use super::SysError;
 
-use crate::{executor::os_send_shutdown, signal::{SigInfo, SIGTERM}, task::{current_task, manager::TASK_MANAGER, INITPROC_PID}, timer::timed_task::suspend_timeout};
+const LINUX_REBOOT_MAGIC1: u32 = 0xfee1dead;
+const LINUX_REBOOT_MAGIC2: u32 = 672274793;
+const LINUX_REBOOT_MAGIC2A: u32 = 85072278;
+const LINUX_REBOOT_MAGIC2B: u32 = 369367448;
+const LINUX_REBOOT_MAGIC2C: u32 = 537993216;
 
-use super::SysError;
+const LINUX_REBOOT_CMD_RESTART: u32 = 0x01234567;
+const LINUX_REBOOT_CMD_HALT: u32 = 0xcdef0123;
+const LINUX_REBOOT_CMD_CAD_ON: u32 = 0x89abcdef;
+const LINUX_REBOOT_CMD_CAD_OFF: u32 = 0x00000000;
+const LINUX_REBOOT_CMD_POWER_OFF: u32 = 0x4321fedc;
 
-pub async fn sys_reboot(_magic1: i32, _magic2: i32, _cmd: u32, _arg: usize) -> Result<isize, SysError> {
-    // let task = current_task().unwrap();
-    // log::info!("[sys_reboot] task {} send reboot", task.tid());
-    os_send_shutdown();
-    Ok(0)
-}
\ No newline at end of file
+/// syscall: reboot
+/// halt and power off power off after the tasks are terminated and the file systems unmounted,
+/// restart is not supported by the boards and is EINVAL
+pub async fn sys_reboot(magic1: i32, magic2: i32, cmd: u32, _arg: usize) -> Result<isize, SysError> {
+    let task = current_task().unwrap().clone();
+    if !task.with_cred(|c| c.is_privileged()) {
+        return Err(SysError::EPERM);
+    }
+    if magic1 as u32 != LINUX_REBOOT_MAGIC1
+        || ![LINUX_REBOOT_MAGIC2, LINUX_REBOOT_MAGIC2A, LINUX_REBOOT_MAGIC2B, LINUX_REBOOT_MAGIC2C]
+            .contains(&(magic2 as u32))
+    {
+        return Err(SysError::EINVAL);
+    }
+    match cmd {
+        LINUX_REBOOT_CMD_CAD_ON | LINUX_REBOOT_CMD_CAD_OFF => Ok(0),
+        // the boards have no way to reset
+        LINUX_REBOOT_CMD_RESTART => Err(SysError::EINVAL),
+        LINUX_REBOOT_CMD_HALT | LINUX_REBOOT_CMD_POWER_OFF => {
+            log::info!("[sys_reboot] task {} powers off", task.tid());
+            power_off(Some(task.pid())).await
+        }
+        _ => Err(SysError::EINVAL),
+    }
+}
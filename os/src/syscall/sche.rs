@@ -1,132 +1,305 @@
 use super::{SysError,SysResult};
-use core::sync::atomic::AtomicUsize;
+use core::{mem::size_of, ops::RangeInclusive};
+use alloc::{sync::Arc, vec, vec::Vec};
 
-use crate::{mm::UserPtrRaw, task::{current_task, manager::TASK_MANAGER, task::CpuMask}}; 
+use crate::{executor::{NICE_MAX, NICE_MIN}, mm::{UserPtrRaw, UserSliceRaw}, processor::{ipi::{harts_running, mm_key, send_ipi_and_wait}, processor::{current_processor, online_harts, CPU_MASK_ALL}}, task::{current_task, manager::{PROCESS_GROUP_MANAGER, TASK_MANAGER}, task::TaskControlBlock}, utils::async_utils::yield_now}; 
 
-/// syscall: 
+/// size in bytes of the cpu mask used by the kernel,
+/// what sched_getaffinity returns
+const KERNEL_CPUSET_SIZE: usize = size_of::<usize>();
+
+/// find the task of `pid`, 0 for the calling task,
+/// the caller must be allowed to renice it (EPERM)
+fn affinity_target(pid: usize) -> Result<Arc<TaskControlBlock>, SysError> {
+    let cur_task = current_task().unwrap().clone();
+    if pid == 0 {
+        return Ok(cur_task);
+    }
+    let task = TASK_MANAGER.get_task(pid).ok_or(SysError::ESRCH)?;
+    let cred = cur_task.with_cred(|cred| cred.clone());
+    if !task.with_cred(|target| cred.can_renice(target)) {
+        return Err(SysError::EPERM);
+    }
+    Ok(task)
+}
+
+/// syscall: sched_setaffinity
 /// sets the CPU affinity mask of the thread whose ID is pid to the value specified by mask.
-/// If pid is zero, then the calling process is used. 
-/// The argument cpusetsize is the length (in bytes) of the data pointed to by mask. 
-/// Normally this argument would be specified as sizeof(cpu_set_t).
-/// (TODO) If the process specified by pid is not currently running on one of the CPUs specified in mask, 
-/// then that process is migrated to one of the CPUs specified in mask.
-pub fn sys_sched_setaffinity(pid: usize, cpusetsize: usize, mask_ptr: usize) -> SysResult {
+/// If pid is zero, then the calling thread is used.
+/// The argument cpusetsize is the length (in bytes) of the data pointed to by mask,
+/// bits of harts the kernel does not have are ignored.
+/// If the calling thread is not running on one of the CPUs specified in mask,
+/// it is migrated to one of them before returning.
+pub async fn sys_sched_setaffinity(pid: usize, cpusetsize: usize, mask_ptr: usize) -> SysResult {
     log::info!("sys_sched_setaffinity: pid {pid} cpusetsize {cpusetsize} mask {:#x}", mask_ptr);
     let cur_task = current_task().unwrap().clone();
-    if cpusetsize < size_of::<CpuMask>() {
+    let task = affinity_target(pid)?;
+    let len = cpusetsize.min(KERNEL_CPUSET_SIZE);
+    let mut bytes = [0u8; KERNEL_CPUSET_SIZE];
+    if len > 0 {
+        let user_mask = UserSliceRaw::new(mask_ptr as *const u8, len)
+            .ensure_read(&mut cur_task.get_vm_space().lock())
+            .ok_or(SysError::EFAULT)?;
+        bytes[..len].copy_from_slice(user_mask.to_ref());
+    }
+    let mask = usize::from_le_bytes(bytes) & CPU_MASK_ALL;
+    if mask & online_harts() == 0 {
         return Err(SysError::EINVAL);
-    } 
-    let task = if pid == 0 {
-        cur_task.clone()
-    } else {
-        if let Some(t) = TASK_MANAGER.get_task(pid) {
-            t
-        } else {
-            return Err(SysError::ESRCH)
-        }
-    };
-    if !task.is_leader() {
-        // hack the cyclictest
-        // return Err(SysError::ESRCH);
     }
-    // todo: handle when pid is 0 , which means calling processor is used but now we have opened all the processors
-    let mask_ptr = UserPtrRaw::new(mask_ptr as *const CpuMask)
-        .ensure_read(&mut cur_task.get_vm_space().lock())
+    task.set_cpu_allowed(mask);
+    if Arc::ptr_eq(&task, &cur_task) && mask & (1 << current_processor().id()) == 0 {
+        // let the scheduler move us to an allowed hart
+        yield_now().await;
+    }
+    Ok(0)
+}
+
+/// syscall: sched_getaffinity
+/// writes the CPU affinity mask of the thread whose ID is pid into mask.
+/// If pid is zero, then the calling thread is used.
+/// The argument cpusetsize is the length (in bytes) of the data pointed to by mask,
+/// the bytes beyond the kernel mask are zero filled.
+/// On success, the raw sched_getaffinity() system call returns
+/// the size (in bytes) of the cpumask_t data type
+/// that is used internally by the kernel to represent the CPU set bit mask.
+pub fn sys_sched_getaffinity(pid: usize, cpusetsize: usize, mask_ptr: usize) -> SysResult {
+    log::info!("sys_sched_getaffinity pid {pid} cpusetsize {cpusetsize} mask {:#x}", mask_ptr);
+    if cpusetsize < KERNEL_CPUSET_SIZE || cpusetsize % size_of::<usize>() != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let cur_task = current_task().unwrap().clone();
+    let task = affinity_target(pid)?;
+    let user_mask = UserSliceRaw::new(mask_ptr as *mut u8, cpusetsize)
+        .ensure_write(&mut cur_task.get_vm_space().lock())
         .ok_or(SysError::EFAULT)?;
-    let mask = *mask_ptr.to_ref();
-    let task_cpu_mask = match mask {
-        CpuMask::CPU_ALL => {
-            15
-        }
-        CpuMask::CPU0 => {
-            1
+    let buf = user_mask.to_mut();
+    buf.fill(0);
+    buf[..KERNEL_CPUSET_SIZE].copy_from_slice(&task.cpu_allowed().to_le_bytes());
+    Ok(KERNEL_CPUSET_SIZE as isize)
+}
+
+/// syscall: getcpu
+/// determines the CPU and NUMA node on which the calling thread is running,
+/// there is only one node
+pub fn sys_getcpu(cpu: usize, node: usize, _tcache: usize) -> SysResult {
+    let task = current_task().unwrap().clone();
+    let id = current_processor().id() as u32;
+    for (ptr, val) in [(cpu, id), (node, 0)] {
+        if ptr == 0 {
+            continue;
         }
-        CpuMask::CPU1 => {
-            2
+        let user_ptr = UserPtrRaw::new(ptr as *mut u32)
+            .ensure_write(&mut task.get_vm_space().lock())
+            .ok_or(SysError::EFAULT)?;
+        user_ptr.write(val);
+    }
+    Ok(0)
+}
+/// membarrier: query the supported commands
+pub const MEMBARRIER_CMD_QUERY: usize = 0;
+/// membarrier: barrier on every running thread of the system
+pub const MEMBARRIER_CMD_GLOBAL: usize = 1 << 0;
+/// membarrier: barrier on every running thread of the caller's address space
+pub const MEMBARRIER_CMD_PRIVATE_EXPEDITED: usize = 1 << 3;
+/// membarrier: register the intent to use MEMBARRIER_CMD_PRIVATE_EXPEDITED
+pub const MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED: usize = 1 << 4;
+
+/// syscall: membarrier
+/// makes every thread selected by cmd execute a full memory barrier before returning,
+/// threads not running are already ordered by the context switch
+pub fn sys_membarrier(cmd: usize, flags: usize, _cpu_id: usize) -> SysResult {
+    if flags != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let task = current_task().unwrap().clone();
+    match cmd {
+        MEMBARRIER_CMD_QUERY => Ok((MEMBARRIER_CMD_GLOBAL
+            | MEMBARRIER_CMD_PRIVATE_EXPEDITED
+            | MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED) as isize),
+        MEMBARRIER_CMD_GLOBAL => {
+            send_ipi_and_wait(harts_running(None));
+            Ok(0)
         }
-        CpuMask::CPU2 => {
-            4
+        MEMBARRIER_CMD_PRIVATE_EXPEDITED => {
+            let registered = task.get_vm_space().lock().membarrier_state();
+            if registered & MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED == 0 {
+                return Err(SysError::EPERM);
+            }
+            send_ipi_and_wait(harts_running(Some(mm_key(&task))));
+            Ok(0)
         }
-        CpuMask::CPU3 => {
-            8
+        MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED => {
+            task.get_vm_space().lock().register_membarrier(cmd);
+            Ok(0)
         }
-        _ => {
-            panic!("Invalid cpu mask")
+        _ => Err(SysError::EINVAL),
+    }
+}
+
+
+/// the default time sharing policy
+pub const SCHED_OTHER: i32 = 0;
+/// real-time policies, only a privileged caller may set them
+pub const SCHED_FIFO: i32 = 1;
+/// see SCHED_FIFO
+pub const SCHED_RR: i32 = 2;
+/// time sharing for batch jobs, which never preempt the others on wake up
+pub const SCHED_BATCH: i32 = 3;
+/// background tasks, which run only when nothing else wants the hart
+pub const SCHED_IDLE: i32 = 5;
+/// or'ed into the policy of sched_setscheduler to reset it in children
+const SCHED_RESET_ON_FORK: i32 = 0x4000_0000;
+/// the priorities of the real-time policies, the others take 0
+const SCHED_RT_PRIORITY: RangeInclusive<i32> = 1..=99;
+
+/// find the task of `pid` for the sched_* syscalls, 0 for the calling task
+fn sched_target(pid: isize) -> Result<Arc<TaskControlBlock>, SysError> {
+    if pid < 0 {
+        return Err(SysError::EINVAL);
+    }
+    affinity_target(pid as usize)
+}
+
+/// syscall: sched_setscheduler
+/// sets the scheduling policy and parameters of the thread whose ID is pid.
+/// The caller must own the thread like for setpriority (EPERM), the real-time policies need
+/// privilege (EPERM), and only a privileged caller clears SCHED_RESET_ON_FORK once set
+pub fn sys_sched_setscheduler(pid: isize, policy: i32, param: usize) -> SysResult {
+    if param == 0 {
+        return Err(SysError::EINVAL);
+    }
+    let cur_task = current_task().unwrap().clone();
+    let priority = *UserPtrRaw::new(param as *const i32)
+        .ensure_read(&mut cur_task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?
+        .to_ref();
+    let task = sched_target(pid)?;
+    let reset_on_fork = policy & SCHED_RESET_ON_FORK != 0;
+    let policy = policy & !SCHED_RESET_ON_FORK;
+    let cred = cur_task.with_cred(|cred| cred.clone());
+    match policy {
+        SCHED_OTHER | SCHED_BATCH | SCHED_IDLE if priority == 0 => {}
+        SCHED_FIFO | SCHED_RR if SCHED_RT_PRIORITY.contains(&priority) => {
+            if !cred.is_privileged() {
+                return Err(SysError::EPERM);
+            }
         }
-    };
-    task.set_cpu_allowed(task_cpu_mask);
+        _ => return Err(SysError::EINVAL),
+    }
+    if !task.with_cred(|target| cred.can_renice(target)) {
+        return Err(SysError::EPERM);
+    }
+    if task.sched_reset_on_fork() && !reset_on_fork && !cred.is_privileged() {
+        return Err(SysError::EPERM);
+    }
+    log::info!("[sys_sched_setscheduler] tid {} policy {} priority {}", task.tid(), policy, priority);
+    // takes effect the next time the task is queued
+    task.set_sched_policy(policy);
+    task.set_sched_priority(priority);
+    task.set_sched_reset_on_fork(reset_on_fork);
     Ok(0)
 }
 
+/// syscall: sched_getscheduler
+/// returns the scheduling policy of the thread whose ID is pid,
+/// with SCHED_RESET_ON_FORK if it is set
+pub fn sys_sched_getscheduler(pid: isize) -> SysResult {
+    let task = sched_target(pid)?;
+    let reset_on_fork = if task.sched_reset_on_fork() { SCHED_RESET_ON_FORK } else { 0 };
+    Ok((task.sched_policy() | reset_on_fork) as isize)
+}
 
-/// syscall: sched_getaffinity
-/// sets the CPU affinity mask of the process whose ID is pid to the value specified by mask. 
-/// If pid is zero, then the calling process is used. 
-/// The argument cpusetsize is the length (in bytes) of the data pointed to by mask. 
-/// Normally this argument would be specified as sizeof(cpu_set_t).
-/// On success, the raw sched_getaffinity() system call returns 
-/// the size (in bytes) of the cpumask_t data type 
-/// that is used internally by the kernel to represent the CPU set bit mask.
-pub fn sys_sched_getaffinity(pid: usize, cpusetusize: usize, mask_ptr: usize) -> SysResult {
-    log::info!("sys_sched_getaffinity pid {pid} cpusetsize {cpusetusize} mask {:#x}", mask_ptr);
+/// syscall: sched_getparam
+/// writes the scheduling priority of the thread whose ID is pid into param,
+/// which is 0 unless its policy is real-time
+pub fn sys_sched_getparam(pid: isize, param: usize) -> SysResult {
+    if param == 0 {
+        return Err(SysError::EINVAL);
+    }
     let cur_task = current_task().unwrap().clone();
-    let mask_ptr = UserPtrRaw::new(mask_ptr as *const CpuMask)
+    let task = sched_target(pid)?;
+    let user_param = UserPtrRaw::new(param as *mut i32)
         .ensure_write(&mut cur_task.get_vm_space().lock())
         .ok_or(SysError::EFAULT)?;
-    let mask = mask_ptr.to_mut();
-    if cpusetusize < size_of::<CpuMask>() {
-        return Err(SysError::EINVAL);
-    } 
-
-    let task = if pid == 0 {
-        cur_task.clone()
-    } else {
-        if let Some(t) = TASK_MANAGER.get_task(pid) {
-            t
-        } else {
-            return Err(SysError::ESRCH)
+    user_param.write(task.sched_priority());
+    Ok(0)
+}
+
+/// setpriority and getpriority: `who` is a process id
+pub const PRIO_PROCESS: i32 = 0;
+/// setpriority and getpriority: `who` is a process group id
+pub const PRIO_PGRP: i32 = 1;
+/// setpriority and getpriority: `who` is a real user id
+pub const PRIO_USER: i32 = 2;
+
+/// the live tasks selected by `which` and `who` of setpriority and getpriority,
+/// `who` 0 stands for the calling process, its process group or its real user
+fn priority_targets(which: i32, who: u32) -> Result<Vec<Arc<TaskControlBlock>>, SysError> {
+    let cur_task = current_task().unwrap().clone();
+    let targets: Vec<_> = match which {
+        PRIO_PROCESS => {
+            if who == 0 {
+                vec![cur_task]
+            } else {
+                TASK_MANAGER.get_task(who as usize).into_iter().collect()
+            }
+        }
+        PRIO_PGRP => {
+            let pgid = if who == 0 { cur_task.pgid() } else { who as usize };
+            PROCESS_GROUP_MANAGER.get_group(pgid)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|task| task.upgrade())
+                .collect()
         }
+        PRIO_USER => {
+            let uid = if who == 0 { cur_task.with_cred(|cred| cred.ruid) } else { who };
+            TASK_MANAGER.tasks_group()
+                .into_iter()
+                .filter(|task| task.with_cred(|cred| cred.ruid) == uid)
+                .collect()
+        }
+        _ => return Err(SysError::EINVAL),
     };
-    if !task.is_leader() {
-        log::warn!("get task {pid} not leader");
+    let targets: Vec<_> = targets.into_iter().filter(|task| !task.is_zombie()).collect();
+    if targets.is_empty() {
         return Err(SysError::ESRCH);
     }
-    let cpu_mask = match task.cpu_allowed() {
-        15 => {
-            CpuMask::CPU_ALL
-        }
-        1 => {
-            CpuMask::CPU0
-        }
-        2 => {
-            CpuMask::CPU1
-        }
-        4 => {
-            CpuMask::CPU2
-        }
-        8 => {
-            CpuMask::CPU3
+    Ok(targets)
+}
+
+/// syscall: setpriority
+/// sets the nice value of the processes selected by which and who to prio, clamped to -20..19.
+/// The caller must own each of them (EPERM),
+/// and lowering a nice value needs privilege (EACCES, like linux)
+pub fn sys_setpriority(which: i32, who: u32, prio: i32) -> SysResult {
+    let cur_task = current_task().unwrap().clone();
+    let nice = prio.clamp(NICE_MIN, NICE_MAX);
+    let cred = cur_task.with_cred(|cred| cred.clone());
+    let mut ret = Ok(0);
+    for task in priority_targets(which, who)? {
+        if !task.with_cred(|target| cred.can_renice(target)) {
+            ret = Err(SysError::EPERM);
+            continue;
         }
-        _ => {
-            panic!("Invalid cpu mask")
+        if nice < task.nice() && !cred.is_privileged() {
+            ret = Err(SysError::EACCES);
+            continue;
         }
-    };
-    log::info!("cpu mask {:?}", cpu_mask);
-    *mask = cpu_mask;
-    Ok(size_of::<CpuMask>() as isize)
-}
-///
-pub fn sys_sched_setscheduler() -> SysResult {
-    log::warn!("[sys_sched_setscheduler] unimplemented");
-    Ok(0)
+        // takes effect the next time the task is queued
+        task.set_nice(nice);
+    }
+    ret
 }
-///
-pub fn sys_sched_getscheduler() -> SysResult {
-    log::warn!("[sys_sched_getscheduler] unimplemented");
-    Ok(0)
+
+/// syscall: getpriority
+/// returns the highest priority, the lowest nice value, of the processes selected by which and who.
+/// The raw syscall returns 20 - nice, in 1..40, so a valid result is never negative
+pub fn sys_getpriority(which: i32, who: u32) -> SysResult {
+    let nice = priority_targets(which, who)?
+        .iter()
+        .map(|task| task.nice())
+        .min()
+        .unwrap();
+    Ok((20 - nice) as isize)
 }
-/// 
-pub fn sys_sched_getparam() -> SysResult {
-    log::warn!("[sys_sched_getparam] unimplemented");
-    Ok(0)
-}
\ No newline at end of file
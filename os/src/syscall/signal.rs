@@ -41,30 +41,33 @@ pub fn sys_kill(pid: isize, signo: i32) -> SysResult {
                 .unwrap()
                 .into_iter()
                 .map(|inner| inner.upgrade().unwrap())
-                .filter(|inner| inner.is_leader())
+                .filter(|inner| inner.is_leader() && cur_task.can_signal(inner))
             {
                 process.recv_sigs_process_level(
-                    SigInfo {
-                        si_signo: signo as usize,
-                        si_code: SigInfo::USER,
-                        si_pid: Some(cur_task.pid())
-                    }
+                    SigInfo { si_pid: Some(cur_task.pid()), ..SigInfo::new(signo as usize, SigInfo::USER) }
                 );
             }
         }
         -1 => {
-            // sent to every process which current process has permission ( except init proc )
+            // sent to every process which current process has permission ( except init proc and itself ),
+            // EPERM if there were processes but none it may signal
             //panic!("[sys_kill] unsupport for sending signal to all process");
+            let (mut found, mut sent) = (false, false);
             TASK_MANAGER.for_each_task(|task|{
-                if task.tid() == INITPROC_PID {
+                if task.tid() == INITPROC_PID || task.pid() == cur_task.pid() || !task.is_leader() {
                     return;
                 }
-                if signo != 0 && task.is_leader(){
+                found = true;
+                if cur_task.can_signal(task) {
+                    sent = true;
                     task.recv_sigs_process_level(
-                        SigInfo { si_signo: signo as usize, si_code: SigInfo::USER, si_pid: Some(cur_task.pid()) },
+                        SigInfo { si_pid: Some(cur_task.pid()), ..SigInfo::new(signo as usize, SigInfo::USER) },
                     );
                 }
             });
+            if !sent {
+                return Err(if found { SysError::EPERM } else { SysError::ESRCH });
+            }
         }
         _ if pid < -1 => {
             // sent to every process in process group whose ID is -pid
@@ -76,8 +79,8 @@ pub fn sys_kill(pid: isize, signo: i32) -> SysResult {
                 .into_iter()
                 .map(|t| t.upgrade().unwrap())
             {
-                if task.tid() == inner_pid {
-                    task.recv_sigs_process_level(SigInfo { si_signo: signo as usize, si_code: SigInfo::USER, si_pid: Some(cur_task.pgid()) });
+                if task.tid() == inner_pid && cur_task.can_signal(&task) {
+                    task.recv_sigs_process_level(SigInfo { si_pid: Some(cur_task.pgid()), ..SigInfo::new(signo as usize, SigInfo::USER) });
                 }
             }
         }
@@ -86,8 +89,11 @@ pub fn sys_kill(pid: isize, signo: i32) -> SysResult {
             //assert!(task.gettid() != pid as usize); // should not send to itself
             if let Some(task) = TASK_MANAGER.get_task(pid as usize) {
                 if task.is_leader() {
+                    if !cur_task.can_signal(&task) {
+                        return Err(SysError::EPERM);
+                    }
                     task.recv_sigs_process_level(
-                        SigInfo { si_signo: signo as usize, si_code: SigInfo::USER, si_pid: Some(cur_task.pid()) },
+                        SigInfo { si_pid: Some(cur_task.pid()), ..SigInfo::new(signo as usize, SigInfo::USER) },
                     );
                 }else {
                     // todo standard error
@@ -366,12 +372,11 @@ pub fn sys_tkill(tid: isize, sig: i32) -> SysResult {
     let cur_task = current_task().unwrap();
     let task = TASK_MANAGER.get_task(tid as usize)
         .ok_or(SysError::ESRCH)?;
+    if !cur_task.can_signal(&task) {
+        return Err(SysError::EPERM);
+    }
     task.recv_sigs(
-        SigInfo {
-            si_signo: sig as usize,
-            si_code: SigInfo::TKILL,
-            si_pid: Some(cur_task.pid()),
-        }
+        SigInfo { si_pid: Some(cur_task.pid()), ..SigInfo::new(sig as usize, SigInfo::TKILL) }
     );
     Ok(0)
 }
@@ -392,10 +397,14 @@ pub fn sys_tgkill(tgid: isize, tid: isize, signo: i32) -> SysResult {
     let cur_task = current_task().unwrap();
     let task = TASK_MANAGER.get_task(tgid as usize).ok_or(SysError::ESRCH)?;
     if task.is_leader() {
+        // the threads share the credentials of their leader
+        if !cur_task.can_signal(&task) {
+            return Err(SysError::EPERM);
+        }
         task.with_mut_thread_group(|thread_group| -> SysResult {
             for thread in thread_group.iter() {
                 if thread.tid() == tid as usize {
-                    thread.recv_sigs(SigInfo { si_signo: signo as usize, si_code: SigInfo::TKILL, si_pid: Some(cur_task.pid())});
+                    thread.recv_sigs(SigInfo { si_pid: Some(cur_task.pid()), ..SigInfo::new(signo as usize, SigInfo::TKILL) });
                     return Ok(0)
                 }
             }
@@ -1,5 +1,8 @@
+use smoltcp::socket::{tcp, udp};
 use strum::FromRepr;
 
+use crate::devices::DevError;
+
 /// reference: linux/include/uapi/asm-generic/errno.h
 #[derive(Clone, Copy, Debug, Eq, PartialEq, FromRepr)]
 #[repr(i32)]
@@ -84,16 +87,28 @@ pub enum SysError {
     ENOTEMPTY = 39,
     /// Too many symbolic links encountered
     ELOOP = 40,
+    /// No data available
+    ENODATA = 61,
     /// Timer expired   
     ETIME = 62,
+    /// Value too large for defined data type
+    EOVERFLOW = 75,
     /// Socket operation on non-socket
     ENOTSOCK = 88,
+    /// Message too long
+    EMSGSIZE = 90,
+    /// Protocol not available
+    ENOPROTOOPT = 92,
     /// Unsupported
     EOPNOTSUPP = 95,
+    /// Address family not supported by protocol
+    EAFNOSUPPORT = 97,
     /// Socket address is already in use
     EADDRINUSE = 98,
     /// Address not available
     EADDRNOTAVAIL = 99,
+    /// Network is unreachable
+    ENETUNREACH = 101,
     /// Connection reset
     ECONNRESET = 104,
     /// Transport endpoint is already connected
@@ -104,9 +119,15 @@ pub enum SysError {
     ETIMEOUT = 110,
     /// Connection refused
     ECONNREFUSED = 111,
+    /// No route to host
+    EHOSTUNREACH = 113,
+    /// A connection attempt is already in progress
+    EALREADY = 114,
     /// The socket is nonblocking and the connection cannot be completed
     /// immediately.(connect.2)
     EINPROGRESS = 115,
+    /// Stale file handle
+    ESTALE = 116,
     EOWNERDIED = 130,
 }
 
@@ -115,10 +136,112 @@ impl SysError {
     pub const fn code(self) -> isize {
         self as isize
     }
+}
+
+/// the error codes lwext4 returns, negated or not, and the fat inodes after it;
+/// a code without a matching errno is reported as EIO
+impl From<i32> for SysError {
+    fn from(e: i32) -> Self {
+        use SysError::*;
+        match e.unsigned_abs() {
+            1 => EPERM,
+            2 => ENOENT,
+            5 => EIO,
+            6 => ENXIO,
+            7 => E2BIG,
+            12 => ENOMEM,
+            13 => EACCES,
+            14 => EFAULT,
+            16 => EBUSY,
+            17 => EEXIST,
+            18 => EXDEV,
+            19 => ENODEV,
+            20 => ENOTDIR,
+            21 => EISDIR,
+            22 => EINVAL,
+            27 => EFBIG,
+            28 => ENOSPC,
+            30 => EROFS,
+            31 => EMLINK,
+            34 => ERANGE,
+            36 => ENAMETOOLONG,
+            39 => ENOTEMPTY,
+            40 => ELOOP,
+            61 => ENODATA,
+            75 => EOVERFLOW,
+            95 => EOPNOTSUPP,
+            _ => EIO,
+        }
+    }
+}
+
+impl From<DevError> for SysError {
+    fn from(e: DevError) -> Self {
+        match e {
+            DevError::AlreadyExists => SysError::EEXIST,
+            DevError::Again => SysError::EAGAIN,
+            DevError::BadState | DevError::Io => SysError::EIO,
+            DevError::InvalidParam => SysError::EINVAL,
+            DevError::NoMemory => SysError::ENOMEM,
+            DevError::ResourceBusy => SysError::EBUSY,
+            DevError::Unsupported => SysError::EOPNOTSUPP,
+        }
+    }
+}
+
+/// connect on a socket smoltcp does not hold closed, or to an address it can't reach
+impl From<tcp::ConnectError> for SysError {
+    fn from(e: tcp::ConnectError) -> Self {
+        match e {
+            tcp::ConnectError::InvalidState => SysError::EISCONN,
+            tcp::ConnectError::Unaddressable => SysError::EADDRNOTAVAIL,
+        }
+    }
+}
+
+impl From<tcp::ListenError> for SysError {
+    fn from(e: tcp::ListenError) -> Self {
+        match e {
+            tcp::ListenError::InvalidState => SysError::EINVAL,
+            tcp::ListenError::Unaddressable => SysError::EADDRNOTAVAIL,
+        }
+    }
+}
+
+/// a send on a connection which can no longer send
+impl From<tcp::SendError> for SysError {
+    fn from(e: tcp::SendError) -> Self {
+        match e {
+            tcp::SendError::InvalidState => SysError::EPIPE,
+        }
+    }
+}
 
-    /// get the error from i32
-    pub fn from_i32(e: i32) -> Self {
-        Self::from_repr(e).expect("unknown error")
+/// a receive on a connection which can not receive, or whose peer closed it; the callers
+/// check the peer closing first, as it reads as the end of the stream rather than an error
+impl From<tcp::RecvError> for SysError {
+    fn from(e: tcp::RecvError) -> Self {
+        match e {
+            tcp::RecvError::InvalidState => SysError::ENOTCONN,
+            tcp::RecvError::Finished => SysError::ECONNRESET,
+        }
     }
 }
 
+impl From<udp::BindError> for SysError {
+    fn from(e: udp::BindError) -> Self {
+        match e {
+            udp::BindError::InvalidState => SysError::EINVAL,
+            udp::BindError::Unaddressable => SysError::EADDRNOTAVAIL,
+        }
+    }
+}
+
+impl From<udp::SendError> for SysError {
+    fn from(e: udp::SendError) -> Self {
+        match e {
+            udp::SendError::BufferFull => SysError::EAGAIN,
+            udp::SendError::Unaddressable => SysError::EINVAL,
+        }
+    }
+}
@@ -5,26 +5,55 @@ use core::{ops::DerefMut, time::Duration};
 use alloc::{boxed::Box, fmt, sync::Arc};
 use fatfs::info;
 use hal::instruction::{Instruction, InstructionHal};
+use hal::trap::TrapContextHal;
 use xmas_elf::program::Flags;
 
-use crate::{mm::UserPtrRaw, processor::context::SumGuard, task::current_task, timer::{clock::{CLOCK_DEVIATION, CLOCK_MONOTONIC, CLOCK_MONOTONIC_COARSE, CLOCK_PROCESS_CPUTIME_ID, CLOCK_REALTIME, CLOCK_REALTIME_COARSE, CLOCK_THREAD_CPUTIME_ID}, ffi::{TimeSpec, TimeVal}, get_current_time_duration, get_current_time_ms, get_current_time_us, timed_task::{ksleep,suspend_timeout}, timer::{alloc_timer_id, ITimerVal, RealITimer, Timer, TIMER_MANAGER}}, utils::Select2Futures
+use crate::{devices::rtc::RTC, mm::UserPtrRaw, processor::context::SumGuard, task::{current_task, task::TaskControlBlock}, timer::{clock::{CLOCK_MONOTONIC, CLOCK_MONOTONIC_COARSE, CLOCK_PROCESS_CPUTIME_ID, CLOCK_REALTIME, CLOCK_REALTIME_COARSE, CLOCK_THREAD_CPUTIME_ID}, ffi::{TimeSpec, TimeVal}, get_current_time_duration, get_realtime_duration, set_realtime, timed_task::{ksleep, suspend_timeout, SleepRestart}, timer::{alloc_timer_id, ITimerVal, RealITimer, Timer, TIMER_MANAGER}}, utils::Select2Futures
 };
 use super::{SysError, SysResult};
 /// get current time of day
+/// the clock itself is read without a lock, only the user pointer is checked
 pub fn sys_gettimeofday(tv: usize) -> SysResult {
+    if tv == 0 {
+        return Ok(0);
+    }
     let task = current_task().unwrap();
     let mut vm = task.get_vm_space().lock();
     let tv_ptr = UserPtrRaw::new(tv as *mut TimeVal)
         .ensure_write(&mut vm)
-        .ok_or(SysError::EINVAL)?;
-    let current_time = get_current_time_us();
-    let time_val = TimeVal {
-        sec: current_time / 1_000_000,
-        usec: (current_time % 1_000_000),
-    };
+        .ok_or(SysError::EFAULT)?;
+    let time_val: TimeVal = get_realtime_duration().into();
     tv_ptr.write(time_val);
     Ok(0)
 }
+
+/// set current time of day, the timezone argument is obsolete and ignored
+pub fn sys_settimeofday(tv: usize, _tz: usize) -> SysResult {
+    if tv == 0 {
+        return Ok(0);
+    }
+    let task = current_task().unwrap();
+    if !task.with_cred(|cred| cred.is_privileged()) {
+        return Err(SysError::EPERM);
+    }
+    let tv_ptr = UserPtrRaw::new(tv as *const TimeVal)
+        .ensure_read(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?;
+    let time_val = *tv_ptr.to_ref();
+    if time_val.usec >= 1_000_000 || (time_val.sec as isize) < 0 {
+        return Err(SysError::EINVAL);
+    }
+    set_wall_clock(time_val.into());
+    Ok(0)
+}
+
+/// set CLOCK_REALTIME and write it back to the rtc, so it survives a reboot
+fn set_wall_clock(now: Duration) {
+    set_realtime(now);
+    if let Some(rtc) = RTC.as_ref() {
+        rtc.set_time(now);
+    }
+}
 use crate::timer::ffi::Tms;
 /// times syscall
 pub fn sys_times(tms: usize) -> SysResult {
@@ -32,31 +61,86 @@ pub fn sys_times(tms: usize) -> SysResult {
     let tms_ptr = UserPtrRaw::new(tms as *mut Tms)
         .ensure_write(&mut task.get_vm_space().lock())
         .ok_or(SysError::EINVAL)?;
-    let current_task = current_task().unwrap();
-    let tms_val = Tms::from_time_recorder(current_task.time_recorder());
+    // the whole process, whichever thread asks
+    let tms_val = Tms::from_times(task.process_time_pair(), task.get_leader().time_recorder().child_time_pair());
     tms_ptr.write(tms_val);
     Ok(0)
 }
-/// sleep syscall
-pub async fn sys_nanosleep(time_ptr: usize, time_out_ptr: usize) -> SysResult {
-    let task = current_task().unwrap();
-    let time_val_ptr = 
-        UserPtrRaw::new(time_ptr as *const TimeSpec)
-            .ensure_read(&mut task.get_vm_space().lock())
-            .ok_or(SysError::EINVAL)?;
-    let time_val = *time_val_ptr.to_ref();
-    let time_out_ptr = 
-        UserPtrRaw::new(time_out_ptr as *const TimeSpec)
+/// sleep until `deadline` on the monotonic clock, through spurious wake ups, unless a signal
+/// the task neither blocks nor ignores comes first: return whether a signal ended the sleep
+async fn sleep_until(task: &Arc<TaskControlBlock>, deadline: Duration) -> bool {
+    loop {
+        let now = get_current_time_duration();
+        if now >= deadline {
+            return false;
+        }
+        // interruptable before the check, a signal coming after it wakes the suspend
+        let wake_up_sigs = task.intr_sigs();
+        task.set_interruptable();
+        task.set_wake_up_sigs(wake_up_sigs);
+        if task.with_sig_manager(|s| s.check_pending_flag(wake_up_sigs)) {
+            task.set_running();
+            return true;
+        }
+        suspend_timeout(task, deadline - now).await;
+        task.set_running();
+    }
+}
+
+/// the deadline of the sleep the current syscall resumes, if it is the restart of one cut short
+fn take_sleep_restart(task: &Arc<TaskControlBlock>) -> Option<Duration> {
+    let syscall = task.current_syscall();
+    let pc = *task.get_trap_cx().sepc();
+    task.with_mut_sleep_restart(|restart| {
+        let r = restart.filter(|r| r.syscall == syscall && r.pc == pc)?;
+        *restart = None;
+        Some(r.deadline)
+    })
+}
+
+/// a relative sleep to `deadline` was interrupted: write the time left to `rem_ptr` if given,
+/// and keep the deadline for a restart of the syscall
+fn interrupt_relative_sleep(task: &Arc<TaskControlBlock>, deadline: Duration, rem_ptr: usize) -> SysResult {
+    if rem_ptr != 0 {
+        let rem = UserPtrRaw::new(rem_ptr as *mut TimeSpec)
             .ensure_write(&mut task.get_vm_space().lock())
-            .ok_or(SysError::EINVAL)?;
-    let time_out = time_out_ptr.to_mut();
-    let sleep_time_duration = time_val.into();
-    let remain = suspend_timeout(current_task().unwrap(), sleep_time_duration).await;
-    if remain.is_zero() {
-        Ok(0)
+            .ok_or(SysError::EFAULT)?;
+        rem.write(deadline.saturating_sub(get_current_time_duration()).into());
+    }
+    let restart = SleepRestart {
+        syscall: task.current_syscall(),
+        pc: *task.get_trap_cx().sepc(),
+        deadline,
+    };
+    task.with_mut_sleep_restart(|r| *r = Some(restart));
+    Err(SysError::EINTR)
+}
+
+/// the duration of the user timespec at `ts`
+fn read_timespec(task: &Arc<TaskControlBlock>, ts: usize) -> Result<Duration, SysError> {
+    let ts_ptr = UserPtrRaw::new(ts as *const TimeSpec)
+        .ensure_read(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?;
+    let ts = *ts_ptr.to_ref();
+    if ts.tv_nsec >= 1_000_000_000 || (ts.tv_sec as isize) < 0 {
+        return Err(SysError::EINVAL);
+    }
+    Ok(ts.into())
+}
+
+/// sleep for a duration of the monotonic clock.
+/// a signal ends it with EINTR and the time left in `rem_ptr`, a restart of it
+/// through SA_RESTART sleeps only what was left
+pub async fn sys_nanosleep(req_ptr: usize, rem_ptr: usize) -> SysResult {
+    let task = current_task().unwrap();
+    let deadline = match take_sleep_restart(task) {
+        Some(deadline) => deadline,
+        None => get_current_time_duration() + read_timespec(task, req_ptr)?,
+    };
+    if sleep_until(task, deadline).await {
+        interrupt_relative_sleep(task, deadline, rem_ptr)
     } else {
-        *time_out = remain.into();
-        Err(SysError::EINTR)
+        Ok(0)
     }
 }
 
@@ -71,10 +155,14 @@ pub fn sys_clock_gettime(clock_id: usize, ts: usize) -> SysResult {
     let ts_ptr = ts as *mut TimeSpec;
 
     match clock_id {
-        CLOCK_REALTIME | CLOCK_MONOTONIC => {
-            let current = get_current_time_duration();
+        CLOCK_REALTIME | CLOCK_REALTIME_COARSE => {
             unsafe {
-                ts_ptr.write((CLOCK_DEVIATION[clock_id] + current).into());
+                ts_ptr.write(get_realtime_duration().into());
+            }
+        }
+        CLOCK_MONOTONIC | CLOCK_MONOTONIC_COARSE => {
+            unsafe {
+                ts_ptr.write(get_current_time_duration().into());
             }
         }
         CLOCK_PROCESS_CPUTIME_ID => {
@@ -86,18 +174,6 @@ pub fn sys_clock_gettime(clock_id: usize, ts: usize) -> SysResult {
             let cpu_time = user_time + kernel_time;
             unsafe { ts_ptr.write(cpu_time.into()); }
         }
-        CLOCK_REALTIME_COARSE => {
-            let current = get_current_time_duration();
-            unsafe {
-                ts_ptr.write((CLOCK_DEVIATION[CLOCK_REALTIME] + current).into());
-            }
-        }
-        CLOCK_MONOTONIC_COARSE => {
-            let current = get_current_time_duration();
-            unsafe {
-                ts_ptr.write((CLOCK_DEVIATION[CLOCK_MONOTONIC] + current).into());
-            }
-        }
         _ => {
             log::warn!("[sys_clock_gettime] unsupported clockid {}", clock_id);
             return Err(SysError::EINVAL);
@@ -106,6 +182,89 @@ pub fn sys_clock_gettime(clock_id: usize, ts: usize) -> SysResult {
     Ok(0)
 }
 
+/// syscall: clock_settime
+/// only CLOCK_REALTIME is settable, the others return EINVAL as on linux
+pub fn sys_clock_settime(clock_id: usize, ts: usize) -> SysResult {
+    if clock_id != CLOCK_REALTIME {
+        return Err(SysError::EINVAL);
+    }
+    let task = current_task().unwrap();
+    if !task.with_cred(|cred| cred.is_privileged()) {
+        return Err(SysError::EPERM);
+    }
+    let ts_ptr = UserPtrRaw::new(ts as *const TimeSpec)
+        .ensure_read(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?;
+    let ts = *ts_ptr.to_ref();
+    if !ts.is_valid() {
+        return Err(SysError::EINVAL);
+    }
+    set_wall_clock(ts.into());
+    Ok(0)
+}
+
+/// struct timex for adjtimex, see <sys/timex.h>
+#[derive(Default, Clone, Copy)]
+#[repr(C)]
+#[allow(missing_docs)]
+pub struct Timex {
+    pub modes: u32,
+    pub offset: isize,
+    pub freq: isize,
+    pub maxerror: isize,
+    pub esterror: isize,
+    pub status: i32,
+    pub constant: isize,
+    pub precision: isize,
+    pub tolerance: isize,
+    pub time: TimeVal,
+    pub tick: isize,
+    pub ppsfreq: isize,
+    pub jitter: isize,
+    pub shift: i32,
+    pub stabil: isize,
+    pub jitcnt: isize,
+    pub calcnt: isize,
+    pub errcnt: isize,
+    pub stbcnt: isize,
+    pub tai: i32,
+    pub _reserved: [i32; 11],
+}
+
+/// clock is synchronized
+const TIME_OK: isize = 0;
+/// clock is not synchronized, as long as STA_UNSYNC is set
+const TIME_ERROR: isize = 5;
+/// clock not synchronized, the kernel does no ntp discipline
+const STA_UNSYNC: i32 = 0x0040;
+
+/// syscall: adjtimex
+/// a stub: there is no ntp discipline, adjustments are accepted and ignored
+/// and the reported state is that of an unsynchronized, undisciplined clock,
+/// so the clock state returned is TIME_ERROR
+pub fn sys_adjtimex(buf: usize) -> SysResult {
+    let task = current_task().unwrap();
+    let buf_ptr = UserPtrRaw::new(buf as *mut Timex)
+        .ensure_write(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?;
+    let modes = buf_ptr.to_ref().modes;
+    log::info!("[sys_adjtimex] modes {:#x} ignored", modes);
+    let timex = Timex {
+        modes,
+        maxerror: 16_000_000,
+        esterror: 16_000_000,
+        status: STA_UNSYNC,
+        constant: 2,
+        precision: 1,
+        tolerance: 32_768_000,
+        time: get_realtime_duration().into(),
+        tick: 10_000,
+        ..Default::default()
+    };
+    buf_ptr.write(timex);
+    Ok(if timex.status & STA_UNSYNC != 0 { TIME_ERROR } else { TIME_OK })
+}
+
 /// syscall: sys clock getres
 /// clock_getres() finds the resolution (precision) of
 /// the specified clock clockid, and, if res is non-NULL, stores it in
@@ -144,7 +303,7 @@ pub fn sys_setitimer(
         return Err(SysError::EINVAL);
     }
     let id = alloc_timer_id();
-    let (prev_timeval, next_expire) = task.with_mut_itimers(|itimers|{
+    let (prev_timeval, next_expire, prev_handle) = task.with_mut_itimers(|itimers|{
         let itimer = &mut itimers[which];
         let prev_timeval = ITimerVal {
             it_interval: itimer.interval.into(),
@@ -152,22 +311,32 @@ pub fn sys_setitimer(
         };
         itimer.interval = new.it_interval.into();
         itimer.id = id;
+        let prev_handle = itimer.handle.take();
         if new.it_value.is_zero() {
             itimer.next_expire = Duration::ZERO;
-            (prev_timeval, Duration::ZERO)
+            (prev_timeval, Duration::ZERO, prev_handle)
         }else {
             let next_expire = get_current_time_duration() + new.it_value.into();
             itimer.next_expire = next_expire;
-            (prev_timeval, next_expire)
+            (prev_timeval, next_expire, prev_handle)
         }
     });
+    // a firing old timer sees the new id and stops
+    if let Some(handle) = prev_handle {
+        handle.cancel();
+    }
 
     if !new.it_value.is_zero(){
         let timer = Timer::new(next_expire, Box::new(RealITimer{
             task: Arc::downgrade(task),
             id: id
         }));
-        TIMER_MANAGER.add_timer(timer);
+        let handle = TIMER_MANAGER.add_timer(timer);
+        task.with_mut_itimers(|itimers| {
+            if itimers[which].id == id {
+                itimers[which].handle = Some(handle);
+            }
+        });
     }
     if old_ptr != 0{
         unsafe {
@@ -201,8 +370,12 @@ pub fn sys_getitimer(which: usize, now_ptr: usize) -> SysResult {
     Ok(0)
 }
 
+/// clock_nanosleep: flag of an absolute time
+const TIMER_ABSTIME: usize = 1;
+
 /// clock_nanosleep is a more general version of nanosleep, 
 /// which allows for more precise timing control.
+/// an absolute sleep is restarted as it is and never writes a time left
 pub async fn sys_clock_nanosleep(
     clock_id: usize,
     flags: usize,
@@ -210,37 +383,27 @@ pub async fn sys_clock_nanosleep(
     rem_ptr: usize
 ) -> SysResult {
     let task = current_task().unwrap();
-    match clock_id {
-        CLOCK_REALTIME | CLOCK_MONOTONIC => {
-            let t = unsafe {
-                Instruction::set_sum();
-                *(t_ptr as *const TimeSpec)
-            }; 
-            let req_time: Duration = t.into();
-            let remain_time = if flags == 1 {
-                let current_time = get_current_time_duration();
-                if req_time.le(&current_time){
-                    return Ok(0);
-                }
-                let sleep_time = req_time - current_time;
-                suspend_timeout(task, sleep_time).await
-            }else {
-                suspend_timeout(task, req_time).await
-            };
-            if remain_time.is_zero() {
-                Ok(0)
-            }else {
-                if rem_ptr != 0 {
-                    let remptr = rem_ptr as *mut TimeSpec;
-                    unsafe {
-                        remptr.write(remain_time.into());
-                    }
-                }
-                Err(SysError::EINTR)
-            }
-        }
-        _ => {
-            return Err(SysError::EINVAL);
-        }
+    if clock_id != CLOCK_REALTIME && clock_id != CLOCK_MONOTONIC {
+        return Err(SysError::EINVAL);
     }
-}
\ No newline at end of file
+    if flags & TIMER_ABSTIME != 0 {
+        let req_time = read_timespec(task, t_ptr)?;
+        let now = get_current_time_duration();
+        // the sleep runs on the monotonic clock, a realtime one ends at the same distance
+        let deadline = if clock_id == CLOCK_REALTIME {
+            now + req_time.saturating_sub(get_realtime_duration())
+        } else {
+            req_time
+        };
+        return if sleep_until(task, deadline).await { Err(SysError::EINTR) } else { Ok(0) };
+    }
+    let deadline = match take_sleep_restart(task) {
+        Some(deadline) => deadline,
+        None => get_current_time_duration() + read_timespec(task, t_ptr)?,
+    };
+    if sleep_until(task, deadline).await {
+        interrupt_relative_sleep(task, deadline, rem_ptr)
+    } else {
+        Ok(0)
+    }
+}
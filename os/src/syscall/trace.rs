@@ -0,0 +1,382 @@
+//! in-kernel syscall tracing
+//! a task with the trace flag set logs every syscall entry and exit to the kernel log ring
+//! and the console, a cheap strace for binaries that misbehave under Chronix
+
+use core::fmt::{self, Write};
+
+use hal::klog;
+
+use super::*;
+use crate::{fs::{AtFlags, OpenFlags}, mm::UserPtrRaw, sync::mutex::SpinNoIrqLock, task::task::TaskControlBlock, timer::{get_current_time_duration, get_current_time_sec}};
+use super::mm::{MmapFlags, MmapProt};
+
+/// the longest trace line, longer ones are cut
+const TRACE_LINE_LEN: usize = 256;
+/// the longest path printed in a trace line
+const TRACE_PATH_LEN: usize = 64;
+/// trace lines allowed per second over all tasks, the rest are counted and dropped
+const TRACE_RATE_LIMIT: usize = 256;
+
+/// a trace line formatted on the stack
+struct LineBuf {
+    buf: [u8; TRACE_LINE_LEN],
+    len: usize,
+}
+
+impl LineBuf {
+    fn new() -> Self {
+        Self { buf: [0; TRACE_LINE_LEN], len: 0 }
+    }
+
+    fn as_str(&self) -> &str {
+        // a cut may split a character, drop the broken tail
+        match core::str::from_utf8(&self.buf[..self.len]) {
+            Ok(s) => s,
+            Err(e) => core::str::from_utf8(&self.buf[..e.valid_up_to()]).unwrap(),
+        }
+    }
+}
+
+impl Write for LineBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(TRACE_LINE_LEN - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// lines logged in the current second and lines dropped since the last note
+struct RateLimit {
+    sec: usize,
+    lines: usize,
+    suppressed: usize,
+}
+
+static RATE_LIMIT: SpinNoIrqLock<RateLimit> = SpinNoIrqLock::new(RateLimit { sec: 0, lines: 0, suppressed: 0 });
+
+/// how a syscall argument is printed
+#[derive(Clone, Copy)]
+enum Arg {
+    Int,
+    Hex,
+    Oct,
+    Fd,
+    DirFd,
+    Path,
+    OpenFlags,
+    MmapProt,
+    MmapFlags,
+}
+
+/// the name of syscall `id`
+pub(crate) fn syscall_name(id: usize) -> &'static str {
+    match id {
+        SYSCALL_SETXATTR => "setxattr",
+        SYSCALL_LSETXATTR => "lsetxattr",
+        SYSCALL_FSETXATTR => "fsetxattr",
+        SYSCALL_GETXATTR => "getxattr",
+        SYSCALL_LGETXATTR => "lgetxattr",
+        SYSCALL_FGETXATTR => "fgetxattr",
+        SYSCALL_LISTXATTR => "listxattr",
+        SYSCALL_LLISTXATTR => "llistxattr",
+        SYSCALL_FLISTXATTR => "flistxattr",
+        SYSCALL_REMOVEXATTR => "removexattr",
+        SYSCALL_LREMOVEXATTR => "lremovexattr",
+        SYSCALL_FREMOVEXATTR => "fremovexattr",
+        SYSCALL_GETCWD => "getcwd",
+        SYSCALL_DUP => "dup",
+        SYSCALL_DUP3 => "dup3",
+        SYSCALL_FCNTL => "fcntl",
+        SYSCALL_IOCTL => "ioctl",
+        SYSCALL_MKDIR => "mkdirat",
+        SYSCALL_UNLINKAT => "unlinkat",
+        SYSCALL_SYMLINKAT => "symlinkat",
+        SYSCALL_LINKAT => "linkat",
+        SYSCALL_UMOUNT2 => "umount2",
+        SYSCALL_MOUNT => "mount",
+        SYSCALL_STATFS => "statfs",
+        SYSCALL_FTRUNCATE => "ftruncate",
+        SYSCALL_FACCESSAT => "faccessat",
+        SYSCALL_CHDIR => "chdir",
+        SYSCALL_FCHDIR => "fchdir",
+        SYSCALL_FCHMOD => "fchmod",
+        SYSCALL_FCHMODAT => "fchmodat",
+        SYSCALL_OPENAT => "openat",
+        SYSCALL_CLOSE => "close",
+        SYSCALL_VHANGUP => "vhangup",
+        SYSCALL_PIPE => "pipe2",
+        SYSCALL_GETDENTS => "getdents64",
+        SYSCALL_LSEEK => "lseek",
+        SYSCALL_READ => "read",
+        SYSCALL_WRITE => "write",
+        SYSCALL_READV => "readv",
+        SYSCALL_WRITEV => "writev",
+        SYSCALL_PREAD => "pread64",
+        SYSCALL_PWRITE => "pwrite64",
+        SYSCALL_SENDFILE => "sendfile",
+        SYSCALL_PSELECT6 => "pselect6",
+        SYSCALL_PPOLL => "ppoll",
+        SYSCALL_READLINKAT => "readlinkat",
+        SYSCALL_FSTATAT => "newfstatat",
+        SYSCALL_FSTAT => "fstat",
+        SYSCALL_SYNC => "sync",
+        SYSCALL_FSYNC => "fsync",
+        SYSCALL_UTIMENSAT => "utimensat",
+        SYSCALL_EXIT => "exit",
+        SYSCALL_EXIT_GROUP => "exit_group",
+        SYSCALL_SET_TID_ADDRESS => "set_tid_address",
+        SYSCALL_FUTEX => "futex",
+        SYSCALL_SET_ROBUST_LIST => "set_robust_list",
+        SYSCALL_GET_ROBUST_LIST => "get_robust_list",
+        SYSCALL_NANOSLEEP => "nanosleep",
+        SYSCALL_GETITIMER => "getitimer",
+        SYSCALL_SETITIMER => "setitimer",
+        SYSCALL_CLOCK_SETTIME => "clock_settime",
+        SYSCALL_CLOCK_GETTIME => "clock_gettime",
+        SYSCALL_CLOCK_GETRES => "clock_getres",
+        SYSCALL_CLOCK_NANOSLEEP => "clock_nanosleep",
+        SYSCALL_SYSLOG => "syslog",
+        SYSCALL_PTRACE => "ptrace",
+        SYSCALL_SCHED_SETSCHEDULER => "sched_setscheduler",
+        SYSCALL_SCHED_GETSCHEDULER => "sched_getscheduler",
+        SYSCALL_SCHED_GETPARAM => "sched_getparam",
+        SYSCALL_SCHED_SETAFFINITY => "sched_setaffinity",
+        SYSCALL_SCHED_GETAFFINITY => "sched_getaffinity",
+        SYSCALL_YIELD => "yield",
+        SYSCALL_KILL => "kill",
+        SYSCALL_TKILL => "tkill",
+        SYSCALL_TGKILL => "tgkill",
+        SYSCALL_RT_SIGSUSPEND => "rt_sigsuspend",
+        SYSCALL_RT_SIGACTION => "rt_sigaction",
+        SYSCALL_RT_SIGPROCMASK => "rt_sigprocmask",
+        SYSCALL_RT_SIGTIMEDWAIT => "rt_sigtimedwait",
+        SYSCALL_RT_SIGRETURN => "rt_sigreturn",
+        SYSCALL_SETPRIORITY => "setpriority",
+        SYSCALL_GETPRIORITY => "getpriority",
+        SYSCALL_REBOOT => "reboot",
+        SYSCALL_SETGID => "setgid",
+        SYSCALL_SETUID => "setuid",
+        SYSCALL_SETRESUID => "setresuid",
+        SYSCALL_GETRESUID => "getresuid",
+        SYSCALL_SETRESGID => "setresgid",
+        SYSCALL_GETRESGID => "getresgid",
+        SYSCALL_TIMES => "times",
+        SYSCALL_SETPGID => "setpgid",
+        SYSCALL_GETPGID => "getpgid",
+        SYSCALL_GETSID => "getsid",
+        SYSCALL_SETSID => "setsid",
+        SYSCALL_GETGROUPS => "getgroups",
+        SYSCALL_SETGROUPS => "setgroups",
+        SYSCALL_UNAME => "uname",
+        SYSCALL_SETHOSTNAME => "sethostname",
+        SYSCALL_SETDOMAINNAME => "setdomainname",
+        SYSCALL_GETRUSAGE => "getrusage",
+        SYSCALL_UMASK => "umask",
+        SYSCALL_PRCTL => "prctl",
+        SYSCALL_GETCPU => "getcpu",
+        SYSCALL_GETTIMEOFDAY => "gettimeofday",
+        SYSCALL_SETTIMEOFDAY => "settimeofday",
+        SYSCALL_ADJTIMEX => "adjtimex",
+        SYSCALL_GETPID => "getpid",
+        SYSCALL_GETPPID => "getppid",
+        SYSCALL_GETUID => "getuid",
+        SYSCALL_GETEUID => "geteuid",
+        SYSCALL_GETGID => "getgid",
+        SYSCALL_GETEGID => "getegid",
+        SYSCALL_GETTID => "gettid",
+        SYSCALL_SYSINFO => "sysinfo",
+        SYSCALL_SHMGET => "shmget",
+        SYSCALL_SHMCTL => "shmctl",
+        SYSCALL_SHMAT => "shmat",
+        SYSCALL_SHMDT => "shmdt",
+        SYSCALL_SOCKET => "socket",
+        SYSCALL_SOCKETPAIR => "socketpair",
+        SYSCALL_BIND => "bind",
+        SYSCALL_LISTEN => "listen",
+        SYSCALL_ACCEPT => "accept",
+        SYSCALL_ACCEPT4 => "accept4",
+        SYSCALL_CONNECT => "connect",
+        SYSCALL_GETSOCKNAME => "getsockname",
+        SYSCALL_GETPEERNAME => "getpeername",
+        SYSCALL_SENDTO => "sendto",
+        SYSCALL_RECVFROM => "recvfrom",
+        SYSCALL_SETSOCKOPT => "setsockopt",
+        SYSCALL_GETSOCKOPT => "getsockopt",
+        SYSCALL_SHUTDOWN => "shutdown",
+        SYSCALL_SENDMSG => "sendmsg",
+        SYSCALL_RECVMSG => "recvmsg",
+        SYSCALL_BRK => "brk",
+        SYSCALL_MUNMAP => "munmap",
+        SYSCALL_MREMAP => "mremap",
+        SYSCALL_CLONE => "clone",
+        SYSCALL_EXEC => "execve",
+        SYSCALL_MMAP => "mmap",
+        SYSCALL_MPROTECE => "mprotect",
+        SYSCALL_MSYNC => "msync",
+        SYSCALL_MLOCK => "mlock",
+        SYSCALL_MADSIVE => "madvise",
+        SYSCALL_GET_MEMPOLICY => "get_mempolicy",
+        SYSCALL_WAITPID => "wait4",
+        SYSCALL_PRLIMIT64 => "prlimit64",
+        SYSCALL_PROCESS_VM_READV => "process_vm_readv",
+        SYSCALL_PROCESS_VM_WRITEV => "process_vm_writev",
+        SYSCALL_RENAMEAT2 => "renameat2",
+        SYSCALL_GETRANDOM => "getrandom",
+        SYSCALL_MEMFD_CREATE => "memfd_create",
+        SYSCALL_MEMBARRIER => "membarrier",
+        SYSCALL_STATX => "statx",
+        SYSCALL_CLONE3 => "clone3",
+        SYSCALL_IO_SUBMIT_BATCH => "io_submit_batch",
+        SYSCALL_KDEBUG => "kdebug",
+        _ => "unknown",
+    }
+}
+
+/// the arguments of the common syscalls, None prints all six in hex
+fn syscall_args(id: usize) -> Option<&'static [Arg]> {
+    use Arg::*;
+    Some(match id {
+        SYSCALL_OPENAT => &[DirFd, Path, OpenFlags, Oct],
+        SYSCALL_CLOSE | SYSCALL_FSYNC | SYSCALL_DUP | SYSCALL_FCHDIR => &[Fd],
+        SYSCALL_DUP3 => &[Fd, Fd, Hex],
+        SYSCALL_READ | SYSCALL_WRITE => &[Fd, Hex, Int],
+        SYSCALL_PREAD | SYSCALL_PWRITE => &[Fd, Hex, Int, Int],
+        SYSCALL_LSEEK => &[Fd, Int, Int],
+        SYSCALL_FSTAT => &[Fd, Hex],
+        SYSCALL_FSTATAT => &[DirFd, Path, Hex, Hex],
+        SYSCALL_STATX => &[DirFd, Path, Hex, Hex, Hex],
+        SYSCALL_FACCESSAT => &[DirFd, Path, Oct, Hex],
+        SYSCALL_MKDIR => &[DirFd, Path, Oct],
+        SYSCALL_UNLINKAT => &[DirFd, Path, Hex],
+        SYSCALL_READLINKAT => &[DirFd, Path, Hex, Int],
+        SYSCALL_FCHMODAT => &[DirFd, Path, Oct, Hex],
+        SYSCALL_CHDIR => &[Path],
+        SYSCALL_EXEC => &[Path, Hex, Hex],
+        SYSCALL_MMAP => &[Hex, Int, MmapProt, MmapFlags, Fd, Hex],
+        SYSCALL_MPROTECE => &[Hex, Int, MmapProt],
+        SYSCALL_MUNMAP => &[Hex, Int],
+        SYSCALL_BRK => &[Hex],
+        SYSCALL_EXIT | SYSCALL_EXIT_GROUP => &[Int],
+        SYSCALL_KILL => &[Int, Int],
+        SYSCALL_WAITPID => &[Int, Hex, Hex],
+        SYSCALL_GETPID | SYSCALL_GETPPID | SYSCALL_GETTID | SYSCALL_YIELD => &[],
+        _ => return None,
+    })
+}
+
+fn write_arg(line: &mut LineBuf, task: &TaskControlBlock, arg: Arg, val: usize) -> fmt::Result {
+    match arg {
+        Arg::Int => write!(line, "{}", val as isize),
+        Arg::Hex => write!(line, "{:#x}", val),
+        Arg::Oct => write!(line, "{:#o}", val),
+        Arg::Fd => write!(line, "{}", val as i32),
+        Arg::DirFd if val as i32 == AtFlags::AT_FDCWD.bits() => line.write_str("AT_FDCWD"),
+        Arg::DirFd => write!(line, "{}", val as i32),
+        Arg::Path if val == 0 => line.write_str("NULL"),
+        Arg::Path => {
+            let mut vm = task.get_vm_space().lock();
+            match UserPtrRaw::new(val as *const u8).cstr_slice(&mut vm) {
+                Some(slice) => {
+                    let bytes = slice.to_ref();
+                    let path = core::str::from_utf8(&bytes[..bytes.len().min(TRACE_PATH_LEN)]).unwrap_or("?");
+                    let cut = if bytes.len() > TRACE_PATH_LEN { "..." } else { "" };
+                    write!(line, "\"{}\"{}", path, cut)
+                }
+                None => write!(line, "{:#x}", val),
+            }
+        }
+        Arg::OpenFlags => {
+            let flags = OpenFlags::from_bits_truncate(val as i32);
+            if flags.is_empty() {
+                line.write_str("O_RDONLY")
+            } else {
+                write!(line, "{:?}", flags)
+            }
+        }
+        Arg::MmapProt => match MmapProt::from_bits(val as i32) {
+            Some(prot) if prot.is_empty() => line.write_str("PROT_NONE"),
+            Some(prot) => write!(line, "{:?}", prot),
+            None => write!(line, "{:#x}", val),
+        },
+        Arg::MmapFlags => write!(line, "{:?}", MmapFlags::from_bits_truncate(val as i32)),
+    }
+}
+
+/// log a finished line, unless over the rate limit
+fn emit(line: &LineBuf) {
+    let suppressed = {
+        let mut limit = RATE_LIMIT.lock();
+        let now = get_current_time_sec();
+        if limit.sec != now {
+            limit.sec = now;
+            limit.lines = 0;
+        }
+        if limit.lines >= TRACE_RATE_LIMIT {
+            limit.suppressed += 1;
+            return;
+        }
+        limit.lines += 1;
+        core::mem::take(&mut limit.suppressed)
+    };
+    if suppressed != 0 {
+        klog!("[strace] {} lines suppressed", suppressed);
+    }
+    // one record on the console and in the kernel log ring
+    klog!("{}", line.as_str().trim_end());
+}
+
+/// the prefix of every line: timestamp, tid and thread name
+fn start_line(task: &TaskControlBlock) -> LineBuf {
+    let mut line = LineBuf::new();
+    let now = get_current_time_duration();
+    let _ = write!(line, "[{:>5}.{:06}] [strace] {}", now.as_secs(), now.subsec_micros(), task.tid());
+    task.with_comm(|comm| {
+        let _ = write!(line, " ({}) ", comm);
+    });
+    line
+}
+
+/// log the entry of syscall `id` of the current task
+pub fn trace_syscall_enter(task: &TaskControlBlock, id: usize, args: &[usize; 6]) {
+    let mut line = start_line(task);
+    let _ = write!(line, "{}(", syscall_name(id));
+    match syscall_args(id) {
+        Some(kinds) => {
+            for (i, (&kind, &val)) in kinds.iter().zip(args.iter()).enumerate() {
+                if i != 0 {
+                    let _ = line.write_str(", ");
+                }
+                let _ = write_arg(&mut line, task, kind, val);
+            }
+        }
+        None => {
+            let _ = write!(line, "{:#x}, {:#x}, {:#x}, {:#x}, {:#x}, {:#x}", args[0], args[1], args[2], args[3], args[4], args[5]);
+        }
+    }
+    if id == SYSCALL_EXIT || id == SYSCALL_EXIT_GROUP || id == SYSCALL_EXEC {
+        // exit never returns, and a successful execve has no return line worth waiting for
+        let _ = writeln!(line, ") ...");
+    } else {
+        let _ = writeln!(line, ")");
+    }
+    emit(&line);
+}
+
+/// log the return value of syscall `id` of the current task
+pub fn trace_syscall_exit(task: &TaskControlBlock, id: usize, ret: isize) {
+    let mut line = start_line(task);
+    let _ = write!(line, "{} = ", syscall_name(id));
+    let err = match ret {
+        ret if ret < 0 => i32::try_from(ret.unsigned_abs()).ok().and_then(SysError::from_repr),
+        _ => None,
+    };
+    if let Some(err) = err {
+        let _ = writeln!(line, "-1 {:?}", err);
+    } else if matches!(id, SYSCALL_MMAP | SYSCALL_MREMAP | SYSCALL_BRK) {
+        let _ = writeln!(line, "{:#x}", ret);
+    } else {
+        let _ = writeln!(line, "{}", ret);
+    }
+    emit(&line);
+}
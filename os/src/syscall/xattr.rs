@@ -0,0 +1,190 @@
+//! extended attributes
+//! only the user namespace is supported, the others fail with EOPNOTSUPP.
+//! Ext4 keeps them on disk, the file systems in memory keep them in the inode
+
+use alloc::{string::{String, ToString}, sync::Arc, vec::Vec};
+
+use crate::{fs::{vfs::{inode::{InodeMode, XattrFlags, XATTR_LIST_MAX, XATTR_NAME_MAX, XATTR_SIZE_MAX}, Inode}, AtFlags}, mm::{UserPtrRaw, UserSliceRaw}, task::{cred::{MAY_READ, MAY_WRITE}, current_task, task::TaskControlBlock}};
+
+use super::{at_helper, SysError, SysResult};
+
+/// the namespace user programs may use freely
+const XATTR_USER_PREFIX: &str = "user.";
+
+/// the inode at `pathname`, the symlink itself unless `follow`
+fn path_inode(task: &Arc<TaskControlBlock>, pathname: *const u8, follow: bool) -> Result<Arc<dyn Inode>, SysError> {
+    let flags = if follow { AtFlags::empty() } else { AtFlags::AT_SYMLINK_NOFOLLOW };
+    let dentry = at_helper(task.clone(), AtFlags::AT_FDCWD.bits() as isize, pathname, flags)?;
+    if dentry.is_negative() {
+        return Err(SysError::ENOENT);
+    }
+    dentry.inode().ok_or(SysError::ENOENT)
+}
+
+/// the inode of the open file `fd`
+fn fd_inode(task: &Arc<TaskControlBlock>, fd: usize) -> Result<Arc<dyn Inode>, SysError> {
+    let file = task.with_fd_table(|t| t.get_file(fd))?;
+    file.inode().ok_or(SysError::EBADF)
+}
+
+/// read the name of an attribute, ERANGE if it is empty or too long
+fn user_xattr_name(task: &Arc<TaskControlBlock>, name: *const u8) -> Result<String, SysError> {
+    let slice = UserPtrRaw::new(name)
+        .cstr_slice_max(&mut task.get_vm_space().lock(), XATTR_NAME_MAX + 1)
+        .ok_or(SysError::EFAULT)?;
+    let name = slice.to_str().map_err(|_| SysError::EINVAL)?;
+    if name.is_empty() || name.len() > XATTR_NAME_MAX {
+        return Err(SysError::ERANGE);
+    }
+    if !name.starts_with(XATTR_USER_PREFIX) {
+        return Err(SysError::EOPNOTSUPP);
+    }
+    Ok(name.to_string())
+}
+
+/// copy `data` out to the user buffer of `size` bytes, a zero size asks for the length only
+fn copy_out(task: &Arc<TaskControlBlock>, data: &[u8], buf: usize, size: usize) -> SysResult {
+    if size == 0 {
+        return Ok(data.len() as isize);
+    }
+    if size < data.len() {
+        return Err(SysError::ERANGE);
+    }
+    let user_buf = UserSliceRaw::new(buf as *mut u8, data.len())
+        .ensure_write(&mut task.get_vm_space().lock())
+        .ok_or(SysError::EFAULT)?;
+    user_buf.to_mut().copy_from_slice(data);
+    Ok(data.len() as isize)
+}
+
+/// user attributes are for regular files and directories only
+fn user_xattr_allowed(inode: &Arc<dyn Inode>) -> bool {
+    matches!(inode.inode_inner().mode().get_type(), InodeMode::FILE | InodeMode::DIR)
+}
+
+fn do_setxattr(task: &Arc<TaskControlBlock>, inode: Arc<dyn Inode>, name: *const u8, value: usize, size: usize, flags: i32) -> SysResult {
+    let flags = XattrFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+    let name = user_xattr_name(task, name)?;
+    if size > XATTR_SIZE_MAX {
+        return Err(SysError::E2BIG);
+    }
+    if !user_xattr_allowed(&inode) {
+        return Err(SysError::EPERM);
+    }
+    task.check_access(inode.inode_inner(), MAY_WRITE)?;
+    let value = if size == 0 {
+        Vec::new()
+    } else {
+        let buf = UserSliceRaw::new(value as *const u8, size)
+            .ensure_read(&mut task.get_vm_space().lock())
+            .ok_or(SysError::EFAULT)?;
+        buf.to_ref().to_vec()
+    };
+    log::info!("[sys_setxattr]: {} of {} bytes, flags {:?}", name, size, flags);
+    inode.set_xattr(&name, &value, flags)?;
+    Ok(0)
+}
+
+fn do_getxattr(task: &Arc<TaskControlBlock>, inode: Arc<dyn Inode>, name: *const u8, value: usize, size: usize) -> SysResult {
+    let name = user_xattr_name(task, name)?;
+    if !user_xattr_allowed(&inode) {
+        return Err(SysError::ENODATA);
+    }
+    task.check_access(inode.inode_inner(), MAY_READ)?;
+    let data = inode.get_xattr(&name)?;
+    copy_out(task, &data, value, size.min(XATTR_SIZE_MAX))
+}
+
+fn do_listxattr(task: &Arc<TaskControlBlock>, inode: Arc<dyn Inode>, list: usize, size: usize) -> SysResult {
+    let mut names = Vec::new();
+    for name in inode.list_xattr()? {
+        names.extend_from_slice(name.as_bytes());
+        names.push(0);
+    }
+    if names.len() > XATTR_LIST_MAX {
+        return Err(SysError::E2BIG);
+    }
+    copy_out(task, &names, list, size.min(XATTR_LIST_MAX))
+}
+
+fn do_removexattr(task: &Arc<TaskControlBlock>, inode: Arc<dyn Inode>, name: *const u8) -> SysResult {
+    let name = user_xattr_name(task, name)?;
+    if !user_xattr_allowed(&inode) {
+        return Err(SysError::EPERM);
+    }
+    task.check_access(inode.inode_inner(), MAY_WRITE)?;
+    inode.remove_xattr(&name)?;
+    Ok(0)
+}
+
+/// syscall: setxattr, following symlinks
+pub fn sys_setxattr(pathname: *const u8, name: *const u8, value: usize, size: usize, flags: i32) -> SysResult {
+    let task = current_task().unwrap().clone();
+    do_setxattr(&task, path_inode(&task, pathname, true)?, name, value, size, flags)
+}
+
+/// syscall: lsetxattr, on the symlink itself
+pub fn sys_lsetxattr(pathname: *const u8, name: *const u8, value: usize, size: usize, flags: i32) -> SysResult {
+    let task = current_task().unwrap().clone();
+    do_setxattr(&task, path_inode(&task, pathname, false)?, name, value, size, flags)
+}
+
+/// syscall: fsetxattr
+pub fn sys_fsetxattr(fd: usize, name: *const u8, value: usize, size: usize, flags: i32) -> SysResult {
+    let task = current_task().unwrap().clone();
+    do_setxattr(&task, fd_inode(&task, fd)?, name, value, size, flags)
+}
+
+/// syscall: getxattr, following symlinks
+pub fn sys_getxattr(pathname: *const u8, name: *const u8, value: usize, size: usize) -> SysResult {
+    let task = current_task().unwrap().clone();
+    do_getxattr(&task, path_inode(&task, pathname, true)?, name, value, size)
+}
+
+/// syscall: lgetxattr, on the symlink itself
+pub fn sys_lgetxattr(pathname: *const u8, name: *const u8, value: usize, size: usize) -> SysResult {
+    let task = current_task().unwrap().clone();
+    do_getxattr(&task, path_inode(&task, pathname, false)?, name, value, size)
+}
+
+/// syscall: fgetxattr
+pub fn sys_fgetxattr(fd: usize, name: *const u8, value: usize, size: usize) -> SysResult {
+    let task = current_task().unwrap().clone();
+    do_getxattr(&task, fd_inode(&task, fd)?, name, value, size)
+}
+
+/// syscall: listxattr, following symlinks
+pub fn sys_listxattr(pathname: *const u8, list: usize, size: usize) -> SysResult {
+    let task = current_task().unwrap().clone();
+    do_listxattr(&task, path_inode(&task, pathname, true)?, list, size)
+}
+
+/// syscall: llistxattr, on the symlink itself
+pub fn sys_llistxattr(pathname: *const u8, list: usize, size: usize) -> SysResult {
+    let task = current_task().unwrap().clone();
+    do_listxattr(&task, path_inode(&task, pathname, false)?, list, size)
+}
+
+/// syscall: flistxattr
+pub fn sys_flistxattr(fd: usize, list: usize, size: usize) -> SysResult {
+    let task = current_task().unwrap().clone();
+    do_listxattr(&task, fd_inode(&task, fd)?, list, size)
+}
+
+/// syscall: removexattr, following symlinks
+pub fn sys_removexattr(pathname: *const u8, name: *const u8) -> SysResult {
+    let task = current_task().unwrap().clone();
+    do_removexattr(&task, path_inode(&task, pathname, true)?, name)
+}
+
+/// syscall: lremovexattr, on the symlink itself
+pub fn sys_lremovexattr(pathname: *const u8, name: *const u8) -> SysResult {
+    let task = current_task().unwrap().clone();
+    do_removexattr(&task, path_inode(&task, pathname, false)?, name)
+}
+
+/// syscall: fremovexattr
+pub fn sys_fremovexattr(fd: usize, name: *const u8) -> SysResult {
+    let task = current_task().unwrap().clone();
+    do_removexattr(&task, fd_inode(&task, fd)?, name)
+}
@@ -0,0 +1,163 @@
+//! sysctl: the kernel tunables by name, shown as files under /proc/sys
+//!
+//! each subsystem owns its parameters: it keeps the live value next to the code using it
+//! and defines the valid range, the registry here only gives them a path and permission bits.
+//! Reads show the live value with a newline, writes are checked by the owner and fail with
+//! EINVAL when it rejects them
+
+use alloc::{format, string::String};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{
+    fs::{
+        page::cache::PAGE_CACHE_LIMIT_MB,
+        pipefs::PIPE_MAX_SIZE,
+        tmpfs::TMPFS_SIZE_MB,
+        vfs::file::{file_nr_read, FILE_MAX},
+        writeback::{DIRTY_BACKGROUND_PAGES, DIRTY_EXPIRE_MS, DIRTY_WRITEBACK_MS},
+        open_inodes_read, DOMAINNAME, HOSTNAME,
+    },
+    mm::vm::aslr::RANDOMIZE_VA_SPACE,
+    net::SOMAXCONN,
+    sync::mutex::SpinNoIrqLock,
+    syscall::SysError,
+    task::schedule::SCHED_TIMESLICE_MS,
+    utils::klog,
+};
+
+/// an integer tunable within `min..=max`
+pub struct IntParam {
+    value: AtomicUsize,
+    min: usize,
+    max: usize,
+}
+
+impl IntParam {
+    pub const fn new(value: usize, min: usize, max: usize) -> Self {
+        Self { value: AtomicUsize::new(value), min, max }
+    }
+
+    /// the live value
+    pub fn get(&self) -> usize {
+        self.value.load(Ordering::Relaxed)
+    }
+
+    /// set the value, EINVAL when out of range
+    pub fn set(&self, value: usize) -> Result<(), SysError> {
+        if value < self.min || value > self.max {
+            return Err(SysError::EINVAL);
+        }
+        self.value.store(value, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// a string tunable of at most `max_len` bytes
+pub struct StrParam {
+    value: SpinNoIrqLock<Option<String>>,
+    default: &'static str,
+    max_len: usize,
+}
+
+impl StrParam {
+    pub const fn new(default: &'static str, max_len: usize) -> Self {
+        Self { value: SpinNoIrqLock::new(None), default, max_len }
+    }
+
+    /// the live value
+    pub fn get(&self) -> String {
+        self.value.lock().clone().unwrap_or_else(|| self.default.into())
+    }
+
+    /// run `f` on the live value in place, where no copy can be allocated
+    pub fn with<R>(&self, f: impl FnOnce(&str) -> R) -> R {
+        f(self.value.lock().as_deref().unwrap_or(self.default))
+    }
+
+    /// set the value, EINVAL when too long or not utf-8
+    pub fn set(&self, value: &[u8]) -> Result<(), SysError> {
+        if value.len() > self.max_len {
+            return Err(SysError::EINVAL);
+        }
+        let value = core::str::from_utf8(value).map_err(|_| SysError::EINVAL)?;
+        *self.value.lock() = Some(value.into());
+        Ok(())
+    }
+}
+
+/// what a sysctl file shows
+pub enum Param {
+    Int(&'static IntParam),
+    Str(&'static StrParam),
+    /// a value kept in a form of its own, formatted and parsed by its owner
+    Custom {
+        read: fn() -> String,
+        write: fn(&str) -> Result<(), SysError>,
+    },
+    /// a value which is only shown, a write is EACCES
+    ReadOnly(fn() -> String),
+}
+
+/// a named tunable
+pub struct Sysctl {
+    /// the path under /proc/sys
+    pub path: &'static str,
+    /// the permission bits of its file
+    pub mode: u32,
+    pub param: Param,
+}
+
+impl Sysctl {
+    /// the value as the file shows it
+    pub fn read(&self) -> String {
+        match &self.param {
+            Param::Int(param) => format!("{}\n", param.get()),
+            Param::Str(param) => format!("{}\n", param.get()),
+            Param::Custom { read, .. } => read(),
+            Param::ReadOnly(read) => read(),
+        }
+    }
+
+    /// set the value from what was written to the file, a trailing newline is dropped
+    pub fn write(&self, text: &[u8]) -> Result<(), SysError> {
+        let text = text.strip_suffix(b"\n").unwrap_or(text);
+        match &self.param {
+            Param::Int(param) => param.set(parse_int(text)?),
+            Param::Str(param) => param.set(text),
+            Param::Custom { write, .. } => write(core::str::from_utf8(text).map_err(|_| SysError::EINVAL)?),
+            Param::ReadOnly(_) => Err(SysError::EACCES),
+        }
+    }
+}
+
+/// a decimal number, with blanks around it
+pub fn parse_int(text: &[u8]) -> Result<usize, SysError> {
+    core::str::from_utf8(text)
+        .map_err(|_| SysError::EINVAL)?
+        .trim()
+        .parse()
+        .map_err(|_| SysError::EINVAL)
+}
+
+/// every tunable, only root may write them
+pub static SYSCTLS: [Sysctl; 15] = [
+    Sysctl { path: "fs/file-max", mode: 0o644, param: Param::Int(&FILE_MAX) },
+    Sysctl { path: "fs/file-nr", mode: 0o444, param: Param::ReadOnly(file_nr_read) },
+    Sysctl { path: "fs/open-inodes", mode: 0o444, param: Param::ReadOnly(open_inodes_read) },
+    Sysctl { path: "fs/pipe-max-size", mode: 0o644, param: Param::Int(&PIPE_MAX_SIZE) },
+    Sysctl { path: "fs/tmpfs-size-mb", mode: 0o644, param: Param::Int(&TMPFS_SIZE_MB) },
+    Sysctl { path: "kernel/domainname", mode: 0o644, param: Param::Str(&DOMAINNAME) },
+    Sysctl { path: "kernel/hostname", mode: 0o644, param: Param::Str(&HOSTNAME) },
+    Sysctl {
+        path: "kernel/printk",
+        mode: 0o644,
+        param: Param::Custom { read: klog::printk_read, write: klog::printk_write },
+    },
+    Sysctl { path: "kernel/randomize_va_space", mode: 0o644, param: Param::Int(&RANDOMIZE_VA_SPACE) },
+    Sysctl { path: "kernel/sched_timeslice_ms", mode: 0o644, param: Param::Int(&SCHED_TIMESLICE_MS) },
+    Sysctl { path: "net/core/somaxconn", mode: 0o644, param: Param::Int(&SOMAXCONN) },
+    Sysctl { path: "vm/dirty-background-pages", mode: 0o644, param: Param::Int(&DIRTY_BACKGROUND_PAGES) },
+    Sysctl { path: "vm/dirty-expire-ms", mode: 0o644, param: Param::Int(&DIRTY_EXPIRE_MS) },
+    Sysctl { path: "vm/dirty-writeback-ms", mode: 0o644, param: Param::Int(&DIRTY_WRITEBACK_MS) },
+    Sysctl { path: "vm/page-cache-limit-mb", mode: 0o644, param: Param::Int(&PAGE_CACHE_LIMIT_MB) },
+];
@@ -0,0 +1,243 @@
+//! process credentials
+//! real, effective and saved user and group ids, and the supplementary groups
+
+use alloc::vec::Vec;
+
+use crate::{fs::vfs::{fstype::MountFlags, inode::InodeMode, InodeInner}, syscall::SysError};
+
+use super::task::TaskControlBlock;
+
+/// user id
+pub type Uid = u32;
+/// group id
+pub type Gid = u32;
+
+/// -1 in setres*id, leave the id unchanged
+pub const ID_UNCHANGED: u32 = u32::MAX;
+/// the maximum number of supplementary groups, NGROUPS_MAX in linux
+pub const NGROUPS_MAX: usize = 65536;
+
+/// test for read permission
+pub const MAY_READ: u32 = 4;
+/// test for write permission
+pub const MAY_WRITE: u32 = 2;
+/// test for execute or search permission
+pub const MAY_EXEC: u32 = 1;
+
+#[derive(Debug, Clone)]
+/// credentials of a process, shared by its threads
+pub struct Credentials {
+    /// real user id
+    pub ruid: Uid,
+    /// effective user id, used for the permission checks
+    pub euid: Uid,
+    /// saved set-user-id
+    pub suid: Uid,
+    /// real group id
+    pub rgid: Gid,
+    /// effective group id
+    pub egid: Gid,
+    /// saved set-group-id
+    pub sgid: Gid,
+    /// supplementary groups
+    pub groups: Vec<Gid>,
+}
+
+impl Credentials {
+    /// credentials of initproc
+    pub fn root() -> Self {
+        Self { ruid: 0, euid: 0, suid: 0, rgid: 0, egid: 0, sgid: 0, groups: Vec::new() }
+    }
+
+    /// only a process with euid 0 may raise its privileges
+    pub fn is_privileged(&self) -> bool {
+        self.euid == 0
+    }
+
+    /// whether the effective gid or a supplementary group is `gid`
+    pub fn in_group(&self, gid: Gid) -> bool {
+        self.egid == gid || self.groups.contains(&gid)
+    }
+
+    /// whether the permission bits of `inode` grant every access in `mask`,
+    /// checked as the real ids for access(2) and as the effective ids otherwise
+    pub fn permits(&self, inode: &InodeInner, mask: u32, use_real: bool) -> bool {
+        let (uid, gid) = if use_real { (self.ruid, self.rgid) } else { (self.euid, self.egid) };
+        let mode = inode.mode();
+        if uid == 0 {
+            // root passes everything but executing a file nobody can execute
+            return mask & MAY_EXEC == 0
+                || mode.get_type() == InodeMode::DIR
+                || mode.intersects(InodeMode::OWNER_EXEC | InodeMode::GROUP_EXEC | InodeMode::OTHER_EXEC);
+        }
+        let bits = mode.bits();
+        let perm = if uid == inode.uid() {
+            bits >> 6
+        } else if gid == inode.gid() || self.groups.contains(&inode.gid()) {
+            bits >> 3
+        } else {
+            bits
+        } & 0o7;
+        perm & mask == mask
+    }
+
+    /// whether the owner of `inode` or root may change its attributes
+    pub fn owns(&self, inode: &InodeInner) -> bool {
+        self.is_privileged() || self.euid == inode.uid()
+    }
+
+    /// whether the sticky bit of `dir` lets these credentials unlink or rename `victim` in it:
+    /// in a sticky directory only the owner of the entry, of the directory or root may
+    pub fn sticky_allows(&self, dir: &InodeInner, victim: &InodeInner) -> bool {
+        !dir.mode().contains(InodeMode::STICKY)
+            || self.is_privileged()
+            || self.euid == victim.uid()
+            || self.euid == dir.uid()
+    }
+
+    /// the group and permission bits of a new inode of type `ty` made in `dir` with `perm`:
+    /// a set-group-id directory hands down its group, and the bit itself to subdirectories;
+    /// a file keeps set-group-id only if its creator is in the group
+    pub fn new_inode_owner(&self, dir: &InodeInner, ty: InodeMode, mut perm: InodeMode) -> (Gid, InodeMode) {
+        if !dir.mode().contains(InodeMode::SET_GID) {
+            return (self.egid, perm);
+        }
+        let gid = dir.gid();
+        if ty == InodeMode::DIR {
+            perm |= InodeMode::SET_GID;
+        } else if perm.contains(InodeMode::SET_GID | InodeMode::GROUP_EXEC) && !self.in_group(gid) && !self.is_privileged() {
+            perm.remove(InodeMode::SET_GID);
+        }
+        (gid, perm)
+    }
+
+    /// whether a process with these credentials may send a signal to one with `target`:
+    /// the real or effective uid of the sender must match the real or saved uid of the target
+    pub fn can_signal(&self, target: &Credentials) -> bool {
+        self.is_privileged()
+            || [self.ruid, self.euid].iter().any(|&uid| uid == target.ruid || uid == target.suid)
+    }
+
+    /// whether a process with these credentials may change the nice value of one with `target`:
+    /// the effective uid of the caller must match the real or effective uid of the target
+    pub fn can_renice(&self, target: &Credentials) -> bool {
+        self.is_privileged() || self.euid == target.ruid || self.euid == target.euid
+    }
+
+    /// whether a process with these credentials may access the memory of one with `target`:
+    /// the real uid and gid of the caller must match all of the uids and gids of the target
+    pub fn can_access_mm(&self, target: &Credentials) -> bool {
+        self.is_privileged()
+            || ([target.ruid, target.euid, target.suid].iter().all(|&uid| uid == self.ruid)
+                && [target.rgid, target.egid, target.sgid].iter().all(|&gid| gid == self.rgid))
+    }
+
+    /// setuid(2): root sets all three ids, others may only switch euid to their real or saved uid
+    pub fn setuid(&mut self, uid: Uid) -> Result<(), SysError> {
+        if self.is_privileged() {
+            self.ruid = uid;
+            self.suid = uid;
+        } else if uid != self.ruid && uid != self.suid {
+            return Err(SysError::EPERM);
+        }
+        self.euid = uid;
+        Ok(())
+    }
+
+    /// setgid(2), same rules as setuid on the group ids
+    pub fn setgid(&mut self, gid: Gid) -> Result<(), SysError> {
+        if self.is_privileged() {
+            self.rgid = gid;
+            self.sgid = gid;
+        } else if gid != self.rgid && gid != self.sgid {
+            return Err(SysError::EPERM);
+        }
+        self.egid = gid;
+        Ok(())
+    }
+
+    /// setresuid(2): unprivileged processes may set each id to one of their current r/e/s uids
+    pub fn setresuid(&mut self, ruid: Uid, euid: Uid, suid: Uid) -> Result<(), SysError> {
+        let current = [self.ruid, self.euid, self.suid];
+        if !self.is_privileged()
+            && [ruid, euid, suid].iter().any(|id| *id != ID_UNCHANGED && !current.contains(id)) {
+            return Err(SysError::EPERM);
+        }
+        if ruid != ID_UNCHANGED { self.ruid = ruid; }
+        if euid != ID_UNCHANGED { self.euid = euid; }
+        if suid != ID_UNCHANGED { self.suid = suid; }
+        Ok(())
+    }
+
+    /// setresgid(2), same rules as setresuid on the group ids
+    pub fn setresgid(&mut self, rgid: Gid, egid: Gid, sgid: Gid) -> Result<(), SysError> {
+        let current = [self.rgid, self.egid, self.sgid];
+        if !self.is_privileged()
+            && [rgid, egid, sgid].iter().any(|id| *id != ID_UNCHANGED && !current.contains(id)) {
+            return Err(SysError::EPERM);
+        }
+        if rgid != ID_UNCHANGED { self.rgid = rgid; }
+        if egid != ID_UNCHANGED { self.egid = egid; }
+        if sgid != ID_UNCHANGED { self.sgid = sgid; }
+        Ok(())
+    }
+
+    /// execve of `inode`: the set-user-id and set-group-id bits change the effective ids,
+    /// unless on a nosuid mount, then the saved ids follow the effective ones
+    pub fn exec(&mut self, inode: &InodeInner, no_new_privs: bool) {
+        let mode = inode.mode();
+        if !no_new_privs && !inode.mount_flags().contains(MountFlags::MS_NOSUID) {
+            if mode.contains(InodeMode::SET_UID) {
+                self.euid = inode.uid();
+            }
+            // without group execute the bit marks mandatory locking, not set-group-id
+            if mode.contains(InodeMode::SET_GID | InodeMode::GROUP_EXEC) {
+                self.egid = inode.gid();
+            }
+        }
+        self.suid = self.euid;
+        self.sgid = self.egid;
+    }
+}
+
+impl TaskControlBlock {
+    /// check the permission bits of `inode` against the effective ids, EACCES if denied.
+    /// EROFS first when writing a file, directory or link of a read-only mount
+    pub fn check_access(&self, inode: &InodeInner, mask: u32) -> Result<(), SysError> {
+        if mask & MAY_WRITE != 0 && matches!(inode.mode().get_type(), InodeMode::FILE | InodeMode::DIR | InodeMode::LINK) {
+            inode.check_writable()?;
+        }
+        if self.with_cred(|cred| cred.permits(inode, mask, false)) {
+            Ok(())
+        } else {
+            Err(SysError::EACCES)
+        }
+    }
+    /// whether self may unlink `victim` from `dir` or rename it away: EACCES without
+    /// write and search permission on `dir`, EPERM if the sticky bit of `dir` forbids it
+    pub fn check_delete(&self, dir: &InodeInner, victim: &InodeInner) -> Result<(), SysError> {
+        self.check_access(dir, MAY_WRITE | MAY_EXEC)?;
+        if self.with_cred(|cred| cred.sticky_allows(dir, victim)) {
+            Ok(())
+        } else {
+            Err(SysError::EPERM)
+        }
+    }
+    /// give `inode`, just made in `dir` with `mode`, its owner and permission bits:
+    /// the effective uid, the group by [`Credentials::new_inode_owner`] and `mode` less the umask
+    pub fn init_new_owner(&self, dir: &InodeInner, inode: &InodeInner, mode: u32) {
+        let perm = InodeMode::from_bits_truncate(mode & 0o7777 & !self.umask());
+        let (euid, (gid, perm)) = self.with_cred(|c| (c.euid, c.new_inode_owner(dir, inode.mode().get_type(), perm)));
+        inode.init_owner(euid, gid, perm);
+    }
+    /// whether self may send a signal to `target`
+    pub fn can_signal(&self, target: &TaskControlBlock) -> bool {
+        let cred = self.with_cred(|cred| cred.clone());
+        target.with_cred(|target| cred.can_signal(target))
+    }
+    /// whether self may read or write the memory of `target`
+    pub fn can_access_mm(&self, target: &TaskControlBlock) -> bool {
+        let cred = self.with_cred(|cred| cred.clone());
+        target.with_cred(|target| cred.can_access_mm(target))
+    }
+}
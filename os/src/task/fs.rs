@@ -2,9 +2,8 @@
 
 use alloc::{sync::Arc, vec::Vec};
 use fatfs::info;
-use log::warn;
 
-use crate::{fs::{devfs::tty::TTY, vfs::{Dentry, File}, OpenFlags, Stdin}, syscall::{misc::RLimit, SysError}, task::current_task};
+use crate::{fs::{devfs::tty::TTY, vfs::{file::ensure_nr_files, Dentry, File}, OpenFlags, Stdin}, syscall::{misc::RLimit, SysError}, task::current_task};
 
 use super::task::TaskControlBlock;
 
@@ -19,6 +18,8 @@ pub struct FdTable {
 
 /// Max file descriptors counts
 pub const MAX_FDS: usize = 1024;
+/// the highest RLIMIT_NOFILE may go, like the default fs/nr_open of linux
+pub const NR_OPEN: usize = 1 << 20;
 
 impl FdTable {
     /// new and init fd table
@@ -35,21 +36,35 @@ impl FdTable {
         // 2 -> stderr
         let stderr = tty_file.clone();
         //stderr.set_flags(OpenFlags::O_WRONLY);
-        table.push(Some(FdInfo { file: stdin, flags: FdFlags::empty() }));
-        table.push(Some(FdInfo { file: stdout, flags: FdFlags::empty() }));
-        table.push(Some(FdInfo { file: stderr, flags: FdFlags::empty() }));
+        table.push(Some(FdInfo { file: stdin, flags: FdFlags::empty(), path: false }));
+        table.push(Some(FdInfo { file: stdout, flags: FdFlags::empty(), path: false }));
+        table.push(Some(FdInfo { file: stderr, flags: FdFlags::empty(), path: false }));
         
         Self { 
             fd_table: table,
             rlimit: RLimit { rlim_cur: MAX_FDS, rlim_max: MAX_FDS }
         }
     }
-    /// allocate a new fd for the task
+    /// the fds below RLIMIT_NOFILE which are free
+    fn free_fds(&self) -> usize {
+        let limit = self.rlimit.rlim_cur;
+        let used = self.fd_table.iter().take(limit).filter(|fd| fd.is_some()).count();
+        limit.saturating_sub(used)
+    }
+    /// check that `n` more fds fit below RLIMIT_NOFILE, else EMFILE
+    pub fn ensure_free_fds(&self, n: usize) -> Result<(), SysError> {
+        if self.free_fds() < n {
+            return Err(SysError::EMFILE);
+        }
+        Ok(())
+    }
+    /// allocate a new fd for the task, below RLIMIT_NOFILE
     /// will not expend the fd table
     pub fn alloc_fd(&mut self) -> Result<usize, SysError> {
-        if let Some (fd) = (0..self.fd_table.len()).find(|fd| self.fd_table[*fd].is_none()) {
+        let limit = self.rlimit.rlim_cur;
+        if let Some (fd) = (0..self.fd_table.len().min(limit)).find(|fd| self.fd_table[*fd].is_none()) {
             Ok(fd)
-        } else if self.fd_table.len() < self.rlimit.rlim_max {
+        } else if self.fd_table.len() < limit {
             self.fd_table.push(None);
             Ok(self.fd_table.len() - 1)
         } else {
@@ -59,7 +74,8 @@ impl FdTable {
     /// allocate a new fd greater or equal to given bound
     /// expend the table if the max fd is not enough
     pub fn alloc_fd_from(&mut self, bound: usize) -> Result<usize, SysError> {
-        if bound > self.rlimit.rlim_max {
+        let limit = self.rlimit.rlim_cur;
+        if bound >= limit {
             return Err(SysError::EMFILE)
         }
 
@@ -67,9 +83,9 @@ impl FdTable {
             // expand the fd table
             self.fd_table.resize(bound + 1, None);
         }
-        if let Some(fd) = (bound..self.fd_table.len()).find(|fd| self.fd_table[*fd].is_none()) {
+        if let Some(fd) = (bound..self.fd_table.len().min(limit)).find(|fd| self.fd_table[*fd].is_none()) {
             Ok(fd)
-        } else if self.fd_table.len() < self.rlimit.rlim_max {
+        } else if self.fd_table.len() < limit {
             // no space, append to end
             self.fd_table.push(None);
             Ok(self.fd_table.len() - 1)
@@ -102,19 +118,33 @@ impl FdTable {
         })
     }
     /// get the file using fd
-    /// error if not found
+    /// error if not found, or if the fd was opened with O_PATH and only names the file
     pub fn get_file(&self, fd: usize) -> Result<Arc<dyn File>, SysError> {
-        if fd >= self.fd_table.len() {
-            log::warn!("[get_file] fd {} is not valid",fd);
-            return Err(SysError::EBADF);
-        }
-        if let Some(fdinfo) = self.fd_table[fd].clone() {
-            return Ok(fdinfo.file)
-        } else {
-            log::warn!("[get_file_2] fd {} is not valid, table len {}, is true: {}",fd,self.fd_table.len(), !self.fd_table[fd].is_none());
-            return Err(SysError::EBADF);
+        match self.fd_table.get(fd) {
+            Some(Some(fdinfo)) if !fdinfo.path => Ok(fdinfo.file.clone()),
+            _ => {
+                log::warn!("[get_file] fd {} is not valid, table len {}", fd, self.fd_table.len());
+                Err(SysError::EBADF)
+            }
         }
     }
+    /// get the file using fd, O_PATH fds included,
+    /// for the operations which only need to know what the fd names
+    pub fn get_path_file(&self, fd: usize) -> Result<Arc<dyn File>, SysError> {
+        self.get_fd_info(fd).map(|fdinfo| fdinfo.file)
+    }
+    /// install `file` at the lowest free fd
+    pub fn install(&mut self, file: Arc<dyn File>, flags: FdFlags) -> Result<usize, SysError> {
+        let fd = self.alloc_fd()?;
+        self.fd_table[fd] = Some(FdInfo { file, flags, path: false });
+        Ok(fd)
+    }
+    /// install a file opened with O_PATH at the lowest free fd
+    pub fn install_path(&mut self, file: Arc<dyn File>, flags: FdFlags) -> Result<usize, SysError> {
+        let fd = self.alloc_fd()?;
+        self.fd_table[fd] = Some(FdInfo { file, flags, path: true });
+        Ok(fd)
+    }
     /// put the file into given fd slot
     pub fn put_file(&mut self, fd: usize, fd_info: FdInfo) -> Result<(), SysError> {
         if fd >= self.fd_table.len() {
@@ -139,33 +169,40 @@ impl FdTable {
     }
     /// dup fd in file table with bound, return new fd
     /// new fd will use the given flags
+    /// the common path of dup and F_DUPFD
     pub fn dup_with_bound(&mut self, old_fd: usize, bound: usize, flags: FdFlags) -> Result<usize, SysError> {
         log::debug!("dup with bound: old fd {}, bound {}", old_fd, bound);
-        let file = self.get_file(old_fd)?;
-        let fd_info = FdInfo {file, flags};
+        // validate old_fd before allocating, so a bad fd never takes a slot
+        let old = self.get_fd_info(old_fd)?;
+        if bound >= self.rlimit.rlim_cur {
+            return Err(SysError::EINVAL);
+        }
         let new_fd = self.alloc_fd_from(bound)?;
-        self.put_file(new_fd, fd_info)?;
         assert!(new_fd >= bound);
-        Ok(new_fd)
+        self.install_dup(new_fd, FdInfo { flags, ..old })
     }
     /// dup fd
-    /// new fd will have empty flags
+    /// new fd will have empty flags, so close-on-exec is cleared
     /// no bound
     pub fn dup_no_flag(&mut self, old_fd: usize) -> Result<usize, SysError> {
-        let file = self.get_file(old_fd)?;
-        let fd_info = FdInfo {file, flags: FdFlags::empty()};
-        let new_fd = self.alloc_fd_from(0)?;
-        self.put_file(new_fd, fd_info)?;
-        Ok(new_fd)
+        self.dup_with_bound(old_fd, 0, FdFlags::empty())
     }
     /// call by dup3
-    /// new fd will use the given flags
+    /// new fd will use the given flags, an open new fd is closed first
     pub fn dup3(&mut self, old_fd: usize, new_fd: usize, flags: FdFlags) -> Result<usize, SysError> {
-        let file = self.get_file(old_fd)?;
+        let old = self.get_fd_info(old_fd)?;
+        self.install_dup(new_fd, FdInfo { flags, ..old })
+    }
+    /// put a duplicate `fd_info` at `new_fd`, growing the table up to the limit.
+    /// both fds refer to the same open file, so they share the offset, status flags and O_PATH
+    fn install_dup(&mut self, new_fd: usize, fd_info: FdInfo) -> Result<usize, SysError> {
+        if new_fd >= self.rlimit.rlim_cur {
+            return Err(SysError::EBADF);
+        }
         if self.fd_table.len() <= new_fd {
-            self.fd_table.resize(new_fd.checked_add(1).ok_or(SysError::EMFILE)?, None);
+            self.fd_table.resize(new_fd + 1, None);
         }
-        self.fd_table[new_fd] = Some(FdInfo {file, flags});
+        self.fd_table[new_fd] = Some(fd_info);
         Ok(new_fd)
     }
     /// call by dup3
@@ -182,13 +219,10 @@ impl FdTable {
     pub fn rlimit(&self) -> RLimit {
         self.rlimit
     }
-    /// set rlimit
+    /// set rlimit, the fds open beyond a lowered limit stay open
+    /// and only new fds are kept below it
     pub fn set_rlimit(&mut self, rlimit: RLimit) {
         self.rlimit = rlimit;
-        if rlimit.rlim_max <= self.fd_table.len() {
-            warn!("[Fd table]: set limit smaller than table size, truncate");
-            self.fd_table.truncate(rlimit.rlim_max);
-        }
     }
     /// handle close-on-exec flag
     pub fn do_close_on_exec(&mut self) {
@@ -213,6 +247,9 @@ pub struct FdInfo {
     pub file: Arc<dyn File>,
     /// fd flags
     pub flags: FdFlags,
+    /// opened with O_PATH, the fd only names the file: it may be
+    /// stated, used as a dirfd or duplicated, but not read or written
+    pub path: bool,
 }
 
 impl FdInfo {
@@ -256,6 +293,12 @@ impl TaskControlBlock {
         log::info!("switching task {}'s cwd to {}", self.gettid(), dentry.path());
         *self.cwd.lock() = dentry;
     }
+    /// check that `n` more files can be opened before building them:
+    /// EMFILE when the fd table is full, ENFILE when the system is
+    pub fn ensure_can_open(&self, n: usize) -> Result<(), SysError> {
+        self.with_fd_table(|table| table.ensure_free_fds(n))?;
+        ensure_nr_files(n, self.with_cred(|c| c.is_privileged()))
+    }
     
     
 }
\ No newline at end of file
@@ -23,6 +23,8 @@ pub mod manager;
 pub mod utils;
 pub mod fs;
 pub mod signal;
+pub mod ptrace;
+pub mod cred;
 
 #[allow(clippy::module_inception)]
 #[allow(rustdoc::private_intra_doc_links)]
@@ -30,7 +32,7 @@ pub mod signal;
 
 use core::sync::atomic::{AtomicI32, Ordering};
 use crate::fs::{
-    utils::FileReader, vfs::file::open_file, OpenFlags
+    initramfs, utils::FileReader, vfs::file::open_file, OpenFlags
 };
 use hal::instruction::{InstructionHal, Instruction};
 use alloc::sync::Arc;
@@ -58,12 +60,20 @@ lazy_static! {
         //info!("trying to open initproc");
         
         #[cfg(target_arch="riscv64")]
-        let file = open_file("/riscv/autotest", OpenFlags::O_WRONLY).unwrap();
-        // let file = open_file("/riscv/initproc", OpenFlags::O_WRONLY).unwrap();
+        let path = "/riscv/autotest";
+        // let path = "/riscv/initproc";
 
         #[cfg(target_arch="loongarch64")]
-        let file = open_file("/loongarch/autotest", OpenFlags::O_WRONLY).unwrap();
-        // let file = open_file("/loongarch/initproc", OpenFlags::O_WRONLY).unwrap();
+        let path = "/loongarch/autotest";
+        // let path = "/loongarch/initproc";
+
+        let file = if initramfs::booted() {
+            initramfs::INIT_PATHS.iter()
+                .find_map(|path| open_file(path, OpenFlags::O_RDONLY))
+                .expect("no /init or /bin/init in the initramfs")
+        } else {
+            open_file(path, OpenFlags::O_WRONLY).unwrap()
+        };
 
         let reader = FileReader::new(file.clone()).unwrap();
         let elf = xmas_elf::ElfFile::new(&reader).unwrap();
@@ -0,0 +1,203 @@
+//! task ptrace related implement
+//! the tracee stops at syscall entry/exit and before a signal is delivered,
+//! the tracer collects the stop with waitpid and resumes it with ptrace
+
+use alloc::{collections::btree_map::BTreeMap, sync::{Arc, Weak}, vec::Vec};
+
+use crate::{signal::{SigInfo, SIGCHLD, SIGKILL, SIGTRAP}, utils::suspend_now};
+
+use super::{task::TaskControlBlock, tid::Tid};
+
+/// set bit 7 of the signal number of syscall stops
+pub const PTRACE_O_TRACESYSGOOD: usize = 1;
+
+/// the ptrace state of a task, both as a tracee and as a tracer
+pub struct PtraceState {
+    /// the tracer, None if the task is not traced
+    pub tracer: Option<Weak<TaskControlBlock>>,
+    /// options set by PTRACE_SETOPTIONS
+    pub options: usize,
+    /// stop at syscall entry and exit, set by PTRACE_SYSCALL
+    pub trace_syscall: bool,
+    /// the task is in a ptrace stop, waiting for the tracer to resume it
+    pub stopped: bool,
+    /// wait status of the current stop, until the tracer's waitpid collects it
+    pub report: Option<usize>,
+    /// signal number the tracer passed on resume, 0 for none
+    pub resume_sig: usize,
+    /// the tasks traced by this task
+    pub tracees: BTreeMap<Tid, Weak<TaskControlBlock>>,
+}
+
+impl PtraceState {
+    /// a task which is neither traced nor tracing
+    pub fn new() -> Self {
+        Self {
+            tracer: None,
+            options: 0,
+            trace_syscall: false,
+            stopped: false,
+            report: None,
+            resume_sig: 0,
+            tracees: BTreeMap::new(),
+        }
+    }
+}
+
+/// wait status of a stopped task
+fn stop_status(signo: usize) -> usize {
+    (signo << 8) | 0x7f
+}
+
+impl TaskControlBlock {
+    /// the tracer of the task, if it is traced
+    pub fn tracer(&self) -> Option<Arc<TaskControlBlock>> {
+        self.with_ptrace(|p| p.tracer.as_ref().and_then(|t| t.upgrade()))
+    }
+    /// whether the task is traced
+    pub fn is_traced(&self) -> bool {
+        self.with_ptrace(|p| p.tracer.is_some())
+    }
+    /// whether the task is in a ptrace stop
+    pub fn in_ptrace_stop(&self) -> bool {
+        self.with_ptrace(|p| p.stopped)
+    }
+    /// the task with `tid` traced by self
+    pub fn tracee(&self, tid: Tid) -> Option<Arc<TaskControlBlock>> {
+        self.with_ptrace(|p| p.tracees.get(&tid).and_then(|t| t.upgrade()))
+    }
+    /// whether self traces `pid`, or any task when pid is -1
+    pub fn has_tracee(&self, pid: isize) -> bool {
+        self.with_ptrace(|p| match pid {
+            -1 => !p.tracees.is_empty(),
+            pid => p.tracees.contains_key(&(pid as Tid)),
+        })
+    }
+    /// collect the stop of a tracee matching `pid` (-1 for any) for waitpid
+    pub fn take_ptrace_report(&self, pid: isize) -> Option<(Tid, usize)> {
+        let tracees: Vec<_> = self.with_ptrace(|p| {
+            p.tracees.iter()
+                .filter(|(&tid, _)| pid == -1 || tid == pid as Tid)
+                .filter_map(|(_, t)| t.upgrade())
+                .collect()
+        });
+        tracees.iter().find_map(|t| t.with_mut_ptrace(|p| p.report.take()).map(|status| (t.tid(), status)))
+    }
+
+    /// start to be traced by `tracer`
+    pub fn ptrace_attach(self: &Arc<Self>, tracer: &Arc<TaskControlBlock>) {
+        self.with_mut_ptrace(|p| {
+            p.tracer = Some(Arc::downgrade(tracer));
+            p.options = 0;
+            p.trace_syscall = false;
+        });
+        tracer.with_mut_ptrace(|p| p.tracees.insert(self.tid(), Arc::downgrade(self)));
+    }
+
+    /// stop being traced, the task goes on running with `sig` delivered if nonzero
+    pub fn ptrace_detach(self: &Arc<Self>, sig: usize) {
+        if let Some(tracer) = self.tracer() {
+            tracer.with_mut_ptrace(|p| p.tracees.remove(&self.tid()));
+        }
+        self.with_mut_ptrace(|p| {
+            p.tracer = None;
+            p.trace_syscall = false;
+            p.report = None;
+        });
+        if self.in_ptrace_stop() {
+            self.ptrace_resume(sig, false);
+        }
+    }
+
+    /// let a stopped tracee run again, delivering `sig` if nonzero
+    pub fn ptrace_resume(&self, sig: usize, trace_syscall: bool) {
+        self.with_mut_ptrace(|p| {
+            p.trace_syscall = trace_syscall;
+            p.resume_sig = sig;
+            p.report = None;
+            p.stopped = false;
+        });
+        if self.is_stopped() {
+            self.wake();
+        }
+    }
+
+    /// drop all the ptrace links of an exiting task, its tracees are left running
+    pub fn ptrace_release(self: &Arc<Self>) {
+        let tracees: Vec<_> = self.with_mut_ptrace(|p| {
+            core::mem::take(&mut p.tracees).into_values().filter_map(|t| t.upgrade()).collect()
+        });
+        for tracee in tracees {
+            tracee.ptrace_detach(0);
+        }
+        if let Some(tracer) = self.tracer() {
+            tracer.with_mut_ptrace(|p| p.tracees.remove(&self.tid()));
+        }
+        self.with_mut_ptrace(|p| p.tracer = None);
+    }
+
+    /// enter a ptrace stop reported as `status`, return the signal given by the tracer on resume
+    async fn ptrace_stop(self: &Arc<Self>, status: usize) -> usize {
+        let Some(tracer) = self.tracer() else {
+            return 0;
+        };
+        // the registers the tracer reads are those at the stop
+        self.save_user_regs();
+        self.with_mut_ptrace(|p| {
+            p.stopped = true;
+            p.report = Some(status);
+            p.resume_sig = 0;
+        });
+        self.set_stopped();
+        tracer.recv_sigs_process_level(
+            SigInfo { si_pid: Some(self.pid()), ..SigInfo::new(SIGCHLD, SigInfo::CLD_TRAPPED) }
+        );
+        // SIGKILL always ends the stop
+        while self.in_ptrace_stop() && !self.with_sig_manager(|m| m.bitmap.contain_sig(SIGKILL)) {
+            suspend_now().await;
+        }
+        self.set_running();
+        self.with_mut_ptrace(|p| {
+            p.stopped = false;
+            p.report = None;
+            core::mem::take(&mut p.resume_sig)
+        })
+    }
+
+    /// syscall-enter-stop and syscall-exit-stop, if the tracer asked for them
+    pub async fn ptrace_syscall_stop(self: &Arc<Self>) {
+        let (trace_syscall, options) = self.with_ptrace(|p| (p.tracer.is_some() && p.trace_syscall, p.options));
+        if !trace_syscall {
+            return;
+        }
+        let signo = if options & PTRACE_O_TRACESYSGOOD != 0 { SIGTRAP | 0x80 } else { SIGTRAP };
+        let sig = self.ptrace_stop(stop_status(signo)).await;
+        if sig != 0 {
+            self.recv_sigs(SigInfo::new(sig, SigInfo::USER));
+        }
+    }
+
+    /// signal-delivery-stop: report every deliverable signal to the tracer first,
+    /// only the signals it passes back on resume stay pending
+    pub async fn ptrace_report_signals(self: &Arc<Self>) {
+        if !self.is_traced() {
+            return;
+        }
+        let mut injected = Vec::new();
+        while self.is_traced() {
+            let sig = self.with_mut_sig_manager(|m| {
+                if m.bitmap.contain_sig(SIGKILL) { None } else { m.dequeue_one() }
+            });
+            let Some(sig) = sig else {
+                break;
+            };
+            let resume_sig = self.ptrace_stop(stop_status(sig.si_signo)).await;
+            if resume_sig == sig.si_signo {
+                injected.push(sig);
+            } else if resume_sig != 0 {
+                injected.push(SigInfo::new(resume_sig, SigInfo::USER));
+            }
+        }
+        self.with_mut_sig_manager(|m| injected.into_iter().for_each(|sig| m.receive(sig)));
+    }
+}
@@ -3,22 +3,50 @@ use hal::{constant::{Constant, ConstantsHal}, println, trap::{TrapContext, TrapC
 use core::{
     future::Future,
     pin::Pin,
+    sync::atomic::Ordering,
     task::{Context, Poll},
 };
 
 use log::{debug, info, trace};
-use crate::{syscall::SysError, timer::get_current_time_duration, trap::user_trap_handler};
+use crate::{sysctl::IntParam, syscall::SysError, timer::get_current_time_duration, trap::user_trap_handler};
 use crate::task::TaskControlBlock;
 use crate::executor;
-use crate::utils::async_utils::{get_waker,suspend_now};
+use crate::utils::async_utils::{get_waker, suspend_now, yield_now};
 use crate::processor::processor::*;
 use crate::trap::trap_return;
 use super::task::TaskStatus;
 use crate::processor::{context::EnvContext,processor::current_processor};
 
+/// the time slice of a task in milliseconds, kernel/sched_timeslice_ms
+pub static SCHED_TIMESLICE_MS: IntParam = IntParam::new(10, 1, 1000);
+
+/// on a timer tick: mark `task` for rescheduling once it used up its time slice,
+/// it is not preempted in the kernel but yields on its way back to user mode
+pub fn check_timeslice(task: &TaskControlBlock) {
+    let recorder = task.time_recorder();
+    recorder.record_tick();
+    if recorder.slice_used().as_millis() as usize >= SCHED_TIMESLICE_MS.get() {
+        task.set_need_resched(true);
+    }
+}
+
+/// yield if the time slice of the current task ran out, the task goes to the back of its run queue.
+/// a yield point for long loops in the kernel, which must hold no lock across it
+pub async fn cond_resched() {
+    let Some(task) = current_task().cloned() else {
+        return;
+    };
+    if task.need_resched.swap(false, Ordering::Relaxed) {
+        task.time_recorder().record_preempt();
+        yield_now().await;
+    }
+}
+
 /// The outermost future for user task
 pub struct UserTaskFuture <F: Future + Send + 'static>{
-    /// pub for cpu_mask
+    /// pub for cpu_mask. `current_task()` points at this Arc while the task is polled,
+    /// and it is the last one dropped when the task exits, by the executor after the
+    /// final poll switched the task out, so no hart can see the TCB freed under it
     pub task: Arc<TaskControlBlock>,
     env: EnvContext,
     future: F,
@@ -46,6 +74,17 @@ impl <F:Future+Send+'static> Future for UserTaskFuture<F> {
     fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
         //info!("now poll task {}", self.task.tid());
         let this = unsafe {self.get_unchecked_mut()};
+        #[cfg(feature = "smp")]
+        {
+            // the affinity changed while queued or the task was stolen by another hart:
+            // wake it again so the scheduler puts it on an allowed hart
+            let processor = current_processor();
+            let allowed = this.task.cpu_allowed() & online_harts();
+            if allowed != 0 && allowed & (1 << processor.id()) == 0 {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+        }
         switch_to_current_task(current_processor(),&mut this.task,&mut this.env);
         let ret = unsafe{Pin::new_unchecked(&mut this.future).poll(cx)};
         //info!("switch out current task, current task is {}", current_task().unwrap().tid());
@@ -87,6 +126,8 @@ pub async fn run_tasks(task: Arc<TaskControlBlock>) {
     );*/
     let mut is_interrupted = false;
     loop {
+        // a task which used up its slice lets the others run before it goes back to user mode
+        cond_resched().await;
         // check current task status before return
         match task.get_status() {
             TaskStatus::Zombie => break,
@@ -117,6 +158,8 @@ pub async fn run_tasks(task: Arc<TaskControlBlock>) {
             _ => {}
         }
 
+        // a traced task hands its signals to the tracer before handling them
+        task.ptrace_report_signals().await;
         task.check_and_handle(is_interrupted, old_a0);
     }
 }
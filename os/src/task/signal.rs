@@ -6,7 +6,7 @@ use alloc::sync::Arc;
 use fatfs::info;
 use hal::{addr::VirtAddr, println, signal::{sigreturn_trampoline_addr, UContext, UContextHal}, trap::TrapContextHal};
 
-use crate::{mm::{vm::UserVmSpaceHal, UserPtrRaw}, signal::{KSigAction, LinuxSigInfo, SigAction, SigActionFlag, SigHandler, SigInfo, SigSet, SIGCHLD, SIGKILL, SIGSTOP}, task::INITPROC_PID, trap::trap_return};
+use crate::{mm::{vm::UserVmSpaceHal, UserPtrRaw}, syscall::SysError, utils::{Select2Futures, SelectOutput}, signal::{KSigAction, LinuxSigInfo, SigAction, SigActionFlag, SigChld, SigHandler, SigInfo, SigSet, SIGCHLD, SIGCONT, SIGKILL, SIGSTOP}, task::INITPROC_PID, timer::duration_to_ticks, trap::trap_return};
 
 use super::task::TaskControlBlock;
 
@@ -41,7 +41,10 @@ impl TaskControlBlock {
             if manager.wake_sigs.contain_sig(sig.si_signo) && self.is_interruptable() {
                 //info!("[TCB]: tid {} has been wake up", self.gettid());
                 self.wake();
-            } 
+            } else if sig.si_signo == SIGKILL && self.is_stopped() && self.in_ptrace_stop() {
+                // SIGKILL ends a ptrace stop
+                self.wake();
+            }
             /* else if manager.wake_sigs.contain_sig(sig.si_signo) && self.is_zombie() {
                 log::info!("[TCB]: wake up tid {} to finish its handle zombie", self.gettid());
                 self.wake();
@@ -50,8 +53,11 @@ impl TaskControlBlock {
     }
     /// Unix has two types of signal: Process level and Thread level
     /// in Process-level, all threads in the same process share the same signal mask
-    pub fn recv_sigs_process_level(&self, sig_info: SigInfo) {
+    pub fn recv_sigs_process_level(self: &Arc<Self>, sig_info: SigInfo) {
         log::info!("[TCB::recv_sigs_process_level]: tid {} recv signo {} at process level",self.tid(),sig_info.si_signo);
+        if sig_info.si_signo == SIGCONT {
+            self.continue_stopped();
+        }
         self.with_mut_thread_group(|tg| {
             let mut signal_delivered = false;
             for thread in tg.iter() {
@@ -69,17 +75,60 @@ impl TaskControlBlock {
         })
     }
 
+    /// SIGCONT continues a stopped process as it is sent, whatever its disposition,
+    /// the parent is told if there was something to continue
+    fn continue_stopped(self: &Arc<Self>) {
+        let continued = self.with_thread_group(|tg| {
+            let mut continued = false;
+            for thread in tg.iter() {
+                if thread.is_stopped() && !thread.in_ptrace_stop() {
+                    thread.set_running();
+                    thread.wake();
+                    continued = true;
+                }
+            }
+            continued
+        });
+        if continued {
+            self.get_leader().notify_parent_with(SigInfo::CLD_CONTINUED, SIGCONT as i32);
+        }
+    }
+
     /// child process notify parent
     /// send SIGCHLD signal to parent
-    /// Let a parent know about the death of a child.
-    /// TODOS: should closer to linux design; si_code;
+    /// Let a parent know about the death of a child:
+    /// exited, killed or killed with a core dump, as told by the wait status of the group
     pub fn notify_parent(self: &Arc<Self>) {
-        
+        let status = self.with_thread_group(|tg| tg.group_exit_code);
+        let (si_code, status) = match status & 0x7f {
+            0 => (SigInfo::CLD_EXITED, (status >> 8) & 0xff),
+            signo if status & 0x80 != 0 => (SigInfo::CLD_DUMPED, signo),
+            signo => (SigInfo::CLD_KILLED, signo),
+        };
+        self.notify_parent_with(si_code, status as i32);
+    }
+
+    /// send SIGCHLD with `si_code` and `status` of this process to the parent,
+    /// a stop or continue is not told to a parent which set SA_NOCLDSTOP
+    pub fn notify_parent_with(self: &Arc<Self>, si_code: i32, status: i32) {
         if let Some(parent) = self.parent() {
             if let Some(parent) = parent.upgrade() {
+                if si_code == SigInfo::CLD_STOPPED || si_code == SigInfo::CLD_CONTINUED {
+                    let flags = parent.with_sig_manager(|s| SigActionFlag::from_bits_truncate(s.sig_handler[SIGCHLD].sa.sa_flags));
+                    if flags.contains(SigActionFlag::SA_NOCLDSTOP) {
+                        return;
+                    }
+                }
+                let (utime, stime) = self.process_time_pair();
+                let chld = SigChld {
+                    uid: self.with_cred(|c| c.ruid),
+                    status,
+                    utime: duration_to_ticks(utime) as i64,
+                    stime: duration_to_ticks(stime) as i64,
+                };
                 // log::info!("[TCB] task {} notify parent", self.gettid());
                 parent.recv_sigs_process_level(
-                    SigInfo { si_signo: SIGCHLD, si_code: SigInfo::CLD_EXITED, si_pid: Some(self.pid()) }
+                    SigInfo { si_pid: Some(self.pid()), si_chld: Some(chld), ..SigInfo::new(SIGCHLD, si_code) }
                 );
             }else {
                 log::error!("no parent !");
@@ -116,7 +165,7 @@ impl TaskControlBlock {
                     };
                     sig_manager.blocked_sigs |= sig_action.sa.sa_mask[0];
                     // save fx state
-                    trap_cx.fx_encounter_signal();
+                    trap_cx.fx_sync();
                     // push the current Ucontext into user stack
                     // (todo) notice that user may provide signal stack
                     // but now we dont support this flag
@@ -140,10 +189,7 @@ impl TaskControlBlock {
                         // the second argument
                         trap_cx.set_arg_nth(2, new_sp);
                         // the third argument
-                        let mut siginfo_v = LinuxSigInfo::default();
-                        siginfo_v.si_signo = sig.si_signo as _;
-                        siginfo_v.si_code = sig.si_code;
-                        siginfo_v._pad[1] = sig.si_pid.unwrap_or(0) as i32;
+                        let siginfo_v = LinuxSigInfo::from(sig);
                         new_sp -= size_of::<LinuxSigInfo>();
                         let dst = 
                             UserPtrRaw::new(new_sp as *mut LinuxSigInfo).ensure_write(&mut self.get_vm_space().lock()).unwrap();
@@ -175,6 +221,18 @@ impl TaskControlBlock {
                 break;
             }
         }
+        if is_intr {
+            // the EINTR reaches the user, the interrupted sleep is not resumed any more
+            *self.sleep_restart.lock() = None;
+        }
+    }
+}
+
+impl TaskControlBlock {
+    /// the signals which interrupt a blocking syscall: any neither blocked nor ignored,
+    /// SIGKILL always
+    pub fn intr_sigs(&self) -> SigSet {
+        self.with_sig_manager(|s| (!s.blocked_sigs - s.ignored_sets()) | SigSet::SIGKILL)
     }
 }
 
@@ -190,7 +248,7 @@ impl Future for IntrBySignalFuture {
     type Output = ();
 
     fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let has_signal = !(self.task.sig_manager.lock().bitmap & !self.mask).is_empty();
+        let has_signal = self.task.sig_manager.lock().check_pending_flag(!self.mask);
         if has_signal {
             log::warn!("[IntrBySignalFuture] received interupt signal");
             Poll::Ready(())
@@ -198,4 +256,23 @@ impl Future for IntrBySignalFuture {
             Poll::Pending
         }
     }
-}
\ No newline at end of file
+}
+/// run `fut` until it completes or a signal of `intr_sigs` is pending, EINTR then.
+/// The task sleeps interruptibly meanwhile, so such a signal wakes it; wrap the
+/// suspend points of a blocking syscall in it, the trap handler turns the EINTR into
+/// a restart for a handler with SA_RESTART
+pub async fn interruptible<F: Future>(task: &Arc<TaskControlBlock>, fut: F) -> Result<F::Output, SysError> {
+    let intr_sigs = task.intr_sigs();
+    task.set_interruptable();
+    task.set_wake_up_sigs(intr_sigs);
+    let intr_future = IntrBySignalFuture {
+        task: task.clone(),
+        mask: !intr_sigs,
+    };
+    let ret = match Select2Futures::new(fut, intr_future).await {
+        SelectOutput::Output1(ret) => Ok(ret),
+        SelectOutput::Output2(_) => Err(SysError::EINTR),
+    };
+    task.set_running();
+    ret
+}
@@ -3,29 +3,38 @@
 #![allow(missing_docs)]
 
 use super::fs::FdTable;
+use super::cred::Credentials;
+use super::ptrace::PtraceState;
 use super::manager::{PROCESS_GROUP_MANAGER, TASK_MANAGER};
 use super::{tid_alloc, schedule, INITPROC};
-use crate::fs::devfs::tty::TTY;
+use crate::fs::devfs::tty::{Session, TTY};
 use crate::processor::context::{EnvContext,SumGuard};
 use crate::fs::vfs::{Dentry, DCACHE};
 use crate::fs::{Stdin, Stdout, vfs::File};
-use crate::mm::{copy_out_str, translate_uva_checked, UserPtr, UserPtrRaw, UserPtrRead, UserVmSpace, KVMSPACE};
-use crate::processor::processor::{current_processor, PROCESSORS};
+use crate::mm::{copy_out_str, UserPtr, UserPtrRaw, UserPtrRead, UserVmSpace, KVMSPACE};
+use crate::processor::ipi::{harts_running, mm_key, set_running_mm};
+use crate::processor::processor::{current_processor, CPU_MASK_ALL, PROCESSORS};
 #[cfg(feature = "smp")]
 use crate::processor::schedule::TaskLoadTracker;
 use crate::sync::mutex::spin_mutex::MutexGuard;
 use crate::sync::mutex::{MutexSupport, SpinNoIrq, SpinNoIrqLock};
 use crate::sync::UPSafeCell;
-use crate::syscall::futex::{futex_manager, FutexHashKey, RobustList, RobustListHead, FUTEX_OWNER_DIED, FUTEX_TID_MASK, FUTEX_WAITERS};
+use crate::syscall::futex::{futex_key, futex_manager, RobustList, RobustListHead, FUTEX_OWNER_DIED, FUTEX_TID_MASK, FUTEX_WAITERS};
+use crate::syscall::misc::{RLimit, RLIM_INFINITY};
 use crate::syscall::process::CloneFlags;
+use crate::executor::{nice_to_level, IDLE_LEVEL};
+use crate::syscall::prctl::{comm_from_bytes, SyscallFilter};
+use crate::syscall::sche::{SCHED_FIFO, SCHED_IDLE, SCHED_OTHER, SCHED_RR};
 use crate::signal::{KSigAction, SigInfo, SigManager, SigSet, SIGCHLD, SIGKILL, SIGSTOP};
 use crate::syscall::SysError;
 use crate::task::{current_task, INITPROC_PID};
-use crate::task::utils::user_stack_init;
+use crate::task::utils::{user_stack_init, AuxHeader};
 use crate::timer::get_current_time_duration;
 use crate::timer::recoder::TimeRecorder;
+use crate::mm::stats::{add_counts, VmEventCounts, VmStats, VM_EVENTS};
 use crate::timer::timer::ITimer;
-use crate::utils::{suspend_forever, SendWrapper};
+use crate::timer::timed_task::SleepRestart;
+use crate::utils::{suspend_forever, yield_now, SendWrapper};
 use alloc::collections::btree_map::BTreeMap;
 use alloc::sync::{Arc, Weak};
 use alloc::{fmt, format, task, vec};
@@ -37,7 +46,7 @@ use hal::pagetable::PageTableHal;
 use hal::trap::{TrapContext, TrapContextHal};
 use hal::println;
 use xmas_elf::reader::Reader;
-use crate::mm::vm::{self, PageFaultAccessType, UserVmSpaceHal};
+use crate::mm::vm::{self, UserVmSpaceHal};
 use hal::signal::*;
 use alloc::slice;
 use alloc::{vec::*, string::String, };
@@ -60,15 +69,45 @@ use log::*;
 use super::tid::{PGid, Pid, Tid, TidAddress, TidHandle};
 /// pack Arc<Spin> into a struct
 pub type Shared<T> = Arc<SpinNoIrqLock<T>>;
+/// [`TaskControlBlock::current_syscall`] of a task in no syscall
+pub const NO_SYSCALL: usize = usize::MAX;
+/// the RLIMIT_MEMLOCK a process starts with, 8 MiB like linux
+pub const DEFAULT_RLIMIT_MEMLOCK: usize = 8 << 20;
 
 /// pack Option<Arc<Spin> into a struct
 pub type SharedOption<T> = Option<Arc<SpinNoIrqLock<T>>>;
 
+/// the thread name derived from the executed file, truncated like linux
+fn comm_of(elf_file: &Option<Arc<dyn File>>) -> String {
+    elf_file.as_ref()
+        .and_then(|f| f.dentry())
+        .map_or(String::new(), |d| comm_from_bytes(d.name().as_bytes()))
+}
+
+/// a program loaded for execve, which does not run until `TaskControlBlock::exec`
+pub struct ExecImage {
+    vm_space: UserVmSpace,
+    user_sp: usize,
+    entry_point: usize,
+    auxv: Vec<AuxHeader>,
+    elf_file: Option<Arc<dyn File>>,
+}
+
+impl ExecImage {
+    /// load `elf` into a new address space, with a main thread stack of `stack_size` bytes
+    pub fn load<T: Reader + ?Sized>(elf: &xmas_elf::ElfFile<'_, T>, elf_file: Option<Arc<dyn File>>, stack_size: usize) -> Result<Self, SysError> {
+        let (vm_space, user_sp, entry_point, auxv) = UserVmSpace::from_elf(elf, elf_file.clone(), stack_size)?;
+        Ok(Self { vm_space, user_sp, entry_point, auxv, elf_file })
+    }
+}
+
 /// new a shared object
+#[cfg_attr(debug_assertions, track_caller)]
 pub fn new_shared<T>(data: T) -> Shared<T> {
     Arc::new(SpinNoIrqLock::new(data))
 }
 /// new a shared option object
+#[cfg_attr(debug_assertions, track_caller)]
 pub fn new_shared_option<T>(data: Option<T>) -> SharedOption<T> {
     if let Some(data) = data {
         Some(Arc::new(SpinNoIrqLock::new(data)))
@@ -93,10 +132,14 @@ pub struct TaskControlBlock {
     /// address of task's thread ID
     pub tid_address: UPSafeCell<TidAddress>,
     /// time recorder for a task
-    pub time_recorder: UPSafeCell<TimeRecorder>,
+    pub time_recorder: TimeRecorder,
+    /// page faults and other memory events of the task
+    pub vm_stats: VmStats,
     /// Futexes used by the task.
     pub robust: UPSafeCell<UserPtrRaw<RobustListHead>>,
     // ! mutable only in self context, can be accessed by other tasks
+    /// the user registers as of the last switch out, the copy of the trap context other tasks read
+    pub user_regs: SpinNoIrqLock<[usize; TrapContext::ELF_NGREG]>,
     /// exit code of the task
     pub exit_code: AtomicUsize,
     /// ELF file the task executes
@@ -119,21 +162,65 @@ pub struct TaskControlBlock {
     pub thread_group: Shared<ThreadGroup>,
     /// process group id
     pub pgid: Shared<PGid>,
+    /// session of the process and its controlling terminal
+    pub session: Shared<Session>,
     /// use signal manager to handle all the signal
     pub sig_manager: Shared<SigManager>,
     /// pointer to user context for signal handling.
     pub sig_ucontext_ptr: AtomicUsize, 
     /// current working dentry
     pub cwd: Shared<Arc<dyn Dentry>>,
+    /// file mode creation mask, shared with the cwd
+    pub umask: Shared<u32>,
     /// Interval timers for the task.
     pub itimers: Shared<[ITimer; 3]>,
+    /// RLIMIT_DATA of the process, bounds the size of the heap
+    pub rlimit_data: Shared<RLimit>,
+    /// RLIMIT_CORE of the process, bounds the size of the core dump, 0 disables it
+    pub rlimit_core: Shared<RLimit>,
+    /// RLIMIT_STACK of the process, sizes the stack of the main thread at exec
+    pub rlimit_stack: Shared<RLimit>,
+    /// RLIMIT_MEMLOCK of the process, bounds the bytes mlock keeps in memory
+    pub rlimit_memlock: Shared<RLimit>,
+    /// ptrace links of the task, as a tracee and as a tracer
+    pub ptrace: Shared<PtraceState>,
+    /// name of the thread, set on exec and by PR_SET_NAME
+    pub comm: Shared<String>,
+    /// whether the process may dump core, PR_SET_DUMPABLE
+    pub dumpable: AtomicBool,
+    /// execve never grants new privileges, PR_SET_NO_NEW_PRIVS
+    pub no_new_privs: AtomicBool,
+    /// log every syscall of the task, inherited by its children
+    pub syscall_trace: AtomicBool,
+    /// the syscall the task is in, [`NO_SYSCALL`] when it is in none
+    pub current_syscall: AtomicUsize,
+    /// the interrupted sleep a restarted syscall resumes
+    pub sleep_restart: Shared<Option<SleepRestart>>,
+    /// the syscalls the task may make, None for all
+    pub syscall_filter: Shared<Option<SyscallFilter>>,
+    /// user and group ids of the process
+    pub cred: Shared<Credentials>,
     #[cfg(feature = "smp")]
     /// sche_entity of the task
     pub sche_entity: Shared<TaskLoadTracker>,
-    /// the cpu allowed to run this task
+    /// the harts allowed to run this task, bit i stands for hart i
     pub cpu_allowed: AtomicUsize,
-    /// the processor id of the task
+    /// the hart the task last ran on, set when the task is switched in
     pub processor_id: AtomicUsize,
+    /// whether the task is running on a hart, between its switch in and its switch out
+    pub on_cpu: AtomicBool,
+    /// times the task called sched_yield, for the scheduler statistics
+    pub yield_count: AtomicUsize,
+    /// nice value of the task, -20 (highest priority) to 19
+    pub nice: AtomicI32,
+    /// scheduling policy of the task, SCHED_OTHER unless set by sched_setscheduler
+    pub sched_policy: AtomicI32,
+    /// real-time priority of the task, 0 for the policies which are not real-time
+    pub sched_priority: AtomicI32,
+    /// the children start with SCHED_OTHER and a nice value of at least 0
+    pub sched_reset_on_fork: AtomicBool,
+    /// the time slice ran out, the task yields on its way back to user mode
+    pub need_resched: AtomicBool,
 }
 
 /// Hold a group of threads which belongs to the same process.
@@ -142,6 +229,12 @@ pub struct ThreadGroup {
     alive: usize,
     pub group_exiting: bool,
     pub group_exit_code: usize,
+    /// a thread of the group is in execve, killing the others
+    pub group_exec: bool,
+    /// user and kernel time of the threads removed from the group
+    exited_time: (Duration, Duration),
+    /// memory events of the threads removed from the group
+    exited_vm_events: VmEventCounts,
 }
 
 impl ThreadGroup {
@@ -151,7 +244,10 @@ impl ThreadGroup {
             members: BTreeMap::new(),
             alive: 0,
             group_exiting: false,
-            group_exit_code: 0
+            group_exit_code: 0,
+            group_exec: false,
+            exited_time: (Duration::ZERO, Duration::ZERO),
+            exited_vm_events: [0; VM_EVENTS],
         }
     }
     /// Get the number of threads in the group.
@@ -170,7 +266,12 @@ impl ThreadGroup {
         if !task.is_zombie() {
             self.alive -= 1;
         }
-        self.members.remove(&task.tid());
+        if self.members.remove(&task.tid()).is_some() {
+            let (user_time, kernel_time) = task.time_recorder().time_pair();
+            self.exited_time.0 += user_time;
+            self.exited_time.1 += kernel_time;
+            add_counts(&mut self.exited_vm_events, &task.vm_stats.counts());
+        }
     }
     pub fn add_alive(&mut self, val: usize) {
         if self.alive + val > self.members.len() {
@@ -213,8 +314,7 @@ impl TaskControlBlock {
     generate_upsafecell_accessors!(
         //trap_cx_ppn: PhysPageNum,
         waker: Option<Waker>,
-        tid_address: TidAddress,
-        time_recorder: TimeRecorder
+        tid_address: TidAddress
     );
     generate_with_methods!(
         fd_table: FdTable,
@@ -222,9 +322,18 @@ impl TaskControlBlock {
         thread_group: ThreadGroup,
         task_status: TaskStatus,
         sig_manager: SigManager,
+        session: Session,
         cwd: Arc<dyn Dentry>,
         vm_space: UserVmSpace,
-        itimers: [ITimer;3]
+        itimers: [ITimer;3],
+        rlimit_data: RLimit,
+        rlimit_core: RLimit,
+        rlimit_stack: RLimit,
+        rlimit_memlock: RLimit,
+        ptrace: PtraceState,
+        comm: String,
+        sleep_restart: Option<SleepRestart>,
+        cred: Credentials
     );
     #[cfg(feature = "smp")]
     generate_with_methods!(
@@ -233,8 +342,19 @@ impl TaskControlBlock {
     generate_atomic_accessors!(
         exit_code: usize,
         sig_ucontext_ptr: usize,
+        dumpable: bool,
+        no_new_privs: bool,
+        syscall_trace: bool,
+        current_syscall: usize,
         cpu_allowed: usize,
-        processor_id: usize
+        processor_id: usize,
+        on_cpu: bool,
+        yield_count: usize,
+        nice: i32,
+        sched_policy: i32,
+        sched_priority: i32,
+        sched_reset_on_fork: bool,
+        need_resched: bool
     );
     generate_state_methods!(
         Ready,
@@ -244,6 +364,15 @@ impl TaskControlBlock {
         Interruptable,
         UnInterruptable
     );
+    /// the run queue level of the task: the real-time policies run first,
+    /// SCHED_IDLE below every nice value, and the others by their nice value
+    pub fn sched_level(&self) -> usize {
+        match self.sched_policy() {
+            SCHED_FIFO | SCHED_RR => 0,
+            SCHED_IDLE => IDLE_LEVEL,
+            _ => nice_to_level(self.nice()),
+        }
+    }
     /// get the process id for a process or leader id for a thread
     pub fn pid(self: &Arc<Self>) -> Pid {
         if self.is_leader(){
@@ -265,14 +394,47 @@ impl TaskControlBlock {
     pub fn set_pgid(&self, pgid: PGid) {
         *self.pgid.lock() = pgid
     }
+    /// get the session of the process
+    pub fn session(&self) -> Session {
+        *self.session.lock()
+    }
+    /// get the file mode creation mask
+    pub fn umask(&self) -> u32 {
+        *self.umask.lock()
+    }
+    /// set the file mode creation mask, return the old one
+    pub fn set_umask(&self, umask: u32) -> u32 {
+        core::mem::replace(&mut *self.umask.lock(), umask & 0o777)
+    }
     /// get task id
     pub fn tid(&self) -> Tid {
         self.tid.0
     }
-    /// get trap_cx of the task
+    /// get trap_cx of the task, for the task itself, other tasks read [`Self::user_regs`]
     pub fn get_trap_cx(&self) -> &mut TrapContext {
         self.trap_context.exclusive_access()
     }
+    /// copy the user registers for other tasks to read, called by the task itself
+    pub fn save_user_regs(&self) {
+        self.get_trap_cx().elf_gregs(&mut *self.user_regs.lock());
+    }
+    /// the user registers of the task, as of its last switch out if it is not the caller:
+    /// the trap context of another task is never touched, it may be running on another hart
+    pub fn user_regs(&self) -> [usize; TrapContext::ELF_NGREG] {
+        *self.user_regs.lock()
+    }
+    /// get the time recorder, readable from any hart
+    pub fn time_recorder(&self) -> &TimeRecorder {
+        &self.time_recorder
+    }
+    /// hand the hart-private cells over to whichever hart runs the task next
+    pub fn release_cells(&self) {
+        self.trap_context.release();
+        self.waker.release();
+        self.tid_address.release();
+        self.robust.release();
+        self.vm_space.release();
+    }
     /// get vm_space of the task
     pub fn get_user_token(&self) -> usize {
         self.vm_space.as_ref().lock().get_page_table().get_token()
@@ -302,8 +464,8 @@ impl TaskControlBlock {
         self.children.lock().insert(child.gettid(),child);
     }
     /// remove a child task
-    pub fn remove_child(&self, pid: usize) {
-        self.children.lock().remove(&pid);
+    pub fn remove_child(&self, pid: usize) -> Option<Arc<TaskControlBlock>> {
+        self.children.lock().remove(&pid)
     }
     /// check whether the task is the leader of the thread group   
     pub fn is_leader(&self) -> bool {
@@ -317,18 +479,6 @@ impl TaskControlBlock {
             self.leader.as_ref().unwrap().upgrade().unwrap()
         }
     }
-
-    pub fn turn_cpu_mask_id(self: Arc<Self>) -> usize {
-        let ret = match self.cpu_allowed() {
-            1 => 0,
-            2 => 1,
-            4 => 2,
-            8 => 3,
-            15 => 4,
-            _ => {panic!("cpu_allowed should be 1, 2, 4, 8 or 15")}
-        };
-        ret
-    }
 }
 
 impl TaskControlBlock {
@@ -344,7 +494,7 @@ impl TaskControlBlock {
             mut user_sp, 
             entry_point, 
             _auxv
-        ) = UserVmSpace::from_elf(&elf, elf_file.clone())?;
+        ) = UserVmSpace::from_elf(&elf, elf_file.clone(), Constant::user_stack_size())?;
 
         // set argc to zero
         user_sp -= 8;
@@ -352,10 +502,8 @@ impl TaskControlBlock {
         // *translated_refmut(vm_space.get_page_table().get_token(), user_sp as *mut usize) = 0;
 
         // initproc should set current working dir to root dentry
-        let root_dentry = {
-            let dcache = DCACHE.lock();
-            Arc::clone(dcache.get("/").unwrap())
-        };
+        let root_dentry = DCACHE.root();
+        let comm = comm_of(&elf_file);
 
         let task_control_block = Arc::new(Self {
             tid: tid_handle,
@@ -372,7 +520,9 @@ impl TaskControlBlock {
             ),
             waker: UPSafeCell::new(None),
             tid_address: UPSafeCell::new(TidAddress::new()),
-            time_recorder: UPSafeCell::new(TimeRecorder::new()),
+            time_recorder: TimeRecorder::new(),
+            vm_stats: VmStats::new(),
+            user_regs: SpinNoIrqLock::new([0; TrapContext::ELF_NGREG]),
             exit_code: AtomicUsize::new(0),
             base_size: AtomicUsize::new(user_sp),
             task_status: SpinNoIrqLock::new(TaskStatus::Ready),
@@ -382,16 +532,38 @@ impl TaskControlBlock {
             fd_table: new_shared(FdTable::new()),
             thread_group: new_shared(ThreadGroup::new()),
             pgid: new_shared(pgid),
+            session: new_shared(Session::new(pgid)),
             sig_manager: new_shared(SigManager::new()),
             sig_ucontext_ptr: AtomicUsize::new(0),
             cwd: new_shared(root_dentry), 
+            umask: new_shared(0o022),
             elf: new_shared(elf_file),
             itimers: new_shared([ITimer::ZERO; 3]),
+            rlimit_data: new_shared(RLimit::new(RLIM_INFINITY)),
+            rlimit_core: new_shared(RLimit::new(0)),
+            rlimit_stack: new_shared(RLimit::new(Constant::user_stack_size())),
+            rlimit_memlock: new_shared(RLimit::new(DEFAULT_RLIMIT_MEMLOCK)),
+            ptrace: new_shared(PtraceState::new()),
+            comm: new_shared(comm),
+            dumpable: AtomicBool::new(true),
+            no_new_privs: AtomicBool::new(false),
+            syscall_trace: AtomicBool::new(false),
+            current_syscall: AtomicUsize::new(NO_SYSCALL),
+            sleep_restart: new_shared(None),
+            syscall_filter: new_shared(None),
+            cred: new_shared(Credentials::root()),
             robust: UPSafeCell::new(UserPtrRaw::new(null_mut())),
             #[cfg(feature = "smp")]
             sche_entity: new_shared(TaskLoadTracker::new()),
-            cpu_allowed: AtomicUsize::new(15),
-            processor_id: AtomicUsize::new(current_processor().id())  
+            cpu_allowed: AtomicUsize::new(CPU_MASK_ALL),
+            processor_id: AtomicUsize::new(current_processor().id()),
+            on_cpu: AtomicBool::new(false),
+            yield_count: AtomicUsize::new(0),
+            nice: AtomicI32::new(0),
+            sched_policy: AtomicI32::new(SCHED_OTHER),
+            sched_priority: AtomicI32::new(0),
+            sched_reset_on_fork: AtomicBool::new(false),
+            need_resched: AtomicBool::new(false),
         });
         // info!("in new");
         // task_control_block.get_trap_cx().set_arg_nth(0, user_sp); // set a0 to user_sp
@@ -413,42 +585,88 @@ impl TaskControlBlock {
         waker.as_ref().unwrap().wake_by_ref();
     }
 
-    /// 
-    pub fn exec<T: Reader + ?Sized>(self: &Arc<Self>, elf: &xmas_elf::ElfFile<'_, T>, elf_file: Option<Arc<dyn File>>, argv: Vec<String>, envp: Vec<String>) ->
-        Result<(), SysError> {
+    /// kill the other threads of the group for an execve and wait until they are gone.
+    /// They may be blocked in a syscall holding pointers into the old address space,
+    /// so they have to leave it on their own before the space can be dropped.
+    /// Fail with EAGAIN when the group is exiting or another thread is already in execve
+    pub async fn de_thread(self: &Arc<Self>) -> Result<(), SysError> {
+        {
+            let mut tg = self.thread_group.lock();
+            if tg.group_exiting || tg.group_exec {
+                return Err(SysError::EAGAIN);
+            }
+            tg.group_exec = true;
+            for task in tg.iter() {
+                if task.tid() == self.tid() || task.is_zombie() {
+                    continue;
+                }
+                task.recv_sigs(SigInfo { si_pid: Some(self.pid()), ..SigInfo::new(SIGKILL, SigInfo::KERNEL) });
+                // a stopped thread has to run to die
+                if task.is_stopped() && !task.in_ptrace_stop() {
+                    task.set_running();
+                    task.wake();
+                }
+            }
+        }
+        // a zombie may still be on its way out on another hart
+        loop {
+            let dying = self.with_thread_group(|tg| {
+                tg.iter().any(|t| t.tid() != self.tid() && !t.is_zombie())
+            });
+            if !dying && harts_running(Some(mm_key(self))) == 0 {
+                break;
+            }
+            yield_now().await;
+        }
+        let mut tg = self.thread_group.lock();
+        // the dead threads go now, with the signals still pending on them.
+        // A dead leader stays for the parent to reap, and must not take the signals of the process
+        let dead: Vec<_> = tg.iter().filter(|t| t.tid() != self.tid()).collect();
+        for thread in dead {
+            if thread.is_leader() {
+                thread.with_mut_sig_manager(|s| s.blocked_sigs = SigSet::all());
+                continue;
+            }
+            tg.remove(&thread);
+            TASK_MANAGER.remove_task(thread.tid());
+        }
+        tg.group_exec = false;
+        Ok(())
+    }
+
+    /// replace the program of the process by `image`,
+    /// the other threads must be gone already, see `de_thread`
+    pub fn exec(self: &Arc<Self>, image: ExecImage, argv: Vec<String>, envp: Vec<String>) {
         self.mm_release();
-        // memory_set with elf program headers/trampoline/trap context/user stack
-        let (
-            mut vm_space, 
-            mut user_sp, 
-            entry_point, 
-            auxv
-        ) = UserVmSpace::from_elf(&elf, elf_file.clone())?;
+        let ExecImage { mut vm_space, user_sp, entry_point, auxv, elf_file } = image;
 
+        // a set-user-id or set-group-id file changes the effective ids
+        if let Some(inode) = elf_file.as_ref().and_then(|f| f.inode()) {
+            let no_new_privs = self.no_new_privs();
+            self.with_mut_cred(|cred| cred.exec(inode.inode_inner(), no_new_privs));
+        }
         // update the executing elf file
+        *self.comm.lock() = comm_of(&elf_file);
         *self.elf.lock() = elf_file;
-        // NOTE: should do termination before switching page table, so that other
-        // threads will trap in by page fault and be handled by handle_zombie
-        // info!("terminating all threads except main");
-        self.with_thread_group(|thread_group|{
-            for thread in thread_group.iter() {
-                if thread.tid() != self.tid() {
-                    thread.do_exit(0);
-                }
-            }
-        });
-        
+        self.set_dumpable(true);
+
         // change hart page table
         vm_space.enable();
 
         // alloc user resource for main thread again since vm_space has changed
         // push argument to user_stack
-        let (new_user_sp, argc, argv, envp) = user_stack_init(&mut vm_space, user_sp, argv, envp, auxv);
-        user_sp = new_user_sp;
+        let (user_sp, argc, argv, envp) = user_stack_init(&mut vm_space, user_sp, argv, envp, auxv);
 
-        // substitute memory_set
-        // self.with_mut_vm_space(|m| *m = vm_space);
-        *self.vm_space.exclusive_access() = new_shared(vm_space);
+        // substitute memory_set, the old one is dropped with the last reference,
+        // which the dead leader hands over as well
+        let vm_space = new_shared(vm_space);
+        *self.vm_space.exclusive_access() = vm_space.clone();
+        if !self.is_leader() {
+            let leader = self.get_leader();
+            *leader.vm_space.exclusive_access() = vm_space;
+            leader.vm_space.release();
+        }
+        set_running_mm(mm_key(self));
         // close fd on exec
         self.with_mut_fd_table(|fd_table|fd_table.do_close_on_exec());
 
@@ -456,7 +674,7 @@ impl TaskControlBlock {
         self.with_mut_sig_manager(|sig_manager| sig_manager.reset_on_exec());
 
         // initialize trap_cx
-        let mut trap_cx = TrapContext::app_init_context(
+        let trap_cx = TrapContext::app_init_context(
             entry_point,
             user_sp,
             argc,
@@ -466,7 +684,6 @@ impl TaskControlBlock {
         //trap_cx.set_arg_nth(0, user_sp); // set a0 to user_sp
         log::debug!("entry: {:x}, argc: {:x}, argv: {:x}, envp: {:x}, sp: {:x}", entry_point, trap_cx.arg_nth(0), trap_cx.arg_nth(1), trap_cx.arg_nth(2), trap_cx.sp());
         *self.get_trap_cx() = trap_cx;
-        Ok(())
     }
     /// 
     pub fn fork(self: &Arc<TaskControlBlock>, flag: CloneFlags) -> Arc<TaskControlBlock> {
@@ -474,14 +691,23 @@ impl TaskControlBlock {
         let tid_handle = tid_alloc();
         // ---- hold parent PCB lock
         let status = SpinNoIrqLock::new(self.get_status());
+        // SCHED_RESET_ON_FORK
+        let reset_sched = self.sched_reset_on_fork();
         let leader;
         let is_leader;
         let parent;
         let children;
         let thread_group;
         let pgid;
+        let session;
         let cwd;
+        let umask;
         let itimers;
+        let rlimit_data;
+        let rlimit_core;
+        let rlimit_stack;
+        let rlimit_memlock;
+        let cred;
         let elf;
         let sig_manager = new_shared(
             match flag.contains(CloneFlags::SIGHAND) {
@@ -496,8 +722,15 @@ impl TaskControlBlock {
             children = self.children.clone();
             thread_group = self.thread_group.clone();
             pgid = self.pgid.clone();
+            session = self.session.clone();
             cwd = self.cwd.clone();
+            umask = self.umask.clone();
             itimers = self.itimers.clone();
+            rlimit_data = self.rlimit_data.clone();
+            rlimit_core = self.rlimit_core.clone();
+            rlimit_stack = self.rlimit_stack.clone();
+            rlimit_memlock = self.rlimit_memlock.clone();
+            cred = self.cred.clone();
             elf = self.elf.clone();
         } else {
             is_leader = true;
@@ -506,8 +739,15 @@ impl TaskControlBlock {
             children = new_shared(BTreeMap::new());
             thread_group = new_shared(ThreadGroup::new());
             pgid = new_shared(*self.pgid.lock());
+            session = new_shared(self.session());
             cwd = new_shared(self.cwd());
+            umask = new_shared(self.umask());
             itimers = new_shared([ITimer::ZERO; 3]);
+            rlimit_data = new_shared(*self.rlimit_data.lock());
+            rlimit_core = new_shared(*self.rlimit_core.lock());
+            rlimit_stack = new_shared(*self.rlimit_stack.lock());
+            rlimit_memlock = new_shared(*self.rlimit_memlock.lock());
+            cred = new_shared(self.cred.lock().clone());
             elf = new_shared(self.elf.lock().clone())
         }
         let vm_space;
@@ -518,7 +758,7 @@ impl TaskControlBlock {
             vm_space = UPSafeCell::new(new_shared(
                 self.with_mut_vm_space(
                     |vm| 
-                        UserVmSpace::from_existed(vm)
+                        UserVmSpace::from_existed(vm, mm_key(self))
                 )
             ));
         }
@@ -528,14 +768,25 @@ impl TaskControlBlock {
         } else {
             new_shared(self.fd_table.lock().clone())
         };
+        // the child starts from the parent's current float registers,
+        // which may still be live in the hardware
+        let trap_context = {
+            let parent_cx = self.get_trap_cx();
+            parent_cx.fx_sync();
+            let mut cx = parent_cx.clone();
+            cx.fx_invalidate();
+            cx
+        };
         let task_control_block = Arc::new(TaskControlBlock {
             tid: tid_handle,
             leader,
             is_leader,
-            trap_context: UPSafeCell::new(self.get_trap_cx().clone()),
+            trap_context: UPSafeCell::new(trap_context),
             waker: UPSafeCell::new(None),
             tid_address: UPSafeCell::new(TidAddress::new()),
-            time_recorder: UPSafeCell::new(TimeRecorder::new()),
+            time_recorder: TimeRecorder::new(),
+            vm_stats: VmStats::new(),
+            user_regs: SpinNoIrqLock::new([0; TrapContext::ELF_NGREG]),
             exit_code: AtomicUsize::new(0),
             base_size: AtomicUsize::new(0),
             task_status: status,
@@ -545,16 +796,41 @@ impl TaskControlBlock {
             fd_table,
             thread_group,
             pgid,
+            session,
             sig_manager,
             sig_ucontext_ptr: AtomicUsize::new(0),
             cwd,
+            umask,
             elf,
             itimers,
+            rlimit_data,
+            rlimit_core,
+            rlimit_stack,
+            rlimit_memlock,
+            // a new task is never traced, even if its creator is
+            ptrace: new_shared(PtraceState::new()),
+            comm: new_shared(self.comm.lock().clone()),
+            dumpable: AtomicBool::new(self.dumpable()),
+            no_new_privs: AtomicBool::new(self.no_new_privs()),
+            syscall_trace: AtomicBool::new(self.syscall_trace()),
+            current_syscall: AtomicUsize::new(NO_SYSCALL),
+            sleep_restart: new_shared(None),
+            syscall_filter: new_shared(*self.syscall_filter.lock()),
+            cred,
             robust: UPSafeCell::new(UserPtrRaw::new(null_mut())),
             #[cfg(feature = "smp")]
             sche_entity: new_shared(TaskLoadTracker::new()),
-            cpu_allowed: AtomicUsize::new(15),
-            processor_id: AtomicUsize::new(self.processor_id())
+            // the affinity is always inherited, like linux
+            cpu_allowed: AtomicUsize::new(self.cpu_allowed()),
+            processor_id: AtomicUsize::new(self.processor_id()),
+            on_cpu: AtomicBool::new(false),
+            yield_count: AtomicUsize::new(0),
+            // kept across exec as well, which leaves it alone
+            nice: AtomicI32::new(if reset_sched { self.nice().max(0) } else { self.nice() }),
+            sched_policy: AtomicI32::new(if reset_sched { SCHED_OTHER } else { self.sched_policy() }),
+            sched_priority: AtomicI32::new(if reset_sched { 0 } else { self.sched_priority() }),
+            sched_reset_on_fork: AtomicBool::new(false),
+            need_resched: AtomicBool::new(false),
         });
         // add child except when creating a thread
         if !flag.contains(CloneFlags::THREAD) {
@@ -575,22 +851,8 @@ impl TaskControlBlock {
     }
 
     fn futex_wake(&self, addr: usize, shared: bool, vm: &mut UserVmSpace) {
-        let key = if shared {
-            if let Some(paddr) = 
-                translate_uva_checked(
-                    vm, 
-                    VirtAddr::from(addr), 
-                    PageFaultAccessType::WRITE
-                ) {
-                FutexHashKey::Shared { paddr }
-            } else {
-                return;
-            }
-        } else {
-            FutexHashKey::Private {
-                mm: self.get_raw_vm_ptr(),
-                vaddr: addr.into()
-            }
+        let Ok((key, _pin)) = futex_key(vm, self.get_raw_vm_ptr(), addr, !shared) else {
+            return;
         };
 
         if futex_manager().wake(&key, 1).is_ok() {
@@ -693,7 +955,7 @@ impl TaskControlBlock {
                 if let Some(child_tid) = child_tid_ptr.ensure_write(&mut self.get_vm_space().lock()) {
                     child_tid.to_mut().store(0, Ordering::Release);
                 }
-                self.futex_wake(addr, false, &mut self.get_vm_space().lock());
+                // a shared key falls back to the private one on private memory
                 self.futex_wake(addr, true, &mut self.get_vm_space().lock());
                 self.tid_address().clear_child_tid = None;
             }
@@ -707,7 +969,17 @@ impl TaskControlBlock {
             return;
         }
         if self.tid() == INITPROC_PID {
+            #[cfg(not(feature = "init_exit_poweroff"))]
             panic!("initproc exited");
+            #[cfg(feature = "init_exit_poweroff")]
+            {
+                log::info!("[do_exit] initproc exited, powering off");
+                self.set_zombie();
+                schedule::spawn_kernel_task(async {
+                    crate::executor::shutdown::power_off(None).await;
+                });
+                return;
+            }
         }
         log::info!("[do_exit] task {} exiting", self.tid());
         self.exit_code.store(code, Ordering::Release);
@@ -720,6 +992,7 @@ impl TaskControlBlock {
         }
         drop(tg);
         self.mm_release();
+        self.ptrace_release();
         self.set_zombie();
         
         if is_last {
@@ -731,7 +1004,7 @@ impl TaskControlBlock {
                 for child in children.values() {
                     if child.is_zombie() {
                         initproc.recv_sigs_process_level(
-                            SigInfo { si_signo: SIGCHLD, si_code: SigInfo::CLD_EXITED, si_pid: None }
+                            SigInfo::new(SIGCHLD, SigInfo::CLD_EXITED)
                         );
                     }
                     *child.parent.lock() = Some(Arc::downgrade(initproc));
@@ -744,21 +1017,27 @@ impl TaskControlBlock {
         }
     }
 
-    pub fn do_group_exit(self: &Arc<Self>, mut code: usize) {
+    /// start the exit of the whole group with `code` and send SIGKILL to the other threads,
+    /// return false if the group is exiting already
+    pub fn zap_threads(self: &Arc<Self>, code: usize) -> bool {
         let mut tg = self.thread_group.lock();
         if tg.group_exiting {
-            code = tg.group_exit_code;
-        } else {
-            tg.group_exiting = true;
-            tg.group_exit_code = code;
-            for task in tg.iter() {
-                if task.tid() == self.tid() || task.is_zombie() {
-                    continue;
-                }
-                task.recv_sigs(SigInfo { si_signo: SIGKILL, si_code: SigInfo::KERNEL, si_pid: Some(self.pid()) });
+            return false;
+        }
+        tg.group_exiting = true;
+        tg.group_exit_code = code;
+        for task in tg.iter() {
+            if task.tid() == self.tid() || task.is_zombie() {
+                continue;
             }
+            task.recv_sigs(SigInfo { si_pid: Some(self.pid()), ..SigInfo::new(SIGKILL, SigInfo::KERNEL) });
         }
-        drop(tg);
+        true
+    }
+
+    pub fn do_group_exit(self: &Arc<Self>, code: usize) {
+        self.zap_threads(code);
+        let code = self.with_thread_group(|tg| tg.group_exit_code);
         self.do_exit(code)
     }
 
@@ -804,7 +1083,7 @@ impl TaskControlBlock {
             for child in children.values() {
                 if child.is_zombie() {
                     initproc.recv_sigs_process_level(
-                        SigInfo { si_signo: SIGCHLD, si_code: SigInfo::CLD_EXITED, si_pid: None }
+                        SigInfo::new(SIGCHLD, SigInfo::CLD_EXITED)
                     );
                 }
                 *child.parent.lock() = Some(Arc::downgrade(initproc));
@@ -833,34 +1112,33 @@ impl TaskControlBlock {
 
 /// caculate the process time of a task
 impl TaskControlBlock {
-    /// get the sum of time pair of all threads in the process 
+    /// get the sum of time pair of all threads in the process, the exited ones included
     pub fn process_time_pair(&self) ->  (Duration, Duration) {
         self.with_thread_group(|thread_group| -> (Duration, Duration) {
             thread_group.iter()
             .map(|thread| thread.time_recorder().time_pair())
-            .reduce(|(user_time_one,kernel_time_one),(user_time_two, kernel_time_two)| {
+            .fold(thread_group.exited_time, |(user_time_one,kernel_time_one),(user_time_two, kernel_time_two)| {
                 (user_time_one + user_time_two, kernel_time_one + kernel_time_two)
             })
-            .unwrap()
+        })
+    }
+    /// the memory events of all threads in the process
+    pub fn process_vm_events(&self) -> VmEventCounts {
+        self.with_thread_group(|thread_group| {
+            thread_group.iter().fold(thread_group.exited_vm_events, |mut counts, thread| {
+                add_counts(&mut counts, &thread.vm_stats.counts());
+                counts
+            })
         })
     }
     /// get the sum of user time of all threads in the process
     pub fn process_user_time(&self) -> Duration {
-        self.with_thread_group(|thread_group| -> Duration {
-            thread_group.iter()
-            .map(|thread| thread.time_recorder().user_time())
-            .reduce(|time_one, time_two| time_one + time_two)
-            .unwrap()
-        })
+        self.process_time_pair().0
     }
     /// get the sum of cpu_time of all threads in the process
     pub fn process_cpu_time(&self) -> Duration {
-        self.with_thread_group(|thread_group| -> Duration{
-            thread_group.iter()
-            .map(|thread| thread.time_recorder().processor_time())
-            .reduce(|time_one, time_two| time_one + time_two)
-            .unwrap()
-        })
+        let (user_time, kernel_time) = self.process_time_pair();
+        user_time + kernel_time
     }
 }
 
@@ -882,36 +1160,3 @@ pub enum TaskStatus {
     /// task is waiting for an event but cannot be interrupt
     UnInterruptable,
 }
-
-bitflags! {
-    #[repr(C)]
-    pub struct CpuMask: usize {
-        const CPU0 = 0b0001;
-        const CPU1 = 0b0010;
-        const CPU2 = 0b0100;
-        const CPU3 = 0b1000;
-        const CPU_ALL = 0b1111; 
-    }
-}
-/// a cpum mask converter
-pub fn get_cpu_mask(id: usize ) -> usize {
-    match id {
-        0 => 1,
-        1 => 2,
-        2 => 4,
-        3 => 8,
-        4 => 15,
-        _ => 0,
-    }
-}
-/// turn a cpu mask to id
-pub fn turn_cpu_mask_to_id(mask: usize) -> usize {
-    match mask {
-        1 => 0,
-        2 => 1,
-        4 => 2,
-        8 => 3,
-        15 => 4,
-        _ => 0,
-    }
-}
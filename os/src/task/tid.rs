@@ -1,5 +1,4 @@
 //!Implementation of [`PidAllocator`]
-use crate::sync::UPSafeCell;
 use alloc::vec::Vec;
 use lazy_static::*;
 use crate::sync::mutex::SpinNoIrqLock;
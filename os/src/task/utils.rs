@@ -130,26 +130,27 @@ pub fn user_stack_init(
 ) -> (usize, usize, usize, usize) {
     let _sum_guard = SumGuard::new();
     let platfrom = "RISC-V64";
-    let rand_bytes = "Chronix Is Here"; // 15 + 1 char for 16 bytes
-    let rand_size = 0usize;
+    // AT_RANDOM always comes from the pool, the pad only when the layout is randomized
+    let rand_bytes = vm::aslr::at_random();
+    let rand_size = vm::aslr::stack_pad();
 
     // calculate the total size from stack buttom to top
     let mut new_sp = sp;
+    // random pad
+    new_sp -= rand_size;
     // args string end with '/0'
     new_sp -= argv.iter().map(|s|s.as_bytes().len() + 1).sum::<usize>();
     let program_name_ptr = new_sp;
     // env strings end with '/0'
     new_sp -= envp.iter().map(|s|s.as_bytes().len() + 1).sum::<usize>();
-    // random aligned (use 0 aligned here)
-    new_sp -= rand_size;
     // platfrom string end with '/0'
     new_sp -= platfrom.as_bytes().len() + 1;
     // random 16 bytes
-    new_sp -= rand_bytes.as_bytes().len() + 1;
+    new_sp -= rand_bytes.len();
     // aligned to 16
     new_sp = (new_sp - 1) & !0xf;
-    // auxv vec and a null auxv
-    new_sp -= (auxv.len() + 1) * core::mem::size_of::<AuxHeader>();
+    // auxv vec, AT_EXECFN, AT_RANDOM and a null auxv
+    new_sp -= (auxv.len() + 3) * core::mem::size_of::<AuxHeader>();
     // envp
     new_sp -= (envp.len() + 1) * core::mem::size_of::<usize>();
     // argv
@@ -163,19 +164,22 @@ pub fn user_stack_init(
         let _ = vm_space.handle_page_fault(VirtAddr::from(sp - PAGE_SIZE * i), PageFaultAccessType::WRITE);
     }
 
-    // push the data into stack in the order mention above
-    let mut new_sp = sp;
+    // push the data into stack in the order mention above,
+    // the pad goes first so the strings move with it
+    let mut new_sp = sp - rand_size;
     // env arg strings
     let env_ptrs: Vec<usize> = envp.iter().rev().map(|s| push_str(&mut new_sp, s)).collect();
     let arg_ptrs: Vec<usize> = argv.iter().rev().map(|s| push_str(&mut new_sp, s)).collect();
     // platfrom, rand bytes, align bytes
-    new_sp -= rand_size;
     push_str(&mut new_sp, platfrom);
-    push_str(&mut new_sp, rand_bytes);
+    new_sp -= rand_bytes.len();
+    let rand_ptr = new_sp;
+    unsafe { core::ptr::copy_nonoverlapping(rand_bytes.as_ptr(), rand_ptr as *mut u8, rand_bytes.len()) };
     align16(&mut new_sp);
     // aux
     push_aux(&mut new_sp, &AuxHeader::new(AT_NULL, 0));
     push_aux(&mut new_sp, &AuxHeader::new(AT_EXECFN, program_name_ptr));
+    push_aux(&mut new_sp, &AuxHeader::new(AT_RANDOM, rand_ptr));
     for aux in auxv.into_iter().rev() {
         push_aux(&mut new_sp, &aux);
     }
@@ -3,11 +3,6 @@
 //! or per-process if it measures time only within a single process.
 //! more info refer to linux manual
 
-use core::time::Duration;
-
-/// the number of global clocks we need to record (no related to task)
-pub const SUPPORT_CLOCK_NUM: usize = 2;
-
 /// A settable system-wide clock that measures real (i.e., wall-clock) time.  
 /// Setting this clock requires appropriate privileges.  
 pub const CLOCK_REALTIME: usize = 0;
@@ -36,7 +31,4 @@ pub const CLOCK_REALTIME_COARSE: usize = 5;
 
 /// A faster but less precise version of CLOCK_MONOTONIC. 
 /// Use when you need very fast, but not fine-grained timestamps.
-pub const CLOCK_MONOTONIC_COARSE: usize = 6;
-
-/// global clocks
-pub static mut CLOCK_DEVIATION: [Duration; SUPPORT_CLOCK_NUM] = [Duration::ZERO; SUPPORT_CLOCK_NUM];
\ No newline at end of file
+pub const CLOCK_MONOTONIC_COARSE: usize = 6;
\ No newline at end of file
@@ -118,13 +118,13 @@ impl Tms {
             cstime: 0,
         }
     }
-    /// new from a TimeRecorder
-    pub fn from_time_recorder(time_recorder: &super::recoder::TimeRecorder) -> Self {
+    /// new from the (user, kernel) time of a process and of its reaped children
+    pub fn from_times((utime, stime): (Duration, Duration), (cutime, cstime): (Duration, Duration)) -> Self {
         Self {
-            utime: time_recorder.user_time().as_micros() as usize,
-            stime: time_recorder.kernel_time().as_micros() as usize,
-            cutime: time_recorder.child_time_pair().0.as_micros() as usize,
-            cstime: time_recorder.child_time_pair().1.as_micros() as usize,
+            utime: utime.as_micros() as usize,
+            stime: stime.as_micros() as usize,
+            cutime: cutime.as_micros() as usize,
+            cstime: cstime.as_micros() as usize,
         }
     }
 }
\ No newline at end of file
@@ -10,7 +10,9 @@ pub mod timer;
 /// time-limited task wrapper
 pub mod timed_task;
 pub mod clock;
-use core::time::Duration;
+/// per-hart skew of the timer counters
+pub mod skew;
+use core::{sync::atomic::{AtomicU64, Ordering}, time::Duration};
 
 const TICKS_PER_SEC: usize = 100;
 const MSEC_PER_SEC: usize = 1_000;
@@ -22,41 +24,75 @@ pub fn get_current_time() -> usize {
     Timer::read()
 }
 
+/// fixed point shift of [`cyc2ns_mult`]
+const CYC2NS_SHIFT: u32 = 32;
+/// cached `(NSEC_PER_SEC << CYC2NS_SHIFT) / freq`, 0 if not computed yet
+static CYC2NS_MULT: AtomicU64 = AtomicU64::new(0);
+
+/// multiplier turning timer cycles into nanoseconds with a single
+/// multiply and shift, computed once from the timer frequency
+#[inline(always)]
+fn cyc2ns_mult() -> u64 {
+    let mult = CYC2NS_MULT.load(Ordering::Relaxed);
+    if mult != 0 {
+        return mult;
+    }
+    let freq = Timer::get_timer_freq() as u128;
+    let mult = (((NSEC_PER_SEC as u128) << CYC2NS_SHIFT) / freq) as u64;
+    CYC2NS_MULT.store(mult, Ordering::Relaxed);
+    mult
+}
+
+/// convert timer cycles into nanoseconds
+#[inline(always)]
+pub fn cycles_to_ns(cycles: usize) -> usize {
+    ((cycles as u128 * cyc2ns_mult() as u128) >> CYC2NS_SHIFT) as usize
+}
+
 /// get current time in seconds
 pub fn get_current_time_sec() -> usize {
-    let cycles = Timer::read() as u128;
-    let freq = Timer::get_timer_freq() as u128;
-    (cycles / freq) as usize
+    get_current_time_ns() / NSEC_PER_SEC
 }
 
 /// get current time in milliseconds
 pub fn get_current_time_ms() -> usize {
-    let cycles = Timer::read() as u128;
-    let freq = Timer::get_timer_freq() as u128;
-    ((cycles * MSEC_PER_SEC as u128) / freq) as usize
+    get_current_time_ns() / (NSEC_PER_SEC / MSEC_PER_SEC)
 }
 
 /// get current time in microseconds
 pub fn get_current_time_us() -> usize {
-    let cycles = Timer::read() as u128;
-    let freq = Timer::get_timer_freq() as u128;
-    ((cycles * USEC_PER_SEC as u128) / freq) as usize
+    get_current_time_ns() / (NSEC_PER_SEC / USEC_PER_SEC)
 }
-/// get current time in nanoseconds
+/// get current time in nanoseconds, on the boot hart counter and never going backwards
 pub fn get_current_time_ns() -> usize {
-    let cycles = Timer::read() as u128;
-    let freq = Timer::get_timer_freq() as u128;
-    ((cycles * NSEC_PER_SEC as u128) / freq) as usize
+    skew::clamp_ns(cycles_to_ns(skew::monotonic_cycles()))
 }
 
 /// get current time in duration
 pub fn get_current_time_duration() -> Duration {
-    let mut cycles = Timer::read() as u64;
-    let freq = Timer::get_timer_freq() as u64;
-    let secs = cycles / freq;
-    cycles = cycles % freq;
-    let nanos =((cycles as u128 * NSEC_PER_SEC as u128) / freq as u128) as u32;
-    Duration::new(secs, nanos)
+    Duration::from_nanos(get_current_time_ns() as u64)
+}
+
+/// `duration` in the clock ticks of user space, as many per second as AT_CLKTCK says
+pub fn duration_to_ticks(duration: Duration) -> usize {
+    (duration.as_nanos() / (NSEC_PER_SEC / TICKS_PER_SEC) as u128) as usize
+}
+
+/// wall clock time at boot (monotonic time 0) in nanoseconds since the epoch,
+/// CLOCK_REALTIME is computed as this plus the monotonic time
+static BOOT_WALL_TIME_NS: AtomicU64 = AtomicU64::new(0);
+
+/// get current wall clock time (CLOCK_REALTIME) in duration
+pub fn get_realtime_duration() -> Duration {
+    Duration::from_nanos(BOOT_WALL_TIME_NS.load(Ordering::Relaxed) + get_current_time_ns() as u64)
+}
+
+/// set current wall clock time (CLOCK_REALTIME) by moving the boot wall time,
+/// the monotonic clock is not affected
+pub fn set_realtime(now: Duration) {
+    let mono = get_current_time_ns() as u64;
+    let boot = (now.as_nanos() as u64).saturating_sub(mono);
+    BOOT_WALL_TIME_NS.store(boot, Ordering::Relaxed);
 }
 
 /// set the next timer interrupt
@@ -1,91 +1,127 @@
-use core::time::Duration;
+use core::{sync::atomic::{AtomicBool, AtomicU64, Ordering}, time::Duration};
 
-use hal::println;
-
-use super::get_current_time_duration;
+use super::get_current_time_ns;
 /// Time recoder for events in tasks and kernel functions
+///
+/// The running task charges the time since the last charge point to its user
+/// or kernel counter on every trap, trap return, context switch and timer tick,
+/// so the counters are never more than a tick behind. Only the hart running the
+/// task writes the counters, everyone may read them without a lock.
 /// Todo:need to distinguish time for calculating cpu usage, time for IO or NET, time for sleeping, etc.
 pub struct TimeRecorder {
-    /// user time duration
-    user_time: Duration,
-    /// kernel time duration
-    kernel_time: Duration,
-    /// kernel time start
-    kernel_start: Duration,
-    /// user time start
-    user_start: Duration,
+    /// user time in nanoseconds
+    user_ns: AtomicU64,
+    /// kernel time in nanoseconds
+    kernel_ns: AtomicU64,
+    /// the last time charged to a counter
+    last_ns: AtomicU64,
+    /// whether the task runs in user mode since the last charge point
+    in_user: AtomicBool,
     /// for a parent task need to record the child task's time
-    /// child user time Duration
-    child_user_time: Duration,
-    /// child kernel time Duration
-    child_kernel_time: Duration,
+    /// child user time in nanoseconds
+    child_user_ns: AtomicU64,
+    /// child kernel time in nanoseconds
+    child_kernel_ns: AtomicU64,
+    /// time run since the task was last switched in, measured against the time slice
+    slice_ns: AtomicU64,
+    /// times the task was preempted at the end of its time slice
+    nivcsw: AtomicU64,
 }
 
 impl TimeRecorder {
     /// new cosnt TimeRecorder
     pub const fn new() -> Self {
         Self {
-            user_time: Duration::ZERO,
-            kernel_time: Duration::ZERO,
-            kernel_start: Duration::ZERO,
-            user_start: Duration::ZERO,
-            child_user_time: Duration::ZERO,
-            child_kernel_time: Duration::ZERO,
+            user_ns: AtomicU64::new(0),
+            kernel_ns: AtomicU64::new(0),
+            last_ns: AtomicU64::new(0),
+            in_user: AtomicBool::new(false),
+            child_user_ns: AtomicU64::new(0),
+            child_kernel_ns: AtomicU64::new(0),
+            slice_ns: AtomicU64::new(0),
+            nivcsw: AtomicU64::new(0),
         }
     }
     /// return a pair for user and kernel time
     pub fn time_pair(&self) -> (Duration, Duration) {
-        (self.user_time, self.kernel_time)
+        (self.user_time(), self.kernel_time())
     }
     /// return a pair for child user and kernel time
     pub fn child_time_pair(&self) -> (Duration, Duration) {
-        (self.child_user_time, self.child_kernel_time)
+        (
+            Duration::from_nanos(self.child_user_ns.load(Ordering::Relaxed)),
+            Duration::from_nanos(self.child_kernel_ns.load(Ordering::Relaxed)),
+        )
     }
-    
+
     #[inline]
     /// user time method
     pub fn user_time(&self) -> Duration {
-        self.user_time
+        Duration::from_nanos(self.user_ns.load(Ordering::Relaxed))
     }
     #[inline]
     /// kernel time method
     pub fn kernel_time(&self) -> Duration {
-        self.kernel_time
-    } 
+        Duration::from_nanos(self.kernel_ns.load(Ordering::Relaxed))
+    }
     /// time for cacluating cpu usage
     pub fn processor_time(&self) -> Duration {
-        self.kernel_time + self.user_time
+        self.kernel_time() + self.user_time()
     }
     /// update user time start
-    pub fn update_user_start(&mut self, user_start: Duration) {
-        self.user_start = user_start;
+    pub fn update_user_start(&self, user_start: Duration) {
+        self.last_ns.store(user_start.as_nanos() as u64, Ordering::Relaxed);
     }
-    /// for parent task to update child task's time
-    pub fn update_child_time(&mut self, (child_user_time, child_kernel_time): (Duration, Duration)) {
-        self.child_user_time += child_user_time;
-        self.child_kernel_time += child_kernel_time;
+    /// for parent task to update child task's time, once per reaped child
+    pub fn update_child_time(&self, (child_user_time, child_kernel_time): (Duration, Duration)) {
+        self.child_user_ns.fetch_add(child_user_time.as_nanos() as u64, Ordering::Relaxed);
+        self.child_kernel_ns.fetch_add(child_kernel_time.as_nanos() as u64, Ordering::Relaxed);
+    }
+    /// charge the time since the last charge point to the current mode
+    fn charge(&self) {
+        let now = get_current_time_ns() as u64;
+        let slice = now.saturating_sub(self.last_ns.swap(now, Ordering::Relaxed));
+        self.slice_ns.fetch_add(slice, Ordering::Relaxed);
+        if self.in_user.load(Ordering::Relaxed) {
+            self.user_ns.fetch_add(slice, Ordering::Relaxed);
+        } else {
+            self.kernel_ns.fetch_add(slice, Ordering::Relaxed);
+        }
     }
-    /// for switch_to_current_task recording 
-    pub fn record_switch_in(&mut self) {
-        let current_time = get_current_time_duration();
-        self.kernel_start = current_time;
+    /// for switch_to_current_task recording
+    pub fn record_switch_in(&self) {
+        self.in_user.store(false, Ordering::Relaxed);
+        self.slice_ns.store(0, Ordering::Relaxed);
+        self.last_ns.store(get_current_time_ns() as u64, Ordering::Relaxed);
     }
     /// for switch_out_current_task recording
-    pub fn record_switch_out(&mut self) {
-        let current_time = get_current_time_duration();
-        let kernel_time_slice = current_time - self.kernel_start;
-        self.kernel_time += kernel_time_slice;
+    pub fn record_switch_out(&self) {
+        self.charge();
     }
     /// for trap recording: from user to kernel
-    pub fn record_trap(&mut self){
-        let current_time = get_current_time_duration();
-        self.kernel_start = current_time;
-        self.user_time += current_time - self.user_start;
+    pub fn record_trap(&self){
+        self.charge();
+        self.in_user.store(false, Ordering::Relaxed);
     }
     /// for trap_return recording: form kernel to user
-    pub fn record_trap_return(&mut self){
-        let current_time = get_current_time_duration();
-        self.kernel_time += current_time - self.user_start;    
-        self.user_start = current_time;
+    pub fn record_trap_return(&self){
+        self.charge();
+        self.in_user.store(true, Ordering::Relaxed);
     }
-}
\ No newline at end of file
+    /// for the timer interrupt: charge the slice so far to the mode the task was interrupted in
+    pub fn record_tick(&self) {
+        self.charge();
+    }
+    /// time run in the current slice, as of the last charge point
+    pub fn slice_used(&self) -> Duration {
+        Duration::from_nanos(self.slice_ns.load(Ordering::Relaxed))
+    }
+    /// for preemption: the task gives up the hart with its slice used up
+    pub fn record_preempt(&self) {
+        self.nivcsw.fetch_add(1, Ordering::Relaxed);
+    }
+    /// involuntary context switches, the preemptions at the end of a slice
+    pub fn nivcsw(&self) -> usize {
+        self.nivcsw.load(Ordering::Relaxed) as usize
+    }
+}
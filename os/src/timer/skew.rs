@@ -0,0 +1,104 @@
+//! Per-hart skew of the timer counters
+//!
+//! The counters of the harts need not agree, and monotonic time read on one
+//! hart and then on another could go backwards. Every hart started after the
+//! boot hart measures how far its counter is from the boot hart's at boot, and
+//! monotonic time adds that offset to the local counter. A per-hart high water
+//! mark catches whatever error is left.
+
+use core::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
+
+use hal::{board::MAX_PROCESSORS, instruction::{Instruction, InstructionHal}, timer::{Timer, TimerHal}};
+use log::{info, warn};
+
+/// request/reply rounds of a hart, the one with the shortest round trip is kept
+const ROUNDS: usize = 16;
+/// how long the boot hart answers the others, in milliseconds
+const SERVE_MS: usize = 50;
+/// how long a hart waits for one reply, in milliseconds
+const REPLY_MS: usize = 1;
+
+/// cycles added to the counter of each hart to match the boot hart
+static OFFSET: [AtomicI64; MAX_PROCESSORS] = [const { AtomicI64::new(0) }; MAX_PROCESSORS];
+/// the round a hart asks the boot hart to answer, 0 for none
+static REQUEST: [AtomicUsize; MAX_PROCESSORS] = [const { AtomicUsize::new(0) }; MAX_PROCESSORS];
+/// the boot hart counter read in answer to the last request, 0 until answered
+static REPLY: [AtomicU64; MAX_PROCESSORS] = [const { AtomicU64::new(0) }; MAX_PROCESSORS];
+/// whether a hart is done measuring, answered or not
+static DONE: [AtomicBool; MAX_PROCESSORS] = [const { AtomicBool::new(false) }; MAX_PROCESSORS];
+/// the highest monotonic time in nanoseconds handed out on each hart
+static LAST_NS: [AtomicU64; MAX_PROCESSORS] = [const { AtomicU64::new(0) }; MAX_PROCESSORS];
+
+/// answer the harts measuring their offset, run by the boot hart right after starting them,
+/// returns once all of them are done or after [`SERVE_MS`] for the harts that never came up
+pub fn serve(boot: usize) {
+    DONE[boot].store(true, Ordering::Release);
+    let deadline = Timer::read() + Timer::get_timer_freq() / 1000 * SERVE_MS;
+    let mut answered = [0usize; MAX_PROCESSORS];
+    while Timer::read() < deadline && !DONE.iter().all(|done| done.load(Ordering::Acquire)) {
+        for hart in 0..MAX_PROCESSORS {
+            let round = REQUEST[hart].load(Ordering::Acquire);
+            if round != answered[hart] {
+                REPLY[hart].store(Timer::read() as u64, Ordering::Release);
+                answered[hart] = round;
+            }
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// measure the offset of the counter of `hart` against the boot hart,
+/// run by every hart but the boot hart as it starts
+pub fn calibrate(hart: usize) {
+    let wait = Timer::get_timer_freq() / 1000 * REPLY_MS;
+    // (round trip, offset) of the best round so far
+    let mut best: Option<(usize, i64)> = None;
+    for round in 1..=ROUNDS {
+        REPLY[hart].store(0, Ordering::Relaxed);
+        let sent = Timer::read();
+        REQUEST[hart].store(round, Ordering::Release);
+        let reply = loop {
+            let reply = REPLY[hart].load(Ordering::Acquire);
+            if reply != 0 {
+                break Some(reply);
+            }
+            if Timer::read() > sent + wait {
+                break None;
+            }
+            core::hint::spin_loop();
+        };
+        let received = Timer::read();
+        let Some(reply) = reply else {
+            // the boot hart stopped answering, a late reply could be taken for the next round
+            warn!("[timer] hart {} got no reply in round {}", hart, round);
+            break;
+        };
+        // the boot hart read its counter halfway through the round trip
+        let rtt = received - sent;
+        let offset = reply as i64 - (sent + rtt / 2) as i64;
+        if best.map_or(true, |(best_rtt, _)| rtt < best_rtt) {
+            best = Some((rtt, offset));
+        }
+    }
+    match best {
+        Some((rtt, offset)) => {
+            OFFSET[hart].store(offset, Ordering::Relaxed);
+            info!("[timer] hart {} counter offset {} cycles, round trip {} cycles", hart, offset, rtt);
+        }
+        None => warn!("[timer] hart {} counter left uncalibrated", hart),
+    }
+    DONE[hart].store(true, Ordering::Release);
+}
+
+/// the counter of the current hart moved onto the boot hart's
+pub fn monotonic_cycles() -> usize {
+    let offset = OFFSET[Instruction::get_tp()].load(Ordering::Relaxed);
+    (Timer::read() as i64).saturating_add(offset).max(0) as usize
+}
+
+/// never hand out less than the current hart handed out before, in case the task
+/// moved harts between reading the hart id and the counter
+pub fn clamp_ns(ns: usize) -> usize {
+    let last = LAST_NS[Instruction::get_tp()].fetch_max(ns as u64, Ordering::Relaxed);
+    ns.max(last as usize)
+}
@@ -7,7 +7,7 @@ use log::info;
 use crate::{task::task::TaskControlBlock, utils::suspend_now};
 
 use super::{get_current_time_duration, timer::TIMER_MANAGER};
-use super::timer::{Timer,TimerManager};
+use super::timer::{Timer, TimerHandle};
 
 /// A future wrapper for a timed task.
 pub struct TimedTaskFuture<F: Future + Send + 'static> {
@@ -15,8 +15,8 @@ pub struct TimedTaskFuture<F: Future + Send + 'static> {
     expire: Duration,
     /// the future which use the task
     future: F,
-    /// whether the task is in the timer manager
-    in_manager: bool,
+    /// the timer waking the task, once it is in the timer manager
+    timer: Option<TimerHandle>,
 }
 
 impl <F: Future + Send + 'static> TimedTaskFuture<F> {
@@ -25,7 +25,7 @@ impl <F: Future + Send + 'static> TimedTaskFuture<F> {
         Self {
             expire: get_current_time_duration() + deadline,
             future,
-            in_manager: false,
+            timer: None,
         }
     }
 }
@@ -50,9 +50,8 @@ impl <F: Future + Send + 'static> Future for TimedTaskFuture<F> {
                     Poll::Ready(TimedTaskOutput::TimedOut)
                 }
                 else {
-                    if !this.in_manager {
-                        TIMER_MANAGER.add_timer(Timer::new_waker_timer(this.expire, cx.waker().clone()));
-                        this.in_manager = true;
+                    if this.timer.is_none() {
+                        this.timer = Some(TIMER_MANAGER.add_timer(Timer::new_waker_timer(this.expire, cx.waker().clone())));
                     }
                     Poll::Pending
                 }
@@ -62,6 +61,15 @@ impl <F: Future + Send + 'static> Future for TimedTaskFuture<F> {
     }
 }
 
+impl <F: Future + Send + 'static> Drop for TimedTaskFuture<F> {
+    fn drop(&mut self) {
+        // finished or dropped early, e.g. interrupted by a signal: the timer goes with it
+        if let Some(timer) = self.timer.take() {
+            timer.cancel();
+        }
+    }
+}
+
 struct PendingFuture ;
 
 impl Future for PendingFuture {
@@ -75,16 +83,32 @@ impl Future for PendingFuture {
 pub async fn ksleep(time: Duration) {
     TimedTaskFuture::new(time,PendingFuture{} ).await;
 }
+/// cancels the timer when it goes out of scope
+struct CancelOnDrop(TimerHandle);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+/// a relative sleep cut short by a signal, so a restart of the same syscall sleeps
+/// only what is left instead of the whole request, as restart_block on linux
+#[derive(Clone, Copy)]
+pub struct SleepRestart {
+    /// the syscall which slept
+    pub syscall: usize,
+    /// the user pc after the ecall of it, a restart comes back with the same
+    pub pc: usize,
+    /// when the sleep ends, on the monotonic clock
+    pub deadline: Duration,
+}
+
 /// suspend out time out task future
 pub async fn suspend_timeout(task: &Arc<TaskControlBlock>, time_limit: Duration) -> Duration {
     let expire = get_current_time_duration() + time_limit;
-    TIMER_MANAGER.add_timer(Timer::new_waker_timer(expire, task.waker().clone().unwrap()));
+    // woken early or dropped, the timer must not wake the task later
+    let _timer = CancelOnDrop(TIMER_MANAGER.add_timer(Timer::new_waker_timer(expire, task.waker().clone().unwrap())));
     suspend_now().await;
-    let now = get_current_time_duration();
-    if expire > now {
-        expire - now
-    }
-    else {
-        Duration::ZERO
-    }
+    expire.saturating_sub(get_current_time_duration())
 }
\ No newline at end of file
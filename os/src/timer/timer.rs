@@ -1,10 +1,9 @@
-use core::{cmp::Reverse, sync::atomic::{AtomicUsize, Ordering}, task::Waker, time::Duration};
+use core::{sync::atomic::{AtomicUsize, Ordering}, task::Waker, time::Duration};
 extern crate alloc;
-use alloc::{boxed::Box, collections::BinaryHeap, sync::{Arc, Weak}};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Weak};
 use log::info;
 
 use super::{ffi::TimeVal, get_current_time_duration};
-use spin::Lazy;
 use crate::{processor::processor::current_processor, signal::{SigInfo, SIGALRM}, sync::mutex::SpinNoIrqLock, task::task::TaskControlBlock};
 use hal::{board::MAX_PROCESSORS, instruction::{Instruction, InstructionHal}};
 /// A trait that defines the event to be triggered when a timer expires.
@@ -19,6 +18,14 @@ pub trait TimerEvent: Send + Sync {
     fn callback(self: Box<Self>) -> Option<Timer>;
 }
 
+/// What a timer does when it expires
+pub enum TimerAction {
+    /// wake a future, the common case, needs no allocation
+    Wake(Waker),
+    /// run an event callback
+    Event(Box<dyn TimerEvent>),
+}
+
 /// Represents a timer with an expiration time and associated event data.
 /// The Timer structure contains the expiration time and the data required
 /// to handle the event when the timer expires.
@@ -27,112 +34,152 @@ pub struct Timer {
     /// This indicates when the timer is set to trigger.
     pub expire: Duration,
 
-    /// A boxed dynamic trait object that implements the TimerEvent trait.
-    /// This allows different types of events to be associated with the timer.
-    pub data: Box<dyn TimerEvent>,
+    /// What to do when the timer expires.
+    pub action: TimerAction,
 }
 
 impl Timer {
     /// new a Timer
     pub fn new(expire: Duration, data: Box<dyn TimerEvent>) -> Self {
-        Self { expire, data }
+        Self { expire, action: TimerAction::Event(data) }
     }
     /// new a Timer for Waker event
     pub fn new_waker_timer(expire: Duration, waker: Waker) -> Self {
-        struct WakerData {
-            waker: Waker,
-        }
-        impl TimerEvent for WakerData {
-            fn callback(self: Box<Self>) -> Option<Timer> {
-                self.waker.wake();
-                None
-            }
-        }
-        Self {
-            expire,
-            data: Box::new(WakerData { waker }),
-        }
+        Self { expire, action: TimerAction::Wake(waker) }
     }
 
     fn callback(self) -> Option<Timer> {
-        self.data.callback()
+        match self.action {
+            TimerAction::Wake(waker) => {
+                waker.wake();
+                None
+            }
+            TimerAction::Event(data) => data.callback(),
+        }
     }
 }
 
-impl Ord for Timer {
-    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
-        self.expire.cmp(&other.expire)
-    }
-}
+/// A token for a timer added to the [`TimerManager`], to cancel or move it.
+/// Ids are never reused, so a stale handle just finds nothing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerHandle(u64);
 
-impl PartialOrd for Timer {
-    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
-        Some(self.cmp(other))
+impl TimerHandle {
+    /// take the timer out of the queue, return true if it was pending.
+    /// After true its callback never runs, after false it ran, is running or was cancelled
+    pub fn cancel(self) -> bool {
+        TIMER_MANAGER.cancel(self)
+    }
+    /// move a pending timer to `deadline`, return false if it is not pending any more
+    pub fn reset(self, deadline: Duration) -> bool {
+        TIMER_MANAGER.reset(self, deadline)
+    }
+    /// the deadline of the timer if it is still pending
+    pub fn deadline(self) -> Option<Duration> {
+        TIMER_MANAGER.queue.lock().deadlines.get(&self.0).copied()
     }
 }
 
-impl Eq for Timer {}
+/// The pending timers ordered by deadline, ties broken by id so keys are unique
+struct TimerQueue {
+    /// pending timers by (deadline, id)
+    timers: BTreeMap<(Duration, u64), TimerAction>,
+    /// deadline of each pending timer by id
+    deadlines: BTreeMap<u64, Duration>,
+    /// the timers whose callback is running, and whether they were cancelled meanwhile,
+    /// a cancelled one is not rearmed by what its callback returns
+    firing: BTreeMap<u64, bool>,
+    next_id: u64,
+}
 
-impl PartialEq for Timer {
-    fn eq(&self, other: &Self) -> bool {
-        self.expire == other.expire
+impl TimerQueue {
+    fn insert(&mut self, id: u64, timer: Timer) {
+        self.deadlines.insert(id, timer.expire);
+        self.timers.insert((timer.expire, id), timer.action);
     }
 }
 
 /// `TimerManager` is responsible for managing all the timers in the system.
-/// It uses a thread-safe lock to protect a priority queue (binary heap) that
-/// stores the timers. The timers are stored in a `BinaryHeap` with their
-/// expiration times wrapped in `Reverse` to create a min-heap, ensuring that
-/// the timer with the earliest expiration time is at the top.
+///
+/// A timer is pending while it is in the queue and leaves it exactly once,
+/// either taken by `check` to fire or by `cancel`, both under the queue lock.
+/// Callbacks run after the lock is released, so they may add, cancel or
+/// reset timers themselves.
 pub struct TimerManager {
-    /// A priority queue to store the timers. The queue is protected by a spin
-    /// lock to ensure thread-safe access. The timers are wrapped in
-    /// `Reverse` to maintain a min-heap.
-    timers: SpinNoIrqLock<BinaryHeap<Reverse<Timer>>>,
+    queue: SpinNoIrqLock<TimerQueue>,
 }
 
 impl TimerManager {
-    fn new() -> Self {
+    const fn new() -> Self {
         Self {
-            timers: SpinNoIrqLock::new(BinaryHeap::new()),
+            queue: SpinNoIrqLock::new(TimerQueue {
+                timers: BTreeMap::new(),
+                deadlines: BTreeMap::new(),
+                firing: BTreeMap::new(),
+                next_id: 1,
+            }),
         }
     }
     /// add a timer for Manager
-    pub fn add_timer(&self, timer: Timer) {
+    pub fn add_timer(&self, timer: Timer) -> TimerHandle {
         log::debug!("add new timer, next expiration {:?}", timer.expire);
-        self.timers.lock().push(Reverse(timer));
+        let mut queue = self.queue.lock();
+        let id = queue.next_id;
+        queue.next_id += 1;
+        queue.insert(id, timer);
+        TimerHandle(id)
+    }
+    /// see [`TimerHandle::cancel`]
+    pub fn cancel(&self, handle: TimerHandle) -> bool {
+        let mut queue = self.queue.lock();
+        if let Some(expire) = queue.deadlines.remove(&handle.0) {
+            queue.timers.remove(&(expire, handle.0));
+            return true;
+        }
+        if let Some(cancelled) = queue.firing.get_mut(&handle.0) {
+            *cancelled = true;
+        }
+        false
+    }
+    /// see [`TimerHandle::reset`]
+    pub fn reset(&self, handle: TimerHandle, deadline: Duration) -> bool {
+        let mut queue = self.queue.lock();
+        let Some(expire) = queue.deadlines.get_mut(&handle.0) else {
+            return false;
+        };
+        let old = core::mem::replace(expire, deadline);
+        let action = queue.timers.remove(&(old, handle.0)).unwrap();
+        queue.timers.insert((deadline, handle.0), action);
+        true
     }
-    /// check for the manager
+    /// fire the expired timers
     pub fn check(&self) {
         loop {
-            let mut timers = self.timers.lock();
-            if let Some(timer) = timers.peek() {
-                let current_time = get_current_time_duration();
-                if current_time >= timer.0.expire {
-                    log::trace!("timers len {}", timers.len());
-                    
-                    // info!(
-                    //     "[Timer Manager] there is a timer expired, current:{:?}, expire:{:?}",
-                    //     current_time,
-                    //     timer.0.expire
-                    // );
-                    
-                    let timer = timers.pop().unwrap().0;
-                    drop(timers);
-                    if let Some(new_timer) = timer.callback() {
-                        self.timers.lock().push(Reverse(new_timer));
-                    }
-                } else {
-                    break;
-                }
-            } else {
+            let current_time = get_current_time_duration();
+            let mut queue = self.queue.lock();
+            let Some(entry) = queue.timers.first_entry() else {
                 break;
+            };
+            let (expire, id) = *entry.key();
+            if current_time < expire {
+                break;
+            }
+            let action = entry.remove();
+            queue.deadlines.remove(&id);
+            queue.firing.insert(id, false);
+            drop(queue);
+            // the timer left the queue, from here on cancel returns false
+            let rearm = Timer { expire, action }.callback();
+            let mut queue = self.queue.lock();
+            let cancelled = queue.firing.remove(&id).unwrap_or(true);
+            if let Some(timer) = rearm.filter(|_| !cancelled) {
+                queue.insert(id, timer);
             }
         }
     }
 }
 /// The global `TimerManager` instance that can be accessed from anywhere in the kernel.
-pub static TIMER_MANAGER: Lazy<TimerManager> = Lazy::new(TimerManager::new);
+pub static TIMER_MANAGER: TimerManager = TimerManager::new();
 
 /// below are timer structure in linux,ITimer is a timer struct in linux used in settimmer
 ///and in get timer, ther are three types of timer in linux
@@ -144,6 +191,8 @@ pub struct ITimer {
     pub next_expire: Duration,
     /// timer id
     pub id: usize,
+    /// the armed timer in the timer manager
+    pub handle: Option<TimerHandle>,
 }
 
 impl ITimer {
@@ -152,6 +201,7 @@ impl ITimer {
         interval: Duration::ZERO,
         next_expire: Duration::ZERO,
         id: 0,
+        handle: None,
     };
 }
 
@@ -205,7 +255,7 @@ impl TimerEvent for RealITimer {
                         return None
                     }
                     task.recv_sigs_process_level(
-                        SigInfo { si_signo: SIGALRM, si_code: SigInfo::KERNEL, si_pid: None }
+                        SigInfo::new(SIGALRM, SigInfo::KERNEL)
                     );
                     let real_timer_interval = real_timer.interval;
                     if real_timer_interval == Duration::ZERO {
@@ -222,4 +272,4 @@ impl TimerEvent for RealITimer {
               })
         })
     }
-}
\ No newline at end of file
+}
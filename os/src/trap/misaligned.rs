@@ -0,0 +1,125 @@
+//! misaligned user loads and stores
+//! like linux, the faulting instruction is decoded and the access done byte by byte
+//! through the user address space, or in strict mode the task gets SIGBUS with BUS_ADRALN;
+//! the mode is system wide and set by prctl(PR_SET_UNALIGN)
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::sync::Arc;
+use hal::addr::VirtAddr;
+use hal::trap::{decode_misaligned, insn_len, MisalignedAccess, MisalignedReg, TrapContext, TrapContextHal};
+
+use crate::mm::vm::UserVmSpaceHal;
+use crate::mm::{UserPtrRaw, UserSliceRaw, UserVmSpace};
+use crate::signal::{SigInfo, SIGBUS, SIGSEGV};
+use crate::task::task::TaskControlBlock;
+
+/// fix up misaligned accesses without logging them
+pub const PR_UNALIGN_NOPRINT: usize = 1;
+/// deliver SIGBUS on a misaligned access instead of fixing it up
+pub const PR_UNALIGN_SIGBUS: usize = 2;
+
+static UNALIGN_CTL: AtomicUsize = AtomicUsize::new(PR_UNALIGN_NOPRINT);
+
+/// the PR_UNALIGN_* bits of the system
+pub fn unalign_ctl() -> usize {
+    UNALIGN_CTL.load(Ordering::Relaxed)
+}
+
+/// set the PR_UNALIGN_* bits of the system
+pub fn set_unalign_ctl(ctl: usize) {
+    UNALIGN_CTL.store(ctl, Ordering::Relaxed);
+}
+
+/// handle a user load or store that faulted on the misaligned address `addr`:
+/// emulate it and step over the instruction, or signal the task
+pub fn handle_misaligned(task: &Arc<TaskControlBlock>, addr: usize) {
+    let cx = task.get_trap_cx();
+    let epc = *cx.sepc();
+    let sigbus = SigInfo::new(SIGBUS, SigInfo::BUS_ADRALN);
+    let ctl = unalign_ctl();
+    if ctl & PR_UNALIGN_SIGBUS != 0 {
+        task.recv_sigs(sigbus);
+        return;
+    }
+    let Some(access) = fetch_insn(task, epc).and_then(decode_misaligned) else {
+        log::warn!(
+            "[misaligned] task {} can not emulate the access to {addr:#x}, epc: {epc:#x}",
+            task.tid()
+        );
+        task.recv_sigs(sigbus);
+        return;
+    };
+    if ctl & PR_UNALIGN_NOPRINT == 0 {
+        log::warn!(
+            "[misaligned] task {} fixed up {:?} at {addr:#x}, epc: {epc:#x}",
+            task.tid(), access
+        );
+    }
+    match emulate(task, cx, addr, access) {
+        Ok(()) => *cx.sepc() += access.insn_len,
+        Err(info) => task.recv_sigs(info),
+    }
+}
+
+/// read the instruction at `epc`, None if it can not be read
+fn fetch_insn(task: &Arc<TaskControlBlock>, epc: usize) -> Option<u32> {
+    let vm = &mut task.get_vm_space().lock();
+    let low = *UserPtrRaw::new(epc as *const u16).ensure_read(vm)?.to_ref();
+    if insn_len(low) == 2 {
+        return Some(low as u32);
+    }
+    let bytes = UserSliceRaw::new(epc as *mut u8, 4).ensure_read(vm)?;
+    Some(u32::from_le_bytes(bytes.to_ref().try_into().unwrap()))
+}
+
+/// do the access byte by byte, every page it touches is faulted in first,
+/// so a fault on the second page leaves the first one untouched
+fn emulate(
+    task: &Arc<TaskControlBlock>,
+    cx: &mut TrapContext,
+    addr: usize,
+    access: MisalignedAccess,
+) -> Result<(), SigInfo> {
+    let vm = &mut task.get_vm_space().lock();
+    let len = access.size;
+    if access.store {
+        let val = match access.reg {
+            MisalignedReg::Gpr(n) => cx.gpr(n) as u64,
+            MisalignedReg::Fpr(n) => cx.fpr(n),
+        };
+        let Some(dst) = UserSliceRaw::new(addr as *mut u8, len).ensure_write(vm) else {
+            return Err(access_fault(vm, addr, len));
+        };
+        dst.to_mut().copy_from_slice(&val.to_le_bytes()[..len]);
+    } else {
+        let Some(src) = UserSliceRaw::new(addr as *mut u8, len).ensure_read(vm) else {
+            return Err(access_fault(vm, addr, len));
+        };
+        let mut bytes = [0u8; 8];
+        bytes[..len].copy_from_slice(src.to_ref());
+        let mut val = u64::from_le_bytes(bytes);
+        if access.signed {
+            let shift = 64 - 8 * len;
+            val = ((val << shift) as i64 >> shift) as u64;
+        }
+        match access.reg {
+            MisalignedReg::Gpr(n) => cx.set_gpr(n, val as usize),
+            // a single is NaN boxed in the double register
+            MisalignedReg::Fpr(n) if len == 4 => cx.set_fpr(n, val | 0xffff_ffff << 32),
+            MisalignedReg::Fpr(n) => cx.set_fpr(n, val),
+        }
+    }
+    Ok(())
+}
+
+/// the SIGSEGV of an emulated access the task may not do
+fn access_fault(vm: &UserVmSpace, addr: usize, len: usize) -> SigInfo {
+    let mapped = |va: usize| vm.get_area_ref(VirtAddr::from(va)).is_some();
+    let si_code = if mapped(addr) && mapped(addr + len - 1) {
+        SigInfo::SEGV_ACCERR
+    } else {
+        SigInfo::SEGV_MAPERR
+    };
+    SigInfo { si_addr: Some(addr), ..SigInfo::new(SIGSEGV, si_code) }
+}
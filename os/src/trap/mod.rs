@@ -12,6 +12,8 @@
 //! was. For example, timer interrupts trigger task preemption, and syscalls go
 //! to [`syscall()`].
 
+pub mod misaligned;
+
 use alloc::sync::Arc;
 use downcast_rs::Downcast;
 use hal::constant::{Constant, ConstantsHal};
@@ -21,15 +23,15 @@ use hal::println;
 use hal::trap::{set_kernel_trap_entry, set_user_trap_entry, TrapContext, TrapContextHal, TrapType, TrapTypeHal};
 use hal::util::backtrace;
 use crate::mm::vm::{KernVmSpaceHal, PageFaultAccessType, UserVmSpaceHal};
-use crate::mm::KVMSPACE;
+use crate::mm::{UserVmSpace, KVMSPACE};
 use crate::signal::{SigInfo, SIGILL, SIGKILL, SIGSEGV, SIGTRAP};
 use crate::utils::timer::TimerGuard;
 use hal::addr::VirtAddr;
 
-use crate::utils::async_utils::yield_now;
 use crate::executor;
 use crate::processor::context::SumGuard;
 use crate::syscall::{syscall, SysError};
+use crate::task::schedule::check_timeslice;
 use crate::task::task::TaskControlBlock;
 use crate::task::{
      current_user_token, current_task,
@@ -58,12 +60,15 @@ pub async fn user_trap_handler() -> bool {
             );
             let task = current_task().unwrap().clone();
             // task.set_stopped();
-            task.recv_sigs(SigInfo { si_signo: SIGTRAP, si_code: SigInfo::KERNEL, si_pid: None });
+            task.recv_sigs(SigInfo::new(SIGTRAP, SigInfo::KERNEL));
         }
         TrapType::Syscall => {
             let _sum = SumGuard::new();
-            let cx = current_task().unwrap().get_trap_cx();
+            let task = current_task().unwrap().clone();
+            let cx = task.get_trap_cx();
             *cx.sepc() += 4;
+            // syscall-enter-stop, the tracer may inspect the arguments
+            task.ptrace_syscall_stop().await;
             // get system call return value
             let result = syscall(
                 cx.syscall_id(), 
@@ -80,6 +85,8 @@ pub async fn user_trap_handler() -> bool {
             // cx.save_to(0, cx.ret_nth(0));
             // report that the syscall is interrupt
             cx.set_ret_nth(0, result as usize);
+            // syscall-exit-stop, the tracer may inspect the return value
+            task.ptrace_syscall_stop().await;
             if result == -(SysError::EINTR as isize) {
                 log::warn!("[user_trap_handler] task {} syscall is interrupted", cx.syscall_id());
                 return true;
@@ -104,30 +111,56 @@ pub async fn user_trap_handler() -> bool {
             match res {
                 Ok(()) => {}
                 Err(()) => {
-                    log::warn!(
-                        "[user_trap_handler] task pid {}, tid {}, cannot handle page fault, addr {stval:#x} access_type: {access_type:?} epc: {epc:#x}",
-                        task.pid(), task.tid()
-                    );
-                    task.recv_sigs(SigInfo { si_signo: SIGSEGV, si_code: SigInfo::KERNEL, si_pid: None });
+                    let va = VirtAddr::from(stval);
+                    let si_code = if UserVmSpace::is_stack_guard(va) {
+                        log::warn!(
+                            "[user_trap_handler] task pid {}, tid {} ({}), fault at {stval:#x} in the stack guard, probable stack overflow, epc: {epc:#x}",
+                            task.pid(), task.tid(), task.comm.lock().as_str()
+                        );
+                        SigInfo::SEGV_ACCERR
+                    } else {
+                        log::warn!(
+                            "[user_trap_handler] task pid {}, tid {} ({}), cannot handle page fault, addr {stval:#x} access_type: {access_type:?} epc: {epc:#x}",
+                            task.pid(), task.tid(), task.comm.lock().as_str()
+                        );
+                        if task.with_vm_space(|vm_space| vm_space.get_area_ref(va).is_some()) {
+                            SigInfo::SEGV_ACCERR
+                        } else {
+                            SigInfo::SEGV_MAPERR
+                        }
+                    };
+                    task.recv_sigs(SigInfo { si_addr: Some(stval), ..SigInfo::new(SIGSEGV, si_code) });
                 }
             }
         }
+        TrapType::LoadMisaligned(addr) | TrapType::StoreMisaligned(addr) => {
+            let task = current_task().unwrap();
+            misaligned::handle_misaligned(task, addr);
+        }
+        TrapType::FpUnavailable => {
+            // load the task's float registers, they are saved again when it dirtied them
+            current_task().unwrap().get_trap_cx().fx_first_use();
+        }
         TrapType::IllegalInstruction(_) => {
             println!("[trap_handler] IllegalInstruction in application, kernel killed it.");
             // illegal instruction exit code
             let task = current_task().unwrap();
-            task.recv_sigs(SigInfo { si_signo: SIGILL, si_code: SigInfo::KERNEL, si_pid: None });
+            task.recv_sigs(SigInfo::new(SIGILL, SigInfo::KERNEL));
         }
         TrapType::Timer => {
+            crate::executor::shutdown::check_watchdog();
             crate::timer::timer::TIMER_MANAGER.check();
             #[cfg(feature = "smp")]
             crate::processor::processor::current_processor().update_load_avg();
             set_next_trigger();
-            yield_now().await;
+            // the task yields on the way back to user mode once its slice ran out
+            check_timeslice(current_task().unwrap());
         }
         TrapType::ExternalInterrupt => {
-            let manager = crate::devices::DEVICE_MANAGER.lock();
-            manager.handle_irq();
+            crate::devices::handle_irq();
+        }
+        TrapType::SoftwareInterrupt => {
+            crate::processor::ipi::handle_ipi();
         }
         TrapType::Processed => {}
         trap => {
@@ -157,10 +190,8 @@ pub fn trap_return(task: &Arc<TaskControlBlock>, _is_intr: bool) {
     // handler the signal before return
     // task.check_and_handle(is_intr);
 
-    // restore float pointer and set status
-    trap_cx.fx_restore();
-    
-    Instruction::set_float_status_clean();
+    // turn FP off unless the registers still hold the task's state
+    trap_cx.fx_lazy_restore();
     // restore
     hal::trap::restore(trap_cx);
     
@@ -180,6 +211,14 @@ fn kernel_trap_handler() {
         TrapType::StorePageFault(stval)
         | TrapType::LoadPageFault(stval)
         | TrapType::InstructionPageFault(stval) => {
+            #[cfg(target_arch = "riscv64")]
+            if let Some(hart) = crate::mm::kstack::guard_hart(stval) {
+                let sp: usize;
+                unsafe { asm!("mv {}, sp", out(reg) sp); }
+                panic!(
+                    "kernel stack overflow on hart {hart}, addr {stval:#x} sp {sp:#x} epc {epc:#x}"
+                );
+            }
             // warn: page fault from kernel is dangerous
             log::warn!(
                 "[kernel_trap_handler] encounter page fault, addr {stval:#x} epc {epc:#x}",
@@ -215,14 +254,28 @@ fn kernel_trap_handler() {
         }
         TrapType::Timer => {
             // println!("interrupt: supervisor timer");
+            // a task staying in the kernel gets charged every tick, from user mode
+            // the trap entry already charged the user slice.
+            // kernel code is not preempted, the task yields at its next cond_resched
+            if let Some(task) = current_task() {
+                check_timeslice(task);
+            }
+            crate::executor::shutdown::check_watchdog();
             crate::timer::timer::TIMER_MANAGER.check();
             set_next_trigger();
         }
         TrapType::ExternalInterrupt => {
-            let manager = crate::devices::DEVICE_MANAGER.lock();
-            manager.handle_irq();
+            crate::devices::handle_irq();
+        }
+        TrapType::SoftwareInterrupt => {
+            crate::processor::ipi::ack_ipi();
         }
         TrapType::Processed => {}
+        TrapType::LoadMisaligned(addr) | TrapType::StoreMisaligned(addr) => {
+            panic!(
+                "[kernel_trap_handler] misaligned access to {addr:#x} from kernel, epc: {epc:#x}",
+            );
+        }
         _ => {
             // error!("other exception!!");
             panic!(
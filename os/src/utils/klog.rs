@@ -0,0 +1,89 @@
+//! the kernel log ring, read by syslog(2)
+//! a fixed size byte ring that overwrites its oldest bytes, so writing never allocates
+
+use alloc::{format, string::String};
+use core::cmp;
+
+use crate::{sync::mutex::SpinNoIrqLock, syscall::SysError};
+
+/// size of the kernel log ring in bytes, like CONFIG_LOG_BUF_SHIFT = 16
+pub const KLOG_SIZE: usize = 1 << 16;
+
+struct LogRing {
+    buf: [u8; KLOG_SIZE],
+    /// bytes written since the last clear, the newest byte is at (written - 1) % KLOG_SIZE
+    written: usize,
+}
+
+static KLOG: SpinNoIrqLock<LogRing> = SpinNoIrqLock::new(LogRing { buf: [0; KLOG_SIZE], written: 0 });
+
+/// append bytes to the ring, dropping the oldest ones when full
+pub fn klog_write(bytes: &[u8]) {
+    // only the tail of an oversized write survives anyway
+    let bytes = &bytes[bytes.len().saturating_sub(KLOG_SIZE)..];
+    let mut ring = KLOG.lock();
+    let start = ring.written % KLOG_SIZE;
+    let first = cmp::min(bytes.len(), KLOG_SIZE - start);
+    ring.buf[start..start + first].copy_from_slice(&bytes[..first]);
+    ring.buf[..bytes.len() - first].copy_from_slice(&bytes[first..]);
+    ring.written += bytes.len();
+}
+
+/// bytes currently held in the ring
+pub fn klog_len() -> usize {
+    cmp::min(KLOG.lock().written, KLOG_SIZE)
+}
+
+/// copy the newest bytes of the ring into `out`, oldest first, return the count
+pub fn klog_read_all(out: &mut [u8]) -> usize {
+    let ring = KLOG.lock();
+    let len = cmp::min(cmp::min(ring.written, KLOG_SIZE), out.len());
+    let start = (ring.written - len) % KLOG_SIZE;
+    let first = cmp::min(len, KLOG_SIZE - start);
+    out[..first].copy_from_slice(&ring.buf[start..start + first]);
+    out[first..len].copy_from_slice(&ring.buf[..len - first]);
+    len
+}
+
+/// forget everything in the ring
+pub fn klog_clear() {
+    KLOG.lock().written = 0;
+}
+
+/// the console log level of a log filter: a message shows when its level,
+/// 3 for errors, 4 warnings, 6 info and 7 debug, is below it
+fn console_loglevel(filter: log::LevelFilter) -> usize {
+    match filter {
+        log::LevelFilter::Off => 1,
+        log::LevelFilter::Error => 4,
+        log::LevelFilter::Warn => 5,
+        log::LevelFilter::Info => 7,
+        log::LevelFilter::Debug => 8,
+        log::LevelFilter::Trace => 9,
+    }
+}
+
+/// sysctl kernel/printk: the console log level, then the fixed default message level,
+/// minimum console level and default console level of linux
+pub fn printk_read() -> String {
+    format!("{}\t4\t1\t7\n", console_loglevel(log::max_level()))
+}
+
+/// sysctl kernel/printk: set the console log level from the first number, 1 to 9,
+/// the others are accepted and ignored
+pub fn printk_write(text: &str) -> Result<(), SysError> {
+    let level: usize = text.split_whitespace().next()
+        .and_then(|level| level.parse().ok())
+        .ok_or(SysError::EINVAL)?;
+    let filter = match level {
+        1..=3 => log::LevelFilter::Off,
+        4 => log::LevelFilter::Error,
+        5 | 6 => log::LevelFilter::Warn,
+        7 => log::LevelFilter::Info,
+        8 => log::LevelFilter::Debug,
+        9 => log::LevelFilter::Trace,
+        _ => return Err(SysError::EINVAL),
+    };
+    log::set_max_level(filter);
+    Ok(())
+}
@@ -0,0 +1,62 @@
+//! the kernel symbol table, linked into the .ksyms section at the end of the image
+//!
+//! `scripts/ksyms.py` writes it from the symbols of the kernel built before, the
+//! section comes after everything else so its size moves no code. The layout,
+//! all little endian: `b"KSYM"`, the count as u32, then for each text symbol in
+//! address order its address as u64, the offset of its name in the table as u32
+//! and the length as u32, then the names.
+
+#[used]
+#[link_section = ".ksyms"]
+static KSYMS: [u8; include_bytes!("../../ksyms.bin").len()] = *include_bytes!("../../ksyms.bin");
+
+const MAGIC: &[u8] = b"KSYM";
+const HEADER_LEN: usize = 8;
+const ENTRY_LEN: usize = 16;
+
+/// the table as linked, the code never refers to [`KSYMS`] so it does not depend on its size
+fn table() -> &'static [u8] {
+    unsafe extern "C" {
+        fn sksyms();
+        fn eksyms();
+    }
+    unsafe { core::slice::from_raw_parts(sksyms as usize as *const u8, eksyms as usize - sksyms as usize) }
+}
+
+fn read_u32(table: &[u8], offset: usize) -> usize {
+    u32::from_le_bytes(table[offset..offset + 4].try_into().unwrap()) as usize
+}
+
+fn read_u64(table: &[u8], offset: usize) -> usize {
+    u64::from_le_bytes(table[offset..offset + 8].try_into().unwrap()) as usize
+}
+
+/// the name of the symbol `addr` lies in and the offset of `addr` from its start,
+/// without allocating or locking as the panic handler calls it
+pub fn lookup(addr: usize) -> Option<(&'static str, usize)> {
+    let table = table();
+    if table.len() < HEADER_LEN || &table[..4] != MAGIC {
+        return None;
+    }
+    let count = read_u32(table, 4);
+    if HEADER_LEN + count * ENTRY_LEN > table.len() {
+        return None;
+    }
+    let entry = |i: usize| HEADER_LEN + i * ENTRY_LEN;
+    // the number of symbols starting at or below `addr`
+    let (mut lo, mut hi) = (0, count);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if read_u64(table, entry(mid)) <= addr {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    let i = lo.checked_sub(1)?;
+    let start = read_u64(table, entry(i));
+    let name_off = read_u32(table, entry(i) + 8);
+    let name_len = read_u32(table, entry(i) + 12);
+    let name = table.get(name_off..name_off + name_len)?;
+    Some((core::str::from_utf8(name).ok()?, addr - start))
+}
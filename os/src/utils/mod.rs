@@ -8,6 +8,8 @@ pub mod ring_buffer;
 pub mod macro_utils;
 pub mod round;
 pub mod timer;
+pub mod klog;
+pub mod ksyms;
 
 pub use async_utils::*;
 pub use path::*;
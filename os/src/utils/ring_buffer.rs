@@ -38,6 +38,39 @@ impl RingBuffer {
         self.state == RingBufferState::FULL
     }
 
+    /// the bytes buffered
+    pub fn len(&self) -> usize {
+        match self.state {
+            RingBufferState::EMPTY => 0,
+            RingBufferState::FULL => self.arr.len(),
+            RingBufferState::NORMAL => (self.tail + self.arr.len() - self.head) % self.arr.len(),
+        }
+    }
+
+    /// the bytes it can hold
+    pub fn capacity(&self) -> usize {
+        self.arr.len()
+    }
+
+    /// change the capacity to `len` keeping what is buffered, false when that does not fit
+    pub fn resize(&mut self, len: usize) -> bool {
+        let used = self.len();
+        if used > len || len == 0 {
+            return false;
+        }
+        let mut arr = vec![0; len];
+        self.read(&mut arr[..used]);
+        self.arr = arr;
+        self.head = 0;
+        self.tail = used % len;
+        self.state = match used {
+            0 => RingBufferState::EMPTY,
+            _ if used == len => RingBufferState::FULL,
+            _ => RingBufferState::NORMAL,
+        };
+        true
+    }
+
     /// Read as much as possible to fill `buf`.
     pub fn read(&mut self, buf: &mut [u8]) -> usize {
         if self.state == RingBufferState::EMPTY || buf.is_empty() {
@@ -17,6 +17,6 @@ impl<'a> TimerGuard<'a> {
 
 impl<'a> Drop for TimerGuard<'a> {
     fn drop(&mut self) {
-        println!("{} {:?}", self.name, get_current_time_duration() - self.start);
+        println!("{} {:?}", self.name, get_current_time_duration().saturating_sub(self.start));
     }
 }
@@ -0,0 +1,76 @@
+#!/usr/bin/env python3
+"""Write the kernel symbol table linked into the .ksyms section.
+
+usage: ksyms.py <nm> <kernel elf> <table>
+           write the table of the text symbols of the kernel, exit 1 if it
+           differs from the table there was, the kernel must be built again
+       ksyms.py --empty <table>
+           write an empty table unless there is one, for the first build
+
+The layout, all little endian: b"KSYM", the count as u32, then for each symbol
+in address order its address as u64, the offset of its name in the table as
+u32 and the length as u32, then the names. See os/src/utils/ksyms.rs.
+"""
+
+import os
+import re
+import struct
+import subprocess
+import sys
+
+MAGIC = b"KSYM"
+TEXT_TYPES = "TtWw"
+# names are cut to this many bytes
+NAME_MAX = 160
+# the hash rustc appends to legacy mangled names
+HASH = re.compile(r"::h[0-9a-f]{16}$")
+
+
+def table(symbols):
+    header = MAGIC + struct.pack("<I", len(symbols))
+    names = b""
+    entries = b""
+    names_start = len(header) + 16 * len(symbols)
+    for addr, name in symbols:
+        entries += struct.pack("<QII", addr, names_start + len(names), len(name))
+        names += name
+    return header + entries + names
+
+
+def text_symbols(nm, elf):
+    out = subprocess.run([nm, "-n", "-C", "--defined-only", elf], check=True, capture_output=True, text=True).stdout
+    symbols = {}
+    for line in out.splitlines():
+        parts = line.split(" ", 2)
+        if len(parts) != 3 or parts[1] not in TEXT_TYPES:
+            continue
+        name = HASH.sub("", parts[2])
+        # mapping symbols and local labels name no function
+        if name.startswith("$") or name.startswith(".L"):
+            continue
+        # of several names at one address keep the first
+        symbols.setdefault(int(parts[0], 16), name.encode()[:NAME_MAX])
+    return sorted(symbols.items())
+
+
+def main(args):
+    if len(args) == 2 and args[0] == "--empty":
+        if not os.path.exists(args[1]):
+            with open(args[1], "wb") as f:
+                f.write(table([]))
+        return 0
+    if len(args) != 3:
+        print(__doc__, file=sys.stderr)
+        return 2
+    nm, elf, path = args
+    new = table(text_symbols(nm, elf))
+    old = open(path, "rb").read() if os.path.exists(path) else None
+    if new == old:
+        return 0
+    with open(path, "wb") as f:
+        f.write(new)
+    return 1
+
+
+if __name__ == "__main__":
+    sys.exit(main(sys.argv[1:]))
@@ -0,0 +1,17 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{kdebug, KDEBUG_PANIC};
+
+#[macro_use]
+extern crate user_lib;
+
+/// usage: kpanic
+/// panic the kernel on purpose, the console shows the crash report with a symbolized backtrace
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let ret = kdebug(KDEBUG_PANIC, 0);
+    // only reached if the kernel refused
+    println!("kpanic: the kernel did not panic: {}", ret);
+    -1
+}
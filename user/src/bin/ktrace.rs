@@ -0,0 +1,25 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{execve, prctl, PR_SET_SYSCALL_TRACE};
+
+#[macro_use]
+extern crate user_lib;
+
+/// usage: ktrace <program> [args...]
+/// run a program with the in-kernel syscall trace on, the transcript goes to the console and dmesg
+#[no_mangle]
+pub fn main(args: &[&str]) -> i32 {
+    if args.len() < 2 {
+        println!("usage: ktrace <program> [args...]");
+        return -1;
+    }
+    // the flag survives execve and is inherited by the children of the program
+    if prctl(PR_SET_SYSCALL_TRACE, 1, 0) != 0 {
+        println!("ktrace: the kernel has no syscall trace");
+        return -1;
+    }
+    execve(args[1], &args[1..], &[]);
+    println!("ktrace: can not execute {}", args[1]);
+    -1
+}
@@ -0,0 +1,89 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{execve, exit, fork, ptrace, waitpid, IoVec, NT_PRSTATUS, PTRACE_GETREGSET, PTRACE_O_TRACESYSGOOD, PTRACE_SETOPTIONS, PTRACE_SYSCALL, PTRACE_TRACEME};
+
+#[macro_use]
+extern crate user_lib;
+
+const SIGTRAP: i32 = 5;
+
+#[cfg(target_arch = "riscv64")]
+const NGREG: usize = 32;
+#[cfg(target_arch = "riscv64")]
+const REG_SYSCALL_ID: usize = 17;
+#[cfg(target_arch = "riscv64")]
+const REG_RET: usize = 10;
+
+#[cfg(target_arch = "loongarch64")]
+const NGREG: usize = 45;
+#[cfg(target_arch = "loongarch64")]
+const REG_SYSCALL_ID: usize = 11;
+#[cfg(target_arch = "loongarch64")]
+const REG_RET: usize = 4;
+
+fn get_regs(pid: usize, regs: &mut [usize; NGREG]) -> isize {
+    let mut iov = IoVec { base: regs.as_mut_ptr() as usize, len: core::mem::size_of_val(regs) };
+    ptrace(PTRACE_GETREGSET, pid, NT_PRSTATUS, &mut iov as *mut IoVec as usize)
+}
+
+/// usage: strace <program> [args...]
+#[no_mangle]
+pub fn main(args: &[&str]) -> i32 {
+    if args.len() < 2 {
+        println!("usage: strace <program> [args...]");
+        return -1;
+    }
+    let pid = fork();
+    if pid == 0 {
+        ptrace(PTRACE_TRACEME, 0, 0, 0);
+        execve(args[1], &args[1..], &[]);
+        println!("strace: can not execute {}", args[1]);
+        exit(-1);
+    }
+    let pid = pid as usize;
+    let mut status = 0;
+    // the child stops with SIGTRAP after execve
+    waitpid(pid, &mut status);
+    if status & 0xff != 0x7f {
+        println!("strace: child exited before execve");
+        return -1;
+    }
+    ptrace(PTRACE_SETOPTIONS, pid, 0, PTRACE_O_TRACESYSGOOD);
+
+    let mut regs = [0usize; NGREG];
+    let mut in_syscall = false;
+    let mut sig = 0;
+    loop {
+        ptrace(PTRACE_SYSCALL, pid, 0, sig);
+        sig = 0;
+        waitpid(pid, &mut status);
+        if status & 0xff != 0x7f {
+            // exited or killed
+            break;
+        }
+        let stopsig = (status >> 8) & 0xff;
+        if stopsig != SIGTRAP | 0x80 {
+            // a real signal, hand it back to the child
+            println!("--- signal {} ---", stopsig);
+            sig = stopsig as usize;
+            continue;
+        }
+        get_regs(pid, &mut regs);
+        if !in_syscall {
+            print!("syscall({})", regs[REG_SYSCALL_ID]);
+        } else {
+            println!(" = {}", regs[REG_RET] as isize);
+        }
+        in_syscall = !in_syscall;
+    }
+    if in_syscall {
+        println!(" = ?");
+    }
+    if status & 0x7f == 0 {
+        println!("+++ exited with {} +++", (status >> 8) & 0xff);
+    } else {
+        println!("+++ killed by signal {} +++", status & 0x7f);
+    }
+    0
+}
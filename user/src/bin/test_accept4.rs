@@ -0,0 +1,140 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    accept4, bind, close, connect, exit, fork, get_time_ms, listen, recvfrom, sendto, sleep, socket, waitpid, yield_,
+    SockaddrIn, EAGAIN,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const AF_INET: i32 = 2;
+const SOCK_STREAM: i32 = 1;
+const IPPROTO_TCP: i32 = 6;
+const SOCK_NONBLOCK: i32 = 0x800;
+const SOCK_CLOEXEC: i32 = 0x80000;
+
+const TEST_PORT: u16 = 4446;
+const TEST_ADDR: u32 = 0x7f000001; // 127.0.0.1
+const CLIENTS: usize = 4;
+const TIMEOUT_MS: isize = 10_000;
+
+fn test_addr() -> SockaddrIn {
+    SockaddrIn::new(TEST_ADDR.to_be(), TEST_PORT.to_be())
+}
+
+/// connect, send our id and keep the connection open for a while,
+/// so the server can see an empty nonblocking socket
+fn client(id: u8) -> i32 {
+    let fd = socket(AF_INET, SOCK_STREAM, IPPROTO_TCP);
+    if fd < 0 {
+        return -1;
+    }
+    let addr = test_addr();
+    if connect(fd as usize, &addr, size_of::<SockaddrIn>() as u32) < 0 {
+        return -1;
+    }
+    sendto(fd as usize, &[id], 1, 0, core::ptr::null(), 0);
+    sleep(1000);
+    close(fd as usize);
+    0
+}
+
+/// read the single byte of an accepted connection, then check a second read
+/// returns EAGAIN instead of blocking
+fn serve(fd: usize, deadline: isize) -> bool {
+    let mut buf = [0u8; 1];
+    loop {
+        let n = recvfrom(fd, &mut buf, 1, 0, core::ptr::null_mut(), core::ptr::null_mut());
+        if n == 1 {
+            break;
+        }
+        if n != EAGAIN || get_time_ms() > deadline {
+            println!("test_accept4: recv on accepted socket returned {}", n);
+            return false;
+        }
+        yield_();
+    }
+    let n = recvfrom(fd, &mut buf, 1, 0, core::ptr::null_mut(), core::ptr::null_mut());
+    if n != EAGAIN {
+        println!("test_accept4: accepted socket is not nonblocking, recv returned {}", n);
+        return false;
+    }
+    true
+}
+
+/// a nonblocking listener accepting several racing connects with accept4
+#[no_mangle]
+pub fn main() -> i32 {
+    let listen_fd = socket(AF_INET, SOCK_STREAM | SOCK_NONBLOCK, IPPROTO_TCP);
+    if listen_fd < 0 {
+        println!("test_accept4: socket failed");
+        return -1;
+    }
+    let addr = test_addr();
+    if bind(listen_fd as usize, &addr, size_of::<SockaddrIn>() as u32) < 0
+        || listen(listen_fd as usize, CLIENTS as i32) < 0
+    {
+        println!("test_accept4: bind/listen failed");
+        return -1;
+    }
+    // nothing pending yet, the nonblocking listener must not block
+    let ret = accept4(listen_fd as usize, core::ptr::null_mut(), core::ptr::null_mut(), 0);
+    if ret != EAGAIN {
+        println!("test_accept4: accept on an empty nonblocking listener returned {}", ret);
+        return -1;
+    }
+    if accept4(listen_fd as usize, core::ptr::null_mut(), core::ptr::null_mut(), 0x1) != -22 {
+        println!("test_accept4: unknown flags were not rejected");
+        return -1;
+    }
+
+    let mut pids = [0usize; CLIENTS];
+    for (id, pid) in pids.iter_mut().enumerate() {
+        let ret = fork();
+        if ret == 0 {
+            close(listen_fd as usize);
+            exit(client(id as u8));
+        }
+        *pid = ret as usize;
+    }
+
+    let deadline = get_time_ms() + TIMEOUT_MS;
+    let mut accepted = 0;
+    let mut ok = true;
+    while accepted < CLIENTS && ok {
+        let mut peer: SockaddrIn = unsafe { core::mem::zeroed() };
+        let mut peer_len = size_of::<SockaddrIn>() as u32;
+        let fd = accept4(listen_fd as usize, &mut peer, &mut peer_len, SOCK_NONBLOCK | SOCK_CLOEXEC);
+        if fd == EAGAIN {
+            if get_time_ms() > deadline {
+                println!("test_accept4: only {} of {} connections accepted", accepted, CLIENTS);
+                ok = false;
+            }
+            yield_();
+            continue;
+        }
+        if fd < 0 {
+            println!("test_accept4: accept4 failed: {}", fd);
+            ok = false;
+            break;
+        }
+        ok &= serve(fd as usize, deadline);
+        close(fd as usize);
+        accepted += 1;
+    }
+
+    for pid in pids {
+        let mut status = 0;
+        waitpid(pid, &mut status);
+        ok &= status == 0;
+    }
+    close(listen_fd as usize);
+    if ok {
+        println!("test_accept4 passed!");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,91 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, exit, fork, getcpu, getpid, sched_getaffinity, sched_setaffinity, setuid, wait, yield_, yield_count, EINVAL,
+    EPERM,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const TARGET_HART: usize = 1;
+const ROUNDS: usize = 200;
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    // an empty mask is rejected
+    ok &= check(sched_setaffinity(0, &[0u8; 16]) == EINVAL, "empty mask");
+
+    // a cpu_set_t larger than the kernel mask, the extra bytes are ignored
+    let mut mask = [0u8; 128];
+    mask[0] = 1 << TARGET_HART;
+    mask[100] = 0xff;
+    let ret = sched_setaffinity(0, &mask);
+    if ret == EINVAL {
+        println!("test_affinity: hart {} is offline, skipped", TARGET_HART);
+        return 0;
+    }
+    ok &= check(ret == 0, "set affinity");
+
+    let yields = yield_count();
+    let mut yielded = 0;
+    for _ in 0..ROUNDS {
+        if getcpu() != TARGET_HART as isize {
+            ok &= check(false, "pinned to hart");
+            break;
+        }
+        yield_();
+        yielded += 1;
+    }
+    ok &= check(yield_count() == yields + yielded, "every sched_yield counted");
+
+    // the kernel mask size is returned and the rest is zero filled
+    let mut got = [0xffu8; 128];
+    let size = sched_getaffinity(0, &mut got);
+    ok &= check(size == 8, "getaffinity size");
+    ok &= check(got[0] == 1 << TARGET_HART, "getaffinity mask");
+    ok &= check(got[8..].iter().all(|&b| b == 0), "getaffinity zero fill");
+    ok &= check(sched_getaffinity(0, &mut got[..4]) == EINVAL, "short cpu_set_t");
+
+    // the mask is inherited by the child
+    let pid = fork();
+    if pid == 0 {
+        for _ in 0..ROUNDS {
+            if getcpu() != TARGET_HART as isize {
+                exit(-1);
+            }
+            yield_();
+        }
+        exit(0);
+    }
+    let mut child_mask = [0u8; 8];
+    ok &= check(sched_getaffinity(pid as usize, &mut child_mask) == 8, "getaffinity of child");
+    ok &= check(child_mask[0] == 1 << TARGET_HART, "child mask");
+    let mut exit_code = 0;
+    wait(&mut exit_code);
+    ok &= check(exit_code == 0, "child pinned to hart");
+
+    // another user may neither read nor change the mask of a root process
+    let root_pid = getpid() as usize;
+    let pid = fork();
+    if pid == 0 {
+        let mut mask = [0u8; 8];
+        let denied = setuid(1000) == 0
+            && sched_setaffinity(root_pid, &[1u8; 8]) == EPERM
+            && sched_getaffinity(root_pid, &mut mask) == EPERM;
+        exit(if denied { 0 } else { 1 });
+    }
+    let mut exit_code = 0;
+    wait(&mut exit_code);
+    ok &= check(exit_code == 0, "affinity of a root process from another user is EPERM");
+
+    if ok {
+        println!("test_affinity passed!");
+        0
+    } else {
+        -1
+    }
+}
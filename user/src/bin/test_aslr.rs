@@ -0,0 +1,122 @@
+#![no_std]
+#![no_main]
+
+use alloc::format;
+use core::hint::black_box;
+
+use user_lib::{
+    brk, check, close, dup3, execve, exit, fork, mmap, open, pipe, read, waitpid, write, MmapFlags, MmapProt, OpenFlags,
+    EINVAL,
+};
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+const RANDOMIZE_VA_SPACE: &str = "/proc/sys/kernel/randomize_va_space\0";
+const PAGE_SIZE: usize = 4096;
+
+/// the stack, the argument block, the break, an anonymous mmap and a file mmap of a process
+type Layout = [usize; 5];
+const NAMES: [&str; 5] = ["the stack", "the arguments", "the break", "an anonymous mmap", "a file mmap"];
+const BREAK: usize = 2;
+
+/// the layout of this process, as the report child sends it
+fn own_layout(args: &[&str]) -> Layout {
+    let local = 0u8;
+    let flags = MmapFlags::MAP_PRIVATE | MmapFlags::MAP_ANONYMOUS;
+    let anon = mmap(0, PAGE_SIZE, MmapProt::PROT_READ | MmapProt::PROT_WRITE, flags, usize::MAX, 0);
+    let fd = open(&format!("{}\0", args[0]), OpenFlags::RDONLY);
+    let file = mmap(0, PAGE_SIZE, MmapProt::PROT_READ, MmapFlags::MAP_PRIVATE, fd as usize, 0);
+    [black_box(&local) as *const u8 as usize, args[0].as_ptr() as usize, brk(0) as usize, anon as usize, file as usize]
+}
+
+/// exec `path` in a child which writes its layout into a pipe
+fn layout_of(path: &str) -> Option<Layout> {
+    let mut fds = [0usize; 2];
+    if pipe(&mut fds) != 0 {
+        return None;
+    }
+    let pid = fork();
+    if pid == 0 {
+        dup3(fds[1], 1, 0);
+        close(fds[0]);
+        close(fds[1]);
+        execve(path, &[path, "report"], &[]);
+        exit(2);
+    }
+    close(fds[1]);
+    let mut layout = [0usize; 5];
+    let bytes = unsafe { core::slice::from_raw_parts_mut(layout.as_mut_ptr() as *mut u8, size_of::<Layout>()) };
+    let mut got = 0;
+    while got < bytes.len() {
+        let n = read(fds[0], &mut bytes[got..]);
+        if n <= 0 {
+            break;
+        }
+        got += n as usize;
+    }
+    close(fds[0]);
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    (got == bytes.len() && status == 0).then_some(layout)
+}
+
+fn set_randomize(level: &str) -> isize {
+    let fd = open(RANDOMIZE_VA_SPACE, OpenFlags::WRONLY);
+    if fd < 0 {
+        return fd;
+    }
+    let ret = write(fd as usize, level.as_bytes(), level.len());
+    close(fd as usize);
+    ret
+}
+
+/// exec `path` twice at the level `level`, the fields in `moved` must differ and the others not
+fn two_runs(path: &str, level: &str, moved: &[bool; 5]) -> bool {
+    let mut ok = check(set_randomize(level) == level.len() as isize, "set kernel/randomize_va_space");
+    let (Some(a), Some(b)) = (layout_of(path), layout_of(path)) else {
+        return check(false, "the report child");
+    };
+    println!("test_aslr: randomize_va_space={} {:x?} {:x?}", level, a, b);
+    for i in 0..NAMES.len() {
+        if moved[i] && a[i] == b[i] {
+            println!("test_aslr: {} stays at {:#x} with randomize_va_space={}", NAMES[i], a[i], level);
+            ok = false;
+        } else if !moved[i] && a[i] != b[i] {
+            println!("test_aslr: {} moves with randomize_va_space={}", NAMES[i], level);
+            ok = false;
+        }
+    }
+    ok
+}
+
+#[no_mangle]
+pub fn main(args: &[&str]) -> i32 {
+    if args.get(1) == Some(&"report") {
+        let layout = own_layout(args);
+        let bytes = unsafe { core::slice::from_raw_parts(layout.as_ptr() as *const u8, size_of::<Layout>()) };
+        write(1, bytes, bytes.len());
+        return 0;
+    }
+    let path = args[0];
+    let mut ok = check(set_randomize("3") == EINVAL, "randomize_va_space=3 is EINVAL");
+
+    // everything moves, the break too
+    ok &= two_runs(path, "2", &[true; 5]);
+    // everything but the break
+    let mut moved = [true; 5];
+    moved[BREAK] = false;
+    ok &= two_runs(path, "1", &moved);
+    // nothing, every run is the same
+    ok &= two_runs(path, "0", &[false; 5]);
+
+    set_randomize("2");
+
+    if ok {
+        println!("test_aslr: passed");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,130 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{brk, check, exit, fork, mmap, mprotect, munmap, waitpid, MmapFlags, MmapProt};
+
+#[macro_use]
+extern crate user_lib;
+
+const PAGE_SIZE: usize = 4096;
+const GENERATIONS: usize = 3;
+/// the pages each generation adds to the heap
+const GROW_PAGES: usize = 8;
+const MAX_PAGES: usize = GENERATIONS * GROW_PAGES;
+
+fn rw() -> MmapProt {
+    MmapProt::PROT_READ | MmapProt::PROT_WRITE
+}
+
+/// the word generation `generation` leaves at the start and the end of heap page `page`
+fn pattern(generation: usize, page: usize) -> u64 {
+    0xa5 << 56 | (generation as u64) << 32 | page as u64
+}
+
+fn word(addr: usize) -> u64 {
+    unsafe { (addr as *const u64).read_volatile() }
+}
+
+fn write_page(base: usize, page: usize, generation: usize) {
+    let addr = base + page * PAGE_SIZE;
+    unsafe {
+        (addr as *mut u64).write_volatile(pattern(generation, page));
+        ((addr + PAGE_SIZE - 16) as *mut u64).write_volatile(pattern(generation, page));
+    }
+}
+
+/// whether the first `pages` heap pages hold what `owners` wrote, a page of no owner is zero
+fn verify(base: usize, pages: usize, owners: &[Option<usize>; MAX_PAGES]) -> bool {
+    let mut sum = 0u64;
+    let mut expected = 0u64;
+    for (page, owner) in owners.iter().enumerate().take(pages) {
+        let addr = base + page * PAGE_SIZE;
+        let want = owner.map_or(0, |generation| pattern(generation, page));
+        sum = sum.wrapping_mul(31).wrapping_add(word(addr)).wrapping_add(word(addr + PAGE_SIZE - 16));
+        expected = expected.wrapping_mul(31).wrapping_add(want).wrapping_add(want);
+    }
+    sum == expected
+}
+
+/// grow the heap by GROW_PAGES, write the new pages and one inherited page, split the heap
+/// with mprotect and hand it to the next generation, whose writes must not show here
+fn generation(generation: usize, base: usize, pages: usize, owners: &mut [Option<usize>; MAX_PAGES]) -> bool {
+    let mut ok = check(verify(base, pages, owners), "the inherited heap");
+    let new_pages = pages + GROW_PAGES;
+    // the break mid-page, the last page is still whole
+    let new_brk = base + new_pages * PAGE_SIZE - 8;
+    ok &= check(brk(new_brk) as usize == new_brk, "brk grows the heap");
+    ok &= check(verify(base, new_pages, owners), "the grown pages are zero");
+    for page in pages..new_pages {
+        write_page(base, page, generation);
+        owners[page] = Some(generation);
+    }
+    // a write to a page shared with the parent breaks the sharing
+    write_page(base, 0, generation);
+    owners[0] = Some(generation);
+
+    // the middle of the heap read only and back splits the heap into three areas
+    let middle = base + (new_pages / 2) * PAGE_SIZE;
+    ok &= check(mprotect(middle, PAGE_SIZE, MmapProt::PROT_READ) == 0, "mprotect a heap page read only");
+    ok &= check(mprotect(middle, PAGE_SIZE, rw()) == 0, "mprotect it back");
+    ok &= check(brk(0) as usize == new_brk, "the split heap keeps its break");
+    ok &= check(verify(base, new_pages, owners), "the heap of the generation");
+
+    if generation < GENERATIONS {
+        let pid = fork();
+        if pid == 0 {
+            let ok = self::generation(generation + 1, base, new_pages, owners);
+            exit(if ok { 0 } else { 1 });
+        }
+        let mut status = 0;
+        waitpid(pid as usize, &mut status);
+        ok &= check(status == 0, "the next generation");
+        ok &= check(brk(0) as usize == new_brk, "the break of the child stays in the child");
+        ok &= check(verify(base, new_pages, owners), "the writes of the child stay in the child");
+    } else {
+        ok &= last_generation(base, new_pages, owners);
+    }
+    ok
+}
+
+/// shrink the split heap across its areas, grow it back and run it into a mapping
+fn last_generation(base: usize, pages: usize, owners: &mut [Option<usize>; MAX_PAGES]) -> bool {
+    let low_brk = base + PAGE_SIZE + 8;
+    let mut ok = check(brk(low_brk) as usize == low_brk, "brk shrinks the split heap");
+    ok &= check(verify(base, 2, owners), "the pages below the break stay");
+    let regrown = base + pages * PAGE_SIZE;
+    ok &= check(brk(regrown) as usize == regrown, "brk grows it back");
+    for owner in owners.iter_mut().take(pages).skip(2) {
+        *owner = None;
+    }
+    ok &= check(verify(base, pages, owners), "the pages grown back are zero");
+
+    // a page mapped above the break stops the heap before it
+    let blocker = regrown + 2 * PAGE_SIZE;
+    let flags = MmapFlags::MAP_PRIVATE | MmapFlags::MAP_ANONYMOUS | MmapFlags::MAP_FIXED;
+    ok &= check(mmap(blocker, PAGE_SIZE, rw(), flags, usize::MAX, 0) == blocker as isize, "map a page above the heap");
+    ok &= check(brk(blocker + PAGE_SIZE) as usize == regrown, "brk into a mapping is refused");
+    ok &= check(brk(blocker) as usize == blocker, "brk up to the mapping");
+    munmap(blocker, PAGE_SIZE);
+    ok &= check(brk(regrown) as usize == regrown, "brk shrinks back");
+    ok
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let base = brk(0) as usize;
+    if base % PAGE_SIZE != 0 {
+        println!("test_brk_fork: the heap already exists, skipped");
+        return 0;
+    }
+    let mut owners = [None; MAX_PAGES];
+    let ok = generation(1, base, 0, &mut owners);
+    brk(base);
+
+    if ok {
+        println!("test_brk_fork: passed");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,123 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{brk, check, mmap, setrlimit, MmapFlags, MmapProt, RLimit, RLIMIT_DATA, RLIM_INFINITY};
+
+#[macro_use]
+extern crate user_lib;
+
+const PAGE_SIZE: usize = 4096;
+const MAX_PAGES: usize = 256;
+const ROUNDS: usize = 2000;
+
+/// word expected at the start of every heap page, 0 for a fresh page
+static mut SHADOW: [u64; MAX_PAGES] = [0; MAX_PAGES];
+
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// compare the first word of every page below the break with the shadow
+fn verify(base: usize, size: usize, shadow: &[u64; MAX_PAGES]) -> Result<(), usize> {
+    let mut sum = 0u64;
+    let mut expected = 0u64;
+    for page in 0..size.div_ceil(PAGE_SIZE) {
+        let word = unsafe { ((base + page * PAGE_SIZE) as *const u64).read_volatile() };
+        sum = sum.wrapping_mul(31).wrapping_add(word);
+        expected = expected.wrapping_mul(31).wrapping_add(shadow[page]);
+        if word != shadow[page] {
+            return Err(page);
+        }
+    }
+    if sum == expected { Ok(()) } else { Err(usize::MAX) }
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let shadow = unsafe { &mut *core::ptr::addr_of_mut!(SHADOW) };
+    let mut ok = true;
+    let base = brk(0) as usize;
+    if base % PAGE_SIZE != 0 {
+        println!("test_brk_fuzz: the heap already exists, skipped");
+        return 0;
+    }
+    ok &= check(brk(0) as usize == base, "brk(0) is a pure query");
+    ok &= check(brk(base - PAGE_SIZE) as usize == base, "brk below the heap");
+
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let mut size = 0;
+    for round in 0..ROUNDS {
+        // word granularity keeps the break mid-page most of the time
+        let new_size = rng.below(MAX_PAGES * PAGE_SIZE / 8) * 8;
+        if brk(base + new_size) as usize != base + new_size {
+            println!("test_brk_fuzz: round {} brk to {:#x} refused", round, new_size);
+            ok = false;
+            break;
+        }
+        if new_size < size {
+            // the pages above the one holding the break are gone
+            for page in new_size.div_ceil(PAGE_SIZE)..MAX_PAGES {
+                shadow[page] = 0;
+            }
+        }
+        size = new_size;
+        if let Err(page) = verify(base, size, shadow) {
+            println!("test_brk_fuzz: round {} heap content mismatch at page {}", round, page);
+            ok = false;
+            break;
+        }
+        for _ in 0..8 {
+            if size == 0 {
+                break;
+            }
+            let page = rng.below(size.div_ceil(PAGE_SIZE));
+            let value = rng.next() | 1;
+            unsafe { ((base + page * PAGE_SIZE) as *mut u64).write_volatile(value); }
+            shadow[page] = value;
+        }
+    }
+
+    // shrinking to nothing destroys the heap, growing again gives zeroed pages
+    ok &= check(brk(base) as usize == base, "shrink to zero");
+    ok &= check(brk(0) as usize == base, "break after shrink to zero");
+    ok &= check(brk(base + PAGE_SIZE) as usize == base + PAGE_SIZE, "regrow");
+    ok &= check(unsafe { (base as *const u64).read_volatile() } == 0, "regrown page is zeroed");
+
+    // RLIMIT_DATA bounds the heap
+    let limit = RLimit { rlim_cur: 4 * PAGE_SIZE, rlim_max: RLIM_INFINITY };
+    ok &= check(setrlimit(RLIMIT_DATA, &limit) == 0, "setrlimit");
+    ok &= check(brk(base + 8 * PAGE_SIZE) as usize == base + PAGE_SIZE, "brk over RLIMIT_DATA");
+    let limit = RLimit { rlim_cur: RLIM_INFINITY, rlim_max: RLIM_INFINITY };
+    setrlimit(RLIMIT_DATA, &limit);
+
+    // growing into another mapping keeps the old break
+    let blocker = base + (MAX_PAGES + 16) * PAGE_SIZE;
+    let ret = mmap(
+        blocker, PAGE_SIZE,
+        MmapProt::PROT_READ | MmapProt::PROT_WRITE,
+        MmapFlags::MAP_PRIVATE | MmapFlags::MAP_ANONYMOUS | MmapFlags::MAP_FIXED,
+        usize::MAX, 0
+    );
+    if ret as usize == blocker {
+        ok &= check(brk(blocker + PAGE_SIZE) as usize == base + PAGE_SIZE, "brk into a mapping");
+        ok &= check(brk(blocker) as usize == blocker, "brk up to a mapping");
+    }
+    brk(base);
+
+    if ok {
+        println!("test_brk_fuzz passed!");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,98 @@
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use user_lib::{
+    check, clock_gettime, exit, get_time_ms, sched_setaffinity, thread_spawn, yield_, TimeSpec, CLOCK_MONOTONIC,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const THREADS: usize = 2;
+const MAX_HARTS: usize = 8;
+/// how long the threads run unless a number of seconds is given
+const DEFAULT_MS: isize = 3000;
+/// clock reads between two hops to another hart
+const READS_PER_HOP: usize = 64;
+
+static mut STACKS: [[u8; 16384]; THREADS] = [[0; 16384]; THREADS];
+/// bit i set when hart i can be moved to
+static HARTS: AtomicUsize = AtomicUsize::new(0);
+static RUN_MS: AtomicUsize = AtomicUsize::new(0);
+static FINISHED: AtomicUsize = AtomicUsize::new(0);
+static BACKWARDS: AtomicUsize = AtomicUsize::new(0);
+static HOPS: AtomicUsize = AtomicUsize::new(0);
+
+fn monotonic_ns() -> u128 {
+    let mut ts = TimeSpec::default();
+    clock_gettime(CLOCK_MONOTONIC, &mut ts);
+    ts.sec as u128 * 1_000_000_000 + ts.nsec as u128
+}
+
+fn pin(hart: usize) -> bool {
+    sched_setaffinity(0, &[1u8 << hart]) == 0
+}
+
+/// read the clock in a loop, hopping to the next hart every few reads
+extern "C" fn bouncer(first: usize) -> ! {
+    let harts = HARTS.load(Ordering::Relaxed);
+    let end = get_time_ms() + RUN_MS.load(Ordering::Relaxed) as isize;
+    let mut hart = first;
+    let mut last = monotonic_ns();
+    while get_time_ms() < end {
+        loop {
+            hart = (hart + 1) % MAX_HARTS;
+            if harts & (1 << hart) != 0 {
+                break;
+            }
+        }
+        pin(hart);
+        HOPS.fetch_add(1, Ordering::Relaxed);
+        for _ in 0..READS_PER_HOP {
+            let now = monotonic_ns();
+            if now < last {
+                BACKWARDS.fetch_add(1, Ordering::Relaxed);
+            }
+            last = now;
+        }
+    }
+    FINISHED.fetch_add(1, Ordering::Release);
+    exit(0);
+}
+
+#[no_mangle]
+pub fn main(args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    let run_ms = args.get(1).and_then(|s| s.parse::<isize>().ok()).map_or(DEFAULT_MS, |secs| secs * 1000);
+    RUN_MS.store(run_ms as usize, Ordering::Relaxed);
+    let harts = (0..MAX_HARTS).filter(|&hart| pin(hart)).fold(0, |mask, hart| mask | 1 << hart);
+    sched_setaffinity(0, &[harts as u8]);
+    HARTS.store(harts, Ordering::Relaxed);
+    if harts.count_ones() < 2 {
+        println!("test_clock_monotonic: a single hart, nothing to hop between");
+    }
+
+    for i in 0..THREADS {
+        let stack = unsafe { &mut (*core::ptr::addr_of_mut!(STACKS))[i] };
+        ok &= check(thread_spawn(stack, bouncer, i) > 0, "spawn a thread");
+    }
+    let timeout = get_time_ms() + run_ms * 2 + 5000;
+    while FINISHED.load(Ordering::Acquire) < THREADS && get_time_ms() < timeout {
+        yield_();
+    }
+    ok &= check(FINISHED.load(Ordering::Acquire) == THREADS, "threads finish");
+    ok &= check(BACKWARDS.load(Ordering::Relaxed) == 0, "monotonic time never goes backwards");
+    if !ok {
+        println!("test_clock_monotonic: {} regressions over {} hops", BACKWARDS.load(Ordering::Relaxed), HOPS.load(Ordering::Relaxed));
+    }
+
+    if ok {
+        println!("test_clock_monotonic: passed");
+        0
+    } else {
+        -1
+    }
+}
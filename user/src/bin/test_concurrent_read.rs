@@ -0,0 +1,98 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{close, exit, fork, get_time_ms, open, pread, unlink, waitpid, write, OpenFlags};
+
+#[macro_use]
+extern crate user_lib;
+
+const FILE: &str = "/test_concurrent_read_file\0";
+const PAGE: usize = 4096;
+const PAGES: usize = 64;
+const ROUNDS: usize = 200;
+const MAX_READERS: usize = 8;
+
+fn byte_at(offset: usize) -> u8 {
+    (offset / PAGE) as u8 ^ offset as u8
+}
+
+/// read the whole cached file `ROUNDS` times, checking every byte
+fn reader() -> i32 {
+    let fd = open(FILE, OpenFlags::RDONLY);
+    if fd < 0 {
+        return -1;
+    }
+    let mut buf = [0u8; PAGE];
+    for _ in 0..ROUNDS {
+        for page in 0..PAGES {
+            let offset = page * PAGE;
+            if pread(fd as usize, &mut buf, offset) != PAGE as isize {
+                return -1;
+            }
+            if buf.iter().enumerate().any(|(i, &b)| b != byte_at(offset + i)) {
+                return -1;
+            }
+        }
+    }
+    close(fd as usize);
+    0
+}
+
+/// run `n` readers at once, return the elapsed milliseconds or -1 on failure
+fn run(n: usize) -> isize {
+    let start = get_time_ms();
+    let mut pids = [0usize; MAX_READERS];
+    for pid in pids.iter_mut().take(n) {
+        let ret = fork();
+        if ret == 0 {
+            exit(reader());
+        }
+        *pid = ret as usize;
+    }
+    let mut ok = true;
+    for &pid in pids.iter().take(n) {
+        let mut status = 0;
+        waitpid(pid, &mut status);
+        ok &= status == 0;
+    }
+    if ok { get_time_ms() - start } else { -1 }
+}
+
+/// many processes reading the same cached ext4 file at once,
+/// the time per reader should stay flat as readers are added
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC);
+    if fd < 0 {
+        println!("test_concurrent_read: open failed");
+        return -1;
+    }
+    let mut page = [0u8; PAGE];
+    for n in 0..PAGES {
+        for (i, b) in page.iter_mut().enumerate() {
+            *b = byte_at(n * PAGE + i);
+        }
+        write(fd as usize, &page, PAGE);
+    }
+    close(fd as usize);
+
+    let mut ok = true;
+    let mut n = 1;
+    while n <= MAX_READERS {
+        let ms = run(n);
+        if ms < 0 {
+            println!("test_concurrent_read: {} readers saw bad data", n);
+            ok = false;
+            break;
+        }
+        println!("test_concurrent_read: {} readers, {} ms", n, ms);
+        n *= 2;
+    }
+    unlink(FILE);
+    if ok {
+        println!("test_concurrent_read passed!");
+        0
+    } else {
+        -1
+    }
+}
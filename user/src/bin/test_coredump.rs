@@ -0,0 +1,122 @@
+#![no_std]
+#![no_main]
+
+use alloc::format;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use user_lib::{
+    check, close, exit, fork, open, read, setrlimit, sleep, thread_spawn, unlink, waitpid, OpenFlags, RLimit,
+    RLIMIT_CORE, RLIM_INFINITY,
+};
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+const ET_CORE: u16 = 4;
+const PT_NOTE: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+
+static mut SPINNER_STACK: [u8; 16384] = [0; 16384];
+/// the spinner thread keeps writing it while the main thread crashes
+static SPINS: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn spinner(_arg: usize) -> ! {
+    loop {
+        SPINS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// fork a child which dies by a null dereference, with a second thread running beside it
+/// if `spin`, return its pid and wait status
+fn crash_child(core_limit: usize, spin: bool) -> (usize, i32) {
+    let pid = fork();
+    if pid == 0 {
+        let limit = RLimit { rlim_cur: core_limit, rlim_max: RLIM_INFINITY };
+        setrlimit(RLIMIT_CORE, &limit);
+        if spin {
+            let stack = unsafe { &mut *core::ptr::addr_of_mut!(SPINNER_STACK) };
+            if thread_spawn(stack, spinner, 0) < 0 {
+                exit(-1);
+            }
+            while SPINS.load(Ordering::Relaxed) == 0 {
+                sleep(1);
+            }
+        }
+        unsafe { (core::ptr::null_mut::<usize>()).write_volatile(0x1234); }
+        exit(0);
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    (pid as usize, status)
+}
+
+/// the NT_PRSTATUS notes of the core file `fd`, one for each thread
+fn prstatus_notes(fd: usize) -> usize {
+    let mut buf = [0u8; 8192];
+    let len = read(fd, &mut buf).max(0) as usize;
+    let word = |at: usize| u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]]);
+    let dword = |at: usize| word(at) as usize | (word(at + 4) as usize) << 32;
+    // the PT_NOTE program header comes first
+    let (mut at, filesz) = (dword(64 + 8), dword(64 + 32));
+    let end = (at + filesz).min(len);
+    let mut count = 0;
+    while at + 12 <= end {
+        let (namesz, descsz, note_type) = (word(at) as usize, word(at + 4) as usize, word(at + 8));
+        count += (note_type == NT_PRSTATUS) as usize;
+        at += 12 + namesz.next_multiple_of(4) + descsz.next_multiple_of(4);
+    }
+    count
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    // no core file while RLIMIT_CORE is zero
+    let (pid, status) = crash_child(0, false);
+    ok &= check(status & 0x7f == 11, "killed by SIGSEGV");
+    ok &= check(status & 0x80 == 0, "no WCOREDUMP with RLIMIT_CORE 0");
+    let path = format!("core.{}\0", pid);
+    ok &= check(open(&path, OpenFlags::RDONLY) < 0, "no core file with RLIMIT_CORE 0");
+
+    let (pid, status) = crash_child(RLIM_INFINITY, false);
+    ok &= check(status & 0x7f == 11, "killed by SIGSEGV");
+    ok &= check(status & 0x80 != 0, "WCOREDUMP");
+    let path = format!("core.{}\0", pid);
+    let fd = open(&path, OpenFlags::RDONLY);
+    if check(fd >= 0, "open the core file") {
+        let mut ehdr = [0u8; 64 + 56];
+        ok &= check(read(fd as usize, &mut ehdr) == ehdr.len() as isize, "read the headers");
+        ok &= check(&ehdr[..4] == b"\x7fELF", "ELF magic");
+        ok &= check(u16::from_le_bytes([ehdr[16], ehdr[17]]) == ET_CORE, "ET_CORE");
+        let phnum = u16::from_le_bytes([ehdr[56], ehdr[57]]);
+        ok &= check(phnum > 1, "program headers");
+        let p_type = u32::from_le_bytes([ehdr[64], ehdr[65], ehdr[66], ehdr[67]]);
+        ok &= check(p_type == PT_NOTE, "PT_NOTE first");
+        close(fd as usize);
+        unlink(&path);
+    } else {
+        ok = false;
+    }
+
+    // the thread running beside is killed before the dump and has its registers in the core
+    let (pid, status) = crash_child(RLIM_INFINITY, true);
+    ok &= check(status & 0x7f == 11 && status & 0x80 != 0, "WCOREDUMP of a process of two threads");
+    let path = format!("core.{}\0", pid);
+    let fd = open(&path, OpenFlags::RDONLY);
+    if check(fd >= 0, "open the core file of two threads") {
+        ok &= check(prstatus_notes(fd as usize) == 2, "a NT_PRSTATUS for each thread");
+        close(fd as usize);
+        unlink(&path);
+    } else {
+        ok = false;
+    }
+
+    if ok {
+        println!("test_coredump passed!");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,115 @@
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use user_lib::{check, exit, fork, sched_setaffinity, thread_spawn, waitpid, yield_};
+
+#[macro_use]
+extern crate user_lib;
+
+const FORKS: usize = 100;
+const STACK_SIZE: usize = 16 * 1024;
+/// four pages of words, so a stale write and a cow copy can land on different pages
+const WORDS: usize = 4 * 4096 / 8;
+/// how long a child keeps checking that its copy does not move
+const CHILD_ROUNDS: usize = 64;
+
+static mut WRITER_STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+/// the writer fills it with one generation after the other, in ascending order
+static PATTERN: [AtomicU64; WORDS] = [const { AtomicU64::new(0) }; WORDS];
+static STOP: AtomicBool = AtomicBool::new(false);
+static WRITER_DONE: AtomicBool = AtomicBool::new(false);
+
+/// pin the calling thread, ignoring harts which are offline
+fn pin_to(hart: usize) {
+    sched_setaffinity(0, &[1 << hart]);
+}
+
+extern "C" fn writer(_arg: usize) -> ! {
+    pin_to(1);
+    let mut generation = 0;
+    while !STOP.load(Ordering::Relaxed) {
+        generation += 1;
+        for word in PATTERN.iter() {
+            // release keeps the stores in order for the snapshot check
+            word.store(generation, Ordering::Release);
+        }
+    }
+    WRITER_DONE.store(true, Ordering::Release);
+    exit(0);
+}
+
+/// a snapshot taken at one instant is a run of generation g + 1 followed by generation g
+fn consistent(snapshot: &[u64]) -> bool {
+    let (first, last) = (snapshot[0], snapshot[WORDS - 1]);
+    snapshot.windows(2).all(|w| w[0] >= w[1]) && first - last <= 1
+}
+
+/// the child's copy must be a snapshot of the parent at fork, which never moves afterwards
+fn child() -> ! {
+    let mut snapshot = [0u64; WORDS];
+    for (value, word) in snapshot.iter_mut().zip(PATTERN.iter()) {
+        *value = word.load(Ordering::Relaxed);
+    }
+    if !check(consistent(&snapshot), "snapshot in the child") {
+        exit(-1);
+    }
+    for _ in 0..CHILD_ROUNDS {
+        yield_();
+        let moved = PATTERN.iter().zip(snapshot.iter())
+            .any(|(word, &value)| word.load(Ordering::Relaxed) != value);
+        if !check(!moved, "child copy unchanged") {
+            exit(-1);
+        }
+    }
+    exit(0);
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    pin_to(0);
+    let stack = unsafe { &mut *core::ptr::addr_of_mut!(WRITER_STACK) };
+    if thread_spawn(stack, writer, 0) < 0 {
+        println!("test_cow_race: thread_spawn failed");
+        return -1;
+    }
+    // let the writer get going on its hart
+    while PATTERN[WORDS - 1].load(Ordering::Relaxed) == 0 {
+        yield_();
+    }
+
+    let mut failed = 0;
+    for _ in 0..FORKS {
+        let pid = fork();
+        if pid == 0 {
+            child();
+        }
+        if pid < 0 {
+            println!("test_cow_race: fork failed");
+            failed += 1;
+            continue;
+        }
+        let mut status = 0;
+        waitpid(pid as usize, &mut status);
+        if status != 0 {
+            failed += 1;
+        }
+    }
+    STOP.store(true, Ordering::Relaxed);
+    while !WRITER_DONE.load(Ordering::Acquire) {
+        yield_();
+    }
+
+    ok &= check(failed == 0, "every child saw its own copy");
+    if ok {
+        println!("test_cow_race passed!");
+        0
+    } else {
+        println!("test_cow_race: {} of {} children failed", failed, FORKS);
+        -1
+    }
+}
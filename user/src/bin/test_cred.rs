@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, exit, fchmod, fork, geteuid, getgroups, getuid, kill, open, setgroups, setuid, sleep, unlink, waitpid,
+    OpenFlags, EACCES, EPERM, SIGCHLD, SIGKILL,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const FILE: &str = "/test_cred_file\0";
+
+/// the unprivileged side, runs as uid 1000 and returns the number of failed checks
+fn unprivileged(root_pid: isize) -> i32 {
+    let mut failed = 0;
+    let mut expect = |ok: bool, what: &str| {
+        if !check(ok, what) {
+            failed += 1;
+        }
+    };
+    expect(setuid(1000) == 0, "setuid(1000)");
+    expect(getuid() == 1000 && geteuid() == 1000, "uid 1000 after setuid");
+    expect(kill(root_pid, SIGKILL) == EPERM, "kill a root process");
+    expect(kill(-1, SIGCHLD) == EPERM, "kill -1 with only root processes to signal");
+    expect(open(FILE, OpenFlags::WRONLY) == EACCES, "write a root-owned 0644 file");
+    let fd = open(FILE, OpenFlags::RDONLY);
+    expect(fd >= 0, "read a root-owned 0644 file");
+    if fd >= 0 {
+        close(fd as usize);
+    }
+    expect(setuid(0) == EPERM, "regain root");
+    expect(setgroups(&[]) == EPERM, "setgroups without root");
+    failed
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::WRONLY);
+    if fd < 0 {
+        println!("test_cred: can not create {}", FILE);
+        return -1;
+    }
+    ok &= check(fchmod(fd as usize, 0o644) == 0, "fchmod 0644");
+    close(fd as usize);
+
+    ok &= check(setgroups(&[10, 20]) == 0, "setgroups as root");
+    let mut groups = [0u32; 4];
+    ok &= check(getgroups(&mut []) == 2, "getgroups count");
+    ok &= check(getgroups(&mut groups) == 2 && groups[..2] == [10, 20], "getgroups list");
+
+    // a root process for the unprivileged child to signal
+    let root_pid = fork();
+    if root_pid == 0 {
+        loop {
+            sleep(100);
+        }
+    }
+
+    let pid = fork();
+    if pid == 0 {
+        exit(unprivileged(root_pid));
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    ok &= check(status & 0x7f == 0 && (status >> 8) & 0xff == 0, "unprivileged child");
+
+    // root may still signal it
+    ok &= check(kill(root_pid, SIGKILL) == 0, "root kills the root child");
+    waitpid(root_pid as usize, &mut status);
+    ok &= check(getuid() == 0, "parent still root");
+    unlink(FILE);
+
+    if ok {
+        println!("test_cred passed!");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,114 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, exit, fork, getpid, getsid, ioctl, open, read, setpgid, setsid, sigaction_flags, sleep, vhangup,
+    waitpid, OpenFlags, EIO, ENOTTY, ENXIO, EPERM, SIGHUP, TIOCGSID, TIOCNOTTY, TIOCSCTTY, TIOCSPGRP,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const TTY: &str = "/dev/tty\0";
+const SIG_DFL: usize = 0;
+const SIG_IGN: usize = 1;
+
+/// whether /dev/tty opens, that is the caller has a controlling terminal
+fn has_ctty() -> bool {
+    let fd = open(TTY, OpenFlags::RDWR);
+    if fd >= 0 {
+        close(fd as usize);
+    }
+    fd >= 0
+}
+
+/// the session of the tty on fd 0, as the caller sees it
+fn tty_sid() -> isize {
+    let mut sid = 0u32;
+    match ioctl(0, TIOCGSID, &mut sid as *mut u32 as usize) {
+        0 => sid as isize,
+        err => err,
+    }
+}
+
+/// the foreground job: sleeps until the hangup kills it
+fn foreground_job() -> ! {
+    sigaction_flags(SIGHUP, SIG_DFL, 0);
+    setpgid(0, 0);
+    sleep(3000);
+    exit(0)
+}
+
+/// the daemon: leaves the session and outlives the hangup
+fn daemon() -> ! {
+    sigaction_flags(SIGHUP, SIG_DFL, 0);
+    let ok = setsid() == getpid() && !has_ctty();
+    sleep(500);
+    exit(if ok && getsid(0) == getpid() { 0 } else { 1 })
+}
+
+/// a new session takes the tty, starts a job and a daemon and hangs the tty up
+fn session_leader() -> ! {
+    let pid = getpid();
+    let mut ok = check(setsid() == pid && getsid(0) == pid, "setsid");
+    ok &= check(open(TTY, OpenFlags::RDWR) == ENXIO && tty_sid() == ENOTTY, "setsid drops the controlling terminal");
+    ok &= check(setsid() == EPERM, "a group leader cannot setsid");
+    ok &= check(ioctl(0, TIOCSCTTY, 0) == EPERM, "the tty of another session is not taken without force");
+    ok &= check(ioctl(0, TIOCSCTTY, 1) == 0, "root steals the tty");
+    ok &= check(has_ctty() && tty_sid() == pid, "the tty is the controlling terminal");
+    sigaction_flags(SIGHUP, SIG_IGN, 0);
+
+    let job = fork();
+    if job == 0 {
+        foreground_job();
+    }
+    setpgid(job as usize, job as usize);
+    let fg = job as u32;
+    ok &= check(ioctl(0, TIOCSPGRP, &fg as *const u32 as usize) == 0, "set the foreground group");
+    let daemon_pid = fork();
+    if daemon_pid == 0 {
+        daemon();
+    }
+    sleep(100);
+
+    ok &= check(vhangup() == 0, "vhangup");
+    let mut status = 0;
+    waitpid(job as usize, &mut status);
+    ok &= check(status & 0x7f == SIGHUP, "the foreground job dies of SIGHUP");
+    let mut buf = [0u8; 1];
+    ok &= check(read(0, &mut buf) == EIO, "a read of the hung up tty is EIO");
+    ok &= check(!has_ctty(), "the hangup takes the controlling terminal");
+    waitpid(daemon_pid as usize, &mut status);
+    ok &= check(status == 0, "the daemon survives the hangup");
+    exit(if ok { 0 } else { 1 })
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = check(has_ctty(), "the console is the controlling terminal from the boot");
+    let pid = fork();
+    if pid == 0 {
+        session_leader();
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    ok &= check(status == 0, "the session of the child");
+    ok &= check(has_ctty(), "the tty goes back to the session of initproc");
+
+    // a process which is not the leader only gives it up for itself
+    let pid = fork();
+    if pid == 0 {
+        let ok = ioctl(0, TIOCNOTTY, 0) == 0 && !has_ctty() && ioctl(0, TIOCNOTTY, 0) == ENOTTY;
+        exit(if ok { 0 } else { 1 });
+    }
+    waitpid(pid as usize, &mut status);
+    ok &= check(status == 0, "TIOCNOTTY");
+    ok &= check(has_ctty(), "TIOCNOTTY of another process leaves the tty of the caller");
+
+    if ok {
+        println!("test_ctty: passed");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,70 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{check, close, get_time_ms, mkdir, open, rename, rmdir, unlink, OpenFlags, ENOENT};
+
+#[macro_use]
+extern crate user_lib;
+
+const LOOKUPS: usize = 10000;
+
+/// open and close, return the open result
+fn probe(path: &str) -> isize {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd >= 0 {
+        close(fd as usize);
+    }
+    fd
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    mkdir("/test_dcache\0");
+
+    // repeated lookups of a missing file are served by the negative dentry
+    let start = get_time_ms();
+    for _ in 0..LOOKUPS {
+        if probe("/test_dcache/missing.conf\0") != ENOENT {
+            ok &= check(false, "lookup of missing file");
+            break;
+        }
+    }
+    println!("{} missing lookups in {} ms", LOOKUPS, get_time_ms() - start);
+
+    // creating the file must invalidate the negative entry
+    let fd = open("/test_dcache/missing.conf\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    ok &= check(fd >= 0, "create");
+    close(fd as usize);
+    ok &= check(probe("/test_dcache/missing.conf\0") >= 0, "lookup after create");
+
+    // and removing it brings the negative entry back
+    ok &= check(unlink("/test_dcache/missing.conf\0") == 0, "unlink");
+    ok &= check(probe("/test_dcache/missing.conf\0") == ENOENT, "lookup after unlink");
+
+    // children of a renamed directory move with it
+    mkdir("/test_dcache/old\0");
+    let fd = open("/test_dcache/old/child\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    close(fd as usize);
+    ok &= check(probe("/test_dcache/old/child\0") >= 0, "lookup before rename");
+    ok &= check(rename("/test_dcache/old\0", "/test_dcache/new\0") == 0, "rename dir");
+    ok &= check(probe("/test_dcache/new/child\0") >= 0, "lookup child under new name");
+    ok &= check(probe("/test_dcache/old/child\0") == ENOENT, "lookup child under old name");
+    ok &= check(probe("/test_dcache/old\0") == ENOENT, "lookup old dir");
+
+    // a directory created again under a removed name starts empty
+    unlink("/test_dcache/new/child\0");
+    rmdir("/test_dcache/new\0");
+    mkdir("/test_dcache/new\0");
+    ok &= check(probe("/test_dcache/new/child\0") == ENOENT, "lookup in recreated dir");
+
+    rmdir("/test_dcache/new\0");
+    rmdir("/test_dcache\0");
+
+    if ok {
+        println!("test_dcache passed!");
+        0
+    } else {
+        -1
+    }
+}
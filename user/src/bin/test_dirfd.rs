@@ -0,0 +1,110 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    chdir, check, close, fchdir, fstat, getcwd, getdents, mkdir, open, openat, read, rename, renameat, rmdir, unlink,
+    unlinkat, write, OpenFlags, Stat, AT_REMOVEDIR, EISDIR, ENOENT, ENOTDIR,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+/// the bytes of a linux_dirent64 before the name
+const DIRENT_NAME: usize = 19;
+
+/// whether listing the directory of `fd` from its start shows `name`
+fn lists(fd: usize, name: &str) -> bool {
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = getdents(fd, &mut buf);
+        if n <= 0 {
+            return false;
+        }
+        let mut off = 0;
+        while off < n as usize {
+            let reclen = u16::from_le_bytes([buf[off + 16], buf[off + 17]]) as usize;
+            let rec = &buf[off + DIRENT_NAME..off + reclen];
+            let len = rec.iter().position(|&c| c == 0).unwrap_or(rec.len());
+            if &rec[..len] == name.as_bytes() {
+                return true;
+            }
+            off += reclen;
+        }
+    }
+}
+
+fn touch(dirfd: isize, name: &str) -> bool {
+    let fd = openat(dirfd, name, OpenFlags::CREATE | OpenFlags::WRONLY);
+    fd >= 0 && close(fd as usize) == 0
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    let mut buf = [0u8; 64];
+    mkdir("/test_dirfd\0");
+
+    // a directory opens read only, and neither reads nor writes
+    let dir = open("/test_dirfd\0", OpenFlags::RDONLY);
+    ok &= check(dir >= 0, "open a directory read only");
+    ok &= check(read(dir as usize, &mut buf) == EISDIR, "read of a directory is EISDIR");
+    ok &= check(write(dir as usize, b"x", 1) == EISDIR, "write of a directory is EISDIR");
+    ok &= check(open("/test_dirfd\0", OpenFlags::WRONLY) == EISDIR, "open a directory write only is EISDIR");
+    ok &= check(open("/test_dirfd\0", OpenFlags::RDWR) == EISDIR, "open a directory read write is EISDIR");
+    ok &= check(open("/test_dirfd\0", OpenFlags::CREATE | OpenFlags::RDONLY) == EISDIR, "O_CREAT on a directory is EISDIR");
+
+    // fstat, getdents and the *at calls through the directory fd
+    let mut st = Stat::default();
+    ok &= check(fstat(dir as usize, &mut st) == 0 && st.st_mode & S_IFMT == S_IFDIR, "fstat of a directory fd");
+    ok &= check(touch(dir, "a\0"), "create through the dirfd");
+    ok &= check(lists(dir as usize, "a"), "getdents lists the new file");
+    ok &= check(renameat(dir, "a\0", dir, "b\0") == 0, "renameat through the dirfd");
+    ok &= check(openat(dir, "a\0", OpenFlags::RDONLY) == ENOENT, "the old name is gone");
+    ok &= check(unlinkat(dir, "b\0", 0) == 0, "unlinkat through the dirfd");
+    ok &= check(openat(dir, "b\0", OpenFlags::RDONLY) == ENOENT, "the unlinked name is gone");
+
+    // a dirfd must name a directory
+    ok &= check(touch(dir, "file\0"), "create a regular file");
+    let file = openat(dir, "file\0", OpenFlags::RDONLY);
+    ok &= check(file >= 0, "open the regular file");
+    ok &= check(openat(file, "x\0", OpenFlags::RDONLY) == ENOTDIR, "openat relative to a file is ENOTDIR");
+    ok &= check(unlinkat(file, "x\0", 0) == ENOTDIR, "unlinkat relative to a file is ENOTDIR");
+    close(file as usize);
+    unlinkat(dir, "file\0", 0);
+
+    // the dirfd keeps naming the directory after it is renamed
+    mkdir("/test_dirfd/sub\0");
+    let sub = open("/test_dirfd/sub\0", OpenFlags::RDONLY | OpenFlags::DIRECTORY);
+    ok &= check(sub >= 0, "open a subdirectory");
+    ok &= check(touch(sub, "child\0"), "create a child");
+    ok &= check(rename("/test_dirfd/sub\0", "/test_dirfd/moved\0") == 0, "rename the subdirectory");
+    let child = openat(sub, "child\0", OpenFlags::RDONLY);
+    ok &= check(child >= 0, "openat the child after the rename");
+    close(child as usize);
+    ok &= check(touch(sub, "late\0") && open("/test_dirfd/moved/late\0", OpenFlags::RDONLY) >= 0, "create after the rename lands in the new name");
+    let up = openat(sub, "..\0", OpenFlags::RDONLY);
+    ok &= check(up >= 0 && lists(up as usize, "moved"), "\"..\" of the renamed dirfd");
+    close(up as usize);
+    ok &= check(fstat(sub as usize, &mut st) == 0 && st.st_mode & S_IFMT == S_IFDIR, "fstat after the rename");
+    ok &= check(lists(sub as usize, "child"), "getdents after the rename");
+    ok &= check(fchdir(sub as usize) == 0, "fchdir after the rename");
+    let len = getcwd(&mut buf);
+    ok &= check(len > 0 && buf.starts_with(b"/test_dirfd/moved\0"), "cwd is the new name");
+    chdir("/\0");
+    close(sub as usize);
+
+    unlink("/test_dirfd/moved/child\0");
+    unlink("/test_dirfd/moved/late\0");
+    unlinkat(dir, "moved\0", AT_REMOVEDIR);
+    close(dir as usize);
+    rmdir("/test_dirfd\0");
+
+    if ok {
+        println!("test_dirfd: passed");
+        0
+    } else {
+        -1
+    }
+}
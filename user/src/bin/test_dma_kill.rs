@@ -0,0 +1,99 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, dma_pages, exit, fork, fsync, kill, open, pread, sleep, unlink, waitpid, write, OpenFlags, SIGKILL,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+/// the tasks streaming to the disk, killed in the middle of it
+const WRITERS: usize = 4;
+const ROUNDS: usize = 3;
+const CHUNK: usize = 256 * 1024;
+/// how long the DMA memory may take to settle once the writers are gone
+const SETTLE_MS: usize = 2000;
+
+static mut BUF: [u8; CHUNK] = [0; CHUNK];
+
+/// the file of writer `i`, on ext4
+fn file_name(i: usize) -> &'static str {
+    ["/test_dma_kill0\0", "/test_dma_kill1\0", "/test_dma_kill2\0", "/test_dma_kill3\0"][i]
+}
+
+/// write, sync and read back `name` until killed
+fn writer(name: &str) -> ! {
+    let fd = open(name, OpenFlags::CREATE | OpenFlags::RDWR | OpenFlags::TRUNC);
+    if fd < 0 {
+        exit(1);
+    }
+    let buf = unsafe { &mut *core::ptr::addr_of_mut!(BUF) };
+    for round in 0.. {
+        buf.iter_mut().enumerate().for_each(|(i, b)| *b = (i + round) as u8);
+        write(fd as usize, buf, CHUNK);
+        fsync(fd as usize);
+        pread(fd as usize, buf, 0);
+    }
+    unreachable!()
+}
+
+/// wait for the DMA memory to come back to `baseline`, return what it settled at
+fn settle(baseline: isize) -> isize {
+    let mut pages = dma_pages();
+    for _ in 0..SETTLE_MS / 10 {
+        if pages == baseline {
+            break;
+        }
+        sleep(10);
+        pages = dma_pages();
+    }
+    pages
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let baseline = dma_pages();
+    let mut ok = check(baseline > 0, "the rings of the queues are counted");
+
+    for round in 0..ROUNDS {
+        let mut pids = [0isize; WRITERS];
+        for (i, pid) in pids.iter_mut().enumerate() {
+            *pid = fork();
+            if *pid == 0 {
+                writer(file_name(i));
+            }
+        }
+        // kill them at different points of a write, a sync or a read
+        sleep(100 + 50 * round);
+        for (i, pid) in pids.iter().enumerate() {
+            kill(*pid, SIGKILL);
+            sleep(7 * i);
+        }
+        for pid in pids {
+            let mut status = 0;
+            waitpid(pid as usize, &mut status);
+        }
+        let pages = settle(baseline);
+        println!("test_dma_kill: round {}: {} DMA pages, {} before", round, pages, baseline);
+        ok &= check(pages == baseline, "the DMA memory is back after the kills");
+    }
+    for i in 0..WRITERS {
+        unlink(file_name(i));
+    }
+    // a fresh file still works after the requests of the killed tasks
+    let fd = open(file_name(0), OpenFlags::CREATE | OpenFlags::RDWR | OpenFlags::TRUNC);
+    let mut back = [0u8; 4];
+    ok &= check(fd >= 0 && write(fd as usize, b"dma!", 4) == 4 && fsync(fd as usize) == 0, "write after the kills");
+    ok &= check(pread(fd as usize, &mut back, 0) == 4 && &back == b"dma!", "read after the kills");
+    close(fd as usize);
+    unlink(file_name(0));
+    ok &= check(settle(baseline) == baseline, "the DMA memory is back at the end");
+
+    if ok {
+        println!("test_dma_kill: passed");
+        0
+    } else {
+        -1
+    }
+}
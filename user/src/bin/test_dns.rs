@@ -0,0 +1,109 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{close, connect, get_time_ms, recvfrom, sendto, socket, yield_, SockaddrIn, EAGAIN, ECONNREFUSED};
+
+#[macro_use]
+extern crate user_lib;
+
+const AF_INET: i32 = 2;
+const SOCK_STREAM: i32 = 1;
+const SOCK_DGRAM: i32 = 2;
+const SOCK_NONBLOCK: i32 = 0x800;
+const IPPROTO_TCP: i32 = 6;
+const IPPROTO_UDP: i32 = 17;
+
+/// the qemu user network dns server and host
+const DNS_ADDR: u32 = 0x0a000203; // 10.0.2.3
+const DNS_PORT: u16 = 53;
+const HOST_ADDR: u32 = 0x0a000202; // 10.0.2.2
+/// nothing listens on the discard port of the host
+const CLOSED_PORT: u16 = 9;
+const TIMEOUT_MS: isize = 5_000;
+const QUERY_ID: u16 = 0x4348;
+
+/// an A query for example.com with recursion desired
+fn build_query(buf: &mut [u8]) -> usize {
+    let header = [
+        (QUERY_ID >> 8) as u8, QUERY_ID as u8,
+        0x01, 0x00, // RD
+        0x00, 0x01, // one question
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+    let name = b"\x07example\x03com\x00";
+    let tail = [0x00, 0x01, 0x00, 0x01]; // type A, class IN
+    let mut len = 0;
+    for part in [&header[..], &name[..], &tail[..]] {
+        buf[len..len + part.len()].copy_from_slice(part);
+        len += part.len();
+    }
+    len
+}
+
+/// send a query to the dns server and wait for the answer with the same id
+fn dns_query() -> bool {
+    let fd = socket(AF_INET, SOCK_DGRAM | SOCK_NONBLOCK, IPPROTO_UDP);
+    if fd < 0 {
+        println!("test_dns: udp socket failed");
+        return false;
+    }
+    let fd = fd as usize;
+    let server = SockaddrIn::new(DNS_ADDR.to_be(), DNS_PORT.to_be());
+    let mut query = [0u8; 64];
+    let len = build_query(&mut query);
+    let ret = sendto(fd, &query[..len], len, 0, &server, size_of::<SockaddrIn>() as u32);
+    if ret != len as isize {
+        println!("test_dns: sendto returned {}", ret);
+        close(fd);
+        return false;
+    }
+    let deadline = get_time_ms() + TIMEOUT_MS;
+    let mut answer = [0u8; 512];
+    let ok = loop {
+        let n = recvfrom(fd, &mut answer, answer.len(), 0, core::ptr::null_mut(), core::ptr::null_mut());
+        if n == EAGAIN && get_time_ms() < deadline {
+            yield_();
+            continue;
+        }
+        if n < 12 {
+            println!("test_dns: no answer, recvfrom returned {}", n);
+            break false;
+        }
+        let id = u16::from_be_bytes([answer[0], answer[1]]);
+        // QR set: a response, whatever its rcode
+        break id == QUERY_ID && answer[2] & 0x80 != 0;
+    };
+    close(fd);
+    ok
+}
+
+/// connect to a port nobody listens on, the refusal must come back quickly
+fn tcp_refused() -> bool {
+    let fd = socket(AF_INET, SOCK_STREAM, IPPROTO_TCP);
+    if fd < 0 {
+        println!("test_dns: tcp socket failed");
+        return false;
+    }
+    let addr = SockaddrIn::new(HOST_ADDR.to_be(), CLOSED_PORT.to_be());
+    let start = get_time_ms();
+    let ret = connect(fd as usize, &addr, size_of::<SockaddrIn>() as u32);
+    let elapsed = get_time_ms() - start;
+    close(fd as usize);
+    if ret != ECONNREFUSED || elapsed > TIMEOUT_MS {
+        println!("test_dns: connect to a closed port returned {} after {} ms", ret, elapsed);
+        return false;
+    }
+    true
+}
+
+/// off-link traffic through the default gateway, run with NET_C=y on the qemu user network
+#[no_mangle]
+pub fn main() -> i32 {
+    let ok = dns_query() & tcp_refused();
+    if ok {
+        println!("test_dns passed!");
+        0
+    } else {
+        -1
+    }
+}
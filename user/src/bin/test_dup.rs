@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{check, close, dup, dup3, open, read, unlink, write, OpenFlags, EBADF, EINVAL};
+
+#[macro_use]
+extern crate user_lib;
+
+const O_CLOEXEC: u32 = 0o2000000;
+const PATH: &str = "/test_dup_file\0";
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    let fd = open(PATH, OpenFlags::CREATE | OpenFlags::RDWR);
+    if fd < 0 {
+        println!("test_dup: can not create {}", PATH);
+        return -1;
+    }
+    let fd = fd as usize;
+
+    // a closed fd can not be duplicated, and the failed dup takes no slot
+    let spare = dup(fd);
+    close(spare as usize);
+    ok &= check(dup(spare as usize) == EBADF, "dup of a closed fd");
+    ok &= check(dup(1 << 20) == EBADF, "dup of a huge fd");
+    let again = dup(fd);
+    ok &= check(again == spare, "failed dup leaked a slot");
+    close(again as usize);
+
+    // dup3 onto an fd far beyond the table size and the fd limit
+    ok &= check(dup3(fd, 1 << 20, 0) == EBADF, "dup3 beyond the fd limit");
+    ok &= check(dup3(fd, fd, 0) == EINVAL, "dup3 onto itself");
+    ok &= check(dup3(fd, 100, 0x1) == EINVAL, "dup3 with a flag other than O_CLOEXEC");
+    ok &= check(dup3(fd, 100, O_CLOEXEC) == 100, "dup3 onto a far fd");
+    ok &= check(close(100) == 0, "close the far fd");
+
+    // the duplicate shares the offset with the original
+    let dup_fd = dup(fd) as usize;
+    write(fd, b"hello", 5);
+    write(dup_fd, b"world", 5);
+    close(dup_fd);
+    close(fd);
+    let fd = open(PATH, OpenFlags::RDONLY) as usize;
+    let mut buf = [0u8; 16];
+    let n = read(fd, &mut buf);
+    ok &= check(n == 10 && &buf[..10] == b"helloworld", "offset shared after dup");
+    close(fd);
+    unlink(PATH);
+
+    if ok {
+        println!("test_dup passed!");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,125 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    bind, check, close, connect, open, socket, unlink, write, OpenFlags, SockaddrIn, ECONNREFUSED, EEXIST, EINVAL,
+    ENAMETOOLONG, ENOENT, ENOSPC, ENOTDIR,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const AF_INET: i32 = 2;
+const SOCK_STREAM: i32 = 1;
+const SOCK_DGRAM: i32 = 2;
+/// nobody listens on it
+const CLOSED_PORT: u16 = 4461;
+const UDP_PORT: u16 = 4462;
+const LOCALHOST: u32 = 0x7f000001;
+
+const TMPFS_SIZE: &str = "/proc/sys/fs/tmpfs-size-mb\0";
+/// on ext4
+const DISK_FILE: &str = "/test_errno\0";
+/// on tmpfs
+const TMP_FILE: &str = "/tmp/test_errno\0";
+/// the most the tmpfs may hold while it is filled
+const TMPFS_LIMIT: &[u8] = b"16\n";
+const CHUNK: usize = 64 * 1024;
+
+static ZEROS: [u8; CHUNK] = [0; CHUNK];
+
+fn check_errno(got: isize, want: isize, what: &str) -> bool {
+    if got != want {
+        println!("test_errno: {}: got {}, want {}", what, got, want);
+    }
+    got == want
+}
+
+fn set_tmpfs_size(value: &[u8]) -> isize {
+    let fd = open(TMPFS_SIZE, OpenFlags::WRONLY);
+    if fd < 0 {
+        return fd;
+    }
+    let ret = write(fd as usize, value, value.len());
+    close(fd as usize);
+    ret
+}
+
+/// the errno of the path lookups and of creating and removing files on `file`
+fn paths(file: &str) -> bool {
+    let mut ok = check_errno(open("/test_errno_missing\0", OpenFlags::RDONLY), ENOENT, "open a missing file");
+    ok &= check_errno(open("/test_errno_missing/file\0", OpenFlags::RDONLY), ENOENT, "open under a missing directory");
+    ok &= check_errno(unlink("/test_errno_missing\0"), ENOENT, "unlink a missing file");
+
+    let fd = open(file, OpenFlags::CREATE | OpenFlags::WRONLY);
+    ok &= check(fd >= 0, "create the file");
+    close(fd as usize);
+    ok &= check_errno(open(file, OpenFlags::CREATE | OpenFlags::EXCL | OpenFlags::WRONLY), EEXIST, "O_EXCL on an existing file");
+    // the path goes through the file as if it were a directory
+    let mut under = [0u8; 64];
+    let len = file.len() - 1;
+    under[..len].copy_from_slice(&file.as_bytes()[..len]);
+    under[len..len + 3].copy_from_slice(b"/x\0");
+    let under = core::str::from_utf8(&under[..len + 3]).unwrap();
+    ok &= check_errno(open(under, OpenFlags::RDONLY), ENOTDIR, "open under a file");
+    ok &= check(unlink(file) == 0, "unlink the file");
+    ok &= check_errno(unlink(file), ENOENT, "unlink it twice");
+
+    let mut long = [b'a'; 258];
+    long[0] = b'/';
+    long[257] = 0;
+    let long = core::str::from_utf8(&long).unwrap();
+    ok & check_errno(open(long, OpenFlags::CREATE | OpenFlags::WRONLY), ENAMETOOLONG, "a name of 256 bytes")
+}
+
+/// fill the tmpfs under a lowered limit, the write finding no room fails with ENOSPC
+fn full_tmpfs() -> bool {
+    let mut ok = check(set_tmpfs_size(TMPFS_LIMIT) == TMPFS_LIMIT.len() as isize, "limit the tmpfs");
+    let fd = open(TMP_FILE, OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC);
+    ok &= check(fd >= 0, "create the tmpfs file");
+    let mut written = 0;
+    let last = loop {
+        let ret = write(fd as usize, &ZEROS, CHUNK);
+        if ret <= 0 || written > 64 << 20 {
+            break ret;
+        }
+        written += ret as usize;
+    };
+    println!("test_errno: the tmpfs took {} KiB before it was full", written / 1024);
+    ok &= check_errno(last, ENOSPC, "write to a full tmpfs");
+    close(fd as usize);
+    ok &= check(unlink(TMP_FILE) == 0, "unlink the tmpfs file");
+    ok & check(set_tmpfs_size(b"0\n") == 2, "lift the tmpfs limit")
+}
+
+fn sockets() -> bool {
+    let addr = SockaddrIn::new(LOCALHOST.to_be(), CLOSED_PORT.to_be());
+    let fd = socket(AF_INET, SOCK_STREAM, 0);
+    let mut ok = check(fd >= 0, "a tcp socket");
+    ok &= check_errno(connect(fd as usize, &addr, size_of::<SockaddrIn>() as u32), ECONNREFUSED, "connect to a closed port");
+    close(fd as usize);
+
+    let addr = SockaddrIn::new(LOCALHOST.to_be(), UDP_PORT.to_be());
+    let fd = socket(AF_INET, SOCK_DGRAM, 0);
+    ok &= check(fd >= 0, "a udp socket");
+    ok &= check(bind(fd as usize, &addr, size_of::<SockaddrIn>() as u32) == 0, "bind the udp socket");
+    ok &= check_errno(bind(fd as usize, &addr, size_of::<SockaddrIn>() as u32), EINVAL, "bind it twice");
+    close(fd as usize);
+    ok
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    ok &= check(paths(DISK_FILE), "the paths on ext4");
+    ok &= check(paths(TMP_FILE), "the paths on tmpfs");
+    ok &= full_tmpfs();
+    ok &= sockets();
+
+    if ok {
+        println!("test_errno: passed");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,110 @@
+#![no_std]
+#![no_main]
+
+use alloc::vec::Vec;
+use user_lib::{check, close, execve, fchmod, open, unlink, write, OpenFlags, EINVAL, ENOEXEC};
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+const PATH: &str = "/test_exec_check.elf";
+
+#[cfg(target_arch = "riscv64")]
+const EM_NATIVE: u16 = 243;
+#[cfg(target_arch = "loongarch64")]
+const EM_NATIVE: u16 = 258;
+const EM_X86_64: u16 = 62;
+const ELFCLASS32: u8 = 1;
+
+const PAGE_SIZE: usize = 4096;
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+/// where the segment of the image goes
+const BASE: u64 = 0x10000;
+
+fn put(image: &mut [u8], at: usize, bytes: &[u8]) {
+    image[at..at + bytes.len()].copy_from_slice(bytes);
+}
+
+/// a native ELF64 executable of one page with one read and execute segment
+/// holding its entry, built as execve would take it
+fn image() -> Vec<u8> {
+    let mut image = alloc::vec![0u8; PAGE_SIZE];
+    put(&mut image, 0, &[0x7f, b'E', b'L', b'F', 2, 1, 1]);
+    put(&mut image, 16, &2u16.to_le_bytes()); // ET_EXEC
+    put(&mut image, 18, &EM_NATIVE.to_le_bytes());
+    put(&mut image, 20, &1u32.to_le_bytes());
+    put(&mut image, 24, &(BASE + (EHDR_SIZE + PHDR_SIZE) as u64).to_le_bytes());
+    put(&mut image, 32, &(EHDR_SIZE as u64).to_le_bytes());
+    put(&mut image, 52, &(EHDR_SIZE as u16).to_le_bytes());
+    put(&mut image, 54, &(PHDR_SIZE as u16).to_le_bytes());
+    put(&mut image, 56, &1u16.to_le_bytes());
+    segment(&mut image, 0, 5, BASE, PAGE_SIZE as u64, PAGE_SIZE as u64);
+    image
+}
+
+/// the `index`th program header, a PT_LOAD of the start of the file
+fn segment(image: &mut [u8], index: usize, flags: u32, vaddr: u64, filesz: u64, memsz: u64) {
+    let ph = EHDR_SIZE + index * PHDR_SIZE;
+    put(image, ph, &1u32.to_le_bytes());
+    put(image, ph + 4, &flags.to_le_bytes());
+    put(image, ph + 16, &vaddr.to_le_bytes());
+    put(image, ph + 24, &vaddr.to_le_bytes());
+    put(image, ph + 32, &filesz.to_le_bytes());
+    put(image, ph + 40, &memsz.to_le_bytes());
+    put(image, ph + 48, &(PAGE_SIZE as u64).to_le_bytes());
+}
+
+/// write `image` as an executable file and execve it, the error comes back
+fn exec(image: &[u8]) -> isize {
+    let fd = open(PATH, OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC);
+    if fd < 0 {
+        return fd;
+    }
+    write(fd as usize, image, image.len());
+    fchmod(fd as usize, 0o755);
+    close(fd as usize);
+    execve(PATH, &[PATH], &[])
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    // not a program of this machine
+    let mut ok = check(exec(&image()[..20]) == ENOEXEC, "a truncated header is ENOEXEC");
+    let mut elf32 = image();
+    elf32[4] = ELFCLASS32;
+    ok &= check(exec(&elf32) == ENOEXEC, "a 32-bit ELF is ENOEXEC");
+    let mut x86 = image();
+    put(&mut x86, 18, &EM_X86_64.to_le_bytes());
+    ok &= check(exec(&x86) == ENOEXEC, "an x86_64 ELF is ENOEXEC");
+    let mut no_phdrs = image();
+    put(&mut no_phdrs, 32, &(PAGE_SIZE as u64).to_le_bytes());
+    ok &= check(exec(&no_phdrs) == ENOEXEC, "program headers past the end of the file are ENOEXEC");
+    ok &= check(exec(b"not an elf\n") == ENOEXEC, "a file without the magic is ENOEXEC");
+
+    // a program of this machine with a broken layout
+    let mut bss_short = image();
+    segment(&mut bss_short, 0, 5, BASE, PAGE_SIZE as u64, 16);
+    ok &= check(exec(&bss_short) == EINVAL, "a segment larger in the file than in memory is EINVAL");
+    let mut past_eof = image();
+    segment(&mut past_eof, 0, 5, BASE, 2 * PAGE_SIZE as u64, 2 * PAGE_SIZE as u64);
+    ok &= check(exec(&past_eof) == EINVAL, "a segment past the end of the file is EINVAL");
+    let mut no_exec = image();
+    segment(&mut no_exec, 0, 4, BASE, PAGE_SIZE as u64, PAGE_SIZE as u64);
+    ok &= check(exec(&no_exec) == EINVAL, "an entry in no executable segment is EINVAL");
+    let mut overlap = image();
+    put(&mut overlap, 56, &2u16.to_le_bytes());
+    segment(&mut overlap, 1, 6, BASE, PAGE_SIZE as u64, PAGE_SIZE as u64);
+    ok &= check(exec(&overlap) == EINVAL, "overlapping segments are EINVAL");
+
+    // every failed execve came back here, to the old image
+    unlink(PATH);
+
+    if ok {
+        println!("test_exec_check: passed");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,83 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{check, execve, exit, fork, nanosleep, sleep, sysinfo, thread_spawn, waitpid, Sysinfo};
+
+#[macro_use]
+extern crate user_lib;
+
+const ROUNDS: usize = 100;
+/// longer than the whole test, only the execve can end it
+const SLEEP_MS: usize = 600_000;
+/// the exit code of the program run by execve
+const EXECED: i32 = 42;
+/// the frames the kernel may keep for itself over the rounds, the page cache of this binary and such
+const SLACK: u64 = 64;
+
+static mut SLEEPER_STACK: [u8; 16384] = [0; 16384];
+
+extern "C" fn sleeper(_arg: usize) -> ! {
+    nanosleep(SLEEP_MS);
+    // only reached if the execve let this thread live
+    exit(-2);
+}
+
+fn free_frames() -> u64 {
+    let mut info = Sysinfo::default();
+    sysinfo(&mut info);
+    info.freeram
+}
+
+/// a process whose second thread sleeps while the main thread runs this program again,
+/// return whether the new program ran to its end
+fn exec_round(path: &str) -> bool {
+    let pid = fork();
+    if pid == 0 {
+        let stack = unsafe { &mut *core::ptr::addr_of_mut!(SLEEPER_STACK) };
+        if thread_spawn(stack, sleeper, 0) < 0 {
+            exit(-1);
+        }
+        // let the sleeper get into nanosleep
+        sleep(2);
+        execve(path, &[path, "execed"], &[]);
+        exit(-1);
+    }
+    if pid < 0 {
+        return false;
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    (status >> 8) & 0xff == EXECED
+}
+
+#[no_mangle]
+pub fn main(args: &[&str]) -> i32 {
+    if args.get(1) == Some(&"execed") {
+        return EXECED;
+    }
+    let path = args[0];
+    let mut ok = true;
+
+    // the first round brings this binary into the page cache
+    ok &= check(exec_round(path), "exec with a sleeping thread");
+    let before = free_frames();
+    let mut failed = 0;
+    for _ in 0..ROUNDS {
+        if !exec_round(path) {
+            failed += 1;
+        }
+    }
+    let after = free_frames();
+    ok &= check(failed == 0, "every exec round");
+    ok &= check(before > 0 && after + SLACK >= before, "free frames after the rounds");
+    if !ok {
+        println!("test_exec_threads: {} of {} rounds failed, free frames {} -> {}", failed, ROUNDS, before, after);
+    }
+
+    if ok {
+        println!("test_exec_threads: passed");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,95 @@
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use user_lib::{check, exit, fork, get_time_ms, getpid, thread_spawn, waitpid, yield_};
+
+#[macro_use]
+extern crate user_lib;
+
+const ROUNDS: usize = 50;
+/// children alive at once in a round, enough to keep every hart busy
+const CHILDREN: usize = 8;
+/// getpid calls between yields, and in the timed loop
+const CALLS: usize = 1000;
+const TIMED_CALLS: usize = 200_000;
+
+static mut THREAD_STACK: [u8; 16384] = [0; 16384];
+/// set by the second thread of a child: 1 when its getpid calls agreed, 2 when not
+static WORKER: AtomicUsize = AtomicUsize::new(0);
+
+/// call getpid around yields, so the caller moves between harts while others exit
+fn churn(pid: isize) -> bool {
+    for _ in 0..4 {
+        for _ in 0..CALLS {
+            if getpid() != pid {
+                return false;
+            }
+        }
+        yield_();
+    }
+    true
+}
+
+extern "C" fn worker(pid: usize) -> ! {
+    let agreed = churn(pid as isize);
+    WORKER.store(if agreed { 1 } else { 2 }, Ordering::Release);
+    // the thread exits on its own, racing the main thread of its process
+    exit(0);
+}
+
+/// a child whose second thread and main thread both exit right after some syscalls
+fn child() -> ! {
+    let pid = getpid();
+    let stack = unsafe { &mut *core::ptr::addr_of_mut!(THREAD_STACK) };
+    if thread_spawn(stack, worker, pid as usize) < 0 {
+        exit(-1);
+    }
+    let agreed = churn(pid);
+    while WORKER.load(Ordering::Acquire) == 0 {
+        yield_();
+    }
+    exit(if agreed && WORKER.load(Ordering::Acquire) == 1 { 0 } else { -1 });
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    let start = get_time_ms();
+    let pid = getpid();
+    for _ in 0..TIMED_CALLS {
+        getpid();
+    }
+    println!("test_exit_stress: {} getpid calls in {} ms", TIMED_CALLS, get_time_ms() - start);
+    ok &= check(pid > 0, "getpid");
+
+    // tasks exit on every hart while the others keep asking for their current task
+    let mut failed = 0;
+    for _ in 0..ROUNDS {
+        let mut pids = [0isize; CHILDREN];
+        for slot in pids.iter_mut() {
+            *slot = fork();
+            if *slot == 0 {
+                child();
+            }
+        }
+        ok &= check(churn(pid), "getpid of the parent");
+        for &child in pids.iter().filter(|&&child| child > 0) {
+            let mut status = 0;
+            if waitpid(child as usize, &mut status) != child || status != 0 {
+                failed += 1;
+            }
+        }
+        failed += pids.iter().filter(|&&child| child < 0).count();
+    }
+    ok &= check(failed == 0, "every child");
+
+    if ok {
+        println!("test_exit_stress: passed");
+        0
+    } else {
+        -1
+    }
+}
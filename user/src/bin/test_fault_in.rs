@@ -0,0 +1,116 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, exit, fork, mmap, munmap, open, read, unlink, waitpid, write, MmapFlags, MmapProt, OpenFlags, EFAULT,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const PAGE_SIZE: usize = 4096;
+/// the size of the file and of the buffer it is read into
+const LEN: usize = 1 << 20;
+const FILE: &str = "/tmp/test_fault_in\0";
+/// what the parent leaves in its copy of the buffer before the fork
+const PARENT_BYTE: u8 = 0xaa;
+
+fn pattern(off: usize) -> u8 {
+    (off % 251) as u8
+}
+
+/// fill the file with the pattern, a page at a time
+fn make_file() -> bool {
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC);
+    if fd < 0 {
+        return false;
+    }
+    let mut page = [0u8; PAGE_SIZE];
+    let mut ok = true;
+    for off in (0..LEN).step_by(PAGE_SIZE) {
+        page.iter_mut().enumerate().for_each(|(i, b)| *b = pattern(off + i));
+        ok &= write(fd as usize, &page, PAGE_SIZE) == PAGE_SIZE as isize;
+    }
+    close(fd as usize);
+    ok
+}
+
+/// a fresh private anonymous mapping of `LEN` bytes, none of it touched yet
+fn map() -> Option<&'static mut [u8]> {
+    let addr = mmap(0, LEN, MmapProt::PROT_READ | MmapProt::PROT_WRITE, MmapFlags::MAP_PRIVATE | MmapFlags::MAP_ANONYMOUS, usize::MAX, 0);
+    if addr < 0 {
+        return None;
+    }
+    Some(unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, LEN) })
+}
+
+/// read the whole file into `buf`, whether it all came and matches the pattern
+fn read_file(buf: &mut [u8]) -> bool {
+    let fd = open(FILE, OpenFlags::RDONLY);
+    if fd < 0 {
+        return false;
+    }
+    let mut got = 0;
+    while got < buf.len() {
+        let n = read(fd as usize, &mut buf[got..]);
+        if n <= 0 {
+            break;
+        }
+        got += n as usize;
+    }
+    close(fd as usize);
+    got == buf.len() && buf.iter().enumerate().all(|(i, &b)| b == pattern(i))
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = check(make_file(), "make the file");
+
+    // the kernel copies into pages the process never touched
+    match map() {
+        Some(buf) => {
+            ok &= check(read_file(buf), "read into an untouched mapping");
+            munmap(buf.as_ptr() as usize, LEN);
+        }
+        None => ok &= check(false, "mmap"),
+    }
+
+    // after a fork the buffer is copy-on-write, the read of the child must not reach the parent
+    match map() {
+        Some(buf) => {
+            buf.fill(PARENT_BYTE);
+            let pid = fork();
+            if pid == 0 {
+                exit(if read_file(buf) { 0 } else { 1 });
+            }
+            let mut status = 0;
+            waitpid(pid as usize, &mut status);
+            ok &= check(status == 0, "the child reads into a copy-on-write buffer");
+            ok &= check(buf.iter().all(|&b| b == PARENT_BYTE), "the copy of the parent unchanged");
+            munmap(buf.as_ptr() as usize, LEN);
+        }
+        None => ok &= check(false, "mmap"),
+    }
+
+    // an unmapped buffer is still a fault, unless nothing is copied
+    match map() {
+        Some(buf) => {
+            let addr = buf.as_mut_ptr();
+            munmap(addr as usize, LEN);
+            let fd = open(FILE, OpenFlags::RDONLY);
+            let gone = unsafe { core::slice::from_raw_parts_mut(addr, PAGE_SIZE) };
+            ok &= check(read(fd as usize, gone) == EFAULT, "read into an unmapped buffer");
+            ok &= check(read(fd as usize, &mut gone[..0]) == 0, "read nothing into an unmapped buffer");
+            close(fd as usize);
+        }
+        None => ok &= check(false, "mmap"),
+    }
+    unlink(FILE);
+
+    if ok {
+        println!("test_fault_in: passed");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,77 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, drop_caches, name_to_handle_at, open, open_by_handle_at, read, unlink, write, FileHandle, OpenFlags,
+    AT_FDCWD, EOVERFLOW, ESTALE,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const FILE: &str = "/test_file_handle_file\0";
+const CONTENT: &[u8] = b"a file reopened by its handle";
+
+fn create(content: &[u8]) -> bool {
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDWR | OpenFlags::TRUNC);
+    if fd < 0 {
+        return false;
+    }
+    let written = write(fd as usize, content, content.len());
+    close(fd as usize);
+    written == content.len() as isize
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    ok &= check(create(CONTENT), "create the file");
+
+    // no room: the size needed comes back
+    let mut mount_id = 0;
+    let mut handle = FileHandle::with_room(0);
+    ok &= check(name_to_handle_at(AT_FDCWD, FILE, &mut handle, &mut mount_id, 0) == EOVERFLOW, "EOVERFLOW without room");
+    let needed = handle.handle_bytes as usize;
+    ok &= check(needed > 0, "the size of the handle");
+    let mut handle = FileHandle::with_room(needed);
+    ok &= check(name_to_handle_at(AT_FDCWD, FILE, &mut handle, &mut mount_id, 0) == 0, "name_to_handle_at");
+    ok &= check(handle.handle_bytes as usize == needed, "handle_bytes");
+    ok &= check(mount_id > 0, "the mount id");
+
+    // nothing refers to the file but the handle
+    let dropped = drop_caches();
+    println!("test_file_handle: {} dentries dropped", dropped);
+    ok &= check(dropped >= 0, "drop the caches");
+
+    let mount_fd = open("/\0", OpenFlags::RDONLY | OpenFlags::DIRECTORY);
+    ok &= check(mount_fd >= 0, "open the mount");
+    let fd = open_by_handle_at(mount_fd, &handle, OpenFlags::RDONLY);
+    ok &= check(fd >= 0, "open_by_handle_at");
+    if fd >= 0 {
+        let mut buf = [0u8; 64];
+        let len = read(fd as usize, &mut buf);
+        ok &= check(len >= 0 && &buf[..len as usize] == CONTENT, "the contents by the handle");
+        close(fd as usize);
+    }
+    // while the file is in memory the handle goes through the caches
+    let fd = open(FILE, OpenFlags::RDONLY);
+    let again = open_by_handle_at(mount_fd, &handle, OpenFlags::RDONLY);
+    ok &= check(again >= 0, "open_by_handle_at of a cached file");
+    close(again as usize);
+    close(fd as usize);
+
+    // a file made in place of the removed one is another file
+    unlink(FILE);
+    drop_caches();
+    ok &= check(create(b"another file"), "create the file again");
+    ok &= check(open_by_handle_at(mount_fd, &handle, OpenFlags::RDONLY) == ESTALE, "ESTALE after unlink");
+    unlink(FILE);
+    close(mount_fd as usize);
+
+    if ok {
+        println!("test_file_handle: passed");
+        0
+    } else {
+        -1
+    }
+}
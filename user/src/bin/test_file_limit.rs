@@ -0,0 +1,162 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, dup3, exit, fork, open, pipe, read, setrlimit, setuid, socket, waitpid, write, OpenFlags, RLimit,
+    EBADF, EMFILE, ENFILE, RLIMIT_NOFILE,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const AF_INET: i32 = 2;
+const SOCK_STREAM: i32 = 1;
+
+const FILE_NR: &str = "/proc/sys/fs/file-nr\0";
+const FILE_MAX: &str = "/proc/sys/fs/file-max\0";
+/// a file anyone may open, and cheap to open
+const TARGET: &str = "/proc/meminfo\0";
+/// the open files an unprivileged task may add before the system is full
+const HEADROOM: usize = 100;
+const NOFILE: usize = 16;
+
+/// a number from the start of `text`
+fn parse(text: &[u8]) -> usize {
+    text.iter().take_while(|c| c.is_ascii_digit()).fold(0, |n, &c| n * 10 + (c - b'0') as usize)
+}
+
+/// the open files of the system, the first number of fs/file-nr
+fn nr_files() -> usize {
+    let mut buf = [0u8; 64];
+    let fd = open(FILE_NR, OpenFlags::RDONLY);
+    if fd < 0 {
+        return 0;
+    }
+    let len = read(fd as usize, &mut buf).max(0) as usize;
+    close(fd as usize);
+    // the count taken before this open was closed, which it included
+    parse(&buf[..len]) - 1
+}
+
+fn set_file_max(max: usize) -> bool {
+    let mut text = [0u8; 24];
+    let mut len = 0;
+    let mut digits = [0u8; 20];
+    let mut n = max;
+    loop {
+        digits[len] = b'0' + (n % 10) as u8;
+        len += 1;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    for i in 0..len {
+        text[i] = digits[len - 1 - i];
+    }
+    let fd = open(FILE_MAX, OpenFlags::WRONLY);
+    if fd < 0 {
+        return false;
+    }
+    let ok = write(fd as usize, &text, len) == len as isize;
+    close(fd as usize);
+    ok
+}
+
+/// open TARGET until it fails, the opened fds go into `fds`; the error and how many were opened
+fn open_until_error(fds: &mut [usize]) -> (isize, usize) {
+    let mut opened = 0;
+    loop {
+        let fd = open(TARGET, OpenFlags::RDONLY);
+        if fd < 0 {
+            return (fd, opened);
+        }
+        if opened == fds.len() {
+            close(fd as usize);
+            return (0, opened);
+        }
+        fds[opened] = fd as usize;
+        opened += 1;
+    }
+}
+
+/// RLIMIT_NOFILE is the fd table size, and a failed pipe leaves no fd behind
+fn emfile_child() -> ! {
+    let mut ok = true;
+    let limit = RLimit { rlim_cur: NOFILE, rlim_max: NOFILE };
+    ok &= check(setrlimit(RLIMIT_NOFILE, &limit) == 0, "set RLIMIT_NOFILE");
+    let mut fds = [0usize; 64];
+    let (err, opened) = open_until_error(&mut fds);
+    ok &= check(err == EMFILE, "opening beyond RLIMIT_NOFILE is EMFILE");
+    ok &= check(opened == NOFILE - 3, "every fd below RLIMIT_NOFILE can be used");
+    ok &= check(dup3(0, NOFILE, 0) == EBADF, "dup3 to an fd beyond RLIMIT_NOFILE is EBADF");
+    // one free fd is not enough for a pipe, which takes neither
+    close(fds[0]);
+    let mut pipe_fds = [0usize; 2];
+    ok &= check(pipe(&mut pipe_fds) == EMFILE, "a pipe with one free fd is EMFILE");
+    let fd = open(TARGET, OpenFlags::RDONLY);
+    ok &= check(fd == fds[0] as isize, "the failed pipe left its free fd");
+    exit(if ok { 0 } else { 1 });
+}
+
+/// an unprivileged task runs into fs/file-max and gets ENFILE from every way of opening
+fn enfile_child() -> ! {
+    let mut ok = true;
+    setuid(1000);
+    let mut fds = [0usize; 2 * HEADROOM];
+    let (err, opened) = open_until_error(&mut fds);
+    ok &= check(err == ENFILE, "opening beyond fs/file-max is ENFILE");
+    ok &= check(opened > HEADROOM / 2 && opened <= HEADROOM, "the files opened before the system was full");
+    let mut pipe_fds = [0usize; 2];
+    ok &= check(pipe(&mut pipe_fds) == ENFILE, "a pipe in a full system is ENFILE");
+    ok &= check(socket(AF_INET, SOCK_STREAM, 0) == ENFILE, "a socket in a full system is ENFILE");
+    // a file closed is a file which can be opened again
+    close(fds[0]);
+    ok &= check(open(TARGET, OpenFlags::RDONLY) >= 0, "open after a close");
+    exit(if ok { 0 } else { 1 });
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    let baseline = nr_files();
+    ok &= check(baseline > 0, "read fs/file-nr");
+
+    let fd = open(TARGET, OpenFlags::RDONLY);
+    ok &= check(nr_files() == baseline + 1, "an open counts");
+    close(fd as usize);
+    ok &= check(nr_files() == baseline, "a close gives it back");
+
+    let pid = fork();
+    if pid == 0 {
+        emfile_child();
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    ok &= check(status == 0, "the fd limit of a process");
+
+    // the system is full HEADROOM files from now
+    ok &= check(set_file_max(baseline + HEADROOM), "write fs/file-max");
+    let pid = fork();
+    if pid == 0 {
+        enfile_child();
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    ok &= check(status == 0, "the file limit of the system");
+    ok &= check(set_file_max(65536), "restore fs/file-max");
+    ok &= check(nr_files() == baseline, "the count is back to the baseline");
+
+    // and everything still works
+    let fd = open(TARGET, OpenFlags::RDONLY);
+    let mut buf = [0u8; 16];
+    ok &= check(fd >= 0 && read(fd as usize, &mut buf) > 0, "read a file afterwards");
+    close(fd as usize);
+
+    if ok {
+        println!("test_file_limit: passed");
+        0
+    } else {
+        -1
+    }
+}
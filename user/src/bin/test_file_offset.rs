@@ -0,0 +1,69 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{check, close, dup, exit, fork, open, read, unlink, wait, write, OpenFlags};
+
+#[macro_use]
+extern crate user_lib;
+
+const PATH: &str = "/test_file_offset\0";
+const SIZE: usize = 300;
+
+/// read `len` bytes from fd and check they are the bytes at `pos` of the file
+fn read_expect(fd: usize, pos: usize, len: usize) -> bool {
+    let mut buf = [0u8; SIZE];
+    if read(fd, &mut buf[..len]) != len as isize {
+        return false;
+    }
+    buf[..len].iter().enumerate().all(|(i, &b)| b == (pos + i) as u8)
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    let mut data = [0u8; SIZE];
+    for (i, b) in data.iter_mut().enumerate() {
+        *b = i as u8;
+    }
+    let fd = open(PATH, OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC);
+    if fd < 0 {
+        println!("test_file_offset: create failed");
+        return -1;
+    }
+    write(fd as usize, &data, SIZE);
+    close(fd as usize);
+
+    // fork shares the description: the child's read moves the parent's offset
+    let fd = open(PATH, OpenFlags::RDONLY) as usize;
+    if fork() == 0 {
+        exit(if read_expect(fd, 0, 100) { 0 } else { -1 });
+    }
+    let mut exit_code = 0;
+    wait(&mut exit_code);
+    ok &= check(exit_code == 0, "child read");
+    ok &= check(read_expect(fd, 100, 10), "parent read after fork");
+
+    // so does dup
+    let dup_fd = dup(fd) as usize;
+    ok &= check(read_expect(dup_fd, 110, 10), "read through dup");
+    ok &= check(read_expect(fd, 120, 10), "read after dup read");
+    close(dup_fd);
+    close(fd);
+
+    // separate opens have their own offsets
+    let fd1 = open(PATH, OpenFlags::RDONLY) as usize;
+    let fd2 = open(PATH, OpenFlags::RDONLY) as usize;
+    ok &= check(read_expect(fd1, 0, 50), "first open");
+    ok &= check(read_expect(fd2, 0, 50), "second open");
+    ok &= check(read_expect(fd1, 50, 50), "first open again");
+    close(fd1);
+    close(fd2);
+
+    unlink(PATH);
+    if ok {
+        println!("test_file_offset passed!");
+        0
+    } else {
+        -1
+    }
+}
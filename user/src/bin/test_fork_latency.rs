@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{brk, exit, fork, get_time_ms, waitpid};
+
+#[macro_use]
+extern crate user_lib;
+
+const HEAP_SIZE: usize = 100 * 1024 * 1024;
+const PAGE_SIZE: usize = 4096;
+const FORKS: usize = 5;
+
+/// the word stored at the start of every heap page
+fn pattern(page: usize) -> usize {
+    page.wrapping_mul(0x9e37_79b9) ^ 0x5a5a
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let base = brk(0) as usize;
+    if brk(base + HEAP_SIZE) < (base + HEAP_SIZE) as isize {
+        println!("test_fork_latency: brk failed");
+        return -1;
+    }
+    let pages = HEAP_SIZE / PAGE_SIZE;
+    let page_ptr = |page: usize| (base + page * PAGE_SIZE) as *mut usize;
+    // populate the whole heap so the fork has frames to share
+    for page in 0..pages {
+        unsafe { page_ptr(page).write_volatile(pattern(page)); }
+    }
+
+    let mut ok = true;
+    let mut total_ms = 0;
+    for _ in 0..FORKS {
+        let start = get_time_ms();
+        let pid = fork();
+        if pid == 0 {
+            // the child breaks the sharing on a spread of pages and checks its view
+            for page in (0..pages).step_by(97) {
+                unsafe {
+                    if page_ptr(page).read_volatile() != pattern(page) {
+                        exit(-1);
+                    }
+                    page_ptr(page).write_volatile(!pattern(page));
+                    if page_ptr(page).read_volatile() != !pattern(page) {
+                        exit(-1);
+                    }
+                }
+            }
+            exit(0);
+        }
+        total_ms += get_time_ms() - start;
+        let mut exit_code = 0;
+        waitpid(pid as usize, &mut exit_code);
+        if exit_code != 0 {
+            println!("test_fork_latency: child saw wrong data");
+            ok = false;
+        }
+        // the parent's pages are untouched by the child's writes
+        for page in 0..pages {
+            if unsafe { page_ptr(page).read_volatile() } != pattern(page) {
+                println!("test_fork_latency: parent page {} corrupted", page);
+                ok = false;
+                break;
+            }
+        }
+    }
+    println!(
+        "test_fork_latency: fork with a {} MiB heap takes {} ms on average",
+        HEAP_SIZE >> 20, total_ms / FORKS as isize
+    );
+    if ok {
+        println!("test_fork_latency passed!");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,146 @@
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+
+use user_lib::{exit, fork, getpid, sigaction, sigreturn, waitpid, SignalAction, SIGUSR1};
+
+#[macro_use]
+extern crate user_lib;
+
+const SYSCALL_YIELD: usize = 124;
+const SYSCALL_KILL: usize = 129;
+const ROUNDS: usize = 200;
+
+/// do a syscall while `fp` sits in float registers, which must come back untouched
+#[cfg(target_arch = "riscv64")]
+fn syscall_keep_fp(id: usize, a0: usize, a1: usize, fp: &mut [f64; 4]) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            inout(freg) fp[0],
+            inout(freg) fp[1],
+            inout(freg) fp[2],
+            inout(freg) fp[3],
+            inlateout("a0") a0 => ret,
+            in("a1") a1,
+            in("a7") id,
+        );
+    }
+    ret
+}
+
+#[cfg(target_arch = "loongarch64")]
+fn syscall_keep_fp(id: usize, a0: usize, a1: usize, fp: &mut [f64; 4]) -> isize {
+    let ret: isize;
+    unsafe {
+        asm!(
+            "syscall 0",
+            inout(freg) fp[0],
+            inout(freg) fp[1],
+            inout(freg) fp[2],
+            inout(freg) fp[3],
+            inlateout("$a0") a0 => ret,
+            in("$a1") a1,
+            in("$a7") id,
+        );
+    }
+    ret
+}
+
+/// distinctive values of one task for one round
+fn values(seed: f64, round: usize) -> [f64; 4] {
+    let r = round as f64;
+    [seed + r, seed * 3.0 - r, -seed / 7.0 + r, seed * seed + r]
+}
+
+/// dirty the float registers with the values of another task
+fn clobber_fp(seed: f64) {
+    let mut fp = values(seed, 0);
+    unsafe {
+        asm!(
+            "",
+            inout(freg) fp[0],
+            inout(freg) fp[1],
+            inout(freg) fp[2],
+            inout(freg) fp[3],
+        );
+    }
+    core::hint::black_box(fp);
+}
+
+/// yield with the task's values live, every round on fresh ones
+fn ping_pong(name: &str, seed: f64) -> bool {
+    for round in 0..ROUNDS {
+        let expect = values(seed, round);
+        let mut fp = expect;
+        syscall_keep_fp(SYSCALL_YIELD, 0, 0, &mut fp);
+        if fp != expect {
+            println!("test_fp_switch: {} saw {:?} instead of {:?} in round {}", name, fp, expect, round);
+            return false;
+        }
+    }
+    true
+}
+
+static mut HANDLED: usize = 0;
+
+fn handler() {
+    clobber_fp(-1234.5);
+    unsafe { HANDLED += 1 };
+    sigreturn();
+}
+
+/// take a signal with the task's values live, the handler uses FP itself
+fn signalled(seed: f64) -> bool {
+    let mut new = SignalAction::default();
+    new.handler = handler as usize;
+    if sigaction(SIGUSR1, Some(&new), None) < 0 {
+        println!("test_fp_switch: sigaction failed");
+        return false;
+    }
+    let pid = getpid() as usize;
+    for round in 0..ROUNDS {
+        let expect = values(seed, round);
+        let mut fp = expect;
+        syscall_keep_fp(SYSCALL_KILL, pid, SIGUSR1 as usize, &mut fp);
+        if fp != expect {
+            println!("test_fp_switch: the signal left {:?} instead of {:?} in round {}", fp, expect, round);
+            return false;
+        }
+    }
+    let handled = unsafe { HANDLED };
+    if handled != ROUNDS {
+        println!("test_fp_switch: {} of {} signals handled", handled, ROUNDS);
+        return false;
+    }
+    true
+}
+
+fn spawn(name: &'static str, seed: f64) -> usize {
+    let pid = fork();
+    if pid == 0 {
+        exit(if ping_pong(name, seed) { 0 } else { -1 });
+    }
+    pid as usize
+}
+
+/// two tasks ping-pong their FP values across yields while a third takes signals,
+/// none may ever see another's values
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let pids = [spawn("pi", core::f64::consts::PI), spawn("e", core::f64::consts::E)];
+    let mut ok = signalled(core::f64::consts::SQRT_2);
+    for pid in pids {
+        let mut status = 0;
+        waitpid(pid, &mut status);
+        ok &= status == 0;
+    }
+    if ok {
+        println!("test_fp_switch passed!");
+        0
+    } else {
+        -1
+    }
+}
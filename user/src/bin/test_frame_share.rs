@@ -0,0 +1,117 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, exit, fork, mmap, munmap, pipe, read, sleep, sysinfo, waitpid, write, MmapFlags, MmapProt, Sysinfo,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const PAGE_SIZE: usize = 4096;
+const PAGES: usize = 32;
+/// children of the parent, each forks one grandchild
+const CHILDREN: usize = 16;
+/// how long the frames of the dead may take to come back
+const SETTLE_MS: usize = 1000;
+
+fn info() -> Sysinfo {
+    let mut info = Sysinfo::default();
+    sysinfo(&mut info);
+    info
+}
+
+/// read every page, the untouched ones map the zero page
+fn read_pages(base: usize) -> usize {
+    (0..PAGES).map(|i| unsafe { core::ptr::read_volatile((base + i * PAGE_SIZE) as *const u8) } as usize).sum()
+}
+
+fn write_pages(base: usize, pages: core::ops::Range<usize>, value: u8) {
+    for i in pages {
+        unsafe { core::ptr::write_volatile((base + i * PAGE_SIZE) as *mut u8, value) };
+    }
+}
+
+/// copy some pages, read the rest, fork a grandchild doing the same, then wait on `fd`
+fn child(base: usize, fd: usize, done: usize) -> ! {
+    write_pages(base, 0..PAGES / 4, 2);
+    let pid = fork();
+    if pid == 0 {
+        write_pages(base, PAGES / 4..PAGES / 2, 3);
+        read_pages(base);
+        exit(0);
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    read_pages(base);
+    write(done, &[1], 1);
+    // held until the parent has looked at the shared frames
+    let mut byte = [0u8];
+    read(fd, &mut byte);
+    exit(status);
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    let len = PAGES * PAGE_SIZE;
+    let base = mmap(0, len, MmapProt::PROT_READ | MmapProt::PROT_WRITE, MmapFlags::MAP_PRIVATE | MmapFlags::MAP_ANONYMOUS, usize::MAX, 0);
+    ok &= check(base > 0, "mmap");
+    let base = base as usize;
+    // half the pages are the own of the parent, half the zero page
+    write_pages(base, 0..PAGES / 2, 1);
+    read_pages(base);
+
+    let mut release = [0usize; 2];
+    let mut done = [0usize; 2];
+    pipe(&mut release);
+    pipe(&mut done);
+    let before = info();
+
+    let mut pids = [0isize; CHILDREN];
+    for pid in pids.iter_mut() {
+        *pid = fork();
+        if *pid == 0 {
+            child(base, release[0], done[1]);
+        }
+    }
+    let mut byte = [0u8];
+    for _ in 0..CHILDREN {
+        read(done[0], &mut byte);
+    }
+    let during = info();
+    println!("test_frame_share: free {} -> {}, shared {} -> {}", before.freeram, during.freeram, before.sharedram, during.sharedram);
+    // the pages the children did not write are shared with the parent
+    ok &= check(during.sharedram >= before.sharedram + (PAGES / 4) as u64, "the untouched pages are shared");
+    write(release[1], &[0u8; CHILDREN], CHILDREN);
+    for &pid in &pids {
+        let mut status = 0;
+        waitpid(pid as usize, &mut status);
+        ok &= check(status == 0, "a child and its grandchild");
+    }
+
+    // every copy and page table of the dead comes back, nothing stays shared for them
+    let mut after = info();
+    let mut waited = 0;
+    while (after.freeram != before.freeram || after.sharedram != before.sharedram) && waited < SETTLE_MS {
+        sleep(10);
+        waited += 10;
+        after = info();
+    }
+    println!("test_frame_share: after the children free {}, shared {}", after.freeram, after.sharedram);
+    ok &= check(after.freeram == before.freeram, "the free frames return to where they were");
+    ok &= check(after.sharedram == before.sharedram, "the shared frames return to where they were");
+    ok &= check(read_pages(base) == PAGES / 2, "the pages of the parent are its own");
+    munmap(base, len);
+    // the pipes were open in the baseline too
+    for fd in release.into_iter().chain(done) {
+        close(fd);
+    }
+
+    if ok {
+        println!("test_frame_share: passed");
+        0
+    } else {
+        -1
+    }
+}
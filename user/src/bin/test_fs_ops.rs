@@ -0,0 +1,85 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, fstat, ftruncate, mkdir, mmap, munmap, open, pread, rename, rmdir, unlink, write, MmapFlags, MmapProt,
+    OpenFlags, Stat, ENOENT, ENOTEMPTY,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const PAGE: usize = 4096;
+const DIR: &str = "/test_fs_ops_dir\0";
+/// a long name with bytes outside ascii, which fat keeps in its long name entries
+const LONG: &str = "/test_fs_ops_dir/Ünïcødé long name.txt\0";
+const MOVED: &str = "/test_fs_ops_dir/moved\0";
+const TOP: &str = "/test_fs_ops_moved\0";
+
+fn size_of(fd: usize) -> i64 {
+    let mut stat = Stat::default();
+    fstat(fd, &mut stat);
+    stat.st_size
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    if mkdir(DIR) < 0 {
+        println!("test_fs_ops: can not create {}", DIR);
+        return -1;
+    }
+    let fd = open(LONG, OpenFlags::CREATE | OpenFlags::RDWR);
+    if fd < 0 {
+        println!("test_fs_ops: can not create {}", LONG);
+        return -1;
+    }
+    let fd = fd as usize;
+    let data = [0x5au8; PAGE + 100];
+    ok &= check(write(fd, &data, data.len()) == data.len() as isize, "write");
+    ok &= check(size_of(fd) == data.len() as i64, "size after write");
+    close(fd);
+
+    // the long name comes back as it was written
+    let fd = open(LONG, OpenFlags::RDWR);
+    ok &= check(fd >= 0, "reopen by the long name");
+    let fd = fd as usize;
+    let mut buf = [0u8; 100];
+    ok &= check(pread(fd, &mut buf, PAGE) == 100 && buf.iter().all(|&b| b == 0x5a), "read back");
+
+    // the file is read through the page cache
+    let addr = mmap(0, 2 * PAGE, MmapProt::PROT_READ, MmapFlags::MAP_SHARED, fd, 0);
+    ok &= check(addr > 0, "mmap");
+    if addr > 0 {
+        let mapped = unsafe { core::slice::from_raw_parts(addr as *const u8, PAGE + 100) };
+        ok &= check(mapped.iter().all(|&b| b == 0x5a), "mapped data");
+        munmap(addr as usize, 2 * PAGE);
+    }
+
+    ok &= check(ftruncate(fd, 10) == 0 && size_of(fd) == 10, "truncate to shrink");
+    ok &= check(pread(fd, &mut buf, 0) == 10, "read the truncated file");
+    ok &= check(ftruncate(fd, 3 * PAGE) == 0 && size_of(fd) == 3 * PAGE as i64, "truncate to extend");
+    ok &= check(pread(fd, &mut buf, 2 * PAGE) == 100 && buf.iter().all(|&b| b == 0), "the extension reads as zero");
+    close(fd);
+
+    // rename inside a directory, then out of it
+    ok &= check(rename(LONG, MOVED) == 0, "rename in the directory");
+    ok &= check(open(LONG, OpenFlags::RDONLY) == ENOENT, "old name is gone");
+    ok &= check(rmdir(DIR) == ENOTEMPTY, "rmdir of a directory that is not empty");
+    ok &= check(rename(MOVED, TOP) == 0, "rename out of the directory");
+    let fd = open(TOP, OpenFlags::RDONLY);
+    ok &= check(fd >= 0 && size_of(fd as usize) == 3 * PAGE as i64, "moved file keeps its data");
+    close(fd as usize);
+
+    ok &= check(rmdir(DIR) == 0, "rmdir");
+    ok &= check(unlink(TOP) == 0, "unlink");
+    ok &= check(open(TOP, OpenFlags::RDONLY) == ENOENT, "unlinked file is gone");
+
+    if ok {
+        println!("test_fs_ops: passed");
+        0
+    } else {
+        -1
+    }
+}
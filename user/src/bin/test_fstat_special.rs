@@ -0,0 +1,72 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{check, close, fstat, fstatat, pipe, socket, statx, write, Stat, Statx, AT_EMPTY_PATH, STATX_BASIC_STATS};
+
+#[macro_use]
+extern crate user_lib;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFIFO: u32 = 0o010000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFSOCK: u32 = 0o140000;
+
+const AF_INET: i32 = 2;
+const SOCK_STREAM: i32 = 1;
+const SOCK_DGRAM: i32 = 2;
+const PAGE_SIZE: i32 = 4096;
+const CONSOLE_BLKSIZE: i32 = 1024;
+
+/// fstat `fd`, which must be of type `fmt`, and check fstatat and statx on it agree
+fn stat_fd(fd: usize, fmt: u32, what: &str) -> Option<Stat> {
+    let mut st = Stat::default();
+    if !check(fstat(fd, &mut st) == 0, what) || !check(st.st_mode & S_IFMT == fmt, what) {
+        return None;
+    }
+    let mut at = Stat::default();
+    let mut stx = Statx::default();
+    let agree = fstatat(fd as isize, "\0", &mut at, AT_EMPTY_PATH) == 0
+        && at.st_mode == st.st_mode
+        && at.st_blksize == st.st_blksize
+        && statx(fd as isize, "\0", AT_EMPTY_PATH, STATX_BASIC_STATS, &mut stx) == 0
+        && stx.stx_mode as u32 == st.st_mode
+        && stx.stx_blksize as i32 == st.st_blksize
+        && stx.stx_size as i64 == st.st_size;
+    check(agree, "fstatat and statx agree with fstat").then_some(st)
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    for fd in 0..3 {
+        let st = stat_fd(fd, S_IFCHR, "the console is a character device");
+        ok &= st.is_some_and(|st| check(st.st_blksize == CONSOLE_BLKSIZE && st.st_rdev != 0, "the console reports its block size and rdev"));
+    }
+
+    let mut fds = [0usize; 2];
+    ok &= check(pipe(&mut fds) == 0, "pipe");
+    ok &= stat_fd(fds[0], S_IFIFO, "the read end is a fifo")
+        .is_some_and(|st| check(st.st_size == 0 && st.st_blksize == PAGE_SIZE, "an empty pipe"));
+    ok &= check(write(fds[1], b"hello", 5) == 5, "write to the pipe");
+    for fd in fds {
+        ok &= stat_fd(fd, S_IFIFO, "both ends are fifos")
+            .is_some_and(|st| check(st.st_size == 5, "the size of a pipe is what it buffers"));
+    }
+    close(fds[0]);
+    close(fds[1]);
+
+    for sock_type in [SOCK_STREAM, SOCK_DGRAM] {
+        let fd = socket(AF_INET, sock_type, 0);
+        ok &= check(fd >= 0, "socket");
+        ok &= stat_fd(fd as usize, S_IFSOCK, "a socket is a socket").is_some();
+        close(fd as usize);
+    }
+
+    if ok {
+        println!("test_fstat_special: passed");
+        0
+    } else {
+        -1
+    }
+}
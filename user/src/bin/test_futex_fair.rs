@@ -0,0 +1,83 @@
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use user_lib::{fork, futex_wait, futex_wake, get_time_ms, mmap, wait, yield_, MmapFlags, MmapProt};
+
+#[macro_use]
+extern crate user_lib;
+
+const NTASKS: usize = 8;
+const DURATION_MS: isize = 5000;
+
+/// shared between all the children
+#[repr(C)]
+struct Shared {
+    /// 0: unlocked, 1: locked, 2: locked with waiters
+    lock: AtomicU32,
+    counts: [AtomicU64; NTASKS],
+}
+
+fn lock(word: &AtomicU32) {
+    if word.compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+        return;
+    }
+    while word.swap(2, Ordering::Acquire) != 0 {
+        futex_wait(word.as_ptr(), 2);
+    }
+}
+
+fn unlock(word: &AtomicU32) {
+    if word.swap(0, Ordering::Release) == 2 {
+        futex_wake(word.as_ptr(), 1);
+    }
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let ptr = mmap(
+        0, 4096,
+        MmapProt::PROT_READ | MmapProt::PROT_WRITE,
+        MmapFlags::MAP_ANONYMOUS | MmapFlags::MAP_SHARED,
+        0, 0
+    );
+    if ptr < 0 {
+        println!("test_futex_fair: mmap failed");
+        return -1;
+    }
+    let shared = unsafe { &*(ptr as *const Shared) };
+    let end = get_time_ms() + DURATION_MS;
+    for i in 0..NTASKS {
+        if fork() == 0 {
+            while get_time_ms() < end {
+                lock(&shared.lock);
+                shared.counts[i].fetch_add(1, Ordering::Relaxed);
+                unlock(&shared.lock);
+                yield_();
+            }
+            return 0;
+        }
+    }
+    let mut exit_code = 0;
+    for _ in 0..NTASKS {
+        wait(&mut exit_code);
+    }
+    let total: u64 = shared.counts.iter().map(|c| c.load(Ordering::Relaxed)).sum();
+    let mut ok = total > 0;
+    for (i, c) in shared.counts.iter().enumerate() {
+        let c = c.load(Ordering::Relaxed);
+        println!("task {}: {} of {} acquisitions", i, c, total);
+        // every task must get at least 5% of the lock
+        if c * 20 < total {
+            ok = false;
+        }
+    }
+    if ok {
+        println!("test_futex_fair passed!");
+        0
+    } else {
+        println!("test_futex_fair failed: some task starved");
+        -1
+    }
+}
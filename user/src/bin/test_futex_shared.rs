@@ -0,0 +1,118 @@
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use user_lib::{
+    check, close, exit, fork, ftruncate, futex_wait, futex_wake, memfd_create, mmap, waitpid, MmapFlags, MmapProt,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const PAGE: usize = 4096;
+const ROUNDS: u32 = 10000;
+
+/// a mutex and a condvar in the second page of a memfd
+#[repr(C)]
+struct Shared {
+    /// 0: unlocked, 1: locked, 2: locked with waiters
+    lock: AtomicU32,
+    /// bumped on every signal
+    seq: AtomicU32,
+    /// the number of turns taken, the parent takes the even ones
+    turn: AtomicU32,
+}
+
+fn lock(word: &AtomicU32) {
+    if word.compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+        return;
+    }
+    while word.swap(2, Ordering::Acquire) != 0 {
+        futex_wait(word.as_ptr(), 2);
+    }
+}
+
+fn unlock(word: &AtomicU32) {
+    if word.swap(0, Ordering::Release) == 2 {
+        futex_wake(word.as_ptr(), 1);
+    }
+}
+
+fn cond_wait(shared: &Shared) {
+    let seq = shared.seq.load(Ordering::Relaxed);
+    unlock(&shared.lock);
+    futex_wait(shared.seq.as_ptr(), seq);
+    lock(&shared.lock);
+}
+
+fn cond_signal(shared: &Shared) {
+    shared.seq.fetch_add(1, Ordering::Release);
+    futex_wake(shared.seq.as_ptr(), 1);
+}
+
+/// take every other turn, waiting on the condvar for the other side
+fn ping_pong(shared: &Shared, parity: u32) {
+    for _ in 0..ROUNDS {
+        lock(&shared.lock);
+        while shared.turn.load(Ordering::Relaxed) % 2 != parity {
+            cond_wait(shared);
+        }
+        shared.turn.fetch_add(1, Ordering::Relaxed);
+        cond_signal(shared);
+        unlock(&shared.lock);
+    }
+}
+
+/// map `len` bytes of the memfd from `offset`, the futexes at the start of the page at `PAGE`
+fn map(fd: usize, len: usize, offset: usize) -> Option<&'static Shared> {
+    let addr = mmap(0, len, MmapProt::PROT_READ | MmapProt::PROT_WRITE, MmapFlags::MAP_SHARED, fd, offset);
+    if addr < 0 {
+        return None;
+    }
+    Some(unsafe { &*((addr as usize + PAGE - offset) as *const Shared) })
+}
+
+/// the two processes reach the futexes through different mappings at different
+/// addresses and offsets, they meet only if the key is the page of the file
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    let fd = memfd_create("test_futex_shared\0", 0);
+    if !check(fd >= 0 && ftruncate(fd as usize, 2 * PAGE) == 0, "create the memfd") {
+        return -1;
+    }
+    let fd = fd as usize;
+    let Some(shared) = map(fd, 2 * PAGE, 0) else {
+        check(false, "map the memfd");
+        return -1;
+    };
+
+    let pid = fork();
+    if pid == 0 {
+        // a mapping of its own, of the second page only
+        let Some(mine) = map(fd, PAGE, PAGE) else {
+            println!("test_futex_shared: map the memfd in the child failed");
+            exit(1);
+        };
+        if core::ptr::eq(mine, shared) {
+            println!("test_futex_shared: the second mapping at the same address");
+            exit(1);
+        }
+        ping_pong(mine, 1);
+        exit(0);
+    }
+    ok &= check(pid > 0, "fork");
+    ping_pong(shared, 0);
+    let mut exit_code = 0;
+    ok &= check(waitpid(pid as usize, &mut exit_code) == pid && exit_code == 0, "the child");
+    ok &= check(shared.turn.load(Ordering::Relaxed) == 2 * ROUNDS, "the number of turns");
+    close(fd);
+
+    if ok {
+        println!("test_futex_shared: passed");
+        0
+    } else {
+        -1
+    }
+}
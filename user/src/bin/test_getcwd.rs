@@ -0,0 +1,93 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    chdir, check, close, fchdir, getcwd, getcwd_raw, mkdir, open, rename, rmdir, OpenFlags, EFAULT, EINVAL, ENOENT,
+    ERANGE,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+/// the path returned by getcwd, without the trailing nul
+fn cwd_of(buf: &[u8]) -> &str {
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    core::str::from_utf8(&buf[..len]).unwrap()
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut buf = [0u8; 512];
+    let mut ok = true;
+
+    // empty buffer
+    ok &= check(getcwd(&mut []) == EINVAL, "getcwd with len 0");
+    ok &= check(getcwd_raw(core::ptr::null_mut(), 64) == EFAULT, "getcwd into NULL");
+
+    // the raw syscall returns strlen(path) + 1, the nul within the length
+    chdir("/\0");
+    buf.fill(0xff);
+    ok &= check(getcwd(&mut buf) == 2 && &buf[..3] == b"/\0\xff", "the return value at the root");
+    ok &= check(getcwd(&mut buf[..1]) == ERANGE, "no room for the nul");
+
+    // fchdir to an open directory
+    mkdir("/test_cwd\0");
+    let fd = open("/test_cwd\0", OpenFlags::RDONLY);
+    ok &= check(fd >= 0, "open dir");
+    chdir("/\0");
+    ok &= check(fchdir(fd as usize) == 0, "fchdir");
+    buf.fill(0xff);
+    let len = getcwd(&mut buf);
+    ok &= check(len > 0 && cwd_of(&buf) == "/test_cwd", "getcwd after fchdir");
+    ok &= check(len == "/test_cwd".len() as isize + 1, "the return value is strlen + 1");
+    ok &= check(buf[len as usize - 1] == 0 && buf[len as usize] == 0xff, "nothing written past the nul");
+    ok &= check(getcwd(&mut buf[..len as usize]) == len, "a buffer of exactly the length");
+    close(fd as usize);
+
+    // a cwd inside a renamed directory has the new path
+    mkdir("/test_cwd/old\0");
+    mkdir("/test_cwd/old/inner\0");
+    chdir("/test_cwd/old/inner\0");
+    ok &= check(rename("/test_cwd/old\0", "/test_cwd/new\0") == 0, "rename the parent of the cwd");
+    let len = getcwd(&mut buf);
+    ok &= check(len == "/test_cwd/new/inner".len() as isize + 1 && cwd_of(&buf) == "/test_cwd/new/inner", "getcwd after the rename");
+    chdir("/\0");
+    rmdir("/test_cwd/new/inner\0");
+    rmdir("/test_cwd/new\0");
+
+    // removed cwd
+    mkdir("/test_cwd/gone\0");
+    chdir("/test_cwd/gone\0");
+    ok &= check(rmdir("/test_cwd/gone\0") == 0, "rmdir cwd");
+    ok &= check(getcwd(&mut buf) == ENOENT, "getcwd of removed cwd");
+
+    // deep path with a short buffer: 30 levels of 10 characters
+    chdir("/test_cwd\0");
+    const LEVEL: &str = "dddddddddd\0";
+    for _ in 0..30 {
+        mkdir(LEVEL);
+        if chdir(LEVEL) != 0 {
+            ok &= check(false, "chdir deep");
+            break;
+        }
+    }
+    let mut short = [0u8; 64];
+    ok &= check(getcwd(&mut short) == ERANGE, "getcwd with short buffer");
+    let len = getcwd(&mut buf);
+    ok &= check(len > 300 && len as usize == cwd_of(&buf).len() + 1, "getcwd of deep path");
+
+    // clean up
+    for _ in 0..30 {
+        chdir("..\0");
+        rmdir(LEVEL);
+    }
+    chdir("/\0");
+    rmdir("/test_cwd\0");
+
+    if ok {
+        println!("test_getcwd passed!");
+        0
+    } else {
+        -1
+    }
+}
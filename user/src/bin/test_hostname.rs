@@ -0,0 +1,72 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, exit, fork, getpid, prctl, sethostname, syslog, uname, uname_field, waitpid, EINVAL, PR_SET_SYSCALL_TRACE,
+    SYSLOG_ACTION_READ_ALL,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const LOG_SIZE: usize = 1 << 16;
+/// the name the kernel has before it is named
+const DEFAULT_HOSTNAME: &[u8] = b"Linux";
+
+static mut LOG: [u8; LOG_SIZE] = [0; LOG_SIZE];
+
+/// the node name uname shows, into `uts`
+fn nodename(uts: &mut [u8; 390]) -> &[u8] {
+    if uname(uts) != 0 {
+        return &[];
+    }
+    uname_field(uts, 1)
+}
+
+/// a child with the syscall trace on makes a syscall, which the kernel logs
+fn log_a_record() {
+    let pid = fork();
+    if pid == 0 {
+        prctl(PR_SET_SYSCALL_TRACE, 1, 0);
+        getpid();
+        exit(0);
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut uts = [0u8; 390];
+
+    // the longest name which still leaves the nul of the utsname field
+    let mut name = [b'n'; 63];
+    name[..8].copy_from_slice(b"chronix-");
+    let mut ok = check(sethostname(&name) == 0, "sethostname of 63 bytes");
+    ok &= check(nodename(&mut uts) == name, "uname shows the hostname");
+    ok &= check(sethostname(&[b'x'; 65]) == EINVAL, "a hostname of 65 bytes is EINVAL");
+    ok &= check(nodename(&mut uts) == name, "a rejected hostname keeps the old one");
+
+    // the log records of the kernel carry the name
+    log_a_record();
+    #[allow(static_mut_refs)]
+    let log = unsafe { &mut LOG };
+    let len = syslog(SYSLOG_ACTION_READ_ALL, log);
+    ok &= check(len > 0, "syslog");
+    let mut prefix = [0u8; 66];
+    prefix[0] = b'[';
+    prefix[1..64].copy_from_slice(&name);
+    prefix[64..].copy_from_slice(b"] ");
+    let log = &log[..len.max(0) as usize];
+    ok &= check(log.windows(prefix.len()).any(|w| w == prefix), "the hostname prefixes the kernel log");
+
+    sethostname(DEFAULT_HOSTNAME);
+    ok &= check(nodename(&mut uts) == DEFAULT_HOSTNAME, "restore the hostname");
+
+    if ok {
+        println!("test_hostname: passed");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,133 @@
+#![no_std]
+#![no_main]
+
+use core::ptr::{read_volatile, write_volatile};
+
+use user_lib::{
+    check, exit, fork, mmap, mprotect, munmap, vm_stats, waitpid, MmapFlags, MmapProt, VmEventCounts, ENOMEM, SIGSEGV,
+    VM_COW, VM_MINOR_FAULT,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const PAGE_SIZE: usize = 4096;
+const HUGE_SIZE: usize = 2 << 20;
+const PAGES: usize = HUGE_SIZE / PAGE_SIZE;
+/// the page made read only, in the first huge page
+const PROTECTED: usize = 16;
+/// the page unmapped, in the second huge page
+const UNMAPPED: usize = PAGES + 8;
+
+fn counts() -> VmEventCounts {
+    let mut counts = [0; 6];
+    vm_stats(&mut counts);
+    counts
+}
+
+fn page(base: usize, i: usize) -> *mut u8 {
+    (base + i * PAGE_SIZE) as *mut u8
+}
+
+/// a private anonymous MAP_HUGETLB mapping of `huge_pages` huge pages
+fn map_huge(huge_pages: usize) -> isize {
+    let prot = MmapProt::PROT_READ | MmapProt::PROT_WRITE;
+    let flags = MmapFlags::MAP_PRIVATE | MmapFlags::MAP_ANONYMOUS | MmapFlags::MAP_HUGETLB;
+    mmap(0, huge_pages * HUGE_SIZE, prot, flags, usize::MAX, 0)
+}
+
+/// every page of `pages` but those in `skip` holds its number
+fn pages_intact(base: usize, pages: usize, skip: &[usize]) -> bool {
+    (0..pages).filter(|i| !skip.contains(i)).all(|i| unsafe { read_volatile(page(base, i)) } == i as u8)
+}
+
+/// the signal which ended a child touching `addr`, 0 if it exited
+fn touch_in_child(addr: *mut u8, write: bool) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        unsafe {
+            if write {
+                write_volatile(addr, 1);
+            } else {
+                read_volatile(addr);
+            }
+        }
+        exit(0);
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    status & 0x7f
+}
+
+/// a child writing one page of a huge mapping copies that page only, the parent keeps its data
+fn cow_case() -> bool {
+    let base = map_huge(1);
+    if base < 0 {
+        return check(false, "a second MAP_HUGETLB mapping");
+    }
+    let base = base as usize;
+    for i in 0..PAGES {
+        unsafe { write_volatile(page(base, i), i as u8) };
+    }
+    let pid = fork();
+    if pid == 0 {
+        let before = counts();
+        unsafe { write_volatile(page(base, 3), 0xff) };
+        let after = counts();
+        let copied_one = after[VM_COW] - before[VM_COW] == 1;
+        let sees_own = unsafe { read_volatile(page(base, 3)) } == 0xff && pages_intact(base, PAGES, &[3]);
+        exit(if copied_one && sees_own { 0 } else { 1 });
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    let mut ok = check(status == 0, "the write of the child copies one small page");
+    ok &= check(pages_intact(base, PAGES, &[]), "the parent keeps its data after the copy of the child");
+    munmap(base, HUGE_SIZE);
+    ok
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let base = map_huge(2);
+    if base == ENOMEM {
+        println!("test_hugetlb: no huge pages here, skipped");
+        return 0;
+    }
+    let mut ok = check(base > 0, "MAP_HUGETLB");
+    if !ok {
+        return -1;
+    }
+    let base = base as usize;
+    ok &= check(base % HUGE_SIZE == 0, "a huge mapping is 2 MiB aligned");
+
+    // populated at mmap, reading and writing every page takes no fault
+    let before = counts();
+    ok &= check((0..2 * PAGES).all(|i| unsafe { read_volatile(page(base, i)) } == 0), "a huge mapping is zeroed");
+    for i in 0..2 * PAGES {
+        unsafe { write_volatile(page(base, i), i as u8) };
+    }
+    ok &= check(counts()[VM_MINOR_FAULT] == before[VM_MINOR_FAULT], "MAP_HUGETLB populates the mapping");
+
+    // a partial mprotect and a partial munmap demote the huge pages,
+    // the small pages left keep their data and stay mapped
+    ok &= check(mprotect(page(base, PROTECTED) as usize, PAGE_SIZE, MmapProt::PROT_READ) == 0, "mprotect of one page");
+    ok &= check(munmap(page(base, UNMAPPED) as usize, PAGE_SIZE) == 0, "munmap of one page");
+    let before = counts();
+    ok &= check(pages_intact(base, 2 * PAGES, &[UNMAPPED]), "the demoted pages keep their data");
+    for i in (0..2 * PAGES).filter(|&i| i != PROTECTED && i != UNMAPPED) {
+        unsafe { write_volatile(page(base, i), i as u8) };
+    }
+    ok &= check(counts()[VM_MINOR_FAULT] == before[VM_MINOR_FAULT], "the demoted pages stay mapped and writable");
+    ok &= check(touch_in_child(page(base, PROTECTED), true) == SIGSEGV, "a write to the read only page faults");
+    ok &= check(touch_in_child(page(base, UNMAPPED), false) == SIGSEGV, "a read of the unmapped page faults");
+    munmap(base, 2 * HUGE_SIZE);
+
+    ok &= cow_case();
+
+    if ok {
+        println!("test_hugetlb: passed");
+        0
+    } else {
+        -1
+    }
+}
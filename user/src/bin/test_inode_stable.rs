@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{check, close, fstat, link, open, pread, unlink, write, OpenFlags, Stat};
+
+#[macro_use]
+extern crate user_lib;
+
+const FILE: &str = "/test_inode_stable_a\0";
+const LINK: &str = "/test_inode_stable_b\0";
+
+fn stat_of(path: &str) -> Option<Stat> {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd < 0 {
+        return None;
+    }
+    let mut stat = Stat::default();
+    let ret = fstat(fd as usize, &mut stat);
+    close(fd as usize);
+    (ret == 0).then_some(stat)
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDWR);
+    if fd < 0 || link(FILE, LINK) < 0 {
+        println!("test_inode_stable: can not create {} and its link", FILE);
+        return -1;
+    }
+    let fd = fd as usize;
+
+    // every lookup of either name is the same inode
+    let (a, b, again) = (stat_of(FILE), stat_of(LINK), stat_of(FILE));
+    match (a, b, again) {
+        (Some(a), Some(b), Some(again)) => {
+            ok &= check(a.st_ino == b.st_ino, "same st_ino through both links");
+            ok &= check(a.st_ino == again.st_ino, "same st_ino on a second lookup");
+            ok &= check(a.st_dev == b.st_dev, "same st_dev through both links");
+            ok &= check(a.st_nlink == 2, "two links");
+        }
+        _ => ok &= check(false, "stat both links"),
+    }
+
+    // one page cache: a write through one name is read through the other without fsync
+    let data = b"written through the first link";
+    ok &= check(write(fd, data, data.len()) == data.len() as isize, "write through the first link");
+    let other = open(LINK, OpenFlags::RDONLY);
+    ok &= check(other >= 0, "open the second link");
+    let mut buf = [0u8; 64];
+    let n = pread(other as usize, &mut buf, 0);
+    ok &= check(n == data.len() as isize && &buf[..data.len()] == data, "read through the second link");
+    close(other as usize);
+
+    close(fd);
+    unlink(LINK);
+    unlink(FILE);
+
+    if ok {
+        println!("test_inode_stable: passed");
+        0
+    } else {
+        -1
+    }
+}
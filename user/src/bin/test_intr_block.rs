@@ -0,0 +1,96 @@
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::AtomicU32;
+
+use user_lib::{
+    accept, bind, check, exit, fork, futex_wait, get_time_ms, kill, listen, nanosleep, pipe, read, sigaction_flags,
+    socket, waitpid, SockaddrIn, EINTR, SIGKILL, SIGTERM,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const AF_INET: i32 = 2;
+const SOCK_STREAM: i32 = 1;
+const IPPROTO_TCP: i32 = 6;
+const TEST_ADDR: u32 = 0x7f000001; // 127.0.0.1
+/// how long the child is left blocking before the signal
+const BLOCK_MS: usize = 300;
+/// how soon after the signal it must be gone
+const PROMPT_MS: isize = 1000;
+
+extern "C" fn handler(_signo: i32) {}
+
+/// block in accept on a listener nobody connects to
+fn block_accept(port: u16) -> isize {
+    let fd = socket(AF_INET, SOCK_STREAM, IPPROTO_TCP);
+    let addr = SockaddrIn::new(TEST_ADDR.to_be(), port.to_be());
+    if fd < 0 || bind(fd as usize, &addr, size_of::<SockaddrIn>() as u32) < 0 || listen(fd as usize, 1) < 0 {
+        return 0;
+    }
+    accept(fd as usize, core::ptr::null_mut(), core::ptr::null_mut())
+}
+
+/// block reading a pipe whose write end stays open and unwritten
+fn block_pipe() -> isize {
+    let mut fds = [0usize; 2];
+    if pipe(&mut fds) < 0 {
+        return 0;
+    }
+    let mut buf = [0u8; 8];
+    read(fds[0], &mut buf)
+}
+
+/// block on a futex nobody wakes
+fn block_futex() -> isize {
+    let word = AtomicU32::new(0);
+    futex_wait(word.as_ptr(), 0)
+}
+
+/// run `block` in a child, send it `signo` once it blocked, return the wait status
+/// and whether the child was gone promptly
+fn signalled(block: fn() -> isize, signo: i32) -> (i32, bool) {
+    let pid = fork();
+    if pid == 0 {
+        // with a handler the call returns EINTR, the child tells by its exit code
+        sigaction_flags(SIGTERM, handler as usize, 0);
+        let ret = block();
+        exit(if ret == EINTR { 0 } else { 1 });
+    }
+    nanosleep(BLOCK_MS);
+    kill(pid, signo);
+    let start = get_time_ms();
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    (status, get_time_ms() - start <= PROMPT_MS)
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    let cases: [(&str, fn() -> isize, fn() -> isize); 3] = [
+        ("accept", || block_accept(4461), || block_accept(4462)),
+        ("pipe read", block_pipe, block_pipe),
+        ("futex wait", block_futex, block_futex),
+    ];
+    for (what, killed, interrupted) in cases {
+        let (status, prompt) = signalled(killed, SIGKILL);
+        if !check(status & 0x7f == SIGKILL && prompt, "SIGKILL") {
+            println!("test_intr_block: {} not killed, status {:#x}", what, status);
+            ok = false;
+        }
+        let (status, prompt) = signalled(interrupted, SIGTERM);
+        if !check(status == 0 && prompt, "SIGTERM with a handler") {
+            println!("test_intr_block: {} not interrupted with EINTR, status {:#x}", what, status);
+            ok = false;
+        }
+    }
+
+    if ok {
+        println!("test_intr_block: passed");
+        0
+    } else {
+        -1
+    }
+}
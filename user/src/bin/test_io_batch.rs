@@ -0,0 +1,119 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, get_time_ms, io_submit_batch, open, pread, unlink, write, IoBatchOp, IoBatchResult, OpenFlags,
+    AT_FDCWD, EBADF, EFAULT, EINVAL, IO_BATCH_MAX,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const FILE: &str = "/test_io_batch_file\0";
+const PAGE: usize = 4096;
+const PAGES: usize = 256;
+const READS: usize = 2048;
+const BATCH: usize = 64;
+
+/// a small lcg, good enough to scatter the reads
+fn next_page(seed: &mut u64) -> usize {
+    *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+    (*seed >> 33) as usize % PAGES
+}
+
+/// every byte of page i is i
+fn page_ok(buf: &[u8], page: usize) -> bool {
+    buf.iter().all(|&b| b == page as u8)
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDWR);
+    if fd < 0 {
+        println!("test_io_batch: can not create {}", FILE);
+        return -1;
+    }
+    let fd = fd as usize;
+    let mut page = [0u8; PAGE];
+    for i in 0..PAGES {
+        page.fill(i as u8);
+        write(fd, &page, PAGE);
+    }
+
+    // 4 KiB random preads, one syscall each
+    let mut seed = 1;
+    let start = get_time_ms();
+    for _ in 0..READS {
+        let n = next_page(&mut seed);
+        ok &= pread(fd, &mut page, n * PAGE) == PAGE as isize && page_ok(&page, n);
+    }
+    let single_ms = get_time_ms() - start;
+    ok &= check(ok, "single preads");
+
+    // the same reads, 64 per syscall
+    let mut seed = 1;
+    let mut bufs = [[0u8; PAGE]; BATCH];
+    let mut pages = [0usize; BATCH];
+    let mut ops = [IoBatchOp::default(); BATCH];
+    let mut results = [IoBatchResult::default(); BATCH];
+    let start = get_time_ms();
+    for _ in 0..READS / BATCH {
+        for (i, buf) in bufs.iter_mut().enumerate() {
+            pages[i] = next_page(&mut seed);
+            ops[i] = IoBatchOp::pread(fd, buf, pages[i] * PAGE, i as u64);
+        }
+        ok &= io_submit_batch(&ops, &mut results) == BATCH as isize;
+        for (i, res) in results.iter().enumerate() {
+            ok &= res.user_data == i as u64 && res.res == PAGE as i64 && page_ok(&bufs[i], pages[i]);
+        }
+    }
+    let batch_ms = get_time_ms() - start;
+    ok &= check(ok, "batched preads");
+    println!("test_io_batch: {} preads took {} ms one by one, {} ms in batches of {}", READS, single_ms, batch_ms, BATCH);
+
+    // a failed op does not stop the batch
+    let mut buf = [0u8; 16];
+    let mut scratch = [0u8; 4];
+    let ops = [
+        IoBatchOp::pwrite(fd, b"batched", 0, 10),
+        IoBatchOp::pread(1000, &mut scratch, 0, 11),
+        IoBatchOp::fsync(fd, 12),
+        IoBatchOp::pread(fd, &mut buf, 0, 13),
+    ];
+    let mut results = [IoBatchResult::default(); 4];
+    ok &= check(io_submit_batch(&ops, &mut results) == 4, "batch with a failed op");
+    ok &= check(results[0].res == 7 && results[1].res == EBADF as i64 && results[2].res == 0, "results of mixed ops");
+    ok &= check(results[3].res == 16 && &buf[..7] == b"batched", "read back the batched write");
+
+    // open and close in a batch
+    let ops = [IoBatchOp::openat(AT_FDCWD, FILE, OpenFlags::RDONLY, 0, 20)];
+    ok &= check(io_submit_batch(&ops, &mut results) == 1 && results[0].res >= 0, "batched openat");
+    let opened = results[0].res as usize;
+    let ops = [IoBatchOp::close(opened, 21), IoBatchOp::close(opened, 22)];
+    ok &= check(io_submit_batch(&ops, &mut results) == 2, "batched close");
+    ok &= check(results[0].res == 0 && results[1].res == EBADF as i64, "close twice");
+
+    // a bad buffer stops the batch before anything runs past it
+    let mut ops = [IoBatchOp::pread(fd, &mut buf, 0, 30); 3];
+    ops[1].buf = 0x10;
+    ops[2].user_data = 32;
+    results = [IoBatchResult { user_data: 0, res: 1 }; 4];
+    ok &= check(io_submit_batch(&ops, &mut results) == 2, "batch with a bad buffer");
+    ok &= check(results[0].res == 16 && results[1].res == EFAULT as i64 && results[2].res == 1, "stop at the bad buffer");
+
+    let ops = [IoBatchOp::fsync(fd, 0); IO_BATCH_MAX + 1];
+    let mut results = [IoBatchResult::default(); IO_BATCH_MAX + 1];
+    ok &= check(io_submit_batch(&ops, &mut results) == EINVAL, "too many ops");
+
+    close(fd);
+    unlink(FILE);
+
+    if ok {
+        println!("test_io_batch passed!");
+        0
+    } else {
+        -1
+    }
+}
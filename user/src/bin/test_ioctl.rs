@@ -0,0 +1,84 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, fcntl, ioctl, isatty, open, pipe, read, unlink, write, OpenFlags, EFAULT, ENOTTY, FIOCLEX, FIONBIO,
+    FIONCLEX, FIONREAD, F_GETFD, F_GETFL, O_NONBLOCK, TCGETS,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+/// a command no file type knows
+const UNKNOWN_CMD: usize = 0x5499;
+
+fn fionread(fd: usize) -> isize {
+    let mut n: i32 = -1;
+    let ret = ioctl(fd, FIONREAD, &mut n as *mut i32 as usize);
+    if ret < 0 { ret } else { n as isize }
+}
+
+fn fionbio(fd: usize, on: bool) -> isize {
+    let on: i32 = on as i32;
+    ioctl(fd, FIONBIO, &on as *const i32 as usize)
+}
+
+fn nonblocking(fd: usize) -> bool {
+    fcntl(fd, F_GETFL, 0) as usize & O_NONBLOCK != 0
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    // FIONREAD on a half full pipe, from either end
+    let mut fds = [0usize; 2];
+    ok &= check(pipe(&mut fds) == 0, "pipe");
+    let (rd, wr) = (fds[0], fds[1]);
+    ok &= check(fionread(rd) == 0, "FIONREAD of an empty pipe");
+    let data = [7u8; 100];
+    ok &= check(write(wr, &data, data.len()) == 100, "write to the pipe");
+    ok &= check(fionread(rd) == 100, "FIONREAD of the read end");
+    ok &= check(fionread(wr) == 100, "FIONREAD of the write end");
+    let mut buf = [0u8; 40];
+    ok &= check(read(rd, &mut buf) == 40, "read part of the pipe");
+    ok &= check(fionread(rd) == 60, "FIONREAD after a read");
+    ok &= check(ioctl(rd, FIONREAD, 0) == EFAULT, "FIONREAD to a null pointer is EFAULT");
+
+    // FIONBIO sets and clears the same O_NONBLOCK as fcntl sees
+    ok &= check(!nonblocking(rd), "a pipe starts blocking");
+    ok &= check(fionbio(rd, true) == 0 && nonblocking(rd), "FIONBIO sets O_NONBLOCK");
+    ok &= check(fionbio(rd, false) == 0 && !nonblocking(rd), "FIONBIO clears O_NONBLOCK");
+
+    // FIOCLEX and FIONCLEX are the close-on-exec flag of the fd
+    ok &= check(ioctl(rd, FIOCLEX, 0) == 0 && fcntl(rd, F_GETFD, 0) == 1, "FIOCLEX");
+    ok &= check(ioctl(rd, FIONCLEX, 0) == 0 && fcntl(rd, F_GETFD, 0) == 0, "FIONCLEX");
+
+    ok &= check(ioctl(rd, TCGETS, buf.as_mut_ptr() as usize) == ENOTTY, "TCGETS of a pipe is ENOTTY");
+    close(rd);
+    close(wr);
+
+    // the console is a terminal, a regular file is not
+    ok &= check(isatty(0) && isatty(1), "isatty of the console");
+    let file = open("/test_ioctl_file\0", OpenFlags::CREATE | OpenFlags::RDWR);
+    ok &= check(file >= 0, "create a regular file");
+    let file = file as usize;
+    ok &= check(!isatty(file), "isatty of a regular file");
+    let mut termios = [0u8; 64];
+    ok &= check(ioctl(file, TCGETS, termios.as_mut_ptr() as usize) == ENOTTY, "TCGETS of a regular file is ENOTTY");
+    ok &= check(fionread(file) == ENOTTY, "FIONREAD of a regular file is ENOTTY");
+    ok &= check(fionbio(file, true) == 0 && nonblocking(file), "FIONBIO works on any fd");
+    close(file);
+    unlink("/test_ioctl_file\0");
+
+    // unknown commands fail instead of taking the kernel down
+    ok &= check(ioctl(0, UNKNOWN_CMD, 0) == ENOTTY, "unknown command on the console");
+    ok &= check(ioctl(0, TCGETS, 0) == EFAULT, "TCGETS to a null pointer is EFAULT");
+
+    if ok {
+        println!("test_ioctl: passed");
+        0
+    } else {
+        -1
+    }
+}
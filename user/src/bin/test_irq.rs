@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{read, syslog, SYSLOG_ACTION_READ_ALL};
+
+#[macro_use]
+extern crate user_lib;
+
+const LOG_SIZE: usize = 1 << 16;
+const PATTERN: &[u8] = b"(serial) fired ";
+
+/// the largest count in the "[irq] N (serial) fired K times" records of the kernel log ring,
+/// the count is logged each time it reaches a power of two
+fn serial_irq_count(log: &[u8]) -> usize {
+    let mut max = 0;
+    for (i, window) in log.windows(PATTERN.len()).enumerate() {
+        if window != PATTERN {
+            continue;
+        }
+        let digits = log[i + PATTERN.len()..].iter().take_while(|b| b.is_ascii_digit());
+        let count = digits.fold(0, |n, &b| n * 10 + (b - b'0') as usize);
+        max = max.max(count);
+    }
+    max
+}
+
+static mut LOG: [u8; LOG_SIZE] = [0; LOG_SIZE];
+
+/// the uart rx interrupt must reach the serial driver through the interrupt controller
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    #[allow(static_mut_refs)]
+    let log = unsafe { &mut LOG };
+
+    println!("test_irq: press a key");
+    let mut key = [0u8; 1];
+    if read(0, &mut key) != 1 {
+        println!("test_irq: read from stdin failed");
+        return -1;
+    }
+
+    let len = syslog(SYSLOG_ACTION_READ_ALL, log);
+    if len < 0 {
+        println!("test_irq: syslog failed");
+        return -1;
+    }
+    let count = serial_irq_count(&log[..len as usize]);
+    println!("test_irq: the serial irq was counted {} times", count);
+    if count == 0 {
+        println!("test_irq: no serial irq in the kernel log");
+        return -1;
+    }
+    println!("test_irq passed!");
+    0
+}
@@ -0,0 +1,131 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, linkat, mkdir, nanosleep, open, read, rmdir, statx, symlink, unlink, write, OpenFlags, Statx,
+    AT_EMPTY_PATH, AT_FDCWD, AT_SYMLINK_FOLLOW, AT_SYMLINK_NOFOLLOW, EEXIST, EINVAL, ENOENT, EPERM, EXDEV,
+    STATX_BASIC_STATS,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const S_IFMT: u16 = 0o170000;
+const S_IFREG: u16 = 0o100000;
+const S_IFLNK: u16 = 0o120000;
+
+const FILE: &str = "/test_linkat_file\0";
+const SYMLINK: &str = "/test_linkat_sym\0";
+const DIR: &str = "/test_linkat_dir\0";
+/// the names made by the test, removed at the end
+const NAMES: [&str; 6] = [
+    "/test_linkat_plain\0",
+    "/test_linkat_sym_link\0",
+    "/test_linkat_followed\0",
+    "/test_linkat_by_fd\0",
+    SYMLINK,
+    FILE,
+];
+
+/// lstat of `path`, None when it does not exist
+fn lstat(path: &str) -> Option<Statx> {
+    let mut stx = Statx::default();
+    (statx(AT_FDCWD, path, AT_SYMLINK_NOFOLLOW, STATX_BASIC_STATS, &mut stx) == 0).then_some(stx)
+}
+
+fn nlink(path: &str) -> u32 {
+    lstat(path).map_or(0, |stx| stx.stx_nlink)
+}
+
+fn file_type(path: &str) -> u16 {
+    lstat(path).map_or(0, |stx| stx.stx_mode & S_IFMT)
+}
+
+fn ctime(path: &str) -> (i64, u32) {
+    lstat(path).map_or((0, 0), |stx| (stx.stx_ctime.tv_sec, stx.stx_ctime.tv_nsec))
+}
+
+fn content_is(path: &str, data: &[u8]) -> bool {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd < 0 {
+        return false;
+    }
+    let mut buf = [0u8; 32];
+    let len = read(fd as usize, &mut buf);
+    close(fd as usize);
+    len >= 0 && &buf[..len as usize] == data
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    for name in NAMES {
+        unlink(name);
+    }
+
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDWR);
+    ok &= check(fd >= 0 && write(fd as usize, b"linked", 6) == 6, "create the file");
+    close(fd as usize);
+    ok &= check(symlink(FILE, SYMLINK) == 0, "create the symlink");
+    ok &= check(nlink(FILE) == 1 && nlink(SYMLINK) == 1, "one link each to start with");
+
+    // a plain file: a second name for the same inode, with a new ctime
+    let before = ctime(FILE);
+    nanosleep(10);
+    ok &= check(linkat(AT_FDCWD, FILE, AT_FDCWD, NAMES[0], 0) == 0, "link a file");
+    ok &= check(nlink(FILE) == 2 && nlink(NAMES[0]) == 2, "a link counts on both names");
+    ok &= check(lstat(FILE).unwrap_or_default().stx_ino == lstat(NAMES[0]).unwrap_or_default().stx_ino, "the same inode");
+    ok &= check(ctime(FILE) > before, "a link changes the ctime");
+    ok &= check(content_is(NAMES[0], b"linked"), "the new name reads the data");
+
+    // a symlink is linked itself by default
+    ok &= check(linkat(AT_FDCWD, SYMLINK, AT_FDCWD, NAMES[1], 0) == 0, "link a symlink");
+    ok &= check(file_type(NAMES[1]) == S_IFLNK, "the link of a symlink is a symlink");
+    ok &= check(nlink(SYMLINK) == 2 && nlink(FILE) == 2, "only the symlink gained a link");
+    ok &= check(content_is(NAMES[1], b"linked"), "the linked symlink still points at the file");
+
+    // AT_SYMLINK_FOLLOW links what it points to
+    ok &= check(linkat(AT_FDCWD, SYMLINK, AT_FDCWD, NAMES[2], AT_SYMLINK_FOLLOW as u32) == 0, "link through a symlink");
+    ok &= check(file_type(NAMES[2]) == S_IFREG, "following links the target file");
+    ok &= check(nlink(FILE) == 3 && nlink(SYMLINK) == 2, "the target gained the link");
+    // following a plain file is the same as not following
+    ok &= check(linkat(AT_FDCWD, FILE, AT_FDCWD, NAMES[3], AT_SYMLINK_FOLLOW as u32) == 0, "follow a plain file");
+    ok &= check(nlink(FILE) == 4, "following a plain file links it");
+    ok &= check(unlink(NAMES[3]) == 0 && nlink(FILE) == 3, "unlink takes a link away");
+
+    // AT_EMPTY_PATH links the open file
+    let fd = open(FILE, OpenFlags::RDONLY);
+    ok &= check(linkat(fd, "\0", AT_FDCWD, NAMES[3], AT_EMPTY_PATH as u32) == 0, "link an fd");
+    ok &= check(nlink(FILE) == 4 && file_type(NAMES[3]) == S_IFREG, "the fd was linked");
+    ok &= check(linkat(fd, "\0", AT_FDCWD, "/test_linkat_none\0", 0) == ENOENT, "an empty path without AT_EMPTY_PATH");
+    close(fd as usize);
+
+    // the errors
+    ok &= check(linkat(AT_FDCWD, FILE, AT_FDCWD, NAMES[0], 0) == EEXIST, "linking to an existing name is EEXIST");
+    ok &= check(linkat(AT_FDCWD, FILE, AT_FDCWD, SYMLINK, AT_SYMLINK_FOLLOW as u32) == EEXIST, "a symlink as the new name is EEXIST");
+    ok &= check(linkat(AT_FDCWD, "/test_linkat_none\0", AT_FDCWD, "/test_linkat_new\0", 0) == ENOENT, "a missing source is ENOENT");
+    ok &= check(linkat(AT_FDCWD, FILE, AT_FDCWD, "/proc/test_linkat\0", 0) == EXDEV, "a link into another file system is EXDEV");
+    ok &= check(linkat(AT_FDCWD, "/proc/meminfo\0", AT_FDCWD, "/test_linkat_new\0", 0) == EXDEV, "a link out of another file system is EXDEV");
+    ok &= check(mkdir(DIR) == 0, "mkdir");
+    ok &= check(linkat(AT_FDCWD, DIR, AT_FDCWD, "/test_linkat_new\0", 0) == EPERM, "linking a directory is EPERM");
+    rmdir(DIR);
+    ok &= check(linkat(AT_FDCWD, FILE, AT_FDCWD, "/test_linkat_new\0", 0x1) == EINVAL, "an unknown flag is EINVAL");
+    ok &= check(lstat("/test_linkat_new\0").is_none(), "a failed link leaves no name");
+    ok &= check(nlink(FILE) == 4, "a failed link leaves the count");
+
+    // unlinking the names takes the count back down
+    for (i, name) in NAMES.iter().enumerate() {
+        ok &= check(unlink(name) == 0, "unlink a name");
+        if i < 3 {
+            ok &= check(nlink(FILE) >= 1, "the file lives on under another name");
+        }
+    }
+    ok &= check(lstat(FILE).is_none(), "every name is gone");
+
+    if ok {
+        println!("test_linkat: passed");
+        0
+    } else {
+        -1
+    }
+}
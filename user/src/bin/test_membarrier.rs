@@ -0,0 +1,106 @@
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{compiler_fence, AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use user_lib::{
+    check, exit, membarrier, sched_setaffinity, thread_spawn, yield_, EPERM, MEMBARRIER_CMD_GLOBAL,
+    MEMBARRIER_CMD_PRIVATE_EXPEDITED, MEMBARRIER_CMD_QUERY, MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const ROUNDS: u64 = 2000;
+const STACK_SIZE: usize = 16 * 1024;
+
+static mut READER_STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+/// seqlock protected pair, `b` is always `!a`
+static SEQ: AtomicU64 = AtomicU64::new(0);
+static A: AtomicU64 = AtomicU64::new(0);
+static B: AtomicU64 = AtomicU64::new(!0);
+
+static STOP: AtomicBool = AtomicBool::new(false);
+static READER_DONE: AtomicBool = AtomicBool::new(false);
+static TORN_READS: AtomicUsize = AtomicUsize::new(0);
+static GOOD_READS: AtomicUsize = AtomicUsize::new(0);
+
+/// pin the calling thread, ignoring harts which are offline
+fn pin_to(hart: usize) {
+    sched_setaffinity(0, &[1 << hart]);
+}
+
+/// the fast side: only compiler fences, the writer's membarrier orders us
+extern "C" fn reader(_arg: usize) -> ! {
+    pin_to(1);
+    while !STOP.load(Ordering::Relaxed) {
+        let s1 = SEQ.load(Ordering::Relaxed);
+        compiler_fence(Ordering::SeqCst);
+        let a = A.load(Ordering::Relaxed);
+        let b = B.load(Ordering::Relaxed);
+        compiler_fence(Ordering::SeqCst);
+        let s2 = SEQ.load(Ordering::Relaxed);
+        if s1 % 2 == 0 && s1 == s2 {
+            if b == !a {
+                GOOD_READS.fetch_add(1, Ordering::Relaxed);
+            } else {
+                TORN_READS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+    READER_DONE.store(true, Ordering::Release);
+    exit(0);
+}
+
+/// the slow side: a barrier on every thread of the process between the steps
+fn write(value: u64) {
+    SEQ.fetch_add(1, Ordering::Relaxed);
+    membarrier(MEMBARRIER_CMD_PRIVATE_EXPEDITED);
+    A.store(value, Ordering::Relaxed);
+    B.store(!value, Ordering::Relaxed);
+    membarrier(MEMBARRIER_CMD_PRIVATE_EXPEDITED);
+    SEQ.fetch_add(1, Ordering::Relaxed);
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    let supported = membarrier(MEMBARRIER_CMD_QUERY) as usize;
+    let wanted = MEMBARRIER_CMD_GLOBAL | MEMBARRIER_CMD_PRIVATE_EXPEDITED | MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED;
+    ok &= check(supported & wanted == wanted, "query");
+    ok &= check(membarrier(MEMBARRIER_CMD_GLOBAL) == 0, "global");
+    ok &= check(membarrier(MEMBARRIER_CMD_PRIVATE_EXPEDITED) == EPERM, "unregistered private expedited");
+    ok &= check(membarrier(MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED) == 0, "register");
+    ok &= check(membarrier(MEMBARRIER_CMD_PRIVATE_EXPEDITED) == 0, "private expedited");
+
+    pin_to(0);
+    let stack = unsafe { &mut *core::ptr::addr_of_mut!(READER_STACK) };
+    if thread_spawn(stack, reader, 0) < 0 {
+        println!("test_membarrier: thread_spawn failed");
+        return -1;
+    }
+    for value in 1..=ROUNDS {
+        write(value);
+        if value % 64 == 0 {
+            yield_();
+        }
+    }
+    STOP.store(true, Ordering::Relaxed);
+    while !READER_DONE.load(Ordering::Acquire) {
+        yield_();
+    }
+
+    println!(
+        "test_membarrier: {} consistent reads, {} torn reads",
+        GOOD_READS.load(Ordering::Relaxed), TORN_READS.load(Ordering::Relaxed)
+    );
+    ok &= check(TORN_READS.load(Ordering::Relaxed) == 0, "seqlock");
+    if ok {
+        println!("test_membarrier passed!");
+        0
+    } else {
+        -1
+    }
+}
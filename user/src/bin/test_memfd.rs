@@ -0,0 +1,127 @@
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use user_lib::{
+    check, close, exit, fcntl, fork, ftruncate, memfd_create, mmap, munmap, open, unlink, waitpid, write, yield_,
+    MmapFlags, MmapProt, OpenFlags, EPERM, F_ADD_SEALS, F_GETFD, F_GET_SEALS, F_SEAL_GROW, F_SEAL_SEAL, F_SEAL_SHRINK,
+    F_SEAL_WRITE, MFD_ALLOW_SEALING, MFD_CLOEXEC,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const PAGE: usize = 4096;
+const ROUNDS: u32 = 100;
+const SHM_PATH: &str = "/dev/shm/test_memfd\0";
+
+fn map_shared(fd: usize) -> Option<&'static AtomicU32> {
+    let addr = mmap(0, PAGE, MmapProt::PROT_READ | MmapProt::PROT_WRITE, MmapFlags::MAP_SHARED, fd, 0);
+    if addr < 0 {
+        return None;
+    }
+    Some(unsafe { &*(addr as *const AtomicU32) })
+}
+
+/// take turns incrementing the counter, the parent on even values and the child on odd ones
+fn ping_pong(counter: &AtomicU32, parity: u32) {
+    loop {
+        let value = counter.load(Ordering::Acquire);
+        if value >= 2 * ROUNDS {
+            return;
+        }
+        if value % 2 == parity {
+            counter.store(value + 1, Ordering::Release);
+        } else {
+            yield_();
+        }
+    }
+}
+
+/// producer and consumer sharing a counter through a memfd mapped in both
+fn shared_counter() -> bool {
+    let fd = memfd_create("counter\0", MFD_CLOEXEC | MFD_ALLOW_SEALING);
+    if !check(fd >= 0, "memfd_create") {
+        return false;
+    }
+    let fd = fd as usize;
+    let mut ok = check(fcntl(fd, F_GETFD, 0) == 1, "MFD_CLOEXEC");
+    ok &= check(ftruncate(fd, PAGE) == 0, "ftruncate");
+    let pid = fork();
+    if pid == 0 {
+        match map_shared(fd) {
+            Some(counter) => ping_pong(counter, 1),
+            None => exit(-1),
+        }
+        exit(0);
+    }
+    let Some(counter) = map_shared(fd) else {
+        return check(false, "mmap MAP_SHARED");
+    };
+    ping_pong(counter, 0);
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    ok &= check(status == 0 && counter.load(Ordering::Acquire) == 2 * ROUNDS, "shared counter");
+    munmap(counter as *const AtomicU32 as usize, PAGE);
+
+    // every seal is enforced once added
+    let seals = F_SEAL_GROW | F_SEAL_SHRINK | F_SEAL_WRITE;
+    ok &= check(fcntl(fd, F_ADD_SEALS, seals) == 0, "F_ADD_SEALS");
+    ok &= check(fcntl(fd, F_GET_SEALS, 0) == seals as isize, "F_GET_SEALS");
+    ok &= check(write(fd, b"x", 1) == EPERM, "F_SEAL_WRITE on write");
+    ok &= check(ftruncate(fd, 2 * PAGE) == EPERM, "F_SEAL_GROW on ftruncate");
+    ok &= check(ftruncate(fd, 0) == EPERM, "F_SEAL_SHRINK on ftruncate");
+    let addr = mmap(0, PAGE, MmapProt::PROT_READ | MmapProt::PROT_WRITE, MmapFlags::MAP_SHARED, fd, 0);
+    ok &= check(addr == EPERM, "F_SEAL_WRITE on mmap");
+    ok &= check(fcntl(fd, F_ADD_SEALS, F_SEAL_SEAL) == 0, "F_SEAL_SEAL");
+    ok &= check(fcntl(fd, F_ADD_SEALS, F_SEAL_GROW) == EPERM, "adding seals after F_SEAL_SEAL");
+    close(fd);
+
+    // without MFD_ALLOW_SEALING the file comes sealed
+    let fd = memfd_create("unsealable\0", 0);
+    ok &= check(fd >= 0 && fcntl(fd as usize, F_ADD_SEALS, F_SEAL_WRITE) == EPERM, "memfd without MFD_ALLOW_SEALING");
+    close(fd as usize);
+    ok
+}
+
+/// shm_open and shm_unlink as musl does them, on files under /dev/shm
+fn shm_open() -> bool {
+    let fd = open(SHM_PATH, OpenFlags::CREATE | OpenFlags::RDWR);
+    if !check(fd >= 0, "shm_open create") {
+        return false;
+    }
+    let mut ok = check(ftruncate(fd as usize, PAGE) == 0, "ftruncate shm");
+    close(fd as usize);
+    let pid = fork();
+    if pid == 0 {
+        let fd = open(SHM_PATH, OpenFlags::RDWR);
+        match (fd >= 0).then(|| map_shared(fd as usize)).flatten() {
+            Some(counter) => counter.store(ROUNDS, Ordering::Release),
+            None => exit(-1),
+        }
+        exit(0);
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    let fd = open(SHM_PATH, OpenFlags::RDWR);
+    match (fd >= 0).then(|| map_shared(fd as usize)).flatten() {
+        Some(counter) => ok &= check(status == 0 && counter.load(Ordering::Acquire) == ROUNDS, "shm shared page"),
+        None => ok &= check(false, "shm_open existing"),
+    }
+    close(fd as usize);
+    ok &= check(unlink(SHM_PATH) == 0, "shm_unlink");
+    ok &= check(open(SHM_PATH, OpenFlags::RDWR) < 0, "open after shm_unlink");
+    ok
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let ok = shared_counter() & shm_open();
+    if ok {
+        println!("test_memfd passed!");
+        0
+    } else {
+        -1
+    }
+}
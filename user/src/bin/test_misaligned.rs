@@ -0,0 +1,133 @@
+#![no_std]
+#![no_main]
+#![cfg_attr(not(target_arch = "riscv64"), allow(unused))]
+
+use user_lib::{
+    check, exit, fork, mmap, munmap, prctl, waitpid, MmapFlags, MmapProt, PR_GET_UNALIGN, PR_SET_UNALIGN,
+    PR_UNALIGN_NOPRINT, PR_UNALIGN_SIGBUS,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const SIGBUS: i32 = 7;
+const SIGSEGV: i32 = 11;
+const PAGE: usize = 4096;
+const VAL: u64 = 0x0123_4567_89ab_cdef;
+
+#[cfg(target_arch = "riscv64")]
+fn store_u64(addr: usize, val: u64) {
+    unsafe { core::arch::asm!("sd {0}, 0({1})", in(reg) val, in(reg) addr) };
+}
+
+#[cfg(target_arch = "riscv64")]
+fn load_u64(addr: usize) -> u64 {
+    let val: u64;
+    unsafe { core::arch::asm!("ld {0}, 0({1})", out(reg) val, in(reg) addr) };
+    val
+}
+
+#[cfg(target_arch = "riscv64")]
+fn store_u32(addr: usize, val: u32) {
+    unsafe { core::arch::asm!("sw {0}, 0({1})", in(reg) val, in(reg) addr) };
+}
+
+#[cfg(target_arch = "riscv64")]
+fn load_i32(addr: usize) -> i64 {
+    let val: i64;
+    unsafe { core::arch::asm!("lw {0}, 0({1})", out(reg) val, in(reg) addr) };
+    val
+}
+
+#[cfg(target_arch = "riscv64")]
+fn store_f64(addr: usize, val: f64) {
+    unsafe { core::arch::asm!("fsd {0}, 0({1})", in(freg) val, in(reg) addr) };
+}
+
+#[cfg(target_arch = "riscv64")]
+fn load_f64(addr: usize) -> f64 {
+    let val: f64;
+    unsafe { core::arch::asm!("fld {0}, 0({1})", out(freg) val, in(reg) addr) };
+    val
+}
+
+/// two fresh pages, neither faulted in yet
+fn map_two_pages(flags: MmapFlags) -> usize {
+    let addr = mmap(0, 2 * PAGE, MmapProt::PROT_READ | MmapProt::PROT_WRITE, flags | MmapFlags::MAP_ANONYMOUS, 0, 0);
+    if addr < 0 {
+        println!("test_misaligned: mmap failed");
+        exit(-1);
+    }
+    addr as usize
+}
+
+/// run `f` in a child and return the signal that killed it, 0 if it exited
+fn child_signal(f: fn()) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        f();
+        exit(0);
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    status & 0x7f
+}
+
+#[cfg(target_arch = "riscv64")]
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    let mut ctl = 0u32;
+    ok &= check(prctl(PR_GET_UNALIGN, &mut ctl as *mut u32 as usize, 0) == 0, "PR_GET_UNALIGN");
+    ok &= check(ctl as usize & PR_UNALIGN_SIGBUS == 0, "emulation by default");
+
+    // a u64 across a page boundary, the second page is faulted in mid emulation
+    let base = map_two_pages(MmapFlags::MAP_PRIVATE);
+    let addr = base + PAGE - 3;
+    store_u64(addr, VAL);
+    let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, 8) };
+    ok &= check(bytes == VAL.to_le_bytes(), "misaligned store across pages");
+    ok &= check(load_u64(addr) == VAL, "misaligned load across pages");
+
+    // loads sign extend, float registers are reached too
+    store_u32(base + 1, 0xffff_fff0);
+    ok &= check(load_i32(base + 1) == -16, "misaligned lw sign extends");
+    store_f64(base + PAGE - 5, 1.5);
+    ok &= check(load_f64(base + PAGE - 5) == 1.5, "misaligned fsd and fld");
+
+    // the second page is gone: SIGSEGV and the first page is left alone
+    let shared = map_two_pages(MmapFlags::MAP_SHARED);
+    munmap(shared + PAGE, PAGE);
+    static mut SHARED: usize = 0;
+    unsafe { SHARED = shared };
+    let sig = child_signal(|| store_u64(unsafe { SHARED } + PAGE - 3, VAL));
+    ok &= check(sig == SIGSEGV, "store into an unmapped second page");
+    let tail = unsafe { core::slice::from_raw_parts((shared + PAGE - 3) as *const u8, 3) };
+    ok &= check(tail == [0, 0, 0], "the first page untouched");
+
+    // strict mode
+    if prctl(PR_SET_UNALIGN, PR_UNALIGN_SIGBUS, 0) == 0 {
+        static mut BASE: usize = 0;
+        unsafe { BASE = base };
+        let sig = child_signal(|| { load_u64(unsafe { BASE } + PAGE - 3); });
+        ok &= check(sig == SIGBUS, "SIGBUS in strict mode");
+        prctl(PR_SET_UNALIGN, PR_UNALIGN_NOPRINT, 0);
+        ok &= check(load_u64(addr) == VAL, "emulation back on");
+    } else {
+        println!("test_misaligned: can not set PR_SET_UNALIGN, strict mode not tested");
+    }
+
+    if ok {
+        println!("test_misaligned passed!");
+        0
+    } else {
+        -1
+    }
+}
+
+#[cfg(not(target_arch = "riscv64"))]
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    println!("test_misaligned: misaligned accesses are only emulated on riscv64");
+    0
+}
@@ -0,0 +1,178 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, drop_caches, exit, fork, fsync, mlock, mlockall, mmap, munlock, munlockall, munmap, open, read,
+    setrlimit, setuid, unlink, vm_stats, waitpid, write, MmapFlags, MmapProt, OpenFlags, RLimit, VmEventCounts, EINVAL,
+    ENOMEM, EPERM, MCL_FUTURE, RLIMIT_MEMLOCK, VM_MAJOR_FAULT,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const PAGE_SIZE: usize = 4096;
+/// twice the page cache limit set below
+const FILE_PAGES: usize = 512;
+/// the pages locked at the start of the file
+const LOCKED_PAGES: usize = 16;
+const FILE: &str = "/test_mlock_file\0";
+const PAGE_CACHE_LIMIT: &str = "/proc/sys/vm/page-cache-limit-mb\0";
+
+static mut BUF: [u8; 4 * PAGE_SIZE] = [0; 4 * PAGE_SIZE];
+
+/// the events of `step`, `[minor, major, cow, mmap, munmap, shootdown]`,
+/// both buffers are faulted in before it starts
+fn events_of(step: impl FnOnce()) -> VmEventCounts {
+    let (mut before, mut after) = ([0; 6], [0; 6]);
+    vm_stats(&mut after);
+    vm_stats(&mut before);
+    step();
+    vm_stats(&mut after);
+    core::array::from_fn(|i| after[i] - before[i])
+}
+
+fn read_pages(base: usize, pages: usize) -> usize {
+    (0..pages).map(|i| unsafe { core::ptr::read_volatile((base + i * PAGE_SIZE) as *const u8) } as usize).sum()
+}
+
+fn write_pages(base: usize, pages: usize) {
+    for i in 0..pages {
+        unsafe { core::ptr::write_volatile((base + i * PAGE_SIZE) as *mut u8, 1) };
+    }
+}
+
+/// the limit of the page cache in MiB, as written to the sysctl
+fn set_cache_limit(value: &[u8]) -> bool {
+    let fd = open(PAGE_CACHE_LIMIT, OpenFlags::WRONLY);
+    if fd < 0 {
+        return false;
+    }
+    let ret = write(fd as usize, value, value.len());
+    close(fd as usize);
+    ret == value.len() as isize
+}
+
+/// a file of `FILE_PAGES` on the disk and in no cache
+fn make_file() -> bool {
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDWR | OpenFlags::TRUNC);
+    if fd < 0 {
+        return false;
+    }
+    let page = [7u8; PAGE_SIZE];
+    let ok = (0..FILE_PAGES).all(|_| write(fd as usize, &page, PAGE_SIZE) == PAGE_SIZE as isize);
+    let ok = ok && fsync(fd as usize) == 0;
+    close(fd as usize);
+    // the inode goes with its page cache, the pages are read from the disk again
+    drop_caches();
+    ok
+}
+
+/// read the whole file through the page cache, which gives pages back on the way
+fn read_through(fd: usize) -> bool {
+    let buf = unsafe { &mut *core::ptr::addr_of_mut!(BUF) };
+    let mut total = 0;
+    loop {
+        match read(fd, buf) {
+            n if n > 0 => total += n as usize,
+            _ => break,
+        }
+    }
+    total == FILE_PAGES * PAGE_SIZE
+}
+
+/// the locked pages of a file mapping stay in memory and in the page cache under pressure
+fn locked_file_pages(base: usize, fd: usize) -> bool {
+    let len = FILE_PAGES * PAGE_SIZE;
+    let mut ok = check(set_cache_limit(b"1"), "limit the page cache");
+    ok &= check(read_through(fd), "read the file");
+    let events = events_of(|| {
+        read_pages(base, LOCKED_PAGES);
+    });
+    println!("test_mlock: touch {} locked pages after the pressure: {:?}", LOCKED_PAGES, events);
+    ok &= check(events == [0; 6], "locked pages never fault");
+
+    // a second mapping finds the locked pages in the cache, the others were given back
+    let other = mmap(0, len, MmapProt::PROT_READ, MmapFlags::MAP_SHARED, fd, 0);
+    ok &= check(other > 0, "mmap the file again");
+    let other = other as usize;
+    let events = events_of(|| {
+        read_pages(other, LOCKED_PAGES);
+    });
+    ok &= check(events[VM_MAJOR_FAULT] == 0, "the locked pages stay in the page cache");
+    let events = events_of(|| {
+        read_pages(other + LOCKED_PAGES * PAGE_SIZE, LOCKED_PAGES);
+    });
+    ok &= check(events[VM_MAJOR_FAULT] > 0, "the unlocked pages are given back");
+    munmap(other, len);
+    ok &= check(set_cache_limit(b"0"), "lift the page cache limit");
+    ok
+}
+
+/// fork drops the locks in the child, which is held to its RLIMIT_MEMLOCK
+fn memlock_limit() -> bool {
+    let pid = fork();
+    if pid == 0 {
+        let len = LOCKED_PAGES / 2 * PAGE_SIZE;
+        let mut ok = check(setuid(1000) == 0, "setuid");
+        let limit = RLimit { rlim_cur: len, rlim_max: len };
+        ok &= check(setrlimit(RLIMIT_MEMLOCK, &limit) == 0, "setrlimit");
+        let base = mmap(0, 2 * len, MmapProt::PROT_READ | MmapProt::PROT_WRITE, MmapFlags::MAP_PRIVATE | MmapFlags::MAP_ANONYMOUS, usize::MAX, 0);
+        ok &= check(base > 0, "mmap in the child");
+        let base = base as usize;
+        ok &= check(mlock(base, len) == 0, "the locks of the parent do not count in the child");
+        ok &= check(mlock(base + len, PAGE_SIZE) == ENOMEM, "no lock beyond RLIMIT_MEMLOCK");
+        ok &= check(munlock(base, len) == 0, "munlock");
+        let limit = RLimit { rlim_cur: 0, rlim_max: len };
+        ok &= check(setrlimit(RLIMIT_MEMLOCK, &limit) == 0, "setrlimit to 0");
+        ok &= check(mlock(base, PAGE_SIZE) == EPERM, "no lock at all with RLIMIT_MEMLOCK at 0");
+        exit(if ok { 0 } else { 1 });
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    check(status == 0, "the locks of the child")
+}
+
+/// after mlockall(MCL_FUTURE) a new mapping is faulted in at once
+fn lock_future() -> bool {
+    let len = LOCKED_PAGES * PAGE_SIZE;
+    let mut ok = check(mlockall(0) == EINVAL && mlockall(8) == EINVAL, "mlockall takes known flags only");
+    ok &= check(mlockall(MCL_FUTURE) == 0, "mlockall");
+    let base = mmap(0, len, MmapProt::PROT_READ | MmapProt::PROT_WRITE, MmapFlags::MAP_PRIVATE | MmapFlags::MAP_ANONYMOUS, usize::MAX, 0);
+    ok &= check(base > 0, "mmap after mlockall");
+    let base = base as usize;
+    let events = events_of(|| write_pages(base, LOCKED_PAGES));
+    ok &= check(events == [0; 6], "a mapping after mlockall never faults");
+    ok &= check(munlockall() == 0, "munlockall");
+    munmap(base, len);
+    ok
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let len = FILE_PAGES * PAGE_SIZE;
+    let mut ok = check(make_file(), "create the file");
+    let fd = open(FILE, OpenFlags::RDONLY);
+    ok &= check(fd >= 0, "open");
+    let fd = fd as usize;
+    let base = mmap(0, len, MmapProt::PROT_READ, MmapFlags::MAP_SHARED, fd, 0);
+    ok &= check(base > 0, "mmap the file");
+    let base = base as usize;
+    ok &= check(mlock(base + 1, LOCKED_PAGES * PAGE_SIZE - 1) == 0, "mlock part of the mapping");
+    ok &= check(read_pages(base, LOCKED_PAGES) == 7 * LOCKED_PAGES, "read the locked pages");
+
+    ok &= locked_file_pages(base, fd);
+    ok &= memlock_limit();
+    ok &= check(munlock(base, LOCKED_PAGES * PAGE_SIZE) == 0, "munlock in the parent");
+    munmap(base, len);
+    close(fd);
+    unlink(FILE);
+
+    ok &= lock_future();
+
+    if ok {
+        println!("test_mlock: passed");
+        0
+    } else {
+        -1
+    }
+}
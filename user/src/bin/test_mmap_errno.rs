@@ -0,0 +1,119 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, mmap, munmap, open, pipe, socket, unlink, MmapFlags, MmapProt, OpenFlags, EACCES, EFAULT, EINVAL,
+    ENODEV, ENOMEM, EOVERFLOW,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const PAGE_SIZE: usize = 4096;
+const AF_INET: i32 = 2;
+const SOCK_DGRAM: i32 = 2;
+const FILE: &str = "/test_mmap_errno_file\0";
+
+struct Case {
+    what: &'static str,
+    addr: usize,
+    len: usize,
+    prot: MmapProt,
+    flags: MmapFlags,
+    fd: usize,
+    offset: usize,
+    errno: isize,
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    let rw = MmapProt::PROT_READ | MmapProt::PROT_WRITE;
+    let anon = MmapFlags::MAP_PRIVATE | MmapFlags::MAP_ANONYMOUS;
+    let file = open(FILE, OpenFlags::CREATE | OpenFlags::RDWR);
+    let ro = open(FILE, OpenFlags::RDONLY);
+    let mut pipe_fd = [0usize; 2];
+    let sock = socket(AF_INET, SOCK_DGRAM, 0);
+    if file < 0 || ro < 0 || pipe(&mut pipe_fd) < 0 || sock < 0 {
+        println!("test_mmap_errno: can not open the files to map");
+        return -1;
+    }
+    let (file, ro, sock) = (file as usize, ro as usize, sock as usize);
+
+    let cases = [
+        Case { what: "zero length", addr: 0, len: 0, prot: rw, flags: anon, fd: 0, offset: 0, errno: EINVAL },
+        Case { what: "unaligned offset", addr: 0, len: PAGE_SIZE, prot: rw, flags: MmapFlags::MAP_PRIVATE, fd: file, offset: 1, errno: EINVAL },
+        Case {
+            what: "offset plus length overflows", addr: 0, len: 2 * PAGE_SIZE, prot: rw, flags: MmapFlags::MAP_PRIVATE,
+            fd: file, offset: usize::MAX & !(PAGE_SIZE - 1), errno: EOVERFLOW,
+        },
+        Case {
+            what: "addr plus length overflows", addr: usize::MAX & !(PAGE_SIZE - 1), len: 2 * PAGE_SIZE, prot: rw, flags: anon,
+            fd: 0, offset: 0, errno: ENOMEM,
+        },
+        Case {
+            what: "unknown prot bits", addr: 0, len: PAGE_SIZE, prot: unsafe { MmapProt::from_bits_unchecked(0x8) }, flags: anon,
+            fd: 0, offset: 0, errno: EINVAL,
+        },
+        Case {
+            what: "unaligned fixed address", addr: 0x1000_0001, len: PAGE_SIZE, prot: rw, flags: anon | MmapFlags::MAP_FIXED,
+            fd: 0, offset: 0, errno: EINVAL,
+        },
+        Case {
+            what: "fixed kernel address", addr: 0xffff_ffc0_0000_0000, len: PAGE_SIZE, prot: rw, flags: anon | MmapFlags::MAP_FIXED,
+            fd: 0, offset: 0, errno: EFAULT,
+        },
+        Case { what: "map a pipe", addr: 0, len: PAGE_SIZE, prot: rw, flags: MmapFlags::MAP_SHARED, fd: pipe_fd[0], offset: 0, errno: ENODEV },
+        Case { what: "map a socket", addr: 0, len: PAGE_SIZE, prot: rw, flags: MmapFlags::MAP_SHARED, fd: sock, offset: 0, errno: ENODEV },
+        Case {
+            what: "writable shared map of a read only file", addr: 0, len: PAGE_SIZE, prot: rw, flags: MmapFlags::MAP_SHARED,
+            fd: ro, offset: 0, errno: EACCES,
+        },
+    ];
+    for case in cases.iter() {
+        let ret = mmap(case.addr, case.len, case.prot, case.flags, case.fd, case.offset);
+        ok &= check(ret == case.errno, case.what);
+    }
+
+    // the same read only file is fine for a private or a read only mapping
+    let ret = mmap(0, PAGE_SIZE, rw, MmapFlags::MAP_PRIVATE, ro, 0);
+    ok &= check(ret > 0, "private map of a read only file");
+    if ret > 0 {
+        munmap(ret as usize, PAGE_SIZE);
+    }
+    let ret = mmap(0, PAGE_SIZE, MmapProt::PROT_READ, MmapFlags::MAP_SHARED, ro, 0);
+    ok &= check(ret > 0, "read only shared map of a read only file");
+    if ret > 0 {
+        munmap(ret as usize, PAGE_SIZE);
+    }
+
+    // a free hint is taken as it is, an unaligned one is rounded down
+    let first = mmap(0, PAGE_SIZE, rw, anon, 0, 0);
+    ok &= check(first > 0, "anonymous map");
+    if first > 0 {
+        let hint = first as usize;
+        ok &= check(munmap(hint, 0) == EINVAL, "munmap of zero length");
+        ok &= check(munmap(hint + 1, PAGE_SIZE) == EINVAL, "munmap of an unaligned address");
+        ok &= check(munmap(hint, PAGE_SIZE) == 0, "munmap");
+        let ret = mmap(hint + 1, PAGE_SIZE, rw, anon, 0, 0);
+        ok &= check(ret == hint as isize, "map at the hint");
+        if ret > 0 {
+            munmap(ret as usize, PAGE_SIZE);
+        }
+    }
+
+    close(sock);
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+    close(ro);
+    close(file);
+    unlink(FILE);
+
+    if ok {
+        println!("test_mmap_errno: passed");
+        0
+    } else {
+        -1
+    }
+}
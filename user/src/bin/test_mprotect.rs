@@ -0,0 +1,156 @@
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use user_lib::{
+    check, exit, fork, mmap, mprotect, munmap, sigaction_flags, sleep, waitpid, MmapFlags, MmapProt, SigInfo, ENOMEM,
+    SA_SIGINFO, SEGV_ACCERR, SEGV_MAPERR, SIGSEGV,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const PAGE_SIZE: usize = 4096;
+/// the writes through the barrier, each one traps once
+const ROUNDS: usize = 100;
+
+/// the faults the handler fixed, by si_code
+static ACCERR: AtomicUsize = AtomicUsize::new(0);
+static MAPERR: AtomicUsize = AtomicUsize::new(0);
+/// the page of the last fault
+static LAST_PAGE: AtomicUsize = AtomicUsize::new(0);
+
+fn rw() -> MmapProt {
+    MmapProt::PROT_READ | MmapProt::PROT_WRITE
+}
+
+/// open the faulting page and count the fault, the access is retried on return
+extern "C" fn on_sigsegv(_signo: i32, info: *const SigInfo, _ucontext: usize) {
+    let info = unsafe { *info };
+    let page = info.addr() & !(PAGE_SIZE - 1);
+    LAST_PAGE.store(page, Ordering::Relaxed);
+    match info.code {
+        SEGV_ACCERR => {
+            mprotect(page, PAGE_SIZE, rw());
+            ACCERR.fetch_add(1, Ordering::Relaxed);
+        }
+        SEGV_MAPERR => {
+            let flags = MmapFlags::MAP_PRIVATE | MmapFlags::MAP_ANONYMOUS | MmapFlags::MAP_FIXED;
+            mmap(page, PAGE_SIZE, rw(), flags, usize::MAX, 0);
+            MAPERR.fetch_add(1, Ordering::Relaxed);
+        }
+        _ => exit(2),
+    }
+}
+
+fn map_pages(pages: usize) -> usize {
+    let flags = MmapFlags::MAP_PRIVATE | MmapFlags::MAP_ANONYMOUS;
+    let base = mmap(0, pages * PAGE_SIZE, rw(), flags, usize::MAX, 0);
+    assert!(base > 0);
+    base as usize
+}
+
+fn read(addr: usize) -> usize {
+    unsafe { core::ptr::read_volatile(addr as *const usize) }
+}
+
+fn write(addr: usize, value: usize) {
+    unsafe { core::ptr::write_volatile(addr as *mut usize, value) }
+}
+
+/// a write barrier: the page is read only again before every write, each write traps
+/// once, the handler opens the page and the write goes through on its retry
+fn write_barrier() -> bool {
+    let page = map_pages(1);
+    let before = ACCERR.load(Ordering::Relaxed);
+    let mut ok = true;
+    for i in 0..ROUNDS {
+        ok &= mprotect(page, PAGE_SIZE, MmapProt::PROT_READ) == 0;
+        write(page + 8, i);
+    }
+    ok = check(ok, "mprotect read only");
+    ok &= check(ACCERR.load(Ordering::Relaxed) - before == ROUNDS, "every write traps exactly once");
+    ok &= check(LAST_PAGE.load(Ordering::Relaxed) == page, "si_addr is in the page");
+    ok &= check(read(page + 8) == ROUNDS - 1, "the retried writes went through");
+    munmap(page, PAGE_SIZE);
+    ok
+}
+
+/// PROT_NONE on the middle page of an area leaves its neighbours alone, the page
+/// keeps its content behind the barrier
+fn guard_page() -> bool {
+    let base = map_pages(3);
+    for i in 0..3 {
+        write(base + i * PAGE_SIZE, i + 1);
+    }
+    let guard = base + PAGE_SIZE;
+    let mut ok = check(mprotect(guard, PAGE_SIZE, MmapProt::empty()) == 0, "mprotect PROT_NONE");
+    let before = ACCERR.load(Ordering::Relaxed);
+    ok &= check(read(base) == 1 && read(base + 2 * PAGE_SIZE) == 3, "the neighbours stay readable");
+    write(base + 2 * PAGE_SIZE + 8, 7);
+    ok &= check(ACCERR.load(Ordering::Relaxed) == before, "the neighbours never trap");
+    ok &= check(read(guard) == 2, "the guard page keeps its content");
+    ok &= check(ACCERR.load(Ordering::Relaxed) == before + 1, "a read of the guard page traps");
+    ok &= check(LAST_PAGE.load(Ordering::Relaxed) == guard, "si_addr of the guard page");
+    munmap(base + 2 * PAGE_SIZE, PAGE_SIZE);
+    ok &= check(mprotect(base, 3 * PAGE_SIZE, MmapProt::PROT_READ) == ENOMEM, "mprotect over a hole is ENOMEM");
+    ok &= check(read(base) == 1 && ACCERR.load(Ordering::Relaxed) == before + 1, "a failed mprotect changes nothing");
+    munmap(base, 2 * PAGE_SIZE);
+    ok
+}
+
+/// an access to an unmapped page is SEGV_MAPERR, the handler maps it
+fn unmapped_page() -> bool {
+    let page = map_pages(1);
+    munmap(page, PAGE_SIZE);
+    let before = MAPERR.load(Ordering::Relaxed);
+    let ok = check(read(page) == 0, "a fresh page behind the fault");
+    let ok = ok && check(MAPERR.load(Ordering::Relaxed) == before + 1, "the unmapped page traps with SEGV_MAPERR");
+    munmap(page, PAGE_SIZE);
+    ok
+}
+
+/// a page shared with a child by fork and flipped through PROT_NONE back to read write
+/// is still copied on the first write, on both sides
+fn cow_after_flip() -> bool {
+    let page = map_pages(1);
+    write(page, 0xa);
+    let pid = fork();
+    if pid == 0 {
+        sleep(100);
+        let mut ok = read(page) == 0xa;
+        ok &= mprotect(page, PAGE_SIZE, MmapProt::empty()) == 0 && mprotect(page, PAGE_SIZE, rw()) == 0;
+        write(page, 0xc);
+        ok &= read(page) == 0xc;
+        exit(if ok { 0 } else { 1 });
+    }
+    let mut ok = check(mprotect(page, PAGE_SIZE, MmapProt::empty()) == 0, "PROT_NONE on a shared page");
+    ok &= check(mprotect(page, PAGE_SIZE, rw()) == 0, "read write again");
+    write(page, 0xb);
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    ok &= check(status == 0, "the child sees its own copy");
+    ok &= check(read(page) == 0xb, "the write of the child stays in the child");
+    munmap(page, PAGE_SIZE);
+    ok
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    if sigaction_flags(SIGSEGV, on_sigsegv as usize, SA_SIGINFO) < 0 {
+        println!("test_mprotect: can not install the handler");
+        return -1;
+    }
+    let mut ok = write_barrier();
+    ok &= guard_page();
+    ok &= unmapped_page();
+    ok &= cow_after_flip();
+
+    if ok {
+        println!("test_mprotect: passed");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,109 @@
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use user_lib::{
+    check, clock_gettime, clock_nanosleep, exit, fork, getpid, kill, nanosleep, sigaction_flags, sys_nanosleep, waitpid,
+    TimeSpec, CLOCK_MONOTONIC, EINTR, SA_RESTART, SIGUSR1, TIMER_ABSTIME,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const SLEEP_MS: usize = 5000;
+/// when the child sends the signal
+const SIGNAL_MS: usize = 1000;
+/// how far a measured time may be off
+const SLACK_MS: usize = 400;
+
+static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn handler(_signo: i32) {
+    HANDLED.fetch_add(1, Ordering::SeqCst);
+}
+
+fn now_ms() -> usize {
+    let mut ts = TimeSpec::default();
+    clock_gettime(CLOCK_MONOTONIC, &mut ts);
+    ts.sec * 1000 + ts.nsec / 1_000_000
+}
+
+fn ms(ts: &TimeSpec) -> usize {
+    ts.sec * 1000 + ts.nsec / 1_000_000
+}
+
+fn near(ms: usize, expect: usize) -> bool {
+    ms + SLACK_MS >= expect && ms <= expect + SLACK_MS
+}
+
+/// run `sleep` while a child signals this process after SIGNAL_MS,
+/// return its result and the milliseconds it took
+fn interrupted(sleep: impl FnOnce() -> isize) -> (isize, usize) {
+    let parent = getpid();
+    let pid = fork();
+    if pid == 0 {
+        nanosleep(SIGNAL_MS);
+        kill(parent, SIGUSR1);
+        exit(0);
+    }
+    let start = now_ms();
+    let ret = sleep();
+    let elapsed = now_ms() - start;
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    (ret, elapsed)
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    let req = TimeSpec { sec: SLEEP_MS / 1000, nsec: 0 };
+
+    // without SA_RESTART the sleep ends at the signal with the time left
+    sigaction_flags(SIGUSR1, handler as usize, 0);
+    let mut rem = TimeSpec::default();
+    let (ret, elapsed) = interrupted(|| sys_nanosleep(&req, &mut rem));
+    ok &= check(ret == EINTR, "nanosleep cut short is EINTR");
+    ok &= check(near(elapsed, SIGNAL_MS), "nanosleep ends at the signal");
+    ok &= check(near(ms(&rem), SLEEP_MS - SIGNAL_MS), "the time left");
+    ok &= check(HANDLED.load(Ordering::SeqCst) == 1, "the handler ran");
+
+    // with SA_RESTART it resumes and sleeps only what was left
+    sigaction_flags(SIGUSR1, handler as usize, SA_RESTART);
+    let (ret, elapsed) = interrupted(|| sys_nanosleep(&req, &mut rem));
+    ok &= check(ret == 0, "restarted nanosleep");
+    ok &= check(near(elapsed, SLEEP_MS), "restarted nanosleep sleeps the request in all");
+    ok &= check(HANDLED.load(Ordering::SeqCst) == 2, "the handler ran before the restart");
+
+    // a relative clock_nanosleep may pass no pointer for the time left
+    sigaction_flags(SIGUSR1, handler as usize, 0);
+    let (ret, elapsed) = interrupted(|| clock_nanosleep(CLOCK_MONOTONIC, 0, &req, None));
+    ok &= check(ret == EINTR && near(elapsed, SIGNAL_MS), "clock_nanosleep with no remaining pointer");
+
+    // an absolute one never writes it
+    let mut deadline = TimeSpec::default();
+    clock_gettime(CLOCK_MONOTONIC, &mut deadline);
+    deadline.sec += SLEEP_MS / 1000;
+    let mut rem = TimeSpec { sec: 12345, nsec: 0 };
+    let (ret, elapsed) = interrupted(|| clock_nanosleep(CLOCK_MONOTONIC, TIMER_ABSTIME, &deadline, Some(&mut rem)));
+    ok &= check(ret == EINTR && near(elapsed, SIGNAL_MS), "absolute clock_nanosleep cut short");
+    ok &= check(rem.sec == 12345 && rem.nsec == 0, "absolute clock_nanosleep leaves the time left alone");
+
+    // spurious wake ups, a SIGCHLD ignored by default, do not end a sleep
+    let pid = fork();
+    if pid == 0 {
+        exit(0);
+    }
+    let start = now_ms();
+    ok &= check(nanosleep(SIGNAL_MS) == 0 && near(now_ms() - start, SIGNAL_MS), "a sleep through an ignored signal");
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+
+    if ok {
+        println!("test_nanosleep_intr: passed");
+        0
+    } else {
+        -1
+    }
+}
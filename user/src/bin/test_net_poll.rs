@@ -0,0 +1,183 @@
+#![no_std]
+#![no_main]
+
+use alloc::vec::Vec;
+use user_lib::{
+    accept, bind, check, close, connect, exit, fork, get_time_ms, ioctl, listen, recvfrom, sendto, setsockopt, socket,
+    waitpid, SockaddrIn, ENODEV,
+};
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+const AF_INET: i32 = 2;
+const SOCK_STREAM: i32 = 1;
+const SOCK_DGRAM: i32 = 2;
+const IPPROTO_TCP: i32 = 6;
+const SOL_SOCKET: i32 = 1;
+const SO_SNDBUF: i32 = 7;
+const SO_RCVBUF: i32 = 8;
+/// the packet counters of an interface, into the buffer ifr_data points to
+const SIOCGIFSTATS: usize = 0x89F0;
+
+const TEST_ADDR: u32 = 0x7f000001; // 127.0.0.1
+const PORT: u16 = 4480;
+/// the idle sockets bind from here on
+const IDLE_PORT: u16 = 20000;
+const FEW_IDLE: usize = 10;
+const MANY_IDLE: usize = 1000;
+/// the smallest buffers, so the idle sockets take little of the kernel heap
+const IDLE_BUF: i32 = 2048;
+const PING_ROUNDS: usize = 500;
+
+/// struct ifreq with ifr_data, the pointer member of its union
+#[repr(C)]
+struct IfReq {
+    ifr_name: [u8; 16],
+    ifr_data: usize,
+    pad: [u8; 16],
+}
+
+/// rx_packets, tx_packets, rx_bytes, tx_bytes, rx_errors, tx_errors, rx_dropped, tx_dropped
+type Stats = [u64; 8];
+
+fn addr(port: u16) -> SockaddrIn {
+    SockaddrIn::new(TEST_ADDR.to_be(), port.to_be())
+}
+
+/// the counters of the interface `name` asked through the socket `fd`
+fn stats_of(fd: usize, name: &[u8]) -> Result<Stats, isize> {
+    let mut stats = [0u64; 8];
+    let mut req = IfReq { ifr_name: [0; 16], ifr_data: &mut stats as *mut Stats as usize, pad: [0; 16] };
+    req.ifr_name[..name.len()].copy_from_slice(name);
+    match ioctl(fd, SIOCGIFSTATS, &mut req as *mut IfReq as usize) {
+        0 => Ok(stats),
+        err => Err(err),
+    }
+}
+
+fn send(fd: usize, data: &[u8]) -> isize {
+    sendto(fd, data, data.len(), 0, core::ptr::null(), 0)
+}
+
+fn recv(fd: usize, buf: &mut [u8]) -> isize {
+    recvfrom(fd, buf, buf.len(), 0, core::ptr::null_mut(), core::ptr::null_mut())
+}
+
+/// `count` udp sockets bound and never used, from `IDLE_PORT + first` on
+fn idle_sockets(first: usize, count: usize) -> Option<Vec<usize>> {
+    let mut fds = Vec::with_capacity(count);
+    for i in first..first + count {
+        let fd = socket(AF_INET, SOCK_DGRAM, 0);
+        if fd < 0 {
+            println!("test_net_poll: idle socket {} failed: {}", i, fd);
+            return None;
+        }
+        let fd = fd as usize;
+        setsockopt(fd, SOL_SOCKET, SO_SNDBUF, &IDLE_BUF);
+        setsockopt(fd, SOL_SOCKET, SO_RCVBUF, &IDLE_BUF);
+        let sa = addr(IDLE_PORT + i as u16);
+        if bind(fd, &sa, size_of::<SockaddrIn>() as u32) < 0 {
+            return None;
+        }
+        fds.push(fd);
+    }
+    Some(fds)
+}
+
+/// a connected pair on `port`
+fn tcp_pair(port: u16) -> Option<(usize, usize)> {
+    let listener = socket(AF_INET, SOCK_STREAM, IPPROTO_TCP);
+    let client = socket(AF_INET, SOCK_STREAM, IPPROTO_TCP);
+    if listener < 0 || client < 0 {
+        return None;
+    }
+    let (listener, client) = (listener as usize, client as usize);
+    let sa = addr(port);
+    let len = size_of::<SockaddrIn>() as u32;
+    if bind(listener, &sa, len) < 0 || listen(listener, 4) < 0 || connect(client, &sa, len) < 0 {
+        return None;
+    }
+    let server = accept(listener, core::ptr::null_mut(), core::ptr::null_mut());
+    close(listener);
+    (server >= 0).then_some((server as usize, client))
+}
+
+/// the mean round trip in microseconds of one byte through a fresh pair on `port`
+fn ping_pong(port: u16) -> Option<usize> {
+    let (server, client) = tcp_pair(port)?;
+    let pid = fork();
+    if pid == 0 {
+        close(client);
+        let mut byte = [0u8; 1];
+        for _ in 0..PING_ROUNDS {
+            if recv(server, &mut byte) != 1 || send(server, &byte) != 1 {
+                exit(1);
+            }
+        }
+        exit(0);
+    }
+    close(server);
+    let start = get_time_ms();
+    let mut ok = true;
+    let mut byte = [7u8; 1];
+    for _ in 0..PING_ROUNDS {
+        ok &= send(client, &byte) == 1 && recv(client, &mut byte) == 1;
+    }
+    let elapsed = (get_time_ms() - start) as usize;
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    close(client);
+    (ok && status == 0).then_some(elapsed * 1000 / PING_ROUNDS)
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let fd = socket(AF_INET, SOCK_DGRAM, 0);
+    if fd < 0 {
+        println!("test_net_poll: no socket, the network is down");
+        return -1;
+    }
+    let fd = fd as usize;
+    let mut ok = check(stats_of(fd, b"eth1") == Err(ENODEV), "the counters of an unknown interface are ENODEV");
+    let before = stats_of(fd, b"eth0").unwrap_or_default();
+
+    // the latency with a few idle sockets, then with many
+    let few = idle_sockets(0, FEW_IDLE);
+    ok &= check(few.is_some(), "the few idle sockets");
+    let rtt_few = ping_pong(PORT);
+    ok &= check(rtt_few.is_some(), "the ping pong beside a few idle sockets");
+    let many = idle_sockets(FEW_IDLE, MANY_IDLE - FEW_IDLE);
+    ok &= check(many.is_some(), "the many idle sockets");
+    let rtt_many = ping_pong(PORT + 1);
+    ok &= check(rtt_many.is_some(), "the ping pong beside many idle sockets");
+    if let (Some(few), Some(many)) = (rtt_few, rtt_many) {
+        println!("test_net_poll: round trip beside {} idle sockets {} us, beside {} {} us", FEW_IDLE, few, MANY_IDLE, many);
+        // the idle sockets are not polled on the path of the busy pair
+        ok &= check(many <= 2 * few + 100, "the round trip does not grow with the idle sockets");
+    }
+
+    // every segment of the pings went through the device and back
+    let after = stats_of(fd, b"eth0");
+    ok &= check(after.is_ok(), "SIOCGIFSTATS");
+    let after = after.unwrap_or_default();
+    let delta: Stats = core::array::from_fn(|i| after[i] - before[i]);
+    println!("test_net_poll: eth0 counters during the test {:?}", delta);
+    let segments = 2 * PING_ROUNDS as u64;
+    ok &= check(delta[0] >= segments && delta[1] >= segments, "the packets are counted");
+    ok &= check(delta[2] >= delta[0] * 40 && delta[3] >= delta[1] * 40, "the bytes are counted with the headers");
+    ok &= check(delta[4] == 0 && delta[5] == 0, "no errors on the loopback device");
+
+    for fd in few.into_iter().chain(many).flatten() {
+        close(fd);
+    }
+    close(fd);
+
+    if ok {
+        println!("test_net_poll: passed");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,96 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, exit, fork, get_time_ms, getpriority, getrusage, pipe, read, sched_setaffinity, setpriority, setuid,
+    waitpid, write, Rusage, EACCES, EINVAL, PRIO_PROCESS, RUSAGE_SELF,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const SPIN_MS: isize = 1000;
+
+/// spin on hart 0 at `nice` for SPIN_MS, then send the user time in ms through `fd`
+fn spinner(nice: i32, fd: usize) -> ! {
+    sched_setaffinity(0, &[1]);
+    setpriority(PRIO_PROCESS, 0, nice);
+    let start = get_time_ms();
+    while get_time_ms() < start + SPIN_MS {}
+    let mut usage = Rusage::default();
+    getrusage(RUSAGE_SELF, &mut usage);
+    let ms = usage.ru_utime.sec * 1000 + usage.ru_utime.usec / 1000;
+    write(fd, &ms.to_le_bytes(), 8);
+    exit(0);
+}
+
+fn read_ms(fd: usize) -> usize {
+    let mut buf = [0u8; 8];
+    read(fd, &mut buf);
+    usize::from_le_bytes(buf)
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    ok &= check(getpriority(PRIO_PROCESS, 0) == 20, "nice 0 by default");
+    ok &= check(getpriority(3, 0) == EINVAL, "bad which");
+
+    // both spinners share hart 0, so they compete for the same run queue
+    let mut low = [0usize; 2];
+    let mut normal = [0usize; 2];
+    pipe(&mut low);
+    pipe(&mut normal);
+    let low_pid = fork();
+    if low_pid == 0 {
+        spinner(19, low[1]);
+    }
+    let normal_pid = fork();
+    if normal_pid == 0 {
+        spinner(0, normal[1]);
+    }
+    let low_ms = read_ms(low[0]);
+    let normal_ms = read_ms(normal[0]);
+    let mut status = 0;
+    waitpid(low_pid as usize, &mut status);
+    waitpid(normal_pid as usize, &mut status);
+    for fd in [low[0], low[1], normal[0], normal[1]] {
+        close(fd);
+    }
+    println!("test_nice: nice 19 ran {} ms, nice 0 ran {} ms", low_ms, normal_ms);
+    ok &= check(normal_ms > low_ms * 2, "nice 0 gets most of the cpu");
+
+    // the nice value is inherited, and only root may lower it
+    let pid = fork();
+    if pid == 0 {
+        setpriority(PRIO_PROCESS, 0, 5);
+        let child = fork();
+        if child == 0 {
+            let mut failed = 0;
+            if !check(getpriority(PRIO_PROCESS, 0) == 15, "inherited nice") {
+                failed += 1;
+            }
+            setuid(1000);
+            if !check(setpriority(PRIO_PROCESS, 0, 10) == 0, "raise nice") {
+                failed += 1;
+            }
+            if !check(setpriority(PRIO_PROCESS, 0, 0) == EACCES, "lower nice without privilege") {
+                failed += 1;
+            }
+            exit(failed);
+        }
+        let mut status = 0;
+        waitpid(child as usize, &mut status);
+        exit((status >> 8) & 0xff);
+    }
+    waitpid(pid as usize, &mut status);
+    ok &= check(status == 0, "nice inheritance and permission");
+
+    if ok {
+        println!("test_nice passed!");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,104 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    chdir, check, close, dup, fchdir, fchmod, fcntl, fstat, fstatat, ftruncate, getcwd, mkdir, mmap, open, openat, read,
+    readlinkat, rmdir, symlink, unlink, write, MmapFlags, MmapProt, OpenFlags, Stat, AT_EMPTY_PATH, EBADF, ELOOP,
+    ENOTDIR,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const DIR: &str = "/test_o_path_dir\0";
+const FILE: &str = "/test_o_path_dir/file\0";
+const LINK: &str = "/test_o_path_link\0";
+const CONTENT: &[u8] = b"opened through an O_PATH dirfd";
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+const F_GETFL: usize = 3;
+const O_PATH: isize = 0o10000000;
+
+fn file_type(stat: &Stat) -> u32 {
+    stat.st_mode & S_IFMT
+}
+
+/// everything which touches the contents fails with EBADF
+fn forbidden(fd: usize) -> bool {
+    let mut ok = true;
+    let mut buf = [0u8; 8];
+    ok &= check(read(fd, &mut buf) == EBADF, "read");
+    ok &= check(write(fd, b"x", 1) == EBADF, "write");
+    ok &= check(ftruncate(fd, 0) == EBADF, "ftruncate");
+    ok &= check(fchmod(fd, 0o600) == EBADF, "fchmod");
+    let addr = mmap(0, 4096, MmapProt::PROT_READ, MmapFlags::MAP_PRIVATE, fd, 0);
+    ok &= check(addr == EBADF, "mmap");
+    ok
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    mkdir(DIR);
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDWR | OpenFlags::TRUNC);
+    ok &= check(fd >= 0 && write(fd as usize, CONTENT, CONTENT.len()) == CONTENT.len() as isize, "create the file");
+    close(fd as usize);
+    ok &= check(symlink("/test_o_path_dir/file\0", LINK) == 0, "symlink");
+
+    // a directory: stat it, anchor a walk on it, change to it
+    let dirfd = open(DIR, OpenFlags::PATH | OpenFlags::DIRECTORY);
+    ok &= check(dirfd >= 0, "open a directory with O_PATH");
+    let mut stat = Stat::default();
+    ok &= check(fstat(dirfd as usize, &mut stat) == 0 && file_type(&stat) == S_IFDIR, "fstat");
+    ok &= check(fstatat(dirfd, "\0", &mut stat, AT_EMPTY_PATH) == 0 && file_type(&stat) == S_IFDIR, "fstatat AT_EMPTY_PATH");
+    ok &= check(fcntl(dirfd as usize, F_GETFL, 0) & O_PATH != 0, "F_GETFL reports O_PATH");
+    let fd = openat(dirfd, "file\0", OpenFlags::RDONLY);
+    ok &= check(fd >= 0, "openat with an O_PATH dirfd");
+    let mut buf = [0u8; 64];
+    ok &= check(read(fd as usize, &mut buf) == CONTENT.len() as isize, "read the file opened through it");
+    close(fd as usize);
+    ok &= check(fchdir(dirfd as usize) == 0, "fchdir");
+    let mut cwd = [0u8; 64];
+    ok &= check(getcwd(&mut cwd) > 0 && cwd.starts_with(b"/test_o_path_dir"), "the cwd after fchdir");
+    chdir("/\0");
+    ok &= check(forbidden(dirfd as usize), "the forbidden operations on a directory");
+
+    // a regular file: the access mode is ignored, nothing reads or writes through it
+    let fd = open(FILE, OpenFlags::PATH | OpenFlags::RDWR);
+    ok &= check(fd >= 0, "open a file with O_PATH");
+    ok &= check(fstat(fd as usize, &mut stat) == 0 && file_type(&stat) == S_IFREG, "fstat a file");
+    ok &= check(stat.st_size == CONTENT.len() as i64, "the size of the file");
+    ok &= check(forbidden(fd as usize), "the forbidden operations on a file");
+    ok &= check(openat(fd, "x\0", OpenFlags::RDONLY) == ENOTDIR, "a file is no dirfd");
+    // a duplicate is an O_PATH fd as well
+    let copy = dup(fd as usize);
+    ok &= check(copy >= 0 && read(copy as usize, &mut buf) == EBADF, "read a duplicate");
+    ok &= check(close(copy as usize) == 0 && close(fd as usize) == 0, "close");
+    ok &= check(fstat(fd as usize, &mut stat) == EBADF, "fstat after close");
+
+    // a symlink itself with O_NOFOLLOW
+    let linkfd = open(LINK, OpenFlags::PATH | OpenFlags::NOFOLLOW);
+    ok &= check(linkfd >= 0, "open a symlink with O_PATH | O_NOFOLLOW");
+    ok &= check(fstatat(linkfd, "\0", &mut stat, AT_EMPTY_PATH) == 0 && file_type(&stat) == S_IFLNK, "fstatat the symlink");
+    let len = readlinkat(linkfd, "\0", &mut buf);
+    ok &= check(len > 0 && &buf[..len as usize] == b"/test_o_path_dir/file", "readlinkat the symlink");
+    close(linkfd as usize);
+    ok &= check(open(LINK, OpenFlags::RDONLY | OpenFlags::NOFOLLOW) == ELOOP, "O_NOFOLLOW without O_PATH");
+    let fd = open(LINK, OpenFlags::PATH);
+    ok &= check(fd >= 0 && fstat(fd as usize, &mut stat) == 0 && file_type(&stat) == S_IFREG, "O_PATH follows the symlink");
+    close(fd as usize);
+
+    close(dirfd as usize);
+    unlink(LINK);
+    unlink(FILE);
+    rmdir(DIR);
+
+    if ok {
+        println!("test_o_path: passed");
+        0
+    } else {
+        -1
+    }
+}
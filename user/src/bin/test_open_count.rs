@@ -0,0 +1,145 @@
+#![no_std]
+#![no_main]
+
+use alloc::{format, string::String};
+use user_lib::{
+    check, close, dup, exit, fork, fstat, fstatat, fsync, mkdir, mmap, mount, munmap, open, pread, read, rmdir, umount2,
+    unlink, waitpid, write, MmapFlags, MmapProt, OpenFlags, Stat, AT_FDCWD, EBUSY, ENOENT,
+};
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+const PAGE_SIZE: usize = 4096;
+/// on ext4, unlinked while open
+const FILE: &str = "/test_open_count_file\0";
+const OPEN_INODES: &str = "/proc/sys/fs/open-inodes\0";
+/// a tmpfs is mounted on it
+const MNT: &str = "/tmp/test_open_count_mnt\0";
+const MNT_FILE: &str = "/tmp/test_open_count_mnt/file\0";
+
+static DATA: [u8; 3 * PAGE_SIZE] = [0x3c; 3 * PAGE_SIZE];
+
+/// the debug dump of the open files of every mount
+fn open_inodes() -> String {
+    let fd = open(OPEN_INODES, OpenFlags::RDONLY);
+    if fd < 0 {
+        return String::new();
+    }
+    let mut buf = [0u8; 4096];
+    let len = read(fd as usize, &mut buf).max(0) as usize;
+    close(fd as usize);
+    String::from(core::str::from_utf8(&buf[..len]).unwrap_or(""))
+}
+
+/// the open files of the inode `ino` of the mount at `mount` in the dump, 0 if it is not listed
+fn opens_of(mount: &str, ino: u64) -> usize {
+    let dump = open_inodes();
+    let mut current = "";
+    for line in dump.lines() {
+        let Some(entry) = line.strip_prefix('\t') else {
+            current = line.split_whitespace().next().unwrap_or("");
+            continue;
+        };
+        let mut fields = entry.split_whitespace().map(|f| f.parse::<u64>().unwrap_or(0));
+        if current == mount && fields.next() == Some(ino) {
+            return fields.next().unwrap_or(0) as usize;
+        }
+    }
+    0
+}
+
+/// the open files of the whole mount at `mount` in the dump
+fn total_of(mount: &str) -> usize {
+    open_inodes()
+        .lines()
+        .filter(|line| !line.starts_with('\t'))
+        .find_map(|line| {
+            let mut fields = line.split_whitespace();
+            (fields.next() == Some(mount)).then(|| fields.nth(1)?.parse().ok()).flatten()
+        })
+        .unwrap_or(0)
+}
+
+/// whether `fd` reads back `DATA` from the start
+fn reads_data(fd: usize) -> bool {
+    let mut buf = [0u8; 3 * PAGE_SIZE];
+    pread(fd, &mut buf, 0) == DATA.len() as isize && buf == DATA
+}
+
+/// the file stays readable through every open of it after its last name is gone,
+/// and is removed with the last close
+fn unlink_while_open() -> bool {
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDWR | OpenFlags::TRUNC);
+    let mut ok = check(fd >= 0, "create the file");
+    let fd = fd as usize;
+    ok &= check(write(fd, &DATA, DATA.len()) == DATA.len() as isize && fsync(fd) == 0, "write the file");
+    let mut stat = Stat::default();
+    ok &= check(fstat(fd, &mut stat) == 0, "fstat");
+    let ino = stat.st_ino;
+    ok &= check(opens_of("/", ino) == 1, "the dump shows the open");
+    let dup_fd = dup(fd);
+    ok &= check(dup_fd >= 0 && opens_of("/", ino) == 1, "dup shares the open");
+    let dup_fd = dup_fd as usize;
+
+    ok &= check(unlink(FILE) == 0, "unlink");
+    ok &= check(open(FILE, OpenFlags::RDONLY) == ENOENT, "the name is gone");
+    ok &= check(reads_data(fd), "read after the unlink");
+    close(fd);
+    ok &= check(reads_data(dup_fd), "read through the dup after the close");
+
+    // the child shares the open and its exit leaves the one of the parent
+    let pid = fork();
+    if pid == 0 {
+        exit(if reads_data(dup_fd) { 0 } else { 1 });
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    ok &= check(status == 0, "read in the child");
+    ok &= check(opens_of("/", ino) == 1, "fork shares the open");
+
+    // a mapping keeps the file open past the last close
+    let base = mmap(0, DATA.len(), MmapProt::PROT_READ, MmapFlags::MAP_SHARED, dup_fd, 0);
+    ok &= check(base > 0, "mmap");
+    close(dup_fd);
+    ok &= check(opens_of("/", ino) == 1, "the mapping holds the open");
+    let mapped = unsafe { core::slice::from_raw_parts(base as *const u8, DATA.len()) };
+    ok &= check(mapped == DATA, "read through the mapping");
+    munmap(base as usize, DATA.len());
+
+    ok &= check(opens_of("/", ino) == 0, "the last close leaves the dump");
+    let orphan = format!("/.orphan-{}\0", ino);
+    ok &= check(fstatat(AT_FDCWD, &orphan, &mut stat, 0) == ENOENT, "the file is removed with the last close");
+    ok
+}
+
+/// umount is EBUSY while a file of the mount is open and succeeds after its close
+fn umount_busy() -> bool {
+    let mut ok = check(mkdir(MNT) == 0, "mkdir the mount point");
+    ok &= check(mount("none\0", MNT, "tmpfs\0", 0, 0) == 0, "mount a tmpfs");
+    let fd = open(MNT_FILE, OpenFlags::CREATE | OpenFlags::RDWR);
+    ok &= check(fd >= 0, "create a file in the tmpfs");
+    ok &= check(total_of("/tmp/test_open_count_mnt") == 1, "the dump shows the open of the tmpfs");
+    ok &= check(umount2(MNT, 0) == EBUSY, "umount with an open file is EBUSY");
+    close(fd as usize);
+    ok &= check(total_of("/tmp/test_open_count_mnt") == 0, "the close leaves the dump");
+    ok &= check(umount2(MNT, 0) == 0, "umount after the close");
+    let mut stat = Stat::default();
+    ok &= check(fstatat(AT_FDCWD, MNT_FILE, &mut stat, 0) == ENOENT, "the files of the tmpfs are gone");
+    ok &= check(rmdir(MNT) == 0, "the covered directory is back");
+    ok
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = unlink_while_open();
+    ok &= umount_busy();
+
+    if ok {
+        println!("test_open_count: passed");
+        0
+    } else {
+        -1
+    }
+}
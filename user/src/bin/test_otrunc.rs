@@ -0,0 +1,69 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, ftruncate, mmap, open, pread, unlink, write, MmapFlags, MmapProt, OpenFlags, EINVAL, EISDIR,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const FILE: &str = "/test_otrunc_file\0";
+const PAGE: usize = 4096;
+const LEN: usize = 2 * PAGE;
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDWR);
+    if fd < 0 {
+        println!("test_otrunc: can not create {}", FILE);
+        return -1;
+    }
+    let fd = fd as usize;
+    let data = [0xaau8; LEN];
+    ok &= check(write(fd, &data, LEN) == LEN as isize, "write 8 KiB");
+
+    // fault both pages in before the truncation
+    let addr = mmap(0, LEN, MmapProt::PROT_READ, MmapFlags::MAP_SHARED, fd, 0);
+    if addr < 0 {
+        println!("test_otrunc: mmap failed");
+        return -1;
+    }
+    let mapped = unsafe { core::slice::from_raw_parts(addr as *const u8, LEN) };
+    ok &= check(mapped.iter().all(|&b| b == 0xaa), "mapped data before truncation");
+
+    // O_CREAT on an existing file keeps it
+    let again = open(FILE, OpenFlags::CREATE | OpenFlags::WRONLY);
+    ok &= check(again >= 0, "reopen with O_CREAT");
+    close(again as usize);
+    let mut buf = [0u8; PAGE];
+    ok &= check(pread(fd, &mut buf, PAGE) == PAGE as isize, "O_CREAT keeps the contents");
+
+    // like linux, O_TRUNC truncates even with O_RDONLY
+    let trunc = open(FILE, OpenFlags::TRUNC | OpenFlags::RDONLY);
+    ok &= check(trunc >= 0, "open with O_TRUNC");
+    ok &= check(pread(fd, &mut buf, PAGE) == 0, "read past the truncated end");
+    ok &= check(pread(fd, &mut buf, 0) == 0, "read the truncated file");
+    ok &= check(mapped.iter().all(|&b| b == 0), "mapped pages read as zero");
+
+    // ftruncate needs a writable fd and a length that is not negative
+    ok &= check(ftruncate(trunc as usize, PAGE) == EINVAL, "ftruncate on a read only fd");
+    ok &= check(ftruncate(fd, usize::MAX) == EINVAL, "ftruncate to a negative length");
+    ok &= check(ftruncate(fd, PAGE) == 0, "ftruncate to extend");
+    ok &= check(pread(fd, &mut buf, 0) == PAGE as isize && buf.iter().all(|&b| b == 0), "the extension reads as zero");
+    close(trunc as usize);
+
+    ok &= check(open("/\0", OpenFlags::TRUNC | OpenFlags::RDONLY) == EISDIR, "O_TRUNC on a directory");
+
+    close(fd);
+    unlink(FILE);
+
+    if ok {
+        println!("test_otrunc passed!");
+        0
+    } else {
+        -1
+    }
+}
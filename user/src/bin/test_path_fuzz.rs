@@ -0,0 +1,204 @@
+#![no_std]
+#![no_main]
+
+use alloc::string::String;
+use user_lib::{
+    close, mkdir, mkdirat, open, openat, rmdir, unlink, unlinkat, OpenFlags, AT_REMOVEDIR, EEXIST, EINVAL, EISDIR,
+    ENAMETOOLONG, ENOENT, ENOTDIR, ENOTEMPTY,
+};
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+const ROOT: &str = "/test_path_fuzz\0";
+const FILE: &str = "/test_path_fuzz/file\0";
+const DIR: &str = "/test_path_fuzz/dir\0";
+const NAME_MAX: usize = 255;
+const PATH_MAX: usize = 4096;
+const ROUNDS: usize = 300;
+
+fn check(ret: isize, expected: isize, path: &str, what: &str) -> bool {
+    let ok = if expected == 0 { ret >= 0 } else { ret == expected };
+    if !ok {
+        println!("test_path_fuzz: {} {:?} returned {}, expected {}", what, path, ret, expected);
+    }
+    ok
+}
+
+/// open, close and return the open result
+fn probe(dirfd: isize, path: &str, flags: OpenFlags) -> isize {
+    let fd = openat(dirfd, path, flags);
+    if fd >= 0 {
+        close(fd as usize);
+    }
+    fd
+}
+
+/// where a path walked from the test directory has got
+#[derive(Clone, Copy, PartialEq)]
+enum Node {
+    Root,
+    Dir,
+    File,
+    Missing,
+}
+
+/// a small linear congruential generator, the fuzz must be reproducible
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self, bound: usize) -> usize {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        ((self.0 >> 33) as usize) % bound
+    }
+}
+
+/// build a random relative path and the errno opening it must give, with O_DIRECTORY if `directory`
+fn random_path(rng: &mut Rng, directory: bool) -> (String, isize) {
+    let mut path = String::new();
+    let mut node = Node::Root;
+    let mut errno = 0;
+    for i in 0..1 + rng.next(5) {
+        // never climb above the test directory, whatever is there is not ours
+        let component = match rng.next(6) {
+            0 => "file",
+            1 => "dir",
+            2 => ".",
+            3 if node != Node::Root => "..",
+            4 => "missing",
+            _ => ".",
+        };
+        if i > 0 {
+            path.push('/');
+            // doubled slashes are a single separator
+            if rng.next(8) == 0 {
+                path.push('/');
+            }
+        }
+        path.push_str(component);
+        if errno != 0 {
+            continue;
+        }
+        node = match (node, component) {
+            (Node::File, _) => {
+                errno = ENOTDIR;
+                node
+            }
+            (Node::Missing, _) => {
+                errno = ENOENT;
+                node
+            }
+            (_, ".") => node,
+            (Node::Dir, "..") => Node::Root,
+            (Node::Root, "file") => Node::File,
+            (Node::Root, "dir") => Node::Dir,
+            _ => Node::Missing,
+        };
+    }
+    let trailing = rng.next(4) == 0;
+    if trailing {
+        path.push('/');
+    }
+    if errno == 0 {
+        errno = match node {
+            Node::Missing => ENOENT,
+            Node::File if trailing || directory => ENOTDIR,
+            _ => 0,
+        };
+    }
+    path.push('\0');
+    (path, errno)
+}
+
+/// the malformed paths with a known answer
+fn fixed_cases(dirfd: isize) -> bool {
+    let mut ok = true;
+    let long_name = "x".repeat(NAME_MAX + 1) + "\0";
+    let long_path = "a/".repeat(PATH_MAX / 2) + "\0";
+    let fits = "x".repeat(NAME_MAX) + "\0";
+
+    ok &= check(probe(dirfd, "\0", OpenFlags::RDONLY), ENOENT, "", "open");
+    ok &= check(probe(dirfd, "file/foo\0", OpenFlags::RDONLY), ENOTDIR, "file/foo", "open");
+    ok &= check(probe(dirfd, "file/\0", OpenFlags::RDONLY), ENOTDIR, "file/", "open");
+    ok &= check(probe(dirfd, "file/.\0", OpenFlags::RDONLY), ENOTDIR, "file/.", "open");
+    ok &= check(probe(dirfd, "file/..\0", OpenFlags::RDONLY), ENOTDIR, "file/..", "open");
+    ok &= check(probe(dirfd, "missing/file\0", OpenFlags::RDONLY), ENOENT, "missing/file", "open");
+    ok &= check(probe(dirfd, "missing/..\0", OpenFlags::RDONLY), ENOENT, "missing/..", "open");
+    ok &= check(probe(dirfd, "dir/../file\0", OpenFlags::RDONLY), 0, "dir/../file", "open");
+    ok &= check(probe(dirfd, "./dir/./\0", OpenFlags::RDONLY), 0, "./dir/./", "open");
+    ok &= check(probe(dirfd, "file\0", OpenFlags::DIRECTORY), ENOTDIR, "file", "open O_DIRECTORY");
+    ok &= check(probe(dirfd, &long_name, OpenFlags::RDONLY), ENAMETOOLONG, "256 char name", "open");
+    ok &= check(probe(dirfd, &long_path, OpenFlags::RDONLY), ENAMETOOLONG, "4096 char path", "open");
+    ok &= check(probe(dirfd, &fits, OpenFlags::RDONLY), ENOENT, "255 char name", "open");
+
+    // creation through a path that cannot hold the new file
+    let create = OpenFlags::CREATE | OpenFlags::WRONLY;
+    ok &= check(probe(dirfd, "new\0", create | OpenFlags::DIRECTORY), EINVAL, "new", "open O_CREAT|O_DIRECTORY");
+    ok &= check(probe(dirfd, "new\0", OpenFlags::RDONLY), ENOENT, "new", "open after O_CREAT|O_DIRECTORY");
+    ok &= check(probe(dirfd, "file/new\0", create), ENOTDIR, "file/new", "create");
+    ok &= check(probe(dirfd, "missing/new\0", create), ENOENT, "missing/new", "create");
+    ok &= check(probe(dirfd, "new/\0", create), EISDIR, "new/", "create");
+    ok &= check(probe(dirfd, "file\0", create | OpenFlags::EXCL), EEXIST, "file", "create O_EXCL");
+    ok &= check(probe(dirfd, &long_name, create), ENAMETOOLONG, "256 char name", "create");
+
+    ok &= check(mkdirat(dirfd, "\0"), ENOENT, "", "mkdir");
+    ok &= check(mkdirat(dirfd, "file\0"), EEXIST, "file", "mkdir");
+    ok &= check(mkdirat(dirfd, "dir/\0"), EEXIST, "dir/", "mkdir");
+    ok &= check(mkdirat(dirfd, "file/sub\0"), ENOTDIR, "file/sub", "mkdir");
+    ok &= check(mkdirat(dirfd, "missing/sub\0"), ENOENT, "missing/sub", "mkdir");
+    ok &= check(mkdirat(dirfd, &long_name), ENAMETOOLONG, "256 char name", "mkdir");
+
+    ok &= check(unlinkat(dirfd, "\0", 0), ENOENT, "", "unlink");
+    ok &= check(unlinkat(dirfd, "missing\0", 0), ENOENT, "missing", "unlink");
+    ok &= check(unlinkat(dirfd, "file/\0", 0), ENOTDIR, "file/", "unlink");
+    ok &= check(unlinkat(dirfd, "file/x\0", 0), ENOTDIR, "file/x", "unlink");
+    ok &= check(unlinkat(dirfd, "dir\0", 0), EISDIR, "dir", "unlink");
+    ok &= check(unlinkat(dirfd, "file\0", AT_REMOVEDIR), ENOTDIR, "file", "rmdir");
+    ok &= check(unlinkat(dirfd, "dir/.\0", AT_REMOVEDIR), EINVAL, "dir/.", "rmdir");
+    ok &= check(unlinkat(dirfd, "dir/..\0", AT_REMOVEDIR), ENOTEMPTY, "dir/..", "rmdir");
+    ok &= check(unlinkat(dirfd, ".\0", AT_REMOVEDIR), EINVAL, ".", "rmdir");
+    ok &= check(unlinkat(dirfd, "file\0", 0x1), EINVAL, "file", "unlink with unknown flags");
+    ok &= check(unlinkat(dirfd, &long_name, 0), ENAMETOOLONG, "256 char name", "unlink");
+    ok
+}
+
+/// feed malformed paths to openat, mkdirat and unlinkat and check the errno of each
+#[no_mangle]
+pub fn main() -> i32 {
+    mkdir(ROOT);
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::WRONLY);
+    if fd < 0 {
+        println!("test_path_fuzz: cannot create {}", FILE);
+        return -1;
+    }
+    close(fd as usize);
+    mkdir(DIR);
+    let dirfd = open(ROOT, OpenFlags::DIRECTORY);
+    if dirfd < 0 {
+        println!("test_path_fuzz: cannot open {}", ROOT);
+        return -1;
+    }
+
+    let mut ok = fixed_cases(dirfd);
+    let mut rng = Rng(0x5eed);
+    for _ in 0..ROUNDS {
+        let directory = rng.next(2) == 0;
+        let (path, errno) = random_path(&mut rng, directory);
+        let flags = if directory { OpenFlags::DIRECTORY } else { OpenFlags::RDONLY };
+        ok &= check(probe(dirfd, &path, flags), errno, path.trim_end_matches('\0'), "fuzz open");
+    }
+    // rmdir of a non-empty directory
+    ok &= check(rmdir(ROOT), ENOTEMPTY, ROOT, "rmdir");
+
+    close(dirfd as usize);
+    unlink(FILE);
+    rmdir(DIR);
+    ok &= check(rmdir(ROOT), 0, ROOT, "rmdir");
+    if ok {
+        println!("test_path_fuzz passed!");
+        0
+    } else {
+        -1
+    }
+}
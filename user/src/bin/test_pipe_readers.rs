@@ -0,0 +1,75 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{check, close, exit, fork, kill, nanosleep, pipe, read, waitpid, write, SIGKILL};
+
+#[macro_use]
+extern crate user_lib;
+
+const READERS: usize = 3;
+/// the bytes each reader must get
+const WANT: usize = 5;
+/// the bytes written, twice what the readers want between them
+const CHUNKS: usize = 2 * READERS * WANT;
+/// the pause between two chunks, long enough for every reader to block again
+const PAUSE_MS: usize = 10;
+/// how long the readers are given after the last chunk
+const GRACE_MS: usize = 500;
+
+/// read a byte at a time until `WANT` came, or the pipe is closed
+fn reader(fd: usize) -> ! {
+    let mut got = 0;
+    let mut buf = [0u8; 1];
+    while got < WANT {
+        if read(fd, &mut buf) != 1 {
+            exit(1);
+        }
+        got += 1;
+    }
+    exit(0);
+}
+
+/// several readers block on one pipe and the writer feeds it a byte at a time,
+/// every reader must get its share rather than one of them sleeping forever
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    let mut fds = [0usize; 2];
+    if !check(pipe(&mut fds) == 0, "pipe") {
+        return -1;
+    }
+    let mut pids = [0isize; READERS];
+    for pid in pids.iter_mut() {
+        *pid = fork();
+        if *pid == 0 {
+            close(fds[1]);
+            reader(fds[0]);
+        }
+    }
+    close(fds[0]);
+    // let all of them block first
+    nanosleep(100);
+    for _ in 0..CHUNKS {
+        ok &= check(write(fds[1], b"x", 1) == 1, "write a chunk");
+        nanosleep(PAUSE_MS);
+    }
+    nanosleep(GRACE_MS);
+    // a reader still blocked is starved, it is killed rather than waited on forever
+    for (i, &pid) in pids.iter().enumerate() {
+        kill(pid, SIGKILL);
+        let mut status = 0;
+        waitpid(pid as usize, &mut status);
+        if !check(status == 0, "a reader making progress") {
+            println!("test_pipe_readers: reader {} starved, status {:#x}", i, status);
+        }
+        ok &= status == 0;
+    }
+    close(fds[1]);
+
+    if ok {
+        println!("test_pipe_readers: passed");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,80 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, open, pipe, ppoll, socket, unlink, write, OpenFlags, PollFd, POLLHUP, POLLIN, POLLNVAL, POLLOUT,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const AF_INET: i32 = 2;
+const SOCK_DGRAM: i32 = 2;
+const FILE: &str = "/test_poll_file\0";
+const CLOSED_FD: i32 = 1000;
+
+fn poll_fd(fd: i32, events: i16) -> PollFd {
+    PollFd { fd, events, revents: 0 }
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    let file = open(FILE, OpenFlags::CREATE | OpenFlags::RDWR);
+    let dir = open("/\0", OpenFlags::RDONLY);
+    let mut pipe_fd = [0usize; 2];
+    let sock = socket(AF_INET, SOCK_DGRAM, 0);
+    if file < 0 || dir < 0 || pipe(&mut pipe_fd) < 0 || sock < 0 {
+        println!("test_poll: can not open the files to poll");
+        return -1;
+    }
+    let (rx, tx) = (pipe_fd[0] as i32, pipe_fd[1] as i32);
+
+    // regular files and directories are always ready
+    let mut fds = [poll_fd(file as i32, POLLIN | POLLOUT), poll_fd(dir as i32, POLLIN)];
+    ok &= check(ppoll(&mut fds, None) == 2, "poll a file and a directory");
+    ok &= check(fds[0].revents == POLLIN | POLLOUT, "file is readable and writable");
+    ok &= check(fds[1].revents == POLLIN, "directory is readable");
+
+    // an empty pipe only reports the file, a negative fd is skipped
+    let mut fds = [poll_fd(rx, POLLIN), poll_fd(file as i32, POLLIN), poll_fd(-1, POLLIN)];
+    ok &= check(ppoll(&mut fds, Some(50)) == 1, "poll an empty pipe and a file");
+    ok &= check(fds[0].revents == 0, "empty pipe is not readable");
+    ok &= check(fds[2].revents == 0, "negative fd is ignored");
+
+    // nothing is ready before the timeout
+    let mut fds = [poll_fd(rx, POLLIN)];
+    ok &= check(ppoll(&mut fds, Some(50)) == 0, "poll times out");
+
+    // a closed fd reports POLLNVAL without failing the whole call
+    let mut fds = [poll_fd(CLOSED_FD, POLLIN), poll_fd(tx, POLLOUT), poll_fd(sock as i32, POLLOUT)];
+    ok &= check(ppoll(&mut fds, None) == 3, "poll a closed fd, a pipe and a socket");
+    ok &= check(fds[0].revents == POLLNVAL, "closed fd reports POLLNVAL");
+    ok &= check(fds[1].revents == POLLOUT, "empty pipe is writable");
+    ok &= check(fds[2].revents & POLLOUT != 0, "udp socket is writable");
+
+    // data in the pipe, then its writer goes away
+    write(tx as usize, b"x", 1);
+    let mut fds = [poll_fd(rx, POLLIN), poll_fd(sock as i32, POLLIN)];
+    ok &= check(ppoll(&mut fds, Some(50)) == 1, "poll a filled pipe and an idle socket");
+    ok &= check(fds[0].revents == POLLIN, "filled pipe is readable");
+    ok &= check(fds[1].revents == 0, "idle socket is not readable");
+    close(tx as usize);
+    let mut fds = [poll_fd(rx, POLLIN)];
+    ok &= check(ppoll(&mut fds, None) == 1, "poll a pipe without writer");
+    ok &= check(fds[0].revents & POLLHUP != 0, "closed writer reports POLLHUP");
+
+    close(rx as usize);
+    close(sock as usize);
+    close(dir as usize);
+    close(file as usize);
+    unlink(FILE);
+
+    if ok {
+        println!("test_poll: passed");
+        0
+    } else {
+        -1
+    }
+}
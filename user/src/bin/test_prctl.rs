@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, exit, fork, open, prctl, set_syscall_filter, waitpid, OpenFlags, EACCES, ENOSYS, EPERM, PR_GET_DUMPABLE,
+    PR_GET_NAME, PR_GET_NO_NEW_PRIVS, PR_SET_DUMPABLE, PR_SET_NAME, PR_SET_NO_NEW_PRIVS, SYSCALL_FILTER_ERRNO,
+    SYSCALL_FILTER_KILL,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const SYS_OPENAT: usize = 56;
+const SYS_WRITE: usize = 64;
+const SYS_EXIT: usize = 93;
+const SYS_EXIT_GROUP: usize = 94;
+const SYS_PRCTL: usize = 167;
+const SIGSYS: i32 = 31;
+
+/// run `f` in a filtered child, return its wait status
+fn filtered_child(mode: usize, f: fn() -> i32) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        prctl(PR_SET_NO_NEW_PRIVS, 1, 0);
+        if set_syscall_filter(&[SYS_WRITE, SYS_EXIT, SYS_EXIT_GROUP, SYS_PRCTL], mode) != 0 {
+            exit(100);
+        }
+        exit(f());
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    status
+}
+
+fn errno_child() -> i32 {
+    if open("/\0", OpenFlags::RDONLY) != ENOSYS {
+        return 1;
+    }
+    // the filter can not be widened
+    if set_syscall_filter(&[SYS_WRITE, SYS_EXIT, SYS_EXIT_GROUP, SYS_PRCTL, SYS_OPENAT], SYSCALL_FILTER_ERRNO) != EPERM {
+        return 2;
+    }
+    // but it can be narrowed
+    if set_syscall_filter(&[SYS_WRITE, SYS_EXIT, SYS_EXIT_GROUP], SYSCALL_FILTER_ERRNO) != 0 {
+        return 3;
+    }
+    0
+}
+
+fn kill_child() -> i32 {
+    open("/\0", OpenFlags::RDONLY);
+    // never reached
+    0
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    let mut name = [0u8; 16];
+    ok &= check(prctl(PR_SET_NAME, b"prctl-test-name-too-long\0".as_ptr() as usize, 0) == 0, "PR_SET_NAME");
+    ok &= check(prctl(PR_GET_NAME, name.as_mut_ptr() as usize, 0) == 0, "PR_GET_NAME");
+    ok &= check(&name == b"prctl-test-name\0", "name truncated to 15 bytes");
+
+    ok &= check(prctl(PR_GET_DUMPABLE, 0, 0) == 1, "dumpable by default");
+    ok &= check(prctl(PR_SET_DUMPABLE, 0, 0) == 0, "PR_SET_DUMPABLE");
+    ok &= check(prctl(PR_GET_DUMPABLE, 0, 0) == 0, "not dumpable");
+    prctl(PR_SET_DUMPABLE, 1, 0);
+
+    // a filter needs no_new_privs first
+    ok &= check(set_syscall_filter(&[SYS_WRITE], SYSCALL_FILTER_ERRNO) == EACCES, "filter without no_new_privs");
+    ok &= check(prctl(PR_GET_NO_NEW_PRIVS, 0, 0) == 0, "no_new_privs clear by default");
+
+    let status = filtered_child(SYSCALL_FILTER_ERRNO, errno_child);
+    ok &= check(status & 0x7f == 0 && (status >> 8) & 0xff == 0, "denied openat returns ENOSYS");
+
+    let status = filtered_child(SYSCALL_FILTER_KILL, kill_child);
+    ok &= check(status & 0x7f == SIGSYS, "denied openat kills with SIGSYS");
+
+    if ok {
+        println!("test_prctl passed!");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,44 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{exit, fork, sched_setaffinity, waitpid};
+
+#[macro_use]
+extern crate user_lib;
+
+const TASKS: usize = 4;
+const LINES: usize = 200;
+const WIDTH: usize = 60;
+
+/// every task prints lines of its own letter from its own hart;
+/// with atomic records each output line holds a single letter and ends in "|",
+/// so a torn line is easy to spot in the log
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut pids = [0isize; TASKS];
+    for (i, pid) in pids.iter_mut().enumerate() {
+        *pid = fork();
+        if *pid == 0 {
+            // an offline hart is rejected, the task then runs wherever it is
+            sched_setaffinity(0, &[1 << i]);
+            let letter = [b'a' + i as u8; WIDTH];
+            let letters = core::str::from_utf8(&letter).unwrap();
+            for line in 0..LINES {
+                println!("test_println: task {} line {:03} {}|", i, line, letters);
+            }
+            exit(0);
+        }
+    }
+    let mut failed = false;
+    for pid in pids {
+        let mut status = 0;
+        waitpid(pid as usize, &mut status);
+        failed |= status != 0;
+    }
+    if failed {
+        println!("test_println: a printing task failed");
+        return -1;
+    }
+    println!("test_println passed! check that every line above holds a single letter");
+    0
+}
@@ -0,0 +1,101 @@
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use user_lib::{
+    check, close, exit, fork, kill, pipe, process_vm_readv, process_vm_writev, read, sleep, waitpid, write, yield_,
+    IoVec, EFAULT, ESRCH, SIGCONT, SIGSTOP,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+/// crosses a page boundary wherever it lands
+const BUF_LEN: usize = 6000;
+const PATCHED: usize = 0xc0ffee;
+
+static mut BUF: [u8; BUF_LEN] = [0; BUF_LEN];
+static FLAG: AtomicUsize = AtomicUsize::new(0);
+
+fn pattern(i: usize) -> u8 {
+    (i * 7 % 251) as u8
+}
+
+fn iov<T>(ptr: *const T, len: usize) -> IoVec {
+    IoVec { base: ptr as usize, len }
+}
+
+/// fills its own copy of the buffer, then waits for the parent to patch the flag
+fn child(ready: usize) -> ! {
+    let buf = unsafe { &mut *core::ptr::addr_of_mut!(BUF) };
+    for (i, byte) in buf.iter_mut().enumerate() {
+        *byte = pattern(i);
+    }
+    write(ready, b"r");
+    while FLAG.load(Ordering::Acquire) == 0 {
+        yield_();
+    }
+    exit(if FLAG.load(Ordering::Relaxed) == PATCHED { 0 } else { -1 });
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    let mut pipe_fd = [0usize; 2];
+    if pipe(&mut pipe_fd) < 0 {
+        println!("test_process_vm: pipe failed");
+        return -1;
+    }
+    let pid = fork();
+    if pid == 0 {
+        close(pipe_fd[0]);
+        child(pipe_fd[1]);
+    }
+    close(pipe_fd[1]);
+    let mut byte = [0u8; 1];
+    read(pipe_fd[0], &mut byte);
+    kill(pid, SIGSTOP);
+    // give the stop time to take effect
+    sleep(10);
+    let pid = pid as usize;
+
+    // the buffer comes back in two pieces, split across two remote iovecs
+    let buf_addr = core::ptr::addr_of!(BUF) as *const u8;
+    let mut first = [0u8; 1000];
+    let mut rest = [0u8; BUF_LEN - 1000];
+    let local = [iov(first.as_mut_ptr(), first.len()), iov(rest.as_mut_ptr(), rest.len())];
+    let remote = [iov(buf_addr, 3000), iov(buf_addr.wrapping_add(3000), BUF_LEN - 3000)];
+    ok &= check(process_vm_readv(pid, &local, &remote) == BUF_LEN as isize, "read the child's buffer");
+    ok &= check(first.iter().chain(rest.iter()).enumerate().all(|(i, &b)| b == pattern(i)), "child's buffer content");
+    ok &= check(unsafe { (*core::ptr::addr_of!(BUF))[1] } == 0, "parent's own buffer untouched");
+
+    // a transfer stops at the first unmapped remote range
+    let local = [iov(first.as_mut_ptr(), first.len())];
+    let remote = [iov(buf_addr, 16), iov(0x10 as *const u8, 16)];
+    ok &= check(process_vm_readv(pid, &local, &remote) == 16, "partial read");
+    let remote = [iov(0x10 as *const u8, 16)];
+    ok &= check(process_vm_readv(pid, &local, &remote) == EFAULT, "read of an unmapped range");
+
+    // patch the flag of the stopped child, which sees it once continued
+    let patched = PATCHED;
+    let local = [iov(&patched as *const usize, core::mem::size_of::<usize>())];
+    let remote = [iov(&FLAG as *const AtomicUsize, core::mem::size_of::<usize>())];
+    ok &= check(process_vm_writev(pid, &local, &remote) == core::mem::size_of::<usize>() as isize, "patch the child's flag");
+    ok &= check(FLAG.load(Ordering::Relaxed) == 0, "parent's own flag untouched");
+    kill(pid as isize, SIGCONT);
+    let mut status = 0;
+    waitpid(pid, &mut status);
+    ok &= check(status == 0, "child observed the patched flag");
+
+    // the child is gone now
+    ok &= check(process_vm_readv(pid, &local, &remote) == ESRCH, "read from a reaped child");
+    close(pipe_fd[0]);
+
+    if ok {
+        println!("test_process_vm: passed");
+        0
+    } else {
+        -1
+    }
+}
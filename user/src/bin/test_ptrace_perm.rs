@@ -0,0 +1,81 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, exit, fork, kill, prctl, ptrace, setuid, sleep, waitpid, EPERM, PR_SET_DUMPABLE, PTRACE_ATTACH,
+    PTRACE_TRACEME, SIGKILL,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+/// a child which sleeps until it is killed, not dumpable unless `dumpable`
+fn sleeper(dumpable: bool) -> usize {
+    let pid = fork();
+    if pid == 0 {
+        if !dumpable {
+            prctl(PR_SET_DUMPABLE, 0, 0);
+        }
+        loop {
+            sleep(1000);
+        }
+    }
+    pid as usize
+}
+
+/// kill `pid` and reap it, past the ptrace stops of a traced child
+fn reap(pid: usize) {
+    kill(pid as isize, SIGKILL);
+    let mut status = 0;
+    loop {
+        if waitpid(pid, &mut status) < 0 || status & 0xff != 0x7f {
+            break;
+        }
+    }
+}
+
+/// the exit code of a child which runs `f`, 0 when `f` holds
+fn in_child(f: impl FnOnce() -> bool) -> bool {
+    let pid = fork();
+    if pid == 0 {
+        exit(if f() { 0 } else { 1 });
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    status == 0
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    // another user may not attach to a process of root
+    let target = sleeper(true);
+    sleep(50);
+    ok &= check(
+        in_child(|| setuid(1000) == 0 && ptrace(PTRACE_ATTACH, target, 0, 0) == EPERM),
+        "PTRACE_ATTACH of another user",
+    );
+    // the same user may
+    ok &= check(ptrace(PTRACE_ATTACH, target, 0, 0) == 0, "PTRACE_ATTACH of the same user");
+    reap(target);
+
+    // nobody may attach to a process which is not dumpable
+    let target = sleeper(false);
+    sleep(50);
+    ok &= check(ptrace(PTRACE_ATTACH, target, 0, 0) == EPERM, "PTRACE_ATTACH of a process not dumpable");
+    reap(target);
+
+    // nor may a process which is not dumpable ask to be traced
+    ok &= check(
+        in_child(|| prctl(PR_SET_DUMPABLE, 0, 0) == 0 && ptrace(PTRACE_TRACEME, 0, 0, 0) == EPERM),
+        "PTRACE_TRACEME of a process not dumpable",
+    );
+
+    if ok {
+        println!("test_ptrace_perm: passed");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,100 @@
+#![no_std]
+#![no_main]
+
+use alloc::{format, string::String, vec::Vec};
+use user_lib::{check, close, getdents, mkdir, open, openat, rmdir, unlink, OpenFlags};
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+const DIR: &str = "/test_readdir\0";
+/// the bytes of a linux_dirent64 before the name
+const DIRENT_NAME: usize = 19;
+const DT_DIR: u8 = 4;
+const DT_REG: u8 = 8;
+/// a buffer of one or two records, so a listing takes many calls
+const SMALL_BUF: usize = 48;
+
+/// the (name, d_type) of every entry getdents gives from the current position of `fd`
+fn list(fd: usize) -> Vec<(String, u8)> {
+    let mut entries = Vec::new();
+    let mut buf = [0u8; SMALL_BUF];
+    loop {
+        let n = getdents(fd, &mut buf);
+        if n <= 0 {
+            return entries;
+        }
+        let mut off = 0;
+        while off < n as usize {
+            let reclen = u16::from_le_bytes([buf[off + 16], buf[off + 17]]) as usize;
+            let d_type = buf[off + 18];
+            let rec = &buf[off + DIRENT_NAME..off + reclen];
+            let len = rec.iter().position(|&c| c == 0).unwrap_or(rec.len());
+            entries.push((String::from(core::str::from_utf8(&rec[..len]).unwrap()), d_type));
+            off += reclen;
+        }
+    }
+}
+
+/// whether `entries` holds exactly the regular files `files` and the directories `dirs`, once each
+fn agrees(entries: &[(String, u8)], files: &[String], dirs: &[&str]) -> bool {
+    entries.len() == files.len() + dirs.len()
+        && files.iter().all(|f| entries.iter().filter(|(name, ty)| name == f && *ty == DT_REG).count() == 1)
+        && dirs.iter().all(|d| entries.iter().filter(|(name, ty)| name == d && *ty == DT_DIR).count() == 1)
+}
+
+fn touch(path: &str) -> bool {
+    let fd = open(path, OpenFlags::CREATE | OpenFlags::WRONLY);
+    fd >= 0 && close(fd as usize) == 0
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    mkdir(DIR);
+    mkdir("/test_readdir/sub\0");
+    let mut files: Vec<String> = (0..10).map(|i| format!("a{}", i)).collect();
+    for f in files.iter() {
+        ok &= check(touch(&format!("/test_readdir/{}\0", f)), "create a file");
+    }
+
+    // a listing spread over many calls shows every entry once
+    let cached = open(DIR, OpenFlags::RDONLY | OpenFlags::DIRECTORY);
+    ok &= check(cached >= 0, "open the directory");
+    ok &= check(agrees(&list(cached as usize), &files, &["sub"]), "first listing");
+
+    // change the directory through another path to it
+    for i in 0..5 {
+        ok &= check(unlink(&format!("/test_readdir/sub/../a{}\0", i)) == 0, "unlink through another path");
+        let name = format!("b{}", i);
+        ok &= check(touch(&format!("/test_readdir/sub/../{}\0", name)), "create through another path");
+        files.push(name);
+    }
+    files.drain(..5);
+
+    // the directory already listed and a new open of it agree with what is there
+    let again = openat(cached, ".\0", OpenFlags::RDONLY);
+    ok &= check(again >= 0, "open the directory again through the cached one");
+    ok &= check(agrees(&list(again as usize), &files, &["sub"]), "listing through the cached directory");
+    close(again as usize);
+    let fresh = open("/test_readdir/sub/..\0", OpenFlags::RDONLY);
+    ok &= check(fresh >= 0, "open the directory by another path");
+    ok &= check(agrees(&list(fresh as usize), &files, &["sub"]), "listing by another path");
+    close(fresh as usize);
+    ok &= check(list(cached as usize).is_empty(), "a finished listing stays finished");
+    close(cached as usize);
+
+    for f in files.iter() {
+        unlink(&format!("/test_readdir/{}\0", f));
+    }
+    rmdir("/test_readdir/sub\0");
+    rmdir(DIR);
+
+    if ok {
+        println!("test_readdir: passed");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,93 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, drop_caches, fchmod, mkdir, open, pread, read, remount, rename, rmdir, statfs, unlink, utimensat_now,
+    write, OpenFlags, StatFs, AT_FDCWD, EBUSY, EROFS, MS_NOATIME, MS_RDONLY, ST_NOATIME, ST_RDONLY,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const ROOT: &str = "/\0";
+/// on ext4, written before the remount and never synced by the test
+const DIRTY_FILE: &str = "/test_remount_dirty\0";
+const NEW_FILE: &str = "/test_remount_new\0";
+const NEW_DIR: &str = "/test_remount_dir\0";
+const RENAMED: &str = "/test_remount_renamed\0";
+const DATA: &[u8] = b"written before the read-only remount";
+
+/// the f_flags of the mount of the root
+fn root_flags() -> isize {
+    let mut buf = StatFs::default();
+    if statfs(ROOT, &mut buf) != 0 {
+        return -1;
+    }
+    buf.f_flags
+}
+
+/// whether `path` holds `DATA`
+fn holds_data(path: &str) -> bool {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd < 0 {
+        return false;
+    }
+    let mut buf = [0u8; 64];
+    let n = read(fd as usize, &mut buf);
+    close(fd as usize);
+    n == DATA.len() as isize && &buf[..DATA.len()] == DATA
+}
+
+/// every write path of a read-only mount fails with EROFS, reads still work
+fn check_read_only() -> bool {
+    let mut ok = check(open(NEW_FILE, OpenFlags::CREATE | OpenFlags::WRONLY) == EROFS, "creat is EROFS");
+    ok &= check(open(DIRTY_FILE, OpenFlags::WRONLY) == EROFS, "open for writing is EROFS");
+    ok &= check(open(DIRTY_FILE, OpenFlags::RDONLY | OpenFlags::TRUNC) == EROFS, "O_TRUNC is EROFS");
+    ok &= check(unlink(DIRTY_FILE) == EROFS, "unlink is EROFS");
+    ok &= check(rename(DIRTY_FILE, RENAMED) == EROFS, "rename is EROFS");
+    ok &= check(mkdir(NEW_DIR) == EROFS, "mkdir is EROFS");
+    ok &= check(utimensat_now(AT_FDCWD, DIRTY_FILE, 0) == EROFS, "utimensat is EROFS");
+    let fd = open(DIRTY_FILE, OpenFlags::RDONLY);
+    ok &= check(fd >= 0, "open for reading");
+    ok &= check(fchmod(fd as usize, 0o600) == EROFS, "fchmod is EROFS");
+    let mut buf = [0u8; 64];
+    ok &= check(pread(fd as usize, &mut buf, 0) == DATA.len() as isize, "read");
+    close(fd as usize);
+    ok
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = check(root_flags() & ST_RDONLY == 0, "the root starts read-write");
+
+    // dirty pages the remount has to write back
+    let fd = open(DIRTY_FILE, OpenFlags::CREATE | OpenFlags::RDWR | OpenFlags::TRUNC);
+    ok &= check(fd >= 0 && write(fd as usize, DATA, DATA.len()) == DATA.len() as isize, "write before the remount");
+    ok &= check(remount(ROOT, MS_RDONLY) == EBUSY, "a remount with a file open for writing is EBUSY");
+    close(fd as usize);
+
+    ok &= check(remount(ROOT, MS_RDONLY) == 0, "remount read-only");
+    ok &= check(root_flags() & ST_RDONLY != 0, "statfs reports ST_RDONLY");
+    ok &= check_read_only();
+    // the file comes back from the disk, not from the pages written before
+    drop_caches();
+    ok &= check(holds_data(DIRTY_FILE), "the dirty data is on disk after the remount");
+
+    ok &= check(remount(ROOT, MS_NOATIME) == 0, "remount read-write");
+    let flags = root_flags();
+    ok &= check(flags & ST_RDONLY == 0 && flags & ST_NOATIME != 0, "statfs reports the new flags");
+    let fd = open(NEW_FILE, OpenFlags::CREATE | OpenFlags::RDWR | OpenFlags::TRUNC);
+    ok &= check(fd >= 0 && write(fd as usize, DATA, DATA.len()) == DATA.len() as isize, "write after the remount");
+    close(fd as usize);
+    ok &= check(holds_data(NEW_FILE), "read back after the remount");
+    ok &= check(mkdir(NEW_DIR) == 0 && rmdir(NEW_DIR) == 0, "mkdir after the remount");
+    ok &= check(unlink(NEW_FILE) == 0 && unlink(DIRTY_FILE) == 0, "unlink after the remount");
+    ok &= check(remount(ROOT, 0) == 0 && root_flags() & ST_NOATIME == 0, "back to the flags at boot");
+
+    if ok {
+        println!("test_remount_ro: passed");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,74 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, exit, fork, fstat, get_time_of_day, ioctl, open, set_time_of_day, setuid, unlink, waitpid, OpenFlags,
+    RtcTime, Stat, TimeVal, EPERM, RTC_RD_TIME,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const FILE: &str = "/test_rtc_file\0";
+/// 2000-01-01, any wall clock read from an rtc is later
+const Y2K: usize = 946_684_800;
+const SLACK_SEC: usize = 5;
+
+fn close_to(a: usize, b: usize) -> bool {
+    a.abs_diff(b) <= SLACK_SEC
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    let mut tv = TimeVal::default();
+    get_time_of_day(&mut tv);
+    println!("test_rtc: wall clock at {}s since the epoch", tv.sec);
+    ok &= check(tv.sec > Y2K, "wall clock seeded from the rtc");
+
+    let fd = open("/dev/rtc0\0", OpenFlags::RDONLY);
+    ok &= check(fd >= 0, "open /dev/rtc0");
+    if fd >= 0 {
+        let mut tm = RtcTime::default();
+        ok &= check(ioctl(fd as usize, RTC_RD_TIME, &mut tm as *mut _ as usize) == 0, "RTC_RD_TIME");
+        println!("test_rtc: rtc reads {}-{:02}-{:02} {:02}:{:02}:{:02}",
+            tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_sec);
+        let rtc_day_sec = (tm.tm_hour * 3600 + tm.tm_min * 60 + tm.tm_sec) as usize;
+        let day_sec = tv.sec % 86400;
+        ok &= check(close_to(rtc_day_sec, day_sec) || close_to(rtc_day_sec + 86400, day_sec)
+            || close_to(rtc_day_sec, day_sec + 86400), "rtc agrees with the wall clock");
+        close(fd as usize);
+    }
+
+    // a new file is stamped with the wall clock
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::WRONLY);
+    if fd >= 0 {
+        let mut stat = Stat::default();
+        fstat(fd as usize, &mut stat);
+        ok &= check(close_to(stat.st_mtime_sec as usize, tv.sec), "mtime of a new file");
+        close(fd as usize);
+        unlink(FILE);
+    } else {
+        ok &= check(false, "create a file");
+    }
+
+    // only root may set the clock, which is written back to the rtc
+    let pid = fork();
+    if pid == 0 {
+        setuid(1000);
+        exit((set_time_of_day(&tv) != EPERM) as i32);
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    ok &= check(status == 0, "settimeofday without privilege");
+    get_time_of_day(&mut tv);
+    ok &= check(set_time_of_day(&tv) == 0, "settimeofday");
+
+    if ok {
+        println!("test_rtc passed!");
+        0
+    } else {
+        -1
+    }
+}
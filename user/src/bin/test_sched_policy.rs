@@ -0,0 +1,138 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, exit, fork, get_time_ms, getrusage, kill, pipe, read, sched_getparam, sched_getscheduler,
+    sched_setaffinity, sched_setscheduler, setuid, waitpid, write, Rusage, EINVAL, EPERM, ESRCH, RUSAGE_SELF,
+    SCHED_BATCH, SCHED_FIFO, SCHED_IDLE, SCHED_OTHER, SCHED_RESET_ON_FORK, SIGKILL,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const SPIN_MS: isize = 1000;
+
+/// spin on hart 0 under `policy` for SPIN_MS, then send the user time in ms through `fd`
+fn spinner(policy: i32, fd: usize) -> ! {
+    sched_setaffinity(0, &[1]);
+    sched_setscheduler(0, policy, 0);
+    let start = get_time_ms();
+    while get_time_ms() < start + SPIN_MS {}
+    let mut usage = Rusage::default();
+    getrusage(RUSAGE_SELF, &mut usage);
+    let ms = usage.ru_utime.sec * 1000 + usage.ru_utime.usec / 1000;
+    write(fd, &ms.to_le_bytes(), 8);
+    exit(0);
+}
+
+/// the user time of a SCHED_OTHER spinner on hart 0, next to a spinner under `rival` if any
+fn other_ms(rival: Option<i32>) -> usize {
+    let mut fds = [0usize; 2];
+    pipe(&mut fds);
+    let rival = rival.map(|policy| {
+        let mut rival_fds = [0usize; 2];
+        pipe(&mut rival_fds);
+        let pid = fork();
+        if pid == 0 {
+            spinner(policy, rival_fds[1]);
+        }
+        (pid, rival_fds)
+    });
+    let pid = fork();
+    if pid == 0 {
+        spinner(SCHED_OTHER, fds[1]);
+    }
+    let mut buf = [0u8; 8];
+    read(fds[0], &mut buf);
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    if let Some((pid, rival_fds)) = rival {
+        waitpid(pid as usize, &mut status);
+        close(rival_fds[0]);
+        close(rival_fds[1]);
+    }
+    close(fds[0]);
+    close(fds[1]);
+    usize::from_le_bytes(buf)
+}
+
+/// the policy of a child forked after `policy` is set, which checks it and exits
+fn child_policy(policy: i32) -> isize {
+    let pid = fork();
+    if pid == 0 {
+        sched_setscheduler(0, policy, 0);
+        let grandchild = fork();
+        if grandchild == 0 {
+            exit(sched_getscheduler(0) as i32);
+        }
+        let mut status = 0;
+        waitpid(grandchild as usize, &mut status);
+        exit((status >> 8) & 0xff);
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    ((status >> 8) & 0xff) as isize
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    let mut priority = -1;
+    ok &= check(sched_getscheduler(0) == SCHED_OTHER as isize, "SCHED_OTHER by default");
+    ok &= check(sched_getparam(0, &mut priority) == 0 && priority == 0, "priority 0 by default");
+
+    // set the policy of another task and read it back
+    let pid = fork();
+    if pid == 0 {
+        loop {
+            get_time_ms();
+        }
+    }
+    let child = pid as usize;
+    ok &= check(sched_setscheduler(child, SCHED_BATCH, 0) == 0, "set SCHED_BATCH on a child");
+    ok &= check(sched_getscheduler(child) == SCHED_BATCH as isize, "read SCHED_BATCH back");
+    ok &= check(sched_setscheduler(child, SCHED_IDLE, 0) == 0, "set SCHED_IDLE on a child");
+    ok &= check(sched_getscheduler(child) == SCHED_IDLE as isize, "read SCHED_IDLE back");
+    priority = -1;
+    ok &= check(sched_getparam(child, &mut priority) == 0 && priority == 0, "the priority of a child");
+    ok &= check(sched_setscheduler(child, SCHED_OTHER, 5) == EINVAL, "a priority for SCHED_OTHER");
+    ok &= check(sched_setscheduler(child, SCHED_FIFO, 0) == EINVAL, "priority 0 for SCHED_FIFO");
+    ok &= check(sched_setscheduler(child, 42, 0) == EINVAL, "an unknown policy");
+    kill(pid, SIGKILL);
+    let mut status = 0;
+    waitpid(child, &mut status);
+    ok &= check(sched_getscheduler(child) == ESRCH, "a task which is gone");
+    ok &= check(sched_getscheduler(usize::MAX) == EINVAL, "a negative pid");
+
+    // the real-time policies and other users' tasks need privilege
+    let pid = fork();
+    if pid == 0 {
+        setuid(1000);
+        let ok = check(sched_setscheduler(0, SCHED_FIFO, 10) == EPERM, "SCHED_FIFO without privilege")
+            & check(sched_setscheduler(1, SCHED_IDLE, 0) == EPERM, "the policy of a task of root")
+            & check(sched_setscheduler(0, SCHED_IDLE, 0) == 0, "SCHED_IDLE without privilege");
+        exit(if ok { 0 } else { 1 });
+    }
+    waitpid(pid as usize, &mut status);
+    ok &= check(status == 0, "the permission checks");
+
+    // the policy is inherited, unless SCHED_RESET_ON_FORK is set
+    ok &= check(child_policy(SCHED_IDLE) == SCHED_IDLE as isize, "SCHED_IDLE inherited");
+    ok &= check(child_policy(SCHED_BATCH | SCHED_RESET_ON_FORK) == SCHED_OTHER as isize, "reset on fork");
+
+    // a SCHED_IDLE spinner hardly slows a SCHED_OTHER one on its hart,
+    // another SCHED_OTHER one takes about half
+    let alone = other_ms(None);
+    let with_idle = other_ms(Some(SCHED_IDLE));
+    let with_other = other_ms(Some(SCHED_OTHER));
+    println!("test_sched_policy: alone {} ms, next to SCHED_IDLE {} ms, next to SCHED_OTHER {} ms", alone, with_idle, with_other);
+    ok &= check(with_idle * 10 >= alone * 9, "SCHED_IDLE stays in the background");
+    ok &= check(with_other * 10 < alone * 7, "SCHED_OTHER shares the hart");
+
+    if ok {
+        println!("test_sched_policy: passed");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,23 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{selftest, ENOSYS};
+
+#[macro_use]
+extern crate user_lib;
+
+/// run the kernel self tests again, now with tasks running around them
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let failed = selftest();
+    if failed == ENOSYS {
+        println!("test_selftest: kernel built without the selftest feature, skipped");
+        return 0;
+    }
+    if failed != 0 {
+        println!("test_selftest: {} kernel self tests failed", failed);
+        return -1;
+    }
+    println!("test_selftest: passed");
+    0
+}
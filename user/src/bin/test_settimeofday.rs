@@ -0,0 +1,102 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, clock_gettime, close, fstat, fstatat, get_time_of_day, getpid, open, set_time_of_day, unlink, utimensat_now,
+    OpenFlags, Stat, TimeSpec, TimeVal, AT_FDCWD, CLOCK_MONOTONIC,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const FILE: &str = "/test_settimeofday_file\0";
+/// the clock is set this far ahead, then twice as far
+const SHIFT_SEC: usize = 86400;
+const SLACK_SEC: usize = 5;
+/// calls timed in the loops
+const ROUNDS: usize = 20000;
+
+/// `sec` is within SLACK_SEC after `want`
+fn just_after(sec: usize, want: usize) -> bool {
+    sec >= want && sec - want <= SLACK_SEC
+}
+
+fn monotonic_ns() -> usize {
+    let mut ts = TimeSpec::default();
+    clock_gettime(CLOCK_MONOTONIC, &mut ts);
+    ts.sec * 1_000_000_000 + ts.nsec
+}
+
+/// nanoseconds per call of `f`, over ROUNDS calls
+fn ns_per_call(mut f: impl FnMut()) -> usize {
+    let start = monotonic_ns();
+    for _ in 0..ROUNDS {
+        f();
+    }
+    (monotonic_ns() - start) / ROUNDS
+}
+
+fn set_sec(sec: usize) -> bool {
+    set_time_of_day(&TimeVal { sec, usec: 0 }) == 0
+}
+
+fn now_sec() -> usize {
+    let mut tv = TimeVal::default();
+    get_time_of_day(&mut tv);
+    tv.sec
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    let start = now_sec();
+    let start_ns = monotonic_ns();
+
+    // the new wall clock is read back and stamps a new file
+    let ahead = start + SHIFT_SEC;
+    ok &= check(set_sec(ahead), "settimeofday");
+    ok &= check(just_after(now_sec(), ahead), "gettimeofday after settimeofday");
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC);
+    if fd < 0 {
+        println!("test_settimeofday: create a file failed");
+        set_sec(start);
+        return -1;
+    }
+    let mut stat = Stat::default();
+    fstat(fd as usize, &mut stat);
+    ok &= check(just_after(stat.st_mtime_sec as usize, ahead), "mtime of a new file");
+    ok &= check(just_after(stat.st_ctime_sec as usize, ahead), "ctime of a new file");
+    close(fd as usize);
+
+    // utimensat to now takes the clock set again
+    let further = start + 2 * SHIFT_SEC;
+    ok &= check(set_sec(further), "settimeofday again");
+    ok &= check(utimensat_now(AT_FDCWD, FILE, 0) == 0, "utimensat");
+    let mut stat = Stat::default();
+    fstatat(AT_FDCWD, FILE, &mut stat, 0);
+    ok &= check(just_after(stat.st_atime_sec as usize, further), "atime after utimensat");
+    ok &= check(just_after(stat.st_mtime_sec as usize, further), "mtime after utimensat");
+    unlink(FILE);
+
+    // the time which passed meanwhile is kept
+    let elapsed = (monotonic_ns() - start_ns) / 1_000_000_000;
+    ok &= check(set_sec(start + elapsed), "restore the clock");
+
+    // gettimeofday takes no lock of the timer, a call costs about as much as a getpid
+    let mut tv = TimeVal::default();
+    let gettimeofday_ns = ns_per_call(|| {
+        get_time_of_day(&mut tv);
+    });
+    let getpid_ns = ns_per_call(|| {
+        getpid();
+    });
+    println!("test_settimeofday: gettimeofday {}ns, getpid {}ns per call", gettimeofday_ns, getpid_ns);
+    ok &= check(gettimeofday_ns <= 2 * getpid_ns.max(1), "gettimeofday as cheap as getpid");
+
+    if ok {
+        println!("test_settimeofday: passed");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,114 @@
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use user_lib::{
+    check, exit, fork, get_time_ms, getpid, getuid, kill, sigaction_flags, sleep, waitpid, yield_, SigInfo,
+    CLD_CONTINUED, CLD_EXITED, CLD_KILLED, CLD_STOPPED, SA_NOCLDSTOP, SA_SIGINFO, SIGCHLD, SIGCONT, SIGKILL, SIGSTOP,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const EXIT_CODE: i32 = 7;
+/// how long the child of the exit case burns cpu time
+const BUSY_MS: isize = 50;
+/// how long to wait for a SIGCHLD before giving up on it
+const TIMEOUT_MS: isize = 1000;
+
+static mut RECORDS: [Option<SigInfo>; 8] = [None; 8];
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn on_sigchld(_signo: i32, info: *const SigInfo, _ucontext: usize) {
+    let n = COUNT.load(Ordering::Relaxed);
+    if n < 8 {
+        unsafe { (*core::ptr::addr_of_mut!(RECORDS))[n] = Some(*info) };
+    }
+    COUNT.store(n + 1, Ordering::Relaxed);
+}
+
+/// wait until `n` SIGCHLDs have been handled, then return the last one
+fn wait_for(n: usize) -> Option<SigInfo> {
+    let start = get_time_ms();
+    while COUNT.load(Ordering::Relaxed) < n {
+        if get_time_ms() > start + TIMEOUT_MS {
+            return None;
+        }
+        yield_();
+    }
+    unsafe { (*core::ptr::addr_of!(RECORDS))[n - 1] }
+}
+
+fn is(info: Option<SigInfo>, pid: isize, code: i32, status: i32) -> bool {
+    info.map_or(false, |info| {
+        info.signo == SIGCHLD && info.pid == pid as i32 && info.code == code && info.status == status
+    })
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    let mut status = 0;
+
+    if sigaction_flags(SIGCHLD, on_sigchld as usize, SA_SIGINFO) < 0 {
+        println!("test_sigchld: can not install the handler");
+        return -1;
+    }
+
+    // a child that exits on its own, after burning some cpu time
+    let pid = fork();
+    if pid == 0 {
+        let start = get_time_ms();
+        while get_time_ms() < start + BUSY_MS {}
+        exit(EXIT_CODE);
+    }
+    let info = wait_for(1);
+    ok &= check(is(info, pid, CLD_EXITED, EXIT_CODE), "SIGCHLD of an exit");
+    ok &= check(info.map_or(false, |info| info.uid == getuid() as u32), "uid of the child");
+    ok &= check(info.map_or(false, |info| info.utime >= 0 && info.stime >= 0 && info.utime + info.stime > 0), "cpu time of the child");
+    waitpid(pid as usize, &mut status);
+
+    // a child killed by its parent
+    let pid = fork();
+    if pid == 0 {
+        loop {
+            yield_();
+        }
+    }
+    kill(pid, SIGKILL);
+    ok &= check(is(wait_for(2), pid, CLD_KILLED, SIGKILL), "SIGCHLD of a kill");
+    waitpid(pid as usize, &mut status);
+
+    // a child that stops itself, is continued and exits
+    let pid = fork();
+    if pid == 0 {
+        kill(getpid(), SIGSTOP);
+        exit(EXIT_CODE);
+    }
+    ok &= check(is(wait_for(3), pid, CLD_STOPPED, SIGSTOP), "SIGCHLD of a stop");
+    kill(pid, SIGCONT);
+    ok &= check(is(wait_for(4), pid, CLD_CONTINUED, SIGCONT), "SIGCHLD of a continue");
+    ok &= check(is(wait_for(5), pid, CLD_EXITED, EXIT_CODE), "SIGCHLD of the exit after a continue");
+    waitpid(pid as usize, &mut status);
+
+    // SA_NOCLDSTOP leaves only the exit to report
+    sigaction_flags(SIGCHLD, on_sigchld as usize, SA_SIGINFO | SA_NOCLDSTOP);
+    let pid = fork();
+    if pid == 0 {
+        kill(getpid(), SIGSTOP);
+        exit(EXIT_CODE);
+    }
+    sleep(100);
+    ok &= check(COUNT.load(Ordering::Relaxed) == 5, "no SIGCHLD of a stop under SA_NOCLDSTOP");
+    kill(pid, SIGCONT);
+    ok &= check(is(wait_for(6), pid, CLD_EXITED, EXIT_CODE), "SIGCHLD of the exit under SA_NOCLDSTOP");
+    waitpid(pid as usize, &mut status);
+
+    if ok {
+        println!("test_sigchld: passed");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,200 @@
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+
+use user_lib::{
+    bind, check, close, exit, fcntl, fork, getpid, kill, pipe, ppoll, read, sendto, setuid, sigaction_flags, sleep,
+    socket, waitpid, write, SigInfo, SockaddrIn, EINTR, EINVAL, ESRCH, F_GETFL, F_GETOWN, F_GETSIG, F_SETFL, F_SETOWN,
+    F_SETSIG, O_ASYNC, POLL_IN, SA_SIGINFO, SIGIO, SIGKILL,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const AF_INET: i32 = 2;
+const SOCK_DGRAM: i32 = 2;
+const TEST_ADDR: u32 = 0x7f000001; // 127.0.0.1
+const PORT: u16 = 4490;
+/// the real time signal F_SETSIG asks for
+const SIGRT: i32 = 40;
+/// POLLIN, the band of input has it
+const POLLIN: i64 = 0x1;
+/// how long the owner waits for a signal which should come
+const WAIT_MS: usize = 3000;
+
+/// the SIGIO the process got
+static SIGIOS: AtomicUsize = AtomicUsize::new(0);
+/// the SIGRT the process got, and the si_code, si_fd and si_band of the last one
+static SIGRTS: AtomicUsize = AtomicUsize::new(0);
+static RT_CODE: AtomicI32 = AtomicI32::new(0);
+static RT_FD: AtomicI32 = AtomicI32::new(-1);
+static RT_BAND: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn on_sigio(_signo: i32) {
+    SIGIOS.fetch_add(1, Ordering::Relaxed);
+}
+
+extern "C" fn on_sigrt(_signo: i32, info: *const SigInfo, _ucontext: usize) {
+    let info = unsafe { *info };
+    RT_CODE.store(info.code, Ordering::Relaxed);
+    RT_FD.store(info.fd(), Ordering::Relaxed);
+    RT_BAND.store(info.band() as usize, Ordering::Relaxed);
+    SIGRTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// the signal count `count` reaches `want` within WAIT_MS
+fn wait_for(count: &AtomicUsize, want: usize) -> bool {
+    for _ in 0..WAIT_MS / 10 {
+        if count.load(Ordering::Relaxed) >= want {
+            return true;
+        }
+        sleep(10);
+    }
+    count.load(Ordering::Relaxed) >= want
+}
+
+/// signal driven io on for `fd`, owned by the caller
+fn own_async(fd: usize) -> bool {
+    let flags = fcntl(fd, F_GETFL, 0);
+    fcntl(fd, F_SETOWN, getpid() as usize) == 0 && fcntl(fd, F_SETFL, flags as usize | O_ASYNC) == 0
+}
+
+/// the owner of a socket sleeps until the data a peer sends wakes it with SIGIO
+fn socket_case() -> bool {
+    let fd = socket(AF_INET, SOCK_DGRAM, 0);
+    if fd < 0 {
+        println!("test_sigio: no socket, the network is down");
+        return false;
+    }
+    let fd = fd as usize;
+    let sa = SockaddrIn::new(TEST_ADDR.to_be(), PORT.to_be());
+    let len = size_of::<SockaddrIn>() as u32;
+    let mut ok = check(bind(fd, &sa, len) == 0, "bind the socket");
+    ok &= check(own_async(fd), "O_ASYNC on the socket");
+    ok &= check(fcntl(fd, F_GETOWN, 0) == getpid(), "F_GETOWN is the owner");
+
+    let before = SIGIOS.load(Ordering::Relaxed);
+    let pid = fork();
+    if pid == 0 {
+        // the owner is asleep by then
+        sleep(200);
+        let peer = socket(AF_INET, SOCK_DGRAM, 0) as usize;
+        let sent = sendto(peer, b"wake up", 7, 0, &sa, len);
+        exit(if sent == 7 { 0 } else { 1 });
+    }
+    // pause: a ppoll of no files ends only by a signal or the timeout
+    let paused = ppoll(&mut [], Some(WAIT_MS));
+    ok &= check(paused == EINTR, "a signal ends the pause");
+    ok &= check(SIGIOS.load(Ordering::Relaxed) == before + 1, "the data of the peer sends one SIGIO");
+    let mut buf = [0u8; 16];
+    ok &= check(read(fd, &mut buf) == 7, "read the data");
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    ok &= check(status == 0, "the peer sends");
+    close(fd);
+    ok
+}
+
+/// a pipe signals when it turns readable, the writes into a pipe with data do not
+fn pipe_case() -> bool {
+    let mut fds = [0usize; 2];
+    if pipe(&mut fds) != 0 {
+        return check(false, "pipe");
+    }
+    let (rd, wr) = (fds[0], fds[1]);
+    let mut ok = check(own_async(rd), "O_ASYNC on the read end");
+    let before = SIGIOS.load(Ordering::Relaxed);
+    for _ in 0..3 {
+        write(wr, b"abc", 3);
+    }
+    ok &= check(wait_for(&SIGIOS, before + 1), "the first write sends SIGIO");
+    sleep(50);
+    ok &= check(SIGIOS.load(Ordering::Relaxed) == before + 1, "the writes into a readable pipe send none");
+    let mut buf = [0u8; 16];
+    ok &= check(read(rd, &mut buf) == 9, "drain the pipe");
+    write(wr, b"abc", 3);
+    ok &= check(wait_for(&SIGIOS, before + 2), "the drained pipe signals again");
+    read(rd, &mut buf);
+
+    // F_SETSIG: the real time signal with the fd and the band
+    ok &= check(fcntl(rd, F_SETSIG, 65) == EINVAL, "F_SETSIG of no signal is EINVAL");
+    ok &= check(fcntl(rd, F_SETSIG, SIGRT as usize) == 0, "F_SETSIG");
+    ok &= check(fcntl(rd, F_GETSIG, 0) == SIGRT as isize, "F_GETSIG");
+    let sigios = SIGIOS.load(Ordering::Relaxed);
+    write(wr, b"abc", 3);
+    ok &= check(wait_for(&SIGRTS, 1), "the write sends the signal of F_SETSIG");
+    ok &= check(SIGIOS.load(Ordering::Relaxed) == sigios, "no SIGIO beside it");
+    ok &= check(RT_CODE.load(Ordering::Relaxed) == POLL_IN, "si_code is POLL_IN");
+    ok &= check(RT_FD.load(Ordering::Relaxed) == rd as i32, "si_fd is the read end");
+    ok &= check(RT_BAND.load(Ordering::Relaxed) as i64 & POLLIN != 0, "si_band has POLLIN");
+    close(rd);
+    close(wr);
+    ok
+}
+
+/// the owner process exits and the file forgets it
+fn owner_exit_case() -> bool {
+    let mut fds = [0usize; 2];
+    if pipe(&mut fds) != 0 {
+        return check(false, "pipe");
+    }
+    let pid = fork();
+    if pid == 0 {
+        loop {
+            sleep(1000);
+        }
+    }
+    let mut ok = check(fcntl(fds[0], F_SETOWN, pid as usize) == 0, "F_SETOWN of a child");
+    ok &= check(fcntl(fds[0], F_GETOWN, 0) == pid, "F_GETOWN is the child");
+    kill(pid, SIGKILL);
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    ok &= check(fcntl(fds[0], F_GETOWN, 0) == 0, "the exited owner is forgotten");
+    ok &= check(fcntl(fds[0], F_SETOWN, pid as usize) == ESRCH, "F_SETOWN of no process is ESRCH");
+    close(fds[0]);
+    close(fds[1]);
+    ok
+}
+
+/// another user points an O_ASYNC pipe at this root process, its writes signal nothing
+fn other_user_case() -> bool {
+    let root_pid = getpid() as usize;
+    let before = SIGIOS.load(Ordering::Relaxed);
+    let pid = fork();
+    if pid == 0 {
+        let mut fds = [0usize; 2];
+        if setuid(1000) != 0 || pipe(&mut fds) != 0 {
+            exit(1);
+        }
+        let flags = fcntl(fds[0], F_GETFL, 0);
+        if fcntl(fds[0], F_SETOWN, root_pid) != 0 || fcntl(fds[0], F_SETFL, flags as usize | O_ASYNC) != 0 {
+            exit(1);
+        }
+        exit(if write(fds[1], b"abc", 3) == 3 { 0 } else { 1 });
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    let mut ok = check(status == 0, "O_ASYNC pipe of another user");
+    sleep(50);
+    ok &= check(SIGIOS.load(Ordering::Relaxed) == before, "another user may not send SIGIO to root");
+    ok
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    sigaction_flags(SIGIO, on_sigio as usize, 0);
+    sigaction_flags(SIGRT, on_sigrt as usize, SA_SIGINFO);
+
+    let mut ok = pipe_case();
+    ok &= owner_exit_case();
+    ok &= other_user_case();
+    ok &= socket_case();
+
+    if ok {
+        println!("test_sigio: passed");
+        0
+    } else {
+        -1
+    }
+}
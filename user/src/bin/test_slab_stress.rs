@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{exit, fork, get_time_ms, mmap, munmap, waitpid, MmapFlags, MmapProt};
+
+#[macro_use]
+extern crate user_lib;
+
+const WORKERS: usize = 8;
+const PAGES: usize = 16;
+const PAGE_SIZE: usize = 4096;
+const DEFAULT_SECS: usize = 120;
+
+/// map fresh pages, share them with a forked child which writes its own copy,
+/// check ours stayed intact and unmap them again. Every round allocates and
+/// frees frames and the slab objects tracking them on whichever hart runs us
+fn round(seed: usize) -> bool {
+    let len = PAGES * PAGE_SIZE;
+    let addr = mmap(
+        0,
+        len,
+        MmapProt::PROT_READ | MmapProt::PROT_WRITE,
+        MmapFlags::MAP_PRIVATE | MmapFlags::MAP_ANONYMOUS,
+        0,
+        0,
+    );
+    if addr < 0 {
+        println!("test_slab_stress: mmap failed: {}", addr);
+        return false;
+    }
+    let words = unsafe { core::slice::from_raw_parts_mut(addr as *mut usize, len / size_of::<usize>()) };
+    for (i, word) in words.iter_mut().enumerate() {
+        *word = seed ^ i;
+    }
+    let pid = fork();
+    if pid == 0 {
+        for word in words.iter_mut().step_by(PAGE_SIZE / size_of::<usize>()) {
+            *word = !*word;
+        }
+        exit(0);
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    let ok = status == 0 && words.iter().enumerate().all(|(i, &word)| word == seed ^ i);
+    munmap(addr as usize, len);
+    ok
+}
+
+fn worker(id: usize, deadline: isize) -> i32 {
+    let mut n = 0;
+    while get_time_ms() < deadline {
+        if !round(id << 32 | n) {
+            println!("test_slab_stress: worker {} failed in round {}", id, n);
+            return -1;
+        }
+        n += 1;
+    }
+    0
+}
+
+/// many processes allocating and freeing shared frames on all harts at once,
+/// `test_slab_stress [seconds]`
+#[no_mangle]
+pub fn main(args: &[&str]) -> i32 {
+    let secs = args.get(1).and_then(|s| s.parse().ok()).unwrap_or(DEFAULT_SECS);
+    let deadline = get_time_ms() + (secs * 1000) as isize;
+    let mut pids = [0; WORKERS];
+    for (id, pid) in pids.iter_mut().enumerate() {
+        let ret = fork();
+        if ret == 0 {
+            exit(worker(id, deadline));
+        }
+        *pid = ret as usize;
+    }
+    let mut ok = true;
+    for pid in pids {
+        let mut status = 0;
+        waitpid(pid, &mut status);
+        ok &= status == 0;
+    }
+    if ok {
+        println!("test_slab_stress passed!");
+        0
+    } else {
+        -1
+    }
+}
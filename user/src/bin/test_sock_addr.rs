@@ -0,0 +1,108 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    accept, bind, check, close, connect, exit, fork, getpeername, getsockname, listen, mmap, munmap, recvfrom, sendto,
+    socket, waitpid, MmapFlags, MmapProt, SockaddrIn, EFAULT,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const AF_INET: i32 = 2;
+const SOCK_STREAM: i32 = 1;
+const IPPROTO_TCP: i32 = 6;
+
+const TEST_PORT: u16 = 4447;
+const TEST_ADDR: u32 = 0x7f000001; // 127.0.0.1
+const TEST_DATA: &[u8] = b"helloworld";
+const HUGE_LEN: usize = 2 << 30;
+const BUF_LEN: usize = 64 * 1024;
+
+/// receive exactly `buf.len()` bytes, asking for `len` bytes each time
+fn recv_exact(fd: usize, buf: &mut [u8], len: usize) -> bool {
+    let mut got = 0;
+    while got < buf.len() {
+        let want = len.min(buf.len() - got);
+        let n = recvfrom(fd, &mut buf[got..], want, 0, core::ptr::null_mut(), core::ptr::null_mut());
+        if n <= 0 {
+            return false;
+        }
+        got += n as usize;
+    }
+    true
+}
+
+fn server(listen_fd: usize) -> bool {
+    let fd = accept(listen_fd, core::ptr::null_mut(), core::ptr::null_mut());
+    if !check(fd >= 0, "accept with NULL addr") {
+        return false;
+    }
+    let fd = fd as usize;
+    let mut ok = true;
+
+    // NULL addr on a connected TCP socket
+    let mut head = [0u8; 5];
+    ok &= check(recv_exact(fd, &mut head, head.len()) && head == TEST_DATA[..5], "recvfrom with NULL addr");
+
+    // a 2 GiB length is capped instead of allocating or faulting
+    let buf = mmap(0, BUF_LEN, MmapProt::PROT_READ | MmapProt::PROT_WRITE, MmapFlags::MAP_PRIVATE | MmapFlags::MAP_ANONYMOUS, 0, 0);
+    let tail = unsafe { core::slice::from_raw_parts_mut(buf as *mut u8, BUF_LEN) };
+    let n = recvfrom(fd, tail, HUGE_LEN, 0, core::ptr::null_mut(), core::ptr::null_mut());
+    ok &= check(n > 0 && tail[..n as usize] == TEST_DATA[5..5 + n as usize], "recvfrom with 2 GiB len");
+    munmap(buf as usize, BUF_LEN);
+
+    // a short addr_len truncates the address and reports the real size
+    let mut peer = SockaddrIn::new(0xdeadbeef, 0);
+    let mut peer_len = 4u32;
+    ok &= check(getpeername(fd, &mut peer, &mut peer_len) == 0, "getpeername");
+    ok &= check(peer_len as usize == size_of::<SockaddrIn>(), "getpeername addr_len write back");
+    ok &= check(peer.sin_family == AF_INET as u16 && peer.sin_addr == 0xdeadbeef, "getpeername truncation");
+
+    // bad pointers return EFAULT instead of faulting in the kernel
+    let mut len = size_of::<SockaddrIn>() as u32;
+    ok &= check(getsockname(fd, 1 as *mut SockaddrIn, &mut len) == EFAULT, "getsockname with a bad addr");
+    ok &= check(recvfrom(fd, tail, 16, 0, core::ptr::null_mut(), core::ptr::null_mut()) == EFAULT, "recvfrom into an unmapped buffer");
+
+    close(fd);
+    ok
+}
+
+/// NULL, short, huge and bad user buffers on the socket address syscalls
+#[no_mangle]
+pub fn main() -> i32 {
+    let listen_fd = socket(AF_INET, SOCK_STREAM, IPPROTO_TCP);
+    let addr = SockaddrIn::new(TEST_ADDR.to_be(), TEST_PORT.to_be());
+    if listen_fd < 0
+        || bind(listen_fd as usize, &addr, size_of::<SockaddrIn>() as u32) < 0
+        || listen(listen_fd as usize, 1) < 0
+    {
+        println!("test_sock_addr: socket/bind/listen failed");
+        return -1;
+    }
+    let pid = fork();
+    if pid == 0 {
+        close(listen_fd as usize);
+        let fd = socket(AF_INET, SOCK_STREAM, IPPROTO_TCP);
+        if fd < 0 || connect(fd as usize, &addr, size_of::<SockaddrIn>() as u32) < 0 {
+            exit(-1);
+        }
+        sendto(fd as usize, TEST_DATA, TEST_DATA.len(), 0, core::ptr::null(), 0);
+        // keep the connection until the server is done with it
+        let mut byte = [0u8; 1];
+        recvfrom(fd as usize, &mut byte, 1, 0, core::ptr::null_mut(), core::ptr::null_mut());
+        close(fd as usize);
+        exit(0);
+    }
+    let mut ok = server(listen_fd as usize);
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    ok &= check(status == 0, "client");
+    close(listen_fd as usize);
+    if ok {
+        println!("test_sock_addr passed!");
+        0
+    } else {
+        -1
+    }
+}
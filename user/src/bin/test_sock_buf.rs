@@ -0,0 +1,245 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    accept, bind, check, close, connect, exit, fcntl, fork, get_time_ms, getsockopt, listen, nanosleep, recvfrom,
+    sendto, setsockopt, socket, waitpid, SockaddrIn, EAGAIN, EISCONN, F_SETFL, O_NONBLOCK,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const AF_INET: i32 = 2;
+const SOCK_STREAM: i32 = 1;
+const SOCK_DGRAM: i32 = 2;
+const IPPROTO_TCP: i32 = 6;
+const SOL_SOCKET: i32 = 1;
+const SO_SNDBUF: i32 = 7;
+const SO_RCVBUF: i32 = 8;
+
+const TEST_ADDR: u32 = 0x7f000001; // 127.0.0.1
+const PORT: u16 = 4460;
+/// the default buffers are 64 KiB, reported doubled
+const DEFAULT_REPORTED: i32 = 128 * 1024;
+const SMALL_SNDBUF: i32 = 8192;
+const SMALL_RCVBUF: i32 = 4096;
+/// how long the reader waits before it drains a blocked sender
+const DRAIN_DELAY_MS: usize = 300;
+/// bytes sent per throughput run
+const BULK_LEN: usize = 4 * 1024 * 1024;
+const PING_ROUNDS: usize = 200;
+
+fn addr(port: u16) -> SockaddrIn {
+    SockaddrIn::new(TEST_ADDR.to_be(), port.to_be())
+}
+
+fn get_buf(fd: usize, opt: i32) -> i32 {
+    let mut val = -1i32;
+    getsockopt(fd, SOL_SOCKET, opt, &mut val);
+    val
+}
+
+fn send(fd: usize, data: &[u8]) -> isize {
+    sendto(fd, data, data.len(), 0, core::ptr::null(), 0)
+}
+
+fn recv(fd: usize, buf: &mut [u8]) -> isize {
+    recvfrom(fd, buf, buf.len(), 0, core::ptr::null_mut(), core::ptr::null_mut())
+}
+
+fn send_all(fd: usize, mut data: &[u8]) -> bool {
+    while !data.is_empty() {
+        let n = send(fd, data);
+        if n <= 0 {
+            return false;
+        }
+        data = &data[n as usize..];
+    }
+    true
+}
+
+/// read exactly `len` bytes
+fn recv_exact(fd: usize, mut len: usize) -> bool {
+    let mut buf = [0u8; 4096];
+    while len > 0 {
+        let n = recv(fd, &mut buf[..len.min(4096)]);
+        if n <= 0 {
+            return false;
+        }
+        len -= n as usize;
+    }
+    true
+}
+
+/// a connected pair on `port`, both ends with buffers of `len` when given
+fn tcp_pair(port: u16, len: Option<i32>) -> Option<(usize, usize)> {
+    let listener = socket(AF_INET, SOCK_STREAM, IPPROTO_TCP);
+    let client = socket(AF_INET, SOCK_STREAM, IPPROTO_TCP);
+    if listener < 0 || client < 0 {
+        return None;
+    }
+    let (listener, client) = (listener as usize, client as usize);
+    if let Some(len) = len {
+        for fd in [listener, client] {
+            setsockopt(fd, SOL_SOCKET, SO_SNDBUF, &len);
+            setsockopt(fd, SOL_SOCKET, SO_RCVBUF, &len);
+        }
+    }
+    let sa = addr(port);
+    let len = size_of::<SockaddrIn>() as u32;
+    if bind(listener, &sa, len) < 0 || listen(listener, 4) < 0 || connect(client, &sa, len) < 0 {
+        return None;
+    }
+    let server = accept(listener, core::ptr::null_mut(), core::ptr::null_mut());
+    close(listener);
+    (server >= 0).then_some((server as usize, client))
+}
+
+/// the KiB/s of BULK_LEN bytes pushed through a pair with buffers of `len`
+fn throughput(port: u16, len: i32) -> Option<usize> {
+    let (server, client) = tcp_pair(port, Some(len))?;
+    let start = get_time_ms();
+    let pid = fork();
+    if pid == 0 {
+        close(server);
+        let chunk = [0x5au8; 16384];
+        let mut left = BULK_LEN;
+        while left > 0 {
+            let n = left.min(chunk.len());
+            if !send_all(client, &chunk[..n]) {
+                exit(1);
+            }
+            left -= n;
+        }
+        exit(0);
+    }
+    close(client);
+    let ok = recv_exact(server, BULK_LEN);
+    let elapsed = (get_time_ms() - start).max(1) as usize;
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    close(server);
+    (ok && status == 0).then_some(BULK_LEN / 1024 * 1000 / elapsed)
+}
+
+/// the mean round trip in microseconds of one byte on buffers of `len`
+fn ping_pong(port: u16, len: i32) -> Option<usize> {
+    let (server, client) = tcp_pair(port, Some(len))?;
+    let pid = fork();
+    if pid == 0 {
+        close(client);
+        let mut byte = [0u8; 1];
+        for _ in 0..PING_ROUNDS {
+            if recv(server, &mut byte) != 1 || send(server, &byte) != 1 {
+                exit(1);
+            }
+        }
+        exit(0);
+    }
+    close(server);
+    let start = get_time_ms();
+    let mut ok = true;
+    let mut byte = [7u8; 1];
+    for _ in 0..PING_ROUNDS {
+        ok &= send(client, &byte) == 1 && recv(client, &mut byte) == 1;
+    }
+    let elapsed = (get_time_ms() - start) as usize;
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    close(client);
+    (ok && status == 0).then_some(elapsed * 1000 / PING_ROUNDS)
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    // sizes read back doubled and clamped, for either kind of socket
+    for (kind, name) in [(SOCK_STREAM, "tcp"), (SOCK_DGRAM, "udp")] {
+        let fd = socket(AF_INET, kind, 0) as usize;
+        ok &= check(get_buf(fd, SO_SNDBUF) == DEFAULT_REPORTED, name);
+        ok &= check(get_buf(fd, SO_RCVBUF) == DEFAULT_REPORTED, name);
+        setsockopt(fd, SOL_SOCKET, SO_SNDBUF, &SMALL_SNDBUF);
+        ok &= check(get_buf(fd, SO_SNDBUF) == 2 * SMALL_SNDBUF, "SO_SNDBUF reads back doubled");
+        setsockopt(fd, SOL_SOCKET, SO_RCVBUF, &1i32);
+        ok &= check(get_buf(fd, SO_RCVBUF) == 4096, "a tiny SO_RCVBUF is raised to the minimum");
+        setsockopt(fd, SOL_SOCKET, SO_RCVBUF, &i32::MAX);
+        ok &= check(get_buf(fd, SO_RCVBUF) == 2 * 1024 * 1024, "a huge SO_RCVBUF is cut to the maximum");
+        close(fd);
+    }
+
+    // a sender which nobody reads fills its own buffer and the window of the peer, then stops
+    let listener = socket(AF_INET, SOCK_STREAM, IPPROTO_TCP) as usize;
+    let client = socket(AF_INET, SOCK_STREAM, IPPROTO_TCP) as usize;
+    setsockopt(listener, SOL_SOCKET, SO_RCVBUF, &SMALL_RCVBUF);
+    setsockopt(client, SOL_SOCKET, SO_SNDBUF, &SMALL_SNDBUF);
+    let sa = addr(PORT);
+    let sa_len = size_of::<SockaddrIn>() as u32;
+    ok &= check(bind(listener, &sa, sa_len) == 0 && listen(listener, 4) == 0, "listen");
+    ok &= check(connect(client, &sa, sa_len) == 0, "connect");
+    let server = accept(listener, core::ptr::null_mut(), core::ptr::null_mut());
+    ok &= check(server >= 0, "accept");
+    let server = server as usize;
+    ok &= check(get_buf(server, SO_RCVBUF) == 2 * SMALL_RCVBUF, "an accepted socket has the buffers of the listener");
+
+    fcntl(client, F_SETFL, O_NONBLOCK);
+    let data = [1u8; 1024];
+    let mut queued = 0;
+    let last = loop {
+        let n = send(client, &data);
+        if n <= 0 {
+            break n;
+        }
+        queued += n as usize;
+        if queued > 1024 * 1024 {
+            break 0;
+        }
+    };
+    ok &= check(last == EAGAIN, "a full nonblocking send is EAGAIN");
+    let (tx, rx) = (SMALL_SNDBUF as usize, SMALL_RCVBUF as usize);
+    ok &= check(queued >= tx && queued <= tx + rx, "the bytes queued fit the two buffers");
+    println!("test_sock_buf: {} bytes queued with a {} byte send and {} byte receive buffer", queued, tx, rx);
+
+    // a blocking send waits for the reader and goes on as soon as it frees room
+    fcntl(client, F_SETFL, 0);
+    let pid = fork();
+    if pid == 0 {
+        nanosleep(DRAIN_DELAY_MS);
+        exit(if recv_exact(server, queued + data.len()) { 0 } else { 1 });
+    }
+    let start = get_time_ms();
+    ok &= check(send_all(client, &data), "a blocked send");
+    let waited = (get_time_ms() - start) as usize;
+    ok &= check(waited + 50 >= DRAIN_DELAY_MS, "the send blocked until the reader drained");
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    ok &= check(status == 0, "the reader got every byte");
+
+    // a connected stream only takes the address of its peer
+    let other = addr(PORT + 1);
+    ok &= check(sendto(client, &data, 1, 0, &other, sa_len) == EISCONN, "sendto another address is EISCONN");
+    ok &= check(sendto(client, &data, 1, 0, &sa, sa_len) == 1, "sendto the peer");
+    ok &= check(recv_exact(server, 1), "recv what was sent to the peer");
+    close(client);
+    close(server);
+    close(listener);
+
+    // throughput grows with the buffers, the latency of small ones stays low
+    for (i, len) in [4096, 16384, 65536, 262144].into_iter().enumerate() {
+        match throughput(PORT + 10 + i as u16, len) {
+            Some(rate) => println!("test_sock_buf: {} byte buffers: {} KiB/s", len, rate),
+            None => ok &= check(false, "a bulk transfer"),
+        }
+    }
+    match ping_pong(PORT + 20, 4096) {
+        Some(rtt) => println!("test_sock_buf: round trip with 4096 byte buffers: {} us", rtt),
+        None => ok &= check(false, "a ping pong"),
+    }
+
+    if ok {
+        println!("test_sock_buf: passed");
+        0
+    } else {
+        -1
+    }
+}
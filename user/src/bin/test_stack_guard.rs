@@ -0,0 +1,38 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{fork, wait};
+
+#[macro_use]
+extern crate user_lib;
+
+const SIGSEGV: i32 = 11;
+
+#[inline(never)]
+fn recurse(depth: usize) -> usize {
+    // keep a big frame alive so the stack is consumed quickly
+    let buf = [depth as u8; 1024];
+    let ret = recurse(depth + 1);
+    ret + unsafe { core::ptr::read_volatile(&buf[depth % 1024]) } as usize
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        recurse(0);
+        println!("child: recursion bomb returned, stack guard missed");
+        return 0;
+    }
+    let mut status: i32 = 0;
+    if wait(&mut status) < 0 {
+        return -1;
+    }
+    if status & 0x7f == SIGSEGV {
+        println!("test_stack_guard passed: child died with SIGSEGV");
+        0
+    } else {
+        println!("test_stack_guard failed: child status {:#x}", status);
+        -1
+    }
+}
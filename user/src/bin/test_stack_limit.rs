@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+
+use core::hint::black_box;
+
+use user_lib::{check, execve, exit, fork, getrlimit, setrlimit, waitpid, RLimit, RLIMIT_STACK, RLIM_INFINITY};
+
+#[macro_use]
+extern crate user_lib;
+
+/// the stack the test is booted with, `BOOTARGS="stack-size=8M"`
+const BOOTED_STACK: usize = 8 << 20;
+/// how deep the recursion goes, well past the 64 KiB stacks of old
+const DEEP: usize = 6 << 20;
+/// the stack of one level
+const FRAME: usize = 4096;
+const SIGSEGV: i32 = 11;
+
+/// use `FRAME` bytes of stack on each of `depth` levels, the sum keeps them from being folded
+#[inline(never)]
+fn recurse(depth: usize) -> usize {
+    let mut frame = [0u8; FRAME];
+    black_box(&mut frame)[depth % FRAME] = depth as u8;
+    if depth == 0 {
+        return 0;
+    }
+    black_box(frame[depth % FRAME] as usize) + recurse(depth - 1)
+}
+
+/// run the recursion in a child execed with RLIMIT_STACK at `limit`, return its wait status
+fn deep_child(path: &str, limit: usize) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        setrlimit(RLIMIT_STACK, &RLimit { rlim_cur: limit, rlim_max: RLIM_INFINITY });
+        execve(path, &[path, "recurse"], &[]);
+        exit(2);
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    status
+}
+
+#[no_mangle]
+pub fn main(args: &[&str]) -> i32 {
+    if args.get(1) == Some(&"recurse") {
+        black_box(recurse(DEEP / FRAME));
+        return 0;
+    }
+    let mut ok = true;
+    let mut limit = RLimit { rlim_cur: 0, rlim_max: 0 };
+    ok &= check(getrlimit(RLIMIT_STACK, &mut limit) == 0, "getrlimit");
+    println!("test_stack_limit: RLIMIT_STACK {:#x}", limit.rlim_cur);
+    ok &= check(limit.rlim_cur >= BOOTED_STACK, "the stack set at boot");
+
+    let path = args[0];
+    let status = deep_child(path, BOOTED_STACK);
+    ok &= check(status == 0, "a 6 MiB recursion under an 8 MiB limit");
+    let status = deep_child(path, 1 << 20);
+    ok &= check(status & 0x7f == SIGSEGV, "a 6 MiB recursion under a 1 MiB limit overflows");
+
+    if ok {
+        println!("test_stack_limit: passed");
+        0
+    } else {
+        -1
+    }
+}
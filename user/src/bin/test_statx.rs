@@ -0,0 +1,97 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, fstat, open, pipe, statx, statx_raw, unlink, write, OpenFlags, Stat, Statx, AT_EMPTY_PATH, AT_FDCWD,
+    AT_SYMLINK_NOFOLLOW, EFAULT, EINVAL, ENOENT, STATX_BASIC_STATS, STATX_MODE, STATX_TYPE,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const FILE: &str = "/test_statx_file\0";
+
+fn major(dev: u64) -> u32 {
+    ((dev >> 8) & 0xfff) as u32
+}
+
+fn minor(dev: u64) -> u32 {
+    ((dev & 0xff) | ((dev >> 12) & 0xfff00)) as u32
+}
+
+/// the fields the libc-test statx case compares against fstat
+fn same_as_stat(stx: &Statx, st: &Stat) -> bool {
+    let mut ok = true;
+    ok &= check(stx.stx_ino == st.st_ino, "stx_ino");
+    ok &= check(stx.stx_mode as u32 == st.st_mode, "stx_mode");
+    ok &= check(stx.stx_nlink == st.st_nlink, "stx_nlink");
+    ok &= check(stx.stx_uid == st.st_uid && stx.stx_gid == st.st_gid, "stx_uid/stx_gid");
+    ok &= check(stx.stx_size as i64 == st.st_size, "stx_size");
+    ok &= check(stx.stx_blksize as i32 == st.st_blksize, "stx_blksize");
+    ok &= check(stx.stx_blocks as i64 == st.st_blocks, "stx_blocks");
+    ok &= check(stx.stx_rdev_major == major(st.st_rdev) && stx.stx_rdev_minor == minor(st.st_rdev), "stx_rdev");
+    ok &= check(stx.stx_dev_major == major(st.st_dev) && stx.stx_dev_minor == minor(st.st_dev), "stx_dev");
+    ok &= check(stx.stx_atime.tv_sec as isize == st.st_atime_sec
+        && stx.stx_atime.tv_nsec as isize == st.st_atime_nsec, "stx_atime");
+    ok &= check(stx.stx_mtime.tv_sec as isize == st.st_mtime_sec
+        && stx.stx_mtime.tv_nsec as isize == st.st_mtime_nsec, "stx_mtime");
+    ok &= check(stx.stx_ctime.tv_sec as isize == st.st_ctime_sec
+        && stx.stx_ctime.tv_nsec as isize == st.st_ctime_nsec, "stx_ctime");
+    ok
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDWR);
+    if fd < 0 {
+        println!("test_statx: can not create {}", FILE);
+        return -1;
+    }
+    write(fd as usize, b"statx", 5);
+
+    let mut st = Stat::default();
+    let mut stx = Statx::default();
+    ok &= check(fstat(fd as usize, &mut st) == 0, "fstat");
+
+    // an empty path with AT_EMPTY_PATH stats the fd itself
+    ok &= check(statx(fd, "\0", AT_EMPTY_PATH, STATX_BASIC_STATS, &mut stx) == 0, "statx AT_EMPTY_PATH");
+    ok &= same_as_stat(&stx, &st);
+    ok &= check(stx.stx_mask & STATX_BASIC_STATS == STATX_BASIC_STATS, "stx_mask has the basic stats");
+
+    // the mask tells what was filled, not what was asked for
+    let mut by_path = Statx::default();
+    ok &= check(statx(AT_FDCWD, FILE, 0, STATX_TYPE, &mut by_path) == 0, "statx by path");
+    ok &= check(by_path.stx_mask & STATX_MODE != 0 && by_path.stx_ino == stx.stx_ino, "stx_mask beyond the request");
+    ok &= check(statx(AT_FDCWD, FILE, AT_SYMLINK_NOFOLLOW, STATX_BASIC_STATS, &mut by_path) == 0, "statx AT_SYMLINK_NOFOLLOW");
+
+    // without AT_EMPTY_PATH an empty path is an error
+    ok &= check(statx(fd, "\0", 0, STATX_BASIC_STATS, &mut stx) == ENOENT, "empty path without AT_EMPTY_PATH");
+    // a path which can not be read is no empty path
+    ok &= check(
+        statx_raw(fd, core::ptr::null(), AT_EMPTY_PATH, STATX_BASIC_STATS, &mut stx) == EFAULT,
+        "a NULL path with AT_EMPTY_PATH is EFAULT",
+    );
+    ok &= check(statx(AT_FDCWD, "/no/such/file\0", 0, STATX_BASIC_STATS, &mut stx) == ENOENT, "nonexistent path");
+    ok &= check(statx(AT_FDCWD, FILE, 0x6000, STATX_BASIC_STATS, &mut stx) == EINVAL, "both sync types");
+    ok &= check(statx(AT_FDCWD, FILE, 0, 0x8000_0000, &mut stx) == EINVAL, "reserved mask bit");
+
+    // a pipe has no path, but can still be stat'ed through its fd
+    let mut fds = [0usize; 2];
+    pipe(&mut fds);
+    ok &= check(statx(fds[0] as isize, "\0", AT_EMPTY_PATH, STATX_BASIC_STATS, &mut stx) == 0, "statx a pipe");
+    ok &= check(stx.stx_mode & 0o170000 == 0o010000, "pipe is a fifo");
+    close(fds[0]);
+    close(fds[1]);
+
+    close(fd as usize);
+    unlink(FILE);
+
+    if ok {
+        println!("test_statx passed!");
+        0
+    } else {
+        -1
+    }
+}
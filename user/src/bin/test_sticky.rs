@@ -0,0 +1,115 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, exit, fchmod, fork, fstatat, mkdir, open, rename, rmdir, setgid, setuid, umask, unlink, waitpid,
+    OpenFlags, Stat, AT_FDCWD, EPERM,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const STICKY: &str = "/test_sticky\0";
+const MINE: &str = "/test_sticky/mine\0";
+const MINE_MOVED: &str = "/test_sticky/mine_moved\0";
+const ROOTS: &str = "/test_sticky/roots\0";
+const SGID: &str = "/test_sticky_sgid\0";
+const SGID_FILE: &str = "/test_sticky_sgid/file\0";
+const SGID_DIR: &str = "/test_sticky_sgid/dir\0";
+const SGID_DEEP: &str = "/test_sticky_sgid/dir/file\0";
+const PLAIN_DIR: &str = "/test_sticky_plain\0";
+const PLAIN: &str = "/test_sticky_plain/file\0";
+const S_ISGID: u32 = 0o2000;
+const GROUP: u32 = 100;
+
+fn stat(path: &str) -> Stat {
+    let mut stat = Stat::default();
+    fstatat(AT_FDCWD, path, &mut stat, 0);
+    stat
+}
+
+fn create(path: &str) -> bool {
+    let fd = open(path, OpenFlags::CREATE | OpenFlags::WRONLY);
+    if fd >= 0 {
+        close(fd as usize);
+    }
+    fd >= 0
+}
+
+/// mkdir `path` and chmod it to `mode`
+fn mkdir_mode(path: &str, mode: u32) -> bool {
+    if mkdir(path) != 0 {
+        return false;
+    }
+    let fd = open(path, OpenFlags::RDONLY | OpenFlags::DIRECTORY);
+    let ok = fd >= 0 && fchmod(fd as usize, mode) == 0;
+    close(fd as usize);
+    ok
+}
+
+/// run `f` in a child with group `gid` and user `uid`, whether it succeeded
+fn as_user(uid: u32, gid: u32, f: fn() -> bool) -> bool {
+    let pid = fork();
+    if pid == 0 {
+        let ok = setgid(gid) == 0 && setuid(uid) == 0 && f();
+        exit(if ok { 0 } else { 1 });
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    status == 0
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    // the umask clears the bits it holds from the mode of new files
+    ok &= check(mkdir_mode(PLAIN_DIR, 0o777), "make a plain directory");
+    let old = umask(0o077);
+    ok &= check(umask(old as u32) == 0o077, "umask returns the old mask");
+    umask(0o077);
+    ok &= check(create(PLAIN) && stat(PLAIN).st_mode & 0o777 == 0o600, "a file created under umask 077");
+    umask(old as u32);
+    unlink(PLAIN);
+
+    // a sticky world writable directory, like /tmp
+    ok &= check(mkdir_mode(STICKY, 0o1777), "make the sticky directory");
+    ok &= check(create(ROOTS), "create a file of root");
+    ok &= check(as_user(1000, GROUP, || create(MINE)), "create a file as 1000");
+    ok &= check(as_user(1001, GROUP, || {
+        check(unlink(MINE) == EPERM, "unlink the file of another user")
+            & check(rename(MINE, MINE_MOVED) == EPERM, "rename the file of another user")
+    }), "as 1001");
+    ok &= check(as_user(1000, GROUP, || {
+        check(unlink(ROOTS) == EPERM, "unlink a file of root")
+            & check(rename(MINE, MINE_MOVED) == 0, "rename an own file")
+            & check(unlink(MINE_MOVED) == 0, "unlink an own file")
+    }), "as 1000");
+    ok &= check(unlink(ROOTS) == 0, "root unlinks its file");
+    rmdir(STICKY);
+
+    // a set-group-id directory hands its group down, and the bit to subdirectories
+    ok &= check(mkdir_mode(SGID, 0o2777), "make the set-group-id directory");
+    ok &= check(as_user(1000, GROUP, || {
+        let root_group = |path: &str| stat(path).st_gid == 0;
+        check(create(SGID_FILE) && root_group(SGID_FILE), "the group of a new file")
+            & check(stat(SGID_FILE).st_mode & S_ISGID == 0, "a new file is not set-group-id")
+            & check(mkdir(SGID_DIR) == 0 && root_group(SGID_DIR), "the group of a new directory")
+            & check(stat(SGID_DIR).st_mode & S_ISGID != 0, "a new directory is set-group-id")
+            & check(create(SGID_DEEP) && root_group(SGID_DEEP), "the group two levels down")
+            & check(create(PLAIN) && stat(PLAIN).st_gid == GROUP, "the group outside a set-group-id directory")
+    }), "as 1000 in the set-group-id directory");
+    unlink(SGID_DEEP);
+    rmdir(SGID_DIR);
+    unlink(SGID_FILE);
+    rmdir(SGID);
+    unlink(PLAIN);
+    rmdir(PLAIN_DIR);
+
+    if ok {
+        println!("test_sticky: passed");
+        0
+    } else {
+        -1
+    }
+}
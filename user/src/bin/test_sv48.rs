@@ -0,0 +1,77 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{check, mmap, munmap, MmapFlags, MmapProt};
+
+#[macro_use]
+extern crate user_lib;
+
+const PAGE_SIZE: usize = 4096;
+const PAGES: usize = 4;
+/// the end of the user address space under sv39
+const SV39_END: usize = 0x40_0000_0000;
+/// fixed addresses only sv48 can map, the first one is the first 512 GiB slot past sv39
+const FIXED: [usize; 2] = [0x80_0000_0000, 0x7f00_0000_0000];
+const HINT: usize = 0x100_0000_0000;
+
+/// write every page of the mapping at `addr` and read it back
+fn touch(addr: usize) -> bool {
+    let mapped = unsafe { core::slice::from_raw_parts_mut(addr as *mut usize, PAGES * PAGE_SIZE / 8) };
+    for (i, word) in mapped.iter_mut().enumerate().step_by(PAGE_SIZE / 8) {
+        *word = addr + i;
+    }
+    mapped.iter().enumerate().step_by(PAGE_SIZE / 8).all(|(i, &word)| word == addr + i)
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    let rw = MmapProt::PROT_READ | MmapProt::PROT_WRITE;
+    let anon = MmapFlags::MAP_PRIVATE | MmapFlags::MAP_ANONYMOUS;
+    let len = PAGES * PAGE_SIZE;
+
+    // the mmap areas sit under the user stack at the end of the address space
+    let anywhere = mmap(0, len, rw, anon, 0, 0);
+    if anywhere < 0 {
+        println!("test_sv48: can not map anywhere");
+        return -1;
+    }
+    ok &= check(touch(anywhere as usize), "data of the mapping anywhere");
+    munmap(anywhere as usize, len);
+
+    if (anywhere as usize) < SV39_END {
+        // sv39: nothing past 256 GiB can be mapped, nor is a hint past it taken
+        ok &= check(mmap(FIXED[0], len, rw, anon | MmapFlags::MAP_FIXED, 0, 0) < 0, "fixed mapping past the sv39 end");
+        let hinted = mmap(HINT, len, rw, anon, 0, 0);
+        ok &= check(hinted > 0 && (hinted as usize) < SV39_END, "hint past the sv39 end");
+        if hinted > 0 {
+            munmap(hinted as usize, len);
+        }
+        if ok {
+            println!("test_sv48: sv39 paging, passed");
+            return 0;
+        }
+        return -1;
+    }
+
+    for &addr in FIXED.iter() {
+        ok &= check(mmap(addr, len, rw, anon | MmapFlags::MAP_FIXED, 0, 0) == addr as isize, "fixed mapping past the sv39 end");
+        ok &= check(touch(addr), "data past the sv39 end");
+        ok &= check(munmap(addr, len) == 0, "munmap past the sv39 end");
+    }
+
+    let hinted = mmap(HINT, len, rw, anon, 0, 0);
+    ok &= check(hinted == HINT as isize, "hint past the sv39 end");
+    if hinted > 0 {
+        ok &= check(touch(hinted as usize), "data of the hinted mapping");
+        munmap(hinted as usize, len);
+    }
+
+    if ok {
+        println!("test_sv48: passed");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,136 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    bind, check, close, connect, exit, fcntl, fork, listen, open, pipe, read, sethostname, setuid, socket, uname,
+    uname_field, waitpid, write, OpenFlags, SockaddrIn, ECONNREFUSED, EINVAL, EPERM, F_GETPIPE_SZ, F_SETPIPE_SZ,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const AF_INET: i32 = 2;
+const SOCK_STREAM: i32 = 1;
+const IPPROTO_TCP: i32 = 6;
+
+const TEST_ADDR: u32 = 0x7f000001; // 127.0.0.1
+const PORT: u16 = 4480;
+const SOMAXCONN: &str = "/proc/sys/net/core/somaxconn\0";
+const HOSTNAME: &str = "/proc/sys/kernel/hostname\0";
+const PIPE_MAX_SIZE: &str = "/proc/sys/fs/pipe-max-size\0";
+
+/// the content of a sysctl file, empty when it cannot be read
+fn get<'a>(path: &str, buf: &'a mut [u8; 64]) -> &'a [u8] {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd < 0 {
+        return &[];
+    }
+    let len = read(fd as usize, buf).max(0) as usize;
+    close(fd as usize);
+    &buf[..len]
+}
+
+/// write `value` to a sysctl file, the result of the write
+fn set(path: &str, value: &[u8]) -> isize {
+    let fd = open(path, OpenFlags::WRONLY);
+    if fd < 0 {
+        return fd;
+    }
+    let ret = write(fd as usize, value, value.len());
+    close(fd as usize);
+    ret
+}
+
+/// listen on `port` with `backlog`, then connect `tries` times without accepting,
+/// the number of connects before the first refused one
+fn connects_before_refused(port: u16, backlog: i32, tries: usize) -> usize {
+    let sa = SockaddrIn::new(TEST_ADDR.to_be(), port.to_be());
+    let sa_len = size_of::<SockaddrIn>() as u32;
+    let listener = socket(AF_INET, SOCK_STREAM, IPPROTO_TCP) as usize;
+    if bind(listener, &sa, sa_len) < 0 || listen(listener, backlog) < 0 {
+        close(listener);
+        return 0;
+    }
+    let mut clients = [0usize; 16];
+    let mut made = 0;
+    while made < tries {
+        let client = socket(AF_INET, SOCK_STREAM, IPPROTO_TCP) as usize;
+        let ret = connect(client, &sa, sa_len);
+        clients[made] = client;
+        if ret < 0 {
+            check(ret == ECONNREFUSED, "a connect beyond the backlog is ECONNREFUSED");
+            close(client);
+            break;
+        }
+        made += 1;
+    }
+    for &client in &clients[..made] {
+        close(client);
+    }
+    close(listener);
+    made
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    let mut buf = [0u8; 64];
+
+    // somaxconn caps the backlog of listen
+    ok &= check(get(SOMAXCONN, &mut buf) == b"4096\n", "read somaxconn");
+    ok &= check(set(SOMAXCONN, b"2\n") == 2, "write somaxconn");
+    ok &= check(get(SOMAXCONN, &mut buf) == b"2\n", "read back somaxconn");
+    ok &= check(set(SOMAXCONN, b"many") == EINVAL, "a value which is no number is EINVAL");
+    ok &= check(set(SOMAXCONN, b"0") == EINVAL, "a value out of range is EINVAL");
+    ok &= check(get(SOMAXCONN, &mut buf) == b"2\n", "a bad write keeps the value");
+    // a backlog of 2 holds 3 connections, like linux
+    ok &= check(connects_before_refused(PORT, 100, 8) == 3, "the backlog is capped by somaxconn");
+    ok &= check(set(SOMAXCONN, b"4096") == 4, "restore somaxconn");
+    ok &= check(connects_before_refused(PORT + 1, 100, 8) == 8, "every connect fits an uncapped backlog");
+
+    // the hostname is the node name of uname, either way it is set
+    let mut uts = [0u8; 390];
+    ok &= check(set(HOSTNAME, b"chronix\n") == 8, "write hostname");
+    ok &= check(uname(&mut uts) == 0 && uname_field(&uts, 1) == b"chronix", "uname shows the sysctl hostname");
+    ok &= check(sethostname(b"box") == 0, "sethostname");
+    ok &= check(get(HOSTNAME, &mut buf) == b"box\n", "the sysctl shows what sethostname set");
+    ok &= check(sethostname(&[b'x'; 65]) == EINVAL, "a hostname over 64 bytes is EINVAL");
+    sethostname(b"Linux");
+
+    // printk is the console level, then three fixed numbers
+    let printk = get("/proc/sys/kernel/printk\0", &mut buf);
+    ok &= check(printk.split(|&c| c == b'\t').count() == 4, "read printk");
+
+    // the pipe size can grow up to pipe-max-size, unless privileged
+    ok &= check(get(PIPE_MAX_SIZE, &mut buf) == b"1048576\n", "read pipe-max-size");
+    let mut fds = [0usize; 2];
+    ok &= check(pipe(&mut fds) == 0, "pipe");
+    ok &= check(fcntl(fds[1], F_SETPIPE_SZ, 100000) == 131072, "F_SETPIPE_SZ rounds up to a power of two");
+    ok &= check(fcntl(fds[0], F_GETPIPE_SZ, 0) == 131072, "F_GETPIPE_SZ");
+    ok &= check(write(fds[1], &[1u8; 5000], 5000) == 5000, "fill part of the pipe");
+    ok &= check(fcntl(fds[1], F_SETPIPE_SZ, 4096) == -16, "shrinking below the buffered data is EBUSY");
+    ok &= check(set(PIPE_MAX_SIZE, b"65536") == 5, "write pipe-max-size");
+    let pid = fork();
+    if pid == 0 {
+        setuid(1000);
+        let mut ok = true;
+        ok &= check(fcntl(fds[1], F_SETPIPE_SZ, 262144) == EPERM, "an unprivileged pipe beyond the max is EPERM");
+        ok &= check(fcntl(fds[1], F_SETPIPE_SZ, 65536) == 65536, "an unprivileged pipe within the max");
+        ok &= check(set(SOMAXCONN, b"1") < 0, "an unprivileged sysctl write fails");
+        exit(if ok { 0 } else { 1 });
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    ok &= check(status == 0, "the limits of an unprivileged task");
+    ok &= check(get(SOMAXCONN, &mut buf) == b"4096\n", "an unprivileged write changes nothing");
+    set(PIPE_MAX_SIZE, b"1048576");
+    close(fds[0]);
+    close(fds[1]);
+
+    if ok {
+        println!("test_sysctl: passed");
+        0
+    } else {
+        -1
+    }
+}
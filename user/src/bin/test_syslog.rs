@@ -0,0 +1,38 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, exit, fork, setuid, syslog, wait, EPERM, SYSLOG_ACTION_CLEAR, SYSLOG_ACTION_READ_ALL,
+    SYSLOG_ACTION_READ_CLEAR,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    // another user may read the log ring but not clear it
+    let pid = fork();
+    if pid == 0 {
+        let mut buf = [0u8; 64];
+        let denied = setuid(1000) == 0
+            && syslog(SYSLOG_ACTION_CLEAR, &mut []) == EPERM
+            && syslog(SYSLOG_ACTION_READ_CLEAR, &mut buf) == EPERM
+            && syslog(SYSLOG_ACTION_READ_ALL, &mut buf) >= 0;
+        exit(if denied { 0 } else { 1 });
+    }
+    let mut exit_code = 0;
+    wait(&mut exit_code);
+    let mut ok = check(exit_code == 0, "clearing the log ring as another user is EPERM");
+
+    let mut buf = [0u8; 64];
+    ok &= check(syslog(SYSLOG_ACTION_READ_CLEAR, &mut buf) >= 0, "root reads and clears the log ring");
+    ok &= check(syslog(SYSLOG_ACTION_CLEAR, &mut []) == 0, "root clears the log ring");
+
+    if ok {
+        println!("test_syslog: passed");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,127 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    accept, bind, close, connect, exit, fork, listen, recvfrom, sendto, setsockopt, socket, waitpid, SockaddrIn,
+    ECONNRESET,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const AF_INET: i32 = 2;
+const SOCK_STREAM: i32 = 1;
+const IPPROTO_TCP: i32 = 6;
+const SOL_SOCKET: i32 = 1;
+const SO_LINGER: i32 = 13;
+
+const TEST_PORT: u16 = 4448;
+const TEST_ADDR: u32 = 0x7f000001; // 127.0.0.1
+const ROUNDS: usize = 200;
+const DATA_LEN: usize = 8192;
+
+#[repr(C)]
+struct Linger {
+    l_onoff: i32,
+    l_linger: i32,
+}
+
+fn test_addr() -> SockaddrIn {
+    SockaddrIn::new(TEST_ADDR.to_be(), TEST_PORT.to_be())
+}
+
+/// connect, send `DATA_LEN` bytes and close right away,
+/// with `reset` the connection is closed by SO_LINGER with a zero timeout
+fn client_round(round: usize, reset: bool) -> bool {
+    let fd = socket(AF_INET, SOCK_STREAM, IPPROTO_TCP);
+    let addr = test_addr();
+    if fd < 0 || connect(fd as usize, &addr, size_of::<SockaddrIn>() as u32) < 0 {
+        println!("test_tcp_close: connect failed in round {}", round);
+        return false;
+    }
+    if reset {
+        setsockopt(fd as usize, SOL_SOCKET, SO_LINGER, &Linger { l_onoff: 1, l_linger: 0 });
+    }
+    let data = [round as u8; DATA_LEN];
+    let mut sent = 0;
+    while sent < DATA_LEN {
+        let n = sendto(fd as usize, &data[sent..], DATA_LEN - sent, 0, core::ptr::null(), 0);
+        if n <= 0 {
+            println!("test_tcp_close: send failed in round {}: {}", round, n);
+            return false;
+        }
+        sent += n as usize;
+    }
+    close(fd as usize);
+    true
+}
+
+/// read until EOF, return the number of bytes or the error
+fn drain(fd: usize, round: usize) -> Result<usize, isize> {
+    let mut buf = [0u8; 1024];
+    let mut total = 0;
+    loop {
+        let n = recvfrom(fd, &mut buf, buf.len(), 0, core::ptr::null_mut(), core::ptr::null_mut());
+        if n < 0 {
+            return Err(n);
+        }
+        if n == 0 {
+            return Ok(total);
+        }
+        if buf[..n as usize].iter().any(|&b| b != round as u8) {
+            return Err(0);
+        }
+        total += n as usize;
+    }
+}
+
+/// rapid connect/send/close rounds must deliver all data and end with a FIN,
+/// only a zero SO_LINGER resets the connection
+#[no_mangle]
+pub fn main() -> i32 {
+    let listen_fd = socket(AF_INET, SOCK_STREAM, IPPROTO_TCP);
+    let addr = test_addr();
+    if listen_fd < 0
+        || bind(listen_fd as usize, &addr, size_of::<SockaddrIn>() as u32) < 0
+        || listen(listen_fd as usize, 16) < 0
+    {
+        println!("test_tcp_close: socket/bind/listen failed");
+        return -1;
+    }
+    let pid = fork();
+    if pid == 0 {
+        close(listen_fd as usize);
+        let ok = (0..ROUNDS).all(|round| client_round(round, false)) && client_round(ROUNDS, true);
+        exit(if ok { 0 } else { -1 });
+    }
+
+    let mut ok = true;
+    for round in 0..=ROUNDS {
+        let fd = accept(listen_fd as usize, core::ptr::null_mut(), core::ptr::null_mut());
+        if fd < 0 {
+            println!("test_tcp_close: accept failed in round {}: {}", round, fd);
+            ok = false;
+            break;
+        }
+        let ret = drain(fd as usize, round);
+        close(fd as usize);
+        match ret {
+            Ok(DATA_LEN) if round < ROUNDS => {}
+            Err(ECONNRESET) if round == ROUNDS => {}
+            _ => {
+                println!("test_tcp_close: round {} ended with {:?}", round, ret);
+                ok = false;
+                break;
+            }
+        }
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    close(listen_fd as usize);
+    if ok && status == 0 {
+        println!("test_tcp_close passed!");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,138 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    accept, bind, check, close, connect, exit, fork, get_time_ms, getsockopt, listen, net_blackhole, pipe, ppoll, read,
+    recvfrom, sendto, setsockopt, socket, waitpid, write, PollFd, SockaddrIn, EINVAL, ETIMEDOUT, POLLHUP, POLLIN,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const AF_INET: i32 = 2;
+const SOCK_STREAM: i32 = 1;
+const IPPROTO_TCP: i32 = 6;
+const SOL_SOCKET: i32 = 1;
+const SO_KEEPALIVE: i32 = 9;
+const TCP_KEEPIDLE: i32 = 4;
+const TCP_KEEPINTVL: i32 = 5;
+const TCP_KEEPCNT: i32 = 6;
+const TCP_USER_TIMEOUT: i32 = 18;
+
+const TEST_PORT: u16 = 4450;
+const TEST_ADDR: u32 = 0x7f000001; // 127.0.0.1
+/// the keepalive gives up after idle + cnt * intvl, 3 seconds, the margin is for a busy machine
+const KEEPALIVE_LIMIT_MS: isize = 6000;
+const USER_TIMEOUT_MS: i32 = 1000;
+const USER_TIMEOUT_LIMIT_MS: isize = 3000;
+
+fn test_addr() -> SockaddrIn {
+    SockaddrIn::new(TEST_ADDR.to_be(), TEST_PORT.to_be())
+}
+
+fn client() -> isize {
+    let fd = socket(AF_INET, SOCK_STREAM, IPPROTO_TCP);
+    if fd < 0 || connect(fd as usize, &test_addr(), size_of::<SockaddrIn>() as u32) < 0 {
+        return -1;
+    }
+    fd
+}
+
+fn get_int(fd: usize, level: i32, option: i32) -> i32 {
+    let mut val = -1;
+    getsockopt(fd, level, option, &mut val);
+    val
+}
+
+/// block in recv until the connection fails, return the error and the time it took
+fn recv_fails(fd: usize) -> (isize, isize) {
+    let start = get_time_ms();
+    let mut buf = [0u8; 64];
+    let ret = recvfrom(fd, &mut buf, buf.len(), 0, core::ptr::null_mut(), core::ptr::null_mut());
+    (ret, get_time_ms() - start)
+}
+
+/// a peer which stops answering is found out by the keepalive probes,
+/// data left unacknowledged by the user timeout, both fail a blocked recv with ETIMEDOUT
+#[no_mangle]
+pub fn main() -> i32 {
+    let listen_fd = socket(AF_INET, SOCK_STREAM, IPPROTO_TCP);
+    if listen_fd < 0
+        || bind(listen_fd as usize, &test_addr(), size_of::<SockaddrIn>() as u32) < 0
+        || listen(listen_fd as usize, 4) < 0
+    {
+        println!("test_tcp_keepalive: socket/bind/listen failed");
+        return -1;
+    }
+    let mut done = [0usize; 2];
+    pipe(&mut done);
+    let pid = fork();
+    if pid == 0 {
+        // the peer accepts both connections and keeps them open, silent
+        let a = accept(listen_fd as usize, core::ptr::null_mut(), core::ptr::null_mut());
+        let b = accept(listen_fd as usize, core::ptr::null_mut(), core::ptr::null_mut());
+        let mut byte = [0u8];
+        read(done[0], &mut byte);
+        close(a as usize);
+        close(b as usize);
+        exit(if a >= 0 && b >= 0 { 0 } else { -1 });
+    }
+
+    let mut ok = true;
+    let probed = client();
+    let timed = client();
+    ok &= check(probed >= 0 && timed >= 0, "connect");
+    let (probed, timed) = (probed as usize, timed as usize);
+
+    // the options read back as set, out of range ones are refused
+    ok &= check(get_int(probed, SOL_SOCKET, SO_KEEPALIVE) == 0, "keepalive is off by default");
+    ok &= check(get_int(probed, IPPROTO_TCP, TCP_KEEPIDLE) == 7200, "the default TCP_KEEPIDLE");
+    setsockopt(probed, SOL_SOCKET, SO_KEEPALIVE, &1i32);
+    setsockopt(probed, IPPROTO_TCP, TCP_KEEPIDLE, &1i32);
+    setsockopt(probed, IPPROTO_TCP, TCP_KEEPINTVL, &1i32);
+    setsockopt(probed, IPPROTO_TCP, TCP_KEEPCNT, &2i32);
+    ok &= check(get_int(probed, SOL_SOCKET, SO_KEEPALIVE) == 1, "SO_KEEPALIVE");
+    ok &= check(get_int(probed, IPPROTO_TCP, TCP_KEEPIDLE) == 1, "TCP_KEEPIDLE");
+    ok &= check(get_int(probed, IPPROTO_TCP, TCP_KEEPINTVL) == 1, "TCP_KEEPINTVL");
+    ok &= check(get_int(probed, IPPROTO_TCP, TCP_KEEPCNT) == 2, "TCP_KEEPCNT");
+    ok &= check(setsockopt(probed, IPPROTO_TCP, TCP_KEEPCNT, &0i32) == EINVAL, "TCP_KEEPCNT of 0 is EINVAL");
+    ok &= check(setsockopt(probed, IPPROTO_TCP, TCP_KEEPIDLE, &40000i32) == EINVAL, "TCP_KEEPIDLE too large is EINVAL");
+    setsockopt(timed, IPPROTO_TCP, TCP_USER_TIMEOUT, &USER_TIMEOUT_MS);
+    ok &= check(get_int(timed, IPPROTO_TCP, TCP_USER_TIMEOUT) == USER_TIMEOUT_MS, "TCP_USER_TIMEOUT");
+
+    // the peer goes dead
+    if net_blackhole(TEST_PORT) < 0 {
+        println!("test_tcp_keepalive: not on the loopback device, skipped");
+    } else {
+        let (ret, took) = recv_fails(probed);
+        println!("test_tcp_keepalive: keepalive recv returned {} after {} ms", ret, took);
+        ok &= check(ret == ETIMEDOUT, "ETIMEDOUT after the probes");
+        ok &= check(took < KEEPALIVE_LIMIT_MS, "the probes give up in time");
+        let mut fds = [PollFd { fd: probed as i32, events: POLLIN, revents: 0 }];
+        ok &= check(ppoll(&mut fds, Some(0)) == 1 && fds[0].revents & POLLHUP != 0, "POLLHUP after the reset");
+
+        ok &= check(sendto(timed, b"lost", 4, 0, core::ptr::null(), 0) == 4, "send to a dead peer");
+        let (ret, took) = recv_fails(timed);
+        println!("test_tcp_keepalive: user timeout recv returned {} after {} ms", ret, took);
+        ok &= check(ret == ETIMEDOUT, "ETIMEDOUT after the user timeout");
+        ok &= check(took < USER_TIMEOUT_LIMIT_MS, "the user timeout in time");
+        net_blackhole(0);
+    }
+
+    close(probed);
+    close(timed);
+    write(done[1], &[0], 1);
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    ok &= check(status == 0, "the peer");
+    close(listen_fd as usize);
+    close(done[0]);
+    close(done[1]);
+
+    if ok {
+        println!("test_tcp_keepalive: passed");
+        0
+    } else {
+        -1
+    }
+}
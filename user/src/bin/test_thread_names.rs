@@ -0,0 +1,137 @@
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use user_lib::{
+    check, close, exit, fork, getpid, kill, open, prctl, read, thread_spawn, waitpid, write, yield_, OpenFlags,
+    PR_GET_NAME, PR_SET_NAME,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const SIGKILL: i32 = 9;
+const SIGSEGV: i32 = 11;
+const NAMES: [&[u8]; 3] = [b"worker-one\0", b"worker-two\0", b"crasher\0"];
+
+static mut STACKS: [[u8; 16384]; 3] = [[0; 16384]; 3];
+/// threads which named themselves and found the name in PR_GET_NAME and /proc/self/comm
+static NAMED: AtomicUsize = AtomicUsize::new(0);
+/// threads which did not
+static MISNAMED: AtomicUsize = AtomicUsize::new(0);
+
+/// the name of the calling thread through PR_GET_NAME, up to the nul
+fn get_name(buf: &mut [u8; 16]) -> &[u8] {
+    *buf = [0xff; 16];
+    prctl(PR_GET_NAME, buf.as_mut_ptr() as usize, 0);
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(16);
+    &buf[..len]
+}
+
+/// the name of the calling thread through /proc/self/comm, with its newline
+fn proc_comm(buf: &mut [u8; 32]) -> &[u8] {
+    let fd = open("/proc/self/comm\0", OpenFlags::RDONLY);
+    if fd < 0 {
+        return &[];
+    }
+    let len = read(fd as usize, buf).max(0) as usize;
+    close(fd as usize);
+    &buf[..len]
+}
+
+fn named(name: &[u8]) -> bool {
+    let name = &name[..name.len() - 1];
+    let mut buf = [0u8; 16];
+    let mut comm = [0u8; 32];
+    let comm = proc_comm(&mut comm);
+    get_name(&mut buf) == name && comm.strip_suffix(b"\n") == Some(name)
+}
+
+extern "C" fn thread(idx: usize) -> ! {
+    let name = NAMES[idx];
+    prctl(PR_SET_NAME, name.as_ptr() as usize, 0);
+    if named(name) {
+        NAMED.fetch_add(1, Ordering::SeqCst);
+    } else {
+        MISNAMED.fetch_add(1, Ordering::SeqCst);
+    }
+    if idx == 2 {
+        // once every thread has its name, crash: the kernel log names "crasher"
+        while NAMED.load(Ordering::SeqCst) + MISNAMED.load(Ordering::SeqCst) < NAMES.len() {
+            yield_();
+        }
+        if MISNAMED.load(Ordering::SeqCst) != 0 {
+            // a name went wrong, die of something the parent tells apart from the crash
+            kill(getpid(), SIGKILL);
+        }
+        unsafe { core::ptr::null_mut::<u8>().write_volatile(1) };
+    }
+    loop {
+        yield_();
+    }
+}
+
+/// a process of three named threads, one of which takes it down
+fn child() -> ! {
+    for idx in 0..NAMES.len() {
+        let stack = unsafe { &mut (*core::ptr::addr_of_mut!(STACKS))[idx] };
+        if thread_spawn(stack, thread, idx) < 0 {
+            exit(1);
+        }
+    }
+    loop {
+        yield_();
+    }
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    let mut buf = [0u8; 16];
+    let mut comm = [0u8; 32];
+
+    // the name starts as the basename of the executed file
+    ok &= check(get_name(&mut buf) == b"test_thread_nam", "the name from exec");
+    ok &= check(proc_comm(&mut comm) == b"test_thread_nam\n", "/proc/self/comm");
+
+    // at most 16 bytes are read and 15 kept, a name with no nul in reach is fine
+    let long = b"a-name-much-longer-than-sixteen";
+    ok &= check(prctl(PR_SET_NAME, long.as_ptr() as usize, 0) == 0, "PR_SET_NAME of a long name");
+    ok &= check(get_name(&mut buf) == b"a-name-much-lon", "a long name is cut to 15 bytes");
+    ok &= check(buf[15] == 0, "PR_GET_NAME ends in a nul");
+
+    // writing /proc/self/comm renames the thread, as echo does with its newline
+    let fd = open("/proc/self/comm\0", OpenFlags::WRONLY);
+    ok &= check(fd >= 0 && write(fd as usize, b"renamed\n", 8) == 8, "write /proc/self/comm");
+    if fd >= 0 {
+        close(fd as usize);
+    }
+    ok &= check(get_name(&mut buf) == b"renamed", "the name written to /proc/self/comm");
+
+    // the child inherits the name
+    let pid = fork();
+    if pid == 0 {
+        let mut buf = [0u8; 16];
+        exit(if get_name(&mut buf) == b"renamed" { 0 } else { 1 });
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    ok &= check(status == 0, "a forked child keeps the name");
+
+    // three threads name themselves, the crasher faults and the process dies of SIGSEGV
+    let pid = fork();
+    if pid == 0 {
+        child();
+    }
+    let mut status = 0;
+    ok &= check(waitpid(pid as usize, &mut status) == pid, "wait for the crashed process");
+    ok &= check(status & 0x7f == SIGSEGV, "the three threads were named and the process died of SIGSEGV");
+
+    if ok {
+        println!("test_thread_names: passed");
+        0
+    } else {
+        -1
+    }
+}
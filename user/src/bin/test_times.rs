@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{exit, fork, get_time_ms, times, waitpid, Tms};
+
+#[macro_use]
+extern crate user_lib;
+
+const SPIN_MS: isize = 1000;
+/// allowed error in percent
+const TOLERANCE: usize = 5;
+
+/// burn user time without a syscall until `ms` of wall time passed
+fn spin(ms: isize) {
+    let start = get_time_ms();
+    let mut x = 0usize;
+    while get_time_ms() - start < ms {
+        for i in 0..10_000 {
+            x = core::hint::black_box(x.wrapping_mul(31).wrapping_add(i));
+        }
+    }
+}
+
+fn close_to(us: usize, expect_ms: isize) -> bool {
+    let expect = expect_ms as usize * 1000;
+    us.abs_diff(expect) * 100 <= expect * TOLERANCE
+}
+
+/// spin a second in a child, then in ourselves, times() must report both within 5%
+#[no_mangle]
+pub fn main() -> i32 {
+    // one after the other, so neither shares its hart with the other
+    let pid = fork();
+    if pid == 0 {
+        spin(SPIN_MS);
+        exit(0);
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    spin(SPIN_MS);
+
+    let mut tms = Tms::default();
+    times(&mut tms);
+    println!("test_times: utime {} us, stime {} us, cutime {} us, cstime {} us", tms.utime, tms.stime, tms.cutime, tms.cstime);
+    if !close_to(tms.utime + tms.stime, SPIN_MS) {
+        println!("test_times: own time off by more than {}%", TOLERANCE);
+        return -1;
+    }
+    if !close_to(tms.cutime + tms.cstime, SPIN_MS) {
+        println!("test_times: child time off by more than {}%", TOLERANCE);
+        return -1;
+    }
+    println!("test_times passed!");
+    0
+}
@@ -0,0 +1,132 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, exit, fork, get_time_ms, getrusage, kill, nanosleep, open, pipe, read, sched_setaffinity, waitpid,
+    write, OpenFlags, Rusage, RUSAGE_SELF, SIGKILL,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const TIMESLICE: &str = "/proc/sys/kernel/sched_timeslice_ms\0";
+/// the printer sleeps this long between two lines
+const PERIOD_MS: isize = 20;
+const LINES: usize = 25;
+/// the most a printer line may be late, a few slices of the spinners
+const MAX_LATE_MS: isize = 100;
+/// how long the counting spinners run
+const SPIN_MS: isize = 300;
+
+/// spin on hart 0 without a single syscall, until killed
+fn spinner() -> ! {
+    sched_setaffinity(0, &[1]);
+    let mut n = 0usize;
+    loop {
+        n = core::hint::black_box(n.wrapping_add(1));
+    }
+}
+
+/// spin on hart 0 for SPIN_MS, then send the times it was preempted through `fd`
+fn counting_spinner(fd: usize) -> ! {
+    sched_setaffinity(0, &[1]);
+    let start = get_time_ms();
+    while get_time_ms() < start + SPIN_MS {}
+    let mut usage = Rusage::default();
+    getrusage(RUSAGE_SELF, &mut usage);
+    write(fd, &usage.ru_nivcsw.to_le_bytes(), 8);
+    exit(0);
+}
+
+fn read_usize(fd: usize) -> usize {
+    let mut buf = [0u8; 8];
+    read(fd, &mut buf);
+    usize::from_le_bytes(buf)
+}
+
+/// the content of the sysctl, empty when it cannot be read
+fn get<'a>(buf: &'a mut [u8; 16]) -> &'a [u8] {
+    let fd = open(TIMESLICE, OpenFlags::RDONLY);
+    if fd < 0 {
+        return &[];
+    }
+    let len = read(fd as usize, buf).max(0) as usize;
+    close(fd as usize);
+    &buf[..len]
+}
+
+fn set(value: &[u8]) -> isize {
+    let fd = open(TIMESLICE, OpenFlags::WRONLY);
+    if fd < 0 {
+        return fd;
+    }
+    let ret = write(fd as usize, value, value.len());
+    close(fd as usize);
+    ret
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    let mut buf = [0u8; 16];
+    ok &= check(get(&mut buf) == b"10\n", "the time slice is 10 ms by default");
+    ok &= check(set(b"0") < 0, "a time slice of 0 is rejected");
+
+    // the printer shares hart 0 with two tasks which never enter the kernel on their own
+    sched_setaffinity(0, &[1]);
+    let mut spinners = [0isize; 2];
+    for pid in spinners.iter_mut() {
+        *pid = fork();
+        if *pid == 0 {
+            spinner();
+        }
+    }
+    let mut worst = 0;
+    let mut last = get_time_ms();
+    for line in 0..LINES {
+        nanosleep(PERIOD_MS as usize);
+        let now = get_time_ms();
+        let late = now - last - PERIOD_MS;
+        worst = worst.max(late);
+        if line % 5 == 0 {
+            println!("test_timeslice: line {} at {} ms, {} ms late", line, now, late);
+        }
+        last = now;
+    }
+    for &pid in &spinners {
+        kill(pid, SIGKILL);
+        let mut status = 0;
+        waitpid(pid as usize, &mut status);
+    }
+    println!("test_timeslice: the printer was at most {} ms late", worst);
+    ok &= check(worst <= MAX_LATE_MS, "the printer makes steady progress beside the spinners");
+
+    // two spinners taking turns are preempted, each time its slice ends
+    let mut fds = [0usize; 2];
+    pipe(&mut fds);
+    let mut pids = [0isize; 2];
+    for pid in pids.iter_mut() {
+        *pid = fork();
+        if *pid == 0 {
+            counting_spinner(fds[1]);
+        }
+    }
+    let preempted = [read_usize(fds[0]), read_usize(fds[0])];
+    for pid in pids {
+        let mut status = 0;
+        waitpid(pid as usize, &mut status);
+    }
+    close(fds[0]);
+    close(fds[1]);
+    println!("test_timeslice: spinners preempted {} and {} times", preempted[0], preempted[1]);
+    ok &= check(preempted.iter().all(|&n| n > 0), "a spinner sharing its hart is preempted");
+
+    ok &= check(set(b"10\n") == 3, "restore the time slice");
+
+    if ok {
+        println!("test_timeslice: passed");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,89 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{check, close, fchmod, fstat, fsync, open, read, sleep, unlink, write, OpenFlags, Stat};
+
+#[macro_use]
+extern crate user_lib;
+
+const PATH: &str = "/test_timestamps\0";
+
+fn stat_of(fd: usize) -> Stat {
+    let mut stat = Stat::default();
+    fstat(fd, &mut stat);
+    stat
+}
+
+fn key(sec: isize, nsec: isize) -> (isize, isize) {
+    (sec, nsec)
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    let fd = open(PATH, OpenFlags::CREATE | OpenFlags::RDWR | OpenFlags::TRUNC);
+    if fd < 0 {
+        println!("test_timestamps: create failed");
+        return -1;
+    }
+    let fd = fd as usize;
+    write(fd, b"hello", 5);
+    let before = stat_of(fd);
+
+    // a write moves mtime and ctime forward
+    sleep(1100);
+    write(fd, b" world", 6);
+    let after = stat_of(fd);
+    ok &= check(
+        key(after.st_mtime_sec, after.st_mtime_nsec) > key(before.st_mtime_sec, before.st_mtime_nsec),
+        "mtime after write",
+    );
+    ok &= check(
+        key(after.st_ctime_sec, after.st_ctime_nsec) > key(before.st_ctime_sec, before.st_ctime_nsec),
+        "ctime after write",
+    );
+    close(fd);
+
+    // relatime: the first read after the write updates atime, an immediate second one does not
+    let fd = open(PATH, OpenFlags::RDONLY) as usize;
+    let mut buf = [0u8; 16];
+    read(fd, &mut buf);
+    let first = stat_of(fd);
+    ok &= check(
+        key(first.st_atime_sec, first.st_atime_nsec) > key(after.st_mtime_sec, after.st_mtime_nsec),
+        "atime after first read",
+    );
+    sleep(1100);
+    close(fd);
+    let fd = open(PATH, OpenFlags::RDONLY) as usize;
+    read(fd, &mut buf);
+    let second = stat_of(fd);
+    ok &= check(
+        key(second.st_atime_sec, second.st_atime_nsec) == key(first.st_atime_sec, first.st_atime_nsec),
+        "atime unchanged on second read",
+    );
+
+    // chmod only touches ctime
+    sleep(1100);
+    ok &= check(fchmod(fd, 0o600) == 0, "fchmod");
+    let chmoded = stat_of(fd);
+    ok &= check(chmoded.st_mode & 0o7777 == 0o600, "mode after fchmod");
+    ok &= check(
+        key(chmoded.st_ctime_sec, chmoded.st_ctime_nsec) > key(second.st_ctime_sec, second.st_ctime_nsec),
+        "ctime after fchmod",
+    );
+    ok &= check(
+        key(chmoded.st_mtime_sec, chmoded.st_mtime_nsec) == key(second.st_mtime_sec, second.st_mtime_nsec),
+        "mtime unchanged by fchmod",
+    );
+    ok &= check(fsync(fd) == 0, "fsync");
+    close(fd);
+    unlink(PATH);
+
+    if ok {
+        println!("test_timestamps passed!");
+        0
+    } else {
+        -1
+    }
+}
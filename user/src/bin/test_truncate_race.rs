@@ -0,0 +1,99 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{check, close, exit, fork, ftruncate, open, read, unlink, waitpid, write, OpenFlags};
+
+#[macro_use]
+extern crate user_lib;
+
+const FILE: &str = "/test_truncate_race_file\0";
+const PAGE: usize = 4096;
+/// longer than a page and not page aligned, so truncation cuts inside a cached page
+const LEN: usize = PAGE + 1000;
+const ROUNDS: usize = 200;
+
+/// read the whole file through a fresh fd, return the length or -1 on a bad byte or error
+fn read_file(expect: u8) -> isize {
+    let fd = open(FILE, OpenFlags::RDONLY);
+    if fd < 0 {
+        return -1;
+    }
+    let mut buf = [0u8; 1024];
+    let mut total = 0isize;
+    loop {
+        let n = read(fd as usize, &mut buf);
+        if n < 0 {
+            total = -1;
+            break;
+        }
+        if n == 0 {
+            break;
+        }
+        if buf[..n as usize].iter().any(|&b| b != expect) {
+            total = -1;
+            break;
+        }
+        total += n;
+    }
+    close(fd as usize);
+    total
+}
+
+fn rewrite(byte: u8) {
+    let fd = open(FILE, OpenFlags::WRONLY);
+    if fd < 0 {
+        exit(-1);
+    }
+    let data = [byte; LEN];
+    ftruncate(fd as usize, 0);
+    write(fd as usize, &data, LEN);
+    close(fd as usize);
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDWR);
+    if fd < 0 {
+        println!("test_truncate_race: can not create {}", FILE);
+        return -1;
+    }
+    let data = [b'x'; 3 * PAGE];
+    ok &= check(write(fd as usize, &data, data.len()) == data.len() as isize, "write 3 pages");
+    ok &= check(ftruncate(fd as usize, 100) == 0, "ftruncate to 100");
+    // the offset is now past the end of file
+    let mut buf = [0u8; 16];
+    ok &= check(read(fd as usize, &mut buf) == 0, "read beyond EOF");
+    ok &= check(read_file(b'x') == 100, "read after truncate stops at the new size");
+    close(fd as usize);
+
+    // a reader looping over the file while another task truncates and rewrites it
+    // must never fail, never see stale bytes and never read past the rewritten size
+    rewrite(b'b');
+    let pid = fork();
+    if pid == 0 {
+        for _ in 0..ROUNDS {
+            let len = read_file(b'b');
+            if !check(len >= 0 && len as usize <= LEN, "read during truncate and rewrite") {
+                exit(-1);
+            }
+        }
+        exit(0);
+    }
+    for _ in 0..ROUNDS {
+        rewrite(b'b');
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    ok &= check(status == 0, "reader child");
+    ok &= check(read_file(b'b') == LEN as isize, "final size");
+    unlink(FILE);
+
+    if ok {
+        println!("test_truncate_race passed!");
+        0
+    } else {
+        -1
+    }
+}
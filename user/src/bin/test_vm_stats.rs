@@ -0,0 +1,129 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, exit, fork, getrusage, mmap, munmap, open, unlink, vm_stats, waitpid, write, MmapFlags, MmapProt,
+    OpenFlags, Rusage, VmEventCounts, RUSAGE_SELF, VM_COW, VM_MAJOR_FAULT, VM_MINOR_FAULT, VM_MMAP, VM_MUNMAP,
+    VM_TLB_SHOOTDOWN,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const PAGE_SIZE: usize = 4096;
+/// pages touched by each step, far from a huge page
+const PAGES: usize = 16;
+const FILE: &str = "/test_vm_stats_file\0";
+
+/// the counters before and after a step, both buffers faulted in before the step starts
+struct Window {
+    before: VmEventCounts,
+    after: VmEventCounts,
+}
+
+impl Window {
+    /// start a step, the window stays in place so its buffers are the ones faulted in
+    fn open(&mut self) {
+        vm_stats(&mut self.after);
+        vm_stats(&mut self.before);
+    }
+
+    /// the events of the step, `[minor, major, cow, mmap, munmap, shootdown]`
+    fn close(&mut self) -> VmEventCounts {
+        vm_stats(&mut self.after);
+        core::array::from_fn(|i| self.after[i] - self.before[i])
+    }
+}
+
+fn write_pages(base: usize, value: u8) {
+    for i in 0..PAGES {
+        unsafe { core::ptr::write_volatile((base + i * PAGE_SIZE) as *mut u8, value) };
+    }
+}
+
+fn read_pages(base: usize) -> usize {
+    (0..PAGES).map(|i| unsafe { core::ptr::read_volatile((base + i * PAGE_SIZE) as *const u8) } as usize).sum()
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    let len = PAGES * PAGE_SIZE;
+    let mut window = Window { before: [0; 6], after: [0; 6] };
+
+    // fresh anonymous pages fault once each, no io and nothing to copy
+    window.open();
+    let base = mmap(0, len, MmapProt::PROT_READ | MmapProt::PROT_WRITE, MmapFlags::MAP_PRIVATE | MmapFlags::MAP_ANONYMOUS, usize::MAX, 0);
+    ok &= check(base > 0, "mmap");
+    let base = base as usize;
+    write_pages(base, 1);
+    let events = window.close();
+    println!("test_vm_stats: touch {} pages: {:?}", PAGES, events);
+    ok &= check(events == [PAGES, 0, 0, 1, 0, 0], "a fault for each touched page");
+    // touching them again is free
+    window.open();
+    write_pages(base, 2);
+    ok &= check(window.close() == [0; 6], "touched pages fault no more");
+
+    // fork shares every page, the child pays a copy for each page it writes
+    window.open();
+    let pid = fork();
+    if pid == 0 {
+        // a window of its own, the one of the parent holds the counters of the parent
+        let mut child = Window { before: [0; 6], after: [0; 6] };
+        child.open();
+        ok &= check(child.before[VM_MMAP] == 0, "the child starts from zero");
+        write_pages(base, 3);
+        let events = child.close();
+        println!("test_vm_stats: child writes {} pages: {:?}", PAGES, events);
+        ok &= check(events == [PAGES, 0, PAGES, 0, 0, 0], "a copy for each page written by the child");
+        exit(if ok { 0 } else { 1 });
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    ok &= check(status == 0, "the counts of the child");
+    let events = window.close();
+    ok &= check(events[VM_TLB_SHOOTDOWN] == 1, "fork takes the write permission away once");
+    // the child copied, so the pages are the parent's alone again: a fault but no copy
+    window.open();
+    write_pages(base, 4);
+    let events = window.close();
+    println!("test_vm_stats: parent writes {} pages after the child: {:?}", PAGES, events);
+    ok &= check(events == [PAGES, 0, 0, 0, 0, 0], "no copy of pages no longer shared");
+    window.open();
+    ok &= check(munmap(base, len) == 0, "munmap");
+    ok &= check(window.close()[VM_MUNMAP] == 1, "a munmap counts");
+
+    // a file just written is in the page cache, mapping it needs no io
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDWR);
+    let page = [7u8; PAGE_SIZE];
+    for _ in 0..PAGES {
+        write(fd as usize, &page, PAGE_SIZE);
+    }
+    window.open();
+    let base = mmap(0, len, MmapProt::PROT_READ, MmapFlags::MAP_PRIVATE, fd as usize, 0) as usize;
+    ok &= check(read_pages(base) == 7 * PAGES, "read the mapped file");
+    let events = window.close();
+    println!("test_vm_stats: read {} cached file pages: {:?}", PAGES, events);
+    ok &= check(events[VM_MINOR_FAULT] == PAGES && events[VM_MAJOR_FAULT] == 0, "cached file pages are minor faults");
+    ok &= check(events[VM_COW] == 0, "reading copies nothing");
+    munmap(base, len);
+    close(fd as usize);
+    unlink(FILE);
+
+    // getrusage reports the same faults
+    let mut usage = Rusage::default();
+    getrusage(RUSAGE_SELF, &mut usage);
+    let mut counts = [0; 6];
+    vm_stats(&mut counts);
+    getrusage(RUSAGE_SELF, &mut usage);
+    ok &= check(usage.ru_minflt == counts[VM_MINOR_FAULT], "ru_minflt");
+    ok &= check(usage.ru_majflt == counts[VM_MAJOR_FAULT], "ru_majflt");
+
+    if ok {
+        println!("test_vm_stats: passed");
+        0
+    } else {
+        -1
+    }
+}
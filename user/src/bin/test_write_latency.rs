@@ -0,0 +1,105 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, exit, fork, get_time_of_day, mmap, nanosleep, open, pipe, pread, read, sched_setaffinity, unlink,
+    waitpid, write, MmapFlags, MmapProt, OpenFlags, TimeVal,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+const TIMESLICE: &str = "/proc/sys/kernel/sched_timeslice_ms\0";
+/// on ext4, streamed by the writer
+const BIG_FILE: &str = "/test_write_latency_big\0";
+/// on ext4, read a byte at a time beside it
+const SMALL_FILE: &str = "/test_write_latency_small\0";
+/// the size of a single write() and of the buffer it comes from
+const WRITE_LEN: usize = 64 << 20;
+/// the writes, 256 MiB in all
+const WRITES: usize = 4;
+const SAMPLES: usize = 200;
+/// the most the 99th percentile of the reads may be late
+const MAX_P99_US: isize = 10_000;
+
+fn now_us() -> isize {
+    let mut tv = TimeVal { sec: 0, usec: 0 };
+    get_time_of_day(&mut tv);
+    (tv.sec * 1_000_000 + tv.usec) as isize
+}
+
+fn set_timeslice(value: &[u8]) -> isize {
+    let fd = open(TIMESLICE, OpenFlags::WRONLY);
+    if fd < 0 {
+        return fd;
+    }
+    let ret = write(fd as usize, value, value.len());
+    close(fd as usize);
+    ret
+}
+
+/// on hart 0, tell `go` when the first write starts, then write 256 MiB in a few giant writes
+fn writer(go: usize) -> ! {
+    sched_setaffinity(0, &[1]);
+    let addr = mmap(0, WRITE_LEN, MmapProt::PROT_READ | MmapProt::PROT_WRITE, MmapFlags::MAP_PRIVATE | MmapFlags::MAP_ANONYMOUS, usize::MAX, 0);
+    let fd = open(BIG_FILE, OpenFlags::CREATE | OpenFlags::WRONLY | OpenFlags::TRUNC);
+    if addr < 0 || fd < 0 {
+        exit(1);
+    }
+    let buf = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, WRITE_LEN) };
+    buf.iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+    write(go, b"g", 1);
+    let ok = (0..WRITES).all(|_| write(fd as usize, buf, WRITE_LEN) == WRITE_LEN as isize);
+    close(fd as usize);
+    exit(if ok { 0 } else { 2 });
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    let fd = open(SMALL_FILE, OpenFlags::CREATE | OpenFlags::RDWR | OpenFlags::TRUNC);
+    ok &= check(fd >= 0 && write(fd as usize, b"x", 1) == 1, "make the small file");
+    // a slice short enough that the writer gives way in time, if it only reaches a yield point
+    ok &= check(set_timeslice(b"2\n") == 2, "shorten the time slice");
+
+    sched_setaffinity(0, &[1]);
+    let mut fds = [0usize; 2];
+    pipe(&mut fds);
+    let pid = fork();
+    if pid == 0 {
+        writer(fds[1]);
+    }
+    let mut go = [0u8; 1];
+    read(fds[0], &mut go);
+
+    // how late a 1-byte read done after a 1 ms sleep comes back, beside the writer on its hart
+    let mut late = [0isize; SAMPLES];
+    let mut byte = [0u8; 1];
+    for sample in late.iter_mut() {
+        let due = now_us() + 1000;
+        nanosleep(1);
+        ok &= check(pread(fd as usize, &mut byte, 0) == 1 && byte[0] == b'x', "read the small file");
+        *sample = (now_us() - due).max(0);
+    }
+    let mut status = 0;
+    waitpid(pid as usize, &mut status);
+    ok &= check(status == 0, "every giant write wrote all of it");
+    close(fds[0]);
+    close(fds[1]);
+    close(fd as usize);
+    unlink(BIG_FILE);
+    unlink(SMALL_FILE);
+    ok &= check(set_timeslice(b"10\n") == 3, "restore the time slice");
+
+    late.sort_unstable();
+    let p99 = late[SAMPLES * 99 / 100 - 1];
+    println!("test_write_latency: reads late by {} us at the median, {} us at p99, {} us at most", late[SAMPLES / 2], p99, late[SAMPLES - 1]);
+    ok &= check(p99 <= MAX_P99_US, "the reads beside a 256 MiB write");
+
+    if ok {
+        println!("test_write_latency: passed");
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,121 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{check, close, fsync, open, read, sleep, unlink, write, OpenFlags};
+
+#[macro_use]
+extern crate user_lib;
+
+const WRITEBACK_MS: &str = "/proc/sys/vm/dirty-writeback-ms\0";
+const EXPIRE_MS: &str = "/proc/sys/vm/dirty-expire-ms\0";
+const BACKGROUND_PAGES: &str = "/proc/sys/vm/dirty-background-pages\0";
+const DISKSTATS: &str = "/proc/diskstats\0";
+/// on ext4, written and never synced until the end
+const FILE: &str = "/test_writeback\0";
+
+/// the steady writes: a chunk every `PERIOD_MS`
+const CHUNK: usize = 16 * 1024;
+const ROUNDS: usize = 30;
+const PERIOD_MS: usize = 100;
+/// the pages of the burst which kicks the flusher
+const BURST_PAGES: usize = 128;
+const PAGE_SIZE: usize = 4096;
+const SECTOR_SIZE: usize = 512;
+
+static BUF: [u8; CHUNK] = [0x5a; CHUNK];
+
+/// the content of a proc file, empty when it cannot be read
+fn get<'a>(path: &str, buf: &'a mut [u8]) -> &'a [u8] {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd < 0 {
+        return &[];
+    }
+    let len = read(fd as usize, buf).max(0) as usize;
+    close(fd as usize);
+    &buf[..len]
+}
+
+/// write `value` to a sysctl file, the result of the write
+fn set(path: &str, value: &[u8]) -> isize {
+    let fd = open(path, OpenFlags::WRONLY);
+    if fd < 0 {
+        return fd;
+    }
+    let ret = write(fd as usize, value, value.len());
+    close(fd as usize);
+    ret
+}
+
+/// the sectors written to every disk since boot, the tenth field of /proc/diskstats
+fn written_sectors() -> usize {
+    let mut buf = [0u8; 1024];
+    let text = core::str::from_utf8(get(DISKSTATS, &mut buf)).unwrap_or("");
+    text.lines()
+        .filter_map(|line| line.split_whitespace().nth(9)?.parse::<usize>().ok())
+        .sum()
+}
+
+/// write a chunk every period without a sync: the flusher writes the aged pages back
+/// while the writes go on. What a hard reset in the middle loses, at most the last
+/// expire and writeback intervals, takes a reboot to check and is left to a manual run
+fn steady_writes(fd: usize) -> bool {
+    let before = written_sectors();
+    let ok = (0..ROUNDS).all(|_| {
+        let ret = write(fd, &BUF, CHUNK);
+        sleep(PERIOD_MS);
+        ret == CHUNK as isize
+    });
+    let mut res = check(ok, "the steady writes");
+    // the chunks older than the expire interval are on the disk already
+    let during = written_sectors() - before;
+    res &= check(during >= ROUNDS / 2 * CHUNK / SECTOR_SIZE, "the aged pages are written back during the writes");
+    sleep(1000);
+    let after = written_sectors() - before;
+    res &= check(after >= ROUNDS * CHUNK / SECTOR_SIZE, "every page is written back once aged");
+    res
+}
+
+/// dirty enough pages at once that the flusher is kicked long before its interval
+fn burst(fd: usize) -> bool {
+    let before = written_sectors();
+    let ok = (0..BURST_PAGES * PAGE_SIZE / CHUNK).all(|_| write(fd, &BUF, CHUNK) == CHUNK as isize);
+    let mut ok = check(ok, "the burst");
+    sleep(300);
+    let written = written_sectors() - before;
+    ok &= check(written >= BURST_PAGES / 2 * PAGE_SIZE / SECTOR_SIZE, "a burst over the threshold kicks the flusher");
+    ok
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let (mut writeback, mut expire, mut background) = ([0u8; 32], [0u8; 32], [0u8; 32]);
+    let writeback = get(WRITEBACK_MS, &mut writeback);
+    let expire = get(EXPIRE_MS, &mut expire);
+    let background = get(BACKGROUND_PAGES, &mut background);
+    let mut ok = check(writeback == b"5000\n" && expire == b"30000\n", "the default intervals");
+
+    let fd = open(FILE, OpenFlags::CREATE | OpenFlags::RDWR | OpenFlags::TRUNC);
+    ok &= check(fd >= 0, "open");
+    let fd = fd as usize;
+
+    ok &= check(set(WRITEBACK_MS, b"100") > 0 && set(EXPIRE_MS, b"500") > 0 && set(BACKGROUND_PAGES, b"0") > 0, "shorten the intervals");
+    ok &= steady_writes(fd);
+
+    ok &= check(set(WRITEBACK_MS, b"600000") > 0 && set(EXPIRE_MS, b"600000") > 0, "lengthen the intervals");
+    ok &= check(set(BACKGROUND_PAGES, b"32") > 0, "lower the threshold");
+    ok &= burst(fd);
+
+    set(WRITEBACK_MS, writeback);
+    set(EXPIRE_MS, expire);
+    set(BACKGROUND_PAGES, background);
+    ok &= check(fsync(fd) == 0, "fsync");
+    close(fd);
+    unlink(FILE);
+
+    if ok {
+        println!("test_writeback: passed");
+        0
+    } else {
+        -1
+    }
+}
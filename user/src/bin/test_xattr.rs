@@ -0,0 +1,133 @@
+#![no_std]
+#![no_main]
+
+use user_lib::{
+    check, close, fgetxattr, getxattr, listxattr, open, removexattr, setxattr, unlink, OpenFlags, E2BIG, EEXIST,
+    ENODATA, EOPNOTSUPP, ERANGE, XATTR_CREATE, XATTR_REPLACE,
+};
+
+#[macro_use]
+extern crate user_lib;
+
+/// on ext4
+const DISK_FILE: &str = "/test_xattr\0";
+/// on tmpfs
+const TMP_FILE: &str = "/tmp/test_xattr\0";
+/// kept on the disk, so a later boot finds the attribute set by this one
+const PERSIST_FILE: &str = "/test_xattr_persist\0";
+const BIG: &str = "user.test_xattr.big\0";
+const SMALL: &str = "user.test_xattr.small\0";
+const BOOT: &str = "user.test_xattr.boot\0";
+const VALUE_LEN: usize = 1024;
+const XATTR_SIZE_MAX: usize = 65536;
+
+static TOO_BIG: [u8; XATTR_SIZE_MAX + 1] = [0; XATTR_SIZE_MAX + 1];
+
+fn value() -> [u8; VALUE_LEN] {
+    core::array::from_fn(|i| (i % 251) as u8)
+}
+
+fn create(path: &str) -> bool {
+    let fd = open(path, OpenFlags::CREATE | OpenFlags::WRONLY);
+    if fd >= 0 {
+        close(fd as usize);
+    }
+    fd >= 0
+}
+
+/// whether `list` holds `name`, which ends with its nul
+fn listed(list: &[u8], name: &str) -> bool {
+    list.split_inclusive(|&c| c == 0).any(|entry| entry == name.as_bytes())
+}
+
+/// set, list, read back and remove an attribute of `path`
+fn round_trip(path: &str) -> bool {
+    let mut ok = check(create(path), "create the file");
+    let value = value();
+    ok &= check(setxattr(path, BIG, &value, XATTR_CREATE) == 0, "set a 1 KiB value");
+    ok &= check(setxattr(path, BIG, &value, XATTR_CREATE) == EEXIST, "XATTR_CREATE on an existing attribute");
+    ok &= check(setxattr(path, SMALL, b"x", XATTR_REPLACE) == ENODATA, "XATTR_REPLACE on a missing attribute");
+    ok &= check(setxattr(path, SMALL, b"small", 0) == 0, "set a small value");
+    ok &= check(setxattr(path, SMALL, b"again", XATTR_REPLACE) == 0, "replace it");
+
+    let mut list = [0u8; 128];
+    let len = listxattr(path, &mut []);
+    ok &= check(len == (BIG.len() + SMALL.len()) as isize, "the size of the list");
+    ok &= check(listxattr(path, &mut list[..4]) == ERANGE, "list into a short buffer");
+    ok &= check(listxattr(path, &mut list) == len, "list");
+    ok &= check(listed(&list[..len.max(0) as usize], BIG) && listed(&list[..len.max(0) as usize], SMALL), "both listed");
+
+    let mut buf = [0u8; 2 * VALUE_LEN];
+    ok &= check(getxattr(path, BIG, &mut []) == VALUE_LEN as isize, "the size of the value");
+    ok &= check(getxattr(path, BIG, &mut buf[..16]) == ERANGE, "get into a short buffer");
+    ok &= check(getxattr(path, SMALL, &mut buf) == 5 && &buf[..5] == b"again", "read the replaced value");
+    // through a fresh open, the value is read from where the file system keeps it
+    let fd = open(path, OpenFlags::RDONLY);
+    ok &= check(fd >= 0 && fgetxattr(fd as usize, BIG, &mut buf) == VALUE_LEN as isize, "read it back");
+    ok &= check(buf[..VALUE_LEN] == value, "the value read back");
+    close(fd as usize);
+
+    ok &= check(removexattr(path, SMALL) == 0, "remove");
+    ok &= check(removexattr(path, SMALL) == ENODATA, "remove twice");
+    ok &= check(getxattr(path, SMALL, &mut buf) == ENODATA, "get a removed attribute");
+    ok &= check(removexattr(path, BIG) == 0, "remove the big one");
+    ok &= check(listxattr(path, &mut list) == 0, "the list is empty");
+    unlink(path);
+    ok
+}
+
+/// a name in the user namespace of `len` bytes, and its nul
+fn long_name(len: usize) -> [u8; 257] {
+    let mut name = [b'a'; 257];
+    name[..5].copy_from_slice(b"user.");
+    name[len] = 0;
+    name
+}
+
+/// the limits and the namespaces
+fn limits(path: &str) -> bool {
+    let mut ok = check(create(path), "create the file");
+    let (too_long, longest) = (long_name(256), long_name(255));
+    let (too_long, longest) = (core::str::from_utf8(&too_long).unwrap(), core::str::from_utf8(&longest[..256]).unwrap());
+    ok &= check(setxattr(path, too_long, b"x", 0) == ERANGE, "a name of 256 bytes");
+    ok &= check(setxattr(path, longest, b"x", 0) == 0, "a name of 255 bytes");
+    ok &= check(setxattr(path, SMALL, &TOO_BIG, 0) == E2BIG, "a value over 64 KiB");
+    ok &= check(setxattr(path, "security.test_xattr\0", b"x", 0) == EOPNOTSUPP, "the security namespace");
+    ok &= check(setxattr(path, "trusted.test_xattr\0", b"x", 0) == EOPNOTSUPP, "the trusted namespace");
+    ok &= check(setxattr(path, SMALL, b"x", 0x4) < 0, "an unknown flag");
+    unlink(path);
+    ok
+}
+
+/// the mount syscall is a stub, so the remount is a reboot: the attribute this run
+/// sets is checked by the next one
+fn persistence() -> bool {
+    let value = value();
+    let mut buf = [0u8; VALUE_LEN];
+    let mut ok = true;
+    match getxattr(PERSIST_FILE, BOOT, &mut buf) {
+        len if len == VALUE_LEN as isize => {
+            ok &= check(buf == value, "the value set by an earlier boot");
+            println!("test_xattr: the value set by an earlier boot read back");
+        }
+        _ => ok &= check(create(PERSIST_FILE), "create the file kept on the disk"),
+    }
+    ok & check(setxattr(PERSIST_FILE, BOOT, &value, 0) == 0, "set the value for the next boot")
+}
+
+#[no_mangle]
+pub fn main(_args: &[&str]) -> i32 {
+    let mut ok = true;
+    ok &= check(round_trip(DISK_FILE), "ext4");
+    ok &= check(round_trip(TMP_FILE), "tmpfs");
+    ok &= check(limits(DISK_FILE), "the limits on ext4");
+    ok &= check(limits(TMP_FILE), "the limits on tmpfs");
+    ok &= persistence();
+
+    if ok {
+        println!("test_xattr: passed");
+        0
+    } else {
+        -1
+    }
+}
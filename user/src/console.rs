@@ -5,17 +5,42 @@ const STDOUT: usize = 1;
 
 use super::{read, write};
 
-struct Stdout;
+/// the most bytes of one print written with a single write,
+/// the kernel keeps a write up to this size in one piece on the console
+const RECORD_SIZE: usize = 1024;
+
+/// collects the pieces of one print, so a line is not split into many writes
+struct Stdout {
+    buf: [u8; RECORD_SIZE],
+    len: usize,
+}
+
+impl Stdout {
+    fn flush(&mut self) {
+        if self.len != 0 {
+            write(STDOUT, &self.buf[..self.len], self.len);
+            self.len = 0;
+        }
+    }
+}
 
 impl Write for Stdout {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        write(STDOUT, s.as_bytes(), s.as_bytes().len());
+        for chunk in s.as_bytes().chunks(RECORD_SIZE) {
+            if self.len + chunk.len() > RECORD_SIZE {
+                self.flush();
+            }
+            self.buf[self.len..self.len + chunk.len()].copy_from_slice(chunk);
+            self.len += chunk.len();
+        }
         Ok(())
     }
 }
 
 pub fn print(args: fmt::Arguments) {
-    Stdout.write_fmt(args).unwrap();
+    let mut stdout = Stdout { buf: [0; RECORD_SIZE], len: 0 };
+    stdout.write_fmt(args).unwrap();
+    stdout.flush();
 }
 
 #[macro_export]
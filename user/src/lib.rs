@@ -78,6 +78,9 @@ pub fn _rust_start(p: *const usize) -> ! {
             .unwrap(),
         );
     }
+    if let Some(arg0) = v.first() {
+        unsafe { PROGRAM = arg0.rsplit('/').next().unwrap_or(arg0) };
+    }
     exit(main(v.as_slice()));
 }
 
@@ -87,13 +90,75 @@ fn main(_args: &[&str]) -> i32 {
     panic!("Cannot find main!");
 }
 
+/// the name the program was started as, without the directories of argv[0]
+static mut PROGRAM: &str = "";
+
+/// the name the program was started as, the prefix of the messages of [`check`]
+pub fn program_name() -> &'static str {
+    unsafe { PROGRAM }
+}
+
+/// report `what` as failed unless `ok`, and pass `ok` on
+pub fn check(ok: bool, what: &str) -> bool {
+    if !ok {
+        println!("{}: {} failed", program_name(), what);
+    }
+    ok
+}
+
+// the errors as the syscalls return them, negated
+pub const EPERM: isize = -1;
+pub const ENOENT: isize = -2;
+pub const ESRCH: isize = -3;
+pub const EINTR: isize = -4;
+pub const EIO: isize = -5;
+pub const ENXIO: isize = -6;
+pub const E2BIG: isize = -7;
+pub const ENOEXEC: isize = -8;
+pub const EBADF: isize = -9;
+pub const EAGAIN: isize = -11;
+pub const ENOMEM: isize = -12;
+pub const EACCES: isize = -13;
+pub const EFAULT: isize = -14;
+pub const EBUSY: isize = -16;
+pub const EEXIST: isize = -17;
+pub const EXDEV: isize = -18;
+pub const ENODEV: isize = -19;
+pub const ENOTDIR: isize = -20;
+pub const EISDIR: isize = -21;
+pub const EINVAL: isize = -22;
+pub const ENFILE: isize = -23;
+pub const EMFILE: isize = -24;
+pub const ENOTTY: isize = -25;
+pub const ENOSPC: isize = -28;
+pub const EROFS: isize = -30;
+pub const ERANGE: isize = -34;
+pub const ENAMETOOLONG: isize = -36;
+pub const ENOSYS: isize = -38;
+pub const ENOTEMPTY: isize = -39;
+pub const ELOOP: isize = -40;
+pub const ENODATA: isize = -61;
+pub const EOVERFLOW: isize = -75;
+pub const EOPNOTSUPP: isize = -95;
+pub const ECONNRESET: isize = -104;
+pub const EISCONN: isize = -106;
+pub const ETIMEDOUT: isize = -110;
+pub const ECONNREFUSED: isize = -111;
+pub const ESTALE: isize = -116;
+
 bitflags! {
     pub struct OpenFlags: u32 {
         const RDONLY = 0;
         const WRONLY = 1 << 0;
         const RDWR = 1 << 1;
-        const CREATE = 1 << 9;
-        const TRUNC = 1 << 10;
+        const CREATE = 0o100;
+        const EXCL = 0o200;
+        const TRUNC = 0o1000;
+        const DIRECTORY = 0o200000;
+        const NOFOLLOW = 0o400000;
+        const CLOEXEC = 0o2000000;
+        /// only name the file, see [`fstatat`] with [`AT_EMPTY_PATH`]
+        const PATH = 0o10000000;
     }
     pub struct CloneFlags: u64 {
         /// Set if VM shared between processes.
@@ -145,24 +210,83 @@ pub fn thread_create(flags:CloneFlags) -> isize {
     let mut stack: [usize;1024] = [0;1024];
     sys_clone(flags.bits() as _, stack.as_mut_ptr() as usize, 0)
 }
+/// spawn a thread sharing everything with the caller, running `entry(arg)` on `stack`
+pub fn thread_spawn(stack: &'static mut [u8], entry: extern "C" fn(usize) -> !, arg: usize) -> isize {
+    let flags = CloneFlags::VM | CloneFlags::FS | CloneFlags::FILES | CloneFlags::SIGHAND | CloneFlags::THREAD;
+    // keep the stack top 16 bytes aligned
+    let stack_top = (stack.as_mut_ptr() as usize + stack.len()) & !0xf;
+    sys_spawn_thread(flags.bits() as usize, stack_top, entry, arg)
+}
 pub fn dup(fd: usize) -> isize {
     sys_dup(fd)
 }
+pub fn dup3(old_fd: usize, new_fd: usize, flags: u32) -> isize {
+    sys_dup3(old_fd, new_fd, flags)
+}
 
 pub fn chdir(path: &str) -> isize {
     sys_chdir(path.as_ptr() as *const u8)
 }
+pub fn fchdir(fd: usize) -> isize {
+    sys_fchdir(fd)
+}
+/// the length of the path placed in buf, with its nul
+pub fn getcwd(buf: &mut [u8]) -> isize {
+    sys_getcwd(buf.as_mut_ptr(), buf.len())
+}
+/// getcwd on any pointer, to check the faults
+pub fn getcwd_raw(buf: *mut u8, len: usize) -> isize {
+    sys_getcwd(buf, len)
+}
 
 pub const AT_FDCWD: isize = -100;
+pub const AT_REMOVEDIR: u32 = 0x200;
+pub fn mkdir(path: &str) -> isize {
+    sys_mkdirat(AT_FDCWD, path, 0o755)
+}
+pub fn rmdir(path: &str) -> isize {
+    sys_unlinkat(AT_FDCWD, path, AT_REMOVEDIR)
+}
+pub fn unlink(path: &str) -> isize {
+    sys_unlinkat(AT_FDCWD, path, 0)
+}
+pub fn link(old_path: &str, new_path: &str) -> isize {
+    sys_linkat(AT_FDCWD, old_path, AT_FDCWD, new_path, 0)
+}
+pub fn linkat(old_dirfd: isize, old_path: &str, new_dirfd: isize, new_path: &str, flags: u32) -> isize {
+    sys_linkat(old_dirfd, old_path, new_dirfd, new_path, flags)
+}
+pub fn symlink(target: &str, link_path: &str) -> isize {
+    sys_symlinkat(target, AT_FDCWD, link_path)
+}
+pub fn rename(old_path: &str, new_path: &str) -> isize {
+    sys_renameat2(AT_FDCWD, old_path, AT_FDCWD, new_path, 0)
+}
+pub fn renameat(old_dirfd: isize, old_path: &str, new_dirfd: isize, new_path: &str) -> isize {
+    sys_renameat2(old_dirfd, old_path, new_dirfd, new_path, 0)
+}
 pub fn open(path: &str, flags: OpenFlags) -> isize {
     sys_openat(AT_FDCWD, path, flags.bits)
 }
+pub fn openat(dirfd: isize, path: &str, flags: OpenFlags) -> isize {
+    sys_openat(dirfd, path, flags.bits)
+}
+pub fn mkdirat(dirfd: isize, path: &str) -> isize {
+    sys_mkdirat(dirfd, path, 0o755)
+}
+pub fn unlinkat(dirfd: isize, path: &str, flags: u32) -> isize {
+    sys_unlinkat(dirfd, path, flags)
+}
 pub fn close(fd: usize) -> isize {
     sys_close(fd)
 }
 pub fn pipe(pipe_fd: &mut [usize]) -> isize {
     sys_pipe(pipe_fd)
 }
+/// fill `buf` with the linux_dirent64 records of the next entries of a directory
+pub fn getdents(fd: usize, buf: &mut [u8]) -> isize {
+    sys_getdents64(fd, buf)
+}
 pub fn read(fd: usize, buf: &mut [u8]) -> isize {
     sys_read(fd, buf)
 }
@@ -175,6 +299,58 @@ pub fn exit(exit_code: i32) -> ! {
 pub fn yield_() -> isize {
     sys_yield()
 }
+pub const SCHED_OTHER: i32 = 0;
+pub const SCHED_FIFO: i32 = 1;
+pub const SCHED_RR: i32 = 2;
+pub const SCHED_BATCH: i32 = 3;
+pub const SCHED_IDLE: i32 = 5;
+pub const SCHED_RESET_ON_FORK: i32 = 0x4000_0000;
+
+/// `priority` is the only field of struct sched_param
+pub fn sched_setscheduler(pid: usize, policy: i32, priority: i32) -> isize {
+    sys_sched_setscheduler(pid, policy, &priority)
+}
+pub fn sched_getscheduler(pid: usize) -> isize {
+    sys_sched_getscheduler(pid)
+}
+pub fn sched_getparam(pid: usize, priority: &mut i32) -> isize {
+    sys_sched_getparam(pid, priority)
+}
+/// `mask` is a cpu_set_t, bit i stands for hart i
+pub fn sched_setaffinity(pid: usize, mask: &[u8]) -> isize {
+    sys_sched_setaffinity(pid, mask)
+}
+/// returns the size of the kernel cpu mask in bytes
+pub fn sched_getaffinity(pid: usize, mask: &mut [u8]) -> isize {
+    sys_sched_getaffinity(pid, mask)
+}
+pub const MEMBARRIER_CMD_QUERY: usize = 0;
+pub const MEMBARRIER_CMD_GLOBAL: usize = 1 << 0;
+pub const MEMBARRIER_CMD_PRIVATE_EXPEDITED: usize = 1 << 3;
+pub const MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED: usize = 1 << 4;
+pub fn membarrier(cmd: usize) -> isize {
+    sys_membarrier(cmd, 0)
+}
+/// the hart the caller is running on
+pub fn getcpu() -> isize {
+    let mut cpu = 0u32;
+    let ret = sys_getcpu(&mut cpu);
+    if ret < 0 {
+        return ret;
+    }
+    cpu as isize
+}
+
+pub const FUTEX_WAIT: i32 = 0;
+pub const FUTEX_WAKE: i32 = 1;
+/// block while `*uaddr == val`
+pub fn futex_wait(uaddr: *const u32, val: u32) -> isize {
+    sys_futex(uaddr, FUTEX_WAIT, val)
+}
+/// wake at most `n` waiters blocked on `uaddr`
+pub fn futex_wake(uaddr: *const u32, n: u32) -> isize {
+    sys_futex(uaddr, FUTEX_WAKE, n)
+}
 
 pub fn get_time_ms() -> isize {
     let mut tv: TimeVal = TimeVal { sec: 0, usec: 0 };
@@ -185,6 +361,91 @@ pub fn get_time_ms() -> isize {
     return (tv.sec*1000 + tv.usec/1000) as isize;
 }
 
+pub fn get_time_of_day(tv: &mut TimeVal) -> isize {
+    sys_get_time_of_day(tv)
+}
+
+pub fn set_time_of_day(tv: &TimeVal) -> isize {
+    sys_set_time_of_day(tv)
+}
+
+pub fn ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
+    sys_ioctl(fd, cmd, arg)
+}
+
+/// ioctl: int of the bytes readable without blocking
+pub const FIONREAD: usize = 0x541B;
+/// ioctl: int, nonzero sets O_NONBLOCK and zero clears it
+pub const FIONBIO: usize = 0x5421;
+/// ioctl: make the tty the controlling terminal, an arg of 1 steals it as root
+pub const TIOCSCTTY: usize = 0x540E;
+/// ioctl: give up the controlling terminal
+pub const TIOCNOTTY: usize = 0x5422;
+/// ioctl: u32 of the session of the tty
+pub const TIOCGSID: usize = 0x5429;
+/// ioctl: u32 of the foreground group of the tty
+pub const TIOCGPGRP: usize = 0x540F;
+/// ioctl: u32, set the foreground group of the tty
+pub const TIOCSPGRP: usize = 0x5410;
+/// ioctl: clear close-on-exec
+pub const FIONCLEX: usize = 0x5450;
+/// ioctl: set close-on-exec
+pub const FIOCLEX: usize = 0x5451;
+/// ioctl of a terminal: read its struct termios
+pub const TCGETS: usize = 0x5401;
+
+/// whether `fd` is a terminal, it is if TCGETS works on it
+pub fn isatty(fd: usize) -> bool {
+    // struct termios is 36 bytes
+    let mut termios = [0u8; 64];
+    ioctl(fd, TCGETS, termios.as_mut_ptr() as usize) == 0
+}
+
+/// syslog action: read the whole kernel log ring without clearing it
+pub const SYSLOG_ACTION_READ_ALL: usize = 3;
+/// syslog action: read the whole kernel log ring and clear it, root only
+pub const SYSLOG_ACTION_READ_CLEAR: usize = 4;
+/// syslog action: clear the kernel log ring, root only
+pub const SYSLOG_ACTION_CLEAR: usize = 5;
+
+pub fn syslog(log_type: usize, buf: &mut [u8]) -> isize {
+    sys_syslog(log_type, buf)
+}
+
+/// uname into the six 65 byte fields of struct utsname
+pub fn uname(buf: &mut [u8; 390]) -> isize {
+    sys_uname(buf)
+}
+
+/// the field of a utsname buffer, up to the nul: 1 is the node name, 5 the domain name
+pub fn uname_field(buf: &[u8; 390], idx: usize) -> &[u8] {
+    let field = &buf[idx * 65..(idx + 1) * 65];
+    let len = field.iter().position(|&c| c == 0).unwrap_or(65);
+    &field[..len]
+}
+
+pub fn sethostname(name: &[u8]) -> isize {
+    sys_sethostname(name)
+}
+
+/// ioctl of /dev/rtc0: read the time into an RtcTime
+pub const RTC_RD_TIME: usize = 0x8024_7009;
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+/// broken down time of the rtc ioctls
+pub struct RtcTime {
+    pub tm_sec: i32,
+    pub tm_min: i32,
+    pub tm_hour: i32,
+    pub tm_mday: i32,
+    pub tm_mon: i32,
+    pub tm_year: i32,
+    pub tm_wday: i32,
+    pub tm_yday: i32,
+    pub tm_isdst: i32,
+}
+
 pub fn getpid() -> isize {
     sys_getpid()
 }
@@ -335,6 +596,61 @@ pub fn kill(pid: isize, signum: i32) -> isize {
     sys_kill(pid as usize, signum)
 }
 
+/// put the process `pid` in the group `pgid`, 0 for the caller and for a group of its own
+pub fn setpgid(pid: usize, pgid: usize) -> isize {
+    sys_setpgid(pid, pgid)
+}
+/// start a session without a controlling terminal, returns the new session id
+pub fn setsid() -> isize {
+    sys_setsid()
+}
+pub fn getsid(pid: usize) -> isize {
+    sys_getsid(pid)
+}
+/// hang up the controlling terminal, root only
+pub fn vhangup() -> isize {
+    sys_vhangup()
+}
+
+pub const PRIO_PROCESS: i32 = 0;
+pub const PRIO_PGRP: i32 = 1;
+pub const PRIO_USER: i32 = 2;
+pub const RUSAGE_SELF: i32 = 0;
+
+pub fn setpriority(which: i32, who: u32, nice: i32) -> isize {
+    sys_setpriority(which, who, nice)
+}
+/// the raw syscall result, 20 - nice or a negative errno, libc returns the nice value itself
+pub fn getpriority(which: i32, who: u32) -> isize {
+    sys_getpriority(which, who)
+}
+pub fn getrusage(who: i32, usage: &mut Rusage) -> isize {
+    sys_getrusage(who, usage)
+}
+pub fn times(tms: &mut Tms) -> isize {
+    sys_times(tms)
+}
+/// sleep for `period_ms` in the kernel, return -EINTR when a signal cut it short
+pub fn nanosleep(period_ms: usize) -> isize {
+    let req = TimeSpec { sec: period_ms / 1000, nsec: period_ms % 1000 * 1_000_000 };
+    let mut rem = TimeSpec::default();
+    sys_nanosleep(&req, &mut rem)
+}
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+/// clock_nanosleep: `req` is an absolute time of the clock
+pub const TIMER_ABSTIME: usize = 1;
+/// `rem` is only written for a relative sleep cut short, and may be null
+pub fn clock_nanosleep(clock_id: usize, flags: usize, req: &TimeSpec, rem: Option<&mut TimeSpec>) -> isize {
+    sys_clock_nanosleep(clock_id, flags, req, rem.map_or(core::ptr::null_mut(), |r| r))
+}
+pub fn clock_gettime(clock_id: usize, ts: &mut TimeSpec) -> isize {
+    sys_clock_gettime(clock_id, ts)
+}
+pub fn sysinfo(info: &mut Sysinfo) -> isize {
+    sys_sysinfo(info)
+}
+
 pub fn sigaction(
     signum: i32,
     action: Option<&SignalAction>,
@@ -347,6 +663,68 @@ pub fn sigaction(
     )
 }
 
+pub const SA_NOCLDSTOP: u32 = 1;
+pub const SA_SIGINFO: u32 = 4;
+pub const SA_RESTART: u32 = 0x1000_0000;
+pub const CLD_EXITED: i32 = 1;
+pub const CLD_KILLED: i32 = 2;
+pub const CLD_DUMPED: i32 = 3;
+pub const CLD_STOPPED: i32 = 5;
+pub const CLD_CONTINUED: i32 = 6;
+pub const SEGV_MAPERR: i32 = 1;
+pub const SEGV_ACCERR: i32 = 2;
+pub const POLL_IN: i32 = 1;
+
+/// sigaction as the kernel lays it out, with the flags `SignalAction` lacks
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SigActionFlags {
+    pub handler: usize,
+    pub flags: u32,
+    pub restorer: usize,
+    pub mask: u64,
+}
+
+/// siginfo_t as handed to a SA_SIGINFO handler, with the fields of kill and SIGCHLD
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SigInfo {
+    pub signo: i32,
+    pub errno: i32,
+    pub code: i32,
+    _pad0: i32,
+    pub pid: i32,
+    pub uid: u32,
+    pub status: i32,
+    _pad1: i32,
+    pub utime: i64,
+    pub stime: i64,
+    _pad: [i32; 20],
+}
+
+impl SigInfo {
+    /// si_addr of SIGSEGV, in the place of pid and uid
+    pub fn addr(&self) -> usize {
+        self.pid as u32 as usize | (self.uid as usize) << 32
+    }
+
+    /// si_band of SIGIO, in the place of pid and uid
+    pub fn band(&self) -> i64 {
+        self.addr() as i64
+    }
+
+    /// si_fd of SIGIO, in the place of status
+    pub fn fd(&self) -> i32 {
+        self.status
+    }
+}
+
+/// install `handler` for `signum` with the sa_flags `flags`
+pub fn sigaction_flags(signum: i32, handler: usize, flags: u32) -> isize {
+    let action = SigActionFlags { handler, flags, ..Default::default() };
+    sys_sigaction(signum, &action as *const SigActionFlags as *const SignalAction, core::ptr::null_mut())
+}
+
 pub fn sigprocmask(mask: u32) -> isize {
     sys_sigprocmask(mask)
 }
@@ -359,6 +737,442 @@ pub fn brk(new_brk: usize) -> isize {
     sys_brk(new_brk)
 }
 
+pub const RLIMIT_DATA: usize = 2;
+pub const RLIMIT_STACK: usize = 3;
+pub const RLIMIT_CORE: usize = 4;
+pub const RLIMIT_NOFILE: usize = 7;
+pub const RLIMIT_MEMLOCK: usize = 8;
+pub const RLIM_INFINITY: usize = usize::MAX;
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct RLimit {
+    pub rlim_cur: usize,
+    pub rlim_max: usize,
+}
+pub fn setrlimit(resource: usize, limit: &RLimit) -> isize {
+    sys_prlimit64(0, resource, limit as *const RLimit as usize, 0)
+}
+pub fn getrlimit(resource: usize, limit: &mut RLimit) -> isize {
+    sys_prlimit64(0, resource, 0, limit as *mut RLimit as usize)
+}
+
+pub const PTRACE_TRACEME: usize = 0;
+pub const PTRACE_PEEKDATA: usize = 2;
+pub const PTRACE_CONT: usize = 7;
+pub const PTRACE_KILL: usize = 8;
+pub const PTRACE_ATTACH: usize = 16;
+pub const PTRACE_DETACH: usize = 17;
+pub const PTRACE_SYSCALL: usize = 24;
+pub const PTRACE_SETOPTIONS: usize = 0x4200;
+pub const PTRACE_GETREGSET: usize = 0x4204;
+pub const PTRACE_O_TRACESYSGOOD: usize = 1;
+pub const NT_PRSTATUS: usize = 1;
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct IoVec {
+    pub base: usize,
+    pub len: usize,
+}
+pub fn ptrace(request: usize, pid: usize, addr: usize, data: usize) -> isize {
+    sys_ptrace(request, pid, addr, data)
+}
+
+pub fn process_vm_readv(pid: usize, local: &[IoVec], remote: &[IoVec]) -> isize {
+    sys_process_vm_readv(pid, local.as_ptr() as usize, local.len(), remote.as_ptr() as usize, remote.len())
+}
+
+pub fn process_vm_writev(pid: usize, local: &[IoVec], remote: &[IoVec]) -> isize {
+    sys_process_vm_writev(pid, local.as_ptr() as usize, local.len(), remote.as_ptr() as usize, remote.len())
+}
+
+pub const PR_GET_DUMPABLE: usize = 3;
+pub const PR_SET_DUMPABLE: usize = 4;
+pub const PR_GET_UNALIGN: usize = 5;
+pub const PR_SET_UNALIGN: usize = 6;
+pub const PR_UNALIGN_NOPRINT: usize = 1;
+pub const PR_UNALIGN_SIGBUS: usize = 2;
+pub const PR_SET_NAME: usize = 15;
+pub const PR_GET_NAME: usize = 16;
+pub const PR_SET_NO_NEW_PRIVS: usize = 38;
+pub const PR_GET_NO_NEW_PRIVS: usize = 39;
+pub const PR_SET_SYSCALL_FILTER: usize = 0x4358_0001;
+pub const PR_SET_SYSCALL_TRACE: usize = 0x4358_0002;
+pub const PR_GET_SYSCALL_TRACE: usize = 0x4358_0003;
+pub const SYSCALL_FILTER_ERRNO: usize = 0;
+pub const SYSCALL_FILTER_KILL: usize = 1;
+pub fn prctl(option: usize, arg2: usize, arg3: usize) -> isize {
+    sys_prctl(option, arg2, arg3)
+}
+pub fn getuid() -> isize {
+    sys_getuid()
+}
+pub fn geteuid() -> isize {
+    sys_geteuid()
+}
+pub fn getgid() -> isize {
+    sys_getgid()
+}
+pub fn setuid(uid: u32) -> isize {
+    sys_setuid(uid)
+}
+pub fn setgid(gid: u32) -> isize {
+    sys_setgid(gid)
+}
+/// set the file mode creation mask, return the old one
+pub fn umask(mask: u32) -> isize {
+    sys_umask(mask)
+}
+pub fn setresuid(ruid: u32, euid: u32, suid: u32) -> isize {
+    sys_setresuid(ruid, euid, suid)
+}
+pub fn getgroups(list: &mut [u32]) -> isize {
+    sys_getgroups(list.len(), list.as_mut_ptr())
+}
+pub fn setgroups(list: &[u32]) -> isize {
+    sys_setgroups(list.len(), list.as_ptr())
+}
+/// install a syscall filter allowing only `allowed`
+pub fn set_syscall_filter(allowed: &[usize], mode: usize) -> isize {
+    let mut bitmap = [0u64; 8];
+    for &id in allowed {
+        bitmap[id / 64] |= 1 << (id % 64);
+    }
+    sys_prctl(PR_SET_SYSCALL_FILTER, mode, bitmap.as_ptr() as usize)
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stat {
+    pub st_dev: u64,
+    pub st_ino: u64,
+    pub st_mode: u32,
+    pub st_nlink: u32,
+    pub st_uid: u32,
+    pub st_gid: u32,
+    pub st_rdev: u64,
+    _pad0: u64,
+    pub st_size: i64,
+    pub st_blksize: i32,
+    _pad1: i32,
+    pub st_blocks: i64,
+    pub st_atime_sec: isize,
+    pub st_atime_nsec: isize,
+    pub st_mtime_sec: isize,
+    pub st_mtime_nsec: isize,
+    pub st_ctime_sec: isize,
+    pub st_ctime_nsec: isize,
+}
+pub fn fstat(fd: usize, stat: &mut Stat) -> isize {
+    sys_fstat(fd, stat as *mut Stat as usize)
+}
+pub fn fstatat(dirfd: isize, path: &str, stat: &mut Stat, flags: i32) -> isize {
+    sys_fstatat(dirfd, path, stat as *mut Stat as usize, flags)
+}
+/// the target of the symlink, an empty path reads the one `dirfd` refers to
+pub fn readlinkat(dirfd: isize, path: &str, buf: &mut [u8]) -> isize {
+    sys_readlinkat(dirfd, path, buf)
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StatxTimestamp {
+    pub tv_sec: i64,
+    pub tv_nsec: u32,
+    _reserved: i32,
+}
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Statx {
+    pub stx_mask: u32,
+    pub stx_blksize: u32,
+    pub stx_attributes: u64,
+    pub stx_nlink: u32,
+    pub stx_uid: u32,
+    pub stx_gid: u32,
+    pub stx_mode: u16,
+    _spare0: u16,
+    pub stx_ino: u64,
+    pub stx_size: u64,
+    pub stx_blocks: u64,
+    pub stx_attributes_mask: u64,
+    pub stx_atime: StatxTimestamp,
+    pub stx_btime: StatxTimestamp,
+    pub stx_ctime: StatxTimestamp,
+    pub stx_mtime: StatxTimestamp,
+    pub stx_rdev_major: u32,
+    pub stx_rdev_minor: u32,
+    pub stx_dev_major: u32,
+    pub stx_dev_minor: u32,
+    _spare: [u64; 14],
+}
+pub const AT_SYMLINK_NOFOLLOW: i32 = 0x100;
+pub const AT_SYMLINK_FOLLOW: i32 = 0x400;
+pub const AT_EMPTY_PATH: i32 = 0x1000;
+/// the largest handle of [`FileHandle`]
+pub const MAX_HANDLE_SZ: usize = 128;
+/// `struct file_handle` with room for the largest handle
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct FileHandle {
+    /// the room for the handle on input, its size on output
+    pub handle_bytes: u32,
+    pub handle_type: i32,
+    pub f_handle: [u8; MAX_HANDLE_SZ],
+}
+impl FileHandle {
+    /// a handle with `room` bytes for the handle
+    pub fn with_room(room: usize) -> Self {
+        Self { handle_bytes: room as u32, handle_type: 0, f_handle: [0; MAX_HANDLE_SZ] }
+    }
+}
+pub fn name_to_handle_at(dirfd: isize, path: &str, handle: &mut FileHandle, mount_id: &mut i32, flags: i32) -> isize {
+    sys_name_to_handle_at(dirfd, path, handle as *mut FileHandle as usize, mount_id, flags)
+}
+pub fn open_by_handle_at(mount_fd: isize, handle: &FileHandle, flags: OpenFlags) -> isize {
+    sys_open_by_handle_at(mount_fd, handle as *const FileHandle as usize, flags.bits)
+}
+pub const STATX_TYPE: u32 = 0x1;
+pub const STATX_MODE: u32 = 0x2;
+pub const STATX_BASIC_STATS: u32 = 0x7ff;
+pub const STATX_BTIME: u32 = 0x800;
+pub fn statx(dirfd: isize, path: &str, flags: i32, mask: u32, statx: &mut Statx) -> isize {
+    sys_statx(dirfd, path.as_ptr(), flags, mask, statx as *mut Statx as usize)
+}
+/// statx on any path pointer, to check the faults
+pub fn statx_raw(dirfd: isize, path: *const u8, flags: i32, mask: u32, statx: &mut Statx) -> isize {
+    sys_statx(dirfd, path, flags, mask, statx as *mut Statx as usize)
+}
+pub fn fchmod(fd: usize, mode: u32) -> isize {
+    sys_fchmod(fd, mode)
+}
+pub const XATTR_CREATE: i32 = 0x1;
+pub const XATTR_REPLACE: i32 = 0x2;
+pub fn setxattr(path: &str, name: &str, value: &[u8], flags: i32) -> isize {
+    sys_setxattr(path, name, value, flags)
+}
+/// an empty `value` asks for the size only
+pub fn getxattr(path: &str, name: &str, value: &mut [u8]) -> isize {
+    sys_getxattr(path, name, value)
+}
+pub fn fgetxattr(fd: usize, name: &str, value: &mut [u8]) -> isize {
+    sys_fgetxattr(fd, name, value)
+}
+/// an empty `list` asks for the size only
+pub fn listxattr(path: &str, list: &mut [u8]) -> isize {
+    sys_listxattr(path, list)
+}
+pub fn removexattr(path: &str, name: &str) -> isize {
+    sys_removexattr(path, name)
+}
+pub fn fsync(fd: usize) -> isize {
+    sys_fsync(fd)
+}
+pub fn ftruncate(fd: usize, length: usize) -> isize {
+    sys_ftruncate(fd, length)
+}
+pub fn pread(fd: usize, buf: &mut [u8], offset: usize) -> isize {
+    sys_pread(fd, buf, offset)
+}
+pub fn fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    sys_fcntl(fd, cmd, arg)
+}
+
+pub const MFD_CLOEXEC: u32 = 0x1;
+pub const MFD_ALLOW_SEALING: u32 = 0x2;
+pub const F_GETFD: usize = 1;
+pub const F_GETFL: usize = 3;
+pub const F_SETFL: usize = 4;
+pub const O_NONBLOCK: usize = 0o4000;
+pub const O_ASYNC: usize = 0o20000;
+pub const F_SETOWN: usize = 8;
+pub const F_GETOWN: usize = 9;
+pub const F_SETSIG: usize = 10;
+pub const F_GETSIG: usize = 11;
+pub const F_SETPIPE_SZ: usize = 1031;
+pub const F_GETPIPE_SZ: usize = 1032;
+pub const F_ADD_SEALS: usize = 1033;
+pub const F_GET_SEALS: usize = 1034;
+pub const F_SEAL_SEAL: usize = 0x1;
+pub const F_SEAL_SHRINK: usize = 0x2;
+pub const F_SEAL_GROW: usize = 0x4;
+pub const F_SEAL_WRITE: usize = 0x8;
+pub fn memfd_create(name: &str, flags: u32) -> isize {
+    sys_memfd_create(name, flags)
+}
+
+pub const POLLIN: i16 = 0x001;
+pub const POLLPRI: i16 = 0x002;
+pub const POLLOUT: i16 = 0x004;
+pub const POLLERR: i16 = 0x008;
+pub const POLLHUP: i16 = 0x010;
+pub const POLLNVAL: i16 = 0x020;
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PollFd {
+    pub fd: i32,
+    pub events: i16,
+    pub revents: i16,
+}
+/// ppoll with a timeout in milliseconds, None to wait forever
+pub fn ppoll(fds: &mut [PollFd], timeout_ms: Option<usize>) -> isize {
+    let ts = timeout_ms.map(|ms| [ms / 1000, ms % 1000 * 1_000_000]);
+    let ts_ptr = ts.as_ref().map_or(0, |ts| ts.as_ptr() as usize);
+    sys_ppoll(fds.as_mut_ptr() as usize, fds.len(), ts_ptr)
+}
+
+pub const IO_BATCH_MAX: usize = 256;
+pub const IO_BATCH_PREAD: u32 = 0;
+pub const IO_BATCH_PWRITE: u32 = 1;
+pub const IO_BATCH_FSYNC: u32 = 2;
+pub const IO_BATCH_OPENAT: u32 = 3;
+pub const IO_BATCH_CLOSE: u32 = 4;
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+/// an operation of io_submit_batch
+pub struct IoBatchOp {
+    pub opcode: u32,
+    /// the dirfd for openat
+    pub fd: i32,
+    pub flags: u32,
+    pub mode: u32,
+    /// the path for openat
+    pub buf: usize,
+    pub len: usize,
+    pub offset: usize,
+    pub user_data: u64,
+}
+
+impl IoBatchOp {
+    pub fn pread(fd: usize, buf: &mut [u8], offset: usize, user_data: u64) -> Self {
+        Self { opcode: IO_BATCH_PREAD, fd: fd as i32, buf: buf.as_mut_ptr() as usize, len: buf.len(), offset, user_data, ..Default::default() }
+    }
+    pub fn pwrite(fd: usize, buf: &[u8], offset: usize, user_data: u64) -> Self {
+        Self { opcode: IO_BATCH_PWRITE, fd: fd as i32, buf: buf.as_ptr() as usize, len: buf.len(), offset, user_data, ..Default::default() }
+    }
+    pub fn fsync(fd: usize, user_data: u64) -> Self {
+        Self { opcode: IO_BATCH_FSYNC, fd: fd as i32, user_data, ..Default::default() }
+    }
+    /// `path` must end with a nul
+    pub fn openat(dirfd: isize, path: &str, flags: OpenFlags, mode: u32, user_data: u64) -> Self {
+        Self { opcode: IO_BATCH_OPENAT, fd: dirfd as i32, flags: flags.bits(), mode, buf: path.as_ptr() as usize, user_data, ..Default::default() }
+    }
+    pub fn close(fd: usize, user_data: u64) -> Self {
+        Self { opcode: IO_BATCH_CLOSE, fd: fd as i32, user_data, ..Default::default() }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+/// the result of an operation of io_submit_batch
+pub struct IoBatchResult {
+    pub user_data: u64,
+    /// the return value of the operation, a negative errno on failure
+    pub res: i64,
+}
+
+/// run `ops` in order, the result of ops[i] goes to results[i],
+/// return the number of results written
+pub fn io_submit_batch(ops: &[IoBatchOp], results: &mut [IoBatchResult]) -> isize {
+    assert!(results.len() >= ops.len());
+    sys_io_submit_batch(ops.as_ptr() as usize, ops.len(), results.as_mut_ptr() as usize)
+}
+
+/// panic the kernel on purpose, see its crash report
+pub const KDEBUG_PANIC: usize = 1;
+/// copy the memory event counters of the process to a [`VmEventCounts`]
+pub const KDEBUG_VM_STATS: usize = 2;
+/// not in linux, debugging aids for the kernel, privileged only but for KDEBUG_VM_STATS and KDEBUG_YIELD_COUNT
+pub fn kdebug(cmd: usize, arg: usize) -> isize {
+    sys_kdebug(cmd, arg)
+}
+/// drop the dentries and inodes nobody uses, returns the number of dentries dropped
+pub const KDEBUG_DROP_CACHES: usize = 3;
+/// drop the unused dentries and inodes, the next lookups go to the file system
+pub fn drop_caches() -> isize {
+    sys_kdebug(KDEBUG_DROP_CACHES, 0)
+}
+/// drop the TCP segments from or to a port on the loopback device, 0 stops dropping
+pub const KDEBUG_NET_BLACKHOLE: usize = 4;
+/// play a dead peer for the connections of `port`, fails unless the network is the loopback device
+pub fn net_blackhole(port: u16) -> isize {
+    sys_kdebug(KDEBUG_NET_BLACKHOLE, port as usize)
+}
+/// run the kernel self tests, only in a kernel built with the selftest feature
+pub const KDEBUG_SELFTEST: usize = 5;
+/// the number of kernel self tests which failed, ENOSYS without the selftest feature
+pub fn selftest() -> isize {
+    sys_kdebug(KDEBUG_SELFTEST, 0)
+}
+/// the pages of DMA memory the devices hold
+pub const KDEBUG_DMA_PAGES: usize = 6;
+/// the pages of DMA memory the devices hold, the rings of their queues and the buffers of the requests in flight
+pub fn dma_pages() -> isize {
+    sys_kdebug(KDEBUG_DMA_PAGES, 0)
+}
+/// the times the calling thread called sched_yield
+pub const KDEBUG_YIELD_COUNT: usize = 7;
+/// the times the calling thread called sched_yield, open to everyone
+pub fn yield_count() -> isize {
+    sys_kdebug(KDEBUG_YIELD_COUNT, 0)
+}
+pub const MS_RDONLY: u32 = 1;
+pub const MS_NOSUID: u32 = 1 << 1;
+pub const MS_NODEV: u32 = 1 << 2;
+pub const MS_REMOUNT: u32 = 1 << 5;
+pub const MS_NOATIME: u32 = 1 << 10;
+/// the flags of a mount in [`StatFs::f_flags`], the same bits as the MS_ ones
+pub const ST_RDONLY: isize = 1;
+pub const ST_NOATIME: isize = 1 << 10;
+pub fn mount(source: &str, target: &str, fstype: &str, flags: u32, data: usize) -> isize {
+    sys_mount(source, target, fstype, flags, data)
+}
+/// detach the file system mounted at `target`
+pub fn umount2(target: &str, flags: u32) -> isize {
+    sys_umount2(target, flags)
+}
+/// change the flags of the mount at `target`
+pub fn remount(target: &str, flags: u32) -> isize {
+    sys_mount("\0", target, "\0", MS_REMOUNT | flags, 0)
+}
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StatFs {
+    pub f_type: i64,
+    pub f_bsize: i64,
+    pub f_blocks: u64,
+    pub f_bfree: u64,
+    pub f_bavail: u64,
+    pub f_files: u64,
+    pub f_ffree: u64,
+    pub f_fsid: [i32; 2],
+    pub f_namelen: isize,
+    pub f_frsize: isize,
+    pub f_flags: isize,
+    pub f_spare: [isize; 4],
+}
+pub fn statfs(path: &str, buf: &mut StatFs) -> isize {
+    sys_statfs(path, buf as *mut StatFs as usize)
+}
+/// set the access and modification times of `path` to now
+pub fn utimensat_now(dirfd: isize, path: &str, flags: i32) -> isize {
+    sys_utimensat(dirfd, path, 0, flags)
+}
+/// indices of the counters in [`VmEventCounts`]
+pub const VM_MINOR_FAULT: usize = 0;
+pub const VM_MAJOR_FAULT: usize = 1;
+pub const VM_COW: usize = 2;
+pub const VM_MMAP: usize = 3;
+pub const VM_MUNMAP: usize = 4;
+pub const VM_TLB_SHOOTDOWN: usize = 5;
+/// the memory event counters of a process
+pub type VmEventCounts = [usize; 6];
+/// the memory event counters of this process
+pub fn vm_stats(counts: &mut VmEventCounts) -> isize {
+    sys_kdebug(KDEBUG_VM_STATS, counts as *mut VmEventCounts as usize)
+}
+
 #[repr(C)]
 pub struct SockaddrIn {
     pub sin_family: u16,
@@ -401,6 +1215,27 @@ pub fn accept (fd: usize, addr: *mut SockaddrIn, addr_len: *mut u32) -> isize {
     sys_accept(fd, addr as *mut _ as *mut u8, addr_len)
 }
 
+pub fn accept4(fd: usize, addr: *mut SockaddrIn, addr_len: *mut u32, flags: i32) -> isize {
+    sys_accept4(fd, addr as *mut _ as *mut u8, addr_len, flags)
+}
+
+pub fn getsockname(fd: usize, addr: *mut SockaddrIn, addr_len: *mut u32) -> isize {
+    sys_getsockname(fd, addr as *mut _ as *mut u8, addr_len)
+}
+
+pub fn getpeername(fd: usize, addr: *mut SockaddrIn, addr_len: *mut u32) -> isize {
+    sys_getpeername(fd, addr as *mut _ as *mut u8, addr_len)
+}
+
+pub fn setsockopt<T>(fd: usize, level: i32, option_name: i32, option_value: &T) -> isize {
+    sys_setsockopt(fd, level, option_name, option_value as *const T as *const u8, size_of::<T>() as u32)
+}
+
+pub fn getsockopt<T>(fd: usize, level: i32, option_name: i32, option_value: &mut T) -> isize {
+    let mut len = size_of::<T>() as u32;
+    sys_getsockopt(fd, level, option_name, option_value as *mut T as *mut u8, &mut len)
+}
+
 pub fn connect(fd: usize, addr: *const SockaddrIn, addr_len: u32) -> isize {
     sys_connect(fd, addr as *const _ as *const u8, addr_len)
 }
@@ -433,6 +1268,8 @@ bitflags! {
         const MAP_ANONYMOUS = 0x20;
         /// Don't check for reservations.
         const MAP_NORESERVE = 0x04000;
+        /// Populate the whole mapping with 2 MiB pages right away.
+        const MAP_HUGETLB = 0x40000;
     }
 }
 
@@ -485,12 +1322,44 @@ pub fn mmap(addr: usize, len: usize, prot: MmapProt, flags: MmapFlags, fd: usize
     sys_mmap(addr, len, prot.bits, flags.bits, fd, offset)
 }
 
+pub fn munmap(addr: usize, len: usize) -> isize {
+    sys_munmap(addr, len)
+}
+
+pub fn mprotect(addr: usize, len: usize, prot: MmapProt) -> isize {
+    sys_mprotect(addr, len, prot.bits)
+}
+
 pub fn mremap(old_addr: usize, old_size: usize, new_size: usize, flags: MremapFlags, new_addr:usize) -> isize {
     sys_mremap(old_addr, old_size, new_size, flags.bits, new_addr)
 }
 
+pub const MCL_CURRENT: i32 = 1;
+pub const MCL_FUTURE: i32 = 2;
+
+pub fn mlock(addr: usize, len: usize) -> isize {
+    sys_mlock(addr, len)
+}
+
+pub fn munlock(addr: usize, len: usize) -> isize {
+    sys_munlock(addr, len)
+}
+
+pub fn mlockall(flags: i32) -> isize {
+    sys_mlockall(flags)
+}
+
+pub fn munlockall() -> isize {
+    sys_munlockall()
+}
+
+pub const LINUX_REBOOT_MAGIC1: i32 = 0xfee1dead_u32 as i32;
+pub const LINUX_REBOOT_MAGIC2: i32 = 672274793;
+pub const LINUX_REBOOT_CMD_POWER_OFF: u32 = 0x4321fedc;
+
+/// terminate the other tasks, unmount the file systems and power off
 pub fn shutdown() -> isize {
-    sys_shutdown(0, 0, 0, 0)
+    sys_shutdown(LINUX_REBOOT_MAGIC1, LINUX_REBOOT_MAGIC2, LINUX_REBOOT_CMD_POWER_OFF, 0)
 }
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -501,4 +1370,69 @@ pub struct TimeVal {
     pub sec: usize,
     /// microseconds
     pub usec: usize,
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+/// resource usage returned by getrusage
+pub struct Rusage {
+    /// user CPU time used
+    pub ru_utime: TimeVal,
+    /// system CPU time used
+    pub ru_stime: TimeVal,
+    /// the memory sizes, unused here
+    pub ru_sizes: [usize; 4],
+    /// page faults served without io
+    pub ru_minflt: usize,
+    /// page faults which read from the device
+    pub ru_majflt: usize,
+    /// the other counters, unused here
+    pub ru_counters: [usize; 6],
+    /// voluntary context switches
+    pub ru_nvcsw: usize,
+    /// involuntary context switches, preemptions at the end of a time slice
+    pub ru_nivcsw: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+/// process times returned by times, in microseconds
+pub struct Tms {
+    /// user time
+    pub utime: usize,
+    /// system time
+    pub stime: usize,
+    /// user time of the reaped children
+    pub cutime: usize,
+    /// system time of the reaped children
+    pub cstime: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+/// TimeSpec struct for syscall, high-precision time value
+pub struct TimeSpec {
+    /// seconds
+    pub sec: usize,
+    /// nanoseconds
+    pub nsec: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+/// system statistics returned by sysinfo, the memory is counted in `mem_unit` bytes
+pub struct Sysinfo {
+    pub uptime: i64,
+    pub loads: [u64; 3],
+    pub totalram: u64,
+    pub freeram: u64,
+    pub sharedram: u64,
+    pub bufferram: u64,
+    pub totalswap: u64,
+    pub freeswap: u64,
+    pub procs: u16,
+    _pad: u16,
+    pub totalhigh: u64,
+    pub freehigh: u64,
+    pub mem_unit: u32,
+}
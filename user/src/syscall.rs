@@ -1,37 +1,119 @@
 use core::arch::asm;
 
-use crate::{SignalAction, TimeVal};
-
-const SYSCALL_DUP: usize = 24;
+use crate::{Rusage, SignalAction, Sysinfo, TimeSpec, TimeVal, Tms};
+
+const SYSCALL_SETXATTR: usize = 5;
+const SYSCALL_GETXATTR: usize = 8;
+const SYSCALL_FGETXATTR: usize = 10;
+const SYSCALL_LISTXATTR: usize = 11;
+const SYSCALL_REMOVEXATTR: usize = 14;
+const SYSCALL_GETCWD: usize = 17;
+const SYSCALL_DUP: usize = 23;
+const SYSCALL_DUP3: usize = 24;
+const SYSCALL_FCNTL: usize = 25;
+const SYSCALL_IOCTL: usize = 29;
+const SYSCALL_MKDIRAT: usize = 34;
+const SYSCALL_UNLINKAT: usize = 35;
+const SYSCALL_SYMLINKAT: usize = 36;
+const SYSCALL_LINKAT: usize = 37;
+const SYSCALL_UMOUNT2: usize = 39;
+const SYSCALL_MOUNT: usize = 40;
+const SYSCALL_STATFS: usize = 43;
+const SYSCALL_FTRUNCATE: usize = 46;
 const SYSCALL_CHDIR: usize = 49;
+const SYSCALL_FCHDIR: usize = 50;
+const SYSCALL_FCHMOD: usize = 52;
 const SYSCALL_OPENAT: usize = 56;
 const SYSCALL_CLOSE: usize = 57;
+const SYSCALL_VHANGUP: usize = 58;
 const SYSCALL_PIPE: usize = 59;
+const SYSCALL_GETDENTS64: usize = 61;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
+const SYSCALL_PREAD: usize = 67;
+const SYSCALL_PPOLL: usize = 73;
+const SYSCALL_READLINKAT: usize = 78;
+const SYSCALL_FSTATAT: usize = 79;
+const SYSCALL_FSTAT: usize = 80;
+const SYSCALL_FSYNC: usize = 82;
+const SYSCALL_UTIMENSAT: usize = 88;
 const SYSCALL_EXIT: usize = 93;
+const SYSCALL_SYSLOG: usize = 116;
+const SYSCALL_PTRACE: usize = 117;
+const SYSCALL_SCHED_SETSCHEDULER: usize = 119;
+const SYSCALL_SCHED_GETSCHEDULER: usize = 120;
+const SYSCALL_SCHED_GETPARAM: usize = 121;
+const SYSCALL_SCHED_SETAFFINITY: usize = 122;
+const SYSCALL_SCHED_GETAFFINITY: usize = 123;
 const SYSCALL_YIELD: usize = 124;
+const SYSCALL_FUTEX: usize = 98;
+const SYSCALL_NANOSLEEP: usize = 101;
+const SYSCALL_CLOCK_GETTIME: usize = 113;
+const SYSCALL_CLOCK_NANOSLEEP: usize = 115;
 const SYSCALL_KILL: usize = 129;
 const SYSCALL_SIGACTION: usize = 134;
 const SYSCALL_SIGPROCMASK: usize = 135;
 const SYSCALL_SIGRETURN: usize = 139;
+const SYSCALL_SETPRIORITY: usize = 140;
+const SYSCALL_GETPRIORITY: usize = 141;
 const SYSCALL_REBOOT: usize = 142;
+const SYSCALL_SETGID: usize = 144;
+const SYSCALL_SETUID: usize = 146;
+const SYSCALL_SETRESUID: usize = 147;
+const SYSCALL_UMASK: usize = 166;
+const SYSCALL_TIMES: usize = 153;
+const SYSCALL_SETPGID: usize = 154;
+const SYSCALL_GETSID: usize = 156;
+const SYSCALL_SETSID: usize = 157;
+const SYSCALL_GETGROUPS: usize = 158;
+const SYSCALL_SETGROUPS: usize = 159;
+const SYSCALL_UNAME: usize = 160;
+const SYSCALL_SETHOSTNAME: usize = 161;
+const SYSCALL_GETRUSAGE: usize = 165;
+const SYSCALL_PRCTL: usize = 167;
+const SYSCALL_GETCPU: usize = 168;
+const SYSCALL_MEMBARRIER: usize = 283;
 const SYSCALL_GETTIMEOFDAY: usize = 169;
+const SYSCALL_SETTIMEOFDAY: usize = 170;
 const SYSCALL_GETPID: usize = 172;
+const SYSCALL_GETUID: usize = 174;
+const SYSCALL_GETEUID: usize = 175;
+const SYSCALL_GETGID: usize = 176;
+const SYSCALL_SYSINFO: usize = 179;
 const SYSCALL_SOCKET: usize = 198;
 const SYSCALL_BIND: usize = 200;
 const SYSCALL_LISTEN: usize = 201;
 const SYSCALL_ACCEPT: usize = 202;
 const SYSCALL_CONNECT: usize = 203;
+const SYSCALL_GETSOCKNAME: usize = 204;
+const SYSCALL_GETPEERNAME: usize = 205;
 const SYSCALL_SENDTO: usize = 206;
 const SYSCALL_RECVFROM: usize = 207;
+const SYSCALL_SETSOCKOPT: usize = 208;
+const SYSCALL_GETSOCKOPT: usize = 209;
 const SYSCALL_BRK: usize = 214;
 const SYSCALL_CLONE: usize = 220;
+const SYSCALL_ACCEPT4: usize = 242;
 const SYSCALL_EXECVE: usize = 221;
 const SYSCALL_WAITPID: usize = 260;
 const SYSCALL_MUNMAP: usize = 215;
 const SYSCALL_MREMAP: usize = 216;
 const SYSCALL_MMAP: usize = 222;
+const SYSCALL_MPROTECT: usize = 226;
+const SYSCALL_MLOCK: usize = 228;
+const SYSCALL_MUNLOCK: usize = 229;
+const SYSCALL_MLOCKALL: usize = 230;
+const SYSCALL_MUNLOCKALL: usize = 231;
+const SYSCALL_PRLIMIT64: usize = 261;
+const SYSCALL_NAME_TO_HANDLE_AT: usize = 264;
+const SYSCALL_OPEN_BY_HANDLE_AT: usize = 265;
+const SYSCALL_PROCESS_VM_READV: usize = 270;
+const SYSCALL_PROCESS_VM_WRITEV: usize = 271;
+const SYSCALL_RENAMEAT2: usize = 276;
+const SYSCALL_MEMFD_CREATE: usize = 279;
+const SYSCALL_STATX: usize = 291;
+const SYSCALL_IO_SUBMIT_BATCH: usize = 1024;
+const SYSCALL_KDEBUG: usize = 1025;
 
 #[cfg(target_arch="riscv64")]
 fn syscall(id: usize, args: [usize; 6]) -> isize {
@@ -75,12 +157,53 @@ pub fn sys_dup(fd: usize) -> isize {
     syscall(SYSCALL_DUP, [fd, 0, 0,0,0,0])
 }
 
+pub fn sys_dup3(old_fd: usize, new_fd: usize, flags: u32) -> isize {
+    syscall(SYSCALL_DUP3, [old_fd, new_fd, flags as usize, 0, 0, 0])
+}
+
 pub fn sys_chdir(path: *const u8) -> isize {
     syscall(SYSCALL_CHDIR, [path as usize, 0, 0, 0, 0, 0])
 }
 
+pub fn sys_fchdir(fd: usize) -> isize {
+    syscall(SYSCALL_FCHDIR, [fd, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_getcwd(buf: *mut u8, len: usize) -> isize {
+    syscall(SYSCALL_GETCWD, [buf as usize, len, 0, 0, 0, 0])
+}
+
+pub fn sys_mkdirat(dirfd: isize, path: &str, mode: u32) -> isize {
+    syscall(SYSCALL_MKDIRAT, [dirfd as usize, path.as_ptr() as usize, mode as usize, 0, 0, 0])
+}
+
+pub fn sys_unlinkat(dirfd: isize, path: &str, flags: u32) -> isize {
+    syscall(SYSCALL_UNLINKAT, [dirfd as usize, path.as_ptr() as usize, flags as usize, 0, 0, 0])
+}
+
+pub fn sys_symlinkat(target: &str, new_dirfd: isize, link_path: &str) -> isize {
+    syscall(SYSCALL_SYMLINKAT, [target.as_ptr() as usize, new_dirfd as usize, link_path.as_ptr() as usize, 0, 0, 0])
+}
+
+pub fn sys_linkat(old_dirfd: isize, old_path: &str, new_dirfd: isize, new_path: &str, flags: u32) -> isize {
+    syscall(SYSCALL_LINKAT, [old_dirfd as usize, old_path.as_ptr() as usize, new_dirfd as usize, new_path.as_ptr() as usize, flags as usize, 0])
+}
+
+pub fn sys_name_to_handle_at(dirfd: isize, path: &str, handle: usize, mount_id: &mut i32, flags: i32) -> isize {
+    syscall(SYSCALL_NAME_TO_HANDLE_AT, [dirfd as usize, path.as_ptr() as usize, handle, mount_id as *mut i32 as usize, flags as usize, 0])
+}
+
+pub fn sys_open_by_handle_at(mount_fd: isize, handle: usize, flags: u32) -> isize {
+    syscall(SYSCALL_OPEN_BY_HANDLE_AT, [mount_fd as usize, handle, flags as usize, 0, 0, 0])
+}
+
+pub fn sys_renameat2(old_dirfd: isize, old_path: &str, new_dirfd: isize, new_path: &str, flags: u32) -> isize {
+    syscall(SYSCALL_RENAMEAT2, [old_dirfd as usize, old_path.as_ptr() as usize, new_dirfd as usize, new_path.as_ptr() as usize, flags as usize, 0])
+}
+
 pub fn sys_openat(dirfd: isize, path: &str, flags: u32) -> isize {
-    syscall(SYSCALL_OPENAT, [dirfd as usize, path.as_ptr() as usize, flags as usize, 0, 0, 0])
+    // a created file gets rw for everyone, like fopen
+    syscall(SYSCALL_OPENAT, [dirfd as usize, path.as_ptr() as usize, flags as usize, 0o666, 0, 0])
 }
 
 pub fn sys_close(fd: usize) -> isize {
@@ -91,6 +214,10 @@ pub fn sys_pipe(pipe: &mut [usize]) -> isize {
     syscall(SYSCALL_PIPE, [pipe.as_mut_ptr() as usize, 0, 0,0,0,0])
 }
 
+pub fn sys_getdents64(fd: usize, buffer: &mut [u8]) -> isize {
+    syscall(SYSCALL_GETDENTS64, [fd, buffer.as_mut_ptr() as usize, buffer.len(), 0, 0, 0])
+}
+
 pub fn sys_read(fd: usize, buffer: &mut [u8]) -> isize {
     syscall(
         SYSCALL_READ,
@@ -111,14 +238,79 @@ pub fn sys_yield() -> isize {
     syscall(SYSCALL_YIELD, [0, 0, 0,0,0,0])
 }
 
+pub fn sys_sched_setscheduler(pid: usize, policy: i32, priority: &i32) -> isize {
+    syscall(SYSCALL_SCHED_SETSCHEDULER, [pid, policy as usize, priority as *const i32 as usize, 0, 0, 0])
+}
+
+pub fn sys_sched_getscheduler(pid: usize) -> isize {
+    syscall(SYSCALL_SCHED_GETSCHEDULER, [pid, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_sched_getparam(pid: usize, priority: &mut i32) -> isize {
+    syscall(SYSCALL_SCHED_GETPARAM, [pid, priority as *mut i32 as usize, 0, 0, 0, 0])
+}
+
+pub fn sys_sched_setaffinity(pid: usize, mask: &[u8]) -> isize {
+    syscall(SYSCALL_SCHED_SETAFFINITY, [pid, mask.len(), mask.as_ptr() as usize, 0, 0, 0])
+}
+
+pub fn sys_sched_getaffinity(pid: usize, mask: &mut [u8]) -> isize {
+    syscall(SYSCALL_SCHED_GETAFFINITY, [pid, mask.len(), mask.as_mut_ptr() as usize, 0, 0, 0])
+}
+
+pub fn sys_getcpu(cpu: &mut u32) -> isize {
+    syscall(SYSCALL_GETCPU, [cpu as *mut u32 as usize, 0, 0, 0, 0, 0])
+}
+
 pub fn sys_kill(pid: usize, signal: i32) -> isize {
     syscall(SYSCALL_KILL, [pid, signal as usize, 0,0,0,0])
 }
 
+pub fn sys_setpriority(which: i32, who: u32, prio: i32) -> isize {
+    syscall(SYSCALL_SETPRIORITY, [which as usize, who as usize, prio as usize, 0, 0, 0])
+}
+
+pub fn sys_getpriority(which: i32, who: u32) -> isize {
+    syscall(SYSCALL_GETPRIORITY, [which as usize, who as usize, 0, 0, 0, 0])
+}
+
+pub fn sys_getrusage(who: i32, usage: &mut Rusage) -> isize {
+    syscall(SYSCALL_GETRUSAGE, [who as usize, usage as *mut _ as usize, 0, 0, 0, 0])
+}
+
+pub fn sys_times(tms: &mut Tms) -> isize {
+    syscall(SYSCALL_TIMES, [tms as *mut _ as usize, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_nanosleep(req: &TimeSpec, rem: &mut TimeSpec) -> isize {
+    syscall(SYSCALL_NANOSLEEP, [req as *const _ as usize, rem as *mut _ as usize, 0, 0, 0, 0])
+}
+
+/// `rem` may be null
+pub fn sys_clock_nanosleep(clock_id: usize, flags: usize, req: &TimeSpec, rem: *mut TimeSpec) -> isize {
+    syscall(SYSCALL_CLOCK_NANOSLEEP, [clock_id, flags, req as *const _ as usize, rem as usize, 0, 0])
+}
+
+pub fn sys_clock_gettime(clock_id: usize, ts: &mut TimeSpec) -> isize {
+    syscall(SYSCALL_CLOCK_GETTIME, [clock_id, ts as *mut _ as usize, 0, 0, 0, 0])
+}
+
+pub fn sys_sysinfo(info: &mut Sysinfo) -> isize {
+    syscall(SYSCALL_SYSINFO, [info as *mut _ as usize, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_futex(uaddr: *const u32, futex_op: i32, val: u32) -> isize {
+    syscall(SYSCALL_FUTEX, [uaddr as usize, futex_op as usize, val as usize, 0, 0, 0])
+}
+
 pub fn sys_get_time_of_day(tv: &mut TimeVal) -> isize {
     syscall(SYSCALL_GETTIMEOFDAY, [tv as *mut _ as usize, 0, 0,0,0,0])
 }
 
+pub fn sys_set_time_of_day(tv: &TimeVal) -> isize {
+    syscall(SYSCALL_SETTIMEOFDAY, [tv as *const _ as usize, 0, 0, 0, 0, 0])
+}
+
 pub fn sys_getpid() -> isize {
     syscall(SYSCALL_GETPID, [0, 0, 0, 0, 0, 0])
 }
@@ -133,6 +325,114 @@ pub fn sys_clone(flags: usize, stack: usize, tls: usize) -> isize {
     )
 }
 
+/// clone a thread running `entry(arg)` on `stack_top`, the child never returns here
+#[cfg(target_arch="riscv64")]
+pub fn sys_spawn_thread(flags: usize, stack_top: usize, entry: extern "C" fn(usize) -> !, arg: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            "bnez a0, 1f",
+            "mv a0, t1",
+            "jr t0",
+            "1:",
+            inlateout("a0") flags => ret,
+            in("a1") stack_top,
+            in("a2") 0,
+            in("a3") 0,
+            in("a4") 0,
+            in("a7") SYSCALL_CLONE,
+            in("t0") entry as usize,
+            in("t1") arg,
+        );
+    }
+    ret
+}
+
+/// clone a thread running `entry(arg)` on `stack_top`, the child never returns here
+#[cfg(target_arch="loongarch64")]
+pub fn sys_spawn_thread(flags: usize, stack_top: usize, entry: extern "C" fn(usize) -> !, arg: usize) -> isize {
+    let mut ret: isize;
+    unsafe {
+        asm!(
+            "syscall 0",
+            "bnez $a0, 1f",
+            "move $a0, $t1",
+            "jirl $zero, $t0, 0",
+            "1:",
+            inlateout("$a0") flags => ret,
+            in("$a1") stack_top,
+            in("$a2") 0,
+            in("$a3") 0,
+            in("$a4") 0,
+            in("$a7") SYSCALL_CLONE,
+            in("$t0") entry as usize,
+            in("$t1") arg,
+        );
+    }
+    ret
+}
+
+pub fn sys_prlimit64(pid: usize, resource: usize, new_limit: usize, old_limit: usize) -> isize {
+    syscall(SYSCALL_PRLIMIT64, [pid, resource, new_limit, old_limit, 0, 0])
+}
+
+pub fn sys_ptrace(request: usize, pid: usize, addr: usize, data: usize) -> isize {
+    syscall(SYSCALL_PTRACE, [request, pid, addr, data, 0, 0])
+}
+
+pub fn sys_process_vm_readv(pid: usize, local_iov: usize, liovcnt: usize, remote_iov: usize, riovcnt: usize) -> isize {
+    syscall(SYSCALL_PROCESS_VM_READV, [pid, local_iov, liovcnt, remote_iov, riovcnt, 0])
+}
+
+pub fn sys_process_vm_writev(pid: usize, local_iov: usize, liovcnt: usize, remote_iov: usize, riovcnt: usize) -> isize {
+    syscall(SYSCALL_PROCESS_VM_WRITEV, [pid, local_iov, liovcnt, remote_iov, riovcnt, 0])
+}
+
+pub fn sys_prctl(option: usize, arg2: usize, arg3: usize) -> isize {
+    syscall(SYSCALL_PRCTL, [option, arg2, arg3, 0, 0, 0])
+}
+
+pub fn sys_getuid() -> isize {
+    syscall(SYSCALL_GETUID, [0, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_geteuid() -> isize {
+    syscall(SYSCALL_GETEUID, [0, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_getgid() -> isize {
+    syscall(SYSCALL_GETGID, [0, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_setuid(uid: u32) -> isize {
+    syscall(SYSCALL_SETUID, [uid as usize, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_setgid(gid: u32) -> isize {
+    syscall(SYSCALL_SETGID, [gid as usize, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_umask(mask: u32) -> isize {
+    syscall(SYSCALL_UMASK, [mask as usize, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_setresuid(ruid: u32, euid: u32, suid: u32) -> isize {
+    syscall(SYSCALL_SETRESUID, [ruid as usize, euid as usize, suid as usize, 0, 0, 0])
+}
+
+pub fn sys_getgroups(size: usize, list: *mut u32) -> isize {
+    syscall(SYSCALL_GETGROUPS, [size, list as usize, 0, 0, 0, 0])
+}
+
+pub fn sys_setgroups(size: usize, list: *const u32) -> isize {
+    syscall(SYSCALL_SETGROUPS, [size, list as usize, 0, 0, 0, 0])
+}
+
+pub fn sys_membarrier(cmd: usize, flags: usize) -> isize {
+    syscall(SYSCALL_MEMBARRIER, [cmd, flags, 0, 0, 0, 0])
+}
+
 pub fn sys_exec(path: &str, args: &[*const u8]) -> isize {
     syscall(
         SYSCALL_EXECVE,
@@ -203,8 +503,26 @@ pub fn sys_connect(fd: usize, addr: *const u8, addr_len: u32) -> isize {
 pub fn sys_accept(fd: usize, addr: *mut u8, addr_len: *mut u32) -> isize {
     syscall(SYSCALL_ACCEPT, [fd, addr as usize, addr_len as usize, 0, 0, 0])
 }
+
+pub fn sys_accept4(fd: usize, addr: *mut u8, addr_len: *mut u32, flags: i32) -> isize {
+    syscall(SYSCALL_ACCEPT4, [fd, addr as usize, addr_len as usize, flags as usize, 0, 0])
+}
+
+pub fn sys_getsockname(fd: usize, addr: *mut u8, addr_len: *mut u32) -> isize {
+    syscall(SYSCALL_GETSOCKNAME, [fd, addr as usize, addr_len as usize, 0, 0, 0])
+}
+
+pub fn sys_getpeername(fd: usize, addr: *mut u8, addr_len: *mut u32) -> isize {
+    syscall(SYSCALL_GETPEERNAME, [fd, addr as usize, addr_len as usize, 0, 0, 0])
+}
+
+pub fn sys_setsockopt(fd: usize, level: i32, option_name: i32, option_value: *const u8, option_len: u32) -> isize {
+    syscall(SYSCALL_SETSOCKOPT, [fd, level as usize, option_name as usize, option_value as usize, option_len as usize, 0])
+}
+pub fn sys_getsockopt(fd: usize, level: i32, option_name: i32, option_value: *mut u8, option_len: *mut u32) -> isize {
+    syscall(SYSCALL_GETSOCKOPT, [fd, level as usize, option_name as usize, option_value as usize, option_len as usize, 0])
+}
 pub fn sys_sendto(sockfd: i32, buf: *const u8, len: usize, flags: i32, dest_addr: *const u8, addrlen: u32) -> isize{
-    println!("addrlen is {}", addrlen);
     syscall(SYSCALL_SENDTO, [sockfd as usize, buf as usize, len, flags as usize, dest_addr as usize, addrlen as usize])
 }
 
@@ -216,10 +534,153 @@ pub fn sys_mmap(addr: usize, len: usize, prot: i32, flags: i32, fd: usize, offse
     syscall(SYSCALL_MMAP, [addr, len, prot as _, flags as _, fd, offset])
 }
 
+pub fn sys_munmap(addr: usize, len: usize) -> isize {
+    syscall(SYSCALL_MUNMAP, [addr, len, 0, 0, 0, 0])
+}
+
+pub fn sys_mprotect(addr: usize, len: usize, prot: i32) -> isize {
+    syscall(SYSCALL_MPROTECT, [addr, len, prot as _, 0, 0, 0])
+}
+
+pub fn sys_mlock(addr: usize, len: usize) -> isize {
+    syscall(SYSCALL_MLOCK, [addr, len, 0, 0, 0, 0])
+}
+
+pub fn sys_munlock(addr: usize, len: usize) -> isize {
+    syscall(SYSCALL_MUNLOCK, [addr, len, 0, 0, 0, 0])
+}
+
+pub fn sys_mlockall(flags: i32) -> isize {
+    syscall(SYSCALL_MLOCKALL, [flags as _, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_munlockall() -> isize {
+    syscall(SYSCALL_MUNLOCKALL, [0, 0, 0, 0, 0, 0])
+}
+
 pub fn sys_mremap(old_addr: usize, old_size: usize, new_size: usize, flags: i32, new_addr:usize) -> isize {
     syscall(SYSCALL_MREMAP, [old_addr, old_size, new_size, flags as _, new_addr, 0])
 }
 
 pub fn sys_shutdown(magic1: i32, magic2: i32, cmd: u32, args: usize) -> isize {
     syscall(SYSCALL_REBOOT, [magic1 as _, magic2 as _, cmd as _, args, 0, 0])
-}
\ No newline at end of file
+}
+pub fn sys_fchmod(fd: usize, mode: u32) -> isize {
+    syscall(SYSCALL_FCHMOD, [fd, mode as usize, 0, 0, 0, 0])
+}
+
+pub fn sys_setxattr(path: &str, name: &str, value: &[u8], flags: i32) -> isize {
+    syscall(SYSCALL_SETXATTR, [path.as_ptr() as usize, name.as_ptr() as usize, value.as_ptr() as usize, value.len(), flags as usize, 0])
+}
+
+pub fn sys_getxattr(path: &str, name: &str, value: &mut [u8]) -> isize {
+    syscall(SYSCALL_GETXATTR, [path.as_ptr() as usize, name.as_ptr() as usize, value.as_mut_ptr() as usize, value.len(), 0, 0])
+}
+
+pub fn sys_fgetxattr(fd: usize, name: &str, value: &mut [u8]) -> isize {
+    syscall(SYSCALL_FGETXATTR, [fd, name.as_ptr() as usize, value.as_mut_ptr() as usize, value.len(), 0, 0])
+}
+
+pub fn sys_listxattr(path: &str, list: &mut [u8]) -> isize {
+    syscall(SYSCALL_LISTXATTR, [path.as_ptr() as usize, list.as_mut_ptr() as usize, list.len(), 0, 0, 0])
+}
+
+pub fn sys_removexattr(path: &str, name: &str) -> isize {
+    syscall(SYSCALL_REMOVEXATTR, [path.as_ptr() as usize, name.as_ptr() as usize, 0, 0, 0, 0])
+}
+
+pub fn sys_fstat(fd: usize, stat: usize) -> isize {
+    syscall(SYSCALL_FSTAT, [fd, stat, 0, 0, 0, 0])
+}
+
+pub fn sys_fstatat(dirfd: isize, path: &str, stat: usize, flags: i32) -> isize {
+    syscall(SYSCALL_FSTATAT, [dirfd as usize, path.as_ptr() as usize, stat, flags as usize, 0, 0])
+}
+
+pub fn sys_readlinkat(dirfd: isize, path: &str, buf: &mut [u8]) -> isize {
+    syscall(SYSCALL_READLINKAT, [dirfd as usize, path.as_ptr() as usize, buf.as_mut_ptr() as usize, buf.len(), 0, 0])
+}
+
+pub fn sys_statx(dirfd: isize, path: *const u8, flags: i32, mask: u32, statx: usize) -> isize {
+    syscall(SYSCALL_STATX, [dirfd as usize, path as usize, flags as usize, mask as usize, statx, 0])
+}
+
+pub fn sys_fsync(fd: usize) -> isize {
+    syscall(SYSCALL_FSYNC, [fd, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_pread(fd: usize, buf: &mut [u8], offset: usize) -> isize {
+    syscall(SYSCALL_PREAD, [fd, buf.as_mut_ptr() as usize, buf.len(), offset, 0, 0])
+}
+
+pub fn sys_io_submit_batch(ops: usize, count: usize, results: usize) -> isize {
+    syscall(SYSCALL_IO_SUBMIT_BATCH, [ops, count, results, 0, 0, 0])
+}
+
+pub fn sys_kdebug(cmd: usize, arg: usize) -> isize {
+    syscall(SYSCALL_KDEBUG, [cmd, arg, 0, 0, 0, 0])
+}
+
+pub fn sys_ftruncate(fd: usize, length: usize) -> isize {
+    syscall(SYSCALL_FTRUNCATE, [fd, length, 0, 0, 0, 0])
+}
+
+pub fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> isize {
+    syscall(SYSCALL_IOCTL, [fd, cmd, arg, 0, 0, 0])
+}
+
+pub fn sys_syslog(log_type: usize, buf: &mut [u8]) -> isize {
+    syscall(SYSCALL_SYSLOG, [log_type, buf.as_mut_ptr() as usize, buf.len(), 0, 0, 0])
+}
+
+pub fn sys_uname(buf: &mut [u8; 390]) -> isize {
+    syscall(SYSCALL_UNAME, [buf.as_mut_ptr() as usize, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_sethostname(name: &[u8]) -> isize {
+    syscall(SYSCALL_SETHOSTNAME, [name.as_ptr() as usize, name.len(), 0, 0, 0, 0])
+}
+
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    syscall(SYSCALL_FCNTL, [fd, cmd, arg, 0, 0, 0])
+}
+
+pub fn sys_memfd_create(name: &str, flags: u32) -> isize {
+    syscall(SYSCALL_MEMFD_CREATE, [name.as_ptr() as usize, flags as usize, 0, 0, 0, 0])
+}
+
+pub fn sys_ppoll(fds: usize, nfds: usize, timeout: usize) -> isize {
+    syscall(SYSCALL_PPOLL, [fds, nfds, timeout, 0, 0, 0])
+}
+
+pub fn sys_mount(source: &str, target: &str, fstype: &str, flags: u32, data: usize) -> isize {
+    syscall(SYSCALL_MOUNT, [source.as_ptr() as usize, target.as_ptr() as usize, fstype.as_ptr() as usize, flags as usize, data, 0])
+}
+
+pub fn sys_umount2(target: &str, flags: u32) -> isize {
+    syscall(SYSCALL_UMOUNT2, [target.as_ptr() as usize, flags as usize, 0, 0, 0, 0])
+}
+
+pub fn sys_statfs(path: &str, buf: usize) -> isize {
+    syscall(SYSCALL_STATFS, [path.as_ptr() as usize, buf, 0, 0, 0, 0])
+}
+
+pub fn sys_utimensat(dirfd: isize, path: &str, times: usize, flags: i32) -> isize {
+    syscall(SYSCALL_UTIMENSAT, [dirfd as usize, path.as_ptr() as usize, times, flags as usize, 0, 0])
+}
+
+pub fn sys_setpgid(pid: usize, pgid: usize) -> isize {
+    syscall(SYSCALL_SETPGID, [pid, pgid, 0, 0, 0, 0])
+}
+
+pub fn sys_setsid() -> isize {
+    syscall(SYSCALL_SETSID, [0, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_getsid(pid: usize) -> isize {
+    syscall(SYSCALL_GETSID, [pid, 0, 0, 0, 0, 0])
+}
+
+pub fn sys_vhangup() -> isize {
+    syscall(SYSCALL_VHANGUP, [0, 0, 0, 0, 0, 0])
+}